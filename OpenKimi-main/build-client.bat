@@ -1,6 +1,6 @@
 @echo off
 REM OpenKimi客户端构建脚本
-REM 使用: build-client.bat [windows|linux|macos|all]
+REM 使用: build-client.bat [windows|linux|macos|all] [--msi] [--web-installer]
 
 REM 检查scripts目录是否存在
 if not exist scripts (
@@ -28,7 +28,7 @@ set PLATFORM=%1
 if "%PLATFORM%"=="" set PLATFORM=all
 
 REM 运行编译后的二进制文件
-.\target\release\build-client.exe %PLATFORM%
+..\target\release\build-client.exe %PLATFORM% %2 %3
 
 cd ..
 echo.
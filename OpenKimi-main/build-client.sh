@@ -1,7 +1,8 @@
 #!/bin/bash
 
 # OpenKimi客户端构建脚本
-# 使用: ./build-client.sh [windows|linux|macos|all]
+# 使用: ./build-client.sh [windows|linux|macos|all] [--msi] [--web-installer]
+#       ./build-client.sh completions <bash|zsh|fish|powershell>
 
 # 如果scripts目录不存在，则输出错误信息并退出
 if [ ! -d "scripts" ]; then
@@ -28,4 +29,4 @@ fi
 PLATFORM=${1:-all}
 
 # 运行编译后的二进制文件
-./target/release/build-client "$PLATFORM" 
\ No newline at end of file
+../target/release/build-client "$PLATFORM" "${@:2}" 
\ No newline at end of file
@@ -0,0 +1,279 @@
+//! 附件：`-f`和`/attach`指定的文件
+//!
+//! 图片按文件头识别（PNG、JPEG、GIF、WebP），不超过4 MB的以`data:` URL内联为`image_url`片段；更大的分块上传到
+//! `/v1/files`，以`image_file`引用，由服务端的图片输入缩小或转换，需要服务端启用`files`。PDF、DOCX、HTML、
+//! Markdown、纯文本和源代码在本地提取文字，连同文件名作为文本片段放在问题之后。超过大小上限的文件在发送之前报错。
+//! 附件的名称、类型、大小和位置随消息保存；继续之前的对话时只有提取的文字和图片的名称，图片本身不再发送。
+
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use openkimi_client::blocking::Client;
+use openkimi_client::types::MessageContent;
+use openkimi_client::{ChatMessage, ClientError, Method};
+use openkimi_rag::{extract, normalize, DocumentKind};
+use openkimi_sessions::NewAttachment;
+use serde_json::{json, Value};
+
+const MB: u64 = 1024 * 1024;
+
+/// 内联图片的上限，更大的图片上传后引用
+pub const INLINE_IMAGE_BYTES: u64 = 4 * MB;
+
+/// 图片的上限，与服务端`vision.max_image_bytes`的默认值相同
+pub const MAX_IMAGE_BYTES: u64 = 20 * MB;
+
+/// 文档文件的上限
+pub const MAX_DOCUMENT_BYTES: u64 = 50 * MB;
+
+/// 从一个文档中提取的文字的上限
+pub const MAX_TEXT_CHARS: usize = 100_000;
+
+/// 分块上传时每块的大小，小于服务端`files.max_chunk_bytes`的默认值
+const CHUNK_BYTES: usize = 2 * MB as usize;
+
+/// 上传的图片的`purpose`
+const PURPOSE: &str = "vision";
+
+/// 读取好的附件
+pub struct Attachment {
+    pub name: String,
+    /// 显示给用户的说明，如“内联图片”
+    pub summary: String,
+    /// 放在问题之后的内容片段
+    parts: Vec<Value>,
+    record: NewAttachment,
+}
+
+/// `1.5 MB`这样的大小
+pub fn format_size(bytes: u64) -> String {
+    match bytes {
+        bytes if bytes < 1024 => format!("{} B", bytes),
+        bytes if bytes < MB => format!("{:.1} KB", bytes as f64 / 1024.0),
+        bytes => format!("{:.1} MB", bytes as f64 / MB as f64),
+    }
+}
+
+/// 按文件头识别图片类型
+fn image_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(b"\xFF\xD8\xFF") {
+        Some("image/jpeg")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+/// 上传进度，标准错误是终端时在同一行刷新
+fn progress(name: &str, sent: u64, total: u64) {
+    if io::stderr().is_terminal() {
+        let percent = (sent * 100).checked_div(total).unwrap_or(100);
+        eprint!(
+            "\r\x1b[2K⬆️ 上传 {} {:>3}%（{} / {}）",
+            name,
+            percent,
+            format_size(sent),
+            format_size(total)
+        );
+    }
+}
+
+/// 分块上传，返回文件id；中途连接中断时按服务端已接收的字节数续传
+fn upload(client: &Client, name: &str, data: &[u8]) -> Result<String, ClientError> {
+    let total = data.len() as u64;
+    let task: Value = client.post(
+        "/files/uploads",
+        &json!({ "filename": name, "bytes": total, "purpose": PURPOSE }),
+    )?;
+    let id = task["id"].as_str().ok_or_else(|| ClientError::Decode("上传任务缺少 id".to_string()))?.to_string();
+    let path = format!("/files/uploads/{}", id);
+
+    let result = (|| {
+        let mut offset = 0;
+        while offset < total {
+            progress(name, offset, total);
+            let end = (offset as usize + CHUNK_BYTES).min(data.len());
+            let chunk = data[offset as usize..end].to_vec();
+            let received = match client.send_bytes::<Value>(
+                Method::PATCH,
+                &path,
+                chunk,
+                &[("upload-offset", &offset.to_string())],
+            ) {
+                Ok(task) => task["received"].as_u64(),
+                // 服务端可能已经收到了这一块，只是响应丢失了
+                Err(err) => match client.get::<Value>(&path).ok().and_then(|task| task["received"].as_u64()) {
+                    Some(received) if received > offset => Some(received),
+                    _ => return Err(err),
+                },
+            };
+            offset = received.ok_or_else(|| ClientError::Decode("上传任务缺少 received".to_string()))?;
+        }
+        progress(name, total, total);
+        let file: Value = client.post(&format!("{}/complete", path), &json!({}))?;
+        file["id"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| ClientError::Decode("文件缺少 id".to_string()))
+    })();
+    if io::stderr().is_terminal() {
+        eprint!("\r\x1b[2K");
+    }
+    if result.is_err() {
+        let _ = client.delete::<Value>(&path);
+    }
+    result
+}
+
+fn image(client: &Client, path: &Path, name: &str, mime_type: &str, data: Vec<u8>) -> Result<Attachment, String> {
+    let size = data.len() as u64;
+    if size > MAX_IMAGE_BYTES {
+        return Err(format!(
+            "{} 有 {}，超过图片 {} 的上限",
+            name,
+            format_size(size),
+            format_size(MAX_IMAGE_BYTES)
+        ));
+    }
+    let (part, summary, uri) = if size <= INLINE_IMAGE_BYTES {
+        let url = format!("data:{};base64,{}", mime_type, STANDARD.encode(&data));
+        let part = json!({ "type": "image_url", "image_url": { "url": url } });
+        (part, "内联图片".to_string(), path.display().to_string())
+    } else {
+        let id = upload(client, name, &data).map_err(|e| {
+            format!(
+                "上传 {} 失败: {}（服务端需要启用 files；也可以把图片缩小到 {} 以内内联发送）",
+                name,
+                e,
+                format_size(INLINE_IMAGE_BYTES)
+            )
+        })?;
+        let part = json!({ "type": "image_file", "image_file": { "file_id": id } });
+        (part, format!("已上传为 {}", id), id)
+    };
+    Ok(Attachment {
+        name: name.to_string(),
+        summary: format!("{}，{}", format_size(size), summary),
+        parts: vec![json!({ "type": "text", "text": format!("\n\n[图片 {}]", name) }), part],
+        record: NewAttachment {
+            name: name.to_string(),
+            mime_type: Some(mime_type.to_string()),
+            size: Some(size),
+            uri: Some(uri),
+            sha256: None,
+        },
+    })
+}
+
+fn document(path: &Path, name: &str, data: Vec<u8>) -> Result<Attachment, String> {
+    let size = data.len() as u64;
+    if size > MAX_DOCUMENT_BYTES {
+        return Err(format!(
+            "{} 有 {}，超过文档 {} 的上限",
+            name,
+            format_size(size),
+            format_size(MAX_DOCUMENT_BYTES)
+        ));
+    }
+    // 不认识扩展名时，UTF-8文本按纯文本处理
+    let kind = match DocumentKind::from_name(name) {
+        Some(kind) => kind,
+        None if !data.contains(&0) && std::str::from_utf8(&data).is_ok() => DocumentKind::Text,
+        None => {
+            return Err(format!(
+                "不支持的附件类型: {}，支持图片、PDF、DOCX、HTML、Markdown、纯文本和源代码",
+                name
+            ))
+        }
+    };
+    let text = extract(kind, &data).map_err(|e| format!("读取 {} 失败: {}", name, e))?;
+    let text = match kind {
+        DocumentKind::Code(_) | DocumentKind::Text => text.trim_end().to_string(),
+        _ => normalize(&text),
+    };
+    if text.trim().is_empty() {
+        return Err(format!("{} 中没有可以提取的文字，扫描版PDF需要先识别文字", name));
+    }
+    let chars = text.chars().count();
+    if chars > MAX_TEXT_CHARS {
+        return Err(format!(
+            "{} 有 {} 字，超过附件 {} 字的上限",
+            name, chars, MAX_TEXT_CHARS
+        ));
+    }
+    let body = match kind {
+        DocumentKind::Code(language) => format!("```{}\n{}\n```", language, text),
+        _ => text,
+    };
+    let mime_type = match kind {
+        DocumentKind::Pdf => "application/pdf",
+        DocumentKind::Docx => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        DocumentKind::Html => "text/html",
+        DocumentKind::Markdown => "text/markdown",
+        DocumentKind::Text | DocumentKind::Code(_) => "text/plain",
+    };
+    Ok(Attachment {
+        name: name.to_string(),
+        summary: format!("{}，{} 字", format_size(size), chars),
+        parts: vec![json!({ "type": "text", "text": format!("\n\n附件 {}：\n\n{}", name, body) })],
+        record: NewAttachment {
+            name: name.to_string(),
+            mime_type: Some(mime_type.to_string()),
+            size: Some(size),
+            uri: Some(path.display().to_string()),
+            sha256: None,
+        },
+    })
+}
+
+/// 读取一个附件，大图片在这里上传
+pub fn load(client: &Client, path: &str) -> Result<Attachment, String> {
+    let path = Path::new(path);
+    let metadata = fs::metadata(path).map_err(|e| format!("读取 {} 失败: {}", path.display(), e))?;
+    if !metadata.is_file() {
+        return Err(format!("{} 不是文件", path.display()));
+    }
+    // 读取之前先按最大的上限检查，避免把过大的文件读进内存
+    let limit = MAX_IMAGE_BYTES.max(MAX_DOCUMENT_BYTES);
+    if metadata.len() > limit {
+        return Err(format!(
+            "{} 有 {}，超过附件 {} 的上限",
+            path.display(),
+            format_size(metadata.len()),
+            format_size(limit)
+        ));
+    }
+    let name = path.file_name().map_or_else(
+        || path.display().to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    );
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let data = fs::read(&path).map_err(|e| format!("读取 {} 失败: {}", path.display(), e))?;
+    match image_type(&data) {
+        Some(mime_type) => image(client, &path, &name, mime_type, data),
+        None => document(&path, &name, data),
+    }
+}
+
+/// 带附件的用户消息，附件在问题之后；返回消息和要保存的附件信息
+pub fn message(text: String, attachments: &[Attachment]) -> (ChatMessage, Vec<NewAttachment>) {
+    if attachments.is_empty() {
+        return (ChatMessage::user(text), Vec::new());
+    }
+    let mut parts = vec![json!({ "type": "text", "text": text })];
+    parts.extend(attachments.iter().flat_map(|attachment| attachment.parts.iter().cloned()));
+    let mut message = ChatMessage::user("");
+    message.content = Some(MessageContent::Parts(parts));
+    (
+        message,
+        attachments.iter().map(|attachment| attachment.record.clone()).collect(),
+    )
+}
@@ -0,0 +1,159 @@
+//! `kimi completions <shell>`：生成kimi的命令行补全脚本
+//!
+//! 子命令和选项与`kimi --help`的用法一致，增加子命令或选项时两处一起修改。
+
+use openkimi_completions::{Command, Flag, Value};
+
+use crate::config;
+
+/// 连接服务端的选项，各个子命令相同
+fn connection_flags() -> Vec<Flag> {
+    vec![
+        Flag::new(&["--profile"], "使用配置文件中的配置档").value(Value::Text),
+        Flag::new(&["--model"], "使用的模型").value(Value::Text),
+        Flag::new(&["--system"], "系统提示词").value(Value::Text),
+        Flag::new(&["--preset"], "系统提示词预设").value(Value::Text),
+        Flag::new(&["--var"], "预设中的变量").value(Value::Text),
+        Flag::new(&["--base-url"], "服务端地址").value(Value::Text),
+        Flag::new(&["--api-key"], "令牌").value(Value::Text),
+        Flag::new(&["--workspace"], "使用指定的工作区").value(Value::Text),
+    ]
+}
+
+/// 提问的选项
+fn ask_flags() -> Vec<Flag> {
+    let mut flags = connection_flags();
+    flags.extend([
+        Flag::new(&["--resume"], "继续之前的对话"),
+        Flag::new(&["--no-stream"], "等回复完整后再显示"),
+        Flag::new(&["--raw"], "原样输出回复，不渲染Markdown"),
+        Flag::new(&["-f", "--file"], "添加附件").value(Value::File),
+        Flag::new(&["--index"], "先从本地索引中检索资料").value(Value::Text),
+        Flag::new(&["--note"], "把问答写入Obsidian库").value(Value::Dir),
+    ]);
+    flags
+}
+
+pub fn command() -> Command {
+    let keys: Vec<&str> =
+        [config::DEFAULT_PROFILE_KEY].into_iter().chain(config::PROFILE_KEYS.iter().copied()).collect();
+    let profile = || Flag::new(&["--profile"], "使用的配置档").value(Value::Text);
+    let limit = || Flag::new(&["--limit"], "显示的数量").value(Value::Text);
+    Command::new("kimi", "OpenKimi的终端对话客户端")
+        .flags(ask_flags())
+        .flag(Flag::new(&["-h", "--help"], "显示帮助"))
+        .subcommand(Command::new("ask", "只回答一次，不进入交互式对话").flags(ask_flags()))
+        .subcommand(
+            Command::new("index", "为本地目录建立向量索引")
+                .flags(connection_flags())
+                .flags([
+                    Flag::new(&["--name"], "索引名称").value(Value::Text),
+                    Flag::new(&["--embedding-model"], "嵌入模型").value(Value::Text),
+                    Flag::new(&["--rebuild"], "重新建立索引"),
+                    Flag::new(&["--list"], "列出本地索引"),
+                    Flag::new(&["--delete"], "删除索引").value(Value::Text),
+                ])
+                .args(Value::Dir),
+        )
+        .subcommand(
+            Command::new("tokens", "统计token数，判断能否放进上下文")
+                .flags(connection_flags())
+                .flags([
+                    Flag::new(&["--context"], "上下文窗口的token数").value(Value::Text),
+                    Flag::new(&["--reserve"], "为回复预留的token数").value(Value::Text),
+                    Flag::new(&["--split"], "切分后写入的目录").value(Value::Dir),
+                    Flag::new(&["--json"], "以JSON输出"),
+                ])
+                .args(Value::File),
+        )
+        .subcommand(
+            Command::new("models", "列出服务端的模型")
+                .flags(connection_flags())
+                .flag(Flag::new(&["--json"], "以JSON输出")),
+        )
+        .subcommand(
+            Command::new("model", "查看或修改默认模型")
+                .flags(connection_flags())
+                .subcommand(Command::new("show", "显示默认模型"))
+                .subcommand(Command::new("set", "设置默认模型"))
+                .subcommand(Command::new("unset", "改为使用服务端的默认模型")),
+        )
+        .subcommand(
+            Command::new("config", "读写配置文件")
+                .flag(profile())
+                .subcommand(Command::new("get", "读取配置项").flag(profile()).args(Value::choices(&keys)))
+                .subcommand(Command::new("set", "设置配置项").flag(profile()).args(Value::choices(&keys)))
+                .subcommand(Command::new("unset", "删除配置项").flag(profile()).args(Value::choices(&keys)))
+                .subcommand(Command::new("list", "列出全部配置档"))
+                .subcommand(Command::new("path", "显示配置文件的路径")),
+        )
+        .subcommand(
+            Command::new("history", "查看保存的对话")
+                .flag(limit())
+                .subcommand(Command::new("list", "列出最近的对话").flag(limit()))
+                .subcommand(Command::new("search", "搜索对话").flag(limit()))
+                .subcommand(Command::new("show", "显示对话").flag(Flag::new(&["--json"], "以JSON输出")))
+                .subcommand(Command::new("delete", "删除对话")),
+        )
+        .subcommand(
+            Command::new("export", "导出对话")
+                .flag(
+                    Flag::new(&["--format"], "导出格式")
+                        .value(Value::choices(&["md", "json", "html", "obsidian", "notion"])),
+                )
+                .flag(Flag::new(&["--template"], "笔记模板").value(Value::File))
+                .flag(Flag::new(&["-o", "--output"], "输出的文件，--all 或 obsidian 时为目录").value(Value::File))
+                .flag(Flag::new(&["--all"], "导出全部对话")),
+        )
+        .subcommand(Command::new("import", "导入ChatGPT或Claude导出的对话").args(Value::File))
+        .subcommand(Command::new("resume", "继续之前的对话").flags(ask_flags()))
+        .subcommand(
+            Command::new("tui", "全屏的终端界面")
+                .flags(connection_flags())
+                .flag(Flag::new(&["--resume"], "继续之前的对话")),
+        )
+        .subcommand(
+            Command::new("git", "根据改动生成提交信息和PR描述")
+                .subcommand(
+                    Command::new("commit", "生成提交信息，确认后提交")
+                        .flags(connection_flags())
+                        .flags([
+                            Flag::new(&["--hint"], "补充给模型的说明").value(Value::Text),
+                            Flag::new(&["--template"], "提示词模板").value(Value::File),
+                            Flag::new(&["-y", "--yes"], "不确认，直接提交"),
+                            Flag::new(&["--print"], "只输出提交信息"),
+                            Flag::new(&["--pr"], "提交后生成PR描述"),
+                        ]),
+                )
+                .subcommand(
+                    Command::new("pr", "生成PR的标题和正文")
+                        .flags(connection_flags())
+                        .flags([
+                            Flag::new(&["--base"], "基准分支").value(Value::Text),
+                            Flag::new(&["--hint"], "补充给模型的说明").value(Value::Text),
+                            Flag::new(&["--template"], "提示词模板").value(Value::File),
+                            Flag::new(&["-o", "--output"], "写入的文件").value(Value::File),
+                        ]),
+                ),
+        )
+        .subcommand(
+            Command::new("sh", "把需求写成一条shell命令，确认后执行")
+                .flags(connection_flags())
+                .flags([
+                    Flag::new(&["--shell"], "执行命令的shell").value(Value::Text),
+                    Flag::new(&["--print"], "只输出命令"),
+                ]),
+        )
+        .subcommand(
+            Command::new("preset", "管理系统提示词预设")
+                .flags(connection_flags())
+                .subcommand(Command::new("list", "列出本地和服务端的预设"))
+                .subcommand(Command::new("show", "显示渲染后的预设"))
+                .subcommand(Command::new("push", "把本地的预设保存到服务端"))
+                .subcommand(Command::new("pull", "把服务端的预设保存到本地").flag(Flag::new(&["--force"], "覆盖本地的预设"))),
+        )
+        .subcommand(Command::new("doctor", "诊断连接服务端的问题").flags(connection_flags()))
+        .subcommand(
+            Command::new("completions", "生成命令行补全脚本").args(Value::choices(openkimi_completions::Shell::NAMES)),
+        )
+}
@@ -0,0 +1,381 @@
+//! 配置文件与配置档
+//!
+//! 配置文件为`$OPENKIMI_CONFIG`，未设置时Windows为`%APPDATA%\OpenKimi\config.toml`，
+//! 其他平台为`$XDG_CONFIG_HOME/openkimi/config.toml`（默认`~/.config/openkimi/config.toml`）：
+//!
+//! ```toml
+//! # 不指定 --profile 时使用的配置档
+//! profile = "work"
+//!
+//! [profiles.work]
+//! base_url = "https://kimi.example.com/v1"
+//! api_key = "ok-..."
+//! model = "kimi"
+//! system = "回答使用中文"
+//! workspace = "team"
+//!
+//! [profiles.review]
+//! preset = "reviewer"
+//! ```
+//!
+//! 只支持TOML中的字符串值和`[profiles.<名称>]`表，足够描述配置档；修改时逐行替换，保留注释和其他内容。
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// 配置档中的字段
+pub const PROFILE_KEYS: &[&str] = &["base_url", "api_key", "model", "system", "preset", "workspace"];
+
+/// 顶层的默认配置档名称
+pub const DEFAULT_PROFILE_KEY: &str = "profile";
+
+/// 一个配置档，未设置的字段为`None`
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    pub base_url: Option<String>,
+    pub api_key: Option<String>,
+    pub model: Option<String>,
+    pub system: Option<String>,
+    /// 没有`system`时使用的预设，见[`crate::preset`]
+    pub preset: Option<String>,
+    pub workspace: Option<String>,
+}
+
+/// 配置文件的一行
+enum Line {
+    /// 空行、注释和不认识的内容，原样保留
+    Other(String),
+    Table(String),
+    Entry { key: String, value: String },
+}
+
+/// 配置文件
+pub struct ConfigFile {
+    path: PathBuf,
+    lines: Vec<Line>,
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("OPENKIMI_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let dir = if cfg!(windows) {
+        PathBuf::from(env::var_os("APPDATA")?).join("OpenKimi")
+    } else {
+        match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir).join("openkimi"),
+            None => PathBuf::from(env::var_os("HOME")?).join(".config/openkimi"),
+        }
+    };
+    Some(dir.join("config.toml"))
+}
+
+/// 模板：`option`指定的文件，否则为配置文件所在目录下的`templates/<name>.md`，都没有时为`default`
+pub fn template(name: &str, option: Option<&Path>, default: &str) -> Result<String, String> {
+    let path = match option {
+        Some(path) => Some(path.to_path_buf()),
+        None => default_path()
+            .and_then(|path| Some(path.parent()?.join("templates").join(format!("{}.md", name))))
+            .filter(|path| path.is_file()),
+    };
+    match path {
+        Some(path) => fs::read_to_string(&path).map_err(|e| format!("读取模板 {} 失败: {}", path.display(), e)),
+        None => Ok(default.to_string()),
+    }
+}
+
+/// 配置档名称只允许字母、数字、`-`和`_`，写入表名时不需要引号
+pub fn check_profile_name(name: &str) -> Result<(), String> {
+    if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        Ok(())
+    } else {
+        Err(format!("无效的配置档名称: {}，只能包含字母、数字、- 和 _", name))
+    }
+}
+
+fn table(profile: &str) -> String {
+    format!("profiles.{}", profile)
+}
+
+/// 解析字符串值，支持基本字符串（带转义）和字面量字符串
+fn parse_string(raw: &str) -> Option<String> {
+    if let Some(literal) = raw.strip_prefix('\'') {
+        return Some(literal.strip_suffix('\'')?.to_string());
+    }
+    let mut chars = raw.strip_prefix('"')?.strip_suffix('"')?.chars();
+    let mut value = String::new();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => value.push('\n'),
+            't' => value.push('\t'),
+            'r' => value.push('\r'),
+            '"' => value.push('"'),
+            '\\' => value.push('\\'),
+            'u' => {
+                let hex: String = chars.by_ref().take(4).collect();
+                value.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+            }
+            _ => return None,
+        }
+    }
+    Some(value)
+}
+
+fn quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// 去掉值后面的注释，引号中的`#`不算
+fn strip_comment(raw: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (index, c) in raw.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return raw[..index].trim_end(),
+            _ => {}
+        }
+        escaped = false;
+    }
+    raw.trim_end()
+}
+
+fn parse_line(line: &str, number: usize) -> Result<Line, String> {
+    let trimmed = line.trim();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
+        return Ok(Line::Other(line.to_string()));
+    }
+    if let Some(name) = trimmed.strip_prefix('[') {
+        let name = strip_comment(name);
+        let name = name.strip_suffix(']').ok_or_else(|| format!("第 {} 行: 表名缺少 ]", number))?;
+        return Ok(Line::Table(name.split('.').map(str::trim).collect::<Vec<_>>().join(".")));
+    }
+    let (key, raw) = trimmed.split_once('=').ok_or_else(|| format!("第 {} 行: 应为 键 = 值", number))?;
+    let value = parse_string(strip_comment(raw.trim()))
+        .ok_or_else(|| format!("第 {} 行: {} 的值应为带引号的字符串", number, key.trim()))?;
+    Ok(Line::Entry {
+        key: key.trim().to_string(),
+        value,
+    })
+}
+
+impl ConfigFile {
+    /// 读取配置文件，文件不存在时为空
+    pub fn load(path: PathBuf) -> Result<ConfigFile, String> {
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(format!("读取配置文件 {} 失败: {}", path.display(), err)),
+        };
+        let lines = content
+            .lines()
+            .enumerate()
+            .map(|(index, line)| parse_line(line, index + 1))
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("配置文件 {} {}", path.display(), e))?;
+        Ok(ConfigFile { path, lines })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 各项及其所在的表，顶层的表为空字符串
+    fn entries(&self) -> impl Iterator<Item = (usize, &str, &str, &str)> {
+        let mut current = "";
+        self.lines.iter().enumerate().filter_map(move |(index, line)| match line {
+            Line::Table(name) => {
+                current = name;
+                None
+            }
+            Line::Entry { key, value } => Some((index, current, key.as_str(), value.as_str())),
+            Line::Other(_) => None,
+        })
+    }
+
+    fn get(&self, table: &str, key: &str) -> Option<String> {
+        self.entries()
+            .filter(|(_, current, name, _)| *current == table && *name == key)
+            .last()
+            .map(|(_, _, _, value)| value.to_string())
+    }
+
+    fn set(&mut self, table: &str, key: &str, value: &str) {
+        let line = Line::Entry {
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+        let existing = self.entries().find(|(_, current, name, _)| *current == table && *name == key);
+        if let Some(index) = existing.map(|(index, ..)| index) {
+            self.lines[index] = line;
+            return;
+        }
+        // 插在表中最后一项之后；顶层的项插在第一个表之前
+        let start = if table.is_empty() {
+            Some(0)
+        } else {
+            self.lines.iter().position(|line| matches!(line, Line::Table(name) if name == table)).map(|i| i + 1)
+        };
+        match start {
+            Some(start) => {
+                let end = self.lines[start..]
+                    .iter()
+                    .position(|line| matches!(line, Line::Table(_)))
+                    .map_or(self.lines.len(), |offset| start + offset);
+                let last_entry = self.lines[start..end]
+                    .iter()
+                    .rposition(|line| matches!(line, Line::Entry { .. }))
+                    .map_or(start, |offset| start + offset + 1);
+                self.lines.insert(last_entry, line);
+                if table.is_empty() && matches!(self.lines.get(last_entry + 1), Some(Line::Table(_))) {
+                    self.lines.insert(last_entry + 1, Line::Other(String::new()));
+                }
+            }
+            None => {
+                if self.lines.last().is_some_and(|line| !matches!(line, Line::Other(text) if text.trim().is_empty())) {
+                    self.lines.push(Line::Other(String::new()));
+                }
+                self.lines.push(Line::Table(table.to_string()));
+                self.lines.push(line);
+            }
+        }
+    }
+
+    fn unset(&mut self, table: &str, key: &str) -> bool {
+        let before = self.lines.len();
+        let indexes: Vec<usize> = self
+            .entries()
+            .filter(|(_, current, name, _)| *current == table && *name == key)
+            .map(|(index, ..)| index)
+            .collect();
+        for index in indexes.into_iter().rev() {
+            self.lines.remove(index);
+        }
+        self.lines.len() != before
+    }
+
+    /// 写回文件；文件中可能有令牌，Unix上只允许所有者读写
+    pub fn save(&self) -> Result<(), String> {
+        let mut content = String::new();
+        for line in &self.lines {
+            match line {
+                Line::Other(text) => content.push_str(text),
+                Line::Table(name) => content.push_str(&format!("[{}]", name)),
+                Line::Entry { key, value } => content.push_str(&format!("{} = {}", key, quote(value))),
+            }
+            content.push('\n');
+        }
+        let write = || -> io::Result<()> {
+            if let Some(parent) = self.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&self.path, content)?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&self.path, fs::Permissions::from_mode(0o600))?;
+            }
+            Ok(())
+        };
+        write().map_err(|e| format!("写入配置文件 {} 失败: {}", self.path.display(), e))
+    }
+
+    /// 默认的配置档名称；没有设置`profile`时，存在名为default的配置档就使用它，与写入时一致
+    pub fn default_profile(&self) -> Option<String> {
+        self.get("", DEFAULT_PROFILE_KEY)
+            .or_else(|| self.profile_names().into_iter().find(|name| name == "default"))
+    }
+
+    /// 全部配置档的名称，按在文件中出现的顺序
+    pub fn profile_names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        for line in &self.lines {
+            if let Line::Table(name) = line {
+                if let Some(profile) = name.strip_prefix("profiles.") {
+                    if !names.iter().any(|existing| existing == profile) {
+                        names.push(profile.to_string());
+                    }
+                }
+            }
+        }
+        names
+    }
+
+    pub fn profile(&self, name: &str) -> Option<Profile> {
+        if !self.profile_names().iter().any(|existing| existing == name) {
+            return None;
+        }
+        let table = table(name);
+        let field = |key| self.get(&table, key);
+        Some(Profile {
+            base_url: field("base_url"),
+            api_key: field("api_key"),
+            model: field("model"),
+            system: field("system"),
+            preset: field("preset"),
+            workspace: field("workspace"),
+        })
+    }
+
+    /// 读取配置档中的一项，`profile`键为默认配置档名称
+    pub fn get_value(&self, profile: &str, key: &str) -> Result<Option<String>, String> {
+        if key == DEFAULT_PROFILE_KEY {
+            return Ok(self.default_profile());
+        }
+        check_key(key)?;
+        Ok(self.get(&table(profile), key))
+    }
+
+    pub fn set_value(&mut self, profile: &str, key: &str, value: &str) -> Result<(), String> {
+        if key == DEFAULT_PROFILE_KEY {
+            check_profile_name(value)?;
+            self.set("", key, value);
+            return Ok(());
+        }
+        check_key(key)?;
+        check_profile_name(profile)?;
+        self.set(&table(profile), key, value);
+        Ok(())
+    }
+
+    /// 删除一项，返回是否存在
+    pub fn unset_value(&mut self, profile: &str, key: &str) -> Result<bool, String> {
+        if key == DEFAULT_PROFILE_KEY {
+            return Ok(self.unset("", key));
+        }
+        check_key(key)?;
+        Ok(self.unset(&table(profile), key))
+    }
+}
+
+fn check_key(key: &str) -> Result<(), String> {
+    if PROFILE_KEYS.contains(&key) {
+        Ok(())
+    } else {
+        Err(format!("未知的配置项: {}，可用的有 {}、{}", key, DEFAULT_PROFILE_KEY, PROFILE_KEYS.join("、")))
+    }
+}
@@ -0,0 +1,354 @@
+//! 对话的保存、检索与导出
+//!
+//! 对话保存在数据目录下的`sessions.db`中，与服务端和桌面客户端使用同一个会话库（[`openkimi_sessions`]），
+//! 可以全文检索，也可以用服务端的导入导出接口迁移，导出的格式与服务端相同（[`openkimi_sessions::export`]）。
+//! 每轮回复后写入，进程被中断也不会丢失之前的内容；重新生成回复时从提问之后开出新的分支，原来的回复仍然保留。
+//! 系统提示词保存在会话的`metadata.system`中。
+//!
+//! 数据目录为`$OPENKIMI_CLI_HOME`，未设置时：Windows为`%APPDATA%\OpenKimi\cli`，
+//! macOS为`~/Library/Application Support/OpenKimi/cli`，其他平台为`$XDG_DATA_HOME/openkimi/cli`
+//! （默认`~/.local/share/openkimi/cli`）。
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use openkimi_client::types::MessageContent;
+use openkimi_client::ChatMessage;
+use openkimi_sessions::external;
+use openkimi_sessions::{
+    ExternalImport, NewAttachment, NewMessage, NewSession, SearchHit, Session, SessionError, SessionQuery, SessionStore, SessionUpdate, TokenUsage,
+};
+use serde_json::{json, Map, Value};
+
+/// 写入会话`metadata.client`，区分终端中开始的对话
+const CLIENT: &str = "kimi-cli";
+
+/// 一个对话，`messages`不含系统提示词
+#[derive(Debug, Clone)]
+pub struct Conversation {
+    /// 第一次保存时生成
+    pub id: Option<String>,
+    pub title: String,
+    /// 为空时使用服务端的默认模型
+    pub model: String,
+    pub system: Option<String>,
+    /// Unix秒
+    pub updated: i64,
+    metadata: Map<String, Value>,
+    messages: Vec<ChatMessage>,
+    /// 已保存的消息id，对应`messages`的开头
+    stored: Vec<i64>,
+    /// 未保存的消息的用量和附件，对应`messages`中`stored`之后的部分
+    usage: Vec<Option<TokenUsage>>,
+    attachments: Vec<Vec<NewAttachment>>,
+    /// 撤回了已保存的消息，下次保存时从最后一条已保存的消息之后开出新的分支
+    fork: bool,
+    /// 模型或系统提示词有修改
+    dirty: bool,
+}
+
+pub fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// 列表中显示的会话id，`resume`和`show`也接受这样的前缀；从其他地方导入的非默认格式id不缩短
+pub fn short_id(id: &str) -> &str {
+    if id.len() == 32 && id.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        &id[..8]
+    } else {
+        id
+    }
+}
+
+impl Conversation {
+    pub fn new(model: String, system: Option<String>) -> Conversation {
+        let mut metadata = Map::new();
+        metadata.insert("client".to_string(), json!(CLIENT));
+        Conversation {
+            id: None,
+            title: String::new(),
+            model,
+            system,
+            updated: now(),
+            metadata,
+            messages: Vec::new(),
+            stored: Vec::new(),
+            usage: Vec::new(),
+            attachments: Vec::new(),
+            fork: false,
+            dirty: false,
+        }
+    }
+
+    pub fn messages(&self) -> &[ChatMessage] {
+        &self.messages
+    }
+
+    pub fn push(&mut self, message: ChatMessage, usage: Option<TokenUsage>) {
+        self.messages.push(message);
+        self.usage.push(usage);
+        self.attachments.push(Vec::new());
+    }
+
+    /// 追加带附件的用户消息，附件信息随消息保存
+    pub fn push_attached(&mut self, message: ChatMessage, attachments: Vec<NewAttachment>) {
+        self.messages.push(message);
+        self.usage.push(None);
+        self.attachments.push(attachments);
+    }
+
+    /// 撤回最后一条消息；已保存的消息不删除，之后的消息保存在新的分支上
+    pub fn pop(&mut self) -> Option<ChatMessage> {
+        if self.messages.len() > self.stored.len() {
+            self.usage.pop();
+            self.attachments.pop();
+        } else if self.stored.pop().is_some() {
+            self.fork = true;
+        }
+        self.messages.pop()
+    }
+
+    pub fn set_model(&mut self, model: String) {
+        self.model = model;
+        self.dirty = true;
+    }
+
+    pub fn set_system(&mut self, system: Option<String>) {
+        self.system = system;
+        self.dirty = true;
+    }
+
+    /// 发送给服务端的消息，系统提示词在最前
+    pub fn request_messages(&self) -> Vec<ChatMessage> {
+        self.system
+            .iter()
+            .map(|system| ChatMessage::system(system.clone()))
+            .chain(self.messages.iter().cloned())
+            .collect()
+    }
+
+    fn session_metadata(&self) -> Map<String, Value> {
+        let mut metadata = self.metadata.clone();
+        match &self.system {
+            Some(system) => metadata.insert("system".to_string(), json!(system)),
+            None => metadata.remove("system"),
+        };
+        metadata
+    }
+
+    /// 导出为与服务端`GET /v1/sessions/{id}/export`相同格式的会话文档
+    pub fn to_document(&self) -> Value {
+        json!({
+            "version": 1,
+            "id": self.id,
+            "title": self.title,
+            "model": (!self.model.is_empty()).then_some(&self.model),
+            "metadata": self.session_metadata(),
+            "updated_at": self.updated,
+            "messages": self.messages,
+        })
+    }
+}
+
+fn new_message(message: &ChatMessage, usage: Option<TokenUsage>, attachments: Vec<NewAttachment>) -> NewMessage {
+    let mut extra = message.extra.clone();
+    if let Some(name) = &message.name {
+        extra.insert("name".to_string(), json!(name));
+    }
+    NewMessage {
+        role: message.role.clone(),
+        content: message.text(),
+        extra,
+        attachments,
+        usage,
+        ..NewMessage::default()
+    }
+}
+
+fn chat_message(message: openkimi_sessions::Message) -> ChatMessage {
+    ChatMessage {
+        role: message.role,
+        content: Some(MessageContent::Text(message.content)),
+        name: None,
+        extra: message.extra,
+    }
+}
+
+/// 对话和本地索引所在的目录
+pub fn data_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("OPENKIMI_CLI_HOME") {
+        return Some(PathBuf::from(dir));
+    }
+    let dir = if cfg!(windows) {
+        PathBuf::from(env::var_os("APPDATA")?).join("OpenKimi")
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from(env::var_os("HOME")?).join("Library/Application Support/OpenKimi")
+    } else {
+        match env::var_os("XDG_DATA_HOME") {
+            Some(dir) => PathBuf::from(dir).join("openkimi"),
+            None => PathBuf::from(env::var_os("HOME")?).join(".local/share/openkimi"),
+        }
+    };
+    Some(dir.join("cli"))
+}
+
+/// 会话库，接口是阻塞的
+pub struct Store {
+    path: PathBuf,
+    sessions: SessionStore,
+    runtime: tokio::runtime::Runtime,
+}
+
+impl Store {
+    pub fn open() -> Result<Store, String> {
+        let dir = data_dir().ok_or("无法确定数据目录，请设置 OPENKIMI_CLI_HOME")?;
+        fs::create_dir_all(&dir).map_err(|e| format!("创建数据目录 {} 失败: {}", dir.display(), e))?;
+        let path = dir.join("sessions.db");
+        let sessions = SessionStore::open(&path).map_err(|e| format!("打开会话库 {} 失败: {}", path.display(), e))?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .map_err(|e| format!("创建运行时失败: {}", e))?;
+        Ok(Store {
+            path,
+            sessions,
+            runtime,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn session(&self, id: &str) -> Result<Session, SessionError> {
+        self.runtime.block_on(self.sessions.session(id))
+    }
+
+    /// 保存新消息和修改；还没有消息的对话不保存
+    pub fn save(&self, conversation: &mut Conversation) -> Result<(), String> {
+        let id = match &conversation.id {
+            Some(id) => {
+                if conversation.dirty {
+                    let update = SessionUpdate {
+                        model: Some(conversation.model.clone()),
+                        metadata: Some(conversation.session_metadata()),
+                        ..SessionUpdate::default()
+                    };
+                    self.runtime.block_on(self.sessions.update_session(id, update)).map_err(|e| e.to_string())?;
+                }
+                id.clone()
+            }
+            None if conversation.messages.is_empty() => return Ok(()),
+            None => {
+                let new = NewSession {
+                    model: Some(conversation.model.clone()).filter(|model| !model.is_empty()),
+                    metadata: conversation.session_metadata(),
+                    ..NewSession::default()
+                };
+                let session = self.runtime.block_on(self.sessions.create_session(new)).map_err(|e| e.to_string())?;
+                conversation.id = Some(session.id.clone());
+                session.id
+            }
+        };
+        conversation.dirty = false;
+
+        let saved = conversation.stored.len();
+        if conversation.messages.len() > saved {
+            let new: Vec<NewMessage> = conversation.messages[saved..]
+                .iter()
+                .zip(conversation.usage.drain(..).zip(conversation.attachments.drain(..)))
+                .map(|(message, (usage, attachments))| new_message(message, usage, attachments))
+                .collect();
+            let inserted = if conversation.fork {
+                self.runtime.block_on(self.sessions.branch_messages(&id, conversation.stored.last().copied(), new))
+            } else {
+                self.runtime.block_on(self.sessions.append_messages(&id, new))
+            };
+            conversation.stored.extend(inserted.map_err(|e| e.to_string())?.iter().map(|message| message.id));
+            conversation.fork = false;
+        }
+        let session = self.session(&id).map_err(|e| e.to_string())?;
+        conversation.title = session.title;
+        conversation.updated = session.updated_at;
+        Ok(())
+    }
+
+    /// 按完整的id或唯一的前缀查找会话
+    fn resolve(&self, id: &str) -> Result<Session, String> {
+        match self.session(id) {
+            Ok(session) => return Ok(session),
+            Err(SessionError::NotFound(_)) => {}
+            Err(err) => return Err(err.to_string()),
+        }
+        let query = SessionQuery {
+            limit: u32::MAX,
+            offset: 0,
+        };
+        let sessions = self.runtime.block_on(self.sessions.list_sessions(query)).map_err(|e| e.to_string())?;
+        let mut matched = sessions.into_iter().filter(|session| session.id.starts_with(id));
+        match (matched.next(), matched.next()) {
+            (Some(session), None) => Ok(session),
+            (Some(_), Some(_)) => Err(format!("有多个对话以 {} 开头，请输入更长的id", id)),
+            (None, _) => Err(format!("对话 {} 不存在", id)),
+        }
+    }
+
+    pub fn load(&self, id: &str) -> Result<Conversation, String> {
+        let session = self.resolve(id)?;
+        let messages = self.runtime.block_on(self.sessions.messages(&session.id)).map_err(|e| e.to_string())?;
+        let mut conversation = Conversation::new(session.model.unwrap_or_default(), None);
+        conversation.system = session.metadata.get("system").and_then(Value::as_str).map(str::to_string);
+        conversation.metadata = session.metadata;
+        conversation.id = Some(session.id);
+        conversation.title = session.title;
+        conversation.updated = session.updated_at;
+        conversation.stored = messages.iter().map(|message| message.id).collect();
+        conversation.messages = messages.into_iter().map(chat_message).collect();
+        Ok(conversation)
+    }
+
+    /// 最近修改的会话，最新的在前
+    pub fn list(&self, limit: u32) -> Result<Vec<Session>, String> {
+        let query = SessionQuery { limit, offset: 0 };
+        self.runtime.block_on(self.sessions.list_sessions(query)).map_err(|e| e.to_string())
+    }
+
+    pub fn latest(&self) -> Result<Conversation, String> {
+        match self.list(1)?.into_iter().next() {
+            Some(session) => self.load(&session.id),
+            None => Err("还没有保存的对话".to_string()),
+        }
+    }
+
+    pub fn search(&self, query: &str, limit: u32) -> Result<Vec<SearchHit>, String> {
+        self.runtime.block_on(self.sessions.search(query, limit)).map_err(|e| e.to_string())
+    }
+
+    /// 导出为会话文档，包含消息的时间、附件和用量
+    pub fn export(&self, id: &str) -> Result<(Session, Value), String> {
+        let session = self.resolve(id)?;
+        let document = self.runtime.block_on(self.sessions.export(&session.id)).map_err(|e| e.to_string())?;
+        Ok((session, document))
+    }
+
+    /// 导入ChatGPT或Claude导出的对话，导入过的跳过
+    pub fn import_external(&self, conversations: Vec<external::Conversation>) -> Result<ExternalImport, String> {
+        self.runtime.block_on(self.sessions.import_external(conversations)).map_err(|e| e.to_string())
+    }
+
+    /// 删除会话，返回完整的id
+    pub fn delete(&self, id: &str) -> Result<String, String> {
+        let session = self.resolve(id)?;
+        self.runtime.block_on(self.sessions.delete_session(&session.id)).map_err(|e| e.to_string())?;
+        Ok(session.id)
+    }
+}
+
+impl Drop for Store {
+    fn drop(&mut self) {
+        let _ = self.runtime.block_on(self.sessions.checkpoint());
+    }
+}
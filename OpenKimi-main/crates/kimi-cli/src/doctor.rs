@@ -0,0 +1,500 @@
+//! `kimi doctor`：诊断连接服务端的问题
+//!
+//! 依次检查配置文件和配置档、服务端地址、代理、DNS解析、TCP连接、TLS和HTTP、令牌以及系统时间，每项输出
+//! ✅/⚠️/❌，有问题时给出处理建议。代理按`HTTPS_PROXY`、`HTTP_PROXY`、`ALL_PROXY`和`NO_PROXY`判断，与发送请求时
+//! 相同；经过代理时DNS和TCP检查的是代理。令牌用一次`GET /models`检验，不消耗用量；系统时间与响应的`Date`头比较。
+//! 前面的检查失败时跳过依赖它的检查，有❌时退出码为1。
+
+use std::env;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant, SystemTime};
+
+use hyper_util::client::proxy::matcher::Matcher;
+use openkimi_client::{ClientConfig, ModelList, WORKSPACE_HEADER};
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE, DATE};
+use reqwest::{StatusCode, Url};
+
+use crate::config::{ConfigFile, Profile};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+/// 超过时提醒
+const SKEW_WARNING: Duration = Duration::from_secs(30);
+/// 超过时证书和令牌的有效期判断会出错
+const SKEW_ERROR: Duration = Duration::from_secs(300);
+
+pub struct Input {
+    pub file: Result<ConfigFile, String>,
+    /// 使用的配置档
+    pub profile: Option<String>,
+    /// 合并命令行、配置档和环境变量的结果
+    pub resolved: Result<(ClientConfig, Profile), String>,
+}
+
+/// 检查结果的计数
+#[derive(Default)]
+struct Report {
+    problems: usize,
+    warnings: usize,
+}
+
+impl Report {
+    fn ok(&self, message: impl AsRef<str>) {
+        println!("✅ {}", message.as_ref());
+    }
+
+    fn info(&self, message: impl AsRef<str>) {
+        println!("ℹ️ {}", message.as_ref());
+    }
+
+    fn warn(&mut self, message: impl AsRef<str>, hint: impl AsRef<str>) {
+        self.warnings += 1;
+        println!("⚠️ {}", message.as_ref());
+        println!("   → {}", hint.as_ref());
+    }
+
+    fn fail(&mut self, message: impl AsRef<str>, hint: impl AsRef<str>) {
+        self.problems += 1;
+        println!("❌ {}", message.as_ref());
+        println!("   → {}", hint.as_ref());
+    }
+}
+
+/// 配置文件和配置档，返回使用的配置
+fn check_config(report: &mut Report, input: Input) -> Option<(ClientConfig, Profile)> {
+    match &input.file {
+        Ok(file) if file.path().exists() => {
+            report.ok(format!("配置文件 {}", file.path().display()));
+            check_permissions(report, file);
+        }
+        Ok(file) => report.ok(format!(
+            "配置文件 {} 不存在，使用命令行、环境变量和默认值",
+            file.path().display()
+        )),
+        Err(err) => report.fail(err, "按提示修改配置文件；kimi config path 显示它的位置"),
+    }
+    let resolved = match input.resolved {
+        Ok(resolved) => resolved,
+        Err(err) => {
+            let hint = match &input.file {
+                Ok(file) if !file.profile_names().is_empty() => {
+                    format!(
+                        "可用的配置档: {}；kimi config list 查看各配置档",
+                        file.profile_names().join(", ")
+                    )
+                }
+                _ => "用 kimi config set base_url <地址> --profile <名称> 创建配置档".to_string(),
+            };
+            report.fail(err, hint);
+            return None;
+        }
+    };
+    match &input.profile {
+        Some(profile) => report.ok(format!("使用配置档 {}", profile)),
+        None => report.ok("没有使用配置档"),
+    }
+    let overrides: Vec<&str> = [
+        "OPENKIMI_PROFILE",
+        "OPENKIMI_BASE_URL",
+        "OPENKIMI_API_KEY",
+        "OPENKIMI_MODEL",
+    ]
+    .into_iter()
+    .filter(|name| env::var_os(name).is_some_and(|value| !value.is_empty()))
+    .collect();
+    if !overrides.is_empty() {
+        report.info(format!(
+            "设置了环境变量 {}，没有明确指定配置档时优先于配置档",
+            overrides.join("、")
+        ));
+    }
+    Some(resolved)
+}
+
+/// 保存了令牌的配置文件不应让其他用户读取
+#[cfg(unix)]
+fn check_permissions(report: &mut Report, file: &ConfigFile) {
+    use std::os::unix::fs::PermissionsExt;
+    let has_key = file.profile_names().iter().any(|name| file.profile(name).is_some_and(|p| p.api_key.is_some()));
+    let Ok(metadata) = fs::metadata(file.path()) else {
+        return;
+    };
+    if has_key && metadata.permissions().mode() & 0o077 != 0 {
+        report.warn(
+            "配置文件中保存了令牌，但其他用户也可以读取",
+            format!("chmod 600 {}", file.path().display()),
+        );
+    }
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_report: &mut Report, _file: &ConfigFile) {}
+
+/// 服务端地址和令牌的格式
+fn check_url(report: &mut Report, config: &ClientConfig) -> Option<Url> {
+    let url = match Url::parse(&config.base_url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") && url.host_str().is_some() => url,
+        _ => {
+            report.fail(
+                format!("服务端地址 {} 无效", config.base_url),
+                "应为 https://kimi.example.com/v1 这样包含协议和 /v1 的完整地址：kimi config set base_url <地址>",
+            );
+            return None;
+        }
+    };
+    report.ok(format!("服务端地址 {}", config.base_url));
+    if !url.path().trim_end_matches('/').ends_with("/v1") {
+        let suggestion = format!("{}/v1", config.base_url.trim_end_matches('/'));
+        report.warn(
+            "服务端地址通常以 /v1 结尾",
+            format!("如果请求返回404，改为 {}", suggestion),
+        );
+    }
+    let loopback = host_ip(&url).is_some_and(|ip| ip.is_loopback()) || url.host_str() == Some("localhost");
+    match &config.api_key {
+        Some(api_key) if api_key.trim() != api_key || api_key.starts_with(['"', '\'']) => report.warn(
+            "令牌的首尾有空格或引号",
+            "重新设置令牌，不要包含引号和空格：kimi config set api_key <令牌>",
+        ),
+        Some(_) if url.scheme() == "http" && !loopback => {
+            report.warn("通过HTTP以明文发送令牌", "服务端支持时改用 https:// 地址")
+        }
+        Some(_) => report.ok("已设置令牌"),
+        None => report.info("没有设置令牌，服务端启用认证时需要设置"),
+    }
+    Some(url)
+}
+
+/// 地址中的IP，域名时为`None`
+fn host_ip(url: &Url) -> Option<IpAddr> {
+    url.host_str()?.trim_start_matches('[').trim_end_matches(']').parse().ok()
+}
+
+/// 请求会经过的代理，与reqwest的选择相同
+fn check_proxy(report: &mut Report, url: &Url) -> Option<Url> {
+    let uri: http::Uri = url.as_str().parse().ok()?;
+    let Some(intercept) = Matcher::from_system().intercept(&uri) else {
+        report.ok("不经过代理");
+        return None;
+    };
+    let proxy = intercept.uri().to_string();
+    let Ok(proxy) = Url::parse(&proxy) else {
+        report.fail(
+            format!("代理地址 {} 无效", proxy),
+            "检查 HTTPS_PROXY、HTTP_PROXY 和 ALL_PROXY 环境变量",
+        );
+        return None;
+    };
+    let shown = format!(
+        "{}://{}:{}",
+        proxy.scheme(),
+        proxy.host_str().unwrap_or(""),
+        proxy.port_or_known_default().unwrap_or(0)
+    );
+    report.ok(format!("经过代理 {}", shown));
+    let loopback = host_ip(url).is_some_and(|ip| ip.is_loopback()) || url.host_str() == Some("localhost");
+    if loopback {
+        report.warn(
+            "连接本机的服务端也经过了代理",
+            format!(
+                "把 {} 加入 NO_PROXY，如 NO_PROXY=localhost,127.0.0.1",
+                url.host_str().unwrap_or("")
+            ),
+        );
+    }
+    Some(proxy)
+}
+
+/// 解析域名，地址是IP时不需要
+fn check_dns(report: &mut Report, url: &Url, what: &str) -> Option<Vec<SocketAddr>> {
+    let host = url.host_str()?.trim_start_matches('[').trim_end_matches(']');
+    let port = url.port_or_known_default()?;
+    if host_ip(url).is_some() {
+        return (host, port).to_socket_addrs().ok().map(Iterator::collect);
+    }
+    let started = Instant::now();
+    match (host, port).to_socket_addrs() {
+        Ok(addrs) => {
+            let addrs: Vec<SocketAddr> = addrs.collect();
+            let ips: Vec<String> = addrs.iter().map(|addr| addr.ip().to_string()).collect();
+            report.ok(format!(
+                "解析{} {}: {}（{} 毫秒）",
+                what,
+                host,
+                ips.join(", "),
+                started.elapsed().as_millis()
+            ));
+            Some(addrs)
+        }
+        Err(err) => {
+            report.fail(
+                format!("无法解析{} {}: {}", what, host, err),
+                "检查域名的拼写和网络连接；公司内网的域名需要连接VPN或使用内网DNS",
+            );
+            None
+        }
+    }
+}
+
+/// 依次尝试各个地址
+fn check_tcp(report: &mut Report, addrs: &[SocketAddr], what: &str) -> bool {
+    let mut last = None;
+    for addr in addrs {
+        let started = Instant::now();
+        match TcpStream::connect_timeout(addr, CONNECT_TIMEOUT) {
+            Ok(_) => {
+                report.ok(format!(
+                    "连接{} {}（{} 毫秒）",
+                    what,
+                    addr,
+                    started.elapsed().as_millis()
+                ));
+                return true;
+            }
+            Err(err) => last = Some((addr, err)),
+        }
+    }
+    let Some((addr, err)) = last else {
+        return false;
+    };
+    let hint = match err.kind() {
+        io::ErrorKind::ConnectionRefused => format!(
+            "{} 上没有程序监听端口 {}：确认{}已经启动，端口是否正确",
+            addr.ip(),
+            addr.port(),
+            what
+        ),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => {
+            "连接超时：可能被防火墙拦截，或需要连接VPN、设置代理".to_string()
+        }
+        _ => "检查网络连接和防火墙".to_string(),
+    };
+    report.fail(format!("无法连接{} {}: {}", what, addr, err), hint);
+    false
+}
+
+/// 错误及其来源，TLS错误的原因通常在最里层
+fn error_chain(err: &reqwest::Error) -> String {
+    let mut messages = vec![err.to_string()];
+    let mut source = std::error::Error::source(err);
+    while let Some(err) = source {
+        let message = err.to_string();
+        if !messages.iter().any(|existing| existing.contains(&message)) {
+            messages.push(message);
+        }
+        source = err.source();
+    }
+    messages.join(": ")
+}
+
+/// 请求失败的原因和建议
+fn request_error(report: &mut Report, base_url: &str, err: &reqwest::Error) {
+    let chain = error_chain(err);
+    let lower = chain.to_lowercase();
+    let hint = if lower.contains("expired") || lower.contains("notvalidyet") {
+        "服务端的证书已过期或尚未生效；先确认本机的系统时间正确".to_string()
+    } else if lower.contains("certificate") || lower.contains("unknownissuer") {
+        "服务端的证书不受信任：kimi使用内置的公共根证书，不读取系统证书库，自签名证书或公司代理签发的证书\
+         需要换成公共CA签发的证书"
+            .to_string()
+    } else if let Some(rest) = base_url.strip_prefix("https://").filter(|_| lower.contains("invalidcontenttype")) {
+        format!("服务端可能没有启用HTTPS，试试 http://{}", rest)
+    } else if err.is_timeout() {
+        format!(
+            "{} 秒内没有响应：服务端过载，或代理、防火墙拦截了请求",
+            REQUEST_TIMEOUT.as_secs()
+        )
+    } else {
+        "检查服务端地址和代理设置".to_string()
+    };
+    report.fail(format!("请求失败: {}", chain), hint);
+}
+
+/// 错误响应中的`error.message`
+fn error_message(body: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(body).ok()?;
+    Some(value["error"]["message"].as_str()?.to_string())
+}
+
+/// 用`GET /models`检查TLS、HTTP和令牌，再比较系统时间
+fn check_request(report: &mut Report, config: &ClientConfig, model: Option<&str>) {
+    let url = format!("{}/models", config.base_url.trim_end_matches('/'));
+    let http = match reqwest::blocking::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+    {
+        Ok(http) => http,
+        Err(err) => {
+            report.fail(format!("无法创建HTTP客户端: {}", err), "检查代理环境变量的格式");
+            return;
+        }
+    };
+    let mut request = http.get(&url);
+    if let Some(api_key) = &config.api_key {
+        request = request.header(AUTHORIZATION, format!("Bearer {}", api_key));
+    }
+    if let Some(workspace) = &config.workspace {
+        request = request.header(WORKSPACE_HEADER, workspace);
+    }
+    let started = Instant::now();
+    let sent = SystemTime::now();
+    let response = match request.send() {
+        Ok(response) => response,
+        Err(err) => {
+            request_error(report, &config.base_url, &err);
+            return;
+        }
+    };
+    let elapsed = started.elapsed();
+    let tls = if url.starts_with("https://") {
+        "TLS握手成功，"
+    } else {
+        ""
+    };
+    report.ok(format!(
+        "{}服务端响应 {}（{} 毫秒）",
+        tls,
+        response.status(),
+        elapsed.as_millis()
+    ));
+
+    // 以请求的中点作为服务端生成`Date`的时刻
+    let date = response.headers().get(DATE).and_then(|date| httpdate::parse_http_date(date.to_str().ok()?).ok());
+    let status = response.status();
+    let json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("json"));
+    let body = response.text().unwrap_or_default();
+    check_status(report, config, model, status, json, &body);
+    match date {
+        Some(date) => check_clock(report, sent + elapsed / 2, date),
+        None => report.info("响应中没有Date头，跳过系统时间的检查"),
+    }
+}
+
+fn check_status(
+    report: &mut Report,
+    config: &ClientConfig,
+    model: Option<&str>,
+    status: StatusCode,
+    json: bool,
+    body: &str,
+) {
+    let message = error_message(body).map(|message| format!("：{}", message)).unwrap_or_default();
+    match status.as_u16() {
+        200..=299 => match serde_json::from_str::<ModelList>(body) {
+            Ok(models) => {
+                let auth = if config.api_key.is_some() {
+                    "令牌有效，"
+                } else {
+                    ""
+                };
+                report.ok(format!("{}服务端有 {} 个模型", auth, models.data.len()));
+                if let Some(model) = model {
+                    if models.data.iter().all(|known| known.id != model) {
+                        let names: Vec<&str> = models.data.iter().map(|known| known.id.as_str()).collect();
+                        report.warn(
+                            format!("服务端没有配置的模型 {}", model),
+                            format!("可用的模型: {}；kimi model set <模型> 修改", names.join(", ")),
+                        );
+                    }
+                }
+            }
+            Err(_) if !json => report.fail(
+                "服务端返回的不是JSON，地址可能指向网页而不是API",
+                "确认服务端地址是API的地址，通常以 /v1 结尾",
+            ),
+            Err(err) => report.fail(
+                format!("无法解析模型列表: {}", err),
+                "服务端可能不兼容OpenAI API，确认地址指向OpenKimi服务端",
+            ),
+        },
+        401 if config.api_key.is_none() => report.fail(
+            format!("服务端要求认证{}", message),
+            "设置令牌：kimi config set api_key <令牌>，或设置 OPENKIMI_API_KEY",
+        ),
+        401 => report.fail(
+            format!("令牌无效{}", message),
+            "令牌可能输错、已过期或被禁用，向管理员申请新的令牌后 kimi config set api_key <令牌>",
+        ),
+        403 => {
+            let hint = match &config.workspace {
+                Some(workspace) => format!("令牌没有访问工作区 {} 的权限，去掉 workspace 或向管理员申请", workspace),
+                None => "令牌没有列出模型的权限，向管理员确认令牌的权限范围".to_string(),
+            };
+            report.fail(format!("没有权限{}", message), hint);
+        }
+        404 => report.fail(
+            format!("服务端没有 {}/models", config.base_url.trim_end_matches('/')),
+            "服务端地址应包含 /v1，如 https://kimi.example.com/v1",
+        ),
+        407 => report.fail(
+            "代理要求认证",
+            "在代理地址中加入用户名和密码，如 HTTPS_PROXY=http://用户名:密码@代理:端口",
+        ),
+        429 => report.warn(format!("请求被限流{}", message), "稍后再试，或向管理员申请更高的限额"),
+        _ => report.fail(
+            format!("服务端返回 {}{}", status, message),
+            "服务端或网关出错，稍后再试；持续出现时联系管理员并提供这个输出",
+        ),
+    }
+}
+
+fn check_clock(report: &mut Report, local: SystemTime, server: SystemTime) {
+    let (skew, direction) = match local.duration_since(server) {
+        Ok(skew) => (skew, "快"),
+        Err(err) => (err.duration(), "慢"),
+    };
+    // `Date`精确到秒
+    let seconds = skew.as_secs();
+    if skew >= SKEW_ERROR {
+        report.fail(
+            format!("系统时间比服务端{} {} 分钟", direction, seconds / 60),
+            "证书和令牌的有效期会判断出错：开启自动同步时间（NTP）",
+        );
+    } else if skew >= SKEW_WARNING {
+        report.warn(
+            format!("系统时间比服务端{} {} 秒", direction, seconds),
+            "开启自动同步时间（NTP）",
+        );
+    } else {
+        report.ok("系统时间与服务端一致");
+    }
+}
+
+pub fn run(input: Input) -> Result<(), String> {
+    let mut report = Report::default();
+    let model = input.resolved.as_ref().ok().and_then(|(_, profile)| profile.model.clone());
+    let checked = check_config(&mut report, input).and_then(|(config, _)| {
+        let url = check_url(&mut report, &config)?;
+        Some((config, url))
+    });
+    if let Some((config, url)) = checked {
+        let proxy = check_proxy(&mut report, &url);
+        let (target, what) = match &proxy {
+            Some(proxy) => (proxy, "代理"),
+            None => (&url, "服务端"),
+        };
+        let reachable = check_dns(&mut report, target, what).is_some_and(|addrs| check_tcp(&mut report, &addrs, what));
+        if reachable {
+            check_request(&mut report, &config, model.as_deref());
+        }
+    }
+    println!();
+    match (report.problems, report.warnings) {
+        (0, 0) => {
+            println!("没有发现问题");
+            Ok(())
+        }
+        (0, warnings) => {
+            println!("没有发现问题，有 {} 个提醒", warnings);
+            Ok(())
+        }
+        (problems, _) => Err(format!("发现 {} 个问题，按 → 后的建议处理", problems)),
+    }
+}
@@ -0,0 +1,430 @@
+//! `kimi git commit`和`kimi git pr`：根据改动生成提交信息和PR描述
+//!
+//! `commit`读取暂存区的改动（`git diff --cached`），生成Conventional Commits格式的提交信息，确认后用`git commit -F`
+//! 提交；确认时可以用`git var GIT_EDITOR`给出的编辑器修改，或补充要求后重新生成。`pr`根据当前分支相对于基准分支的
+//! 提交和改动生成PR的标题和正文，`commit --pr`提交后接着生成。提示词由模板生成，依次取`--template`指定的文件、
+//! 仓库中的`.kimi/templates/commit.md`（`pr.md`）和配置文件所在目录下的`templates/commit.md`（`pr.md`），
+//! 都没有时使用内置的[`COMMIT_TEMPLATE`]和[`PR_TEMPLATE`]。改动超过token上限时只保留开头的部分。
+
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use openkimi_client::blocking::Client;
+use openkimi_client::{ChatCompletionRequest, ChatMessage};
+use openkimi_sessions::notes::render_template;
+use openkimi_tokenizer::{context_window, Tokenizer};
+
+use crate::config;
+
+/// 改动最多占用的token数
+const MAX_DIFF_TOKENS: usize = 12_000;
+
+/// 为提示词的其余部分和回复预留的token数
+const RESERVE_TOKENS: usize = 4096;
+
+/// 提供给模型参考风格的最近提交数
+const RECENT_COMMITS: &str = "10";
+
+/// Conventional Commits的类型
+const TYPES: &[&str] = &[
+    "feat", "fix", "docs", "style", "refactor", "perf", "test", "build", "ci", "chore", "revert",
+];
+
+/// 提交信息的默认模板
+///
+/// 可用的名称：`diff`（暂存的改动）、`stat`（改动的文件）、`branch`（当前分支）、`recent`（最近的提交标题）、
+/// `hint`（`--hint`的说明）。
+pub const COMMIT_TEMPLATE: &str = "\
+根据下面暂存的改动写一条 Git 提交信息。
+
+要求：
+- 使用 Conventional Commits 格式：第一行为 `<类型>(<范围>): <摘要>`，类型为 feat、fix、docs、style、refactor、perf、\
+test、build、ci、chore 或 revert，范围可以省略，第一行不超过 72 个字符，结尾不加句号。
+- 改动较多时，空一行后用几条要点说明改了什么、为什么改；不兼容的改动在末尾加 `BREAKING CHANGE: <说明>`。
+- 使用与最近的提交相同的语言和风格。
+- 只输出提交信息本身，不要加代码块或其他说明。
+
+补充说明：{{hint}}
+
+当前分支：{{branch}}
+
+最近的提交：
+{{recent}}
+
+改动的文件：
+{{stat}}
+
+改动：
+{{diff}}
+";
+
+/// PR描述的默认模板
+///
+/// 可用的名称：`commits`（分支上的提交信息）、`diff`、`stat`、`branch`、`base`（基准分支）、`hint`。
+pub const PR_TEMPLATE: &str = "\
+根据当前分支的提交和改动写一段 Pull Request 描述。
+
+要求：
+- 第一行为 PR 标题，不超过 72 个字符，不加 `#`。
+- 空一行后是 Markdown 正文：先用一两句话说明改了什么、为什么改，再列出主要改动和验证的方法；\
+有不兼容的改动时单独说明。
+- 使用与提交信息相同的语言。
+- 只输出标题和正文，不要加代码块或其他说明。
+
+补充说明：{{hint}}
+
+分支：{{branch}} → {{base}}
+
+提交：
+{{commits}}
+
+改动的文件：
+{{stat}}
+
+改动：
+{{diff}}
+";
+
+pub struct Options {
+    /// 为空时使用服务端的默认模型
+    pub model: String,
+    pub system: Option<String>,
+    pub template: Option<PathBuf>,
+    /// 补充给模型的说明，如关联的问题编号
+    pub hint: Option<String>,
+    /// 不确认，直接提交
+    pub yes: bool,
+    /// 只输出生成的内容
+    pub print: bool,
+    /// 提交后接着生成PR描述
+    pub pr: bool,
+    /// PR的基准分支，默认为`origin/HEAD`、`main`或`master`
+    pub base: Option<String>,
+    /// PR描述写入的文件
+    pub output: Option<PathBuf>,
+    /// 传给`git commit`的其他参数
+    pub git_args: Vec<String>,
+}
+
+/// 运行git，返回标准输出
+fn git(args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git").args(args).output().map_err(|e| format!("运行 git 失败: {}", e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("git {} 失败: {}", args.join(" "), stderr.trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn current_branch() -> String {
+    git(&["branch", "--show-current"])
+        .ok()
+        .map(|branch| branch.trim().to_string())
+        .filter(|branch| !branch.is_empty())
+        .unwrap_or_else(|| "（分离的HEAD）".to_string())
+}
+
+/// 远端的默认分支，没有远端时为本地的`main`或`master`
+fn default_base() -> Result<String, String> {
+    if let Ok(base) = git(&["rev-parse", "--abbrev-ref", "origin/HEAD"]) {
+        return Ok(base.trim().to_string());
+    }
+    ["main", "master"]
+        .into_iter()
+        .find(|name| git(&["rev-parse", "--verify", "--quiet", name]).is_ok())
+        .map(String::from)
+        .ok_or_else(|| "无法确定基准分支，请用 --base 指定".to_string())
+}
+
+/// 仓库中的模板优先于配置文件所在目录下的
+fn template(name: &str, option: Option<&Path>, default: &str) -> Result<String, String> {
+    let local = git(&["rev-parse", "--show-toplevel"])
+        .ok()
+        .map(|root| Path::new(root.trim()).join(".kimi/templates").join(format!("{}.md", name)))
+        .filter(|path| path.is_file());
+    config::template(name, option.or(local.as_deref()), default)
+}
+
+/// 超过上限的改动只保留开头
+fn truncate(diff: &str, model: &str) -> String {
+    let budget = context_window(model).map_or(MAX_DIFF_TOKENS, |window| {
+        (window as usize).saturating_sub(RESERVE_TOKENS).min(MAX_DIFF_TOKENS)
+    });
+    let tokenizer = Tokenizer::for_model(model);
+    if tokenizer.count(diff) <= budget {
+        return diff.trim_end().to_string();
+    }
+    format!(
+        "{}\n…（改动过长，之后的部分已省略）",
+        tokenizer.truncate(diff, budget).trim_end()
+    )
+}
+
+/// 去掉模型有时加上的代码块
+fn clean(text: &str) -> String {
+    let text = text.trim();
+    let Some(rest) = text.strip_prefix("```") else {
+        return text.to_string();
+    };
+    let body = rest.split_once('\n').map_or("", |(_, body)| body);
+    body.trim_end().strip_suffix("```").unwrap_or(body).trim().to_string()
+}
+
+/// 第一行是否为`<类型>(<范围>)!: <摘要>`
+fn is_conventional(message: &str) -> bool {
+    let header = message.lines().next().unwrap_or("");
+    let Some((prefix, summary)) = header.split_once(": ") else {
+        return false;
+    };
+    let prefix = prefix.strip_suffix('!').unwrap_or(prefix);
+    let kind = match prefix.split_once('(') {
+        Some((kind, scope)) if scope.ends_with(')') && scope.len() > 1 => kind,
+        Some(_) => return false,
+        None => prefix,
+    };
+    TYPES.contains(&kind) && !summary.trim().is_empty()
+}
+
+/// 与模型的对话，重新生成和修改时保留之前的内容
+struct Writer<'a> {
+    client: &'a Client,
+    model: String,
+    messages: Vec<ChatMessage>,
+}
+
+impl Writer<'_> {
+    fn new<'a>(client: &'a Client, options: &Options, prompt: String) -> Writer<'a> {
+        let system = options.system.iter().map(|system| ChatMessage::system(system.clone()));
+        Writer {
+            client,
+            model: options.model.clone(),
+            messages: system.chain([ChatMessage::user(prompt)]).collect(),
+        }
+    }
+
+    fn generate(&mut self) -> Result<String, String> {
+        eprintln!("✍️ 正在生成…");
+        let request = ChatCompletionRequest::new(self.model.clone(), self.messages.clone());
+        let response = self.client.chat(&request).map_err(|e| e.to_string())?;
+        let text = clean(&response.text());
+        if text.is_empty() {
+            return Err("模型没有返回内容".to_string());
+        }
+        self.messages.push(ChatMessage::assistant(text.clone()));
+        Ok(text)
+    }
+
+    /// 按补充的要求修改；没有要求时重新生成
+    fn revise(&mut self, request: &str) -> Result<String, String> {
+        if request.is_empty() {
+            self.messages.pop();
+        } else {
+            self.messages.push(ChatMessage::user(format!(
+                "按以下要求修改，只输出修改后的全文：{}",
+                request
+            )));
+        }
+        self.generate()
+    }
+}
+
+fn ask(prompt: &str) -> Result<Option<String>, String> {
+    eprint!("{}", prompt);
+    io::stderr().flush().map_err(|e| e.to_string())?;
+    let mut line = String::new();
+    match io::stdin().lock().read_line(&mut line) {
+        Ok(0) => Ok(None),
+        Ok(_) => Ok(Some(line.trim().to_string())),
+        Err(err) => Err(format!("读取输入失败: {}", err)),
+    }
+}
+
+/// 用git配置的编辑器修改，以`#`开头的行忽略
+fn edit(path: &Path, text: &str) -> Result<String, String> {
+    let editor = git(&["var", "GIT_EDITOR"])?;
+    fs::write(
+        path,
+        format!("{}\n\n# 修改后保存并关闭编辑器，以 # 开头的行会被忽略\n", text),
+    )
+    .map_err(|e| format!("写入 {} 失败: {}", path.display(), e))?;
+    // 与git相同，编辑器的命令可以带参数
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor.trim()))
+        .arg("editor")
+        .arg(path)
+        .status()
+        .map_err(|e| format!("启动编辑器失败: {}", e))?;
+    if !status.success() {
+        return Err(format!("编辑器异常退出: {}", status));
+    }
+    let content = fs::read_to_string(path).map_err(|e| format!("读取 {} 失败: {}", path.display(), e))?;
+    let lines: Vec<&str> = content.lines().filter(|line| !line.starts_with('#')).collect();
+    Ok(lines.join("\n").trim().to_string())
+}
+
+/// 显示生成的内容，确认、编辑或重新生成；取消时为`None`
+fn confirm(
+    writer: &mut Writer,
+    mut text: String,
+    question: &str,
+    file: &Path,
+    commit: bool,
+) -> Result<Option<String>, String> {
+    loop {
+        println!("\n{}\n", text);
+        if commit && !is_conventional(&text) {
+            eprintln!("⚠️ 第一行不是 Conventional Commits 格式（<类型>(<范围>): <摘要>）");
+        }
+        let Some(answer) = ask(&format!("{} [Y]确认 [e]编辑 [r]重新生成 [n]取消: ", question))? else {
+            return Ok(None);
+        };
+        match answer.to_lowercase().as_str() {
+            "" | "y" | "yes" => return Ok(Some(text)),
+            "e" => {
+                let edited = edit(file, &text)?;
+                if edited.is_empty() {
+                    return Ok(None);
+                }
+                text = edited;
+            }
+            "r" => {
+                let request = ask("补充要求（直接回车重新生成）: ")?.unwrap_or_default();
+                text = writer.revise(&request)?;
+            }
+            "n" | "q" => return Ok(None),
+            _ => {}
+        }
+    }
+}
+
+fn git_dir() -> Result<PathBuf, String> {
+    Ok(PathBuf::from(git(&["rev-parse", "--absolute-git-dir"])?.trim()))
+}
+
+/// 标准输入是终端并且没有`--yes`或`--print`时逐步确认
+fn interactive(options: &Options) -> bool {
+    !options.yes && !options.print && io::stdin().is_terminal()
+}
+
+/// `kimi git commit`
+pub fn commit(client: &Client, options: &Options) -> Result<(), String> {
+    let dir = git_dir()?;
+    let diff = git(&["diff", "--cached", "--no-color", "--no-ext-diff"])?;
+    if diff.trim().is_empty() {
+        return Err("暂存区没有改动，先用 git add 添加要提交的文件".to_string());
+    }
+    let stat = git(&["diff", "--cached", "--no-color", "--stat"])?;
+    // 还没有提交的仓库没有历史
+    let recent = git(&["log", "-n", RECENT_COMMITS, "--no-color", "--format=%s"]).unwrap_or_default();
+    let template = template("commit", options.template.as_deref(), COMMIT_TEMPLATE)?;
+    let prompt = render_template(
+        &template,
+        &[
+            ("diff", truncate(&diff, &options.model)),
+            ("stat", stat.trim_end().to_string()),
+            ("branch", current_branch()),
+            (
+                "recent",
+                if recent.trim().is_empty() {
+                    "（还没有提交）".to_string()
+                } else {
+                    recent.trim_end().to_string()
+                },
+            ),
+            ("hint", options.hint.clone().unwrap_or_else(|| "无".to_string())),
+        ],
+    );
+    let mut writer = Writer::new(client, options, prompt);
+    let message = writer.generate()?;
+    let file = dir.join("KIMI_COMMIT_EDITMSG");
+    let message = if interactive(options) {
+        match confirm(&mut writer, message, "提交？", &file, true)? {
+            Some(message) => message,
+            None => {
+                eprintln!("已取消");
+                return Ok(());
+            }
+        }
+    } else if options.yes {
+        message
+    } else {
+        println!("{}", message);
+        return Ok(());
+    };
+
+    fs::write(&file, format!("{}\n", message)).map_err(|e| format!("写入 {} 失败: {}", file.display(), e))?;
+    let status = Command::new("git")
+        .arg("commit")
+        .arg("-F")
+        .arg(&file)
+        .args(&options.git_args)
+        .status()
+        .map_err(|e| format!("运行 git 失败: {}", e))?;
+    if !status.success() {
+        return Err(format!("git commit 失败，提交信息保存在 {}", file.display()));
+    }
+    let _ = fs::remove_file(&file);
+    if options.pr {
+        pr(client, options)?;
+    }
+    Ok(())
+}
+
+/// `kimi git pr`
+pub fn pr(client: &Client, options: &Options) -> Result<(), String> {
+    let base = match &options.base {
+        Some(base) => base.clone(),
+        None => default_base()?,
+    };
+    let commits = git(&[
+        "log",
+        "--no-color",
+        "--reverse",
+        "--format=- %s%n%w(0,2,2)%b",
+        &format!("{}..HEAD", base),
+    ])?;
+    if commits.trim().is_empty() {
+        return Err(format!("当前分支相对于 {} 没有新的提交", base));
+    }
+    let range = format!("{}...HEAD", base);
+    let diff = git(&["diff", "--no-color", "--no-ext-diff", &range])?;
+    let stat = git(&["diff", "--no-color", "--stat", &range])?;
+    let template = template("pr", options.template.as_deref(), PR_TEMPLATE)?;
+    let prompt = render_template(
+        &template,
+        &[
+            ("commits", commits.trim_end().to_string()),
+            ("diff", truncate(&diff, &options.model)),
+            ("stat", stat.trim_end().to_string()),
+            ("branch", current_branch()),
+            ("base", base),
+            ("hint", options.hint.clone().unwrap_or_else(|| "无".to_string())),
+        ],
+    );
+    let mut writer = Writer::new(client, options, prompt);
+    let mut description = writer.generate()?;
+    if interactive(options) {
+        let file = git_dir()?.join("KIMI_PR_EDITMSG");
+        match confirm(&mut writer, description, "使用这段描述？", &file, false)? {
+            Some(text) => description = text,
+            None => {
+                eprintln!("已取消");
+                return Ok(());
+            }
+        }
+        let _ = fs::remove_file(&file);
+    }
+    match &options.output {
+        Some(path) => {
+            fs::write(path, format!("{}\n", description))
+                .map_err(|e| format!("写入 {} 失败: {}", path.display(), e))?;
+            eprintln!("已写入 {}，第一行为标题", path.display());
+        }
+        None if interactive(options) => eprintln!("已确认，可以复制上面的标题和正文"),
+        None => println!("{}", description),
+    }
+    Ok(())
+}
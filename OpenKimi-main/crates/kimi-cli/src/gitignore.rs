@@ -0,0 +1,144 @@
+//! 按`.gitignore`遍历目录
+//!
+//! 支持`.gitignore`的常用规则：`#`注释、`!`重新包含、以`/`结尾的只匹配目录、中间或开头含`/`的相对`.gitignore`
+//! 所在的目录匹配，其余的匹配任意一层的名称；通配符`*`、`?`、`[...]`和`**`。规则依次来自`.git/info/exclude`
+//! 和从仓库根目录到每一层目录的`.gitignore`，后面的优先。被忽略的目录不再进入，隐藏文件、隐藏目录和符号链接总是跳过。
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use glob::{MatchOptions, Pattern};
+use openkimi_rag::DocumentKind;
+
+const OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+struct Rule {
+    pattern: Pattern,
+    /// `!`开头，重新包含
+    negate: bool,
+    /// 以`/`结尾，只匹配目录
+    dir_only: bool,
+    /// 含`/`，匹配相对`base`的路径；否则只匹配名称
+    anchored: bool,
+    base: PathBuf,
+}
+
+impl Rule {
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            path.strip_prefix(&self.base)
+                .is_ok_and(|relative| self.pattern.matches_path_with(relative, OPTIONS))
+        } else {
+            path.file_name().is_some_and(|name| self.pattern.matches_with(&name.to_string_lossy(), OPTIONS))
+        }
+    }
+}
+
+/// 读取一个忽略文件，不存在时没有规则
+fn read(file: &Path, base: &Path, rules: &mut Vec<Rule>) {
+    let Ok(text) = fs::read_to_string(file) else {
+        return;
+    };
+    for line in text.lines() {
+        // 行尾的空格除非用`\`转义，否则忽略
+        let line = match line.strip_suffix("\\ ") {
+            Some(line) => format!("{} ", line),
+            None => line.trim_end().to_string(),
+        };
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (negate, line) = match line.strip_prefix('!') {
+            Some(line) => (true, line),
+            None => (false, line.as_str()),
+        };
+        // `\#`、`\!`开头的是字面的`#`和`!`
+        let line = line.strip_prefix('\\').filter(|rest| rest.starts_with(['#', '!'])).unwrap_or(line);
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        if let Ok(pattern) = Pattern::new(line) {
+            rules.push(Rule {
+                pattern,
+                negate,
+                dir_only,
+                anchored,
+                base: base.to_path_buf(),
+            });
+        }
+    }
+}
+
+/// 最后一条匹配的规则决定是否忽略
+fn ignored(rules: &[Rule], path: &Path, is_dir: bool) -> bool {
+    rules.iter().rev().find(|rule| rule.matches(path, is_dir)).is_some_and(|rule| !rule.negate)
+}
+
+/// 目录所在的仓库中，适用于这个目录的上层规则
+fn inherited(dir: &Path) -> Vec<Rule> {
+    let mut rules = Vec::new();
+    let Some(root) = dir.ancestors().find(|ancestor| ancestor.join(".git").exists()) else {
+        return rules;
+    };
+    read(&root.join(".git/info/exclude"), root, &mut rules);
+    let mut ancestors: Vec<&Path> = dir.ancestors().skip(1).take_while(|ancestor| ancestor.starts_with(root)).collect();
+    ancestors.reverse();
+    for ancestor in ancestors {
+        read(&ancestor.join(".gitignore"), ancestor, &mut rules);
+    }
+    rules
+}
+
+fn walk(dir: &Path, rules: &mut Vec<Rule>, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    let count = rules.len();
+    read(&dir.join(".gitignore"), dir, rules);
+    let mut entries: Vec<_> = fs::read_dir(dir)
+        .and_then(|entries| entries.collect::<Result<_, _>>())
+        .map_err(|e| format!("读取目录 {} 失败: {}", dir.display(), e))?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let path = entry.path();
+        if file_type.is_symlink() || ignored(rules, &path, file_type.is_dir()) {
+            continue;
+        }
+        if file_type.is_dir() {
+            walk(&path, rules, files)?;
+        } else if DocumentKind::from_name(&path.to_string_lossy()).is_some() {
+            files.push(path);
+        }
+    }
+    rules.truncate(count);
+    Ok(())
+}
+
+/// 展开给出的路径：目录中查找支持的文件并跳过忽略的，文件原样保留；返回的都是绝对路径
+pub fn collect(paths: &[PathBuf]) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+    for path in paths {
+        let path = fs::canonicalize(path).map_err(|e| format!("读取 {} 失败: {}", path.display(), e))?;
+        if path.is_dir() {
+            walk(&path, &mut inherited(&path), &mut files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
@@ -0,0 +1,311 @@
+//! 代码块的语法高亮
+//!
+//! 不做完整的语法分析，只按语言识别关键字、字符串、数字和注释，覆盖回复中常见的语言；
+//! 不认识的语言按通用规则高亮字符串、数字和`#`、`//`注释。块注释可以跨行。
+
+use crate::render::{color, paint};
+
+struct Language {
+    keywords: &'static [&'static str],
+    line_comments: &'static [&'static str],
+    /// 块注释的开始和结束
+    block_comment: Option<(&'static str, &'static str)>,
+}
+
+const RUST: Language = Language {
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "false", "fn",
+        "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self",
+        "Self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use", "where", "while",
+    ],
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+};
+
+const PYTHON: Language = Language {
+    keywords: &[
+        "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif", "else", "except",
+        "False", "finally", "for", "from", "global", "if", "import", "in", "is", "lambda", "None", "nonlocal", "not",
+        "or", "pass", "raise", "return", "True", "try", "while", "with", "yield", "self",
+    ],
+    line_comments: &["#"],
+    block_comment: None,
+};
+
+const JAVASCRIPT: Language = Language {
+    keywords: &[
+        "async",
+        "await",
+        "break",
+        "case",
+        "catch",
+        "class",
+        "const",
+        "continue",
+        "default",
+        "delete",
+        "do",
+        "else",
+        "export",
+        "extends",
+        "false",
+        "finally",
+        "for",
+        "from",
+        "function",
+        "if",
+        "import",
+        "in",
+        "instanceof",
+        "interface",
+        "let",
+        "new",
+        "null",
+        "return",
+        "static",
+        "super",
+        "switch",
+        "this",
+        "throw",
+        "true",
+        "try",
+        "type",
+        "typeof",
+        "undefined",
+        "var",
+        "void",
+        "while",
+        "yield",
+    ],
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+};
+
+const GO: Language = Language {
+    keywords: &[
+        "break",
+        "case",
+        "chan",
+        "const",
+        "continue",
+        "default",
+        "defer",
+        "else",
+        "false",
+        "for",
+        "func",
+        "go",
+        "goto",
+        "if",
+        "import",
+        "interface",
+        "map",
+        "nil",
+        "package",
+        "range",
+        "return",
+        "select",
+        "struct",
+        "switch",
+        "true",
+        "type",
+        "var",
+    ],
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+};
+
+const C_LIKE: Language = Language {
+    keywords: &[
+        "auto",
+        "bool",
+        "break",
+        "case",
+        "catch",
+        "char",
+        "class",
+        "const",
+        "continue",
+        "default",
+        "delete",
+        "do",
+        "double",
+        "else",
+        "enum",
+        "extends",
+        "false",
+        "final",
+        "float",
+        "for",
+        "if",
+        "implements",
+        "import",
+        "int",
+        "long",
+        "namespace",
+        "new",
+        "null",
+        "nullptr",
+        "package",
+        "private",
+        "protected",
+        "public",
+        "return",
+        "short",
+        "static",
+        "struct",
+        "switch",
+        "template",
+        "this",
+        "throw",
+        "true",
+        "try",
+        "typedef",
+        "unsigned",
+        "using",
+        "void",
+        "while",
+    ],
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+};
+
+const SHELL: Language = Language {
+    keywords: &[
+        "case", "do", "done", "echo", "elif", "else", "esac", "export", "fi", "for", "function", "if", "in", "local",
+        "return", "then", "while",
+    ],
+    line_comments: &["#"],
+    block_comment: None,
+};
+
+const SQL: Language = Language {
+    keywords: &[
+        "ADD", "ALTER", "AND", "AS", "ASC", "BY", "CREATE", "DELETE", "DESC", "DROP", "FROM", "GROUP", "HAVING", "IN",
+        "INDEX", "INNER", "INSERT", "INTO", "IS", "JOIN", "KEY", "LEFT", "LIMIT", "NOT", "NULL", "ON", "OR", "ORDER",
+        "PRIMARY", "SELECT", "SET", "TABLE", "UPDATE", "VALUES", "WHERE", "WITH",
+    ],
+    line_comments: &["--"],
+    block_comment: Some(("/*", "*/")),
+};
+
+const DATA: Language = Language {
+    keywords: &["true", "false", "null"],
+    line_comments: &["#"],
+    block_comment: None,
+};
+
+const GENERIC: Language = Language {
+    keywords: &[],
+    line_comments: &["#", "//"],
+    block_comment: None,
+};
+
+fn language(name: &str) -> &'static Language {
+    match name.to_ascii_lowercase().as_str() {
+        "rust" | "rs" => &RUST,
+        "python" | "py" => &PYTHON,
+        "javascript" | "js" | "jsx" | "typescript" | "ts" | "tsx" => &JAVASCRIPT,
+        "go" | "golang" => &GO,
+        "c" | "h" | "cpp" | "c++" | "cc" | "java" | "kotlin" | "kt" | "csharp" | "cs" | "swift" => &C_LIKE,
+        "sh" | "bash" | "zsh" | "shell" | "console" | "powershell" | "ps1" => &SHELL,
+        "sql" => &SQL,
+        "json" | "yaml" | "yml" | "toml" | "ini" => &DATA,
+        _ => &GENERIC,
+    }
+}
+
+/// 一个代码块的高亮状态
+pub struct Highlighter {
+    language: &'static Language,
+    /// SQL关键字不区分大小写
+    case_insensitive: bool,
+    /// 是否在块注释中
+    in_comment: bool,
+}
+
+impl Highlighter {
+    pub fn new(name: &str) -> Highlighter {
+        Highlighter {
+            language: language(name),
+            case_insensitive: name.eq_ignore_ascii_case("sql"),
+            in_comment: false,
+        }
+    }
+
+    fn is_keyword(&self, word: &str) -> bool {
+        if self.case_insensitive {
+            self.language.keywords.iter().any(|keyword| keyword.eq_ignore_ascii_case(word))
+        } else {
+            self.language.keywords.contains(&word)
+        }
+    }
+
+    /// 高亮一行代码
+    pub fn line(&mut self, line: &str) -> String {
+        let mut out = String::new();
+        let mut rest = line;
+        while !rest.is_empty() {
+            if self.in_comment {
+                let (_, end) = self.language.block_comment.unwrap_or(("", ""));
+                match rest.find(end) {
+                    Some(index) => {
+                        out.push_str(&paint(&rest[..index + end.len()], color::COMMENT));
+                        rest = &rest[index + end.len()..];
+                        self.in_comment = false;
+                    }
+                    None => {
+                        out.push_str(&paint(rest, color::COMMENT));
+                        rest = "";
+                    }
+                }
+                continue;
+            }
+            if self.language.line_comments.iter().any(|prefix| rest.starts_with(prefix)) {
+                out.push_str(&paint(rest, color::COMMENT));
+                break;
+            }
+            if let Some((start, _)) = self.language.block_comment.filter(|(start, _)| rest.starts_with(start)) {
+                out.push_str(&paint(start, color::COMMENT));
+                rest = &rest[start.len()..];
+                self.in_comment = true;
+                continue;
+            }
+
+            let c = rest.chars().next().unwrap_or_default();
+            let len = if c == '"' || c == '\'' || c == '`' {
+                // 字符串到同样的引号为止，跳过转义；没有结束的引号时到行尾
+                let mut escaped = false;
+                let end = rest
+                    .char_indices()
+                    .skip(1)
+                    .find(|&(_, next)| {
+                        let found = next == c && !escaped;
+                        escaped = next == '\\' && !escaped;
+                        found
+                    })
+                    .map_or(rest.len(), |(index, _)| index + 1);
+                out.push_str(&paint(&rest[..end], color::STRING));
+                end
+            } else if c.is_ascii_digit() {
+                let end = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '.' && c != '_').unwrap_or(rest.len());
+                out.push_str(&paint(&rest[..end], color::NUMBER));
+                end
+            } else if c.is_alphabetic() || c == '_' {
+                let end = rest.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(rest.len());
+                let word = &rest[..end];
+                if self.is_keyword(word) {
+                    out.push_str(&paint(word, color::KEYWORD));
+                } else {
+                    out.push_str(word);
+                }
+                end
+            } else {
+                out.push(c);
+                c.len_utf8()
+            };
+            rest = &rest[len..];
+        }
+        out
+    }
+}
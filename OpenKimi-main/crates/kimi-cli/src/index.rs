@@ -0,0 +1,461 @@
+//! 本地文件的向量索引：`kimi index`和`kimi ask --index`
+//!
+//! `kimi index <路径...>`遍历目录（遵循`.gitignore`，见[`gitignore`](crate::gitignore)），把支持的文件分块后通过
+//! 服务端的`/v1/embeddings`计算嵌入，写入数据目录下`indexes/<名称>/`中的HNSW索引。`manifest.json`记录索引的路径、
+//! 嵌入模型和每个文件的大小、修改时间和SHA-256：再次运行时只重新导入新增和内容有变化的文件，删除已不存在的文件的
+//! 分块。导入失败的文件保留旧的分块，下次运行时重试。
+//!
+//! 提问时先检索与问题最相关的分块，连同来源放在问题之后；HNSW索引支持混合检索，同时按语义和关键词召回。
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use futures_util::future::BoxFuture;
+use openkimi_client::blocking::Client as BlockingClient;
+use openkimi_client::types::EmbeddingRequest;
+use openkimi_client::{Client, ClientConfig};
+use openkimi_rag::{
+    ChunkConfig, Chunker, Document, DocumentKind, Embedder, HnswConfig, HnswStore, Ingestor, RagError, SearchHit,
+    VectorStore,
+};
+use openkimi_sessions::time::format_time;
+use openkimi_tokenizer::Tokenizer;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::attach::{format_size, MAX_DOCUMENT_BYTES};
+use crate::conversation::{data_dir, now};
+use crate::gitignore;
+
+const MANIFEST: &str = "manifest.json";
+
+const STORE: &str = "index.hnsw";
+
+/// 检索的分块数
+pub const TOP_K: usize = 5;
+
+/// 每导入这么多个文件写一次盘，中途中断时已写盘的文件不再重新导入
+const FLUSH_EVERY: usize = 20;
+
+#[derive(Serialize, Deserialize)]
+struct FileEntry {
+    size: u64,
+    /// 修改时间，自1970年起的纳秒数
+    modified: u64,
+    sha256: String,
+    chunks: usize,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Manifest {
+    roots: Vec<PathBuf>,
+    /// 嵌入模型，为空时使用服务端的默认嵌入模型
+    #[serde(default)]
+    model: String,
+    /// 按绝对路径
+    #[serde(default)]
+    files: BTreeMap<String, FileEntry>,
+    #[serde(default)]
+    updated_at: i64,
+}
+
+impl Manifest {
+    fn load(dir: &Path) -> Result<Option<Manifest>, String> {
+        let path = dir.join(MANIFEST);
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                serde_json::from_str(&text).map(Some).map_err(|e| format!("解析 {} 失败: {}", path.display(), e))
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(format!("读取 {} 失败: {}", path.display(), err)),
+        }
+    }
+
+    /// 先写临时文件再改名
+    fn save(&self, dir: &Path) -> Result<(), String> {
+        let path = dir.join(MANIFEST);
+        let temp = path.with_extension("json.tmp");
+        let text = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(&temp, text)
+            .and_then(|()| fs::rename(&temp, &path))
+            .map_err(|e| format!("写入 {} 失败: {}", path.display(), e))
+    }
+
+    fn chunks(&self) -> usize {
+        self.files.values().map(|entry| entry.chunks).sum()
+    }
+}
+
+/// 通过服务端的`/v1/embeddings`计算嵌入
+struct ServerEmbedder {
+    client: Client,
+    model: String,
+}
+
+impl Embedder for ServerEmbedder {
+    fn embed<'a>(&'a self, texts: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>, RagError>> {
+        Box::pin(async move {
+            let request = EmbeddingRequest::new(self.model.clone(), texts.to_vec());
+            let mut response =
+                self.client.embeddings(&request).await.map_err(|e| RagError::Embedding(e.to_string()))?;
+            response.data.sort_by_key(|embedding| embedding.index);
+            response
+                .data
+                .iter()
+                .map(|embedding| {
+                    embedding.vector().ok_or_else(|| RagError::Embedding("服务端返回的向量不是数组".to_string()))
+                })
+                .collect()
+        })
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+}
+
+fn indexes_dir() -> Result<PathBuf, String> {
+    Ok(data_dir().ok_or("无法确定数据目录，请设置 OPENKIMI_CLI_HOME")?.join("indexes"))
+}
+
+fn check_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("无效的索引名称: {}，只能包含字母、数字、-、_和.", name))
+    }
+}
+
+/// 没有指定名称时以第一个路径的目录名或文件名作为索引名称
+pub fn default_name(path: &Path) -> Result<String, String> {
+    let path = fs::canonicalize(path).map_err(|e| format!("读取 {} 失败: {}", path.display(), e))?;
+    let name = path.file_stem().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    check_name(&name).map_err(|_| format!("无法以 {} 作为索引名称，请用 --name 指定", path.display()))?;
+    Ok(name)
+}
+
+/// 索引是否存在
+pub fn exists(name: &str) -> bool {
+    check_name(name).is_ok() && indexes_dir().is_ok_and(|dir| dir.join(name).join(MANIFEST).is_file())
+}
+
+/// 记录中显示的路径：相对索引路径的上一级目录，如`docs/guide.md`
+fn display_name(roots: &[PathBuf], path: &Path) -> String {
+    roots
+        .iter()
+        .filter(|root| path.starts_with(root))
+        .find_map(|root| path.strip_prefix(root.parent().unwrap_or(root)).ok())
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn modified(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_nanos() as u64)
+}
+
+/// 建立或更新索引的选项
+pub struct Update {
+    pub name: String,
+    /// 为空时使用索引中记录的路径
+    pub roots: Vec<PathBuf>,
+    pub model: Option<String>,
+    /// 删除已有的内容重新导入
+    pub rebuild: bool,
+}
+
+/// 建立或增量更新索引
+pub fn update(config: ClientConfig, options: Update) -> Result<(), String> {
+    check_name(&options.name)?;
+    let dir = indexes_dir()?.join(&options.name);
+    if options.rebuild && dir.exists() {
+        fs::remove_dir_all(&dir).map_err(|e| format!("删除 {} 失败: {}", dir.display(), e))?;
+    }
+    let existing = Manifest::load(&dir)?;
+    let created = existing.is_none();
+    let mut manifest = existing.unwrap_or_default();
+    if !options.roots.is_empty() {
+        manifest.roots = options
+            .roots
+            .iter()
+            .map(|root| fs::canonicalize(root).map_err(|e| format!("读取 {} 失败: {}", root.display(), e)))
+            .collect::<Result<_, _>>()?;
+    } else if created {
+        return Err(format!("没有名为 {} 的索引，请指定要索引的目录", options.name));
+    }
+    match options.model {
+        Some(model) if created => manifest.model = model,
+        Some(model) if model != manifest.model => {
+            return Err(format!(
+                "索引 {} 使用{}，换用 {} 需要加 --rebuild 重新导入",
+                options.name,
+                if manifest.model.is_empty() {
+                    "服务端的默认嵌入模型".to_string()
+                } else {
+                    format!("嵌入模型 {}", manifest.model)
+                },
+                model
+            ))
+        }
+        _ => {}
+    }
+
+    let files = gitignore::collect(&manifest.roots)?;
+    fs::create_dir_all(&dir).map_err(|e| format!("创建 {} 失败: {}", dir.display(), e))?;
+    let store_path = dir.join(STORE);
+    let mut store = HnswStore::open(&store_path, HnswConfig::default())
+        .map_err(|e| format!("打开索引 {} 失败: {}", store_path.display(), e))?;
+    let embedder = ServerEmbedder {
+        client: Client::new(config).map_err(|e| e.to_string())?,
+        model: manifest.model.clone(),
+    };
+    let chunker = Chunker::new(ChunkConfig::default(), Tokenizer::for_model(&manifest.model));
+    let ingestor = Ingestor::new(&chunker, &embedder);
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| format!("创建运行时失败: {}", e))?;
+    let save = |store: &mut HnswStore, manifest: &mut Manifest| {
+        store.flush().map_err(|e| format!("写入 {} 失败: {}", store_path.display(), e))?;
+        manifest.updated_at = now();
+        manifest.save(&dir)
+    };
+
+    let (mut added, mut changed, mut unchanged, mut failed) = (0, 0, 0, 0);
+    let mut seen = BTreeSet::new();
+    for path in &files {
+        let key = path.to_string_lossy().into_owned();
+        let name = display_name(&manifest.roots, path);
+        seen.insert(key.clone());
+        let result = (|| {
+            let metadata = fs::metadata(path).map_err(|e| e.to_string())?;
+            let (size, modified) = (metadata.len(), modified(&metadata));
+            let entry = manifest.files.get(&key);
+            if entry.is_some_and(|entry| entry.size == size && entry.modified == modified) {
+                return Ok(None);
+            }
+            if size > MAX_DOCUMENT_BYTES {
+                return Err(format!(
+                    "有 {}，超过 {} 的上限",
+                    format_size(size),
+                    format_size(MAX_DOCUMENT_BYTES)
+                ));
+            }
+            let data = fs::read(path).map_err(|e| e.to_string())?;
+            let sha256 = format!("{:x}", Sha256::digest(&data));
+            // 只有修改时间变了，内容相同时不重新导入
+            if let Some(entry) = entry.filter(|entry| entry.sha256 == sha256) {
+                return Ok(Some((
+                    FileEntry {
+                        size,
+                        modified,
+                        sha256,
+                        chunks: entry.chunks,
+                    },
+                    false,
+                )));
+            }
+            let kind = DocumentKind::from_name(&name).unwrap_or(DocumentKind::Text);
+            let mut document_metadata = Map::new();
+            document_metadata.insert("path".to_string(), Value::from(key.clone()));
+            let document = Document {
+                id: key.clone(),
+                name: name.clone(),
+                kind,
+                data,
+                metadata: document_metadata,
+                chunking: None,
+            };
+            let records = runtime.block_on(ingestor.ingest(&document)).map_err(|e| e.to_string())?;
+            let chunks = records.len();
+            store.replace_document(&key, records).map_err(|e| e.to_string())?;
+            println!("📄 {}: {} 块", name, chunks);
+            Ok(Some((
+                FileEntry {
+                    size,
+                    modified,
+                    sha256,
+                    chunks,
+                },
+                true,
+            )))
+        })();
+        match result {
+            Ok(None) => unchanged += 1,
+            Ok(Some((entry, false))) => {
+                manifest.files.insert(key, entry);
+                unchanged += 1;
+            }
+            Ok(Some((entry, true))) => {
+                match manifest.files.insert(key, entry) {
+                    Some(_) => changed += 1,
+                    None => added += 1,
+                }
+                if (added + changed) % FLUSH_EVERY == 0 {
+                    save(&mut store, &mut manifest)?;
+                }
+            }
+            Err(err) => {
+                eprintln!("⚠️ {}: {}", name, err);
+                // 保留旧的分块，清除校验和使下次运行时重新导入
+                if let Some(entry) = manifest.files.get_mut(&key) {
+                    entry.modified = 0;
+                    entry.sha256.clear();
+                }
+                failed += 1;
+            }
+        }
+    }
+
+    let removed: Vec<String> = manifest.files.keys().filter(|key| !seen.contains(*key)).cloned().collect();
+    for key in &removed {
+        store.delete_document(key).map_err(|e| e.to_string())?;
+        manifest.files.remove(key);
+    }
+    save(&mut store, &mut manifest)?;
+
+    println!(
+        "✅ 索引 {}：新增 {} 个、更新 {} 个、删除 {} 个、未变 {} 个文件，共 {} 个文件 {} 块",
+        options.name,
+        added,
+        changed,
+        removed.len(),
+        unchanged,
+        manifest.files.len(),
+        manifest.chunks()
+    );
+    if failed > 0 {
+        return Err(format!("{} 个文件导入失败", failed));
+    }
+    Ok(())
+}
+
+/// 列出所有索引
+pub fn list() -> Result<(), String> {
+    let dir = indexes_dir()?;
+    let mut names: Vec<String> = match fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| exists(name))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    names.sort();
+    if names.is_empty() {
+        println!("还没有本地索引，用 kimi index <目录> 建立");
+    }
+    for name in names {
+        if let Some(manifest) = Manifest::load(&dir.join(&name))? {
+            let roots: Vec<String> = manifest.roots.iter().map(|root| root.display().to_string()).collect();
+            println!(
+                "{}  {}  {} 个文件  {} 块  {}",
+                name,
+                format_time(manifest.updated_at),
+                manifest.files.len(),
+                manifest.chunks(),
+                roots.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+/// 删除一个索引
+pub fn delete(name: &str) -> Result<(), String> {
+    if !exists(name) {
+        return Err(format!("没有名为 {} 的索引", name));
+    }
+    let dir = indexes_dir()?.join(name);
+    fs::remove_dir_all(&dir).map_err(|e| format!("删除 {} 失败: {}", dir.display(), e))
+}
+
+/// 打开的索引，用于提问时检索
+pub struct Index {
+    pub name: String,
+    model: String,
+    store: HnswStore,
+}
+
+impl Index {
+    pub fn open(name: &str) -> Result<Index, String> {
+        if !exists(name) {
+            return Err(format!(
+                "没有名为 {} 的索引，先用 kimi index <目录> --name {} 建立",
+                name, name
+            ));
+        }
+        let dir = indexes_dir()?.join(name);
+        let manifest = Manifest::load(&dir)?.unwrap_or_default();
+        let path = dir.join(STORE);
+        let store = HnswStore::open(&path, HnswConfig::default())
+            .map_err(|e| format!("打开索引 {} 失败: {}", path.display(), e))?;
+        Ok(Index {
+            name: name.to_string(),
+            model: manifest.model,
+            store,
+        })
+    }
+
+    /// 检索与问题最相关的分块
+    pub fn search(&self, client: &BlockingClient, query: &str) -> Result<Vec<SearchHit>, String> {
+        let response = client
+            .embeddings(&EmbeddingRequest::new(self.model.clone(), query))
+            .map_err(|e| format!("计算问题的嵌入失败: {}", e))?;
+        let vector =
+            response.data.first().and_then(|embedding| embedding.vector()).ok_or("服务端没有返回问题的向量")?;
+        let hits = if self.store.supports_hybrid() {
+            self.store.search_hybrid(query, &vector, TOP_K, None)
+        } else {
+            self.store.search(&vector, TOP_K, None)
+        };
+        hits.map_err(|e| format!("检索索引 {} 失败: {}", self.name, e))
+    }
+}
+
+/// 分块的来源，代码带行号，如`src/main.rs:10-42`；其余带段号，如`docs/guide.md（第 2/5 段）`
+pub fn source(hit: &SearchHit) -> String {
+    let metadata = &hit.record.metadata;
+    let name = metadata.get("source").and_then(Value::as_str).unwrap_or(&hit.record.document);
+    let number = |key: &str| metadata.get(key).and_then(Value::as_u64);
+    match (
+        number("start_line"),
+        number("end_line"),
+        number("chunk"),
+        number("chunks"),
+    ) {
+        (Some(start), Some(end), _, _) => format!("{}:{}-{}", name, start, end),
+        (_, _, Some(chunk), Some(chunks)) if chunks > 1 => format!("{}（第 {}/{} 段）", name, chunk + 1, chunks),
+        _ => name.to_string(),
+    }
+}
+
+/// 把检索到的分块编号后放在问题之后
+pub fn augment(question: String, index: &str, hits: &[SearchHit]) -> String {
+    if hits.is_empty() {
+        return question;
+    }
+    let mut text = format!(
+        "{}\n\n以下是从本地索引 {} 中检索到的资料，请据此回答，并用 [编号] 标出引用的来源：",
+        question, index
+    );
+    for (number, hit) in hits.iter().enumerate() {
+        text.push_str(&format!(
+            "\n\n[{}] {}\n{}",
+            number + 1,
+            source(hit),
+            hit.record.text.trim_end()
+        ));
+    }
+    text
+}
@@ -0,0 +1,46 @@
+//! 读取一次输入
+//!
+//! 以`\`结尾的行与下一行连接；单独一行`"""`开始多行输入，直到下一行单独的`"""`结束，适合粘贴代码。
+
+use std::io::{self, BufRead, Write};
+
+const PROMPT: &str = "› ";
+const CONTINUATION: &str = "… ";
+const FENCE: &str = "\"\"\"";
+
+/// 读到的一次输入，输入结束（Ctrl-D）时为`None`
+pub fn read(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut fenced = false;
+    loop {
+        print!("{}", if lines.is_empty() && !fenced { PROMPT } else { CONTINUATION });
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            // 多行输入中途结束时保留已输入的内容
+            return Ok((!lines.is_empty()).then(|| lines.join("\n")));
+        }
+        let line = line.trim_end_matches(['\n', '\r']);
+
+        if line.trim() == FENCE {
+            if fenced {
+                return Ok(Some(lines.join("\n")));
+            }
+            if lines.is_empty() {
+                fenced = true;
+                continue;
+            }
+        }
+        if fenced {
+            lines.push(line.to_string());
+            continue;
+        }
+        match line.strip_suffix('\\') {
+            Some(head) => lines.push(head.to_string()),
+            None => {
+                lines.push(line.to_string());
+                return Ok(Some(lines.join("\n")));
+            }
+        }
+    }
+}
@@ -0,0 +1,994 @@
+//! kimi：OpenKimi的终端对话客户端
+//!
+//! ```text
+//! kimi [--model <模型>] [--system <提示词>|--preset <名称> [--var <名称=值>...]] [--resume [编号]] [--no-stream] [--raw]
+//!      [--base-url <地址>] [--api-key <令牌>] [--workspace <工作区>]
+//! kimi [选项] <问题...>
+//! kimi ask [-f <文件>...] [--index <名称> [--note <库目录>]] [选项] <问题...>
+//! echo "问题" | kimi -
+//! kimi [选项] "总结一下" < file.txt
+//! kimi index <路径...> [--name <名称>] [--embedding-model <模型>] [--rebuild]
+//! kimi index [<名称>|--list|--delete <名称>]
+//! kimi tokens [<文件|->...] [--model <模型>] [--context <token数>] [--reserve <token数>] [--split <目录>] [--json]
+//! kimi models [--json]
+//! kimi model [show|set <模型>|unset] [--profile <名称>]
+//! kimi config <get|set|unset|list|path> [键] [值] [--profile <名称>]
+//! kimi history [list|search <关键词>|show <id>|delete <id>]
+//! kimi export <id> [--format md|json|html] [-o <文件>]
+//! kimi export --all [--format md|json|html] [-o <目录>]
+//! kimi export <id>|--all --format obsidian|notion [--template <文件>] -o <库目录|zip>
+//! kimi import <ChatGPT或Claude导出的zip或conversations.json>
+//! kimi resume [id]
+//! kimi tui [--profile <名称>] [--model <模型>] [--resume [id]]
+//! kimi git commit [--hint <说明>] [--template <文件>] [-y|--print] [--pr] [-- <git commit 的参数>]
+//! kimi git pr [--base <分支>] [--hint <说明>] [--template <文件>] [-o <文件>]
+//! kimi sh [--shell <程序>] [--print] <需求...>
+//! kimi preset [list|show <名称> [--var <名称=值>...]|push <名称>|pull <名称> [--force]]
+//! kimi completions <bash|zsh|fish|powershell>
+//! kimi doctor [--profile <名称>] [--base-url <地址>] [--api-key <令牌>] [--workspace <工作区>]
+//! ```
+//!
+//! 不带问题且标准输入是终端时进入交互式对话，逐块显示回复，支持多行输入和斜杠命令，每轮回复后保存对话。
+//! 带问题或从管道读取时只回答一次：标准输入的全部内容接在问题之后作为一条消息，回复输出到标准输出，
+//! 提示和错误输出到标准错误，请求失败时退出码为1，便于在管道中组合使用。一次性提问不保存，
+//! 与`--resume`一起使用时追加到之前的对话中。`kimi tui`打开带对话列表的全屏界面，见[`tui`]。
+//!
+//! `-f`添加附件，见[`attach`]：一次性提问时随问题发送，交互式对话中随第一条消息发送。
+//! `kimi index`为本地目录建立向量索引，`--index`提问时先从中检索资料，见[`index`]。
+//! `kimi tokens`统计token数并判断能否放进模型的上下文，见[`tokens`]。`kimi models`列出服务端的模型及其上下文窗口、
+//! 接受的输入和单价，见[`models`]；`kimi model set`修改配置档的默认模型。`kimi completions`输出shell的补全脚本，
+//! 见[`completions`]。`kimi doctor`诊断连接服务端的问题，见[`doctor`]。`kimi export`把对话导出为Markdown、
+//! 会话文档或HTML，与服务端的导出接口共用[`openkimi_sessions::export`]，`--all`导出全部对话用于备份。
+//! `--format obsidian|notion`导出为Obsidian库中或Notion可以导入的互相链接的笔记，`kimi ask --index`的`--note`
+//! 把问答和引用的资料也写入Obsidian库，见[`notes`]。
+//! `kimi import`导入ChatGPT和Claude导出的对话，见[`openkimi_sessions::external`]。
+//! `kimi git commit`根据暂存的改动生成提交信息，确认后提交，`kimi git pr`生成PR描述，见[`git`]。
+//! `kimi sh`把需求写成一条shell命令，确认后执行，危险的命令不执行，见[`sh`]。
+//! `--preset`使用本地或服务端保存的系统提示词预设，`--var`填入其中的变量，`kimi preset`管理预设，见[`preset`]。
+//!
+//! 标准输出是终端时按Markdown样式显示回复，见[`render`]；`--raw`、设置了`NO_COLOR`或输出到管道时原样输出。
+//!
+//! 服务端地址、令牌、模型、系统提示词和工作区可以保存在配置文件的配置档中，见[`config`]；
+//! 也可以用`OPENKIMI_BASE_URL`、`OPENKIMI_API_KEY`和`OPENKIMI_MODEL`环境变量指定，未指定模型时使用服务端的默认模型。
+
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use openkimi_client::blocking::Client;
+use openkimi_client::ClientConfig;
+use openkimi_completions::Shell;
+use openkimi_sessions::export::{self, Format};
+use openkimi_sessions::external;
+use openkimi_sessions::notes::Layout;
+use openkimi_sessions::time::format_time;
+use serde_json::{Map, Value};
+
+mod attach;
+mod completions;
+mod config;
+mod conversation;
+mod doctor;
+mod git;
+mod gitignore;
+mod highlight;
+mod index;
+mod input;
+mod models;
+mod notes;
+mod preset;
+mod render;
+mod repl;
+mod sh;
+#[cfg(any(unix, windows))]
+mod terminal;
+mod tokens;
+#[cfg(any(unix, windows))]
+mod tui;
+
+use config::{ConfigFile, Profile};
+use conversation::{short_id, Conversation, Store};
+use index::Index;
+use repl::Repl;
+
+/// 命令行选项，未指定的由配置档和环境变量补全
+struct Options {
+    profile: Option<String>,
+    base_url: Option<String>,
+    api_key: Option<String>,
+    workspace: Option<String>,
+    model: Option<String>,
+    system: Option<String>,
+    /// 系统提示词预设及其变量
+    preset: Option<String>,
+    variables: Map<String, Value>,
+    /// `Some(None)`为继续最近的对话
+    resume: Option<Option<String>>,
+    stream: bool,
+    /// 不渲染Markdown
+    raw: bool,
+    /// 一次性提问
+    prompt: Vec<String>,
+    /// `kimi ask`：即使没有问题也不进入交互式对话
+    ask: bool,
+    /// 附件
+    files: Vec<String>,
+    /// 检索的本地索引
+    index: Option<String>,
+    /// `--note`：把问答连同检索到的资料写入Obsidian库
+    note: Option<PathBuf>,
+    /// `-`：从标准输入读取问题
+    stdin: bool,
+}
+
+fn print_usage() {
+    println!("用法: kimi [选项] [问题...] [-]");
+    println!("      kimi ask [-f <文件>...] [--index <名称> [--note <库目录>]] [选项] <问题...>");
+    println!("      kimi index <路径...> [--name <名称>] [--embedding-model <模型>] [--rebuild]");
+    println!("      kimi index [<名称>|--list|--delete <名称>]");
+    println!("      kimi tokens [<文件|->...] [--model <模型>] [--context <token数>] [--reserve <token数>] [--split <目录>] [--json]");
+    println!("      kimi models [--json]");
+    println!("      kimi model [show|set <模型>|unset] [--profile <名称>]");
+    println!("      kimi config <get|set|unset|list|path> [键] [值] [--profile <名称>]");
+    println!("      kimi history [list|search <关键词>|show <id> [--json]|delete <id>] [--limit <数量>]");
+    println!("      kimi export <id> [--format md|json|html] [-o <文件>]");
+    println!("      kimi export --all [--format md|json|html] [-o <目录>]");
+    println!("      kimi export <id>|--all --format obsidian|notion [--template <文件>] -o <库目录|zip>");
+    println!("      kimi import <ChatGPT或Claude导出的zip或conversations.json>");
+    println!("      kimi resume [id]");
+    println!("      kimi tui [--profile <名称>] [--model <模型>] [--resume [id]]");
+    println!("      kimi git commit [--hint <说明>] [--template <文件>] [-y|--print] [--pr] [-- <git commit 的参数>]");
+    println!("      kimi git pr [--base <分支>] [--hint <说明>] [--template <文件>] [-o <文件>]");
+    println!("      kimi sh [--shell <程序>] [--print] <需求...>");
+    println!("      kimi preset [list|show <名称> [--var <名称=值>...]|push <名称>|pull <名称> [--force]]");
+    println!("      kimi completions <bash|zsh|fish|powershell>");
+    println!("      kimi doctor [--profile <名称>] [--base-url <地址>] [--api-key <令牌>] [--workspace <工作区>]");
+    println!("  --profile <名称>      使用配置文件中的配置档，默认读取 OPENKIMI_PROFILE 或配置文件中的 profile");
+    println!("  --model <模型>        使用的模型，默认读取 OPENKIMI_MODEL，未设置时使用服务端的默认模型");
+    println!("  --system <提示词>     系统提示词");
+    println!("  --preset <名称>       使用系统提示词预设：本地的 presets/<名称>.md 或服务端工作区中的同名提示词模板");
+    println!("  --var <名称=值>       预设中的变量，可以重复，值按JSON解析");
+    println!("  --resume [id]         继续之前的对话，不带id时继续最近的一个，id可以只输入开头几位");
+    println!("  --no-stream           等回复完整后再显示");
+    println!("  --raw                 原样输出回复，不渲染Markdown");
+    println!("  --base-url <地址>     服务端地址，默认读取 OPENKIMI_BASE_URL");
+    println!("  --api-key <令牌>      令牌，默认读取 OPENKIMI_API_KEY");
+    println!("  --workspace <工作区>  使用指定的工作区");
+    println!("  -f, --file <文件>     添加附件，可以重复：图片内联或上传，PDF、DOCX、文本和代码提取文字");
+    println!("  --index <名称>        先从 kimi index 建立的本地索引中检索资料，连同来源随问题发送");
+    println!("  --note <库目录>       与 --index 一起使用，把问答和引用的资料写成Obsidian库中互相链接的笔记");
+    println!("  -                     从标准输入读取问题，接在命令行中的问题之后");
+    println!("带问题或从管道读取时只回答一次后退出；否则进入交互式对话，输入 /help 查看命令。");
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        profile: None,
+        base_url: None,
+        api_key: None,
+        workspace: None,
+        model: None,
+        system: None,
+        preset: None,
+        variables: Map::new(),
+        resume: None,
+        stream: true,
+        raw: false,
+        prompt: Vec::new(),
+        ask: false,
+        files: Vec::new(),
+        index: None,
+        note: None,
+        stdin: false,
+    };
+
+    let mut iter = args.iter().peekable();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--profile" => options.profile = Some(iter.next().ok_or("--profile 需要配置档名称")?.clone()),
+            "--model" => options.model = Some(iter.next().ok_or("--model 需要模型名称")?.clone()),
+            "--system" => options.system = Some(iter.next().ok_or("--system 需要提示词")?.clone()),
+            "--preset" => options.preset = Some(iter.next().ok_or("--preset 需要预设名称")?.clone()),
+            "--var" => {
+                let (name, value) = preset::parse_var(iter.next().ok_or("--var 需要 名称=值")?)?;
+                options.variables.insert(name, value);
+            }
+            "--resume" => options.resume = Some(iter.next_if(|next| !next.starts_with('-')).cloned()),
+            "--no-stream" => options.stream = false,
+            "--raw" => options.raw = true,
+            "--base-url" => options.base_url = Some(iter.next().ok_or("--base-url 需要服务端地址")?.clone()),
+            "--api-key" => options.api_key = Some(iter.next().ok_or("--api-key 需要一个令牌")?.clone()),
+            "--workspace" => options.workspace = Some(iter.next().ok_or("--workspace 需要工作区名称")?.clone()),
+            "-f" | "--file" => options.files.push(iter.next().ok_or_else(|| format!("{} 需要文件路径", arg))?.clone()),
+            "--index" => options.index = Some(iter.next().ok_or("--index 需要索引名称")?.clone()),
+            "--note" => options.note = Some(PathBuf::from(iter.next().ok_or("--note 需要Obsidian库的目录")?)),
+            "-" => options.stdin = true,
+            "--" => options.prompt.extend(iter.by_ref().cloned()),
+            other if other.starts_with("--") => return Err(format!("未知参数: {}", other)),
+            _ => options.prompt.push(arg.clone()),
+        }
+    }
+    if options.system.is_some() && options.preset.is_some() {
+        return Err("--system 和 --preset 不能同时使用".to_string());
+    }
+    Ok(options)
+}
+
+fn env_value(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+fn load_config() -> Result<ConfigFile, String> {
+    ConfigFile::load(config::default_path().ok_or("无法确定配置文件的位置，请设置 OPENKIMI_CONFIG")?)
+}
+
+/// 使用的配置档：`--profile`、`OPENKIMI_PROFILE`、配置文件中的`profile`；返回名称和是否为明确指定的
+fn selected_profile(option: Option<&String>, file: &ConfigFile) -> Option<(String, bool)> {
+    match option.cloned().or_else(|| env_value("OPENKIMI_PROFILE")) {
+        Some(name) => Some((name, true)),
+        None => file.default_profile().map(|name| (name, false)),
+    }
+}
+
+/// 合并命令行、配置档和环境变量：命令行优先；明确指定的配置档优先于环境变量，默认配置档在环境变量之后。
+/// 系统提示词和预设只保留优先的一个，预设还没有渲染
+fn merge(options: &Options) -> Result<(ClientConfig, Profile), String> {
+    let file = load_config()?;
+    let (profile, explicit) = match selected_profile(options.profile.as_ref(), &file) {
+        Some((name, explicit)) => {
+            let profile = file
+                .profile(&name)
+                .ok_or_else(|| format!("配置文件 {} 中没有配置档 {}", file.path().display(), name))?;
+            (profile, explicit)
+        }
+        None => (Profile::default(), false),
+    };
+    let pick = |option: &Option<String>, profile: Option<String>, env: &str| {
+        option.clone().or(if explicit { profile.or_else(|| env_value(env)) } else { env_value(env).or(profile) })
+    };
+    let mut config = ClientConfig::default();
+    if let Some(base_url) = pick(&options.base_url, profile.base_url.clone(), "OPENKIMI_BASE_URL") {
+        config.base_url = base_url;
+    }
+    config.api_key = pick(&options.api_key, profile.api_key.clone(), "OPENKIMI_API_KEY");
+    config.workspace = options.workspace.clone().or(profile.workspace.clone());
+    // 命令行的--system、--preset优先，其次是配置档的system、preset
+    let (system, preset) = match (&options.system, &options.preset) {
+        (Some(system), _) => (Some(system.clone()), None),
+        (None, Some(name)) => (None, Some(name.clone())),
+        (None, None) if profile.system.is_some() => (profile.system, None),
+        (None, None) => (None, profile.preset),
+    };
+    let resolved = Profile {
+        model: pick(&options.model, profile.model.clone(), "OPENKIMI_MODEL"),
+        system,
+        preset,
+        ..Profile::default()
+    };
+    Ok((config, resolved))
+}
+
+/// 合并后渲染预设，作为系统提示词
+fn resolve(options: &Options) -> Result<(ClientConfig, Profile), String> {
+    let (config, mut profile) = merge(options)?;
+    match &profile.preset {
+        Some(name) => profile.system = Some(preset::render(&config, name, &options.variables)?),
+        None if !options.variables.is_empty() => return Err("--var 需要与 --preset 一起使用".to_string()),
+        None => {}
+    }
+    Ok((config, profile))
+}
+
+/// 令牌只显示开头，过短的令牌全部隐藏
+fn mask(value: &str) -> String {
+    if value.chars().count() <= 12 {
+        return "…".to_string();
+    }
+    let head: String = value.chars().take(6).collect();
+    format!("{}…", head)
+}
+
+/// `kimi config`子命令
+fn run_config(args: &[String]) -> Result<(), String> {
+    let mut profile = None;
+    let mut rest = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--profile" => profile = Some(iter.next().ok_or("--profile 需要配置档名称")?.clone()),
+            _ => rest.push(arg.as_str()),
+        }
+    }
+    let mut file = load_config()?;
+    // 没有指定也没有默认配置档时读写名为default的配置档
+    let profile = selected_profile(profile.as_ref(), &file).map_or_else(|| "default".to_string(), |(name, _)| name);
+
+    match rest.as_slice() {
+        ["path"] => println!("{}", file.path().display()),
+        ["get", key] => match file.get_value(&profile, key)? {
+            Some(value) => println!("{}", value),
+            None => return Err(format!("配置档 {} 中没有设置 {}", profile, key)),
+        },
+        ["set", key, value] => {
+            file.set_value(&profile, key, value)?;
+            file.save()?;
+        }
+        ["unset", key] => {
+            if !file.unset_value(&profile, key)? {
+                return Err(format!("配置档 {} 中没有设置 {}", profile, key));
+            }
+            file.save()?;
+        }
+        ["list"] => {
+            println!("配置文件: {}", file.path().display());
+            let default = file.default_profile();
+            for name in file.profile_names() {
+                let marker = if Some(&name) == default.as_ref() { " (默认)" } else { "" };
+                println!("[{}]{}", name, marker);
+                for key in config::PROFILE_KEYS {
+                    if let Some(value) = file.get_value(&name, key)? {
+                        let value = if *key == "api_key" { mask(&value) } else { value };
+                        println!("  {} = {}", key, value.replace('\n', "\\n"));
+                    }
+                }
+            }
+        }
+        _ => {
+            return Err(format!(
+                "用法: kimi config <get <键>|set <键> <值>|unset <键>|list|path> [--profile <名称>]，键为 {}、{}",
+                config::DEFAULT_PROFILE_KEY,
+                config::PROFILE_KEYS.join("、")
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// `kimi models`子命令，列出服务端的模型
+fn run_models(args: &[String]) -> Result<(), String> {
+    let json = args.iter().any(|arg| arg == "--json");
+    let rest: Vec<String> = args.iter().filter(|arg| *arg != "--json").cloned().collect();
+    let options = parse_args(&rest)?;
+    if !options.prompt.is_empty() {
+        return Err("用法: kimi models [--json]".to_string());
+    }
+    let (config, profile) = resolve(&options)?;
+    let client = Client::new(config).map_err(|e| e.to_string())?;
+    let models = client.models().map_err(|e| e.to_string())?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&models.data).map_err(|e| e.to_string())?);
+        return Ok(());
+    }
+    let current = profile.model.unwrap_or_default();
+    models::print(&models.data, &current);
+    if current.is_empty() {
+        println!("没有指定模型，提问时使用服务端的默认模型；用 kimi model set <模型> 设置");
+    } else if models.data.iter().all(|model| model.id != current) {
+        println!("⚠️ 当前模型 {} 不在服务端的模型列表中", current);
+    }
+    Ok(())
+}
+
+/// `kimi model`子命令，查看或修改配置档的默认模型
+fn run_model(args: &[String]) -> Result<(), String> {
+    let options = parse_args(args)?;
+    let mut file = load_config()?;
+    let profile = selected_profile(options.profile.as_ref(), &file).map_or_else(|| "default".to_string(), |(name, _)| name);
+    let words: Vec<&str> = options.prompt.iter().map(String::as_str).collect();
+    match words.as_slice() {
+        [] | ["show"] => {
+            let (_, resolved) = resolve(&options)?;
+            match resolved.model {
+                Some(model) => println!("{}", model),
+                None => println!("没有指定模型，使用服务端的默认模型"),
+            }
+        }
+        ["set", model] => {
+            // 能连上服务端时检查模型名，避免拼错
+            let (config, _) = resolve(&options)?;
+            match Client::new(config).and_then(|client| client.models()) {
+                Ok(models) if models.data.iter().all(|known| known.id != *model) => {
+                    let names: Vec<&str> = models.data.iter().map(|known| known.id.as_str()).collect();
+                    return Err(format!("服务端没有模型 {}，可用的模型: {}", model, names.join(", ")));
+                }
+                Ok(_) => {}
+                Err(err) => eprintln!("⚠️ 无法获取模型列表，未检查模型名: {}", err),
+            }
+            file.set_value(&profile, "model", model)?;
+            file.save()?;
+            println!("配置档 {} 的默认模型已设为 {}", profile, model);
+        }
+        ["unset"] => {
+            if !file.unset_value(&profile, "model")? {
+                return Err(format!("配置档 {} 中没有设置 model", profile));
+            }
+            file.save()?;
+            println!("配置档 {} 改为使用服务端的默认模型", profile);
+        }
+        _ => return Err("用法: kimi model [show|set <模型>|unset] [--profile <名称>]".to_string()),
+    }
+    Ok(())
+}
+
+/// `kimi history`子命令
+fn run_history(args: &[String]) -> Result<(), String> {
+    let mut limit = 20;
+    let mut json = false;
+    let mut rest = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--limit" => {
+                let value = iter.next().ok_or("--limit 需要一个数")?;
+                limit = value.parse().map_err(|_| format!("无效的数量: {}", value))?;
+            }
+            "--json" => json = true,
+            _ => rest.push(arg.as_str()),
+        }
+    }
+    let store = Store::open()?;
+
+    match rest.as_slice() {
+        [] | ["list"] => {
+            for session in store.list(limit)? {
+                let (id, updated) = (short_id(&session.id), format_time(session.updated_at));
+                println!("{}  {}  {:>3} 条  {}", id, updated, session.message_count, session.title);
+            }
+        }
+        ["search", query @ ..] if !query.is_empty() => {
+            let hits = store.search(&query.join(" "), limit)?;
+            if hits.is_empty() {
+                return Err(format!("没有找到 {}", query.join(" ")));
+            }
+            for hit in hits {
+                println!("{}  {}  {}", short_id(&hit.session_id), format_time(hit.created_at), hit.title);
+                println!("    {}: {}", hit.role, hit.snippet.replace('\n', " "));
+            }
+        }
+        ["show", id] => {
+            let (_, document) = store.export(id)?;
+            print!("{}", export::render(&document, if json { Format::Json } else { Format::Markdown }));
+        }
+        ["delete", id] => {
+            let id = store.delete(id)?;
+            println!("已删除对话 {}", id);
+        }
+        _ => {
+            return Err("用法: kimi history [list|search <关键词>|show <id> [--json]|delete <id>] [--limit <数量>]".to_string())
+        }
+    }
+    Ok(())
+}
+
+/// `kimi import`子命令
+fn run_import(args: &[String]) -> Result<(), String> {
+    let [path] = args else {
+        return Err("用法: kimi import <ChatGPT或Claude导出的zip或conversations.json>".to_string());
+    };
+    let data = fs::read(path).map_err(|e| format!("读取 {} 失败: {}", path, e))?;
+    let conversations = external::parse(&data).map_err(|e| format!("{}: {}", path, e))?;
+    let source = conversations.first().map_or(String::new(), |conversation| conversation.source.to_string());
+    let total = conversations.len();
+    let store = Store::open()?;
+    let result = store.import_external(conversations)?;
+    for session in &result.imported {
+        let (id, created) = (short_id(&session.id), format_time(session.created_at));
+        println!("{}  {}  {:>3} 条  {}", id, created, session.message_count, session.title);
+    }
+    let (imported, skipped) = (result.imported.len(), result.skipped);
+    eprintln!("从 {} 的 {} 个对话中导入了 {} 个，跳过 {} 个（导入过或没有消息）", source, total, imported, skipped);
+    Ok(())
+}
+
+/// `kimi export`子命令
+fn run_export(args: &[String]) -> Result<(), String> {
+    let mut format = None;
+    let mut layout = None;
+    let mut template = None;
+    let mut output = None;
+    let mut all = false;
+    let mut ids = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--format" => {
+                let value = iter.next().ok_or("--format 需要 md、json、html、obsidian 或 notion")?;
+                match (Format::from_name(value), Layout::from_name(value)) {
+                    (Some(parsed), _) => format = Some(parsed),
+                    (None, Some(parsed)) => layout = Some(parsed),
+                    (None, None) => {
+                        return Err(format!("不支持的导出格式: {}，可选: md、json、html、obsidian、notion", value))
+                    }
+                }
+            }
+            "--template" => template = Some(PathBuf::from(iter.next().ok_or("--template 需要一个文件")?)),
+            "-o" | "--output" => output = Some(PathBuf::from(iter.next().ok_or("-o 需要一个路径")?)),
+            "--all" => all = true,
+            _ => ids.push(arg.as_str()),
+        }
+    }
+    let store = Store::open()?;
+
+    if let Some(layout) = layout {
+        let path = match (layout, output) {
+            (_, Some(path)) => path,
+            (Layout::Obsidian, None) => return Err("导出到Obsidian需要用 -o 指定库的目录".to_string()),
+            (Layout::Notion, None) => PathBuf::from("kimi-notion.zip"),
+        };
+        let documents = match (all, ids.as_slice()) {
+            (false, [id]) => vec![store.export(id)?.1],
+            (true, []) => {
+                let sessions = store.list(u32::MAX)?;
+                sessions.iter().map(|session| Ok(store.export(&session.id)?.1)).collect::<Result<Vec<_>, String>>()?
+            }
+            _ => {
+                return Err(
+                    "用法: kimi export <id>|--all --format obsidian|notion [--template <文件>] -o <路径>".to_string()
+                )
+            }
+        };
+        let template = notes::template("conversation", template.as_deref())?;
+        match layout {
+            Layout::Obsidian => notes::write_obsidian(&path, &documents, &template)?,
+            Layout::Notion => notes::write_notion(&path, &documents, &template)?,
+        }
+        println!("已导出 {} 个对话到 {}", documents.len(), path.display());
+        return Ok(());
+    }
+
+    match (all, ids.as_slice()) {
+        (false, [id]) => {
+            // 未指定格式时按输出文件的扩展名选择，输出到标准输出时为Markdown
+            let format = format
+                .or_else(|| {
+                    let extension = output.as_deref()?.extension()?;
+                    Format::from_name(&extension.to_string_lossy())
+                })
+                .unwrap_or(Format::Markdown);
+            let (_, document) = store.export(id)?;
+            let content = export::render(&document, format);
+            match output {
+                Some(path) => {
+                    fs::write(&path, content).map_err(|e| format!("写入 {} 失败: {}", path.display(), e))?;
+                    eprintln!("已导出到 {}", path.display());
+                }
+                None => print!("{}", content),
+            }
+        }
+        (true, []) => {
+            // 备份默认为可以再导入的会话文档
+            let format = format.unwrap_or(Format::Json);
+            let dir = output.unwrap_or_else(|| PathBuf::from("kimi-export"));
+            fs::create_dir_all(&dir).map_err(|e| format!("创建目录 {} 失败: {}", dir.display(), e))?;
+            let sessions = store.list(u32::MAX)?;
+            for session in &sessions {
+                let (_, document) = store.export(&session.id)?;
+                let date = &format_time(session.created_at)[..10];
+                let path = dir.join(format!("{}-{}.{}", date, short_id(&session.id), format.extension()));
+                fs::write(&path, export::render(&document, format))
+                    .map_err(|e| format!("写入 {} 失败: {}", path.display(), e))?;
+            }
+            println!("已导出 {} 个对话到 {}", sessions.len(), dir.display());
+        }
+        _ => {
+            return Err(
+                "用法: kimi export <id> [--format md|json|html] [-o <文件>] 或 kimi export --all [--format ...] [-o <目录>]"
+                    .to_string(),
+            )
+        }
+    }
+    Ok(())
+}
+
+/// `kimi index`子命令，连接服务端的选项与提问相同
+fn run_index(args: &[String]) -> Result<(), String> {
+    let mut name = None;
+    let mut model = None;
+    let mut rebuild = false;
+    let mut rest = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--name" => name = Some(iter.next().ok_or("--name 需要索引名称")?.clone()),
+            "--embedding-model" => model = Some(iter.next().ok_or("--embedding-model 需要模型名称")?.clone()),
+            "--rebuild" => rebuild = true,
+            "--list" => return index::list(),
+            "--delete" => {
+                let name = iter.next().ok_or("--delete 需要索引名称")?;
+                index::delete(name)?;
+                println!("已删除索引 {}", name);
+                return Ok(());
+            }
+            _ => rest.push(arg.clone()),
+        }
+    }
+    let options = parse_args(&rest)?;
+    let mut roots: Vec<PathBuf> = options.prompt.iter().map(PathBuf::from).collect();
+    // `kimi index <名称>`更新已有的索引
+    if let [root] = roots.as_slice() {
+        let root = root.to_string_lossy().into_owned();
+        if name.is_none() && !Path::new(&root).exists() && index::exists(&root) {
+            name = Some(root);
+            roots.clear();
+        }
+    }
+    let name = match (name, roots.first()) {
+        (Some(name), _) => name,
+        (None, Some(root)) => index::default_name(root)?,
+        (None, None) => {
+            return Err(
+                "用法: kimi index <路径...> [--name <名称>] [--embedding-model <模型>] [--rebuild]，\
+                 kimi index [<名称>|--list|--delete <名称>]"
+                    .to_string(),
+            )
+        }
+    };
+    let (config, _) = resolve(&options)?;
+    index::update(
+        config,
+        index::Update {
+            name,
+            roots,
+            model,
+            rebuild,
+        },
+    )
+}
+
+/// `kimi doctor`子命令，配置有误时也继续检查，由诊断报告
+fn run_doctor(args: &[String]) -> Result<(), String> {
+    let options = parse_args(args)?;
+    if !options.prompt.is_empty() {
+        return Err(
+            "用法: kimi doctor [--profile <名称>] [--base-url <地址>] [--api-key <令牌>] [--workspace <工作区>]".to_string(),
+        );
+    }
+    let file = load_config();
+    let profile = file.as_ref().ok().and_then(|file| selected_profile(options.profile.as_ref(), file)).map(|(name, _)| name);
+    doctor::run(doctor::Input {
+        file,
+        profile,
+        resolved: resolve(&options),
+    })
+}
+
+/// `kimi tui`子命令，连接服务端的选项与提问相同
+#[cfg(any(unix, windows))]
+fn run_tui(args: &[String]) -> Result<(), String> {
+    let options = parse_args(args)?;
+    if !options.prompt.is_empty() || options.stdin || !options.files.is_empty() || options.index.is_some() {
+        return Err(
+            "用法: kimi tui [--profile <名称>] [--model <模型>] [--system <提示词>] [--resume [id]] [连接选项]".to_string(),
+        );
+    }
+    let file = load_config()?;
+    let profile = selected_profile(options.profile.as_ref(), &file).map(|(name, _)| name);
+    let (config, defaults) = resolve(&options)?;
+    let client = Client::new(config).map_err(|e| e.to_string())?;
+    let store = Store::open()?;
+    let conversation = open_conversation(&options, &store, defaults.clone())?;
+    tui::run(tui::Input {
+        client,
+        store,
+        conversation,
+        profile,
+        profiles: file.profile_names(),
+        defaults,
+        // 切换配置档后只用配置档中的设置，不再使用命令行中的地址和令牌
+        connect: Box::new(|name| resolve(&parse_args(&["--profile".to_string(), name.to_string()])?)),
+    })
+}
+
+#[cfg(not(any(unix, windows)))]
+fn run_tui(_args: &[String]) -> Result<(), String> {
+    Err("kimi tui 目前只支持 Linux、macOS 和 Windows".to_string())
+}
+
+/// `kimi git`子命令，连接服务端的选项与提问相同
+fn run_git(args: &[String]) -> Result<(), String> {
+    let usage = "用法: kimi git commit [--hint <说明>] [--template <文件>] [-y|--print] [--pr] [-- <git commit 的参数>]，\
+                 kimi git pr [--base <分支>] [--hint <说明>] [--template <文件>] [-o <文件>]";
+    let (action, args) = match args.split_first() {
+        Some((action, rest)) if action == "commit" || action == "pr" => (action.as_str(), rest),
+        _ => return Err(usage.to_string()),
+    };
+    let mut options = git::Options {
+        model: String::new(),
+        system: None,
+        template: None,
+        hint: None,
+        yes: false,
+        print: false,
+        pr: false,
+        base: None,
+        output: None,
+        git_args: Vec::new(),
+    };
+    let mut rest = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--hint" => options.hint = Some(iter.next().ok_or("--hint 需要说明")?.clone()),
+            "--template" => options.template = Some(PathBuf::from(iter.next().ok_or("--template 需要文件路径")?)),
+            "--base" => options.base = Some(iter.next().ok_or("--base 需要分支名称")?.clone()),
+            "-o" | "--output" => options.output = Some(PathBuf::from(iter.next().ok_or("-o 需要文件路径")?)),
+            "-y" | "--yes" => options.yes = true,
+            "--print" => options.print = true,
+            "--pr" => options.pr = true,
+            "--" => options.git_args.extend(iter.by_ref().cloned()),
+            _ => rest.push(arg.clone()),
+        }
+    }
+    let connection = parse_args(&rest)?;
+    if !connection.prompt.is_empty() || connection.stdin || !connection.files.is_empty() || connection.index.is_some() {
+        return Err(usage.to_string());
+    }
+    if action == "pr" && !options.git_args.is_empty() {
+        return Err("kimi git pr 不接受 git commit 的参数".to_string());
+    }
+    let (config, profile) = resolve(&connection)?;
+    options.model = profile.model.unwrap_or_default();
+    options.system = profile.system;
+    let client = Client::new(config).map_err(|e| e.to_string())?;
+    match action {
+        "commit" => git::commit(&client, &options),
+        _ => git::pr(&client, &options),
+    }
+}
+
+/// `kimi sh`子命令，连接服务端的选项与提问相同
+fn run_sh(args: &[String]) -> Result<(), String> {
+    let mut shell = None;
+    let mut print = false;
+    let mut rest = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--shell" => shell = Some(iter.next().ok_or("--shell 需要程序名称")?.clone()),
+            "--print" => print = true,
+            _ => rest.push(arg.clone()),
+        }
+    }
+    let options = parse_args(&rest)?;
+    if options.prompt.is_empty() || options.stdin || !options.files.is_empty() || options.index.is_some() {
+        return Err("用法: kimi sh [--shell <程序>] [--print] <需求...>".to_string());
+    }
+    let (config, profile) = resolve(&options)?;
+    let client = Client::new(config).map_err(|e| e.to_string())?;
+    sh::run(
+        &client,
+        sh::Options {
+            model: profile.model.unwrap_or_default(),
+            system: profile.system,
+            shell,
+            print,
+            request: options.prompt.join(" "),
+        },
+    )
+}
+
+/// `kimi preset`子命令，连接服务端的选项与提问相同
+fn run_preset(args: &[String]) -> Result<(), String> {
+    let usage = "用法: kimi preset [list]，kimi preset show <名称> [--var <名称=值>...]，kimi preset push|pull <名称> [--force]";
+    let mut force = false;
+    let mut rest = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--force" => force = true,
+            _ => rest.push(arg.clone()),
+        }
+    }
+    let options = parse_args(&rest)?;
+    if options.preset.is_some() || options.stdin || !options.files.is_empty() || options.index.is_some() {
+        return Err(usage.to_string());
+    }
+    // 只需要连接服务端的设置，不渲染配置档中的预设
+    let (config, _) = merge(&options)?;
+    let prompt: Vec<&str> = options.prompt.iter().map(String::as_str).collect();
+    if !options.variables.is_empty() && !matches!(prompt.as_slice(), ["show", _]) {
+        return Err(usage.to_string());
+    }
+    match prompt.as_slice() {
+        [] | ["list"] => preset::list(&config),
+        ["show", name] => {
+            println!("{}", preset::render(&config, name, &options.variables)?);
+            Ok(())
+        }
+        ["push", name] => preset::push(&config, name),
+        ["pull", name] => preset::pull(&config, name, force),
+        _ => Err(usage.to_string()),
+    }
+}
+
+/// `kimi completions`子命令，把补全脚本输出到标准输出
+fn run_completions(args: &[String]) -> Result<(), String> {
+    let [shell] = args else {
+        return Err("用法: kimi completions <bash|zsh|fish|powershell>".to_string());
+    };
+    let shell: Shell = shell.parse()?;
+    print!("{}", openkimi_completions::generate(shell, &completions::command()));
+    Ok(())
+}
+
+/// `kimi tokens`子命令，模型和系统提示词与提问时相同
+fn run_tokens(args: &[String]) -> Result<(), String> {
+    let number = |option: &str, value: Option<&String>| {
+        let value = value.ok_or_else(|| format!("{} 需要一个数", option))?;
+        value.parse::<usize>().map_err(|_| format!("无效的token数: {}", value))
+    };
+    let mut context = None;
+    let mut reserve = tokens::DEFAULT_RESERVE;
+    let mut split = None;
+    let mut json = false;
+    let mut rest = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--context" => context = Some(number(arg, iter.next())?),
+            "--reserve" => reserve = number(arg, iter.next())?,
+            "--split" => split = Some(PathBuf::from(iter.next().ok_or("--split 需要目录")?)),
+            "--json" => json = true,
+            _ => rest.push(arg.clone()),
+        }
+    }
+    let options = parse_args(&rest)?;
+    let (_, profile) = resolve(&options)?;
+    let mut inputs = options.prompt;
+    if options.stdin {
+        inputs.push("-".to_string());
+    }
+    tokens::run(tokens::Options {
+        inputs,
+        model: profile.model.unwrap_or_default(),
+        system: profile.system,
+        context,
+        reserve,
+        split,
+        json,
+    })
+}
+
+/// 新的对话，或`--resume`继续的对话
+fn open_conversation(options: &Options, store: &Store, profile: Profile) -> Result<Conversation, String> {
+    let conversation = match &options.resume {
+        // 继续之前的对话时，只有命令行中指定的模型和提示词覆盖保存的
+        Some(id) => {
+            let mut conversation = match id {
+                Some(id) => store.load(id)?,
+                None => store.latest()?,
+            };
+            if let Some(model) = &options.model {
+                conversation.set_model(model.clone());
+            }
+            // `profile.system`为--system或渲染后的--preset
+            if options.system.is_some() || options.preset.is_some() {
+                conversation.set_system(profile.system);
+            }
+            conversation
+        }
+        None => Conversation::new(profile.model.unwrap_or_default(), profile.system),
+    };
+    Ok(conversation)
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let (config, profile) = resolve(&options)?;
+    let client = Client::new(config).map_err(|e| e.to_string())?;
+    let store = Store::open()?;
+    let conversation = open_conversation(&options, &store, profile)?;
+
+    let mut repl = Repl {
+        client,
+        store,
+        conversation,
+        stream: options.stream,
+        render: !options.raw
+            && io::stdout().is_terminal()
+            && env::var_os("NO_COLOR").is_none()
+            && env::var("TERM").map_or(true, |term| term != "dumb"),
+        attachments: Vec::new(),
+        index: options.index.as_deref().map(Index::open).transpose()?,
+        retrieved: Vec::new(),
+    };
+    for file in &options.files {
+        repl.attach(file)?;
+    }
+    let piped = !io::stdin().is_terminal();
+    let once = options.ask || options.stdin || piped || !options.prompt.is_empty();
+    if options.note.is_some() && (!once || options.index.is_none()) {
+        return Err("--note 只用于带 --index 的一次性提问".to_string());
+    }
+    if once {
+        let mut question = options.prompt.join(" ");
+        if options.stdin || piped {
+            let mut content = String::new();
+            io::stdin().read_to_string(&mut content).map_err(|e| format!("读取标准输入失败: {}", e))?;
+            if !question.is_empty() && !content.trim().is_empty() {
+                question.push_str("\n\n");
+            }
+            question.push_str(content.trim_end());
+        }
+        if question.trim().is_empty() {
+            return Err("没有输入问题".to_string());
+        }
+        repl.question(question.clone())?;
+        let result = repl.complete();
+        if options.resume.is_some() {
+            repl.save();
+        }
+        result.map_err(|e| e.to_string())?;
+        if let (Some(vault), Some(index)) = (&options.note, &options.index) {
+            let answer = notes::answer(&repl, question, index);
+            let template = notes::template("answer", None)?;
+            let path = notes::write_answer(vault, &answer, &template)?;
+            eprintln!("📝 已写入笔记 {}", path.display());
+        }
+        return Ok(());
+    }
+
+    if options.resume.is_some() {
+        repl.print_transcript();
+    }
+    repl.run(&mut io::stdin().lock()).map_err(|e| format!("读取输入失败: {}", e))
+}
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    // `kimi ask ...`只回答一次，与带问题的`kimi ...`相同
+    let ask = args.first().is_some_and(|arg| arg == "ask");
+    if ask {
+        args.remove(0);
+    }
+    let subcommand = match args.first().map(String::as_str) {
+        Some("config") => Some(run_config as fn(&[String]) -> Result<(), String>),
+        Some("history") => Some(run_history as fn(&[String]) -> Result<(), String>),
+        Some("export") => Some(run_export as fn(&[String]) -> Result<(), String>),
+        Some("import") => Some(run_import as fn(&[String]) -> Result<(), String>),
+        Some("index") => Some(run_index as fn(&[String]) -> Result<(), String>),
+        Some("tokens") => Some(run_tokens as fn(&[String]) -> Result<(), String>),
+        Some("models") => Some(run_models as fn(&[String]) -> Result<(), String>),
+        Some("model") => Some(run_model as fn(&[String]) -> Result<(), String>),
+        Some("completions") => Some(run_completions as fn(&[String]) -> Result<(), String>),
+        Some("doctor") => Some(run_doctor as fn(&[String]) -> Result<(), String>),
+        Some("tui") => Some(run_tui as fn(&[String]) -> Result<(), String>),
+        Some("git") => Some(run_git as fn(&[String]) -> Result<(), String>),
+        Some("sh") => Some(run_sh as fn(&[String]) -> Result<(), String>),
+        Some("preset") => Some(run_preset as fn(&[String]) -> Result<(), String>),
+        // `kimi resume [id]`与`kimi --resume [id]`相同
+        Some("resume") => {
+            args[0] = "--resume".to_string();
+            None
+        }
+        _ => None,
+    };
+    if let Some(subcommand) = subcommand {
+        return match subcommand(&args[1..]) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("❌ {}", err);
+                ExitCode::FAILURE
+            }
+        };
+    }
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        print_usage();
+        return ExitCode::SUCCESS;
+    }
+    let options = match parse_args(&args) {
+        Ok(options) => Options { ask, ..options },
+        Err(err) => {
+            eprintln!("❌ {}", err);
+            print_usage();
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("❌ {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
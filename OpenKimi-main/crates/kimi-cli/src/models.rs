@@ -0,0 +1,88 @@
+//! 服务端提供的模型：`kimi models`和交互式对话中的`/model`
+//!
+//! OpenKimi服务端在`/v1/models`中返回每个模型的类型、上下文窗口、接受的输入和单价；其他兼容OpenAI的服务端
+//! 只返回模型名，这时上下文窗口按`openkimi_tokenizer`的内置表估计，前面标`≈`，其余各列为`-`。
+
+use openkimi_client::Model;
+use unicode_width::UnicodeWidthStr;
+
+/// 按显示宽度在右边补空格
+fn pad(text: &str, width: usize) -> String {
+    format!("{}{}", text, " ".repeat(width.saturating_sub(text.width())))
+}
+
+/// `256K`这样的上下文窗口，不是1024的整数倍时原样显示
+fn format_tokens(tokens: u32) -> String {
+    if tokens >= 1024 && tokens.is_multiple_of(1024) {
+        format!("{}K", tokens / 1024)
+    } else {
+        tokens.to_string()
+    }
+}
+
+/// 去掉单价末尾多余的0，如`2.50`显示为`2.5`
+fn format_price(price: f64) -> String {
+    let text = format!("{:.4}", price);
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+fn row(model: &Model) -> [String; 4] {
+    let kind = match model.kind.as_deref() {
+        Some("chat") => "对话".to_string(),
+        Some("embedding") => "嵌入".to_string(),
+        Some(kind) => kind.to_string(),
+        None => "-".to_string(),
+    };
+    let context = match model.context_length {
+        Some(tokens) => format_tokens(tokens),
+        None => openkimi_tokenizer::context_window(&model.id)
+            .map_or_else(|| "-".to_string(), |tokens| format!("≈{}", format_tokens(tokens))),
+    };
+    let modalities = if model.modalities.is_empty() {
+        "-".to_string()
+    } else {
+        model
+            .modalities
+            .iter()
+            .map(|modality| match modality.as_str() {
+                "text" => "文本",
+                "image" => "图片",
+                "audio" => "音频",
+                other => other,
+            })
+            .collect::<Vec<_>>()
+            .join("、")
+    };
+    let pricing = match &model.pricing {
+        Some(pricing) => format!(
+            "输入 {} / 输出 {} {}",
+            format_price(pricing.prompt),
+            format_price(pricing.completion),
+            pricing.currency
+        ),
+        None => "-".to_string(),
+    };
+    [kind, context, modalities, pricing]
+}
+
+/// 以表格列出模型，`current`前面标`*`
+pub fn print(models: &[Model], current: &str) {
+    let header = ["模型", "类型", "上下文", "输入", "单价（每百万token）"];
+    let rows: Vec<(String, [String; 4])> = models.iter().map(|model| (model.id.clone(), row(model))).collect();
+    let mut widths: Vec<usize> = header.iter().map(|title| title.width()).collect();
+    for (id, columns) in &rows {
+        widths[0] = widths[0].max(id.width());
+        for (index, column) in columns.iter().enumerate() {
+            widths[index + 1] = widths[index + 1].max(column.width());
+        }
+    }
+    let line = |marker: &str, cells: [&str; 5]| {
+        let cells: Vec<String> = cells.iter().zip(&widths).map(|(cell, width)| pad(cell, *width)).collect();
+        println!("{} {}", marker, cells.join("  ").trim_end());
+    };
+    line(" ", header);
+    for (id, [kind, context, modalities, pricing]) in &rows {
+        let marker = if id == current { "*" } else { " " };
+        line(marker, [id, kind, context, modalities, pricing]);
+    }
+}
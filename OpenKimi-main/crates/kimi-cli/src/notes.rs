@@ -0,0 +1,107 @@
+//! 导出为Obsidian和Notion的笔记：`kimi export --format obsidian|notion`和`kimi ask --note`
+//!
+//! 笔记的内容见[`openkimi_sessions::notes`]。Obsidian的笔记直接写入库的目录，再次导出同一个对话时覆盖，
+//! 目录笔记按库中已有的对话笔记重新生成；Notion的笔记打包成一个zip。模板依次取`--template`指定的文件、
+//! 配置文件所在目录下的`templates/conversation.md`和`templates/answer.md`，都没有时使用内置的模板。
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use openkimi_sessions::notes::{self, Answer, AnswerSource, Layout, Note, ANSWER_TEMPLATE, CONVERSATION_TEMPLATE};
+use serde_json::Value;
+
+use crate::conversation::now;
+use crate::repl::Repl;
+use crate::{config, index};
+
+/// 笔记模板：`conversation`或`answer`
+pub fn template(kind: &str, option: Option<&Path>) -> Result<String, String> {
+    let default = if kind == "answer" { ANSWER_TEMPLATE } else { CONVERSATION_TEMPLATE };
+    config::template(kind, option, default)
+}
+
+fn write(root: &Path, note: &Note) -> Result<PathBuf, String> {
+    let path = root.join(&note.path);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("创建目录 {} 失败: {}", dir.display(), e))?;
+    }
+    fs::write(&path, &note.content).map_err(|e| format!("写入 {} 失败: {}", path.display(), e))?;
+    Ok(path)
+}
+
+/// 把会话文档写入Obsidian库，更新目录笔记
+pub fn write_obsidian(vault: &Path, documents: &[Value], template: &str) -> Result<(), String> {
+    for document in documents {
+        write(vault, &notes::conversation_note(document, Layout::Obsidian, template))?;
+    }
+    // 目录列出库中所有的对话笔记，包括以前导出的
+    let dir = vault.join(Layout::Obsidian.conversations_dir());
+    let entries = fs::read_dir(&dir).map_err(|e| format!("读取目录 {} 失败: {}", dir.display(), e))?;
+    let paths: Vec<String> = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| name.ends_with(".md"))
+        .map(|name| format!("{}/{}", Layout::Obsidian.conversations_dir(), name))
+        .collect();
+    write(vault, &notes::catalog_note(&paths, Layout::Obsidian))?;
+    Ok(())
+}
+
+/// 把会话文档打包成Notion可以导入的zip
+pub fn write_notion(output: &Path, documents: &[Value], template: &str) -> Result<(), String> {
+    let mut pages: Vec<Note> = documents
+        .iter()
+        .map(|document| notes::conversation_note(document, Layout::Notion, template))
+        .collect();
+    let paths: Vec<String> = pages.iter().map(|page| page.path.clone()).collect();
+    pages.push(notes::catalog_note(&paths, Layout::Notion));
+    let data = notes::zip(&pages).map_err(|e| e.to_string())?;
+    fs::write(output, data).map_err(|e| format!("写入 {} 失败: {}", output.display(), e))
+}
+
+/// 刚完成的一次检索问答，`question`为补充资料之前的问题
+pub fn answer(repl: &Repl, question: String, index: &str) -> Answer {
+    let conversation = &repl.conversation;
+    let answer = conversation.messages().last().filter(|message| message.role == "assistant");
+    Answer {
+        question,
+        answer: answer.map(|message| message.text()).unwrap_or_default(),
+        index: index.to_string(),
+        model: Some(conversation.model.clone()).filter(|model| !model.is_empty()),
+        created_at: now(),
+        sources: repl
+            .retrieved
+            .iter()
+            .map(|hit| AnswerSource {
+                document: hit
+                    .record
+                    .metadata
+                    .get("source")
+                    .and_then(Value::as_str)
+                    .unwrap_or(&hit.record.document)
+                    .to_string(),
+                location: index::source(hit),
+                text: hit.record.text.clone(),
+            })
+            .collect(),
+    }
+}
+
+/// 把问答写入Obsidian库，同名的问答笔记加上序号；返回问答笔记的路径
+pub fn write_answer(vault: &Path, answer: &Answer, template: &str) -> Result<PathBuf, String> {
+    let base = notes::answer_path(answer);
+    let stem = base.strip_suffix(".md").unwrap_or(&base);
+    let path = (1..)
+        .map(|number| {
+            if number == 1 {
+                base.clone()
+            } else {
+                format!("{} {}.md", stem, number)
+            }
+        })
+        .find(|path| !vault.join(path).exists())
+        .unwrap_or(base.clone());
+    for note in notes::source_notes(answer, |path| fs::read_to_string(vault.join(path)).ok()) {
+        write(vault, &note)?;
+    }
+    write(vault, &notes::answer_note(answer, &path, template))
+}
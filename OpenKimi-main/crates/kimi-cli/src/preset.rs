@@ -0,0 +1,207 @@
+//! 系统提示词预设：`--preset <名称>`和`kimi preset`
+//!
+//! 本地的预设是配置文件所在目录下的`presets/<名称>.md`，内容是模板，语法与服务端的提示词模板相同，见
+//! [`openkimi_template`]，开头的`{# ... #}`注释作为说明。本地没有时使用服务端当前工作区中同名的提示词模板
+//! （`/v1/prompts`），由服务端按模板声明的变量校验并渲染。`--var 名称=值`提供变量，值按JSON解析，不是合法的
+//! JSON时作为字符串。预设文件可以直接复制给别人，`kimi preset push`把本地的预设保存到服务端供整个工作区使用，
+//! `kimi preset pull`把服务端的模板保存为本地的预设。
+
+use std::fs;
+use std::path::PathBuf;
+
+use openkimi_client::blocking::Client;
+use openkimi_client::{ClientConfig, ClientError};
+use openkimi_template::Template;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::config;
+
+/// 服务端的提示词模板，只取用到的字段
+#[derive(Debug, Deserialize)]
+struct ServerPrompt {
+    name: String,
+    version: u32,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    template: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ServerPrompts {
+    data: Vec<ServerPrompt>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Rendered {
+    content: String,
+}
+
+/// 与服务端的模板名相同：字母、数字、`_`、`-`和`.`，不以`.`开头
+fn check_name(name: &str) -> Result<(), String> {
+    let valid = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+    if !name.is_empty() && name.len() <= 64 && valid && !name.starts_with('.') {
+        Ok(())
+    } else {
+        Err(format!(
+            "无效的预设名称 {:?}：只能包含字母、数字、_、- 和 .，且不能以 . 开头",
+            name
+        ))
+    }
+}
+
+/// 预设所在的目录
+pub fn dir() -> Result<PathBuf, String> {
+    config::default_path()
+        .and_then(|path| Some(path.parent()?.join("presets")))
+        .ok_or_else(|| "无法确定配置文件的位置，请设置 OPENKIMI_CONFIG".to_string())
+}
+
+fn path(name: &str) -> Result<PathBuf, String> {
+    check_name(name)?;
+    Ok(dir()?.join(format!("{}.md", name)))
+}
+
+/// 本地的预设，没有时为`None`
+fn local(name: &str) -> Result<Option<String>, String> {
+    let path = path(name)?;
+    if !path.is_file() {
+        return Ok(None);
+    }
+    fs::read_to_string(&path).map(Some).map_err(|e| format!("读取预设 {} 失败: {}", path.display(), e))
+}
+
+/// 开头注释的第一行
+fn description(source: &str) -> Option<String> {
+    let comment = source.trim_start().strip_prefix("{#")?.split_once("#}")?.0;
+    let comment = comment.trim_matches(|c: char| c == '-' || c.is_whitespace());
+    comment.lines().next().map(String::from).filter(|line| !line.is_empty())
+}
+
+/// 解析`--var 名称=值`
+pub fn parse_var(arg: &str) -> Result<(String, Value), String> {
+    let (name, value) = arg.split_once('=').ok_or_else(|| format!("--var 的格式为 名称=值: {}", arg))?;
+    let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+    Ok((name.trim().to_string(), value))
+}
+
+fn compile(name: &str, source: &str) -> Result<Template, String> {
+    Template::compile(source).map_err(|e| format!("预设 {} 的模板有误: {}", name, e))
+}
+
+fn client(config: &ClientConfig) -> Result<Client, String> {
+    Client::new(config.clone()).map_err(|e| e.to_string())
+}
+
+/// 渲染预设，本地没有时由服务端渲染
+pub fn render(config: &ClientConfig, name: &str, variables: &Map<String, Value>) -> Result<String, String> {
+    if let Some(source) = local(name)? {
+        let template = compile(name, &source)?;
+        let used = template.variables();
+        if let Some(unknown) = variables.keys().find(|key| !used.contains(*key)) {
+            return Err(format!("预设 {} 没有使用变量 {}", name, unknown));
+        }
+        return Ok(template.render(variables).trim().to_string());
+    }
+    let request = json!({ "variables": variables });
+    match client(config)?.post::<_, Rendered>(&format!("/prompts/{}/render", name), &request) {
+        Ok(rendered) => Ok(rendered.content.trim().to_string()),
+        Err(ClientError::NotFound(_)) => Err(format!(
+            "找不到预设 {}：本地没有 {}，服务端的工作区中也没有同名的提示词模板",
+            name,
+            path(name)?.display()
+        )),
+        Err(err) => Err(format!("渲染服务端的预设 {} 失败: {}", name, err)),
+    }
+}
+
+/// 本地的预设名称和说明，按名称排序
+fn locals() -> Result<Vec<(String, Option<String>)>, String> {
+    let dir = dir()?;
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("读取目录 {} 失败: {}", dir.display(), e)),
+    };
+    let mut presets: Vec<(String, Option<String>)> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = path.file_name()?.to_str()?.strip_suffix(".md")?.to_string();
+            let source = fs::read_to_string(&path).ok()?;
+            Some((name, description(&source)))
+        })
+        .collect();
+    presets.sort();
+    Ok(presets)
+}
+
+/// `kimi preset list`：本地的和服务端的预设；连不上服务端时只列出本地的
+pub fn list(config: &ClientConfig) -> Result<(), String> {
+    let locals = locals()?;
+    println!("本地（{}）:", dir()?.display());
+    if locals.is_empty() {
+        println!("  （没有）");
+    }
+    for (name, description) in &locals {
+        println!("  {:<20} {}", name, description.as_deref().unwrap_or(""));
+    }
+    match client(config)?.get::<ServerPrompts>("/prompts") {
+        Ok(prompts) => {
+            println!("服务端（工作区 {}）:", config.workspace.as_deref().unwrap_or("default"));
+            if prompts.data.is_empty() {
+                println!("  （没有）");
+            }
+            for prompt in prompts.data {
+                // 同名时使用本地的
+                let shadowed = if locals.iter().any(|(name, _)| name == &prompt.name) {
+                    "（被本地的覆盖）"
+                } else {
+                    ""
+                };
+                let description = prompt.description.unwrap_or_default();
+                println!("  {:<20} v{} {}{}", prompt.name, prompt.version, description, shadowed);
+            }
+        }
+        Err(err) => eprintln!("⚠️ 无法列出服务端的预设: {}", err),
+    }
+    Ok(())
+}
+
+/// `kimi preset push`：保存为服务端同名模板的新版本，用到的变量都声明为可选、不限类型
+pub fn push(config: &ClientConfig, name: &str) -> Result<(), String> {
+    let source = local(name)?.ok_or_else(|| format!("本地没有预设 {}", name))?;
+    let variables: Vec<Value> = compile(name, &source)?
+        .variables()
+        .into_iter()
+        .map(|variable| json!({ "name": variable, "type": "any", "required": false }))
+        .collect();
+    let request = json!({
+        "name": name,
+        "description": description(&source),
+        "template": source,
+        "variables": variables,
+    });
+    let prompt: ServerPrompt = client(config)?.post("/prompts", &request).map_err(|e| e.to_string())?;
+    println!("已保存到服务端: {} v{}", prompt.name, prompt.version);
+    Ok(())
+}
+
+/// `kimi preset pull`：保存服务端模板的最新版本，已有同名的本地预设时需要`force`
+pub fn pull(config: &ClientConfig, name: &str, force: bool) -> Result<(), String> {
+    let path = path(name)?;
+    if path.exists() && !force {
+        return Err(format!("{} 已存在，加 --force 覆盖", path.display()));
+    }
+    let prompt: ServerPrompt = client(config)?.get(&format!("/prompts/{}", name)).map_err(|e| e.to_string())?;
+    let content = match &prompt.description {
+        Some(text) if description(&prompt.template).is_none() => format!("{{# {} #}}\n{}", text, prompt.template),
+        _ => prompt.template,
+    };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("创建目录 {} 失败: {}", dir.display(), e))?;
+    }
+    fs::write(&path, content).map_err(|e| format!("写入 {} 失败: {}", path.display(), e))?;
+    println!("已保存 {} v{} 到 {}", prompt.name, prompt.version, path.display());
+    Ok(())
+}
@@ -0,0 +1,385 @@
+//! 在终端中按Markdown样式显示回复
+//!
+//! 流式回复逐行渲染：一行完整后才输出，标题、列表、引用和行内的粗体、斜体、代码、链接用ANSI样式显示，
+//! 围栏代码块按语言高亮；表格要等最后一行到达后才能对齐，整张表一起输出。宽度按终端中的显示宽度计算，
+//! 中文等宽字符占两列。关闭渲染时原样输出。
+
+use std::env;
+use std::io::{self, Write};
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::highlight::Highlighter;
+
+/// ANSI样式
+pub mod color {
+    pub const BOLD: &str = "1";
+    pub const DIM: &str = "2";
+    pub const ITALIC: &str = "3";
+    pub const UNDERLINE: &str = "4";
+    pub const STRIKE: &str = "9";
+    pub const HEADING: &str = "1;36";
+    pub const CODE: &str = "33";
+    pub const KEYWORD: &str = "35";
+    pub const STRING: &str = "32";
+    pub const NUMBER: &str = "36";
+    pub const COMMENT: &str = "90";
+}
+
+const RESET: &str = "\x1b[0m";
+
+/// 给文字加上样式；文字中已有的样式结束后恢复外层的样式，以便嵌套
+pub fn paint(text: &str, style: &str) -> String {
+    let start = format!("\x1b[{}m", style);
+    format!(
+        "{}{}{}",
+        start,
+        text.replace(RESET, &format!("{}{}", RESET, start)),
+        RESET
+    )
+}
+
+/// 在终端中的显示宽度，不计ANSI样式
+fn display_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|c| c.is_ascii_alphabetic());
+        } else {
+            width += c.width().unwrap_or(0);
+        }
+    }
+    width
+}
+
+/// 分隔线的宽度，取`COLUMNS`，最多60列
+fn rule_width() -> usize {
+    env::var("COLUMNS").ok().and_then(|columns| columns.parse().ok()).unwrap_or(80).clamp(3, 60)
+}
+
+/// 从`start`之后找`marker`，要求中间有内容
+fn closing(text: &str, start: usize, marker: &str) -> Option<usize> {
+    let index = text[start..].find(marker)? + start;
+    (index > start).then_some(index)
+}
+
+/// 渲染一行中的行内样式
+fn inline(text: &str) -> String {
+    let mut out = String::new();
+    let mut index = 0;
+    let mut previous = ' ';
+    while index < text.len() {
+        let rest = &text[index..];
+        let c = rest.chars().next().unwrap_or_default();
+
+        // 反斜杠转义的标点原样输出
+        if c == '\\' {
+            if let Some(next) = rest[1..].chars().next().filter(char::is_ascii_punctuation) {
+                out.push(next);
+                index += 1 + next.len_utf8();
+                previous = next;
+                continue;
+            }
+        }
+        if c == '`' {
+            let ticks = rest.len() - rest.trim_start_matches('`').len();
+            let marker = &rest[..ticks];
+            if let Some(end) = closing(rest, ticks, marker) {
+                out.push_str(&paint(rest[ticks..end].trim(), color::CODE));
+                index += end + ticks;
+                previous = '`';
+                continue;
+            }
+            out.push_str(marker);
+            index += ticks;
+            continue;
+        }
+        let styled = [
+            ("**", color::BOLD),
+            ("__", color::BOLD),
+            ("~~", color::STRIKE),
+            ("*", color::ITALIC),
+            ("_", color::ITALIC),
+        ]
+        .into_iter()
+        .filter(|(marker, _)| rest.starts_with(marker))
+        // `_`只在单词边界起作用，避免把snake_case当成斜体
+        .filter(|(marker, _)| !marker.starts_with('_') || !previous.is_alphanumeric())
+        .find_map(|(marker, style)| {
+            let end = closing(rest, marker.len(), marker)?;
+            let inner = &rest[marker.len()..end];
+            (!inner.starts_with(' ') && !inner.ends_with(' '))
+                .then(|| (paint(&inline(inner), style), end + marker.len()))
+        });
+        if let Some((styled, len)) = styled {
+            out.push_str(&styled);
+            index += len;
+            previous = '*';
+            continue;
+        }
+        if c == '[' {
+            let link = rest.find("](").and_then(|middle| Some((middle, rest[middle..].find(')')? + middle)));
+            if let Some((middle, end)) = link {
+                let (label, url) = (&rest[1..middle], &rest[middle + 2..end]);
+                out.push_str(&paint(&inline(label), color::UNDERLINE));
+                if url != label {
+                    out.push_str(&paint(&format!(" ({})", url), color::DIM));
+                }
+                index += end + 1;
+                previous = ')';
+                continue;
+            }
+        }
+        out.push(c);
+        index += c.len_utf8();
+        previous = c;
+    }
+    out
+}
+
+/// 围栏代码块的开始行，返回围栏和语言
+fn fence(line: &str) -> Option<(String, &str)> {
+    let marker = line.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let len = line.len() - line.trim_start_matches(marker).len();
+    (len >= 3).then(|| {
+        (
+            line[..len].to_string(),
+            line[len..].split_whitespace().next().unwrap_or(""),
+        )
+    })
+}
+
+/// 只由`-`、`*`或`_`组成的分隔线
+fn is_rule(line: &str) -> bool {
+    let marks: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    marks.len() >= 3 && ["-", "*", "_"].iter().any(|mark| marks.chars().all(|c| c.to_string() == *mark))
+}
+
+/// 表格一行中的单元格，`\|`不分隔
+fn cells(line: &str) -> Vec<String> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = line.strip_suffix('|').filter(|rest| !rest.ends_with('\\')).unwrap_or(line);
+    let mut cells = vec![String::new()];
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'|') => cells.last_mut().unwrap().push(chars.next().unwrap()),
+            '|' => cells.push(String::new()),
+            c => cells.last_mut().unwrap().push(c),
+        }
+    }
+    cells.into_iter().map(|cell| cell.trim().to_string()).collect()
+}
+
+#[derive(Clone, Copy)]
+enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// 表头下的分隔行，返回各列的对齐方式
+fn alignments(line: &str) -> Option<Vec<Align>> {
+    cells(line)
+        .iter()
+        .map(|cell| {
+            let dashes = cell.trim_start_matches(':').trim_end_matches(':');
+            if dashes.is_empty() || !dashes.chars().all(|c| c == '-') {
+                return None;
+            }
+            Some(match (cell.starts_with(':'), cell.ends_with(':')) {
+                (true, true) => Align::Center,
+                (false, true) => Align::Right,
+                _ => Align::Left,
+            })
+        })
+        .collect()
+}
+
+fn pad(text: &str, width: usize, align: Align) -> String {
+    let space = width.saturating_sub(display_width(text));
+    let (left, right) = match align {
+        Align::Left => (0, space),
+        Align::Right => (space, 0),
+        Align::Center => (space / 2, space - space / 2),
+    };
+    format!("{}{}{}", " ".repeat(left), text, " ".repeat(right))
+}
+
+struct CodeBlock {
+    fence: String,
+    highlighter: Highlighter,
+}
+
+/// 流式Markdown渲染器
+pub struct Renderer<W: Write> {
+    out: W,
+    enabled: bool,
+    /// 还没有换行的部分
+    pending: String,
+    code: Option<CodeBlock>,
+    /// 还没有结束的表格
+    table: Vec<String>,
+}
+
+impl<W: Write> Renderer<W> {
+    /// `enabled`为`false`时原样输出
+    pub fn new(out: W, enabled: bool) -> Renderer<W> {
+        Renderer {
+            out,
+            enabled,
+            pending: String::new(),
+            code: None,
+            table: Vec::new(),
+        }
+    }
+
+    /// 写入回复的一段
+    pub fn push(&mut self, text: &str) -> io::Result<()> {
+        if !self.enabled {
+            write!(self.out, "{}", text)?;
+            return self.out.flush();
+        }
+        self.pending.push_str(text);
+        while let Some(index) = self.pending.find('\n') {
+            let line: String = self.pending.drain(..=index).collect();
+            self.line(line.trim_end_matches(['\n', '\r']))?;
+        }
+        self.out.flush()
+    }
+
+    /// 回复结束，输出剩下的内容并换行
+    pub fn finish(&mut self) -> io::Result<()> {
+        if !self.enabled {
+            writeln!(self.out)?;
+            return self.out.flush();
+        }
+        if !self.pending.is_empty() {
+            let line = std::mem::take(&mut self.pending);
+            self.line(line.trim_end_matches('\r'))?;
+        }
+        self.flush_table()?;
+        self.code = None;
+        self.out.flush()
+    }
+
+    fn line(&mut self, line: &str) -> io::Result<()> {
+        let trimmed = line.trim_start();
+        if let Some(code) = &mut self.code {
+            if trimmed.starts_with(code.fence.as_str())
+                && trimmed.trim_start_matches(&code.fence[..1]).trim().is_empty()
+            {
+                self.code = None;
+                return writeln!(self.out, "{}", paint(&"─".repeat(rule_width()), color::DIM));
+            }
+            return writeln!(self.out, "{}", code.highlighter.line(line));
+        }
+
+        if trimmed.starts_with('|') {
+            self.table.push(line.to_string());
+            return Ok(());
+        }
+        self.flush_table()?;
+
+        let indent = &line[..line.len() - trimmed.len()];
+        if let Some((fence, language)) = fence(trimmed) {
+            let label = if language.is_empty() {
+                String::new()
+            } else {
+                format!(" {} ", language)
+            };
+            let rule = "─".repeat(rule_width().saturating_sub(display_width(&label) + 3));
+            writeln!(self.out, "{}", paint(&format!("───{}{}", label, rule), color::DIM))?;
+            self.code = Some(CodeBlock {
+                fence,
+                highlighter: Highlighter::new(language),
+            });
+            return Ok(());
+        }
+        if let Some(text) = trimmed.strip_prefix('#') {
+            let level = 1 + text.len() - text.trim_start_matches('#').len();
+            let text = text.trim_start_matches('#');
+            if level <= 6 && (text.is_empty() || text.starts_with(' ')) {
+                let text = text.trim().trim_end_matches('#').trim_end();
+                return writeln!(self.out, "{}", paint(&inline(text), color::HEADING));
+            }
+        }
+        if is_rule(trimmed) {
+            return writeln!(self.out, "{}", paint(&"─".repeat(rule_width()), color::DIM));
+        }
+        if let Some(quote) = trimmed.strip_prefix('>') {
+            let quote = quote.strip_prefix(' ').unwrap_or(quote);
+            return writeln!(
+                self.out,
+                "{}{}{}",
+                indent,
+                paint("│ ", color::DIM),
+                paint(&inline(quote), color::ITALIC)
+            );
+        }
+        let item = ["- ", "* ", "+ "].iter().find_map(|marker| trimmed.strip_prefix(marker));
+        if let Some(item) = item {
+            let (bullet, item) = if let Some(item) = item.strip_prefix("[ ] ") {
+                ("☐", item)
+            } else if let Some(item) = item.strip_prefix("[x] ").or_else(|| item.strip_prefix("[X] ")) {
+                ("☑", item)
+            } else {
+                ("•", item)
+            };
+            return writeln!(self.out, "{}{} {}", indent, bullet, inline(item));
+        }
+        writeln!(self.out, "{}", inline(line))
+    }
+
+    /// 输出缓存的表格；第二行不是分隔行时不当作表格
+    fn flush_table(&mut self) -> io::Result<()> {
+        if self.table.is_empty() {
+            return Ok(());
+        }
+        let lines = std::mem::take(&mut self.table);
+        let Some(aligns) = lines.get(1).and_then(|line| alignments(line)) else {
+            for line in &lines {
+                writeln!(self.out, "{}", inline(line))?;
+            }
+            return Ok(());
+        };
+
+        let rows: Vec<Vec<String>> = lines
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| *index != 1)
+            .map(|(_, line)| cells(line).iter().map(|cell| inline(cell)).collect())
+            .collect();
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0).max(aligns.len());
+        let mut widths = vec![0; columns];
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(display_width(cell));
+            }
+        }
+
+        let separator = paint(" │ ", color::DIM);
+        for (index, row) in rows.iter().enumerate() {
+            let cells: Vec<String> = (0..columns)
+                .map(|column| {
+                    let cell = row.get(column).map_or("", String::as_str);
+                    let align = aligns.get(column).copied().unwrap_or(Align::Left);
+                    let cell = pad(cell, widths[column], align);
+                    if index == 0 {
+                        paint(&cell, color::BOLD)
+                    } else {
+                        cell
+                    }
+                })
+                .collect();
+            writeln!(self.out, "{}", cells.join(&separator).trim_end())?;
+            if index == 0 {
+                let rule: Vec<String> = widths.iter().map(|width| "─".repeat(*width)).collect();
+                writeln!(self.out, "{}", paint(&rule.join("─┼─"), color::DIM))?;
+            }
+        }
+        Ok(())
+    }
+}
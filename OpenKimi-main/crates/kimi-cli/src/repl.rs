@@ -0,0 +1,329 @@
+//! 交互式对话与斜杠命令
+
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::Path;
+
+use openkimi_client::blocking::Client;
+use openkimi_client::{ChatCompletionRequest, ChatEvent, ChatMessage, ClientError, Usage};
+use openkimi_rag::SearchHit;
+use openkimi_sessions::export::{self, Format};
+use openkimi_sessions::time::format_time;
+use openkimi_sessions::TokenUsage;
+use serde_json::json;
+
+use crate::attach::{self, Attachment};
+use crate::conversation::{short_id, Conversation, Store};
+use crate::index::{self, Index};
+use crate::input;
+use crate::models;
+use crate::render::Renderer;
+
+const HELP: &str = "\
+命令:
+  /model [名称]      切换模型，不带参数时列出可用的模型
+  /attach [文件]     给下一条消息添加附件（图片、PDF、文档或代码），不带参数时列出，/attach - 清除
+  /system [提示词]   设置系统提示词，不带参数时显示，/system - 清除
+  /retry             重新生成最后一条回复，原来的回复保留在另一个分支上
+  /save [文件]       导出当前对话，.json 为会话文档，.html 为网页，其余为Markdown，默认 <id>.md
+  /new               开始新的对话
+  /history           列出最近的对话
+  /load <id>         继续之前的对话，id可以只输入开头几位
+  /help              显示本帮助
+  /exit              退出（也可以按 Ctrl-D）
+多行输入: 行尾加 \\ 续行，或用单独一行 \"\"\" 包围";
+
+pub struct Repl {
+    pub client: Client,
+    pub store: Store,
+    pub conversation: Conversation,
+    /// 逐块显示回复
+    pub stream: bool,
+    /// 按Markdown样式显示回复
+    pub render: bool,
+    /// 随下一条消息发送的附件
+    pub attachments: Vec<Attachment>,
+    /// `--index`：每条消息先从本地索引检索资料
+    pub index: Option<Index>,
+    /// 上一条消息从索引中检索到的分块
+    pub retrieved: Vec<SearchHit>,
+}
+
+impl Repl {
+    pub fn save(&mut self) {
+        if let Err(err) = self.store.save(&mut self.conversation) {
+            eprintln!("⚠️ 保存对话失败: {}", err);
+        }
+    }
+
+    fn model_name(&self) -> &str {
+        if self.conversation.model.is_empty() {
+            "服务端默认"
+        } else {
+            &self.conversation.model
+        }
+    }
+
+    /// 请求回复最后一条用户消息，成功后追加到对话中
+    ///
+    /// 回复写到标准输出，提示写到标准错误；标准输出被关闭（如管道后的`head`已退出）时停止接收。
+    pub fn complete(&mut self) -> Result<(), ClientError> {
+        let request = ChatCompletionRequest::new(self.conversation.model.clone(), self.conversation.request_messages());
+        let mut stdout = Renderer::new(io::stdout(), self.render);
+        let (message, finish, usage) = if self.stream {
+            let request = request.with("stream_options", json!({ "include_usage": true }));
+            let mut events = self.client.chat_stream(&request)?.events();
+            let mut finish = None;
+            for event in events.by_ref() {
+                match event {
+                    Ok(ChatEvent::Content(text)) => {
+                        if stdout.push(&text).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(ChatEvent::Finish(reason)) => finish = Some(reason),
+                    Ok(_) => {}
+                    Err(err) => {
+                        let _ = stdout.finish();
+                        return Err(err);
+                    }
+                }
+            }
+            let _ = stdout.finish();
+            let response = events.collected();
+            (response.choices.into_iter().next().map(|choice| choice.message), finish, response.usage)
+        } else {
+            let response = self.client.chat(&request)?;
+            let _ = stdout.push(&response.text()).and_then(|_| stdout.finish());
+            let choice = response.choices.into_iter().next();
+            let finish = choice.as_ref().and_then(|choice| choice.finish_reason.clone());
+            (choice.map(|choice| choice.message), finish, response.usage)
+        };
+        if finish.as_deref() == Some("length") {
+            eprintln!("⚠️ 回复达到长度上限被截断");
+        }
+        let mut message = message.unwrap_or_else(|| ChatMessage::assistant(""));
+        message.role = "assistant".to_string();
+        let usage = usage.map(|usage: Usage| TokenUsage {
+            prompt_tokens: usage.prompt_tokens.into(),
+            completion_tokens: usage.completion_tokens.into(),
+        });
+        self.conversation.push(message, usage);
+        Ok(())
+    }
+
+    /// 读取附件，随下一条消息发送
+    pub fn attach(&mut self, path: &str) -> Result<(), String> {
+        let attachment = attach::load(&self.client, path)?;
+        eprintln!("📎 {}（{}）", attachment.name, attachment.summary);
+        self.attachments.push(attachment);
+        Ok(())
+    }
+
+    /// 追加一条用户消息，带上从索引检索到的资料和已添加的附件
+    pub fn question(&mut self, text: String) -> Result<(), String> {
+        let text = match &self.index {
+            Some(index) => {
+                let hits = index.search(&self.client, &text)?;
+                if hits.is_empty() {
+                    eprintln!("🔍 索引 {} 中没有找到相关内容", index.name);
+                } else {
+                    let mut sources: Vec<String> = hits.iter().map(index::source).collect();
+                    sources.dedup();
+                    eprintln!("🔍 从索引 {} 检索到 {} 段: {}", index.name, hits.len(), sources.join("、"));
+                }
+                let text = index::augment(text, &index.name, &hits);
+                self.retrieved = hits;
+                text
+            }
+            None => text,
+        };
+        let attachments = std::mem::take(&mut self.attachments);
+        let (message, records) = attach::message(text, &attachments);
+        self.conversation.push_attached(message, records);
+        Ok(())
+    }
+
+    /// 发送一条用户消息，出错时保留这条消息，可以用`/retry`重试
+    fn send(&mut self, text: String) {
+        match self.question(text) {
+            Ok(()) => self.reply(),
+            Err(err) => eprintln!("❌ {}", err),
+        }
+    }
+
+    fn reply(&mut self) {
+        if let Err(err) = self.complete() {
+            eprintln!("❌ {}", err);
+            eprintln!("输入 /retry 重试");
+        }
+        self.save();
+    }
+
+    fn list_models(&self) -> Result<(), ClientError> {
+        let models = self.client.models()?;
+        models::print(&models.data, &self.conversation.model);
+        println!("当前模型: {}", self.model_name());
+        Ok(())
+    }
+
+    fn history(&self) -> Result<(), String> {
+        let sessions = self.store.list(20)?;
+        if sessions.is_empty() {
+            println!("还没有保存的对话");
+            return Ok(());
+        }
+        for session in &sessions {
+            let marker = if Some(&session.id) == self.conversation.id.as_ref() { "*" } else { " " };
+            println!(
+                "{} {}  {}  {:>3} 条  {}",
+                marker,
+                short_id(&session.id),
+                format_time(session.updated_at),
+                session.message_count,
+                session.title
+            );
+        }
+        println!("对话保存在 {}", self.store.path().display());
+        Ok(())
+    }
+
+    fn export(&self, path: Option<&str>) -> Result<String, String> {
+        let path = path.map(String::from).unwrap_or_else(|| {
+            format!("{}.md", self.conversation.id.as_deref().map_or("kimi", short_id))
+        });
+        let format = Path::new(&path)
+            .extension()
+            .and_then(|extension| Format::from_name(&extension.to_string_lossy()))
+            .unwrap_or(Format::Markdown);
+        // 保存过的对话从会话库导出，带有消息的时间和附件
+        let document = match &self.conversation.id {
+            Some(id) => self.store.export(id)?.1,
+            None => self.conversation.to_document(),
+        };
+        let content = export::render(&document, format);
+        fs::write(&path, content).map_err(|e| format!("写入 {} 失败: {}", path, e))?;
+        Ok(path)
+    }
+
+    /// 执行斜杠命令，返回是否继续
+    fn command(&mut self, line: &str) -> bool {
+        let (name, argument) = match line.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (line, ""),
+        };
+        let argument = (!argument.is_empty()).then_some(argument);
+        match (name, argument) {
+            ("/exit" | "/quit", _) => return false,
+            ("/help", _) => println!("{}", HELP),
+            ("/model", None) => {
+                if let Err(err) = self.list_models() {
+                    eprintln!("❌ {}", err);
+                }
+            }
+            ("/model", Some(model)) => {
+                self.conversation.set_model(model.to_string());
+                self.save();
+                println!("已切换到 {}", model);
+            }
+            ("/attach", None) => {
+                if self.attachments.is_empty() {
+                    println!("没有待发送的附件");
+                }
+                for attachment in &self.attachments {
+                    println!("📎 {}（{}）", attachment.name, attachment.summary);
+                }
+            }
+            ("/attach", Some("-")) => {
+                self.attachments.clear();
+                println!("已清除附件");
+            }
+            ("/attach", Some(path)) => {
+                if let Err(err) = self.attach(path) {
+                    eprintln!("❌ {}", err);
+                }
+            }
+            ("/system", None) => match &self.conversation.system {
+                Some(system) => println!("{}", system),
+                None => println!("没有设置系统提示词"),
+            },
+            ("/system", Some("-")) => {
+                self.conversation.set_system(None);
+                self.save();
+                println!("已清除系统提示词");
+            }
+            ("/system", Some(system)) => {
+                self.conversation.set_system(Some(system.to_string()));
+                self.save();
+                println!("已设置系统提示词");
+            }
+            ("/retry", _) => {
+                while self.conversation.messages().last().is_some_and(|message| message.role == "assistant") {
+                    self.conversation.pop();
+                }
+                if self.conversation.messages().last().is_some_and(|message| message.role == "user") {
+                    self.reply();
+                } else {
+                    println!("没有可以重试的回复");
+                }
+            }
+            ("/save", path) => match self.export(path) {
+                Ok(path) => println!("已导出到 {}", path),
+                Err(err) => eprintln!("❌ {}", err),
+            },
+            ("/new", _) => {
+                self.conversation = Conversation::new(self.conversation.model.clone(), self.conversation.system.clone());
+                println!("已开始新的对话");
+            }
+            ("/history", _) => {
+                if let Err(err) = self.history() {
+                    eprintln!("❌ {}", err);
+                }
+            }
+            ("/load", None) => println!("用法: /load <id>，id见 /history"),
+            ("/load", Some(id)) => match self.store.load(id) {
+                Ok(conversation) => {
+                    self.conversation = conversation;
+                    self.print_transcript();
+                }
+                Err(err) => eprintln!("❌ {}", err),
+            },
+            _ => println!("未知命令: {}，输入 /help 查看可用的命令", name),
+        }
+        true
+    }
+
+    /// 继续之前的对话时显示已有的内容
+    pub fn print_transcript(&self) {
+        let id = self.conversation.id.as_deref().map_or("", short_id);
+        let updated = format_time(self.conversation.updated);
+        println!("对话 {} {}（{}，模型 {}）", id, self.conversation.title, updated, self.model_name());
+        for message in self.conversation.messages() {
+            match message.role.as_str() {
+                "user" => println!("› {}", message.text().replace('\n', "\n  ")),
+                _ => {
+                    let mut renderer = Renderer::new(io::stdout(), self.render);
+                    let _ = renderer.push(&message.text()).and_then(|_| renderer.finish());
+                }
+            }
+        }
+    }
+
+    pub fn run(&mut self, input: &mut impl BufRead) -> io::Result<()> {
+        println!("OpenKimi {}，模型 {}。输入 /help 查看命令，Ctrl-D 退出。", env!("CARGO_PKG_VERSION"), self.model_name());
+        while let Some(line) = input::read(input)? {
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            if trimmed.starts_with('/') && !trimmed.contains('\n') {
+                if !self.command(trimmed) {
+                    break;
+                }
+                continue;
+            }
+            self.send(line);
+        }
+        Ok(())
+    }
+}
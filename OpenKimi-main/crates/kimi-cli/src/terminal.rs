@@ -0,0 +1,636 @@
+//! 全屏界面用到的终端操作：原始模式、备用屏幕、按键解码和按单元格比较的重绘
+//!
+//! Linux和macOS用termios，Windows用控制台API开启虚拟终端输入输出（Windows 10 1809起支持），
+//! 之后两边都只用ANSI转义序列。进入时切换到备用屏幕并开启括号粘贴，退出或panic时
+//! 由[`Terminal`]的`Drop`恢复终端的设置和原来的屏幕内容。[`Screen`]保存上一帧，每次只输出有变化的单元格。
+
+use std::io::{self, Write};
+use std::time::Duration;
+
+use unicode_width::UnicodeWidthChar;
+
+/// 一次按键或粘贴
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    /// Ctrl加字母，字母为小写
+    Ctrl(char),
+    Enter,
+    /// Alt-Enter，插入换行
+    AltEnter,
+    Backspace,
+    Delete,
+    Tab,
+    BackTab,
+    Esc,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    F(u8),
+    /// 括号粘贴的内容，换行统一为`\n`
+    Paste(String),
+}
+
+/// 处于原始模式和备用屏幕中的终端，丢弃时恢复
+pub struct Terminal {
+    original: sys::Mode,
+    /// 读到但还没有解码的字节
+    pending: Vec<u8>,
+}
+
+impl Terminal {
+    pub fn enter() -> io::Result<Terminal> {
+        let terminal = Terminal {
+            original: sys::enter_raw()?,
+            pending: Vec::new(),
+        };
+        let mut stdout = io::stdout();
+        stdout.write_all(b"\x1b[?1049h\x1b[?2004h\x1b[?25l\x1b[2J")?;
+        stdout.flush()?;
+        Ok(terminal)
+    }
+
+    /// 终端的列数和行数
+    pub fn size(&self) -> (u16, u16) {
+        sys::size().filter(|(width, height)| *width > 0 && *height > 0).unwrap_or((80, 24))
+    }
+
+    /// 等待按键，超时返回空；一次读到的多个按键全部返回
+    pub fn read(&mut self, timeout: Duration) -> io::Result<Vec<Key>> {
+        sys::read(timeout, &mut self.pending)?;
+        let mut keys = Vec::new();
+        while let Some((key, used)) = decode(&self.pending) {
+            self.pending.drain(..used);
+            keys.extend(key);
+        }
+        Ok(keys)
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(b"\x1b[0m\x1b[?25h\x1b[?2004l\x1b[?1049l");
+        let _ = stdout.flush();
+        sys::restore(&self.original);
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::io;
+    use std::os::fd::RawFd;
+    use std::time::Duration;
+
+    const STDIN: RawFd = libc::STDIN_FILENO;
+    const STDOUT: RawFd = libc::STDOUT_FILENO;
+
+    /// 进入原始模式前的终端设置
+    pub type Mode = libc::termios;
+
+    pub fn enter_raw() -> io::Result<Mode> {
+        let mut original = unsafe { std::mem::zeroed::<libc::termios>() };
+        if unsafe { libc::tcgetattr(STDIN, &mut original) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = original;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        if unsafe { libc::tcsetattr(STDIN, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(original)
+    }
+
+    pub fn restore(original: &Mode) {
+        unsafe { libc::tcsetattr(STDIN, libc::TCSANOW, original) };
+    }
+
+    pub fn size() -> Option<(u16, u16)> {
+        let mut size = unsafe { std::mem::zeroed::<libc::winsize>() };
+        if unsafe { libc::ioctl(STDOUT, libc::TIOCGWINSZ, &mut size) } != 0 {
+            return None;
+        }
+        Some((size.ws_col, size.ws_row))
+    }
+
+    /// 等待输入并追加到`pending`，超时不追加
+    pub fn read(timeout: Duration, pending: &mut Vec<u8>) -> io::Result<()> {
+        let mut poll = libc::pollfd {
+            fd: STDIN,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ready = unsafe { libc::poll(&mut poll, 1, timeout.as_millis() as libc::c_int) };
+        if ready < 0 {
+            let err = io::Error::last_os_error();
+            // 窗口大小改变时被SIGWINCH打断
+            return if err.kind() == io::ErrorKind::Interrupted {
+                Ok(())
+            } else {
+                Err(err)
+            };
+        }
+        if ready > 0 {
+            let mut buffer = [0u8; 4096];
+            let read = unsafe { libc::read(STDIN, buffer.as_mut_ptr().cast(), buffer.len()) };
+            if read < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if read == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "终端已关闭"));
+            }
+            pending.extend_from_slice(&buffer[..read as usize]);
+        }
+        Ok(())
+    }
+}
+
+/// 直接调用kernel32的控制台函数，只用到下面几个，不为此引入windows-sys
+#[cfg(windows)]
+mod sys {
+    use std::ffi::c_void;
+    use std::io;
+    use std::time::Duration;
+
+    type Handle = *mut c_void;
+
+    const STD_INPUT_HANDLE: u32 = -10i32 as u32;
+    const STD_OUTPUT_HANDLE: u32 = -11i32 as u32;
+    const ENABLE_PROCESSED_INPUT: u32 = 0x0001;
+    const ENABLE_LINE_INPUT: u32 = 0x0002;
+    const ENABLE_ECHO_INPUT: u32 = 0x0004;
+    const ENABLE_VIRTUAL_TERMINAL_INPUT: u32 = 0x0200;
+    const ENABLE_PROCESSED_OUTPUT: u32 = 0x0001;
+    const ENABLE_VIRTUAL_TERMINAL_PROCESSING: u32 = 0x0004;
+    const DISABLE_NEWLINE_AUTO_RETURN: u32 = 0x0008;
+    const CP_UTF8: u32 = 65001;
+    const WAIT_OBJECT_0: u32 = 0;
+    const WAIT_TIMEOUT: u32 = 0x102;
+    const KEY_EVENT: u16 = 0x0001;
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Coord {
+        x: i16,
+        y: i16,
+    }
+
+    #[repr(C)]
+    struct ScreenBufferInfo {
+        size: Coord,
+        cursor_position: Coord,
+        attributes: u16,
+        window: [i16; 4],
+        maximum_window_size: Coord,
+    }
+
+    /// `INPUT_RECORD`，只读取其中的`KEY_EVENT_RECORD`；它是联合体中最大的成员，大小与原结构相同
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct InputRecord {
+        event_type: u16,
+        key_down: i32,
+        repeat_count: u16,
+        virtual_key_code: u16,
+        virtual_scan_code: u16,
+        unicode_char: u16,
+        control_key_state: u32,
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetStdHandle(std_handle: u32) -> Handle;
+        fn GetConsoleMode(console: Handle, mode: *mut u32) -> i32;
+        fn SetConsoleMode(console: Handle, mode: u32) -> i32;
+        fn GetConsoleCP() -> u32;
+        fn SetConsoleCP(code_page: u32) -> i32;
+        fn GetConsoleOutputCP() -> u32;
+        fn SetConsoleOutputCP(code_page: u32) -> i32;
+        fn GetConsoleScreenBufferInfo(console: Handle, info: *mut ScreenBufferInfo) -> i32;
+        fn WaitForSingleObject(handle: Handle, milliseconds: u32) -> u32;
+        fn ReadConsoleInputW(console: Handle, buffer: *mut InputRecord, length: u32, read: *mut u32) -> i32;
+    }
+
+    /// 进入原始模式前的控制台模式和代码页
+    pub struct Mode {
+        input: u32,
+        output: u32,
+        input_code_page: u32,
+        output_code_page: u32,
+    }
+
+    fn handles() -> (Handle, Handle) {
+        unsafe { (GetStdHandle(STD_INPUT_HANDLE), GetStdHandle(STD_OUTPUT_HANDLE)) }
+    }
+
+    fn check(result: i32) -> io::Result<()> {
+        if result == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn enter_raw() -> io::Result<Mode> {
+        let (input, output) = handles();
+        let mut original = Mode {
+            input: 0,
+            output: 0,
+            input_code_page: unsafe { GetConsoleCP() },
+            output_code_page: unsafe { GetConsoleOutputCP() },
+        };
+        check(unsafe { GetConsoleMode(input, &mut original.input) })?;
+        check(unsafe { GetConsoleMode(output, &mut original.output) })?;
+        // 按键以VT序列读入，Ctrl-C作为普通按键
+        let raw_input = (original.input & !(ENABLE_PROCESSED_INPUT | ENABLE_LINE_INPUT | ENABLE_ECHO_INPUT))
+            | ENABLE_VIRTUAL_TERMINAL_INPUT;
+        let raw_output = original.output
+            | ENABLE_PROCESSED_OUTPUT
+            | ENABLE_VIRTUAL_TERMINAL_PROCESSING
+            | DISABLE_NEWLINE_AUTO_RETURN;
+        check(unsafe { SetConsoleMode(input, raw_input) })?;
+        if let Err(err) = check(unsafe { SetConsoleMode(output, raw_output) }) {
+            restore(&original);
+            let message = format!("控制台不支持虚拟终端序列，需要 Windows 10 或更新版本: {}", err);
+            return Err(io::Error::new(err.kind(), message));
+        }
+        unsafe {
+            SetConsoleCP(CP_UTF8);
+            SetConsoleOutputCP(CP_UTF8);
+        }
+        Ok(original)
+    }
+
+    pub fn restore(original: &Mode) {
+        let (input, output) = handles();
+        unsafe {
+            SetConsoleMode(input, original.input);
+            SetConsoleMode(output, original.output);
+            SetConsoleCP(original.input_code_page);
+            SetConsoleOutputCP(original.output_code_page);
+        }
+    }
+
+    pub fn size() -> Option<(u16, u16)> {
+        let (_, output) = handles();
+        let mut info = unsafe { std::mem::zeroed::<ScreenBufferInfo>() };
+        if unsafe { GetConsoleScreenBufferInfo(output, &mut info) } == 0 {
+            return None;
+        }
+        let [left, top, right, bottom] = info.window;
+        Some(((right - left + 1) as u16, (bottom - top + 1) as u16))
+    }
+
+    /// 等待输入并追加到`pending`，超时不追加
+    ///
+    /// 控制台输入中还有窗口大小、焦点等事件，只取按下按键产生的字符；开启虚拟终端输入后方向键等也以VT序列的字符到达。
+    pub fn read(timeout: Duration, pending: &mut Vec<u8>) -> io::Result<()> {
+        let (input, _) = handles();
+        match unsafe { WaitForSingleObject(input, timeout.as_millis().min(u32::MAX as u128 - 1) as u32) } {
+            WAIT_OBJECT_0 => {}
+            WAIT_TIMEOUT => return Ok(()),
+            _ => return Err(io::Error::last_os_error()),
+        }
+        let mut records = [InputRecord {
+            event_type: 0,
+            key_down: 0,
+            repeat_count: 0,
+            virtual_key_code: 0,
+            virtual_scan_code: 0,
+            unicode_char: 0,
+            control_key_state: 0,
+        }; 256];
+        let mut units = Vec::new();
+        // 代理对的两半总是相继到达，读到前一半时继续读取
+        loop {
+            let mut read = 0;
+            check(unsafe { ReadConsoleInputW(input, records.as_mut_ptr(), records.len() as u32, &mut read) })?;
+            for record in &records[..read as usize] {
+                if record.event_type == KEY_EVENT && record.key_down != 0 && record.unicode_char != 0 {
+                    units.extend(std::iter::repeat_n(record.unicode_char, record.repeat_count.max(1) as usize));
+                }
+            }
+            if !units.last().is_some_and(|unit| (0xd800..0xdc00).contains(unit)) {
+                break;
+            }
+        }
+        pending.extend_from_slice(String::from_utf16_lossy(&units).as_bytes());
+        Ok(())
+    }
+}
+
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+/// 从开头解码一个按键，返回按键和用掉的字节数；字节不完整时为`None`，不认识的序列丢弃
+fn decode(bytes: &[u8]) -> Option<(Option<Key>, usize)> {
+    let first = *bytes.first()?;
+    if let Some(rest) = bytes.strip_prefix(PASTE_START) {
+        let end = rest.windows(PASTE_END.len()).position(|window| window == PASTE_END)?;
+        let text = String::from_utf8_lossy(&rest[..end]).replace("\r\n", "\n").replace('\r', "\n");
+        return Some((Some(Key::Paste(text)), PASTE_START.len() + end + PASTE_END.len()));
+    }
+    let key = match first {
+        0x1b => return Some(escape(bytes)),
+        b'\r' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f | 0x08 => Key::Backspace,
+        1..=26 => Key::Ctrl((b'a' + first - 1) as char),
+        0x80.. => {
+            let length = match first {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => return Some((None, 1)),
+            };
+            if bytes.len() < length {
+                return None;
+            }
+            return Some(match std::str::from_utf8(&bytes[..length]) {
+                Ok(text) => (text.chars().next().map(Key::Char), length),
+                Err(_) => (None, 1),
+            });
+        }
+        byte if byte < 0x20 => return Some((None, 1)),
+        byte => Key::Char(byte as char),
+    };
+    Some((Some(key), 1))
+}
+
+/// 以ESC开头的序列；单独的ESC为Esc键
+fn escape(bytes: &[u8]) -> (Option<Key>, usize) {
+    match bytes.get(1) {
+        None => (Some(Key::Esc), 1),
+        Some(b'\r' | b'\n') => (Some(Key::AltEnter), 2),
+        Some(b'O') => match bytes.get(2) {
+            Some(&code @ b'P'..=b'S') => (Some(Key::F(code - b'P' + 1)), 3),
+            Some(b'H') => (Some(Key::Home), 3),
+            Some(b'F') => (Some(Key::End), 3),
+            Some(_) => (None, 3),
+            None => (Some(Key::Esc), 1),
+        },
+        Some(b'[') => {
+            // CSI：参数和中间字节之后以0x40-0x7e结束
+            let Some(end) = bytes[2..].iter().position(|byte| (0x40..=0x7e).contains(byte)) else {
+                return (Some(Key::Esc), 1);
+            };
+            let parameters = &bytes[2..2 + end];
+            let key = match (bytes[2 + end], parameters) {
+                (b'A', _) => Some(Key::Up),
+                (b'B', _) => Some(Key::Down),
+                (b'C', _) => Some(Key::Right),
+                (b'D', _) => Some(Key::Left),
+                (b'H', _) => Some(Key::Home),
+                (b'F', _) => Some(Key::End),
+                (b'Z', _) => Some(Key::BackTab),
+                (b'~', b"1" | b"7") => Some(Key::Home),
+                (b'~', b"4" | b"8") => Some(Key::End),
+                (b'~', b"3") => Some(Key::Delete),
+                (b'~', b"5") => Some(Key::PageUp),
+                (b'~', b"6") => Some(Key::PageDown),
+                (b'~', b"11") => Some(Key::F(1)),
+                _ => None,
+            };
+            (key, 3 + end)
+        }
+        // 其他Alt组合键按单独的ESC处理，后面的字节另外解码
+        Some(_) => (Some(Key::Esc), 1),
+    }
+}
+
+/// 单元格的样式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Style {
+    /// ANSI前景色，0-7
+    pub color: Option<u8>,
+    pub bold: bool,
+    pub dim: bool,
+    pub reverse: bool,
+}
+
+impl Style {
+    pub const PLAIN: Style = Style {
+        color: None,
+        bold: false,
+        dim: false,
+        reverse: false,
+    };
+
+    pub const fn color(color: u8) -> Style {
+        Style {
+            color: Some(color),
+            ..Style::PLAIN
+        }
+    }
+
+    pub const fn bold(self) -> Style {
+        Style { bold: true, ..self }
+    }
+
+    pub const fn dim(self) -> Style {
+        Style { dim: true, ..self }
+    }
+
+    pub const fn reverse(self) -> Style {
+        Style { reverse: true, ..self }
+    }
+
+    fn sequence(self) -> String {
+        let mut codes = vec!["0".to_string()];
+        if self.bold {
+            codes.push("1".to_string());
+        }
+        if self.dim {
+            codes.push("2".to_string());
+        }
+        if self.reverse {
+            codes.push("7".to_string());
+        }
+        if let Some(color) = self.color {
+            codes.push(format!("3{}", color));
+        }
+        format!("\x1b[{}m", codes.join(";"))
+    }
+}
+
+/// 一个单元格；宽字符占两格，第二格的`symbol`为`None`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    symbol: Option<char>,
+    style: Style,
+}
+
+const BLANK: Cell = Cell {
+    symbol: Some(' '),
+    style: Style::PLAIN,
+};
+
+/// 一帧画面
+pub struct Screen {
+    pub width: u16,
+    pub height: u16,
+    cells: Vec<Cell>,
+    /// 上一次输出的画面，尺寸改变后为空，整屏重绘
+    previous: Vec<Cell>,
+    /// 显示光标的位置，`None`时隐藏光标
+    pub cursor: Option<(u16, u16)>,
+}
+
+impl Screen {
+    pub fn new() -> Screen {
+        Screen {
+            width: 0,
+            height: 0,
+            cells: Vec::new(),
+            previous: Vec::new(),
+            cursor: None,
+        }
+    }
+
+    /// 开始新的一帧
+    pub fn clear(&mut self, (width, height): (u16, u16)) {
+        if (width, height) != (self.width, self.height) {
+            self.width = width;
+            self.height = height;
+            self.previous.clear();
+        }
+        self.cells = vec![BLANK; width as usize * height as usize];
+        self.cursor = None;
+    }
+
+    /// 从`(x, y)`开始写入文字，最多占`width`列，返回占用的列数；写不下的宽字符以空格代替
+    pub fn put(&mut self, x: u16, y: u16, text: &str, width: u16, style: Style) -> u16 {
+        if y >= self.height {
+            return 0;
+        }
+        let end = x.saturating_add(width).min(self.width);
+        let mut column = x;
+        for symbol in text.chars() {
+            let symbol = if symbol.is_control() { ' ' } else { symbol };
+            let size = symbol.width().unwrap_or(0) as u16;
+            if size == 0 {
+                continue;
+            }
+            if column + size > end {
+                if column < end {
+                    self.set(
+                        column,
+                        y,
+                        Cell {
+                            symbol: Some(' '),
+                            style,
+                        },
+                    );
+                    column += 1;
+                }
+                break;
+            }
+            self.set(
+                column,
+                y,
+                Cell {
+                    symbol: Some(symbol),
+                    style,
+                },
+            );
+            if size == 2 {
+                self.set(column + 1, y, Cell { symbol: None, style });
+            }
+            column += size;
+        }
+        column - x
+    }
+
+    /// 用样式填充一个区域
+    pub fn fill(&mut self, x: u16, y: u16, width: u16, height: u16, style: Style) {
+        for row in y..y.saturating_add(height).min(self.height) {
+            for column in x..x.saturating_add(width).min(self.width) {
+                self.set(
+                    column,
+                    row,
+                    Cell {
+                        symbol: Some(' '),
+                        style,
+                    },
+                );
+            }
+        }
+    }
+
+    /// 画一个带标题的边框
+    pub fn frame(&mut self, x: u16, y: u16, width: u16, height: u16, title: &str, style: Style) {
+        if width < 2 || height < 2 {
+            return;
+        }
+        let (right, bottom) = (x + width - 1, y + height - 1);
+        for column in x + 1..right {
+            self.put(column, y, "─", 1, style);
+            self.put(column, bottom, "─", 1, style);
+        }
+        for row in y + 1..bottom {
+            self.put(x, row, "│", 1, style);
+            self.put(right, row, "│", 1, style);
+        }
+        self.put(x, y, "┌", 1, style);
+        self.put(right, y, "┐", 1, style);
+        self.put(x, bottom, "└", 1, style);
+        self.put(right, bottom, "┘", 1, style);
+        if !title.is_empty() && width > 4 {
+            self.put(x + 1, y, &format!(" {} ", title), width - 3, style.bold());
+        }
+    }
+
+    fn set(&mut self, x: u16, y: u16, cell: Cell) {
+        if x < self.width && y < self.height {
+            let index = y as usize * self.width as usize + x as usize;
+            // 覆盖宽字符的一半时，另一半改为空格
+            if self.cells[index].symbol.is_none() && x > 0 {
+                self.cells[index - 1].symbol = Some(' ');
+            }
+            if let Some(next) = self.cells.get_mut(index + 1) {
+                if next.symbol.is_none() && x + 1 < self.width {
+                    next.symbol = Some(' ');
+                }
+            }
+            self.cells[index] = cell;
+        }
+    }
+
+    /// 输出与上一帧不同的单元格
+    pub fn flush(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let mut output = String::from("\x1b[?25l");
+        let mut style = None;
+        let mut position = None;
+        let width = self.width as usize;
+        for (index, cell) in self.cells.iter().enumerate() {
+            if self.previous.get(index) == Some(cell) {
+                continue;
+            }
+            let Some(symbol) = cell.symbol else {
+                continue;
+            };
+            let (x, y) = (index % width, index / width);
+            if position != Some((x, y)) {
+                output.push_str(&format!("\x1b[{};{}H", y + 1, x + 1));
+            }
+            if style != Some(cell.style) {
+                output.push_str(&cell.style.sequence());
+                style = Some(cell.style);
+            }
+            output.push(symbol);
+            position = Some((x + symbol.width().unwrap_or(1), y));
+        }
+        output.push_str("\x1b[0m");
+        if let Some((x, y)) = self.cursor {
+            output.push_str(&format!("\x1b[{};{}H\x1b[?25h", y + 1, x + 1));
+        }
+        out.write_all(output.as_bytes())?;
+        out.flush()?;
+        self.previous = self.cells.clone();
+        Ok(())
+    }
+}
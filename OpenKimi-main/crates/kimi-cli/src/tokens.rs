@@ -0,0 +1,207 @@
+//! `kimi tokens`：统计token数，判断能否放进模型的上下文
+//!
+//! 按模型选择分词器（见`openkimi_tokenizer::Tokenizer::for_model`），Kimi等没有公开词表的模型用`cl100k_base`近似，
+//! 输出中会注明。所有输入接在一起作为一条用户消息计算，加上消息格式的开销和系统提示词，再为回复预留
+//! `--reserve`个token（默认与服务端`context.reserve_tokens`相同）。放不下时给出需要分成几段，`--split`按这个
+//! 大小把每个输入切开写入目录，切分点不会落在多字节字符中间。PDF和DOCX按提取的文字计算，与`-f`附件相同。
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use openkimi_rag::{extract, DocumentKind};
+use openkimi_tokenizer::{context_window, Tokenizer};
+use serde_json::{json, Value};
+use unicode_width::UnicodeWidthStr;
+
+/// 默认为回复预留的token数
+pub const DEFAULT_RESERVE: usize = 1024;
+
+pub struct Options {
+    /// 文件路径，`-`为标准输入
+    pub inputs: Vec<String>,
+    pub model: String,
+    pub system: Option<String>,
+    /// 覆盖内置的上下文窗口
+    pub context: Option<usize>,
+    pub reserve: usize,
+    pub split: Option<PathBuf>,
+    pub json: bool,
+}
+
+struct Input {
+    name: String,
+    text: String,
+    tokens: usize,
+}
+
+fn read(input: &str) -> Result<Input, String> {
+    let text = if input == "-" {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text).map_err(|e| format!("读取标准输入失败: {}", e))?;
+        text
+    } else {
+        let data = fs::read(input).map_err(|e| format!("读取 {} 失败: {}", input, e))?;
+        match DocumentKind::from_name(input) {
+            Some(kind @ (DocumentKind::Pdf | DocumentKind::Docx)) => {
+                extract(kind, &data).map_err(|e| format!("读取 {} 失败: {}", input, e))?
+            }
+            _ => String::from_utf8(data).map_err(|_| format!("{} 不是UTF-8文本", input))?,
+        }
+    };
+    Ok(Input {
+        name: input.to_string(),
+        text,
+        tokens: 0,
+    })
+}
+
+/// 切分后的文件名，如`report.part01.txt`
+fn part_path(dir: &Path, name: &str, number: usize, width: usize) -> PathBuf {
+    let path = Path::new(name);
+    let stem = match name {
+        "-" => "stdin".to_string(),
+        _ => path.file_stem().map_or_else(|| name.to_string(), |stem| stem.to_string_lossy().into_owned()),
+    };
+    let extension = match path.extension().map(|extension| extension.to_string_lossy()) {
+        Some(extension) if name != "-" && extension != "pdf" && extension != "docx" => extension.into_owned(),
+        _ => "txt".to_string(),
+    };
+    dir.join(format!("{}.part{:0width$}.{}", stem, number, extension, width = width))
+}
+
+/// 把每个输入切成不超过`budget`个token的若干段写入`dir`，返回写入的文件
+fn split(tokenizer: &Tokenizer, inputs: &[Input], budget: usize, dir: &Path) -> Result<Vec<PathBuf>, String> {
+    fs::create_dir_all(dir).map_err(|e| format!("创建 {} 失败: {}", dir.display(), e))?;
+    let mut written = Vec::new();
+    for input in inputs {
+        let parts = tokenizer.chunks(&input.text, budget);
+        let width = parts.len().to_string().len().max(2);
+        for (index, part) in parts.iter().enumerate() {
+            let path = part_path(dir, &input.name, index + 1, width);
+            fs::write(&path, part).map_err(|e| format!("写入 {} 失败: {}", path.display(), e))?;
+            written.push(path);
+        }
+    }
+    Ok(written)
+}
+
+pub fn run(options: Options) -> Result<(), String> {
+    let inputs = if options.inputs.is_empty() {
+        vec!["-".to_string()]
+    } else {
+        options.inputs.clone()
+    };
+    if inputs.iter().filter(|input| *input == "-").count() > 1 {
+        return Err("标准输入只能读取一次".to_string());
+    }
+    let tokenizer = Tokenizer::for_model(&options.model);
+    let mut inputs = inputs.iter().map(|input| read(input)).collect::<Result<Vec<_>, _>>()?;
+    for input in &mut inputs {
+        input.tokens = tokenizer.count(&input.text);
+    }
+
+    let tokens: usize = inputs.iter().map(|input| input.tokens).sum();
+    // 消息格式、系统提示词和多个输入之间的空行
+    let mut messages = vec![("user", "", None)];
+    if let Some(system) = &options.system {
+        messages.push(("system", system.as_str(), None));
+    }
+    let overhead = tokenizer.count_messages(messages) + inputs.len().saturating_sub(1) * tokenizer.count("\n\n");
+    let window = options.context.or_else(|| context_window(&options.model).map(|window| window as usize));
+    let available = window.map(|window| window.saturating_sub(options.reserve + overhead));
+    let fits = available.map(|available| tokens <= available);
+    let parts = available.map(|available| tokens.div_ceil(available.max(1)).max(1));
+
+    let written = match (&options.split, available) {
+        (Some(dir), Some(available)) if available > 0 => split(&tokenizer, &inputs, available, dir)?,
+        (Some(_), Some(_)) => return Err("为回复预留的token超过了上下文窗口".to_string()),
+        (Some(_), None) => {
+            return Err(format!(
+                "不知道模型 {} 的上下文窗口，请用 --context 指定",
+                options.model
+            ))
+        }
+        (None, _) => Vec::new(),
+    };
+
+    if options.json {
+        let report = json!({
+            "model": options.model,
+            "tokenizer": tokenizer.name(),
+            "exact": tokenizer.is_exact(),
+            "files": inputs
+                .iter()
+                .map(|input| json!({
+                    "name": input.name,
+                    "tokens": input.tokens,
+                    "chars": input.text.chars().count(),
+                    "bytes": input.text.len(),
+                }))
+                .collect::<Vec<Value>>(),
+            "tokens": tokens,
+            "overhead": overhead,
+            "context_window": window,
+            "reserve": options.reserve,
+            "available": available,
+            "fits": fits,
+            "parts": parts,
+            "written": written.iter().map(|path| path.display().to_string()).collect::<Vec<_>>(),
+        });
+        println!("{}", serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?);
+        return Ok(());
+    }
+
+    // 文件名按显示宽度对齐
+    let width = inputs.iter().map(|input| input.name.width()).max().unwrap_or(0);
+    for input in &inputs {
+        println!(
+            "{}{}  {:>9} token  {:>9} 字",
+            input.name,
+            " ".repeat(width - input.name.width()),
+            input.tokens,
+            input.text.chars().count()
+        );
+    }
+    let exact = if tokenizer.is_exact() { "" } else { "（近似）" };
+    let model = if options.model.is_empty() {
+        "服务端默认"
+    } else {
+        &options.model
+    };
+    println!(
+        "合计 {} token，另有消息格式和系统提示词 {} token；模型 {}，词表 {}{}",
+        tokens,
+        overhead,
+        model,
+        tokenizer.name(),
+        exact
+    );
+    match (window, available) {
+        (Some(window), Some(available)) => {
+            println!(
+                "上下文窗口 {} token，为回复预留 {}，可用 {}",
+                window, options.reserve, available
+            );
+            if tokens <= available {
+                println!("✅ 可以放进上下文，还剩 {} token", available - tokens);
+            } else {
+                println!(
+                    "❌ 超出 {} token，需要分成 {} 段，每段不超过 {} token",
+                    tokens - available,
+                    parts.unwrap_or(1),
+                    available
+                );
+                if options.split.is_none() {
+                    println!("   用 --split <目录> 按这个大小切分后逐段发送");
+                }
+            }
+        }
+        _ if options.model.is_empty() => println!("没有指定模型，不知道上下文窗口，可以用 --model 或 --context 指定"),
+        _ => println!("不知道模型 {} 的上下文窗口，可以用 --context 指定", model),
+    }
+    for path in &written {
+        println!("📄 {}", path.display());
+    }
+    Ok(())
+}
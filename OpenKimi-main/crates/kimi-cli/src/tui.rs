@@ -0,0 +1,928 @@
+//! `kimi tui`：全屏的终端界面
+//!
+//! 左边是对话列表，右边是对话内容和输入框，底部显示模型、配置档、上下文和token用量，适合在没有桌面环境的
+//! 服务器上代替Electron客户端。对话与`kimi`使用同一个会话库，每轮回复后保存。回复在后台线程中流式接收，
+//! 接收时仍然可以滚动和编辑；Esc停止生成，已经收到的部分保留。Ctrl-O从服务端的模型中切换模型，Ctrl-P切换
+//! 配置文件中的配置档，F1列出全部按键。终端的操作见[`terminal`](crate::terminal)。
+
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use openkimi_client::blocking::Client;
+use openkimi_client::{ChatCompletionRequest, ChatEvent, ChatMessage, ClientConfig, Model, Usage};
+use openkimi_sessions::time::format_time;
+use openkimi_sessions::{Session, TokenUsage};
+use openkimi_tokenizer::{context_window, Tokenizer};
+use serde_json::json;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::config::Profile;
+use crate::conversation::{Conversation, Store};
+use crate::terminal::{Key, Screen, Style, Terminal};
+
+/// 列表中显示的对话数
+const LIST_LIMIT: u32 = 200;
+
+/// 输入框最多显示的行数
+const INPUT_ROWS: usize = 5;
+
+const CYAN: u8 = 6;
+const GREEN: u8 = 2;
+const YELLOW: u8 = 3;
+const RED: u8 = 1;
+
+const HELP: &[(&str, &str)] = &[
+    ("Enter", "发送；在对话列表中打开选中的对话"),
+    ("Alt-Enter、Ctrl-J", "换行"),
+    ("Tab", "在对话列表和输入框之间切换"),
+    ("↑ ↓", "在列表中选择；在输入框中滚动对话"),
+    ("PgUp PgDn", "翻页"),
+    ("Esc", "停止生成回复，关闭弹窗"),
+    ("Ctrl-R", "重新生成最后一条回复"),
+    ("Ctrl-N", "开始新的对话"),
+    ("Ctrl-O", "切换模型"),
+    ("Ctrl-P", "切换配置档"),
+    ("Ctrl-U", "清空输入"),
+    ("F1", "显示这些按键"),
+    ("Ctrl-C、Ctrl-Q", "退出；生成回复时Ctrl-C停止"),
+];
+
+/// 按名称解析配置档，切换配置档时使用
+pub type Connect = Box<dyn Fn(&str) -> Result<(ClientConfig, Profile), String>>;
+
+/// 打开界面需要的连接和设置，由命令行参数和配置档解析得到
+pub struct Input {
+    pub client: Client,
+    pub store: Store,
+    pub conversation: Conversation,
+    /// 使用的配置档，没有配置档时为`None`
+    pub profile: Option<String>,
+    /// 配置文件中的全部配置档
+    pub profiles: Vec<String>,
+    /// 新对话的模型和系统提示词
+    pub defaults: Profile,
+    pub connect: Connect,
+}
+
+/// 后台线程发回的回复
+enum Update {
+    Text(String),
+    Done(Result<Reply, String>),
+}
+
+struct Reply {
+    message: Option<ChatMessage>,
+    finish: Option<String>,
+    usage: Option<Usage>,
+}
+
+/// 正在生成的回复
+struct Pending {
+    receiver: Receiver<Update>,
+    stop: Arc<AtomicBool>,
+    text: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    List,
+    Input,
+}
+
+enum Popup {
+    /// 模型列表，第一项为服务端的默认模型
+    Models {
+        items: Vec<String>,
+        selected: usize,
+    },
+    Profiles {
+        selected: usize,
+    },
+    Help,
+}
+
+struct App {
+    store: Store,
+    client: Client,
+    profile: Option<String>,
+    profiles: Vec<String>,
+    defaults: Profile,
+    connect: Connect,
+    conversation: Conversation,
+    sessions: Vec<Session>,
+    /// 列表中选中的行，0为“新对话”
+    selected: usize,
+    focus: Focus,
+    input: String,
+    /// 输入框中光标的字节位置
+    cursor: usize,
+    /// 对话内容从底部向上滚动的行数
+    scroll: usize,
+    pending: Option<Pending>,
+    popup: Option<Popup>,
+    /// 底部的提示，按下一个键后清除；`true`为错误
+    status: Option<(String, bool)>,
+    /// 最近一次回复的用量
+    last: Option<TokenUsage>,
+    /// 上次列出的服务端模型，用于查找上下文窗口
+    models: Vec<Model>,
+    tokenizer: Option<(String, Tokenizer)>,
+    /// 当前对话的token数，对话改变后重新计算
+    context: Option<usize>,
+    quit: bool,
+}
+
+pub fn run(input: Input) -> Result<(), String> {
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        return Err("kimi tui 需要在终端中运行".to_string());
+    }
+    let mut app = App {
+        store: input.store,
+        client: input.client,
+        profile: input.profile,
+        profiles: input.profiles,
+        defaults: input.defaults,
+        connect: input.connect,
+        conversation: input.conversation,
+        sessions: Vec::new(),
+        selected: 0,
+        focus: Focus::Input,
+        input: String::new(),
+        cursor: 0,
+        scroll: 0,
+        pending: None,
+        popup: None,
+        status: None,
+        last: None,
+        models: Vec::new(),
+        tokenizer: None,
+        context: None,
+        quit: false,
+    };
+    app.refresh();
+
+    let mut terminal = Terminal::enter().map_err(|e| format!("无法进入全屏界面: {}", e))?;
+    let mut screen = Screen::new();
+    let mut stdout = io::stdout();
+    while !app.quit {
+        app.receive();
+        app.count_context();
+        screen.clear(terminal.size());
+        app.draw(&mut screen);
+        screen.flush(&mut stdout).map_err(|e| format!("输出失败: {}", e))?;
+        let timeout = Duration::from_millis(if app.pending.is_some() { 30 } else { 250 });
+        for key in terminal.read(timeout).map_err(|e| format!("读取按键失败: {}", e))? {
+            app.key(key);
+        }
+    }
+    Ok(())
+}
+
+/// 在后台线程中接收流式回复
+fn generate(client: Client, request: ChatCompletionRequest, stop: Arc<AtomicBool>, sender: Sender<Update>) {
+    let result = (|| {
+        let mut events = client.chat_stream(&request).map_err(|e| e.to_string())?.events();
+        let mut finish = None;
+        for event in events.by_ref() {
+            // 停止后不再读取，丢弃时关闭连接
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            match event.map_err(|e| e.to_string())? {
+                ChatEvent::Content(text) => {
+                    let _ = sender.send(Update::Text(text));
+                }
+                ChatEvent::Finish(reason) => finish = Some(reason),
+                _ => {}
+            }
+        }
+        let response = events.collected();
+        Ok(Reply {
+            message: response.choices.into_iter().next().map(|choice| choice.message),
+            finish,
+            usage: response.usage,
+        })
+    })();
+    let _ = sender.send(Update::Done(result));
+}
+
+/// `1.2K`这样的token数
+fn format_tokens(tokens: u64) -> String {
+    if tokens >= 1000 {
+        format!("{:.1}K", tokens as f64 / 1000.0)
+    } else {
+        tokens.to_string()
+    }
+}
+
+/// 按显示宽度折行，尽量在空格处断开
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+    for line in text.replace('\t', "    ").split('\n') {
+        let mut current = String::new();
+        let mut current_width = 0;
+        // 当前行中最后一个空格之后的位置
+        let mut space = None;
+        for symbol in line.chars() {
+            let size = symbol.width().unwrap_or(0);
+            if current_width + size > width && !current.is_empty() {
+                match space.filter(|&index| index < current.len() && symbol != ' ') {
+                    Some(index) => {
+                        let rest = current.split_off(index);
+                        lines.push(current.trim_end().to_string());
+                        current_width = rest.width();
+                        current = rest;
+                    }
+                    None => {
+                        lines.push(current.trim_end().to_string());
+                        current = String::new();
+                        current_width = 0;
+                    }
+                }
+                space = None;
+                if symbol == ' ' && current.is_empty() {
+                    continue;
+                }
+            }
+            current.push(symbol);
+            current_width += size;
+            if symbol == ' ' {
+                space = Some(current.len());
+            }
+        }
+        lines.push(current);
+    }
+    lines
+}
+
+/// 输入框按字符折行后的各行和光标所在的行列
+fn layout_input(text: &str, cursor: usize, width: usize) -> (Vec<String>, (usize, usize)) {
+    let width = width.max(1);
+    let mut lines = vec![String::new()];
+    let mut column = 0;
+    let mut position = (0, 0);
+    for (index, symbol) in text.char_indices() {
+        if index == cursor {
+            position = (lines.len() - 1, column);
+        }
+        if symbol == '\n' {
+            lines.push(String::new());
+            column = 0;
+            continue;
+        }
+        let size = symbol.width().unwrap_or(0);
+        if column + size > width {
+            lines.push(String::new());
+            column = 0;
+        }
+        lines.last_mut().expect("至少有一行").push(symbol);
+        column += size;
+    }
+    if cursor >= text.len() {
+        // 光标在行末且正好占满一行时移到下一行
+        if column >= width {
+            lines.push(String::new());
+            column = 0;
+        }
+        position = (lines.len() - 1, column);
+    }
+    (lines, position)
+}
+
+impl App {
+    fn model_name(&self) -> &str {
+        if self.conversation.model.is_empty() {
+            "服务端默认"
+        } else {
+            &self.conversation.model
+        }
+    }
+
+    fn error(&mut self, message: impl Into<String>) {
+        self.status = Some((message.into(), true));
+    }
+
+    fn info(&mut self, message: impl Into<String>) {
+        self.status = Some((message.into(), false));
+    }
+
+    /// 重新读取对话列表，选中当前的对话
+    fn refresh(&mut self) {
+        match self.store.list(LIST_LIMIT) {
+            Ok(sessions) => self.sessions = sessions,
+            Err(err) => self.error(err),
+        }
+        self.selected = self
+            .conversation
+            .id
+            .as_ref()
+            .and_then(|id| self.sessions.iter().position(|session| &session.id == id))
+            .map_or(0, |index| index + 1);
+    }
+
+    fn save(&mut self) {
+        if let Err(err) = self.store.save(&mut self.conversation) {
+            self.error(format!("保存对话失败: {}", err));
+        }
+        self.refresh();
+    }
+
+    /// 切换到另一个对话
+    fn open(&mut self, conversation: Conversation) {
+        self.conversation = conversation;
+        self.scroll = 0;
+        self.last = None;
+        self.context = None;
+        self.refresh();
+    }
+
+    /// 有回复正在生成时提示，不能切换对话
+    fn busy(&mut self) -> bool {
+        if self.pending.is_some() {
+            self.error("正在生成回复，按 Esc 停止");
+        }
+        self.pending.is_some()
+    }
+
+    fn send(&mut self) {
+        if self.input.trim().is_empty() || self.busy() {
+            return;
+        }
+        let text = std::mem::take(&mut self.input);
+        self.cursor = 0;
+        self.conversation.push(ChatMessage::user(text), None);
+        self.start();
+    }
+
+    /// 请求回复最后一条用户消息
+    fn start(&mut self) {
+        let request = ChatCompletionRequest::new(self.conversation.model.clone(), self.conversation.request_messages())
+            .with("stream_options", json!({ "include_usage": true }));
+        let (sender, receiver) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let client = self.client.clone();
+        let flag = stop.clone();
+        thread::spawn(move || generate(client, request, flag, sender));
+        self.pending = Some(Pending {
+            receiver,
+            stop,
+            text: String::new(),
+        });
+        self.scroll = 0;
+        self.context = None;
+    }
+
+    /// 取出后台线程发回的内容
+    fn receive(&mut self) {
+        let Some(pending) = &mut self.pending else {
+            return;
+        };
+        loop {
+            match pending.receiver.try_recv() {
+                Ok(Update::Text(text)) => pending.text.push_str(&text),
+                Ok(Update::Done(result)) => {
+                    self.pending = None;
+                    self.finish(result);
+                    return;
+                }
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => {
+                    self.pending = None;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn finish(&mut self, result: Result<Reply, String>) {
+        match result {
+            Ok(reply) => {
+                if reply.finish.as_deref() == Some("length") {
+                    self.error("回复达到长度上限被截断");
+                }
+                let mut message = reply.message.unwrap_or_else(|| ChatMessage::assistant(""));
+                message.role = "assistant".to_string();
+                let usage = reply.usage.map(|usage| TokenUsage {
+                    prompt_tokens: usage.prompt_tokens.into(),
+                    completion_tokens: usage.completion_tokens.into(),
+                });
+                self.last = usage;
+                self.conversation.push(message, usage);
+            }
+            // 保留提问，可以用Ctrl-R重试
+            Err(err) => self.error(format!("{}，按 Ctrl-R 重试", err)),
+        }
+        self.context = None;
+        self.save();
+    }
+
+    /// 停止生成，保留已经收到的部分
+    fn stop(&mut self) {
+        let Some(pending) = self.pending.take() else {
+            return;
+        };
+        pending.stop.store(true, Ordering::Relaxed);
+        if !pending.text.is_empty() {
+            self.conversation.push(ChatMessage::assistant(pending.text), None);
+        }
+        self.context = None;
+        self.save();
+        self.info("已停止生成");
+    }
+
+    fn retry(&mut self) {
+        if self.busy() {
+            return;
+        }
+        while self.conversation.messages().last().is_some_and(|message| message.role == "assistant") {
+            self.conversation.pop();
+        }
+        if self.conversation.messages().last().is_some_and(|message| message.role == "user") {
+            self.start();
+        } else {
+            self.error("没有可以重新生成的回复");
+        }
+    }
+
+    fn new_conversation(&mut self) {
+        if self.busy() {
+            return;
+        }
+        self.open(Conversation::new(
+            self.defaults.model.clone().unwrap_or_default(),
+            self.defaults.system.clone(),
+        ));
+        self.focus = Focus::Input;
+    }
+
+    fn open_selected(&mut self) {
+        if self.busy() {
+            return;
+        }
+        let Some(index) = self.selected.checked_sub(1) else {
+            self.new_conversation();
+            return;
+        };
+        let Some(session) = self.sessions.get(index) else {
+            return;
+        };
+        match self.store.load(&session.id) {
+            Ok(conversation) => {
+                self.open(conversation);
+                self.focus = Focus::Input;
+            }
+            Err(err) => self.error(err),
+        }
+    }
+
+    fn show_models(&mut self) {
+        match self.client.models() {
+            Ok(list) => {
+                self.models = list.data;
+                let mut items = vec![String::new()];
+                items.extend(
+                    self.models
+                        .iter()
+                        .filter(|model| model.kind.as_deref().is_none_or(|kind| kind == "chat"))
+                        .map(|model| model.id.clone()),
+                );
+                let selected = items.iter().position(|item| *item == self.conversation.model).unwrap_or(0);
+                self.popup = Some(Popup::Models { items, selected });
+                self.context = None;
+            }
+            Err(err) => self.error(format!("列出模型失败: {}", err)),
+        }
+    }
+
+    fn choose_model(&mut self, model: String) {
+        self.conversation.set_model(model);
+        self.tokenizer = None;
+        self.context = None;
+        if self.conversation.id.is_some() {
+            self.save();
+        }
+        self.info(format!("已切换到 {}", self.model_name()));
+    }
+
+    fn show_profiles(&mut self) {
+        if self.busy() {
+            return;
+        }
+        if self.profiles.is_empty() {
+            self.error("配置文件中没有配置档，可以用 kimi config set --profile <名称> 添加");
+            return;
+        }
+        let selected = self.profile.as_ref().and_then(|name| self.profiles.iter().position(|p| p == name));
+        self.popup = Some(Popup::Profiles {
+            selected: selected.unwrap_or(0),
+        });
+    }
+
+    /// 改用配置档的连接；配置档设置了模型时当前对话也改用该模型
+    fn choose_profile(&mut self, name: String) {
+        let result = (self.connect)(&name)
+            .and_then(|(config, profile)| Ok((Client::new(config).map_err(|e| e.to_string())?, profile)));
+        let (client, profile) = match result {
+            Ok(connection) => connection,
+            Err(err) => return self.error(err),
+        };
+        self.client = client;
+        self.models.clear();
+        if let Some(model) = &profile.model {
+            if *model != self.conversation.model {
+                self.choose_model(model.clone());
+            }
+        }
+        self.defaults = profile;
+        self.info(format!("已切换到配置档 {}", name));
+        self.profile = Some(name);
+    }
+
+    /// 对话改变后重新计算token数，换模型时换用对应的分词器
+    fn count_context(&mut self) {
+        if self.context.is_some() {
+            return;
+        }
+        let model = self.conversation.model.clone();
+        if self.tokenizer.as_ref().is_none_or(|(name, _)| *name != model) {
+            self.tokenizer = Some((model.clone(), Tokenizer::for_model(&model)));
+        }
+        let (_, tokenizer) = self.tokenizer.as_ref().expect("刚刚设置");
+        let messages = self.conversation.request_messages();
+        let texts: Vec<(String, String)> =
+            messages.iter().map(|message| (message.role.clone(), message.text())).collect();
+        let count = tokenizer.count_messages(texts.iter().map(|(role, text)| (role.as_str(), text.as_str(), None)));
+        self.context = Some(count);
+    }
+
+    fn context_window(&self) -> Option<u32> {
+        let model = &self.conversation.model;
+        self.models
+            .iter()
+            .find(|item| item.id == *model)
+            .and_then(|item| item.context_length)
+            .or_else(|| context_window(model))
+    }
+
+    fn key(&mut self, key: Key) {
+        self.status = None;
+        if let Some(popup) = self.popup.take() {
+            return self.popup_key(popup, key);
+        }
+        match key {
+            Key::Ctrl('c') if self.pending.is_some() => self.stop(),
+            Key::Ctrl('c' | 'q') => self.quit = true,
+            Key::Esc => self.stop(),
+            Key::F(1) => self.popup = Some(Popup::Help),
+            Key::Ctrl('o') => self.show_models(),
+            Key::Ctrl('p') => self.show_profiles(),
+            Key::Ctrl('n') => self.new_conversation(),
+            Key::Ctrl('r') => self.retry(),
+            Key::Tab | Key::BackTab => {
+                self.focus = if self.focus == Focus::List {
+                    Focus::Input
+                } else {
+                    Focus::List
+                };
+            }
+            Key::PageUp => self.scroll += 10,
+            Key::PageDown => self.scroll = self.scroll.saturating_sub(10),
+            key if self.focus == Focus::List => self.list_key(key),
+            key => self.input_key(key),
+        }
+    }
+
+    fn list_key(&mut self, key: Key) {
+        let last = self.sessions.len();
+        match key {
+            Key::Up => self.selected = self.selected.saturating_sub(1),
+            Key::Down => self.selected = (self.selected + 1).min(last),
+            Key::Home => self.selected = 0,
+            Key::End => self.selected = last,
+            Key::Enter => self.open_selected(),
+            Key::Char('?') => self.popup = Some(Popup::Help),
+            // 在列表中直接打字时转到输入框
+            Key::Char(_) | Key::Paste(_) => {
+                self.focus = Focus::Input;
+                self.input_key(key);
+            }
+            _ => {}
+        }
+    }
+
+    fn input_key(&mut self, key: Key) {
+        match key {
+            Key::Enter => self.send(),
+            Key::AltEnter | Key::Ctrl('j') => self.insert("\n"),
+            Key::Char(symbol) => self.insert(symbol.encode_utf8(&mut [0; 4])),
+            Key::Paste(text) => self.insert(&text),
+            Key::Backspace => {
+                if let Some(symbol) = self.input[..self.cursor].chars().next_back() {
+                    self.cursor -= symbol.len_utf8();
+                    self.input.remove(self.cursor);
+                }
+            }
+            Key::Delete if self.cursor < self.input.len() => {
+                self.input.remove(self.cursor);
+            }
+            Key::Left => {
+                if let Some(symbol) = self.input[..self.cursor].chars().next_back() {
+                    self.cursor -= symbol.len_utf8();
+                }
+            }
+            Key::Right => {
+                if let Some(symbol) = self.input[self.cursor..].chars().next() {
+                    self.cursor += symbol.len_utf8();
+                }
+            }
+            Key::Home | Key::Ctrl('a') => self.cursor = 0,
+            Key::End | Key::Ctrl('e') => self.cursor = self.input.len(),
+            Key::Ctrl('u') => {
+                self.input.clear();
+                self.cursor = 0;
+            }
+            Key::Up => self.scroll += 1,
+            Key::Down => self.scroll = self.scroll.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    fn insert(&mut self, text: &str) {
+        self.input.insert_str(self.cursor, text);
+        self.cursor += text.len();
+    }
+
+    fn popup_key(&mut self, popup: Popup, key: Key) {
+        let popup = match (popup, key) {
+            (_, Key::Esc | Key::Ctrl('c')) | (Popup::Help, _) => None,
+            (Popup::Models { items, selected }, Key::Enter) => {
+                self.choose_model(items[selected].clone());
+                None
+            }
+            (Popup::Profiles { selected }, Key::Enter) => {
+                self.choose_profile(self.profiles[selected].clone());
+                None
+            }
+            (Popup::Models { items, selected }, key) => {
+                let selected = step(selected, items.len(), &key);
+                Some(Popup::Models { items, selected })
+            }
+            (Popup::Profiles { selected }, key) => Some(Popup::Profiles {
+                selected: step(selected, self.profiles.len(), &key),
+            }),
+        };
+        self.popup = popup;
+    }
+
+    fn draw(&self, screen: &mut Screen) {
+        let (width, height) = (screen.width, screen.height);
+        if width < 40 || height < 10 {
+            screen.put(0, 0, "窗口太小", width, Style::PLAIN);
+            return;
+        }
+        let body = height - 2;
+        // 窗口较窄时隐藏对话列表
+        let list = if width >= 70 { (width / 4).clamp(24, 36) } else { 0 };
+        if list > 0 {
+            self.draw_list(screen, list, body);
+        }
+        self.draw_chat(screen, list, width - list, body);
+        self.draw_footer(screen, body);
+        if let Some(popup) = &self.popup {
+            self.draw_popup(screen, popup);
+        }
+    }
+
+    fn border(&self, focus: Focus) -> Style {
+        if self.focus == focus && self.popup.is_none() {
+            Style::color(CYAN)
+        } else {
+            Style::PLAIN.dim()
+        }
+    }
+
+    fn draw_list(&self, screen: &mut Screen, width: u16, height: u16) {
+        screen.frame(0, 0, width, height, "对话", self.border(Focus::List));
+        let rows = (height - 2) as usize;
+        let first = self.selected.saturating_sub(rows.saturating_sub(1));
+        let inner = width - 2;
+        for (row, index) in (first..=self.sessions.len()).take(rows).enumerate() {
+            let (text, current) = match index.checked_sub(1).and_then(|index| self.sessions.get(index)) {
+                Some(session) => {
+                    let title = if session.title.is_empty() {
+                        "（无标题）"
+                    } else {
+                        &session.title
+                    };
+                    let date = &format_time(session.updated_at)[5..10];
+                    (
+                        format!("{} {}", date, title),
+                        Some(&session.id) == self.conversation.id.as_ref(),
+                    )
+                }
+                None => ("＋ 新对话".to_string(), self.conversation.id.is_none()),
+            };
+            let mut style = if current {
+                Style::color(CYAN).bold()
+            } else {
+                Style::PLAIN
+            };
+            if index == self.selected && self.focus == Focus::List {
+                style = style.reverse();
+                screen.fill(1, row as u16 + 1, inner, 1, style);
+            }
+            screen.put(1, row as u16 + 1, &text, inner, style);
+        }
+    }
+
+    /// 对话内容的各行；最后一条消息之后是正在生成的回复
+    fn transcript(&self, width: usize) -> Vec<(String, Style)> {
+        let mut lines = Vec::new();
+        let pending = self.pending.as_ref().map(|pending| ("assistant".to_string(), format!("{}▍", pending.text)));
+        let messages = self.conversation.messages().iter().map(|message| (message.role.clone(), message.text()));
+        for (role, text) in messages.chain(pending) {
+            let header = match role.as_str() {
+                "user" => ("你".to_string(), Style::color(CYAN).bold()),
+                "assistant" => ("Kimi".to_string(), Style::color(GREEN).bold()),
+                other => (other.to_string(), Style::PLAIN.dim()),
+            };
+            lines.push(header);
+            let mut code = false;
+            for line in text.lines() {
+                let fence = line.trim_start().starts_with("```");
+                let style = if fence || code {
+                    Style::color(YELLOW)
+                } else if line.starts_with('#') {
+                    Style::PLAIN.bold()
+                } else {
+                    Style::PLAIN
+                };
+                if fence {
+                    code = !code;
+                }
+                lines.extend(wrap(line, width).into_iter().map(|line| (line, style)));
+            }
+            lines.push((String::new(), Style::PLAIN));
+        }
+        lines
+    }
+
+    fn draw_chat(&self, screen: &mut Screen, x: u16, width: u16, height: u16) {
+        let inner = (width - 2) as usize;
+        let (input, cursor) = layout_input(&self.input, self.cursor, inner);
+        let rows = input.len().min(INPUT_ROWS);
+        let chat = height - rows as u16 - 2;
+
+        let title = match &self.conversation.title {
+            title if title.is_empty() => format!("新对话 · {}", self.model_name()),
+            title => format!("{} · {}", title, self.model_name()),
+        };
+        screen.frame(x, 0, width, chat, &title, Style::PLAIN.dim());
+        let lines = self.transcript(inner);
+        let visible = (chat - 2) as usize;
+        if lines.is_empty() {
+            let hint = "输入问题后按 Enter 发送，F1 查看全部按键";
+            let left = x + 1 + (inner.saturating_sub(hint.width()) / 2) as u16;
+            screen.put(left, chat / 2, hint, inner as u16, Style::PLAIN.dim());
+        }
+        let scroll = self.scroll.min(lines.len().saturating_sub(visible));
+        let first = lines.len().saturating_sub(visible + scroll);
+        for (row, (text, style)) in lines.iter().skip(first).take(visible).enumerate() {
+            screen.put(x + 1, row as u16 + 1, text, inner as u16, *style);
+        }
+        if scroll > 0 {
+            let marker = format!(" ↓ 还有 {} 行 ", scroll);
+            screen.put(
+                x + width - 1 - marker.width() as u16,
+                chat - 1,
+                &marker,
+                width - 2,
+                Style::color(YELLOW),
+            );
+        }
+
+        let title = if self.pending.is_some() {
+            "正在回复 · Esc 停止"
+        } else {
+            "输入"
+        };
+        screen.frame(x, chat, width, rows as u16 + 2, title, self.border(Focus::Input));
+        let first = (cursor.0 + 1).saturating_sub(rows);
+        for (row, line) in input.iter().skip(first).take(rows).enumerate() {
+            screen.put(x + 1, chat + 1 + row as u16, line, inner as u16, Style::PLAIN);
+        }
+        if self.focus == Focus::Input && self.popup.is_none() {
+            screen.cursor = Some((x + 1 + cursor.1 as u16, chat + 1 + (cursor.0 - first) as u16));
+        }
+    }
+
+    fn draw_footer(&self, screen: &mut Screen, y: u16) {
+        let width = screen.width;
+        let mut parts = vec![format!("模型 {}", self.model_name())];
+        parts.push(format!("配置档 {}", self.profile.as_deref().unwrap_or("-")));
+        if let Some(tokens) = self.context {
+            parts.push(match self.context_window() {
+                Some(window) => format!(
+                    "上下文 {}/{}（{}%）",
+                    format_tokens(tokens as u64),
+                    format_tokens(window.into()),
+                    tokens as u64 * 100 / u64::from(window.max(1))
+                ),
+                None => format!("上下文 {}", format_tokens(tokens as u64)),
+            });
+        }
+        if let Some(usage) = self.last {
+            parts.push(format!(
+                "本轮 输入 {} 输出 {}",
+                format_tokens(usage.prompt_tokens),
+                format_tokens(usage.completion_tokens)
+            ));
+        }
+        let session = self.conversation.id.as_ref().and_then(|id| self.sessions.iter().find(|s| &s.id == id));
+        if let Some(usage) = session.map(|session| session.usage).filter(|usage| *usage != TokenUsage::default()) {
+            parts.push(format!(
+                "累计 输入 {} 输出 {}",
+                format_tokens(usage.prompt_tokens),
+                format_tokens(usage.completion_tokens)
+            ));
+        }
+        let style = Style::PLAIN.reverse();
+        screen.fill(0, y, width, 1, style);
+        screen.put(1, y, &parts.join(" │ "), width - 2, style);
+
+        let (text, style) = match &self.status {
+            Some((message, true)) => (message.as_str(), Style::color(RED).bold()),
+            Some((message, false)) => (message.as_str(), Style::color(GREEN)),
+            None => (
+                "Enter 发送 · Alt-Enter 换行 · Tab 切换 · Ctrl-O 模型 · Ctrl-P 配置档 · F1 帮助 · Ctrl-Q 退出",
+                Style::PLAIN.dim(),
+            ),
+        };
+        screen.put(1, y + 1, text, width - 2, style);
+    }
+
+    fn draw_popup(&self, screen: &mut Screen, popup: &Popup) {
+        let (title, items, selected): (&str, Vec<String>, Option<usize>) = match popup {
+            Popup::Models { items, selected } => {
+                let items = items
+                    .iter()
+                    .map(|item| {
+                        if item.is_empty() {
+                            "（服务端默认）".to_string()
+                        } else {
+                            item.clone()
+                        }
+                    })
+                    .collect();
+                ("模型 · Enter 切换", items, Some(*selected))
+            }
+            Popup::Profiles { selected } => ("配置档 · Enter 切换", self.profiles.clone(), Some(*selected)),
+            Popup::Help => {
+                let items = HELP
+                    .iter()
+                    .map(|(key, action)| format!("{}{}  {}", key, " ".repeat(18 - key.width().min(18)), action))
+                    .collect();
+                ("按键 · 任意键关闭", items, None)
+            }
+        };
+        let width = (items.iter().map(|item| item.width()).max().unwrap_or(0) + 4)
+            .max(title.width() + 6)
+            .min(screen.width as usize - 4) as u16;
+        let height = (items.len() + 2).min(screen.height as usize - 4) as u16;
+        let (x, y) = ((screen.width - width) / 2, (screen.height - height) / 2);
+        screen.fill(x, y, width, height, Style::PLAIN);
+        screen.frame(x, y, width, height, title, Style::color(CYAN));
+        let rows = (height - 2) as usize;
+        let first = selected.map_or(0, |selected| selected.saturating_sub(rows.saturating_sub(1)));
+        for (row, (index, item)) in items.iter().enumerate().skip(first).take(rows).enumerate() {
+            let style = if Some(index) == selected {
+                Style::PLAIN.reverse()
+            } else {
+                Style::PLAIN
+            };
+            if style.reverse {
+                screen.fill(x + 1, y + 1 + row as u16, width - 2, 1, style);
+            }
+            screen.put(x + 2, y + 1 + row as u16, item, width - 3, style);
+        }
+    }
+}
+
+/// 在弹窗的列表中移动
+fn step(selected: usize, len: usize, key: &Key) -> usize {
+    let last = len.saturating_sub(1);
+    match key {
+        Key::Up => selected.saturating_sub(1),
+        Key::Down => (selected + 1).min(last),
+        Key::PageUp => selected.saturating_sub(10),
+        Key::PageDown => (selected + 10).min(last),
+        Key::Home => 0,
+        Key::End => last,
+        _ => selected,
+    }
+}
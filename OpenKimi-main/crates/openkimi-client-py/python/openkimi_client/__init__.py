@@ -0,0 +1,47 @@
+"""OpenKimi API的Python客户端
+
+基于Rust的openkimi-client，认证、重试和错误分类与Rust客户端相同。
+`Client`为同步接口，`AsyncClient`为asyncio接口；请求和响应都是dict。
+"""
+
+from openkimi_client._native import (
+    APIConnectionError,
+    APIStatusError,
+    AsyncChatStream,
+    AsyncClient,
+    AuthenticationError,
+    BadRequestError,
+    ChatStream,
+    Client,
+    ContextLengthExceededError,
+    NotFoundError,
+    OpenKimiError,
+    PayloadTooLargeError,
+    PermissionDeniedError,
+    RateLimitError,
+    SchemaValidationError,
+    ServiceUnavailableError,
+    StreamError,
+    UpstreamError,
+)
+
+__all__ = [
+    "Client",
+    "AsyncClient",
+    "ChatStream",
+    "AsyncChatStream",
+    "OpenKimiError",
+    "APIStatusError",
+    "BadRequestError",
+    "ContextLengthExceededError",
+    "AuthenticationError",
+    "PermissionDeniedError",
+    "NotFoundError",
+    "PayloadTooLargeError",
+    "SchemaValidationError",
+    "RateLimitError",
+    "ServiceUnavailableError",
+    "UpstreamError",
+    "APIConnectionError",
+    "StreamError",
+]
@@ -0,0 +1,162 @@
+//! asyncio接口，方法返回awaitable，请求在pyo3-async-runtimes管理的tokio运行时中执行
+
+use std::sync::Arc;
+
+use futures_util::StreamExt;
+use pyo3::exceptions::PyStopAsyncIteration;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use pyo3_async_runtimes::tokio::future_into_py;
+use tokio::sync::Mutex;
+
+use crate::convert::{self, from_py, to_py};
+use crate::errors::to_pyerr;
+
+#[pyclass(module = "openkimi_client")]
+pub(crate) struct AsyncClient {
+    inner: openkimi_client::Client,
+}
+
+#[pymethods]
+impl AsyncClient {
+    #[new]
+    #[pyo3(signature = (base_url=None, api_key=None, *, workspace=None, timeout=None, max_retries=None))]
+    fn new(
+        base_url: Option<String>,
+        api_key: Option<String>,
+        workspace: Option<String>,
+        timeout: Option<f64>,
+        max_retries: Option<u32>,
+    ) -> PyResult<AsyncClient> {
+        let config = convert::config(base_url, api_key, workspace, timeout, max_retries)?;
+        let inner = openkimi_client::Client::new(config).map_err(to_pyerr)?;
+        Ok(AsyncClient { inner })
+    }
+
+    fn models<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let client = self.inner.clone();
+        future_into_py(py, async move {
+            let models = client.models().await.map_err(to_pyerr)?;
+            Python::with_gil(|py| to_py(py, &models))
+        })
+    }
+
+    #[pyo3(signature = (messages, model=None, **params))]
+    fn chat<'py>(
+        &self,
+        py: Python<'py>,
+        messages: &Bound<'py, PyAny>,
+        model: Option<String>,
+        params: Option<&Bound<'py, PyDict>>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let request = convert::chat_request(messages, model, params)?;
+        let client = self.inner.clone();
+        future_into_py(py, async move {
+            let response = client.chat(&request).await.map_err(to_pyerr)?;
+            Python::with_gil(|py| to_py(py, &response))
+        })
+    }
+
+    /// await后得到可以`async for`的流
+    #[pyo3(signature = (messages, model=None, **params))]
+    fn chat_stream<'py>(
+        &self,
+        py: Python<'py>,
+        messages: &Bound<'py, PyAny>,
+        model: Option<String>,
+        params: Option<&Bound<'py, PyDict>>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let request = convert::chat_request(messages, model, params)?;
+        let client = self.inner.clone();
+        future_into_py(py, async move {
+            let stream = client.chat_stream(&request).await.map_err(to_pyerr)?;
+            Ok(AsyncChatStream {
+                inner: Arc::new(Mutex::new(Some(stream))),
+            })
+        })
+    }
+
+    #[pyo3(signature = (input, model=None, **params))]
+    fn embeddings<'py>(
+        &self,
+        py: Python<'py>,
+        input: &Bound<'py, PyAny>,
+        model: Option<String>,
+        params: Option<&Bound<'py, PyDict>>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let request = convert::embedding_request(input, model, params)?;
+        let client = self.inner.clone();
+        future_into_py(py, async move {
+            let response = client.embeddings(&request).await.map_err(to_pyerr)?;
+            Python::with_gil(|py| to_py(py, &response))
+        })
+    }
+
+    fn get<'py>(&self, py: Python<'py>, path: String) -> PyResult<Bound<'py, PyAny>> {
+        let client = self.inner.clone();
+        future_into_py(py, async move {
+            let response: serde_json::Value = client.get(&path).await.map_err(to_pyerr)?;
+            Python::with_gil(|py| to_py(py, &response))
+        })
+    }
+
+    fn post<'py>(&self, py: Python<'py>, path: String, body: &Bound<'py, PyAny>) -> PyResult<Bound<'py, PyAny>> {
+        let body: serde_json::Value = from_py(body)?;
+        let client = self.inner.clone();
+        future_into_py(py, async move {
+            let response: serde_json::Value = client.post(&path, &body).await.map_err(to_pyerr)?;
+            Python::with_gil(|py| to_py(py, &response))
+        })
+    }
+
+    fn delete<'py>(&self, py: Python<'py>, path: String) -> PyResult<Bound<'py, PyAny>> {
+        let client = self.inner.clone();
+        future_into_py(py, async move {
+            let response: serde_json::Value = client.delete(&path).await.map_err(to_pyerr)?;
+            Python::with_gil(|py| to_py(py, &response))
+        })
+    }
+}
+
+/// 异步流式回复；中途停止读取时`await stream.aclose()`，关闭连接后服务端随即停止生成
+#[pyclass(module = "openkimi_client")]
+pub(crate) struct AsyncChatStream {
+    inner: Arc<Mutex<Option<openkimi_client::ChatStream>>>,
+}
+
+#[pymethods]
+impl AsyncChatStream {
+    fn __aiter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __anext__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let inner = self.inner.clone();
+        future_into_py(py, async move {
+            let mut stream = inner.lock().await;
+            let next = match stream.as_mut() {
+                Some(chunks) => chunks.next().await,
+                None => None,
+            };
+            match next {
+                Some(Ok(chunk)) => Python::with_gil(|py| to_py(py, &chunk)),
+                Some(Err(err)) => {
+                    *stream = None;
+                    Err(to_pyerr(err))
+                }
+                None => {
+                    *stream = None;
+                    Err(PyStopAsyncIteration::new_err(()))
+                }
+            }
+        })
+    }
+
+    fn aclose<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let inner = self.inner.clone();
+        future_into_py(py, async move {
+            inner.lock().await.take();
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use openkimi_client::{ChatCompletionRequest, ClientConfig, EmbeddingRequest};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// 经Python的json模块转换，dict中可以使用任何能序列化为JSON的值
+pub(crate) fn from_py<T: DeserializeOwned>(value: &Bound<'_, PyAny>) -> PyResult<T> {
+    let json = value.py().import_bound("json")?;
+    let text: String = json.call_method1("dumps", (value,))?.extract()?;
+    serde_json::from_str(&text).map_err(|e| PyValueError::new_err(format!("参数格式不正确: {}", e)))
+}
+
+pub(crate) fn to_py<T: Serialize>(py: Python<'_>, value: &T) -> PyResult<PyObject> {
+    let text = serde_json::to_string(value).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let json = py.import_bound("json")?;
+    Ok(json.call_method1("loads", (text,))?.unbind())
+}
+
+/// 未给出的参数与Rust客户端一样从`OPENKIMI_BASE_URL`和`OPENKIMI_API_KEY`环境变量读取
+pub(crate) fn config(
+    base_url: Option<String>,
+    api_key: Option<String>,
+    workspace: Option<String>,
+    timeout: Option<f64>,
+    max_retries: Option<u32>,
+) -> PyResult<ClientConfig> {
+    let mut config = ClientConfig::from_env();
+    if let Some(base_url) = base_url {
+        config.base_url = base_url;
+    }
+    if api_key.is_some() {
+        config.api_key = api_key;
+    }
+    config.workspace = workspace;
+    if let Some(timeout) = timeout {
+        config.timeout =
+            Duration::try_from_secs_f64(timeout).map_err(|_| PyValueError::new_err("timeout 必须是非负的秒数"))?;
+    }
+    if let Some(max_retries) = max_retries {
+        config.retry.max_retries = max_retries;
+    }
+    Ok(config)
+}
+
+/// 把`model`、`messages`或`input`与其他关键字参数合成请求体
+fn body(
+    key: &str,
+    value: &Bound<'_, PyAny>,
+    model: Option<String>,
+    params: Option<&Bound<'_, PyDict>>,
+) -> PyResult<Value> {
+    let mut body: Map<String, Value> = match params {
+        Some(params) => from_py(params.as_any())?,
+        None => Map::new(),
+    };
+    body.insert(key.to_string(), from_py(value)?);
+    body.insert("model".to_string(), Value::String(model.unwrap_or_default()));
+    Ok(Value::Object(body))
+}
+
+pub(crate) fn chat_request(
+    messages: &Bound<'_, PyAny>,
+    model: Option<String>,
+    params: Option<&Bound<'_, PyDict>>,
+) -> PyResult<ChatCompletionRequest> {
+    let body = body("messages", messages, model, params)?;
+    serde_json::from_value(body).map_err(|e| PyValueError::new_err(format!("messages 格式不正确: {}", e)))
+}
+
+pub(crate) fn embedding_request(
+    input: &Bound<'_, PyAny>,
+    model: Option<String>,
+    params: Option<&Bound<'_, PyDict>>,
+) -> PyResult<EmbeddingRequest> {
+    let body = body("input", input, model, params)?;
+    serde_json::from_value(body).map_err(|e| PyValueError::new_err(format!("input 格式不正确: {}", e)))
+}
@@ -0,0 +1,77 @@
+//! Python异常，按`ClientError`的变体分类，服务端返回的错误带有`status_code`、`code`和`error_type`属性
+
+use openkimi_client::ClientError;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+
+create_exception!(openkimi_client, OpenKimiError, PyException, "OpenKimi客户端错误的基类");
+create_exception!(openkimi_client, APIStatusError, OpenKimiError, "服务端返回的错误");
+create_exception!(openkimi_client, BadRequestError, APIStatusError, "请求参数错误（400）");
+create_exception!(
+    openkimi_client,
+    ContextLengthExceededError,
+    BadRequestError,
+    "提示词加上max_tokens超出模型的上下文窗口"
+);
+create_exception!(openkimi_client, AuthenticationError, APIStatusError, "缺少或提供了错误的令牌（401）");
+create_exception!(openkimi_client, PermissionDeniedError, APIStatusError, "令牌没有所需的权限（403）");
+create_exception!(openkimi_client, NotFoundError, APIStatusError, "模型、会话等不存在（404）");
+create_exception!(openkimi_client, PayloadTooLargeError, APIStatusError, "请求体超出大小上限（413）");
+create_exception!(openkimi_client, SchemaValidationError, APIStatusError, "模型输出不符合response_format（422）");
+create_exception!(openkimi_client, RateLimitError, APIStatusError, "超出限流额度或配额（429），retry_after为建议等待的秒数");
+create_exception!(openkimi_client, ServiceUnavailableError, APIStatusError, "服务正在退出或暂时不可用（503）");
+create_exception!(openkimi_client, UpstreamError, APIStatusError, "无法连接上游模型或上游出错（502、504）");
+create_exception!(openkimi_client, APIConnectionError, OpenKimiError, "无法连接服务端、超时或读取响应失败");
+create_exception!(openkimi_client, StreamError, OpenKimiError, "流式响应中途出错");
+
+pub(crate) fn to_pyerr(err: ClientError) -> PyErr {
+    let message = err.to_string();
+    let pyerr = match &err {
+        ClientError::InvalidRequest(_) => BadRequestError::new_err(message),
+        ClientError::ContextLengthExceeded(_) => ContextLengthExceededError::new_err(message),
+        ClientError::Unauthorized(_) => AuthenticationError::new_err(message),
+        ClientError::Forbidden(_) => PermissionDeniedError::new_err(message),
+        ClientError::NotFound(_) => NotFoundError::new_err(message),
+        ClientError::PayloadTooLarge(_) => PayloadTooLargeError::new_err(message),
+        ClientError::SchemaValidation(_) => SchemaValidationError::new_err(message),
+        ClientError::RateLimited { .. } => RateLimitError::new_err(message),
+        ClientError::Unavailable(_) => ServiceUnavailableError::new_err(message),
+        ClientError::Upstream { .. } => UpstreamError::new_err(message),
+        ClientError::Status { .. } => APIStatusError::new_err(message),
+        ClientError::Stream(_) => StreamError::new_err(message),
+        ClientError::Http(_) => APIConnectionError::new_err(message),
+        ClientError::Decode(_) => OpenKimiError::new_err(message),
+        ClientError::Config(_) => PyValueError::new_err(message),
+    };
+    Python::with_gil(|py| {
+        let value = pyerr.value_bound(py);
+        let detail = err.detail();
+        let _ = value.setattr("status_code", err.status());
+        let _ = value.setattr("code", detail.and_then(|detail| detail.code.clone()));
+        let _ = value.setattr("error_type", detail.map(|detail| detail.kind.clone()));
+        if let ClientError::RateLimited { retry_after, .. } = &err {
+            let _ = value.setattr("retry_after", retry_after.map(|delay| delay.as_secs_f64()));
+        }
+    });
+    pyerr
+}
+
+pub(crate) fn register(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = m.py();
+    m.add("OpenKimiError", py.get_type_bound::<OpenKimiError>())?;
+    m.add("APIStatusError", py.get_type_bound::<APIStatusError>())?;
+    m.add("BadRequestError", py.get_type_bound::<BadRequestError>())?;
+    m.add("ContextLengthExceededError", py.get_type_bound::<ContextLengthExceededError>())?;
+    m.add("AuthenticationError", py.get_type_bound::<AuthenticationError>())?;
+    m.add("PermissionDeniedError", py.get_type_bound::<PermissionDeniedError>())?;
+    m.add("NotFoundError", py.get_type_bound::<NotFoundError>())?;
+    m.add("PayloadTooLargeError", py.get_type_bound::<PayloadTooLargeError>())?;
+    m.add("SchemaValidationError", py.get_type_bound::<SchemaValidationError>())?;
+    m.add("RateLimitError", py.get_type_bound::<RateLimitError>())?;
+    m.add("ServiceUnavailableError", py.get_type_bound::<ServiceUnavailableError>())?;
+    m.add("UpstreamError", py.get_type_bound::<UpstreamError>())?;
+    m.add("APIConnectionError", py.get_type_bound::<APIConnectionError>())?;
+    m.add("StreamError", py.get_type_bound::<StreamError>())?;
+    Ok(())
+}
@@ -0,0 +1,21 @@
+//! openkimi-client的Python绑定
+//!
+//! 同步的`Client`基于阻塞接口，请求期间释放GIL；`AsyncClient`的方法返回asyncio的awaitable，在共享的tokio运行时中执行。
+//! 请求和响应在Python中都是dict，与服务端的JSON一一对应；认证、重试和错误分类与Rust客户端相同，
+//! 错误转为`OpenKimiError`的各个子类。
+
+use pyo3::prelude::*;
+
+mod aio;
+mod convert;
+mod errors;
+mod sync;
+
+#[pymodule]
+fn _native(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<sync::Client>()?;
+    m.add_class::<sync::ChatStream>()?;
+    m.add_class::<aio::AsyncClient>()?;
+    m.add_class::<aio::AsyncChatStream>()?;
+    errors::register(m)
+}
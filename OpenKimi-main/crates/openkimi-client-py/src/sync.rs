@@ -0,0 +1,139 @@
+//! 同步接口，基于`openkimi_client::blocking`，请求期间释放GIL，可以在多个Python线程中共用一个客户端
+
+use std::sync::{Arc, Mutex};
+
+use openkimi_client::blocking;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::convert::{self, from_py, to_py};
+use crate::errors::to_pyerr;
+
+#[pyclass(module = "openkimi_client")]
+pub(crate) struct Client {
+    inner: blocking::Client,
+}
+
+#[pymethods]
+impl Client {
+    #[new]
+    #[pyo3(signature = (base_url=None, api_key=None, *, workspace=None, timeout=None, max_retries=None))]
+    fn new(
+        base_url: Option<String>,
+        api_key: Option<String>,
+        workspace: Option<String>,
+        timeout: Option<f64>,
+        max_retries: Option<u32>,
+    ) -> PyResult<Client> {
+        let config = convert::config(base_url, api_key, workspace, timeout, max_retries)?;
+        let inner = blocking::Client::new(config).map_err(to_pyerr)?;
+        Ok(Client { inner })
+    }
+
+    fn models(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let models = py.allow_threads(|| self.inner.models()).map_err(to_pyerr)?;
+        to_py(py, &models)
+    }
+
+    /// 其余关键字参数（如`temperature`、`tools`、`session_id`）原样放入请求体
+    #[pyo3(signature = (messages, model=None, **params))]
+    fn chat(
+        &self,
+        py: Python<'_>,
+        messages: &Bound<'_, PyAny>,
+        model: Option<String>,
+        params: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<PyObject> {
+        let request = convert::chat_request(messages, model, params)?;
+        let response = py.allow_threads(|| self.inner.chat(&request)).map_err(to_pyerr)?;
+        to_py(py, &response)
+    }
+
+    /// 返回逐块给出`chat.completion.chunk`的迭代器
+    #[pyo3(signature = (messages, model=None, **params))]
+    fn chat_stream(
+        &self,
+        py: Python<'_>,
+        messages: &Bound<'_, PyAny>,
+        model: Option<String>,
+        params: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<ChatStream> {
+        let request = convert::chat_request(messages, model, params)?;
+        let stream = py.allow_threads(|| self.inner.chat_stream(&request)).map_err(to_pyerr)?;
+        Ok(ChatStream {
+            inner: Arc::new(Mutex::new(Some(stream))),
+        })
+    }
+
+    #[pyo3(signature = (input, model=None, **params))]
+    fn embeddings(
+        &self,
+        py: Python<'_>,
+        input: &Bound<'_, PyAny>,
+        model: Option<String>,
+        params: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<PyObject> {
+        let request = convert::embedding_request(input, model, params)?;
+        let response = py.allow_threads(|| self.inner.embeddings(&request)).map_err(to_pyerr)?;
+        to_py(py, &response)
+    }
+
+    /// 调用其他接口，`path`为`/v1`之后的部分，如`/sessions`
+    fn get(&self, py: Python<'_>, path: &str) -> PyResult<PyObject> {
+        let response = py.allow_threads(|| self.inner.get::<serde_json::Value>(path)).map_err(to_pyerr)?;
+        to_py(py, &response)
+    }
+
+    fn post(&self, py: Python<'_>, path: &str, body: &Bound<'_, PyAny>) -> PyResult<PyObject> {
+        let body: serde_json::Value = from_py(body)?;
+        let response = py.allow_threads(|| self.inner.post::<_, serde_json::Value>(path, &body)).map_err(to_pyerr)?;
+        to_py(py, &response)
+    }
+
+    fn delete(&self, py: Python<'_>, path: &str) -> PyResult<PyObject> {
+        let response = py.allow_threads(|| self.inner.delete::<serde_json::Value>(path)).map_err(to_pyerr)?;
+        to_py(py, &response)
+    }
+}
+
+/// 流式回复；中途停止读取时调用`close()`或用`with`语句，关闭连接后服务端随即停止生成
+#[pyclass(module = "openkimi_client")]
+pub(crate) struct ChatStream {
+    inner: Arc<Mutex<Option<blocking::ChatStream>>>,
+}
+
+#[pymethods]
+impl ChatStream {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        let next = py.allow_threads(|| {
+            let mut stream = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+            let next = stream.as_mut().and_then(Iterator::next);
+            if !matches!(next, Some(Ok(_))) {
+                *stream = None;
+            }
+            next
+        });
+        match next {
+            Some(Ok(chunk)) => Ok(Some(to_py(py, &chunk)?)),
+            Some(Err(err)) => Err(to_pyerr(err)),
+            None => Ok(None),
+        }
+    }
+
+    fn close(&self) {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).take();
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    #[pyo3(signature = (*_args))]
+    fn __exit__(&self, _args: &Bound<'_, pyo3::types::PyTuple>) {
+        self.close();
+    }
+}
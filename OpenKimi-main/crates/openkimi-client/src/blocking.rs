@@ -0,0 +1,350 @@
+//! 阻塞接口，与异步接口的方法一一对应
+//!
+//! 内部会启动自己的异步运行时，不能在异步代码中创建或调用，异步代码请使用[`crate::Client`]。
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Instant;
+
+use reqwest::blocking::{Body, Request, RequestBuilder, Response};
+use reqwest::header::{HeaderMap, CONTENT_TYPE};
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::config::ClientConfig;
+use crate::error::ClientError;
+use crate::events::{ChatCollector, ChatEvent};
+use crate::intercept::{Interceptor, Interceptors, RequestParts, ResponseParts};
+use crate::retry::{idempotency_key, IDEMPOTENCY_HEADER};
+use crate::stream::{parse_event, SseDecoder};
+use crate::types::{
+    ChatCompletionChunk, ChatCompletionRequest, ChatCompletionResponse, EmbeddingRequest, EmbeddingResponse, ModelList,
+};
+
+/// 阻塞客户端的传输层，收到任意状态码的响应都应返回`Ok`
+pub trait Transport: Send + Sync + 'static {
+    fn send(&self, request: Request) -> Result<Response, ClientError>;
+}
+
+impl Transport for reqwest::blocking::Client {
+    fn send(&self, request: Request) -> Result<Response, ClientError> {
+        Ok(self.execute(request)?)
+    }
+}
+
+/// 阻塞客户端，克隆后共享同一个连接池
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::blocking::Client,
+    config: ClientConfig,
+    headers: HeaderMap,
+    interceptors: Interceptors,
+    transport: Arc<dyn Transport>,
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("config", &self.config)
+            .field("interceptors", &self.interceptors)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Client {
+    pub fn new(config: ClientConfig) -> Result<Client, ClientError> {
+        let http = reqwest::blocking::Client::builder()
+            .connect_timeout(config.connect_timeout)
+            // 阻塞客户端默认30秒超时，流式响应不限制总时长
+            .timeout(None)
+            .build()?;
+        Ok(Client {
+            headers: config.headers()?,
+            transport: Arc::new(http.clone()),
+            http,
+            config,
+            interceptors: Interceptors::default(),
+        })
+    }
+
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    /// 注册拦截器，按注册的顺序调用
+    pub fn with_interceptor(mut self, interceptor: impl Interceptor) -> Client {
+        self.interceptors.push(interceptor);
+        self
+    }
+
+    /// 替换传输层；`connect_timeout`只对默认的传输层有效
+    pub fn with_transport(mut self, transport: impl Transport) -> Client {
+        self.transport = Arc::new(transport);
+        self
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http.request(method, self.config.url(path)).headers(self.headers.clone())
+    }
+
+    fn send(&self, request: RequestBuilder, attempt: u32) -> Result<Response, ClientError> {
+        let mut request = request.build()?;
+        let method = request.method().clone();
+        let url = request.url().clone();
+        let mut headers = std::mem::take(request.headers_mut());
+        self.interceptors.on_request(&mut RequestParts {
+            method: &method,
+            url: &url,
+            headers: &mut headers,
+            body: request.body().and_then(Body::as_bytes),
+            attempt,
+        })?;
+        *request.headers_mut() = headers;
+
+        let started = Instant::now();
+        let result = self.transport.send(request);
+        let parts = |result| ResponseParts {
+            method: &method,
+            url: &url,
+            attempt,
+            elapsed: started.elapsed(),
+            result,
+        };
+        let response = match result {
+            Ok(response) => response,
+            Err(err) => {
+                self.interceptors.on_response(&parts(Err(&err)));
+                return Err(err);
+            }
+        };
+        let status = response.status();
+        self.interceptors.on_response(&parts(Ok((status, response.headers()))));
+        if status.is_success() {
+            return Ok(response);
+        }
+        let headers = response.headers().clone();
+        let body = response.text().unwrap_or_default();
+        Err(ClientError::from_response(status.as_u16(), &headers, &body))
+    }
+
+    /// 按重试策略执行，每次尝试发送`request`的一个副本
+    fn retry<T, A>(&self, request: RequestBuilder, idempotent: bool, attempt: A) -> Result<T, ClientError>
+    where
+        A: Fn(RequestBuilder, u32) -> Result<T, ClientError>,
+    {
+        let mut retries = 0;
+        loop {
+            let copy = request.try_clone().ok_or_else(|| ClientError::Config("请求体不能重复发送".to_string()))?;
+            let err = match attempt(copy, retries) {
+                Err(err) => err,
+                result => return result,
+            };
+            let Some(delay) = self.config.retry.delay(retries, &err, idempotent) else {
+                return Err(err);
+            };
+            retries += 1;
+            std::thread::sleep(delay);
+        }
+    }
+
+    fn json<T: DeserializeOwned>(&self, request: RequestBuilder, idempotent: bool) -> Result<T, ClientError> {
+        self.retry(request.timeout(self.config.timeout), idempotent, |request, attempt| {
+            let body = self.send(request, attempt)?.bytes()?;
+            serde_json::from_slice(&body).map_err(|e| ClientError::Decode(e.to_string()))
+        })
+    }
+
+    /// 调用其他接口，`path`为`/v1`之后的部分，如`/sessions`
+    pub fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, ClientError> {
+        self.json(self.request(Method::GET, path), true)
+    }
+
+    /// 启用`retry.idempotency`时带上幂等键，各次重试使用同一个键
+    pub fn post<B, T>(&self, path: &str, body: &B) -> Result<T, ClientError>
+    where
+        B: Serialize + ?Sized,
+        T: DeserializeOwned,
+    {
+        let mut request = self.request(Method::POST, path).json(body);
+        let idempotent = self.config.retry.idempotency;
+        if idempotent {
+            request = request.header(IDEMPOTENCY_HEADER, idempotency_key());
+        }
+        self.json(request, idempotent)
+    }
+
+    pub fn delete<T: DeserializeOwned>(&self, path: &str) -> Result<T, ClientError> {
+        self.json(self.request(Method::DELETE, path), true)
+    }
+
+    /// 以原始字节为请求体调用接口，如上传文件；`headers`为额外的请求头，如分块上传的`Upload-Offset`。
+    /// POST与[`post`](Self::post)一样按`retry.idempotency`决定是否带幂等键，其他方法按幂等请求重试
+    pub fn send_bytes<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Vec<u8>,
+        headers: &[(&str, &str)],
+    ) -> Result<T, ClientError> {
+        let post = method == Method::POST;
+        let mut request = self.request(method, path).header(CONTENT_TYPE, "application/octet-stream").body(body);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let idempotent = !post || self.config.retry.idempotency;
+        if post && idempotent {
+            request = request.header(IDEMPOTENCY_HEADER, idempotency_key());
+        }
+        self.json(request, idempotent)
+    }
+
+    pub fn models(&self) -> Result<ModelList, ClientError> {
+        self.get("/models")
+    }
+
+    pub fn chat(&self, request: &ChatCompletionRequest) -> Result<ChatCompletionResponse, ClientError> {
+        let request = ChatCompletionRequest {
+            stream: Some(false),
+            ..request.clone()
+        };
+        self.post("/chat/completions", &request)
+    }
+
+    /// 逐块返回回复；HTTP错误在开始时返回，之后的错误作为迭代器中的一项
+    pub fn chat_stream(&self, request: &ChatCompletionRequest) -> Result<ChatStream, ClientError> {
+        let request = ChatCompletionRequest {
+            stream: Some(true),
+            ..request.clone()
+        };
+        // 没有幂等键，只在请求肯定没有执行时重试；开始接收之后中断的流不重试
+        let request = self.request(Method::POST, "/chat/completions").json(&request);
+        let response = self.retry(request, false, |request, attempt| self.send(request, attempt))?;
+        Ok(ChatStream {
+            response,
+            decoder: SseDecoder::default(),
+            pending: VecDeque::new(),
+            finished: false,
+        })
+    }
+
+    pub fn embeddings(&self, request: &EmbeddingRequest) -> Result<EmbeddingResponse, ClientError> {
+        self.post("/embeddings", request)
+    }
+}
+
+/// 流式回复的迭代器，收到`[DONE]`或出错后结束；中途丢弃时关闭连接
+pub struct ChatStream {
+    response: Response,
+    decoder: SseDecoder,
+    pending: VecDeque<String>,
+    finished: bool,
+}
+
+impl ChatStream {
+    /// 下一个事件的`data`，连接结束时为`None`
+    fn next_event(&mut self) -> Option<Result<String, ClientError>> {
+        let mut buffer = [0; 8192];
+        loop {
+            if let Some(data) = self.pending.pop_front() {
+                return Some(Ok(data));
+            }
+            if self.finished {
+                return None;
+            }
+            match self.response.read(&mut buffer) {
+                Ok(0) => {
+                    self.finished = true;
+                    self.pending.extend(self.decoder.finish());
+                }
+                Ok(read) => self.pending.extend(self.decoder.push(&buffer[..read])),
+                Err(err) => {
+                    self.finished = true;
+                    return Some(Err(ClientError::Decode(format!("读取流式响应失败: {}", err))));
+                }
+            }
+        }
+    }
+
+    /// 读完整个流，拼接第一个候选回复的文本
+    pub fn text(self) -> Result<String, ClientError> {
+        let mut text = String::new();
+        for chunk in self {
+            text.push_str(chunk?.text());
+        }
+        Ok(text)
+    }
+
+    /// 改为逐项返回增量事件
+    pub fn events(self) -> ChatEvents {
+        ChatEvents {
+            chunks: self,
+            collector: ChatCollector::default(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// 读完整个流，拼接为与`chat`相同的完整回复，包括工具调用和用量
+    pub fn response(self) -> Result<ChatCompletionResponse, ClientError> {
+        self.events().response()
+    }
+}
+
+impl Iterator for ChatStream {
+    type Item = Result<ChatCompletionChunk, ClientError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = match self.next_event()? {
+            Ok(data) => parse_event(&data),
+            Err(err) => Some(Err(err)),
+        };
+        // `[DONE]`之后不再读取，出错后结束
+        if !matches!(item, Some(Ok(_))) {
+            self.finished = true;
+            self.pending.clear();
+        }
+        item
+    }
+}
+
+/// 流式回复的增量事件迭代器，同时记录已经收到的内容
+pub struct ChatEvents {
+    chunks: ChatStream,
+    collector: ChatCollector,
+    pending: VecDeque<ChatEvent>,
+}
+
+impl ChatEvents {
+    /// 已经收到的部分拼接出的回复，如中途出错时保留已生成的内容
+    pub fn collected(&self) -> ChatCompletionResponse {
+        self.collector.response()
+    }
+
+    /// 读完剩余的事件，返回完整的回复
+    pub fn response(mut self) -> Result<ChatCompletionResponse, ClientError> {
+        for event in self.by_ref() {
+            event?;
+        }
+        Ok(self.collector.response())
+    }
+}
+
+impl Iterator for ChatEvents {
+    type Item = Result<ChatEvent, ClientError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(Ok(event));
+            }
+            let chunk = match self.chunks.next()? {
+                Ok(chunk) => chunk,
+                Err(err) => return Some(Err(err)),
+            };
+            self.collector.push(&chunk);
+            self.pending.extend(ChatEvent::from_chunk(&chunk));
+        }
+    }
+}
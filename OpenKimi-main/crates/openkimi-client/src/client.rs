@@ -0,0 +1,360 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+
+use bytes::Bytes;
+use futures_util::stream::{self, Stream, StreamExt};
+use reqwest::header::{HeaderMap, CONTENT_TYPE};
+use reqwest::{Body, Method, RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::config::ClientConfig;
+use crate::error::ClientError;
+use crate::events::{ChatCollector, ChatEvent};
+use crate::intercept::{Interceptor, Interceptors, RequestParts, ResponseParts, Transport};
+use crate::retry::{idempotency_key, IDEMPOTENCY_HEADER};
+use crate::rt::{self, BoxStream, Instant};
+use crate::stream::{parse_event, SseDecoder};
+use crate::types::{
+    ChatCompletionChunk, ChatCompletionRequest, ChatCompletionResponse, EmbeddingRequest, EmbeddingResponse, ModelList,
+};
+
+/// 异步客户端，克隆后共享同一个连接池
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    config: ClientConfig,
+    headers: HeaderMap,
+    interceptors: Interceptors,
+    transport: Arc<dyn Transport>,
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("config", &self.config)
+            .field("interceptors", &self.interceptors)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Client {
+    pub fn new(config: ClientConfig) -> Result<Client, ClientError> {
+        let builder = reqwest::Client::builder();
+        #[cfg(not(target_arch = "wasm32"))]
+        let builder = builder.connect_timeout(config.connect_timeout);
+        let http = builder.build()?;
+        Ok(Client {
+            headers: config.headers()?,
+            transport: Arc::new(http.clone()),
+            http,
+            config,
+            interceptors: Interceptors::default(),
+        })
+    }
+
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    /// 注册拦截器，按注册的顺序调用
+    pub fn with_interceptor(mut self, interceptor: impl Interceptor) -> Client {
+        self.interceptors.push(interceptor);
+        self
+    }
+
+    /// 替换传输层；`connect_timeout`只对默认的传输层有效
+    pub fn with_transport(mut self, transport: impl Transport) -> Client {
+        self.transport = Arc::new(transport);
+        self
+    }
+
+    /// 认证和工作区请求头直接放在请求中，拦截器和自定义传输层都能看到
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http.request(method, self.config.url(path)).headers(self.headers.clone())
+    }
+
+    /// 经过拦截器发送请求，非2xx的响应转为对应的错误
+    async fn send(&self, request: RequestBuilder, attempt: u32) -> Result<Response, ClientError> {
+        let mut request = request.build()?;
+        let method = request.method().clone();
+        let url = request.url().clone();
+        let mut headers = std::mem::take(request.headers_mut());
+        self.interceptors.on_request(&mut RequestParts {
+            method: &method,
+            url: &url,
+            headers: &mut headers,
+            body: request.body().and_then(Body::as_bytes),
+            attempt,
+        })?;
+        *request.headers_mut() = headers;
+
+        let started = Instant::now();
+        let result = self.transport.send(request).await;
+        let parts = |result| ResponseParts {
+            method: &method,
+            url: &url,
+            attempt,
+            elapsed: started.elapsed(),
+            result,
+        };
+        let response = match result {
+            Ok(response) => response,
+            Err(err) => {
+                self.interceptors.on_response(&parts(Err(&err)));
+                return Err(err);
+            }
+        };
+        let status = response.status();
+        self.interceptors.on_response(&parts(Ok((status, response.headers()))));
+        if status.is_success() {
+            return Ok(response);
+        }
+        let headers = response.headers().clone();
+        let body = response.text().await.unwrap_or_default();
+        Err(ClientError::from_response(status.as_u16(), &headers, &body))
+    }
+
+    /// 按重试策略执行，每次尝试发送`request`的一个副本
+    async fn retry<T, A, F>(&self, request: RequestBuilder, idempotent: bool, attempt: A) -> Result<T, ClientError>
+    where
+        A: Fn(RequestBuilder, u32) -> F,
+        F: Future<Output = Result<T, ClientError>>,
+    {
+        let mut retries = 0;
+        loop {
+            let copy = request.try_clone().ok_or_else(|| ClientError::Config("请求体不能重复发送".to_string()))?;
+            let err = match attempt(copy, retries).await {
+                Err(err) => err,
+                result => return result,
+            };
+            let Some(delay) = self.config.retry.delay(retries, &err, idempotent) else {
+                return Err(err);
+            };
+            retries += 1;
+            rt::sleep(delay).await;
+        }
+    }
+
+    async fn json<T: DeserializeOwned>(&self, request: RequestBuilder, idempotent: bool) -> Result<T, ClientError> {
+        let request = request.timeout(self.config.timeout);
+        self.retry(request, idempotent, |request, attempt| async move {
+            let body = self.send(request, attempt).await?.bytes().await?;
+            serde_json::from_slice(&body).map_err(|e| ClientError::Decode(e.to_string()))
+        })
+        .await
+    }
+
+    /// 调用其他接口，`path`为`/v1`之后的部分，如`/sessions`
+    pub async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, ClientError> {
+        self.json(self.request(Method::GET, path), true).await
+    }
+
+    /// 启用`retry.idempotency`时带上幂等键，各次重试使用同一个键
+    pub async fn post<B, T>(&self, path: &str, body: &B) -> Result<T, ClientError>
+    where
+        B: Serialize + ?Sized,
+        T: DeserializeOwned,
+    {
+        let mut request = self.request(Method::POST, path).json(body);
+        let idempotent = self.config.retry.idempotency;
+        if idempotent {
+            request = request.header(IDEMPOTENCY_HEADER, idempotency_key());
+        }
+        self.json(request, idempotent).await
+    }
+
+    pub async fn delete<T: DeserializeOwned>(&self, path: &str) -> Result<T, ClientError> {
+        self.json(self.request(Method::DELETE, path), true).await
+    }
+
+    /// 以原始字节为请求体调用接口，如上传文件；`headers`为额外的请求头，如分块上传的`Upload-Offset`。
+    /// POST与[`post`](Self::post)一样按`retry.idempotency`决定是否带幂等键，其他方法按幂等请求重试
+    pub async fn send_bytes<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Vec<u8>,
+        headers: &[(&str, &str)],
+    ) -> Result<T, ClientError> {
+        let post = method == Method::POST;
+        let mut request = self.request(method, path).header(CONTENT_TYPE, "application/octet-stream").body(body);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let idempotent = !post || self.config.retry.idempotency;
+        if post && idempotent {
+            request = request.header(IDEMPOTENCY_HEADER, idempotency_key());
+        }
+        self.json(request, idempotent).await
+    }
+
+    pub async fn models(&self) -> Result<ModelList, ClientError> {
+        self.get("/models").await
+    }
+
+    /// 一次返回完整回复
+    pub async fn chat(&self, request: &ChatCompletionRequest) -> Result<ChatCompletionResponse, ClientError> {
+        let request = ChatCompletionRequest {
+            stream: Some(false),
+            ..request.clone()
+        };
+        self.post("/chat/completions", &request).await
+    }
+
+    /// 逐块返回回复；HTTP错误在开始时返回，之后的错误作为流中的一项
+    pub async fn chat_stream(&self, request: &ChatCompletionRequest) -> Result<ChatStream, ClientError> {
+        let request = ChatCompletionRequest {
+            stream: Some(true),
+            ..request.clone()
+        };
+        // 没有幂等键，只在请求肯定没有执行时重试；开始接收之后中断的流不重试
+        let request = self.request(Method::POST, "/chat/completions").json(&request);
+        let response = self.retry(request, false, |request, attempt| self.send(request, attempt)).await?;
+        Ok(ChatStream::new(response))
+    }
+
+    pub async fn embeddings(&self, request: &EmbeddingRequest) -> Result<EmbeddingResponse, ClientError> {
+        self.post("/embeddings", request).await
+    }
+}
+
+/// 解析中的流式响应
+struct Decoding {
+    body: BoxStream<'static, reqwest::Result<Bytes>>,
+    decoder: SseDecoder,
+    pending: VecDeque<String>,
+    finished: bool,
+}
+
+impl Decoding {
+    /// 下一个事件的`data`，连接结束时为`None`
+    async fn next_event(&mut self) -> Option<Result<String, ClientError>> {
+        loop {
+            if let Some(data) = self.pending.pop_front() {
+                return Some(Ok(data));
+            }
+            if self.finished {
+                return None;
+            }
+            match self.body.next().await {
+                Some(Ok(chunk)) => self.pending.extend(self.decoder.push(&chunk)),
+                Some(Err(err)) => {
+                    self.finished = true;
+                    return Some(Err(ClientError::Http(err)));
+                }
+                None => {
+                    self.finished = true;
+                    self.pending.extend(self.decoder.finish());
+                }
+            }
+        }
+    }
+}
+
+/// 流式回复，每项为一块；收到`[DONE]`或出错后结束
+///
+/// 中途丢弃时关闭连接，服务端随即停止生成（启用断线续读时除外）。
+pub struct ChatStream {
+    inner: BoxStream<'static, Result<ChatCompletionChunk, ClientError>>,
+}
+
+impl ChatStream {
+    fn new(response: Response) -> ChatStream {
+        let decoding = Decoding {
+            body: rt::boxed(response.bytes_stream()),
+            decoder: SseDecoder::default(),
+            pending: VecDeque::new(),
+            finished: false,
+        };
+        let inner = stream::unfold(Some(decoding), |decoding| async move {
+            let mut decoding = decoding?;
+            // `[DONE]`之后不再读取，出错后结束
+            match decoding.next_event().await? {
+                Ok(data) => match parse_event(&data)? {
+                    Ok(chunk) => Some((Ok(chunk), Some(decoding))),
+                    Err(err) => Some((Err(err), None)),
+                },
+                Err(err) => Some((Err(err), None)),
+            }
+        });
+        ChatStream { inner: rt::boxed(inner) }
+    }
+
+    /// 读完整个流，拼接第一个候选回复的文本
+    pub async fn text(mut self) -> Result<String, ClientError> {
+        let mut text = String::new();
+        while let Some(chunk) = self.inner.next().await {
+            text.push_str(chunk?.text());
+        }
+        Ok(text)
+    }
+
+    /// 改为逐项返回增量事件
+    pub fn events(self) -> ChatEvents {
+        ChatEvents {
+            chunks: self,
+            collector: ChatCollector::default(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// 读完整个流，拼接为与`chat`相同的完整回复，包括工具调用和用量
+    pub async fn response(self) -> Result<ChatCompletionResponse, ClientError> {
+        self.events().response().await
+    }
+}
+
+impl Stream for ChatStream {
+    type Item = Result<ChatCompletionChunk, ClientError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.poll_next_unpin(cx)
+    }
+}
+
+/// 流式回复的增量事件，同时记录已经收到的内容
+pub struct ChatEvents {
+    chunks: ChatStream,
+    collector: ChatCollector,
+    pending: VecDeque<ChatEvent>,
+}
+
+impl ChatEvents {
+    /// 已经收到的部分拼接出的回复，如中途出错时保留已生成的内容
+    pub fn collected(&self) -> ChatCompletionResponse {
+        self.collector.response()
+    }
+
+    /// 读完剩余的事件，返回完整的回复
+    pub async fn response(mut self) -> Result<ChatCompletionResponse, ClientError> {
+        while let Some(event) = self.next().await {
+            event?;
+        }
+        Ok(self.collector.response())
+    }
+}
+
+impl Stream for ChatEvents {
+    type Item = Result<ChatEvent, ClientError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Poll::Ready(Some(Ok(event)));
+            }
+            match ready!(self.chunks.poll_next_unpin(cx)) {
+                Some(Ok(chunk)) => {
+                    self.collector.push(&chunk);
+                    self.pending.extend(ChatEvent::from_chunk(&chunk));
+                }
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
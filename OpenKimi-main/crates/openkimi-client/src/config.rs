@@ -0,0 +1,97 @@
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+
+use crate::error::ClientError;
+use crate::retry::RetryConfig;
+
+/// 服务端的默认地址
+pub const DEFAULT_BASE_URL: &str = "http://127.0.0.1:8000/v1";
+
+/// 指定工作区的请求头，与服务端`workspaces.header`的默认值一致
+pub const WORKSPACE_HEADER: &str = "x-openkimi-workspace";
+
+/// 客户端配置
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// 包含`/v1`的服务端地址
+    pub base_url: String,
+    /// 服务端启用认证时使用的令牌
+    pub api_key: Option<String>,
+    /// 使用令牌绑定的工作区之外的工作区时指定
+    pub workspace: Option<String>,
+    /// wasm32上由浏览器决定，不起作用
+    pub connect_timeout: Duration,
+    /// 非流式请求每次尝试的超时，流式响应只受`connect_timeout`限制
+    pub timeout: Duration,
+    pub retry: RetryConfig,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            base_url: DEFAULT_BASE_URL.to_string(),
+            api_key: None,
+            workspace: None,
+            connect_timeout: Duration::from_secs(10),
+            timeout: Duration::from_secs(600),
+            retry: RetryConfig::default(),
+        }
+    }
+}
+
+impl ClientConfig {
+    pub fn new(base_url: impl Into<String>) -> ClientConfig {
+        ClientConfig {
+            base_url: base_url.into(),
+            ..ClientConfig::default()
+        }
+    }
+
+    /// 读取`OPENKIMI_BASE_URL`和`OPENKIMI_API_KEY`环境变量，未设置的使用默认值
+    pub fn from_env() -> ClientConfig {
+        let env = |name| std::env::var(name).ok().filter(|value: &String| !value.is_empty());
+        ClientConfig {
+            base_url: env("OPENKIMI_BASE_URL").unwrap_or_else(|| DEFAULT_BASE_URL.to_string()),
+            api_key: env("OPENKIMI_API_KEY"),
+            ..ClientConfig::default()
+        }
+    }
+
+    pub fn api_key(mut self, api_key: impl Into<String>) -> ClientConfig {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    pub fn workspace(mut self, workspace: impl Into<String>) -> ClientConfig {
+        self.workspace = Some(workspace.into());
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryConfig) -> ClientConfig {
+        self.retry = retry;
+        self
+    }
+
+    /// 每个请求都带上的请求头
+    pub(crate) fn headers(&self) -> Result<HeaderMap, ClientError> {
+        let mut headers = HeaderMap::new();
+        if let Some(api_key) = &self.api_key {
+            let mut value = HeaderValue::from_str(&format!("Bearer {}", api_key))
+                .map_err(|_| ClientError::Config("令牌中有不能放在请求头中的字符".to_string()))?;
+            value.set_sensitive(true);
+            headers.insert(AUTHORIZATION, value);
+        }
+        if let Some(workspace) = &self.workspace {
+            let value = HeaderValue::from_str(workspace)
+                .map_err(|_| ClientError::Config(format!("无效的工作区名: {}", workspace)))?;
+            headers.insert(WORKSPACE_HEADER, value);
+        }
+        Ok(headers)
+    }
+
+    /// `path`为`/v1`之后的部分，如`/chat/completions`
+    pub(crate) fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url.trim_end_matches('/'), path.trim_start_matches('/'))
+    }
+}
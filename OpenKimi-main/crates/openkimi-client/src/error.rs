@@ -0,0 +1,205 @@
+use std::fmt;
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// 服务端返回的错误体`{"error":{"message":...,"type":...,"code":...}}`
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ErrorDetail {
+    #[serde(default)]
+    pub message: String,
+    /// 如`invalid_request_error`、`rate_limit_error`
+    #[serde(default, rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub code: Option<String>,
+    /// 结构化输出校验失败时的各条错误
+    #[serde(default)]
+    pub errors: Vec<String>,
+}
+
+impl ErrorDetail {
+    /// 解析错误响应体，不是OpenAI格式时把整个响应体作为消息
+    pub(crate) fn parse(body: &str) -> ErrorDetail {
+        #[derive(Deserialize)]
+        struct Envelope {
+            error: Value,
+        }
+        let envelope = serde_json::from_str::<Envelope>(body).ok();
+        match envelope.map(|envelope| envelope.error) {
+            Some(Value::String(message)) => ErrorDetail {
+                message,
+                ..ErrorDetail::default()
+            },
+            Some(error) => serde_json::from_value(error).unwrap_or_else(|_| ErrorDetail {
+                message: body.to_string(),
+                ..ErrorDetail::default()
+            }),
+            None => ErrorDetail {
+                message: body.trim().to_string(),
+                ..ErrorDetail::default()
+            },
+        }
+    }
+}
+
+impl fmt::Display for ErrorDetail {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)?;
+        for error in &self.errors {
+            write!(f, "; {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// 客户端错误，HTTP错误按状态码和`code`分类
+#[derive(Debug)]
+pub enum ClientError {
+    /// 请求参数错误（400）
+    InvalidRequest(ErrorDetail),
+    /// 提示词加上`max_tokens`超出模型的上下文窗口（400，`context_length_exceeded`）
+    ContextLengthExceeded(ErrorDetail),
+    /// 缺少或提供了错误的令牌（401）
+    Unauthorized(ErrorDetail),
+    /// 令牌没有所需的权限（403）
+    Forbidden(ErrorDetail),
+    /// 模型、会话等不存在（404）
+    NotFound(ErrorDetail),
+    /// 请求体超出大小上限（413）
+    PayloadTooLarge(ErrorDetail),
+    /// 模型输出不符合`response_format`（422）
+    SchemaValidation(ErrorDetail),
+    /// 超出限流额度或配额（429），`retry_after`为服务端建议的等待时间
+    RateLimited {
+        detail: ErrorDetail,
+        retry_after: Option<Duration>,
+    },
+    /// 服务正在退出或暂时不可用（503）
+    Unavailable(ErrorDetail),
+    /// 无法连接上游模型或上游出错（502、504）
+    Upstream { status: u16, detail: ErrorDetail },
+    /// 其他状态码
+    Status { status: u16, detail: ErrorDetail },
+    /// 流式响应中途出错，如上游连接中断
+    Stream(ErrorDetail),
+    /// 无法连接服务端、超时或读取响应失败
+    Http(reqwest::Error),
+    /// 响应不是预期的格式
+    Decode(String),
+    /// 客户端配置错误，如令牌中有不能放在请求头中的字符
+    Config(String),
+}
+
+impl ClientError {
+    /// 由错误响应的状态码、响应头和响应体得到错误
+    pub(crate) fn from_response(status: u16, headers: &HeaderMap, body: &str) -> ClientError {
+        let detail = ErrorDetail::parse(body);
+        match status {
+            400 if detail.code.as_deref() == Some("context_length_exceeded") => {
+                ClientError::ContextLengthExceeded(detail)
+            }
+            400 => ClientError::InvalidRequest(detail),
+            401 => ClientError::Unauthorized(detail),
+            403 => ClientError::Forbidden(detail),
+            404 => ClientError::NotFound(detail),
+            413 => ClientError::PayloadTooLarge(detail),
+            422 => ClientError::SchemaValidation(detail),
+            429 => {
+                let retry_after = headers
+                    .get(RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.trim().parse().ok())
+                    .map(Duration::from_secs);
+                ClientError::RateLimited { detail, retry_after }
+            }
+            503 => ClientError::Unavailable(detail),
+            502 | 504 => ClientError::Upstream { status, detail },
+            _ => ClientError::Status { status, detail },
+        }
+    }
+
+    /// HTTP状态码，连接失败等没有响应时为`None`
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            ClientError::InvalidRequest(_) | ClientError::ContextLengthExceeded(_) => Some(400),
+            ClientError::Unauthorized(_) => Some(401),
+            ClientError::Forbidden(_) => Some(403),
+            ClientError::NotFound(_) => Some(404),
+            ClientError::PayloadTooLarge(_) => Some(413),
+            ClientError::SchemaValidation(_) => Some(422),
+            ClientError::RateLimited { .. } => Some(429),
+            ClientError::Unavailable(_) => Some(503),
+            ClientError::Upstream { status, .. } | ClientError::Status { status, .. } => Some(*status),
+            ClientError::Http(err) => err.status().map(|status| status.as_u16()),
+            ClientError::Stream(_) | ClientError::Decode(_) | ClientError::Config(_) => None,
+        }
+    }
+
+    /// 服务端返回的错误体
+    pub fn detail(&self) -> Option<&ErrorDetail> {
+        match self {
+            ClientError::InvalidRequest(detail)
+            | ClientError::ContextLengthExceeded(detail)
+            | ClientError::Unauthorized(detail)
+            | ClientError::Forbidden(detail)
+            | ClientError::NotFound(detail)
+            | ClientError::PayloadTooLarge(detail)
+            | ClientError::SchemaValidation(detail)
+            | ClientError::RateLimited { detail, .. }
+            | ClientError::Unavailable(detail)
+            | ClientError::Upstream { detail, .. }
+            | ClientError::Status { detail, .. }
+            | ClientError::Stream(detail) => Some(detail),
+            ClientError::Http(_) | ClientError::Decode(_) | ClientError::Config(_) => None,
+        }
+    }
+
+    /// 稍后重试是否可能成功
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ClientError::RateLimited { .. } | ClientError::Unavailable(_) | ClientError::Upstream { .. } => true,
+            #[cfg(not(target_arch = "wasm32"))]
+            ClientError::Http(err) => err.is_connect() || err.is_timeout(),
+            // fetch不区分连接失败和其他请求错误
+            #[cfg(target_arch = "wasm32")]
+            ClientError::Http(err) => err.is_request() || err.is_timeout(),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Http(err) => write!(f, "请求OpenKimi失败: {}", err),
+            ClientError::Decode(reason) => write!(f, "无法解析OpenKimi的响应: {}", reason),
+            ClientError::Config(reason) => write!(f, "客户端配置错误: {}", reason),
+            ClientError::Stream(detail) => write!(f, "流式响应中断: {}", detail),
+            other => match (other.status(), other.detail()) {
+                (Some(status), Some(detail)) => write!(f, "OpenKimi返回 {}: {}", status, detail),
+                _ => f.write_str("OpenKimi返回错误"),
+            },
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Http(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(err: reqwest::Error) -> ClientError {
+        if err.is_decode() {
+            return ClientError::Decode(err.to_string());
+        }
+        ClientError::Http(err)
+    }
+}
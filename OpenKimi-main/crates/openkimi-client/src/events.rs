@@ -0,0 +1,148 @@
+//! 流式回复的增量事件
+//!
+//! 每块中的增量拆成内容、工具调用、结束原因和用量等事件，[`ChatCollector`]把各块拼接为与非流式接口相同的完整回复。
+
+use serde_json::{Map, Value};
+
+use crate::types::{
+    ChatChoice, ChatCompletionChunk, ChatCompletionResponse, ChatMessage, MessageContent, ToolCall, ToolCallDelta,
+    Usage,
+};
+
+/// 第一个候选回复的一项增量，多个候选回复（`n`大于1）时请直接读取各块
+#[derive(Debug, Clone)]
+pub enum ChatEvent {
+    /// 回复的角色，通常只在第一块中给出
+    Role(String),
+    /// 新增的文本
+    Content(String),
+    /// 工具调用的一段，按`index`拼接
+    ToolCall(ToolCallDelta),
+    /// 回复结束，如`stop`、`length`、`tool_calls`
+    Finish(String),
+    /// 整个请求的用量，请求中`stream_options.include_usage`为`true`时在最后给出
+    Usage(Usage),
+}
+
+impl ChatEvent {
+    /// 一块中的事件，按角色、内容、工具调用、结束原因、用量的顺序
+    pub(crate) fn from_chunk(chunk: &ChatCompletionChunk) -> Vec<ChatEvent> {
+        let mut events = Vec::new();
+        if let Some(choice) = chunk.choices.iter().find(|choice| choice.index == 0) {
+            let delta = &choice.delta;
+            events.extend(delta.role.clone().map(ChatEvent::Role));
+            events.extend(delta.content.clone().filter(|text| !text.is_empty()).map(ChatEvent::Content));
+            events.extend(delta.tool_calls.iter().flatten().cloned().map(ChatEvent::ToolCall));
+            events.extend(choice.finish_reason.clone().map(ChatEvent::Finish));
+        }
+        events.extend(chunk.usage.clone().map(ChatEvent::Usage));
+        events
+    }
+}
+
+#[derive(Debug, Default)]
+struct Collected {
+    role: Option<String>,
+    content: String,
+    tool_calls: Vec<ToolCall>,
+    finish_reason: Option<String>,
+}
+
+/// 把流式响应的各块拼接为完整的回复
+#[derive(Debug, Default)]
+pub struct ChatCollector {
+    id: String,
+    model: String,
+    created: u64,
+    choices: Vec<Collected>,
+    usage: Option<Usage>,
+}
+
+impl ChatCollector {
+    pub fn push(&mut self, chunk: &ChatCompletionChunk) {
+        if self.id.is_empty() {
+            self.id.clone_from(&chunk.id);
+            self.model.clone_from(&chunk.model);
+            self.created = chunk.created;
+        }
+        if chunk.usage.is_some() {
+            self.usage.clone_from(&chunk.usage);
+        }
+        for choice in &chunk.choices {
+            let index = choice.index as usize;
+            if index >= self.choices.len() {
+                self.choices.resize_with(index + 1, Collected::default);
+            }
+            let collected = &mut self.choices[index];
+            if let Some(role) = &choice.delta.role {
+                collected.role = Some(role.clone());
+            }
+            if let Some(content) = &choice.delta.content {
+                collected.content.push_str(content);
+            }
+            for delta in choice.delta.tool_calls.iter().flatten() {
+                let index = delta.index as usize;
+                if index >= collected.tool_calls.len() {
+                    collected.tool_calls.resize_with(index + 1, || ToolCall {
+                        kind: "function".to_string(),
+                        ..ToolCall::default()
+                    });
+                }
+                let call = &mut collected.tool_calls[index];
+                if let Some(id) = &delta.id {
+                    call.id.clone_from(id);
+                }
+                if let Some(kind) = &delta.kind {
+                    call.kind.clone_from(kind);
+                }
+                if let Some(name) = &delta.function.name {
+                    call.function.name.push_str(name);
+                }
+                if let Some(arguments) = &delta.function.arguments {
+                    call.function.arguments.push_str(arguments);
+                }
+            }
+            if choice.finish_reason.is_some() {
+                collected.finish_reason.clone_from(&choice.finish_reason);
+            }
+        }
+    }
+
+    /// 到目前为止拼接出的回复；只有工具调用时`content`为`None`，与非流式接口一致
+    pub fn response(&self) -> ChatCompletionResponse {
+        let choices = self
+            .choices
+            .iter()
+            .enumerate()
+            .map(|(index, collected)| {
+                let mut extra = Map::new();
+                if !collected.tool_calls.is_empty() {
+                    let calls = serde_json::to_value(&collected.tool_calls).unwrap_or(Value::Null);
+                    extra.insert("tool_calls".to_string(), calls);
+                }
+                let content = (!collected.content.is_empty() || collected.tool_calls.is_empty())
+                    .then(|| MessageContent::Text(collected.content.clone()));
+                ChatChoice {
+                    index: index as u32,
+                    message: ChatMessage {
+                        role: collected.role.clone().unwrap_or_else(|| "assistant".to_string()),
+                        content,
+                        name: None,
+                        extra,
+                    },
+                    finish_reason: collected.finish_reason.clone(),
+                    extra: Map::new(),
+                }
+            })
+            .collect();
+        ChatCompletionResponse {
+            id: self.id.clone(),
+            object: "chat.completion".to_string(),
+            created: self.created,
+            model: self.model.clone(),
+            choices,
+            usage: self.usage.clone(),
+            extra: Map::new(),
+        }
+    }
+}
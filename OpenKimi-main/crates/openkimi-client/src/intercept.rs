@@ -0,0 +1,102 @@
+//! 拦截器与可替换的传输层
+//!
+//! [`Interceptor`]在每次尝试发送前后被调用，用于添加请求头、签名、记录日志或注入链路追踪上下文，
+//! 重试时每次尝试都会再调用一次。[`Transport`]负责实际发送请求，默认为reqwest，可以替换为企业的HTTP网关、
+//! 带双向TLS的客户端或测试中的模拟实现。异步和阻塞客户端共用同一个拦截器接口，阻塞客户端的传输层为`blocking::Transport`。
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::header::HeaderMap;
+use reqwest::{Method, StatusCode, Url};
+
+use crate::error::ClientError;
+use crate::rt::BoxFuture;
+
+/// 发送前的请求，可以修改请求头
+pub struct RequestParts<'a> {
+    pub method: &'a Method,
+    pub url: &'a Url,
+    pub headers: &'a mut HeaderMap,
+    /// JSON请求体，GET和DELETE为`None`
+    pub body: Option<&'a [u8]>,
+    /// 第几次尝试，从0开始
+    pub attempt: u32,
+}
+
+/// 一次尝试的结果；流式响应在收到响应头时即调用，`elapsed`不包括读取流的时间
+pub struct ResponseParts<'a> {
+    pub method: &'a Method,
+    pub url: &'a Url,
+    pub attempt: u32,
+    pub elapsed: Duration,
+    /// 收到响应时为`Ok`，包括非2xx的状态码；连接失败、超时等为`Err`
+    pub result: Result<(StatusCode, &'a HeaderMap), &'a ClientError>,
+}
+
+/// 请求拦截器，按注册的顺序调用
+pub trait Interceptor: Send + Sync + 'static {
+    /// 发送前调用，返回错误时不发送，调用直接返回该错误
+    fn on_request(&self, request: &mut RequestParts<'_>) -> Result<(), ClientError> {
+        let _ = request;
+        Ok(())
+    }
+
+    fn on_response(&self, response: &ResponseParts<'_>) {
+        let _ = response;
+    }
+}
+
+/// 在每个请求上设置固定请求头的拦截器，如网关要求的租户标识
+#[derive(Debug, Clone)]
+pub struct SetHeaders(pub HeaderMap);
+
+impl Interceptor for SetHeaders {
+    fn on_request(&self, request: &mut RequestParts<'_>) -> Result<(), ClientError> {
+        for (name, value) in &self.0 {
+            request.headers.insert(name.clone(), value.clone());
+        }
+        Ok(())
+    }
+}
+
+/// 异步客户端的传输层，收到任意状态码的响应都应返回`Ok`
+///
+/// 原生平台上自定义实现可以由`http::Response`构造`reqwest::Response`。请求的超时在`reqwest::Request::timeout`中给出。
+pub trait Transport: Send + Sync + 'static {
+    fn send(&self, request: reqwest::Request) -> BoxFuture<'static, Result<reqwest::Response, ClientError>>;
+}
+
+impl Transport for reqwest::Client {
+    fn send(&self, request: reqwest::Request) -> BoxFuture<'static, Result<reqwest::Response, ClientError>> {
+        let response = self.execute(request);
+        Box::pin(async move { Ok(response.await?) })
+    }
+}
+
+/// 已注册的拦截器
+#[derive(Clone, Default)]
+pub(crate) struct Interceptors(Vec<Arc<dyn Interceptor>>);
+
+impl Interceptors {
+    pub(crate) fn push(&mut self, interceptor: impl Interceptor) {
+        self.0.push(Arc::new(interceptor));
+    }
+
+    pub(crate) fn on_request(&self, request: &mut RequestParts<'_>) -> Result<(), ClientError> {
+        self.0.iter().try_for_each(|interceptor| interceptor.on_request(request))
+    }
+
+    pub(crate) fn on_response(&self, response: &ResponseParts<'_>) {
+        for interceptor in &self.0 {
+            interceptor.on_response(response);
+        }
+    }
+}
+
+impl fmt::Debug for Interceptors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}个拦截器", self.0.len())
+    }
+}
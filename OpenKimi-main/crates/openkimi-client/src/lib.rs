@@ -0,0 +1,38 @@
+//! OpenKimi API的Rust客户端
+//!
+//! 提供类型化的对话、流式对话、嵌入和模型列表接口，其他接口可以用`get`/`post`/`delete`按路径调用，
+//! 上传文件等以原始字节为请求体的接口用`send_bytes`。
+//! 流式回复可以逐块读取、按[`ChatEvent`]逐个增量读取，或拼接为完整的回复。
+//! [`Client`]为异步接口，需要tokio运行时；启用默认的`blocking`特性时另有[`blocking::Client`]。
+//! 也可以编译到`wasm32-unknown-unknown`，在浏览器和Electron渲染进程中通过fetch调用，接口与原生平台相同。
+//! 服务端返回的错误按状态码和错误码转为[`ClientError`]的各个变体，如限流时的`RateLimited`附带建议的等待时间。
+//! 限流、上游出错和连接失败时按[`RetryConfig`]自动重试。
+//! 可以注册[`Interceptor`]添加请求头、签名或记录日志，也可以用自定义的[`Transport`]发送请求。
+//! 启用`vcr`特性时，[`vcr::Vcr`]把真实的交互录制为磁带文件，之后在测试中离线回放。
+
+#[cfg(all(feature = "blocking", not(target_arch = "wasm32")))]
+pub mod blocking;
+mod client;
+mod config;
+mod error;
+mod events;
+mod intercept;
+mod retry;
+mod rt;
+mod stream;
+pub mod types;
+#[cfg(all(feature = "vcr", not(target_arch = "wasm32")))]
+pub mod vcr;
+
+pub use client::{ChatEvents, ChatStream, Client};
+pub use config::{ClientConfig, DEFAULT_BASE_URL, WORKSPACE_HEADER};
+pub use error::{ClientError, ErrorDetail};
+pub use events::{ChatCollector, ChatEvent};
+pub use intercept::{Interceptor, RequestParts, ResponseParts, SetHeaders, Transport};
+pub use retry::{RetryConfig, IDEMPOTENCY_HEADER};
+pub use rt::BoxFuture;
+pub use reqwest::Method;
+pub use types::{
+    ChatCompletionChunk, ChatCompletionRequest, ChatCompletionResponse, ChatMessage, EmbeddingInput, EmbeddingRequest,
+    EmbeddingResponse, FunctionCall, Model, ModelList, ModelPricing, ToolCall, ToolCallDelta, Usage,
+};
@@ -0,0 +1,103 @@
+//! 重试策略
+//!
+//! 限流、服务暂时不可用、上游出错和连接失败时按指数退避加随机抖动重试，服务端给出`Retry-After`时按它等待。
+//! 非流式的POST请求带上`Idempotency-Key`，同一次调用的各次重试使用同一个键，服务端启用`idempotency`后
+//! 已经执行过的请求直接返回第一次的响应，超时后重试不会重复计费或重复创建资源。
+//! 没有幂等键的请求（流式对话、关闭`idempotency`时的POST）只在请求肯定没有执行时重试，超时不重试。
+
+use std::time::Duration;
+
+use crate::error::ClientError;
+use crate::rt::SystemTime;
+
+/// 幂等键的请求头
+pub const IDEMPOTENCY_HEADER: &str = "idempotency-key";
+
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    /// 第一次请求之外最多重试几次，0为不重试
+    pub max_retries: u32,
+    /// 第一次重试前的退避时间，之后每次加倍
+    pub initial_backoff: Duration,
+    /// 退避时间的上限；`Retry-After`超过它时不再重试，直接返回错误
+    pub max_backoff: Duration,
+    /// 在0到退避时间之间随机等待，避免大量客户端同时重试
+    pub jitter: bool,
+    /// 非流式的POST请求带上幂等键
+    pub idempotency: bool,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            jitter: true,
+            idempotency: true,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// 不重试
+    pub fn disabled() -> RetryConfig {
+        RetryConfig {
+            max_retries: 0,
+            ..RetryConfig::default()
+        }
+    }
+
+    /// 第`attempt`次（从0开始）失败后等待多久再重试，不应重试时返回`None`
+    ///
+    /// `idempotent`为请求可以安全地重复发送：GET、DELETE和带幂等键的POST。
+    pub(crate) fn delay(&self, attempt: u32, err: &ClientError, idempotent: bool) -> Option<Duration> {
+        if attempt >= self.max_retries {
+            return None;
+        }
+        let retryable = match err {
+            // 同一个键的第一次请求还在处理，稍后重试可以拿到它的响应
+            ClientError::Status { status: 409, .. } => idempotent,
+            ClientError::Http(err) if err.is_timeout() => idempotent,
+            err => err.is_retryable(),
+        };
+        if !retryable {
+            return None;
+        }
+        if let ClientError::RateLimited {
+            retry_after: Some(retry_after),
+            ..
+        } = err
+        {
+            // 如按日计算的配额，要等很久才会恢复，重试没有意义
+            return (*retry_after <= self.max_backoff).then_some(*retry_after);
+        }
+        let backoff = self.initial_backoff.saturating_mul(1 << attempt.min(16)).min(self.max_backoff);
+        if !self.jitter {
+            return Some(backoff);
+        }
+        let nanos = backoff.as_nanos() as u64;
+        Some(Duration::from_nanos(random() % nanos.saturating_add(1)))
+    }
+}
+
+fn random() -> u64 {
+    let mut bytes = [0u8; 8];
+    // 取不到系统随机数时退化为不抖动
+    if getrandom::getrandom(&mut bytes).is_err() {
+        return u64::MAX;
+    }
+    u64::from_le_bytes(bytes)
+}
+
+/// 一次调用的幂等键，128位随机数
+pub(crate) fn idempotency_key() -> String {
+    let mut bytes = [0u8; 16];
+    if getrandom::getrandom(&mut bytes).is_err() {
+        // 没有系统随机数时用时间和进程号，只要求同一客户端内不重复
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+        bytes[..8].copy_from_slice(&(now.as_nanos() as u64).to_le_bytes());
+        bytes[8..12].copy_from_slice(&std::process::id().to_le_bytes());
+    }
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
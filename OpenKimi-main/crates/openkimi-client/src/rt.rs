@@ -0,0 +1,70 @@
+//! 原生平台与wasm32的差异
+//!
+//! 原生平台上用tokio等待，future和流都是`Send`的；wasm32上reqwest通过浏览器的fetch发送请求，
+//! 响应体来自Web Streams，不能跨线程，等待用JS的`setTimeout`，计时用`performance.now()`。
+
+use std::time::Duration;
+
+use futures_util::stream::Stream;
+#[cfg(not(target_arch = "wasm32"))]
+use futures_util::stream::StreamExt;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use std::time::{Instant, SystemTime};
+#[cfg(target_arch = "wasm32")]
+pub(crate) use web_time::{Instant, SystemTime};
+
+/// [`crate::Transport`]返回的future，wasm32上不要求`Send`
+#[cfg(not(target_arch = "wasm32"))]
+pub type BoxFuture<'a, T> = futures_util::future::BoxFuture<'a, T>;
+#[cfg(target_arch = "wasm32")]
+pub type BoxFuture<'a, T> = futures_util::future::LocalBoxFuture<'a, T>;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) type BoxStream<'a, T> = futures_util::stream::BoxStream<'a, T>;
+#[cfg(target_arch = "wasm32")]
+pub(crate) type BoxStream<'a, T> = futures_util::stream::LocalBoxStream<'a, T>;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn boxed<'a, S>(stream: S) -> BoxStream<'a, S::Item>
+where
+    S: Stream + Send + 'a,
+{
+    stream.boxed()
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn boxed<'a, S>(stream: S) -> BoxStream<'a, S::Item>
+where
+    S: Stream + 'a,
+{
+    Box::pin(stream)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await;
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) async fn sleep(duration: Duration) {
+    use wasm_bindgen::JsCast;
+
+    let millis = duration.as_millis().min(i32::MAX as u128) as i32;
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        // 浏览器页面、Web Worker和Electron渲染进程的全局对象上都有`setTimeout`
+        let global = js_sys::global();
+        let set_timeout = js_sys::Reflect::get(&global, &"setTimeout".into())
+            .ok()
+            .and_then(|function| function.dyn_into::<js_sys::Function>().ok());
+        match set_timeout {
+            Some(set_timeout) => {
+                let _ = set_timeout.call2(&global, &resolve, &millis.into());
+            }
+            None => {
+                let _ = resolve.call0(&global);
+            }
+        }
+    });
+    let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+}
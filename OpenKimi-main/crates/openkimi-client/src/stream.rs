@@ -0,0 +1,58 @@
+//! 流式响应的解析，异步和阻塞接口共用
+
+use crate::error::{ClientError, ErrorDetail};
+use crate::types::ChatCompletionChunk;
+
+/// 把`text/event-stream`按行拆成事件的`data`
+#[derive(Debug, Default)]
+pub(crate) struct SseDecoder {
+    /// 按字节缓冲，避免多字节字符被分块截断
+    buffer: Vec<u8>,
+    data: Vec<String>,
+}
+
+impl SseDecoder {
+    fn feed_line(&mut self, line: &str) -> Option<String> {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.is_empty() {
+            return (!self.data.is_empty()).then(|| std::mem::take(&mut self.data).join("\n"));
+        }
+        if let Some(value) = line.strip_prefix("data:") {
+            self.data.push(value.strip_prefix(' ').unwrap_or(value).to_string());
+        }
+        // event、id和保活的注释行都用不到
+        None
+    }
+
+    /// 追加一块数据，返回其中已完整的事件
+    pub(crate) fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(newline) = self.buffer.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=newline).collect();
+            events.extend(self.feed_line(&String::from_utf8_lossy(&line[..newline])));
+        }
+        events
+    }
+
+    /// 连接结束时处理剩余的内容
+    pub(crate) fn finish(&mut self) -> Option<String> {
+        let rest = String::from_utf8_lossy(&std::mem::take(&mut self.buffer)).into_owned();
+        self.feed_line(&rest).or_else(|| self.feed_line(""))
+    }
+}
+
+/// 解析一个事件，`[DONE]`返回`None`；服务端在流中发送的错误转为`ClientError::Stream`
+pub(crate) fn parse_event(data: &str) -> Option<Result<ChatCompletionChunk, ClientError>> {
+    if data.trim() == "[DONE]" {
+        return None;
+    }
+    let value: serde_json::Value = match serde_json::from_str(data) {
+        Ok(value) => value,
+        Err(err) => return Some(Err(ClientError::Decode(format!("{}: {}", err, data)))),
+    };
+    if value.get("error").is_some() {
+        return Some(Err(ClientError::Stream(ErrorDetail::parse(data))));
+    }
+    Some(serde_json::from_value(value).map_err(|e| ClientError::Decode(format!("{}: {}", e, data))))
+}
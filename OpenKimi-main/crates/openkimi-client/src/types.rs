@@ -0,0 +1,385 @@
+//! 请求与响应结构
+//!
+//! 与服务端一样只声明常用的字段，`tools`、`response_format`、`fallbacks`等其余参数放在`extra`中原样发送，
+//! 响应中未声明的字段也保留在`extra`中。
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// 消息内容：纯文本，或由多个内容片段组成（如图文混合）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<Value>),
+}
+
+/// 对话消息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    #[serde(default)]
+    pub content: Option<MessageContent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// `tool_calls`、`tool_call_id`等
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl ChatMessage {
+    pub fn new(role: impl Into<String>, content: impl Into<String>) -> ChatMessage {
+        ChatMessage {
+            role: role.into(),
+            content: Some(MessageContent::Text(content.into())),
+            name: None,
+            extra: Map::new(),
+        }
+    }
+
+    pub fn system(content: impl Into<String>) -> ChatMessage {
+        ChatMessage::new("system", content)
+    }
+
+    pub fn user(content: impl Into<String>) -> ChatMessage {
+        ChatMessage::new("user", content)
+    }
+
+    pub fn assistant(content: impl Into<String>) -> ChatMessage {
+        ChatMessage::new("assistant", content)
+    }
+
+    /// assistant消息中的工具调用
+    pub fn tool_calls(&self) -> Vec<ToolCall> {
+        self.extra
+            .get("tool_calls")
+            .and_then(|calls| serde_json::from_value(calls.clone()).ok())
+            .unwrap_or_default()
+    }
+
+    /// 消息的文本内容，多段内容只保留其中的文本片段
+    pub fn text(&self) -> String {
+        match &self.content {
+            Some(MessageContent::Text(text)) => text.clone(),
+            Some(MessageContent::Parts(parts)) => parts
+                .iter()
+                .filter_map(|part| part.get("text").and_then(Value::as_str))
+                .collect::<Vec<_>>()
+                .join(""),
+            None => String::new(),
+        }
+    }
+}
+
+/// 模型要求调用的函数，`arguments`为JSON字符串
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: String,
+}
+
+impl FunctionCall {
+    /// 把参数解析为JSON，模型给出的参数不是合法JSON时返回错误
+    pub fn parse_arguments(&self) -> serde_json::Result<Value> {
+        if self.arguments.trim().is_empty() {
+            return Ok(Value::Object(Map::new()));
+        }
+        serde_json::from_str(&self.arguments)
+    }
+}
+
+/// assistant消息中的一个工具调用
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    /// 目前只有`function`
+    #[serde(rename = "type", default = "default_tool_type")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+fn default_tool_type() -> String {
+    "function".to_string()
+}
+
+/// `POST /v1/chat/completions`请求，`stream`由调用的方法决定
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChatCompletionRequest {
+    /// 为空时使用服务端的默认模型
+    #[serde(default)]
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl ChatCompletionRequest {
+    pub fn new(model: impl Into<String>, messages: Vec<ChatMessage>) -> ChatCompletionRequest {
+        ChatCompletionRequest {
+            model: model.into(),
+            messages,
+            ..ChatCompletionRequest::default()
+        }
+    }
+
+    /// 设置其他参数，如`session_id`、`response_format`
+    pub fn with(mut self, key: impl Into<String>, value: impl Into<Value>) -> ChatCompletionRequest {
+        self.extra.insert(key.into(), value.into());
+        self
+    }
+}
+
+/// Token用量
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Usage {
+    #[serde(default)]
+    pub prompt_tokens: u32,
+    #[serde(default)]
+    pub completion_tokens: u32,
+    #[serde(default)]
+    pub total_tokens: u32,
+}
+
+/// 单个候选回复
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatChoice {
+    pub index: u32,
+    pub message: ChatMessage,
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// `POST /v1/chat/completions`响应
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatCompletionResponse {
+    pub id: String,
+    #[serde(default)]
+    pub object: String,
+    #[serde(default)]
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<ChatChoice>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl ChatCompletionResponse {
+    /// 第一个候选回复的文本
+    pub fn text(&self) -> String {
+        self.choices.first().map(|choice| choice.message.text()).unwrap_or_default()
+    }
+}
+
+/// 函数调用的增量，名称和参数按片段给出
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FunctionCallDelta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<String>,
+}
+
+/// 工具调用的增量，`index`相同的各段依次拼接为一个调用；`id`只在第一段中给出
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ToolCallDelta {
+    #[serde(default)]
+    pub index: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(default)]
+    pub function: FunctionCallDelta,
+}
+
+/// 流式响应中一个候选回复的增量
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChatDelta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCallDelta>>,
+    /// 其他增量字段
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatChunkChoice {
+    #[serde(default)]
+    pub index: u32,
+    #[serde(default)]
+    pub delta: ChatDelta,
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+}
+
+/// 流式响应中的一块，`object`为`chat.completion.chunk`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatCompletionChunk {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub object: String,
+    #[serde(default)]
+    pub created: u64,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default)]
+    pub choices: Vec<ChatChunkChoice>,
+    /// 请求中`stream_options.include_usage`为`true`时在最后一块中给出
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl ChatCompletionChunk {
+    /// 第一个候选回复新增的文本
+    pub fn text(&self) -> &str {
+        self.choices.first().and_then(|choice| choice.delta.content.as_deref()).unwrap_or_default()
+    }
+}
+
+/// 模型信息；`kind`之后的字段由OpenKimi服务端提供，其他服务端不返回
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Model {
+    pub id: String,
+    #[serde(default)]
+    pub object: String,
+    #[serde(default)]
+    pub created: u64,
+    #[serde(default)]
+    pub owned_by: String,
+    /// `chat`或`embedding`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    /// 上下文窗口（token）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<u32>,
+    /// 接受的输入，如`text`、`image`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub modalities: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pricing: Option<ModelPricing>,
+}
+
+/// 模型的单价，按每百万token计
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelPricing {
+    pub prompt: f64,
+    pub completion: f64,
+    #[serde(default)]
+    pub currency: String,
+}
+
+/// `GET /v1/models`响应
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelList {
+    #[serde(default)]
+    pub object: String,
+    pub data: Vec<Model>,
+}
+
+/// 嵌入输入：单个字符串、字符串数组或token数组
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum EmbeddingInput {
+    Text(String),
+    Texts(Vec<String>),
+    Tokens(Vec<u32>),
+    TokenBatches(Vec<Vec<u32>>),
+}
+
+impl From<&str> for EmbeddingInput {
+    fn from(text: &str) -> EmbeddingInput {
+        EmbeddingInput::Text(text.to_string())
+    }
+}
+
+impl From<String> for EmbeddingInput {
+    fn from(text: String) -> EmbeddingInput {
+        EmbeddingInput::Text(text)
+    }
+}
+
+impl From<Vec<String>> for EmbeddingInput {
+    fn from(texts: Vec<String>) -> EmbeddingInput {
+        EmbeddingInput::Texts(texts)
+    }
+}
+
+/// `POST /v1/embeddings`请求
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbeddingRequest {
+    /// 为空时使用服务端的默认嵌入模型
+    #[serde(default)]
+    pub model: String,
+    pub input: EmbeddingInput,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl EmbeddingRequest {
+    pub fn new(model: impl Into<String>, input: impl Into<EmbeddingInput>) -> EmbeddingRequest {
+        EmbeddingRequest {
+            model: model.into(),
+            input: input.into(),
+            extra: Map::new(),
+        }
+    }
+}
+
+/// 单个嵌入向量；`encoding_format=base64`时为字符串
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Embedding {
+    #[serde(default)]
+    pub object: String,
+    pub index: u32,
+    pub embedding: Value,
+}
+
+impl Embedding {
+    /// 浮点数形式的向量，`base64`编码时为`None`
+    pub fn vector(&self) -> Option<Vec<f32>> {
+        self.embedding
+            .as_array()?
+            .iter()
+            .map(|value| value.as_f64().map(|value| value as f32))
+            .collect()
+    }
+}
+
+/// 嵌入请求的token用量
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EmbeddingUsage {
+    #[serde(default)]
+    pub prompt_tokens: u32,
+    #[serde(default)]
+    pub total_tokens: u32,
+}
+
+/// `POST /v1/embeddings`响应
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbeddingResponse {
+    #[serde(default)]
+    pub object: String,
+    pub data: Vec<Embedding>,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<EmbeddingUsage>,
+}
@@ -0,0 +1,358 @@
+//! 录制与回放请求的传输层，供下游在测试中离线使用客户端
+//!
+//! [`Vcr`]把真实的HTTP交互保存到磁带文件（JSON），之后按方法、路径和请求体从磁带中回放，
+//! 不访问网络，结果是确定的。同时实现了异步和阻塞客户端的传输层：
+//!
+//! ```no_run
+//! # fn main() -> Result<(), openkimi_client::ClientError> {
+//! use openkimi_client::vcr::{Vcr, VcrMode};
+//! use openkimi_client::{Client, ClientConfig};
+//!
+//! let vcr = Vcr::open("tests/cassettes/chat.json", VcrMode::Auto)?.scrub("sk-real-secret", "<API_KEY>");
+//! let client = Client::new(ClientConfig::from_env())?.with_transport(vcr);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! 写入磁带前脱敏：`Authorization`、`Cookie`等请求头和`Set-Cookie`响应头替换为`<REDACTED>`，
+//! [`Vcr::scrub`]登记的字符串在路径、请求体和响应体中都会被替换。回放时请求先经过同样的脱敏再匹配。
+//! 流式响应中被拆到几个事件里的字符串无法整体替换，需要时检查磁带。
+//! 相同的请求按录制的顺序依次回放，如先是429再成功的重试。流式响应录制时读完整个响应体，回放时一次返回。
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::ClientError;
+use crate::intercept::Transport;
+use crate::rt::BoxFuture;
+
+/// 磁带文件的格式版本
+const CASSETTE_VERSION: u32 = 1;
+
+const REDACTED: &str = "<REDACTED>";
+
+/// 始终脱敏的请求头和响应头
+const SENSITIVE_HEADERS: &[&str] = &["authorization", "proxy-authorization", "cookie", "set-cookie", "x-api-key"];
+
+/// 不写入磁带的响应头，回放时响应体已经完整
+const SKIPPED_RESPONSE_HEADERS: &[&str] = &["content-length", "transfer-encoding", "connection"];
+
+/// 录制还是回放
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VcrMode {
+    /// 访问网络并重新录制，覆盖已有的磁带
+    Record,
+    /// 只从磁带回放，磁带中没有的请求返回错误
+    Replay,
+    /// 磁带文件存在时回放，否则录制
+    Auto,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    /// 路径和查询参数，不含主机，换一个服务端地址也能回放
+    pub url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// 完整的响应体，流式响应为原始的SSE文本
+    #[serde(default)]
+    pub body: String,
+}
+
+/// 一次请求和响应
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Cassette {
+    version: u32,
+    interactions: Vec<Interaction>,
+}
+
+impl RecordedRequest {
+    /// 请求体都是JSON时按值比较，不受键的顺序和空白影响
+    fn matches(&self, other: &RecordedRequest) -> bool {
+        let same_body = match (&self.body, &other.body) {
+            (Some(a), Some(b)) => match (serde_json::from_str::<Value>(a), serde_json::from_str::<Value>(b)) {
+                (Ok(a), Ok(b)) => a == b,
+                _ => a == b,
+            },
+            (a, b) => a == b,
+        };
+        self.method.eq_ignore_ascii_case(&other.method) && self.url == other.url && same_body
+    }
+}
+
+struct State {
+    path: PathBuf,
+    recording: bool,
+    /// 替换为占位符的字符串
+    secrets: Vec<(String, String)>,
+    /// 额外脱敏的请求头，小写
+    headers: Vec<String>,
+    cassette: Cassette,
+    /// 回放时各条交互是否已经用过
+    used: Vec<bool>,
+}
+
+impl State {
+    fn scrub(&self, text: &str) -> String {
+        self.secrets
+            .iter()
+            .fold(text.to_string(), |text, (secret, placeholder)| text.replace(secret.as_str(), placeholder))
+    }
+
+    fn scrub_headers(&self, headers: &HeaderMap, skipped: &[&str]) -> BTreeMap<String, String> {
+        headers
+            .iter()
+            .filter(|(name, _)| !skipped.contains(&name.as_str()))
+            .map(|(name, value)| {
+                let name = name.as_str();
+                let value = if SENSITIVE_HEADERS.contains(&name) || self.headers.iter().any(|header| header == name) {
+                    REDACTED.to_string()
+                } else {
+                    self.scrub(&String::from_utf8_lossy(value.as_bytes()))
+                };
+                (name.to_string(), value)
+            })
+            .collect()
+    }
+
+    fn describe(&self, method: &reqwest::Method, url: &reqwest::Url, headers: &HeaderMap, body: Option<&[u8]>) -> RecordedRequest {
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        RecordedRequest {
+            method: method.to_string(),
+            url: self.scrub(&path),
+            headers: self.scrub_headers(headers, &[]),
+            body: body.map(|body| self.scrub(&String::from_utf8_lossy(body))),
+        }
+    }
+
+    /// 第一条还没有用过的匹配交互
+    fn replay(&mut self, request: &RecordedRequest) -> Result<RecordedResponse, ClientError> {
+        let index = self
+            .cassette
+            .interactions
+            .iter()
+            .enumerate()
+            .position(|(index, interaction)| !self.used[index] && interaction.request.matches(request))
+            .ok_or_else(|| {
+                ClientError::Config(format!(
+                    "磁带 {} 中没有匹配 {} {} 的请求，需要重新录制",
+                    self.path.display(),
+                    request.method,
+                    request.url
+                ))
+            })?;
+        self.used[index] = true;
+        Ok(self.cassette.interactions[index].response.clone())
+    }
+
+    /// 追加一条交互并立即写回磁带
+    fn record(&mut self, request: RecordedRequest, status: u16, headers: &HeaderMap, body: &[u8]) -> Result<(), ClientError> {
+        let response = RecordedResponse {
+            status,
+            headers: self.scrub_headers(headers, SKIPPED_RESPONSE_HEADERS),
+            body: self.scrub(&String::from_utf8_lossy(body)),
+        };
+        self.cassette.interactions.push(Interaction { request, response });
+        let save = || -> std::io::Result<()> {
+            if let Some(parent) = self.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&self.path, serde_json::to_vec_pretty(&self.cassette)?)
+        };
+        save().map_err(|e| ClientError::Config(format!("写入磁带 {} 失败: {}", self.path.display(), e)))
+    }
+}
+
+/// 由录制的响应构造响应
+fn to_http(response: RecordedResponse) -> Result<http::Response<Bytes>, ClientError> {
+    let mut builder = http::Response::builder().status(response.status);
+    for (name, value) in &response.headers {
+        builder = builder.header(name, value);
+    }
+    builder
+        .body(Bytes::from(response.body))
+        .map_err(|e| ClientError::Decode(format!("磁带中的响应无效: {}", e)))
+}
+
+/// 录制或回放请求的传输层，克隆后共用同一盘磁带
+#[derive(Clone)]
+pub struct Vcr {
+    state: Arc<Mutex<State>>,
+    http: reqwest::Client,
+    #[cfg(feature = "blocking")]
+    blocking: Arc<std::sync::OnceLock<reqwest::blocking::Client>>,
+}
+
+impl std::fmt::Debug for Vcr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.lock();
+        f.debug_struct("Vcr")
+            .field("path", &state.path)
+            .field("recording", &state.recording)
+            .field("interactions", &state.cassette.interactions.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Vcr {
+    /// 打开磁带；回放时磁带必须存在，录制时从空磁带开始
+    pub fn open(path: impl AsRef<Path>, mode: VcrMode) -> Result<Vcr, ClientError> {
+        let path = path.as_ref().to_path_buf();
+        let recording = match mode {
+            VcrMode::Record => true,
+            VcrMode::Replay => false,
+            VcrMode::Auto => !path.exists(),
+        };
+        let cassette = if recording {
+            Cassette {
+                version: CASSETTE_VERSION,
+                interactions: Vec::new(),
+            }
+        } else {
+            let content =
+                fs::read(&path).map_err(|e| ClientError::Config(format!("读取磁带 {} 失败: {}", path.display(), e)))?;
+            let cassette: Cassette = serde_json::from_slice(&content)
+                .map_err(|e| ClientError::Config(format!("解析磁带 {} 失败: {}", path.display(), e)))?;
+            if cassette.version > CASSETTE_VERSION {
+                return Err(ClientError::Config(format!("磁带格式 v{} 比当前客户端支持的更新", cassette.version)));
+            }
+            cassette
+        };
+        let used = vec![false; cassette.interactions.len()];
+        Ok(Vcr {
+            state: Arc::new(Mutex::new(State {
+                path,
+                recording,
+                secrets: Vec::new(),
+                headers: Vec::new(),
+                cassette,
+                used,
+            })),
+            http: reqwest::Client::builder().build()?,
+            #[cfg(feature = "blocking")]
+            blocking: Arc::default(),
+        })
+    }
+
+    /// 在写入磁带和匹配之前把`secret`替换为`placeholder`，如令牌、用户名或内部地址
+    pub fn scrub(self, secret: impl Into<String>, placeholder: impl Into<String>) -> Vcr {
+        let secret = secret.into();
+        if !secret.is_empty() {
+            self.lock().secrets.push((secret, placeholder.into()));
+        }
+        self
+    }
+
+    /// 额外脱敏的请求头或响应头，如网关的签名
+    pub fn scrub_header(self, name: &str) -> Vcr {
+        self.lock().headers.push(name.to_ascii_lowercase());
+        self
+    }
+
+    /// 是否在录制
+    pub fn is_recording(&self) -> bool {
+        self.lock().recording
+    }
+
+    /// 磁带中的全部交互
+    pub fn interactions(&self) -> Vec<Interaction> {
+        self.lock().cassette.interactions.clone()
+    }
+
+    /// 回放时还没有用到的交互数，测试结束时可以检查是否少发了请求
+    pub fn remaining(&self) -> usize {
+        self.lock().used.iter().filter(|used| !**used).count()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Transport for Vcr {
+    fn send(&self, request: reqwest::Request) -> BoxFuture<'static, Result<reqwest::Response, ClientError>> {
+        let vcr = self.clone();
+        Box::pin(async move {
+            let (recorded, recording) = {
+                let state = vcr.lock();
+                let body = request.body().and_then(reqwest::Body::as_bytes);
+                let recorded = state.describe(request.method(), request.url(), request.headers(), body);
+                (recorded, state.recording)
+            };
+            if !recording {
+                let response = vcr.lock().replay(&recorded)?;
+                return Ok(reqwest::Response::from(to_http(response)?));
+            }
+
+            let response = vcr.http.execute(request).await?;
+            let status = response.status();
+            let headers = response.headers().clone();
+            let body = response.bytes().await?;
+            vcr.lock().record(recorded, status.as_u16(), &headers, &body)?;
+            let mut replayed = http::Response::new(body);
+            *replayed.status_mut() = status;
+            *replayed.headers_mut() = headers;
+            Ok(reqwest::Response::from(replayed))
+        })
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl crate::blocking::Transport for Vcr {
+    fn send(&self, request: reqwest::blocking::Request) -> Result<reqwest::blocking::Response, ClientError> {
+        let (recorded, recording) = {
+            let state = self.lock();
+            let body = request.body().and_then(reqwest::blocking::Body::as_bytes);
+            let recorded = state.describe(request.method(), request.url(), request.headers(), body);
+            (recorded, state.recording)
+        };
+        if !recording {
+            let response = self.lock().replay(&recorded)?;
+            return Ok(reqwest::blocking::Response::from(to_http(response)?));
+        }
+
+        // 阻塞客户端不能在异步运行时中创建，第一次录制时再创建
+        let http = match self.blocking.get() {
+            Some(http) => http,
+            None => {
+                let http = reqwest::blocking::Client::builder().timeout(None).build()?;
+                self.blocking.get_or_init(|| http)
+            }
+        };
+        let response = http.execute(request)?;
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.bytes()?;
+        self.lock().record(recorded, status.as_u16(), &headers, &body)?;
+        let mut replayed = http::Response::new(body);
+        *replayed.status_mut() = status;
+        *replayed.headers_mut() = headers;
+        Ok(reqwest::blocking::Response::from(replayed))
+    }
+}
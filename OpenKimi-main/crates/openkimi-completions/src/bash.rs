@@ -0,0 +1,109 @@
+//! bash：`complete -F`注册的补全函数
+
+use crate::{ident, sh_patterns, sh_quote, value_flags, Command, Node, Value};
+
+/// 补全`value`的语句
+fn complete_value(value: &Value) -> String {
+    match value {
+        Value::Text => "COMPREPLY=()".to_string(),
+        Value::File => "compopt -o filenames 2>/dev/null; COMPREPLY=( $(compgen -f -- \"$cur\") )".to_string(),
+        Value::Dir => "compopt -o filenames 2>/dev/null; COMPREPLY=( $(compgen -d -- \"$cur\") )".to_string(),
+        Value::Choices(choices) => format!(
+            "COMPREPLY=( $(compgen -W {} -- \"$cur\") )",
+            sh_quote(&choices.join(" "))
+        ),
+    }
+}
+
+/// 补全子命令和位置参数的语句
+fn complete_words(command: &Command) -> String {
+    let mut words: Vec<&str> = command.subcommands.iter().map(|subcommand| subcommand.name.as_str()).collect();
+    let mut extra = "";
+    match &command.args {
+        Some(Value::Choices(choices)) => words.extend(choices.iter().map(String::as_str)),
+        Some(Value::File) => extra = " $(compgen -f -- \"$cur\")",
+        Some(Value::Dir) => extra = " $(compgen -d -- \"$cur\")",
+        _ => {}
+    }
+    let filenames = if extra.is_empty() {
+        ""
+    } else {
+        "compopt -o filenames 2>/dev/null; "
+    };
+    format!(
+        "{}COMPREPLY=( $(compgen -W {} -- \"$cur\"){} )",
+        filenames,
+        sh_quote(&words.join(" ")),
+        extra
+    )
+}
+
+pub(crate) fn generate(command: &Command, nodes: &[Node]) -> String {
+    let name = &command.name;
+    let function = format!("_{}", ident(name));
+    let value_flags = value_flags(nodes);
+    let mut lines = vec![
+        format!("# {}的bash补全，由 `{} completions bash` 生成", name, name),
+        format!("{}() {{", function),
+        "    local cur prev cmd word i".to_string(),
+        "    cur=\"${COMP_WORDS[COMP_CWORD]}\"".to_string(),
+        "    prev=\"${COMP_WORDS[COMP_CWORD-1]}\"".to_string(),
+        format!("    cmd={}", sh_quote(name)),
+        "    # 找出所在的子命令，跳过选项的值".to_string(),
+        "    for (( i = 1; i < COMP_CWORD; i++ )); do".to_string(),
+        "        word=\"${COMP_WORDS[i]}\"".to_string(),
+        "        case \"$cmd:$word\" in".to_string(),
+    ];
+    for node in nodes {
+        for subcommand in &node.command.subcommands {
+            lines.push(format!(
+                "            {}) cmd={} ;;",
+                sh_quote(&format!("{}:{}", node.path, subcommand.name)),
+                sh_quote(&format!("{} {}", node.path, subcommand.name))
+            ));
+        }
+    }
+    for node in nodes {
+        let names = value_flags.iter().filter(|(path, _, _)| *path == node.path).flat_map(|(_, flag, _)| &flag.names);
+        let patterns = sh_patterns(&node.path, names);
+        if !patterns.is_empty() {
+            lines.push(format!("            {}) (( i++ )) ;;", patterns));
+        }
+    }
+    lines.extend([
+        "        esac".to_string(),
+        "    done".to_string(),
+        "    case \"$cmd:$prev\" in".to_string(),
+    ]);
+    for (path, flag, value) in &value_flags {
+        lines.push(format!(
+            "        {}) {}; return ;;",
+            sh_patterns(path, &flag.names),
+            complete_value(value)
+        ));
+    }
+    lines.extend(["    esac".to_string(), "    case \"$cmd\" in".to_string()]);
+    for node in nodes {
+        let flags: Vec<&str> =
+            node.command.flags.iter().flat_map(|flag| flag.names.iter().map(String::as_str)).collect();
+        lines.extend([
+            format!("        {})", sh_quote(&node.path)),
+            "            if [[ $cur == -* ]]; then".to_string(),
+            format!(
+                "                COMPREPLY=( $(compgen -W {} -- \"$cur\") )",
+                sh_quote(&flags.join(" "))
+            ),
+            "            else".to_string(),
+            format!("                {}", complete_words(node.command)),
+            "            fi".to_string(),
+            "            ;;".to_string(),
+        ]);
+    }
+    lines.extend([
+        "    esac".to_string(),
+        "}".to_string(),
+        format!("complete -F {} {}", function, name),
+        String::new(),
+    ]);
+    lines.join("\n")
+}
@@ -0,0 +1,133 @@
+//! fish：用一个函数求出所在的子命令，每个子命令的选项和参数各一条`complete`
+
+use crate::{ident, value_flags, Command, Node, Value};
+
+/// fish的单引号字符串，`\`和`'`需要转义
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// 参数部分：补全的候选值，以及是否允许补全文件
+fn value_arguments(value: &Value) -> String {
+    match value {
+        Value::Text => "-x".to_string(),
+        Value::File => "-r -F".to_string(),
+        Value::Dir => "-x -a '(__fish_complete_directories)'".to_string(),
+        Value::Choices(choices) => format!("-x -a {}", quote(&choices.join(" "))),
+    }
+}
+
+/// 选项的各种写法：`-f`为`-s f`，`--file`为`-l file`，`-name`这样单个`-`的长选项为`-o name`
+fn flag_names(names: &[String]) -> String {
+    let names: Vec<String> = names
+        .iter()
+        .map(|name| match name.strip_prefix("--") {
+            Some(long) => format!("-l {}", long),
+            None => {
+                let short = name.trim_start_matches('-');
+                if short.chars().count() == 1 {
+                    format!("-s {}", short)
+                } else {
+                    format!("-o {}", short)
+                }
+            }
+        })
+        .collect();
+    names.join(" ")
+}
+
+fn help(help: &str) -> String {
+    if help.is_empty() {
+        String::new()
+    } else {
+        format!(" -d {}", quote(help))
+    }
+}
+
+pub(crate) fn generate(command: &Command, nodes: &[Node]) -> String {
+    let name = &command.name;
+    let function = format!("__{}_cmd", ident(name));
+    let value_flags = value_flags(nodes);
+    let mut lines = vec![
+        format!("# {}的fish补全，由 `{} completions fish` 生成", name, name),
+        "# 找出所在的子命令，跳过选项的值".to_string(),
+        format!("function {}", function),
+        "    set -l words (commandline -opc)".to_string(),
+        "    set -e words[1]".to_string(),
+        format!("    set -l cmd {}", quote(name)),
+        "    set -l skip 0".to_string(),
+        "    for word in $words".to_string(),
+        "        if test $skip = 1".to_string(),
+        "            set skip 0".to_string(),
+        "            continue".to_string(),
+        "        end".to_string(),
+        "        switch \"$cmd:$word\"".to_string(),
+    ];
+    for node in nodes {
+        for subcommand in &node.command.subcommands {
+            lines.push(format!(
+                "            case {}",
+                quote(&format!("{}:{}", node.path, subcommand.name))
+            ));
+            lines.push(format!(
+                "                set cmd {}",
+                quote(&format!("{} {}", node.path, subcommand.name))
+            ));
+        }
+    }
+    for node in nodes {
+        let names = value_flags.iter().filter(|(path, _, _)| *path == node.path).flat_map(|(_, flag, _)| &flag.names);
+        let patterns: Vec<String> = names.map(|name| quote(&format!("{}:{}", node.path, name))).collect();
+        if !patterns.is_empty() {
+            lines.push(format!("            case {}", patterns.join(" ")));
+            lines.push("                set skip 1".to_string());
+        }
+    }
+    lines.extend([
+        "        end".to_string(),
+        "    end".to_string(),
+        "    echo $cmd".to_string(),
+        "end".to_string(),
+        String::new(),
+        format!("complete -c {} -f", name),
+    ]);
+    for node in nodes {
+        let condition = format!("-n {}", quote(&format!("test ({}) = \"{}\"", function, node.path)));
+        for subcommand in &node.command.subcommands {
+            lines.push(format!(
+                "complete -c {} {} -a {}{}",
+                name,
+                condition,
+                quote(&subcommand.name),
+                help(&subcommand.help)
+            ));
+        }
+        match &node.command.args {
+            Some(Value::File) => lines.push(format!("complete -c {} {} -F", name, condition)),
+            Some(Value::Dir) => lines.push(format!(
+                "complete -c {} {} -a '(__fish_complete_directories)'",
+                name, condition
+            )),
+            Some(Value::Choices(choices)) => lines.push(format!(
+                "complete -c {} {} -a {}",
+                name,
+                condition,
+                quote(&choices.join(" "))
+            )),
+            Some(Value::Text) | None => {}
+        }
+        for flag in &node.command.flags {
+            let value = flag.value.as_ref().map(|value| format!(" {}", value_arguments(value))).unwrap_or_default();
+            lines.push(format!(
+                "complete -c {} {} {}{}{}",
+                name,
+                condition,
+                flag_names(&flag.names),
+                value,
+                help(&flag.help)
+            ));
+        }
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
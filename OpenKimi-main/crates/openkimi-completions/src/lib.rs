@@ -0,0 +1,197 @@
+//! 命令行补全脚本的生成
+//!
+//! 用[`Command`]描述程序的子命令、选项和位置参数，[`generate`]生成bash、zsh、fish和PowerShell的补全脚本。
+//! 各个shell的脚本结构相同：从已输入的词中找出所在的子命令（跳过需要值的选项后面的词），前一个词是需要值的
+//! 选项时补全它的值（文件、目录或候选值），否则补全子命令和位置参数，输入`-`开头时补全选项。
+
+mod bash;
+mod fish;
+mod powershell;
+mod zsh;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// 选项或位置参数的值
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// 任意文本，不补全
+    Text,
+    File,
+    Dir,
+    /// 从候选值中选择
+    Choices(Vec<String>),
+}
+
+impl Value {
+    pub fn choices(choices: &[&str]) -> Value {
+        Value::Choices(choices.iter().map(|choice| choice.to_string()).collect())
+    }
+}
+
+/// 一个选项，如`-f, --file <文件>`
+#[derive(Debug, Clone)]
+pub struct Flag {
+    /// 全部写法，如`["-f", "--file"]`
+    pub names: Vec<String>,
+    /// 需要值时为值的类型
+    pub value: Option<Value>,
+    pub help: String,
+}
+
+impl Flag {
+    pub fn new(names: &[&str], help: &str) -> Flag {
+        Flag {
+            names: names.iter().map(|name| name.to_string()).collect(),
+            value: None,
+            help: help.to_string(),
+        }
+    }
+
+    /// 需要值的选项
+    pub fn value(mut self, value: Value) -> Flag {
+        self.value = Some(value);
+        self
+    }
+}
+
+/// 程序或子命令
+#[derive(Debug, Clone)]
+pub struct Command {
+    pub name: String,
+    pub help: String,
+    pub flags: Vec<Flag>,
+    pub subcommands: Vec<Command>,
+    /// 位置参数的类型，没有位置参数时为`None`
+    pub args: Option<Value>,
+}
+
+impl Command {
+    pub fn new(name: &str, help: &str) -> Command {
+        Command {
+            name: name.to_string(),
+            help: help.to_string(),
+            flags: Vec::new(),
+            subcommands: Vec::new(),
+            args: None,
+        }
+    }
+
+    pub fn flag(mut self, flag: Flag) -> Command {
+        self.flags.push(flag);
+        self
+    }
+
+    pub fn flags(mut self, flags: impl IntoIterator<Item = Flag>) -> Command {
+        self.flags.extend(flags);
+        self
+    }
+
+    pub fn subcommand(mut self, command: Command) -> Command {
+        self.subcommands.push(command);
+        self
+    }
+
+    pub fn args(mut self, value: Value) -> Command {
+        self.args = Some(value);
+        self
+    }
+}
+
+/// 支持的shell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    PowerShell,
+}
+
+impl Shell {
+    pub const NAMES: &'static [&'static str] = &["bash", "zsh", "fish", "powershell"];
+}
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Shell, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            "powershell" | "pwsh" => Ok(Shell::PowerShell),
+            _ => Err(format!("不支持的shell: {}，可选: {}", name, Shell::NAMES.join(", "))),
+        }
+    }
+}
+
+impl fmt::Display for Shell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Shell::Bash => "bash",
+            Shell::Zsh => "zsh",
+            Shell::Fish => "fish",
+            Shell::PowerShell => "powershell",
+        })
+    }
+}
+
+/// 展开后的一个子命令，`path`为从程序名开始以空格连接的各级名称，如`kimi history`
+struct Node<'a> {
+    path: String,
+    command: &'a Command,
+}
+
+/// 按先序展开命令树
+fn flatten(command: &Command) -> Vec<Node<'_>> {
+    fn walk<'a>(path: String, command: &'a Command, nodes: &mut Vec<Node<'a>>) {
+        nodes.push(Node {
+            path: path.clone(),
+            command,
+        });
+        for subcommand in &command.subcommands {
+            walk(format!("{} {}", path, subcommand.name), subcommand, nodes);
+        }
+    }
+    let mut nodes = Vec::new();
+    walk(command.name.clone(), command, &mut nodes);
+    nodes
+}
+
+/// 需要值的选项，`(子命令, 选项, 值)`
+fn value_flags<'a>(nodes: &'a [Node<'a>]) -> Vec<(&'a str, &'a Flag, &'a Value)> {
+    nodes
+        .iter()
+        .flat_map(|node| {
+            let flags = node.command.flags.iter();
+            flags.filter_map(move |flag| Some((node.path.as_str(), flag, flag.value.as_ref()?)))
+        })
+        .collect()
+}
+
+/// `case`的模式：子命令和选项的各种写法，如`'kimi:-f'|'kimi:--file'`
+fn sh_patterns<'a>(path: &str, names: impl IntoIterator<Item = &'a String>) -> String {
+    let patterns: Vec<String> = names.into_iter().map(|name| sh_quote(&format!("{}:{}", path, name))).collect();
+    patterns.join("|")
+}
+
+/// 程序名中不能用于函数名的字符换成`_`，如`build-client`为`build_client`
+fn ident(name: &str) -> String {
+    name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect()
+}
+
+/// 用单引号包围，内部的单引号按sh的规则转义
+fn sh_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+/// 生成补全脚本
+pub fn generate(shell: Shell, command: &Command) -> String {
+    let nodes = flatten(command);
+    match shell {
+        Shell::Bash => bash::generate(command, &nodes),
+        Shell::Zsh => zsh::generate(command, &nodes),
+        Shell::Fish => fish::generate(command, &nodes),
+        Shell::PowerShell => powershell::generate(command, &nodes),
+    }
+}
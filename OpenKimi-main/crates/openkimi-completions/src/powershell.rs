@@ -0,0 +1,131 @@
+//! PowerShell：`Register-ArgumentCompleter -Native`，补全文件时不返回结果，由PowerShell补全路径
+
+use crate::{Command, Node, Value};
+
+/// PowerShell的单引号字符串，`'`写两次
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+/// 一个`CompletionResult`，提示不能为空
+fn result(text: &str, kind: &str, help: &str) -> String {
+    let help = if help.is_empty() { text } else { help };
+    format!(
+        "[System.Management.Automation.CompletionResult]::new({}, {}, '{}', {})",
+        quote(text),
+        quote(text),
+        kind,
+        quote(help)
+    )
+}
+
+/// 补全子命令和位置参数的语句；位置参数是文件或目录时，没有匹配的子命令就交给PowerShell补全路径
+fn complete_words(command: &Command) -> Vec<String> {
+    let mut lines: Vec<String> = command
+        .subcommands
+        .iter()
+        .map(|subcommand| {
+            format!(
+                "                {}",
+                result(&subcommand.name, "ParameterValue", &subcommand.help)
+            )
+        })
+        .collect();
+    if let Some(Value::Choices(choices)) = &command.args {
+        lines.extend(choices.iter().map(|choice| format!("                {}", result(choice, "ParameterValue", ""))));
+    }
+    lines
+}
+
+pub(crate) fn generate(command: &Command, nodes: &[Node]) -> String {
+    let name = &command.name;
+    let mut lines = vec![
+        format!("# {}的PowerShell补全，由 `{} completions powershell` 生成", name, name),
+        format!(
+            "Register-ArgumentCompleter -Native -CommandName {} -ScriptBlock {{",
+            quote(name)
+        ),
+        "    param($wordToComplete, $commandAst, $cursorPosition)".to_string(),
+        "    # 光标之前已经输入完的词".to_string(),
+        "    $words = @($commandAst.CommandElements |".to_string(),
+        "        Where-Object { $_.Extent.EndOffset -lt $cursorPosition } |".to_string(),
+        "        ForEach-Object { $_.Extent.Text })".to_string(),
+        format!("    $cmd = {}", quote(name)),
+        "    # 找出所在的子命令，跳过选项的值".to_string(),
+        "    for ($i = 1; $i -lt $words.Count; $i++) {".to_string(),
+        "        switch -CaseSensitive -Exact ($cmd + ':' + $words[$i]) {".to_string(),
+    ];
+    for node in nodes {
+        for subcommand in &node.command.subcommands {
+            lines.push(format!(
+                "            {} {{ $cmd = {} }}",
+                quote(&format!("{}:{}", node.path, subcommand.name)),
+                quote(&format!("{} {}", node.path, subcommand.name))
+            ));
+        }
+        for flag in node.command.flags.iter().filter(|flag| flag.value.is_some()) {
+            for flag_name in &flag.names {
+                lines.push(format!(
+                    "            {} {{ $i++ }}",
+                    quote(&format!("{}:{}", node.path, flag_name))
+                ));
+            }
+        }
+    }
+    lines.extend([
+        "        }".to_string(),
+        "    }".to_string(),
+        "    $prev = $words[-1]".to_string(),
+        "    $results = switch -CaseSensitive -Exact ($cmd + ':' + $prev) {".to_string(),
+    ]);
+    for node in nodes {
+        for flag in &node.command.flags {
+            let Some(value) = &flag.value else {
+                continue;
+            };
+            let body = match value {
+                Value::Choices(choices) => {
+                    let choices: Vec<String> =
+                        choices.iter().map(|choice| result(choice, "ParameterValue", "")).collect();
+                    choices.join("; ")
+                }
+                // 文本、文件和目录都交给PowerShell
+                _ => "return".to_string(),
+            };
+            for flag_name in &flag.names {
+                lines.push(format!(
+                    "        {} {{ {} }}",
+                    quote(&format!("{}:{}", node.path, flag_name)),
+                    body
+                ));
+            }
+        }
+    }
+    lines.push("        default {".to_string());
+    lines.push("            switch -CaseSensitive -Exact ($cmd) {".to_string());
+    for node in nodes {
+        lines.push(format!("                {} {{", quote(&node.path)));
+        lines.push("                    if ($wordToComplete.StartsWith('-')) {".to_string());
+        for flag in &node.command.flags {
+            for flag_name in &flag.names {
+                lines.push(format!(
+                    "                        {}",
+                    result(flag_name, "ParameterName", &flag.help)
+                ));
+            }
+        }
+        lines.push("                    } else {".to_string());
+        lines.extend(complete_words(node.command).into_iter().map(|line| format!("        {}", line)));
+        lines.push("                    }".to_string());
+        lines.push("                }".to_string());
+    }
+    lines.extend([
+        "            }".to_string(),
+        "        }".to_string(),
+        "    }".to_string(),
+        "    $results | Where-Object { $_.CompletionText.StartsWith($wordToComplete) }".to_string(),
+        "}".to_string(),
+        String::new(),
+    ]);
+    lines.join("\n")
+}
@@ -0,0 +1,126 @@
+//! zsh：`#compdef`补全函数，可以放进`$fpath`，也可以直接`source`
+
+use crate::{ident, sh_patterns, sh_quote, value_flags, Command, Node, Value};
+
+/// `_describe`的一项，名称中的`:`需要转义
+fn describe(name: &str, help: &str) -> String {
+    let name = name.replace(':', "\\:");
+    if help.is_empty() {
+        sh_quote(&name)
+    } else {
+        sh_quote(&format!("{}:{}", name, help))
+    }
+}
+
+/// 补全`value`的语句，任意文本时显示`help`作为提示
+fn complete_value(value: &Value, help: &str) -> String {
+    match value {
+        Value::Text => format!("_message {}", sh_quote(help)),
+        Value::File => "_files".to_string(),
+        Value::Dir => "_files -/".to_string(),
+        Value::Choices(choices) => {
+            let choices: Vec<String> = choices.iter().map(|choice| sh_quote(choice)).collect();
+            format!("compadd -- {}", choices.join(" "))
+        }
+    }
+}
+
+/// 补全选项的语句
+fn complete_flags(command: &Command) -> Vec<String> {
+    let flags: Vec<String> = command
+        .flags
+        .iter()
+        .flat_map(|flag| flag.names.iter().map(|name| describe(name, &flag.help)))
+        .collect();
+    vec![
+        format!("                local -a flags=({})", flags.join(" ")),
+        "                _describe -t options '选项' flags".to_string(),
+    ]
+}
+
+/// 补全子命令和位置参数的语句
+fn complete_words(command: &Command) -> Vec<String> {
+    let mut lines = Vec::new();
+    if !command.subcommands.is_empty() {
+        let subcommands: Vec<String> =
+            command.subcommands.iter().map(|subcommand| describe(&subcommand.name, &subcommand.help)).collect();
+        lines.push(format!("                local -a commands=({})", subcommands.join(" ")));
+        lines.push("                _describe -t commands '子命令' commands".to_string());
+    }
+    match &command.args {
+        Some(Value::Text) | None => {}
+        Some(value) => lines.push(format!("                {}", complete_value(value, ""))),
+    }
+    if lines.is_empty() {
+        lines.push("                :".to_string());
+    }
+    lines
+}
+
+pub(crate) fn generate(command: &Command, nodes: &[Node]) -> String {
+    let name = &command.name;
+    let function = format!("_{}", ident(name));
+    let value_flags = value_flags(nodes);
+    let mut lines = vec![
+        format!("#compdef {}", name),
+        format!("# {}的zsh补全，由 `{} completions zsh` 生成", name, name),
+        format!("{}() {{", function),
+        format!(
+            "    local cmd={} cur=${{words[CURRENT]}} prev=${{words[CURRENT-1]}} word i",
+            sh_quote(name)
+        ),
+        "    # 找出所在的子命令，跳过选项的值".to_string(),
+        "    for (( i = 2; i < CURRENT; i++ )); do".to_string(),
+        "        word=${words[i]}".to_string(),
+        "        case \"$cmd:$word\" in".to_string(),
+    ];
+    for node in nodes {
+        for subcommand in &node.command.subcommands {
+            lines.push(format!(
+                "            {}) cmd={} ;;",
+                sh_quote(&format!("{}:{}", node.path, subcommand.name)),
+                sh_quote(&format!("{} {}", node.path, subcommand.name))
+            ));
+        }
+    }
+    for node in nodes {
+        let names = value_flags.iter().filter(|(path, _, _)| *path == node.path).flat_map(|(_, flag, _)| &flag.names);
+        let patterns = sh_patterns(&node.path, names);
+        if !patterns.is_empty() {
+            lines.push(format!("            {}) (( i++ )) ;;", patterns));
+        }
+    }
+    lines.extend([
+        "        esac".to_string(),
+        "    done".to_string(),
+        "    case \"$cmd:$prev\" in".to_string(),
+    ]);
+    for (path, flag, value) in &value_flags {
+        lines.push(format!(
+            "        {}) {}; return ;;",
+            sh_patterns(path, &flag.names),
+            complete_value(value, &flag.help)
+        ));
+    }
+    lines.extend(["    esac".to_string(), "    case $cmd in".to_string()]);
+    for node in nodes {
+        lines.push(format!("        {})", sh_quote(&node.path)));
+        lines.push("            if [[ $cur == -* ]]; then".to_string());
+        lines.extend(complete_flags(node.command));
+        lines.push("            else".to_string());
+        lines.extend(complete_words(node.command));
+        lines.extend(["            fi".to_string(), "            ;;".to_string()]);
+    }
+    lines.extend([
+        "    esac".to_string(),
+        "}".to_string(),
+        // 从`$fpath`自动加载时直接补全，`source`时注册
+        format!("if [[ $funcstack[1] == {} ]]; then", function),
+        format!("    {} \"$@\"", function),
+        "else".to_string(),
+        format!("    compdef {} {}", function, name),
+        "fi".to_string(),
+        String::new(),
+    ]);
+    lines.join("\n")
+}
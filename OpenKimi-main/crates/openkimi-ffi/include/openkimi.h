@@ -0,0 +1,249 @@
+#ifndef OPENKIMI_H
+#define OPENKIMI_H
+
+/* 由cbindgen生成，不要手工修改 */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * 错误类别，数值固定，新增的类别只会追加在末尾
+ */
+typedef enum OpenKimiErrorKind {
+  /**
+   * 参数为空指针、不是UTF-8或不是合法的JSON
+   */
+  OPEN_KIMI_ERROR_KIND_INVALID_ARGUMENT = 1,
+  /**
+   * 请求参数错误（400）
+   */
+  OPEN_KIMI_ERROR_KIND_INVALID_REQUEST = 2,
+  /**
+   * 提示词加上`max_tokens`超出模型的上下文窗口（400）
+   */
+  OPEN_KIMI_ERROR_KIND_CONTEXT_LENGTH_EXCEEDED = 3,
+  /**
+   * 缺少或提供了错误的令牌（401）
+   */
+  OPEN_KIMI_ERROR_KIND_UNAUTHORIZED = 4,
+  /**
+   * 令牌没有所需的权限（403）
+   */
+  OPEN_KIMI_ERROR_KIND_FORBIDDEN = 5,
+  /**
+   * 模型、会话等不存在（404）
+   */
+  OPEN_KIMI_ERROR_KIND_NOT_FOUND = 6,
+  /**
+   * 请求体超出大小上限（413）
+   */
+  OPEN_KIMI_ERROR_KIND_PAYLOAD_TOO_LARGE = 7,
+  /**
+   * 模型输出不符合`response_format`（422）
+   */
+  OPEN_KIMI_ERROR_KIND_SCHEMA_VALIDATION = 8,
+  /**
+   * 超出限流额度或配额（429）
+   */
+  OPEN_KIMI_ERROR_KIND_RATE_LIMITED = 9,
+  /**
+   * 服务正在退出或暂时不可用（503）
+   */
+  OPEN_KIMI_ERROR_KIND_UNAVAILABLE = 10,
+  /**
+   * 无法连接上游模型或上游出错（502、504）
+   */
+  OPEN_KIMI_ERROR_KIND_UPSTREAM = 11,
+  /**
+   * 其他状态码
+   */
+  OPEN_KIMI_ERROR_KIND_STATUS = 12,
+  /**
+   * 流式响应中途出错
+   */
+  OPEN_KIMI_ERROR_KIND_STREAM = 13,
+  /**
+   * 无法连接服务端、超时或读取响应失败
+   */
+  OPEN_KIMI_ERROR_KIND_CONNECTION = 14,
+  /**
+   * 响应不是预期的格式
+   */
+  OPEN_KIMI_ERROR_KIND_DECODE = 15,
+  /**
+   * 客户端配置错误
+   */
+  OPEN_KIMI_ERROR_KIND_CONFIG = 16,
+} OpenKimiErrorKind;
+
+/**
+ * 客户端，用`openkimi_client_new`创建，`openkimi_client_free`释放
+ */
+typedef struct OpenKimiClient OpenKimiClient;
+
+/**
+ * 调用失败时通过`error`参数返回的错误
+ */
+typedef struct OpenKimiError OpenKimiError;
+
+/**
+ * 流式回复每收到一块调用一次，`chunk`为`chat.completion.chunk`的JSON，只在回调期间有效；
+ * 返回非0时停止读取并关闭连接，服务端随即停止生成
+ */
+typedef int (*OpenKimiStreamCallback)(const char *chunk, void *user_data);
+
+#ifdef __cplusplus
+extern "C" {
+#endif  // __cplusplus
+
+/**
+ * 对话，`request`为`/chat/completions`的请求体，其中的`stream`被忽略
+ *
+ * # Safety
+ *
+ * `client`为有效的客户端，`request`为以NUL结尾的字符串，`error`为`NULL`或可写的指针。
+ */
+char *openkimi_chat(const OpenKimiClient *client, const char *request, OpenKimiError **error);
+
+/**
+ * 流式对话，在当前线程中逐块调用`callback`，读完、回调要求停止或出错后返回；
+ * 出错时返回`false`，此前已经交给回调的部分不会撤回
+ *
+ * # Safety
+ *
+ * `client`为有效的客户端，`request`为以NUL结尾的字符串，`error`为`NULL`或可写的指针；
+ * `user_data`原样交给回调，本库不访问。
+ */
+bool openkimi_chat_stream(const OpenKimiClient *client,
+                          const char *request,
+                          OpenKimiStreamCallback callback,
+                          void *user_data,
+                          OpenKimiError **error);
+
+/**
+ * 释放客户端，`client`可以为`NULL`；释放前应确保没有其他线程还在使用
+ *
+ * # Safety
+ *
+ * `client`必须是`openkimi_client_new`返回的客户端，且只能释放一次。
+ */
+void openkimi_client_free(OpenKimiClient *client);
+
+/**
+ * 创建客户端，`options`为JSON对象，可以有`base_url`、`api_key`、`workspace`、`timeout`（秒）和`max_retries`，
+ * 为`NULL`时全部使用默认值；失败时返回`NULL`
+ *
+ * # Safety
+ *
+ * `options`为`NULL`或以NUL结尾的字符串，`error`为`NULL`或可写的指针。
+ */
+OpenKimiClient *openkimi_client_new(const char *options, OpenKimiError **error);
+
+/**
+ * 嵌入，`request`为`/embeddings`的请求体
+ *
+ * # Safety
+ *
+ * `client`为有效的客户端，`request`为以NUL结尾的字符串，`error`为`NULL`或可写的指针。
+ */
+char *openkimi_embeddings(const OpenKimiClient *client, const char *request, OpenKimiError **error);
+
+/**
+ * 服务端返回的错误码，如`context_length_exceeded`，没有时为`NULL`
+ *
+ * # Safety
+ *
+ * `error`必须是本库返回的、尚未释放的错误。
+ */
+const char *openkimi_error_code(const OpenKimiError *error);
+
+/**
+ * 释放错误，`error`可以为`NULL`
+ *
+ * # Safety
+ *
+ * `error`必须是本库返回的错误，且只能释放一次。
+ */
+void openkimi_error_free(OpenKimiError *error);
+
+/**
+ * 错误类别
+ *
+ * # Safety
+ *
+ * `error`必须是本库返回的、尚未释放的错误。
+ */
+OpenKimiErrorKind openkimi_error_kind(const OpenKimiError *error);
+
+/**
+ * 错误消息，随错误一起释放
+ *
+ * # Safety
+ *
+ * `error`必须是本库返回的、尚未释放的错误。
+ */
+const char *openkimi_error_message(const OpenKimiError *error);
+
+/**
+ * 限流时服务端建议等待的秒数，没有时为负数
+ *
+ * # Safety
+ *
+ * `error`必须是本库返回的、尚未释放的错误。
+ */
+double openkimi_error_retry_after(const OpenKimiError *error);
+
+/**
+ * HTTP状态码，连接失败等没有响应时为0
+ *
+ * # Safety
+ *
+ * `error`必须是本库返回的、尚未释放的错误。
+ */
+uint16_t openkimi_error_status(const OpenKimiError *error);
+
+/**
+ * 模型列表
+ *
+ * # Safety
+ *
+ * `client`为有效的客户端，`error`为`NULL`或可写的指针。
+ */
+char *openkimi_models(const OpenKimiClient *client, OpenKimiError **error);
+
+/**
+ * 调用其他接口，`method`为`GET`、`POST`或`DELETE`，`path`为`/v1`之后的部分，如`/sessions`；
+ * `body`只用于`POST`，为`NULL`时发送空对象
+ *
+ * # Safety
+ *
+ * `client`为有效的客户端，`method`和`path`为以NUL结尾的字符串，`body`为`NULL`或以NUL结尾的字符串，
+ * `error`为`NULL`或可写的指针。
+ */
+char *openkimi_request(const OpenKimiClient *client,
+                       const char *method,
+                       const char *path,
+                       const char *body,
+                       OpenKimiError **error);
+
+/**
+ * 释放本库返回的字符串，`text`可以为`NULL`
+ *
+ * # Safety
+ *
+ * `text`必须是本库返回的字符串，且只能释放一次。
+ */
+void openkimi_string_free(char *text);
+
+/**
+ * 库的版本号，不需要释放
+ */
+const char *openkimi_version(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* OPENKIMI_H */
@@ -0,0 +1,239 @@
+//! 客户端和请求函数
+
+use std::ffi::{c_char, c_int, c_void};
+use std::ptr;
+use std::time::Duration;
+
+use openkimi_client::{blocking, ChatCompletionRequest, ClientConfig, ClientError, EmbeddingRequest};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::{set_error, OpenKimiError};
+use crate::{read_str, to_cstring};
+
+/// 客户端，用`openkimi_client_new`创建，`openkimi_client_free`释放
+pub struct OpenKimiClient {
+    inner: blocking::Client,
+}
+
+/// 流式回复每收到一块调用一次，`chunk`为`chat.completion.chunk`的JSON，只在回调期间有效；
+/// 返回非0时停止读取并关闭连接，服务端随即停止生成
+pub type OpenKimiStreamCallback = extern "C" fn(chunk: *const c_char, user_data: *mut c_void) -> c_int;
+
+/// `openkimi_client_new`的选项，与Python绑定的构造参数相同
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Options {
+    base_url: Option<String>,
+    api_key: Option<String>,
+    workspace: Option<String>,
+    /// 秒
+    timeout: Option<f64>,
+    max_retries: Option<u32>,
+}
+
+/// 未给出的地址和令牌与Rust客户端一样从`OPENKIMI_BASE_URL`和`OPENKIMI_API_KEY`环境变量读取
+fn config(options: Options) -> Result<ClientConfig, OpenKimiError> {
+    let mut config = ClientConfig::from_env();
+    if let Some(base_url) = options.base_url {
+        config.base_url = base_url;
+    }
+    if options.api_key.is_some() {
+        config.api_key = options.api_key;
+    }
+    config.workspace = options.workspace;
+    if let Some(timeout) = options.timeout {
+        config.timeout = Duration::try_from_secs_f64(timeout)
+            .map_err(|_| OpenKimiError::invalid_argument("timeout 必须是非负的秒数"))?;
+    }
+    if let Some(max_retries) = options.max_retries {
+        config.retry.max_retries = max_retries;
+    }
+    Ok(config)
+}
+
+/// 解析调用方传入的JSON，`NULL`视为错误
+unsafe fn parse<T: DeserializeOwned>(value: *const c_char, name: &str) -> Result<T, OpenKimiError> {
+    let text = read_str(value, name)?.ok_or_else(|| OpenKimiError::invalid_argument(format!("{} 不能为空", name)))?;
+    serde_json::from_str(text).map_err(|e| OpenKimiError::invalid_argument(format!("{} 格式不正确: {}", name, e)))
+}
+
+/// 执行请求并把响应序列化为新分配的字符串，失败时返回`NULL`
+unsafe fn respond<T, F>(client: *const OpenKimiClient, error: *mut *mut OpenKimiError, call: F) -> *mut c_char
+where
+    T: Serialize,
+    F: FnOnce(&blocking::Client) -> Result<T, OpenKimiError>,
+{
+    if client.is_null() {
+        set_error(error, OpenKimiError::invalid_argument("client 不能为空"));
+        return ptr::null_mut();
+    }
+    let result = call(&(*client).inner)
+        .and_then(|response| serde_json::to_string(&response).map_err(|e| ClientError::Decode(e.to_string()).into()));
+    match result {
+        Ok(text) => to_cstring(text).into_raw(),
+        Err(err) => {
+            set_error(error, err);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// 创建客户端，`options`为JSON对象，可以有`base_url`、`api_key`、`workspace`、`timeout`（秒）和`max_retries`，
+/// 为`NULL`时全部使用默认值；失败时返回`NULL`
+///
+/// # Safety
+///
+/// `options`为`NULL`或以NUL结尾的字符串，`error`为`NULL`或可写的指针。
+#[no_mangle]
+pub unsafe extern "C" fn openkimi_client_new(
+    options: *const c_char,
+    error: *mut *mut OpenKimiError,
+) -> *mut OpenKimiClient {
+    let options = if options.is_null() {
+        Ok(Options::default())
+    } else {
+        parse(options, "options")
+    };
+    let client = options
+        .and_then(config)
+        .and_then(|config| blocking::Client::new(config).map_err(OpenKimiError::from));
+    match client {
+        Ok(inner) => Box::into_raw(Box::new(OpenKimiClient { inner })),
+        Err(err) => {
+            set_error(error, err);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// 释放客户端，`client`可以为`NULL`；释放前应确保没有其他线程还在使用
+///
+/// # Safety
+///
+/// `client`必须是`openkimi_client_new`返回的客户端，且只能释放一次。
+#[no_mangle]
+pub unsafe extern "C" fn openkimi_client_free(client: *mut OpenKimiClient) {
+    if !client.is_null() {
+        drop(Box::from_raw(client));
+    }
+}
+
+/// 模型列表
+///
+/// # Safety
+///
+/// `client`为有效的客户端，`error`为`NULL`或可写的指针。
+#[no_mangle]
+pub unsafe extern "C" fn openkimi_models(client: *const OpenKimiClient, error: *mut *mut OpenKimiError) -> *mut c_char {
+    respond(client, error, |client| Ok(client.models()?))
+}
+
+/// 对话，`request`为`/chat/completions`的请求体，其中的`stream`被忽略
+///
+/// # Safety
+///
+/// `client`为有效的客户端，`request`为以NUL结尾的字符串，`error`为`NULL`或可写的指针。
+#[no_mangle]
+pub unsafe extern "C" fn openkimi_chat(
+    client: *const OpenKimiClient,
+    request: *const c_char,
+    error: *mut *mut OpenKimiError,
+) -> *mut c_char {
+    respond(client, error, |client| {
+        let request: ChatCompletionRequest = parse(request, "request")?;
+        Ok(client.chat(&request)?)
+    })
+}
+
+/// 流式对话，在当前线程中逐块调用`callback`，读完、回调要求停止或出错后返回；
+/// 出错时返回`false`，此前已经交给回调的部分不会撤回
+///
+/// # Safety
+///
+/// `client`为有效的客户端，`request`为以NUL结尾的字符串，`error`为`NULL`或可写的指针；
+/// `user_data`原样交给回调，本库不访问。
+#[no_mangle]
+pub unsafe extern "C" fn openkimi_chat_stream(
+    client: *const OpenKimiClient,
+    request: *const c_char,
+    callback: Option<OpenKimiStreamCallback>,
+    user_data: *mut c_void,
+    error: *mut *mut OpenKimiError,
+) -> bool {
+    let result = (|| {
+        if client.is_null() {
+            return Err(OpenKimiError::invalid_argument("client 不能为空"));
+        }
+        let callback = callback.ok_or_else(|| OpenKimiError::invalid_argument("callback 不能为空"))?;
+        let request: ChatCompletionRequest = parse(request, "request")?;
+        for chunk in (*client).inner.chat_stream(&request)? {
+            let chunk = serde_json::to_string(&chunk?).map_err(|e| ClientError::Decode(e.to_string()))?;
+            // 提前返回时丢弃迭代器，关闭连接
+            if callback(to_cstring(chunk).as_ptr(), user_data) != 0 {
+                break;
+            }
+        }
+        Ok(())
+    })();
+    match result {
+        Ok(()) => true,
+        Err(err) => {
+            set_error(error, err);
+            false
+        }
+    }
+}
+
+/// 嵌入，`request`为`/embeddings`的请求体
+///
+/// # Safety
+///
+/// `client`为有效的客户端，`request`为以NUL结尾的字符串，`error`为`NULL`或可写的指针。
+#[no_mangle]
+pub unsafe extern "C" fn openkimi_embeddings(
+    client: *const OpenKimiClient,
+    request: *const c_char,
+    error: *mut *mut OpenKimiError,
+) -> *mut c_char {
+    respond(client, error, |client| {
+        let request: EmbeddingRequest = parse(request, "request")?;
+        Ok(client.embeddings(&request)?)
+    })
+}
+
+/// 调用其他接口，`method`为`GET`、`POST`或`DELETE`，`path`为`/v1`之后的部分，如`/sessions`；
+/// `body`只用于`POST`，为`NULL`时发送空对象
+///
+/// # Safety
+///
+/// `client`为有效的客户端，`method`和`path`为以NUL结尾的字符串，`body`为`NULL`或以NUL结尾的字符串，
+/// `error`为`NULL`或可写的指针。
+#[no_mangle]
+pub unsafe extern "C" fn openkimi_request(
+    client: *const OpenKimiClient,
+    method: *const c_char,
+    path: *const c_char,
+    body: *const c_char,
+    error: *mut *mut OpenKimiError,
+) -> *mut c_char {
+    respond(client, error, |client| {
+        let method = read_str(method, "method")?.unwrap_or_default();
+        let path = read_str(path, "path")?.ok_or_else(|| OpenKimiError::invalid_argument("path 不能为空"))?;
+        let response: Value = match method.to_ascii_uppercase().as_str() {
+            "GET" => client.get(path)?,
+            "DELETE" => client.delete(path)?,
+            "POST" => {
+                let body: Value = if body.is_null() {
+                    Value::Object(Default::default())
+                } else {
+                    parse(body, "body")?
+                };
+                client.post(path, &body)?
+            }
+            other => return Err(OpenKimiError::invalid_argument(format!("不支持的方法: {}", other))),
+        };
+        Ok(response)
+    })
+}
@@ -0,0 +1,172 @@
+//! 错误，按`ClientError`的变体分类，调用方用`openkimi_error_*`读取后以`openkimi_error_free`释放
+
+use std::ffi::{c_char, CString};
+use std::ptr;
+
+use openkimi_client::ClientError;
+
+use crate::to_cstring;
+
+/// 错误类别，数值固定，新增的类别只会追加在末尾
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenKimiErrorKind {
+    /// 参数为空指针、不是UTF-8或不是合法的JSON
+    InvalidArgument = 1,
+    /// 请求参数错误（400）
+    InvalidRequest = 2,
+    /// 提示词加上`max_tokens`超出模型的上下文窗口（400）
+    ContextLengthExceeded = 3,
+    /// 缺少或提供了错误的令牌（401）
+    Unauthorized = 4,
+    /// 令牌没有所需的权限（403）
+    Forbidden = 5,
+    /// 模型、会话等不存在（404）
+    NotFound = 6,
+    /// 请求体超出大小上限（413）
+    PayloadTooLarge = 7,
+    /// 模型输出不符合`response_format`（422）
+    SchemaValidation = 8,
+    /// 超出限流额度或配额（429）
+    RateLimited = 9,
+    /// 服务正在退出或暂时不可用（503）
+    Unavailable = 10,
+    /// 无法连接上游模型或上游出错（502、504）
+    Upstream = 11,
+    /// 其他状态码
+    Status = 12,
+    /// 流式响应中途出错
+    Stream = 13,
+    /// 无法连接服务端、超时或读取响应失败
+    Connection = 14,
+    /// 响应不是预期的格式
+    Decode = 15,
+    /// 客户端配置错误
+    Config = 16,
+}
+
+/// 调用失败时通过`error`参数返回的错误
+pub struct OpenKimiError {
+    kind: OpenKimiErrorKind,
+    message: CString,
+    status: u16,
+    code: Option<CString>,
+    retry_after: f64,
+}
+
+impl OpenKimiError {
+    pub(crate) fn invalid_argument(message: impl Into<String>) -> OpenKimiError {
+        OpenKimiError {
+            kind: OpenKimiErrorKind::InvalidArgument,
+            message: to_cstring(message.into()),
+            status: 0,
+            code: None,
+            retry_after: -1.0,
+        }
+    }
+}
+
+impl From<ClientError> for OpenKimiError {
+    fn from(err: ClientError) -> OpenKimiError {
+        let kind = match &err {
+            ClientError::InvalidRequest(_) => OpenKimiErrorKind::InvalidRequest,
+            ClientError::ContextLengthExceeded(_) => OpenKimiErrorKind::ContextLengthExceeded,
+            ClientError::Unauthorized(_) => OpenKimiErrorKind::Unauthorized,
+            ClientError::Forbidden(_) => OpenKimiErrorKind::Forbidden,
+            ClientError::NotFound(_) => OpenKimiErrorKind::NotFound,
+            ClientError::PayloadTooLarge(_) => OpenKimiErrorKind::PayloadTooLarge,
+            ClientError::SchemaValidation(_) => OpenKimiErrorKind::SchemaValidation,
+            ClientError::RateLimited { .. } => OpenKimiErrorKind::RateLimited,
+            ClientError::Unavailable(_) => OpenKimiErrorKind::Unavailable,
+            ClientError::Upstream { .. } => OpenKimiErrorKind::Upstream,
+            ClientError::Status { .. } => OpenKimiErrorKind::Status,
+            ClientError::Stream(_) => OpenKimiErrorKind::Stream,
+            ClientError::Http(_) => OpenKimiErrorKind::Connection,
+            ClientError::Decode(_) => OpenKimiErrorKind::Decode,
+            ClientError::Config(_) => OpenKimiErrorKind::Config,
+        };
+        let retry_after = match &err {
+            ClientError::RateLimited {
+                retry_after: Some(delay),
+                ..
+            } => delay.as_secs_f64(),
+            _ => -1.0,
+        };
+        OpenKimiError {
+            kind,
+            message: to_cstring(err.to_string()),
+            status: err.status().unwrap_or(0),
+            code: err.detail().and_then(|detail| detail.code.clone()).map(to_cstring),
+            retry_after,
+        }
+    }
+}
+
+/// 把错误写入调用方传入的`error`，`error`为空时丢弃
+pub(crate) unsafe fn set_error(error: *mut *mut OpenKimiError, err: impl Into<OpenKimiError>) {
+    if !error.is_null() {
+        *error = Box::into_raw(Box::new(err.into()));
+    }
+}
+
+/// 错误类别
+///
+/// # Safety
+///
+/// `error`必须是本库返回的、尚未释放的错误。
+#[no_mangle]
+pub unsafe extern "C" fn openkimi_error_kind(error: *const OpenKimiError) -> OpenKimiErrorKind {
+    (*error).kind
+}
+
+/// 错误消息，随错误一起释放
+///
+/// # Safety
+///
+/// `error`必须是本库返回的、尚未释放的错误。
+#[no_mangle]
+pub unsafe extern "C" fn openkimi_error_message(error: *const OpenKimiError) -> *const c_char {
+    (*error).message.as_ptr()
+}
+
+/// HTTP状态码，连接失败等没有响应时为0
+///
+/// # Safety
+///
+/// `error`必须是本库返回的、尚未释放的错误。
+#[no_mangle]
+pub unsafe extern "C" fn openkimi_error_status(error: *const OpenKimiError) -> u16 {
+    (*error).status
+}
+
+/// 服务端返回的错误码，如`context_length_exceeded`，没有时为`NULL`
+///
+/// # Safety
+///
+/// `error`必须是本库返回的、尚未释放的错误。
+#[no_mangle]
+pub unsafe extern "C" fn openkimi_error_code(error: *const OpenKimiError) -> *const c_char {
+    (*error).code.as_ref().map_or(ptr::null(), |code| code.as_ptr())
+}
+
+/// 限流时服务端建议等待的秒数，没有时为负数
+///
+/// # Safety
+///
+/// `error`必须是本库返回的、尚未释放的错误。
+#[no_mangle]
+pub unsafe extern "C" fn openkimi_error_retry_after(error: *const OpenKimiError) -> f64 {
+    (*error).retry_after
+}
+
+/// 释放错误，`error`可以为`NULL`
+///
+/// # Safety
+///
+/// `error`必须是本库返回的错误，且只能释放一次。
+#[no_mangle]
+pub unsafe extern "C" fn openkimi_error_free(error: *mut OpenKimiError) {
+    if !error.is_null() {
+        drop(Box::from_raw(error));
+    }
+}
@@ -0,0 +1,54 @@
+//! openkimi-client的C接口
+//!
+//! 导出稳定的C ABI，供C++、Swift、Kotlin（JNI）等桌面程序调用，头文件`include/openkimi.h`由cbindgen生成。
+//! 基于`openkimi_client::blocking`，所有调用都是阻塞的，同一个客户端可以在多个线程中同时使用。
+//! 请求和响应都是UTF-8的JSON字符串，与HTTP接口的请求体和响应体相同；认证、重试和错误分类与Rust客户端相同。
+//!
+//! 内存约定：返回的`char *`由调用方用`openkimi_string_free`释放，客户端和错误分别用
+//! `openkimi_client_free`和`openkimi_error_free`释放；传入的字符串只在调用期间使用，不会被保存。
+//! 失败的调用返回`NULL`或`false`，并在`error`不为`NULL`时写入新分配的错误。
+
+use std::ffi::{c_char, CStr, CString};
+
+mod client;
+mod error;
+
+pub use client::{OpenKimiClient, OpenKimiStreamCallback};
+pub use error::{OpenKimiError, OpenKimiErrorKind};
+
+/// 字符串中的NUL替换为U+FFFD，使其能交给C
+pub(crate) fn to_cstring(text: String) -> CString {
+    CString::new(text).unwrap_or_else(|err| {
+        let text = String::from_utf8_lossy(&err.into_vec()).replace('\0', "\u{fffd}");
+        CString::new(text).unwrap_or_default()
+    })
+}
+
+/// 读取调用方传入的字符串，`NULL`为`None`
+pub(crate) unsafe fn read_str<'a>(value: *const c_char, name: &str) -> Result<Option<&'a str>, OpenKimiError> {
+    if value.is_null() {
+        return Ok(None);
+    }
+    CStr::from_ptr(value)
+        .to_str()
+        .map(Some)
+        .map_err(|_| OpenKimiError::invalid_argument(format!("{} 不是UTF-8字符串", name)))
+}
+
+/// 库的版本号，不需要释放
+#[no_mangle]
+pub extern "C" fn openkimi_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast()
+}
+
+/// 释放本库返回的字符串，`text`可以为`NULL`
+///
+/// # Safety
+///
+/// `text`必须是本库返回的字符串，且只能释放一次。
+#[no_mangle]
+pub unsafe extern "C" fn openkimi_string_free(text: *mut c_char) {
+    if !text.is_null() {
+        drop(CString::from_raw(text));
+    }
+}
@@ -0,0 +1,201 @@
+//! 开机自启动
+//!
+//! - Windows：`HKCU\Software\Microsoft\Windows\CurrentVersion\Run`下的`OpenKimi`值
+//! - macOS：`~/Library/LaunchAgents/<appId>.plist`
+//! - Linux：`$XDG_CONFIG_HOME/autostart/openkimi.desktop`
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::Serialize;
+
+use crate::paths::PRODUCT_NAME;
+
+/// 与package.json中的`appId`保持一致
+const APP_ID: &str = "com.example.kimi-client";
+
+const RUN_KEY: &str = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Run";
+
+/// 自启动状态
+#[derive(Debug, Serialize)]
+pub struct AutostartStatus {
+    pub enabled: bool,
+    /// 登录时执行的命令
+    pub command: Option<String>,
+    /// 配置所在位置（注册表键或文件路径）
+    pub location: String,
+}
+
+/// 登录时启动的参数，`hidden`为true时客户端以最小化方式启动
+fn launch_args(hidden: bool) -> Vec<String> {
+    if hidden {
+        vec!["--hidden".to_string()]
+    } else {
+        Vec::new()
+    }
+}
+
+fn home_dir() -> io::Result<PathBuf> {
+    env::var_os("HOME")
+        .map(PathBuf::from)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "环境变量 HOME 未设置"))
+}
+
+fn launch_agent_path() -> io::Result<PathBuf> {
+    Ok(home_dir()?.join("Library/LaunchAgents").join(format!("{}.plist", APP_ID)))
+}
+
+fn desktop_entry_path() -> io::Result<PathBuf> {
+    let config = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => home_dir()?.join(".config"),
+    };
+    Ok(config.join("autostart").join("openkimi.desktop"))
+}
+
+fn xml_escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// 按desktop entry规范为Exec字段加引号
+pub(crate) fn desktop_quote(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('`', "\\`")
+        .replace('$', "\\$");
+    format!("\"{}\"", escaped)
+}
+
+fn windows_command(exe: &Path, hidden: bool) -> String {
+    let mut command = format!("\"{}\"", exe.display());
+    for arg in launch_args(hidden) {
+        command.push(' ');
+        command.push_str(&arg);
+    }
+    command
+}
+
+/// 启用开机自启动
+pub fn enable(exe: &Path, hidden: bool) -> io::Result<AutostartStatus> {
+    if cfg!(windows) {
+        let command = windows_command(exe, hidden);
+        let output = Command::new("reg")
+            .args(["add", RUN_KEY, "/v", PRODUCT_NAME, "/t", "REG_SZ", "/d", &command, "/f"])
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::other("写入注册表 Run 键失败"));
+        }
+    } else if cfg!(target_os = "macos") {
+        let path = launch_agent_path()?;
+        let mut program_args = format!("    <string>{}</string>\n", xml_escape(&exe.to_string_lossy()));
+        for arg in launch_args(hidden) {
+            program_args.push_str(&format!("    <string>{}</string>\n", xml_escape(&arg)));
+        }
+        let plist = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+  <key>Label</key>
+  <string>{}</string>
+  <key>ProgramArguments</key>
+  <array>
+{}  </array>
+  <key>RunAtLoad</key>
+  <true/>
+</dict>
+</plist>
+"#,
+            APP_ID, program_args
+        );
+        fs::create_dir_all(path.parent().unwrap_or(Path::new(".")))?;
+        fs::write(&path, plist)?;
+    } else {
+        let path = desktop_entry_path()?;
+        let mut exec = desktop_quote(&exe.to_string_lossy());
+        for arg in launch_args(hidden) {
+            exec.push(' ');
+            exec.push_str(&arg);
+        }
+        let entry = format!(
+            "[Desktop Entry]\nType=Application\nName={}\nExec={}\nX-GNOME-Autostart-enabled=true\nHidden=false\nNoDisplay=false\n",
+            PRODUCT_NAME, exec
+        );
+        fs::create_dir_all(path.parent().unwrap_or(Path::new(".")))?;
+        fs::write(&path, entry)?;
+    }
+    status()
+}
+
+/// 关闭开机自启动
+pub fn disable() -> io::Result<AutostartStatus> {
+    if cfg!(windows) {
+        // 值不存在时reg delete返回失败，视为已关闭
+        Command::new("reg")
+            .args(["delete", RUN_KEY, "/v", PRODUCT_NAME, "/f"])
+            .output()?;
+    } else {
+        let path = if cfg!(target_os = "macos") {
+            launch_agent_path()?
+        } else {
+            desktop_entry_path()?
+        };
+        match fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+    }
+    status()
+}
+
+/// 查询开机自启动状态
+pub fn status() -> io::Result<AutostartStatus> {
+    if cfg!(windows) {
+        let output = Command::new("reg")
+            .args(["query", RUN_KEY, "/v", PRODUCT_NAME])
+            .output()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let command = stdout.lines().find_map(|line| {
+            let (_, value) = line.trim().split_once("REG_SZ")?;
+            Some(value.trim().to_string())
+        });
+        return Ok(AutostartStatus {
+            enabled: command.is_some(),
+            command,
+            location: format!(r"{}\{}", RUN_KEY, PRODUCT_NAME),
+        });
+    }
+
+    let (path, command) = if cfg!(target_os = "macos") {
+        let path = launch_agent_path()?;
+        // plist中第一个<string>是Label，之后是ProgramArguments
+        let command = fs::read_to_string(&path).ok().map(|content| {
+            content
+                .split("<string>")
+                .skip(2)
+                .filter_map(|part| part.split("</string>").next())
+                .collect::<Vec<_>>()
+                .join(" ")
+        });
+        (path, command)
+    } else {
+        let path = desktop_entry_path()?;
+        let command = fs::read_to_string(&path).ok().and_then(|content| {
+            content
+                .lines()
+                .find_map(|line| line.strip_prefix("Exec=").map(str::to_string))
+        });
+        (path, command)
+    };
+
+    Ok(AutostartStatus {
+        enabled: command.is_some(),
+        command,
+        location: path.to_string_lossy().into_owned(),
+    })
+}
@@ -0,0 +1,106 @@
+//! OpenKimi卸载清理工具
+//!
+//! ```text
+//! openkimi-cleanup            交互模式，逐类询问是否删除
+//! openkimi-cleanup --purge    静默删除全部内容（企业静默卸载）
+//! openkimi-cleanup --dry-run  只列出将要删除的内容
+//! ```
+//!
+//! 标准输入不是终端且未指定`--purge`时，只删除默认删除的类别，保留用户数据。
+
+use std::env;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::process::ExitCode;
+
+use openkimi_helper::cleanup::{self, Category, CleanupItem};
+
+const CATEGORIES: [Category; 4] = [
+    Category::UserData,
+    Category::Cache,
+    Category::Autostart,
+    Category::ProtocolHandler,
+];
+
+/// 询问是否删除某一类别，直接回车使用默认值
+fn ask(category: Category, items: &[&CleanupItem]) -> io::Result<bool> {
+    println!("\n🗂️ {}:", category.label());
+    for item in items {
+        println!("   {}", item.describe());
+    }
+    let hint = if category.removed_by_default() { "[Y/n]" } else { "[y/N]" };
+    print!("是否删除？{} ", hint);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(match answer.trim().to_lowercase().as_str() {
+        "" => category.removed_by_default(),
+        "y" | "yes" => true,
+        _ => false,
+    })
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let purge = args.iter().any(|arg| arg == "--purge");
+    let dry_run = args.iter().any(|arg| arg == "--dry-run");
+    let interactive = !purge && io::stdin().is_terminal();
+
+    let plan = cleanup::plan();
+    if plan.is_empty() {
+        println!("✅ 没有需要清理的内容");
+        return ExitCode::SUCCESS;
+    }
+
+    let mut failed = false;
+    for category in CATEGORIES {
+        let items: Vec<&CleanupItem> = plan.iter().filter(|item| item.category == category).collect();
+        if items.is_empty() {
+            continue;
+        }
+
+        if dry_run {
+            println!("🗂️ {}:", category.label());
+            for item in &items {
+                println!("   {}", item.describe());
+            }
+            continue;
+        }
+
+        let selected = if purge {
+            true
+        } else if interactive {
+            match ask(category, &items) {
+                Ok(selected) => selected,
+                Err(err) => {
+                    eprintln!("❌ 读取输入失败: {}", err);
+                    return ExitCode::FAILURE;
+                }
+            }
+        } else {
+            category.removed_by_default()
+        };
+        if !selected {
+            continue;
+        }
+
+        for item in items {
+            match cleanup::remove(item) {
+                Ok(()) => println!("🗑️ 已删除: {}", item.describe()),
+                Err(err) => {
+                    eprintln!("❌ 删除失败 {}: {}", item.describe(), err);
+                    failed = true;
+                }
+            }
+        }
+    }
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        if !dry_run {
+            println!("✅ 清理完成");
+        }
+        ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,200 @@
+//! 离线更新包
+//!
+//! 无法访问CDN的内网用`build-client export-update-bundle`把发布清单`latest-<平台>.json`和安装包打成一个zip，
+//! 拷贝到U盘后用`openkimi-setup --bundle <路径>`安装。包内的`signature`是对清单原文的Ed25519签名，
+//! 清单记录了安装包的SHA-256，因此签名同时保护安装包。
+//!
+//! 签名私钥由构建工具从`OPENKIMI_UPDATE_SECRET`读取（`openkimi-license keygen`生成，与许可证密钥分开），
+//! 公钥在构建时通过`OPENKIMI_UPDATE_PUBLIC_KEY`注入。
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use openkimi_licensing::{LicenseIssuer, LicenseVerifier};
+use sha2::{Digest, Sha256};
+use zip::write::SimpleFileOptions;
+use zip::ZipArchive;
+
+use crate::payload::{self, PayloadManifest};
+
+/// 发布构建时注入的更新包公钥（URL安全Base64）
+const EMBEDDED_PUBLIC_KEY: Option<&str> = option_env!("OPENKIMI_UPDATE_PUBLIC_KEY");
+
+const SIGNATURE_ENTRY: &str = "signature";
+
+fn manifest_entry(platform: &str) -> String {
+    format!("latest-{}.json", platform)
+}
+
+/// 使用构建时注入的公钥创建校验器
+pub fn verifier() -> Result<LicenseVerifier, String> {
+    let public_key = EMBEDDED_PUBLIC_KEY.ok_or("此版本未内置更新包公钥，不能导入离线更新包")?;
+    LicenseVerifier::new(public_key).map_err(|e| e.to_string())
+}
+
+/// 把`release_dir`中`platform`的清单和安装包导出为签名的更新包，返回更新包路径
+pub fn export(release_dir: &Path, platform: &str, issuer: &LicenseIssuer, output_dir: &Path) -> Result<PathBuf, String> {
+    let manifest_path = release_dir.join(manifest_entry(platform));
+    let manifest_bytes =
+        fs::read(&manifest_path).map_err(|e| format!("读取发布清单 {} 失败: {}", manifest_path.display(), e))?;
+    let manifest: PayloadManifest =
+        serde_json::from_slice(&manifest_bytes).map_err(|e| format!("解析发布清单失败: {}", e))?;
+    let payload_path = release_dir.join(manifest.file_name()?);
+
+    fs::create_dir_all(output_dir).map_err(|e| e.to_string())?;
+    let bundle_path = output_dir.join(format!("OpenKimi-{}-{}-update.zip", manifest.version, platform));
+    let mut writer = zip::ZipWriter::new(File::create(&bundle_path).map_err(|e| e.to_string())?);
+    // 安装包已经压缩过，直接存储
+    let options = SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored)
+        .large_file(manifest.size >= u32::MAX as u64);
+
+    let write = |writer: &mut zip::ZipWriter<File>, name: &str, data: &[u8]| -> Result<(), String> {
+        writer.start_file(name, options).map_err(|e| e.to_string())?;
+        io::Write::write_all(writer, data).map_err(|e| e.to_string())
+    };
+    write(&mut writer, &manifest_entry(platform), &manifest_bytes)?;
+    write(&mut writer, SIGNATURE_ENTRY, issuer.sign(&manifest_bytes).as_bytes())?;
+
+    // 边写边计算摘要，清单与安装包不一致时不导出
+    writer.start_file(manifest.file.as_str(), options).map_err(|e| e.to_string())?;
+    let mut payload =
+        File::open(&payload_path).map_err(|e| format!("读取安装包 {} 失败: {}", payload_path.display(), e))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = payload.read(&mut buffer).map_err(|e| e.to_string())?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        io::Write::write_all(&mut writer, &buffer[..read]).map_err(|e| e.to_string())?;
+    }
+    writer.finish().map_err(|e| e.to_string())?;
+
+    let digest: String = hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect();
+    if !digest.eq_ignore_ascii_case(&manifest.sha256) {
+        let _ = fs::remove_file(&bundle_path);
+        return Err(format!("安装包与清单不一致: 期望 {}，实际 {}", manifest.sha256, digest));
+    }
+    Ok(bundle_path)
+}
+
+/// 已校验签名的离线更新包
+pub struct UpdateBundle {
+    archive: ZipArchive<File>,
+    manifest: PayloadManifest,
+}
+
+impl UpdateBundle {
+    /// 打开更新包并校验清单签名
+    pub fn open(path: &Path, platform: &str, verifier: &LicenseVerifier) -> Result<UpdateBundle, String> {
+        let file = File::open(path).map_err(|e| format!("打开更新包 {} 失败: {}", path.display(), e))?;
+        let mut archive = ZipArchive::new(file).map_err(|e| format!("更新包格式错误: {}", e))?;
+        let mut read_entry = |name: &str| -> Result<Vec<u8>, String> {
+            let mut entry = archive.by_name(name).map_err(|_| format!("更新包缺少 {}", name))?;
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).map_err(|e| e.to_string())?;
+            Ok(data)
+        };
+        let manifest_bytes = read_entry(&manifest_entry(platform))?;
+        let signature = String::from_utf8(read_entry(SIGNATURE_ENTRY)?).map_err(|_| "更新包签名格式错误")?;
+        verifier
+            .verify_signature(&manifest_bytes, &signature)
+            .map_err(|e| format!("更新包签名校验失败: {}", e))?;
+
+        let manifest = serde_json::from_slice(&manifest_bytes).map_err(|e| format!("解析发布清单失败: {}", e))?;
+        Ok(UpdateBundle { archive, manifest })
+    }
+
+    pub fn manifest(&self) -> &PayloadManifest {
+        &self.manifest
+    }
+
+    /// 把安装包解压到`dir`并校验SHA-256，返回文件路径；`progress`收到已解压和总字节数
+    pub fn extract(mut self, dir: &Path, mut progress: impl FnMut(u64, u64)) -> Result<PathBuf, String> {
+        let manifest = &self.manifest;
+        let entry = self
+            .archive
+            .by_name(manifest.file_name()?.to_str().unwrap_or_default())
+            .map_err(|_| format!("更新包缺少 {}", manifest.file))?;
+        payload::save_verified(entry, manifest, dir, 64 * 1024, |written| {
+            progress(written, manifest.size);
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn release(dir: &Path, payload: &[u8], sha256: &str) {
+        fs::write(dir.join("OpenKimi-1.2.0-win-x64.zip"), payload).unwrap();
+        let manifest = format!(
+            r#"{{"version":"1.2.0","file":"OpenKimi-1.2.0-win-x64.zip","sha256":"{}","size":{}}}"#,
+            sha256,
+            payload.len()
+        );
+        fs::write(dir.join("latest-windows.json"), manifest).unwrap();
+    }
+
+    fn sha256(data: &[u8]) -> String {
+        Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn export_then_import() {
+        let dir = tempfile::tempdir().unwrap();
+        release(dir.path(), b"payload", &sha256(b"payload"));
+        let issuer = LicenseIssuer::generate().unwrap();
+        let bundle = export(dir.path(), "windows", &issuer, &dir.path().join("out")).unwrap();
+        assert!(bundle.ends_with("out/OpenKimi-1.2.0-windows-update.zip"));
+
+        let verifier = LicenseVerifier::new(&issuer.public_key()).unwrap();
+        let opened = UpdateBundle::open(&bundle, "windows", &verifier).unwrap();
+        assert_eq!(opened.manifest().version, "1.2.0");
+        let payload = opened.extract(&dir.path().join("import"), |_, _| {}).unwrap();
+        assert_eq!(fs::read(payload).unwrap(), b"payload");
+
+        // 其他密钥签名的更新包不能导入
+        let other = LicenseVerifier::new(&LicenseIssuer::generate().unwrap().public_key()).unwrap();
+        assert!(UpdateBundle::open(&bundle, "windows", &other).is_err());
+    }
+
+    #[test]
+    fn import_rejects_tampered_payload() {
+        let dir = tempfile::tempdir().unwrap();
+        release(dir.path(), b"payload", &sha256(b"payload"));
+        let issuer = LicenseIssuer::generate().unwrap();
+        let manifest = fs::read(dir.path().join("latest-windows.json")).unwrap();
+        // 签名有效的清单配上被替换的安装包
+        let path = dir.path().join("tampered.zip");
+        let mut writer = zip::ZipWriter::new(File::create(&path).unwrap());
+        for (name, data) in [
+            ("latest-windows.json", manifest.clone()),
+            (SIGNATURE_ENTRY, issuer.sign(&manifest).into_bytes()),
+            ("OpenKimi-1.2.0-win-x64.zip", b"pwned!!".to_vec()),
+        ] {
+            writer.start_file(name, SimpleFileOptions::default()).unwrap();
+            io::Write::write_all(&mut writer, &data).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let verifier = LicenseVerifier::new(&issuer.public_key()).unwrap();
+        let bundle = UpdateBundle::open(&path, "windows", &verifier).unwrap();
+        let import_dir = dir.path().join("import");
+        assert!(bundle.extract(&import_dir, |_, _| {}).is_err());
+        assert_eq!(fs::read_dir(&import_dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn export_rejects_mismatched_payload() {
+        let dir = tempfile::tempdir().unwrap();
+        release(dir.path(), b"payload", &sha256(b"other"));
+        let issuer = LicenseIssuer::generate().unwrap();
+        assert!(export(dir.path(), "windows", &issuer, dir.path()).is_err());
+        assert!(!dir.path().join("OpenKimi-1.2.0-windows-update.zip").exists());
+    }
+}
@@ -0,0 +1,148 @@
+//! 卸载时的残留清理
+//!
+//! 卸载程序只删除安装目录，这里负责清理安装目录之外由客户端产生的内容。
+//! [`plan`]只返回当前确实存在的条目，调用方可以先展示给用户确认再逐项删除。
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::autostart;
+use crate::deeplink::{protocol_desktop_path, PROTOCOL_CLASS_KEY};
+use crate::paths::{cache_and_log_dirs, default_user_data_dir};
+
+/// 清理类别
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    /// 配置、聊天记录、插件、许可证及迁移备份
+    UserData,
+    /// 缓存与日志
+    Cache,
+    /// 开机自启动
+    Autostart,
+    /// kimi:// 协议注册
+    ProtocolHandler,
+}
+
+impl Category {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Category::UserData => "用户数据（配置、聊天记录、插件）",
+            Category::Cache => "缓存与日志",
+            Category::Autostart => "开机自启动",
+            Category::ProtocolHandler => "kimi:// 链接关联",
+        }
+    }
+
+    /// 交互模式下是否默认删除；用户数据默认保留
+    pub fn removed_by_default(&self) -> bool {
+        !matches!(self, Category::UserData)
+    }
+}
+
+/// 待清理的对象
+#[derive(Debug, Clone)]
+pub enum Target {
+    Dir(PathBuf),
+    File(PathBuf),
+    RegistryKey(String),
+    Autostart,
+}
+
+/// 单个清理条目
+#[derive(Debug, Clone)]
+pub struct CleanupItem {
+    pub category: Category,
+    pub target: Target,
+}
+
+impl CleanupItem {
+    pub fn describe(&self) -> String {
+        match &self.target {
+            Target::Dir(path) | Target::File(path) => path.display().to_string(),
+            Target::RegistryKey(key) => key.clone(),
+            Target::Autostart => "登录时启动 OpenKimi".to_string(),
+        }
+    }
+}
+
+fn registry_key_exists(key: &str) -> bool {
+    Command::new("reg")
+        .args(["query", key])
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+/// 收集当前存在的全部清理条目
+pub fn plan() -> Vec<CleanupItem> {
+    let mut items = Vec::new();
+
+    if let Some(data_dir) = default_user_data_dir() {
+        let backups = data_dir.with_file_name(format!(
+            "{}-backups",
+            data_dir.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        for dir in [data_dir, backups] {
+            if dir.is_dir() {
+                items.push(CleanupItem {
+                    category: Category::UserData,
+                    target: Target::Dir(dir),
+                });
+            }
+        }
+    }
+
+    for dir in cache_and_log_dirs() {
+        if dir.is_dir() {
+            items.push(CleanupItem {
+                category: Category::Cache,
+                target: Target::Dir(dir),
+            });
+        }
+    }
+
+    if autostart::status().map(|status| status.enabled).unwrap_or(false) {
+        items.push(CleanupItem {
+            category: Category::Autostart,
+            target: Target::Autostart,
+        });
+    }
+
+    if cfg!(windows) {
+        if registry_key_exists(PROTOCOL_CLASS_KEY) {
+            items.push(CleanupItem {
+                category: Category::ProtocolHandler,
+                target: Target::RegistryKey(PROTOCOL_CLASS_KEY.to_string()),
+            });
+        }
+    } else if !cfg!(target_os = "macos") {
+        // macOS的协议关联随.app一起由LaunchServices注销
+        if let Some(path) = protocol_desktop_path().filter(|path| path.is_file()) {
+            items.push(CleanupItem {
+                category: Category::ProtocolHandler,
+                target: Target::File(path),
+            });
+        }
+    }
+
+    items
+}
+
+/// 删除单个条目
+pub fn remove(item: &CleanupItem) -> io::Result<()> {
+    match &item.target {
+        Target::Dir(path) => fs::remove_dir_all(path),
+        Target::File(path) => fs::remove_file(path),
+        Target::RegistryKey(key) => {
+            let output = Command::new("reg").args(["delete", key, "/f"]).output()?;
+            if output.status.success() {
+                Ok(())
+            } else {
+                Err(io::Error::other(format!("删除注册表键失败: {}", key)))
+            }
+        }
+        Target::Autostart => autostart::disable().map(|_| ()),
+    }
+}
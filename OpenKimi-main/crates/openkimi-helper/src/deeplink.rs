@@ -0,0 +1,236 @@
+//! kimi:// 链接注册与转发
+//!
+//! Windows和Linux上把协议处理程序注册为本辅助程序（`deeplink open`），
+//! 由它把链接转交给正在运行的客户端实例；没有运行中的实例时再启动客户端。
+//! macOS的协议声明写在`.app`的Info.plist中，系统直接把链接交给客户端，这里只负责刷新LaunchServices。
+//!
+//! 客户端启动后在`127.0.0.1`上监听一个随机端口，并把端口和一次性令牌写入用户数据目录下的
+//! `instance.json`；转发时逐行发送`{"token":"...","url":"kimi://..."}`，客户端回复`ok`。
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::autostart::desktop_quote;
+
+/// 协议名
+pub const SCHEME: &str = "kimi";
+/// kimi:// 协议在Windows上的注册位置
+pub const PROTOCOL_CLASS_KEY: &str = r"HKCU\Software\Classes\kimi";
+/// kimi:// 协议在Linux上的桌面文件名
+pub const PROTOCOL_DESKTOP_FILE: &str = "openkimi-url-handler.desktop";
+/// 客户端写入的实例信息文件
+pub const INSTANCE_FILE: &str = "instance.json";
+
+/// 连接运行中实例的超时时间
+const HANDOFF_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// 协议注册状态
+#[derive(Debug, Serialize)]
+pub struct RegistrationStatus {
+    pub registered: bool,
+    /// 打开链接时执行的命令
+    pub command: Option<String>,
+    /// 注册所在位置（注册表键或文件路径）
+    pub location: String,
+}
+
+/// 运行中实例写入的连接信息
+#[derive(Debug, Deserialize)]
+struct InstanceInfo {
+    port: u16,
+    token: String,
+}
+
+/// 链接的处理结果
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Handoff {
+    /// 已转交给运行中的实例
+    Forwarded,
+    /// 没有运行中的实例，已启动新的客户端
+    Launched,
+}
+
+/// Linux上kimi://处理程序的桌面文件
+pub fn protocol_desktop_path() -> Option<PathBuf> {
+    // 按XDG规范，空值等同于未设置
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))?;
+    Some(data_home.join("applications").join(PROTOCOL_DESKTOP_FILE))
+}
+
+fn desktop_path() -> io::Result<PathBuf> {
+    protocol_desktop_path().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "环境变量 HOME 未设置"))
+}
+
+fn reg_add(key: &str, name: Option<&str>, value: &str) -> io::Result<()> {
+    let mut command = Command::new("reg");
+    command.args(["add", key]);
+    match name {
+        Some(name) => command.args(["/v", name]),
+        None => command.arg("/ve"),
+    };
+    let output = command.args(["/t", "REG_SZ", "/d", value, "/f"]).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("写入注册表失败: {}", key)));
+    }
+    Ok(())
+}
+
+/// macOS上定位客户端所在的`.app`
+fn app_bundle(exe: &Path) -> Option<&Path> {
+    exe.ancestors()
+        .find(|dir| dir.extension().is_some_and(|ext| ext == "app"))
+}
+
+/// 注册kimi://协议
+///
+/// `handler`为本辅助程序的路径，`exe`为客户端可执行文件，转发失败时用于启动客户端。
+pub fn register(handler: &Path, exe: &Path) -> io::Result<RegistrationStatus> {
+    if cfg!(windows) {
+        let command = format!(
+            "\"{}\" deeplink open --exe \"{}\" \"%1\"",
+            handler.display(),
+            exe.display()
+        );
+        reg_add(PROTOCOL_CLASS_KEY, None, "URL:OpenKimi")?;
+        reg_add(PROTOCOL_CLASS_KEY, Some("URL Protocol"), "")?;
+        reg_add(&format!(r"{}\DefaultIcon", PROTOCOL_CLASS_KEY), None, &format!("\"{}\",0", exe.display()))?;
+        reg_add(&format!(r"{}\shell\open\command", PROTOCOL_CLASS_KEY), None, &command)?;
+    } else if cfg!(target_os = "macos") {
+        let bundle = app_bundle(exe)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "客户端不在 .app 包中"))?;
+        let lsregister = "/System/Library/Frameworks/CoreServices.framework/Frameworks/LaunchServices.framework/Support/lsregister";
+        Command::new(lsregister).arg("-f").arg(bundle).output()?;
+    } else {
+        let path = desktop_path()?;
+        let entry = format!(
+            "[Desktop Entry]\nType=Application\nName=OpenKimi\nExec={} deeplink open --exe {} %u\nMimeType=x-scheme-handler/{};\nNoDisplay=true\n",
+            desktop_quote(&handler.to_string_lossy()),
+            desktop_quote(&exe.to_string_lossy()),
+            SCHEME
+        );
+        fs::create_dir_all(path.parent().unwrap_or(Path::new(".")))?;
+        fs::write(&path, entry)?;
+        // xdg-mime不存在时文件本身已足够让多数桌面环境识别
+        let _ = Command::new("xdg-mime")
+            .args(["default", PROTOCOL_DESKTOP_FILE, &format!("x-scheme-handler/{}", SCHEME)])
+            .output();
+    }
+    status(exe)
+}
+
+/// 取消kimi://协议注册
+pub fn unregister(exe: &Path) -> io::Result<RegistrationStatus> {
+    if cfg!(windows) {
+        // 键不存在时reg delete返回失败，视为已取消
+        Command::new("reg").args(["delete", PROTOCOL_CLASS_KEY, "/f"]).output()?;
+    } else if !cfg!(target_os = "macos") {
+        match fs::remove_file(desktop_path()?) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+    }
+    status(exe)
+}
+
+/// 查询kimi://协议注册状态
+pub fn status(exe: &Path) -> io::Result<RegistrationStatus> {
+    if cfg!(windows) {
+        let key = format!(r"{}\shell\open\command", PROTOCOL_CLASS_KEY);
+        let output = Command::new("reg").args(["query", &key, "/ve"]).output()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let command = stdout.lines().find_map(|line| {
+            let (_, value) = line.trim().split_once("REG_SZ")?;
+            Some(value.trim().to_string())
+        });
+        return Ok(RegistrationStatus {
+            registered: command.is_some(),
+            command,
+            location: PROTOCOL_CLASS_KEY.to_string(),
+        });
+    }
+
+    if cfg!(target_os = "macos") {
+        // 由Info.plist中的CFBundleURLTypes声明，存在.app即视为已注册
+        let bundle = app_bundle(exe);
+        return Ok(RegistrationStatus {
+            registered: bundle.is_some(),
+            command: None,
+            location: bundle.map(|dir| dir.to_string_lossy().into_owned()).unwrap_or_default(),
+        });
+    }
+
+    let path = desktop_path()?;
+    let command = fs::read_to_string(&path).ok().and_then(|content| {
+        content
+            .lines()
+            .find_map(|line| line.strip_prefix("Exec=").map(str::to_string))
+    });
+    Ok(RegistrationStatus {
+        registered: command.is_some(),
+        command,
+        location: path.to_string_lossy().into_owned(),
+    })
+}
+
+/// 检查链接是否为kimi://协议
+pub fn validate_url(url: &str) -> Result<(), String> {
+    let prefix = format!("{}://", SCHEME);
+    let matches = url
+        .get(..prefix.len())
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case(&prefix));
+    if matches && url.len() > prefix.len() {
+        Ok(())
+    } else {
+        Err(format!("不是有效的 {} 链接: {}", prefix, url))
+    }
+}
+
+/// 尝试把链接转交给运行中的实例
+fn forward(data_dir: &Path, url: &str) -> io::Result<()> {
+    let content = fs::read_to_string(data_dir.join(INSTANCE_FILE))?;
+    let instance: InstanceInfo = serde_json::from_str(&content)?;
+
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, instance.port));
+    let mut stream = TcpStream::connect_timeout(&addr, HANDOFF_TIMEOUT)?;
+    stream.set_read_timeout(Some(HANDOFF_TIMEOUT))?;
+    writeln!(stream, "{}", json!({ "token": instance.token, "url": url }))?;
+
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply)?;
+    if reply.trim() != "ok" {
+        return Err(io::Error::other(format!("客户端拒绝了链接: {}", reply.trim())));
+    }
+    Ok(())
+}
+
+/// 打开kimi://链接：优先转交给运行中的实例，否则启动客户端并把链接作为参数传入
+///
+/// 实例信息文件残留但进程已退出时连接会失败，同样回退为启动客户端。
+pub fn open(exe: &Path, data_dir: &Path, url: &str) -> Result<Handoff, String> {
+    validate_url(url)?;
+    if forward(data_dir, url).is_ok() {
+        return Ok(Handoff::Forwarded);
+    }
+
+    Command::new(exe)
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("启动客户端失败: {}", e))?;
+    Ok(Handoff::Launched)
+}
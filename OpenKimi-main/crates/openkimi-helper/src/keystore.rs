@@ -0,0 +1,171 @@
+//! 本地密钥存储
+//!
+//! 客户端保存的API密钥等秘密按名称存放在用户数据目录下的`secrets.json`，每项单独用AES-256-GCM加密，
+//! 名称作为附加数据，防止在文件中调换。主密钥由调用方提供：Electron客户端用`safeStorage`
+//! （Windows DPAPI、macOS钥匙串、Linux Secret Service）加密后保存，不与密文放在一起。
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+const SECRETS_FILE: &str = "secrets.json";
+
+const SECRETS_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Sealed {
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SecretsFile {
+    version: u32,
+    secrets: BTreeMap<String, Sealed>,
+}
+
+/// 生成新的主密钥
+pub fn generate_master_key() -> Result<[u8; 32], String> {
+    let mut key = [0u8; 32];
+    getrandom::getrandom(&mut key).map_err(|e| format!("生成随机数失败: {}", e))?;
+    Ok(key)
+}
+
+/// 写入只有当前用户可读的文件，先写临时文件再重命名
+fn write_private(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, content)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(0o600))?;
+    }
+    fs::rename(&tmp, path)
+}
+
+/// 加密保存的秘密，每次修改立即写回文件
+pub struct KeyStore {
+    path: PathBuf,
+    cipher: Aes256Gcm,
+    file: SecretsFile,
+}
+
+impl std::fmt::Debug for KeyStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyStore")
+            .field("path", &self.path)
+            .field("secrets", &self.file.secrets.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl KeyStore {
+    /// 打开`data_dir`下的密钥存储，文件不存在时为空
+    pub fn open(data_dir: &Path, master_key: &[u8]) -> Result<KeyStore, String> {
+        if master_key.len() != 32 {
+            return Err("主密钥必须是32字节".to_string());
+        }
+        let path = data_dir.join(SECRETS_FILE);
+        let file = match fs::read(&path) {
+            Ok(content) => serde_json::from_slice::<SecretsFile>(&content)
+                .map_err(|e| format!("解析密钥存储 {} 失败: {}", path.display(), e))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => SecretsFile {
+                version: SECRETS_VERSION,
+                secrets: BTreeMap::new(),
+            },
+            Err(err) => return Err(format!("读取密钥存储 {} 失败: {}", path.display(), err)),
+        };
+        if file.version > SECRETS_VERSION {
+            return Err(format!("密钥存储格式 v{} 比当前程序支持的更新", file.version));
+        }
+        Ok(KeyStore {
+            path,
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(master_key)),
+            file,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 已保存的秘密名称，不解密
+    pub fn names(&self) -> Vec<String> {
+        self.file.secrets.keys().cloned().collect()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.file.secrets.contains_key(name)
+    }
+
+    /// 解密读取，不存在时为`None`；主密钥不对时返回错误
+    pub fn get(&self, name: &str) -> Result<Option<String>, String> {
+        let Some(sealed) = self.file.secrets.get(name) else {
+            return Ok(None);
+        };
+        let invalid = || format!("无法解密 {}，主密钥不正确或密钥存储已损坏", name);
+        let nonce = STANDARD.decode(&sealed.nonce).map_err(|_| invalid())?;
+        let ciphertext = STANDARD.decode(&sealed.ciphertext).map_err(|_| invalid())?;
+        if nonce.len() != 12 {
+            return Err(invalid());
+        }
+        let plaintext = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &ciphertext,
+                    aad: name.as_bytes(),
+                },
+            )
+            .map_err(|_| invalid())?;
+        String::from_utf8(plaintext).map(Some).map_err(|_| invalid())
+    }
+
+    /// 保存秘密，同名的覆盖
+    pub fn set(&mut self, name: &str, secret: &str) -> Result<(), String> {
+        let mut nonce = [0u8; 12];
+        getrandom::getrandom(&mut nonce).map_err(|e| format!("生成随机数失败: {}", e))?;
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: secret.as_bytes(),
+                    aad: name.as_bytes(),
+                },
+            )
+            .map_err(|_| format!("加密 {} 失败", name))?;
+        self.file.secrets.insert(
+            name.to_string(),
+            Sealed {
+                nonce: STANDARD.encode(nonce),
+                ciphertext: STANDARD.encode(ciphertext),
+            },
+        );
+        self.save()
+    }
+
+    /// 删除秘密，返回是否存在
+    pub fn delete(&mut self, name: &str) -> Result<bool, String> {
+        if self.file.secrets.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let content = serde_json::to_vec_pretty(&self.file).map_err(|e| e.to_string())?;
+        write_private(&self.path, &content).map_err(|e| format!("写入密钥存储 {} 失败: {}", self.path.display(), e))
+    }
+}
@@ -0,0 +1,15 @@
+//! OpenKimi桌面客户端的本地功能
+//!
+//! 供`openkimi-helper`（客户端调用的辅助程序）、`openkimi-cleanup`（卸载清理工具）、在线安装程序`openkimi-setup`、数据迁移工具`migrate`和Node原生模块`openkimi-node`共用。
+
+pub mod autostart;
+pub mod bundle;
+pub mod cleanup;
+pub mod deeplink;
+pub mod keystore;
+pub mod license;
+pub mod paths;
+pub mod payload;
+pub mod policy;
+pub mod portable;
+pub mod update;
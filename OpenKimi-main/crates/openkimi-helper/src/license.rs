@@ -0,0 +1,59 @@
+//! 企业版许可证的安装与状态查询
+//!
+//! 许可证密钥保存在用户数据目录下的`license.key`，便携模式时由客户端通过`--data-dir`传入数据目录。
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use openkimi_licensing::{LicenseVerifier, Validation};
+use serde::Serialize;
+
+const LICENSE_FILE: &str = "license.key";
+
+/// 许可证查询结果
+#[derive(Debug, Serialize)]
+pub struct LicenseReport {
+    pub installed: bool,
+    pub path: PathBuf,
+    pub validation: Option<Validation>,
+}
+
+fn verifier() -> Result<LicenseVerifier, String> {
+    LicenseVerifier::embedded().map_err(|e| e.to_string())
+}
+
+/// 查询已安装的许可证状态
+pub fn status(data_dir: &Path) -> Result<LicenseReport, String> {
+    let path = data_dir.join(LICENSE_FILE);
+    let Ok(license_key) = fs::read_to_string(&path) else {
+        return Ok(LicenseReport {
+            installed: false,
+            path,
+            validation: None,
+        });
+    };
+
+    let validation = verifier()?.validate(&license_key).map_err(|e| e.to_string())?;
+    Ok(LicenseReport {
+        installed: true,
+        path,
+        validation: Some(validation),
+    })
+}
+
+/// 校验并安装许可证，校验失败时不会覆盖已有许可证
+pub fn install(data_dir: &Path, license_key: &str) -> Result<LicenseReport, String> {
+    let validation = verifier()?.validate(license_key).map_err(|e| e.to_string())?;
+    if !validation.status.is_usable() {
+        return Err("许可证已过期".to_string());
+    }
+
+    fs::create_dir_all(data_dir).map_err(|e| e.to_string())?;
+    let path = data_dir.join(LICENSE_FILE);
+    fs::write(&path, license_key.trim()).map_err(|e| e.to_string())?;
+    Ok(LicenseReport {
+        installed: true,
+        path,
+        validation: Some(validation),
+    })
+}
@@ -0,0 +1,194 @@
+//! OpenKimi桌面客户端辅助程序
+//!
+//! 由Electron主进程以子进程方式调用，每次调用执行一个命令，并在标准输出打印一行JSON结果：
+//! 成功时为`{"ok":true,"result":...}`，失败时为`{"ok":false,"error":"..."}`。
+//!
+//! 以`--stdio`启动时作为常驻进程运行，每行读取一个请求`{"id":1,"args":["autostart","status"]}`，
+//! 并输出带相同`id`的一行响应，省去频繁调用时反复启动进程的开销。
+
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use openkimi_helper::{autostart, deeplink, license, paths, policy, portable};
+
+/// 命令执行错误
+type CommandResult = Result<Value, String>;
+
+fn to_value<T: Serialize>(value: T) -> CommandResult {
+    serde_json::to_value(value).map_err(|e| e.to_string())
+}
+
+/// 读取`--name <value>`形式的参数
+fn option_value(args: &[String], name: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|index| args.get(index + 1))
+        .cloned()
+}
+
+fn has_flag(args: &[String], name: &str) -> bool {
+    args.iter().any(|arg| arg == name)
+}
+
+/// 客户端可执行文件路径，默认为辅助程序自身
+fn exe_path(args: &[String]) -> Result<PathBuf, String> {
+    match option_value(args, "--exe") {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => env::current_exe().map_err(|e| e.to_string()),
+    }
+}
+
+fn run_portable(args: &[String]) -> CommandResult {
+    let exe = exe_path(args)?;
+    match args.first().map(String::as_str) {
+        Some("status") => to_value(portable::detect(&exe)),
+        Some("migrate") => {
+            let report = portable::migrate(&exe, has_flag(args, "--move")).map_err(|e| e.to_string())?;
+            to_value(report)
+        }
+        _ => Err("用法: openkimi-helper portable <status|migrate> [--exe <路径>] [--move]".to_string()),
+    }
+}
+
+/// 用户数据目录，便携模式下由客户端通过`--data-dir`指定
+fn data_dir(args: &[String]) -> Result<PathBuf, String> {
+    match option_value(args, "--data-dir") {
+        Some(dir) => Ok(PathBuf::from(dir)),
+        None => paths::default_user_data_dir().ok_or_else(|| "无法确定用户数据目录".to_string()),
+    }
+}
+
+fn run_license(args: &[String]) -> CommandResult {
+    let data_dir = data_dir(args)?;
+    match args.first().map(String::as_str) {
+        Some("status") => to_value(license::status(&data_dir)?),
+        Some("install") => {
+            let key = args.get(1).ok_or("缺少许可证密钥")?;
+            to_value(license::install(&data_dir, key)?)
+        }
+        _ => Err("用法: openkimi-helper license <status|install <密钥>> [--data-dir <目录>]".to_string()),
+    }
+}
+
+fn run_autostart(args: &[String]) -> CommandResult {
+    let result = match args.first().map(String::as_str) {
+        Some("enable") => autostart::enable(&exe_path(args)?, has_flag(args, "--hidden")),
+        Some("disable") => autostart::disable(),
+        Some("status") => autostart::status(),
+        _ => return Err("用法: openkimi-helper autostart <enable|disable|status> [--exe <路径>] [--hidden]".to_string()),
+    };
+    to_value(result.map_err(|e| e.to_string())?)
+}
+
+/// 打开链接时使用的数据目录：显式指定优先，其次是客户端的便携数据目录
+fn instance_data_dir(args: &[String], exe: &std::path::Path) -> Result<PathBuf, String> {
+    if option_value(args, "--data-dir").is_some() {
+        return data_dir(args);
+    }
+    match portable::detect(exe).data_dir {
+        Some(dir) => Ok(dir),
+        None => data_dir(args),
+    }
+}
+
+fn run_deeplink(args: &[String]) -> CommandResult {
+    let exe = exe_path(args)?;
+    let result = match args.first().map(String::as_str) {
+        Some("register") => {
+            let handler = env::current_exe().map_err(|e| e.to_string())?;
+            deeplink::register(&handler, &exe)
+        }
+        Some("unregister") => deeplink::unregister(&exe),
+        Some("status") => deeplink::status(&exe),
+        Some("open") => {
+            // 链接是唯一不以--开头、也不是选项值的参数
+            let url = args[1..]
+                .iter()
+                .enumerate()
+                .find(|(index, arg)| !arg.starts_with("--") && !matches!(args[*index].as_str(), "--exe" | "--data-dir"))
+                .map(|(_, arg)| arg)
+                .ok_or("缺少要打开的链接")?;
+            let data_dir = instance_data_dir(args, &exe)?;
+            return to_value(deeplink::open(&exe, &data_dir, url)?);
+        }
+        _ => {
+            return Err(
+                "用法: openkimi-helper deeplink <register|unregister|status|open <链接>> [--exe <路径>] [--data-dir <目录>]"
+                    .to_string(),
+            )
+        }
+    };
+    to_value(result.map_err(|e| e.to_string())?)
+}
+
+fn run(args: &[String]) -> CommandResult {
+    match args.first().map(String::as_str) {
+        Some("portable") => run_portable(&args[1..]),
+        Some("license") => run_license(&args[1..]),
+        Some("autostart") => run_autostart(&args[1..]),
+        Some("deeplink") => run_deeplink(&args[1..]),
+        Some("policy") => to_value(policy::read()),
+        Some(other) => Err(format!("未知命令: {}", other)),
+        None => Err("用法: openkimi-helper <portable|license|autostart|deeplink|policy> ...".to_string()),
+    }
+}
+
+/// 生成一行JSON响应
+fn response(id: Value, result: CommandResult) -> Value {
+    match result {
+        Ok(result) => json!({ "id": id, "ok": true, "result": result }),
+        Err(error) => json!({ "id": id, "ok": false, "error": error }),
+    }
+}
+
+/// 常驻模式：逐行处理标准输入中的请求，直到标准输入关闭
+fn serve_stdio() -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut stdout = io::stdout().lock();
+    for line in stdin.lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let reply = match serde_json::from_str::<Value>(&line) {
+            Ok(request) => {
+                let id = request.get("id").cloned().unwrap_or(Value::Null);
+                match serde_json::from_value::<Vec<String>>(request.get("args").cloned().unwrap_or(Value::Null)) {
+                    Ok(args) => response(id, run(&args)),
+                    Err(_) => response(id, Err("请求缺少 args 字符串数组".to_string())),
+                }
+            }
+            Err(err) => response(Value::Null, Err(format!("无效的JSON请求: {}", err))),
+        };
+        writeln!(stdout, "{}", reply)?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if has_flag(&args, "--stdio") {
+        return match serve_stdio() {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(_) => ExitCode::FAILURE,
+        };
+    }
+
+    match run(&args) {
+        Ok(result) => {
+            println!("{}", json!({ "ok": true, "result": result }));
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            println!("{}", json!({ "ok": false, "error": error }));
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use std::env;
+use std::path::PathBuf;
+
+/// 与package.json中的`productName`保持一致，Electron用它作为数据目录名
+pub const PRODUCT_NAME: &str = "OpenKimi";
+
+/// Electron默认的`userData`目录
+pub fn default_user_data_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        PathBuf::from(env::var_os("APPDATA")?)
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from(env::var_os("HOME")?).join("Library/Application Support")
+    } else {
+        match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        }
+    };
+    Some(base.join(PRODUCT_NAME))
+}
+
+/// 用户数据目录之外的缓存与日志目录
+pub fn cache_and_log_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if cfg!(windows) {
+        if let Some(local) = env::var_os("LOCALAPPDATA") {
+            dirs.push(PathBuf::from(local).join(PRODUCT_NAME));
+        }
+    } else if let Some(home) = env::var_os("HOME") {
+        let home = PathBuf::from(home);
+        if cfg!(target_os = "macos") {
+            dirs.push(home.join("Library/Caches").join(PRODUCT_NAME));
+            dirs.push(home.join("Library/Logs").join(PRODUCT_NAME));
+        } else {
+            let cache = env::var_os("XDG_CACHE_HOME")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| home.join(".cache"));
+            dirs.push(cache.join(PRODUCT_NAME));
+        }
+    }
+    dirs
+}
@@ -0,0 +1,288 @@
+//! 企业托管策略
+//!
+//! 普通用户不能修改的位置：
+//! - Windows：企业MSI或组策略写入的`HKLM\SOFTWARE\Policies\OpenKimi`
+//! - macOS：MDM配置描述文件下发的`/Library/Managed Preferences/com.example.kimi-client.plist`
+//! - Linux：管理员或配置管理工具写入的`/etc/openkimi/policy.json`
+//!
+//! 三处使用相同的值名称，如`{"DisableAutoUpdate": true, "UpdateQuietHours": "22:00-06:00"}`。
+
+use std::fmt;
+use std::fs;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Serializer};
+
+const POLICY_KEY: &str = r"HKLM\SOFTWARE\Policies\OpenKimi";
+/// 偏好设置的域与客户端的`appId`相同
+const MANAGED_PREFERENCES: &str = "/Library/Managed Preferences/com.example.kimi-client.plist";
+const POLICY_FILE: &str = "/etc/openkimi/policy.json";
+/// 系统时区，`ActiveTimeBias`为UTC减去本地时间的分钟数，已计入夏令时
+const TIME_ZONE_KEY: &str = r"HKLM\SYSTEM\CurrentControlSet\Control\TimeZoneInformation";
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// 每天的一个本地时段，`end`早于`start`时跨过午夜，如`22:00-06:00`；序列化为同样格式的字符串
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    /// 开始时间，午夜起的分钟数
+    pub start: u32,
+    /// 结束时间（不含），午夜起的分钟数
+    pub end: u32,
+}
+
+impl QuietHours {
+    /// 解析`HH:MM-HH:MM`
+    pub fn parse(value: &str) -> Option<QuietHours> {
+        let minutes = |time: &str| -> Option<u32> {
+            let (hours, minutes) = time.trim().split_once(':')?;
+            let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+            (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+        };
+        let (start, end) = value.split_once('-')?;
+        Some(QuietHours {
+            start: minutes(start)?,
+            end: minutes(end)?,
+        })
+    }
+
+    /// `minute`（午夜起的分钟数）是否在时段内
+    pub fn contains(&self, minute: u32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+
+    /// 当前本地时间是否在时段内
+    pub fn is_now(&self) -> bool {
+        self.contains(local_minute())
+    }
+}
+
+impl fmt::Display for QuietHours {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}-{:02}:{:02}", self.start / 60, self.start % 60, self.end / 60, self.end % 60)
+    }
+}
+
+impl Serialize for QuietHours {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// 托管配置，未配置的项为默认值
+#[derive(Debug, Default, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManagedPolicy {
+    /// 禁止客户端检查和下载更新，由企业统一分发新版本
+    pub disable_auto_update: bool,
+    /// 固定使用的服务端地址
+    pub api_endpoint: Option<String>,
+    /// 下载更新的最大速度（千比特每秒）
+    pub max_download_kbps: Option<u32>,
+    /// 不下载更新的本地时段
+    pub update_quiet_hours: Option<QuietHours>,
+}
+
+/// 解析`reg query`的输出为`(名称, 数据)`，每个值一行：`名称    REG_类型    数据`
+fn values(output: &str) -> impl Iterator<Item = (&str, String)> {
+    output.lines().filter_map(|line| {
+        let mut parts = line.split_whitespace();
+        let (Some(name), Some(kind)) = (parts.next(), parts.next()) else {
+            return None;
+        };
+        kind.starts_with("REG_").then(|| (name, parts.collect::<Vec<_>>().join(" ")))
+    })
+}
+
+/// REG_DWORD显示为`0x1`
+fn dword(data: &str) -> Option<u32> {
+    match data.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => data.parse().ok(),
+    }
+}
+
+fn reg_query(key: &str) -> Option<String> {
+    // 键不存在时reg返回非零退出码
+    match Command::new("reg").args(["query", key]).output() {
+        Ok(output) if output.status.success() => Some(String::from_utf8_lossy(&output.stdout).into_owned()),
+        _ => None,
+    }
+}
+
+/// 当前本地时间，午夜起的分钟数；读不到系统时区时按UTC计算
+fn local_minute() -> u32 {
+    let utc = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() / 60) as i64;
+    let bias = if cfg!(windows) {
+        reg_query(TIME_ZONE_KEY)
+            .and_then(|output| {
+                values(&output).find(|(name, _)| *name == "ActiveTimeBias").and_then(|(_, data)| dword(&data))
+            })
+            // 东区的偏移为负数，以补码形式存储
+            .map_or(0, |bias| bias as i32 as i64)
+    } else {
+        // `date +%z`输出`+0800`形式的UTC偏移
+        Command::new("date")
+            .arg("+%z")
+            .output()
+            .ok()
+            .and_then(|output| utc_offset(String::from_utf8_lossy(&output.stdout).trim()))
+            .map_or(0, |offset| -offset)
+    };
+    (utc - bias).rem_euclid(MINUTES_PER_DAY as i64) as u32
+}
+
+/// 解析`+0800`、`-0530`为分钟数
+fn utc_offset(value: &str) -> Option<i64> {
+    let (sign, digits) = match value.split_at_checked(1)? {
+        ("+", digits) => (1, digits),
+        ("-", digits) => (-1, digits),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes): (i64, i64) = (digits[..2].parse().ok()?, digits[2..].parse().ok()?);
+    Some(sign * (hours * 60 + minutes))
+}
+
+/// 策略文件中的值转为与注册表相同的文本形式，布尔值为`1`或`0`
+fn json_values(json: &str) -> Vec<(String, String)> {
+    let Ok(serde_json::Value::Object(map)) = serde_json::from_str(json) else {
+        return Vec::new();
+    };
+    map.into_iter()
+        .filter_map(|(name, value)| {
+            let data = match value {
+                serde_json::Value::Bool(flag) => u8::from(flag).to_string(),
+                serde_json::Value::Number(number) => number.to_string(),
+                serde_json::Value::String(text) => text,
+                _ => return None,
+            };
+            Some((name, data))
+        })
+        .collect()
+}
+
+/// 托管偏好设置是二进制或XML格式的plist，用系统自带的`plutil`转为JSON
+fn read_plist(path: &str) -> Option<String> {
+    match Command::new("plutil").args(["-convert", "json", "-o", "-", path]).output() {
+        Ok(output) if output.status.success() => Some(String::from_utf8_lossy(&output.stdout).into_owned()),
+        _ => None,
+    }
+}
+
+fn parse<'a>(values: impl IntoIterator<Item = (&'a str, String)>) -> ManagedPolicy {
+    let mut policy = ManagedPolicy::default();
+    for (name, data) in values {
+        match name {
+            "DisableAutoUpdate" => policy.disable_auto_update = dword(&data) == Some(1),
+            "ApiEndpoint" => policy.api_endpoint = Some(data).filter(|value| !value.is_empty()),
+            // 0表示不限速
+            "MaxDownloadKbps" => policy.max_download_kbps = dword(&data).filter(|kbps| *kbps > 0),
+            "UpdateQuietHours" => policy.update_quiet_hours = QuietHours::parse(&data),
+            _ => {}
+        }
+    }
+    policy
+}
+
+/// 读取托管策略，策略不存在或无法解析时视为未配置
+pub fn read() -> ManagedPolicy {
+    if cfg!(windows) {
+        return reg_query(POLICY_KEY).map_or_else(ManagedPolicy::default, |output| parse(values(&output)));
+    }
+    let json = if cfg!(target_os = "macos") {
+        read_plist(MANAGED_PREFERENCES)
+    } else {
+        fs::read_to_string(POLICY_FILE).ok()
+    };
+    json.map_or_else(ManagedPolicy::default, |json| {
+        parse(json_values(&json).iter().map(|(name, data)| (name.as_str(), data.clone())))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_json(json: &str) -> ManagedPolicy {
+        parse(json_values(json).iter().map(|(name, data)| (name.as_str(), data.clone())))
+    }
+
+    #[test]
+    fn policy_file_uses_registry_value_names() {
+        let policy = parse_json(
+            r#"{"DisableAutoUpdate": true, "ApiEndpoint": "https://kimi.corp", "MaxDownloadKbps": 512,
+                "UpdateQuietHours": "22:00-06:00", "Unknown": [1]}"#,
+        );
+        assert!(policy.disable_auto_update);
+        assert_eq!(policy.api_endpoint.as_deref(), Some("https://kimi.corp"));
+        assert_eq!(policy.max_download_kbps, Some(512));
+        assert_eq!(policy.update_quiet_hours, QuietHours::parse("22:00-06:00"));
+
+        let policy = parse_json(r#"{"DisableAutoUpdate": false, "MaxDownloadKbps": "0"}"#);
+        assert!(!policy.disable_auto_update);
+        assert_eq!(policy.max_download_kbps, None);
+        assert!(!parse_json("not json").disable_auto_update);
+    }
+
+    #[test]
+    fn registry_output() {
+        let output = concat!(
+            "\r\nHKEY_LOCAL_MACHINE\\SOFTWARE\\Policies\\OpenKimi\r\n",
+            "    DisableAutoUpdate    REG_DWORD    0x1\r\n",
+            "    ApiEndpoint    REG_SZ    https://kimi.corp\r\n",
+        );
+        let policy = parse(values(output));
+        assert!(policy.disable_auto_update);
+        assert_eq!(policy.api_endpoint.as_deref(), Some("https://kimi.corp"));
+    }
+
+    #[test]
+    fn utc_offsets() {
+        assert_eq!(utc_offset("+0800"), Some(480));
+        assert_eq!(utc_offset("-0530"), Some(-330));
+        assert_eq!(utc_offset("0800"), None);
+        assert_eq!(utc_offset(""), None);
+    }
+
+    #[test]
+    fn quiet_hours_parse() {
+        let hours = QuietHours::parse("22:00-06:30").unwrap();
+        assert_eq!((hours.start, hours.end), (22 * 60, 6 * 60 + 30));
+        assert_eq!(hours.to_string(), "22:00-06:30");
+        assert_eq!(QuietHours::parse(" 9:05 - 17:00 ").unwrap().to_string(), "09:05-17:00");
+        for invalid in ["", "22:00", "24:00-06:00", "22:60-06:00", "22-06", "22:00-06:00-07:00", "a:b-c:d"] {
+            assert_eq!(QuietHours::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn quiet_hours_contains() {
+        let day = QuietHours::parse("09:00-17:00").unwrap();
+        assert!(!day.contains(8 * 60 + 59));
+        assert!(day.contains(9 * 60));
+        assert!(day.contains(16 * 60 + 59));
+        assert!(!day.contains(17 * 60));
+
+        // 跨过午夜
+        let night = QuietHours::parse("22:00-06:00").unwrap();
+        assert!(night.contains(22 * 60));
+        assert!(night.contains(23 * 60 + 59));
+        assert!(night.contains(0));
+        assert!(night.contains(5 * 60 + 59));
+        assert!(!night.contains(6 * 60));
+        assert!(!night.contains(12 * 60));
+        assert!(!night.contains(21 * 60 + 59));
+
+        // 起止相同表示空时段
+        assert!(!QuietHours::parse("08:00-08:00").unwrap().contains(8 * 60));
+    }
+
+}
@@ -0,0 +1,148 @@
+//! 便携模式
+//!
+//! 可执行文件旁存在`portable`标记（文件或目录）时，配置、缓存和聊天记录都保存在同级的`data/`目录中，
+//! 而不是系统的用户数据目录。已有安装切换到便携模式时，可以通过`migrate`将旧数据复制过来。
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::paths::default_user_data_dir;
+
+const MARKER_NAME: &str = "portable";
+const DATA_DIR_NAME: &str = "data";
+/// 迁移完成后写入数据目录的标记，记录数据来源
+const MIGRATED_MARKER: &str = ".migrated-from";
+
+/// Electron运行时的锁文件，复制它们会导致新目录无法启动
+pub const SKIPPED_FILES: [&str; 4] = ["SingletonLock", "SingletonSocket", "SingletonCookie", "lockfile"];
+
+/// 便携模式检测结果
+#[derive(Debug, Serialize)]
+pub struct PortableStatus {
+    pub portable: bool,
+    pub app_dir: PathBuf,
+    pub data_dir: Option<PathBuf>,
+    pub default_dir: Option<PathBuf>,
+    pub needs_migration: bool,
+}
+
+/// 迁移结果
+#[derive(Debug, Serialize)]
+pub struct MigrationReport {
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    pub copied_files: u64,
+    pub removed_source: bool,
+}
+
+/// 确定放置`portable`标记和`data/`的应用目录
+///
+/// - AppImage运行时可执行文件位于临时挂载点，使用`APPIMAGE`指向的真实文件所在目录
+/// - macOS上跳出`.app`包，避免写入包内破坏签名
+pub fn app_dir_for(exe: &Path) -> PathBuf {
+    if let Some(appimage) = env::var_os("APPIMAGE") {
+        if let Some(dir) = Path::new(&appimage).parent() {
+            return dir.to_path_buf();
+        }
+    }
+
+    let exe_dir = exe.parent().unwrap_or(Path::new(".")).to_path_buf();
+    let bundle = exe_dir
+        .ancestors()
+        .find(|dir| dir.extension().is_some_and(|ext| ext == "app"));
+    match bundle.and_then(Path::parent) {
+        Some(dir) => dir.to_path_buf(),
+        None => exe_dir,
+    }
+}
+
+/// 检测便携模式
+pub fn detect(exe: &Path) -> PortableStatus {
+    let app_dir = app_dir_for(exe);
+    let portable = app_dir.join(MARKER_NAME).exists();
+    let default_dir = default_user_data_dir();
+
+    if !portable {
+        return PortableStatus {
+            portable,
+            app_dir,
+            data_dir: None,
+            default_dir,
+            needs_migration: false,
+        };
+    }
+
+    let data_dir = app_dir.join(DATA_DIR_NAME);
+    let data_is_empty = fs::read_dir(&data_dir)
+        .map(|mut entries| entries.next().is_none())
+        .unwrap_or(true);
+    let needs_migration = data_is_empty && default_dir.as_deref().is_some_and(Path::is_dir);
+
+    PortableStatus {
+        portable,
+        app_dir,
+        data_dir: Some(data_dir),
+        default_dir,
+        needs_migration,
+    }
+}
+
+/// 递归复制目录，返回复制的文件数
+fn copy_tree(src: &Path, dst: &Path) -> io::Result<u64> {
+    fs::create_dir_all(dst)?;
+    let mut copied = 0;
+
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if SKIPPED_FILES.iter().any(|skipped| name == *skipped) {
+            continue;
+        }
+
+        let src_path = entry.path();
+        let dst_path = dst.join(&name);
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            copied += copy_tree(&src_path, &dst_path)?;
+        } else if file_type.is_file() {
+            fs::copy(&src_path, &dst_path)?;
+            copied += 1;
+        }
+    }
+
+    Ok(copied)
+}
+
+/// 将系统用户数据目录迁移到便携数据目录
+///
+/// 默认只复制不删除，`move_source`为true时迁移成功后删除旧目录。
+pub fn migrate(exe: &Path, move_source: bool) -> io::Result<MigrationReport> {
+    let status = detect(exe);
+    let destination = status.data_dir.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "未启用便携模式，缺少 portable 标记")
+    })?;
+    let source = status
+        .default_dir
+        .filter(|dir| dir.is_dir())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "没有需要迁移的用户数据"))?;
+
+    if destination.join(MIGRATED_MARKER).exists() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, "数据目录已经迁移过"));
+    }
+
+    let copied_files = copy_tree(&source, &destination)?;
+    fs::write(destination.join(MIGRATED_MARKER), source.to_string_lossy().as_bytes())?;
+
+    let removed_source = move_source && fs::remove_dir_all(&source).is_ok();
+
+    Ok(MigrationReport {
+        source,
+        destination,
+        copied_files,
+        removed_source,
+    })
+}
@@ -0,0 +1,158 @@
+//! 客户端更新
+//!
+//! 与在线安装程序读取同一份发布清单`latest-<平台>.json`，目前只发布Windows版本，清单和下载见[`crate::payload`]。
+//! 托管策略禁止自动更新时不访问网络；策略设置了最大下载速度和静默时段时，下载按此限速，静默时段内不下载。
+
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::payload::{self, Limits, PayloadManifest};
+use crate::policy::ManagedPolicy;
+
+/// 检查更新的结果
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateStatus {
+    pub current_version: String,
+    /// 托管策略禁止更新时为`true`，此时不检查
+    pub disabled: bool,
+    /// 处于托管策略的静默时段，有新版本也应稍后再下载
+    pub deferred: bool,
+    pub available: bool,
+    pub manifest: Option<PayloadManifest>,
+}
+
+/// 按点分隔的数字逐段比较，`1.10.0`比`1.9.2`新；预发布后缀（`-beta.1`）比同号的正式版旧，
+/// 预发布之间按语义化版本逐段比较，数字段按数值、其余按字符串，数字段比字符串段旧；`+`之后的构建信息不参与比较
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let split = |version: &str| -> (Vec<u64>, Option<String>) {
+        let version = version.trim().trim_start_matches('v');
+        let version = version.split_once('+').map_or(version, |(version, _)| version);
+        let (release, pre) = match version.split_once('-') {
+            Some((release, pre)) => (release, Some(pre.to_string())),
+            None => (version, None),
+        };
+        (release.split('.').map(|part| part.parse().unwrap_or(0)).collect(), pre)
+    };
+    let (mut a_parts, a_pre) = split(a);
+    let (mut b_parts, b_pre) = split(b);
+    let len = a_parts.len().max(b_parts.len());
+    a_parts.resize(len, 0);
+    b_parts.resize(len, 0);
+    a_parts.cmp(&b_parts).then_with(|| match (a_pre, b_pre) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a_pre), Some(b_pre)) => compare_pre_release(&a_pre, &b_pre),
+    })
+}
+
+fn compare_pre_release(a: &str, b: &str) -> Ordering {
+    let mut a_parts = a.split('.');
+    let mut b_parts = b.split('.');
+    loop {
+        let ordering = match (a_parts.next(), b_parts.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a), Some(b)) => match (a.parse::<u64>(), b.parse::<u64>()) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                (Ok(_), Err(_)) => Ordering::Less,
+                (Err(_), Ok(_)) => Ordering::Greater,
+                (Err(_), Err(_)) => a.cmp(b),
+            },
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// 检查是否有比`current_version`更新的版本
+pub fn check(
+    base_url: &str,
+    platform: &str,
+    current_version: &str,
+    policy: &ManagedPolicy,
+) -> Result<UpdateStatus, String> {
+    let mut status = UpdateStatus {
+        current_version: current_version.to_string(),
+        disabled: policy.disable_auto_update,
+        deferred: policy.update_quiet_hours.is_some_and(|quiet_hours| quiet_hours.is_now()),
+        available: false,
+        manifest: None,
+    };
+    if status.disabled {
+        return Ok(status);
+    }
+
+    let manifest = payload::fetch_manifest(base_url, platform)?;
+    status.available = compare_versions(&manifest.version, current_version) == Ordering::Greater;
+    status.manifest = Some(manifest);
+    Ok(status)
+}
+
+/// 按托管策略下载安装包到`dir`并校验SHA-256，返回文件路径；`progress`收到已下载和总字节数
+pub fn download(
+    base_url: &str,
+    manifest: &PayloadManifest,
+    dir: &Path,
+    policy: &ManagedPolicy,
+    progress: impl FnMut(u64, u64),
+) -> Result<PathBuf, String> {
+    if policy.disable_auto_update {
+        return Err("托管策略已禁止自动更新".to_string());
+    }
+    let limits = Limits {
+        max_kbps: policy.max_download_kbps,
+        quiet_hours: policy.update_quiet_hours,
+    };
+    payload::download(base_url, manifest, dir, &limits, progress)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_ordering() {
+        let ascending = [
+            "0.9.9",
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "1.0.1",
+            "1.9.2",
+            "1.10",
+            "1.10.0.1",
+            "2",
+        ];
+        for (index, older) in ascending.iter().enumerate() {
+            for newer in &ascending[index + 1..] {
+                assert_eq!(compare_versions(older, newer), Ordering::Less, "{} < {}", older, newer);
+                assert_eq!(compare_versions(newer, older), Ordering::Greater, "{} > {}", newer, older);
+            }
+        }
+    }
+
+    #[test]
+    fn equivalent_versions() {
+        for (a, b) in [
+            ("1.2", "1.2.0"),
+            ("1.2.0", "1.2.0.0"),
+            ("v1.2.0", "1.2.0"),
+            (" 1.2.0\n", "1.2.0"),
+            ("1.2.0+build.5", "1.2.0"),
+            ("1.2.0-rc.1+abc", "1.2.0-rc.1"),
+        ] {
+            assert_eq!(compare_versions(a, b), Ordering::Equal, "{} == {}", a, b);
+        }
+    }
+}
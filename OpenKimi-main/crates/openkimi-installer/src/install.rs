@@ -0,0 +1,274 @@
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// 卸载信息注册表路径
+const UNINSTALL_KEY: &str = r"Software\Microsoft\Windows\CurrentVersion\Uninstall\OpenKimi";
+
+/// 安装范围
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstallScope {
+    PerUser,
+    PerMachine,
+}
+
+impl InstallScope {
+    pub fn flag(&self) -> &'static str {
+        match self {
+            InstallScope::PerUser => "--per-user",
+            InstallScope::PerMachine => "--per-machine",
+        }
+    }
+
+    fn registry_root(&self) -> &'static str {
+        match self {
+            InstallScope::PerUser => "HKCU",
+            InstallScope::PerMachine => "HKLM",
+        }
+    }
+
+    /// 默认安装目录：按用户安装到`%LOCALAPPDATA%\Programs`，按机器安装到`%ProgramFiles%`
+    pub fn default_install_dir(&self) -> io::Result<PathBuf> {
+        let (var, sub) = match self {
+            InstallScope::PerUser => ("LOCALAPPDATA", Path::new("Programs").join("OpenKimi")),
+            InstallScope::PerMachine => ("ProgramFiles", PathBuf::from("OpenKimi")),
+        };
+        env::var_os(var)
+            .map(|base| PathBuf::from(base).join(sub))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("环境变量 {} 未设置", var)))
+    }
+
+    /// 开始菜单程序目录
+    fn start_menu_dir(&self) -> Option<PathBuf> {
+        let base = match self {
+            InstallScope::PerUser => env::var_os("APPDATA")?,
+            InstallScope::PerMachine => env::var_os("ProgramData")?,
+        };
+        Some(PathBuf::from(base).join(r"Microsoft\Windows\Start Menu\Programs"))
+    }
+}
+
+/// PowerShell单引号字符串；PowerShell把弯引号也当作单引号，一并加倍转义
+fn ps_quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('\'');
+    for c in value.chars() {
+        if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}') {
+            quoted.push(c);
+        }
+        quoted.push(c);
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// 执行PowerShell脚本，失败时返回其错误输出
+fn powershell(script: &str) -> io::Result<std::process::Output> {
+    Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", script])
+        .output()
+}
+
+/// 检查当前进程是否具有管理员权限
+pub fn is_elevated() -> bool {
+    Command::new("net")
+        .arg("session")
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+/// 以管理员身份重新启动当前安装程序
+pub fn relaunch_elevated(args: &[String]) -> io::Result<()> {
+    let exe = env::current_exe()?;
+    let arg_list = args.iter().map(|arg| ps_quote(arg)).collect::<Vec<_>>().join(",");
+    let mut script = format!("Start-Process -FilePath {} -Verb RunAs", ps_quote(&exe.to_string_lossy()));
+    if !arg_list.is_empty() {
+        script.push_str(&format!(" -ArgumentList {}", arg_list));
+    }
+
+    let output = powershell(&script)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("无法以管理员身份启动（可能是用户拒绝了请求）: {}", stderr.trim()),
+        ));
+    }
+    Ok(())
+}
+
+/// 解压安装包到目标目录
+pub fn extract_payload(payload: &Path, install_dir: &Path) -> io::Result<u64> {
+    let mut archive = zip::ZipArchive::new(File::open(payload)?).map_err(io::Error::other)?;
+    let mut total_size = 0;
+
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).map_err(io::Error::other)?;
+        // 拒绝包含`..`等越界路径的条目
+        let relative = match entry.enclosed_name() {
+            Some(path) => path,
+            None => continue,
+        };
+        let target = install_dir.join(relative);
+
+        if entry.is_dir() {
+            fs::create_dir_all(&target)?;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut out = File::create(&target)?;
+        total_size += io::copy(&mut entry, &mut out)?;
+    }
+
+    Ok(total_size)
+}
+
+/// 写入单个注册表值
+fn reg_add(key: &str, name: &str, kind: &str, value: &str) -> io::Result<()> {
+    let status = Command::new("reg")
+        .args(["add", key, "/v", name, "/t", kind, "/d", value, "/f"])
+        .output()?
+        .status;
+    if !status.success() {
+        return Err(io::Error::other(format!("写入注册表失败: {}\\{}", key, name)));
+    }
+    Ok(())
+}
+
+/// 注册“应用和功能”中的卸载信息
+pub fn register_uninstall(scope: InstallScope, install_dir: &Path, version: &str, size_bytes: u64) -> io::Result<()> {
+    let key = format!(r"{}\{}", scope.registry_root(), UNINSTALL_KEY);
+    let uninstaller = install_dir.join("uninstall.exe");
+    let exe = install_dir.join("OpenKimi.exe");
+
+    reg_add(&key, "DisplayName", "REG_SZ", "OpenKimi")?;
+    reg_add(&key, "DisplayVersion", "REG_SZ", version)?;
+    reg_add(&key, "Publisher", "REG_SZ", "OpenKimi")?;
+    reg_add(&key, "InstallLocation", "REG_SZ", &install_dir.to_string_lossy())?;
+    reg_add(&key, "DisplayIcon", "REG_SZ", &exe.to_string_lossy())?;
+    reg_add(
+        &key,
+        "UninstallString",
+        "REG_SZ",
+        &format!("\"{}\" --uninstall {}", uninstaller.display(), scope.flag()),
+    )?;
+    reg_add(
+        &key,
+        "QuietUninstallString",
+        "REG_SZ",
+        &format!("\"{}\" --uninstall --silent {}", uninstaller.display(), scope.flag()),
+    )?;
+    reg_add(&key, "EstimatedSize", "REG_DWORD", &(size_bytes / 1024).to_string())?;
+    reg_add(&key, "NoModify", "REG_DWORD", "1")?;
+    reg_add(&key, "NoRepair", "REG_DWORD", "1")?;
+    Ok(())
+}
+
+/// 删除卸载信息
+pub fn unregister_uninstall(scope: InstallScope) -> io::Result<()> {
+    let key = format!(r"{}\{}", scope.registry_root(), UNINSTALL_KEY);
+    Command::new("reg").args(["delete", &key, "/f"]).output()?;
+    Ok(())
+}
+
+/// 读取已安装的目录
+pub fn installed_location(scope: InstallScope) -> Option<PathBuf> {
+    let key = format!(r"{}\{}", scope.registry_root(), UNINSTALL_KEY);
+    let output = Command::new("reg")
+        .args(["query", &key, "/v", "InstallLocation"])
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout.lines().find_map(|line| {
+        let (_, value) = line.trim().split_once("REG_SZ")?;
+        Some(PathBuf::from(value.trim()))
+    })
+}
+
+/// 创建开始菜单快捷方式
+pub fn create_shortcut(scope: InstallScope, install_dir: &Path) -> io::Result<()> {
+    let Some(dir) = scope.start_menu_dir() else {
+        return Ok(());
+    };
+    let link = dir.join("OpenKimi.lnk");
+    let script = format!(
+        "$ErrorActionPreference='Stop';$s=(New-Object -ComObject WScript.Shell).CreateShortcut({});\
+         $s.TargetPath={};$s.WorkingDirectory={};$s.Save()",
+        ps_quote(&link.to_string_lossy()),
+        ps_quote(&install_dir.join("OpenKimi.exe").to_string_lossy()),
+        ps_quote(&install_dir.to_string_lossy())
+    );
+    let output = powershell(&script)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!("创建开始菜单快捷方式失败: {}", stderr.trim())));
+    }
+    Ok(())
+}
+
+/// 删除开始菜单快捷方式
+pub fn remove_shortcut(scope: InstallScope) {
+    if let Some(dir) = scope.start_menu_dir() {
+        let _ = fs::remove_file(dir.join("OpenKimi.lnk"));
+    }
+}
+
+/// 将安装程序自身复制为卸载程序
+pub fn install_uninstaller(install_dir: &Path) -> io::Result<()> {
+    fs::copy(env::current_exe()?, install_dir.join("uninstall.exe"))?;
+    Ok(())
+}
+
+/// 调用随客户端打包的openkimi-cleanup清理安装目录之外的残留
+///
+/// 静默卸载且未指定`--purge`时保留用户数据；清理失败不影响卸载本身。
+pub fn run_cleanup(install_dir: &Path, silent: bool, purge: bool) {
+    let cleanup = install_dir.join(r"resources\bin\openkimi-cleanup.exe");
+    if !cleanup.is_file() {
+        return;
+    }
+
+    let mut command = Command::new(cleanup);
+    if purge {
+        command.arg("--purge");
+    } else if silent {
+        command.stdin(std::process::Stdio::null());
+    }
+    if let Err(err) = command.status() {
+        eprintln!("⚠️ 清理残留数据失败: {}", err);
+    }
+}
+
+/// 删除安装目录
+///
+/// 卸载程序本身位于安装目录中，运行时无法删除，因此交给延迟执行的`cmd`完成。
+pub fn schedule_dir_removal(install_dir: &Path) -> io::Result<()> {
+    let script = format!("/D /C ping -n 3 127.0.0.1 >nul & rmdir /s /q \"{}\"", install_dir.display());
+    let mut command = Command::new("cmd");
+    // cmd不按C运行时的规则解析参数，经过转义的引号会原样传给rmdir，因此不经转义直接传入整条命令
+    #[cfg(windows)]
+    std::os::windows::process::CommandExt::raw_arg(&mut command, script);
+    #[cfg(not(windows))]
+    command.arg(script);
+    command.spawn()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn powershell_quoting() {
+        assert_eq!(ps_quote(r"C:\Program Files\OpenKimi"), r"'C:\Program Files\OpenKimi'");
+        assert_eq!(ps_quote(r"C:\Users\O'Brien\OpenKimi"), r"'C:\Users\O''Brien\OpenKimi'");
+        assert_eq!(ps_quote("a\u{2019}b"), "'a\u{2019}\u{2019}b'");
+        assert_eq!(ps_quote("$(calc) `n"), "'$(calc) `n'");
+        assert_eq!(ps_quote(""), "''");
+    }
+}
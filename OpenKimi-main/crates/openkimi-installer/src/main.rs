@@ -0,0 +1,267 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use openkimi_helper::bundle::{self, UpdateBundle};
+use openkimi_helper::payload::{self, Limits};
+
+mod install;
+
+use install::InstallScope;
+
+/// 构建发布版本时由构建工具注入的CDN地址
+const DEFAULT_BASE_URL: Option<&str> = option_env!("OPENKIMI_CDN_BASE");
+
+/// 命令行选项
+struct Options {
+    scope: InstallScope,
+    base_url: Option<String>,
+    /// 离线更新包，设置时不访问网络
+    bundle: Option<PathBuf>,
+    install_dir: Option<PathBuf>,
+    silent: bool,
+    uninstall: bool,
+    purge: bool,
+}
+
+fn print_usage() {
+    println!("用法: openkimi-setup [--per-user|--per-machine] [--dir <目录>] [--base-url <地址>] [--silent]");
+    println!("      openkimi-setup --bundle <离线更新包> [--per-user|--per-machine] [--dir <目录>] [--silent]");
+    println!("      openkimi-setup --uninstall [--per-user|--per-machine] [--dir <目录>] [--silent] [--purge]");
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        scope: InstallScope::PerUser,
+        base_url: DEFAULT_BASE_URL.map(str::to_string),
+        bundle: None,
+        install_dir: None,
+        silent: false,
+        uninstall: false,
+        purge: false,
+    };
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--per-user" => options.scope = InstallScope::PerUser,
+            "--per-machine" => options.scope = InstallScope::PerMachine,
+            "--silent" | "/S" => options.silent = true,
+            "--uninstall" => options.uninstall = true,
+            "--purge" => options.purge = true,
+            "--dir" => {
+                let value = iter.next().ok_or("--dir 需要一个目录参数")?;
+                options.install_dir = Some(PathBuf::from(value));
+            }
+            "--bundle" => {
+                let value = iter.next().ok_or("--bundle 需要一个文件参数")?;
+                options.bundle = Some(PathBuf::from(value));
+            }
+            "--base-url" => {
+                let value = iter.next().ok_or("--base-url 需要一个地址参数")?;
+                options.base_url = Some(value.clone());
+            }
+            other => return Err(format!("未知参数: {}", other)),
+        }
+    }
+
+    Ok(options)
+}
+
+/// 交互模式下询问用户确认，静默模式直接通过
+fn confirm(options: &Options, question: &str) -> io::Result<bool> {
+    if options.silent {
+        return Ok(true);
+    }
+    print!("{} [Y/n] ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    let answer = answer.trim().to_lowercase();
+    Ok(answer.is_empty() || answer == "y" || answer == "yes")
+}
+
+fn is_empty_dir(dir: &Path) -> io::Result<bool> {
+    Ok(fs::read_dir(dir)?.next().is_none())
+}
+
+/// 目录中是否为之前安装的OpenKimi：注册表中记录的安装目录，或者有卸载程序和主程序
+fn is_previous_install(scope: InstallScope, dir: &Path) -> bool {
+    // Windows路径不区分大小写
+    let normalize = |path: &Path| path.to_string_lossy().trim_end_matches('\\').to_lowercase();
+    install::installed_location(scope).is_some_and(|location| normalize(&location) == normalize(dir))
+        || (dir.join("uninstall.exe").is_file() && dir.join("OpenKimi.exe").is_file())
+}
+
+/// 安装包的来源
+enum Source<'a> {
+    Cdn(&'a str),
+    Bundle(UpdateBundle),
+}
+
+/// 在终端显示下载或解压进度，每变化1%刷新一次
+fn print_progress(action: &str, file: &str) -> impl FnMut(u64, u64) {
+    let (action, file) = (action.to_string(), file.to_string());
+    let mut last_percent = u64::MAX;
+    move |done, total| {
+        if let Some(percent) = (done * 100).checked_div(total) {
+            if percent != last_percent {
+                print!("\r{} {} ... {:>3}%", action, file, percent.min(100));
+                let _ = io::stdout().flush();
+                last_percent = percent;
+            }
+        }
+    }
+}
+
+fn run_install(options: &Options) -> io::Result<()> {
+    let source = match (&options.bundle, options.base_url.as_deref()) {
+        (Some(path), _) => {
+            println!("🔍 正在校验离线更新包 {}...", path.display());
+            let verifier = bundle::verifier().map_err(io::Error::other)?;
+            Source::Bundle(UpdateBundle::open(path, "windows", &verifier).map_err(io::Error::other)?)
+        }
+        (None, Some(base_url)) => Source::Cdn(base_url),
+        (None, None) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "未配置下载地址，请使用 --base-url 指定，或用 --bundle 安装离线更新包",
+            ))
+        }
+    };
+    let install_dir = match &options.install_dir {
+        Some(dir) => dir.clone(),
+        None => options.scope.default_install_dir()?,
+    };
+    // 只清理之前安装OpenKimi的目录，避免`--dir`指向其他文件夹时误删
+    let replace = install_dir.exists() && !is_empty_dir(&install_dir)?;
+    if replace && !is_previous_install(options.scope, &install_dir) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} 不是空目录，也不是已安装的 OpenKimi，请选择其他目录", install_dir.display()),
+        ));
+    }
+
+    let manifest = match &source {
+        Source::Cdn(base_url) => {
+            println!("🔍 正在获取最新版本信息...");
+            payload::fetch_manifest(base_url, "windows").map_err(io::Error::other)?
+        }
+        Source::Bundle(bundle) => bundle.manifest().clone(),
+    };
+    println!("📦 最新版本: {}", manifest.version);
+
+    if !confirm(options, &format!("将 OpenKimi {} 安装到 {}？", manifest.version, install_dir.display()))? {
+        println!("已取消安装");
+        return Ok(());
+    }
+
+    let payload = match source {
+        Source::Cdn(base_url) => payload::download(
+            base_url,
+            &manifest,
+            &env::temp_dir(),
+            &Limits::default(),
+            print_progress("⬇️  正在下载", &manifest.file),
+        ),
+        Source::Bundle(bundle) => bundle.extract(&env::temp_dir(), print_progress("📤 正在读取", &manifest.file)),
+    }
+    .map_err(io::Error::other)?;
+    println!();
+    println!("✅ 安装包校验通过");
+
+    // 覆盖安装前清理旧文件，避免残留过期的资源
+    if replace {
+        fs::remove_dir_all(&install_dir)?;
+    }
+    fs::create_dir_all(&install_dir)?;
+
+    println!("📂 正在解压到 {}...", install_dir.display());
+    let size = install::extract_payload(&payload, &install_dir)?;
+    let _ = fs::remove_file(&payload);
+
+    install::install_uninstaller(&install_dir)?;
+    install::register_uninstall(options.scope, &install_dir, &manifest.version, size)?;
+    install::create_shortcut(options.scope, &install_dir)?;
+
+    println!("🎉 OpenKimi {} 安装完成", manifest.version);
+    Ok(())
+}
+
+fn run_uninstall(options: &Options) -> io::Result<()> {
+    let install_dir = match &options.install_dir {
+        Some(dir) => dir.clone(),
+        None => install::installed_location(options.scope)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "未找到已安装的 OpenKimi"))?,
+    };
+    // 与覆盖安装相同，`--dir`指向其他文件夹时拒绝删除
+    if !is_previous_install(options.scope, &install_dir) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} 不是已安装的 OpenKimi，已拒绝卸载", install_dir.display()),
+        ));
+    }
+
+    if !confirm(options, &format!("确定要卸载 {} 中的 OpenKimi 吗？", install_dir.display()))? {
+        println!("已取消卸载");
+        return Ok(());
+    }
+
+    install::run_cleanup(&install_dir, options.silent, options.purge);
+    install::remove_shortcut(options.scope);
+    install::unregister_uninstall(options.scope)?;
+    install::schedule_dir_removal(&install_dir)?;
+
+    println!("✅ OpenKimi 已卸载");
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        print_usage();
+        return ExitCode::SUCCESS;
+    }
+
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("❌ {}", err);
+            print_usage();
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if !cfg!(windows) {
+        eprintln!("❌ 在线安装程序仅支持 Windows");
+        return ExitCode::FAILURE;
+    }
+
+    // 按机器安装需要管理员权限，必要时重新以管理员身份启动
+    if options.scope == InstallScope::PerMachine && !install::is_elevated() {
+        println!("🔐 按机器安装需要管理员权限，正在请求提升...");
+        return match install::relaunch_elevated(&args) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("❌ {}", err);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let result = if options.uninstall {
+        run_uninstall(&options)
+    } else {
+        run_install(&options)
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("❌ {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! 许可证签发工具（内部使用）
+//!
+//! ```text
+//! openkimi-license keygen
+//! openkimi-license issue --org <组织> --seats <席位> --days <天数> [--id <编号>]
+//! openkimi-license verify --public-key <公钥> <许可证>
+//! ```
+//!
+//! `issue`从环境变量`OPENKIMI_LICENSE_SECRET`读取私钥，避免私钥出现在命令行历史中。
+
+use std::env;
+use std::process::ExitCode;
+
+use openkimi_licensing::{unix_now, LicenseInfo, LicenseIssuer, LicenseVerifier};
+
+fn option_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|index| args.get(index + 1))
+        .map(String::as_str)
+}
+
+fn required<'a>(args: &'a [String], name: &str) -> Result<&'a str, String> {
+    option_value(args, name).ok_or_else(|| format!("缺少参数 {}", name))
+}
+
+fn keygen() -> Result<(), String> {
+    let issuer = LicenseIssuer::generate().map_err(|e| e.to_string())?;
+    println!("私钥 (OPENKIMI_LICENSE_SECRET，请妥善保管): {}", issuer.secret());
+    println!("公钥 (OPENKIMI_LICENSE_PUBLIC_KEY): {}", issuer.public_key());
+    Ok(())
+}
+
+fn issue(args: &[String]) -> Result<(), String> {
+    let secret = env::var("OPENKIMI_LICENSE_SECRET").map_err(|_| "未设置 OPENKIMI_LICENSE_SECRET".to_string())?;
+    let issuer = LicenseIssuer::from_secret(&secret).map_err(|e| e.to_string())?;
+
+    let organization = required(args, "--org")?.to_string();
+    let seats: u32 = required(args, "--seats")?
+        .parse()
+        .map_err(|_| "--seats 必须是正整数".to_string())?;
+    let days: u64 = required(args, "--days")?
+        .parse()
+        .map_err(|_| "--days 必须是正整数".to_string())?;
+
+    let issued_at = unix_now();
+    let license_id = match option_value(args, "--id") {
+        Some(id) => id.to_string(),
+        None => format!("OK-{}", issued_at),
+    };
+    let info = LicenseInfo {
+        license_id,
+        organization,
+        seats,
+        issued_at,
+        expires_at: issued_at + days * 24 * 60 * 60,
+    };
+
+    println!("{}", issuer.issue(&info));
+    Ok(())
+}
+
+fn verify(args: &[String]) -> Result<(), String> {
+    let public_key = required(args, "--public-key")?;
+    let license_key = args.last().ok_or("缺少许可证")?;
+    let verifier = LicenseVerifier::new(public_key).map_err(|e| e.to_string())?;
+    let validation = verifier.validate(license_key).map_err(|e| e.to_string())?;
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&validation).map_err(|e| e.to_string())?
+    );
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("keygen") => keygen(),
+        Some("issue") => issue(&args[1..]),
+        Some("verify") => verify(&args[1..]),
+        _ => Err("用法: openkimi-license <keygen|issue|verify> ...".to_string()),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("❌ {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,339 @@
+//! OpenKimi企业版许可证
+//!
+//! 许可证密钥格式为`OKL1.<载荷>.<签名>`，载荷是URL安全Base64编码的JSON（组织名、席位数、有效期等），
+//! 签名是对`OKL1.<载荷>`的Ed25519签名。校验只需要公钥，不需要联网。
+//!
+//! 许可证过期后仍有一段宽限期，期间[`LicenseStatus::Grace`]允许继续使用，调用方应提醒用户续期。
+//!
+//! 同样的签名方式也用于离线更新包（见`openkimi_helper::bundle`），更新包使用单独的密钥对。
+
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+/// 许可证密钥前缀，同时表示格式版本
+const KEY_PREFIX: &str = "OKL1";
+
+/// 默认宽限期（天）
+pub const DEFAULT_GRACE_DAYS: u64 = 14;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// 发布构建时注入的许可证公钥（URL安全Base64）
+const EMBEDDED_PUBLIC_KEY: Option<&str> = option_env!("OPENKIMI_LICENSE_PUBLIC_KEY");
+
+/// 许可证错误
+#[derive(Debug)]
+pub enum LicenseError {
+    /// 密钥格式不正确
+    Malformed(String),
+    /// 签名与公钥不匹配
+    InvalidSignature,
+    /// 公钥或私钥本身无效
+    InvalidKey(String),
+    /// 构建时没有注入公钥
+    NoPublicKey,
+}
+
+impl fmt::Display for LicenseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LicenseError::Malformed(reason) => write!(f, "许可证格式错误: {}", reason),
+            LicenseError::InvalidSignature => write!(f, "许可证签名无效"),
+            LicenseError::InvalidKey(reason) => write!(f, "密钥无效: {}", reason),
+            LicenseError::NoPublicKey => write!(f, "此版本未内置许可证公钥"),
+        }
+    }
+}
+
+impl std::error::Error for LicenseError {}
+
+/// 许可证内容
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LicenseInfo {
+    /// 许可证编号
+    pub license_id: String,
+    /// 授权组织
+    pub organization: String,
+    /// 授权席位数
+    pub seats: u32,
+    /// 签发时间（Unix秒）
+    pub issued_at: u64,
+    /// 到期时间（Unix秒）
+    pub expires_at: u64,
+}
+
+/// 许可证状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum LicenseStatus {
+    /// 尚未生效（签发时间晚于当前时间，通常是系统时钟错误）
+    NotYetValid,
+    /// 有效期内
+    Valid { days_left: u64 },
+    /// 已过期但仍在宽限期内
+    Grace { days_left: u64 },
+    /// 已过期且超过宽限期
+    Expired,
+}
+
+impl LicenseStatus {
+    /// 当前状态是否允许使用企业版功能
+    pub fn is_usable(&self) -> bool {
+        matches!(self, LicenseStatus::Valid { .. } | LicenseStatus::Grace { .. })
+    }
+}
+
+/// 校验结果
+#[derive(Debug, Clone, Serialize)]
+pub struct Validation {
+    pub info: LicenseInfo,
+    pub status: LicenseStatus,
+}
+
+impl Validation {
+    /// 当前使用人数是否在授权席位内
+    pub fn allows_seats(&self, active_seats: u32) -> bool {
+        self.status.is_usable() && active_seats <= self.info.seats
+    }
+}
+
+/// 当前Unix时间（秒）
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn decode_b64(value: &str, what: &str) -> Result<Vec<u8>, LicenseError> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim())
+        .map_err(|e| LicenseError::Malformed(format!("{} 不是有效的Base64: {}", what, e)))
+}
+
+/// 许可证校验器，只持有公钥
+pub struct LicenseVerifier {
+    key: VerifyingKey,
+    grace_period_secs: u64,
+}
+
+impl LicenseVerifier {
+    /// 使用URL安全Base64编码的公钥创建校验器
+    pub fn new(public_key: &str) -> Result<Self, LicenseError> {
+        let bytes: [u8; 32] = decode_b64(public_key, "公钥")?
+            .try_into()
+            .map_err(|_| LicenseError::InvalidKey("公钥长度必须为32字节".to_string()))?;
+        let key = VerifyingKey::from_bytes(&bytes).map_err(|e| LicenseError::InvalidKey(e.to_string()))?;
+        Ok(LicenseVerifier {
+            key,
+            grace_period_secs: DEFAULT_GRACE_DAYS * SECS_PER_DAY,
+        })
+    }
+
+    /// 使用构建时注入的公钥创建校验器
+    pub fn embedded() -> Result<Self, LicenseError> {
+        Self::new(EMBEDDED_PUBLIC_KEY.ok_or(LicenseError::NoPublicKey)?)
+    }
+
+    /// 设置宽限期天数
+    pub fn with_grace_period_days(mut self, days: u64) -> Self {
+        self.grace_period_secs = days * SECS_PER_DAY;
+        self
+    }
+
+    /// 校验签名并解析许可证内容，不检查有效期
+    pub fn verify(&self, license_key: &str) -> Result<LicenseInfo, LicenseError> {
+        let license_key = license_key.trim();
+        let mut parts = license_key.split('.');
+        let (Some(prefix), Some(payload), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(LicenseError::Malformed("应为 OKL1.<载荷>.<签名>".to_string()));
+        };
+        if prefix != KEY_PREFIX {
+            return Err(LicenseError::Malformed(format!("不支持的许可证版本: {}", prefix)));
+        }
+
+        self.verify_signature(format!("{}.{}", prefix, payload).as_bytes(), signature)?;
+
+        serde_json::from_slice(&decode_b64(payload, "载荷")?)
+            .map_err(|e| LicenseError::Malformed(format!("载荷不是有效的许可证: {}", e)))
+    }
+
+    /// 校验`message`的Ed25519签名（URL安全Base64）
+    pub fn verify_signature(&self, message: &[u8], signature: &str) -> Result<(), LicenseError> {
+        let signature_bytes: [u8; 64] = decode_b64(signature, "签名")?
+            .try_into()
+            .map_err(|_| LicenseError::Malformed("签名长度必须为64字节".to_string()))?;
+        self.key
+            .verify(message, &Signature::from_bytes(&signature_bytes))
+            .map_err(|_| LicenseError::InvalidSignature)
+    }
+
+    /// 校验许可证并计算指定时间点的状态
+    pub fn validate_at(&self, license_key: &str, now: u64) -> Result<Validation, LicenseError> {
+        let info = self.verify(license_key)?;
+        let status = if now < info.issued_at {
+            LicenseStatus::NotYetValid
+        } else if now < info.expires_at {
+            LicenseStatus::Valid {
+                days_left: (info.expires_at - now) / SECS_PER_DAY,
+            }
+        } else if now < info.expires_at + self.grace_period_secs {
+            LicenseStatus::Grace {
+                days_left: (info.expires_at + self.grace_period_secs - now) / SECS_PER_DAY,
+            }
+        } else {
+            LicenseStatus::Expired
+        };
+        Ok(Validation { info, status })
+    }
+
+    /// 校验许可证并计算当前状态
+    pub fn validate(&self, license_key: &str) -> Result<Validation, LicenseError> {
+        self.validate_at(license_key, unix_now())
+    }
+}
+
+/// 许可证签发器，持有私钥，仅用于内部签发工具
+pub struct LicenseIssuer {
+    key: SigningKey,
+}
+
+impl LicenseIssuer {
+    /// 生成新的签名密钥
+    pub fn generate() -> Result<Self, LicenseError> {
+        let mut seed = [0u8; 32];
+        getrandom::getrandom(&mut seed).map_err(|e| LicenseError::InvalidKey(e.to_string()))?;
+        Ok(LicenseIssuer {
+            key: SigningKey::from_bytes(&seed),
+        })
+    }
+
+    /// 从URL安全Base64编码的私钥恢复签发器
+    pub fn from_secret(secret: &str) -> Result<Self, LicenseError> {
+        let bytes: [u8; 32] = decode_b64(secret, "私钥")?
+            .try_into()
+            .map_err(|_| LicenseError::InvalidKey("私钥长度必须为32字节".to_string()))?;
+        Ok(LicenseIssuer {
+            key: SigningKey::from_bytes(&bytes),
+        })
+    }
+
+    /// 私钥（URL安全Base64）
+    pub fn secret(&self) -> String {
+        URL_SAFE_NO_PAD.encode(self.key.to_bytes())
+    }
+
+    /// 公钥（URL安全Base64），发布构建时通过`OPENKIMI_LICENSE_PUBLIC_KEY`注入
+    pub fn public_key(&self) -> String {
+        URL_SAFE_NO_PAD.encode(self.key.verifying_key().to_bytes())
+    }
+
+    /// 对`message`签名，返回URL安全Base64
+    pub fn sign(&self, message: &[u8]) -> String {
+        URL_SAFE_NO_PAD.encode(self.key.sign(message).to_bytes())
+    }
+
+    /// 签发许可证密钥
+    pub fn issue(&self, info: &LicenseInfo) -> String {
+        let payload = serde_json::to_vec(info).expect("LicenseInfo 序列化不会失败");
+        let signed = format!("{}.{}", KEY_PREFIX, URL_SAFE_NO_PAD.encode(payload));
+        format!("{}.{}", signed, self.sign(signed.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_750_000_000;
+
+    fn license(issued_at: u64, expires_at: u64) -> LicenseInfo {
+        LicenseInfo {
+            license_id: "OK-TEST".to_string(),
+            organization: "Example".to_string(),
+            seats: 10,
+            issued_at,
+            expires_at,
+        }
+    }
+
+    fn keypair() -> (LicenseIssuer, LicenseVerifier) {
+        let issuer = LicenseIssuer::generate().unwrap();
+        let verifier = LicenseVerifier::new(&issuer.public_key()).unwrap();
+        (issuer, verifier)
+    }
+
+    #[test]
+    fn valid_license_round_trips() {
+        let (issuer, verifier) = keypair();
+        let key = issuer.issue(&license(NOW - SECS_PER_DAY, NOW + 30 * SECS_PER_DAY));
+        let validation = verifier.validate_at(&key, NOW).unwrap();
+        assert_eq!(validation.info.organization, "Example");
+        assert_eq!(validation.status, LicenseStatus::Valid { days_left: 30 });
+        assert!(validation.allows_seats(10));
+        assert!(!validation.allows_seats(11));
+
+        // 私钥导出后恢复的签发器签出相同的密钥
+        let restored = LicenseIssuer::from_secret(&issuer.secret()).unwrap();
+        assert_eq!(restored.public_key(), issuer.public_key());
+    }
+
+    #[test]
+    fn rejects_tampered_payload() {
+        let (issuer, verifier) = keypair();
+        let key = issuer.issue(&license(NOW, NOW + SECS_PER_DAY));
+        let parts: Vec<&str> = key.split('.').collect();
+        let mut info = license(NOW, NOW + SECS_PER_DAY);
+        info.seats = 1000;
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&info).unwrap());
+        let tampered = format!("{}.{}.{}", parts[0], payload, parts[2]);
+        assert!(matches!(verifier.verify(&tampered), Err(LicenseError::InvalidSignature)));
+
+        let truncated = format!("{}.{}", parts[0], parts[1]);
+        assert!(matches!(verifier.verify(&truncated), Err(LicenseError::Malformed(_))));
+        let other_version = key.replacen(KEY_PREFIX, "OKL2", 1);
+        assert!(matches!(verifier.verify(&other_version), Err(LicenseError::Malformed(_))));
+    }
+
+    #[test]
+    fn rejects_wrong_key() {
+        let (issuer, _) = keypair();
+        let (_, other) = keypair();
+        let key = issuer.issue(&license(NOW, NOW + SECS_PER_DAY));
+        assert!(matches!(other.verify(&key), Err(LicenseError::InvalidSignature)));
+        assert!(matches!(LicenseVerifier::new("AAAA"), Err(LicenseError::InvalidKey(_))));
+    }
+
+    #[test]
+    fn expiry_and_grace_period() {
+        let (issuer, verifier) = keypair();
+        let verifier = verifier.with_grace_period_days(14);
+        let key = issuer.issue(&license(NOW - 60 * SECS_PER_DAY, NOW - 10 * SECS_PER_DAY));
+        let validation = verifier.validate_at(&key, NOW).unwrap();
+        assert_eq!(validation.status, LicenseStatus::Grace { days_left: 4 });
+        assert!(validation.allows_seats(1));
+
+        let validation = verifier.validate_at(&key, NOW + 5 * SECS_PER_DAY).unwrap();
+        assert_eq!(validation.status, LicenseStatus::Expired);
+        assert!(!validation.status.is_usable());
+        assert!(!validation.allows_seats(1));
+
+        let future = issuer.issue(&license(NOW + SECS_PER_DAY, NOW + 30 * SECS_PER_DAY));
+        assert_eq!(verifier.validate_at(&future, NOW).unwrap().status, LicenseStatus::NotYetValid);
+    }
+
+    #[test]
+    fn signs_arbitrary_messages() {
+        let (issuer, verifier) = keypair();
+        let signature = issuer.sign(b"manifest");
+        assert!(verifier.verify_signature(b"manifest", &signature).is_ok());
+        assert!(verifier.verify_signature(b"manifest2", &signature).is_err());
+    }
+}
@@ -0,0 +1,292 @@
+//! 补全、解释和对话：构造提示词，流式请求模型，每收到一段检查请求是否已被取消
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use openkimi_client::blocking::Client;
+use openkimi_client::{ChatCompletionRequest, ChatEvent, ChatMessage, ClientConfig};
+use serde_json::Value;
+
+use crate::documents::{Document, Encoding, Position, Range};
+use crate::rpc::{RpcError, INVALID_PARAMS};
+
+/// 补全时光标前后分别取的字符数
+const PREFIX_CHARS: usize = 6000;
+const SUFFIX_CHARS: usize = 2000;
+
+/// 对话中附带整个文件时取的字符数
+const FILE_CHARS: usize = 12000;
+
+/// 补全提示词中光标的位置
+const CURSOR: &str = "<|cursor|>";
+
+const COMPLETION_SYSTEM: &str = "你是代码补全引擎。用户给出一个文件的内容，其中<|cursor|>标记光标的位置。\
+只输出应当插入在光标处的代码，不要重复光标前后已有的内容，不要解释，不要使用Markdown代码块。\
+不确定时输出尽量短的补全，没有合适的补全时什么也不输出。";
+
+const EXPLAIN_SYSTEM: &str = "你是资深的程序员。用简洁的中文解释用户选中的代码：它做了什么、关键的步骤，\
+以及值得注意的边界情况或潜在问题。不要逐行复述代码。";
+
+const CHAT_SYSTEM: &str = "你是集成在代码编辑器中的编程助手，用中文简洁地回答。用户可能附上当前文件或选中的代码，\
+给出代码时使用Markdown代码块并标明语言。";
+
+/// 连接和模型设置，来自环境变量、`initializationOptions`和`workspace/didChangeConfiguration`
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub client: ClientConfig,
+    /// 解释和对话使用的模型，为空时使用服务端的默认模型
+    pub model: String,
+    /// 补全使用的模型，缺省时与`model`相同
+    pub completion_model: Option<String>,
+    pub max_completion_tokens: u32,
+}
+
+impl Settings {
+    /// 读取`OPENKIMI_BASE_URL`、`OPENKIMI_API_KEY`和`OPENKIMI_MODEL`
+    pub fn from_env() -> Settings {
+        Settings {
+            client: ClientConfig::from_env(),
+            model: std::env::var("OPENKIMI_MODEL").unwrap_or_default(),
+            completion_model: None,
+            max_completion_tokens: 256,
+        }
+    }
+
+    /// 合并编辑器给出的设置，可以直接给出各项，也可以放在`openkimi`之下
+    pub fn apply(&mut self, options: &Value) {
+        let options = options.get("openkimi").unwrap_or(options);
+        let text = |key: &str| options.get(key).and_then(Value::as_str).map(String::from);
+        if let Some(base_url) = text("baseUrl") {
+            self.client.base_url = base_url;
+        }
+        if let Some(api_key) = text("apiKey") {
+            self.client.api_key = Some(api_key).filter(|key| !key.is_empty());
+        }
+        if let Some(workspace) = text("workspace") {
+            self.client.workspace = Some(workspace).filter(|workspace| !workspace.is_empty());
+        }
+        if let Some(model) = text("model") {
+            self.model = model;
+        }
+        if let Some(model) = text("completionModel") {
+            self.completion_model = Some(model).filter(|model| !model.is_empty());
+        }
+        if let Some(tokens) = options.get("maxCompletionTokens").and_then(Value::as_u64) {
+            self.max_completion_tokens = tokens.clamp(1, u32::MAX as u64) as u32;
+        }
+    }
+}
+
+/// 处理请求用到的连接和设置，设置改变时重新创建
+#[derive(Debug, Clone)]
+pub struct Backend {
+    pub client: Client,
+    pub settings: Settings,
+    pub encoding: Encoding,
+}
+
+impl Backend {
+    pub fn new(settings: Settings, encoding: Encoding) -> Result<Backend, String> {
+        let client = Client::new(settings.client.clone()).map_err(|e| e.to_string())?;
+        Ok(Backend {
+            client,
+            settings,
+            encoding,
+        })
+    }
+}
+
+/// 流式请求模型，`on_delta`收到每段新增的文本；取消时返回[`RpcError::cancelled`]
+fn generate(
+    client: &Client,
+    request: &ChatCompletionRequest,
+    cancel: &AtomicBool,
+    mut on_delta: impl FnMut(&str),
+) -> Result<String, RpcError> {
+    let stream = client.chat_stream(request).map_err(|e| RpcError::internal(e.to_string()))?;
+    let mut text = String::new();
+    for event in stream.events() {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(RpcError::cancelled());
+        }
+        if let ChatEvent::Content(delta) = event.map_err(|e| RpcError::internal(e.to_string()))? {
+            on_delta(&delta);
+            text.push_str(&delta);
+        }
+    }
+    if cancel.load(Ordering::Relaxed) {
+        return Err(RpcError::cancelled());
+    }
+    Ok(text)
+}
+
+/// 末尾的`count`个字符
+fn tail(text: &str, count: usize) -> &str {
+    match text.char_indices().rev().nth(count) {
+        Some((index, c)) => &text[index + c.len_utf8()..],
+        None => text,
+    }
+}
+
+/// 开头的`count`个字符
+fn head(text: &str, count: usize) -> &str {
+    let end = text.char_indices().nth(count).map_or(text.len(), |(index, _)| index);
+    &text[..end]
+}
+
+/// 去掉模型仍然加上的代码块标记，以及与光标后已有内容重复的结尾
+fn clean_completion(text: &str, suffix: &str) -> String {
+    let mut text = text;
+    if let Some(rest) = text.trim_start().strip_prefix("```") {
+        // 第一行是语言名
+        text = rest.split_once('\n').map_or("", |(_, body)| body);
+        text = text.trim_end().strip_suffix("```").unwrap_or(text);
+        text = text.strip_suffix('\n').unwrap_or(text);
+    }
+    let text = text.strip_prefix(CURSOR).unwrap_or(text);
+    // 结尾与光标后的内容开头相同的部分，取最长的
+    let overlap = (1..=text.len().min(suffix.len()))
+        .rev()
+        .find(|&length| {
+            text.is_char_boundary(text.len() - length)
+                && suffix.is_char_boundary(length)
+                && text.ends_with(&suffix[..length])
+        })
+        .unwrap_or(0);
+    text[..text.len() - overlap].to_string()
+}
+
+/// `textDocument/inlineCompletion`：在光标处插入的一项补全，没有补全时列表为空
+pub fn complete(
+    backend: &Backend,
+    document: &Document,
+    position: Position,
+    cancel: &AtomicBool,
+) -> Result<Value, RpcError> {
+    let settings = &backend.settings;
+    let offset = document.offset(position, backend.encoding);
+    let prefix = tail(&document.text[..offset], PREFIX_CHARS);
+    let suffix = head(&document.text[offset..], SUFFIX_CHARS);
+    let prompt = format!(
+        "文件：{}（{}）\n\n{}{}{}",
+        document.name(),
+        document.language,
+        prefix,
+        CURSOR,
+        suffix
+    );
+    let model = settings.completion_model.clone().unwrap_or_else(|| settings.model.clone());
+    let mut request = ChatCompletionRequest::new(
+        model,
+        vec![
+            ChatMessage::new("system", COMPLETION_SYSTEM),
+            ChatMessage::new("user", prompt),
+        ],
+    );
+    request.max_tokens = Some(settings.max_completion_tokens);
+    request.temperature = Some(0.2);
+    let text = clean_completion(&generate(&backend.client, &request, cancel, |_| {})?, suffix);
+    if text.trim().is_empty() {
+        return Ok(serde_json::json!({ "items": [] }));
+    }
+    let range = Range {
+        start: position,
+        end: position,
+    };
+    Ok(serde_json::json!({ "items": [{ "insertText": text, "range": range }] }))
+}
+
+/// 附在问题中的代码：选中的部分，没有选中时为整个文件
+fn code_block(document: &Document, range: Option<Range>, encoding: Encoding) -> String {
+    let (label, code) = match range.filter(|range| !range.is_empty()) {
+        Some(range) => (
+            format!("{} 第{}行起选中的代码", document.name(), range.start.line + 1),
+            document.slice(range, encoding),
+        ),
+        None => (
+            format!("当前文件 {}", document.name()),
+            head(&document.text, FILE_CHARS),
+        ),
+    };
+    format!(
+        "{}：\n\n```{}\n{}\n```",
+        label,
+        document.language,
+        code.trim_end_matches('\n')
+    )
+}
+
+/// `openkimi/explain`：解释选中的代码
+pub fn explain(backend: &Backend, document: &Document, range: Range, cancel: &AtomicBool) -> Result<String, RpcError> {
+    if range.is_empty() {
+        return Err(RpcError::new(INVALID_PARAMS, "没有选中代码"));
+    }
+    let request = ChatCompletionRequest::new(
+        backend.settings.model.clone(),
+        vec![
+            ChatMessage::new("system", EXPLAIN_SYSTEM),
+            ChatMessage::new("user", code_block(document, Some(range), backend.encoding)),
+        ],
+    );
+    generate(&backend.client, &request, cancel, |_| {}).map(|text| text.trim().to_string())
+}
+
+/// 编辑器内的对话，只保存在内存中，语言服务器退出后丢弃
+#[derive(Debug, Default)]
+pub struct Conversations {
+    next: AtomicU64,
+    conversations: Mutex<HashMap<String, Vec<ChatMessage>>>,
+}
+
+impl Conversations {
+    /// 对话的消息，`id`为空时新建对话；新建的对话即使第一轮被取消也保留
+    fn history(&self, id: Option<String>) -> Result<(String, Vec<ChatMessage>), RpcError> {
+        let mut conversations = self.conversations.lock().unwrap_or_else(|e| e.into_inner());
+        match id {
+            Some(id) => match conversations.get(&id) {
+                Some(messages) => Ok((id, messages.clone())),
+                None => Err(RpcError::new(INVALID_PARAMS, format!("没有对话 {}", id))),
+            },
+            None => {
+                let id = format!("chat-{}", self.next.fetch_add(1, Ordering::Relaxed) + 1);
+                let messages = vec![ChatMessage::new("system", CHAT_SYSTEM)];
+                conversations.insert(id.clone(), messages.clone());
+                Ok((id, messages))
+            }
+        }
+    }
+
+    fn save(&self, id: String, messages: Vec<ChatMessage>) {
+        self.conversations.lock().unwrap_or_else(|e| e.into_inner()).insert(id, messages);
+    }
+
+    /// 删除对话，返回是否存在
+    pub fn reset(&self, id: &str) -> bool {
+        self.conversations.lock().unwrap_or_else(|e| e.into_inner()).remove(id).is_some()
+    }
+
+    /// `openkimi/chat`：发送一条消息，`on_delta`收到对话的id和回复的每段新增文本；返回对话的id和完整的回复。
+    /// 取消或出错时这一轮不计入对话
+    pub fn send(
+        &self,
+        backend: &Backend,
+        id: Option<String>,
+        message: &str,
+        context: Option<(&Document, Option<Range>)>,
+        cancel: &AtomicBool,
+        mut on_delta: impl FnMut(&str, &str),
+    ) -> Result<(String, String), RpcError> {
+        let (id, mut messages) = self.history(id)?;
+        let content = match context {
+            Some((document, range)) => format!("{}\n\n{}", code_block(document, range, backend.encoding), message),
+            None => message.to_string(),
+        };
+        messages.push(ChatMessage::new("user", content));
+        let request = ChatCompletionRequest::new(backend.settings.model.clone(), messages.clone());
+        let text = generate(&backend.client, &request, cancel, |delta| on_delta(&id, delta))?;
+        messages.push(ChatMessage::new("assistant", text.clone()));
+        self.save(id.clone(), messages);
+        Ok((id, text))
+    }
+}
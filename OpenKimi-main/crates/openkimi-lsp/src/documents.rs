@@ -0,0 +1,199 @@
+//! 编辑器中打开的文档
+//!
+//! 按`didOpen`、`didChange`、`didClose`同步全文，支持增量修改。位置中的列默认按UTF-16计算，
+//! 编辑器在`general.positionEncodings`中声明支持`utf-8`时改用字节，行按`\n`分隔。
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Position {
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Range {
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+}
+
+/// 列的计算方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf16,
+    Utf8,
+}
+
+impl Encoding {
+    /// 从`initialize`的客户端能力中选择，优先使用UTF-8
+    pub fn negotiate(capabilities: &Value) -> Encoding {
+        let supported = capabilities["general"]["positionEncodings"].as_array();
+        if supported.is_some_and(|encodings| encodings.iter().any(|encoding| encoding == "utf-8")) {
+            Encoding::Utf8
+        } else {
+            Encoding::Utf16
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf16 => "utf-16",
+            Encoding::Utf8 => "utf-8",
+        }
+    }
+
+    fn width(self, c: char) -> u32 {
+        match self {
+            Encoding::Utf16 => c.len_utf16() as u32,
+            Encoding::Utf8 => c.len_utf8() as u32,
+        }
+    }
+}
+
+/// 打开的文档
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub uri: String,
+    pub language: String,
+    pub text: String,
+}
+
+impl Document {
+    /// 文件名，用于提示词；不是`file://`时为整个URI
+    pub fn name(&self) -> &str {
+        let path = self.uri.strip_prefix("file://").unwrap_or(&self.uri);
+        path.rsplit('/').next().filter(|name| !name.is_empty()).unwrap_or(path)
+    }
+
+    /// 位置对应的字节偏移，超出行尾或文件末尾时取行尾或文件末尾
+    pub fn offset(&self, position: Position, encoding: Encoding) -> usize {
+        let mut start = 0;
+        for _ in 0..position.line {
+            match self.text[start..].find('\n') {
+                Some(end) => start += end + 1,
+                None => return self.text.len(),
+            }
+        }
+        let mut column = 0;
+        for (index, c) in self.text[start..].char_indices() {
+            if c == '\n' || column >= position.character {
+                return start + index;
+            }
+            column += encoding.width(c);
+        }
+        self.text.len()
+    }
+
+    /// 范围内的文本
+    pub fn slice(&self, range: Range, encoding: Encoding) -> &str {
+        let start = self.offset(range.start, encoding);
+        let end = self.offset(range.end, encoding).max(start);
+        &self.text[start..end]
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TextDocumentItem {
+    uri: String,
+    #[serde(rename = "languageId", default)]
+    language_id: String,
+    text: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DidOpenParams {
+    #[serde(rename = "textDocument")]
+    text_document: TextDocumentItem,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TextDocumentIdentifier {
+    pub uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ContentChange {
+    /// 省略时为全文
+    #[serde(default)]
+    range: Option<Range>,
+    text: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DidChangeParams {
+    #[serde(rename = "textDocument")]
+    text_document: TextDocumentIdentifier,
+    #[serde(rename = "contentChanges")]
+    content_changes: Vec<ContentChange>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DidCloseParams {
+    #[serde(rename = "textDocument")]
+    text_document: TextDocumentIdentifier,
+}
+
+/// 打开的全部文档，只在主线程中修改
+#[derive(Debug)]
+pub struct Documents {
+    pub encoding: Encoding,
+    documents: HashMap<String, Document>,
+}
+
+impl Documents {
+    pub fn new(encoding: Encoding) -> Documents {
+        Documents {
+            encoding,
+            documents: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, uri: &str) -> Option<&Document> {
+        self.documents.get(uri)
+    }
+
+    pub fn open(&mut self, params: Value) -> Result<(), String> {
+        let params: DidOpenParams = serde_json::from_value(params).map_err(|e| e.to_string())?;
+        let item = params.text_document;
+        let document = Document {
+            uri: item.uri.clone(),
+            language: item.language_id,
+            text: item.text,
+        };
+        self.documents.insert(item.uri, document);
+        Ok(())
+    }
+
+    /// 依次应用各项修改，每项的范围相对于应用了前一项之后的文本
+    pub fn change(&mut self, params: Value) -> Result<(), String> {
+        let params: DidChangeParams = serde_json::from_value(params).map_err(|e| e.to_string())?;
+        let uri = params.text_document.uri;
+        let document = self.documents.get_mut(&uri).ok_or_else(|| format!("文档没有打开: {}", uri))?;
+        for change in params.content_changes {
+            match change.range {
+                Some(range) => {
+                    let start = document.offset(range.start, self.encoding);
+                    let end = document.offset(range.end, self.encoding).max(start);
+                    document.text.replace_range(start..end, &change.text);
+                }
+                None => document.text = change.text,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn close(&mut self, params: Value) -> Result<(), String> {
+        let params: DidCloseParams = serde_json::from_value(params).map_err(|e| e.to_string())?;
+        self.documents.remove(&params.text_document.uri);
+        Ok(())
+    }
+}
@@ -0,0 +1,371 @@
+//! openkimi-lsp：OpenKimi的语言服务器
+//!
+//! ```text
+//! openkimi-lsp [--stdio]
+//! ```
+//!
+//! 由编辑器作为子进程启动，通过标准输入输出收发LSP消息，日志写到标准错误。连接服务端的设置依次取
+//! `OPENKIMI_BASE_URL`、`OPENKIMI_API_KEY`、`OPENKIMI_MODEL`环境变量，`initializationOptions`和
+//! `workspace/didChangeConfiguration`中`openkimi`之下的`baseUrl`、`apiKey`、`workspace`、`model`、
+//! `completionModel`和`maxCompletionTokens`。提供的方法：
+//!
+//! - `textDocument/inlineCompletion`（LSP 3.18）和同样参数的`openkimi/inlineCompletion`：光标处的行内补全；
+//! - `openkimi/explain`：解释选中的代码，返回`{"text": ...}`；选中代码时`textDocument/codeAction`提供
+//!   同样功能的`openkimi.explain`命令，结果用`window/showMessage`显示；
+//! - `openkimi/chat`：编辑器内的对话，可以附上当前文件或选中的代码，回复的每段新增文本作为
+//!   `openkimi/chatDelta`通知发送，返回对话的id和完整的回复；`openkimi/resetChat`删除对话。
+//!
+//! 每个请求在单独的线程中处理，`$/cancelRequest`取消后返回`-32800`。
+
+use std::collections::HashMap;
+use std::env;
+use std::io::{self, BufReader};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+mod assist;
+mod documents;
+mod rpc;
+
+use assist::{Backend, Conversations, Settings};
+use documents::{Document, Documents, Encoding, Position, Range, TextDocumentIdentifier};
+use rpc::{
+    Output, RpcError, INVALID_PARAMS, INVALID_REQUEST, METHOD_NOT_FOUND, PARSE_ERROR, REQUEST_CANCELLED,
+    SERVER_NOT_INITIALIZED,
+};
+
+/// 解释选中代码的命令
+const EXPLAIN_COMMAND: &str = "openkimi.explain";
+
+/// `window/showMessage`的消息类型
+const MESSAGE_ERROR: u8 = 1;
+const MESSAGE_INFO: u8 = 3;
+
+#[derive(Debug, Deserialize)]
+struct PositionParams {
+    #[serde(rename = "textDocument")]
+    text_document: TextDocumentIdentifier,
+    position: Position,
+}
+
+#[derive(Debug, Deserialize)]
+struct RangeParams {
+    #[serde(rename = "textDocument")]
+    text_document: TextDocumentIdentifier,
+    range: Range,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatParams {
+    /// 省略时新建对话
+    #[serde(default)]
+    conversation: Option<String>,
+    message: String,
+    /// 附上的文件，给出`range`时只附上选中的部分
+    #[serde(rename = "textDocument", default)]
+    text_document: Option<TextDocumentIdentifier>,
+    #[serde(default)]
+    range: Option<Range>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResetChatParams {
+    conversation: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExecuteCommandParams {
+    command: String,
+    #[serde(default)]
+    arguments: Vec<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CancelParams {
+    id: Value,
+}
+
+/// 语言服务器的状态，只在主线程中修改
+struct Server {
+    output: Output,
+    settings: Settings,
+    documents: Documents,
+    /// 收到`initialize`之前为`None`
+    backend: Option<Backend>,
+    conversations: Arc<Conversations>,
+    /// 正在处理的请求的取消标记，键为请求id的JSON
+    pending: Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>,
+    shutdown: bool,
+}
+
+impl Server {
+    fn new(output: Output) -> Server {
+        Server {
+            output,
+            settings: Settings::from_env(),
+            documents: Documents::new(Encoding::Utf16),
+            backend: None,
+            conversations: Arc::new(Conversations::default()),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            shutdown: false,
+        }
+    }
+
+    /// 处理一条消息，收到`exit`时返回退出码
+    fn handle(&mut self, message: Value) -> Option<ExitCode> {
+        let Some(method) = message.get("method").and_then(Value::as_str) else {
+            // 编辑器对请求的响应，这里不发送请求
+            if message.get("id").is_none() {
+                self.output.respond(Value::Null, Err(RpcError::new(INVALID_REQUEST, "不是JSON-RPC消息")));
+            }
+            return None;
+        };
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+        match message.get("id").cloned() {
+            Some(id) => self.request(id, method, params),
+            None => return self.notification(method, params),
+        }
+        None
+    }
+
+    fn notification(&mut self, method: &str, params: Value) -> Option<ExitCode> {
+        let result = match method {
+            "exit" => {
+                return Some(if self.shutdown {
+                    ExitCode::SUCCESS
+                } else {
+                    ExitCode::FAILURE
+                })
+            }
+            "$/cancelRequest" => {
+                if let Ok(params) = serde_json::from_value::<CancelParams>(params) {
+                    let pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+                    if let Some(cancel) = pending.get(&params.id.to_string()) {
+                        cancel.store(true, Ordering::Relaxed);
+                    }
+                }
+                Ok(())
+            }
+            "textDocument/didOpen" => self.documents.open(params),
+            "textDocument/didChange" => self.documents.change(params),
+            "textDocument/didClose" => self.documents.close(params),
+            "workspace/didChangeConfiguration" => {
+                self.settings.apply(&params["settings"]);
+                self.connect().map(|_| ())
+            }
+            // `initialized`、`$/setTrace`等
+            _ => Ok(()),
+        };
+        if let Err(err) = result {
+            eprintln!("{} 处理失败: {}", method, err);
+        }
+        None
+    }
+
+    /// 按当前设置重新创建连接，已经开始的请求继续使用原来的连接
+    fn connect(&mut self) -> Result<&Backend, String> {
+        let backend = Backend::new(self.settings.clone(), self.documents.encoding)?;
+        Ok(self.backend.insert(backend))
+    }
+
+    fn request(&mut self, id: Value, method: &str, params: Value) {
+        let result = match method {
+            "initialize" => self.initialize(params),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            _ if self.backend.is_none() => Err(RpcError::new(SERVER_NOT_INITIALIZED, "还没有收到initialize")),
+            _ if self.shutdown => Err(RpcError::new(INVALID_REQUEST, "语言服务器正在关闭")),
+            "textDocument/inlineCompletion" | "openkimi/inlineCompletion" => {
+                return self.spawn(id, |server| {
+                    let params: PositionParams = rpc::params(params)?;
+                    let document = server.document(&params.text_document)?;
+                    Ok(move |backend: &Backend, cancel: &AtomicBool| {
+                        assist::complete(backend, &document, params.position, cancel)
+                    })
+                })
+            }
+            "openkimi/explain" => {
+                return self.spawn(id, |server| {
+                    let params: RangeParams = rpc::params(params)?;
+                    let document = server.document(&params.text_document)?;
+                    Ok(move |backend: &Backend, cancel: &AtomicBool| {
+                        let text = assist::explain(backend, &document, params.range, cancel)?;
+                        Ok(json!({ "text": text }))
+                    })
+                })
+            }
+            "textDocument/codeAction" => self.code_actions(params),
+            "workspace/executeCommand" => return self.execute_command(id, params),
+            "openkimi/chat" => return self.chat(id, params),
+            "openkimi/resetChat" => rpc::params(params)
+                .map(|params: ResetChatParams| json!({ "removed": self.conversations.reset(&params.conversation) })),
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("不支持的方法: {}", method))),
+        };
+        self.output.respond(id, result);
+    }
+
+    fn initialize(&mut self, params: Value) -> Result<Value, RpcError> {
+        let encoding = Encoding::negotiate(&params["capabilities"]);
+        if let Some(options) = params.get("initializationOptions") {
+            self.settings.apply(options);
+        }
+        self.documents = Documents::new(encoding);
+        self.connect().map_err(RpcError::internal)?;
+        Ok(json!({
+            "capabilities": {
+                "positionEncoding": encoding.name(),
+                "textDocumentSync": { "openClose": true, "change": 2 },
+                "inlineCompletionProvider": true,
+                "codeActionProvider": true,
+                "executeCommandProvider": { "commands": [EXPLAIN_COMMAND] },
+            },
+            "serverInfo": { "name": "openkimi-lsp", "version": env!("CARGO_PKG_VERSION") },
+        }))
+    }
+
+    /// 打开的文档的副本，交给处理请求的线程
+    fn document(&self, identifier: &TextDocumentIdentifier) -> Result<Document, RpcError> {
+        self.documents
+            .get(&identifier.uri)
+            .cloned()
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("文档没有打开: {}", identifier.uri)))
+    }
+
+    /// 在新线程中处理请求：`prepare`在主线程中读取参数和文档，返回的闭包在新线程中执行
+    fn spawn<F>(&mut self, id: Value, prepare: impl FnOnce(&Server) -> Result<F, RpcError>)
+    where
+        F: FnOnce(&Backend, &AtomicBool) -> Result<Value, RpcError> + Send + 'static,
+    {
+        let (work, backend) = match (prepare(self), &self.backend) {
+            (Ok(work), Some(backend)) => (work, backend.clone()),
+            (Err(err), _) => return self.output.respond(id, Err(err)),
+            (_, None) => return self.output.respond(id, Err(RpcError::new(SERVER_NOT_INITIALIZED, "还没有连接"))),
+        };
+        let key = id.to_string();
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.pending.lock().unwrap_or_else(|e| e.into_inner()).insert(key.clone(), cancel.clone());
+        let output = self.output.clone();
+        let pending = self.pending.clone();
+        thread::spawn(move || {
+            let result = work(&backend, &cancel);
+            pending.lock().unwrap_or_else(|e| e.into_inner()).remove(&key);
+            output.respond(id, result);
+        });
+    }
+
+    /// 选中代码时提供解释的命令
+    fn code_actions(&self, params: Value) -> Result<Value, RpcError> {
+        let params: RangeParams = rpc::params(params)?;
+        if params.range.is_empty() || self.documents.get(&params.text_document.uri).is_none() {
+            return Ok(json!([]));
+        }
+        let title = "OpenKimi：解释选中的代码";
+        let arguments = json!({ "textDocument": { "uri": params.text_document.uri }, "range": params.range });
+        Ok(json!([{
+            "title": title,
+            "command": { "title": title, "command": EXPLAIN_COMMAND, "arguments": [arguments] },
+        }]))
+    }
+
+    fn execute_command(&mut self, id: Value, params: Value) {
+        self.spawn(id, |server| {
+            let params: ExecuteCommandParams = rpc::params(params)?;
+            if params.command != EXPLAIN_COMMAND {
+                return Err(RpcError::new(INVALID_PARAMS, format!("未知的命令: {}", params.command)));
+            }
+            let argument = params.arguments.into_iter().next().unwrap_or(Value::Null);
+            let argument: RangeParams = rpc::params(argument)?;
+            let document = server.document(&argument.text_document)?;
+            let output = server.output.clone();
+            Ok(move |backend: &Backend, cancel: &AtomicBool| {
+                let result = assist::explain(backend, &document, argument.range, cancel);
+                let (kind, message) = match &result {
+                    Ok(text) => (MESSAGE_INFO, text.clone()),
+                    Err(err) if err.code == REQUEST_CANCELLED => return result.map(|_| Value::Null),
+                    Err(err) => (MESSAGE_ERROR, format!("OpenKimi：{}", err.message)),
+                };
+                output.notify("window/showMessage", json!({ "type": kind, "message": message }));
+                result.map(|text| json!({ "text": text }))
+            })
+        })
+    }
+
+    fn chat(&mut self, id: Value, params: Value) {
+        self.spawn(id, |server| {
+            let params: ChatParams = rpc::params(params)?;
+            let document = params.text_document.as_ref().map(|document| server.document(document)).transpose()?;
+            let conversations = server.conversations.clone();
+            let output = server.output.clone();
+            Ok(move |backend: &Backend, cancel: &AtomicBool| {
+                let context = document.as_ref().map(|document| (document, params.range));
+                let on_delta = |conversation: &str, delta: &str| {
+                    output.notify(
+                        "openkimi/chatDelta",
+                        json!({ "conversation": conversation, "delta": delta }),
+                    );
+                };
+                let (conversation, text) =
+                    conversations.send(backend, params.conversation, &params.message, context, cancel, on_delta)?;
+                Ok(json!({ "conversation": conversation, "text": text }))
+            })
+        })
+    }
+}
+
+fn print_usage() {
+    println!("用法: openkimi-lsp [--stdio]");
+    println!("由编辑器启动，通过标准输入输出收发 LSP 消息。");
+    println!("环境变量: OPENKIMI_BASE_URL、OPENKIMI_API_KEY、OPENKIMI_MODEL");
+}
+
+fn main() -> ExitCode {
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            // 编辑器通常会加上，只支持stdio
+            "--stdio" => {}
+            "-h" | "--help" => {
+                print_usage();
+                return ExitCode::SUCCESS;
+            }
+            "-V" | "--version" => {
+                println!("openkimi-lsp {}", env!("CARGO_PKG_VERSION"));
+                return ExitCode::SUCCESS;
+            }
+            other => {
+                eprintln!("未知参数: {}", other);
+                print_usage();
+                return ExitCode::from(2);
+            }
+        }
+    }
+
+    let mut server = Server::new(Output::new(io::stdout()));
+    let mut reader = BufReader::new(io::stdin());
+    loop {
+        let body = match rpc::read_message(&mut reader) {
+            Ok(Some(body)) => body,
+            // 编辑器没有发送exit就关闭了连接
+            Ok(None) => return ExitCode::FAILURE,
+            Err(err) => {
+                eprintln!("读取消息失败: {}", err);
+                return ExitCode::FAILURE;
+            }
+        };
+        match serde_json::from_slice::<Value>(&body) {
+            Ok(message) if message.is_object() => {
+                if let Some(code) = server.handle(message) {
+                    return code;
+                }
+            }
+            Ok(_) => server.output.respond(Value::Null, Err(RpcError::new(INVALID_REQUEST, "消息不是对象"))),
+            Err(err) => server.output.respond(Value::Null, Err(RpcError::new(PARSE_ERROR, err.to_string()))),
+        }
+    }
+}
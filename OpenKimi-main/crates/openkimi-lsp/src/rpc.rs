@@ -0,0 +1,111 @@
+//! LSP的JSON-RPC消息：`Content-Length`头加JSON消息体
+
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+/// LSP约定的收到`initialize`之前的请求
+pub const SERVER_NOT_INITIALIZED: i64 = -32002;
+/// LSP约定的请求被`$/cancelRequest`取消
+pub const REQUEST_CANCELLED: i64 = -32800;
+
+/// JSON-RPC错误
+#[derive(Debug)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> RpcError {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn internal(message: impl Into<String>) -> RpcError {
+        RpcError::new(INTERNAL_ERROR, message)
+    }
+
+    pub fn cancelled() -> RpcError {
+        RpcError::new(REQUEST_CANCELLED, "请求已取消")
+    }
+}
+
+pub fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, format!("参数无效: {}", e)))
+}
+
+/// 读取一条消息的消息体，输入结束时为`None`
+pub fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Vec<u8>>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            // 消息之间多余的空行
+            if length.is_none() {
+                continue;
+            }
+            break;
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("无效的消息头: {}", line)))?;
+        if name.trim().eq_ignore_ascii_case("content-length") {
+            let value = value.trim();
+            let parsed = value
+                .parse::<usize>()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("无效的Content-Length: {}", value)))?;
+            length = Some(parsed);
+        }
+    }
+    let mut body = vec![0; length.unwrap_or_default()];
+    reader.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+/// 向编辑器发送消息，各线程共享，每条消息整体写出
+#[derive(Clone)]
+pub struct Output {
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl Output {
+    pub fn new(writer: impl Write + Send + 'static) -> Output {
+        Output {
+            writer: Arc::new(Mutex::new(Box::new(writer))),
+        }
+    }
+
+    fn send(&self, message: &Value) {
+        let body = message.to_string();
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        let written = write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body).and_then(|_| writer.flush());
+        if let Err(err) = written {
+            eprintln!("写入消息失败: {}", err);
+        }
+    }
+
+    pub fn respond(&self, id: Value, result: Result<Value, RpcError>) {
+        self.send(&match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(err) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": err.code, "message": err.message } }),
+        });
+    }
+
+    pub fn notify(&self, method: &str, params: Value) {
+        self.send(&json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+}
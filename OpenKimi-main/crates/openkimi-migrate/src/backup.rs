@@ -0,0 +1,162 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use openkimi_helper::portable::SKIPPED_FILES;
+
+/// 保留的备份数量，超出后删除最旧的备份
+const KEEP_BACKUPS: usize = 3;
+
+/// 备份根目录：与数据目录同级的`<目录名>-backups`，避免备份被复制进自身
+pub fn backup_root(data_dir: &Path) -> PathBuf {
+    let name = data_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "data".to_string());
+    data_dir.with_file_name(format!("{}-backups", name))
+}
+
+/// Chromium的缓存目录，可以重新生成，不需要备份
+const CACHE_DIRS: [&str; 7] = [
+    "Cache",
+    "Code Cache",
+    "GPUCache",
+    "DawnCache",
+    "DawnGraphiteCache",
+    "ShaderCache",
+    "GrShaderCache",
+];
+
+/// 不备份的条目：Electron的锁文件、缓存目录和符号链接；锁文件是指向其他位置的符号链接，所有符号链接都不复制
+fn skipped(entry: &fs::DirEntry) -> io::Result<bool> {
+    let name = entry.file_name();
+    let file_type = entry.file_type()?;
+    Ok(SKIPPED_FILES.iter().any(|skipped| name == *skipped)
+        || file_type.is_symlink()
+        || (file_type.is_dir() && CACHE_DIRS.iter().any(|cache| name == *cache)))
+}
+
+/// 复制目录树，跳过不备份的条目
+fn copy_dir_all(src: &Path, dst: &Path) -> io::Result<()> {
+    fs::create_dir_all(dst)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        if skipped(&entry)? {
+            continue;
+        }
+        let src_path = entry.path();
+        let dst_path = dst.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            copy_dir_all(&src_path, &dst_path)?;
+        } else if file_type.is_file() {
+            fs::copy(&src_path, &dst_path)?;
+        }
+    }
+    Ok(())
+}
+
+/// 删除目录中会被备份的内容，保留不备份的条目；只剩下保留条目的子目录不删除
+fn remove_backed_up(dir: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if skipped(&entry)? {
+            continue;
+        }
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            remove_backed_up(&path)?;
+            if fs::read_dir(&path)?.next().is_none() {
+                fs::remove_dir(&path)?;
+            }
+        } else {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// 按名称（时间戳）排序的全部备份
+pub fn list_backups(data_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let root = backup_root(data_dir);
+    if !root.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut backups: Vec<PathBuf> = fs::read_dir(root)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    backups.sort();
+    Ok(backups)
+}
+
+/// 创建数据目录的完整备份
+pub fn create_backup(data_dir: &Path) -> io::Result<PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    let backup_dir = backup_root(data_dir).join(timestamp.to_string());
+    copy_dir_all(data_dir, &backup_dir)?;
+
+    let backups = list_backups(data_dir)?;
+    if backups.len() > KEEP_BACKUPS {
+        for old in &backups[..backups.len() - KEEP_BACKUPS] {
+            let _ = fs::remove_dir_all(old);
+        }
+    }
+
+    Ok(backup_dir)
+}
+
+/// 用备份覆盖数据目录，成功后删除该备份
+///
+/// 备份时跳过的锁文件、符号链接和缓存目录原样保留，其余内容恢复为备份时的状态，迁移中新建的文件也会删除。
+pub fn restore_backup(data_dir: &Path, backup_dir: &Path) -> io::Result<()> {
+    if data_dir.is_dir() {
+        remove_backed_up(data_dir)?;
+    }
+    copy_dir_all(backup_dir, data_dir)?;
+    fs::remove_dir_all(backup_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restore_keeps_skipped_entries() {
+        let root = tempfile::tempdir().unwrap();
+        let data = root.path().join("OpenKimi");
+        fs::create_dir_all(data.join("plugins")).unwrap();
+        fs::create_dir_all(data.join("Partitions/main/Cache")).unwrap();
+        fs::write(data.join("license.json"), "old").unwrap();
+        fs::write(data.join("plugins/a.js"), "a").unwrap();
+        fs::write(data.join("Partitions/main/Cache/data_0"), "cache").unwrap();
+        fs::write(data.join("lockfile"), "").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("/nonexistent", data.join("SingletonLock")).unwrap();
+
+        let backup = create_backup(&data).unwrap();
+        assert!(!backup.join("lockfile").exists());
+        assert!(!backup.join("Partitions/main/Cache").exists());
+
+        // 迁移改动了文件、删除了插件、新建了文件
+        fs::write(data.join("license.json"), "new").unwrap();
+        fs::remove_file(data.join("plugins/a.js")).unwrap();
+        fs::create_dir_all(data.join("sessions")).unwrap();
+        fs::write(data.join("sessions/1.json"), "{}").unwrap();
+
+        restore_backup(&data, &backup).unwrap();
+        assert_eq!(fs::read_to_string(data.join("license.json")).unwrap(), "old");
+        assert_eq!(fs::read_to_string(data.join("plugins/a.js")).unwrap(), "a");
+        assert!(!data.join("sessions").exists());
+        assert_eq!(fs::read_to_string(data.join("Partitions/main/Cache/data_0")).unwrap(), "cache");
+        assert!(data.join("lockfile").exists());
+        #[cfg(unix)]
+        assert!(fs::symlink_metadata(data.join("SingletonLock")).unwrap().file_type().is_symlink());
+        assert!(!backup.exists());
+    }
+}
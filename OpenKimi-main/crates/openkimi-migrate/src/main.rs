@@ -0,0 +1,150 @@
+//! OpenKimi客户端数据迁移工具
+//!
+//! ```text
+//! migrate run --data-dir <目录> [--app-version <版本>]
+//! migrate status --data-dir <目录>
+//! migrate rollback --data-dir <目录>
+//! ```
+//!
+//! 数据格式版本记录在数据目录的`data-version.json`中。`run`会先完整备份数据目录，
+//! 任何一步失败都会自动回滚到备份；`rollback`用于手动恢复最近一次迁移前的数据。
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use serde::{Deserialize, Serialize};
+
+mod backup;
+mod steps;
+
+const STATE_FILE: &str = "data-version.json";
+
+/// 数据目录的版本状态
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DataState {
+    data_version: u32,
+    app_version: Option<String>,
+}
+
+fn read_state(data_dir: &Path) -> io::Result<DataState> {
+    match fs::read(data_dir.join(STATE_FILE)) {
+        Ok(content) => serde_json::from_slice(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        // 没有状态文件说明是迁移工具出现之前的旧版本数据
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(DataState::default()),
+        Err(err) => Err(err),
+    }
+}
+
+fn write_state(data_dir: &Path, state: &DataState) -> io::Result<()> {
+    fs::write(data_dir.join(STATE_FILE), serde_json::to_vec_pretty(state)?)
+}
+
+fn option_value(args: &[String], name: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|index| args.get(index + 1))
+        .cloned()
+}
+
+fn run_migrations(data_dir: &Path, app_version: Option<String>) -> io::Result<()> {
+    let latest = steps::latest_version();
+
+    if !data_dir.exists() {
+        // 全新安装，直接标记为最新格式
+        fs::create_dir_all(data_dir)?;
+        write_state(data_dir, &DataState { data_version: latest, app_version })?;
+        println!("✅ 全新数据目录，已标记为数据格式 v{}", latest);
+        return Ok(());
+    }
+
+    let mut state = read_state(data_dir)?;
+    if state.data_version > latest {
+        return Err(io::Error::other(format!(
+            "数据格式 v{} 比当前程序支持的 v{} 更新，请升级客户端",
+            state.data_version, latest
+        )));
+    }
+
+    let pending: Vec<_> = steps::MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version > state.data_version)
+        .collect();
+    if pending.is_empty() {
+        if app_version.is_some() && state.app_version != app_version {
+            state.app_version = app_version;
+            write_state(data_dir, &state)?;
+        }
+        println!("✅ 数据已是最新格式 v{}", latest);
+        return Ok(());
+    }
+
+    let backup_dir = backup::create_backup(data_dir)?;
+    println!("💾 已备份数据到 {:?}", backup_dir);
+
+    for migration in pending {
+        println!("🔄 正在执行迁移 v{} ({})...", migration.version, migration.name);
+        if let Err(err) = (migration.apply)(data_dir) {
+            eprintln!("❌ 迁移 {} 失败: {}，正在回滚...", migration.name, err);
+            backup::restore_backup(data_dir, &backup_dir)?;
+            eprintln!("↩️ 已恢复迁移前的数据");
+            return Err(err);
+        }
+        state.data_version = migration.version;
+    }
+
+    state.app_version = app_version;
+    write_state(data_dir, &state)?;
+    println!("🎉 数据已升级到格式 v{}", latest);
+    Ok(())
+}
+
+fn show_status(data_dir: &Path) -> io::Result<()> {
+    let state = read_state(data_dir)?;
+    println!("📂 数据目录: {:?}", data_dir);
+    println!("📄 数据格式: v{} (最新 v{})", state.data_version, steps::latest_version());
+    if let Some(version) = &state.app_version {
+        println!("🏷️ 上次运行的客户端版本: {}", version);
+    }
+    let backups = backup::list_backups(data_dir)?;
+    println!("💾 可用备份: {}", backups.len());
+    for backup_dir in backups {
+        println!("   {:?}", backup_dir);
+    }
+    Ok(())
+}
+
+fn rollback(data_dir: &Path) -> io::Result<()> {
+    let backups = backup::list_backups(data_dir)?;
+    let latest = backups
+        .last()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "没有可用的备份"))?;
+    backup::restore_backup(data_dir, latest)?;
+    println!("↩️ 已从 {:?} 恢复数据", latest);
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let Some(data_dir) = option_value(&args, "--data-dir").map(PathBuf::from) else {
+        eprintln!("用法: migrate <run|status|rollback> --data-dir <目录> [--app-version <版本>]");
+        return ExitCode::FAILURE;
+    };
+
+    let result = match args.first().map(String::as_str) {
+        Some("run") => run_migrations(&data_dir, option_value(&args, "--app-version")),
+        Some("status") => show_status(&data_dir),
+        Some("rollback") => rollback(&data_dir),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "未知命令，可用: run, status, rollback")),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("❌ {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
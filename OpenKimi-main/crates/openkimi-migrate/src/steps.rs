@@ -0,0 +1,25 @@
+//! 数据格式迁移步骤
+//!
+//! 每个步骤把数据目录从`version - 1`升级到`version`，按版本号顺序执行。新增步骤时只需追加到[`MIGRATIONS`]末尾。
+//! 客户端的会话保存在网页端，用户数据目录中目前只有插件、密钥存储和许可证等文件，它们的格式还没有变过，
+//! 所以暂时没有迁移步骤，`run`只记录数据格式版本和客户端版本，不做备份。
+//! 1.0之前的客户端没有在数据目录中保存配置文件（插件的名称和启用状态写在插件脚本本身），
+//! 不存在需要转换的旧配置格式，因此也没有配置迁移步骤。
+
+use std::io;
+use std::path::Path;
+
+/// 单个迁移步骤
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub apply: fn(&Path) -> io::Result<()>,
+}
+
+/// 全部迁移步骤，按版本号递增排列
+pub const MIGRATIONS: &[Migration] = &[];
+
+/// 当前的数据格式版本
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
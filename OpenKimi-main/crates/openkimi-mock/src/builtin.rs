@@ -0,0 +1,267 @@
+//! 没有匹配的夹具时使用的内置响应
+//!
+//! 对话回复固定为"模拟回复："加上最后一条用户消息，按`stream`逐块或一次返回；请求要求调用工具
+//! （`tool_choice`为`required`或指定函数）时改为调用第一个或指定的工具，要求JSON输出时回复`{}`。
+//! 嵌入向量由文本的SHA-256确定，同一文本总是得到同一个单位向量。token数按每4个字符一个估算。
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+/// 默认的嵌入向量维度，请求中的`dimensions`优先
+pub const DEFAULT_DIMENSIONS: usize = 256;
+
+/// 流式回复每块的字符数
+const CHUNK_CHARS: usize = 4;
+
+/// 内置响应的结果
+pub enum Reply {
+    Json(Value),
+    /// SSE事件的`data`，最后一项为`[DONE]`
+    Events(Vec<Value>),
+    /// 状态码和错误消息
+    Error(u16, String),
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn estimate_tokens(text: &str) -> u64 {
+    (text.chars().count() as u64).div_ceil(4).max(1)
+}
+
+/// 消息内容为字符串或多模态的内容数组时拼接其中的文本
+fn content_text(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part.get("text").and_then(Value::as_str))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// 按请求路径选择内置响应，没有对应的内置响应时为`None`
+pub fn reply(method: &str, path: &str, body: &Value, models: &[String]) -> Option<Reply> {
+    match (method, path.trim_end_matches('/')) {
+        ("GET", "/healthz") | ("GET", "/readyz") => Some(Reply::Json(json!({ "status": "ok" }))),
+        ("GET", "/v1/models") => Some(Reply::Json(model_list(models))),
+        ("POST", "/v1/chat/completions") => Some(chat(body, models)),
+        ("POST", "/v1/embeddings") => Some(embeddings(body, models)),
+        _ => None,
+    }
+}
+
+fn model_list(models: &[String]) -> Value {
+    let data: Vec<Value> = models
+        .iter()
+        .map(|id| json!({ "id": id, "object": "model", "created": 0, "owned_by": "openkimi-mock" }))
+        .collect();
+    json!({ "object": "list", "data": data })
+}
+
+/// 回复的内容或工具调用
+enum Answer {
+    Text(String),
+    Tool { name: String, arguments: String },
+}
+
+fn answer(body: &Value) -> Answer {
+    let tools = body.get("tools").and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default();
+    let tool_name = |tool: &Value| tool.pointer("/function/name").and_then(Value::as_str).map(str::to_string);
+    match body.get("tool_choice") {
+        Some(Value::String(choice)) if choice == "required" => {
+            if let Some(name) = tools.first().and_then(tool_name) {
+                return Answer::Tool {
+                    name,
+                    arguments: "{}".to_string(),
+                };
+            }
+        }
+        Some(choice @ Value::Object(_)) => {
+            if let Some(name) = tool_name(choice) {
+                return Answer::Tool {
+                    name,
+                    arguments: "{}".to_string(),
+                };
+            }
+        }
+        _ => {}
+    }
+
+    let format = body.pointer("/response_format/type").and_then(Value::as_str);
+    if matches!(format, Some("json_object" | "json_schema")) {
+        return Answer::Text("{}".to_string());
+    }
+    let last_user = body
+        .get("messages")
+        .and_then(Value::as_array)
+        .and_then(|messages| {
+            messages
+                .iter()
+                .rev()
+                .find(|message| message.get("role").and_then(Value::as_str) == Some("user"))
+        })
+        .and_then(|message| message.get("content"))
+        .map(content_text)
+        .unwrap_or_default();
+    Answer::Text(format!("模拟回复：{}", last_user))
+}
+
+fn chat(body: &Value, models: &[String]) -> Reply {
+    let Some(messages) = body.get("messages").and_then(Value::as_array).filter(|messages| !messages.is_empty()) else {
+        return Reply::Error(400, "messages 不能为空".to_string());
+    };
+    let model = body
+        .get("model")
+        .and_then(Value::as_str)
+        .filter(|model| !model.is_empty())
+        .or(models.first().map(String::as_str))
+        .unwrap_or("kimi-mock")
+        .to_string();
+    let id = format!("chatcmpl-mock-{}", NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    let created = now();
+    let prompt: String = messages
+        .iter()
+        .filter_map(|message| message.get("content"))
+        .map(content_text)
+        .collect();
+    let answer = answer(body);
+    let (completion, finish_reason) = match &answer {
+        Answer::Text(text) => (text.as_str(), "stop"),
+        Answer::Tool { arguments, .. } => (arguments.as_str(), "tool_calls"),
+    };
+    let usage = {
+        let (prompt_tokens, completion_tokens) = (estimate_tokens(&prompt), estimate_tokens(completion));
+        json!({
+            "prompt_tokens": prompt_tokens,
+            "completion_tokens": completion_tokens,
+            "total_tokens": prompt_tokens + completion_tokens,
+        })
+    };
+
+    if body.get("stream").and_then(Value::as_bool) != Some(true) {
+        let message = match &answer {
+            Answer::Text(text) => json!({ "role": "assistant", "content": text }),
+            Answer::Tool { name, arguments } => json!({
+                "role": "assistant",
+                "content": null,
+                "tool_calls": [{
+                    "id": "call_mock_0",
+                    "type": "function",
+                    "function": { "name": name, "arguments": arguments },
+                }],
+            }),
+        };
+        return Reply::Json(json!({
+            "id": id,
+            "object": "chat.completion",
+            "created": created,
+            "model": model,
+            "choices": [{ "index": 0, "message": message, "finish_reason": finish_reason }],
+            "usage": usage,
+        }));
+    }
+
+    let chunk = |delta: Value, finish_reason: Value| {
+        json!({
+            "id": id,
+            "object": "chat.completion.chunk",
+            "created": created,
+            "model": model,
+            "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+        })
+    };
+    let mut events = vec![chunk(json!({ "role": "assistant", "content": "" }), Value::Null)];
+    match &answer {
+        Answer::Text(text) => {
+            let chars: Vec<char> = text.chars().collect();
+            for piece in chars.chunks(CHUNK_CHARS) {
+                events.push(chunk(json!({ "content": piece.iter().collect::<String>() }), Value::Null));
+            }
+        }
+        Answer::Tool { name, arguments } => events.push(chunk(
+            json!({
+                "tool_calls": [{
+                    "index": 0,
+                    "id": "call_mock_0",
+                    "type": "function",
+                    "function": { "name": name, "arguments": arguments },
+                }],
+            }),
+            Value::Null,
+        )),
+    }
+    events.push(chunk(json!({}), json!(finish_reason)));
+    if body.pointer("/stream_options/include_usage").and_then(Value::as_bool) == Some(true) {
+        events.push(json!({
+            "id": id,
+            "object": "chat.completion.chunk",
+            "created": created,
+            "model": model,
+            "choices": [],
+            "usage": usage,
+        }));
+    }
+    events.push(json!("[DONE]"));
+    Reply::Events(events)
+}
+
+/// 由文本确定的单位向量
+fn embed(text: &str, dimensions: usize) -> Vec<f32> {
+    let mut vector = Vec::with_capacity(dimensions);
+    let mut block = 0u32;
+    while vector.len() < dimensions {
+        let digest = Sha256::new().chain_update(block.to_le_bytes()).chain_update(text.as_bytes()).finalize();
+        vector.extend(digest.chunks(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]]) as f32 / i16::MAX as f32));
+        block += 1;
+    }
+    vector.truncate(dimensions);
+    let norm = vector.iter().map(|value| value * value).sum::<f32>().sqrt().max(f32::EPSILON);
+    vector.iter_mut().for_each(|value| *value /= norm);
+    vector
+}
+
+fn embeddings(body: &Value, models: &[String]) -> Reply {
+    let inputs: Vec<String> = match body.get("input") {
+        Some(Value::String(text)) => vec![text.clone()],
+        Some(Value::Array(items)) => items
+            .iter()
+            .map(|item| match item {
+                Value::String(text) => text.clone(),
+                // token数组按原样的JSON文本计算
+                other => other.to_string(),
+            })
+            .collect(),
+        _ => return Reply::Error(400, "input 必须是字符串或数组".to_string()),
+    };
+    let dimensions = body
+        .get("dimensions")
+        .and_then(Value::as_u64)
+        .map_or(DEFAULT_DIMENSIONS, |dimensions| dimensions.clamp(1, 8192) as usize);
+    let model = body
+        .get("model")
+        .and_then(Value::as_str)
+        .filter(|model| !model.is_empty())
+        .or(models.last().map(String::as_str))
+        .unwrap_or("kimi-mock-embedding");
+    let tokens: u64 = inputs.iter().map(|input| estimate_tokens(input)).sum();
+    let data: Vec<Value> = inputs
+        .iter()
+        .enumerate()
+        .map(|(index, input)| json!({ "object": "embedding", "index": index, "embedding": embed(input, dimensions) }))
+        .collect();
+    Reply::Json(json!({
+        "object": "list",
+        "data": data,
+        "model": model,
+        "usage": { "prompt_tokens": tokens, "total_tokens": tokens },
+    }))
+}
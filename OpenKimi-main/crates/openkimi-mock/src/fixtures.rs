@@ -0,0 +1,141 @@
+//! 夹具：按方法、路径和请求体匹配的固定响应
+//!
+//! 夹具目录下的每个`.json`文件是一个夹具或夹具数组，按文件名排序后逐个匹配，第一个匹配的生效：
+//!
+//! ```json
+//! {
+//!   "request": { "method": "POST", "path": "/v1/sessions/{id}/messages", "body": { "role": "user" } },
+//!   "response": { "status": 200, "headers": {}, "body": { ... } }
+//! }
+//! ```
+//!
+//! - `path`中的`{name}`匹配一段，末尾的`{*name}`匹配剩余的全部；查询参数不参与匹配。
+//! - `body`为请求体需要包含的字段，对象按键递归比较，其余值要求相等；省略时不检查请求体。
+//! - 流式响应用`events`代替`body`，每项是SSE事件的`data`，字符串原样发送，其他值序列化为JSON，通常以`"[DONE]"`结尾。
+//! - `latency_ms`为返回前等待的毫秒数，`interval_ms`为流式事件之间的间隔，未指定时使用命令行参数。
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FixtureRequest {
+    pub method: String,
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FixtureResponse {
+    #[serde(default = "default_status")]
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub body: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub events: Option<Vec<Value>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fixture {
+    pub request: FixtureRequest,
+    pub response: FixtureResponse,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FixtureFile {
+    One(Fixture),
+    Many(Vec<Fixture>),
+}
+
+/// `pattern`的每一段与`path`比较
+fn path_matches(pattern: &str, path: &str) -> bool {
+    let mut pattern = pattern.trim_matches('/').split('/');
+    let mut path = path.trim_matches('/').split('/');
+    loop {
+        match (pattern.next(), path.next()) {
+            (None, None) => return true,
+            (Some(segment), Some(_)) if segment.starts_with("{*") => return true,
+            (Some(segment), Some(actual)) if segment.starts_with('{') && segment.ends_with('}') => {
+                if actual.is_empty() {
+                    return false;
+                }
+            }
+            (Some(segment), Some(actual)) if segment == actual => {}
+            _ => return false,
+        }
+    }
+}
+
+/// `actual`是否包含`expected`的全部字段
+fn body_matches(expected: &Value, actual: &Value) -> bool {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => expected
+            .iter()
+            .all(|(key, value)| actual.get(key).is_some_and(|actual| body_matches(value, actual))),
+        _ => expected == actual,
+    }
+}
+
+impl Fixture {
+    fn matches(&self, method: &str, path: &str, body: &Value) -> bool {
+        self.request.method.eq_ignore_ascii_case(method)
+            && path_matches(&self.request.path, path)
+            && self.request.body.as_ref().is_none_or(|expected| body_matches(expected, body))
+    }
+}
+
+/// 已加载的全部夹具
+#[derive(Debug, Default)]
+pub struct Fixtures {
+    fixtures: Vec<Fixture>,
+}
+
+impl Fixtures {
+    /// 读取目录下的全部`.json`文件，目录不存在时为空
+    pub fn load(dir: &Path) -> Result<Fixtures, String> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Fixtures::default()),
+            Err(err) => return Err(format!("读取夹具目录 {} 失败: {}", dir.display(), err)),
+        };
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        paths.sort();
+
+        let mut fixtures = Vec::new();
+        for path in paths {
+            let content = fs::read_to_string(&path).map_err(|e| format!("读取夹具 {} 失败: {}", path.display(), e))?;
+            match serde_json::from_str(&content).map_err(|e| format!("解析夹具 {} 失败: {}", path.display(), e))? {
+                FixtureFile::One(fixture) => fixtures.push(fixture),
+                FixtureFile::Many(many) => fixtures.extend(many),
+            }
+        }
+        Ok(Fixtures { fixtures })
+    }
+
+    pub fn len(&self) -> usize {
+        self.fixtures.len()
+    }
+
+    /// 第一个匹配的夹具，请求体不是JSON时视为`null`
+    pub fn find(&self, method: &str, path: &str, body: &Value) -> Option<&Fixture> {
+        self.fixtures.iter().find(|fixture| fixture.matches(method, path, body))
+    }
+}
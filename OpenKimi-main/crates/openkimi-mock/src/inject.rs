@@ -0,0 +1,79 @@
+//! 延迟和错误注入
+//!
+//! 命令行参数对所有`/v1`请求生效；单个请求可以用请求头覆盖，便于前端逐个调试加载和出错状态：
+//! `x-openkimi-mock-latency`为毫秒数，`x-openkimi-mock-error`为要返回的状态码。
+
+use std::time::Duration;
+
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::json;
+
+pub const LATENCY_HEADER: &str = "x-openkimi-mock-latency";
+pub const ERROR_HEADER: &str = "x-openkimi-mock-error";
+
+/// 注入的配置
+#[derive(Debug, Clone, Default)]
+pub struct Injection {
+    /// 返回前固定等待的时间
+    pub latency: Duration,
+    /// 在`latency`之外随机增加的最长时间
+    pub jitter: Duration,
+    /// 返回错误的概率，0到1
+    pub error_rate: f64,
+    /// 随机错误的状态码
+    pub error_status: u16,
+}
+
+/// 0到1之间的随机数
+fn random() -> f64 {
+    let mut bytes = [0u8; 4];
+    let _ = getrandom::getrandom(&mut bytes);
+    u32::from_le_bytes(bytes) as f64 / u32::MAX as f64
+}
+
+fn header<T: std::str::FromStr>(headers: &HeaderMap, name: &str) -> Option<T> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+impl Injection {
+    /// 本次请求的延迟
+    pub fn delay(&self, headers: &HeaderMap) -> Duration {
+        if let Some(millis) = header::<u64>(headers, LATENCY_HEADER) {
+            return Duration::from_millis(millis);
+        }
+        self.latency + self.jitter.mul_f64(random())
+    }
+
+    /// 本次请求要返回的错误状态码，不注入时为`None`
+    pub fn error(&self, headers: &HeaderMap) -> Option<u16> {
+        if let Some(status) = header::<u16>(headers, ERROR_HEADER) {
+            return Some(status);
+        }
+        (self.error_rate > 0.0 && random() < self.error_rate).then_some(self.error_status)
+    }
+}
+
+/// OpenAI格式的错误响应，类型和错误码与服务端对应状态码返回的相同
+pub fn error_response(status: u16, message: &str) -> Response {
+    let status = StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let (kind, code) = match status.as_u16() {
+        400 => ("invalid_request_error", None),
+        401 => ("invalid_request_error", Some("invalid_api_key")),
+        403 => ("invalid_request_error", Some("insufficient_permissions")),
+        404 => ("invalid_request_error", Some("not_found")),
+        413 => ("invalid_request_error", Some("payload_too_large")),
+        422 => ("invalid_response_error", Some("schema_validation_failed")),
+        429 => ("rate_limit_error", Some("rate_limit_exceeded")),
+        502 | 504 => ("upstream_error", None),
+        503 => ("server_error", Some("service_unavailable")),
+        _ => ("server_error", None),
+    };
+    let body = json!({ "error": { "message": message, "type": kind, "code": code } });
+    let mut response = (status, Json(body)).into_response();
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        response.headers_mut().insert("retry-after", HeaderValue::from_static("1"));
+    }
+    response
+}
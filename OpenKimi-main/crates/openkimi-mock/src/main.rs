@@ -0,0 +1,295 @@
+//! openkimi-mock：OpenKimi API的模拟服务
+//!
+//! ```text
+//! openkimi-mock [--host 127.0.0.1] [--port 8000] [--fixtures <目录>] [--models kimi-mock,kimi-mock-embedding]
+//!               [--latency <毫秒>] [--jitter <毫秒>] [--interval <毫秒>] [--error-rate <0-1>] [--error-status 500]
+//!               [--api-key <令牌>]
+//! openkimi-mock --record <服务端地址> --fixtures <目录> [--upstream-key <令牌>] [--host ...] [--port ...]
+//! ```
+//!
+//! 依次用夹具目录中匹配的夹具、内置的对话/嵌入/模型列表响应回复，都没有时返回404。
+//! 录制模式下把全部请求转发给真实的服务端，并把每次交互保存为夹具目录中的一个文件，之后不带`--record`即可回放。
+
+use std::convert::Infallible;
+use std::env;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::{Body, Bytes};
+use axum::extract::State;
+use axum::http::{header, HeaderMap, Method, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::{Json, Router};
+use futures_util::stream::{self, StreamExt};
+use serde_json::Value;
+
+mod builtin;
+mod fixtures;
+mod inject;
+mod record;
+
+use builtin::Reply;
+use fixtures::{FixtureResponse, Fixtures};
+use inject::{error_response, Injection};
+use record::Recorder;
+
+/// 命令行选项
+struct Options {
+    host: String,
+    port: u16,
+    fixtures: Option<PathBuf>,
+    models: Vec<String>,
+    injection: Injection,
+    /// 流式事件之间的间隔
+    interval: Duration,
+    /// 指定时要求请求带上这个令牌
+    api_key: Option<String>,
+    /// 录制模式下转发到的服务端地址
+    record: Option<String>,
+    upstream_key: Option<String>,
+}
+
+/// 模拟服务的状态
+struct Mock {
+    fixtures: Fixtures,
+    models: Vec<String>,
+    injection: Injection,
+    interval: Duration,
+    api_key: Option<String>,
+    recorder: Option<Arc<Recorder>>,
+}
+
+fn print_usage() {
+    println!("用法: openkimi-mock [选项]");
+    println!("  --host <地址>           监听地址，默认 127.0.0.1");
+    println!("  --port <端口>           监听端口，默认 8000");
+    println!("  --fixtures <目录>       夹具目录，录制模式下保存到这里");
+    println!("  --models <列表>         /v1/models 返回的模型，逗号分隔");
+    println!("  --latency <毫秒>        每个请求返回前等待的时间");
+    println!("  --jitter <毫秒>         在 --latency 之外随机增加的最长时间");
+    println!("  --interval <毫秒>       流式事件之间的间隔，默认 20");
+    println!("  --error-rate <0-1>      随机返回错误的概率");
+    println!("  --error-status <状态码> 随机错误的状态码，默认 500");
+    println!("  --api-key <令牌>        要求请求带上这个令牌");
+    println!("  --record <地址>         录制模式：转发给该地址的服务端并保存为夹具");
+    println!("  --upstream-key <令牌>   录制时替换请求中的令牌");
+}
+
+fn parse_millis(name: &str, value: Option<&String>) -> Result<Duration, String> {
+    let value = value.ok_or_else(|| format!("{} 需要一个毫秒数", name))?;
+    let millis = value.parse().map_err(|_| format!("无效的毫秒数: {}", value))?;
+    Ok(Duration::from_millis(millis))
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        host: "127.0.0.1".to_string(),
+        port: 8000,
+        fixtures: None,
+        models: vec!["kimi-mock".to_string(), "kimi-mock-embedding".to_string()],
+        injection: Injection {
+            error_status: 500,
+            ..Injection::default()
+        },
+        interval: Duration::from_millis(20),
+        api_key: None,
+        record: None,
+        upstream_key: None,
+    };
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--host" => options.host = iter.next().ok_or("--host 需要一个地址参数")?.clone(),
+            "--port" => {
+                let value = iter.next().ok_or("--port 需要一个端口参数")?;
+                options.port = value.parse().map_err(|_| format!("无效的端口: {}", value))?;
+            }
+            "--fixtures" => options.fixtures = Some(PathBuf::from(iter.next().ok_or("--fixtures 需要一个目录参数")?)),
+            "--models" => {
+                let value = iter.next().ok_or("--models 需要模型列表")?;
+                options.models = value.split(',').map(str::trim).filter(|m| !m.is_empty()).map(String::from).collect();
+            }
+            "--latency" => options.injection.latency = parse_millis("--latency", iter.next())?,
+            "--jitter" => options.injection.jitter = parse_millis("--jitter", iter.next())?,
+            "--interval" => options.interval = parse_millis("--interval", iter.next())?,
+            "--error-rate" => {
+                let value = iter.next().ok_or("--error-rate 需要一个0到1之间的数")?;
+                options.injection.error_rate = value
+                    .parse()
+                    .ok()
+                    .filter(|rate: &f64| (0.0..=1.0).contains(rate))
+                    .ok_or_else(|| format!("无效的错误概率: {}", value))?;
+            }
+            "--error-status" => {
+                let value = iter.next().ok_or("--error-status 需要一个状态码")?;
+                options.injection.error_status = value
+                    .parse()
+                    .ok()
+                    .filter(|status| (400..600).contains(status))
+                    .ok_or_else(|| format!("无效的错误状态码: {}", value))?;
+            }
+            "--api-key" => options.api_key = Some(iter.next().ok_or("--api-key 需要一个令牌")?.clone()),
+            "--record" => options.record = Some(iter.next().ok_or("--record 需要服务端地址")?.clone()),
+            "--upstream-key" => options.upstream_key = Some(iter.next().ok_or("--upstream-key 需要一个令牌")?.clone()),
+            other => return Err(format!("未知参数: {}", other)),
+        }
+    }
+    if options.record.is_some() && options.fixtures.is_none() {
+        return Err("录制模式需要用 --fixtures 指定保存的目录".to_string());
+    }
+    Ok(options)
+}
+
+/// 逐个发送SSE事件，每个事件之前等待`interval`
+fn event_stream(events: Vec<Value>, interval: Duration) -> Response {
+    let chunks = stream::iter(events).then(move |event| async move {
+        tokio::time::sleep(interval).await;
+        let data = match event {
+            Value::String(data) => data,
+            other => other.to_string(),
+        };
+        Ok::<_, Infallible>(Bytes::from(format!("data: {}\n\n", data)))
+    });
+    (
+        [(header::CONTENT_TYPE, "text/event-stream"), (header::CACHE_CONTROL, "no-cache")],
+        Body::from_stream(chunks),
+    )
+        .into_response()
+}
+
+fn fixture_response(fixture: &FixtureResponse, interval: Duration) -> Response {
+    let mut response = match &fixture.events {
+        Some(events) => {
+            let interval = fixture.interval_ms.map_or(interval, Duration::from_millis);
+            event_stream(events.clone(), interval)
+        }
+        // 录制时不是JSON的响应体保存为字符串，原样返回
+        None => match &fixture.body {
+            Value::String(text) => text.clone().into_response(),
+            body => Json(body.clone()).into_response(),
+        },
+    };
+    *response.status_mut() = StatusCode::from_u16(fixture.status).unwrap_or(StatusCode::OK);
+    for (name, value) in &fixture.headers {
+        if let (Ok(name), Ok(value)) = (name.parse::<header::HeaderName>(), value.parse()) {
+            response.headers_mut().insert(name, value);
+        }
+    }
+    response
+}
+
+fn authorized(headers: &HeaderMap, api_key: &str) -> bool {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| token.trim() == api_key)
+}
+
+/// 所有请求都由这里处理
+async fn handle(State(mock): State<Arc<Mock>>, method: Method, uri: Uri, headers: HeaderMap, body: Bytes) -> Response {
+    let path = uri.path();
+    if let Some(recorder) = &mock.recorder {
+        let path_and_query = uri.path_and_query().map_or(path, |value| value.as_str());
+        let response = recorder.forward(method.clone(), path, path_and_query, &headers, body).await;
+        println!("⏺ {} {} {}", method, path, response.status().as_u16());
+        return response;
+    }
+
+    if path.starts_with("/v1/") {
+        if let Some(api_key) = &mock.api_key {
+            if !authorized(&headers, api_key) {
+                return error_response(401, "缺少或提供了错误的令牌");
+            }
+        }
+        tokio::time::sleep(mock.injection.delay(&headers)).await;
+        if let Some(status) = mock.injection.error(&headers) {
+            println!("💥 {} {} {}（注入的错误）", method, path, status);
+            return error_response(status, "模拟服务注入的错误");
+        }
+    }
+
+    let json_body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+    if let Some(fixture) = mock.fixtures.find(method.as_str(), path, &json_body) {
+        if let Some(latency) = fixture.response.latency_ms {
+            tokio::time::sleep(Duration::from_millis(latency)).await;
+        }
+        println!("📄 {} {} {}（夹具）", method, path, fixture.response.status);
+        return fixture_response(&fixture.response, mock.interval);
+    }
+
+    let response = match builtin::reply(method.as_str(), path, &json_body, &mock.models) {
+        Some(Reply::Json(body)) => Json(body).into_response(),
+        Some(Reply::Events(events)) => event_stream(events, mock.interval),
+        Some(Reply::Error(status, message)) => error_response(status, &message),
+        None => error_response(404, &format!("模拟服务中没有 {} {} 的夹具", method, path)),
+    };
+    println!("🤖 {} {} {}", method, path, response.status().as_u16());
+    response
+}
+
+async fn serve(options: Options) -> Result<(), String> {
+    let fixtures = match (&options.fixtures, &options.record) {
+        (Some(dir), None) => Fixtures::load(dir)?,
+        _ => Fixtures::default(),
+    };
+    let recorder = match (options.record, &options.fixtures) {
+        (Some(upstream), Some(dir)) => {
+            println!("⏺ 录制模式: 转发到 {}，保存到 {}", upstream, dir.display());
+            Some(Arc::new(Recorder::new(upstream, dir.clone(), options.upstream_key)?))
+        }
+        _ => None,
+    };
+    if recorder.is_none() {
+        println!("📄 已加载 {} 个夹具", fixtures.len());
+    }
+    let mock = Arc::new(Mock {
+        fixtures,
+        models: options.models,
+        injection: options.injection,
+        interval: options.interval,
+        api_key: options.api_key,
+        recorder,
+    });
+
+    let listener = tokio::net::TcpListener::bind((options.host.as_str(), options.port))
+        .await
+        .map_err(|e| format!("监听 {}:{} 失败: {}", options.host, options.port, e))?;
+    println!("🚀 OpenKimi 模拟服务已启动: http://{}:{}/v1", options.host, options.port);
+    let app = Router::new().fallback(handle).with_state(mock);
+    axum::serve(listener, app).await.map_err(|e| format!("服务异常退出: {}", e))
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        print_usage();
+        return ExitCode::SUCCESS;
+    }
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("❌ {}", err);
+            print_usage();
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            eprintln!("❌ 创建运行时失败: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    match runtime.block_on(serve(options)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("❌ {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,183 @@
+//! 录制：把请求转发给真实的服务端，同时把每次交互保存为夹具
+//!
+//! 保存的夹具以完整的请求体作为匹配条件，不含任何请求头，令牌不会写入文件；需要放宽匹配时手工删减`body`。
+//! 流式响应在转发完后保存，客户端中途断开的不保存。
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use axum::body::Body;
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use axum::response::{IntoResponse, Response};
+use bytes::Bytes;
+use futures_util::stream::{self, StreamExt};
+use serde_json::Value;
+
+use crate::fixtures::{Fixture, FixtureRequest, FixtureResponse};
+use crate::inject::error_response;
+
+/// 不转发给服务端的请求头
+const SKIPPED_REQUEST_HEADERS: &[&str] = &["host", "content-length", "connection", "accept-encoding"];
+
+/// 保存到夹具中的响应头，其余的由回放时重新生成
+const RECORDED_RESPONSE_HEADERS: &[&str] = &["retry-after", "x-request-id", "x-openkimi-stream-id"];
+
+pub struct Recorder {
+    http: reqwest::Client,
+    upstream: String,
+    dir: PathBuf,
+    /// 替换请求中的令牌，录制时客户端可以不带令牌
+    api_key: Option<String>,
+    next: AtomicU64,
+}
+
+/// SSE响应体中各个事件的`data`，能解析为JSON的保存为JSON
+fn parse_events(body: &[u8]) -> Vec<Value> {
+    let text = String::from_utf8_lossy(body);
+    let mut events = Vec::new();
+    let mut data: Vec<&str> = Vec::new();
+    for line in text.lines().chain(std::iter::once("")) {
+        if line.is_empty() {
+            if !data.is_empty() {
+                let joined = data.join("\n");
+                events.push(serde_json::from_str(&joined).unwrap_or(Value::String(joined)));
+                data.clear();
+            }
+        } else if let Some(value) = line.strip_prefix("data:") {
+            data.push(value.strip_prefix(' ').unwrap_or(value));
+        }
+    }
+    events
+}
+
+/// 路径转为文件名中的一段，如`/v1/chat/completions`转为`v1-chat-completions`
+fn slug(path: &str) -> String {
+    let slug: String = path.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '-' }).collect();
+    slug.split('-').filter(|part| !part.is_empty()).collect::<Vec<_>>().join("-")
+}
+
+impl Recorder {
+    /// `upstream`为服务端地址，不含`/v1`；编号接在目录中已有夹具之后
+    pub fn new(upstream: String, dir: PathBuf, api_key: Option<String>) -> Result<Recorder, String> {
+        fs::create_dir_all(&dir).map_err(|e| format!("创建夹具目录 {} 失败: {}", dir.display(), e))?;
+        let existing = fs::read_dir(&dir)
+            .map_err(|e| format!("读取夹具目录 {} 失败: {}", dir.display(), e))?
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+            .count();
+        let http = reqwest::Client::builder().build().map_err(|e| e.to_string())?;
+        Ok(Recorder {
+            http,
+            upstream: upstream.trim_end_matches('/').to_string(),
+            dir,
+            api_key,
+            next: AtomicU64::new(existing as u64 + 1),
+        })
+    }
+
+    fn save(&self, request: FixtureRequest, response: FixtureResponse) {
+        let number = self.next.fetch_add(1, Ordering::Relaxed);
+        let name = format!("{:04}-{}-{}.json", number, request.method.to_ascii_lowercase(), slug(&request.path));
+        let path = self.dir.join(name);
+        let fixture = Fixture { request, response };
+        let result = serde_json::to_vec_pretty(&fixture)
+            .map_err(|e| e.to_string())
+            .and_then(|content| fs::write(&path, content).map_err(|e| e.to_string()));
+        match result {
+            Ok(()) => println!("💾 已录制 {}", path.display()),
+            Err(err) => eprintln!("⚠️ 保存夹具 {} 失败: {}", path.display(), err),
+        }
+    }
+
+    /// 转发请求并录制响应；`path_and_query`用于转发，`path`写入夹具
+    pub async fn forward(
+        self: &Arc<Self>,
+        method: Method,
+        path: &str,
+        path_and_query: &str,
+        headers: &HeaderMap,
+        body: Bytes,
+    ) -> Response {
+        let mut request = self.http.request(method.clone(), format!("{}{}", self.upstream, path_and_query));
+        for (name, value) in headers {
+            if !SKIPPED_REQUEST_HEADERS.contains(&name.as_str()) && !name.as_str().starts_with("x-openkimi-mock-") {
+                request = request.header(name, value);
+            }
+        }
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        let response = match request.body(body.clone()).send().await {
+            Ok(response) => response,
+            Err(err) => return error_response(502, &format!("请求服务端失败: {}", err)),
+        };
+
+        let status = response.status();
+        let recorded_headers = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| RECORDED_RESPONSE_HEADERS.contains(&name.as_str()))
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let content_type = response.headers().get(header::CONTENT_TYPE).cloned();
+        let request_body = serde_json::from_slice::<Value>(&body).ok().filter(Value::is_object);
+        let fixture_request = FixtureRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            body: request_body,
+        };
+        let mut fixture_response = FixtureResponse {
+            status: status.as_u16(),
+            headers: recorded_headers,
+            body: Value::Null,
+            events: None,
+            latency_ms: None,
+            interval_ms: None,
+        };
+        let mut builder = Response::builder().status(StatusCode::from_u16(status.as_u16()).unwrap_or(StatusCode::OK));
+        for (name, value) in &fixture_response.headers {
+            builder = builder.header(name, value);
+        }
+        if let Some(content_type) = &content_type {
+            builder = builder.header(header::CONTENT_TYPE, content_type);
+        }
+
+        let is_stream = content_type
+            .as_ref()
+            .and_then(|value: &HeaderValue| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("text/event-stream"));
+        if is_stream {
+            // 边转发边缓存，流结束后保存
+            let captured = Arc::new(Mutex::new(Vec::new()));
+            let tap = Arc::clone(&captured);
+            let chunks = response.bytes_stream().map(move |chunk| {
+                if let Ok(bytes) = &chunk {
+                    tap.lock().unwrap_or_else(|e| e.into_inner()).extend_from_slice(bytes);
+                }
+                chunk
+            });
+            let recorder = Arc::clone(self);
+            let finish = stream::once(async move {
+                let body = std::mem::take(&mut *captured.lock().unwrap_or_else(|e| e.into_inner()));
+                fixture_response.events = Some(parse_events(&body));
+                recorder.save(fixture_request, fixture_response);
+                None
+            })
+            .filter_map(|chunk: Option<Result<Bytes, reqwest::Error>>| async move { chunk });
+            return builder
+                .body(Body::from_stream(chunks.chain(finish)))
+                .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        }
+
+        let bytes = match response.bytes().await {
+            Ok(bytes) => bytes,
+            Err(err) => return error_response(502, &format!("读取服务端响应失败: {}", err)),
+        };
+        fixture_response.body =
+            serde_json::from_slice(&bytes).unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()));
+        self.save(fixture_request, fixture_response);
+        builder.body(Body::from(bytes)).unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+    }
+}
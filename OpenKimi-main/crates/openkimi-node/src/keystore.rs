@@ -0,0 +1,54 @@
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+
+use crate::to_napi_error;
+
+/// 加密保存在用户数据目录中的API密钥等秘密
+///
+/// 主密钥由调用方保管，见`openkimi_helper::keystore`。
+#[napi]
+pub struct KeyStore {
+    inner: openkimi_helper::keystore::KeyStore,
+}
+
+#[napi]
+impl KeyStore {
+    #[napi(constructor)]
+    pub fn new(data_dir: String, master_key: Buffer) -> Result<KeyStore> {
+        let inner = openkimi_helper::keystore::KeyStore::open(data_dir.as_ref(), &master_key).map_err(to_napi_error)?;
+        Ok(KeyStore { inner })
+    }
+
+    /// 生成32字节的随机主密钥
+    #[napi]
+    pub fn generate_master_key() -> Result<Buffer> {
+        let key = openkimi_helper::keystore::generate_master_key().map_err(to_napi_error)?;
+        Ok(key.to_vec().into())
+    }
+
+    #[napi]
+    pub fn names(&self) -> Vec<String> {
+        self.inner.names()
+    }
+
+    #[napi]
+    pub fn has(&self, name: String) -> bool {
+        self.inner.contains(&name)
+    }
+
+    #[napi]
+    pub fn get(&self, name: String) -> Result<Option<String>> {
+        self.inner.get(&name).map_err(to_napi_error)
+    }
+
+    #[napi]
+    pub fn set(&mut self, name: String, secret: String) -> Result<()> {
+        self.inner.set(&name, &secret).map_err(to_napi_error)
+    }
+
+    /// 返回是否存在
+    #[napi]
+    pub fn delete(&mut self, name: String) -> Result<bool> {
+        self.inner.delete(&name).map_err(to_napi_error)
+    }
+}
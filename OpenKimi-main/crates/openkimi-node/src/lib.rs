@@ -0,0 +1,15 @@
+//! OpenKimi桌面客户端的Node原生模块
+//!
+//! Electron主进程直接调用：分词器、本地向量存储、托管策略与更新检查、加密的密钥存储。
+//! 与`openkimi-helper`辅助程序共用同一套实现，省去启动子进程和解析输出的开销。
+//! 会访问网络的更新检查和下载在libuv线程池中执行，返回Promise；其余调用都是同步的。
+
+mod keystore;
+mod tokenizer;
+mod updater;
+mod vectorstore;
+
+/// 错误统一转为带消息的JS `Error`
+pub(crate) fn to_napi_error(err: impl ToString) -> napi::Error {
+    napi::Error::from_reason(err.to_string())
+}
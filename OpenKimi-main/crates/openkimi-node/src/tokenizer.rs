@@ -0,0 +1,95 @@
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+
+use crate::to_napi_error;
+
+/// 对话格式中的一条消息
+#[napi(object)]
+pub struct TokenizerMessage {
+    pub role: String,
+    pub content: String,
+    pub name: Option<String>,
+}
+
+/// 按模型选择的分词器
+#[napi]
+pub struct Tokenizer {
+    inner: openkimi_tokenizer::Tokenizer,
+}
+
+#[napi]
+impl Tokenizer {
+    /// 按模型名选择内置词表
+    #[napi(factory)]
+    pub fn for_model(model: String) -> Tokenizer {
+        Tokenizer {
+            inner: openkimi_tokenizer::Tokenizer::for_model(&model),
+        }
+    }
+
+    /// 加载tiktoken格式的词表文件，`pattern`为预分词正则
+    #[napi(factory)]
+    pub fn from_tiktoken_file(path: String, pattern: Option<String>) -> Result<Tokenizer> {
+        let inner = openkimi_tokenizer::Tokenizer::from_tiktoken_file(path.as_ref(), pattern.as_deref())
+            .map_err(to_napi_error)?;
+        Ok(Tokenizer { inner })
+    }
+
+    #[napi(getter)]
+    pub fn name(&self) -> String {
+        self.inner.name().to_string()
+    }
+
+    /// 为false时是用近似词表估算的
+    #[napi(getter)]
+    pub fn is_exact(&self) -> bool {
+        self.inner.is_exact()
+    }
+
+    #[napi]
+    pub fn encode(&self, text: String) -> Uint32Array {
+        Uint32Array::new(self.inner.encode(&text))
+    }
+
+    #[napi]
+    pub fn decode(&self, tokens: Uint32Array) -> Result<String> {
+        self.inner.decode(&tokens).map_err(to_napi_error)
+    }
+
+    #[napi]
+    pub fn count(&self, text: String) -> u32 {
+        self.inner.count(&text) as u32
+    }
+
+    /// 按OpenAI对话格式计算消息列表的token数，包括回复开头的开销
+    #[napi]
+    pub fn count_messages(&self, messages: Vec<TokenizerMessage>) -> u32 {
+        let messages = messages
+            .iter()
+            .map(|message| (message.role.as_str(), message.content.as_str(), message.name.as_deref()));
+        self.inner.count_messages(messages) as u32
+    }
+
+    /// 保留开头截断
+    #[napi]
+    pub fn truncate(&self, text: String, max_tokens: u32) -> String {
+        self.inner.truncate(&text, max_tokens as usize)
+    }
+
+    /// 保留结尾截断
+    #[napi]
+    pub fn truncate_start(&self, text: String, max_tokens: u32) -> String {
+        self.inner.truncate_start(&text, max_tokens as usize)
+    }
+
+    #[napi]
+    pub fn chunks(&self, text: String, max_tokens: u32) -> Vec<String> {
+        self.inner.chunks(&text, max_tokens as usize)
+    }
+}
+
+/// 模型的上下文窗口，未知模型为`null`
+#[napi]
+pub fn context_window(model: String) -> Option<u32> {
+    openkimi_tokenizer::context_window(&model)
+}
@@ -0,0 +1,121 @@
+use std::path::PathBuf;
+
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi_derive::napi;
+use openkimi_helper::{payload, policy, update};
+use serde_json::Value;
+
+use crate::to_napi_error;
+
+#[napi(object)]
+pub struct ManagedPolicy {
+    pub disable_auto_update: bool,
+    pub api_endpoint: Option<String>,
+    pub max_download_kbps: Option<u32>,
+    /// 静默时段`HH:MM-HH:MM`
+    pub update_quiet_hours: Option<String>,
+}
+
+/// 企业托管策略，未配置时为默认值
+#[napi]
+pub fn managed_policy() -> ManagedPolicy {
+    let policy = policy::read();
+    ManagedPolicy {
+        disable_auto_update: policy.disable_auto_update,
+        api_endpoint: policy.api_endpoint,
+        max_download_kbps: policy.max_download_kbps,
+        update_quiet_hours: policy.update_quiet_hours.map(|quiet_hours| quiet_hours.to_string()),
+    }
+}
+
+#[napi(object)]
+pub struct DownloadProgress {
+    pub downloaded: f64,
+    pub total: f64,
+}
+
+pub struct CheckTask {
+    base_url: String,
+    platform: String,
+    current_version: String,
+}
+
+impl Task for CheckTask {
+    type Output = update::UpdateStatus;
+    type JsValue = Value;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        update::check(&self.base_url, &self.platform, &self.current_version, &policy::read()).map_err(to_napi_error)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        serde_json::to_value(output).map_err(to_napi_error)
+    }
+}
+
+/// 检查更新，托管策略禁止更新时返回`disabled: true`且不访问网络，处于静默时段时返回`deferred: true`
+#[napi]
+pub fn check_for_update(base_url: String, current_version: String, platform: Option<String>) -> AsyncTask<CheckTask> {
+    AsyncTask::new(CheckTask {
+        base_url,
+        platform: platform.unwrap_or_else(|| "windows".to_string()),
+        current_version,
+    })
+}
+
+pub struct DownloadTask {
+    base_url: String,
+    manifest: payload::PayloadManifest,
+    dir: PathBuf,
+    progress: Option<ThreadsafeFunction<DownloadProgress, ErrorStrategy::Fatal>>,
+}
+
+impl Task for DownloadTask {
+    type Output = PathBuf;
+    type JsValue = String;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        // 每变化1%通知一次，避免大量回调阻塞主进程
+        let mut last_percent = u64::MAX;
+        let progress = self.progress.as_ref();
+        update::download(&self.base_url, &self.manifest, &self.dir, &policy::read(), |downloaded, total| {
+            let Some(progress) = progress else {
+                return;
+            };
+            let percent = (downloaded * 100).checked_div(total).unwrap_or(0);
+            if percent != last_percent || downloaded == total {
+                last_percent = percent;
+                progress.call(
+                    DownloadProgress {
+                        downloaded: downloaded as f64,
+                        total: total as f64,
+                    },
+                    ThreadsafeFunctionCallMode::NonBlocking,
+                );
+            }
+        })
+        .map_err(to_napi_error)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(output.to_string_lossy().into_owned())
+    }
+}
+
+/// 下载`checkForUpdate`返回的安装包到`dir`并校验SHA-256，返回文件路径；按托管策略限速，静默时段内失败
+#[napi]
+pub fn download_update(
+    base_url: String,
+    manifest: Value,
+    dir: String,
+    on_progress: Option<ThreadsafeFunction<DownloadProgress, ErrorStrategy::Fatal>>,
+) -> Result<AsyncTask<DownloadTask>> {
+    let manifest = serde_json::from_value(manifest).map_err(|e| to_napi_error(format!("无效的发布清单: {}", e)))?;
+    Ok(AsyncTask::new(DownloadTask {
+        base_url,
+        manifest,
+        dir: PathBuf::from(dir),
+        progress: on_progress,
+    }))
+}
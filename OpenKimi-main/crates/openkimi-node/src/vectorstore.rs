@@ -0,0 +1,178 @@
+use std::path::Path;
+
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use openkimi_vectorstore::{Filter, FlatStore, HnswConfig, HnswStore, Record, SearchHit};
+use serde_json::{Map, Value};
+
+use crate::to_napi_error;
+
+/// 打开本地索引的选项
+#[napi(object)]
+pub struct VectorStoreOptions {
+    /// `hnsw`（默认）或`flat`
+    pub kind: Option<String>,
+    pub m: Option<u32>,
+    pub ef_construction: Option<u32>,
+    pub ef_search: Option<u32>,
+}
+
+#[napi(object)]
+pub struct VectorRecord {
+    pub id: String,
+    pub document: String,
+    pub text: String,
+    pub metadata: Option<Map<String, Value>>,
+    pub vector: Float32Array,
+}
+
+/// 检索结果，不带向量
+#[napi(object)]
+pub struct VectorHit {
+    pub score: f64,
+    pub id: String,
+    pub document: String,
+    pub text: String,
+    pub metadata: Map<String, Value>,
+}
+
+fn to_record(record: VectorRecord) -> Record {
+    Record {
+        id: record.id,
+        document: record.document,
+        text: record.text,
+        metadata: record.metadata.unwrap_or_default(),
+        vector: record.vector.to_vec(),
+    }
+}
+
+fn to_hits(hits: Vec<SearchHit>) -> Vec<VectorHit> {
+    hits.into_iter()
+        .map(|hit| VectorHit {
+            score: hit.score as f64,
+            id: hit.record.id,
+            document: hit.record.document,
+            text: hit.record.text,
+            metadata: hit.record.metadata,
+        })
+        .collect()
+}
+
+/// 过滤条件与服务端RAG接口的`filter`格式相同
+fn to_filter(filter: Option<Value>) -> Result<Option<Filter>> {
+    filter
+        .filter(|value| !value.is_null())
+        .map(|value| Filter::from_json(&value).map_err(to_napi_error))
+        .transpose()
+}
+
+/// 内嵌的本地向量存储，写入后调用`flush`保存到磁盘
+#[napi]
+pub struct VectorStore {
+    inner: Box<dyn openkimi_vectorstore::VectorStore>,
+}
+
+#[napi]
+impl VectorStore {
+    #[napi(factory)]
+    pub fn open(path: String, options: Option<VectorStoreOptions>) -> Result<VectorStore> {
+        let options = options.unwrap_or(VectorStoreOptions {
+            kind: None,
+            m: None,
+            ef_construction: None,
+            ef_search: None,
+        });
+        let path = Path::new(&path);
+        let inner: Box<dyn openkimi_vectorstore::VectorStore> = match options.kind.as_deref().unwrap_or("hnsw") {
+            "flat" => Box::new(FlatStore::open(path).map_err(to_napi_error)?),
+            "hnsw" => {
+                let defaults = HnswConfig::default();
+                let config = HnswConfig {
+                    m: options.m.map_or(defaults.m, |m| m as usize),
+                    ef_construction: options.ef_construction.map_or(defaults.ef_construction, |ef| ef as usize),
+                    ef_search: options.ef_search.map_or(defaults.ef_search, |ef| ef as usize),
+                    ..defaults
+                };
+                Box::new(HnswStore::open(path, config).map_err(to_napi_error)?)
+            }
+            other => return Err(to_napi_error(format!("未知的索引类型: {}", other))),
+        };
+        Ok(VectorStore { inner })
+    }
+
+    #[napi]
+    pub fn upsert(&mut self, records: Vec<VectorRecord>) -> Result<()> {
+        let records = records.into_iter().map(to_record).collect();
+        self.inner.upsert(records).map_err(to_napi_error)
+    }
+
+    #[napi]
+    pub fn delete(&mut self, ids: Vec<String>) -> Result<u32> {
+        Ok(self.inner.delete(&ids).map_err(to_napi_error)? as u32)
+    }
+
+    /// 替换文档的全部分块，返回删除的旧分块数
+    #[napi]
+    pub fn replace_document(&mut self, document: String, records: Vec<VectorRecord>) -> Result<u32> {
+        let records = records.into_iter().map(to_record).collect();
+        Ok(self.inner.replace_document(&document, records).map_err(to_napi_error)? as u32)
+    }
+
+    #[napi]
+    pub fn delete_document(&mut self, document: String) -> Result<u32> {
+        Ok(self.inner.delete_document(&document).map_err(to_napi_error)? as u32)
+    }
+
+    #[napi]
+    pub fn search(&self, vector: Float32Array, top_k: u32, filter: Option<Value>) -> Result<Vec<VectorHit>> {
+        let filter = to_filter(filter)?;
+        let hits = self.inner.search(&vector, top_k as usize, filter.as_ref()).map_err(to_napi_error)?;
+        Ok(to_hits(hits))
+    }
+
+    /// 关键词与向量的混合检索
+    #[napi]
+    pub fn search_hybrid(
+        &self,
+        text: String,
+        vector: Float32Array,
+        top_k: u32,
+        filter: Option<Value>,
+    ) -> Result<Vec<VectorHit>> {
+        let filter = to_filter(filter)?;
+        let hits = self
+            .inner
+            .search_hybrid(&text, &vector, top_k as usize, filter.as_ref())
+            .map_err(to_napi_error)?;
+        Ok(to_hits(hits))
+    }
+
+    #[napi]
+    pub fn search_keyword(&self, text: String, top_k: u32, filter: Option<Value>) -> Result<Vec<VectorHit>> {
+        let filter = to_filter(filter)?;
+        let hits = self.inner.search_keyword(&text, top_k as usize, filter.as_ref()).map_err(to_napi_error)?;
+        Ok(to_hits(hits))
+    }
+
+    #[napi(getter)]
+    pub fn length(&self) -> Result<u32> {
+        Ok(self.inner.len().map_err(to_napi_error)? as u32)
+    }
+
+    /// 向量维度，空索引为`null`
+    #[napi(getter)]
+    pub fn dimension(&self) -> Option<u32> {
+        self.inner.dimension().map(|dimension| dimension as u32)
+    }
+
+    #[napi]
+    pub fn flush(&mut self) -> Result<()> {
+        self.inner.flush().map_err(to_napi_error)
+    }
+
+    /// 已删除记录的占比超过`threshold`时整理索引，返回是否做了整理
+    #[napi]
+    pub fn compact(&mut self, threshold: f64) -> Result<bool> {
+        self.inner.compact(threshold as f32).map_err(to_napi_error)
+    }
+}
@@ -0,0 +1,27 @@
+use std::fmt;
+
+/// PostgreSQL访问错误
+#[derive(Debug)]
+pub enum PgError {
+    /// 连接或执行SQL失败
+    Postgres(postgres::Error),
+    /// 连接串不对
+    Config(String),
+}
+
+impl fmt::Display for PgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PgError::Postgres(err) => write!(f, "访问PostgreSQL失败: {}", err),
+            PgError::Config(reason) => write!(f, "PostgreSQL配置错误: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for PgError {}
+
+impl From<postgres::Error> for PgError {
+    fn from(err: postgres::Error) -> PgError {
+        PgError::Postgres(err)
+    }
+}
@@ -0,0 +1,13 @@
+//! PostgreSQL连接池
+//!
+//! 会话、用量和审计日志可以保存在同一个PostgreSQL数据库中，多个服务实例共用，不再依赖单机的SQLite文件。
+//! 各部分的表结构由`openkimi-schema`按版本迁移。
+//!
+//! 连接使用阻塞IO，在异步代码中应放到阻塞线程中调用。连接不加密，远程数据库请通过内网或SSH隧道访问。
+
+mod error;
+mod pool;
+
+pub use error::PgError;
+pub use pool::{Pool, Pooled};
+pub use postgres;
@@ -0,0 +1,376 @@
+//! 代码感知的分块策略
+
+use std::ops::Range;
+
+use serde_json::Value;
+
+use super::{Budget, Chunk, Splitter, Unit};
+use crate::extract::DocumentKind;
+
+/// 引出定义名称的关键字
+const DEFINITION_KEYWORDS: &[&str] = &[
+    "fn", "def", "class", "struct", "enum", "trait", "impl", "interface", "func", "function", "type", "mod", "module",
+    "object", "union", "record",
+];
+
+/// 后面跟括号但不是定义的关键字
+const CONTROL_KEYWORDS: &[&str] =
+    &["if", "for", "while", "switch", "match", "catch", "return", "sizeof", "new", "else", "elif", "until"];
+
+/// 缩进式语言中与上一行同级、但属于同一结构的行
+const CONTINUATION_KEYWORDS: &[&str] = &[
+    "else", "elif", "elsif", "elseif", "except", "finally", "rescue", "ensure", "when", "end", "fi", "done", "esac",
+    "then", "do",
+];
+
+/// 按函数、类等定义切分代码
+///
+/// 花括号语言按括号深度、Python等按缩进找出同一层的定义，定义前紧挨着的注释、属性和装饰器归入该定义。
+/// 放不下的定义把首行单独成块，其余部分往里一层继续切分，最后把相邻的块合并到预算以内。
+/// 每块的元数据有`language`、`symbols`（块中定义的名称）以及`start_line`、`end_line`（从1开始）。
+#[derive(Debug, Clone)]
+pub struct CodeSplitter {
+    budget: Budget,
+}
+
+/// 源代码按行的视图
+struct Source<'a> {
+    lines: Vec<&'a str>,
+    /// 每行的层级：缩进式语言是缩进宽度，花括号语言是行首的括号深度
+    levels: Vec<usize>,
+    indented: bool,
+}
+
+/// 切分出的一块连续的行，`level`是块首行所在的层级
+struct Block {
+    lines: Range<usize>,
+    level: usize,
+}
+
+impl CodeSplitter {
+    pub(crate) fn new(budget: Budget) -> CodeSplitter {
+        CodeSplitter { budget }
+    }
+
+    /// 把`range`中的行按其中最浅一层的定义切成块，放不下的块往里一层继续切分
+    fn blocks(&self, source: &Source, range: Range<usize>, blocks: &mut Vec<Block>) {
+        let Some(level) = range.clone().filter(|&i| !blank(source.lines[i])).map(|i| source.levels[i]).min() else {
+            return;
+        };
+
+        let mut starts = Vec::new();
+        let mut previous: Option<usize> = None;
+        let mut gap = false;
+        for i in range.clone() {
+            if blank(source.lines[i]) {
+                gap = true;
+                continue;
+            }
+            let starts_block = match previous {
+                None => true,
+                Some(previous) => source.levels[i] == level && source.starts_block(previous, i, gap),
+            };
+            if starts_block {
+                starts.push(i);
+            }
+            previous = Some(i);
+            gap = false;
+        }
+
+        for (index, &start) in starts.iter().enumerate() {
+            let end = starts.get(index + 1).copied().unwrap_or(range.end);
+            // 块之间的空行不计入块
+            let end = (start..end).rev().find(|&i| !blank(source.lines[i])).map_or(start + 1, |i| i + 1);
+            if end - start == 1 || self.budget.fits(&source.text(start..end)) {
+                blocks.push(Block {
+                    lines: start..end,
+                    level,
+                });
+            } else {
+                blocks.push(Block {
+                    lines: start..start + 1,
+                    level,
+                });
+                self.blocks(source, start + 1..end, blocks);
+            }
+        }
+    }
+}
+
+impl Splitter for CodeSplitter {
+    fn split(&self, text: &str, kind: DocumentKind) -> Vec<Chunk> {
+        let language = match kind {
+            DocumentKind::Code(language) => language,
+            _ => "",
+        };
+        let source = Source::new(text, language);
+        let mut blocks = Vec::new();
+        self.blocks(&source, 0..source.lines.len(), &mut blocks);
+
+        // 每个单元对应的块，超长的单行切开后各段对应同一块
+        let mut owners = Vec::new();
+        let mut units: Vec<Unit> = Vec::new();
+        for (index, block) in blocks.iter().enumerate() {
+            let separator = match index {
+                0 => "",
+                _ if block.lines.start > blocks[index - 1].lines.end => "\n\n",
+                _ => "\n",
+            };
+            let text = source.text(block.lines.clone());
+            let pieces = self.budget.units([(text.as_str(), separator)]);
+            owners.extend(std::iter::repeat_n(index, pieces.len()));
+            units.extend(pieces);
+        }
+
+        self.budget
+            .pack(&units)
+            .into_iter()
+            .map(|range| {
+                let first = &blocks[owners[range.start]];
+                let last = &blocks[owners[range.end - 1]];
+                let mut symbols: Vec<String> = Vec::new();
+                for block in &blocks[owners[range.start]..=owners[range.end - 1]] {
+                    for symbol in source.symbols(block) {
+                        if !symbols.contains(&symbol) {
+                            symbols.push(symbol);
+                        }
+                    }
+                }
+
+                let mut chunk = Chunk::new(join(&units[range]));
+                if !language.is_empty() {
+                    chunk.metadata.insert("language".to_string(), Value::from(language));
+                }
+                if !symbols.is_empty() {
+                    chunk.metadata.insert("symbols".to_string(), Value::from(symbols));
+                }
+                chunk.metadata.insert("start_line".to_string(), Value::from(first.lines.start + 1));
+                chunk.metadata.insert("end_line".to_string(), Value::from(last.lines.end));
+                chunk
+            })
+            .collect()
+    }
+}
+
+impl<'a> Source<'a> {
+    fn new(text: &'a str, language: &str) -> Source<'a> {
+        let lines: Vec<&str> = text.lines().collect();
+        let indented = matches!(language, "python" | "ruby" | "lua" | "shell");
+        let levels = if indented {
+            lines.iter().map(|line| indent(line)).collect()
+        } else {
+            depths(&lines, language)
+        };
+        Source {
+            lines,
+            levels,
+            indented,
+        }
+    }
+
+    fn text(&self, lines: Range<usize>) -> String {
+        self.lines[lines].join("\n")
+    }
+
+    /// 与上一非空行`previous`同级的第`line`行是否开始新的块，`gap`表示两行之间有空行
+    fn starts_block(&self, previous: usize, line: usize, gap: bool) -> bool {
+        let previous = self.lines[previous].trim();
+        let current = self.lines[line].trim();
+        if current.starts_with([')', ']', '}']) {
+            return false;
+        }
+        if self.indented {
+            let first = current.split(|c: char| !c.is_alphanumeric()).next().unwrap_or_default();
+            // 装饰器和注释归入后面的定义
+            !CONTINUATION_KEYWORDS.contains(&first) && !previous.starts_with(['@', '#']) && !previous.starts_with("--")
+        } else {
+            gap || previous.ends_with(['}', ';'])
+        }
+    }
+
+    /// 块中定义的名称，只看块所在层级的行
+    fn symbols(&self, block: &Block) -> Vec<String> {
+        let header = block
+            .lines
+            .clone()
+            .filter(|&i| self.levels[i] == block.level)
+            .map(|i| self.lines[i].trim())
+            .filter(|line| !line.is_empty() && !comment(line));
+        let mut symbols: Vec<String> = header.clone().filter_map(definition).collect();
+        // C、Java等没有定义关键字，取首个代码行括号前的名称
+        if symbols.is_empty() && !self.indented {
+            symbols.extend(header.take(1).filter_map(signature));
+        }
+        symbols
+    }
+}
+
+/// 拼接单元，与[`super::join`]不同的是保留首行的缩进
+fn join(units: &[Unit]) -> String {
+    let mut text = String::new();
+    for (index, unit) in units.iter().enumerate() {
+        if index > 0 {
+            text.push_str(unit.separator);
+        }
+        text.push_str(&unit.text);
+    }
+    text.trim_start_matches('\n').trim_end().to_string()
+}
+
+fn blank(line: &str) -> bool {
+    line.trim().is_empty()
+}
+
+fn comment(line: &str) -> bool {
+    line.starts_with("//")
+        || line.starts_with("/*")
+        || line.starts_with('*')
+        || line.starts_with("--")
+        || (line.starts_with('#') && !line.starts_with("#["))
+}
+
+fn indent(line: &str) -> usize {
+    line.chars()
+        .take_while(|c| c.is_whitespace())
+        .map(|c| if c == '\t' { 4 } else { 1 })
+        .sum()
+}
+
+fn identifier(text: &str) -> &str {
+    let end = text
+        .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+        .unwrap_or(text.len());
+    &text[..end]
+}
+
+/// 跳过开头成对的`<...>`或`(...)`，如泛型参数和Go方法的接收者
+fn skip_group(text: &str) -> &str {
+    let text = text.trim_start();
+    let Some(open) = text.chars().next().filter(|c| matches!(c, '<' | '(')) else {
+        return text;
+    };
+    let close = if open == '<' { '>' } else { ')' };
+    let mut depth = 0;
+    for (index, c) in text.char_indices() {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                return text[index + 1..].trim_start();
+            }
+        }
+    }
+    text
+}
+
+/// 由定义关键字引出的名称，`impl`取到`{`为止的整个类型
+fn definition(line: &str) -> Option<String> {
+    // 关键字要出现在赋值和代码体之前
+    let head = &line[..line.find(['=', '{']).unwrap_or(line.len())];
+    let mut start = None;
+    for (index, c) in head.char_indices().chain([(head.len(), ' ')]) {
+        if c.is_alphanumeric() || c == '_' {
+            start.get_or_insert(index);
+            continue;
+        }
+        let Some(begin) = start.take() else {
+            continue;
+        };
+        let (word, rest) = (&head[begin..index], &head[index..]);
+        if !DEFINITION_KEYWORDS.contains(&word) || !rest.starts_with(char::is_whitespace) && !rest.starts_with('<') {
+            continue;
+        }
+        let rest = skip_group(rest);
+        let name = if word == "impl" {
+            rest.split(" where").next().unwrap_or_default().trim()
+        } else {
+            identifier(rest)
+        };
+        if !name.is_empty() {
+            return Some(name.to_string());
+        }
+    }
+    None
+}
+
+/// 形如`int main(...)`的函数签名中的函数名
+fn signature(line: &str) -> Option<String> {
+    if line.ends_with(';') || line.starts_with(['@', '}']) {
+        return None;
+    }
+    let open = line.find('(')?;
+    if line[..open].contains('=') {
+        return None;
+    }
+    let before = line[..open].trim_end();
+    let start = before
+        .rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':' || c == '~'))
+        .map_or(0, |index| index + 1);
+    let name = &before[start..];
+    let first = identifier(line);
+    (!name.is_empty() && !CONTROL_KEYWORDS.contains(&name) && !CONTROL_KEYWORDS.contains(&first))
+        .then(|| name.to_string())
+}
+
+/// 逐行计算行首的括号深度，跳过字符串和注释
+fn depths(lines: &[&str], language: &str) -> Vec<usize> {
+    // 这些语言的单引号是字符字面量（Rust还有生命周期），其余语言是字符串
+    let char_literals = matches!(
+        language,
+        "rust" | "c" | "cpp" | "java" | "kotlin" | "csharp" | "swift" | "scala" | "go"
+    );
+    let hash_comments = language == "php";
+    let mut depth: usize = 0;
+    let mut block_comment = false;
+    // 跨行的反引号字符串
+    let mut template = false;
+    let mut levels = Vec::with_capacity(lines.len());
+    for line in lines {
+        levels.push(depth);
+        let chars: Vec<char> = line.chars().collect();
+        let mut quote = template.then_some('`');
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            let next = chars.get(i + 1).copied();
+            if block_comment {
+                if c == '*' && next == Some('/') {
+                    block_comment = false;
+                    i += 1;
+                }
+            } else if let Some(q) = quote {
+                if c == '\\' {
+                    i += 1;
+                } else if c == q {
+                    quote = None;
+                }
+            } else if c == '/' && next == Some('/') || c == '#' && hash_comments {
+                break;
+            } else if c == '/' && next == Some('*') {
+                block_comment = true;
+                i += 1;
+            } else if c == '"' || c == '`' || c == '\'' && !char_literals {
+                quote = Some(c);
+            } else if c == '\'' {
+                // 'x'或'\n'这样的字符字面量，其他情况是生命周期
+                if next == Some('\\') {
+                    if let Some(end) = chars[i + 2..].iter().position(|&c| c == '\'') {
+                        i += end + 2;
+                    }
+                } else if chars.get(i + 2) == Some(&'\'') {
+                    i += 2;
+                }
+            } else {
+                match c {
+                    '{' | '(' | '[' => depth += 1,
+                    '}' | ')' | ']' => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+            }
+            i += 1;
+        }
+        template = quote == Some('`');
+    }
+    levels
+}
@@ -0,0 +1,267 @@
+//! 文本分块
+//!
+//! 每种分块策略实现[`Splitter`]，由[`ChunkConfig`]的`strategy`选择：`tokens`固定token数的滑动窗口，
+//! `paragraph`、`sentence`按段落或句子合并，`recursive`按标题、段落、行、句子逐级细分，`code`按函数、类等定义切分代码。
+//! 分块除文本外还带有元数据（如所在的标题、代码中的符号和行号），导入时合并到分块记录的元数据中。
+
+mod code;
+mod text;
+
+use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
+
+use openkimi_tokenizer::Tokenizer;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::extract::DocumentKind;
+
+pub use code::CodeSplitter;
+pub use text::{ParagraphSplitter, RecursiveSplitter, SentenceSplitter, TokenSplitter};
+
+/// 分块策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkStrategy {
+    /// 固定token数的滑动窗口
+    Tokens,
+    /// 按段落合并，段落过长时再按token切分
+    Paragraph,
+    /// 按句子合并，适合问答类短文本
+    Sentence,
+    /// 先按Markdown标题分节，过长的节依次按段落、行、句子、token细分后再合并
+    Recursive,
+    /// 按函数、类等顶层定义切分代码，过长的定义再按内部的定义或行切分
+    Code,
+}
+
+/// 分块配置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ChunkConfig {
+    pub strategy: ChunkStrategy,
+    /// 每块最多的token数
+    pub max_tokens: usize,
+    /// 相邻两块重叠的token数
+    pub overlap: usize,
+}
+
+impl Default for ChunkConfig {
+    fn default() -> Self {
+        ChunkConfig {
+            strategy: ChunkStrategy::Paragraph,
+            max_tokens: 512,
+            overlap: 64,
+        }
+    }
+}
+
+/// 一个分块
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub text: String,
+    /// 分块策略给出的元数据，如`headings`、`symbols`、`start_line`
+    pub metadata: Map<String, Value>,
+}
+
+impl Chunk {
+    pub fn new(text: String) -> Chunk {
+        Chunk {
+            text,
+            metadata: Map::new(),
+        }
+    }
+}
+
+/// 分块策略
+pub trait Splitter: Send + Sync {
+    /// 切分规范化后的文本，`kind`是文档类型
+    fn split(&self, text: &str, kind: DocumentKind) -> Vec<Chunk>;
+}
+
+/// 每块的token预算，以及各策略共用的切分与合并方法
+#[derive(Debug, Clone)]
+pub(crate) struct Budget {
+    tokenizer: Tokenizer,
+    max_tokens: usize,
+    overlap: usize,
+}
+
+/// 合并的最小单元
+#[derive(Debug, Clone)]
+pub(crate) struct Unit {
+    text: String,
+    tokens: usize,
+    /// 与前一个单元拼接时放在中间的分隔符
+    separator: &'static str,
+}
+
+impl Budget {
+    fn new(config: &ChunkConfig, tokenizer: Tokenizer) -> Budget {
+        let max_tokens = config.max_tokens.max(16);
+        Budget {
+            tokenizer,
+            max_tokens,
+            // 重叠至少要给新内容留一半的空间
+            overlap: config.overlap.min(max_tokens / 2),
+        }
+    }
+
+    fn count(&self, text: &str) -> usize {
+        self.tokenizer.count(text)
+    }
+
+    fn fits(&self, text: &str) -> bool {
+        self.count(text) <= self.max_tokens
+    }
+
+    /// 计算单元的token数，超出预算的单元按token切开，切开的各段之间不加分隔符
+    fn units<'a>(&self, pieces: impl IntoIterator<Item = (&'a str, &'static str)>) -> Vec<Unit> {
+        let mut units = Vec::new();
+        for (piece, separator) in pieces {
+            let tokens = self.count(piece);
+            if tokens <= self.max_tokens {
+                units.push(Unit {
+                    text: piece.to_string(),
+                    tokens,
+                    separator,
+                });
+                continue;
+            }
+            for (index, part) in self.tokenizer.chunks(piece, self.max_tokens).into_iter().enumerate() {
+                units.push(Unit {
+                    tokens: self.count(&part),
+                    text: part,
+                    separator: if index == 0 { separator } else { "" },
+                });
+            }
+        }
+        units
+    }
+
+    /// 把单元贪心合并到`max_tokens`以内，返回每块包含的单元范围
+    ///
+    /// 新块以上一块末尾不超过`overlap`个token的单元开头，相邻的范围因此可能重叠。
+    fn pack(&self, units: &[Unit]) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        let mut start = 0;
+        let mut tokens = 0;
+        for (index, unit) in units.iter().enumerate() {
+            if index > start && tokens + unit.tokens > self.max_tokens {
+                ranges.push(start..index);
+                // 保留末尾不超过overlap的单元作为下一块的开头
+                let mut kept = index;
+                let mut kept_tokens = 0;
+                while kept > start {
+                    let previous = units[kept - 1].tokens;
+                    if kept_tokens + previous > self.overlap || kept_tokens + previous + unit.tokens > self.max_tokens {
+                        break;
+                    }
+                    kept_tokens += previous;
+                    kept -= 1;
+                }
+                start = kept;
+                tokens = kept_tokens;
+            }
+            tokens += unit.tokens;
+        }
+        if start < units.len() {
+            ranges.push(start..units.len());
+        }
+        ranges
+    }
+
+    /// 合并单元并按范围拼接出各块的文本
+    fn merge(&self, units: &[Unit]) -> Vec<String> {
+        self.pack(units).into_iter().map(|range| join(&units[range])).collect()
+    }
+
+    /// 固定token数的滑动窗口，每块开头重复上一块末尾`overlap`个token
+    fn sliding_window(&self, text: &str) -> Vec<String> {
+        let step = self.max_tokens - self.overlap;
+        let pieces = self.tokenizer.chunks(text, step);
+        let mut chunks = Vec::with_capacity(pieces.len());
+        for (index, piece) in pieces.iter().enumerate() {
+            if index == 0 || self.overlap == 0 {
+                chunks.push(piece.clone());
+            } else {
+                let overlap = self.tokenizer.truncate_start(&pieces[index - 1], self.overlap);
+                chunks.push(format!("{}{}", overlap, piece));
+            }
+        }
+        chunks
+    }
+}
+
+fn join(units: &[Unit]) -> String {
+    let mut text = String::new();
+    for (index, unit) in units.iter().enumerate() {
+        if index > 0 {
+            text.push_str(unit.separator);
+        }
+        text.push_str(&unit.text);
+    }
+    text.trim().to_string()
+}
+
+/// 分块器，按配置的策略切分文档
+#[derive(Clone)]
+pub struct Chunker {
+    config: ChunkConfig,
+    tokenizer: Tokenizer,
+    splitter: Arc<dyn Splitter>,
+}
+
+impl fmt::Debug for Chunker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Chunker")
+            .field("config", &self.config)
+            .field("tokenizer", &self.tokenizer)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Chunker {
+    pub fn new(config: ChunkConfig, tokenizer: Tokenizer) -> Chunker {
+        let budget = Budget::new(&config, tokenizer.clone());
+        let config = ChunkConfig {
+            max_tokens: budget.max_tokens,
+            overlap: budget.overlap,
+            ..config
+        };
+        let splitter: Arc<dyn Splitter> = match config.strategy {
+            ChunkStrategy::Tokens => Arc::new(TokenSplitter::new(budget)),
+            ChunkStrategy::Paragraph => Arc::new(ParagraphSplitter::new(budget)),
+            ChunkStrategy::Sentence => Arc::new(SentenceSplitter::new(budget)),
+            ChunkStrategy::Recursive => Arc::new(RecursiveSplitter::new(budget)),
+            ChunkStrategy::Code => Arc::new(CodeSplitter::new(budget)),
+        };
+        Chunker {
+            config,
+            tokenizer,
+            splitter,
+        }
+    }
+
+    /// 使用同一分词器、按另一份配置分块的分块器
+    pub fn with_config(&self, config: ChunkConfig) -> Chunker {
+        Chunker::new(config, self.tokenizer.clone())
+    }
+
+    pub fn config(&self) -> &ChunkConfig {
+        &self.config
+    }
+
+    pub fn chunk(&self, text: &str, kind: DocumentKind) -> Vec<Chunk> {
+        // 不预先去掉首尾空白，代码分块的行号才与原文一致
+        if text.trim().is_empty() {
+            return Vec::new();
+        }
+        self.splitter
+            .split(text, kind)
+            .into_iter()
+            .filter(|chunk| !chunk.text.trim().is_empty())
+            .collect()
+    }
+}
@@ -0,0 +1,194 @@
+//! 面向文本的分块策略
+
+use serde_json::Value;
+
+use super::{Budget, Chunk, Splitter};
+use crate::extract::DocumentKind;
+
+/// 句末标点，中英文都算
+const SENTENCE_ENDS: &[char] = &['。', '！', '？', '；', '!', '?', ';', '.', '…'];
+
+/// 固定token数的滑动窗口，不考虑文本结构
+#[derive(Debug, Clone)]
+pub struct TokenSplitter {
+    budget: Budget,
+}
+
+impl TokenSplitter {
+    pub(crate) fn new(budget: Budget) -> TokenSplitter {
+        TokenSplitter { budget }
+    }
+}
+
+impl Splitter for TokenSplitter {
+    fn split(&self, text: &str, _kind: DocumentKind) -> Vec<Chunk> {
+        self.budget.sliding_window(text).into_iter().map(Chunk::new).collect()
+    }
+}
+
+/// 按空行分段后合并，过长的段落再按token切开
+#[derive(Debug, Clone)]
+pub struct ParagraphSplitter {
+    budget: Budget,
+}
+
+impl ParagraphSplitter {
+    pub(crate) fn new(budget: Budget) -> ParagraphSplitter {
+        ParagraphSplitter { budget }
+    }
+}
+
+impl Splitter for ParagraphSplitter {
+    fn split(&self, text: &str, _kind: DocumentKind) -> Vec<Chunk> {
+        let paragraphs = text.split("\n\n").map(str::trim).filter(|p| !p.is_empty());
+        let units = self.budget.units(paragraphs.map(|paragraph| (paragraph, "\n\n")));
+        self.budget.merge(&units).into_iter().map(Chunk::new).collect()
+    }
+}
+
+/// 按句末标点切句后合并，适合问答、FAQ类短文本
+#[derive(Debug, Clone)]
+pub struct SentenceSplitter {
+    budget: Budget,
+}
+
+impl SentenceSplitter {
+    pub(crate) fn new(budget: Budget) -> SentenceSplitter {
+        SentenceSplitter { budget }
+    }
+}
+
+impl Splitter for SentenceSplitter {
+    fn split(&self, text: &str, _kind: DocumentKind) -> Vec<Chunk> {
+        let units = self.budget.units(split_sentences(text).into_iter().map(|sentence| (sentence, "")));
+        self.budget.merge(&units).into_iter().map(Chunk::new).collect()
+    }
+}
+
+/// 按结构逐级细分：先按Markdown标题分节，节内放不下时依次按段落、行、句子细分，最后合并
+///
+/// 分块不跨节，元数据`headings`是所在节的标题路径。HTML的`<h1>`到`<h6>`在提取时也转为Markdown标题。
+#[derive(Debug, Clone)]
+pub struct RecursiveSplitter {
+    budget: Budget,
+}
+
+impl RecursiveSplitter {
+    pub(crate) fn new(budget: Budget) -> RecursiveSplitter {
+        RecursiveSplitter { budget }
+    }
+
+    /// 把`text`细分为放得下的片段，每个片段带有与前一片段之间的分隔符
+    fn atoms<'a>(
+        &self,
+        text: &'a str,
+        level: usize,
+        separator: &'static str,
+        atoms: &mut Vec<(&'a str, &'static str)>,
+    ) {
+        let (parts, inner): (Vec<&str>, &'static str) = match level {
+            _ if self.budget.fits(text) => (Vec::new(), ""),
+            0 => (text.split("\n\n").map(str::trim).filter(|p| !p.is_empty()).collect(), "\n\n"),
+            1 => (text.lines().map(str::trim).filter(|line| !line.is_empty()).collect(), "\n"),
+            2 => (split_sentences(text), ""),
+            // 仍然过长的句子由`Budget::units`按token切开
+            _ => (Vec::new(), ""),
+        };
+        if parts.is_empty() {
+            atoms.push((text, separator));
+            return;
+        }
+        for (index, part) in parts.into_iter().enumerate() {
+            self.atoms(part, level + 1, if index == 0 { separator } else { inner }, atoms);
+        }
+    }
+}
+
+impl Splitter for RecursiveSplitter {
+    fn split(&self, text: &str, _kind: DocumentKind) -> Vec<Chunk> {
+        let mut chunks = Vec::new();
+        for (section, headings) in sections(text) {
+            let mut atoms = Vec::new();
+            self.atoms(section.trim(), 0, "", &mut atoms);
+            let units = self.budget.units(atoms);
+            for text in self.budget.merge(&units) {
+                let mut chunk = Chunk::new(text);
+                if !headings.is_empty() {
+                    chunk.metadata.insert("headings".to_string(), Value::from(headings.clone()));
+                }
+                chunks.push(chunk);
+            }
+        }
+        chunks
+    }
+}
+
+/// Markdown的ATX标题行，返回级别和标题文字
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|&c| c == '#').count();
+    let title = line[level..].strip_prefix([' ', '\t'])?.trim().trim_end_matches('#').trim();
+    ((1..=6).contains(&level) && !title.is_empty()).then_some((level, title))
+}
+
+/// 按标题分节，返回每节的文本（含标题行）和标题路径；代码块中以`#`开头的行不算标题
+fn sections(text: &str) -> Vec<(&str, Vec<String>)> {
+    let mut sections = Vec::new();
+    let mut path: Vec<(usize, String)> = Vec::new();
+    let mut start = 0;
+    let mut offset = 0;
+    let mut fenced = false;
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim_end();
+        if trimmed.trim_start().starts_with("```") {
+            fenced = !fenced;
+        } else if let Some((level, title)) = heading(trimmed).filter(|_| !fenced) {
+            push_section(&mut sections, &text[start..offset], &path);
+            path.retain(|(parent, _)| *parent < level);
+            path.push((level, title.to_string()));
+            start = offset;
+        }
+        offset += line.len();
+    }
+    push_section(&mut sections, &text[start..], &path);
+    sections
+}
+
+/// 只有标题行的节跳过，标题仍出现在下级各节的标题路径中
+fn push_section<'a>(sections: &mut Vec<(&'a str, Vec<String>)>, section: &'a str, path: &[(usize, String)]) {
+    let mut lines = section.lines().filter(|line| !line.trim().is_empty());
+    let Some(first) = lines.next() else {
+        return;
+    };
+    if heading(first).is_none() || lines.next().is_some() {
+        sections.push((section, path.iter().map(|(_, title)| title.clone()).collect()));
+    }
+}
+
+/// 在句末标点和换行处切分，标点和随后的空行留在句子末尾，合并时不会丢失段落分隔
+fn split_sentences(text: &str) -> Vec<&str> {
+    let mut bounds: Vec<(usize, usize)> = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        let next = chars.peek().map(|(_, next)| *next);
+        let boundary = match c {
+            '\n' => true,
+            // 英文句号后面要有空白，避免切开小数和缩写
+            '.' => next.is_none_or(char::is_whitespace),
+            c if SENTENCE_ENDS.contains(&c) => !next.is_some_and(|next| SENTENCE_ENDS.contains(&next)),
+            _ => false,
+        };
+        if boundary {
+            let end = index + c.len_utf8();
+            match bounds.last_mut() {
+                Some(last) if text[start..end].trim().is_empty() => last.1 = end,
+                _ => bounds.push((start, end)),
+            }
+            start = end;
+        }
+    }
+    if !text[start..].trim().is_empty() {
+        bounds.push((start, text.len()));
+    }
+    bounds.into_iter().map(|(start, end)| &text[start..end]).collect()
+}
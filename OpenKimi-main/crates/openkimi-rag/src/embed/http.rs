@@ -0,0 +1,120 @@
+//! OpenAI兼容的`/embeddings`接口
+
+use std::env;
+use std::time::Duration;
+
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{Embedder, HttpEmbedderConfig};
+use crate::error::RagError;
+use crate::retry::post_json;
+
+const OPENAI_API_URL: &str = "https://api.openai.com/v1";
+const OPENAI_DEFAULT_MODEL: &str = "text-embedding-3-small";
+const MOONSHOT_API_URL: &str = "https://api.moonshot.cn/v1";
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+/// 通过OpenAI兼容接口计算嵌入，遇到限流和服务端错误时退避重试
+#[derive(Debug, Clone)]
+pub struct HttpEmbedder {
+    client: reqwest::Client,
+    url: String,
+    api_key: Option<String>,
+    model: String,
+    dimensions: Option<usize>,
+    /// 是否在请求中带上`dimensions`参数，只有OpenAI支持
+    send_dimensions: bool,
+    batch_size: usize,
+    max_retries: u32,
+}
+
+impl HttpEmbedder {
+    /// OpenAI，也适用于其他兼容OpenAI接口的服务（如vLLM、Ollama）
+    pub fn openai(config: &HttpEmbedderConfig) -> Result<HttpEmbedder, RagError> {
+        HttpEmbedder::from_config(config, OPENAI_API_URL, "OPENAI_API_KEY", Some(OPENAI_DEFAULT_MODEL), true)
+    }
+
+    pub fn moonshot(config: &HttpEmbedderConfig) -> Result<HttpEmbedder, RagError> {
+        HttpEmbedder::from_config(config, MOONSHOT_API_URL, "MOONSHOT_API_KEY", None, false)
+    }
+
+    fn from_config(
+        config: &HttpEmbedderConfig,
+        default_url: &str,
+        key_env: &str,
+        default_model: Option<&str>,
+        send_dimensions: bool,
+    ) -> Result<HttpEmbedder, RagError> {
+        let model = config
+            .model
+            .clone()
+            .or_else(|| default_model.map(str::to_string))
+            .ok_or_else(|| RagError::Embedding("嵌入模型配置缺少 model".to_string()))?;
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("openkimi-rag/", env!("CARGO_PKG_VERSION")))
+            .timeout(Duration::from_secs(120))
+            .build()
+            .map_err(|e| RagError::Embedding(format!("创建HTTP客户端失败: {}", e)))?;
+        let api_url = config.api_url.as_deref().filter(|url| !url.is_empty()).unwrap_or(default_url);
+        Ok(HttpEmbedder {
+            client,
+            url: format!("{}/embeddings", api_url.trim_end_matches('/')),
+            api_key: config
+                .api_key
+                .clone()
+                .filter(|key| !key.is_empty())
+                .or_else(|| env::var(key_env).ok()),
+            model,
+            dimensions: config.dimensions,
+            send_dimensions,
+            batch_size: config.batch_size.max(1),
+            max_retries: config.max_retries,
+        })
+    }
+
+    async fn request(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, RagError> {
+        let mut body = json!({ "model": self.model, "input": texts, "encoding_format": "float" });
+        if let (true, Some(dimensions)) = (self.send_dimensions, self.dimensions) {
+            body["dimensions"] = Value::from(dimensions);
+        }
+        let response = post_json(&self.client, &self.url, self.api_key.as_deref(), &body, self.max_retries, "嵌入接口")
+            .await
+            .map_err(RagError::Embedding)?;
+        let mut response: EmbeddingResponse = response
+            .json()
+            .await
+            .map_err(|e| RagError::Embedding(format!("无法解析嵌入响应: {}", e)))?;
+        response.data.sort_by_key(|data| data.index);
+        Ok(response.data.into_iter().map(|data| data.embedding).collect())
+    }
+}
+
+impl Embedder for HttpEmbedder {
+    fn embed<'a>(&'a self, texts: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>, RagError>> {
+        Box::pin(self.request(texts))
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    fn dimensions(&self) -> Option<usize> {
+        self.dimensions
+    }
+}
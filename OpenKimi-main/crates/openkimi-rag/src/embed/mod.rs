@@ -0,0 +1,142 @@
+//! 嵌入模型
+//!
+//! [`Embedder`]的实现有OpenAI兼容接口（OpenAI、Moonshot）和本地ONNX模型（需启用`onnx`特性），
+//! 由[`EmbedderConfig`]的`provider`字段选择，每个索引可以使用不同的嵌入模型。
+
+mod http;
+#[cfg(feature = "onnx")]
+mod onnx;
+
+use std::path::PathBuf;
+
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+
+use crate::error::RagError;
+
+pub use http::HttpEmbedder;
+#[cfg(feature = "onnx")]
+pub use onnx::OnnxEmbedder;
+
+/// 计算文本嵌入
+///
+/// 返回的向量与`texts`一一对应。调用方负责分批，单次传入的文本数不超过[`Embedder::batch_size`]。
+pub trait Embedder: Send + Sync {
+    fn embed<'a>(&'a self, texts: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>, RagError>>;
+
+    /// 模型名，分块时据此选择分词器
+    fn model(&self) -> &str;
+
+    /// 单次请求最多提交的文本数
+    fn batch_size(&self) -> usize {
+        64
+    }
+
+    /// 配置中声明的向量维度，未声明时只检查同一批向量的维度一致
+    fn dimensions(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// 检查一批向量的数量和维度
+pub fn validate_vectors(embedder: &dyn Embedder, texts: usize, vectors: &[Vec<f32>]) -> Result<(), RagError> {
+    if vectors.len() != texts {
+        return Err(RagError::Embedding(format!(
+            "提交了 {} 段文本，却返回了 {} 个向量",
+            texts,
+            vectors.len()
+        )));
+    }
+    let expected = embedder.dimensions().or_else(|| vectors.first().map(Vec::len));
+    if let Some(expected) = expected {
+        if let Some(vector) = vectors.iter().find(|vector| vector.len() != expected) {
+            return Err(RagError::Embedding(format!(
+                "模型 {} 返回了 {} 维的向量，应为 {} 维",
+                embedder.model(),
+                vector.len(),
+                expected
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn default_batch_size() -> usize {
+    64
+}
+
+fn default_max_retries() -> u32 {
+    5
+}
+
+/// OpenAI兼容嵌入接口的配置
+#[derive(Debug, Clone, Deserialize)]
+pub struct HttpEmbedderConfig {
+    /// 缺省时OpenAI使用`text-embedding-3-small`，Moonshot必须指定
+    #[serde(default)]
+    pub model: Option<String>,
+    /// 缺省时读取`OPENAI_API_KEY`或`MOONSHOT_API_KEY`
+    #[serde(default)]
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub api_url: Option<String>,
+    /// 向量维度；OpenAI的`text-embedding-3`系列会按它缩短向量
+    #[serde(default)]
+    pub dimensions: Option<usize>,
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// 遇到429和5xx时的最大重试次数
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+}
+
+fn default_max_length() -> usize {
+    512
+}
+
+fn default_normalize() -> bool {
+    true
+}
+
+/// 本地ONNX句向量模型的配置，如导出为ONNX的bge、e5、MiniLM
+#[derive(Debug, Clone, Deserialize)]
+pub struct OnnxEmbedderConfig {
+    /// `model.onnx`的路径
+    pub model_path: PathBuf,
+    /// Hugging Face格式的`tokenizer.json`
+    pub tokenizer_path: PathBuf,
+    #[serde(default)]
+    pub dimensions: Option<usize>,
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// 超出的部分截断
+    #[serde(default = "default_max_length")]
+    pub max_length: usize,
+    /// 是否把向量归一化为单位长度，使用余弦相似度检索时应开启
+    #[serde(default = "default_normalize")]
+    pub normalize: bool,
+}
+
+/// 嵌入模型配置，按`provider`区分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "provider", rename_all = "lowercase")]
+pub enum EmbedderConfig {
+    #[serde(rename = "openai")]
+    OpenAi(HttpEmbedderConfig),
+    Moonshot(HttpEmbedderConfig),
+    Onnx(OnnxEmbedderConfig),
+}
+
+/// 按配置创建嵌入模型
+pub fn build_embedder(config: &EmbedderConfig) -> Result<Box<dyn Embedder>, RagError> {
+    match config {
+        EmbedderConfig::OpenAi(config) => Ok(Box::new(HttpEmbedder::openai(config)?)),
+        EmbedderConfig::Moonshot(config) => Ok(Box::new(HttpEmbedder::moonshot(config)?)),
+        #[cfg(feature = "onnx")]
+        EmbedderConfig::Onnx(config) => Ok(Box::new(OnnxEmbedder::load(config)?)),
+        #[cfg(not(feature = "onnx"))]
+        EmbedderConfig::Onnx(_) => Err(RagError::Embedding(
+            "本地ONNX嵌入需要启用 onnx 特性重新编译（cargo build --features openkimi-rag/onnx）".to_string(),
+        )),
+    }
+}
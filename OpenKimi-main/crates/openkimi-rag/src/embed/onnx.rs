@@ -0,0 +1,166 @@
+//! 本地ONNX句向量模型
+
+use std::sync::{Arc, Mutex};
+
+use futures_util::future::BoxFuture;
+use ort::session::Session;
+use ort::value::Tensor;
+use tokenizers::{PaddingParams, PaddingStrategy, Tokenizer, TruncationParams};
+
+use super::{Embedder, OnnxEmbedderConfig};
+use crate::error::RagError;
+
+fn onnx_error(err: impl std::fmt::Display) -> RagError {
+    RagError::Embedding(format!("ONNX推理失败: {}", err))
+}
+
+#[derive(Debug)]
+struct Model {
+    session: Mutex<Session>,
+    tokenizer: Tokenizer,
+    /// 模型是否有`token_type_ids`输入，BERT类模型有，部分导出的模型没有
+    type_ids: bool,
+    normalize: bool,
+}
+
+/// 用ONNX Runtime在本机计算嵌入
+///
+/// 输出为`[批, 序列, 维度]`时按注意力掩码做平均池化，已是`[批, 维度]`时直接使用。
+/// 推理在阻塞线程池中执行，不占用异步运行时。
+#[derive(Debug, Clone)]
+pub struct OnnxEmbedder {
+    model: Arc<Model>,
+    name: String,
+    dimensions: Option<usize>,
+    batch_size: usize,
+}
+
+impl OnnxEmbedder {
+    pub fn load(config: &OnnxEmbedderConfig) -> Result<OnnxEmbedder, RagError> {
+        let session = Session::builder()
+            .and_then(|builder| builder.commit_from_file(&config.model_path))
+            .map_err(|e| RagError::Embedding(format!("加载ONNX模型 {} 失败: {}", config.model_path.display(), e)))?;
+        let type_ids = session.inputs.iter().any(|input| input.name == "token_type_ids");
+
+        let mut tokenizer = Tokenizer::from_file(&config.tokenizer_path).map_err(|e| {
+            RagError::Embedding(format!("加载分词器 {} 失败: {}", config.tokenizer_path.display(), e))
+        })?;
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: config.max_length.max(1),
+                ..TruncationParams::default()
+            }))
+            .map_err(|e| RagError::Embedding(format!("分词器配置无效: {}", e)))?;
+        tokenizer.with_padding(Some(PaddingParams {
+            strategy: PaddingStrategy::BatchLongest,
+            ..PaddingParams::default()
+        }));
+
+        Ok(OnnxEmbedder {
+            model: Arc::new(Model {
+                session: Mutex::new(session),
+                tokenizer,
+                type_ids,
+                normalize: config.normalize,
+            }),
+            name: config.model_path.to_string_lossy().into_owned(),
+            dimensions: config.dimensions,
+            batch_size: config.batch_size.max(1),
+        })
+    }
+}
+
+impl Model {
+    fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, RagError> {
+        let encodings = self
+            .tokenizer
+            .encode_batch(texts, true)
+            .map_err(|e| RagError::Embedding(format!("分词失败: {}", e)))?;
+        let batch = encodings.len();
+        let length = encodings.first().map_or(0, |encoding| encoding.len());
+        if batch == 0 || length == 0 {
+            return Ok(vec![Vec::new(); batch]);
+        }
+
+        let flatten = |field: fn(&tokenizers::Encoding) -> &[u32]| -> Vec<i64> {
+            encodings
+                .iter()
+                .flat_map(|encoding| field(encoding).iter().map(|&v| v as i64))
+                .collect()
+        };
+        let mask = flatten(tokenizers::Encoding::get_attention_mask);
+        let shape = [batch, length];
+        let mut inputs = ort::inputs![
+            "input_ids" => Tensor::from_array((shape, flatten(tokenizers::Encoding::get_ids))).map_err(onnx_error)?,
+            "attention_mask" => Tensor::from_array((shape, mask.clone())).map_err(onnx_error)?,
+        ];
+        if self.type_ids {
+            let type_ids = Tensor::from_array((shape, flatten(tokenizers::Encoding::get_type_ids))).map_err(onnx_error)?;
+            inputs.push(("token_type_ids".into(), type_ids.into()));
+        }
+
+        let mut session = self.session.lock().unwrap();
+        let outputs = session.run(inputs).map_err(onnx_error)?;
+        let (output_shape, values) = outputs[0].try_extract_tensor::<f32>().map_err(onnx_error)?;
+
+        let mut vectors = match **output_shape {
+            [_, dimension] => values.chunks(dimension as usize).map(<[f32]>::to_vec).collect::<Vec<_>>(),
+            [_, _, dimension] => {
+                let dimension = dimension as usize;
+                (0..batch)
+                    .map(|row| {
+                        let mut sum = vec![0.0f32; dimension];
+                        let mut count = 0.0f32;
+                        for position in 0..length {
+                            if mask[row * length + position] == 0 {
+                                continue;
+                            }
+                            let offset = (row * length + position) * dimension;
+                            for (total, value) in sum.iter_mut().zip(&values[offset..offset + dimension]) {
+                                *total += value;
+                            }
+                            count += 1.0;
+                        }
+                        sum.iter_mut().for_each(|value| *value /= count.max(1.0));
+                        sum
+                    })
+                    .collect()
+            }
+            ref other => return Err(RagError::Embedding(format!("无法识别的模型输出形状 {:?}", other))),
+        };
+
+        if self.normalize {
+            for vector in &mut vectors {
+                let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+                if norm > 0.0 {
+                    vector.iter_mut().for_each(|v| *v /= norm);
+                }
+            }
+        }
+        Ok(vectors)
+    }
+}
+
+impl Embedder for OnnxEmbedder {
+    fn embed<'a>(&'a self, texts: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>, RagError>> {
+        let model = Arc::clone(&self.model);
+        let texts = texts.to_vec();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || model.embed(texts))
+                .await
+                .map_err(onnx_error)?
+        })
+    }
+
+    fn model(&self) -> &str {
+        &self.name
+    }
+
+    fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    fn dimensions(&self) -> Option<usize> {
+        self.dimensions
+    }
+}
@@ -0,0 +1,50 @@
+use std::fmt;
+use std::io;
+
+use openkimi_vectorstore::StoreError;
+
+/// 导入和检索错误
+#[derive(Debug)]
+pub enum RagError {
+    Io(io::Error),
+    /// 不支持的文件类型
+    Unsupported(String),
+    /// 文件损坏或无法提取文本
+    Extract(String),
+    /// 计算嵌入失败
+    Embedding(String),
+    /// 重排失败
+    Rerank(String),
+    /// 向量存储读写失败或数据不一致
+    Store(String),
+}
+
+impl fmt::Display for RagError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RagError::Io(err) => write!(f, "读写文件失败: {}", err),
+            RagError::Unsupported(name) => write!(f, "不支持的文件类型: {}", name),
+            RagError::Extract(reason) => write!(f, "提取文本失败: {}", reason),
+            RagError::Embedding(reason) => write!(f, "计算嵌入失败: {}", reason),
+            RagError::Rerank(reason) => write!(f, "重排失败: {}", reason),
+            RagError::Store(reason) => write!(f, "向量存储错误: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for RagError {}
+
+impl From<io::Error> for RagError {
+    fn from(err: io::Error) -> RagError {
+        RagError::Io(err)
+    }
+}
+
+impl From<StoreError> for RagError {
+    fn from(err: StoreError) -> RagError {
+        match err {
+            StoreError::Io(err) => RagError::Io(err),
+            err => RagError::Store(err.to_string()),
+        }
+    }
+}
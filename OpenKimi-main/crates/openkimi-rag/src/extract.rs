@@ -0,0 +1,264 @@
+//! 按文件类型提取纯文本
+
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use pulldown_cmark::{Event, Parser, Tag, TagEnd};
+use quick_xml::events::Event as XmlEvent;
+use quick_xml::Reader;
+
+use crate::error::RagError;
+
+/// 支持导入的文件类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentKind {
+    Pdf,
+    Docx,
+    Html,
+    Markdown,
+    Text,
+    /// 源代码，带有语言名
+    Code(&'static str),
+}
+
+impl DocumentKind {
+    /// 按扩展名判断文件类型，不支持的类型返回`None`
+    pub fn from_name(name: &str) -> Option<DocumentKind> {
+        let extension = Path::new(name).extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "pdf" => Some(DocumentKind::Pdf),
+            "docx" => Some(DocumentKind::Docx),
+            "html" | "htm" | "xhtml" => Some(DocumentKind::Html),
+            "md" | "markdown" | "mdx" => Some(DocumentKind::Markdown),
+            "txt" | "text" | "log" | "csv" | "rst" => Some(DocumentKind::Text),
+            extension => code_language(extension).map(DocumentKind::Code),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DocumentKind::Pdf => "pdf",
+            DocumentKind::Docx => "docx",
+            DocumentKind::Html => "html",
+            DocumentKind::Markdown => "markdown",
+            DocumentKind::Text => "text",
+            DocumentKind::Code(_) => "code",
+        }
+    }
+}
+
+/// 代码文件扩展名对应的语言
+fn code_language(extension: &str) -> Option<&'static str> {
+    Some(match extension {
+        "rs" => "rust",
+        "py" | "pyi" => "python",
+        "js" | "mjs" | "cjs" | "jsx" => "javascript",
+        "ts" | "tsx" => "typescript",
+        "go" => "go",
+        "java" => "java",
+        "kt" | "kts" => "kotlin",
+        "c" | "h" => "c",
+        "cpp" | "cc" | "cxx" | "hpp" | "hh" => "cpp",
+        "cs" => "csharp",
+        "swift" => "swift",
+        "scala" => "scala",
+        "rb" => "ruby",
+        "php" => "php",
+        "sh" | "bash" | "zsh" => "shell",
+        "lua" => "lua",
+        _ => return None,
+    })
+}
+
+/// 提取文本，结果尚未规范化
+pub fn extract(kind: DocumentKind, data: &[u8]) -> Result<String, RagError> {
+    match kind {
+        DocumentKind::Pdf => pdf_extract::extract_text_from_mem(data).map_err(|e| RagError::Extract(format!("PDF: {}", e))),
+        DocumentKind::Docx => docx_text(data),
+        DocumentKind::Html => Ok(html_text(&decode_text(data))),
+        DocumentKind::Markdown => Ok(markdown_text(&decode_text(data))),
+        DocumentKind::Text | DocumentKind::Code(_) => Ok(decode_text(data)),
+    }
+}
+
+/// 按UTF-8解码，去掉BOM；非法字节替换为`�`而不是整份拒绝
+fn decode_text(data: &[u8]) -> String {
+    let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+    String::from_utf8_lossy(data).into_owned()
+}
+
+/// 读取`word/document.xml`，段落之间换行，`<w:tab/>`、`<w:br/>`分别转为制表符和换行
+fn docx_text(data: &[u8]) -> Result<String, RagError> {
+    let invalid = |e: &dyn std::fmt::Display| RagError::Extract(format!("DOCX: {}", e));
+    let mut archive = zip::ZipArchive::new(Cursor::new(data)).map_err(|e| invalid(&e))?;
+    let mut xml = String::new();
+    archive
+        .by_name("word/document.xml")
+        .map_err(|e| invalid(&e))?
+        .read_to_string(&mut xml)
+        .map_err(|e| invalid(&e))?;
+
+    let mut reader = Reader::from_str(&xml);
+    let mut text = String::new();
+    let mut in_text = false;
+    loop {
+        match reader.read_event().map_err(|e| invalid(&e))? {
+            XmlEvent::Start(tag) if tag.name().as_ref() == b"w:t" => in_text = true,
+            XmlEvent::End(tag) => match tag.name().as_ref() {
+                b"w:t" => in_text = false,
+                b"w:p" => text.push('\n'),
+                _ => {}
+            },
+            XmlEvent::Empty(tag) => match tag.name().as_ref() {
+                b"w:tab" => text.push('\t'),
+                b"w:br" | b"w:cr" => text.push('\n'),
+                _ => {}
+            },
+            XmlEvent::Text(content) if in_text => {
+                text.push_str(&content.unescape().map_err(|e| invalid(&e))?);
+            }
+            XmlEvent::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(text)
+}
+
+/// 换行的块级标签
+const HTML_BLOCK_TAGS: &[&str] = &[
+    "p", "div", "br", "li", "ul", "ol", "tr", "table", "h1", "h2", "h3", "h4", "h5", "h6", "section", "article",
+    "header", "footer", "blockquote", "pre", "hr", "dd", "dt",
+];
+
+/// 内容不是正文的标签，整个跳过
+const HTML_SKIP_TAGS: &[&str] = &["script", "style", "noscript", "template", "svg", "head"];
+
+/// 去掉HTML标签，块级标签处换行，解码常见实体
+///
+/// 只求提取正文，不做完整的HTML解析；标签不闭合等不规范写法也能得到可用的文本。
+fn html_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len() / 2);
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        text.push_str(&decode_entities(&rest[..start]));
+        rest = &rest[start..];
+
+        if let Some(after) = rest.strip_prefix("<!--") {
+            rest = after.find("-->").map_or("", |end| &after[end + 3..]);
+            continue;
+        }
+        let Some(end) = rest.find('>') else {
+            rest = "";
+            break;
+        };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        let closing = tag.starts_with('/');
+        let name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        if !closing && HTML_SKIP_TAGS.contains(&name.as_str()) && !tag.ends_with('/') {
+            // 跳到对应的结束标签
+            let lower = rest.to_ascii_lowercase();
+            rest = match lower.find(&format!("</{}", name)) {
+                Some(position) => {
+                    let after = &rest[position..];
+                    after.find('>').map_or("", |end| &after[end + 1..])
+                }
+                None => "",
+            };
+            continue;
+        }
+        if HTML_BLOCK_TAGS.contains(&name.as_str()) {
+            text.push('\n');
+            // 标题转为Markdown形式，按结构分块时据此分节
+            if let Some(level) = name.strip_prefix('h').and_then(|level| level.parse().ok()).filter(|_| !closing) {
+                text.push_str(&"#".repeat(level));
+                text.push(' ');
+            }
+        } else if name == "td" || name == "th" {
+            text.push('\t');
+        }
+    }
+    text.push_str(&decode_entities(rest));
+    text
+}
+
+fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest.find(';').filter(|end| *end <= 10).and_then(|end| {
+            let entity = &rest[1..end];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ => {
+                    let code = if let Some(hex) = entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                        u32::from_str_radix(hex, 16).ok()
+                    } else {
+                        entity.strip_prefix('#').and_then(|digits| digits.parse().ok())
+                    };
+                    code.and_then(char::from_u32)
+                }
+            };
+            c.map(|c| (c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// 去掉Markdown语法，保留标题、正文、列表和代码块的文字
+///
+/// 标题保留`#`前缀、代码块保留围栏，按结构分块时用来分节和识别代码。
+fn markdown_text(markdown: &str) -> String {
+    let mut text = String::with_capacity(markdown.len());
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                text.push_str(&"#".repeat(level as usize));
+                text.push(' ');
+            }
+            Event::Start(Tag::CodeBlock(_)) => text.push_str("```\n"),
+            Event::End(TagEnd::CodeBlock) => {
+                if !text.ends_with('\n') {
+                    text.push('\n');
+                }
+                text.push_str("```\n\n");
+            }
+            Event::Text(content) | Event::Code(content) => text.push_str(&content),
+            Event::SoftBreak => text.push(' '),
+            Event::HardBreak | Event::Rule => text.push('\n'),
+            Event::End(TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::Item | TagEnd::TableRow) => {
+                text.push_str("\n\n");
+            }
+            Event::End(TagEnd::TableCell) => text.push('\t'),
+            _ => {}
+        }
+    }
+    text
+}
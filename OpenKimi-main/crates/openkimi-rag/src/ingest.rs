@@ -0,0 +1,162 @@
+//! 导入流程
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use openkimi_vectorstore::Record;
+use serde_json::{Map, Value};
+
+use crate::chunk::{Chunk, ChunkConfig, ChunkStrategy, Chunker};
+use crate::embed::{validate_vectors, Embedder};
+use crate::error::RagError;
+use crate::extract::{extract, DocumentKind};
+use crate::normalize::{normalize, normalize_code};
+
+/// 待导入的文档
+#[derive(Debug, Clone)]
+pub struct Document {
+    /// 文档id，同一id重新导入时替换旧内容
+    pub id: String,
+    /// 文件名，用于判断类型并写入元数据
+    pub name: String,
+    pub kind: DocumentKind,
+    pub data: Vec<u8>,
+    /// 附加到每个分块上的元数据
+    pub metadata: Map<String, Value>,
+    /// 这个文档使用的分块配置，不设置时按文件类型或默认配置分块
+    pub chunking: Option<ChunkConfig>,
+}
+
+impl Document {
+    /// 读取本地文件，文档id为文件路径
+    pub fn from_path(path: &Path) -> Result<Document, RagError> {
+        let name = path.to_string_lossy().into_owned();
+        let kind = DocumentKind::from_name(&name).ok_or_else(|| RagError::Unsupported(name.clone()))?;
+        Ok(Document {
+            id: name.clone(),
+            name,
+            kind,
+            data: fs::read(path)?,
+            metadata: Map::new(),
+            chunking: None,
+        })
+    }
+}
+
+/// 分块、计算嵌入并生成待写入的记录
+pub struct Ingestor<'a> {
+    chunker: &'a Chunker,
+    embedder: &'a dyn Embedder,
+    /// 按文件类型名（[`DocumentKind::name`]）覆盖的分块配置
+    by_kind: Option<&'a HashMap<String, ChunkConfig>>,
+}
+
+impl<'a> Ingestor<'a> {
+    pub fn new(chunker: &'a Chunker, embedder: &'a dyn Embedder) -> Ingestor<'a> {
+        Ingestor {
+            chunker,
+            embedder,
+            by_kind: None,
+        }
+    }
+
+    /// 按文件类型选择分块配置，没有配置的类型使用默认分块器
+    pub fn with_kinds(mut self, by_kind: &'a HashMap<String, ChunkConfig>) -> Ingestor<'a> {
+        self.by_kind = Some(by_kind);
+        self
+    }
+
+    /// 文档使用的分块器：依次取文档自身的配置、文件类型的配置；代码文件没有配置时按代码分块，其余用默认分块器
+    fn chunker(&self, document: &Document) -> Option<Chunker> {
+        let config = document
+            .chunking
+            .clone()
+            .or_else(|| self.by_kind.and_then(|by_kind| by_kind.get(document.kind.name())).cloned());
+        match (config, document.kind) {
+            (Some(config), _) => Some(self.chunker.with_config(config)),
+            (None, DocumentKind::Code(_)) if self.chunker.config().strategy != ChunkStrategy::Code => {
+                Some(self.chunker.with_config(ChunkConfig {
+                    strategy: ChunkStrategy::Code,
+                    ..self.chunker.config().clone()
+                }))
+            }
+            (None, _) => None,
+        }
+    }
+
+    /// 提取、规范化并分块，不计算嵌入
+    pub fn chunk(&self, document: &Document) -> Result<Vec<Chunk>, RagError> {
+        let text = extract(document.kind, &document.data)?;
+        let text = match document.kind {
+            DocumentKind::Code(_) => normalize_code(&text),
+            _ => normalize(&text),
+        };
+        let chunker = self.chunker(document);
+        Ok(chunker.as_ref().unwrap_or(self.chunker).chunk(&text, document.kind))
+    }
+
+    /// 处理一个文档，返回的记录交给[`VectorStore::replace_document`](crate::VectorStore::replace_document)写入
+    pub async fn ingest(&self, document: &Document) -> Result<Vec<Record>, RagError> {
+        let chunks = self.chunk(document)?;
+        let texts: Vec<String> = chunks.iter().map(|chunk| chunk.text.clone()).collect();
+        let mut vectors = Vec::with_capacity(chunks.len());
+        for batch in texts.chunks(self.embedder.batch_size().max(1)) {
+            let embedded = self.embedder.embed(batch).await?;
+            validate_vectors(self.embedder, batch.len(), &embedded)?;
+            vectors.extend(embedded);
+        }
+
+        let total = chunks.len();
+        Ok(chunks
+            .into_iter()
+            .zip(vectors)
+            .enumerate()
+            .map(|(index, (chunk, vector))| {
+                let mut metadata = document.metadata.clone();
+                metadata.extend(chunk.metadata);
+                metadata.insert("source".to_string(), Value::from(document.name.clone()));
+                metadata.insert("kind".to_string(), Value::from(document.kind.name()));
+                metadata.insert("chunk".to_string(), Value::from(index));
+                metadata.insert("chunks".to_string(), Value::from(total));
+                Record {
+                    id: format!("{}#{}", document.id, index),
+                    document: document.id.clone(),
+                    text: chunk.text,
+                    metadata,
+                    vector,
+                }
+            })
+            .collect())
+    }
+}
+
+/// 展开命令行给出的路径：目录递归查找支持的文件（跳过隐藏文件），文件原样保留
+pub fn collect_files(paths: &[PathBuf]) -> Result<Vec<PathBuf>, RagError> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            walk(path, &mut files)?;
+        } else {
+            files.push(path.clone());
+        }
+    }
+    Ok(files)
+}
+
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), RagError> {
+    let mut entries: Vec<_> = fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            walk(&path, files)?;
+        } else if DocumentKind::from_name(&path.to_string_lossy()).is_some() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
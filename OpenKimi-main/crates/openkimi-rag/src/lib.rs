@@ -0,0 +1,32 @@
+//! 检索增强（RAG）的文档导入与重排
+//!
+//! 导入流程：按文件类型提取文本（PDF、DOCX、HTML、Markdown、纯文本、代码）→ 规范化 →
+//! 按配置的策略分块 → 计算嵌入 → 写入向量存储。服务端的`/v1/rag/ingest`接口和
+//! `openkimi-server index`命令都通过[`Ingestor`]完成导入。检索时可以用[`Reranker`]对向量检索的候选重新排序。
+
+mod chunk;
+mod embed;
+mod error;
+mod extract;
+mod ingest;
+mod normalize;
+mod rerank;
+mod retry;
+
+pub use chunk::{
+    Chunk, ChunkConfig, ChunkStrategy, Chunker, CodeSplitter, ParagraphSplitter, RecursiveSplitter, SentenceSplitter,
+    Splitter, TokenSplitter,
+};
+#[cfg(feature = "onnx")]
+pub use embed::OnnxEmbedder;
+pub use embed::{
+    build_embedder, validate_vectors, Embedder, EmbedderConfig, HttpEmbedder, HttpEmbedderConfig, OnnxEmbedderConfig,
+};
+pub use error::RagError;
+pub use extract::{extract, DocumentKind};
+pub use ingest::{collect_files, Document, Ingestor};
+pub use normalize::normalize;
+#[cfg(feature = "onnx")]
+pub use rerank::OnnxReranker;
+pub use rerank::{build_reranker, HttpReranker, HttpRerankerConfig, OnnxRerankerConfig, Reranker, RerankerConfig};
+pub use openkimi_vectorstore::{Filter, FlatStore, HnswConfig, HnswStore, Record, SearchHit, StoreError, VectorStore};
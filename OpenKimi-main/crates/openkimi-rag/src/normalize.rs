@@ -0,0 +1,72 @@
+//! 文本规范化
+
+use unicode_normalization::UnicodeNormalization;
+
+/// 统一换行和空白，去掉控制字符，合并多余的空行
+///
+/// 只做NFC而不是NFKC，避免把中文全角标点转成半角。
+pub fn normalize(text: &str) -> String {
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+    let mut lines = Vec::new();
+    let mut blank = 0;
+    for line in text.nfc().collect::<String>().lines() {
+        let line: String = line
+            .chars()
+            .map(|c| match c {
+                '\t' | '\u{a0}' | '\u{3000}' => ' ',
+                c => c,
+            })
+            .filter(|c| !c.is_control() && *c != '\u{feff}' && *c != '\u{200b}')
+            .collect();
+        let line = collapse_spaces(line.trim());
+        if line.is_empty() {
+            blank += 1;
+            // 最多保留一个空行，用来分隔段落
+            if blank == 1 && !lines.is_empty() {
+                lines.push(String::new());
+            }
+            continue;
+        }
+        blank = 0;
+        lines.push(line);
+    }
+    while lines.last().is_some_and(String::is_empty) {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+fn collapse_spaces(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut previous_space = false;
+    for c in line.chars() {
+        if c == ' ' {
+            if !previous_space {
+                out.push(c);
+            }
+            previous_space = true;
+        } else {
+            out.push(c);
+            previous_space = false;
+        }
+    }
+    out
+}
+
+/// 代码的规范化：统一换行，去掉控制字符和行尾空白，保留缩进和空行，分块的行号与原文件一致
+pub fn normalize_code(text: &str) -> String {
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+    let lines: Vec<String> = text
+        .nfc()
+        .collect::<String>()
+        .lines()
+        .map(|line| {
+            line.chars()
+                .filter(|c| (*c == '\t' || !c.is_control()) && *c != '\u{feff}' && *c != '\u{200b}')
+                .collect::<String>()
+                .trim_end()
+                .to_string()
+        })
+        .collect();
+    lines.join("\n")
+}
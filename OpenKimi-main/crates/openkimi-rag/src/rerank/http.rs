@@ -0,0 +1,129 @@
+//! Cohere、Jina格式的`/rerank`接口
+
+use std::env;
+use std::time::Duration;
+
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{HttpRerankerConfig, Reranker};
+use crate::error::RagError;
+use crate::retry::post_json;
+
+const COHERE_API_URL: &str = "https://api.cohere.com/v2";
+const COHERE_DEFAULT_MODEL: &str = "rerank-multilingual-v3.0";
+const JINA_API_URL: &str = "https://api.jina.ai/v1";
+const JINA_DEFAULT_MODEL: &str = "jina-reranker-v2-base-multilingual";
+
+#[derive(Deserialize)]
+struct RerankResult {
+    index: usize,
+    relevance_score: f32,
+}
+
+#[derive(Deserialize)]
+struct RerankResponse {
+    results: Vec<RerankResult>,
+}
+
+/// 通过`/rerank`接口重排，遇到限流和服务端错误时退避重试
+#[derive(Debug, Clone)]
+pub struct HttpReranker {
+    client: reqwest::Client,
+    url: String,
+    api_key: Option<String>,
+    model: String,
+    max_retries: u32,
+}
+
+impl HttpReranker {
+    pub fn cohere(config: &HttpRerankerConfig) -> Result<HttpReranker, RagError> {
+        HttpReranker::from_config(config, Some(COHERE_API_URL), Some("COHERE_API_KEY"), Some(COHERE_DEFAULT_MODEL))
+    }
+
+    pub fn jina(config: &HttpRerankerConfig) -> Result<HttpReranker, RagError> {
+        HttpReranker::from_config(config, Some(JINA_API_URL), Some("JINA_API_KEY"), Some(JINA_DEFAULT_MODEL))
+    }
+
+    /// 其他兼容的接口，必须指定地址和模型
+    pub fn compatible(config: &HttpRerankerConfig) -> Result<HttpReranker, RagError> {
+        HttpReranker::from_config(config, None, None, None)
+    }
+
+    fn from_config(
+        config: &HttpRerankerConfig,
+        default_url: Option<&str>,
+        key_env: Option<&str>,
+        default_model: Option<&str>,
+    ) -> Result<HttpReranker, RagError> {
+        let model = config
+            .model
+            .clone()
+            .or_else(|| default_model.map(str::to_string))
+            .ok_or_else(|| RagError::Rerank("重排模型配置缺少 model".to_string()))?;
+        let api_url = config
+            .api_url
+            .as_deref()
+            .filter(|url| !url.is_empty())
+            .or(default_url)
+            .ok_or_else(|| RagError::Rerank("重排模型配置缺少 api_url".to_string()))?;
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("openkimi-rag/", env!("CARGO_PKG_VERSION")))
+            .timeout(Duration::from_secs(60))
+            .build()
+            .map_err(|e| RagError::Rerank(format!("创建HTTP客户端失败: {}", e)))?;
+        Ok(HttpReranker {
+            client,
+            url: format!("{}/rerank", api_url.trim_end_matches('/')),
+            api_key: config
+                .api_key
+                .clone()
+                .filter(|key| !key.is_empty())
+                .or_else(|| key_env.and_then(|key_env| env::var(key_env).ok())),
+            model,
+            max_retries: config.max_retries,
+        })
+    }
+
+    async fn request(&self, query: &str, documents: &[String]) -> Result<Vec<f32>, RagError> {
+        if documents.is_empty() {
+            return Ok(Vec::new());
+        }
+        let body = json!({
+            "model": self.model,
+            "query": query,
+            "documents": documents,
+            "top_n": documents.len(),
+        });
+        let response = post_json(&self.client, &self.url, self.api_key.as_deref(), &body, self.max_retries, "重排接口")
+            .await
+            .map_err(RagError::Rerank)?;
+        let response: RerankResponse = response
+            .json()
+            .await
+            .map_err(|e| RagError::Rerank(format!("无法解析重排响应: {}", e)))?;
+
+        let mut scores = vec![None; documents.len()];
+        for result in response.results {
+            let slot = scores
+                .get_mut(result.index)
+                .ok_or_else(|| RagError::Rerank(format!("重排接口返回了不存在的序号 {}", result.index)))?;
+            *slot = Some(result.relevance_score);
+        }
+        scores
+            .into_iter()
+            .collect::<Option<Vec<f32>>>()
+            .ok_or_else(|| RagError::Rerank("重排接口没有返回全部候选的得分".to_string()))
+    }
+}
+
+impl Reranker for HttpReranker {
+    fn rerank<'a>(&'a self, query: &'a str, documents: &'a [String]) -> BoxFuture<'a, Result<Vec<f32>, RagError>> {
+        Box::pin(self.request(query, documents))
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+}
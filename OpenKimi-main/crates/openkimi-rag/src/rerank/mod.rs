@@ -0,0 +1,106 @@
+//! 重排模型
+//!
+//! 向量检索只比较查询和分块各自的向量，召回的候选中常混有不相关的分块。重排模型同时读入查询和每个候选，
+//! 逐一给出相关度，用于在拼接提示词之前重新排序和筛选。[`Reranker`]的实现有Cohere、Jina及兼容的
+//! `/rerank`接口，以及本地ONNX交叉编码器（需启用`onnx`特性），由[`RerankerConfig`]的`provider`字段选择。
+
+mod http;
+#[cfg(feature = "onnx")]
+mod onnx;
+
+use std::path::PathBuf;
+
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+
+use crate::error::RagError;
+
+pub use http::HttpReranker;
+#[cfg(feature = "onnx")]
+pub use onnx::OnnxReranker;
+
+/// 按与查询的相关度给候选文本打分
+///
+/// 返回的分数与`documents`一一对应，越大越相关。
+pub trait Reranker: Send + Sync {
+    fn rerank<'a>(&'a self, query: &'a str, documents: &'a [String]) -> BoxFuture<'a, Result<Vec<f32>, RagError>>;
+
+    /// 模型名，用于日志和接口返回
+    fn model(&self) -> &str;
+}
+
+fn default_max_retries() -> u32 {
+    5
+}
+
+/// 重排接口的配置
+#[derive(Debug, Clone, Deserialize)]
+pub struct HttpRerankerConfig {
+    /// Cohere缺省为`rerank-multilingual-v3.0`，Jina缺省为`jina-reranker-v2-base-multilingual`，`http`必须指定
+    #[serde(default)]
+    pub model: Option<String>,
+    /// 缺省时读取`COHERE_API_KEY`或`JINA_API_KEY`
+    #[serde(default)]
+    pub api_key: Option<String>,
+    /// 接口的基础地址，请求发往`<api_url>/rerank`；`http`必须指定
+    #[serde(default)]
+    pub api_url: Option<String>,
+    /// 遇到429和5xx时的最大重试次数
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+}
+
+fn default_batch_size() -> usize {
+    16
+}
+
+fn default_max_length() -> usize {
+    512
+}
+
+fn default_sigmoid() -> bool {
+    true
+}
+
+/// 本地ONNX交叉编码器的配置，如导出为ONNX的bge-reranker、ms-marco-MiniLM
+#[derive(Debug, Clone, Deserialize)]
+pub struct OnnxRerankerConfig {
+    /// `model.onnx`的路径
+    pub model_path: PathBuf,
+    /// Hugging Face格式的`tokenizer.json`
+    pub tokenizer_path: PathBuf,
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// 查询和候选拼接后超出的部分截断
+    #[serde(default = "default_max_length")]
+    pub max_length: usize,
+    /// 是否用sigmoid把模型输出的logit换算到0到1之间，便于设置阈值
+    #[serde(default = "default_sigmoid")]
+    pub sigmoid: bool,
+}
+
+/// 重排模型配置，按`provider`区分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "provider", rename_all = "lowercase")]
+pub enum RerankerConfig {
+    Cohere(HttpRerankerConfig),
+    Jina(HttpRerankerConfig),
+    /// 与Cohere、Jina请求格式相同的其他接口，如vLLM的`/v1/rerank`
+    Http(HttpRerankerConfig),
+    Onnx(OnnxRerankerConfig),
+}
+
+/// 按配置创建重排模型
+pub fn build_reranker(config: &RerankerConfig) -> Result<Box<dyn Reranker>, RagError> {
+    match config {
+        RerankerConfig::Cohere(config) => Ok(Box::new(HttpReranker::cohere(config)?)),
+        RerankerConfig::Jina(config) => Ok(Box::new(HttpReranker::jina(config)?)),
+        RerankerConfig::Http(config) => Ok(Box::new(HttpReranker::compatible(config)?)),
+        #[cfg(feature = "onnx")]
+        RerankerConfig::Onnx(config) => Ok(Box::new(OnnxReranker::load(config)?)),
+        #[cfg(not(feature = "onnx"))]
+        RerankerConfig::Onnx(_) => Err(RagError::Rerank(
+            "本地ONNX重排需要启用 onnx 特性重新编译（cargo build --features openkimi-rag/onnx）".to_string(),
+        )),
+    }
+}
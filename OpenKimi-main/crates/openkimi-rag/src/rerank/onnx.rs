@@ -0,0 +1,149 @@
+//! 本地ONNX交叉编码器
+
+use std::sync::{Arc, Mutex};
+
+use futures_util::future::BoxFuture;
+use ort::session::Session;
+use ort::value::Tensor;
+use tokenizers::{EncodeInput, PaddingParams, PaddingStrategy, Tokenizer, TruncationParams, TruncationStrategy};
+
+use super::{OnnxRerankerConfig, Reranker};
+use crate::error::RagError;
+
+fn onnx_error(err: impl std::fmt::Display) -> RagError {
+    RagError::Rerank(format!("ONNX推理失败: {}", err))
+}
+
+#[derive(Debug)]
+struct Model {
+    session: Mutex<Session>,
+    tokenizer: Tokenizer,
+    /// 模型是否有`token_type_ids`输入，BERT类模型有，XLM-RoBERTa类（如bge-reranker）没有
+    type_ids: bool,
+    batch_size: usize,
+    sigmoid: bool,
+}
+
+/// 用ONNX Runtime在本机重排
+///
+/// 查询和候选作为一对句子输入模型，输出为`[批, 1]`或`[批]`时直接作为得分，`[批, 2]`时取第二列（相关）的logit。
+/// 推理在阻塞线程池中执行，不占用异步运行时。
+#[derive(Debug, Clone)]
+pub struct OnnxReranker {
+    model: Arc<Model>,
+    name: String,
+}
+
+impl OnnxReranker {
+    pub fn load(config: &OnnxRerankerConfig) -> Result<OnnxReranker, RagError> {
+        let session = Session::builder()
+            .and_then(|builder| builder.commit_from_file(&config.model_path))
+            .map_err(|e| RagError::Rerank(format!("加载ONNX模型 {} 失败: {}", config.model_path.display(), e)))?;
+        let type_ids = session.inputs.iter().any(|input| input.name == "token_type_ids");
+
+        let mut tokenizer = Tokenizer::from_file(&config.tokenizer_path).map_err(|e| {
+            RagError::Rerank(format!("加载分词器 {} 失败: {}", config.tokenizer_path.display(), e))
+        })?;
+        // 过长时只截断候选，保留完整的查询
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: config.max_length.max(1),
+                strategy: TruncationStrategy::OnlySecond,
+                ..TruncationParams::default()
+            }))
+            .map_err(|e| RagError::Rerank(format!("分词器配置无效: {}", e)))?;
+        tokenizer.with_padding(Some(PaddingParams {
+            strategy: PaddingStrategy::BatchLongest,
+            ..PaddingParams::default()
+        }));
+
+        Ok(OnnxReranker {
+            model: Arc::new(Model {
+                session: Mutex::new(session),
+                tokenizer,
+                type_ids,
+                batch_size: config.batch_size.max(1),
+                sigmoid: config.sigmoid,
+            }),
+            name: config.model_path.to_string_lossy().into_owned(),
+        })
+    }
+}
+
+impl Model {
+    fn score(&self, query: &str, documents: &[String]) -> Result<Vec<f32>, RagError> {
+        let pairs: Vec<EncodeInput> = documents
+            .iter()
+            .map(|document| (query.to_string(), document.clone()).into())
+            .collect();
+        let encodings = self
+            .tokenizer
+            .encode_batch(pairs, true)
+            .map_err(|e| RagError::Rerank(format!("分词失败: {}", e)))?;
+        let batch = encodings.len();
+        let length = encodings.first().map_or(0, |encoding| encoding.len());
+        if batch == 0 || length == 0 {
+            return Ok(vec![0.0; batch]);
+        }
+
+        let flatten = |field: fn(&tokenizers::Encoding) -> &[u32]| -> Vec<i64> {
+            encodings
+                .iter()
+                .flat_map(|encoding| field(encoding).iter().map(|&v| v as i64))
+                .collect()
+        };
+        let shape = [batch, length];
+        let mut inputs = ort::inputs![
+            "input_ids" => Tensor::from_array((shape, flatten(tokenizers::Encoding::get_ids))).map_err(onnx_error)?,
+            "attention_mask" => Tensor::from_array((shape, flatten(tokenizers::Encoding::get_attention_mask)))
+                .map_err(onnx_error)?,
+        ];
+        if self.type_ids {
+            let type_ids =
+                Tensor::from_array((shape, flatten(tokenizers::Encoding::get_type_ids))).map_err(onnx_error)?;
+            inputs.push(("token_type_ids".into(), type_ids.into()));
+        }
+
+        let mut session = self.session.lock().unwrap();
+        let outputs = session.run(inputs).map_err(onnx_error)?;
+        let (output_shape, values) = outputs[0].try_extract_tensor::<f32>().map_err(onnx_error)?;
+        let logits: Vec<f32> = match **output_shape {
+            [_] | [_, 1] => values.to_vec(),
+            [_, 2] => values.chunks(2).map(|pair| pair[1]).collect(),
+            ref other => return Err(RagError::Rerank(format!("无法识别的模型输出形状 {:?}", other))),
+        };
+        if logits.len() != batch {
+            return Err(RagError::Rerank(format!("输入了 {} 个候选，却返回了 {} 个得分", batch, logits.len())));
+        }
+        Ok(if self.sigmoid {
+            logits.into_iter().map(|logit| 1.0 / (1.0 + (-logit).exp())).collect()
+        } else {
+            logits
+        })
+    }
+
+    fn rerank(&self, query: &str, documents: &[String]) -> Result<Vec<f32>, RagError> {
+        let mut scores = Vec::with_capacity(documents.len());
+        for batch in documents.chunks(self.batch_size) {
+            scores.extend(self.score(query, batch)?);
+        }
+        Ok(scores)
+    }
+}
+
+impl Reranker for OnnxReranker {
+    fn rerank<'a>(&'a self, query: &'a str, documents: &'a [String]) -> BoxFuture<'a, Result<Vec<f32>, RagError>> {
+        let model = Arc::clone(&self.model);
+        let query = query.to_string();
+        let documents = documents.to_vec();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || model.rerank(&query, &documents))
+                .await
+                .map_err(onnx_error)?
+        })
+    }
+
+    fn model(&self) -> &str {
+        &self.name
+    }
+}
@@ -0,0 +1,73 @@
+//! 远程模型接口的请求与重试
+
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use serde_json::Value;
+
+/// 重试等待时间的上限
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// 发送JSON请求，遇到限流、服务端错误、超时和连接失败时按`Retry-After`或指数退避重试
+///
+/// `name`是日志和错误信息中的接口名，如“嵌入接口”。失败时返回错误说明，由调用方包装为对应的错误类型。
+pub(crate) async fn post_json(
+    client: &reqwest::Client,
+    url: &str,
+    api_key: Option<&str>,
+    body: &Value,
+    max_retries: u32,
+    name: &str,
+) -> Result<reqwest::Response, String> {
+    let mut attempt = 0;
+    loop {
+        let mut request = client.post(url).json(body);
+        if let Some(key) = api_key {
+            request = request.bearer_auth(key);
+        }
+
+        let (reason, retry_after) = match request.send().await {
+            Ok(response) if response.status().is_success() => return Ok(response),
+            Ok(response) => {
+                let status = response.status();
+                let retry_after = response
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.trim().parse().ok())
+                    .map(Duration::from_secs);
+                let body = response.text().await.unwrap_or_default();
+                let retryable = status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error();
+                if !retryable || attempt >= max_retries {
+                    return Err(format!("{}返回 {}: {}", name, status, error_message(&body)));
+                }
+                (status.to_string(), retry_after)
+            }
+            Err(err) if (err.is_timeout() || err.is_connect()) && attempt < max_retries => (err.to_string(), None),
+            Err(err) => return Err(format!("请求{}失败: {}", name, err)),
+        };
+
+        let delay = retry_after.unwrap_or_else(|| Duration::from_millis(500 << attempt.min(6))).min(MAX_BACKOFF);
+        attempt += 1;
+        eprintln!(
+            "⏳ {} {}，{:.1} 秒后重试（{}/{}）",
+            name,
+            reason,
+            delay.as_secs_f32(),
+            attempt,
+            max_retries
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// 取出错误体中的`error.message`（OpenAI格式）或`message`（Cohere、Jina格式）
+fn error_message(body: &str) -> String {
+    serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|value| {
+            let message = value["error"]["message"].as_str().or(value["message"].as_str());
+            message.map(str::to_string)
+        })
+        .unwrap_or_else(|| body.to_string())
+}
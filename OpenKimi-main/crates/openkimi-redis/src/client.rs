@@ -0,0 +1,104 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::connection::{Connection, Options};
+use crate::error::RedisError;
+use crate::value::{Arg, Value};
+
+#[derive(Debug)]
+struct Inner {
+    options: Options,
+    /// 用完归还的空闲连接
+    idle: Mutex<Vec<Connection>>,
+    max_idle: usize,
+}
+
+/// 带连接池的客户端，克隆后共享同一个池
+///
+/// 池中没有空闲连接时新建连接，用完归还；空闲连接超过`pool_size`时多出的直接关闭。
+#[derive(Debug, Clone)]
+pub struct Client {
+    inner: Arc<Inner>,
+}
+
+impl Client {
+    /// 解析地址并建立第一个连接，确认Redis可以访问
+    pub fn open(url: &str, pool_size: usize, timeout: Duration) -> Result<Client, RedisError> {
+        let client = Client {
+            inner: Arc::new(Inner {
+                options: Options::parse(url, timeout)?,
+                idle: Mutex::new(Vec::new()),
+                max_idle: pool_size.max(1),
+            }),
+        };
+        client.query(&[&"PING"])?;
+        Ok(client)
+    }
+
+    /// 取一个连接，释放时归还；`WATCH`/`MULTI`等需要在同一个连接上执行的命令使用它
+    pub fn get(&self) -> Result<Pooled, RedisError> {
+        let idle = self.inner.idle.lock().unwrap_or_else(|e| e.into_inner()).pop();
+        let connection = match idle {
+            Some(connection) => connection,
+            None => Connection::connect(&self.inner.options)?,
+        };
+        Ok(Pooled {
+            connection: Some(connection),
+            inner: Arc::clone(&self.inner),
+        })
+    }
+
+    /// 在池中的连接上执行一条命令；空闲连接已被服务端关闭时换新连接重试一次
+    pub fn query(&self, args: &[&dyn Arg]) -> Result<Value, RedisError> {
+        let mut connection = self.get()?;
+        match connection.query(args) {
+            Err(RedisError::Io(_)) => {
+                connection.discard();
+                let mut connection = self.get()?;
+                connection.query(args)
+            }
+            result => result,
+        }
+    }
+}
+
+/// 从池中取出的连接
+#[derive(Debug)]
+pub struct Pooled {
+    connection: Option<Connection>,
+    inner: Arc<Inner>,
+}
+
+impl Pooled {
+    /// 关闭连接，不归还到池中
+    pub fn discard(&mut self) {
+        self.connection = None;
+    }
+}
+
+impl Deref for Pooled {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.connection.as_ref().expect("连接已关闭")
+    }
+}
+
+impl DerefMut for Pooled {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.connection.as_mut().expect("连接已关闭")
+    }
+}
+
+impl Drop for Pooled {
+    fn drop(&mut self) {
+        let Some(connection) = self.connection.take().filter(|connection| !connection.is_broken()) else {
+            return;
+        };
+        let mut idle = self.inner.idle.lock().unwrap_or_else(|e| e.into_inner());
+        if idle.len() < self.inner.max_idle {
+            idle.push(connection);
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::error::RedisError;
+use crate::value::{encode, Arg, Value};
+
+/// 连接参数，由`redis://[用户名:密码@]主机[:端口][/库]`解析得到
+#[derive(Debug, Clone)]
+pub(crate) struct Options {
+    pub address: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub database: u32,
+    /// 连接、读写的超时
+    pub timeout: Duration,
+}
+
+impl Options {
+    pub fn parse(url: &str, timeout: Duration) -> Result<Options, RedisError> {
+        let rest = match url.split_once("://") {
+            Some(("redis", rest)) => rest,
+            Some((scheme, _)) => return Err(RedisError::Config(format!("不支持的协议 {}://", scheme))),
+            None => url,
+        };
+        let (credentials, rest) = match rest.rsplit_once('@') {
+            Some((credentials, rest)) => (Some(credentials), rest),
+            None => (None, rest),
+        };
+        let (username, password) = match credentials {
+            Some(credentials) => match credentials.split_once(':') {
+                Some((username, password)) => {
+                    (Some(username.to_string()).filter(|name| !name.is_empty()), Some(password.to_string()))
+                }
+                None => (None, Some(credentials.to_string())),
+            },
+            None => (None, None),
+        };
+        let (host, database) = match rest.split_once('/') {
+            Some((host, "")) => (host, 0),
+            Some((host, database)) => {
+                let database = database.parse().map_err(|_| RedisError::Config(format!("无效的库编号 {}", database)))?;
+                (host, database)
+            }
+            None => (rest, 0),
+        };
+        if host.is_empty() {
+            return Err(RedisError::Config(format!("地址 {} 缺少主机名", url)));
+        }
+        let address = if host.rsplit_once(':').is_some_and(|(_, port)| port.parse::<u16>().is_ok()) {
+            host.to_string()
+        } else {
+            format!("{}:6379", host)
+        };
+        Ok(Options {
+            address,
+            username,
+            password,
+            database,
+            timeout,
+        })
+    }
+}
+
+/// 一个Redis连接
+#[derive(Debug)]
+pub struct Connection {
+    reader: BufReader<TcpStream>,
+    /// 读写出错后回复可能已错位，连接不再使用
+    broken: bool,
+}
+
+impl Connection {
+    pub(crate) fn connect(options: &Options) -> Result<Connection, RedisError> {
+        let mut last = None;
+        let mut stream = None;
+        for address in options.address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, options.timeout) {
+                Ok(connected) => {
+                    stream = Some(connected);
+                    break;
+                }
+                Err(err) => last = Some(err),
+            }
+        }
+        let stream = match (stream, last) {
+            (Some(stream), _) => stream,
+            (None, Some(err)) => return Err(RedisError::Io(err)),
+            (None, None) => return Err(RedisError::Config(format!("无法解析地址 {}", options.address))),
+        };
+        stream.set_read_timeout(Some(options.timeout))?;
+        stream.set_write_timeout(Some(options.timeout))?;
+        stream.set_nodelay(true)?;
+        let mut connection = Connection {
+            reader: BufReader::new(stream),
+            broken: false,
+        };
+        match (&options.username, &options.password) {
+            (Some(username), Some(password)) => connection.query(&[&"AUTH", username, password])?,
+            (None, Some(password)) => connection.query(&[&"AUTH", password])?,
+            _ => Value::Nil,
+        };
+        if options.database != 0 {
+            connection.query(&[&"SELECT", &options.database])?;
+        }
+        Ok(connection)
+    }
+
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    /// 发送一条命令并读取回复，错误回复转为[`RedisError::Server`]
+    pub fn query(&mut self, args: &[&dyn Arg]) -> Result<Value, RedisError> {
+        let result = self.exchange(args);
+        if result.as_ref().is_err_and(RedisError::is_fatal) {
+            self.broken = true;
+        }
+        match result? {
+            Value::Error(message) => Err(RedisError::Server(message)),
+            value => Ok(value),
+        }
+    }
+
+    fn exchange(&mut self, args: &[&dyn Arg]) -> Result<Value, RedisError> {
+        if self.broken {
+            return Err(RedisError::Io(ErrorKind::NotConnected.into()));
+        }
+        let stream = self.reader.get_mut();
+        stream.write_all(&encode(args))?;
+        stream.flush()?;
+        self.read_value()
+    }
+
+    fn read_line(&mut self) -> Result<Vec<u8>, RedisError> {
+        let mut line = Vec::new();
+        self.reader.read_until(b'\n', &mut line)?;
+        if !line.ends_with(b"\r\n") {
+            return Err(RedisError::Io(ErrorKind::UnexpectedEof.into()));
+        }
+        line.truncate(line.len() - 2);
+        Ok(line)
+    }
+
+    fn read_value(&mut self) -> Result<Value, RedisError> {
+        let line = self.read_line()?;
+        let Some((&kind, rest)) = line.split_first() else {
+            return Err(RedisError::Protocol("空行".to_string()));
+        };
+        let text = String::from_utf8_lossy(rest).into_owned();
+        let number = |text: &str| text.parse::<i64>().map_err(|_| RedisError::Protocol(format!("无效的长度 {}", text)));
+        match kind {
+            b'+' => Ok(Value::Status(text)),
+            b'-' => Ok(Value::Error(text)),
+            b':' => Ok(Value::Int(number(&text)?)),
+            b'$' => match number(&text)? {
+                length if length < 0 => Ok(Value::Nil),
+                length => {
+                    let mut bytes = vec![0; length as usize + 2];
+                    self.reader.read_exact(&mut bytes)?;
+                    bytes.truncate(length as usize);
+                    Ok(Value::Bulk(bytes))
+                }
+            },
+            b'*' => match number(&text)? {
+                length if length < 0 => Ok(Value::Nil),
+                length => (0..length).map(|_| self.read_value()).collect::<Result<_, _>>().map(Value::Array),
+            },
+            other => Err(RedisError::Protocol(format!("未知的回复类型 {}", other as char))),
+        }
+    }
+}
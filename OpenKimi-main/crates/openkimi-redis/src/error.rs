@@ -0,0 +1,41 @@
+use std::fmt;
+use std::io;
+
+/// Redis客户端错误
+#[derive(Debug)]
+pub enum RedisError {
+    /// 连接或读写失败，连接不再可用
+    Io(io::Error),
+    /// 回复不符合RESP协议
+    Protocol(String),
+    /// Redis返回的错误回复，如`WRONGTYPE`、`NOAUTH`
+    Server(String),
+    /// 连接地址或参数不对
+    Config(String),
+}
+
+impl RedisError {
+    /// 连接是否因此不能再用，需要丢弃
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, RedisError::Io(_) | RedisError::Protocol(_))
+    }
+}
+
+impl fmt::Display for RedisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RedisError::Io(err) => write!(f, "访问Redis失败: {}", err),
+            RedisError::Protocol(reason) => write!(f, "Redis回复格式错误: {}", reason),
+            RedisError::Server(message) => write!(f, "Redis返回错误: {}", message),
+            RedisError::Config(reason) => write!(f, "Redis配置错误: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for RedisError {}
+
+impl From<io::Error> for RedisError {
+    fn from(err: io::Error) -> RedisError {
+        RedisError::Io(err)
+    }
+}
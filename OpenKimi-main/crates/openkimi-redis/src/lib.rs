@@ -0,0 +1,17 @@
+//! Redis客户端
+//!
+//! 只实现服务端用到的部分：按RESP2协议发送命令、读取回复，连接时认证和选库，以及一个简单的连接池。
+//! 多个服务实例连接同一个Redis即可共享会话、限流和回复缓存等状态。
+//!
+//! 客户端使用阻塞IO，在异步代码中应放到阻塞线程中调用。不支持TLS（`rediss://`）、集群和哨兵，
+//! 远程Redis请通过内网或SSH隧道访问。
+
+mod client;
+mod connection;
+mod error;
+mod value;
+
+pub use client::{Client, Pooled};
+pub use connection::Connection;
+pub use error::RedisError;
+pub use value::{Arg, Value};
@@ -0,0 +1,118 @@
+use crate::error::RedisError;
+
+/// 命令的回复
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Nil,
+    Int(i64),
+    /// 简单字符串，如`OK`
+    Status(String),
+    Bulk(Vec<u8>),
+    Array(Vec<Value>),
+    /// 数组中的错误，如`EXEC`中某条命令的错误；顶层的错误回复转为[`RedisError::Server`]
+    Error(String),
+}
+
+impl Value {
+    pub fn is_nil(&self) -> bool {
+        matches!(self, Value::Nil)
+    }
+
+    /// 整数回复，或内容是整数的字符串
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Int(value) => Some(*value),
+            Value::Bulk(bytes) => std::str::from_utf8(bytes).ok()?.parse().ok(),
+            Value::Status(text) => text.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// 字符串回复的内容，`Nil`为`None`
+    pub fn into_bytes(self) -> Option<Vec<u8>> {
+        match self {
+            Value::Bulk(bytes) => Some(bytes),
+            Value::Status(text) => Some(text.into_bytes()),
+            Value::Int(value) => Some(value.to_string().into_bytes()),
+            _ => None,
+        }
+    }
+
+    pub fn into_string(self) -> Option<String> {
+        self.into_bytes().map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// 数组回复的元素，`Nil`为空数组
+    pub fn into_array(self) -> Result<Vec<Value>, RedisError> {
+        match self {
+            Value::Array(items) => Ok(items),
+            Value::Nil => Ok(Vec::new()),
+            Value::Error(message) => Err(RedisError::Server(message)),
+            other => Err(RedisError::Protocol(format!("应为数组，实际为 {:?}", other))),
+        }
+    }
+}
+
+/// 可以作为命令参数的值
+pub trait Arg {
+    fn write_arg(&self, out: &mut Vec<u8>);
+}
+
+fn write_bulk(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(format!("${}\r\n", bytes.len()).as_bytes());
+    out.extend_from_slice(bytes);
+    out.extend_from_slice(b"\r\n");
+}
+
+impl Arg for str {
+    fn write_arg(&self, out: &mut Vec<u8>) {
+        write_bulk(out, self.as_bytes());
+    }
+}
+
+impl Arg for String {
+    fn write_arg(&self, out: &mut Vec<u8>) {
+        write_bulk(out, self.as_bytes());
+    }
+}
+
+impl Arg for [u8] {
+    fn write_arg(&self, out: &mut Vec<u8>) {
+        write_bulk(out, self);
+    }
+}
+
+impl Arg for Vec<u8> {
+    fn write_arg(&self, out: &mut Vec<u8>) {
+        write_bulk(out, self);
+    }
+}
+
+macro_rules! number_arg {
+    ($($ty:ty),*) => {
+        $(
+            impl Arg for $ty {
+                fn write_arg(&self, out: &mut Vec<u8>) {
+                    write_bulk(out, self.to_string().as_bytes());
+                }
+            }
+        )*
+    };
+}
+
+number_arg!(i64, u64, u32, usize, f64);
+
+impl<T: Arg + ?Sized> Arg for &T {
+    fn write_arg(&self, out: &mut Vec<u8>) {
+        (**self).write_arg(out);
+    }
+}
+
+/// 把一条命令编码为RESP数组
+pub(crate) fn encode(args: &[&dyn Arg]) -> Vec<u8> {
+    let mut out = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        arg.write_arg(&mut out);
+    }
+    out
+}
@@ -0,0 +1,66 @@
+use std::fmt;
+
+/// 结构迁移错误
+#[derive(Debug)]
+pub enum SchemaError {
+    /// 读写SQLite失败
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
+    /// 读写PostgreSQL失败
+    #[cfg(feature = "postgres")]
+    Postgres(postgres::Error),
+    /// 数据库中的表结构由更新版本的程序创建
+    TooNew {
+        component: String,
+        version: u32,
+        supported: u32,
+    },
+    /// 该版本没有回退步骤
+    Irreversible { component: String, version: u32 },
+    /// 要迁移到的版本不存在
+    Target { component: String, target: u32, latest: u32 },
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "sqlite")]
+            SchemaError::Sqlite(err) => write!(f, "迁移数据库结构失败: {}", err),
+            #[cfg(feature = "postgres")]
+            SchemaError::Postgres(err) => write!(f, "迁移数据库结构失败: {}", err),
+            SchemaError::TooNew {
+                component,
+                version,
+                supported,
+            } => write!(
+                f,
+                "数据库中 {} 的表结构 v{} 比当前程序支持的 v{} 更新，请升级程序",
+                component, version, supported
+            ),
+            SchemaError::Irreversible { component, version } => {
+                write!(f, "{} 的表结构 v{} 不能回退", component, version)
+            }
+            SchemaError::Target {
+                component,
+                target,
+                latest,
+            } => write!(f, "{} 没有表结构 v{}，最新为 v{}", component, target, latest),
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for SchemaError {
+    fn from(err: rusqlite::Error) -> SchemaError {
+        SchemaError::Sqlite(err)
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl From<postgres::Error> for SchemaError {
+    fn from(err: postgres::Error) -> SchemaError {
+        SchemaError::Postgres(err)
+    }
+}
@@ -0,0 +1,111 @@
+//! 数据库结构版本迁移
+//!
+//! 每个部分（如会话、记忆、用量）的表结构有一个版本号，迁移列表的下标加一即版本号。每个版本包含升级步骤和可选的
+//! 回退步骤，步骤可以是内嵌的SQL，也可以是Rust函数（需要逐行转换数据时使用），与版本号的更新在同一个事务中执行。
+//! 新增版本时只需追加到列表末尾，已发布的版本不再修改。
+//!
+//! 打开数据库时用`migrate`把结构升级到最新；数据库的版本比程序支持的更新时拒绝打开，避免旧程序按旧结构写坏数据。
+//! 回退用于降级程序之前把结构退回旧版本，没有回退步骤的版本不能回退。
+//!
+//! SQLite的一个文件只保存一个部分，版本记录在`PRAGMA user_version`中，见[`sqlite`]；PostgreSQL中各部分共用一个
+//! 数据库，版本记录在`openkimi_schema`表中，见[`postgres`]。
+
+mod error;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+pub use error::SchemaError;
+
+/// 迁移步骤，`F`为所用数据库的Rust步骤函数类型
+pub enum Step<F> {
+    /// 内嵌的SQL，可以包含多条语句
+    Sql(&'static str),
+    /// Rust函数
+    Rust(F),
+}
+
+/// 一个版本的迁移
+pub struct Migration<F> {
+    /// 简短说明，查看迁移状态时显示
+    pub description: &'static str,
+    pub up: Step<F>,
+    /// 回退到上一个版本的步骤，为`None`时不能回退
+    pub down: Option<Step<F>>,
+}
+
+impl<F> Migration<F> {
+    /// 升级和回退步骤都是SQL的版本
+    pub const fn sql(description: &'static str, up: &'static str, down: Option<&'static str>) -> Migration<F> {
+        Migration {
+            description,
+            up: Step::Sql(up),
+            down: match down {
+                Some(sql) => Some(Step::Sql(sql)),
+                None => None,
+            },
+        }
+    }
+}
+
+/// 某个部分的结构版本
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub component: String,
+    /// 数据库当前的版本，0表示还没有建表
+    pub version: u32,
+    /// 程序支持的最新版本
+    pub latest: u32,
+}
+
+impl Status {
+    /// 数据库由更新版本的程序创建
+    pub fn is_newer(&self) -> bool {
+        self.version > self.latest
+    }
+
+    /// 还没有执行的版本数
+    pub fn pending(&self) -> u32 {
+        self.latest.saturating_sub(self.version)
+    }
+}
+
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+/// 从`version`迁移到`target`依次执行的步骤及执行后的版本：升级时依次执行各版本的升级步骤，回退时从高到低执行回退步骤
+fn plan<'a, F>(
+    component: &str,
+    migrations: &'a [Migration<F>],
+    version: u32,
+    target: u32,
+) -> Result<Vec<(&'a Step<F>, u32)>, SchemaError> {
+    let latest = migrations.len() as u32;
+    if version > latest {
+        return Err(SchemaError::TooNew {
+            component: component.to_string(),
+            version,
+            supported: latest,
+        });
+    }
+    if target > latest {
+        return Err(SchemaError::Target {
+            component: component.to_string(),
+            target,
+            latest,
+        });
+    }
+    if target >= version {
+        return Ok((version + 1..=target).map(|version| (&migrations[version as usize - 1].up, version)).collect());
+    }
+    // 先确认每个版本都能回退，不会退到一半停下
+    (target + 1..=version)
+        .rev()
+        .map(|version| match &migrations[version as usize - 1].down {
+            Some(step) => Ok((step, version - 1)),
+            None => Err(SchemaError::Irreversible {
+                component: component.to_string(),
+                version,
+            }),
+        })
+        .collect()
+}
@@ -0,0 +1,68 @@
+//! PostgreSQL的结构迁移，各部分的版本记录在`openkimi_schema`表中
+//!
+//! 多个服务实例同时启动时用事务级的咨询锁排队，每个版本在单独的事务中加锁后重新读取版本号，同一版本只执行一次。
+
+use postgres::{Client, Transaction};
+
+use crate::{plan, SchemaError, Status, Step};
+
+/// PostgreSQL的迁移，Rust步骤在迁移的事务中执行
+pub type Migration = crate::Migration<fn(&mut Transaction<'_>) -> Result<(), postgres::Error>>;
+
+/// 读取`component`的版本，版本表还不存在时为0
+fn version(client: &mut impl postgres::GenericClient, component: &str) -> Result<u32, postgres::Error> {
+    let exists: bool = client.query_one("SELECT to_regclass('openkimi_schema') IS NOT NULL", &[])?.get(0);
+    if !exists {
+        return Ok(0);
+    }
+    let row = client.query_opt("SELECT version FROM openkimi_schema WHERE component = $1", &[&component])?;
+    Ok(row.map(|row| row.get::<_, i32>(0) as u32).unwrap_or(0))
+}
+
+/// 数据库当前的结构版本
+pub fn status(client: &mut Client, component: &str, migrations: &[Migration]) -> Result<Status, SchemaError> {
+    Ok(Status {
+        component: component.to_string(),
+        version: version(client, component)?,
+        latest: migrations.len() as u32,
+    })
+}
+
+/// 把`component`的表升级到最新结构，数据库比程序新时返回错误；打开数据库时调用
+pub fn migrate(client: &mut Client, component: &str, migrations: &[Migration]) -> Result<(), SchemaError> {
+    migrate_to(client, component, migrations, migrations.len() as u32)
+}
+
+/// 把`component`的表升级或回退到`target`版本，每个版本在单独的事务中执行
+pub fn migrate_to(
+    client: &mut Client,
+    component: &str,
+    migrations: &[Migration],
+    target: u32,
+) -> Result<(), SchemaError> {
+    loop {
+        let mut tx = client.transaction()?;
+        // 建版本表也在锁内，同时启动的实例不会并发建表
+        tx.batch_execute(
+            "SELECT pg_advisory_xact_lock(hashtext('openkimi_schema'));
+             CREATE TABLE IF NOT EXISTS openkimi_schema (
+                 component TEXT PRIMARY KEY,
+                 version INTEGER NOT NULL
+             );",
+        )?;
+        let current = version(&mut tx, component)?;
+        let Some(&(step, version)) = plan(component, migrations, current, target)?.first() else {
+            return Ok(());
+        };
+        match step {
+            Step::Sql(sql) => tx.batch_execute(sql)?,
+            Step::Rust(run) => run(&mut tx)?,
+        }
+        tx.execute(
+            "INSERT INTO openkimi_schema (component, version) VALUES ($1, $2)
+             ON CONFLICT (component) DO UPDATE SET version = excluded.version",
+            &[&component, &(version as i32)],
+        )?;
+        tx.commit()?;
+    }
+}
@@ -0,0 +1,46 @@
+//! SQLite的结构迁移，版本记录在`PRAGMA user_version`中
+
+use rusqlite::Connection;
+
+use crate::{plan, SchemaError, Status, Step};
+
+/// SQLite的迁移，Rust步骤在迁移的事务中执行
+pub type Migration = crate::Migration<fn(&Connection) -> rusqlite::Result<()>>;
+
+fn version(conn: &Connection) -> rusqlite::Result<u32> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+/// 数据库当前的结构版本
+pub fn status(conn: &Connection, component: &str, migrations: &[Migration]) -> Result<Status, SchemaError> {
+    Ok(Status {
+        component: component.to_string(),
+        version: version(conn)?,
+        latest: migrations.len() as u32,
+    })
+}
+
+/// 把数据库升级到最新结构，数据库比程序新时返回错误；打开数据库时调用
+pub fn migrate(conn: &mut Connection, component: &str, migrations: &[Migration]) -> Result<(), SchemaError> {
+    migrate_to(conn, component, migrations, migrations.len() as u32)
+}
+
+/// 把数据库升级或回退到`target`版本，每个版本在单独的事务中执行
+pub fn migrate_to(
+    conn: &mut Connection,
+    component: &str,
+    migrations: &[Migration],
+    target: u32,
+) -> Result<(), SchemaError> {
+    let current = version(conn)?;
+    for (step, version) in plan(component, migrations, current, target)? {
+        let tx = conn.transaction()?;
+        match step {
+            Step::Sql(sql) => tx.execute_batch(sql)?,
+            Step::Rust(run) => run(&tx)?,
+        }
+        tx.pragma_update(None, "user_version", version)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,12 @@
+//! 编译gRPC接口定义
+//!
+//! 使用纯Rust实现的protox解析proto文件，构建时不需要安装protoc。
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto/openkimi.proto");
+    let descriptors = protox::compile(["proto/openkimi.proto"], ["proto"])?;
+    tonic_build::configure()
+        .build_client(false)
+        .compile_fds(descriptors)?;
+    Ok(())
+}
@@ -0,0 +1,592 @@
+//! 管理接口
+//!
+//! 挂在`/admin`下，需要`Authorization: Bearer <vault.admin_token>`；没有配置管理令牌时不提供这些接口。
+//! 用于管理密钥库中的上游密钥、查看上游端点的健康状态和各工作区的用量、查看许可证状态、创建用户并签发和吊销API令牌、
+//! 查看和调整用户与团队的配额、导出计量的用量、清除回复缓存、
+//! 查看和重新加载工具插件、查看外部MCP服务器的连接状态、查看和手动运行定时任务、查看监视的文件夹的导入状态、
+//! 查看内容安全事件以及查询和校验安全事件日志，
+//! 修改立即生效，无需重启服务。
+
+use std::sync::Arc;
+
+use axum::extract::{Path, Query, Request, State};
+use axum::http::header::{AUTHORIZATION, CONTENT_DISPOSITION, CONTENT_TYPE};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::audit;
+use crate::cache::{CacheStats, ResponseCache};
+use crate::auth::{bearer, constant_time_eq};
+use crate::config::UserQuotaConfig;
+use crate::error::{ApiError, ApiResult};
+use crate::indexer::{FolderStatus, Indexer};
+use crate::jobs::{JobStatus, Scheduler};
+use crate::mcp_client::McpServerInfo;
+use crate::metering::{self, Dimension, UsageQuery};
+use crate::plugins::{PluginInfo, PluginRegistry};
+use crate::pool::EndpointInfo;
+use crate::quotas::{Holder, QuotaInfo, Quotas};
+use crate::safety::SafetyEvent;
+use crate::trail::{self, AuditTrail, TrailQuery, Verification};
+use crate::types::{DeletedResponse, ListResponse};
+use crate::users::{self, IssuedToken, NewToken, NewUser, TokenInfo, UserDirectory, UserInfo, UserUpdate};
+use crate::vault::{KeyInfo, KeyVault};
+use crate::workspace::WorkspaceInfo;
+use crate::AppState;
+
+/// `POST /admin/keys`请求
+#[derive(Debug, Deserialize)]
+pub struct AddKeyRequest {
+    pub provider: String,
+    pub secret: String,
+    #[serde(default)]
+    pub label: Option<String>,
+    /// 每分钟最多请求数
+    #[serde(default)]
+    pub rpm: Option<u32>,
+}
+
+/// `POST /admin/keys/{id}/rotate`请求
+#[derive(Debug, Deserialize)]
+pub struct RotateKeyRequest {
+    pub secret: String,
+}
+
+/// `GET /admin/usage`的查询参数
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct UsageParams {
+    /// 起止日期，`YYYY-MM-DD`，缺省为本月1日到今天
+    pub from: Option<String>,
+    pub to: Option<String>,
+    /// 逗号分隔的汇总维度，缺省为`workspace,user,api_key,model`，为空时只返回总计
+    pub group_by: Option<String>,
+    pub workspace: Option<String>,
+    pub user: Option<String>,
+    pub api_key: Option<String>,
+    pub model: Option<String>,
+    /// `json`（默认）或`csv`
+    pub format: Option<String>,
+}
+
+/// `DELETE /admin/cache`的查询参数，都不指定时清空缓存
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct InvalidateParams {
+    pub workspace: Option<String>,
+    pub model: Option<String>,
+}
+
+/// `GET /admin/safety/events`的查询参数
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SafetyEventParams {
+    /// 最多返回的事件数，缺省为100
+    pub limit: Option<usize>,
+    pub category: Option<String>,
+    pub workspace: Option<String>,
+}
+
+/// `GET /admin/audit/events`的查询参数
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct TrailParams {
+    /// 起止日期，`YYYY-MM-DD`
+    pub from: Option<String>,
+    pub to: Option<String>,
+    /// 事件名，以`.`结尾时按前缀匹配
+    pub event: Option<String>,
+    pub actor: Option<String>,
+    /// 最多返回最后的多少条，缺省为100；`format=jsonl`时缺省返回全部
+    pub limit: Option<usize>,
+    /// `json`（默认）或`jsonl`
+    pub format: Option<String>,
+}
+
+/// 管理接口的路由，没有配置管理令牌时返回`None`
+pub fn router(state: Arc<AppState>) -> Option<Router> {
+    state.config().vault.admin_token.as_ref()?;
+    let router = Router::new()
+        .route("/admin/keys", get(list_keys).post(add_key))
+        .route("/admin/keys/{id}", delete(remove_key))
+        .route("/admin/keys/{id}/enable", post(enable_key))
+        .route("/admin/keys/{id}/disable", post(disable_key))
+        .route("/admin/keys/{id}/rotate", post(rotate_key))
+        .route("/admin/endpoints", get(list_endpoints))
+        .route("/admin/workspaces", get(list_workspaces))
+        .route("/admin/license", get(license_status))
+        .route("/admin/users", get(list_users).post(create_user))
+        .route("/admin/users/{name}", get(get_user).patch(update_user).delete(remove_user))
+        .route("/admin/users/{name}/tokens", post(issue_token))
+        .route("/admin/users/{name}/tokens/{id}", delete(revoke_token))
+        .route("/admin/users/{name}/usage", get(user_usage))
+        .route("/admin/quotas", get(list_quotas))
+        .route(
+            "/admin/quotas/{holder}/{name}",
+            get(get_quota).put(adjust_quota).delete(restore_quota),
+        )
+        .route("/admin/quotas/{holder}/{name}/reset", post(reset_quota))
+        .route("/admin/usage", get(usage_report))
+        .route("/admin/cache", get(cache_stats).delete(invalidate_cache))
+        .route("/admin/plugins", get(list_plugins))
+        .route("/admin/plugins/reload", post(reload_plugins))
+        .route("/admin/mcp", get(list_mcp_servers))
+        .route("/admin/jobs", get(list_jobs))
+        .route("/admin/jobs/{name}/run", post(run_job))
+        .route("/admin/indexer", get(list_watched_folders))
+        .route("/admin/indexer/rescan", post(rescan_folders))
+        .route("/admin/safety/events", get(list_safety_events))
+        .route("/admin/audit/events", get(list_trail_events))
+        .route("/admin/audit/verify", get(verify_trail))
+        .route_layer(middleware::from_fn_with_state(Arc::clone(&state), require_admin))
+        // 在管理令牌检查之外，令牌错误的请求也会记录
+        .route_layer(middleware::from_fn_with_state(Arc::clone(&state), trail::watch))
+        .with_state(state);
+    Some(router)
+}
+
+async fn require_admin(State(state): State<Arc<AppState>>, request: Request, next: Next) -> ApiResult<Response> {
+    let config = state.config();
+    let expected = config.vault.admin_token.as_deref().unwrap_or_default();
+    let header = request.headers().get(AUTHORIZATION).and_then(|value| value.to_str().ok());
+    let provided = bearer(header).unwrap_or_default();
+    if expected.is_empty() || !constant_time_eq(provided.as_bytes(), expected.as_bytes()) {
+        return Err(ApiError::Unauthorized("管理令牌无效".to_string()));
+    }
+    Ok(next.run(request).await)
+}
+
+fn vault(state: &AppState) -> ApiResult<&KeyVault> {
+    state
+        .vault
+        .as_deref()
+        .ok_or_else(|| ApiError::invalid_request("密钥库未启用（vault.enabled 为 false）"))
+}
+
+/// 列出全部密钥及其健康状态，不返回密钥本身
+async fn list_keys(State(state): State<Arc<AppState>>) -> ApiResult<Json<ListResponse<KeyInfo>>> {
+    Ok(Json(ListResponse::new(vault(&state)?.list())))
+}
+
+/// 列出各后端`api_url`和`endpoints`中的端点及其健康状态
+async fn list_endpoints(State(state): State<Arc<AppState>>) -> Json<ListResponse<EndpointInfo>> {
+    let endpoints = state.models().backends().flat_map(|backend| backend.upstream.pool().endpoints()).collect();
+    Json(ListResponse::new(endpoints))
+}
+
+/// 列出全部工作区及其配额和用量
+async fn list_workspaces(State(state): State<Arc<AppState>>) -> ApiResult<Json<ListResponse<WorkspaceInfo>>> {
+    let workspaces = state
+        .workspaces
+        .as_ref()
+        .ok_or_else(|| ApiError::invalid_request("未启用工作区（workspaces.enabled 为 false）"))?;
+    Ok(Json(ListResponse::new(workspaces.list())))
+}
+
+/// 许可证的状态和已占用的席位数；未启用用户目录时`seats_used`为`null`
+async fn license_status(State(state): State<Arc<AppState>>) -> ApiResult<Json<Value>> {
+    let license = state
+        .license
+        .as_ref()
+        .ok_or_else(|| ApiError::invalid_request("没有配置许可证（license.key 或 license.key_file）"))?;
+    let validation = license.validation().map_err(ApiError::Internal)?;
+    let seats_used = state.users.as_ref().map(|users| users.active_count()).transpose()?;
+    Ok(Json(json!({
+        "license": validation.info,
+        "status": validation.status,
+        "seats_used": seats_used,
+    })))
+}
+
+fn user_directory(state: &AppState) -> ApiResult<&UserDirectory> {
+    state
+        .users
+        .as_deref()
+        .ok_or_else(|| ApiError::invalid_request("未启用用户目录（users.enabled 为 false）"))
+}
+
+/// 列出全部用户及其令牌，不返回令牌本身
+async fn list_users(State(state): State<Arc<AppState>>) -> ApiResult<Json<ListResponse<UserInfo>>> {
+    Ok(Json(ListResponse::new(user_directory(&state)?.list()?)))
+}
+
+async fn create_user(State(state): State<Arc<AppState>>, Json(request): Json<NewUser>) -> ApiResult<Json<UserInfo>> {
+    let directory = user_directory(&state)?;
+    let workspace = match &request.workspace {
+        Some(workspace) => {
+            users::resolve_workspace(&state.config().workspaces, workspace).map_err(ApiError::InvalidRequest)?
+        }
+        None => None,
+    };
+    Ok(Json(directory.create(&request.name, workspace, request.scopes)?))
+}
+
+async fn get_user(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> ApiResult<Json<UserInfo>> {
+    Ok(Json(user_directory(&state)?.get(&name)?))
+}
+
+/// 修改用户的工作区、权限或停用状态，停用后其全部令牌立即失效
+async fn update_user(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Json(update): Json<UserUpdate>,
+) -> ApiResult<Json<UserInfo>> {
+    let directory = user_directory(&state)?;
+    let workspace = update
+        .workspace
+        .map(|workspace| users::resolve_workspace(&state.config().workspaces, &workspace))
+        .transpose()
+        .map_err(ApiError::InvalidRequest)?;
+    Ok(Json(directory.update(&name, workspace, update.scopes, update.disabled)?))
+}
+
+/// 删除用户及其全部令牌，已有的用量记录保留
+async fn remove_user(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> ApiResult<Json<DeletedResponse>> {
+    user_directory(&state)?.remove(&name)?;
+    Ok(Json(DeletedResponse {
+        id: name,
+        object: "user.deleted".to_string(),
+        deleted: true,
+    }))
+}
+
+/// 签发新令牌，响应中的`token`之后无法再次查看
+async fn issue_token(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    request: Option<Json<NewToken>>,
+) -> ApiResult<Json<IssuedToken>> {
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+    Ok(Json(user_directory(&state)?.issue(&name, request)?))
+}
+
+async fn revoke_token(
+    State(state): State<Arc<AppState>>,
+    Path((name, id)): Path<(String, String)>,
+) -> ApiResult<Json<TokenInfo>> {
+    Ok(Json(user_directory(&state)?.revoke(&name, &id)?))
+}
+
+/// 某个用户的计量用量，参数与`GET /admin/usage`相同，`user`固定为该用户
+async fn user_usage(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Query(mut params): Query<UsageParams>,
+) -> ApiResult<Response> {
+    user_directory(&state)?.get(&name)?;
+    params.user = Some(name);
+    usage_report(State(state), Query(params)).await
+}
+
+fn quotas(state: &AppState) -> ApiResult<&Quotas> {
+    state
+        .quotas
+        .as_deref()
+        .ok_or_else(|| ApiError::invalid_request("未启用用户配额（quotas.enabled 为 false）"))
+}
+
+/// 路径中的`users`或`teams`
+fn holder(segment: &str) -> ApiResult<Holder> {
+    Holder::parse(segment).ok_or_else(|| ApiError::NotFound(format!("未知的配额类型: {}（可选 users、teams）", segment)))
+}
+
+/// 列出用户和团队的配额、用量和剩余额度
+async fn list_quotas(State(state): State<Arc<AppState>>) -> ApiResult<Json<ListResponse<QuotaInfo>>> {
+    Ok(Json(ListResponse::new(quotas(&state)?.list())))
+}
+
+async fn get_quota(
+    State(state): State<Arc<AppState>>,
+    Path((segment, name)): Path<(String, String)>,
+) -> ApiResult<Json<QuotaInfo>> {
+    Ok(Json(quotas(&state)?.info(holder(&segment)?, &name)?))
+}
+
+/// 替换某个用户或团队的配额，未给出的项不限；在删除调整之前不再跟随配置文件
+async fn adjust_quota(
+    State(state): State<Arc<AppState>>,
+    Path((segment, name)): Path<(String, String)>,
+    Json(quota): Json<UserQuotaConfig>,
+) -> ApiResult<Json<QuotaInfo>> {
+    Ok(Json(quotas(&state)?.adjust(holder(&segment)?, &name, Some(quota))?))
+}
+
+/// 删除调整，恢复为配置文件中的配额
+async fn restore_quota(
+    State(state): State<Arc<AppState>>,
+    Path((segment, name)): Path<(String, String)>,
+) -> ApiResult<Json<QuotaInfo>> {
+    Ok(Json(quotas(&state)?.adjust(holder(&segment)?, &name, None)?))
+}
+
+/// 清零今天的token数和本月的费用
+async fn reset_quota(
+    State(state): State<Arc<AppState>>,
+    Path((segment, name)): Path<(String, String)>,
+) -> ApiResult<Json<QuotaInfo>> {
+    Ok(Json(quotas(&state)?.reset(holder(&segment)?, &name)?))
+}
+
+/// 按日期范围和维度汇总计量的用量，`format=csv`时以附件形式返回CSV
+async fn usage_report(State(state): State<Arc<AppState>>, Query(params): Query<UsageParams>) -> ApiResult<Response> {
+    let meter = state
+        .metering
+        .clone()
+        .ok_or_else(|| ApiError::invalid_request("未启用用量计量（metering.enabled 为 false）"))?;
+    let csv = match params.format.as_deref() {
+        None | Some("json") => false,
+        Some("csv") => true,
+        Some(other) => return Err(ApiError::invalid_request(format!("不支持的格式: {}（可选 json、csv）", other))),
+    };
+
+    let today = audit::today();
+    let from = params.from.unwrap_or_else(|| format!("{}-01", &today[..7]));
+    let to = params.to.unwrap_or(today);
+    for date in [&from, &to] {
+        if !metering::is_date(date) {
+            return Err(ApiError::invalid_request(format!("无效的日期: {}（格式为 YYYY-MM-DD）", date)));
+        }
+    }
+    let group_by = params
+        .group_by
+        .as_deref()
+        .unwrap_or("workspace,user,api_key,model")
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            Dimension::parse(name).ok_or_else(|| {
+                ApiError::invalid_request(format!("无效的汇总维度: {}（可选 day、workspace、user、api_key、model）", name))
+            })
+        })
+        .collect::<ApiResult<Vec<_>>>()?;
+    let filters = [
+        (Dimension::Workspace, params.workspace),
+        (Dimension::User, params.user),
+        (Dimension::ApiKey, params.api_key),
+        (Dimension::Model, params.model),
+    ]
+    .into_iter()
+    .filter_map(|(dimension, value)| value.map(|value| (dimension, value)))
+    .collect();
+    let query = UsageQuery {
+        from,
+        to,
+        group_by,
+        filters,
+    };
+
+    let report = tokio::task::spawn_blocking(move || meter.report(query))
+        .await
+        .map_err(|e| ApiError::Internal(format!("查询用量失败: {}", e)))?
+        .map_err(ApiError::Internal)?;
+    if !csv {
+        return Ok(Json(report.to_json()).into_response());
+    }
+    let disposition = format!("attachment; filename=\"usage-{}-{}.csv\"", report.query.from, report.query.to);
+    let headers = [(CONTENT_TYPE, "text/csv; charset=utf-8".to_string()), (CONTENT_DISPOSITION, disposition)];
+    Ok((headers, report.to_csv()).into_response())
+}
+
+fn cache(state: &AppState) -> ApiResult<&ResponseCache> {
+    state
+        .cache
+        .as_ref()
+        .ok_or_else(|| ApiError::invalid_request("未启用回复缓存（cache.enabled 为 false）"))
+}
+
+/// 缓存的条目数、大小和命中情况
+async fn cache_stats(State(state): State<Arc<AppState>>) -> ApiResult<Json<CacheStats>> {
+    Ok(Json(cache(&state)?.stats().await))
+}
+
+/// 删除缓存的回复，例如更新了常见问题的答案之后
+async fn invalidate_cache(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<InvalidateParams>,
+) -> ApiResult<Json<Value>> {
+    let deleted = cache(&state)?
+        .invalidate(params.workspace.as_deref(), params.model.as_deref())
+        .await
+        .map_err(ApiError::Unavailable)?;
+    Ok(Json(json!({ "object": "cache.invalidated", "deleted": deleted })))
+}
+
+fn plugins(state: &AppState) -> ApiResult<Arc<PluginRegistry>> {
+    state
+        .plugins
+        .clone()
+        .ok_or_else(|| ApiError::invalid_request("未启用工具插件（plugins.enabled 为 false）"))
+}
+
+/// 各插件的状态，未加载的附带原因
+async fn list_plugins(State(state): State<Arc<AppState>>) -> ApiResult<Json<ListResponse<PluginInfo>>> {
+    Ok(Json(ListResponse::new(plugins(&state)?.list())))
+}
+
+/// 立即扫描插件目录，不等待下一次定期检查
+async fn reload_plugins(State(state): State<Arc<AppState>>) -> ApiResult<Json<ListResponse<PluginInfo>>> {
+    let plugins = plugins(&state)?;
+    let list = tokio::task::spawn_blocking(move || plugins.reload())
+        .await
+        .map_err(|e| ApiError::Internal(format!("重新加载插件失败: {}", e)))?;
+    Ok(Json(ListResponse::new(list)))
+}
+
+/// 外部MCP服务器的连接状态和提供的工具
+async fn list_mcp_servers(State(state): State<Arc<AppState>>) -> ApiResult<Json<ListResponse<McpServerInfo>>> {
+    let clients = state
+        .mcp_clients
+        .as_ref()
+        .ok_or_else(|| ApiError::invalid_request("没有配置外部 MCP 服务器（mcp.servers 为空）"))?;
+    Ok(Json(ListResponse::new(clients.list())))
+}
+
+fn jobs(state: &AppState) -> ApiResult<&Scheduler> {
+    state
+        .jobs
+        .as_ref()
+        .ok_or_else(|| ApiError::invalid_request("未启用定时任务（jobs.enabled 为 false）"))
+}
+
+/// 各定时任务的计划、运行状态和最近一次的结果
+async fn list_jobs(State(state): State<Arc<AppState>>) -> ApiResult<Json<ListResponse<JobStatus>>> {
+    Ok(Json(ListResponse::new(jobs(&state)?.list())))
+}
+
+/// 立即在后台运行一次定时任务，不影响原有计划
+async fn run_job(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> ApiResult<Json<JobStatus>> {
+    Ok(Json(jobs(&state)?.trigger(&state, &name)?))
+}
+
+fn indexer(state: &AppState) -> ApiResult<&Indexer> {
+    state
+        .indexer
+        .as_deref()
+        .ok_or_else(|| ApiError::invalid_request("未启用文件夹监视（indexer.enabled 为 false）"))
+}
+
+/// 各监视的文件夹的导入状态
+async fn list_watched_folders(State(state): State<Arc<AppState>>) -> ApiResult<Json<ListResponse<FolderStatus>>> {
+    Ok(Json(ListResponse::new(indexer(&state)?.list())))
+}
+
+/// 立即扫描所有文件夹，导入失败的文件也重试；扫描在后台进行
+async fn rescan_folders(State(state): State<Arc<AppState>>) -> ApiResult<Json<ListResponse<FolderStatus>>> {
+    let indexer = indexer(&state)?;
+    indexer.rescan();
+    Ok(Json(ListResponse::new(indexer.list())))
+}
+
+/// 最近的内容安全事件，新的在前
+async fn list_safety_events(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SafetyEventParams>,
+) -> ApiResult<Json<ListResponse<SafetyEvent>>> {
+    let safety = state
+        .safety
+        .as_ref()
+        .ok_or_else(|| ApiError::invalid_request("未启用内容安全检查（safety.enabled 为 false）"))?;
+    let events = safety.recent(
+        params.limit.unwrap_or(100),
+        params.category.as_deref(),
+        params.workspace.as_deref(),
+    );
+    Ok(Json(ListResponse::new(events)))
+}
+
+fn audit_trail(state: &AppState) -> ApiResult<Arc<AuditTrail>> {
+    state
+        .trail
+        .clone()
+        .ok_or_else(|| ApiError::invalid_request("未启用安全事件日志（audit_trail.enabled 为 false）"))
+}
+
+/// 查询安全事件，`format=jsonl`时以附件形式返回原始记录，可以离线校验哈希链
+async fn list_trail_events(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<TrailParams>,
+) -> ApiResult<Response> {
+    let trail = audit_trail(&state)?;
+    let jsonl = match params.format.as_deref() {
+        None | Some("json") => false,
+        Some("jsonl") => true,
+        Some(other) => return Err(ApiError::invalid_request(format!("不支持的格式: {}（可选 json、jsonl）", other))),
+    };
+    for date in [&params.from, &params.to].into_iter().flatten() {
+        if !metering::is_date(date) {
+            return Err(ApiError::invalid_request(format!("无效的日期: {}（格式为 YYYY-MM-DD）", date)));
+        }
+    }
+    let query = TrailQuery {
+        from: params.from,
+        to: params.to,
+        event: params.event,
+        actor: params.actor,
+        limit: params.limit.or((!jsonl).then_some(100)),
+    };
+    let lines = tokio::task::spawn_blocking(move || trail.query(&query))
+        .await
+        .map_err(|e| ApiError::Internal(format!("查询安全事件失败: {}", e)))?
+        .map_err(ApiError::Internal)?;
+    if jsonl {
+        let mut body = lines.join("\n");
+        if !body.is_empty() {
+            body.push('\n');
+        }
+        let headers = [
+            (CONTENT_TYPE, "application/x-ndjson".to_string()),
+            (CONTENT_DISPOSITION, "attachment; filename=\"audit_trail.jsonl\"".to_string()),
+        ];
+        return Ok((headers, body).into_response());
+    }
+    let events: Vec<Value> = lines.iter().filter_map(|line| serde_json::from_str(line).ok()).collect();
+    Ok(Json(ListResponse::new(events)).into_response())
+}
+
+/// 从头校验哈希链
+async fn verify_trail(State(state): State<Arc<AppState>>) -> ApiResult<Json<Verification>> {
+    let trail = audit_trail(&state)?;
+    let result = tokio::task::spawn_blocking(move || trail.verify())
+        .await
+        .map_err(|e| ApiError::Internal(format!("校验安全事件日志失败: {}", e)))?
+        .map_err(ApiError::Internal)?;
+    Ok(Json(result))
+}
+
+async fn add_key(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<AddKeyRequest>,
+) -> ApiResult<Json<KeyInfo>> {
+    Ok(Json(vault(&state)?.add(request.provider, request.secret, request.label, request.rpm)?))
+}
+
+async fn enable_key(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> ApiResult<Json<KeyInfo>> {
+    Ok(Json(vault(&state)?.set_enabled(&id, true)?))
+}
+
+async fn disable_key(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> ApiResult<Json<KeyInfo>> {
+    Ok(Json(vault(&state)?.set_enabled(&id, false)?))
+}
+
+/// 更换密钥，同时清除其失效和暂停状态
+async fn rotate_key(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(request): Json<RotateKeyRequest>,
+) -> ApiResult<Json<KeyInfo>> {
+    Ok(Json(vault(&state)?.rotate(&id, request.secret)?))
+}
+
+async fn remove_key(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> ApiResult<Json<DeletedResponse>> {
+    vault(&state)?.remove(&id)?;
+    Ok(Json(DeletedResponse {
+        id,
+        object: "key.deleted".to_string(),
+        deleted: true,
+    }))
+}
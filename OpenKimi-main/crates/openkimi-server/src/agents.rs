@@ -0,0 +1,721 @@
+//! 智能体
+//!
+//! 由服务端执行“规划 → 调用工具 → 观察”的循环：先请求模型为目标列出分步计划，再反复请求模型并执行它调用的
+//! 服务端工具，把结果作为观察追加到对话中，直到模型给出最终答复或用完步数；步数用完时不带工具再请求一次，
+//! 让模型根据已有的观察作答。每一步都写入`agents.dir`下的运行记录（草稿本），服务重启后仍可查询。
+//! 任务在后台运行，可以随时取消；客户端通过SSE实时收到各步，断开连接不影响任务，之后可以重新订阅。
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::extract::{Path, State};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use futures_util::future::join_all;
+use futures_util::stream::{self, BoxStream};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch, OwnedSemaphorePermit, Semaphore};
+
+use crate::config::AgentsConfig;
+use crate::error::{ApiError, ApiResult};
+use crate::metering::{self, Consumer};
+use crate::ratelimit::{self, RateLimitKey};
+use crate::tools::{self, ToolRunner, ToolScope};
+use crate::types::{ChatCompletionRequest, ChatMessage, DeletedResponse, ListResponse, MessageContent, Usage};
+use crate::workspace::{self, Workspace};
+use crate::{interpreter, sse, AppState};
+
+/// 清理过期运行记录的间隔
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 每个任务缓存的事件数，订阅方读得太慢时丢弃较早的事件
+const EVENT_CAPACITY: usize = 256;
+
+const SYSTEM_PROMPT: &str = "你是一个自主完成任务的智能体。先为用户的目标制定简洁的分步计划，然后逐步执行：\
+需要信息或操作时调用工具，根据工具返回的结果调整计划，直到能够给出完整的最终答复。";
+
+const PLAN_PROMPT: &str = "先列出完成这个目标的编号计划，暂时不要执行。";
+
+const EXECUTE_PROMPT: &str = "按照计划开始执行，需要时调用工具。完成后直接给出最终答复，不要再调用工具。";
+
+const BUDGET_PROMPT: &str = "步数已经用完，不能再调用工具。请根据已有的观察给出最终答复，并说明还有哪些没有完成。";
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// 任务状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    Running,
+    /// 模型给出了最终答复
+    Completed,
+    /// 步数用完，答复可能不完整
+    Incomplete,
+    Cancelled,
+    /// 请求上游失败或服务重启时任务仍在运行
+    Failed,
+}
+
+/// 步骤类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepKind {
+    Plan,
+    /// 一轮工具调用及其观察
+    Action,
+    Answer,
+}
+
+/// 一次工具调用及其结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: String,
+    pub output: String,
+    /// 工具执行失败时`output`为错误信息
+    pub error: bool,
+}
+
+/// 运行中的一步
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentStep {
+    /// 从1开始
+    pub index: u32,
+    pub kind: StepKind,
+    /// 计划、调用工具前的思考或最终答复
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<StepToolCall>,
+    pub created_at: u64,
+}
+
+/// 一次运行的记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentRun {
+    pub id: String,
+    pub object: String,
+    pub model: String,
+    pub goal: String,
+    pub status: RunStatus,
+    /// 最多执行几轮工具调用
+    pub max_steps: u32,
+    /// 已执行的工具调用轮数
+    pub steps_used: u32,
+    pub steps: Vec<AgentStep>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub answer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// 各次请求上游的用量之和
+    pub usage: Usage,
+    pub created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u64>,
+}
+
+/// `POST /v1/agents/runs`请求
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewRun {
+    #[serde(default)]
+    pub model: String,
+    pub goal: String,
+    /// 放在目标之前的对话，如背景材料
+    #[serde(default)]
+    pub messages: Vec<ChatMessage>,
+    /// 可以使用的服务端工具，缺省为全部（需要用户确认的MCP工具除外）
+    #[serde(default)]
+    pub tools: Option<Vec<String>>,
+    #[serde(default)]
+    pub max_steps: Option<u32>,
+    /// 代码解释器的会话，缺省时文件只在本次运行中共享
+    #[serde(default)]
+    pub session_id: Option<String>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+    /// 以SSE返回各步和最终结果
+    #[serde(default)]
+    pub stream: bool,
+    /// 立即返回运行记录，不等待任务结束
+    #[serde(default)]
+    pub background: bool,
+}
+
+/// 推送给订阅方的事件
+#[derive(Debug, Clone)]
+enum RunEvent {
+    Step(String),
+    /// 最终的运行记录，之后不再有事件
+    Finished(String),
+}
+
+/// 一个任务，运行结束后仍保留以便查询
+#[derive(Debug)]
+struct RunEntry {
+    workspace: String,
+    path: PathBuf,
+    run: Mutex<AgentRun>,
+    events: broadcast::Sender<RunEvent>,
+    cancel: watch::Sender<bool>,
+}
+
+fn write_json(path: &FsPath, value: &impl Serialize) -> Result<(), String> {
+    let text = serde_json::to_string_pretty(value).unwrap_or_default();
+    fs::write(path, text).map_err(|e| format!("写入 {} 失败: {}", path.display(), e))
+}
+
+fn step_event(run: &AgentRun, step: &AgentStep) -> String {
+    json!({ "object": "agent.run.step", "run_id": run.id, "step": step }).to_string()
+}
+
+impl RunEntry {
+    fn new(workspace: String, path: PathBuf, run: AgentRun) -> RunEntry {
+        RunEntry {
+            workspace,
+            path,
+            run: Mutex::new(run),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+            cancel: watch::channel(false).0,
+        }
+    }
+
+    fn snapshot(&self) -> AgentRun {
+        self.run.lock().unwrap().clone()
+    }
+
+    fn save(&self, run: &AgentRun) {
+        if let Err(err) = write_json(&self.path, run) {
+            eprintln!("⚠️ 保存智能体运行记录失败: {}", err);
+        }
+    }
+
+    fn add_usage(&self, usage: &Usage) {
+        let mut run = self.run.lock().unwrap();
+        run.usage = tools::add_usage(Some(run.usage.clone()), Some(usage)).unwrap_or_default();
+    }
+
+    /// 追加一步并推送给订阅方
+    fn push_step(&self, kind: StepKind, content: Option<String>, tool_calls: Vec<StepToolCall>) {
+        let mut run = self.run.lock().unwrap();
+        let step = AgentStep {
+            index: run.steps.len() as u32 + 1,
+            kind,
+            content: content.filter(|content| !content.is_empty()),
+            tool_calls,
+            created_at: unix_now(),
+        };
+        if kind == StepKind::Action {
+            run.steps_used += 1;
+        }
+        let _ = self.events.send(RunEvent::Step(step_event(&run, &step)));
+        run.steps.push(step);
+        self.save(&run);
+    }
+
+    /// 记录结果并通知订阅方任务已结束
+    fn finish(&self, status: RunStatus, answer: Option<String>, error: Option<String>) {
+        let mut run = self.run.lock().unwrap();
+        run.status = status;
+        run.answer = answer;
+        run.error = error;
+        run.finished_at = Some(unix_now());
+        self.save(&run);
+        let data = serde_json::to_string(&*run).unwrap_or_default();
+        let _ = self.events.send(RunEvent::Finished(data));
+    }
+
+    /// 已有的各步加上之后的事件，任务结束后以最终的运行记录和`[DONE]`结束
+    fn events(&self) -> BoxStream<'static, String> {
+        let run = self.run.lock().unwrap();
+        let mut replay: VecDeque<String> = run.steps.iter().map(|step| step_event(&run, step)).collect();
+        // 在持有锁时订阅，不会漏掉或重复推送的事件
+        let receiver = if run.status == RunStatus::Running {
+            Some(self.events.subscribe())
+        } else {
+            replay.push_back(serde_json::to_string(&*run).unwrap_or_default());
+            replay.push_back("[DONE]".to_string());
+            None
+        };
+        drop(run);
+
+        stream::unfold((replay, receiver), |(mut replay, mut receiver)| async move {
+            loop {
+                if let Some(data) = replay.pop_front() {
+                    return Some((data, (replay, receiver)));
+                }
+                match receiver.as_mut()?.recv().await {
+                    Ok(RunEvent::Step(data)) => return Some((data, (replay, receiver))),
+                    Ok(RunEvent::Finished(data)) => {
+                        receiver = None;
+                        replay.extend([data, "[DONE]".to_string()]);
+                    }
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+        .boxed()
+    }
+}
+
+/// 一次运行中请求上游所需的状态
+struct RunContext {
+    state: Arc<AppState>,
+    consumer: Consumer,
+    limit_key: Option<RateLimitKey>,
+    model: String,
+    temperature: Option<f32>,
+    max_tokens: Option<u32>,
+    /// 到目前为止的对话
+    messages: Vec<ChatMessage>,
+    runner: Option<Arc<ToolRunner>>,
+    /// 附加到请求上的工具定义
+    tools: Vec<Value>,
+    scope: ToolScope,
+    max_steps: u32,
+}
+
+fn message(role: &str, content: String) -> ChatMessage {
+    ChatMessage {
+        role: role.to_string(),
+        content: Some(MessageContent::Text(content)),
+        name: None,
+        extra: Map::new(),
+    }
+}
+
+impl RunContext {
+    /// 请求一次模型并计入用量，`with_tools`为`false`时不附加工具
+    async fn complete(&self, entry: &RunEntry, with_tools: bool) -> ApiResult<ChatMessage> {
+        let mut request = ChatCompletionRequest {
+            model: self.model.clone(),
+            messages: self.messages.clone(),
+            stream: None,
+            max_tokens: self.max_tokens,
+            temperature: self.temperature,
+            extra: Map::new(),
+        };
+        // 工具在校验之后附加，否则会被当作与服务端工具重名的客户端工具
+        let prompt_tokens = self.state.prepare_chat(&mut request).await?;
+        if with_tools && !self.tools.is_empty() {
+            request.extra.insert("tools".to_string(), Value::Array(self.tools.clone()));
+        }
+        let response = self.state.models().chat_completion(&request).await?;
+        let usage = match &response.usage {
+            Some(usage) => usage.clone(),
+            None => self.state.usage(&request.model, prompt_tokens, &response),
+        };
+        ratelimit::charge(&self.state, self.limit_key.as_ref(), usage.total_tokens);
+        workspace::charge(&self.state, &self.consumer.workspace, usage.total_tokens);
+        metering::record(&self.state, &self.consumer, &request.model, usage.prompt_tokens, usage.completion_tokens);
+        entry.add_usage(&usage);
+        response
+            .choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message)
+            .ok_or_else(|| ApiError::Upstream("上游没有返回回复".to_string()))
+    }
+
+    /// 执行一次工具调用，失败时把错误作为结果
+    async fn call(&self, call: &Value) -> StepToolCall {
+        let id = call["id"].as_str().unwrap_or_default().to_string();
+        let name = call["function"]["name"].as_str().unwrap_or_default().to_string();
+        let arguments = call["function"]["arguments"].as_str().unwrap_or_default().to_string();
+        let allowed = self.tools.iter().any(|tool| tool["function"]["name"] == name.as_str());
+        let result = match (&self.runner, allowed) {
+            (Some(runner), true) if arguments.trim().is_empty() => runner.execute(&name, &json!({}), &self.scope).await,
+            (Some(runner), true) => match serde_json::from_str::<Value>(&arguments) {
+                Ok(parsed) => runner.execute(&name, &parsed, &self.scope).await,
+                Err(e) => Err(format!("参数不是有效的 JSON: {}", e)),
+            },
+            _ => Err(format!("工具 {} 不可用", name)),
+        };
+        let (output, error) = match result {
+            Ok(output) => (output, false),
+            Err(message) => (json!({ "error": message }).to_string(), true),
+        };
+        StepToolCall {
+            id,
+            name,
+            arguments,
+            output,
+            error,
+        }
+    }
+
+    /// 规划后循环调用工具，返回结束状态和最终答复
+    async fn run(&mut self, entry: &RunEntry) -> ApiResult<(RunStatus, String)> {
+        let plan = self.complete(entry, false).await?.text();
+        entry.push_step(StepKind::Plan, Some(plan.clone()), Vec::new());
+        self.messages.push(message("assistant", plan));
+        self.messages.push(message("user", EXECUTE_PROMPT.to_string()));
+
+        for _ in 0..self.max_steps {
+            let reply = self.complete(entry, true).await?;
+            let calls = reply.extra.get("tool_calls").and_then(Value::as_array).cloned().unwrap_or_default();
+            if calls.is_empty() {
+                let answer = reply.text();
+                entry.push_step(StepKind::Answer, Some(answer.clone()), Vec::new());
+                return Ok((RunStatus::Completed, answer));
+            }
+            let thought = reply.text();
+            self.messages.push(reply);
+            let results = join_all(calls.iter().map(|call| self.call(call))).await;
+            for result in &results {
+                let mut observation = message("tool", result.output.clone());
+                observation.extra.insert("tool_call_id".to_string(), Value::from(result.id.as_str()));
+                self.messages.push(observation);
+            }
+            entry.push_step(StepKind::Action, Some(thought), results);
+        }
+
+        self.messages.push(message("user", BUDGET_PROMPT.to_string()));
+        let answer = self.complete(entry, false).await?.text();
+        entry.push_step(StepKind::Answer, Some(answer.clone()), Vec::new());
+        Ok((RunStatus::Incomplete, answer))
+    }
+}
+
+/// 在后台执行任务直到结束或被取消
+async fn execute(mut context: RunContext, entry: Arc<RunEntry>, permit: OwnedSemaphorePermit) {
+    let mut cancelled = entry.cancel.subscribe();
+    let outcome = tokio::select! {
+        outcome = context.run(&entry) => Some(outcome),
+        _ = cancelled.wait_for(|cancelled| *cancelled) => None,
+    };
+    match outcome {
+        Some(Ok((status, answer))) => entry.finish(status, Some(answer), None),
+        Some(Err(err)) => entry.finish(RunStatus::Failed, None, Some(err.to_string())),
+        None => entry.finish(RunStatus::Cancelled, None, None),
+    }
+    drop(permit);
+}
+
+/// 智能体任务及其运行记录
+#[derive(Debug)]
+pub struct Agents {
+    config: AgentsConfig,
+    runs: Arc<Mutex<HashMap<String, Arc<RunEntry>>>>,
+    permits: Arc<Semaphore>,
+}
+
+impl Agents {
+    /// 载入`agents.dir`中的运行记录并启动清理线程，上次退出时仍在运行的任务记为失败
+    pub fn open(config: &AgentsConfig) -> Result<Agents, String> {
+        fs::create_dir_all(&config.dir).map_err(|e| format!("创建目录 {} 失败: {}", config.dir.display(), e))?;
+        let mut runs = HashMap::new();
+        for workspace in fs::read_dir(&config.dir).map_err(|e| format!("读取目录 {} 失败: {}", config.dir.display(), e))? {
+            let Ok(workspace) = workspace else { continue };
+            let name = workspace.file_name().to_string_lossy().into_owned();
+            let Ok(files) = fs::read_dir(workspace.path()) else { continue };
+            for file in files.flatten() {
+                let path = file.path();
+                if path.extension().is_none_or(|extension| extension != "json") {
+                    continue;
+                }
+                let Ok(text) = fs::read_to_string(&path) else { continue };
+                let Ok(mut run) = serde_json::from_str::<AgentRun>(&text) else {
+                    eprintln!("⚠️ 无法解析智能体运行记录 {}", path.display());
+                    continue;
+                };
+                let entry = if run.status == RunStatus::Running {
+                    run.status = RunStatus::Failed;
+                    run.error = Some("服务重启，任务中断".to_string());
+                    run.finished_at = Some(unix_now());
+                    let entry = RunEntry::new(name.clone(), path, run.clone());
+                    entry.save(&run);
+                    entry
+                } else {
+                    RunEntry::new(name.clone(), path, run.clone())
+                };
+                runs.insert(run.id, Arc::new(entry));
+            }
+        }
+
+        let runs = Arc::new(Mutex::new(runs));
+        if config.ttl_seconds > 0 {
+            let runs = Arc::clone(&runs);
+            let ttl = config.ttl_seconds;
+            std::thread::spawn(move || loop {
+                std::thread::sleep(CLEANUP_INTERVAL);
+                remove_expired(&runs, ttl);
+            });
+        }
+        Ok(Agents {
+            config: config.clone(),
+            runs,
+            permits: Arc::new(Semaphore::new(config.max_running.max(1))),
+        })
+    }
+
+    fn entry(&self, workspace: &Workspace, id: &str) -> ApiResult<Arc<RunEntry>> {
+        match self.runs.lock().unwrap().get(id) {
+            Some(entry) if entry.workspace == workspace.name() => Ok(Arc::clone(entry)),
+            _ => Err(ApiError::NotFound(format!("智能体任务 {} 不存在", id))),
+        }
+    }
+
+    /// 校验请求并在后台开始运行
+    fn start(
+        &self,
+        state: &Arc<AppState>,
+        consumer: Consumer,
+        limit_key: Option<RateLimitKey>,
+        request: NewRun,
+    ) -> ApiResult<Arc<RunEntry>> {
+        let goal = request.goal.trim().to_string();
+        if goal.is_empty() {
+            return Err(ApiError::invalid_request("goal 不能为空"));
+        }
+        let max_steps = request.max_steps.unwrap_or(self.config.max_steps);
+        if max_steps == 0 || max_steps > self.config.max_steps_limit {
+            return Err(ApiError::invalid_request(format!(
+                "max_steps 应在 1 到 {} 之间",
+                self.config.max_steps_limit
+            )));
+        }
+        let scope = ToolScope::new(consumer.workspace.clone(), request.session_id)?;
+
+        // 任务无人值守，需要用户确认的工具不提供
+        let runner = state.tools.clone();
+        let mut available: Vec<Value> = runner
+            .iter()
+            .flat_map(|runner| runner.definitions())
+            .filter(|tool| {
+                let name = tool["function"]["name"].as_str().unwrap_or_default();
+                runner.as_ref().is_some_and(|runner| !runner.requires_approval(name))
+            })
+            .collect();
+        if let Some(names) = &request.tools {
+            let names: HashSet<&str> = names.iter().map(String::as_str).collect();
+            for name in &names {
+                if !available.iter().any(|tool| tool["function"]["name"] == *name) {
+                    return Err(ApiError::invalid_request(format!("工具 {} 不存在或需要用户确认", name)));
+                }
+            }
+            available.retain(|tool| tool["function"]["name"].as_str().is_some_and(|name| names.contains(name)));
+        }
+
+        let permit = Arc::clone(&self.permits).try_acquire_owned().map_err(|_| {
+            ApiError::RateLimited(format!("同时运行的智能体任务已达上限 {}，请稍后重试", self.config.max_running))
+        })?;
+
+        let mut system = self.config.system_prompt.clone().unwrap_or_else(|| SYSTEM_PROMPT.to_string());
+        if available.is_empty() {
+            system.push_str("\n\n没有可用的工具。");
+        } else {
+            system.push_str("\n\n可用的工具：");
+            for tool in &available {
+                let function = &tool["function"];
+                let name = function["name"].as_str().unwrap_or_default();
+                match function["description"].as_str() {
+                    Some(description) => system.push_str(&format!("\n- {}: {}", name, description)),
+                    None => system.push_str(&format!("\n- {}", name)),
+                }
+            }
+        }
+        let mut messages = vec![message("system", system)];
+        messages.extend(request.messages);
+        messages.push(message("user", goal.clone()));
+        messages.push(message("user", PLAN_PROMPT.to_string()));
+
+        let model = if request.model.is_empty() {
+            state.config().llm.model_name.clone()
+        } else {
+            request.model
+        };
+        let id = format!("run-{}", interpreter::random_hex().map_err(ApiError::Internal)?);
+        let run = AgentRun {
+            id: id.clone(),
+            object: "agent.run".to_string(),
+            model: model.clone(),
+            goal,
+            status: RunStatus::Running,
+            max_steps,
+            steps_used: 0,
+            steps: Vec::new(),
+            answer: None,
+            error: None,
+            usage: Usage::default(),
+            created_at: unix_now(),
+            finished_at: None,
+        };
+        let dir = self.config.dir.join(consumer.workspace.name());
+        fs::create_dir_all(&dir).map_err(|e| ApiError::Internal(format!("创建目录 {} 失败: {}", dir.display(), e)))?;
+        let entry = Arc::new(RunEntry::new(
+            consumer.workspace.name().to_string(),
+            dir.join(format!("{}.json", id)),
+            run.clone(),
+        ));
+        entry.save(&run);
+        self.runs.lock().unwrap().insert(id, Arc::clone(&entry));
+
+        let context = RunContext {
+            state: Arc::clone(state),
+            consumer,
+            limit_key,
+            model,
+            temperature: request.temperature,
+            max_tokens: request.max_tokens,
+            messages,
+            runner,
+            tools: available,
+            scope,
+            max_steps,
+        };
+        tokio::spawn(execute(context, Arc::clone(&entry), permit));
+        Ok(entry)
+    }
+
+    pub fn get(&self, workspace: &Workspace, id: &str) -> ApiResult<AgentRun> {
+        Ok(self.entry(workspace, id)?.snapshot())
+    }
+
+    /// 工作区中的运行记录，新的在前
+    pub fn list(&self, workspace: &Workspace) -> Vec<AgentRun> {
+        let entries: Vec<Arc<RunEntry>> = self
+            .runs
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|entry| entry.workspace == workspace.name())
+            .cloned()
+            .collect();
+        let mut runs: Vec<AgentRun> = entries.iter().map(|entry| entry.snapshot()).collect();
+        runs.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| a.id.cmp(&b.id)));
+        runs
+    }
+
+    /// 取消运行中的任务，已结束的任务不受影响；正在执行的工具调用随之中止
+    pub async fn cancel(&self, workspace: &Workspace, id: &str) -> ApiResult<AgentRun> {
+        let entry = self.entry(workspace, id)?;
+        if entry.snapshot().status == RunStatus::Running {
+            entry.cancel.send_replace(true);
+            // 等待任务记录结束状态后再返回
+            entry.events().for_each(|_| async {}).await;
+        }
+        Ok(entry.snapshot())
+    }
+
+    pub fn delete(&self, workspace: &Workspace, id: &str) -> ApiResult<()> {
+        let entry = self.entry(workspace, id)?;
+        if entry.snapshot().status == RunStatus::Running {
+            return Err(ApiError::invalid_request(format!("智能体任务 {} 仍在运行，请先取消", id)));
+        }
+        self.runs.lock().unwrap().remove(id);
+        remove_file(&entry.path);
+        Ok(())
+    }
+}
+
+fn remove_file(path: &FsPath) {
+    if let Err(e) = fs::remove_file(path) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            eprintln!("⚠️ 删除 {} 失败: {}", path.display(), e);
+        }
+    }
+}
+
+/// 删除结束超过`ttl`秒的运行记录
+fn remove_expired(runs: &Mutex<HashMap<String, Arc<RunEntry>>>, ttl: u64) {
+    let now = unix_now();
+    let mut runs = runs.lock().unwrap();
+    let expired: Vec<String> = runs
+        .iter()
+        .filter(|(_, entry)| entry.snapshot().finished_at.is_some_and(|finished| finished + ttl < now))
+        .map(|(id, _)| id.clone())
+        .collect();
+    for id in expired {
+        if let Some(entry) = runs.remove(&id) {
+            remove_file(&entry.path);
+        }
+    }
+}
+
+fn agents(state: &AppState) -> ApiResult<&Agents> {
+    state
+        .agents
+        .as_ref()
+        .ok_or_else(|| ApiError::invalid_request("智能体未启用（agents.enabled 为 false）"))
+}
+
+/// `POST /v1/agents/runs`，`stream: true`时以SSE推送各步，`background: true`时立即返回
+pub async fn create_run(
+    State(state): State<Arc<AppState>>,
+    limit_key: Option<Extension<RateLimitKey>>,
+    consumer: Consumer,
+    Json(request): Json<NewRun>,
+) -> ApiResult<Response> {
+    let (stream, background) = (request.stream, request.background);
+    let entry = agents(&state)?.start(&state, consumer, limit_key.map(|Extension(key)| key), request)?;
+    if stream {
+        return Ok(sse::sse_response(entry.events()).into_response());
+    }
+    if !background {
+        // 客户端断开时任务继续在后台运行
+        entry.events().for_each(|_| async {}).await;
+    }
+    Ok(Json(entry.snapshot()).into_response())
+}
+
+/// `GET /v1/agents/runs`
+pub async fn list_runs(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+) -> ApiResult<Json<ListResponse<AgentRun>>> {
+    Ok(Json(ListResponse::new(agents(&state)?.list(&workspace))))
+}
+
+/// `GET /v1/agents/runs/{id}`
+pub async fn get_run(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+) -> ApiResult<Json<AgentRun>> {
+    Ok(Json(agents(&state)?.get(&workspace, &id)?))
+}
+
+/// `GET /v1/agents/runs/{id}/events`，先补发已有的各步，任务结束后以最终的运行记录结束
+pub async fn run_events(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+) -> ApiResult<Response> {
+    let entry = agents(&state)?.entry(&workspace, &id)?;
+    Ok(sse::sse_response(entry.events()).into_response())
+}
+
+/// `POST /v1/agents/runs/{id}/cancel`
+pub async fn cancel_run(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+) -> ApiResult<Json<AgentRun>> {
+    Ok(Json(agents(&state)?.cancel(&workspace, &id).await?))
+}
+
+/// `DELETE /v1/agents/runs/{id}`，运行中的任务需要先取消
+pub async fn delete_run(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+) -> ApiResult<Json<DeletedResponse>> {
+    agents(&state)?.delete(&workspace, &id)?;
+    Ok(Json(DeletedResponse {
+        id,
+        object: "agent.run".to_string(),
+        deleted: true,
+    }))
+}
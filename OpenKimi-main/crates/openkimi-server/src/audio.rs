@@ -0,0 +1,955 @@
+//! 语音转写
+//!
+//! `/v1/audio/transcriptions`接收`multipart/form-data`上传的音频（WAV、MP3、FLAC、OGG、M4A、WebM），
+//! 转发给模型路由到的上游，或交给本地命令（如whisper.cpp）转写，返回带时间戳的分段。
+//! 启用语音检测时先按能量切出语音段，只转写有声音的部分，再把各段的时间戳换算回原音频。
+//! 本地只能解码WAV，其他格式需要配置`convert_command`转换。
+
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use axum::extract::{Request, State};
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use axum::http::HeaderMap;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use bytes::Bytes;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::config::{AudioConfig, TranscriptionBackend, VadConfig};
+use crate::error::{ApiError, ApiResult};
+use crate::metering::{self, Consumer};
+use crate::AppState;
+
+/// 本地处理使用的采样率
+const SAMPLE_RATE: u32 = 16_000;
+
+/// 表单中除音频外其他字段的余量
+const FORM_OVERHEAD: usize = 64 * 1024;
+
+/// 请求体的上限
+pub fn max_request_bytes(config: &AudioConfig) -> usize {
+    config.max_file_bytes + FORM_OVERHEAD
+}
+
+/// 按`Content-Length`拒绝超出`audio.max_file_bytes`的请求，不读取请求体
+pub async fn limit_body(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let limit = max_request_bytes(&state.config().audio);
+    let length = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if let Some(length) = length.filter(|&length| length > limit) {
+        let message = format!(
+            "请求体为 {} 字节，音频不能超过 {} 字节",
+            length, state.config().audio.max_file_bytes
+        );
+        return ApiError::PayloadTooLarge(message).into_response();
+    }
+    next.run(request).await
+}
+
+/// 音频格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AudioFormat {
+    Wav,
+    Mp3,
+    Flac,
+    Ogg,
+    M4a,
+    Webm,
+}
+
+impl AudioFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            AudioFormat::Wav => "wav",
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::Flac => "flac",
+            AudioFormat::Ogg => "ogg",
+            AudioFormat::M4a => "m4a",
+            AudioFormat::Webm => "webm",
+        }
+    }
+
+    fn mime_type(self) -> &'static str {
+        match self {
+            AudioFormat::Wav => "audio/wav",
+            AudioFormat::Mp3 => "audio/mpeg",
+            AudioFormat::Flac => "audio/flac",
+            AudioFormat::Ogg => "audio/ogg",
+            AudioFormat::M4a => "audio/mp4",
+            AudioFormat::Webm => "audio/webm",
+        }
+    }
+}
+
+/// 按文件头识别音频格式
+fn sniff(data: &[u8]) -> Option<AudioFormat> {
+    if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WAVE" {
+        Some(AudioFormat::Wav)
+    } else if data.starts_with(b"ID3") || (data.len() >= 2 && data[0] == 0xFF && data[1] & 0xE0 == 0xE0) {
+        Some(AudioFormat::Mp3)
+    } else if data.starts_with(b"fLaC") {
+        Some(AudioFormat::Flac)
+    } else if data.starts_with(b"OggS") {
+        Some(AudioFormat::Ogg)
+    } else if data.len() >= 8 && &data[4..8] == b"ftyp" {
+        Some(AudioFormat::M4a)
+    } else if data.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        Some(AudioFormat::Webm)
+    } else {
+        None
+    }
+}
+
+/// 表单中的一个字段
+struct Part {
+    name: String,
+    filename: Option<String>,
+    data: Bytes,
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|index| index + from)
+}
+
+/// 从`Content-Type`中取出multipart的分隔符
+fn boundary(content_type: &str) -> Option<String> {
+    let (kind, params) = content_type.split_once(';')?;
+    if !kind.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params.split(';').find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case("boundary")
+            .then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+/// 从字段头部的`Content-Disposition`中取出`name`和`filename`
+fn disposition(headers: &str) -> Option<(String, Option<String>)> {
+    let line = headers.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case("content-disposition").then_some(value)
+    })?;
+    let mut name = None;
+    let mut filename = None;
+    for param in line.split(';').skip(1) {
+        let Some((key, value)) = param.split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"').to_string();
+        match key.trim().to_ascii_lowercase().as_str() {
+            "name" => name = Some(value),
+            "filename" => filename = Some(value),
+            _ => {}
+        }
+    }
+    Some((name?, filename))
+}
+
+fn parse_multipart(body: &Bytes, boundary: &str) -> Result<Vec<Part>, String> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let separator = [b"\r\n".as_slice(), &delimiter].concat();
+    let mut position = find(body, &delimiter, 0).ok_or("找不到 multipart 分隔符")? + delimiter.len();
+    let mut parts = Vec::new();
+    loop {
+        if body[position..].starts_with(b"--") {
+            return Ok(parts);
+        }
+        if !body[position..].starts_with(b"\r\n") {
+            return Err("multipart 分隔符后应为换行".to_string());
+        }
+        position += 2;
+        let header_end = find(body, b"\r\n\r\n", position).ok_or("multipart 字段缺少头部")?;
+        let headers = std::str::from_utf8(&body[position..header_end]).map_err(|_| "multipart 字段头部不是 UTF-8")?;
+        let (name, filename) = disposition(headers).ok_or("multipart 字段缺少 Content-Disposition 中的 name")?;
+        let data_start = header_end + 4;
+        let data_end = find(body, &separator, data_start).ok_or("multipart 字段没有结束")?;
+        parts.push(Part {
+            name,
+            filename,
+            data: body.slice(data_start..data_end),
+        });
+        position = data_end + separator.len();
+    }
+}
+
+/// 构造发给上游的multipart表单
+struct Form {
+    boundary: String,
+    body: Vec<u8>,
+}
+
+impl Form {
+    fn new() -> ApiResult<Form> {
+        let mut bytes = [0u8; 16];
+        getrandom::getrandom(&mut bytes).map_err(|e| ApiError::Internal(format!("生成随机数失败: {}", e)))?;
+        let boundary = bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+        Ok(Form {
+            boundary: format!("openkimi-{}", boundary),
+            body: Vec::new(),
+        })
+    }
+
+    fn text(&mut self, name: &str, value: &str) {
+        self.body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                self.boundary, name, value
+            )
+            .as_bytes(),
+        );
+    }
+
+    fn file(&mut self, name: &str, filename: &str, mime_type: &str, data: &[u8]) {
+        self.body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+                self.boundary,
+                name,
+                filename.replace(['"', '\r', '\n'], "_"),
+                mime_type
+            )
+            .as_bytes(),
+        );
+        self.body.extend_from_slice(data);
+        self.body.extend_from_slice(b"\r\n");
+    }
+
+    /// 返回`Content-Type`和请求体
+    fn finish(mut self) -> (String, Bytes) {
+        self.body.extend_from_slice(format!("--{}--\r\n", self.boundary).as_bytes());
+        (format!("multipart/form-data; boundary={}", self.boundary), Bytes::from(self.body))
+    }
+}
+
+/// 返回格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ResponseFormat {
+    Json,
+    Text,
+    Srt,
+    Vtt,
+    VerboseJson,
+}
+
+impl ResponseFormat {
+    fn parse(value: &str) -> Option<ResponseFormat> {
+        match value {
+            "json" => Some(ResponseFormat::Json),
+            "text" => Some(ResponseFormat::Text),
+            "srt" => Some(ResponseFormat::Srt),
+            "vtt" => Some(ResponseFormat::Vtt),
+            "verbose_json" => Some(ResponseFormat::VerboseJson),
+            _ => None,
+        }
+    }
+}
+
+/// 解析后的转写请求
+struct TranscriptionRequest {
+    file: Bytes,
+    filename: String,
+    model: String,
+    language: Option<String>,
+    prompt: Option<String>,
+    temperature: Option<String>,
+    response_format: ResponseFormat,
+    /// 是否需要逐词时间戳
+    words: bool,
+    /// 请求中的`vad`，缺省时按配置
+    vad: Option<bool>,
+}
+
+fn parse_request(config: &AudioConfig, headers: &HeaderMap, body: &Bytes) -> ApiResult<TranscriptionRequest> {
+    let boundary = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(boundary)
+        .ok_or_else(|| ApiError::invalid_request("请求体应为 multipart/form-data"))?;
+    let parts = parse_multipart(body, &boundary).map_err(ApiError::invalid_request)?;
+
+    let mut file = None;
+    let mut request = TranscriptionRequest {
+        file: Bytes::new(),
+        filename: String::new(),
+        model: config.model.clone(),
+        language: None,
+        prompt: None,
+        temperature: None,
+        response_format: ResponseFormat::Json,
+        words: false,
+        vad: None,
+    };
+    for part in parts {
+        if part.name == "file" {
+            file = Some((part.filename.unwrap_or_else(|| "audio".to_string()), part.data));
+            continue;
+        }
+        let value = String::from_utf8(part.data.to_vec())
+            .map_err(|_| ApiError::invalid_request(format!("字段 {} 不是 UTF-8 文本", part.name)))?;
+        let value = value.trim().to_string();
+        match part.name.as_str() {
+            "model" if !value.is_empty() => request.model = value,
+            "language" if !value.is_empty() => request.language = Some(value),
+            "prompt" if !value.is_empty() => request.prompt = Some(value),
+            "temperature" => {
+                value
+                    .parse::<f32>()
+                    .map_err(|_| ApiError::invalid_request(format!("temperature 应为数字，收到 {}", value)))?;
+                request.temperature = Some(value);
+            }
+            "response_format" => {
+                request.response_format = ResponseFormat::parse(&value).ok_or_else(|| {
+                    ApiError::invalid_request(format!(
+                        "不支持的 response_format {}，应为 json、text、srt、vtt 或 verbose_json",
+                        value
+                    ))
+                })?;
+            }
+            "timestamp_granularities[]" | "timestamp_granularities" => match value.as_str() {
+                "word" => request.words = true,
+                "segment" => {}
+                _ => {
+                    return Err(ApiError::invalid_request(format!(
+                        "不支持的 timestamp_granularities {}，应为 segment 或 word",
+                        value
+                    )))
+                }
+            },
+            "vad" => {
+                request.vad = Some(match value.as_str() {
+                    "true" | "1" => true,
+                    "false" | "0" => false,
+                    _ => return Err(ApiError::invalid_request(format!("vad 应为 true 或 false，收到 {}", value))),
+                })
+            }
+            _ => {}
+        }
+    }
+    let Some((filename, data)) = file else {
+        return Err(ApiError::invalid_request("缺少 file 字段"));
+    };
+    if data.len() > config.max_file_bytes {
+        return Err(ApiError::PayloadTooLarge(format!(
+            "音频为 {} 字节，超过 {} 字节的上限",
+            data.len(),
+            config.max_file_bytes
+        )));
+    }
+    request.filename = filename;
+    request.file = data;
+    Ok(request)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+/// 解码PCM或浮点WAV，混为单声道并重采样到16kHz
+fn decode_wav(data: &[u8]) -> Result<Vec<f32>, String> {
+    if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Err("不是 WAV 文件".to_string());
+    }
+    let mut format = None;
+    let mut samples = None;
+    let mut position = 12;
+    while position + 8 <= data.len() {
+        let size = read_u32(data, position + 4) as usize;
+        let start = position + 8;
+        // 流式写出的WAV可能没有回填长度
+        let end = start.saturating_add(size).min(data.len());
+        match &data[position..position + 4] {
+            b"fmt " if end - start >= 16 => {
+                let mut tag = read_u16(data, start);
+                // WAVE_FORMAT_EXTENSIBLE的实际编码在子格式GUID的前两个字节
+                if tag == 0xFFFE && end - start >= 26 {
+                    tag = read_u16(data, start + 24);
+                }
+                let channels = read_u16(data, start + 2);
+                let rate = read_u32(data, start + 4);
+                let bits = read_u16(data, start + 14);
+                format = Some((tag, channels, rate, bits));
+            }
+            b"data" => samples = Some(&data[start..end]),
+            _ => {}
+        }
+        position = end.saturating_add(size & 1);
+    }
+    let (tag, channels, rate, bits) = format.ok_or("WAV 缺少 fmt 块")?;
+    let samples = samples.ok_or("WAV 缺少 data 块")?;
+    if channels == 0 || rate == 0 {
+        return Err("WAV 的声道数或采样率无效".to_string());
+    }
+    let decode: fn(&[u8]) -> f32 = match (tag, bits) {
+        (1, 8) => |b| (f32::from(b[0]) - 128.0) / 128.0,
+        (1, 16) => |b| f32::from(i16::from_le_bytes([b[0], b[1]])) / 32768.0,
+        (1, 24) => |b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8_388_608.0,
+        (1, 32) => |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0,
+        (3, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        (3, 64) => |b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f32,
+        _ => return Err(format!("不支持的 WAV 编码（格式 {}，{} 位）", tag, bits)),
+    };
+    let width = usize::from(bits / 8);
+    let frame = width * usize::from(channels);
+    let mono: Vec<f32> = samples
+        .chunks_exact(frame)
+        .map(|frame| frame.chunks_exact(width).map(decode).sum::<f32>() / f32::from(channels))
+        .collect();
+    Ok(resample(&mono, rate))
+}
+
+/// 重采样到16kHz，降采样时取区间平均以减少混叠
+fn resample(samples: &[f32], rate: u32) -> Vec<f32> {
+    if rate == SAMPLE_RATE || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let length = (samples.len() as u64 * u64::from(SAMPLE_RATE) / u64::from(rate)) as usize;
+    let step = f64::from(rate) / f64::from(SAMPLE_RATE);
+    (0..length)
+        .map(|i| {
+            let position = i as f64 * step;
+            let index = (position as usize).min(samples.len() - 1);
+            if step > 1.0 {
+                let end = (((i + 1) as f64 * step) as usize).clamp(index + 1, samples.len());
+                samples[index..end].iter().sum::<f32>() / (end - index) as f32
+            } else {
+                let fraction = (position - index as f64) as f32;
+                let next = samples.get(index + 1).copied().unwrap_or(samples[index]);
+                samples[index] + (next - samples[index]) * fraction
+            }
+        })
+        .collect()
+}
+
+/// 编码为16kHz单声道16位WAV
+fn encode_wav(samples: &[f32]) -> Vec<u8> {
+    let size = (samples.len() * 2) as u32;
+    let mut data = Vec::with_capacity(44 + samples.len() * 2);
+    data.extend_from_slice(b"RIFF");
+    data.extend_from_slice(&(36 + size).to_le_bytes());
+    data.extend_from_slice(b"WAVEfmt ");
+    data.extend_from_slice(&16u32.to_le_bytes());
+    data.extend_from_slice(&1u16.to_le_bytes());
+    data.extend_from_slice(&1u16.to_le_bytes());
+    data.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    data.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    data.extend_from_slice(&2u16.to_le_bytes());
+    data.extend_from_slice(&16u16.to_le_bytes());
+    data.extend_from_slice(b"data");
+    data.extend_from_slice(&size.to_le_bytes());
+    for sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * 32767.0).round() as i16;
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    data
+}
+
+/// 按能量找出语音段，返回采样位置的区间
+fn detect_speech(samples: &[f32], config: &VadConfig) -> Vec<(usize, usize)> {
+    let frame_ms = config.frame_ms.max(1);
+    let frame = (SAMPLE_RATE * frame_ms / 1000).max(1) as usize;
+    let energies: Vec<f32> = samples
+        .chunks(frame)
+        .map(|chunk| {
+            let power = chunk.iter().map(|sample| sample * sample).sum::<f32>() / chunk.len() as f32;
+            10.0 * (power + 1e-10).log10()
+        })
+        .collect();
+    if energies.is_empty() {
+        return Vec::new();
+    }
+    let mut sorted = energies.clone();
+    sorted.sort_by(f32::total_cmp);
+    let floor = sorted[sorted.len() / 10];
+    let threshold = config.threshold_db.max(floor + config.margin_db);
+    let frames = |ms: u32| (ms as usize).div_ceil(frame_ms as usize);
+
+    // 连续的语音帧，间隔短于min_silence_ms的合并
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for (i, &energy) in energies.iter().enumerate() {
+        if energy < threshold {
+            continue;
+        }
+        match runs.last_mut() {
+            Some(run) if i - run.1 < frames(config.min_silence_ms) => run.1 = i + 1,
+            _ => runs.push((i, i + 1)),
+        }
+    }
+    runs.retain(|(start, end)| end - start >= frames(config.min_speech_ms));
+
+    // 前后留出余量，重叠的合并
+    let padding = frames(config.padding_ms);
+    let mut segments: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in runs {
+        let (start, end) = (start.saturating_sub(padding), (end + padding).min(energies.len()));
+        match segments.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => segments.push((start, end)),
+        }
+    }
+
+    // 过长的段在最后五分之一里最安静的帧处切开
+    let longest = frames(config.max_segment_seconds.saturating_mul(1000)).max(1);
+    let mut result = Vec::new();
+    for (mut start, end) in segments {
+        while end - start > longest {
+            let cut = (start + longest - longest / 5..start + longest)
+                .min_by(|&a, &b| energies[a].total_cmp(&energies[b]))
+                .unwrap_or(start + longest);
+            result.push((start, cut));
+            start = cut;
+        }
+        result.push((start, end));
+    }
+    result
+        .into_iter()
+        .map(|(start, end)| (start * frame, (end * frame).min(samples.len())))
+        .collect()
+}
+
+/// 转写出的一段
+#[derive(Debug, Clone, Serialize)]
+struct Segment {
+    id: usize,
+    start: f64,
+    end: f64,
+    text: String,
+}
+
+/// 逐词时间戳
+#[derive(Debug, Clone, Serialize)]
+struct Word {
+    word: String,
+    start: f64,
+    end: f64,
+}
+
+/// 一次转写的结果，时间以秒计
+#[derive(Debug, Default)]
+struct Transcript {
+    text: String,
+    language: Option<String>,
+    duration: Option<f64>,
+    segments: Vec<Segment>,
+    words: Vec<Word>,
+}
+
+impl Transcript {
+    /// 没有分段时间戳时把整段文本作为一段
+    fn fill_segments(&mut self, length: f64) {
+        if self.segments.is_empty() && !self.text.trim().is_empty() {
+            self.segments.push(Segment {
+                id: 0,
+                start: 0.0,
+                end: self.duration.unwrap_or(length),
+                text: self.text.clone(),
+            });
+        }
+    }
+}
+
+/// 解析上游`verbose_json`格式的响应，上游只返回文本时分段为空
+fn parse_upstream(value: &Value) -> Transcript {
+    let number = |value: &Value, key: &str| value[key].as_f64().unwrap_or_default();
+    let segments = value["segments"].as_array().into_iter().flatten();
+    let words = value["words"].as_array().into_iter().flatten();
+    Transcript {
+        text: value["text"].as_str().unwrap_or_default().to_string(),
+        language: value["language"].as_str().map(str::to_string),
+        duration: value["duration"].as_f64(),
+        segments: segments
+            .map(|segment| Segment {
+                id: 0,
+                start: number(segment, "start"),
+                end: number(segment, "end"),
+                text: segment["text"].as_str().unwrap_or_default().to_string(),
+            })
+            .collect(),
+        words: words
+            .map(|word| Word {
+                word: word["word"].as_str().unwrap_or_default().to_string(),
+                start: number(word, "start"),
+                end: number(word, "end"),
+            })
+            .collect(),
+    }
+}
+
+/// 解析whisper.cpp的`-oj`输出，时间以毫秒计
+fn parse_whisper(value: &Value) -> Transcript {
+    let segments: Vec<Segment> = value["transcription"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|segment| Segment {
+            id: 0,
+            start: segment["offsets"]["from"].as_f64().unwrap_or_default() / 1000.0,
+            end: segment["offsets"]["to"].as_f64().unwrap_or_default() / 1000.0,
+            text: segment["text"].as_str().unwrap_or_default().to_string(),
+        })
+        .collect();
+    Transcript {
+        text: segments.iter().map(|segment| segment.text.as_str()).collect::<String>().trim().to_string(),
+        language: value["result"]["language"].as_str().map(str::to_string),
+        duration: None,
+        segments,
+        words: Vec::new(),
+    }
+}
+
+async fn transcribe_upstream(
+    state: &AppState,
+    request: &TranscriptionRequest,
+    filename: &str,
+    mime_type: &str,
+    data: &[u8],
+) -> ApiResult<Transcript> {
+    let models = state.models();
+    let route = models.route(&request.model);
+    let mut form = Form::new()?;
+    form.file("file", filename, mime_type, data);
+    form.text("model", route.model);
+    for (name, value) in [
+        ("language", &request.language),
+        ("prompt", &request.prompt),
+        ("temperature", &request.temperature),
+    ] {
+        if let Some(value) = value {
+            form.text(name, value);
+        }
+    }
+    form.text("response_format", "verbose_json");
+    form.text("timestamp_granularities[]", "segment");
+    if request.words {
+        form.text("timestamp_granularities[]", "word");
+    }
+    let (content_type, body) = form.finish();
+    let response = route.backend.upstream.transcription(&content_type, body).await?;
+    Ok(parse_upstream(&response))
+}
+
+/// 临时目录，丢弃时删除
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new() -> Result<TempDir, String> {
+        let mut bytes = [0u8; 8];
+        getrandom::getrandom(&mut bytes).map_err(|e| format!("生成随机数失败: {}", e))?;
+        let name = bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+        let path = env::temp_dir().join(format!("openkimi-audio-{}", name));
+        fs::create_dir(&path).map_err(|e| format!("无法创建临时目录 {}: {}", path.display(), e))?;
+        Ok(TempDir(path))
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// 在后台线程读完管道，避免输出填满管道缓冲区后子进程阻塞
+pub(crate) fn drain(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<String> {
+    std::thread::spawn(move || {
+        let mut output = String::new();
+        if let Some(mut pipe) = pipe {
+            let mut data = Vec::new();
+            let _ = pipe.read_to_end(&mut data);
+            output = String::from_utf8_lossy(&data).into_owned();
+        }
+        output
+    })
+}
+
+/// 运行命令，成功时返回标准输出
+fn run_command(args: &[String], timeout_seconds: u64) -> Result<String, String> {
+    let mut child = Command::new(&args[0])
+        .args(&args[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("无法运行命令 {}: {}", args[0], e))?;
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+    let deadline = Instant::now() + Duration::from_secs(timeout_seconds);
+    let status = loop {
+        match child.try_wait().map_err(|e| format!("等待命令 {} 失败: {}", args[0], e))? {
+            Some(status) => break status,
+            None if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("命令 {} 超过 {} 秒", args[0], timeout_seconds));
+            }
+            None => std::thread::sleep(Duration::from_millis(50)),
+        }
+    };
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    if !status.success() {
+        // whisper.cpp等工具的日志很长，只保留末尾
+        let tail: String = stderr.trim().chars().rev().take(500).collect::<Vec<_>>().into_iter().rev().collect();
+        return Err(format!("命令 {} 异常退出（{}）: {}", args[0], status, tail));
+    }
+    Ok(stdout)
+}
+
+/// 用`convert_command`把其他格式转为WAV
+fn convert(config: &AudioConfig, format: AudioFormat, data: &[u8]) -> Result<Vec<u8>, String> {
+    let dir = TempDir::new()?;
+    let input = dir.path().join(format!("input.{}", format.extension()));
+    let output = dir.path().join("converted.wav");
+    fs::write(&input, data).map_err(|e| format!("写入临时文件失败: {}", e))?;
+    let (input, output_arg) = (input.to_string_lossy(), output.to_string_lossy());
+    let args: Vec<String> = config
+        .convert_command
+        .iter()
+        .map(|arg| arg.replace("{input}", &input).replace("{output}", &output_arg))
+        .collect();
+    run_command(&args, config.timeout_seconds)?;
+    fs::read(&output).map_err(|e| format!("转换命令没有生成 {}: {}", output.display(), e))
+}
+
+/// 解码为16kHz单声道采样
+fn load_samples(config: &AudioConfig, format: AudioFormat, data: &[u8]) -> ApiResult<Vec<f32>> {
+    if format == AudioFormat::Wav {
+        return decode_wav(data).map_err(ApiError::invalid_request);
+    }
+    if config.convert_command.is_empty() {
+        return Err(ApiError::invalid_request(format!(
+            "本地转写和语音检测只支持 WAV，{} 格式需要配置 audio.convert_command",
+            format.extension()
+        )));
+    }
+    let wav = convert(config, format, data).map_err(|e| ApiError::Internal(format!("转换音频失败: {}", e)))?;
+    decode_wav(&wav).map_err(|e| ApiError::Internal(format!("转换后的音频无法解码: {}", e)))
+}
+
+/// 用本地命令转写一段16kHz单声道采样
+fn transcribe_command(config: &AudioConfig, samples: &[f32], language: Option<&str>) -> Result<Transcript, String> {
+    let dir = TempDir::new()?;
+    let input = dir.path().join("input.wav");
+    let output = dir.path().join("output");
+    fs::write(&input, encode_wav(samples)).map_err(|e| format!("写入临时文件失败: {}", e))?;
+    let (input, output_arg) = (input.to_string_lossy(), output.to_string_lossy());
+    let args: Vec<String> = config
+        .command
+        .iter()
+        .map(|arg| {
+            arg.replace("{input}", &input)
+                .replace("{output}", &output_arg)
+                .replace("{language}", language.unwrap_or("auto"))
+        })
+        .collect();
+    let stdout = run_command(&args, config.timeout_seconds)?;
+    match fs::read(output.with_extension("json")) {
+        Ok(data) => {
+            let value: Value = serde_json::from_slice(&data).map_err(|e| format!("无法解析转写结果: {}", e))?;
+            Ok(parse_whisper(&value))
+        }
+        Err(_) => Ok(Transcript {
+            text: stdout.trim().to_string(),
+            ..Transcript::default()
+        }),
+    }
+}
+
+/// 保留到毫秒，避免浮点误差出现在返回的时间戳中
+fn millis(seconds: f64) -> f64 {
+    (seconds * 1000.0).round() / 1000.0
+}
+
+/// 按语音段转写本地解码的音频，时间戳换算回原音频
+async fn transcribe_local(
+    state: &AppState,
+    request: &TranscriptionRequest,
+    format: AudioFormat,
+    vad: bool,
+) -> ApiResult<Transcript> {
+    let config = state.config().audio.clone();
+    let data = request.file.clone();
+    // 解码、转换和语音检测都比较耗时，不占用异步运行时的线程
+    let (samples, ranges) = tokio::task::spawn_blocking(move || {
+        let samples = load_samples(&config, format, &data)?;
+        let ranges = if vad {
+            detect_speech(&samples, &config.vad)
+        } else {
+            vec![(0, samples.len())]
+        };
+        Ok::<_, ApiError>((Arc::new(samples), ranges))
+    })
+    .await
+    .map_err(|e| ApiError::Internal(format!("处理音频失败: {}", e)))??;
+
+    let duration = samples.len() as f64 / f64::from(SAMPLE_RATE);
+    let mut merged = Transcript {
+        duration: Some(duration),
+        ..Transcript::default()
+    };
+    let mut texts = Vec::new();
+    for (index, &(start, end)) in ranges.iter().enumerate() {
+        let offset = start as f64 / f64::from(SAMPLE_RATE);
+        let length = (end - start) as f64 / f64::from(SAMPLE_RATE);
+        let mut part = match state.config().audio.backend {
+            TranscriptionBackend::Command => {
+                let (config, samples) = (state.config().audio.clone(), Arc::clone(&samples));
+                let language = request.language.clone();
+                tokio::task::spawn_blocking(move || {
+                    transcribe_command(&config, &samples[start..end], language.as_deref())
+                })
+                .await
+                .map_err(|e| ApiError::Internal(format!("转写失败: {}", e)))?
+                .map_err(|e| ApiError::Internal(format!("转写失败: {}", e)))?
+            }
+            TranscriptionBackend::Upstream => {
+                let wav = encode_wav(&samples[start..end]);
+                let filename = format!("segment-{}.wav", index);
+                transcribe_upstream(state, request, &filename, AudioFormat::Wav.mime_type(), &wav).await?
+            }
+        };
+        part.duration = None;
+        part.fill_segments(length);
+        if merged.language.is_none() {
+            merged.language = part.language;
+        }
+        let text = part.text.trim();
+        if !text.is_empty() {
+            texts.push(text.to_string());
+        }
+        merged.segments.extend(part.segments.into_iter().map(|segment| Segment {
+            start: millis(segment.start + offset),
+            end: millis(segment.end + offset),
+            ..segment
+        }));
+        merged.words.extend(part.words.into_iter().map(|word| Word {
+            start: millis(word.start + offset),
+            end: millis(word.end + offset),
+            ..word
+        }));
+    }
+    merged.text = texts.join(" ");
+    Ok(merged)
+}
+
+/// 把秒数格式化为`HH:MM:SS,mmm`（SRT）或`HH:MM:SS.mmm`（WebVTT）
+fn timestamp(seconds: f64, separator: char) -> String {
+    let millis = (seconds.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        separator,
+        millis % 1000
+    )
+}
+
+fn respond(format: ResponseFormat, transcript: Transcript, words: bool) -> Response {
+    let text = |body: String| ([(CONTENT_TYPE, "text/plain; charset=utf-8")], body).into_response();
+    match format {
+        ResponseFormat::Json => Json(json!({ "text": transcript.text })).into_response(),
+        ResponseFormat::Text => text(transcript.text),
+        ResponseFormat::Srt => text(
+            transcript
+                .segments
+                .iter()
+                .map(|segment| {
+                    format!(
+                        "{}\n{} --> {}\n{}\n\n",
+                        segment.id + 1,
+                        timestamp(segment.start, ','),
+                        timestamp(segment.end, ','),
+                        segment.text.trim()
+                    )
+                })
+                .collect(),
+        ),
+        ResponseFormat::Vtt => {
+            let mut body = "WEBVTT\n\n".to_string();
+            for segment in &transcript.segments {
+                body.push_str(&format!(
+                    "{} --> {}\n{}\n\n",
+                    timestamp(segment.start, '.'),
+                    timestamp(segment.end, '.'),
+                    segment.text.trim()
+                ));
+            }
+            text(body)
+        }
+        ResponseFormat::VerboseJson => {
+            let mut body = json!({
+                "task": "transcribe",
+                "language": transcript.language,
+                "duration": transcript.duration,
+                "text": transcript.text,
+                "segments": transcript.segments,
+            });
+            if words {
+                body["words"] = json!(transcript.words);
+            }
+            Json(body).into_response()
+        }
+    }
+}
+
+/// `POST /v1/audio/transcriptions`
+pub async fn transcriptions(
+    State(state): State<Arc<AppState>>,
+    consumer: Consumer,
+    headers: HeaderMap,
+    body: Bytes,
+) -> ApiResult<Response> {
+    let config = &state.config().audio;
+    if !config.enabled {
+        return Err(ApiError::invalid_request("语音转写未启用（audio.enabled 为 false）"));
+    }
+    let request = parse_request(config, &headers, &body)?;
+    let format = sniff(&request.file).ok_or_else(|| {
+        ApiError::invalid_request(format!(
+            "无法识别 {} 的音频格式，支持 WAV、MP3、FLAC、OGG、M4A 和 WebM",
+            request.filename
+        ))
+    })?;
+    let vad = request.vad.unwrap_or(config.vad.enabled);
+
+    let mut transcript = if vad || config.backend == TranscriptionBackend::Command {
+        transcribe_local(&state, &request, format, vad).await?
+    } else {
+        let mut transcript =
+            transcribe_upstream(&state, &request, &request.filename, format.mime_type(), &request.file).await?;
+        let length = transcript.segments.last().map(|segment| segment.end).unwrap_or_default();
+        transcript.fill_segments(length);
+        transcript.duration = transcript.duration.or(Some(length));
+        transcript
+    };
+    for (id, segment) in transcript.segments.iter_mut().enumerate() {
+        segment.id = id;
+    }
+    metering::record(&state, &consumer, &request.model, 0, 0);
+    Ok(respond(request.response_format, transcript, request.words))
+}
@@ -0,0 +1,689 @@
+//! 请求审计日志
+//!
+//! 记录`/v1`下每个请求的时间、租户、调用方（启用认证时）、客户端、模型、状态、耗时、用量以及脱敏后的请求和响应内容，
+//! 写入按天分割的JSON Lines文件、PostgreSQL的`audit_log`表，或以OTLP/HTTP发送到日志收集器。
+//! 流式响应在结束后记录拼接好的回复文本。
+//! 记录由后台任务写出，不阻塞请求；队列满时丢弃并打印警告。
+
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use axum::body::{Body, BodyDataStream, Bytes};
+use axum::extract::{Request, State};
+use axum::http::header::{CONTENT_TYPE, UPGRADE};
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use futures_util::{Stream, StreamExt};
+use openkimi_postgres::{PgError, Pool};
+use openkimi_schema::postgres::Migration;
+use openkimi_sessions::time::{civil_from_days, days_from_civil};
+use regex::Regex;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+use crate::auth::Principal;
+use crate::config::{AuditConfig, AuditFormat, RedactConfig};
+use crate::error::ApiError;
+use crate::migrations;
+use crate::pii::PiiDetector;
+use crate::ratelimit;
+use crate::telemetry;
+use crate::workspace::Workspace;
+use crate::AppState;
+
+/// 等待写出的记录数上限
+const QUEUE_SIZE: usize = 4096;
+
+/// OTLP每批最多发送的记录数
+const OTLP_BATCH: usize = 100;
+
+/// OTLP攒批的最长等待时间
+const OTLP_INTERVAL: Duration = Duration::from_secs(2);
+
+/// 每个事务最多写入PostgreSQL的记录数
+const POSTGRES_BATCH: usize = 100;
+
+/// PostgreSQL中各版本的迁移，下标加一即版本号；`record`为完整的记录，常用字段另存为列便于检索
+pub(crate) const PG_MIGRATIONS: &[Migration] = &[
+    // v1是初始结构，回退会删除全部审计记录，不提供
+    Migration::sql(
+        "审计记录",
+        "
+        CREATE TABLE audit_log (
+            id BIGSERIAL PRIMARY KEY,
+            time TIMESTAMPTZ NOT NULL,
+            tenant TEXT,
+            request_id TEXT,
+            model TEXT,
+            status INTEGER,
+            record JSONB NOT NULL
+        );
+        CREATE INDEX audit_log_time ON audit_log(time);
+        CREATE INDEX audit_log_tenant_time ON audit_log(tenant, time);
+        ",
+        None,
+    ),
+];
+
+/// 读取请求体的上限，与axum `Json`提取器的默认上限相同
+const MAX_REQUEST_BYTES: usize = 2 * 1024 * 1024;
+
+pub(crate) const EMAIL_PATTERN: &str = r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}";
+
+/// `sk-`等前缀的API密钥、Bearer令牌和AWS访问密钥
+pub(crate) const API_KEY_PATTERN: &str = concat!(
+    r"\b(?:sk|pk|rk)-[A-Za-z0-9_-]{16,}",
+    r"|\bBearer\s+[A-Za-z0-9._~+/=-]{16,}",
+    r"|\bAKIA[0-9A-Z]{16}\b"
+);
+
+/// 按顺序应用的脱敏规则
+#[derive(Debug)]
+pub struct Redactor {
+    rules: Vec<(Regex, String)>,
+    /// 启用`pii.logs`时在最后按个人信息规则脱敏
+    pii: Option<Arc<PiiDetector>>,
+}
+
+impl Redactor {
+    pub fn new(config: &RedactConfig) -> Result<Redactor, String> {
+        let mut rules = Vec::new();
+        if config.api_keys {
+            rules.push((API_KEY_PATTERN, "[API_KEY]"));
+        }
+        if config.emails {
+            rules.push((EMAIL_PATTERN, "[EMAIL]"));
+        }
+        let custom = config
+            .patterns
+            .iter()
+            .map(|rule| (rule.pattern.as_str(), rule.replacement.as_str()));
+        let rules = rules
+            .into_iter()
+            .chain(custom)
+            .map(|(pattern, replacement)| {
+                Regex::new(pattern)
+                    .map(|regex| (regex, replacement.to_string()))
+                    .map_err(|e| format!("无效的脱敏规则 {}: {}", pattern, e))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Redactor { rules, pii: None })
+    }
+
+    pub fn with_pii(mut self, pii: Option<Arc<PiiDetector>>) -> Redactor {
+        self.pii = pii;
+        self
+    }
+
+    pub fn redact(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for (regex, replacement) in &self.rules {
+            if let std::borrow::Cow::Owned(replaced) = regex.replace_all(&text, replacement.as_str()) {
+                text = replaced;
+            }
+        }
+        match &self.pii {
+            Some(pii) => pii.redact(&text),
+            None => text,
+        }
+    }
+
+    /// 对JSON中的所有字符串脱敏
+    pub fn redact_value(&self, value: &mut Value) {
+        match value {
+            Value::String(text) => *text = self.redact(text),
+            Value::Array(items) => items.iter_mut().for_each(|item| self.redact_value(item)),
+            Value::Object(map) => map.values_mut().for_each(|item| self.redact_value(item)),
+            _ => {}
+        }
+    }
+}
+
+/// 一条待写出的记录
+#[derive(Debug)]
+pub(crate) struct Entry {
+    pub(crate) time_ms: u64,
+    pub(crate) record: Value,
+}
+
+/// 已放入队列但还没写出的条数，后台任务每处理完一条减一；退出前据此等待队列写完
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Pending(Arc<AtomicUsize>);
+
+impl Pending {
+    /// 放入队列，队列已满时返回`false`
+    pub(crate) fn send<T>(&self, tx: &mpsc::Sender<T>, item: T) -> bool {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        let sent = tx.try_send(item).is_ok();
+        if !sent {
+            self.done(1);
+        }
+        sent
+    }
+
+    pub(crate) fn done(&self, count: usize) {
+        self.0.fetch_sub(count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// 等待全部写出，超过`timeout`仍未写完时返回`false`
+    pub(crate) async fn flush(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while self.len() > 0 {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        true
+    }
+}
+
+#[derive(Debug)]
+pub struct AuditLog {
+    config: AuditConfig,
+    redactor: Redactor,
+    tx: mpsc::Sender<Entry>,
+    pending: Pending,
+}
+
+impl AuditLog {
+    /// 启动后台写出任务，需要在tokio运行时中调用
+    pub fn new(
+        config: &AuditConfig,
+        pii: Option<Arc<PiiDetector>>,
+        postgres: Option<&Pool>,
+    ) -> Result<AuditLog, String> {
+        let redactor = Redactor::new(&config.redact)?.with_pii(pii);
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        let pending = Pending::default();
+        let written = pending.clone();
+        match config.format {
+            AuditFormat::Jsonl => {
+                fs::create_dir_all(&config.dir)
+                    .map_err(|e| format!("创建审计日志目录 {} 失败: {}", config.dir.display(), e))?;
+                let dir = config.dir.clone();
+                let retention_days = config.retention_days;
+                std::thread::spawn(move || write_jsonl(dir, "audit", retention_days, rx, written));
+            }
+            AuditFormat::Otlp => {
+                let http = reqwest::Client::builder()
+                    .user_agent(concat!("openkimi-server/", env!("CARGO_PKG_VERSION")))
+                    .build()
+                    .map_err(|e| format!("创建HTTP客户端失败: {}", e))?;
+                tokio::spawn(export_otlp(http, config.clone(), rx, written));
+            }
+            AuditFormat::Postgres => {
+                let pool = postgres.ok_or("audit.format 为 postgres 时需要配置 postgres 部分")?.clone();
+                migrations::upgrade_postgres(&pool, "audit", PG_MIGRATIONS)
+                    .map_err(|e| format!("打开审计日志数据库失败: {}", e))?;
+                let retention_days = config.retention_days;
+                std::thread::spawn(move || write_postgres(pool, retention_days, rx, written));
+            }
+        }
+        Ok(AuditLog {
+            config: config.clone(),
+            redactor,
+            tx,
+            pending,
+        })
+    }
+
+    /// 脱敏后的请求或响应体，不记录内容或超出大小时为`None`
+    fn body(&self, bytes: &[u8], parsed: Option<&Value>) -> Option<Value> {
+        if !self.config.include_bodies || bytes.is_empty() || bytes.len() > self.config.max_body_bytes {
+            return None;
+        }
+        let mut body = parsed
+            .cloned()
+            .unwrap_or_else(|| Value::String(String::from_utf8_lossy(bytes).into_owned()));
+        self.redactor.redact_value(&mut body);
+        Some(body)
+    }
+
+    fn finish(&self, time_ms: u64, started: Instant, mut record: Value) {
+        record["duration_ms"] = json!(started.elapsed().as_millis() as u64);
+        if !self.pending.send(&self.tx, Entry { time_ms, record }) {
+            eprintln!("⚠️ 审计日志队列已满，丢弃一条记录");
+        }
+    }
+
+    /// 等待写出的记录数
+    pub fn queue_depth(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// 等待队列中的记录写出，超过`timeout`时返回`false`
+    pub async fn flush(&self, timeout: Duration) -> bool {
+        self.pending.flush(timeout).await
+    }
+}
+
+pub(crate) fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// 自1970-01-01起的天数对应的`YYYY-MM-DD`
+pub fn date(days: i64) -> String {
+    let (year, month, day) = civil_from_days(days);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// 当天的UTC日期，`YYYY-MM-DD`
+pub fn today() -> String {
+    date((unix_ms() / 86_400_000) as i64)
+}
+
+/// UTC的RFC 3339时间，精确到毫秒
+pub(crate) fn rfc3339(time_ms: u64) -> String {
+    let seconds = time_ms / 1000;
+    let of_day = seconds % 86_400;
+    format!(
+        "{}T{:02}:{:02}:{:02}.{:03}Z",
+        date((seconds / 86_400) as i64),
+        of_day / 3600,
+        of_day / 60 % 60,
+        of_day % 60,
+        time_ms % 1000
+    )
+}
+
+fn request_id() -> String {
+    let mut bytes = [0u8; 12];
+    let _ = getrandom::getrandom(&mut bytes);
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("req_{}", hex)
+}
+
+/// 删除超过保留天数的日志文件
+fn remove_expired(dir: &Path, prefix: &str, retention_days: u32, today: i64) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(day) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(prefix))
+            .and_then(|name| name.strip_prefix('-'))
+            .and_then(|name| name.strip_suffix(".jsonl"))
+            .and_then(|date| {
+                let mut parts = date.split('-').map(str::parse::<i64>);
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(Ok(year)), Some(Ok(month)), Some(Ok(day))) => {
+                        Some(days_from_civil(year, month as u32, day as u32))
+                    }
+                    _ => None,
+                }
+            })
+        else {
+            continue;
+        };
+        if today - day >= retention_days as i64 {
+            if let Err(err) = fs::remove_file(entry.path()) {
+                eprintln!("⚠️ 删除过期日志 {} 失败: {}", entry.path().display(), err);
+            }
+        }
+    }
+}
+
+/// 在独立线程中按天写入`<prefix>-YYYY-MM-DD.jsonl`，换天时清理过期文件
+pub(crate) fn write_jsonl(
+    dir: PathBuf,
+    prefix: &'static str,
+    retention_days: u32,
+    mut rx: mpsc::Receiver<Entry>,
+    pending: Pending,
+) {
+    let mut current: Option<(i64, File)> = None;
+    while let Some(entry) = rx.blocking_recv() {
+        let today = (entry.time_ms / 1000 / 86_400) as i64;
+        if current.as_ref().is_none_or(|(day, _)| *day != today) {
+            if retention_days > 0 {
+                remove_expired(&dir, prefix, retention_days, today);
+            }
+            let path = dir.join(format!("{}-{}.jsonl", prefix, date(today)));
+            match OpenOptions::new().create(true).append(true).open(&path) {
+                Ok(file) => current = Some((today, file)),
+                Err(err) => {
+                    eprintln!("⚠️ 打开日志 {} 失败: {}", path.display(), err);
+                    pending.done(1);
+                    continue;
+                }
+            }
+        }
+        if let Some((_, file)) = &mut current {
+            if let Err(err) = writeln!(file, "{}", entry.record) {
+                eprintln!("⚠️ 写入日志失败: {}", err);
+            }
+        }
+        pending.done(1);
+    }
+}
+
+fn insert_postgres(pool: &Pool, entries: &[Entry]) -> Result<(), PgError> {
+    let mut client = pool.get()?;
+    let mut tx = client.transaction()?;
+    let statement = tx.prepare(
+        "INSERT INTO audit_log (time, tenant, request_id, model, status, record)
+         SELECT to_timestamp($1::BIGINT / 1000.0), r ->> 'tenant', r ->> 'request_id', r ->> 'model',
+             (r ->> 'status')::INTEGER, r
+         FROM (SELECT $2::TEXT::JSONB AS r) record",
+    )?;
+    for entry in entries {
+        tx.execute(&statement, &[&(entry.time_ms as i64), &entry.record.to_string()])?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// 在独立线程中攒批写入`audit_log`表，失败时丢弃该批并打印警告；换天时删除过期记录
+fn write_postgres(pool: Pool, retention_days: u32, mut rx: mpsc::Receiver<Entry>, pending: Pending) {
+    let mut cleaned: Option<i64> = None;
+    while let Some(first) = rx.blocking_recv() {
+        let mut batch = vec![first];
+        while batch.len() < POSTGRES_BATCH {
+            match rx.try_recv() {
+                Ok(entry) => batch.push(entry),
+                Err(_) => break,
+            }
+        }
+        let today = (batch[0].time_ms / 1000 / 86_400) as i64;
+        if retention_days > 0 && cleaned != Some(today) {
+            let removed = pool.get().and_then(|mut client| {
+                let sql = "DELETE FROM audit_log WHERE time < now() - make_interval(days => $1)";
+                Ok(client.execute(sql, &[&(retention_days as i32)])?)
+            });
+            match removed {
+                Ok(_) => cleaned = Some(today),
+                Err(err) => eprintln!("⚠️ 删除过期审计日志失败: {}", err),
+            }
+        }
+        if let Err(err) = insert_postgres(&pool, &batch) {
+            eprintln!("⚠️ 写入审计日志失败: {}", err);
+        }
+        pending.done(batch.len());
+    }
+}
+
+/// OTLP的属性，整数、浮点数和布尔值按对应类型编码，其余转为字符串
+pub(crate) fn attribute(key: &str, value: Value) -> Value {
+    let value = match value {
+        Value::Number(number) if number.is_f64() => json!({ "doubleValue": number }),
+        Value::Number(number) => json!({ "intValue": number.to_string() }),
+        Value::Bool(flag) => json!({ "boolValue": flag }),
+        Value::String(text) => json!({ "stringValue": text }),
+        other => json!({ "stringValue": other.to_string() }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+/// OTLP日志请求体，每条记录的JSON作为日志正文，常用字段另作为属性便于检索
+fn otlp_payload(entries: &[Entry]) -> Value {
+    let records: Vec<Value> = entries
+        .iter()
+        .map(|entry| {
+            let attributes: Vec<Value> = [
+                ("openkimi.tenant", "tenant"),
+                ("enduser.id", "user"),
+                ("openkimi.request_id", "request_id"),
+                ("http.request.method", "method"),
+                ("url.path", "path"),
+                ("http.response.status_code", "status"),
+                ("gen_ai.request.model", "model"),
+            ]
+            .into_iter()
+            .filter_map(|(key, field)| {
+                entry
+                    .record
+                    .get(field)
+                    .filter(|value| !value.is_null())
+                    .map(|value| attribute(key, value.clone()))
+            })
+            .collect();
+            let mut record = json!({
+                "timeUnixNano": (entry.time_ms as u128 * 1_000_000).to_string(),
+                "severityNumber": 9,
+                "severityText": "INFO",
+                "body": { "stringValue": entry.record.to_string() },
+                "attributes": attributes,
+            });
+            // 与追踪关联，可以从日志跳转到对应的链路
+            if let Some(trace_id) = entry.record.get("trace_id").filter(|trace_id| trace_id.is_string()) {
+                record["traceId"] = trace_id.clone();
+            }
+            record
+        })
+        .collect();
+    json!({
+        "resourceLogs": [{
+            "resource": { "attributes": [attribute("service.name", json!("openkimi-server"))] },
+            "scopeLogs": [{ "scope": { "name": "openkimi.audit" }, "logRecords": records }],
+        }]
+    })
+}
+
+/// 攒够一批或等待`OTLP_INTERVAL`后发送，失败时丢弃该批并打印警告
+async fn export_otlp(http: reqwest::Client, config: AuditConfig, mut rx: mpsc::Receiver<Entry>, pending: Pending) {
+    while let Some(first) = rx.recv().await {
+        let mut batch = vec![first];
+        let deadline = tokio::time::Instant::now() + OTLP_INTERVAL;
+        while batch.len() < OTLP_BATCH {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some(entry)) => batch.push(entry),
+                _ => break,
+            }
+        }
+
+        let mut request = http.post(&config.otlp_endpoint).json(&otlp_payload(&batch));
+        for (name, value) in &config.otlp_headers {
+            request = request.header(name, value);
+        }
+        match request.send().await {
+            Ok(response) if response.status().is_success() => {}
+            Ok(response) => eprintln!("⚠️ 发送审计日志失败: {} 返回 {}", config.otlp_endpoint, response.status()),
+            Err(err) => eprintln!("⚠️ 发送审计日志失败: {}", err),
+        }
+        pending.done(batch.len());
+    }
+}
+
+/// 从SSE分块中拼出回复文本和用量
+#[derive(Debug, Default)]
+struct StreamCapture {
+    /// 尚未遇到换行的部分
+    line: Vec<u8>,
+    content: String,
+    finish_reason: Option<String>,
+    usage: Option<Value>,
+    truncated: bool,
+}
+
+impl StreamCapture {
+    fn feed(&mut self, bytes: &[u8], max_content: usize) {
+        for &byte in bytes {
+            if byte != b'\n' {
+                self.line.push(byte);
+                continue;
+            }
+            let line = std::mem::take(&mut self.line);
+            let Some(data) = std::str::from_utf8(&line).ok().and_then(|line| line.strip_prefix("data:")) else {
+                continue;
+            };
+            let Ok(chunk) = serde_json::from_str::<Value>(data.trim()) else {
+                continue;
+            };
+            if let Some(usage) = chunk.get("usage").filter(|usage| !usage.is_null()) {
+                self.usage = Some(usage.clone());
+            }
+            let Some(choice) = chunk["choices"].get(0) else {
+                continue;
+            };
+            if let Some(text) = choice["delta"]["content"].as_str() {
+                if self.content.len() + text.len() <= max_content {
+                    self.content.push_str(text);
+                } else {
+                    self.truncated = true;
+                }
+            }
+            if let Some(reason) = choice["finish_reason"].as_str() {
+                self.finish_reason = Some(reason.to_string());
+            }
+        }
+    }
+}
+
+/// 原样转发流式响应，结束或客户端断开时写出记录
+struct Tee {
+    inner: BodyDataStream,
+    audit: Arc<AuditLog>,
+    capture: StreamCapture,
+    completed: bool,
+    time_ms: u64,
+    started: Instant,
+    record: Value,
+}
+
+impl Stream for Tee {
+    type Item = Result<Bytes, axum::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let poll = this.inner.poll_next_unpin(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(bytes))) => this.capture.feed(bytes, this.audit.config.max_body_bytes),
+            Poll::Ready(None) => this.completed = true,
+            _ => {}
+        }
+        poll
+    }
+}
+
+impl Drop for Tee {
+    fn drop(&mut self) {
+        let capture = std::mem::take(&mut self.capture);
+        let mut record = std::mem::take(&mut self.record);
+        record["stream_completed"] = json!(self.completed);
+        record["usage"] = capture.usage.unwrap_or(Value::Null);
+        if self.audit.config.include_bodies {
+            record["response"] = json!({
+                "content": self.audit.redactor.redact(&capture.content),
+                "finish_reason": capture.finish_reason,
+                "truncated": capture.truncated,
+            });
+        }
+        self.audit.finish(self.time_ms, self.started, record);
+    }
+}
+
+/// 审计中间件，未启用审计或租户选择不记录时直接放行
+///
+/// WebSocket连接中的消息不经过HTTP中间件，不会被记录。
+pub async fn record(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let Some(audit) = state.audit.clone() else {
+        return next.run(request).await;
+    };
+    if request.headers().contains_key(UPGRADE) {
+        return next.run(request).await;
+    }
+    let tenant = request
+        .headers()
+        .get(audit.config.tenant_header.as_str())
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    if tenant.as_ref().is_some_and(|tenant| audit.config.opt_out.contains(tenant)) {
+        return next.run(request).await;
+    }
+
+    let time_ms = unix_ms();
+    let started = Instant::now();
+    let id = request_id();
+    let client = ratelimit::client_ip(&request, state.config().rate_limit.trust_proxy);
+    let (parts, body) = request.into_parts();
+    let bytes = match axum::body::to_bytes(body, MAX_REQUEST_BYTES).await {
+        Ok(bytes) => bytes,
+        Err(_) => return ApiError::invalid_request("请求体过大或读取失败").into_response(),
+    };
+    let parsed: Option<Value> = serde_json::from_slice(&bytes).ok();
+    let mut record = json!({
+        "timestamp": rfc3339(time_ms),
+        "request_id": id,
+        "trace_id": telemetry::trace_id(),
+        "tenant": tenant,
+        "client": client,
+        "method": parts.method.as_str(),
+        "path": parts.uri.path(),
+        "model": parsed.as_ref().and_then(|body| body.get("model")).and_then(Value::as_str),
+    });
+    if let Some(body) = audit.body(&bytes, parsed.as_ref()) {
+        record["request"] = body;
+    }
+
+    let mut response = next.run(Request::from_parts(parts, Body::from(bytes))).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert("x-request-id", value);
+    }
+    record["status"] = json!(response.status().as_u16());
+    if let Some(principal) = response.extensions().get::<Principal>() {
+        record["user"] = json!(principal.subject);
+    }
+    // 启用工作区时以请求所属的工作区作为租户
+    if let Some(workspace) = response.extensions().get::<Workspace>() {
+        if audit.config.opt_out.iter().any(|tenant| tenant == workspace.name()) {
+            return response;
+        }
+        record["tenant"] = json!(workspace.name());
+    }
+    let streaming = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"));
+
+    let (parts, body) = response.into_parts();
+    if streaming {
+        let tee = Tee {
+            inner: body.into_data_stream(),
+            audit,
+            capture: StreamCapture::default(),
+            completed: false,
+            time_ms,
+            started,
+            record,
+        };
+        return Response::from_parts(parts, Body::from_stream(tee));
+    }
+
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            record["error"] = json!(format!("读取响应失败: {}", err));
+            audit.finish(time_ms, started, record);
+            return ApiError::Internal("读取响应失败".to_string()).into_response();
+        }
+    };
+    let parsed: Option<Value> = serde_json::from_slice(&bytes).ok();
+    record["usage"] = parsed
+        .as_ref()
+        .and_then(|body| body.get("usage"))
+        .cloned()
+        .unwrap_or(Value::Null);
+    if let Some(body) = audit.body(&bytes, parsed.as_ref()) {
+        record["response"] = body;
+    }
+    audit.finish(time_ms, started, record);
+    Response::from_parts(parts, Body::from(bytes))
+}
@@ -0,0 +1,730 @@
+//! 认证与授权
+//!
+//! 启用后`/v1`下的接口和gRPC接口都需要`Authorization: Bearer <令牌>`。令牌可以是`tokens`中的静态API令牌，
+//! 也可以是`jwt.issuer`签发的JWT：按OIDC发现（`{issuer}/.well-known/openid-configuration`）取得JWKS校验签名，
+//! 支持RS256/RS384/RS512、PS256/PS384/PS512、ES256/ES384和EdDSA，配置了`jwt.secret`时也接受HS256。
+//! 认证通过后按`policies`中第一条匹配请求的规则检查权限，没有匹配的规则时只要求认证。
+//! 启用工作区时，`workspaces.list.<名称>.tokens`中的令牌属于对应的工作区，JWT按`jwt.workspace_claim`归属。
+//! 启用`users`时还接受用户目录签发的令牌，调用方为令牌所属的用户，见[`crate::users`]。
+//! `/admin`仍只使用`vault.admin_token`。
+
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use axum::extract::{Request, State};
+use axum::http::header::{AUTHORIZATION, UPGRADE, WWW_AUTHENTICATE};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::signature::{self, RsaParameters, RsaPublicKeyComponents, UnparsedPublicKey, VerificationAlgorithm};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio::sync::Mutex;
+
+use crate::config::{ApiTokenConfig, AuthConfig, JwtConfig, PolicyConfig, WorkspacesConfig};
+use crate::error::{ApiError, ApiResult};
+use crate::users::UserDirectory;
+use crate::AppState;
+
+/// 遇到未知`kid`时两次获取JWKS的最短间隔
+const MIN_REFETCH: Duration = Duration::from_secs(60);
+
+/// 浏览器无法为WebSocket设置请求头，握手时可以改用该查询参数传令牌
+const ACCESS_TOKEN_PARAM: &str = "access_token";
+
+/// 逐字节比较全部内容，耗时与第一个不同字节的位置无关
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// `Authorization: Bearer <令牌>`中的令牌
+pub fn bearer(value: Option<&str>) -> Option<&str> {
+    value
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+}
+
+/// 调用方的认证方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMethod {
+    Token,
+    Jwt,
+}
+
+/// 认证通过的调用方，由中间件放入请求和响应的扩展
+#[derive(Debug, Clone, Serialize)]
+pub struct Principal {
+    /// 静态令牌的`name`或JWT中的`subject_claim`
+    pub subject: String,
+    pub method: AuthMethod,
+    pub scopes: Vec<String>,
+    /// 调用方所属的工作区，`None`为默认工作区
+    pub workspace: Option<String>,
+    /// 使用的API令牌：静态令牌为`name`，用户目录签发的令牌为令牌ID，JWT为`None`
+    pub token: Option<String>,
+}
+
+impl Principal {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|granted| granted == "*" || granted == scope)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct JwtHeader {
+    alg: String,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+/// JWKS中的一个公钥，只保留校验签名用到的字段
+#[derive(Debug, Clone, Deserialize)]
+struct Jwk {
+    kty: String,
+    #[serde(default)]
+    kid: Option<String>,
+    #[serde(default)]
+    alg: Option<String>,
+    #[serde(default, rename = "use")]
+    usage: Option<String>,
+    #[serde(default)]
+    crv: Option<String>,
+    #[serde(default)]
+    n: Option<String>,
+    #[serde(default)]
+    e: Option<String>,
+    #[serde(default)]
+    x: Option<String>,
+    #[serde(default)]
+    y: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Default)]
+struct JwksCache {
+    keys: Vec<Jwk>,
+    /// 最近一次成功获取的时间
+    fetched: Option<Instant>,
+    /// 最近一次尝试获取的时间
+    attempted: Option<Instant>,
+}
+
+/// `alg`要求的密钥类型
+fn key_type(alg: &str) -> Option<&'static str> {
+    match alg {
+        "RS256" | "RS384" | "RS512" | "PS256" | "PS384" | "PS512" => Some("RSA"),
+        "ES256" | "ES384" => Some("EC"),
+        "EdDSA" => Some("OKP"),
+        _ => None,
+    }
+}
+
+fn decode(part: &str) -> Result<Vec<u8>, String> {
+    URL_SAFE_NO_PAD
+        .decode(part.trim_end_matches('='))
+        .map_err(|e| format!("base64url解码失败: {}", e))
+}
+
+fn key_field(key: &Jwk, value: &Option<String>, name: &str) -> Result<Vec<u8>, String> {
+    let value = value.as_deref().ok_or_else(|| format!("公钥 {} 缺少 {}", key.kid.as_deref().unwrap_or("-"), name))?;
+    decode(value)
+}
+
+/// 用JWKS中的公钥校验签名
+fn verify_with_key(alg: &str, key: &Jwk, message: &[u8], signature: &[u8]) -> Result<(), String> {
+    let rsa: Option<&'static RsaParameters> = match alg {
+        "RS256" => Some(&signature::RSA_PKCS1_2048_8192_SHA256),
+        "RS384" => Some(&signature::RSA_PKCS1_2048_8192_SHA384),
+        "RS512" => Some(&signature::RSA_PKCS1_2048_8192_SHA512),
+        "PS256" => Some(&signature::RSA_PSS_2048_8192_SHA256),
+        "PS384" => Some(&signature::RSA_PSS_2048_8192_SHA384),
+        "PS512" => Some(&signature::RSA_PSS_2048_8192_SHA512),
+        _ => None,
+    };
+    let verified = if let Some(params) = rsa {
+        let components = RsaPublicKeyComponents {
+            n: key_field(key, &key.n, "n")?,
+            e: key_field(key, &key.e, "e")?,
+        };
+        components.verify(params, message, signature)
+    } else {
+        let (algorithm, curve): (&'static dyn VerificationAlgorithm, &str) = match alg {
+            "ES256" => (&signature::ECDSA_P256_SHA256_FIXED, "P-256"),
+            "ES384" => (&signature::ECDSA_P384_SHA384_FIXED, "P-384"),
+            "EdDSA" => (&signature::ED25519, "Ed25519"),
+            _ => return Err(format!("不支持的签名算法 {}", alg)),
+        };
+        if key.crv.as_deref() != Some(curve) {
+            return Err(format!("公钥的曲线不是 {}", curve));
+        }
+        let mut public_key = Vec::new();
+        if key.kty == "EC" {
+            // 未压缩的椭圆曲线点：0x04 || x || y
+            public_key.push(0x04);
+            public_key.extend(key_field(key, &key.x, "x")?);
+            public_key.extend(key_field(key, &key.y, "y")?);
+        } else {
+            public_key.extend(key_field(key, &key.x, "x")?);
+        }
+        UnparsedPublicKey::new(algorithm, public_key).verify(message, signature)
+    };
+    verified.map_err(|_| "签名无效".to_string())
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// 声明的值：字符串，或字符串数组
+fn claim_strings(value: Option<&Value>) -> Vec<String> {
+    match value {
+        Some(Value::String(text)) => text.split_whitespace().map(str::to_string).collect(),
+        Some(Value::Array(items)) => items.iter().filter_map(Value::as_str).map(str::to_string).collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[derive(Debug)]
+struct JwtVerifier {
+    config: JwtConfig,
+    http: reqwest::Client,
+    jwks: Mutex<JwksCache>,
+}
+
+impl JwtVerifier {
+    async fn get_json<T: DeserializeOwned>(&self, url: &str) -> ApiResult<T> {
+        let fetch = async { self.http.get(url).send().await?.error_for_status()?.json().await };
+        fetch.await.map_err(|e: reqwest::Error| ApiError::Upstream(format!("获取 {} 失败: {}", url, e)))
+    }
+
+    /// 经OIDC发现或`jwks_uri`获取签发方的公钥
+    async fn fetch(&self) -> ApiResult<Vec<Jwk>> {
+        let jwks_uri = match &self.config.jwks_uri {
+            Some(uri) => uri.clone(),
+            None => {
+                let url = format!("{}/.well-known/openid-configuration", self.config.issuer.trim_end_matches('/'));
+                let discovery: Value = self.get_json(&url).await?;
+                discovery["jwks_uri"]
+                    .as_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| ApiError::Upstream(format!("{} 中没有 jwks_uri", url)))?
+            }
+        };
+        let set: JwkSet = self.get_json(&jwks_uri).await?;
+        Ok(set.keys)
+    }
+
+    /// 与JWT头匹配的公钥；缓存过期或找不到`kid`时重新获取
+    async fn key(&self, header: &JwtHeader) -> ApiResult<Jwk> {
+        let kty = key_type(&header.alg).ok_or_else(|| unsupported(&header.alg))?;
+        let find = |keys: &[Jwk]| {
+            keys.iter()
+                .find(|key| {
+                    key.kty == kty
+                        && key.usage.as_deref().is_none_or(|usage| usage == "sig")
+                        && key.alg.as_deref().is_none_or(|alg| alg == header.alg)
+                        && (header.kid.is_none() || key.kid == header.kid)
+                })
+                .cloned()
+        };
+
+        let now = Instant::now();
+        let mut cache = self.jwks.lock().await;
+        let refresh = Duration::from_secs(self.config.jwks_refresh_seconds);
+        if cache.fetched.is_some_and(|fetched| now.saturating_duration_since(fetched) < refresh) {
+            if let Some(key) = find(&cache.keys) {
+                return Ok(key);
+            }
+        }
+        // 找不到`kid`多半是签发方轮换了密钥，但伪造的`kid`也会走到这里，所以限制获取频率
+        if cache.attempted.is_none_or(|attempted| now.saturating_duration_since(attempted) >= MIN_REFETCH) {
+            cache.attempted = Some(now);
+            match self.fetch().await {
+                Ok(keys) => {
+                    cache.keys = keys;
+                    cache.fetched = Some(now);
+                }
+                Err(err) if cache.keys.is_empty() => return Err(err),
+                Err(err) => eprintln!("⚠️ 刷新 JWKS 失败，继续使用已有公钥: {}", err),
+            }
+        }
+        if cache.keys.is_empty() {
+            return Err(ApiError::Upstream("暂时无法获取签发方的公钥，请稍后重试".to_string()));
+        }
+        find(&cache.keys).ok_or_else(|| ApiError::Unauthorized("找不到JWT签名对应的公钥".to_string()))
+    }
+
+    async fn verify(&self, token: &str) -> ApiResult<Principal> {
+        let invalid = |message: String| ApiError::Unauthorized(format!("JWT无效: {}", message));
+        let parts: Vec<&str> = token.split('.').collect();
+        let [header, payload, signature] = parts[..] else {
+            return Err(invalid("格式错误".to_string()));
+        };
+        let header_json: JwtHeader =
+            serde_json::from_slice(&decode(header).map_err(invalid)?).map_err(|e| invalid(e.to_string()))?;
+        let claims: Map<String, Value> =
+            serde_json::from_slice(&decode(payload).map_err(invalid)?).map_err(|e| invalid(e.to_string()))?;
+        let signature = decode(signature).map_err(invalid)?;
+        let message = &token.as_bytes()[..header.len() + 1 + payload.len()];
+
+        if header_json.alg == "HS256" {
+            let secret = self.config.secret.as_deref().ok_or_else(|| unsupported("HS256"))?;
+            let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret.as_bytes());
+            ring::hmac::verify(&key, message, &signature).map_err(|_| invalid("签名无效".to_string()))?;
+        } else {
+            let key = self.key(&header_json).await?;
+            verify_with_key(&header_json.alg, &key, message, &signature).map_err(invalid)?;
+        }
+
+        if claims.get("iss").and_then(Value::as_str) != Some(self.config.issuer.as_str()) {
+            return Err(invalid("签发方不匹配".to_string()));
+        }
+        if let Some(audience) = &self.config.audience {
+            if !claim_strings(claims.get("aud")).contains(audience) {
+                return Err(invalid("受众不匹配".to_string()));
+            }
+        }
+        let now = unix_now();
+        let leeway = self.config.leeway_seconds;
+        let exp = claims
+            .get("exp")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| invalid("缺少 exp".to_string()))?;
+        if now > exp.saturating_add(leeway) {
+            return Err(ApiError::Unauthorized("JWT已过期".to_string()));
+        }
+        if claims.get("nbf").and_then(Value::as_u64).is_some_and(|nbf| now.saturating_add(leeway) < nbf) {
+            return Err(ApiError::Unauthorized("JWT尚未生效".to_string()));
+        }
+
+        let subject = match claims.get(&self.config.subject_claim) {
+            Some(Value::String(subject)) if !subject.is_empty() => subject.clone(),
+            Some(Value::Number(subject)) => subject.to_string(),
+            _ => return Err(invalid(format!("缺少 {}", self.config.subject_claim))),
+        };
+        let workspace = self
+            .config
+            .workspace_claim
+            .as_ref()
+            .and_then(|claim| claims.get(claim))
+            .and_then(Value::as_str)
+            .filter(|workspace| !workspace.is_empty())
+            .map(str::to_string);
+        Ok(Principal {
+            subject,
+            method: AuthMethod::Jwt,
+            scopes: claim_strings(claims.get(&self.config.scope_claim)),
+            workspace,
+            token: None,
+        })
+    }
+}
+
+fn unsupported(alg: &str) -> ApiError {
+    ApiError::Unauthorized(format!("不接受签名算法为 {} 的JWT", alg))
+}
+
+/// 规则中的路径是否匹配请求路径，以`*`结尾时按前缀匹配
+fn path_matches(pattern: &str, path: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => path.starts_with(prefix),
+        None => pattern == path,
+    }
+}
+
+/// 静态令牌及其所属的工作区
+#[derive(Debug)]
+struct StaticToken {
+    config: ApiTokenConfig,
+    workspace: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Authenticator {
+    tokens: Vec<StaticToken>,
+    policies: Vec<PolicyConfig>,
+    jwt: Option<JwtVerifier>,
+    users: Option<Arc<UserDirectory>>,
+}
+
+impl Authenticator {
+    /// 工作区未启用时不接受各工作区的令牌；`users`为启用的用户目录，静态令牌不匹配时在其中查找
+    pub fn new(
+        config: &AuthConfig,
+        workspaces: &WorkspacesConfig,
+        users: Option<Arc<UserDirectory>>,
+    ) -> Result<Authenticator, String> {
+        let mut tokens: Vec<(String, StaticToken)> = config
+            .tokens
+            .iter()
+            .enumerate()
+            .map(|(index, token)| {
+                let token = StaticToken {
+                    config: token.clone(),
+                    workspace: None,
+                };
+                (format!("auth.tokens[{}]", index), token)
+            })
+            .collect();
+        if workspaces.enabled {
+            for (name, workspace) in &workspaces.list {
+                for (index, token) in workspace.tokens.iter().enumerate() {
+                    let token = StaticToken {
+                        config: token.clone(),
+                        workspace: Some(name.clone()),
+                    };
+                    tokens.push((format!("workspaces.list.{}.tokens[{}]", name, index), token));
+                }
+            }
+        }
+        if tokens.is_empty() && config.jwt.is_none() && users.is_none() {
+            return Err(
+                "auth.enabled 为 true 时至少需要配置 auth.tokens、工作区的 tokens、auth.jwt 或启用 users".to_string()
+            );
+        }
+        for (section, token) in &tokens {
+            if token.config.name.is_empty() || token.config.token.is_empty() {
+                return Err(format!("{} 缺少 name 或 token", section));
+            }
+            let duplicates = tokens.iter().filter(|(_, other)| other.config.token == token.config.token).count();
+            if duplicates > 1 {
+                return Err(format!("{} 的 token 与其他令牌重复", section));
+            }
+        }
+        for (index, policy) in config.policies.iter().enumerate() {
+            if !policy.path.starts_with('/') {
+                return Err(format!("auth.policies[{}] 的 path 必须以 / 开头", index));
+            }
+        }
+        let jwt = match &config.jwt {
+            Some(jwt) if jwt.issuer.is_empty() => return Err("auth.jwt 缺少 issuer".to_string()),
+            Some(jwt) => {
+                let http = reqwest::Client::builder()
+                    .timeout(Duration::from_secs(10))
+                    .build()
+                    .map_err(|e| format!("无法创建 HTTP 客户端: {}", e))?;
+                Some(JwtVerifier {
+                    config: jwt.clone(),
+                    http,
+                    jwks: Mutex::new(JwksCache::default()),
+                })
+            }
+            None => None,
+        };
+        Ok(Authenticator {
+            tokens: tokens.into_iter().map(|(_, token)| token).collect(),
+            policies: config.policies.clone(),
+            jwt,
+            users,
+        })
+    }
+
+    /// 识别令牌对应的调用方
+    pub async fn authenticate(&self, token: Option<&str>) -> ApiResult<Principal> {
+        let token = token.ok_or_else(|| ApiError::Unauthorized("缺少认证令牌".to_string()))?;
+        // 比较全部静态令牌，不因提前命中而暴露令牌所在的位置
+        let matched = self.tokens.iter().fold(None, |matched, candidate| {
+            if constant_time_eq(token.as_bytes(), candidate.config.token.as_bytes()) {
+                Some(candidate)
+            } else {
+                matched
+            }
+        });
+        if let Some(matched) = matched {
+            return Ok(Principal {
+                subject: matched.config.name.clone(),
+                method: AuthMethod::Token,
+                scopes: matched.config.scopes.clone(),
+                workspace: matched.workspace.clone(),
+                token: Some(matched.config.name.clone()),
+            });
+        }
+        if let Some(users) = &self.users {
+            if let Some(principal) = users.authenticate(token)? {
+                return Ok(principal);
+            }
+        }
+        match &self.jwt {
+            Some(jwt) if token.split('.').count() == 3 => jwt.verify(token).await,
+            _ => Err(ApiError::Unauthorized("认证令牌无效".to_string())),
+        }
+    }
+
+    fn policy(&self, method: &str, path: &str) -> Option<&PolicyConfig> {
+        self.policies.iter().find(|policy| {
+            path_matches(&policy.path, path)
+                && (policy.methods.is_empty() || policy.methods.iter().any(|m| m.eq_ignore_ascii_case(method)))
+        })
+    }
+
+    /// 认证并检查权限；匹配的规则为`public`时返回`None`
+    pub async fn check(&self, token: Option<&str>, method: &str, path: &str) -> ApiResult<Option<Principal>> {
+        let policy = self.policy(method, path);
+        if policy.is_some_and(|policy| policy.public) {
+            return Ok(None);
+        }
+        let principal = self.authenticate(token).await?;
+        if let Some(policy) = policy {
+            let missing: Vec<&str> = policy
+                .scopes
+                .iter()
+                .filter(|scope| !principal.has_scope(scope))
+                .map(String::as_str)
+                .collect();
+            if !missing.is_empty() {
+                return Err(ApiError::Forbidden(format!(
+                    "{} 没有访问 {} 所需的权限: {}",
+                    principal.subject,
+                    path,
+                    missing.join(", ")
+                )));
+            }
+        }
+        Ok(Some(principal))
+    }
+}
+
+/// 请求携带的令牌；WebSocket握手时也可以放在`access_token`查询参数中
+fn request_token(headers: &HeaderMap, query: Option<&str>) -> Option<String> {
+    if let Some(token) = bearer(headers.get(AUTHORIZATION).and_then(|value| value.to_str().ok())) {
+        return Some(token.to_string());
+    }
+    if !headers.contains_key(UPGRADE) {
+        return None;
+    }
+    query?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == ACCESS_TOKEN_PARAM)
+        .map(|(_, token)| token.to_string())
+        .filter(|token| !token.is_empty())
+}
+
+/// 认证中间件，未启用认证时直接放行
+pub async fn require(State(state): State<Arc<AppState>>, mut request: Request, next: Next) -> Response {
+    let auth = state.auth();
+    let Some(auth) = auth.as_ref() else {
+        return next.run(request).await;
+    };
+    let token = request_token(request.headers(), request.uri().query());
+    let path = request.uri().path().to_string();
+    match auth.check(token.as_deref(), request.method().as_str(), &path).await {
+        Ok(None) => next.run(request).await,
+        Ok(Some(principal)) => {
+            request.extensions_mut().insert(principal.clone());
+            let mut response = next.run(request).await;
+            // 供外层的审计中间件记录调用方
+            response.extensions_mut().insert(principal);
+            response
+        }
+        Err(err) => {
+            let mut response = err.into_response();
+            if response.status() == StatusCode::UNAUTHORIZED {
+                response.headers_mut().insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            }
+            response
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use serde_json::json;
+
+    use super::*;
+
+    const SECRET: &str = "test-secret";
+    const ISSUER: &str = "https://id.example.com";
+
+    fn verifier(secret: Option<&str>) -> JwtVerifier {
+        JwtVerifier {
+            config: JwtConfig {
+                issuer: ISSUER.to_string(),
+                audience: Some("openkimi".to_string()),
+                // 测试中不应访问网络
+                jwks_uri: Some("http://127.0.0.1:9/jwks".to_string()),
+                secret: secret.map(str::to_string),
+                leeway_seconds: 0,
+                ..JwtConfig::default()
+            },
+            http: reqwest::Client::new(),
+            jwks: Mutex::new(JwksCache::default()),
+        }
+    }
+
+    /// 预先放入JWKS缓存，并标记为刚获取过，避免重新获取
+    async fn with_keys(verifier: JwtVerifier, keys: Vec<Jwk>) -> JwtVerifier {
+        let now = Instant::now();
+        *verifier.jwks.lock().await = JwksCache {
+            keys,
+            fetched: Some(now),
+            attempted: Some(now),
+        };
+        verifier
+    }
+
+    fn encode(value: &Value) -> String {
+        URL_SAFE_NO_PAD.encode(value.to_string())
+    }
+
+    fn claims(overrides: Value) -> Value {
+        let now = unix_now();
+        let mut claims = json!({
+            "iss": ISSUER,
+            "aud": "openkimi",
+            "sub": "alice",
+            "scope": "chat models",
+            "exp": now + 300,
+        });
+        claims.as_object_mut().unwrap().extend(overrides.as_object().unwrap().clone());
+        claims
+    }
+
+    fn hs256(claims: &Value, secret: &[u8]) -> String {
+        let signed = format!("{}.{}", encode(&json!({"alg": "HS256", "typ": "JWT"})), encode(claims));
+        let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret);
+        let signature = ring::hmac::sign(&key, signed.as_bytes());
+        format!("{}.{}", signed, URL_SAFE_NO_PAD.encode(signature.as_ref()))
+    }
+
+    fn rsa_jwk() -> Jwk {
+        Jwk {
+            kty: "RSA".to_string(),
+            kid: Some("rsa-1".to_string()),
+            alg: None,
+            usage: Some("sig".to_string()),
+            crv: None,
+            n: Some(URL_SAFE_NO_PAD.encode([0xc5u8; 256])),
+            e: Some("AQAB".to_string()),
+            x: None,
+            y: None,
+        }
+    }
+
+    fn rejected(result: ApiResult<Principal>) -> String {
+        match result {
+            Err(ApiError::Unauthorized(message)) => message,
+            other => panic!("应拒绝，实际为 {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn accepts_valid_hs256() {
+        let principal = verifier(Some(SECRET)).verify(&hs256(&claims(json!({})), SECRET.as_bytes())).await.unwrap();
+        assert_eq!(principal.subject, "alice");
+        assert_eq!(principal.method, AuthMethod::Jwt);
+        assert!(principal.has_scope("models"));
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_claims() {
+        let verifier = verifier(Some(SECRET));
+        let now = unix_now();
+        let cases = [
+            (json!({"exp": now - 10}), "已过期"),
+            (json!({"nbf": now + 300}), "尚未生效"),
+            (json!({"iss": "https://evil.example.com"}), "签发方不匹配"),
+            (json!({"aud": ["other", "another"]}), "受众不匹配"),
+            (json!({"exp": null}), "缺少 exp"),
+            (json!({"sub": ""}), "缺少 sub"),
+        ];
+        for (overrides, expected) in cases {
+            let token = hs256(&claims(overrides.clone()), SECRET.as_bytes());
+            let message = rejected(verifier.verify(&token).await);
+            assert!(message.contains(expected), "{}: {}", overrides, message);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_secret_and_tampered_payload() {
+        let verifier = verifier(Some(SECRET));
+        let token = hs256(&claims(json!({})), b"other-secret");
+        assert!(rejected(verifier.verify(&token).await).contains("签名无效"));
+
+        let token = hs256(&claims(json!({})), SECRET.as_bytes());
+        let parts: Vec<&str> = token.split('.').collect();
+        let forged = format!("{}.{}.{}", parts[0], encode(&claims(json!({"sub": "root"}))), parts[2]);
+        assert!(rejected(verifier.verify(&forged).await).contains("签名无效"));
+    }
+
+    #[tokio::test]
+    async fn hs256_is_not_verified_with_rsa_jwk() {
+        // 算法混淆：用RSA公钥的内容作为HMAC密钥签名，未配置secret时不接受HS256
+        let key = rsa_jwk();
+        let token = hs256(&claims(json!({})), key.n.as_deref().unwrap().as_bytes());
+        let verifier = with_keys(verifier(None), vec![key]).await;
+        assert!(rejected(verifier.verify(&token).await).contains("HS256"));
+    }
+
+    #[tokio::test]
+    async fn alg_must_match_key_type() {
+        let rng = SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let okp = Jwk {
+            kty: "OKP".to_string(),
+            kid: Some("ed-1".to_string()),
+            alg: None,
+            usage: None,
+            crv: Some("Ed25519".to_string()),
+            n: None,
+            e: None,
+            x: Some(URL_SAFE_NO_PAD.encode(pair.public_key().as_ref())),
+            y: None,
+        };
+        let verifier = with_keys(verifier(None), vec![rsa_jwk(), okp]).await;
+        let sign = |alg: &str, kid: &str| {
+            let signed = format!("{}.{}", encode(&json!({"alg": alg, "kid": kid})), encode(&claims(json!({}))));
+            let signature = pair.sign(signed.as_bytes());
+            format!("{}.{}", signed, URL_SAFE_NO_PAD.encode(signature.as_ref()))
+        };
+
+        assert_eq!(verifier.verify(&sign("EdDSA", "ed-1")).await.unwrap().subject, "alice");
+        // 声明为RS256的Ed25519签名不会拿到OKP公钥，指向RSA公钥时签名也不成立
+        assert!(rejected(verifier.verify(&sign("RS256", "ed-1")).await).contains("找不到"));
+        assert!(rejected(verifier.verify(&sign("RS256", "rsa-1")).await).contains("签名无效"));
+        assert!(rejected(verifier.verify(&sign("none", "ed-1")).await).contains("none"));
+        assert!(verify_with_key("ES256", &rsa_jwk(), b"message", &[0; 64]).is_err());
+    }
+
+    #[test]
+    fn constant_time_compare() {
+        assert!(constant_time_eq(b"ok-secret", b"ok-secret"));
+        assert!(!constant_time_eq(b"ok-secret", b"ok-secreT"));
+        assert!(!constant_time_eq(b"ok-secret", b"ok-secret2"));
+        assert!(!constant_time_eq(b"ok-secret", b"ok-"));
+        assert!(!constant_time_eq(b"", b"x"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[tokio::test]
+    async fn static_tokens_match_exactly() {
+        let config = AuthConfig {
+            enabled: true,
+            tokens: vec![ApiTokenConfig {
+                name: "ci".to_string(),
+                token: "ok-secret".to_string(),
+                scopes: vec!["chat".to_string()],
+            }],
+            ..AuthConfig::default()
+        };
+        let auth = Authenticator::new(&config, &WorkspacesConfig::default(), None).unwrap();
+        let principal = auth.authenticate(Some("ok-secret")).await.unwrap();
+        assert_eq!((principal.subject.as_str(), principal.method), ("ci", AuthMethod::Token));
+        for token in ["ok-secre", "ok-secret ", "OK-SECRET", ""] {
+            assert!(auth.authenticate(Some(token)).await.is_err(), "{:?}", token);
+        }
+        assert!(auth.authenticate(None).await.is_err());
+    }
+}
@@ -0,0 +1,643 @@
+//! 批处理接口`/v1/batch`
+//!
+//! 一次提交大量对话补全或嵌入请求：JSON数组、JSONL请求体或`/v1/files`上传的JSONL文件，每行的格式同OpenAI
+//! Batch API的输入文件。服务端在后台以限定的并发把请求发给上游，每完成一个就把结果追加到结果文件，
+//! 可以随时查询进度、下载已有的结果或取消，适合离线评测和批量处理文档。
+//! 记录和结果保存在`batch.dir`下，服务重启后仍可查询，重启时仍在运行的批处理记为失败。
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use futures_util::stream;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::{watch, OwnedSemaphorePermit, Semaphore};
+
+use crate::config::BatchConfig;
+use crate::error::{ApiError, ApiResult};
+use crate::metering::{self, Consumer};
+use crate::ratelimit::{self, RateLimitKey};
+use crate::tools::ToolScope;
+use crate::types::{ChatCompletionRequest, DeletedResponse, EmbeddingRequest, ListResponse, Usage};
+use crate::workspace::{self, Workspace};
+use crate::{interpreter, prompts, structured, tools, AppState};
+
+/// 清理过期批处理的间隔
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+const CHAT_URL: &str = "/v1/chat/completions";
+const EMBEDDINGS_URL: &str = "/v1/embeddings";
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// 批处理状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    InProgress,
+    /// 所有请求都已处理，其中可能有失败的
+    Completed,
+    Cancelled,
+    /// 写入结果失败或服务重启时仍在运行
+    Failed,
+}
+
+/// 各状态的请求数
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RequestCounts {
+    pub total: usize,
+    pub completed: usize,
+    pub failed: usize,
+}
+
+/// 一个批处理的记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Batch {
+    pub id: String,
+    pub object: String,
+    pub status: BatchStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_file_id: Option<String>,
+    /// 同时发给上游的请求数
+    pub concurrency: usize,
+    pub request_counts: RequestCounts,
+    /// 成功的请求的用量之和
+    pub usage: Usage,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u64>,
+}
+
+/// 批处理中的一个请求，即输入JSONL的一行
+#[derive(Debug, Clone, Deserialize)]
+pub struct BatchRequest {
+    /// 在结果中标识请求，缺省为`request-<序号>`
+    #[serde(default)]
+    pub custom_id: Option<String>,
+    /// 只支持`POST`
+    #[serde(default)]
+    pub method: Option<String>,
+    /// `/v1/chat/completions`或`/v1/embeddings`
+    pub url: String,
+    pub body: Value,
+}
+
+/// `POST /v1/batch`的JSON请求体，`requests`和`input_file_id`二选一
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewBatch {
+    #[serde(default)]
+    pub requests: Vec<BatchRequest>,
+    /// `/v1/files`中的JSONL文件
+    #[serde(default)]
+    pub input_file_id: Option<String>,
+    #[serde(default)]
+    pub concurrency: Option<usize>,
+}
+
+/// `POST /v1/batch`的查询参数，请求体为JSONL时用来指定并发
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct BatchParams {
+    #[serde(default)]
+    pub concurrency: Option<usize>,
+}
+
+/// 校验后的请求
+enum Call {
+    Chat(ChatCompletionRequest),
+    Embeddings(EmbeddingRequest),
+}
+
+struct Item {
+    custom_id: String,
+    call: Call,
+}
+
+/// 校验全部请求，有一个不合法时整个批处理被拒绝
+fn validate(requests: Vec<BatchRequest>, max_requests: usize) -> ApiResult<Vec<Item>> {
+    if requests.is_empty() {
+        return Err(ApiError::invalid_request("批处理中没有请求"));
+    }
+    if requests.len() > max_requests {
+        return Err(ApiError::invalid_request(format!("批处理最多包含 {} 个请求", max_requests)));
+    }
+    let mut seen = HashSet::new();
+    let mut items = Vec::with_capacity(requests.len());
+    for (index, request) in requests.into_iter().enumerate() {
+        let invalid = |message: String| ApiError::invalid_request(format!("第 {} 个请求{}", index + 1, message));
+        if request.method.as_deref().is_some_and(|method| !method.eq_ignore_ascii_case("POST")) {
+            return Err(invalid("的 method 只能是 POST".to_string()));
+        }
+        let custom_id = request.custom_id.unwrap_or_else(|| format!("request-{}", index + 1));
+        if !seen.insert(custom_id.clone()) {
+            return Err(invalid(format!("的 custom_id {} 重复", custom_id)));
+        }
+        let call = match request.url.as_str() {
+            CHAT_URL => {
+                let chat: ChatCompletionRequest =
+                    serde_json::from_value(request.body).map_err(|e| invalid(format!("不是有效的对话请求: {}", e)))?;
+                if chat.stream == Some(true) {
+                    return Err(invalid("不能使用 stream".to_string()));
+                }
+                Call::Chat(chat)
+            }
+            EMBEDDINGS_URL => Call::Embeddings(
+                serde_json::from_value(request.body).map_err(|e| invalid(format!("不是有效的嵌入请求: {}", e)))?,
+            ),
+            url => return Err(invalid(format!("的 url {} 不受支持，只能是 {} 或 {}", url, CHAT_URL, EMBEDDINGS_URL))),
+        };
+        items.push(Item { custom_id, call });
+    }
+    Ok(items)
+}
+
+/// 解析JSONL，忽略空行
+fn parse_jsonl(data: &[u8]) -> ApiResult<Vec<BatchRequest>> {
+    let text = std::str::from_utf8(data).map_err(|_| ApiError::invalid_request("JSONL 不是有效的 UTF-8"))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(number, line)| {
+            serde_json::from_str(line).map_err(|e| {
+                ApiError::invalid_request(format!("第 {} 行不是有效的批处理请求: {}", number + 1, e))
+            })
+        })
+        .collect()
+}
+
+fn write_json(path: &FsPath, value: &impl Serialize) -> Result<(), String> {
+    let text = serde_json::to_string_pretty(value).unwrap_or_default();
+    fs::write(path, text).map_err(|e| format!("写入 {} 失败: {}", path.display(), e))
+}
+
+/// 一个批处理，结束后仍保留以便查询结果
+#[derive(Debug)]
+struct BatchEntry {
+    workspace: String,
+    /// 批处理记录
+    path: PathBuf,
+    /// 结果JSONL
+    results: PathBuf,
+    batch: Mutex<Batch>,
+    cancel: watch::Sender<bool>,
+    finished: watch::Sender<bool>,
+}
+
+impl BatchEntry {
+    fn new(workspace: String, path: PathBuf, batch: Batch) -> BatchEntry {
+        let finished = batch.status != BatchStatus::InProgress;
+        BatchEntry {
+            workspace,
+            results: path.with_extension("jsonl"),
+            path,
+            batch: Mutex::new(batch),
+            cancel: watch::channel(false).0,
+            finished: watch::channel(finished).0,
+        }
+    }
+
+    fn snapshot(&self) -> Batch {
+        self.batch.lock().unwrap().clone()
+    }
+
+    fn save(&self, batch: &Batch) {
+        if let Err(err) = write_json(&self.path, batch) {
+            eprintln!("⚠️ 保存批处理记录失败: {}", err);
+        }
+    }
+
+    /// 追加一个请求的结果并更新计数
+    fn record(&self, file: &mut File, custom_id: String, result: ApiResult<(Value, Usage)>) -> Result<(), String> {
+        let (status, body, usage) = match result {
+            Ok((body, usage)) => (StatusCode::OK, body, Some(usage)),
+            Err(err) => {
+                let (status, body) = err.into_parts();
+                (status, body, None)
+            }
+        };
+        let line = json!({
+            "custom_id": custom_id,
+            "response": { "status_code": status.as_u16(), "body": body },
+        });
+        writeln!(file, "{}", line).map_err(|e| format!("写入 {} 失败: {}", self.results.display(), e))?;
+        let mut batch = self.batch.lock().unwrap();
+        match usage {
+            Some(usage) => {
+                batch.request_counts.completed += 1;
+                batch.usage = tools::add_usage(Some(batch.usage.clone()), Some(&usage)).unwrap_or_default();
+            }
+            None => batch.request_counts.failed += 1,
+        }
+        self.save(&batch);
+        Ok(())
+    }
+
+    fn finish(&self, status: BatchStatus, error: Option<String>) {
+        let mut batch = self.batch.lock().unwrap();
+        batch.status = status;
+        batch.error = error;
+        batch.finished_at = Some(unix_now());
+        self.save(&batch);
+        self.finished.send_replace(true);
+    }
+}
+
+/// 处理请求所需的状态，用量计入提交批处理的调用方
+struct BatchContext {
+    state: Arc<AppState>,
+    consumer: Consumer,
+    limit_key: Option<RateLimitKey>,
+}
+
+impl BatchContext {
+    /// 与`/v1/chat/completions`相同地套用提示词模板、执行服务端工具并校验结构化输出，
+    /// 不检索知识库和长期记忆，也不使用回复缓存
+    async fn chat(&self, mut request: ChatCompletionRequest) -> ApiResult<(Value, Usage)> {
+        let workspace = &self.consumer.workspace;
+        if request.model.is_empty() {
+            request.model = self.state.config().llm.model_name.clone();
+        }
+        prompts::apply(&self.state, workspace, &mut request)?;
+        let scope = ToolScope::take(workspace, &mut request)?;
+        let prompt_tokens = self.state.prepare_chat(&mut request).await?;
+        let mut response = structured::chat_completion(&self.state, &request, &scope).await?;
+        if response.usage.is_none() {
+            response.usage = Some(self.state.usage(&request.model, prompt_tokens, &response));
+        }
+        let usage = response.usage.clone().unwrap_or_default();
+        ratelimit::charge(&self.state, self.limit_key.as_ref(), usage.total_tokens);
+        workspace::charge(&self.state, workspace, usage.total_tokens);
+        metering::record(&self.state, &self.consumer, &request.model, usage.prompt_tokens, usage.completion_tokens);
+        Ok((json!(response), usage))
+    }
+
+    async fn embeddings(&self, mut request: EmbeddingRequest) -> ApiResult<(Value, Usage)> {
+        if request.model.is_empty() {
+            request.model = self
+                .state
+                .config()
+                .llm
+                .embedding_model
+                .clone()
+                .ok_or_else(|| ApiError::invalid_request("未指定 model，且配置中没有 llm.embedding_model"))?;
+        }
+        let response = self.state.models().embeddings(&request).await?;
+        let usage = response.usage.clone().unwrap_or_default();
+        ratelimit::charge(&self.state, self.limit_key.as_ref(), usage.total_tokens);
+        workspace::charge(&self.state, &self.consumer.workspace, usage.total_tokens);
+        metering::record(&self.state, &self.consumer, &request.model, usage.prompt_tokens, 0);
+        let usage = Usage {
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: 0,
+            total_tokens: usage.total_tokens,
+        };
+        Ok((json!(response), usage))
+    }
+
+    async fn process(&self, item: Item) -> (String, ApiResult<(Value, Usage)>) {
+        let result = match item.call {
+            Call::Chat(request) => self.chat(request).await,
+            Call::Embeddings(request) => self.embeddings(request).await,
+        };
+        (item.custom_id, result)
+    }
+}
+
+/// 在后台处理全部请求，直到完成或被取消；取消时正在处理的请求随之中止，不写入结果
+async fn execute(context: BatchContext, entry: Arc<BatchEntry>, items: Vec<Item>, permit: OwnedSemaphorePermit) {
+    let opened = OpenOptions::new().create(true).append(true).open(&entry.results);
+    let mut file = match opened {
+        Ok(file) => file,
+        Err(e) => {
+            entry.finish(BatchStatus::Failed, Some(format!("打开 {} 失败: {}", entry.results.display(), e)));
+            return;
+        }
+    };
+    let concurrency = entry.snapshot().concurrency;
+    let mut results = stream::iter(items).map(|item| context.process(item)).buffer_unordered(concurrency);
+    let mut cancelled = entry.cancel.subscribe();
+    let (status, error) = loop {
+        let next = tokio::select! {
+            next = results.next() => next,
+            _ = cancelled.wait_for(|cancelled| *cancelled) => break (BatchStatus::Cancelled, None),
+        };
+        let Some((custom_id, result)) = next else {
+            break (BatchStatus::Completed, None);
+        };
+        if let Err(err) = entry.record(&mut file, custom_id, result) {
+            break (BatchStatus::Failed, Some(err));
+        }
+    };
+    drop(results);
+    entry.finish(status, error);
+    drop(permit);
+}
+
+/// 批处理及其结果
+#[derive(Debug)]
+pub struct Batches {
+    config: BatchConfig,
+    batches: Arc<Mutex<HashMap<String, Arc<BatchEntry>>>>,
+    permits: Arc<Semaphore>,
+}
+
+impl Batches {
+    /// 载入`batch.dir`中的记录并启动清理线程，上次退出时仍在运行的批处理记为失败
+    pub fn open(config: &BatchConfig) -> Result<Batches, String> {
+        fs::create_dir_all(&config.dir).map_err(|e| format!("创建目录 {} 失败: {}", config.dir.display(), e))?;
+        let mut batches = HashMap::new();
+        for workspace in fs::read_dir(&config.dir).map_err(|e| format!("读取目录 {} 失败: {}", config.dir.display(), e))? {
+            let Ok(workspace) = workspace else { continue };
+            let name = workspace.file_name().to_string_lossy().into_owned();
+            let Ok(files) = fs::read_dir(workspace.path()) else { continue };
+            for file in files.flatten() {
+                let path = file.path();
+                if path.extension().is_none_or(|extension| extension != "json") {
+                    continue;
+                }
+                let Ok(text) = fs::read_to_string(&path) else { continue };
+                let Ok(mut batch) = serde_json::from_str::<Batch>(&text) else {
+                    eprintln!("⚠️ 无法解析批处理记录 {}", path.display());
+                    continue;
+                };
+                if batch.status == BatchStatus::InProgress {
+                    batch.status = BatchStatus::Failed;
+                    batch.error = Some("服务重启，批处理中断".to_string());
+                    batch.finished_at = Some(unix_now());
+                }
+                let entry = BatchEntry::new(name.clone(), path, batch.clone());
+                entry.save(&batch);
+                batches.insert(batch.id, Arc::new(entry));
+            }
+        }
+
+        let batches = Arc::new(Mutex::new(batches));
+        if config.ttl_seconds > 0 {
+            let batches = Arc::clone(&batches);
+            let ttl = config.ttl_seconds;
+            std::thread::spawn(move || loop {
+                std::thread::sleep(CLEANUP_INTERVAL);
+                remove_expired(&batches, ttl);
+            });
+        }
+        Ok(Batches {
+            config: config.clone(),
+            batches,
+            permits: Arc::new(Semaphore::new(config.max_running.max(1))),
+        })
+    }
+
+    fn entry(&self, workspace: &Workspace, id: &str) -> ApiResult<Arc<BatchEntry>> {
+        match self.batches.lock().unwrap().get(id) {
+            Some(entry) if entry.workspace == workspace.name() => Ok(Arc::clone(entry)),
+            _ => Err(ApiError::NotFound(format!("批处理 {} 不存在", id))),
+        }
+    }
+
+    /// 校验请求并在后台开始处理
+    fn start(
+        &self,
+        state: &Arc<AppState>,
+        consumer: Consumer,
+        limit_key: Option<RateLimitKey>,
+        requests: Vec<BatchRequest>,
+        input_file_id: Option<String>,
+        concurrency: Option<usize>,
+    ) -> ApiResult<Batch> {
+        let concurrency = concurrency.unwrap_or(self.config.concurrency);
+        if concurrency == 0 || concurrency > self.config.max_concurrency {
+            return Err(ApiError::invalid_request(format!(
+                "concurrency 应在 1 到 {} 之间",
+                self.config.max_concurrency
+            )));
+        }
+        let items = validate(requests, self.config.max_requests)?;
+        let permit = Arc::clone(&self.permits).try_acquire_owned().map_err(|_| {
+            ApiError::RateLimited(format!("同时运行的批处理已达上限 {}，请稍后重试", self.config.max_running))
+        })?;
+
+        let id = format!("batch-{}", interpreter::random_hex().map_err(ApiError::Internal)?);
+        let batch = Batch {
+            id: id.clone(),
+            object: "batch".to_string(),
+            status: BatchStatus::InProgress,
+            input_file_id,
+            concurrency,
+            request_counts: RequestCounts {
+                total: items.len(),
+                ..RequestCounts::default()
+            },
+            usage: Usage::default(),
+            error: None,
+            created_at: unix_now(),
+            finished_at: None,
+        };
+        let dir = self.config.dir.join(consumer.workspace.name());
+        fs::create_dir_all(&dir).map_err(|e| ApiError::Internal(format!("创建目录 {} 失败: {}", dir.display(), e)))?;
+        let entry = Arc::new(BatchEntry::new(
+            consumer.workspace.name().to_string(),
+            dir.join(format!("{}.json", id)),
+            batch.clone(),
+        ));
+        entry.save(&batch);
+        self.batches.lock().unwrap().insert(id, Arc::clone(&entry));
+
+        let context = BatchContext {
+            state: Arc::clone(state),
+            consumer,
+            limit_key,
+        };
+        tokio::spawn(execute(context, entry, items, permit));
+        Ok(batch)
+    }
+
+    pub fn get(&self, workspace: &Workspace, id: &str) -> ApiResult<Batch> {
+        Ok(self.entry(workspace, id)?.snapshot())
+    }
+
+    /// 工作区中的批处理，新的在前
+    pub fn list(&self, workspace: &Workspace) -> Vec<Batch> {
+        let entries: Vec<Arc<BatchEntry>> = self
+            .batches
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|entry| entry.workspace == workspace.name())
+            .cloned()
+            .collect();
+        let mut batches: Vec<Batch> = entries.iter().map(|entry| entry.snapshot()).collect();
+        batches.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| a.id.cmp(&b.id)));
+        batches
+    }
+
+    /// 到目前为止的结果，按完成的先后排列
+    pub fn results(&self, workspace: &Workspace, id: &str) -> ApiResult<Vec<u8>> {
+        let entry = self.entry(workspace, id)?;
+        match fs::read(&entry.results) {
+            Ok(data) => Ok(data),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(ApiError::Internal(format!("读取 {} 失败: {}", entry.results.display(), e))),
+        }
+    }
+
+    /// 取消运行中的批处理，已写入的结果保留
+    pub async fn cancel(&self, workspace: &Workspace, id: &str) -> ApiResult<Batch> {
+        let entry = self.entry(workspace, id)?;
+        if entry.snapshot().status == BatchStatus::InProgress {
+            entry.cancel.send_replace(true);
+            // 等待记录结束状态后再返回
+            let _ = entry.finished.subscribe().wait_for(|finished| *finished).await;
+        }
+        Ok(entry.snapshot())
+    }
+
+    pub fn delete(&self, workspace: &Workspace, id: &str) -> ApiResult<()> {
+        let entry = self.entry(workspace, id)?;
+        if entry.snapshot().status == BatchStatus::InProgress {
+            return Err(ApiError::invalid_request(format!("批处理 {} 仍在运行，请先取消", id)));
+        }
+        self.batches.lock().unwrap().remove(id);
+        remove_files(&entry);
+        Ok(())
+    }
+}
+
+fn remove_files(entry: &BatchEntry) {
+    for path in [&entry.path, &entry.results] {
+        if let Err(e) = fs::remove_file(path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                eprintln!("⚠️ 删除 {} 失败: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// 删除结束超过`ttl`秒的批处理及其结果
+fn remove_expired(batches: &Mutex<HashMap<String, Arc<BatchEntry>>>, ttl: u64) {
+    let now = unix_now();
+    let mut batches = batches.lock().unwrap();
+    let expired: Vec<String> = batches
+        .iter()
+        .filter(|(_, entry)| entry.snapshot().finished_at.is_some_and(|finished| finished + ttl < now))
+        .map(|(id, _)| id.clone())
+        .collect();
+    for id in expired {
+        if let Some(entry) = batches.remove(&id) {
+            remove_files(&entry);
+        }
+    }
+}
+
+fn batches(state: &AppState) -> ApiResult<&Batches> {
+    state
+        .batches
+        .as_ref()
+        .ok_or_else(|| ApiError::invalid_request("批处理未启用（batch.enabled 为 false）"))
+}
+
+/// 请求体是否为JSONL
+fn is_jsonl(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("jsonl") || value.contains("ndjson"))
+}
+
+/// `POST /v1/batch`，请求体为JSON对象或JSONL，立即返回批处理记录
+pub async fn create_batch(
+    State(state): State<Arc<AppState>>,
+    limit_key: Option<Extension<RateLimitKey>>,
+    consumer: Consumer,
+    Query(params): Query<BatchParams>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> ApiResult<Json<Batch>> {
+    let batches = batches(&state)?;
+    let (requests, input_file_id, concurrency) = if is_jsonl(&headers) {
+        (parse_jsonl(&body)?, None, params.concurrency)
+    } else {
+        let request: NewBatch =
+            serde_json::from_slice(&body).map_err(|e| ApiError::invalid_request(format!("请求体不是有效的 JSON: {}", e)))?;
+        let concurrency = request.concurrency.or(params.concurrency);
+        match request.input_file_id {
+            Some(_) if !request.requests.is_empty() => {
+                return Err(ApiError::invalid_request("requests 和 input_file_id 只能指定一个"));
+            }
+            Some(id) => {
+                let files = state
+                    .files
+                    .as_ref()
+                    .ok_or_else(|| ApiError::invalid_request("文件上传未启用（files.enabled 为 false）"))?;
+                let (_, data) = files.read(&consumer.workspace, &id)?;
+                (parse_jsonl(&data)?, Some(id), concurrency)
+            }
+            None => (request.requests, None, concurrency),
+        }
+    };
+    let limit_key = limit_key.map(|Extension(key)| key);
+    Ok(Json(batches.start(&state, consumer, limit_key, requests, input_file_id, concurrency)?))
+}
+
+/// `GET /v1/batch`
+pub async fn list_batches(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+) -> ApiResult<Json<ListResponse<Batch>>> {
+    Ok(Json(ListResponse::new(batches(&state)?.list(&workspace))))
+}
+
+/// `GET /v1/batch/{id}`
+pub async fn get_batch(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+) -> ApiResult<Json<Batch>> {
+    Ok(Json(batches(&state)?.get(&workspace, &id)?))
+}
+
+/// `GET /v1/batch/{id}/results`，JSONL，运行中也可以下载已完成的部分
+pub async fn batch_results(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+) -> ApiResult<Response> {
+    let data = batches(&state)?.results(&workspace, &id)?;
+    Ok(([(CONTENT_TYPE, "application/jsonl")], data).into_response())
+}
+
+/// `POST /v1/batch/{id}/cancel`
+pub async fn cancel_batch(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+) -> ApiResult<Json<Batch>> {
+    Ok(Json(batches(&state)?.cancel(&workspace, &id).await?))
+}
+
+/// `DELETE /v1/batch/{id}`，运行中的批处理需要先取消
+pub async fn delete_batch(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+) -> ApiResult<Json<DeletedResponse>> {
+    batches(&state)?.delete(&workspace, &id)?;
+    Ok(Json(DeletedResponse {
+        id,
+        object: "batch".to_string(),
+        deleted: true,
+    }))
+}
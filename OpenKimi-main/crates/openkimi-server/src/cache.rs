@@ -0,0 +1,686 @@
+//! 对话回复缓存
+//!
+//! 非流式对话补全请求的模型、消息和参数完全相同时直接返回缓存的回复，不再请求上游。
+//! 启用`cache.semantic`后，之前的消息和参数相同、最后一条用户消息的嵌入向量与缓存中的请求
+//! 余弦相似度达到`cache.similarity_threshold`时也算命中，适合常见问题类的流量。
+//! 缓存按工作区隔离，超过`cache.ttl_seconds`的回复失效。缓存默认在内存中，条目数或总大小超出上限时淘汰最久未用的回复；
+//! `cache.store`为`redis`时保存在Redis中由各服务实例共用，条目由Redis按时间过期，不限条目数和大小，
+//! 命中次数等统计只计本实例。Redis不可用时按未命中处理，回复不缓存。
+//!
+//! 请求头`Cache-Control: no-cache`跳过查找但仍缓存新的回复，`no-store`既不查找也不缓存。
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::http::header::CACHE_CONTROL;
+use axum::http::{HeaderMap, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use openkimi_redis::{Arg, RedisError};
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::audit;
+use crate::config::{CacheConfig, StateStoreKind};
+use crate::redis::Redis;
+use crate::router::ModelRouter;
+use crate::types::{ChatCompletionRequest, ChatCompletionResponse, ChatMessage, EmbeddingInput, EmbeddingRequest};
+use crate::workspace::Workspace;
+use crate::AppState;
+
+/// 响应头，取值为`hit`、`semantic`或`miss`
+pub const CACHE_HEADER: &str = "x-openkimi-cache";
+
+type Key = [u8; 32];
+
+fn hash(value: &Value) -> Key {
+    let bytes = serde_json::to_vec(value).unwrap_or_default();
+    let mut key = [0u8; 32];
+    key.copy_from_slice(digest(&SHA256, &bytes).as_ref());
+    key
+}
+
+fn hex(key: &Key) -> String {
+    key.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// 请求头中的缓存指令
+#[derive(Debug, Clone, Copy)]
+pub struct CacheControl {
+    pub lookup: bool,
+    pub store: bool,
+}
+
+impl CacheControl {
+    pub fn from_headers(headers: &HeaderMap) -> CacheControl {
+        let directives: Vec<String> = headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|directive| directive.trim().to_ascii_lowercase())
+            .collect();
+        let has = |name: &str| directives.iter().any(|directive| directive == name);
+        CacheControl {
+            lookup: !has("no-cache") && !has("no-store"),
+            store: !has("no-store"),
+        }
+    }
+}
+
+/// 命中的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HitKind {
+    Exact,
+    Semantic,
+}
+
+impl HitKind {
+    fn header(self) -> &'static str {
+        match self {
+            HitKind::Exact => "hit",
+            HitKind::Semantic => "semantic",
+        }
+    }
+}
+
+/// 一次查找的结果，未命中时用于之后缓存上游的回复
+#[derive(Debug)]
+pub struct Lookup {
+    key: Key,
+    /// 最后一条消息之外的部分，语义匹配只在它相同的请求之间进行
+    context: Key,
+    workspace: String,
+    model: String,
+    embedding: Option<Vec<f32>>,
+    store: bool,
+    hit: Option<(ChatCompletionResponse, HitKind)>,
+}
+
+impl Lookup {
+    pub fn take_hit(&mut self) -> Option<(ChatCompletionResponse, HitKind)> {
+        self.hit.take()
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    workspace: String,
+    model: String,
+    context: Key,
+    embedding: Option<Vec<f32>>,
+    response: ChatCompletionResponse,
+    size: usize,
+    expires: Instant,
+    /// 最近一次使用的序号，用于淘汰最久未用的条目
+    used: u64,
+}
+
+/// 缓存的统计信息
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub hits: u64,
+    pub semantic_hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    entries: HashMap<Key, Entry>,
+    /// 使用序号到条目，最小的最久未用
+    lru: BTreeMap<u64, Key>,
+    /// 上下文相同的条目，语义匹配时只比较这些
+    groups: HashMap<Key, Vec<Key>>,
+    tick: u64,
+    stats: CacheStats,
+}
+
+impl Inner {
+    fn remove(&mut self, key: &Key) {
+        let Some(entry) = self.entries.remove(key) else {
+            return;
+        };
+        self.lru.remove(&entry.used);
+        self.stats.bytes -= entry.size;
+        if let Some(group) = self.groups.get_mut(&entry.context) {
+            group.retain(|member| member != key);
+            if group.is_empty() {
+                self.groups.remove(&entry.context);
+            }
+        }
+    }
+
+    /// 命中后更新使用序号，返回缓存的回复
+    fn touch(&mut self, key: &Key) -> Option<ChatCompletionResponse> {
+        self.tick += 1;
+        let tick = self.tick;
+        let entry = self.entries.get_mut(key)?;
+        self.lru.remove(&entry.used);
+        entry.used = tick;
+        self.lru.insert(tick, *key);
+        Some(entry.response.clone())
+    }
+
+    /// 与`embedding`最相似且达到阈值的未过期条目
+    fn nearest(&self, context: &Key, embedding: &[f32], threshold: f32, now: Instant) -> Option<Key> {
+        self.groups
+            .get(context)?
+            .iter()
+            .filter_map(|key| {
+                let entry = self.entries.get(key).filter(|entry| entry.expires > now)?;
+                let similarity = dot(entry.embedding.as_deref()?, embedding);
+                (similarity >= threshold).then_some((similarity, *key))
+            })
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, key)| key)
+    }
+}
+
+/// 保存在Redis中的条目，键为`<前缀>cache:<请求摘要>`，由Redis按`ttl_seconds`过期
+#[derive(Debug, Serialize, Deserialize)]
+struct SharedEntry {
+    #[serde(default)]
+    embedding: Option<Vec<f32>>,
+    response: ChatCompletionResponse,
+}
+
+/// 条目的索引，保存在哈希表`<前缀>cache:index`中，用于按工作区和模型删除、清理和统计
+#[derive(Debug, Serialize, Deserialize)]
+struct SharedIndex {
+    workspace: String,
+    model: String,
+    context: String,
+    size: usize,
+    /// 过期的Unix时间（毫秒）
+    expires: u64,
+}
+
+/// 保存在Redis中的缓存，各方法都是阻塞的
+#[derive(Debug, Clone)]
+struct Shared {
+    redis: Redis,
+    ttl: Duration,
+}
+
+impl Shared {
+    /// 在阻塞线程中执行
+    async fn run<T, F>(&self, f: F) -> Result<T, RedisError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Shared) -> Result<T, RedisError> + Send + 'static,
+    {
+        let shared = self.clone();
+        self.redis.run(move |_| f(&shared)).await
+    }
+
+    fn entry_key(&self, key: &str) -> String {
+        self.redis.key(&format!("cache:{}", key))
+    }
+
+    /// 上下文相同的条目，语义匹配时只比较这些
+    fn group_key(&self, context: &str) -> String {
+        self.redis.key(&format!("cache:group:{}", context))
+    }
+
+    fn index_key(&self) -> String {
+        self.redis.key("cache:index")
+    }
+
+    /// 按键批量读取，已过期的为`None`
+    fn entries(&self, keys: &[String]) -> Result<Vec<Option<SharedEntry>>, RedisError> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        let names: Vec<String> = keys.iter().map(|key| self.entry_key(key)).collect();
+        let mut args: Vec<&dyn Arg> = vec![&"MGET"];
+        args.extend(names.iter().map(|name| name as &dyn Arg));
+        let replies = self.redis.client().query(&args)?.into_array()?;
+        Ok(replies
+            .into_iter()
+            .map(|reply| reply.into_bytes().and_then(|bytes| serde_json::from_slice(&bytes).ok()))
+            .collect())
+    }
+
+    fn exact(&self, key: &Key) -> Result<Option<ChatCompletionResponse>, RedisError> {
+        let entry = self.entries(&[hex(key)])?.into_iter().next().flatten();
+        Ok(entry.map(|entry| entry.response))
+    }
+
+    /// 与`embedding`最相似且达到阈值的条目
+    fn nearest(
+        &self,
+        context: &Key,
+        embedding: &[f32],
+        threshold: f32,
+    ) -> Result<Option<ChatCompletionResponse>, RedisError> {
+        let members = self.redis.client().query(&[&"SMEMBERS", &self.group_key(&hex(context))])?;
+        let keys: Vec<String> = members.into_array()?.into_iter().filter_map(|member| member.into_string()).collect();
+        let nearest = self
+            .entries(&keys)?
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| {
+                let similarity = dot(entry.embedding.as_deref()?, embedding);
+                (similarity >= threshold).then_some((similarity, entry.response))
+            })
+            .max_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(nearest.map(|(_, response)| response))
+    }
+
+    fn store(&self, lookup: Lookup, response: ChatCompletionResponse) -> Result<(), RedisError> {
+        let key = hex(&lookup.key);
+        let context = hex(&lookup.context);
+        let ttl = self.ttl.as_millis() as u64;
+        let entry = serde_json::to_vec(&SharedEntry {
+            embedding: lookup.embedding,
+            response,
+        })
+        .map_err(|e| RedisError::Protocol(e.to_string()))?;
+        let index = serde_json::to_vec(&SharedIndex {
+            workspace: lookup.workspace,
+            model: lookup.model,
+            context: context.clone(),
+            size: entry.len(),
+            expires: audit::unix_ms() + ttl,
+        })
+        .map_err(|e| RedisError::Protocol(e.to_string()))?;
+        let client = self.redis.client();
+        client.query(&[&"SET", &self.entry_key(&key), &entry, &"PX", &ttl])?;
+        client.query(&[&"SADD", &self.group_key(&context), &key])?;
+        client.query(&[&"PEXPIRE", &self.group_key(&context), &ttl])?;
+        client.query(&[&"HSET", &self.index_key(), &key, &index])?;
+        Ok(())
+    }
+
+    /// 索引中的所有条目
+    fn index(&self) -> Result<Vec<(String, SharedIndex)>, RedisError> {
+        let items = self.redis.client().query(&[&"HGETALL", &self.index_key()])?.into_array()?;
+        let mut index = Vec::with_capacity(items.len() / 2);
+        let mut items = items.into_iter();
+        while let (Some(key), Some(value)) = (items.next(), items.next()) {
+            let (Some(key), Some(value)) = (key.into_string(), value.into_bytes()) else {
+                continue;
+            };
+            if let Ok(value) = serde_json::from_slice(&value) {
+                index.push((key, value));
+            }
+        }
+        Ok(index)
+    }
+
+    /// 删除条目及其索引
+    fn remove(&self, entries: &[(String, SharedIndex)]) -> Result<(), RedisError> {
+        let client = self.redis.client();
+        for (key, index) in entries {
+            client.query(&[&"DEL", &self.entry_key(key)])?;
+            client.query(&[&"SREM", &self.group_key(&index.context), key])?;
+            client.query(&[&"HDEL", &self.index_key(), key])?;
+        }
+        Ok(())
+    }
+}
+
+/// 向量已归一化，点积即余弦相似度
+pub(crate) fn dot(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+pub(crate) fn normalize(mut vector: Vec<f32>) -> Vec<f32> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+    vector
+}
+
+/// 对话回复缓存
+#[derive(Debug)]
+pub struct ResponseCache {
+    config: CacheConfig,
+    /// 启用语义匹配时使用的嵌入模型
+    embedding_model: Option<String>,
+    /// 内存中的条目和本实例的统计，使用Redis时只有统计
+    inner: Mutex<Inner>,
+    /// `cache.store`为`redis`时为`Some`
+    shared: Option<Shared>,
+}
+
+impl ResponseCache {
+    /// `cache.store`为`redis`时使用`redis`
+    pub fn new(
+        config: &CacheConfig,
+        default_embedding_model: Option<&str>,
+        redis: Option<&Redis>,
+    ) -> Result<ResponseCache, String> {
+        let embedding_model = if config.semantic {
+            let model = config.embedding_model.as_deref().or(default_embedding_model);
+            Some(model.ok_or("cache.semantic 为 true 时需要配置 cache.embedding_model 或 llm.embedding_model")?)
+        } else {
+            None
+        };
+        if !(0.0..=1.0).contains(&config.similarity_threshold) {
+            return Err("cache.similarity_threshold 必须在 0 到 1 之间".to_string());
+        }
+        Ok(ResponseCache {
+            config: config.clone(),
+            embedding_model: embedding_model.map(str::to_string),
+            inner: Mutex::new(Inner::default()),
+            shared: redis.filter(|_| config.store == StateStoreKind::Redis).map(|redis| Shared {
+                redis: redis.clone(),
+                ttl: Duration::from_secs(config.ttl_seconds),
+            }),
+        })
+    }
+
+    /// 最后一条用户消息的归一化嵌入向量，失败时只做精确匹配
+    async fn embed(&self, models: &ModelRouter, request: &ChatCompletionRequest) -> Option<Vec<f32>> {
+        let model = self.embedding_model.clone()?;
+        let last = request.messages.last().filter(|message| message.role == "user")?;
+        let text = last.text();
+        if text.trim().is_empty() {
+            return None;
+        }
+        let request = EmbeddingRequest {
+            model,
+            input: EmbeddingInput::Texts(vec![text]),
+            extra: Default::default(),
+        };
+        match models.embeddings(&request).await {
+            Ok(response) => {
+                let values = response.data.first()?.embedding.as_array()?;
+                let vector: Vec<f32> = values.iter().filter_map(Value::as_f64).map(|value| value as f32).collect();
+                Some(normalize(vector))
+            }
+            Err(err) => {
+                eprintln!("⚠️ 计算缓存的嵌入向量失败: {}", err);
+                None
+            }
+        }
+    }
+
+    pub async fn lookup(
+        &self,
+        models: &ModelRouter,
+        workspace: &Workspace,
+        request: &ChatCompletionRequest,
+        control: CacheControl,
+    ) -> Lookup {
+        let fingerprint = |messages: &[ChatMessage]| {
+            hash(&json!({
+                "workspace": workspace.name(),
+                "model": request.model,
+                "messages": messages,
+                "max_tokens": request.max_tokens,
+                "temperature": request.temperature,
+                "extra": request.extra,
+            }))
+        };
+        let key = fingerprint(&request.messages);
+        let context = fingerprint(&request.messages[..request.messages.len().saturating_sub(1)]);
+        let mut lookup = Lookup {
+            key,
+            context,
+            workspace: workspace.name().to_string(),
+            model: request.model.clone(),
+            embedding: None,
+            store: control.store,
+            hit: None,
+        };
+        if !control.lookup && !control.store {
+            return lookup;
+        }
+
+        if let Some(shared) = &self.shared {
+            return self.lookup_shared(shared, models, request, control, lookup).await;
+        }
+
+        let now = Instant::now();
+        if control.lookup {
+            let mut inner = self.inner.lock().unwrap();
+            match inner.entries.get(&key).map(|entry| entry.expires > now) {
+                Some(true) => {
+                    inner.stats.hits += 1;
+                    lookup.hit = inner.touch(&key).map(|response| (response, HitKind::Exact));
+                    return lookup;
+                }
+                Some(false) => inner.remove(&key),
+                None => {}
+            }
+        }
+
+        lookup.embedding = self.embed(models, request).await;
+        if control.lookup {
+            let mut inner = self.inner.lock().unwrap();
+            let threshold = self.config.similarity_threshold;
+            let nearest = lookup
+                .embedding
+                .as_deref()
+                .and_then(|embedding| inner.nearest(&context, embedding, threshold, now));
+            match nearest {
+                Some(nearest) => {
+                    inner.stats.semantic_hits += 1;
+                    lookup.hit = inner.touch(&nearest).map(|response| (response, HitKind::Semantic));
+                }
+                None => inner.stats.misses += 1,
+            }
+        }
+        lookup
+    }
+
+    /// 在Redis中查找，出错时按未命中处理
+    async fn lookup_shared(
+        &self,
+        shared: &Shared,
+        models: &ModelRouter,
+        request: &ChatCompletionRequest,
+        control: CacheControl,
+        mut lookup: Lookup,
+    ) -> Lookup {
+        let key = lookup.key;
+        if control.lookup {
+            match shared.run(move |shared| shared.exact(&key)).await {
+                Ok(Some(response)) => {
+                    self.inner.lock().unwrap().stats.hits += 1;
+                    lookup.hit = Some((response, HitKind::Exact));
+                    return lookup;
+                }
+                Ok(None) => {}
+                Err(err) => eprintln!("⚠️ 读取Redis中的回复缓存失败: {}", err),
+            }
+        }
+
+        lookup.embedding = self.embed(models, request).await;
+        if !control.lookup {
+            return lookup;
+        }
+        let nearest = match lookup.embedding.clone() {
+            Some(embedding) => {
+                let (context, threshold) = (lookup.context, self.config.similarity_threshold);
+                let result = shared.run(move |shared| shared.nearest(&context, &embedding, threshold)).await;
+                result.unwrap_or_else(|err| {
+                    eprintln!("⚠️ 读取Redis中的回复缓存失败: {}", err);
+                    None
+                })
+            }
+            None => None,
+        };
+        let mut inner = self.inner.lock().unwrap();
+        match nearest {
+            Some(response) => {
+                inner.stats.semantic_hits += 1;
+                lookup.hit = Some((response, HitKind::Semantic));
+            }
+            None => inner.stats.misses += 1,
+        }
+        lookup
+    }
+
+    /// 缓存上游的回复；被截断或出错的回复不缓存，保存到Redis时在后台写入
+    pub fn store(&self, lookup: Lookup, response: &ChatCompletionResponse) {
+        if !lookup.store || response.choices.iter().any(|choice| choice.finish_reason.as_deref() == Some("length")) {
+            return;
+        }
+        if let Some(shared) = &self.shared {
+            let shared = shared.clone();
+            let response = response.clone();
+            tokio::spawn(async move {
+                if let Err(err) = shared.run(move |shared| shared.store(lookup, response)).await {
+                    eprintln!("⚠️ 写入Redis中的回复缓存失败: {}", err);
+                }
+            });
+            return;
+        }
+        let size = serde_json::to_vec(response).map_or(0, |bytes| bytes.len())
+            + lookup.embedding.as_ref().map_or(0, |embedding| embedding.len() * 4);
+        if size > self.config.max_bytes || self.config.max_entries == 0 {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.remove(&lookup.key);
+        inner.tick += 1;
+        let used = inner.tick;
+        inner.lru.insert(used, lookup.key);
+        inner.groups.entry(lookup.context).or_default().push(lookup.key);
+        inner.stats.bytes += size;
+        inner.entries.insert(
+            lookup.key,
+            Entry {
+                workspace: lookup.workspace,
+                model: lookup.model,
+                context: lookup.context,
+                embedding: lookup.embedding,
+                response: response.clone(),
+                size,
+                expires: Instant::now() + Duration::from_secs(self.config.ttl_seconds),
+                used,
+            },
+        );
+        while inner.entries.len() > self.config.max_entries || inner.stats.bytes > self.config.max_bytes {
+            let Some((_, oldest)) = inner.lru.pop_first() else {
+                break;
+            };
+            inner.remove(&oldest);
+        }
+    }
+
+    /// 删除指定工作区和模型的缓存，都不指定时清空，返回删除的条目数
+    pub async fn invalidate(&self, workspace: Option<&str>, model: Option<&str>) -> Result<usize, String> {
+        if let Some(shared) = &self.shared {
+            let (workspace, model) = (workspace.map(str::to_string), model.map(str::to_string));
+            let deleted = shared.run(move |shared| {
+                let matched: Vec<_> = shared
+                    .index()?
+                    .into_iter()
+                    .filter(|(_, index)| workspace.as_ref().is_none_or(|workspace| &index.workspace == workspace))
+                    .filter(|(_, index)| model.as_ref().is_none_or(|model| &index.model == model))
+                    .collect();
+                shared.remove(&matched)?;
+                Ok(matched.len())
+            });
+            return deleted.await.map_err(|e| format!("删除Redis中的回复缓存失败: {}", e));
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        let keys: Vec<Key> = inner
+            .entries
+            .iter()
+            .filter(|(_, entry)| workspace.is_none_or(|workspace| entry.workspace == workspace))
+            .filter(|(_, entry)| model.is_none_or(|model| entry.model == model))
+            .map(|(key, _)| *key)
+            .collect();
+        for key in &keys {
+            inner.remove(key);
+        }
+        Ok(keys.len())
+    }
+
+    /// 删除已过期的条目，返回删除的条目数
+    ///
+    /// 过期条目在查找时不会命中，但在被淘汰前一直占用内存，由后台任务定期清理；
+    /// 使用Redis时条目已由Redis删除，这里清理留在索引中的记录。
+    pub async fn evict_expired(&self) -> Result<usize, String> {
+        if let Some(shared) = &self.shared {
+            let evicted = shared.run(|shared| {
+                let now = audit::unix_ms();
+                let expired: Vec<_> = shared.index()?.into_iter().filter(|(_, index)| index.expires <= now).collect();
+                shared.remove(&expired)?;
+                Ok(expired.len())
+            });
+            return evicted.await.map_err(|e| format!("清理Redis中的回复缓存失败: {}", e));
+        }
+
+        let now = Instant::now();
+        let mut inner = self.inner.lock().unwrap();
+        let keys: Vec<Key> = inner
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.expires <= now)
+            .map(|(key, _)| *key)
+            .collect();
+        for key in &keys {
+            inner.remove(key);
+        }
+        Ok(keys.len())
+    }
+
+    /// 使用Redis时条目数和大小按索引中未过期的条目统计，命中次数只计本实例
+    pub async fn stats(&self) -> CacheStats {
+        let stats = {
+            let inner = self.inner.lock().unwrap();
+            CacheStats {
+                entries: inner.entries.len(),
+                ..inner.stats.clone()
+            }
+        };
+        let Some(shared) = &self.shared else {
+            return stats;
+        };
+        let index = shared.run(Shared::index).await.unwrap_or_else(|err| {
+            eprintln!("⚠️ 读取Redis中的回复缓存失败: {}", err);
+            Vec::new()
+        });
+        let now = audit::unix_ms();
+        let live = index.iter().filter(|(_, index)| index.expires > now);
+        CacheStats {
+            entries: live.clone().count(),
+            bytes: live.map(|(_, index)| index.size).sum(),
+            ..stats
+        }
+    }
+}
+
+/// 查找非流式请求的缓存；未启用缓存或流式请求时返回`None`
+pub async fn lookup(
+    state: &AppState,
+    workspace: &Workspace,
+    request: &ChatCompletionRequest,
+    headers: &HeaderMap,
+) -> Option<Lookup> {
+    let cache = state.cache.as_ref()?;
+    if request.stream == Some(true) {
+        return None;
+    }
+    Some(cache.lookup(&state.models(), workspace, request, CacheControl::from_headers(headers)).await)
+}
+
+/// 缓存上游的回复
+pub fn store(state: &AppState, lookup: Lookup, response: &ChatCompletionResponse) {
+    if let Some(cache) = &state.cache {
+        cache.store(lookup, response);
+    }
+}
+
+/// 命中时的响应，带有说明命中方式的响应头
+pub fn hit_response(response: ChatCompletionResponse, kind: HitKind) -> Response {
+    let mut response = Json(response).into_response();
+    response.headers_mut().insert(CACHE_HEADER, HeaderValue::from_static(kind.header()));
+    response
+}
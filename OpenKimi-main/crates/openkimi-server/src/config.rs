@@ -0,0 +1,2525 @@
+//! 服务配置
+//!
+//! 与Python版`KimiEngine`共用同一份JSON配置文件，这里只读取`llm`、`backends`、`models`、`context`、`rag`、
+//! `sessions`、`vault`、`rate_limit`、`audit`、`auth`、`workspaces`、`metering`、`cache`、`tools`、
+//! `structured_output`、`prompts`、`files`、`vision`、`audio`、`speech`、`search`、`interpreter`、
+//! `plugins`、`mcp`、`agents`、`batch`、`memory`和`jobs`部分，其余字段忽略。
+//! `llm.api_key`和`llm.api_url`缺省时分别回退到`OPENAI_API_KEY`和`OPENAI_API_BASE`环境变量。
+
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use openkimi_rag::{ChunkConfig, EmbedderConfig, HnswConfig, RerankerConfig};
+use openkimi_vectorstore::{MilvusConfig, PgvectorConfig, QdrantConfig};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+const DEFAULT_MODEL: &str = "gpt-3.5-turbo";
+
+/// `llm`部分对应的后端名
+pub const DEFAULT_BACKEND: &str = "default";
+
+/// 上游服务的类型，决定默认地址和需要调整的请求参数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// OpenAI及其他完全兼容的接口，请求原样转发
+    #[default]
+    Openai,
+    Moonshot,
+    Ollama,
+    /// 本地的llama.cpp、vLLM等OpenAI兼容服务
+    Local,
+}
+
+impl BackendKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            BackendKind::Openai => "openai",
+            BackendKind::Moonshot => "moonshot",
+            BackendKind::Ollama => "ollama",
+            BackendKind::Local => "local",
+        }
+    }
+
+    /// 未配置`api_url`时使用的地址
+    pub fn default_url(self) -> &'static str {
+        match self {
+            BackendKind::Openai => "https://api.openai.com/v1",
+            BackendKind::Moonshot => "https://api.moonshot.cn/v1",
+            BackendKind::Ollama => "http://127.0.0.1:11434/v1",
+            BackendKind::Local => "http://127.0.0.1:8080/v1",
+        }
+    }
+}
+
+/// 一个上游后端：`llm`部分本身是名为`default`的后端，`backends`中可以再定义其他后端
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BackendConfig {
+    pub kind: BackendKind,
+    /// 缺省为该类型的默认地址
+    pub api_url: Option<String>,
+    pub api_key: Option<String>,
+    /// 从密钥库中取上游密钥时使用的服务商名，缺省同`kind`
+    pub provider: Option<String>,
+    /// `api_key`每分钟最多请求数，超出后暂时改用其他密钥或端点
+    pub rpm: Option<u32>,
+    /// 与`api_url`一起分担请求的其他上游端点
+    pub endpoints: Vec<EndpointConfig>,
+    pub load_balancing: LoadBalancing,
+    /// 一次请求最多尝试几个密钥或端点，遇到429、5xx或连接失败时换下一个
+    pub max_attempts: u32,
+    /// 由本服务启动llama.cpp加载的GGUF模型，只用于`local`后端，地址由其中的`port`决定
+    pub gguf: Option<GgufConfig>,
+}
+
+impl Default for BackendConfig {
+    fn default() -> Self {
+        BackendConfig {
+            kind: BackendKind::default(),
+            api_url: None,
+            api_key: None,
+            provider: None,
+            rpm: None,
+            endpoints: Vec::new(),
+            load_balancing: LoadBalancing::default(),
+            max_attempts: 3,
+            gguf: None,
+        }
+    }
+}
+
+impl BackendConfig {
+    /// 上游API地址，不带末尾的`/`
+    pub fn api_url(&self) -> String {
+        if let Some(url) = self.api_url.as_deref().filter(|url| !url.is_empty()) {
+            return url.trim_end_matches('/').to_string();
+        }
+        match &self.gguf {
+            Some(gguf) => format!("http://127.0.0.1:{}/v1", gguf.port),
+            None => self.kind.default_url().to_string(),
+        }
+    }
+
+    pub fn provider(&self) -> &str {
+        self.provider.as_deref().unwrap_or(self.kind.name())
+    }
+}
+
+/// 后端的`gguf`部分：启动llama.cpp的`llama-server`时使用的参数
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct GgufConfig {
+    /// GGUF模型文件，不存在时在`model_store.dir`中按文件名查找，可以省略`.gguf`
+    pub model: PathBuf,
+    /// `llama-server`可执行文件，缺省在`PATH`中查找
+    pub server: PathBuf,
+    /// 只监听`127.0.0.1`上的这个端口
+    pub port: u16,
+    /// 上下文窗口的token数
+    pub context_length: u32,
+    /// 运行模型的设备，如`CUDA0`、`Metal`、`Vulkan0`，`cpu`为只用CPU；缺省由llama.cpp选择，
+    /// 可用的设备见`GET /v1/system/devices`
+    pub device: Option<String>,
+    /// 放到GPU上的层数，0为只用CPU
+    pub gpu_layers: u32,
+    /// 推理线程数，缺省由llama.cpp决定
+    pub threads: Option<u32>,
+    /// 附加的命令行参数
+    pub args: Vec<String>,
+    /// `llama-server`的输出追加到这个文件，缺省丢弃
+    pub log: Option<PathBuf>,
+    /// 超过这么多秒仍未加载完成时打印警告
+    pub startup_timeout_seconds: u64,
+}
+
+impl Default for GgufConfig {
+    fn default() -> Self {
+        GgufConfig {
+            model: PathBuf::new(),
+            server: PathBuf::from("llama-server"),
+            port: 8080,
+            context_length: 4096,
+            device: None,
+            gpu_layers: 0,
+            threads: None,
+            args: Vec::new(),
+            log: None,
+            startup_timeout_seconds: 120,
+        }
+    }
+}
+
+/// 配置文件中的`llm`部分
+#[derive(Debug, Clone, Deserialize)]
+pub struct LlmConfig {
+    /// 默认后端，模型名不在`models`中时使用
+    #[serde(flatten)]
+    pub backend: BackendConfig,
+    #[serde(default = "default_model")]
+    pub model_name: String,
+    /// `/v1/embeddings`未指定模型时使用的上游嵌入模型
+    #[serde(default)]
+    pub embedding_model: Option<String>,
+    #[serde(default)]
+    pub context_length: Option<u32>,
+}
+
+/// 上游端点配置中的一项
+#[derive(Debug, Clone, Deserialize)]
+pub struct EndpointConfig {
+    /// 出现在管理接口和日志中，缺省为`endpoint-<序号>`
+    #[serde(default)]
+    pub name: Option<String>,
+    pub api_url: String,
+    /// 缺省使用所属后端的`api_key`
+    #[serde(default)]
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub rpm: Option<u32>,
+}
+
+/// 在多个密钥或端点之间分配请求的方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadBalancing {
+    /// 依次轮流
+    #[default]
+    RoundRobin,
+    /// 优先使用连续失败和累计失败次数最少的
+    LeastErrors,
+}
+
+fn default_model() -> String {
+    DEFAULT_MODEL.to_string()
+}
+
+impl Default for LlmConfig {
+    fn default() -> Self {
+        LlmConfig {
+            backend: BackendConfig::default(),
+            model_name: default_model(),
+            embedding_model: None,
+            context_length: None,
+        }
+    }
+}
+
+/// `models`中的一项：逻辑模型名对应的后端和实际模型
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModelRoute {
+    /// `backends`中的名称，缺省为`default`即`llm`部分
+    #[serde(default = "default_backend")]
+    pub backend: String,
+    /// 后端使用的模型id
+    pub model: String,
+    /// 上下文窗口，缺省按实际模型查内置表
+    #[serde(default)]
+    pub context_length: Option<u32>,
+    /// 该模型接受的图片，缺省同`vision`部分
+    #[serde(default)]
+    pub vision: Option<VisionLimits>,
+    /// 该模型失败时依次改用的其他模型名，见`fallback`部分
+    #[serde(default)]
+    pub fallbacks: Vec<String>,
+    /// 等待该模型响应的最长秒数，流式请求只等到开始输出；缺省不限
+    #[serde(default)]
+    pub timeout_seconds: Option<u64>,
+}
+
+fn default_backend() -> String {
+    DEFAULT_BACKEND.to_string()
+}
+
+/// 改用下一个模型的条件
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FallbackTrigger {
+    /// 连接失败，或各密钥和端点都返回429、5xx、401/403
+    Error,
+    /// 超过模型的`timeout_seconds`
+    Timeout,
+    /// 上游因内容审核拒绝请求或截断回复
+    ContentFilter,
+}
+
+/// 配置文件中的`fallback`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FallbackConfig {
+    /// 在哪些情况下改用`models.<名称>.fallbacks`中的下一个模型
+    pub on: Vec<FallbackTrigger>,
+}
+
+impl Default for FallbackConfig {
+    fn default() -> Self {
+        FallbackConfig {
+            on: vec![FallbackTrigger::Error, FallbackTrigger::Timeout, FallbackTrigger::ContentFilter],
+        }
+    }
+}
+
+/// 对话超出上下文窗口时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionKind {
+    /// 分层摘要较早的消息
+    Hierarchical,
+    /// 丢弃最早的消息
+    Truncate,
+    /// 不压缩，直接拒绝请求
+    Off,
+}
+
+/// 配置文件中的`context`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ContextConfig {
+    pub strategy: CompressionKind,
+    /// 压缩后原样保留的最近消息最多占提示词预算的比例，其余留给摘要
+    pub recent_ratio: f32,
+    /// 每次摘要的原文长度（token）
+    pub chunk_tokens: usize,
+    /// 请求未指定`max_tokens`时为回复预留的token数
+    pub reserve_tokens: u32,
+    /// 生成摘要使用的模型，缺省为`llm.model_name`
+    pub summary_model: Option<String>,
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        ContextConfig {
+            strategy: CompressionKind::Hierarchical,
+            recent_ratio: 0.5,
+            chunk_tokens: 2000,
+            reserve_tokens: 1024,
+            summary_model: None,
+        }
+    }
+}
+
+/// 向量索引的存储方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreKind {
+    /// 逐条比较，索引文件为`<名称>.jsonl`
+    Flat,
+    /// HNSW图，索引文件为`<名称>.hnsw`
+    Hnsw,
+    Qdrant,
+    Pgvector,
+    Milvus,
+}
+
+impl StoreKind {
+    /// 本地索引文件的扩展名，外部数据库为`None`
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            StoreKind::Flat => Some("jsonl"),
+            StoreKind::Hnsw => Some("hnsw"),
+            StoreKind::Qdrant | StoreKind::Pgvector | StoreKind::Milvus => None,
+        }
+    }
+}
+
+/// 配置文件中的`rag`部分，其中顶层的`top_k`等字段是Python版使用的，这里忽略，检索参数见`retrieval`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RagConfig {
+    /// 索引文件所在目录
+    pub index_dir: PathBuf,
+    pub store: StoreKind,
+    pub hnsw: HnswConfig,
+    pub qdrant: QdrantConfig,
+    pub pgvector: PgvectorConfig,
+    pub milvus: MilvusConfig,
+    /// 导入时未指定索引名使用的索引
+    pub default_index: String,
+    pub chunking: ChunkConfig,
+    /// 按文件类型（`pdf`、`docx`、`html`、`markdown`、`text`、`code`）覆盖的分块配置
+    pub chunking_by_kind: HashMap<String, ChunkConfig>,
+    /// 使用上游嵌入接口时每次请求提交的分块数
+    pub batch_size: usize,
+    /// 默认的嵌入模型，缺省时使用上游的`llm.embedding_model`
+    pub embedder: Option<EmbedderConfig>,
+    /// 默认的重排模型，缺省时不重排
+    pub reranker: Option<RerankerConfig>,
+    /// 默认的检索参数
+    pub retrieval: RetrievalConfig,
+    /// 按索引名覆盖的配置
+    pub indexes: HashMap<String, IndexConfig>,
+}
+
+/// 检索方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RetrievalMode {
+    /// 按查询的向量检索
+    #[default]
+    Vector,
+    /// 按关键词（BM25等全文检索）检索，适合查找标识符、错误码和名称
+    Keyword,
+    /// 两者各取候选，用RRF融合；存储不支持关键词检索时退回纯向量检索
+    Hybrid,
+}
+
+/// 检索参数
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RetrievalConfig {
+    /// 返回的分块数
+    pub top_k: usize,
+    /// 重排时先检索出的候选数，不重排时不使用
+    pub candidates: usize,
+    pub mode: RetrievalMode,
+    /// 配置了重排模型时是否重排
+    pub rerank: bool,
+    /// 检索得分低于这个值的候选丢弃，得分的含义随`mode`不同，见[`openkimi_rag::SearchHit::score`]
+    pub min_score: Option<f32>,
+    /// 重排得分低于这个值的候选丢弃，不重排时不使用
+    pub min_rerank_score: Option<f32>,
+}
+
+impl Default for RetrievalConfig {
+    fn default() -> Self {
+        RetrievalConfig {
+            top_k: 4,
+            candidates: 20,
+            mode: RetrievalMode::Vector,
+            rerank: true,
+            min_score: None,
+            min_rerank_score: None,
+        }
+    }
+}
+
+/// 单个索引的配置
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct IndexConfig {
+    /// 该索引使用的嵌入模型，缺省时同`rag.embedder`
+    pub embedder: Option<EmbedderConfig>,
+    /// 该索引使用的重排模型，缺省时同`rag.reranker`
+    pub reranker: Option<RerankerConfig>,
+    /// 该索引的检索参数，整体替换`rag.retrieval`
+    pub retrieval: Option<RetrievalConfig>,
+    /// 导入该索引的默认分块配置，缺省时同`rag.chunking`
+    pub chunking: Option<ChunkConfig>,
+}
+
+impl RagConfig {
+    /// 索引`name`使用的嵌入模型配置，`None`表示使用上游嵌入接口
+    pub fn embedder(&self, name: &str) -> Option<&EmbedderConfig> {
+        self.indexes
+            .get(name)
+            .and_then(|index| index.embedder.as_ref())
+            .or(self.embedder.as_ref())
+    }
+
+    /// 索引`name`使用的重排模型配置，`None`表示不重排
+    pub fn reranker(&self, name: &str) -> Option<&RerankerConfig> {
+        self.indexes
+            .get(name)
+            .and_then(|index| index.reranker.as_ref())
+            .or(self.reranker.as_ref())
+    }
+
+    /// 导入索引`name`的默认分块配置
+    pub fn chunking(&self, name: &str) -> &ChunkConfig {
+        self.indexes
+            .get(name)
+            .and_then(|index| index.chunking.as_ref())
+            .unwrap_or(&self.chunking)
+    }
+
+    /// 索引`name`的检索参数
+    pub fn retrieval(&self, name: &str) -> &RetrievalConfig {
+        self.indexes
+            .get(name)
+            .and_then(|index| index.retrieval.as_ref())
+            .unwrap_or(&self.retrieval)
+    }
+}
+
+impl Default for RagConfig {
+    fn default() -> Self {
+        RagConfig {
+            index_dir: PathBuf::from("data/indexes"),
+            store: StoreKind::Hnsw,
+            hnsw: HnswConfig::default(),
+            qdrant: QdrantConfig::default(),
+            pgvector: PgvectorConfig::default(),
+            milvus: MilvusConfig::default(),
+            default_index: "default".to_string(),
+            chunking: ChunkConfig::default(),
+            chunking_by_kind: HashMap::new(),
+            batch_size: 64,
+            embedder: None,
+            reranker: None,
+            retrieval: RetrievalConfig::default(),
+            indexes: HashMap::new(),
+        }
+    }
+}
+
+/// 会话的存储方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionStoreKind {
+    /// 保存在`sessions.path`指定的SQLite数据库中
+    #[default]
+    Sqlite,
+    /// 保存在`postgres`部分指定的PostgreSQL数据库中，多个服务实例共享
+    Postgres,
+    /// 保存在`redis`部分指定的Redis中，多个服务实例共享
+    Redis,
+}
+
+/// 用量等记录的存储方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseKind {
+    /// 保存在本地的SQLite数据库文件中
+    #[default]
+    Sqlite,
+    /// 保存在`postgres`部分指定的PostgreSQL数据库中，多个服务实例共享
+    Postgres,
+}
+
+/// 限流、缓存等运行状态的存储方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StateStoreKind {
+    /// 保存在本进程内存中，每个服务实例各自一份
+    #[default]
+    Memory,
+    /// 保存在`redis`部分指定的Redis中，多个服务实例共享
+    Redis,
+}
+
+/// 配置文件中的`sessions`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SessionsConfig {
+    /// 为`false`时不打开会话数据库，`/v1/sessions`接口不可用
+    pub enabled: bool,
+    pub store: SessionStoreKind,
+    /// SQLite数据库文件
+    pub path: PathBuf,
+    /// `POST /v1/sessions/import/external`请求体的上限，ChatGPT的导出文件可能有几百MB
+    pub max_import_bytes: usize,
+}
+
+impl Default for SessionsConfig {
+    fn default() -> Self {
+        SessionsConfig {
+            enabled: true,
+            store: SessionStoreKind::Sqlite,
+            path: PathBuf::from("data/sessions.db"),
+            max_import_bytes: 256 * 1024 * 1024,
+        }
+    }
+}
+
+/// 配置文件中的`vault`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct VaultConfig {
+    /// 为`true`时从密钥库中轮流取上游密钥，库中没有该服务商的可用密钥时仍使用`llm.api_key`
+    pub enabled: bool,
+    /// 加密保存密钥的文件
+    pub path: PathBuf,
+    /// 未设置`OPENKIMI_VAULT_KEY`时读取的主密钥文件
+    pub key_file: PathBuf,
+    /// `/admin`接口的令牌，缺省时读取`OPENKIMI_ADMIN_TOKEN`；都没有时不提供管理接口
+    pub admin_token: Option<String>,
+}
+
+impl Default for VaultConfig {
+    fn default() -> Self {
+        VaultConfig {
+            enabled: false,
+            path: PathBuf::from("data/vault.json"),
+            key_file: PathBuf::from("data/vault.key"),
+            admin_token: None,
+        }
+    }
+}
+
+/// 限流按什么区分客户端
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKeyKind {
+    /// `Authorization: Bearer`中的API密钥
+    #[default]
+    ApiKey,
+    /// `user_header`指定的请求头
+    User,
+    /// 客户端IP
+    Ip,
+}
+
+/// 配置文件中的`rate_limit`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    /// 取不到API密钥或用户时按IP
+    pub key_by: RateLimitKeyKind,
+    /// `key_by`为`user`时读取的请求头
+    pub user_header: String,
+    pub requests_per_minute: u32,
+    /// 每分钟最多消耗的token数，缺省不限
+    pub tokens_per_minute: Option<u32>,
+    /// 服务在反向代理之后时为`true`，按`X-Forwarded-For`中的第一个地址区分IP，审计日志同样使用
+    pub trust_proxy: bool,
+    /// 为`redis`时各服务实例共用同一组令牌桶
+    pub store: StateStoreKind,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            enabled: false,
+            key_by: RateLimitKeyKind::default(),
+            user_header: "x-openkimi-user".to_string(),
+            requests_per_minute: 60,
+            tokens_per_minute: None,
+            trust_proxy: false,
+            store: StateStoreKind::Memory,
+        }
+    }
+}
+
+/// 审计日志的输出方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditFormat {
+    /// 按天写入`dir`下的`audit-YYYY-MM-DD.jsonl`
+    #[default]
+    Jsonl,
+    /// 以OTLP/HTTP（JSON编码）发送到`otlp_endpoint`
+    Otlp,
+    /// 写入`postgres`部分指定的数据库的`audit_log`表
+    Postgres,
+}
+
+/// 自定义的脱敏规则
+#[derive(Debug, Clone, Deserialize)]
+pub struct RedactPattern {
+    /// 正则表达式
+    pub pattern: String,
+    #[serde(default = "default_replacement")]
+    pub replacement: String,
+}
+
+fn default_replacement() -> String {
+    "[REDACTED]".to_string()
+}
+
+/// 写入审计日志前对提示词和回复做的脱敏
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RedactConfig {
+    /// 把邮箱地址替换为`[EMAIL]`
+    pub emails: bool,
+    /// 把`sk-...`等API密钥和Bearer令牌替换为`[API_KEY]`
+    pub api_keys: bool,
+    pub patterns: Vec<RedactPattern>,
+}
+
+impl Default for RedactConfig {
+    fn default() -> Self {
+        RedactConfig {
+            emails: true,
+            api_keys: true,
+            patterns: Vec::new(),
+        }
+    }
+}
+
+/// 配置文件中的`audit`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    pub enabled: bool,
+    pub format: AuditFormat,
+    /// `jsonl`格式的日志目录
+    pub dir: PathBuf,
+    /// `jsonl`和`postgres`格式的日志保留天数，0表示不删除
+    pub retention_days: u32,
+    pub otlp_endpoint: String,
+    /// 发送OTLP时附加的请求头，如认证信息
+    pub otlp_headers: HashMap<String, String>,
+    /// 为`false`时只记录模型、状态和用量，不记录提示词和回复
+    pub include_bodies: bool,
+    /// 请求或响应体超过该字节数时不记录内容
+    pub max_body_bytes: usize,
+    pub redact: RedactConfig,
+    /// 区分租户的请求头
+    pub tenant_header: String,
+    /// 不记录审计日志的租户
+    pub opt_out: Vec<String>,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        AuditConfig {
+            enabled: false,
+            format: AuditFormat::default(),
+            dir: PathBuf::from("data/audit"),
+            retention_days: 30,
+            otlp_endpoint: "http://127.0.0.1:4318/v1/logs".to_string(),
+            otlp_headers: HashMap::new(),
+            include_bodies: true,
+            max_body_bytes: 64 * 1024,
+            redact: RedactConfig::default(),
+            tenant_header: "x-openkimi-tenant".to_string(),
+            opt_out: Vec::new(),
+        }
+    }
+}
+
+/// 配置文件中的`audit_trail`部分：带哈希链的安全事件日志
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AuditTrailConfig {
+    pub enabled: bool,
+    /// 只追加的JSON Lines文件
+    pub path: PathBuf,
+    /// 同时发送到该syslog地址（UDP，`主机:端口`），缺省不发送
+    pub syslog: Option<String>,
+}
+
+impl Default for AuditTrailConfig {
+    fn default() -> Self {
+        AuditTrailConfig {
+            enabled: false,
+            path: PathBuf::from("data/audit_trail.jsonl"),
+            syslog: None,
+        }
+    }
+}
+
+/// 静态API令牌
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiTokenConfig {
+    /// 令牌的名称，作为调用方的身份出现在审计日志中，也用于按用户限流
+    pub name: String,
+    pub token: String,
+    /// 令牌拥有的权限，`*`表示全部
+    #[serde(default = "all_scopes")]
+    pub scopes: Vec<String>,
+}
+
+fn all_scopes() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+/// 校验JWT的配置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct JwtConfig {
+    /// 签发方，JWT的`iss`必须与之相同
+    pub issuer: String,
+    /// 设置时JWT的`aud`必须包含该值
+    pub audience: Option<String>,
+    /// 缺省时通过OIDC发现从`{issuer}/.well-known/openid-configuration`获取
+    pub jwks_uri: Option<String>,
+    /// HS256的共享密钥，缺省时不接受HS256
+    pub secret: Option<String>,
+    /// 存放权限的声明，取值为空格分隔的字符串或字符串数组
+    pub scope_claim: String,
+    /// 作为调用方身份的声明
+    pub subject_claim: String,
+    /// 存放工作区名的声明，缺省时JWT的调用方都属于默认工作区
+    pub workspace_claim: Option<String>,
+    /// 校验`exp`和`nbf`时允许的时钟偏差
+    pub leeway_seconds: u64,
+    /// JWKS的缓存时间；遇到未知的`kid`时最多每分钟重新获取一次
+    pub jwks_refresh_seconds: u64,
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        JwtConfig {
+            issuer: String::new(),
+            audience: None,
+            jwks_uri: None,
+            secret: None,
+            scope_claim: "scope".to_string(),
+            subject_claim: "sub".to_string(),
+            workspace_claim: None,
+            leeway_seconds: 60,
+            jwks_refresh_seconds: 3600,
+        }
+    }
+}
+
+/// 按路径的授权规则
+#[derive(Debug, Clone, Deserialize)]
+pub struct PolicyConfig {
+    /// 请求路径，以`*`结尾时按前缀匹配
+    pub path: String,
+    /// 适用的HTTP方法，缺省为全部
+    #[serde(default)]
+    pub methods: Vec<String>,
+    /// 需要同时拥有的权限
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// 为`true`时无需认证
+    #[serde(default)]
+    pub public: bool,
+}
+
+/// 配置文件中的`auth`部分
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub enabled: bool,
+    pub tokens: Vec<ApiTokenConfig>,
+    pub jwt: Option<JwtConfig>,
+    /// 按顺序匹配，第一条匹配的规则生效；没有匹配的规则时只要求认证
+    pub policies: Vec<PolicyConfig>,
+}
+
+/// 配置文件中的`users`部分：保存在数据库中、通过管理接口或命令行维护的用户和API令牌
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct UsersConfig {
+    pub enabled: bool,
+    /// 用户数据库文件
+    pub path: PathBuf,
+}
+
+impl Default for UsersConfig {
+    fn default() -> Self {
+        UsersConfig {
+            enabled: false,
+            path: PathBuf::from("data/users.db"),
+        }
+    }
+}
+
+/// 配置文件中的`license`部分：企业版许可证，设置了`key`或`key_file`时启动时校验
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LicenseConfig {
+    /// 许可证密钥，`OKL1.`开头
+    pub key: Option<String>,
+    /// 从文件读取许可证密钥，与`key`二选一
+    pub key_file: Option<PathBuf>,
+    /// 校验签名用的公钥（base64），缺省使用构建时内嵌的公钥
+    pub public_key: Option<String>,
+    /// 过期后仍可使用的天数
+    pub grace_period_days: u64,
+}
+
+impl LicenseConfig {
+    pub fn is_configured(&self) -> bool {
+        self.key.is_some() || self.key_file.is_some()
+    }
+}
+
+impl Default for LicenseConfig {
+    fn default() -> Self {
+        LicenseConfig {
+            key: None,
+            key_file: None,
+            public_key: None,
+            grace_period_days: openkimi_licensing::DEFAULT_GRACE_DAYS,
+        }
+    }
+}
+
+/// 工作区的配额，按UTC的自然日和自然月计算，缺省不限
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct QuotaConfig {
+    pub requests_per_day: Option<u64>,
+    pub tokens_per_day: Option<u64>,
+    pub tokens_per_month: Option<u64>,
+}
+
+/// 单个工作区
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct WorkspaceConfig {
+    /// 属于该工作区的API令牌，启用认证时生效
+    pub tokens: Vec<ApiTokenConfig>,
+    pub quota: QuotaConfig,
+}
+
+/// 配置文件中的`workspaces`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WorkspacesConfig {
+    pub enabled: bool,
+    /// 未启用认证时选择工作区的请求头
+    pub header: String,
+    /// 各工作区累计用量的保存位置
+    pub usage_path: PathBuf,
+    /// 按名称配置的工作区，`default`工作区总是存在
+    pub list: BTreeMap<String, WorkspaceConfig>,
+}
+
+impl Default for WorkspacesConfig {
+    fn default() -> Self {
+        WorkspacesConfig {
+            enabled: false,
+            header: "x-openkimi-workspace".to_string(),
+            usage_path: PathBuf::from("data/workspace_usage.json"),
+            list: BTreeMap::new(),
+        }
+    }
+}
+
+/// 模型的单价，按每百万token计
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PriceConfig {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+impl PriceConfig {
+    /// 按单价计算一次请求的费用
+    pub fn cost(&self, prompt_tokens: u32, completion_tokens: u32) -> f64 {
+        (prompt_tokens as f64 * self.prompt + completion_tokens as f64 * self.completion) / 1_000_000.0
+    }
+}
+
+/// 配置文件中的`metering`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MeteringConfig {
+    pub enabled: bool,
+    /// 为`postgres`时写入`postgres`部分指定的数据库，多个服务实例的用量汇总在一起，`path`不起作用
+    pub store: DatabaseKind,
+    /// 用量数据库文件
+    pub path: PathBuf,
+    /// 未启用认证时区分用户的请求头
+    pub user_header: String,
+    /// 导出时标注的币种，只作说明，不做换算
+    pub currency: String,
+    /// 按客户端请求的模型名配置单价，未配置的模型费用为0
+    pub prices: HashMap<String, PriceConfig>,
+}
+
+impl Default for MeteringConfig {
+    fn default() -> Self {
+        MeteringConfig {
+            enabled: false,
+            store: DatabaseKind::Sqlite,
+            path: PathBuf::from("data/usage.db"),
+            user_header: "x-openkimi-user".to_string(),
+            currency: "CNY".to_string(),
+            prices: HashMap::new(),
+        }
+    }
+}
+
+/// 用户或团队的配额，每日token数按UTC的自然日、每月费用按UTC的自然月计算，缺省不限
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct UserQuotaConfig {
+    pub tokens_per_day: Option<u64>,
+    /// 按`metering.prices`的单价计算，币种为`metering.currency`
+    pub cost_per_month: Option<f64>,
+    /// 同时进行的请求数上限，流式响应在结束前一直占用
+    pub max_concurrent: Option<u32>,
+}
+
+/// 单个团队
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TeamConfig {
+    /// 成员的用户名，与计量中的用户一致；一个用户可以属于多个团队
+    pub members: Vec<String>,
+    /// 全体成员共用的配额
+    pub quota: UserQuotaConfig,
+}
+
+/// 配置文件中的`quotas`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct QuotasConfig {
+    pub enabled: bool,
+    /// 累计用量和通过管理接口调整的配额的保存位置
+    pub path: PathBuf,
+    /// `users`中没有单独配置的用户各自的配额
+    pub default: UserQuotaConfig,
+    pub users: BTreeMap<String, UserQuotaConfig>,
+    pub teams: BTreeMap<String, TeamConfig>,
+}
+
+impl Default for QuotasConfig {
+    fn default() -> Self {
+        QuotasConfig {
+            enabled: false,
+            path: PathBuf::from("data/quotas.json"),
+            default: UserQuotaConfig::default(),
+            users: BTreeMap::new(),
+            teams: BTreeMap::new(),
+        }
+    }
+}
+
+/// 配置文件中的`cache`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    /// 缓存的回复保留多久
+    pub ttl_seconds: u64,
+    /// 最多缓存的回复数
+    pub max_entries: usize,
+    /// 缓存的回复总大小上限（字节）
+    pub max_bytes: usize,
+    /// 为`true`时，最后一条用户消息与缓存中的相似度达到`similarity_threshold`也算命中
+    pub semantic: bool,
+    pub similarity_threshold: f32,
+    /// 计算相似度的嵌入模型，缺省为`llm.embedding_model`
+    pub embedding_model: Option<String>,
+    /// 为`redis`时各服务实例共用缓存，条目按`ttl_seconds`过期，`max_entries`和`max_bytes`不起作用
+    pub store: StateStoreKind,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            enabled: false,
+            ttl_seconds: 3600,
+            max_entries: 10_000,
+            max_bytes: 64 * 1024 * 1024,
+            semantic: false,
+            similarity_threshold: 0.95,
+            embedding_model: None,
+            store: StateStoreKind::Memory,
+        }
+    }
+}
+
+/// 由服务端执行的工具，调用时把参数以JSON POST到`url`，响应体作为工具结果交给模型
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServerToolConfig {
+    #[serde(default)]
+    pub description: Option<String>,
+    /// 参数的JSON Schema，缺省为没有参数
+    #[serde(default = "no_parameters")]
+    pub parameters: Value,
+    pub url: String,
+    /// 附加的请求头，如认证信息
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default = "default_tool_timeout")]
+    pub timeout_seconds: u64,
+}
+
+fn no_parameters() -> Value {
+    json!({ "type": "object", "properties": {} })
+}
+
+fn default_tool_timeout() -> u64 {
+    30
+}
+
+/// 配置文件中的`tools`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ToolsConfig {
+    pub enabled: bool,
+    /// 一个请求中最多执行几轮工具调用，超出后把工具调用原样返回给客户端
+    pub max_iterations: u32,
+    /// 工具结果的最大字节数，超出部分截断
+    pub max_result_bytes: usize,
+    /// 按函数名配置的服务端工具
+    pub functions: BTreeMap<String, ServerToolConfig>,
+}
+
+impl Default for ToolsConfig {
+    fn default() -> Self {
+        ToolsConfig {
+            enabled: false,
+            max_iterations: 5,
+            max_result_bytes: 32 * 1024,
+            functions: BTreeMap::new(),
+        }
+    }
+}
+
+/// 配置文件中的`structured_output`部分，请求中带`response_format`时生效
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StructuredOutputConfig {
+    /// 输出不符合要求时最多让模型重新生成几次
+    pub max_retries: u32,
+    /// 是否先尝试从输出中提取JSON，如去掉Markdown代码块
+    pub repair: bool,
+}
+
+impl Default for StructuredOutputConfig {
+    fn default() -> Self {
+        StructuredOutputConfig {
+            max_retries: 2,
+            repair: true,
+        }
+    }
+}
+
+/// 提示词模板的存储方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PromptStoreKind {
+    /// 每个版本一个JSON文件，保存在`prompts.dir`下
+    #[default]
+    File,
+    /// 保存在`prompts.database`指定的SQLite数据库中
+    Sqlite,
+}
+
+/// 配置文件中的`prompts`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PromptsConfig {
+    pub enabled: bool,
+    pub store: PromptStoreKind,
+    pub dir: PathBuf,
+    pub database: PathBuf,
+    /// 单个模板的最大字节数
+    pub max_template_bytes: usize,
+}
+
+impl Default for PromptsConfig {
+    fn default() -> Self {
+        PromptsConfig {
+            enabled: false,
+            store: PromptStoreKind::File,
+            dir: PathBuf::from("data/prompts"),
+            database: PathBuf::from("data/prompts.db"),
+            max_template_bytes: 64 * 1024,
+        }
+    }
+}
+
+/// 上传文件的病毒扫描，`url`和`command`都未配置时不扫描
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ScanConfig {
+    /// 扫描命令及参数，`{path}`替换为文件路径；退出码0为无毒，1为发现病毒，如`["clamscan", "--no-summary", "{path}"]`
+    pub command: Vec<String>,
+    /// 扫描接口，文件内容POST到这里，响应`{"clean": true}`为无毒；配置后优先于`command`
+    pub url: Option<String>,
+    pub timeout_seconds: u64,
+    /// 扫描本身失败（超时、命令无法运行等）时是否放行
+    pub fail_open: bool,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        ScanConfig {
+            command: Vec::new(),
+            url: None,
+            timeout_seconds: 60,
+            fail_open: false,
+        }
+    }
+}
+
+/// 配置文件中的`files`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FilesConfig {
+    pub enabled: bool,
+    pub dir: PathBuf,
+    /// 单个文件的最大字节数
+    pub max_file_bytes: u64,
+    /// 每块的最大字节数，也是不分块直接上传的上限
+    pub max_chunk_bytes: u64,
+    /// 文件保留的秒数，0为永久保留；上传时可以用`expires_after`单独指定
+    pub ttl_seconds: u64,
+    /// 上传任务创建后多久仍未完成即删除
+    pub upload_ttl_seconds: u64,
+    /// 允许的文件类型（按内容识别），以`/`结尾的按前缀匹配，为空时允许所有类型
+    pub allowed_types: Vec<String>,
+    pub scan: ScanConfig,
+}
+
+impl Default for FilesConfig {
+    fn default() -> Self {
+        FilesConfig {
+            enabled: false,
+            dir: PathBuf::from("data/files"),
+            max_file_bytes: 100 * 1024 * 1024,
+            max_chunk_bytes: 8 * 1024 * 1024,
+            ttl_seconds: 0,
+            upload_ttl_seconds: 24 * 3600,
+            allowed_types: Vec::new(),
+            scan: ScanConfig::default(),
+        }
+    }
+}
+
+/// 图片格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+}
+
+impl ImageFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpeg",
+            ImageFormat::Gif => "gif",
+            ImageFormat::Webp => "webp",
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "image/png",
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Gif => "image/gif",
+            ImageFormat::Webp => "image/webp",
+        }
+    }
+}
+
+/// 模型接受的图片，`vision`部分为默认值，`models`中的模型可以单独配置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct VisionLimits {
+    /// 模型是否支持图片输入，不支持时拒绝带图片的请求
+    pub supported: bool,
+    /// 每个请求最多几张图片
+    pub max_images: usize,
+    /// 图片长边的最大像素数，超出时等比缩小
+    pub max_dimension: u32,
+    /// 发给上游的单张图片最大字节数，超出时缩小或重新编码
+    pub max_bytes: usize,
+    /// 上游接受的格式，其他格式转为PNG或JPEG
+    pub formats: Vec<ImageFormat>,
+}
+
+impl Default for VisionLimits {
+    fn default() -> Self {
+        VisionLimits {
+            supported: true,
+            max_images: 10,
+            max_dimension: 2048,
+            max_bytes: 5 * 1024 * 1024,
+            formats: vec![ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::Gif, ImageFormat::Webp],
+        }
+    }
+}
+
+/// 配置文件中的`vision`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct VisionConfig {
+    pub enabled: bool,
+    /// 对话请求体的最大字节数，超出时不读取请求体直接拒绝
+    pub max_request_bytes: usize,
+    /// 客户端提交的单张图片最大字节数，超出时不解码直接拒绝
+    pub max_image_bytes: usize,
+    /// 单张图片的最大像素数（宽×高），超出时不解码直接拒绝
+    pub max_pixels: u64,
+    /// 是否允许http(s)图片地址，允许时原样转发给上游，不做检查
+    pub allow_remote_urls: bool,
+    /// 重新编码为JPEG时的质量
+    pub jpeg_quality: u8,
+    #[serde(flatten)]
+    pub limits: VisionLimits,
+}
+
+impl Default for VisionConfig {
+    fn default() -> Self {
+        VisionConfig {
+            enabled: false,
+            max_request_bytes: 32 * 1024 * 1024,
+            max_image_bytes: 20 * 1024 * 1024,
+            max_pixels: 50_000_000,
+            allow_remote_urls: true,
+            jpeg_quality: 85,
+            limits: VisionLimits::default(),
+        }
+    }
+}
+
+/// 语音转写的方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TranscriptionBackend {
+    /// 转发给模型路由到的上游的`/audio/transcriptions`
+    #[default]
+    Upstream,
+    /// 运行本地命令（如whisper.cpp）
+    Command,
+}
+
+/// 按能量检测语音的参数
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct VadConfig {
+    pub enabled: bool,
+    /// 每帧的毫秒数
+    pub frame_ms: u32,
+    /// 语音帧能量的下限（dBFS）
+    pub threshold_db: f32,
+    /// 语音帧需要高出底噪（最安静的一成帧）的分贝数
+    pub margin_db: f32,
+    /// 短于此的静音不切分
+    pub min_silence_ms: u32,
+    /// 短于此的语音丢弃
+    pub min_speech_ms: u32,
+    /// 每段前后保留的静音
+    pub padding_ms: u32,
+    /// 单段的最长秒数，超出时在最安静处切开
+    pub max_segment_seconds: u32,
+}
+
+impl Default for VadConfig {
+    fn default() -> Self {
+        VadConfig {
+            enabled: false,
+            frame_ms: 30,
+            threshold_db: -50.0,
+            margin_db: 10.0,
+            min_silence_ms: 500,
+            min_speech_ms: 250,
+            padding_ms: 200,
+            max_segment_seconds: 30,
+        }
+    }
+}
+
+/// 配置文件中的`audio`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AudioConfig {
+    pub enabled: bool,
+    pub backend: TranscriptionBackend,
+    /// 请求未指定`model`时使用的模型，按`models`路由到后端
+    pub model: String,
+    /// 上传音频的最大字节数
+    pub max_file_bytes: usize,
+    /// `command`方式的命令，`{input}`替换为16kHz单声道WAV的路径，`{output}`替换为输出路径（不含扩展名），
+    /// `{language}`替换为语言；命令写出`{output}.json`（whisper.cpp的`-oj`格式）时读取其中的时间戳，否则以标准输出为文本
+    pub command: Vec<String>,
+    /// 把其他格式转为WAV的命令（如ffmpeg），`{input}`和`{output}`分别替换为输入和输出路径；
+    /// 未配置时本地转写和语音检测只支持WAV
+    pub convert_command: Vec<String>,
+    /// 命令的超时秒数
+    pub timeout_seconds: u64,
+    pub vad: VadConfig,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            enabled: false,
+            backend: TranscriptionBackend::Upstream,
+            model: "whisper-1".to_string(),
+            max_file_bytes: 25 * 1024 * 1024,
+            command: Vec::new(),
+            convert_command: Vec::new(),
+            timeout_seconds: 600,
+            vad: VadConfig::default(),
+        }
+    }
+}
+
+/// 语音合成的方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpeechBackend {
+    /// 转发给模型路由到的上游的`/audio/speech`
+    #[default]
+    Upstream,
+    /// 运行本地TTS命令（如piper、espeak-ng）
+    Command,
+}
+
+/// 本地TTS命令写到标准输出的音频格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpeechOutput {
+    #[default]
+    Wav,
+    /// 16位单声道小端PCM，采样率为`sample_rate`
+    Pcm,
+}
+
+/// 配置文件中的`speech`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SpeechConfig {
+    pub enabled: bool,
+    pub backend: SpeechBackend,
+    /// 请求未指定`model`时使用的模型，按`models`路由到后端
+    pub model: String,
+    /// 请求未指定`voice`时使用的音色
+    pub voice: String,
+    /// 输入文本的最大字符数
+    pub max_input_chars: usize,
+    /// `command`方式的命令，文本从标准输入写入；`{voice}`、`{speed}`、`{length_scale}`（语速的倒数）和`{model}`
+    /// 替换为请求中的值
+    pub command: Vec<String>,
+    pub output: SpeechOutput,
+    /// `output`为`pcm`时的采样率
+    pub sample_rate: u32,
+    /// 请求中的音色到`{voice}`的映射，非空时只接受其中的音色
+    pub voices: BTreeMap<String, String>,
+    /// WAV和PCM以外的格式的编码命令，从标准输入读WAV，向标准输出写编码后的音频
+    pub encoders: BTreeMap<String, Vec<String>>,
+    /// 命令的超时秒数
+    pub timeout_seconds: u64,
+}
+
+impl Default for SpeechConfig {
+    fn default() -> Self {
+        SpeechConfig {
+            enabled: false,
+            backend: SpeechBackend::Upstream,
+            model: "tts-1".to_string(),
+            voice: "alloy".to_string(),
+            max_input_chars: 4096,
+            command: Vec::new(),
+            output: SpeechOutput::Wav,
+            sample_rate: 22050,
+            voices: BTreeMap::new(),
+            encoders: BTreeMap::new(),
+            timeout_seconds: 300,
+        }
+    }
+}
+
+/// 网页搜索服务
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchProvider {
+    /// 自建的SearxNG实例，需要开启JSON输出
+    #[default]
+    Searxng,
+    Bing,
+    Brave,
+}
+
+impl SearchProvider {
+    pub fn name(self) -> &'static str {
+        match self {
+            SearchProvider::Searxng => "searxng",
+            SearchProvider::Bing => "bing",
+            SearchProvider::Brave => "brave",
+        }
+    }
+
+    /// 官方接口地址，SearxNG没有默认地址
+    pub fn default_url(self) -> Option<&'static str> {
+        match self {
+            SearchProvider::Searxng => None,
+            SearchProvider::Bing => Some("https://api.bing.microsoft.com/v7.0/search"),
+            SearchProvider::Brave => Some("https://api.search.brave.com/res/v1/web/search"),
+        }
+    }
+}
+
+/// 配置文件中的`search`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SearchConfig {
+    pub enabled: bool,
+    pub provider: SearchProvider,
+    /// 搜索接口地址，SearxNG为实例的根地址，Bing和Brave缺省为官方地址
+    pub url: Option<String>,
+    /// Bing和Brave的订阅密钥
+    pub api_key: Option<String>,
+    /// 搜索语言或地区，如`zh-CN`
+    pub language: Option<String>,
+    /// 每次最多返回几条结果
+    pub max_results: usize,
+    /// 抓取前几条结果的网页并提取正文，0为不抓取
+    pub fetch_results: usize,
+    /// 网页的最大字节数，超出部分不读取
+    pub max_page_bytes: usize,
+    /// 每条结果正文的最大字符数
+    pub max_content_chars: usize,
+    pub timeout_seconds: u64,
+    /// 只保留这些域名（含子域名）的结果，为空时不限制
+    pub allowed_domains: Vec<String>,
+    /// 丢弃这些域名（含子域名）的结果
+    pub blocked_domains: Vec<String>,
+    /// 启用`tools`时是否注册内置的`web_search`工具
+    pub tool: bool,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            enabled: false,
+            provider: SearchProvider::Searxng,
+            url: None,
+            api_key: None,
+            language: None,
+            max_results: 5,
+            fetch_results: 3,
+            max_page_bytes: 2 * 1024 * 1024,
+            max_content_chars: 4000,
+            timeout_seconds: 10,
+            allowed_domains: Vec::new(),
+            blocked_domains: Vec::new(),
+            tool: true,
+        }
+    }
+}
+
+/// 代码解释器支持的一种语言
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SandboxLanguage {
+    /// 编译为WASI的解释器，如CPython的`python.wasm`或QuickJS的`qjs.wasm`
+    pub module: PathBuf,
+    /// 传给解释器的参数，`{file}`替换为沙箱中代码文件的路径
+    pub args: Vec<String>,
+    /// 代码文件的扩展名
+    pub extension: String,
+    /// 解释器自带的文件（如Python标准库），沙箱中的路径到本机目录
+    pub dirs: BTreeMap<String, PathBuf>,
+    /// 沙箱中的环境变量
+    pub env: BTreeMap<String, String>,
+}
+
+impl Default for SandboxLanguage {
+    fn default() -> Self {
+        SandboxLanguage {
+            module: PathBuf::new(),
+            args: vec!["{file}".to_string()],
+            extension: String::new(),
+            dirs: BTreeMap::new(),
+            env: BTreeMap::new(),
+        }
+    }
+}
+
+/// 配置文件中的`interpreter`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct InterpreterConfig {
+    pub enabled: bool,
+    /// wasmtime命令
+    pub wasmtime: String,
+    /// 语言名到解释器的映射
+    pub languages: BTreeMap<String, SandboxLanguage>,
+    /// 各会话的文件保存在这个目录下
+    pub dir: PathBuf,
+    /// 代码的最大字节数
+    pub max_code_bytes: usize,
+    /// WebAssembly线性内存的上限
+    pub max_memory_bytes: u64,
+    /// 每次运行的燃料，大致为可以执行的WebAssembly指令数，0为不限制
+    pub fuel: u64,
+    pub timeout_seconds: u64,
+    /// 标准输出和标准错误各自保留的最大字节数
+    pub max_output_bytes: usize,
+    /// 每个会话的文件总大小上限
+    pub max_session_bytes: u64,
+    /// 会话多久未运行代码即删除其文件，0为永久保留
+    pub session_ttl_seconds: u64,
+    /// 同时运行的代码数
+    pub max_concurrent: usize,
+    /// 启用`tools`时是否注册内置的`run_code`工具
+    pub tool: bool,
+}
+
+impl Default for InterpreterConfig {
+    fn default() -> Self {
+        let python = SandboxLanguage {
+            module: PathBuf::from("sandbox/python.wasm"),
+            extension: "py".to_string(),
+            ..SandboxLanguage::default()
+        };
+        let javascript = SandboxLanguage {
+            module: PathBuf::from("sandbox/qjs.wasm"),
+            args: vec!["--std".to_string(), "{file}".to_string()],
+            extension: "js".to_string(),
+            ..SandboxLanguage::default()
+        };
+        InterpreterConfig {
+            enabled: false,
+            wasmtime: "wasmtime".to_string(),
+            languages: BTreeMap::from([("python".to_string(), python), ("javascript".to_string(), javascript)]),
+            dir: PathBuf::from("data/sandbox"),
+            max_code_bytes: 256 * 1024,
+            max_memory_bytes: 256 * 1024 * 1024,
+            fuel: 20_000_000_000,
+            timeout_seconds: 30,
+            max_output_bytes: 64 * 1024,
+            max_session_bytes: 100 * 1024 * 1024,
+            session_ttl_seconds: 24 * 3600,
+            max_concurrent: 4,
+            tool: true,
+        }
+    }
+}
+
+/// 文件搜索使用的系统索引
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileSearchProvider {
+    /// macOS为Spotlight，Windows上安装了Everything命令行时为Everything，否则为Windows Search，
+    /// 其他系统为plocate或locate
+    #[default]
+    Auto,
+    Spotlight,
+    /// Everything的命令行工具`es.exe`
+    Everything,
+    /// Windows Search的系统索引，通过PowerShell查询
+    Windows,
+    /// plocate或mlocate，只能按文件名搜索
+    Locate,
+}
+
+impl FileSearchProvider {
+    pub fn name(self) -> &'static str {
+        match self {
+            FileSearchProvider::Auto => "auto",
+            FileSearchProvider::Spotlight => "spotlight",
+            FileSearchProvider::Everything => "everything",
+            FileSearchProvider::Windows => "windows",
+            FileSearchProvider::Locate => "locate",
+        }
+    }
+}
+
+/// 配置文件中的`file_search`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FileSearchConfig {
+    pub enabled: bool,
+    pub provider: FileSearchProvider,
+    /// 查询索引的命令，缺省为`mdfind`、`es`、`powershell`、`plocate`或`locate`
+    pub command: Option<String>,
+    /// 只返回这些目录下的文件，不能为空
+    pub roots: Vec<PathBuf>,
+    /// 不返回的文件，匹配绝对路径的glob，如`**/node_modules/**`
+    pub exclude: Vec<String>,
+    /// 每次最多返回几个文件
+    pub max_results: usize,
+    pub timeout_seconds: u64,
+}
+
+impl Default for FileSearchConfig {
+    fn default() -> Self {
+        FileSearchConfig {
+            enabled: false,
+            provider: FileSearchProvider::Auto,
+            command: None,
+            roots: Vec::new(),
+            exclude: Vec::new(),
+            max_results: 20,
+            timeout_seconds: 10,
+        }
+    }
+}
+
+/// 配置文件中的`plugins`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PluginsConfig {
+    pub enabled: bool,
+    /// 每个插件一个子目录，其中有`plugin.json`
+    pub dir: PathBuf,
+    /// 授予`storage`能力的插件的数据目录在这里，按插件名分开
+    pub data_dir: PathBuf,
+    /// 运行WebAssembly插件的wasmtime命令
+    pub wasmtime: String,
+    /// 是否加载动态库插件，它们在本进程中运行，不受沙箱限制
+    pub allow_native: bool,
+    /// 插件名到授予的能力，插件声明的能力都被授予时才加载
+    pub grants: BTreeMap<String, Vec<String>>,
+    /// 检查插件目录变化的间隔秒数，0为只在启动和手动重新加载时检查
+    pub reload_interval_seconds: u64,
+    /// 每次调用的超时秒数
+    pub timeout_seconds: u64,
+    /// WebAssembly插件的线性内存上限
+    pub max_memory_bytes: u64,
+    /// WebAssembly插件每次调用的燃料，0为不限制
+    pub fuel: u64,
+    /// 插件输出的最大字节数
+    pub max_output_bytes: usize,
+}
+
+impl Default for PluginsConfig {
+    fn default() -> Self {
+        PluginsConfig {
+            enabled: false,
+            dir: PathBuf::from("plugins"),
+            data_dir: PathBuf::from("data/plugins"),
+            wasmtime: "wasmtime".to_string(),
+            allow_native: false,
+            grants: BTreeMap::new(),
+            reload_interval_seconds: 5,
+            timeout_seconds: 30,
+            max_memory_bytes: 128 * 1024 * 1024,
+            fuel: 0,
+            max_output_bytes: 1024 * 1024,
+        }
+    }
+}
+
+/// 调用外部MCP服务器的工具前是否需要用户确认
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolApproval {
+    /// 由本服务直接执行
+    Auto,
+    /// 工具调用返回给客户端，用户同意后在下一个请求的`tool_approvals`中确认才执行
+    #[default]
+    Prompt,
+}
+
+/// `mcp.servers`中的一个外部MCP服务器，`command`和`url`二选一
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct McpServerConfig {
+    /// 以stdio传输连接时启动的命令及参数
+    pub command: Vec<String>,
+    /// 子进程的额外环境变量
+    pub env: BTreeMap<String, String>,
+    /// 以SSE传输连接时服务器的SSE端点
+    pub url: Option<String>,
+    /// 连接SSE端点和发送消息时附加的请求头
+    pub headers: BTreeMap<String, String>,
+    /// 只提供这些工具，缺省为服务器的全部工具
+    pub tools: Option<Vec<String>>,
+    pub approval: ToolApproval,
+    /// `approval`为`prompt`时仍直接执行的工具
+    pub auto_approve: Vec<String>,
+    /// 是否提供读取服务器资源的工具
+    pub resources: bool,
+    /// 每次请求的超时秒数
+    pub timeout_seconds: u64,
+}
+
+impl Default for McpServerConfig {
+    fn default() -> Self {
+        McpServerConfig {
+            command: Vec::new(),
+            env: BTreeMap::new(),
+            url: None,
+            headers: BTreeMap::new(),
+            tools: None,
+            approval: ToolApproval::default(),
+            auto_approve: Vec::new(),
+            resources: true,
+            timeout_seconds: 60,
+        }
+    }
+}
+
+/// 配置文件中的`mcp`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct McpConfig {
+    /// 是否提供SSE传输的`/v1/mcp/sse`；stdio传输由`mcp`子命令提供，不受这一项影响
+    pub enabled: bool,
+    /// 是否提供服务端工具，需要同时启用`tools`
+    pub tools: bool,
+    /// 是否提供提示词模板，需要同时启用`prompts`
+    pub prompts: bool,
+    /// 是否把上传的文件作为资源提供，需要同时启用`files`
+    pub resources: bool,
+    /// 读取资源时文件的大小上限
+    pub max_resource_bytes: u64,
+    /// 同时连接的SSE会话数上限
+    pub max_sessions: usize,
+    /// 要连接的外部MCP服务器，其工具作为服务端工具提供给模型；与`enabled`无关
+    pub servers: BTreeMap<String, McpServerConfig>,
+}
+
+impl Default for McpConfig {
+    fn default() -> Self {
+        McpConfig {
+            enabled: false,
+            tools: true,
+            prompts: true,
+            resources: true,
+            max_resource_bytes: 10 * 1024 * 1024,
+            max_sessions: 64,
+            servers: BTreeMap::new(),
+        }
+    }
+}
+
+/// 配置文件中的`agents`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AgentsConfig {
+    pub enabled: bool,
+    /// 保存运行记录的目录，按工作区分子目录
+    pub dir: PathBuf,
+    /// 请求未指定`max_steps`时的步数上限
+    pub max_steps: u32,
+    /// 请求中`max_steps`的最大值
+    pub max_steps_limit: u32,
+    /// 同时运行的任务数上限
+    pub max_running: usize,
+    /// 运行记录在结束后保留的秒数，0为永久保留
+    pub ttl_seconds: u64,
+    /// 替换内置的系统提示词
+    pub system_prompt: Option<String>,
+}
+
+impl Default for AgentsConfig {
+    fn default() -> Self {
+        AgentsConfig {
+            enabled: false,
+            dir: PathBuf::from("data/agents"),
+            max_steps: 10,
+            max_steps_limit: 50,
+            max_running: 16,
+            ttl_seconds: 7 * 24 * 3600,
+            system_prompt: None,
+        }
+    }
+}
+
+/// 配置文件中的`idempotency`部分：带`Idempotency-Key`请求头的POST请求重试时返回第一次的响应
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct IdempotencyConfig {
+    pub enabled: bool,
+    /// 保存的响应多久内可以重放
+    pub ttl_seconds: u64,
+    /// 最多保存的响应数，超出时丢弃最早的
+    pub max_entries: usize,
+    /// 请求体或响应体超过这个字节数时不保存，重试会再次执行
+    pub max_body_bytes: usize,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        IdempotencyConfig {
+            enabled: false,
+            ttl_seconds: 24 * 3600,
+            max_entries: 10_000,
+            max_body_bytes: 1024 * 1024,
+        }
+    }
+}
+
+/// 配置文件中的`model_store`部分：`openkimi-server models`管理的本地模型目录
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ModelStoreConfig {
+    /// 下载、转换和量化得到的GGUF文件都保存在这里
+    pub dir: PathBuf,
+    /// llama.cpp的`llama-quantize`，缺省在`PATH`中查找
+    pub quantize: PathBuf,
+    /// llama.cpp的`convert_hf_to_gguf.py`，把Hugging Face格式的模型转换为GGUF
+    pub convert: PathBuf,
+    /// 运行`convert`的Python解释器
+    pub python: PathBuf,
+}
+
+impl Default for ModelStoreConfig {
+    fn default() -> Self {
+        ModelStoreConfig {
+            dir: PathBuf::from("data/models"),
+            quantize: PathBuf::from("llama-quantize"),
+            convert: PathBuf::from("convert_hf_to_gguf.py"),
+            python: PathBuf::from("python3"),
+        }
+    }
+}
+
+/// 配置文件中的`batch`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BatchConfig {
+    pub enabled: bool,
+    /// 保存批处理记录和结果的目录，按工作区分子目录
+    pub dir: PathBuf,
+    /// 一个批处理最多包含的请求数
+    pub max_requests: usize,
+    /// `POST /v1/batch`请求体的字节数上限，JSONL可能远大于axum默认的2MB
+    pub max_request_bytes: usize,
+    /// 请求未指定`concurrency`时同时发给上游的请求数
+    pub concurrency: usize,
+    /// 请求中`concurrency`的最大值
+    pub max_concurrency: usize,
+    /// 同时运行的批处理数上限
+    pub max_running: usize,
+    /// 批处理在结束后保留的秒数，0为永久保留
+    pub ttl_seconds: u64,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig {
+            enabled: false,
+            dir: PathBuf::from("data/batches"),
+            max_requests: 50_000,
+            max_request_bytes: 100 * 1024 * 1024,
+            concurrency: 4,
+            max_concurrency: 32,
+            max_running: 4,
+            ttl_seconds: 7 * 24 * 3600,
+        }
+    }
+}
+
+/// 配置文件中的`memory`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MemoryConfig {
+    pub enabled: bool,
+    /// 记忆数据库文件
+    pub path: PathBuf,
+    /// 从消息中提取记忆的模型，缺省为`llm.model_name`
+    pub model: Option<String>,
+    /// 检索记忆的嵌入模型，缺省为`llm.embedding_model`
+    pub embedding_model: Option<String>,
+    /// 注入提示词的记忆最多占用的token数
+    pub max_tokens: u32,
+    /// 每次最多注入的记忆条数
+    pub top_k: usize,
+    /// 与最后一条用户消息的相似度低于这个值的记忆不注入
+    pub min_similarity: f32,
+    /// 新提取的记忆与已有记忆的相似度达到这个值时视为同一条，用新的内容替换已有的那条
+    pub merge_threshold: f32,
+    /// 未固定的记忆从最后一次使用起，每隔这么多天强度减半
+    pub half_life_days: f64,
+    /// 强度低于这个值的记忆由定时任务`forget_memories`删除
+    pub forget_below: f32,
+    /// 每个用户最多保存的记忆数，超出时删除最久未使用的未固定记忆
+    pub max_per_user: usize,
+    /// 提取记忆时模型回复的最大token数
+    pub extract_max_tokens: u32,
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        MemoryConfig {
+            enabled: false,
+            path: PathBuf::from("data/memory.db"),
+            model: None,
+            embedding_model: None,
+            max_tokens: 512,
+            top_k: 8,
+            min_similarity: 0.3,
+            merge_threshold: 0.9,
+            half_life_days: 30.0,
+            forget_below: 0.05,
+            max_per_user: 500,
+            extract_max_tokens: 256,
+        }
+    }
+}
+
+/// 一个定时任务的调度和重试设置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct JobConfig {
+    pub enabled: bool,
+    /// 五段cron表达式（分 时 日 月 周，UTC），也可以写`@hourly`、`@daily`等；缺省为任务自己的默认值
+    pub schedule: Option<String>,
+    /// 失败后的重试次数
+    pub retries: u32,
+    /// 第一次重试前等待的秒数，之后每次翻倍
+    pub retry_delay_seconds: u64,
+}
+
+impl Default for JobConfig {
+    fn default() -> Self {
+        JobConfig {
+            enabled: true,
+            schedule: None,
+            retries: 2,
+            retry_delay_seconds: 60,
+        }
+    }
+}
+
+/// 配置文件中的`jobs`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct JobsConfig {
+    pub enabled: bool,
+    /// 为会话生成摘要，需要启用`sessions`
+    pub summarize_sessions: JobConfig,
+    /// 整理本地HNSW索引，只在`rag.store`为`hnsw`时有事可做
+    pub compact_indexes: JobConfig,
+    /// 清理回复缓存中过期的条目，需要启用`cache`
+    pub evict_cache: JobConfig,
+    /// 把按日的用量汇总为月度用量，需要启用`metering`
+    pub rollup_usage: JobConfig,
+    /// 删除强度已衰减到`memory.forget_below`以下的长期记忆，需要启用`memory`
+    pub forget_memories: JobConfig,
+    /// 会话自上次摘要以来新增这么多条消息后重新生成摘要
+    pub summary_min_messages: u64,
+    /// 会话最后一次修改后空闲这么多秒才生成摘要
+    pub summary_idle_seconds: u64,
+    /// 每次运行最多为多少个会话生成摘要
+    pub summary_batch: u32,
+    /// 摘要的最大token数
+    pub summary_max_tokens: u32,
+    /// 已删除记录的占比超过这个值的索引才整理
+    pub compact_threshold: f32,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        JobsConfig {
+            enabled: false,
+            summarize_sessions: JobConfig::default(),
+            compact_indexes: JobConfig::default(),
+            evict_cache: JobConfig::default(),
+            rollup_usage: JobConfig::default(),
+            forget_memories: JobConfig::default(),
+            summary_min_messages: 10,
+            summary_idle_seconds: 600,
+            summary_batch: 20,
+            summary_max_tokens: 512,
+            compact_threshold: 0.1,
+        }
+    }
+}
+
+/// 监视的文件夹
+#[derive(Debug, Clone, Deserialize)]
+pub struct WatchedFolder {
+    pub path: PathBuf,
+    /// 导入的索引，缺省为`rag.default_index`
+    #[serde(default)]
+    pub index: Option<String>,
+    /// 索引所属的工作区，缺省为默认工作区
+    #[serde(default)]
+    pub workspace: Option<String>,
+    /// 不导入的文件，相对于文件夹的glob，如`drafts/**`、`*.log`
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+/// 配置文件中的`indexer`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct IndexerConfig {
+    pub enabled: bool,
+    pub folders: Vec<WatchedFolder>,
+    /// 检查文件变化的间隔
+    pub interval_seconds: u64,
+    /// 文件最后一次修改后这么多秒内不导入，避免导入写了一半的文件
+    pub settle_seconds: u64,
+    /// 超过这个大小的文件不导入
+    pub max_file_bytes: u64,
+    /// 已导入文件的清单，重启后据此只导入期间变化的文件
+    pub state_path: PathBuf,
+}
+
+impl Default for IndexerConfig {
+    fn default() -> Self {
+        IndexerConfig {
+            enabled: false,
+            folders: Vec::new(),
+            interval_seconds: 5,
+            settle_seconds: 2,
+            max_file_bytes: 50 * 1024 * 1024,
+            state_path: PathBuf::from("data/indexer.json"),
+        }
+    }
+}
+
+/// 内容安全检查命中后的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SafetyAction {
+    /// 只写入安全日志，内容不变
+    Flag,
+    /// 入站内容前加上警示，出站回复中附加`safety`字段
+    Annotate,
+    /// 入站内容不交给模型，出站回复替换为`safety.block_message`
+    Block,
+}
+
+/// 提示注入检查
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct InjectionConfig {
+    pub enabled: bool,
+    pub action: SafetyAction,
+    /// 使用内置的中英文提示注入特征
+    pub builtin: bool,
+    /// 附加的正则表达式，不区分大小写
+    pub patterns: Vec<String>,
+    /// 检查导入索引的文档
+    pub documents: bool,
+    /// 检查检索后注入提示词的分块
+    pub retrieval: bool,
+    /// 检查服务端执行的工具和客户端在消息中给出的工具结果
+    pub tools: bool,
+}
+
+impl Default for InjectionConfig {
+    fn default() -> Self {
+        InjectionConfig {
+            enabled: true,
+            action: SafetyAction::Annotate,
+            builtin: true,
+            patterns: Vec::new(),
+            documents: true,
+            retrieval: true,
+            tools: true,
+        }
+    }
+}
+
+/// 回复内容的策略类别
+#[derive(Debug, Clone, Deserialize)]
+pub struct SafetyPolicyConfig {
+    /// 类别名；`secrets`和`pii`没有给出规则时使用内置规则
+    pub category: String,
+    #[serde(default = "default_policy_action")]
+    pub action: SafetyAction,
+    /// 正则表达式，不区分大小写
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    /// 按字面匹配的关键词，不区分大小写
+    #[serde(default)]
+    pub keywords: Vec<String>,
+}
+
+fn default_policy_action() -> SafetyAction {
+    SafetyAction::Block
+}
+
+/// 配置文件中的`safety`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SafetyConfig {
+    pub enabled: bool,
+    pub injection: InjectionConfig,
+    pub policies: Vec<SafetyPolicyConfig>,
+    /// 拦截回复时返回给客户端的内容
+    pub block_message: String,
+    /// 安全日志目录，按天写入`safety-YYYY-MM-DD.jsonl`
+    pub log_dir: PathBuf,
+    /// 安全日志保留天数，0表示不删除
+    pub retention_days: u32,
+    /// 内存中保留的最近事件数，供`/admin/safety/events`查看
+    pub recent_events: usize,
+}
+
+impl Default for SafetyConfig {
+    fn default() -> Self {
+        SafetyConfig {
+            enabled: false,
+            injection: InjectionConfig::default(),
+            policies: Vec::new(),
+            block_message: "抱歉，这条回复违反了内容安全策略，已被拦截。".to_string(),
+            log_dir: PathBuf::from("data/safety"),
+            retention_days: 90,
+            recent_events: 500,
+        }
+    }
+}
+
+/// 自定义的个人信息规则
+#[derive(Debug, Clone, Deserialize)]
+pub struct PiiPattern {
+    /// 类别名，脱敏后显示为`[LABEL]`，大写字母、数字和下划线
+    pub label: String,
+    /// 正则表达式，含名为`pii`的捕获组时只替换该组
+    pub pattern: String,
+}
+
+/// 识别人名、机构名等实体的HTTP服务
+#[derive(Debug, Clone, Deserialize)]
+pub struct NerConfig {
+    /// 接收`{"texts": [...], "labels": [...]}`，返回`{"entities": [[{"text", "label"}]]}`
+    pub url: String,
+    #[serde(default)]
+    pub api_key: Option<String>,
+    /// 只处理这些类别的实体，为空时处理全部
+    #[serde(default = "default_ner_labels")]
+    pub labels: Vec<String>,
+    #[serde(default = "default_ner_timeout")]
+    pub timeout_seconds: u64,
+}
+
+fn default_ner_labels() -> Vec<String> {
+    vec!["PERSON".to_string(), "ORG".to_string(), "LOC".to_string()]
+}
+
+fn default_ner_timeout() -> u64 {
+    10
+}
+
+/// 配置文件中的`pii`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PiiConfig {
+    pub enabled: bool,
+    /// 邮箱地址
+    pub emails: bool,
+    /// 手机号、固定电话和带国际区号的号码
+    pub phones: bool,
+    /// 身份证号和通过校验的银行卡号
+    pub ids: bool,
+    pub patterns: Vec<PiiPattern>,
+    pub ner: Option<NerConfig>,
+    /// 审计日志和安全日志中的个人信息替换为`[LABEL]`，只使用正则规则
+    pub logs: bool,
+    /// 保存到会话存储的消息中的个人信息替换为`[LABEL]`
+    pub transcripts: bool,
+    /// 发给上游的对话消息中的个人信息替换为`[LABEL_n]`，回复中的占位符还原后再返回客户端
+    pub upstream: bool,
+}
+
+impl Default for PiiConfig {
+    fn default() -> Self {
+        PiiConfig {
+            enabled: false,
+            emails: true,
+            phones: true,
+            ids: true,
+            patterns: Vec::new(),
+            ner: None,
+            logs: true,
+            transcripts: true,
+            upstream: false,
+        }
+    }
+}
+
+/// 配置文件中的`metrics`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    /// 导出指标的路径
+    pub path: String,
+    /// 抓取时需要在`Authorization: Bearer`中给出的令牌，不设置时不需要认证
+    pub token: Option<String>,
+    /// 请求耗时直方图各个桶的上界（秒），从小到大
+    pub buckets: Vec<f64>,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            enabled: false,
+            path: "/metrics".to_string(),
+            token: None,
+            buckets: vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0],
+        }
+    }
+}
+
+/// 配置文件中的`tracing`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TracingConfig {
+    pub enabled: bool,
+    /// OTLP/HTTP（JSON编码）的接收地址
+    pub otlp_endpoint: String,
+    /// 发送OTLP时附加的请求头，如认证信息
+    pub otlp_headers: HashMap<String, String>,
+    /// 资源属性`service.name`
+    pub service_name: String,
+    /// 请求没有带采样决定时的采样比例，0到1
+    pub sample_ratio: f64,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        TracingConfig {
+            enabled: false,
+            otlp_endpoint: "http://127.0.0.1:4318/v1/traces".to_string(),
+            otlp_headers: HashMap::new(),
+            service_name: "openkimi-server".to_string(),
+            sample_ratio: 1.0,
+        }
+    }
+}
+
+/// 配置文件中的`health`部分，用于`/healthz`和`/readyz`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HealthCheckConfig {
+    /// 检查结果的缓存时间，期间的探测直接返回上次的结果
+    pub cache_seconds: u64,
+    /// 单项检查的超时时间
+    pub timeout_seconds: u64,
+    /// 某个后端的所有端点都不可达时是否视为未就绪，为`false`时只标记为`degraded`
+    pub upstream: bool,
+    /// 为`false`时不返回各项检查的位置和错误信息
+    pub details: bool,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        HealthCheckConfig {
+            cache_seconds: 10,
+            timeout_seconds: 5,
+            upstream: true,
+            details: true,
+        }
+    }
+}
+
+/// 配置文件中的`reload`部分；SIGHUP总是触发重新加载，不受这一部分影响
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ReloadConfig {
+    /// 配置文件的修改时间变化时自动重新加载
+    pub watch: bool,
+    /// 检查修改时间的间隔
+    pub interval_seconds: u64,
+}
+
+impl Default for ReloadConfig {
+    fn default() -> Self {
+        ReloadConfig {
+            watch: false,
+            interval_seconds: 2,
+        }
+    }
+}
+
+/// 配置文件中的`shutdown`部分
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ShutdownConfig {
+    /// 收到退出信号后等待进行中的请求、WebSocket生成和定时任务完成的最长时间，超时后强制退出
+    pub drain_seconds: u64,
+    /// 排空后等待审计日志、安全日志和追踪数据写出的最长时间
+    pub flush_seconds: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig {
+            drain_seconds: 30,
+            flush_seconds: 10,
+        }
+    }
+}
+
+/// 配置文件中的`redis`部分，`sessions`、`rate_limit`、`cache`或`streams`的`store`为`redis`时连接
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RedisConfig {
+    /// `redis://[用户名:密码@]主机[:端口][/库]`
+    pub url: String,
+    /// 所有键的前缀，多套部署共用一个Redis时用来区分
+    pub key_prefix: String,
+    /// 最多保留的空闲连接数
+    pub pool_size: usize,
+    /// 连接和单条命令的超时
+    pub timeout_seconds: u64,
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        RedisConfig {
+            url: "redis://127.0.0.1:6379".to_string(),
+            key_prefix: "openkimi:".to_string(),
+            pool_size: 8,
+            timeout_seconds: 5,
+        }
+    }
+}
+
+/// 配置文件中的`postgres`部分，会话、用量或审计日志保存在PostgreSQL中时连接
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PostgresConfig {
+    /// `postgresql://[用户名[:密码]@]主机[:端口]/库`，也可以写成`host=... user=... dbname=...`
+    pub url: String,
+    /// 最多保留的空闲连接数
+    pub pool_size: usize,
+    /// 建立连接的超时
+    pub timeout_seconds: u64,
+}
+
+impl Default for PostgresConfig {
+    fn default() -> Self {
+        PostgresConfig {
+            url: "postgresql://postgres@localhost/openkimi".to_string(),
+            pool_size: 8,
+            timeout_seconds: 5,
+        }
+    }
+}
+
+/// 配置文件中的`streams`部分：流式对话在服务端生成到底并保存事件，客户端断线后可以从任一实例续读
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StreamsConfig {
+    pub enabled: bool,
+    /// 为`redis`时可以从其他服务实例续读
+    pub store: StateStoreKind,
+    /// 生成结束后事件保留多久
+    pub ttl_seconds: u64,
+}
+
+impl Default for StreamsConfig {
+    fn default() -> Self {
+        StreamsConfig {
+            enabled: false,
+            store: StateStoreKind::Memory,
+            ttl_seconds: 600,
+        }
+    }
+}
+
+/// 服务配置
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub llm: LlmConfig,
+    #[serde(default)]
+    pub context: ContextConfig,
+    #[serde(default)]
+    pub rag: RagConfig,
+    #[serde(default)]
+    pub sessions: SessionsConfig,
+    #[serde(default)]
+    pub vault: VaultConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    #[serde(default)]
+    pub audit_trail: AuditTrailConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub users: UsersConfig,
+    #[serde(default)]
+    pub license: LicenseConfig,
+    #[serde(default)]
+    pub workspaces: WorkspacesConfig,
+    #[serde(default)]
+    pub metering: MeteringConfig,
+    #[serde(default)]
+    pub quotas: QuotasConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub idempotency: IdempotencyConfig,
+    #[serde(default)]
+    pub tools: ToolsConfig,
+    #[serde(default)]
+    pub structured_output: StructuredOutputConfig,
+    #[serde(default)]
+    pub prompts: PromptsConfig,
+    #[serde(default)]
+    pub files: FilesConfig,
+    #[serde(default)]
+    pub vision: VisionConfig,
+    #[serde(default)]
+    pub audio: AudioConfig,
+    #[serde(default)]
+    pub speech: SpeechConfig,
+    #[serde(default)]
+    pub search: SearchConfig,
+    #[serde(default)]
+    pub interpreter: InterpreterConfig,
+    #[serde(default)]
+    pub file_search: FileSearchConfig,
+    #[serde(default)]
+    pub plugins: PluginsConfig,
+    #[serde(default)]
+    pub mcp: McpConfig,
+    #[serde(default)]
+    pub agents: AgentsConfig,
+    #[serde(default)]
+    pub batch: BatchConfig,
+    #[serde(default)]
+    pub model_store: ModelStoreConfig,
+    #[serde(default)]
+    pub memory: MemoryConfig,
+    #[serde(default)]
+    pub jobs: JobsConfig,
+    #[serde(default)]
+    pub indexer: IndexerConfig,
+    #[serde(default)]
+    pub safety: SafetyConfig,
+    #[serde(default)]
+    pub pii: PiiConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub tracing: TracingConfig,
+    #[serde(default)]
+    pub health: HealthCheckConfig,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    #[serde(default)]
+    pub reload: ReloadConfig,
+    #[serde(default)]
+    pub streams: StreamsConfig,
+    #[serde(default)]
+    pub redis: RedisConfig,
+    #[serde(default)]
+    pub postgres: PostgresConfig,
+    /// `llm`之外的上游后端，按名称引用
+    #[serde(default)]
+    pub backends: BTreeMap<String, BackendConfig>,
+    /// 逻辑模型名到后端和实际模型的映射，客户端只使用这里的名称
+    #[serde(default)]
+    pub models: BTreeMap<String, ModelRoute>,
+    #[serde(default)]
+    pub fallback: FallbackConfig,
+}
+
+impl Config {
+    /// 是否有启用的部分保存在Redis中，是时在启动时连接`redis.url`
+    pub fn uses_redis(&self) -> bool {
+        (self.sessions.enabled && self.sessions.store == SessionStoreKind::Redis)
+            || (self.rate_limit.enabled && self.rate_limit.store == StateStoreKind::Redis)
+            || (self.cache.enabled && self.cache.store == StateStoreKind::Redis)
+            || (self.streams.enabled && self.streams.store == StateStoreKind::Redis)
+    }
+
+    /// 是否有启用的部分保存在PostgreSQL中，是时在启动时连接`postgres.url`并升级表结构
+    pub fn uses_postgres(&self) -> bool {
+        (self.sessions.enabled && self.sessions.store == SessionStoreKind::Postgres)
+            || (self.metering.enabled && self.metering.store == DatabaseKind::Postgres)
+            || (self.audit.enabled && self.audit.format == AuditFormat::Postgres)
+    }
+
+    /// 读取配置文件，未指定文件时只使用环境变量和默认值
+    pub fn load(path: Option<&Path>) -> Result<Config, String> {
+        let mut config = match path {
+            Some(path) => {
+                let content = fs::read_to_string(path)
+                    .map_err(|e| format!("读取配置文件 {} 失败: {}", path.display(), e))?;
+                serde_json::from_str(&content)
+                    .map_err(|e| format!("解析配置文件 {} 失败: {}", path.display(), e))?
+            }
+            None => Config::default(),
+        };
+
+        if config.llm.backend.api_key.as_deref().is_none_or(str::is_empty) {
+            config.llm.backend.api_key = env::var("OPENAI_API_KEY").ok();
+        }
+        if config.llm.backend.api_url.as_deref().is_none_or(str::is_empty) {
+            config.llm.backend.api_url = env::var("OPENAI_API_BASE").ok();
+        }
+        if config.vault.admin_token.as_deref().is_none_or(str::is_empty) {
+            config.vault.admin_token = env::var("OPENKIMI_ADMIN_TOKEN").ok().filter(|token| !token.is_empty());
+        }
+        Ok(config)
+    }
+}
@@ -0,0 +1,343 @@
+//! 上下文压缩
+//!
+//! 对话超出模型上下文窗口时，先按[`CompressionStrategy`]压缩较早的消息再转发给上游。
+//! 系统消息和带`"pinned": true`的消息始终原样保留；默认的[`Hierarchical`]策略把较早的消息
+//! 分段摘要，摘要合起来仍放不下时再对摘要做摘要，层层向上直到放得下为止。
+
+use std::fmt;
+use std::sync::Arc;
+
+use futures_util::future::BoxFuture;
+use openkimi_tokenizer::Tokenizer;
+use serde_json::{Map, Value};
+
+use crate::config::{CompressionKind, ContextConfig};
+use crate::error::{ApiError, ApiResult};
+use crate::router::ModelRouter;
+use crate::types::{ChatCompletionRequest, ChatMessage, MessageContent};
+
+/// 标记消息在压缩时必须原样保留的字段，转发给上游前会去掉
+pub const PINNED_FIELD: &str = "pinned";
+
+/// 摘要消息的开头
+const SUMMARY_PREFIX: &str = "以下是较早对话的摘要：\n";
+
+/// 最多摘要的层数，仍放不下时截断最后一层摘要
+const MAX_LEVELS: usize = 4;
+
+/// 单段摘要最少允许的token数，再少就很难保留有用的信息
+const MIN_SUMMARY_TOKENS: usize = 64;
+
+/// 系统消息和显式固定的消息不参与压缩
+pub fn is_pinned(message: &ChatMessage) -> bool {
+    message.role == "system" || message.extra.get(PINNED_FIELD).and_then(Value::as_bool) == Some(true)
+}
+
+fn message_tokens(tokenizer: &Tokenizer, message: &ChatMessage) -> usize {
+    tokenizer.count_message(&message.role, &message.text(), message.name.as_deref())
+}
+
+/// 消息列表在对话格式中的token数
+pub fn count_messages(tokenizer: &Tokenizer, messages: &[ChatMessage]) -> usize {
+    tokenizer.reply_priming_tokens()
+        + messages
+            .iter()
+            .map(|message| message_tokens(tokenizer, message))
+            .sum::<usize>()
+}
+
+/// 提示词的token预算
+pub struct Budget<'a> {
+    pub tokenizer: &'a Tokenizer,
+    /// 压缩后的消息列表最多可用的token数
+    pub max_tokens: usize,
+}
+
+/// 压缩结果
+#[derive(Debug)]
+pub struct Compressed {
+    pub messages: Vec<ChatMessage>,
+    /// 被摘要或丢弃的消息数
+    pub removed: usize,
+    /// 摘要层数，不做摘要的策略为0
+    pub levels: usize,
+}
+
+/// 压缩策略
+pub trait CompressionStrategy: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// 压缩`messages`使其不超过`budget`，固定保留的消息本身就放不下时返回错误
+    fn compress<'a>(&'a self, messages: &'a [ChatMessage], budget: &'a Budget<'a>) -> BoxFuture<'a, ApiResult<Compressed>>;
+}
+
+/// 生成摘要的模型
+pub trait Summarizer: Send + Sync {
+    /// 把`text`摘要为不超过`max_tokens`个token的文本
+    fn summarize<'a>(&'a self, text: &'a str, max_tokens: usize) -> BoxFuture<'a, ApiResult<String>>;
+}
+
+/// 由上游对话模型生成摘要
+pub struct UpstreamSummarizer {
+    models: Arc<ModelRouter>,
+    model: String,
+}
+
+impl UpstreamSummarizer {
+    pub fn new(models: Arc<ModelRouter>, model: impl Into<String>) -> UpstreamSummarizer {
+        UpstreamSummarizer {
+            models,
+            model: model.into(),
+        }
+    }
+}
+
+fn text_message(role: &str, text: String) -> ChatMessage {
+    ChatMessage {
+        role: role.to_string(),
+        content: Some(MessageContent::Text(text)),
+        name: None,
+        extra: Map::new(),
+    }
+}
+
+impl Summarizer for UpstreamSummarizer {
+    fn summarize<'a>(&'a self, text: &'a str, max_tokens: usize) -> BoxFuture<'a, ApiResult<String>> {
+        Box::pin(async move {
+            let instruction = format!(
+                "你是对话摘要助手。请用不超过{}个token概括下面的对话记录，保留事实、结论、用户的要求和偏好、\
+                 未完成的事项以及代码、数字、名称等细节，省略寒暄。只输出摘要本身。",
+                max_tokens
+            );
+            let request = ChatCompletionRequest {
+                model: self.model.clone(),
+                messages: vec![text_message("system", instruction), text_message("user", text.to_string())],
+                stream: None,
+                max_tokens: Some(max_tokens as u32),
+                temperature: Some(0.2),
+                extra: Map::new(),
+            };
+            let response = self.models.chat_completion(&request).await?;
+            response
+                .choices
+                .first()
+                .map(|choice| choice.message.text())
+                .ok_or_else(|| ApiError::Upstream("摘要请求没有返回内容".to_string()))
+        })
+    }
+}
+
+/// 从最新的消息往前，在`recent_budget`内尽量多地保留未固定的消息；
+/// 最新一条消息只要放得进`available`就总是保留。返回每条消息是否保留
+fn keep_recent(messages: &[ChatMessage], tokenizer: &Tokenizer, recent_budget: usize, available: usize) -> (Vec<bool>, usize) {
+    let mut keep: Vec<bool> = messages.iter().map(is_pinned).collect();
+    let mut used = 0;
+    for (index, message) in messages.iter().enumerate().rev() {
+        if keep[index] {
+            continue;
+        }
+        let tokens = message_tokens(tokenizer, message);
+        let limit = if used == 0 { available } else { recent_budget };
+        if used + tokens > limit {
+            break;
+        }
+        used += tokens;
+        keep[index] = true;
+    }
+    (keep, used)
+}
+
+/// 固定保留的消息占用的token数，超出预算时返回错误
+fn pinned_tokens(messages: &[ChatMessage], budget: &Budget) -> ApiResult<usize> {
+    let pinned: usize = messages
+        .iter()
+        .filter(|message| is_pinned(message))
+        .map(|message| message_tokens(budget.tokenizer, message))
+        .sum::<usize>()
+        + budget.tokenizer.reply_priming_tokens();
+    if pinned >= budget.max_tokens {
+        return Err(ApiError::ContextLengthExceeded(format!(
+            "系统消息和固定消息共 {} tokens，已超出可用的 {} tokens",
+            pinned, budget.max_tokens
+        )));
+    }
+    Ok(pinned)
+}
+
+fn latest_too_long(budget: &Budget) -> ApiError {
+    ApiError::ContextLengthExceeded(format!("最新一条消息超出了可用的 {} tokens", budget.max_tokens))
+}
+
+/// 丢弃最早的未固定消息
+pub struct Truncate;
+
+impl CompressionStrategy for Truncate {
+    fn name(&self) -> &'static str {
+        "truncate"
+    }
+
+    fn compress<'a>(&'a self, messages: &'a [ChatMessage], budget: &'a Budget<'a>) -> BoxFuture<'a, ApiResult<Compressed>> {
+        Box::pin(async move {
+            let available = budget.max_tokens - pinned_tokens(messages, budget)?;
+            let (keep, used) = keep_recent(messages, budget.tokenizer, available, available);
+            if used == 0 && messages.iter().any(|message| !is_pinned(message)) {
+                return Err(latest_too_long(budget));
+            }
+
+            let kept: Vec<ChatMessage> = messages
+                .iter()
+                .zip(&keep)
+                .filter(|(_, keep)| **keep)
+                .map(|(message, _)| message.clone())
+                .collect();
+            Ok(Compressed {
+                removed: messages.len() - kept.len(),
+                messages: kept,
+                levels: 0,
+            })
+        })
+    }
+}
+
+/// 分层摘要：最近的消息原样保留，更早的消息分段摘要，摘要仍放不下时继续对摘要做摘要
+pub struct Hierarchical {
+    summarizer: Box<dyn Summarizer>,
+    recent_ratio: f32,
+    chunk_tokens: usize,
+}
+
+impl Hierarchical {
+    pub fn new(summarizer: Box<dyn Summarizer>, recent_ratio: f32, chunk_tokens: usize) -> Hierarchical {
+        Hierarchical {
+            summarizer,
+            recent_ratio: recent_ratio.clamp(0.0, 1.0),
+            chunk_tokens: chunk_tokens.max(MIN_SUMMARY_TOKENS * 2),
+        }
+    }
+
+    /// 把`text`摘要到不超过`target`个token，返回摘要和层数
+    async fn summarize_to(&self, text: String, target: usize, tokenizer: &Tokenizer) -> ApiResult<(String, usize)> {
+        let mut text = text;
+        let mut levels = 0;
+        // 第一层总要做，较早的消息即使本身放得下，也是因为整体超长才需要压缩
+        while levels == 0 || tokenizer.count(&text) > target {
+            if levels == MAX_LEVELS {
+                return Ok((tokenizer.truncate(&text, target), levels));
+            }
+            let total = tokenizer.count(&text).max(1);
+            let chunks = tokenizer.chunks(&text, self.chunk_tokens);
+            // 按原文比例分配每段摘要的长度，合起来刚好放进目标预算
+            let mut summaries = Vec::with_capacity(chunks.len());
+            for chunk in &chunks {
+                let share = (tokenizer.count(chunk) * target / total).max(MIN_SUMMARY_TOKENS);
+                summaries.push(self.summarizer.summarize(chunk, share).await?);
+            }
+            text = summaries.join("\n\n");
+            levels += 1;
+        }
+        Ok((text, levels))
+    }
+}
+
+impl CompressionStrategy for Hierarchical {
+    fn name(&self) -> &'static str {
+        "hierarchical"
+    }
+
+    fn compress<'a>(&'a self, messages: &'a [ChatMessage], budget: &'a Budget<'a>) -> BoxFuture<'a, ApiResult<Compressed>> {
+        Box::pin(async move {
+            let tokenizer = budget.tokenizer;
+            let available = budget.max_tokens - pinned_tokens(messages, budget)?;
+            let recent_budget = (available as f32 * self.recent_ratio) as usize;
+            let (keep, used) = keep_recent(messages, tokenizer, recent_budget, available);
+            if used == 0 && messages.iter().any(|message| !is_pinned(message)) {
+                return Err(latest_too_long(budget));
+            }
+
+            let older: Vec<usize> = (0..messages.len()).filter(|index| !keep[*index]).collect();
+            let Some(&first_older) = older.first() else {
+                return Ok(Compressed {
+                    messages: messages.to_vec(),
+                    removed: 0,
+                    levels: 0,
+                });
+            };
+
+            let transcript = older
+                .iter()
+                .map(|index| format!("{}: {}", messages[*index].role, messages[*index].text()))
+                .collect::<Vec<_>>()
+                .join("\n\n");
+            let overhead = tokenizer.count_message("system", SUMMARY_PREFIX, None);
+            let summary_budget = (available - used).saturating_sub(overhead);
+
+            let mut levels = 0;
+            let summary = if summary_budget >= MIN_SUMMARY_TOKENS {
+                let (summary, depth) = self.summarize_to(transcript, summary_budget, tokenizer).await?;
+                levels = depth;
+                Some(text_message("system", format!("{}{}", SUMMARY_PREFIX, summary)))
+            } else {
+                // 剩余预算太少，摘要也放不下，只能丢弃
+                None
+            };
+
+            let mut compressed = Vec::with_capacity(messages.len() - older.len() + 1);
+            let mut summary = summary;
+            for (index, message) in messages.iter().enumerate() {
+                if keep[index] {
+                    compressed.push(message.clone());
+                } else if index == first_older {
+                    compressed.extend(summary.take());
+                }
+            }
+            Ok(Compressed {
+                messages: compressed,
+                removed: older.len(),
+                levels,
+            })
+        })
+    }
+}
+
+/// 按配置选择压缩策略，为`None`时超长请求直接拒绝
+pub struct ContextManager {
+    strategy: Option<Box<dyn CompressionStrategy>>,
+    reserve_tokens: u32,
+}
+
+impl fmt::Debug for ContextManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ContextManager")
+            .field("strategy", &self.strategy.as_ref().map(|strategy| strategy.name()))
+            .field("reserve_tokens", &self.reserve_tokens)
+            .finish()
+    }
+}
+
+impl ContextManager {
+    pub fn new(strategy: Option<Box<dyn CompressionStrategy>>, reserve_tokens: u32) -> ContextManager {
+        ContextManager { strategy, reserve_tokens }
+    }
+
+    pub fn from_config(config: &ContextConfig, models: &Arc<ModelRouter>, default_model: &str) -> ContextManager {
+        let strategy: Option<Box<dyn CompressionStrategy>> = match config.strategy {
+            CompressionKind::Hierarchical => {
+                let model = config.summary_model.as_deref().unwrap_or(default_model);
+                let summarizer = UpstreamSummarizer::new(Arc::clone(models), model);
+                Some(Box::new(Hierarchical::new(Box::new(summarizer), config.recent_ratio, config.chunk_tokens)))
+            }
+            CompressionKind::Truncate => Some(Box::new(Truncate)),
+            CompressionKind::Off => None,
+        };
+        ContextManager::new(strategy, config.reserve_tokens)
+    }
+
+    pub fn strategy(&self) -> Option<&dyn CompressionStrategy> {
+        self.strategy.as_deref()
+    }
+
+    /// 请求未指定`max_tokens`时为回复预留的token数
+    pub fn reserve_tokens(&self) -> u32 {
+        self.reserve_tokens
+    }
+}
@@ -0,0 +1,146 @@
+//! OpenAI风格的错误响应`{"error":{"message":...,"type":...,"code":...}}`
+
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::{json, Value};
+
+/// 接口错误
+#[derive(Debug)]
+pub enum ApiError {
+    /// 请求参数错误
+    InvalidRequest(String),
+    /// 提示词加上`max_tokens`超出模型上下文窗口
+    ContextLengthExceeded(String),
+    /// 缺少或提供了错误的令牌
+    Unauthorized(String),
+    /// 令牌有效但没有所需的权限
+    Forbidden(String),
+    /// 请求的资源不存在，如会话
+    NotFound(String),
+    /// 请求体或其中的图片超出大小上限
+    PayloadTooLarge(String),
+    /// 与正在处理的请求冲突，如同一个幂等键的第一次请求尚未完成
+    Conflict(String),
+    /// 客户端超出限流额度，或所有上游密钥都已用满每分钟请求数上限
+    RateLimited(String),
+    /// 服务端本地的错误，如读写索引文件失败
+    Internal(String),
+    /// 服务正在退出，不再接受新的生成
+    Unavailable(String),
+    /// 无法连接上游或上游返回了无法解析的内容
+    Upstream(String),
+    /// 上游返回的错误，原样转发状态码和响应体
+    UpstreamStatus(StatusCode, Value),
+    /// 模型输出在重试后仍不符合`response_format`，附带各条校验错误
+    SchemaValidation(String, Vec<String>),
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::InvalidRequest(message)
+            | ApiError::ContextLengthExceeded(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::NotFound(message)
+            | ApiError::PayloadTooLarge(message)
+            | ApiError::Conflict(message)
+            | ApiError::RateLimited(message)
+            | ApiError::Internal(message)
+            | ApiError::Unavailable(message)
+            | ApiError::Upstream(message)
+            | ApiError::SchemaValidation(message, _) => f.write_str(message),
+            ApiError::UpstreamStatus(status, body) => write!(f, "上游返回 {}: {}", status, body),
+        }
+    }
+}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(err: reqwest::Error) -> ApiError {
+        ApiError::Upstream(format!("请求上游模型失败: {}", err))
+    }
+}
+
+fn error_body(message: &str, kind: &str, code: Option<&str>) -> Value {
+    json!({ "error": { "message": message, "type": kind, "code": code } })
+}
+
+impl ApiError {
+    pub fn invalid_request(message: impl Into<String>) -> ApiError {
+        ApiError::InvalidRequest(message.into())
+    }
+
+    /// HTTP状态码和OpenAI格式的错误体，WebSocket等非HTTP响应的传输方式也使用同样的错误体
+    pub fn into_parts(self) -> (StatusCode, Value) {
+        match self {
+            ApiError::InvalidRequest(message) => (
+                StatusCode::BAD_REQUEST,
+                error_body(&message, "invalid_request_error", None),
+            ),
+            ApiError::ContextLengthExceeded(message) => (
+                StatusCode::BAD_REQUEST,
+                error_body(&message, "invalid_request_error", Some("context_length_exceeded")),
+            ),
+            ApiError::Unauthorized(message) => (
+                StatusCode::UNAUTHORIZED,
+                error_body(&message, "invalid_request_error", Some("invalid_api_key")),
+            ),
+            ApiError::Forbidden(message) => (
+                StatusCode::FORBIDDEN,
+                error_body(&message, "invalid_request_error", Some("insufficient_permissions")),
+            ),
+            ApiError::NotFound(message) => (
+                StatusCode::NOT_FOUND,
+                error_body(&message, "invalid_request_error", Some("not_found")),
+            ),
+            ApiError::PayloadTooLarge(message) => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                error_body(&message, "invalid_request_error", Some("payload_too_large")),
+            ),
+            ApiError::Conflict(message) => (
+                StatusCode::CONFLICT,
+                error_body(&message, "invalid_request_error", Some("conflict")),
+            ),
+            ApiError::RateLimited(message) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                error_body(&message, "rate_limit_error", Some("rate_limit_exceeded")),
+            ),
+            ApiError::Internal(message) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                error_body(&message, "server_error", None),
+            ),
+            ApiError::Unavailable(message) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                error_body(&message, "server_error", Some("service_unavailable")),
+            ),
+            ApiError::Upstream(message) => (
+                StatusCode::BAD_GATEWAY,
+                error_body(&message, "upstream_error", None),
+            ),
+            ApiError::SchemaValidation(message, errors) => {
+                let mut body = error_body(&message, "invalid_response_error", Some("schema_validation_failed"));
+                body["error"]["errors"] = Value::from(errors);
+                (StatusCode::UNPROCESSABLE_ENTITY, body)
+            }
+            ApiError::UpstreamStatus(status, body) => {
+                // 上游已是OpenAI格式的错误时直接转发，否则包装一层
+                let body = if body.get("error").is_some() {
+                    body
+                } else {
+                    error_body(&body.to_string(), "upstream_error", None)
+                };
+                (status, body)
+            }
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, body) = self.into_parts();
+        (status, Json(body)).into_response()
+    }
+}
+
+pub type ApiResult<T> = Result<T, ApiError>;
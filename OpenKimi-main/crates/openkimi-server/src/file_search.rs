@@ -0,0 +1,486 @@
+//! 通过操作系统的文件索引查找本机文件
+//!
+//! macOS使用Spotlight（`mdfind`），Windows使用Everything的命令行工具`es.exe`或Windows Search的系统索引，
+//! 其他系统使用plocate或locate，不另建索引。结果只保留`file_search.roots`之下、存在且不是隐藏文件或符号链接的路径。
+//! 启用`tools`时注册为内置的`find_files`工具，模型可以按文件名或内容查找文件。
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::config::{FileSearchConfig, FileSearchProvider};
+use crate::interpreter;
+
+/// 内置工具的函数名
+pub const TOOL_NAME: &str = "find_files";
+
+/// 单次搜索最多返回的文件数
+const MAX_RESULTS: usize = 100;
+
+/// 索引命令输出的最大字节数，超出部分丢弃
+const MAX_OUTPUT_BYTES: usize = 1024 * 1024;
+
+/// 一次搜索
+#[derive(Debug, Clone)]
+pub struct FileQuery {
+    pub text: String,
+    /// 按内容而不是文件名搜索
+    pub content: bool,
+    /// 搜索的目录，均为绝对路径
+    pub roots: Vec<PathBuf>,
+    /// 向索引要的结果数，比返回的多一些，以抵消过滤掉的
+    pub limit: usize,
+}
+
+/// 查询文件索引的方式
+pub trait FileSearchBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// 能否按文件内容搜索
+    fn searches_content(&self) -> bool;
+
+    /// 查询索引的命令行，命令每行输出一个绝对路径
+    fn command(&self, query: &FileQuery) -> Vec<String>;
+}
+
+fn display(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+/// Spotlight，按内容搜索时`text`为Spotlight的查询语法
+struct Spotlight {
+    command: String,
+}
+
+impl FileSearchBackend for Spotlight {
+    fn name(&self) -> &'static str {
+        "spotlight"
+    }
+
+    fn searches_content(&self) -> bool {
+        true
+    }
+
+    fn command(&self, query: &FileQuery) -> Vec<String> {
+        let mut args = vec![self.command.clone()];
+        for root in &query.roots {
+            args.extend(["-onlyin".to_string(), display(root)]);
+        }
+        if !query.content {
+            args.push("-name".to_string());
+        }
+        args.push(query.text.clone());
+        args
+    }
+}
+
+/// Everything的命令行工具，需要Everything在后台运行
+struct Everything {
+    command: String,
+}
+
+impl FileSearchBackend for Everything {
+    fn name(&self) -> &'static str {
+        "everything"
+    }
+
+    fn searches_content(&self) -> bool {
+        true
+    }
+
+    fn command(&self, query: &FileQuery) -> Vec<String> {
+        // 包含`\`的词匹配完整路径，`<a|b>`为其中之一
+        let roots: Vec<String> =
+            query.roots.iter().map(|root| format!("\"{}\\\"", display(root).trim_end_matches('\\'))).collect();
+        let text = query.text.replace('"', "");
+        let terms = if query.content {
+            format!("content:\"{}\"", text)
+        } else {
+            text
+        };
+        vec![
+            self.command.clone(),
+            "-n".to_string(),
+            query.limit.to_string(),
+            format!("<{}> {}", roots.join("|"), terms),
+        ]
+    }
+}
+
+/// Windows Search的系统索引，只包含在索引选项中添加过的位置
+struct WindowsSearch {
+    command: String,
+}
+
+/// SQL字符串中的单引号
+fn sql_string(text: &str) -> String {
+    text.replace('\'', "''")
+}
+
+impl FileSearchBackend for WindowsSearch {
+    fn name(&self) -> &'static str {
+        "windows"
+    }
+
+    fn searches_content(&self) -> bool {
+        true
+    }
+
+    fn command(&self, query: &FileQuery) -> Vec<String> {
+        let scopes: Vec<String> = query
+            .roots
+            .iter()
+            .map(|root| format!("SCOPE='file:{}'", sql_string(&display(root).replace('\\', "/"))))
+            .collect();
+        let condition = if query.content {
+            format!("CONTAINS('\"{}\"')", sql_string(&query.text.replace('"', "")))
+        } else {
+            let escaped = query.text.replace('[', "[[]").replace('%', "[%]").replace('_', "[_]");
+            format!("System.FileName LIKE '%{}%'", sql_string(&escaped))
+        };
+        let sql = format!(
+            "SELECT TOP {} System.ItemPathDisplay FROM SYSTEMINDEX WHERE ({}) AND {}",
+            query.limit,
+            scopes.join(" OR "),
+            condition
+        );
+        let script = format!(
+            "[Console]::OutputEncoding = [Text.Encoding]::UTF8\n\
+             $connection = New-Object -ComObject ADODB.Connection\n\
+             $connection.Open(\"Provider=Search.CollatorDSO;Extended Properties='Application=Windows';\")\n\
+             $records = $connection.Execute('{}')\n\
+             while (-not $records.EOF) {{ $records.Fields.Item('System.ItemPathDisplay').Value; $records.MoveNext() }}",
+            sql.replace('\'', "''")
+        );
+        // `-EncodedCommand`为UTF-16LE的Base64，避免命令行转义
+        let encoded: Vec<u8> = script.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        vec![
+            self.command.clone(),
+            "-NoProfile".to_string(),
+            "-NonInteractive".to_string(),
+            "-EncodedCommand".to_string(),
+            STANDARD.encode(encoded),
+        ]
+    }
+}
+
+/// plocate或mlocate，数据库由`updatedb`定期更新，按文件名不区分大小写地匹配
+struct Locate {
+    command: String,
+}
+
+impl FileSearchBackend for Locate {
+    fn name(&self) -> &'static str {
+        "locate"
+    }
+
+    fn searches_content(&self) -> bool {
+        false
+    }
+
+    fn command(&self, query: &FileQuery) -> Vec<String> {
+        // 数据库中的路径不分目录，交给结果过滤
+        vec![
+            self.command.clone(),
+            "-i".to_string(),
+            "-b".to_string(),
+            "--".to_string(),
+            query.text.clone(),
+        ]
+    }
+}
+
+/// 在`PATH`中查找命令，带目录的命令只检查文件是否存在
+fn find_command(command: &str) -> Option<PathBuf> {
+    let path = Path::new(command);
+    if path.components().count() > 1 {
+        return path.is_file().then(|| path.to_path_buf());
+    }
+    let names = if cfg!(windows) && path.extension().is_none() {
+        vec![format!("{}.exe", command), command.to_string()]
+    } else {
+        vec![command.to_string()]
+    };
+    env::split_paths(&env::var_os("PATH")?)
+        .flat_map(|dir| names.iter().map(move |name| dir.join(name)))
+        .find(|candidate| candidate.is_file())
+}
+
+/// `auto`在当前系统上对应的后端
+fn detect() -> FileSearchProvider {
+    if cfg!(target_os = "macos") {
+        FileSearchProvider::Spotlight
+    } else if cfg!(windows) {
+        if find_command("es").is_some() {
+            FileSearchProvider::Everything
+        } else {
+            FileSearchProvider::Windows
+        }
+    } else {
+        FileSearchProvider::Locate
+    }
+}
+
+/// `find_files`工具的参数
+#[derive(Debug, Clone, Deserialize)]
+pub struct FileSearchRequest {
+    pub query: String,
+    /// 按内容搜索，缺省为按文件名
+    #[serde(default)]
+    pub content: bool,
+    /// 只搜索这个目录，须在`file_search.roots`之内
+    #[serde(default)]
+    pub path: Option<String>,
+    /// 缺省为`file_search.max_results`
+    #[serde(default)]
+    pub max_results: Option<usize>,
+}
+
+/// 找到的一个文件或目录
+#[derive(Debug, Clone, Serialize)]
+pub struct FoundFile {
+    pub path: String,
+    pub directory: bool,
+    /// 目录为空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+    /// 修改时间（Unix秒）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified: Option<u64>,
+}
+
+/// `find_files`的结果
+#[derive(Debug, Clone, Serialize)]
+pub struct FileSearchResponse {
+    pub query: String,
+    pub provider: &'static str,
+    pub results: Vec<FoundFile>,
+    /// 符合条件的文件多于返回的
+    pub truncated: bool,
+}
+
+/// 本机文件搜索
+pub struct FileSearch {
+    config: FileSearchConfig,
+    backend: Box<dyn FileSearchBackend>,
+    roots: Vec<PathBuf>,
+    exclude: Vec<Pattern>,
+}
+
+impl fmt::Debug for FileSearch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileSearch")
+            .field("provider", &self.backend.name())
+            .field("roots", &self.roots)
+            .finish()
+    }
+}
+
+impl FileSearch {
+    /// 选择后端并检查命令是否存在
+    pub fn new(config: &FileSearchConfig) -> Result<FileSearch, String> {
+        if config.roots.is_empty() {
+            return Err("file_search.roots 不能为空".to_string());
+        }
+        let mut roots = Vec::new();
+        for root in &config.roots {
+            let root = std::path::absolute(root)
+                .map_err(|e| format!("file_search.roots: 无效的路径 {}: {}", root.display(), e))?;
+            if !root.is_dir() {
+                eprintln!("⚠️ file_search.roots 中的 {} 不存在或不是目录", root.display());
+            }
+            roots.push(root);
+        }
+        let exclude = config
+            .exclude
+            .iter()
+            .map(|pattern| {
+                Pattern::new(pattern).map_err(|e| format!("file_search.exclude: 无效的规则 {}: {}", pattern, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let provider = match config.provider {
+            FileSearchProvider::Auto => detect(),
+            provider => provider,
+        };
+        let command = match (&config.command, provider) {
+            (Some(command), _) => command.clone(),
+            (None, FileSearchProvider::Spotlight) => "mdfind".to_string(),
+            (None, FileSearchProvider::Everything) => "es".to_string(),
+            (None, FileSearchProvider::Windows) => "powershell".to_string(),
+            (None, _) if find_command("plocate").is_some() => "plocate".to_string(),
+            (None, _) => "locate".to_string(),
+        };
+        let Some(command) = find_command(&command) else {
+            return Err(format!(
+                "找不到 file_search.provider 为 {} 时使用的命令 {}",
+                provider.name(),
+                command
+            ));
+        };
+        let command = display(&command);
+        let backend: Box<dyn FileSearchBackend> = match provider {
+            FileSearchProvider::Spotlight => Box::new(Spotlight { command }),
+            FileSearchProvider::Everything => Box::new(Everything { command }),
+            FileSearchProvider::Windows => Box::new(WindowsSearch { command }),
+            FileSearchProvider::Auto | FileSearchProvider::Locate => Box::new(Locate { command }),
+        };
+        Ok(FileSearch {
+            config: config.clone(),
+            backend,
+            roots,
+            exclude,
+        })
+    }
+
+    /// 路径是否可以返回：在搜索的目录之下，没有`..`、隐藏的部分或排除规则匹配
+    fn visible(&self, path: &Path, roots: &[PathBuf]) -> bool {
+        let Some(relative) = roots.iter().find_map(|root| path.strip_prefix(root).ok()) else {
+            return false;
+        };
+        relative.components().all(|component| match component {
+            Component::Normal(name) => !name.to_string_lossy().starts_with('.'),
+            _ => false,
+        }) && !self.exclude.iter().any(|pattern| pattern.matches_path(path))
+    }
+
+    pub async fn search(&self, request: &FileSearchRequest) -> Result<FileSearchResponse, String> {
+        let text = request.query.trim();
+        if text.is_empty() {
+            return Err("query 不能为空".to_string());
+        }
+        if request.content && !self.backend.searches_content() {
+            return Err(format!("{} 只能按文件名搜索", self.backend.name()));
+        }
+        let roots = match request.path.as_deref().filter(|path| !path.trim().is_empty()) {
+            Some(path) => {
+                let path = std::path::absolute(path).map_err(|e| format!("path 无效: {}", e))?;
+                if !self.roots.iter().any(|root| path.starts_with(root))
+                    || path.components().any(|component| component == Component::ParentDir)
+                {
+                    return Err(format!("不允许搜索 {}", path.display()));
+                }
+                vec![path]
+            }
+            None => self.roots.clone(),
+        };
+        let max_results = request.max_results.unwrap_or(self.config.max_results).clamp(1, MAX_RESULTS);
+        let query = FileQuery {
+            text: text.to_string(),
+            content: request.content,
+            roots: roots.clone(),
+            limit: max_results * 5,
+        };
+
+        let args = self.backend.command(&query);
+        let timeout_seconds = self.config.timeout_seconds;
+        let execution = tokio::task::spawn_blocking(move || {
+            interpreter::execute(&args, Vec::new(), MAX_OUTPUT_BYTES, timeout_seconds)
+        })
+        .await
+        .map_err(|e| e.to_string())??;
+        let name = self.backend.name();
+        if execution.timed_out {
+            return Err(format!("{} 搜索超时", name));
+        }
+        let stdout = String::from_utf8_lossy(&execution.stdout);
+        let stderr = String::from_utf8_lossy(&execution.stderr);
+        // locate没有找到时以1退出，没有输出
+        if !execution.status.is_some_and(|status| status.success()) && stdout.trim().is_empty() {
+            if stderr.trim().is_empty() {
+                return Ok(FileSearchResponse {
+                    query: text.to_string(),
+                    provider: name,
+                    results: Vec::new(),
+                    truncated: false,
+                });
+            }
+            return Err(format!("{} 搜索失败: {}", name, stderr.trim()));
+        }
+
+        let mut lines: Vec<&str> = stdout.lines().collect();
+        if execution.truncated {
+            // 最后一行可能不完整
+            lines.pop();
+        }
+        let mut results: Vec<FoundFile> = Vec::new();
+        let mut truncated = false;
+        for line in lines {
+            let path = Path::new(line.trim_end_matches('\r'));
+            if !path.is_absolute() || !self.visible(path, &roots) {
+                continue;
+            }
+            // 索引可能过时，不存在的文件和符号链接都跳过
+            let Ok(metadata) = fs::symlink_metadata(path) else {
+                continue;
+            };
+            if metadata.is_symlink() || results.iter().any(|result| Path::new(&result.path) == path) {
+                continue;
+            }
+            if results.len() == max_results {
+                truncated = true;
+                break;
+            }
+            results.push(FoundFile {
+                path: display(path),
+                directory: metadata.is_dir(),
+                bytes: metadata.is_file().then_some(metadata.len()),
+                modified: metadata
+                    .modified()
+                    .ok()
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .map(|duration| duration.as_secs()),
+            });
+        }
+        Ok(FileSearchResponse {
+            query: text.to_string(),
+            provider: name,
+            results,
+            truncated,
+        })
+    }
+
+    /// `find_files`工具的定义
+    pub fn tool_definition(&self) -> Value {
+        let mut description = format!(
+            "在本机的文件索引中查找文件，返回路径、大小和修改时间。可以搜索的目录：{}。",
+            self.roots.iter().map(|root| display(root)).collect::<Vec<_>>().join("、")
+        );
+        if !self.backend.searches_content() {
+            description.push_str("只能按文件名搜索。");
+        }
+        json!({
+            "type": "function",
+            "function": {
+                "name": TOOL_NAME,
+                "description": description,
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "query": {"type": "string", "description": "文件名中包含的文字，按内容搜索时为内容中的文字"},
+                        "content": {"type": "boolean", "description": "按文件内容搜索，缺省为按文件名"},
+                        "path": {"type": "string", "description": "只搜索这个目录"},
+                        "max_results": {"type": "integer", "description": "返回几个文件"}
+                    },
+                    "required": ["query"]
+                }
+            }
+        })
+    }
+
+    /// 执行`find_files`工具
+    pub async fn call_tool(&self, arguments: &Value) -> Result<String, String> {
+        let request: FileSearchRequest =
+            serde_json::from_value(arguments.clone()).map_err(|e| format!("参数无效: {}", e))?;
+        let response = self.search(&request).await?;
+        serde_json::to_string(&response).map_err(|e| e.to_string())
+    }
+}
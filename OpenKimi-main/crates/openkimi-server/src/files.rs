@@ -0,0 +1,860 @@
+//! 文件上传接口`/v1/files`
+//!
+//! 小文件可以直接`POST /v1/files`上传；大文件先创建上传任务，再分块`PATCH`，每块的`Upload-Offset`
+//! 必须等于已接收的字节数，连接中断后查询任务得到已接收的字节数即可续传，全部接收后`complete`生成文件。
+//! 完成时按文件头识别实际类型，检查`files.allowed_types`，并调用配置的病毒扫描命令或接口，未通过的文件被删除。
+//! 文件按工作区隔离，保存在`files.dir`下，过期的文件和未完成的上传任务由后台线程定期清理。
+//! 上传后的文件可以`POST /v1/files/{id}/ingest`导入向量索引。
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path as FsPath, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use futures_util::StreamExt;
+use openkimi_rag::{ChunkConfig, Document, DocumentKind};
+use ring::digest::{Context, SHA256};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::config::{FilesConfig, ScanConfig};
+use crate::error::{ApiError, ApiResult};
+use crate::rag;
+use crate::types::{DeletedResponse, IngestResponse, ListResponse};
+use crate::workspace::Workspace;
+use crate::AppState;
+
+/// 分块上传时请求头中的起始偏移
+pub const OFFSET_HEADER: &str = "upload-offset";
+
+/// 识别类型时读取的文件头长度
+const SNIFF_BYTES: usize = 8192;
+
+/// 清理过期文件的间隔
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+fn random_id(prefix: &str) -> ApiResult<String> {
+    let mut bytes = [0u8; 12];
+    getrandom::getrandom(&mut bytes).map_err(|e| ApiError::Internal(format!("生成文件id失败: {}", e)))?;
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    Ok(format!("{}-{}", prefix, hex))
+}
+
+/// 已上传的文件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileObject {
+    pub id: String,
+    pub object: String,
+    pub bytes: u64,
+    pub filename: String,
+    pub purpose: String,
+    /// 按文件内容识别的类型
+    pub mime_type: String,
+    pub sha256: String,
+    pub created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl FileObject {
+    /// 已过期的文件在被清理之前也不再可见
+    fn expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// 分块上传任务
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadObject {
+    pub id: String,
+    pub object: String,
+    pub filename: String,
+    pub purpose: String,
+    /// 文件总大小
+    pub bytes: u64,
+    /// 已接收的字节数，即下一块的`Upload-Offset`
+    pub received: u64,
+    pub created_at: u64,
+    /// 到期仍未完成时删除
+    pub expires_at: u64,
+    /// 完成后生成的文件保留多久，`None`为`files.ttl_seconds`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_after: Option<u64>,
+    /// 正在写入或完成中，拒绝并发的请求
+    #[serde(skip)]
+    busy: bool,
+}
+
+fn check_filename(filename: &str) -> ApiResult<()> {
+    let invalid = filename.is_empty()
+        || filename.len() > 255
+        || filename == "."
+        || filename == ".."
+        || filename.chars().any(|c| c == '/' || c == '\\' || c.is_control());
+    if invalid {
+        return Err(ApiError::invalid_request(format!("无效的文件名 {:?}", filename)));
+    }
+    Ok(())
+}
+
+/// 按文件头识别类型，无法识别的二进制内容为`application/octet-stream`
+pub fn sniff(head: &[u8], filename: &str) -> &'static str {
+    let extension = FsPath::new(filename)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_default();
+    let starts = |magic: &[u8]| head.starts_with(magic);
+    if starts(b"%PDF-") {
+        return "application/pdf";
+    }
+    if starts(b"PK\x03\x04") {
+        // DOCX等Office文档都是ZIP，按是否包含word/目录区分
+        let docx = head.windows(5).any(|window| window == b"word/") || extension == "docx";
+        return if docx {
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+        } else {
+            "application/zip"
+        };
+    }
+    if starts(b"\x89PNG\r\n\x1a\n") {
+        return "image/png";
+    }
+    if starts(b"\xff\xd8\xff") {
+        return "image/jpeg";
+    }
+    if starts(b"GIF87a") || starts(b"GIF89a") {
+        return "image/gif";
+    }
+    if head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+        return "image/webp";
+    }
+    if starts(b"\x1f\x8b") {
+        return "application/gzip";
+    }
+    if starts(b"\x7fELF") || starts(b"MZ") {
+        return "application/x-executable";
+    }
+
+    // 文件头可能在多字节字符中间截断，只要求截断处之前是有效的UTF-8
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&head[..e.valid_up_to()]).unwrap_or_default(),
+        Err(_) => return "application/octet-stream",
+    };
+    if head.contains(&0) {
+        return "application/octet-stream";
+    }
+    let lower = text.trim_start_matches('\u{feff}').trim_start().to_ascii_lowercase();
+    if lower.starts_with("<!doctype html") || lower.starts_with("<html") {
+        return "text/html";
+    }
+    match extension.as_str() {
+        "md" | "markdown" | "mdx" => "text/markdown",
+        "csv" => "text/csv",
+        "json" => "application/json",
+        "html" | "htm" | "xhtml" => "text/html",
+        _ => "text/plain",
+    }
+}
+
+/// 类型是否在允许列表中，列表为空时允许所有类型；以`/`结尾的项按前缀匹配，如`text/`
+fn allowed(config: &FilesConfig, mime_type: &str) -> bool {
+    config.allowed_types.is_empty()
+        || config.allowed_types.iter().any(|allowed| {
+            if allowed.ends_with('/') {
+                mime_type.starts_with(allowed.as_str())
+            } else {
+                mime_type == allowed
+            }
+        })
+}
+
+/// 病毒扫描的结论
+enum Verdict {
+    Clean,
+    Infected(String),
+}
+
+/// 运行扫描命令：退出码0为无毒，1为发现病毒，其他为扫描失败
+fn scan_command(config: &ScanConfig, path: &FsPath) -> Result<Verdict, String> {
+    let path = path.to_string_lossy();
+    let args: Vec<String> = config.command.iter().map(|arg| arg.replace("{path}", &path)).collect();
+    let mut child = Command::new(&args[0])
+        .args(&args[1..])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("无法运行扫描命令 {}: {}", args[0], e))?;
+    let deadline = Instant::now() + Duration::from_secs(config.timeout_seconds);
+    let status = loop {
+        match child.try_wait().map_err(|e| format!("等待扫描命令失败: {}", e))? {
+            Some(status) => break status,
+            None if Instant::now() >= deadline => {
+                let _ = child.kill();
+                return Err(format!("扫描超过 {} 秒", config.timeout_seconds));
+            }
+            None => std::thread::sleep(Duration::from_millis(50)),
+        }
+    };
+    let mut output = String::new();
+    if let Some(mut stdout) = child.stdout.take() {
+        let _ = stdout.read_to_string(&mut output);
+    }
+    match status.code() {
+        Some(0) => Ok(Verdict::Clean),
+        Some(1) => Ok(Verdict::Infected(output.trim().to_string())),
+        code => Err(format!("扫描命令异常退出（{:?}）: {}", code, output.trim())),
+    }
+}
+
+/// 把文件POST到扫描接口，响应`{"clean": true}`为无毒，`{"clean": false, "reason": ...}`为发现病毒
+async fn scan_http(
+    http: &reqwest::Client,
+    config: &ScanConfig,
+    url: &str,
+    path: &FsPath,
+    filename: &str,
+) -> Result<Verdict, String> {
+    let data = fs::read(path).map_err(|e| format!("读取文件失败: {}", e))?;
+    let response = http
+        .post(url)
+        .timeout(Duration::from_secs(config.timeout_seconds))
+        .header(CONTENT_TYPE, "application/octet-stream")
+        .header("x-openkimi-filename", filename)
+        .body(data)
+        .send()
+        .await
+        .map_err(|e| format!("请求扫描接口失败: {}", e))?;
+    let status = response.status();
+    let body: Value = response.json().await.unwrap_or_default();
+    if !status.is_success() {
+        return Err(format!("扫描接口返回 {}: {}", status, body));
+    }
+    match body.get("clean").and_then(Value::as_bool) {
+        Some(true) => Ok(Verdict::Clean),
+        Some(false) => Ok(Verdict::Infected(body["reason"].as_str().unwrap_or_default().to_string())),
+        None => Err(format!("扫描接口的响应缺少 clean: {}", body)),
+    }
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    /// 按id索引，值为所属工作区和文件
+    files: HashMap<String, (String, FileObject)>,
+    uploads: HashMap<String, (String, UploadObject)>,
+}
+
+/// 文件库
+#[derive(Debug)]
+pub struct FileStore {
+    config: FilesConfig,
+    http: reqwest::Client,
+    entries: Arc<Mutex<Entries>>,
+}
+
+fn write_json(path: &FsPath, value: &impl Serialize) -> ApiResult<()> {
+    let text = serde_json::to_string_pretty(value).unwrap_or_default();
+    fs::write(path, text).map_err(|e| ApiError::Internal(format!("写入 {} 失败: {}", path.display(), e)))
+}
+
+fn remove(path: &FsPath) {
+    if let Err(e) = fs::remove_file(path) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            eprintln!("⚠️ 删除 {} 失败: {}", path.display(), e);
+        }
+    }
+}
+
+impl FileStore {
+    /// 载入`files.dir`中已有的文件和上传任务，并启动清理线程
+    pub fn open(config: &FilesConfig) -> Result<FileStore, String> {
+        if config.scan.url.is_none() && config.scan.command.first().is_some_and(String::is_empty) {
+            return Err("files.scan.command 的第一项应为命令".to_string());
+        }
+        fs::create_dir_all(&config.dir).map_err(|e| format!("创建目录 {} 失败: {}", config.dir.display(), e))?;
+        let mut entries = Entries::default();
+        for workspace in fs::read_dir(&config.dir).map_err(|e| format!("读取目录 {} 失败: {}", config.dir.display(), e))? {
+            let Ok(workspace) = workspace else { continue };
+            let name = workspace.file_name().to_string_lossy().into_owned();
+            let Ok(files) = fs::read_dir(workspace.path()) else { continue };
+            for file in files.flatten() {
+                let path = file.path();
+                if path.extension().is_none_or(|extension| extension != "json") {
+                    continue;
+                }
+                let Ok(text) = fs::read_to_string(&path) else { continue };
+                if let Ok(file) = serde_json::from_str::<FileObject>(&text) {
+                    entries.files.insert(file.id.clone(), (name.clone(), file));
+                } else if let Ok(upload) = serde_json::from_str::<UploadObject>(&text) {
+                    entries.uploads.insert(upload.id.clone(), (name.clone(), upload));
+                } else {
+                    eprintln!("⚠️ 无法解析文件元数据 {}", path.display());
+                }
+            }
+        }
+
+        let http = reqwest::Client::builder()
+            .build()
+            .map_err(|e| format!("无法创建 HTTP 客户端: {}", e))?;
+        let store = FileStore {
+            config: config.clone(),
+            http,
+            entries: Arc::new(Mutex::new(entries)),
+        };
+        let entries = Arc::clone(&store.entries);
+        let dir = config.dir.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(CLEANUP_INTERVAL);
+            remove_expired(&dir, &entries);
+        });
+        Ok(store)
+    }
+
+    fn dir(&self, workspace: &str) -> PathBuf {
+        self.config.dir.join(workspace)
+    }
+
+    fn data_path(&self, workspace: &str, id: &str) -> PathBuf {
+        self.dir(workspace).join(id)
+    }
+
+    fn meta_path(&self, workspace: &str, id: &str) -> PathBuf {
+        self.dir(workspace).join(format!("{}.json", id))
+    }
+
+    pub fn list(&self, workspace: &Workspace, purpose: Option<&str>) -> Vec<FileObject> {
+        let now = unix_now();
+        let entries = self.entries.lock().unwrap();
+        let mut files: Vec<FileObject> = entries
+            .files
+            .values()
+            .filter(|(owner, file)| owner == workspace.name() && !file.expired(now))
+            .filter(|(_, file)| purpose.is_none_or(|purpose| file.purpose == purpose))
+            .map(|(_, file)| file.clone())
+            .collect();
+        files.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| a.id.cmp(&b.id)));
+        files
+    }
+
+    pub fn file(&self, workspace: &Workspace, id: &str) -> ApiResult<FileObject> {
+        let entries = self.entries.lock().unwrap();
+        match entries.files.get(id) {
+            Some((owner, file)) if owner == workspace.name() && !file.expired(unix_now()) => Ok(file.clone()),
+            _ => Err(ApiError::NotFound(format!("文件 {} 不存在", id))),
+        }
+    }
+
+    pub fn upload(&self, workspace: &Workspace, id: &str) -> ApiResult<UploadObject> {
+        let entries = self.entries.lock().unwrap();
+        match entries.uploads.get(id) {
+            Some((owner, upload)) if owner == workspace.name() => Ok(upload.clone()),
+            _ => Err(ApiError::NotFound(format!("上传任务 {} 不存在", id))),
+        }
+    }
+
+    /// 文件内容
+    pub fn read(&self, workspace: &Workspace, id: &str) -> ApiResult<(FileObject, Vec<u8>)> {
+        let file = self.file(workspace, id)?;
+        let path = self.data_path(workspace.name(), id);
+        let data = fs::read(&path).map_err(|e| ApiError::Internal(format!("读取文件 {} 失败: {}", id, e)))?;
+        Ok((file, data))
+    }
+
+    pub fn create_upload(&self, workspace: &Workspace, request: NewUpload) -> ApiResult<UploadObject> {
+        check_filename(&request.filename)?;
+        if request.bytes > self.config.max_file_bytes {
+            return Err(ApiError::invalid_request(format!("文件超过 {} 字节的上限", self.config.max_file_bytes)));
+        }
+        let now = unix_now();
+        let upload = UploadObject {
+            id: random_id("upload")?,
+            object: "upload".to_string(),
+            filename: request.filename,
+            purpose: request.purpose.unwrap_or_else(|| "rag".to_string()),
+            bytes: request.bytes,
+            received: 0,
+            created_at: now,
+            expires_at: now + self.config.upload_ttl_seconds,
+            expires_after: request.expires_after,
+            busy: false,
+        };
+        let dir = self.dir(workspace.name());
+        fs::create_dir_all(&dir).map_err(|e| ApiError::Internal(format!("创建目录 {} 失败: {}", dir.display(), e)))?;
+        File::create(self.data_path(workspace.name(), &upload.id))
+            .map_err(|e| ApiError::Internal(format!("创建上传文件失败: {}", e)))?;
+        write_json(&self.meta_path(workspace.name(), &upload.id), &upload)?;
+        self.entries
+            .lock()
+            .unwrap()
+            .uploads
+            .insert(upload.id.clone(), (workspace.name().to_string(), upload.clone()));
+        Ok(upload)
+    }
+
+    /// 标记上传任务为进行中，返回其当前状态
+    fn claim(&self, workspace: &Workspace, id: &str) -> ApiResult<UploadObject> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.uploads.get_mut(id) {
+            Some((owner, upload)) if owner == workspace.name() => {
+                if upload.busy {
+                    return Err(ApiError::invalid_request(format!("上传任务 {} 正在处理另一个请求", id)));
+                }
+                upload.busy = true;
+                Ok(upload.clone())
+            }
+            _ => Err(ApiError::NotFound(format!("上传任务 {} 不存在", id))),
+        }
+    }
+
+    fn release(&self, workspace: &Workspace, id: &str, received: Option<u64>) -> Option<UploadObject> {
+        let mut entries = self.entries.lock().unwrap();
+        let (_, upload) = entries.uploads.get_mut(id)?;
+        upload.busy = false;
+        if let Some(received) = received {
+            upload.received = received;
+            if let Err(err) = write_json(&self.meta_path(workspace.name(), id), upload) {
+                eprintln!("⚠️ {}", err);
+            }
+        }
+        Some(upload.clone())
+    }
+
+    /// 把请求体追加到上传文件，连接中断时保留已写入的部分以便续传
+    pub async fn append(&self, workspace: &Workspace, id: &str, offset: u64, body: Body) -> ApiResult<UploadObject> {
+        let upload = self.claim(workspace, id)?;
+        if offset != upload.received {
+            self.release(workspace, id, None);
+            return Err(ApiError::invalid_request(format!(
+                "Upload-Offset 应为已接收的字节数 {}，而不是 {}",
+                upload.received, offset
+            )));
+        }
+        let path = self.data_path(workspace.name(), id);
+        let (received, result) = self.write_body(&path, upload.received, upload.bytes, body).await;
+        let upload = self.release(workspace, id, Some(received));
+        result?;
+        upload.ok_or_else(|| ApiError::NotFound(format!("上传任务 {} 不存在", id)))
+    }
+
+    /// 从`offset`开始写入，返回写入后的长度；超出大小限制时回退到`offset`
+    async fn write_body(&self, path: &FsPath, offset: u64, total: u64, body: Body) -> (u64, ApiResult<()>) {
+        let mut file = match OpenOptions::new().write(true).open(path) {
+            Ok(file) => file,
+            Err(e) => return (offset, Err(ApiError::Internal(format!("打开上传文件失败: {}", e)))),
+        };
+        // 上次中断时可能多写了未记录的数据
+        if let Err(e) = file.set_len(offset).and_then(|_| std::io::Seek::seek(&mut file, std::io::SeekFrom::End(0))) {
+            return (offset, Err(ApiError::Internal(format!("写入上传文件失败: {}", e))));
+        }
+
+        let mut written = 0u64;
+        let mut stream = body.into_data_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => return (offset + written, Err(ApiError::invalid_request(format!("读取请求体失败: {}", e)))),
+            };
+            written += chunk.len() as u64;
+            let limit = if written > self.config.max_chunk_bytes {
+                Some(format!("每块最多 {} 字节", self.config.max_chunk_bytes))
+            } else if offset + written > total {
+                Some(format!("超出上传任务声明的 {} 字节", total))
+            } else {
+                None
+            };
+            if let Some(message) = limit {
+                let _ = file.set_len(offset);
+                return (offset, Err(ApiError::invalid_request(message)));
+            }
+            if let Err(e) = file.write_all(&chunk) {
+                let _ = file.set_len(offset);
+                return (offset, Err(ApiError::Internal(format!("写入上传文件失败: {}", e))));
+            }
+        }
+        (offset + written, Ok(()))
+    }
+
+    pub fn cancel(&self, workspace: &Workspace, id: &str) -> ApiResult<()> {
+        self.claim(workspace, id)?;
+        self.entries.lock().unwrap().uploads.remove(id);
+        remove(&self.data_path(workspace.name(), id));
+        remove(&self.meta_path(workspace.name(), id));
+        Ok(())
+    }
+
+    /// 接收完毕后识别类型、扫描，并转为文件
+    pub async fn complete(&self, workspace: &Workspace, id: &str) -> ApiResult<FileObject> {
+        let upload = self.claim(workspace, id)?;
+        if upload.received != upload.bytes {
+            self.release(workspace, id, None);
+            return Err(ApiError::invalid_request(format!(
+                "上传尚未完成：已接收 {} / {} 字节",
+                upload.received, upload.bytes
+            )));
+        }
+        let path = self.data_path(workspace.name(), id);
+        match self.finish(workspace, &upload, &path).await {
+            Ok(file) => Ok(file),
+            Err(err) => {
+                // 类型不允许或扫描未通过的内容不再保留
+                if matches!(err, ApiError::InvalidRequest(_)) {
+                    self.entries.lock().unwrap().uploads.remove(id);
+                    remove(&path);
+                    remove(&self.meta_path(workspace.name(), id));
+                } else {
+                    self.release(workspace, id, None);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    async fn finish(&self, workspace: &Workspace, upload: &UploadObject, path: &FsPath) -> ApiResult<FileObject> {
+        let (head, sha256) = digest_file(path).map_err(|e| ApiError::Internal(format!("读取上传文件失败: {}", e)))?;
+        let mime_type = sniff(&head, &upload.filename);
+        if !allowed(&self.config, mime_type) {
+            return Err(ApiError::invalid_request(format!("不允许上传 {} 类型的文件", mime_type)));
+        }
+        self.scan(path, &upload.filename).await?;
+
+        let file_id = random_id("file")?;
+        let now = unix_now();
+        let ttl = upload.expires_after.or((self.config.ttl_seconds > 0).then_some(self.config.ttl_seconds));
+        let file = FileObject {
+            id: file_id.clone(),
+            object: "file".to_string(),
+            bytes: upload.bytes,
+            filename: upload.filename.clone(),
+            purpose: upload.purpose.clone(),
+            mime_type: mime_type.to_string(),
+            sha256,
+            created_at: now,
+            expires_at: ttl.map(|ttl| now + ttl),
+        };
+        let target = self.data_path(workspace.name(), &file_id);
+        fs::rename(path, &target).map_err(|e| ApiError::Internal(format!("保存文件失败: {}", e)))?;
+        if let Err(err) = write_json(&self.meta_path(workspace.name(), &file_id), &file) {
+            remove(&target);
+            return Err(err);
+        }
+        remove(&self.meta_path(workspace.name(), &upload.id));
+        let mut entries = self.entries.lock().unwrap();
+        entries.uploads.remove(&upload.id);
+        entries.files.insert(file_id, (workspace.name().to_string(), file.clone()));
+        Ok(file)
+    }
+
+    /// 运行配置的扫描，发现病毒时返回请求错误，扫描本身失败时按`scan.fail_open`处理
+    async fn scan(&self, path: &FsPath, filename: &str) -> ApiResult<()> {
+        let scan = &self.config.scan;
+        let verdict = if let Some(url) = &scan.url {
+            scan_http(&self.http, scan, url, path, filename).await
+        } else if !scan.command.is_empty() {
+            let (config, path) = (scan.clone(), path.to_path_buf());
+            tokio::task::spawn_blocking(move || scan_command(&config, &path))
+                .await
+                .unwrap_or_else(|e| Err(format!("扫描任务异常: {}", e)))
+        } else {
+            return Ok(());
+        };
+        match verdict {
+            Ok(Verdict::Clean) => Ok(()),
+            Ok(Verdict::Infected(reason)) => {
+                eprintln!("⚠️ 文件 {} 未通过病毒扫描: {}", filename, reason);
+                Err(ApiError::invalid_request(format!("文件未通过病毒扫描: {}", reason)))
+            }
+            Err(message) if scan.fail_open => {
+                eprintln!("⚠️ 扫描文件 {} 失败，按配置放行: {}", filename, message);
+                Ok(())
+            }
+            Err(message) => Err(ApiError::Internal(format!("扫描文件失败: {}", message))),
+        }
+    }
+
+    pub fn delete(&self, workspace: &Workspace, id: &str) -> ApiResult<()> {
+        self.file(workspace, id)?;
+        self.entries.lock().unwrap().files.remove(id);
+        remove(&self.data_path(workspace.name(), id));
+        remove(&self.meta_path(workspace.name(), id));
+        Ok(())
+    }
+}
+
+/// 文件头和SHA-256
+fn digest_file(path: &FsPath) -> std::io::Result<(Vec<u8>, String)> {
+    let mut file = File::open(path)?;
+    let mut context = Context::new(&SHA256);
+    let mut head = Vec::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        if head.len() < SNIFF_BYTES {
+            let take = (SNIFF_BYTES - head.len()).min(read);
+            head.extend_from_slice(&buffer[..take]);
+        }
+        context.update(&buffer[..read]);
+    }
+    let hex = context.finish().as_ref().iter().map(|byte| format!("{:02x}", byte)).collect();
+    Ok((head, hex))
+}
+
+/// 删除过期的文件和上传任务
+fn remove_expired(dir: &FsPath, entries: &Mutex<Entries>) {
+    let now = unix_now();
+    let mut entries = entries.lock().unwrap();
+    let mut expired = Vec::new();
+    entries.files.retain(|id, (workspace, file)| {
+        let keep = !file.expired(now);
+        if !keep {
+            expired.push((workspace.clone(), id.clone()));
+        }
+        keep
+    });
+    entries.uploads.retain(|id, (workspace, upload)| {
+        let keep = upload.busy || upload.expires_at > now;
+        if !keep {
+            expired.push((workspace.clone(), id.clone()));
+        }
+        keep
+    });
+    drop(entries);
+    for (workspace, id) in expired {
+        remove(&dir.join(&workspace).join(&id));
+        remove(&dir.join(&workspace).join(format!("{}.json", id)));
+    }
+}
+
+fn store(state: &AppState) -> ApiResult<&FileStore> {
+    state
+        .files
+        .as_ref()
+        .ok_or_else(|| ApiError::invalid_request("文件上传未启用（files.enabled 为 false）"))
+}
+
+/// `POST /v1/files/uploads`请求
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewUpload {
+    pub filename: String,
+    /// 文件总大小
+    pub bytes: u64,
+    #[serde(default)]
+    pub purpose: Option<String>,
+    /// 生成的文件保留多少秒
+    #[serde(default)]
+    pub expires_after: Option<u64>,
+}
+
+/// `POST /v1/files`的查询参数
+#[derive(Debug, Deserialize)]
+pub struct UploadParams {
+    pub filename: String,
+    #[serde(default)]
+    pub purpose: Option<String>,
+    #[serde(default)]
+    pub expires_after: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListParams {
+    #[serde(default)]
+    pub purpose: Option<String>,
+}
+
+/// `POST /v1/files/{id}/ingest`请求
+#[derive(Debug, Default, Deserialize)]
+pub struct FileIngestRequest {
+    /// 索引名，缺省为`rag.default_index`
+    #[serde(default)]
+    pub index: Option<String>,
+    #[serde(default)]
+    pub metadata: Map<String, Value>,
+    /// 分块配置，缺省时按文件类型或索引的配置
+    #[serde(default)]
+    pub chunking: Option<ChunkConfig>,
+}
+
+pub async fn list_files(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Query(params): Query<ListParams>,
+) -> ApiResult<Json<ListResponse<FileObject>>> {
+    Ok(Json(ListResponse::new(store(&state)?.list(&workspace, params.purpose.as_deref()))))
+}
+
+/// 一次请求上传整个文件，请求体为文件内容
+pub async fn upload_file(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Query(params): Query<UploadParams>,
+    headers: HeaderMap,
+    body: Body,
+) -> ApiResult<Json<FileObject>> {
+    let store = store(&state)?;
+    let length = headers
+        .get(axum::http::header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    let Some(bytes) = length else {
+        return Err(ApiError::invalid_request("需要 Content-Length，大小未知的文件请使用分块上传"));
+    };
+    if bytes > store.config.max_chunk_bytes {
+        return Err(ApiError::invalid_request(format!(
+            "超过 {} 字节的文件请使用分块上传 /v1/files/uploads",
+            store.config.max_chunk_bytes
+        )));
+    }
+    let upload = store.create_upload(
+        &workspace,
+        NewUpload {
+            filename: params.filename,
+            bytes,
+            purpose: params.purpose,
+            expires_after: params.expires_after,
+        },
+    )?;
+    let result = match store.append(&workspace, &upload.id, 0, body).await {
+        Ok(_) => store.complete(&workspace, &upload.id).await,
+        Err(err) => Err(err),
+    };
+    if result.is_err() {
+        let _ = store.cancel(&workspace, &upload.id);
+    }
+    Ok(Json(result?))
+}
+
+pub async fn get_file(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+) -> ApiResult<Json<FileObject>> {
+    Ok(Json(store(&state)?.file(&workspace, &id)?))
+}
+
+pub async fn file_content(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+) -> ApiResult<Response> {
+    let (file, data) = store(&state)?.read(&workspace, &id)?;
+    let disposition = format!("attachment; filename=\"{}\"", file.filename.replace(['"', '\\'], "_"));
+    Ok((
+        [(CONTENT_TYPE, file.mime_type), (CONTENT_DISPOSITION, disposition)],
+        data,
+    )
+        .into_response())
+}
+
+pub async fn delete_file(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+) -> ApiResult<Json<DeletedResponse>> {
+    store(&state)?.delete(&workspace, &id)?;
+    Ok(Json(DeletedResponse {
+        id,
+        object: "file".to_string(),
+        deleted: true,
+    }))
+}
+
+/// 把文件导入向量索引，文档id为文件id
+pub async fn ingest_file(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+    request: Option<Json<FileIngestRequest>>,
+) -> ApiResult<Json<IngestResponse>> {
+    let Json(request) = request.unwrap_or_default();
+    let (file, data) = store(&state)?.read(&workspace, &id)?;
+    let kind = DocumentKind::from_name(&file.filename)
+        .ok_or_else(|| ApiError::invalid_request(format!("不支持导入的文件类型: {}", file.filename)))?;
+    let mut metadata = request.metadata;
+    metadata.insert("file_id".to_string(), json!(file.id));
+    let index = request.index.unwrap_or_else(|| state.config().rag.default_index.clone());
+    let document = Document {
+        id: file.id.clone(),
+        name: file.filename,
+        kind,
+        data,
+        metadata,
+        chunking: request.chunking,
+    };
+    let results = rag::ingest_documents(&state, &workspace, &index, &[document]).await?;
+    Ok(Json(IngestResponse {
+        object: "rag.ingest".to_string(),
+        index,
+        chunks: results.iter().map(|result| result.chunks).sum(),
+        documents: results,
+    }))
+}
+
+pub async fn create_upload(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Json(request): Json<NewUpload>,
+) -> ApiResult<Json<UploadObject>> {
+    Ok(Json(store(&state)?.create_upload(&workspace, request)?))
+}
+
+/// 上传任务的状态，续传前用`received`确定下一块的偏移
+pub async fn get_upload(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+) -> ApiResult<Json<UploadObject>> {
+    Ok(Json(store(&state)?.upload(&workspace, &id)?))
+}
+
+/// 追加一块，请求头`Upload-Offset`为这块在文件中的起始位置
+pub async fn append_upload(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> ApiResult<Json<UploadObject>> {
+    let offset = headers
+        .get(OFFSET_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .ok_or_else(|| ApiError::invalid_request("缺少或无效的 Upload-Offset 请求头"))?;
+    Ok(Json(store(&state)?.append(&workspace, &id, offset, body).await?))
+}
+
+pub async fn complete_upload(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+) -> ApiResult<Json<FileObject>> {
+    Ok(Json(store(&state)?.complete(&workspace, &id).await?))
+}
+
+pub async fn cancel_upload(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+) -> ApiResult<Json<DeletedResponse>> {
+    store(&state)?.cancel(&workspace, &id)?;
+    Ok(Json(DeletedResponse {
+        id,
+        object: "upload".to_string(),
+        deleted: true,
+    }))
+}
@@ -0,0 +1,621 @@
+//! gRPC接口
+//!
+//! 定义见`proto/openkimi.proto`。各方法与HTTP接口共用上游客户端、会话存储和请求校验逻辑，
+//! 只在这里完成protobuf消息与OpenAI JSON结构之间的转换。
+
+// tonic的接口约定以`Status`作为错误类型，它本身就比较大
+#![allow(clippy::result_large_err)]
+
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures_util::{Stream, StreamExt};
+use openkimi_sessions::{Message, NewMessage, NewSession, Session, SessionQuery, TokenUsage};
+use serde_json::{Map, Value};
+use tonic::{Request, Response, Status};
+
+use crate::error::ApiError;
+use crate::types::{self, ChatCompletionRequest, EmbeddingInput, MessageContent};
+use crate::metering::{self, Consumer};
+use crate::tools::ToolScope;
+use crate::workspace::{self, Workspace};
+use crate::pii::{self, Tokens};
+use crate::{auth, quotas, safety, sessions, structured, AppState};
+
+/// 由build.rs根据proto生成的代码
+pub mod proto {
+    tonic::include_proto!("openkimi.v1");
+}
+
+use proto::chat_server::{Chat, ChatServer};
+use proto::embeddings_server::{Embeddings, EmbeddingsServer};
+use proto::models_server::{Models, ModelsServer};
+use proto::sessions_server::{Sessions, SessionsServer};
+
+impl From<ApiError> for Status {
+    fn from(err: ApiError) -> Status {
+        match err {
+            ApiError::InvalidRequest(message) => Status::invalid_argument(message),
+            ApiError::ContextLengthExceeded(message) => Status::out_of_range(message),
+            ApiError::Unauthorized(message) => Status::unauthenticated(message),
+            ApiError::Forbidden(message) => Status::permission_denied(message),
+            ApiError::NotFound(message) => Status::not_found(message),
+            ApiError::PayloadTooLarge(message) => Status::out_of_range(message),
+            ApiError::Conflict(message) => Status::aborted(message),
+            ApiError::RateLimited(message) => Status::resource_exhausted(message),
+            ApiError::Internal(message) => Status::internal(message),
+            ApiError::Unavailable(message) => Status::unavailable(message),
+            ApiError::Upstream(message) => Status::unavailable(message),
+            ApiError::SchemaValidation(message, errors) => {
+                Status::failed_precondition(format!("{}: {}", message, errors.join("; ")))
+            }
+            ApiError::UpstreamStatus(status, body) => {
+                // 优先使用上游OpenAI格式错误中的message
+                let message = body["error"]["message"]
+                    .as_str()
+                    .map(str::to_string)
+                    .unwrap_or_else(|| body.to_string());
+                grpc_status(status.as_u16(), message)
+            }
+        }
+    }
+}
+
+/// 按HTTP状态码选择对应的gRPC状态码
+fn grpc_status(http_status: u16, message: String) -> Status {
+    match http_status {
+        400 | 422 => Status::invalid_argument(message),
+        401 => Status::unauthenticated(message),
+        403 => Status::permission_denied(message),
+        404 => Status::not_found(message),
+        429 => Status::resource_exhausted(message),
+        _ => Status::unavailable(message),
+    }
+}
+
+fn usage_from(usage: &types::Usage) -> proto::Usage {
+    proto::Usage {
+        prompt_tokens: usage.prompt_tokens,
+        completion_tokens: usage.completion_tokens,
+        total_tokens: usage.total_tokens,
+    }
+}
+
+/// 转换并校验对话请求，同时返回提示词的token数
+async fn chat_request(
+    state: &AppState,
+    request: proto::ChatCompletionRequest,
+    stream: bool,
+) -> Result<(ChatCompletionRequest, u32, Tokens), Status> {
+    let mut extra = Map::new();
+    if let Some(top_p) = request.top_p {
+        extra.insert("top_p".to_string(), Value::from(top_p));
+    }
+    if !request.stop.is_empty() {
+        extra.insert("stop".to_string(), Value::from(request.stop));
+    }
+
+    let mut request = ChatCompletionRequest {
+        model: request.model,
+        messages: request
+            .messages
+            .into_iter()
+            .map(|message| types::ChatMessage {
+                role: message.role,
+                content: Some(MessageContent::Text(message.content)),
+                name: message.name,
+                extra: Map::new(),
+            })
+            .collect(),
+        stream: stream.then_some(true),
+        max_tokens: request.max_tokens,
+        temperature: request.temperature,
+        extra,
+    };
+    let placeholders = pii::tokenize(state, &mut request).await?;
+    let prompt_tokens = state.prepare_chat(&mut request).await?;
+    Ok((request, prompt_tokens, placeholders))
+}
+
+/// 把SSE中的`chat.completion.chunk`转为protobuf消息
+fn chunk_from(data: &Value) -> proto::ChatCompletionChunk {
+    let text = |value: &Value, key: &str| value.get(key).and_then(Value::as_str).map(str::to_string);
+    let choices = data
+        .get("choices")
+        .and_then(Value::as_array)
+        .map(|choices| {
+            choices
+                .iter()
+                .map(|choice| proto::ChatChunkChoice {
+                    index: choice.get("index").and_then(Value::as_u64).unwrap_or(0) as u32,
+                    delta: choice.get("delta").map(|delta| proto::ChatDelta {
+                        role: text(delta, "role"),
+                        content: text(delta, "content"),
+                    }),
+                    finish_reason: text(choice, "finish_reason"),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let usage = data
+        .get("usage")
+        .and_then(|usage| serde_json::from_value::<types::Usage>(usage.clone()).ok());
+
+    proto::ChatCompletionChunk {
+        id: text(data, "id").unwrap_or_default(),
+        model: text(data, "model").unwrap_or_default(),
+        created: data.get("created").and_then(Value::as_u64).unwrap_or(0),
+        choices,
+        usage: usage.as_ref().map(usage_from),
+    }
+}
+
+/// 启用认证时按对应HTTP接口的路径检查`authorization`元数据中的令牌，再确定工作区并检查配额，
+/// 返回用量的归属
+async fn authorize<T>(state: &AppState, request: &Request<T>, method: &str, path: &str) -> Result<Consumer, Status> {
+    let metadata = |name: &str| request.metadata().get(name).and_then(|value| value.to_str().ok());
+    let auth = state.auth();
+    let principal = match auth.as_ref() {
+        Some(auth) => auth.check(auth::bearer(metadata("authorization")), method, path).await?,
+        None => None,
+    };
+    let workspace = match &state.workspaces {
+        Some(workspaces) => {
+            let workspace = workspaces.resolve(principal.as_ref(), metadata(&state.config().workspaces.header))?;
+            workspaces.acquire(&workspace)?;
+            workspace
+        }
+        None => Workspace::default(),
+    };
+    Ok(Consumer::new(principal.as_ref(), workspace, metadata(&state.config().metering.user_header)))
+}
+
+/// 对话服务
+pub struct ChatService {
+    state: Arc<AppState>,
+}
+
+type ChunkStream = Pin<Box<dyn Stream<Item = Result<proto::ChatCompletionChunk, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Chat for ChatService {
+    async fn complete(
+        &self,
+        request: Request<proto::ChatCompletionRequest>,
+    ) -> Result<Response<proto::ChatCompletionResponse>, Status> {
+        let consumer = authorize(&self.state, &request, "POST", "/v1/chat/completions").await?;
+        let _slot = quotas::acquire(&self.state, &consumer).map_err(ApiError::from)?;
+        let (request, prompt_tokens, placeholders) = chat_request(&self.state, request.into_inner(), false).await?;
+        let scope = ToolScope::new(consumer.workspace.clone(), None)?;
+        let mut response = structured::chat_completion(&self.state, &request, &scope).await?;
+        placeholders.restore_response(&mut response);
+        safety::screen_response(&self.state, &consumer.workspace, &mut response);
+        if response.usage.is_none() {
+            response.usage = Some(self.state.usage(&request.model, prompt_tokens, &response));
+        }
+        let usage = response.usage.clone().unwrap_or_default();
+        workspace::charge(&self.state, &consumer.workspace, usage.total_tokens);
+        metering::record(&self.state, &consumer, &request.model, usage.prompt_tokens, usage.completion_tokens);
+
+        Ok(Response::new(proto::ChatCompletionResponse {
+            id: response.id,
+            model: response.model,
+            created: response.created,
+            choices: response
+                .choices
+                .into_iter()
+                .map(|choice| proto::ChatChoice {
+                    index: choice.index,
+                    message: Some(proto::ChatMessage {
+                        role: choice.message.role.clone(),
+                        content: choice.message.text(),
+                        name: choice.message.name.clone(),
+                    }),
+                    finish_reason: choice.finish_reason.unwrap_or_default(),
+                })
+                .collect(),
+            usage: response.usage.as_ref().map(usage_from),
+        }))
+    }
+
+    type StreamCompleteStream = ChunkStream;
+
+    async fn stream_complete(
+        &self,
+        request: Request<proto::ChatCompletionRequest>,
+    ) -> Result<Response<Self::StreamCompleteStream>, Status> {
+        let consumer = authorize(&self.state, &request, "POST", "/v1/chat/completions").await?;
+        let slot = quotas::acquire(&self.state, &consumer).map_err(ApiError::from)?;
+        let (request, prompt_tokens, placeholders) = chat_request(&self.state, request.into_inner(), true).await?;
+        let scope = ToolScope::new(consumer.workspace.clone(), None)?;
+        let data = structured::chat_completion_stream(&self.state, &request, &scope).await?;
+        workspace::charge(&self.state, &consumer.workspace, self.state.stream_tokens(&request, prompt_tokens));
+        let data = placeholders.restore_stream(data);
+        let data = safety::screen_stream(&self.state, &consumer.workspace, &request.model, data);
+        let data = metering::meter_stream(&self.state, &consumer, &request.model, prompt_tokens, data);
+
+        // 调用方取消时流被丢弃，上游连接随之关闭
+        let chunks = data
+            .take_while(|data| std::future::ready(data != "[DONE]"))
+            // 并发名额随流一起释放
+            .map(move |data| {
+                let _ = &slot;
+                let data: Value = serde_json::from_str(&data)
+                    .map_err(|e| Status::internal(format!("无法解析上游分块: {}", e)))?;
+                if let Some(error) = data.get("error") {
+                    let message = error["message"].as_str().unwrap_or("上游流式响应中断");
+                    return Err(Status::unavailable(message));
+                }
+                Ok(chunk_from(&data))
+            });
+        Ok(Response::new(Box::pin(chunks)))
+    }
+}
+
+/// 嵌入服务
+pub struct EmbeddingsService {
+    state: Arc<AppState>,
+}
+
+#[tonic::async_trait]
+impl Embeddings for EmbeddingsService {
+    async fn embed(
+        &self,
+        request: Request<proto::EmbeddingRequest>,
+    ) -> Result<Response<proto::EmbeddingResponse>, Status> {
+        let consumer = authorize(&self.state, &request, "POST", "/v1/embeddings").await?;
+        let _slot = quotas::acquire(&self.state, &consumer).map_err(ApiError::from)?;
+        let request = request.into_inner();
+        let model = if request.model.is_empty() {
+            self.state
+                .config()
+                .llm
+                .embedding_model
+                .clone()
+                .ok_or_else(|| Status::invalid_argument("未指定 model，且配置中没有 llm.embedding_model"))?
+        } else {
+            request.model
+        };
+        let request = types::EmbeddingRequest {
+            model,
+            input: EmbeddingInput::Texts(request.input),
+            extra: Map::new(),
+        };
+        let response = self.state.models().embeddings(&request).await?;
+
+        let usage = response.usage.unwrap_or_default();
+        workspace::charge(&self.state, &consumer.workspace, usage.total_tokens);
+        metering::record(&self.state, &consumer, &request.model, usage.prompt_tokens, 0);
+        Ok(Response::new(proto::EmbeddingResponse {
+            model: response.model,
+            data: response
+                .data
+                .into_iter()
+                .map(|embedding| proto::Embedding {
+                    index: embedding.index,
+                    values: embedding
+                        .embedding
+                        .as_array()
+                        .map(|values| values.iter().filter_map(Value::as_f64).map(|v| v as f32).collect())
+                        .unwrap_or_default(),
+                })
+                .collect(),
+            prompt_tokens: usage.prompt_tokens,
+            total_tokens: usage.total_tokens,
+        }))
+    }
+}
+
+/// 模型列表服务
+pub struct ModelsService {
+    state: Arc<AppState>,
+}
+
+#[tonic::async_trait]
+impl Models for ModelsService {
+    async fn list(
+        &self,
+        request: Request<proto::ListModelsRequest>,
+    ) -> Result<Response<proto::ListModelsResponse>, Status> {
+        authorize(&self.state, &request, "GET", "/v1/models").await?;
+        let models = self
+            .state
+            .model_list()
+            .data
+            .into_iter()
+            .map(|model| proto::Model {
+                id: model.id,
+                owned_by: model.owned_by,
+            })
+            .collect();
+        Ok(Response::new(proto::ListModelsResponse { models }))
+    }
+}
+
+/// 解析JSON对象字段，空字符串视为空对象
+fn json_object(value: Option<&str>, field: &str) -> Result<Map<String, Value>, Status> {
+    match value.filter(|value| !value.trim().is_empty()) {
+        Some(value) => serde_json::from_str(value)
+            .map_err(|e| Status::invalid_argument(format!("{} 不是有效的JSON对象: {}", field, e))),
+        None => Ok(Map::new()),
+    }
+}
+
+fn json_string(object: &Map<String, Value>) -> String {
+    if object.is_empty() {
+        String::new()
+    } else {
+        Value::Object(object.clone()).to_string()
+    }
+}
+
+fn session_usage_from(usage: &TokenUsage) -> proto::SessionUsage {
+    proto::SessionUsage {
+        prompt_tokens: usage.prompt_tokens,
+        completion_tokens: usage.completion_tokens,
+    }
+}
+
+fn session_from(session: Session) -> proto::Session {
+    proto::Session {
+        metadata: json_string(&session.metadata),
+        usage: Some(session_usage_from(&session.usage)),
+        id: session.id,
+        title: session.title,
+        model: session.model,
+        created_at: session.created_at,
+        updated_at: session.updated_at,
+        message_count: session.message_count,
+        head_id: session.head_id,
+        summary: session.summary,
+    }
+}
+
+fn message_from(message: Message) -> proto::SessionMessage {
+    proto::SessionMessage {
+        extra: json_string(&message.extra),
+        usage: message.usage.as_ref().map(session_usage_from),
+        id: message.id,
+        parent_id: message.parent_id,
+        role: message.role,
+        content: message.content,
+        created_at: message.created_at,
+        attachments: message
+            .attachments
+            .into_iter()
+            .map(|attachment| proto::Attachment {
+                id: attachment.id,
+                name: attachment.name,
+                mime_type: attachment.mime_type,
+                size: attachment.size,
+                uri: attachment.uri,
+                sha256: attachment.sha256,
+                created_at: attachment.created_at,
+            })
+            .collect(),
+    }
+}
+
+fn messages_response(messages: Vec<Message>) -> Response<proto::ListMessagesResponse> {
+    Response::new(proto::ListMessagesResponse {
+        messages: messages.into_iter().map(message_from).collect(),
+    })
+}
+
+/// 会话服务
+pub struct SessionsService {
+    state: Arc<AppState>,
+}
+
+#[tonic::async_trait]
+impl Sessions for SessionsService {
+    async fn list(
+        &self,
+        request: Request<proto::ListSessionsRequest>,
+    ) -> Result<Response<proto::ListSessionsResponse>, Status> {
+        let consumer = authorize(&self.state, &request, "GET", "/v1/sessions").await?;
+        let request = request.into_inner();
+        let query = SessionQuery {
+            limit: match request.limit {
+                0 => SessionQuery::default().limit,
+                limit => limit.min(sessions::MAX_LIMIT),
+            },
+            offset: request.offset,
+        };
+        let store = sessions::store(&self.state, &consumer.workspace)?;
+        let list = store.list_sessions(query).await.map_err(ApiError::from)?;
+        Ok(Response::new(proto::ListSessionsResponse {
+            sessions: list.into_iter().map(session_from).collect(),
+        }))
+    }
+
+    async fn get(&self, request: Request<proto::GetSessionRequest>) -> Result<Response<proto::SessionDetail>, Status> {
+        let path = format!("/v1/sessions/{}", request.get_ref().id);
+        let consumer = authorize(&self.state, &request, "GET", &path).await?;
+        let id = request.into_inner().id;
+        let store = sessions::store(&self.state, &consumer.workspace)?;
+        let session = store.session(&id).await.map_err(ApiError::from)?;
+        let messages = store.messages(&id).await.map_err(ApiError::from)?;
+        Ok(Response::new(proto::SessionDetail {
+            session: Some(session_from(session)),
+            messages: messages.into_iter().map(message_from).collect(),
+        }))
+    }
+
+    async fn create(&self, request: Request<proto::CreateSessionRequest>) -> Result<Response<proto::Session>, Status> {
+        let consumer = authorize(&self.state, &request, "POST", "/v1/sessions").await?;
+        let request = request.into_inner();
+        let new_session = NewSession {
+            metadata: json_object(request.metadata.as_deref(), "metadata")?,
+            id: request.id,
+            title: request.title,
+            model: request.model,
+            created_at: None,
+        };
+        let store = sessions::store(&self.state, &consumer.workspace)?;
+        let session = store.create_session(new_session).await.map_err(ApiError::from)?;
+        Ok(Response::new(session_from(session)))
+    }
+
+    async fn delete(
+        &self,
+        request: Request<proto::DeleteSessionRequest>,
+    ) -> Result<Response<proto::DeleteSessionResponse>, Status> {
+        let path = format!("/v1/sessions/{}", request.get_ref().id);
+        let consumer = authorize(&self.state, &request, "DELETE", &path).await?;
+        let id = request.into_inner().id;
+        sessions::store(&self.state, &consumer.workspace)?.delete_session(&id).await.map_err(ApiError::from)?;
+        Ok(Response::new(proto::DeleteSessionResponse { id, deleted: true }))
+    }
+
+    async fn list_messages(
+        &self,
+        request: Request<proto::ListMessagesRequest>,
+    ) -> Result<Response<proto::ListMessagesResponse>, Status> {
+        let path = format!("/v1/sessions/{}/messages", request.get_ref().session_id);
+        let consumer = authorize(&self.state, &request, "GET", &path).await?;
+        let request = request.into_inner();
+        let store = sessions::store(&self.state, &consumer.workspace)?;
+        let messages = if request.tree {
+            store.message_tree(&request.session_id).await
+        } else {
+            store.messages(&request.session_id).await
+        }
+        .map_err(ApiError::from)?;
+        Ok(messages_response(messages))
+    }
+
+    async fn append_messages(
+        &self,
+        request: Request<proto::AppendMessagesRequest>,
+    ) -> Result<Response<proto::ListMessagesResponse>, Status> {
+        let path = format!("/v1/sessions/{}/messages", request.get_ref().session_id);
+        let consumer = authorize(&self.state, &request, "POST", &path).await?;
+        let request = request.into_inner();
+        if request.messages.is_empty() {
+            return Err(Status::invalid_argument("messages 不能为空"));
+        }
+        let mut messages = request
+            .messages
+            .into_iter()
+            .map(|message| {
+                Ok(NewMessage {
+                    extra: json_object(message.extra.as_deref(), "extra")?,
+                    usage: message.usage.map(|usage| TokenUsage {
+                        prompt_tokens: usage.prompt_tokens,
+                        completion_tokens: usage.completion_tokens,
+                    }),
+                    role: message.role,
+                    content: message.content,
+                    model: message.model,
+                    ..NewMessage::default()
+                })
+            })
+            .collect::<Result<Vec<_>, Status>>()?;
+        let store = sessions::store(&self.state, &consumer.workspace)?;
+        pii::redact_transcript(&self.state, messages.iter_mut().map(|message| &mut message.content).collect()).await?;
+        let id = &request.session_id;
+        let messages = match (request.from_start, request.parent_id) {
+            (true, _) => store.branch_messages(id, None, messages).await,
+            (false, Some(parent)) => store.branch_messages(id, Some(parent), messages).await,
+            (false, None) => store.append_messages(id, messages).await,
+        }
+        .map_err(ApiError::from)?;
+        Ok(messages_response(messages))
+    }
+}
+
+/// 在`addr`上启动gRPC服务
+pub async fn serve(state: Arc<AppState>, addr: SocketAddr) -> Result<(), String> {
+    let shutdown = state.shutdown.clone();
+    tonic::transport::Server::builder()
+        .add_service(ChatServer::new(ChatService { state: Arc::clone(&state) }))
+        .add_service(EmbeddingsServer::new(EmbeddingsService { state: Arc::clone(&state) }))
+        .add_service(ModelsServer::new(ModelsService { state: Arc::clone(&state) }))
+        .add_service(SessionsServer::new(SessionsService { state }))
+        .serve_with_shutdown(addr, async move { shutdown.wait().await })
+        .await
+        .map_err(|e| format!("gRPC 服务异常退出: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn service(dir: &std::path::Path) -> SessionsService {
+        let mut config = Config::default();
+        config.sessions.path = dir.join("sessions.db");
+        SessionsService {
+            state: Arc::new(AppState::new(config).unwrap()),
+        }
+    }
+
+    #[tokio::test]
+    async fn sessions_share_the_rest_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = service(dir.path());
+        let created = service
+            .create(Request::new(proto::CreateSessionRequest {
+                title: Some("周报".to_string()),
+                metadata: Some(r#"{"client":"grpc"}"#.to_string()),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(created.metadata, r#"{"client":"grpc"}"#);
+
+        let appended = service
+            .append_messages(Request::new(proto::AppendMessagesRequest {
+                session_id: created.id.clone(),
+                messages: vec![proto::NewSessionMessage {
+                    role: "user".to_string(),
+                    content: "你好".to_string(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(appended.messages.len(), 1);
+
+        // REST接口读到同一个会话
+        let store = sessions::store(&service.state, &Workspace::default()).unwrap();
+        assert_eq!(store.messages(&created.id).await.unwrap()[0].content, "你好");
+
+        let detail = service
+            .get(Request::new(proto::GetSessionRequest { id: created.id.clone() }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(detail.session.unwrap().message_count, 1);
+        let list = service.list(Request::new(proto::ListSessionsRequest::default())).await.unwrap();
+        assert_eq!(list.into_inner().sessions.len(), 1);
+
+        service
+            .delete(Request::new(proto::DeleteSessionRequest { id: created.id.clone() }))
+            .await
+            .unwrap();
+        let missing = service
+            .get(Request::new(proto::GetSessionRequest { id: created.id }))
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let status = service(dir.path())
+            .create(Request::new(proto::CreateSessionRequest {
+                metadata: Some("[1]".to_string()),
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+}
@@ -0,0 +1,185 @@
+//! 上游密钥和端点的健康状态
+//!
+//! 密钥库中的密钥和`llm.endpoints`中的端点共用同一套规则：返回401/403的标记为失效，
+//! 返回429的按`Retry-After`暂停，连续失败多次的暂停一段时间；配置了每分钟请求数上限的，
+//! 最近一分钟内用满后暂时跳过。状态只保存在内存中，重启后重置。
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+/// 连续失败达到该次数后暂停使用
+const MAX_CONSECUTIVE_FAILURES: u32 = 3;
+
+/// 连续失败后的暂停时间
+const FAILURE_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// 返回429但没有`Retry-After`时的暂停时间
+const RATE_LIMIT_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// 每分钟请求数上限的统计窗口
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// 一次上游请求的结果
+#[derive(Debug, Clone)]
+pub enum KeyOutcome {
+    Success,
+    /// 上游返回401或403
+    Rejected(String),
+    /// 上游返回429，可能带有建议的等待时间
+    RateLimited(Option<Duration>),
+    /// 5xx或网络错误
+    Failed(String),
+}
+
+/// 选择密钥时需要的状态
+#[derive(Debug, Clone, Copy)]
+pub struct Snapshot {
+    pub consecutive_failures: u32,
+    pub failures: u64,
+    /// 暂停或超出每分钟上限时，最早可以再用的时间
+    pub ready_at: Option<Instant>,
+    /// 是否因为超出每分钟上限而不可用，这种情况不能提前使用
+    pub throttled: bool,
+}
+
+/// 管理接口返回的健康状态
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthInfo {
+    /// `healthy`、`cooling_down`、`throttled`、`invalid`或`disabled`
+    pub status: &'static str,
+    pub successes: u64,
+    pub failures: u64,
+    pub consecutive_failures: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cooldown_seconds: Option<u64>,
+    /// 最近一分钟内的请求数
+    pub recent_requests: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct Health {
+    successes: u64,
+    failures: u64,
+    consecutive_failures: u32,
+    cooldown_until: Option<Instant>,
+    /// 上游拒绝了该密钥（401/403）
+    invalid: bool,
+    last_error: Option<String>,
+    last_used_at: Option<u64>,
+    /// 最近一分钟内各次请求的时间
+    recent: VecDeque<Instant>,
+}
+
+impl Health {
+    pub fn invalid(&self) -> bool {
+        self.invalid
+    }
+
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    fn cooling_down(&self, now: Instant) -> bool {
+        self.cooldown_until.is_some_and(|until| until > now)
+    }
+
+    fn prune(&mut self, now: Instant) {
+        while self.recent.front().is_some_and(|&time| now.duration_since(time) >= RATE_WINDOW) {
+            self.recent.pop_front();
+        }
+    }
+
+    /// 最近一分钟内的请求数已达到`rpm`时，返回最早可以再发请求的时间
+    fn throttled_until(&mut self, rpm: Option<u32>, now: Instant) -> Option<Instant> {
+        let rpm = rpm.filter(|&rpm| rpm > 0)? as usize;
+        self.prune(now);
+        if self.recent.len() < rpm {
+            return None;
+        }
+        self.recent.get(self.recent.len() - rpm).map(|&time| time + RATE_WINDOW)
+    }
+
+    pub fn snapshot(&mut self, rpm: Option<u32>, now: Instant) -> Snapshot {
+        let throttled_until = self.throttled_until(rpm, now);
+        let cooldown = self.cooldown_until.filter(|&until| until > now);
+        Snapshot {
+            consecutive_failures: self.consecutive_failures,
+            failures: self.failures,
+            ready_at: cooldown.max(throttled_until),
+            throttled: throttled_until.is_some(),
+        }
+    }
+
+    /// 记录一次请求，用于每分钟上限和`last_used_at`
+    pub fn mark_used(&mut self, now: Instant) {
+        self.recent.push_back(now);
+        self.last_used_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|d| d.as_secs());
+    }
+
+    /// 更新请求结果，返回是否是失败
+    pub fn record(&mut self, outcome: KeyOutcome) -> bool {
+        match outcome {
+            KeyOutcome::Success => {
+                self.successes += 1;
+                self.consecutive_failures = 0;
+                self.cooldown_until = None;
+                return false;
+            }
+            KeyOutcome::Rejected(reason) => {
+                self.invalid = true;
+                self.last_error = Some(reason);
+            }
+            KeyOutcome::RateLimited(retry_after) => {
+                self.cooldown_until = Some(Instant::now() + retry_after.unwrap_or(RATE_LIMIT_COOLDOWN));
+                self.last_error = Some("上游限流 (429)".to_string());
+            }
+            KeyOutcome::Failed(reason) => {
+                self.consecutive_failures += 1;
+                if self.consecutive_failures >= MAX_CONSECUTIVE_FAILURES {
+                    self.cooldown_until = Some(Instant::now() + FAILURE_COOLDOWN);
+                }
+                self.last_error = Some(reason);
+            }
+        }
+        self.failures += 1;
+        true
+    }
+
+    pub fn info(&mut self, enabled: bool, rpm: Option<u32>) -> HealthInfo {
+        let now = Instant::now();
+        let throttled = self.throttled_until(rpm, now).is_some();
+        let status = if !enabled {
+            "disabled"
+        } else if self.invalid {
+            "invalid"
+        } else if self.cooling_down(now) {
+            "cooling_down"
+        } else if throttled {
+            "throttled"
+        } else {
+            "healthy"
+        };
+        HealthInfo {
+            status,
+            successes: self.successes,
+            failures: self.failures,
+            consecutive_failures: self.consecutive_failures,
+            cooldown_seconds: self
+                .cooldown_until
+                .map(|until| until.saturating_duration_since(now).as_secs())
+                .filter(|&seconds| seconds > 0),
+            recent_requests: self.recent.len(),
+            last_error: self.last_error.clone(),
+            last_used_at: self.last_used_at,
+        }
+    }
+}
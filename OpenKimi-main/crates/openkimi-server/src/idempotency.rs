@@ -0,0 +1,253 @@
+//! 幂等键
+//!
+//! 客户端在超时或连接中断后无法知道请求是否已经执行，直接重试可能重复计费，或重复创建会话、批处理等。
+//! 启用`idempotency`后，带`Idempotency-Key`请求头的POST请求完成时保存响应，同一调用方在同一工作区以同一个键
+//! 重试同一接口时直接返回保存的响应并带上`Idempotent-Replayed: true`，不再执行，也不再计量用量和扣减配额。
+//!
+//! 第一次请求还在处理时重试返回409，键相同但请求体不同时返回400。流式响应、5xx、408和429等没有完成执行的响应不保存，
+//! 重试时会再次执行；请求体超过`max_body_bytes`或未给出`Content-Length`的请求不受保护。
+//! 保存的响应只在本实例的内存中，多实例部署时重试需要落到同一实例才能去重。
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::body::{to_bytes, Body, Bytes};
+use axum::extract::{Request, State};
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use axum::http::{HeaderName, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use ring::digest::{Context, SHA256};
+
+use crate::auth::Principal;
+use crate::config::IdempotencyConfig;
+use crate::error::ApiError;
+use crate::workspace::Workspace;
+use crate::AppState;
+
+/// 客户端为每次逻辑调用生成的键，重试时保持不变
+pub const IDEMPOTENCY_HEADER: &str = "idempotency-key";
+
+/// 重放保存的响应时加上的响应头
+pub const REPLAYED_HEADER: &str = "idempotent-replayed";
+
+/// 键的最大长度
+const MAX_KEY_LEN: usize = 255;
+
+type Digest = [u8; 32];
+
+fn digest(parts: &[&[u8]]) -> Digest {
+    let mut context = Context::new(&SHA256);
+    for part in parts {
+        // 带上长度，避免不同的切分得到相同的摘要
+        context.update(&(part.len() as u64).to_le_bytes());
+        context.update(part);
+    }
+    let mut digest = [0u8; 32];
+    digest.copy_from_slice(context.finish().as_ref());
+    digest
+}
+
+/// 保存的响应，只保留内容类型和本服务的`x-openkimi-*`响应头
+#[derive(Debug, Clone)]
+struct Saved {
+    status: StatusCode,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    body: Bytes,
+}
+
+impl Saved {
+    fn replay(self) -> Response {
+        let mut response = Response::new(Body::from(self.body));
+        *response.status_mut() = self.status;
+        let headers = response.headers_mut();
+        for (name, value) in self.headers {
+            headers.insert(name, value);
+        }
+        headers.insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+        response
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// 请求体的摘要，同一个键只能用于同一个请求
+    body: Digest,
+    /// 处理中为`None`
+    saved: Option<Saved>,
+    created: Instant,
+}
+
+/// 幂等键到响应的映射
+#[derive(Debug)]
+pub struct Idempotency {
+    config: IdempotencyConfig,
+    entries: Mutex<HashMap<Digest, Entry>>,
+}
+
+/// 开始处理后持有，处理中断（如客户端断开）时释放键，重试可以再次执行
+struct Pending<'a> {
+    store: &'a Idempotency,
+    key: Digest,
+    saved: Option<Saved>,
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        let mut entries = self.store.entries.lock().unwrap_or_else(|e| e.into_inner());
+        match self.saved.take() {
+            Some(saved) => {
+                if let Some(entry) = entries.get_mut(&self.key) {
+                    entry.saved = Some(saved);
+                }
+            }
+            None => {
+                entries.remove(&self.key);
+            }
+        }
+    }
+}
+
+impl Idempotency {
+    pub fn new(config: &IdempotencyConfig) -> Idempotency {
+        Idempotency {
+            config: config.clone(),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 登记一次请求；已完成时返回保存的响应
+    fn begin(&self, key: Digest, body: Digest) -> Result<Option<Saved>, ApiError> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let ttl = Duration::from_secs(self.config.ttl_seconds);
+        if let Some(entry) = entries.get(&key) {
+            if entry.created.elapsed() < ttl {
+                if entry.body != body {
+                    return Err(ApiError::invalid_request("Idempotency-Key 已用于另一个不同的请求，请为新请求生成新的键"));
+                }
+                return match &entry.saved {
+                    Some(saved) => Ok(Some(saved.clone())),
+                    None => Err(ApiError::Conflict("使用同一个 Idempotency-Key 的请求仍在处理中，请稍后重试".to_string())),
+                };
+            }
+        }
+        if entries.len() >= self.config.max_entries {
+            entries.retain(|_, entry| entry.created.elapsed() < ttl || entry.saved.is_none());
+        }
+        while entries.len() >= self.config.max_entries.max(1) {
+            // 处理中的键不能丢弃，否则重试会再次执行
+            let saved = entries.iter().filter(|(_, entry)| entry.saved.is_some());
+            let oldest = saved.min_by_key(|(_, entry)| entry.created);
+            let Some((&oldest, _)) = oldest else { break };
+            entries.remove(&oldest);
+        }
+        entries.insert(
+            key,
+            Entry {
+                body,
+                saved: None,
+                created: Instant::now(),
+            },
+        );
+        Ok(None)
+    }
+}
+
+/// 响应是否表示请求已经执行完成，可以在重试时重放
+fn storable(response: &Response) -> bool {
+    let status = response.status();
+    let streaming = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"));
+    let retryable = matches!(
+        status,
+        StatusCode::REQUEST_TIMEOUT | StatusCode::CONFLICT | StatusCode::TOO_MANY_REQUESTS
+    );
+    !streaming && !retryable && (status.is_success() || status.is_client_error())
+}
+
+/// 按`Idempotency-Key`去重POST请求，在认证和工作区之内、配额检查之外，重放的响应不扣减配额
+pub async fn deduplicate(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let Some(idempotency) = &state.idempotency else {
+        return next.run(request).await;
+    };
+    if request.method() != Method::POST {
+        return next.run(request).await;
+    }
+    let Some(key) = request.headers().get(IDEMPOTENCY_HEADER) else {
+        return next.run(request).await;
+    };
+    let key = match key.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LEN => key.to_string(),
+        _ => {
+            let message = format!("Idempotency-Key 必须是1到{}个可见ASCII字符", MAX_KEY_LEN);
+            return ApiError::invalid_request(message).into_response();
+        }
+    };
+    let limit = idempotency.config.max_body_bytes;
+    let length = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if length.is_none_or(|length| length > limit) {
+        return next.run(request).await;
+    }
+
+    let (parts, body) = request.into_parts();
+    let body = match to_bytes(body, limit).await {
+        Ok(body) => body,
+        Err(err) => return ApiError::invalid_request(format!("读取请求体失败: {}", err)).into_response(),
+    };
+    let principal = parts.extensions.get::<Principal>();
+    let subject = principal.map(|principal| principal.subject.as_str()).unwrap_or_default();
+    let token = principal.and_then(|principal| principal.token.as_deref()).unwrap_or_default();
+    let workspace = parts.extensions.get::<Workspace>().map(Workspace::name).unwrap_or_default();
+    let path = parts.uri.path_and_query().map(|path| path.as_str()).unwrap_or_default();
+    let scope = digest(&[
+        subject.as_bytes(),
+        token.as_bytes(),
+        workspace.as_bytes(),
+        path.as_bytes(),
+        key.as_bytes(),
+    ]);
+    match idempotency.begin(scope, digest(&[&body])) {
+        Ok(Some(saved)) => return saved.replay(),
+        Ok(None) => {}
+        Err(err) => return err.into_response(),
+    }
+
+    let mut pending = Pending {
+        store: idempotency,
+        key: scope,
+        saved: None,
+    };
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    if !storable(&response) {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(err) => return ApiError::Internal(format!("读取响应失败: {}", err)).into_response(),
+    };
+    if body.len() <= limit {
+        let headers = parts
+            .headers
+            .iter()
+            .filter(|(name, _)| *name == CONTENT_TYPE || name.as_str().starts_with("x-openkimi-"))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        pending.saved = Some(Saved {
+            status: parts.status,
+            headers,
+            body: body.clone(),
+        });
+    }
+    drop(pending);
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(body))
+}
@@ -0,0 +1,1240 @@
+//! 图片的识别、解码、缩放和编码
+//!
+//! 只实现图片输入需要的部分：读取PNG、JPEG、GIF和WebP文件头中的格式和尺寸，
+//! 解码PNG和基线JPEG（按EXIF方向旋转），缩小后编码为PNG或基线JPEG。
+//! 渐进式JPEG、GIF和WebP只能识别尺寸，不能解码。
+
+use std::f32::consts::PI;
+use std::io::{Read, Write};
+use std::sync::OnceLock;
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+
+use crate::config::ImageFormat;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// 文件头中的信息
+#[derive(Debug, Clone, Copy)]
+pub struct ImageInfo {
+    pub format: ImageFormat,
+    pub width: u32,
+    pub height: u32,
+    /// 服务端能否解码，即能否缩放和转换格式
+    pub decodable: bool,
+}
+
+fn be16(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?)))
+}
+
+fn be32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn le16(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?)))
+}
+
+fn le24(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at + 3)?;
+    Some(u32::from(bytes[0]) | u32::from(bytes[1]) << 8 | u32::from(bytes[2]) << 16)
+}
+
+/// 识别图片格式并读取尺寸，不解码像素
+pub fn probe(data: &[u8]) -> Result<ImageInfo, String> {
+    let info = |format, width, height, decodable| ImageInfo {
+        format,
+        width,
+        height,
+        decodable,
+    };
+    let truncated = || "图片数据不完整".to_string();
+    let result = if data.starts_with(PNG_SIGNATURE) {
+        if data.get(12..16) != Some(b"IHDR") {
+            return Err("PNG 缺少 IHDR".to_string());
+        }
+        info(ImageFormat::Png, be32(data, 16).ok_or_else(truncated)?, be32(data, 20).ok_or_else(truncated)?, true)
+    } else if data.starts_with(b"\xff\xd8\xff") {
+        let (width, height, baseline) = jpeg_frame(data)?;
+        info(ImageFormat::Jpeg, width, height, baseline)
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        info(ImageFormat::Gif, le16(data, 6).ok_or_else(truncated)?, le16(data, 8).ok_or_else(truncated)?, false)
+    } else if data.len() >= 16 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        let (width, height) = match &data[12..16] {
+            b"VP8 " => {
+                if data.get(23..26) != Some(b"\x9d\x01\x2a") {
+                    return Err("无效的 WebP 数据".to_string());
+                }
+                let width = le16(data, 26).ok_or_else(truncated)? & 0x3fff;
+                let height = le16(data, 28).ok_or_else(truncated)? & 0x3fff;
+                (width, height)
+            }
+            b"VP8L" => {
+                let bits = data.get(21..25).ok_or_else(truncated)?;
+                let bits = u32::from_le_bytes(bits.try_into().unwrap_or_default());
+                ((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1)
+            }
+            b"VP8X" => (le24(data, 24).ok_or_else(truncated)? + 1, le24(data, 27).ok_or_else(truncated)? + 1),
+            _ => return Err("无效的 WebP 数据".to_string()),
+        };
+        info(ImageFormat::Webp, width, height, false)
+    } else {
+        return Err("无法识别的图片格式，支持 PNG、JPEG、GIF 和 WebP".to_string());
+    };
+    if result.width == 0 || result.height == 0 {
+        return Err("图片的宽或高为 0".to_string());
+    }
+    Ok(result)
+}
+
+/// JPEG的宽、高，以及是否为基线编码
+fn jpeg_frame(data: &[u8]) -> Result<(u32, u32, bool), String> {
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xff {
+            return Err("无效的 JPEG 数据".to_string());
+        }
+        let marker = data[pos + 1];
+        if marker == 0xff {
+            pos += 1;
+            continue;
+        }
+        if marker == 0xd8 || marker == 0x01 || (0xd0..=0xd7).contains(&marker) {
+            pos += 2;
+            continue;
+        }
+        let length = be16(data, pos + 2).ok_or("JPEG 数据不完整")? as usize;
+        if is_sof(marker) {
+            let height = be16(data, pos + 5).ok_or("JPEG 数据不完整")?;
+            let width = be16(data, pos + 7).ok_or("JPEG 数据不完整")?;
+            return Ok((width, height, marker == 0xc0 || marker == 0xc1));
+        }
+        pos += 2 + length;
+    }
+    Err("JPEG 缺少帧头".to_string())
+}
+
+fn is_sof(marker: u8) -> bool {
+    (0xc0..=0xcf).contains(&marker) && !matches!(marker, 0xc4 | 0xc8 | 0xcc)
+}
+
+/// 解码后的图片，每个像素依次为RGBA
+#[derive(Debug, Clone)]
+pub struct Bitmap {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Bitmap {
+    fn new(width: u32, height: u32) -> Bitmap {
+        Bitmap {
+            width,
+            height,
+            pixels: vec![255; width as usize * height as usize * 4],
+        }
+    }
+
+    /// 是否有不完全透明的像素
+    pub fn has_alpha(&self) -> bool {
+        self.pixels.chunks_exact(4).any(|pixel| pixel[3] != 255)
+    }
+
+    fn set(&mut self, x: usize, y: usize, rgba: [u8; 4]) {
+        let at = (y * self.width as usize + x) * 4;
+        self.pixels[at..at + 4].copy_from_slice(&rgba);
+    }
+
+    /// 按面积加权平均缩小到指定尺寸，透明度预乘后再平均，避免透明像素的颜色渗出
+    pub fn resize(&self, width: u32, height: u32) -> Bitmap {
+        let (src_width, src_height) = (self.width as usize, self.height as usize);
+        let (width, height) = (width.max(1) as usize, height.max(1) as usize);
+        let columns = weights(src_width, width);
+        let rows = weights(src_height, height);
+
+        // 先横向缩放到width×src_height
+        let mut horizontal = vec![0f32; width * src_height * 4];
+        for y in 0..src_height {
+            let row = &self.pixels[y * src_width * 4..(y + 1) * src_width * 4];
+            for (x, (first, column)) in columns.iter().enumerate() {
+                let mut sum = [0f32; 4];
+                for (i, weight) in column.iter().enumerate() {
+                    let pixel = &row[(first + i) * 4..(first + i) * 4 + 4];
+                    let alpha = f32::from(pixel[3]) / 255.0;
+                    for c in 0..3 {
+                        sum[c] += f32::from(pixel[c]) * alpha * weight;
+                    }
+                    sum[3] += alpha * weight;
+                }
+                horizontal[(y * width + x) * 4..(y * width + x) * 4 + 4].copy_from_slice(&sum);
+            }
+        }
+
+        let mut output = Bitmap::new(width as u32, height as u32);
+        for (y, (first, row)) in rows.iter().enumerate() {
+            for x in 0..width {
+                let mut sum = [0f32; 4];
+                for (i, weight) in row.iter().enumerate() {
+                    let at = ((first + i) * width + x) * 4;
+                    for c in 0..4 {
+                        sum[c] += horizontal[at + c] * weight;
+                    }
+                }
+                let alpha = sum[3];
+                let color = |value: f32| {
+                    let value = if alpha > 0.0 { value / alpha } else { 0.0 };
+                    value.round().clamp(0.0, 255.0) as u8
+                };
+                output.set(x, y, [color(sum[0]), color(sum[1]), color(sum[2]), (alpha * 255.0).round() as u8]);
+            }
+        }
+        output
+    }
+
+    /// 把透明像素合成到白色背景上
+    fn flatten(&self) -> Bitmap {
+        let mut output = self.clone();
+        for pixel in output.pixels.chunks_exact_mut(4) {
+            let alpha = u32::from(pixel[3]);
+            for value in &mut pixel[..3] {
+                *value = ((u32::from(*value) * alpha + 255 * (255 - alpha) + 127) / 255) as u8;
+            }
+            pixel[3] = 255;
+        }
+        output
+    }
+}
+
+/// 缩小时每个目标位置覆盖的源像素：第一个源像素的下标和各源像素的权重，权重之和为1
+fn weights(source: usize, target: usize) -> Vec<(usize, Vec<f32>)> {
+    let scale = source as f64 / target as f64;
+    (0..target)
+        .map(|i| {
+            let start = i as f64 * scale;
+            let end = ((i + 1) as f64 * scale).min(source as f64);
+            let first = start.floor() as usize;
+            let last = (end.ceil() as usize).clamp(first + 1, source);
+            let weights = (first..last)
+                .map(|j| ((end.min(j as f64 + 1.0) - start.max(j as f64)) / (end - start)) as f32)
+                .collect();
+            (first, weights)
+        })
+        .collect()
+}
+
+/// 解码PNG或基线JPEG
+pub fn decode(data: &[u8]) -> Result<Bitmap, String> {
+    if data.starts_with(PNG_SIGNATURE) {
+        decode_png(data)
+    } else if data.starts_with(b"\xff\xd8\xff") {
+        decode_jpeg(data)
+    } else {
+        Err("只能解码 PNG 和基线 JPEG".to_string())
+    }
+}
+
+/// 编码为指定格式，JPEG不支持透明度，透明像素合成到白色背景上
+pub fn encode(bitmap: &Bitmap, format: ImageFormat, quality: u8) -> Result<Vec<u8>, String> {
+    match format {
+        ImageFormat::Png => Ok(encode_png(bitmap)),
+        ImageFormat::Jpeg => Ok(encode_jpeg(&bitmap.flatten(), quality)),
+        _ => Err(format!("不能编码为 {}", format.name())),
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let (pa, pb, pc) = ((p - i16::from(a)).abs(), (p - i16::from(b)).abs(), (p - i16::from(c)).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// PNG的隔行扫描：各遍的起点和步长
+const ADAM7: [(usize, usize, usize, usize); 7] =
+    [(0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)];
+
+fn decode_png(data: &[u8]) -> Result<Bitmap, String> {
+    let mut header = None;
+    let mut palette: Vec<[u8; 3]> = Vec::new();
+    let mut transparency: Vec<u8> = Vec::new();
+    let mut compressed = Vec::new();
+    let mut pos = PNG_SIGNATURE.len();
+    loop {
+        let length = be32(data, pos).ok_or("PNG 数据不完整")? as usize;
+        let kind = data.get(pos + 4..pos + 8).ok_or("PNG 数据不完整")?;
+        let body = data.get(pos + 8..pos + 8 + length).ok_or("PNG 数据不完整")?;
+        match kind {
+            b"IHDR" if body.len() >= 13 => header = Some((be32(body, 0), be32(body, 4), body[8], body[9], body[12])),
+            b"PLTE" => palette = body.chunks_exact(3).map(|rgb| [rgb[0], rgb[1], rgb[2]]).collect(),
+            b"tRNS" => transparency = body.to_vec(),
+            b"IDAT" => compressed.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {}
+        }
+        pos += 12 + length;
+    }
+    let Some((Some(width), Some(height), depth, color, interlace)) = header else {
+        return Err("PNG 缺少 IHDR".to_string());
+    };
+    let channels = match color {
+        0 | 3 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => return Err(format!("无效的 PNG 颜色类型 {}", color)),
+    };
+    let valid_depth = match color {
+        0 => matches!(depth, 1 | 2 | 4 | 8 | 16),
+        3 => matches!(depth, 1 | 2 | 4 | 8),
+        _ => matches!(depth, 8 | 16),
+    };
+    if !valid_depth {
+        return Err(format!("无效的 PNG 位深 {}", depth));
+    }
+    if color == 3 && palette.is_empty() {
+        return Err("PNG 缺少调色板".to_string());
+    }
+
+    let (width, height) = (width as usize, height as usize);
+    let bits = channels * usize::from(depth);
+    let row_bytes = |pixels: usize| (pixels * bits).div_ceil(8);
+    let passes: Vec<(usize, usize, usize, usize)> = if interlace == 1 {
+        ADAM7.to_vec()
+    } else {
+        vec![(0, 0, 1, 1)]
+    };
+    let pass_size = |&(x0, y0, dx, dy): &(usize, usize, usize, usize)| {
+        ((width + dx - 1 - x0) / dx, (height + dy - 1 - y0) / dy)
+    };
+    let expected: usize = passes
+        .iter()
+        .map(pass_size)
+        .filter(|&(w, h)| w > 0 && h > 0)
+        .map(|(w, h)| h * (row_bytes(w) + 1))
+        .sum();
+    let mut raw = Vec::with_capacity(expected);
+    ZlibDecoder::new(compressed.as_slice())
+        .take(expected as u64)
+        .read_to_end(&mut raw)
+        .map_err(|e| format!("PNG 解压失败: {}", e))?;
+    if raw.len() < expected {
+        return Err("PNG 数据不完整".to_string());
+    }
+
+    let filter_bytes = bits.div_ceil(8);
+    let scale = |value: u16| -> u8 {
+        match depth {
+            16 => (value >> 8) as u8,
+            8 => value as u8,
+            _ => (u32::from(value) * 255 / ((1 << depth) - 1)) as u8,
+        }
+    };
+    let mut bitmap = Bitmap::new(width as u32, height as u32);
+    let mut offset = 0;
+    for pass in &passes {
+        let (pass_width, pass_height) = pass_size(pass);
+        if pass_width == 0 || pass_height == 0 {
+            continue;
+        }
+        let (x0, y0, dx, dy) = *pass;
+        let stride = row_bytes(pass_width);
+        let mut previous = vec![0u8; stride];
+        for y in 0..pass_height {
+            let filter = raw[offset];
+            let mut row = raw[offset + 1..offset + 1 + stride].to_vec();
+            offset += stride + 1;
+            for i in 0..stride {
+                let left = if i >= filter_bytes { row[i - filter_bytes] } else { 0 };
+                let up = previous[i];
+                let corner = if i >= filter_bytes { previous[i - filter_bytes] } else { 0 };
+                row[i] = row[i].wrapping_add(match filter {
+                    0 => 0,
+                    1 => left,
+                    2 => up,
+                    3 => ((u16::from(left) + u16::from(up)) / 2) as u8,
+                    4 => paeth(left, up, corner),
+                    _ => return Err(format!("无效的 PNG 过滤类型 {}", filter)),
+                });
+            }
+
+            let sample = |x: usize, c: usize| -> u16 {
+                match depth {
+                    16 => u16::from_be_bytes([row[(x * channels + c) * 2], row[(x * channels + c) * 2 + 1]]),
+                    8 => u16::from(row[x * channels + c]),
+                    _ => {
+                        let bit = x * usize::from(depth);
+                        let shift = 8 - usize::from(depth) - bit % 8;
+                        u16::from((row[bit / 8] >> shift) & ((1 << depth) - 1))
+                    }
+                }
+            };
+            let transparent = |values: &[u16]| {
+                transparency.len() >= values.len() * 2
+                    && values.iter().enumerate().all(|(i, &value)| be16(&transparency, i * 2) == Some(u32::from(value)))
+            };
+            for x in 0..pass_width {
+                let rgba = match color {
+                    0 => {
+                        let gray = sample(x, 0);
+                        let g = scale(gray);
+                        [g, g, g, if transparent(&[gray]) { 0 } else { 255 }]
+                    }
+                    2 => {
+                        let rgb = [sample(x, 0), sample(x, 1), sample(x, 2)];
+                        let alpha = if transparent(&rgb) { 0 } else { 255 };
+                        [scale(rgb[0]), scale(rgb[1]), scale(rgb[2]), alpha]
+                    }
+                    3 => {
+                        let index = usize::from(sample(x, 0));
+                        let [r, g, b] = *palette.get(index).ok_or("PNG 调色板下标越界")?;
+                        [r, g, b, transparency.get(index).copied().unwrap_or(255)]
+                    }
+                    4 => {
+                        let g = scale(sample(x, 0));
+                        [g, g, g, scale(sample(x, 1))]
+                    }
+                    _ => [scale(sample(x, 0)), scale(sample(x, 1)), scale(sample(x, 2)), scale(sample(x, 3))],
+                };
+                bitmap.set(x0 + x * dx, y0 + y * dy, rgba);
+            }
+            previous = row;
+        }
+    }
+    Ok(bitmap)
+}
+
+fn png_chunk(output: &mut Vec<u8>, kind: &[u8; 4], body: &[u8]) {
+    output.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    let mut crc = Crc::new();
+    crc.update(kind);
+    crc.update(body);
+    output.extend_from_slice(kind);
+    output.extend_from_slice(body);
+    output.extend_from_slice(&crc.sum().to_be_bytes());
+}
+
+fn encode_png(bitmap: &Bitmap) -> Vec<u8> {
+    let alpha = bitmap.has_alpha();
+    let channels = if alpha { 4 } else { 3 };
+    let stride = bitmap.width as usize * channels;
+    let mut raw = Vec::with_capacity((stride + 1) * bitmap.height as usize);
+    let mut previous = vec![0u8; stride];
+    let mut candidates = vec![vec![0u8; stride]; 5];
+    for pixels in bitmap.pixels.chunks_exact(bitmap.width as usize * 4) {
+        let row: Vec<u8> = pixels.chunks_exact(4).flat_map(|pixel| pixel[..channels].iter().copied()).collect();
+        for i in 0..stride {
+            let left = if i >= channels { row[i - channels] } else { 0 };
+            let up = previous[i];
+            let corner = if i >= channels { previous[i - channels] } else { 0 };
+            candidates[0][i] = row[i];
+            candidates[1][i] = row[i].wrapping_sub(left);
+            candidates[2][i] = row[i].wrapping_sub(up);
+            candidates[3][i] = row[i].wrapping_sub(((u16::from(left) + u16::from(up)) / 2) as u8);
+            candidates[4][i] = row[i].wrapping_sub(paeth(left, up, corner));
+        }
+        // 选绝对值之和最小的过滤方式，这是libpng的默认做法
+        let cost = |candidate: &Vec<u8>| {
+            candidate.iter().map(|&byte| u32::from((byte as i8).unsigned_abs())).sum::<u32>()
+        };
+        let (filter, best) = candidates.iter().enumerate().min_by_key(|(_, candidate)| cost(candidate)).unwrap();
+        raw.push(filter as u8);
+        raw.extend_from_slice(best);
+        previous = row;
+    }
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    let _ = encoder.write_all(&raw);
+    let compressed = encoder.finish().unwrap_or_default();
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&bitmap.width.to_be_bytes());
+    header.extend_from_slice(&bitmap.height.to_be_bytes());
+    header.extend_from_slice(&[8, if alpha { 6 } else { 2 }, 0, 0, 0]);
+    let mut output = PNG_SIGNATURE.to_vec();
+    png_chunk(&mut output, b"IHDR", &header);
+    png_chunk(&mut output, b"IDAT", &compressed);
+    png_chunk(&mut output, b"IEND", &[]);
+    output
+}
+
+/// 8×8块中按之字形顺序排列的系数在自然顺序中的位置
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20, 13, 6, 7, 14, 21,
+    28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54,
+    47, 55, 62, 63,
+];
+
+/// DCT的余弦表：`cos[x][u] = C(u) / 2 · cos((2x + 1)uπ / 16)`
+fn cosines() -> &'static [[f32; 8]; 8] {
+    static TABLE: OnceLock<[[f32; 8]; 8]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [[0f32; 8]; 8];
+        for (x, row) in table.iter_mut().enumerate() {
+            for (u, value) in row.iter_mut().enumerate() {
+                let scale = if u == 0 { std::f32::consts::FRAC_1_SQRT_2 } else { 1.0 };
+                *value = scale / 2.0 * ((2 * x + 1) as f32 * u as f32 * PI / 16.0).cos();
+            }
+        }
+        table
+    })
+}
+
+/// 逆DCT，输入和输出都是自然顺序，输出已加上128
+fn idct(coefficients: &[f32; 64]) -> [f32; 64] {
+    let table = cosines();
+    let mut rows = [0f32; 64];
+    for v in 0..8 {
+        for x in 0..8 {
+            rows[v * 8 + x] = (0..8).map(|u| table[x][u] * coefficients[v * 8 + u]).sum();
+        }
+    }
+    let mut output = [0f32; 64];
+    for y in 0..8 {
+        for x in 0..8 {
+            output[y * 8 + x] = (0..8).map(|v| table[y][v] * rows[v * 8 + x]).sum::<f32>() + 128.0;
+        }
+    }
+    output
+}
+
+/// 正向DCT，输入为减去128后的像素
+fn fdct(block: &[f32; 64]) -> [f32; 64] {
+    let table = cosines();
+    let mut rows = [0f32; 64];
+    for y in 0..8 {
+        for u in 0..8 {
+            rows[y * 8 + u] = (0..8).map(|x| table[x][u] * block[y * 8 + x]).sum();
+        }
+    }
+    let mut output = [0f32; 64];
+    for v in 0..8 {
+        for u in 0..8 {
+            output[v * 8 + u] = (0..8).map(|y| table[y][v] * rows[y * 8 + u]).sum();
+        }
+    }
+    output
+}
+
+/// 解码用的Huffman表
+#[derive(Debug, Clone, Default)]
+struct HuffmanTable {
+    /// 各码长的最大码值，没有该长度的码时为-1
+    max_code: [i32; 17],
+    min_code: [i32; 17],
+    /// 各码长的第一个码在`values`中的下标
+    offset: [usize; 17],
+    values: Vec<u8>,
+}
+
+impl HuffmanTable {
+    fn new(counts: &[u8], values: &[u8]) -> HuffmanTable {
+        let mut table = HuffmanTable {
+            values: values.to_vec(),
+            ..HuffmanTable::default()
+        };
+        let (mut code, mut index) = (0i32, 0usize);
+        for length in 1..=16 {
+            let count = usize::from(counts[length - 1]);
+            table.max_code[length] = -1;
+            if count > 0 {
+                table.offset[length] = index;
+                table.min_code[length] = code;
+                code += count as i32;
+                index += count;
+                table.max_code[length] = code - 1;
+            }
+            code <<= 1;
+        }
+        table
+    }
+}
+
+/// 熵编码数据的位读取器，处理`0xFF00`转义；遇到标记后只返回0
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32,
+    marker: bool,
+}
+
+impl BitReader<'_> {
+    fn fill(&mut self) {
+        while self.count <= 24 {
+            let mut byte = 0;
+            if !self.marker && self.pos < self.data.len() {
+                byte = self.data[self.pos];
+                if byte == 0xff {
+                    if self.data.get(self.pos + 1) == Some(&0) {
+                        self.pos += 2;
+                    } else {
+                        self.marker = true;
+                        byte = 0;
+                    }
+                } else {
+                    self.pos += 1;
+                }
+            }
+            self.buffer |= u32::from(byte) << (24 - self.count);
+            self.count += 8;
+        }
+    }
+
+    fn bits(&mut self, count: u32) -> u32 {
+        if count == 0 {
+            return 0;
+        }
+        if self.count < count {
+            self.fill();
+        }
+        let value = self.buffer >> (32 - count);
+        self.buffer <<= count;
+        self.count -= count;
+        value
+    }
+
+    fn decode(&mut self, table: &HuffmanTable) -> Result<u8, String> {
+        let mut code = 0i32;
+        for length in 1..=16 {
+            code = (code << 1) | self.bits(1) as i32;
+            if code <= table.max_code[length] {
+                let index = table.offset[length] + (code - table.min_code[length]) as usize;
+                return table.values.get(index).copied().ok_or_else(|| "无效的 Huffman 编码".to_string());
+            }
+        }
+        Err("无效的 Huffman 编码".to_string())
+    }
+
+    /// 读取`size`位并按JPEG的规则还原符号
+    fn receive(&mut self, size: u32) -> i32 {
+        if size == 0 {
+            return 0;
+        }
+        let value = self.bits(size) as i32;
+        if value < 1 << (size - 1) {
+            value - (1 << size) + 1
+        } else {
+            value
+        }
+    }
+
+    /// 跳过重启标记
+    fn restart(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+        self.marker = false;
+        while self.pos + 1 < self.data.len() {
+            if self.data[self.pos] == 0xff && (0xd0..=0xd7).contains(&self.data[self.pos + 1]) {
+                self.pos += 2;
+                return;
+            }
+            self.pos += 1;
+        }
+    }
+}
+
+/// JPEG帧中的一个颜色分量
+#[derive(Debug, Default)]
+struct Component {
+    id: u8,
+    horizontal: usize,
+    vertical: usize,
+    quantization: usize,
+    dc_table: usize,
+    ac_table: usize,
+    /// 按整数个MCU补齐后的块数
+    blocks_wide: usize,
+    blocks_high: usize,
+    samples: Vec<u8>,
+    prediction: i32,
+}
+
+fn decode_jpeg(data: &[u8]) -> Result<Bitmap, String> {
+    let truncated = || "JPEG 数据不完整".to_string();
+    let mut quantization = [[0u16; 64]; 4];
+    let mut dc_tables: [HuffmanTable; 4] = Default::default();
+    let mut ac_tables: [HuffmanTable; 4] = Default::default();
+    let mut components: Vec<Component> = Vec::new();
+    let (mut width, mut height) = (0usize, 0usize);
+    let (mut max_horizontal, mut max_vertical) = (1usize, 1usize);
+    let mut restart_interval = 0usize;
+    let mut adobe_transform = None;
+    let mut orientation = 1;
+    let mut scanned = false;
+
+    let mut pos = 2;
+    while pos + 1 < data.len() {
+        if data[pos] != 0xff {
+            pos += 1;
+            continue;
+        }
+        let marker = data[pos + 1];
+        pos += 2;
+        if marker == 0xff || marker == 0 || marker == 0x01 || (0xd0..=0xd8).contains(&marker) {
+            if marker == 0xff {
+                pos -= 1;
+            }
+            continue;
+        }
+        if marker == 0xd9 {
+            break;
+        }
+        let length = be16(data, pos).ok_or_else(truncated)? as usize;
+        let segment = data.get(pos + 2..pos + length).ok_or_else(truncated)?;
+        pos += length;
+        match marker {
+            0xdb => {
+                let mut rest = segment;
+                while let Some((&info, tail)) = rest.split_first() {
+                    let (precision, id) = (info >> 4, usize::from(info & 3));
+                    let size = if precision == 0 { 64 } else { 128 };
+                    let values = tail.get(..size).ok_or_else(truncated)?;
+                    for (k, value) in quantization[id].iter_mut().enumerate() {
+                        *value = if precision == 0 {
+                            u16::from(values[k])
+                        } else {
+                            u16::from_be_bytes([values[k * 2], values[k * 2 + 1]])
+                        };
+                    }
+                    rest = &tail[size..];
+                }
+            }
+            0xc4 => {
+                let mut rest = segment;
+                while let Some((&info, tail)) = rest.split_first() {
+                    let counts = tail.get(..16).ok_or_else(truncated)?;
+                    let total: usize = counts.iter().map(|&count| usize::from(count)).sum();
+                    let values = tail.get(16..16 + total).ok_or_else(truncated)?;
+                    let table = HuffmanTable::new(counts, values);
+                    if info >> 4 == 0 {
+                        dc_tables[usize::from(info & 3)] = table;
+                    } else {
+                        ac_tables[usize::from(info & 3)] = table;
+                    }
+                    rest = &tail[16 + total..];
+                }
+            }
+            0xc0 | 0xc1 => {
+                if segment.first() != Some(&8) {
+                    return Err("只支持 8 位精度的 JPEG".to_string());
+                }
+                height = be16(segment, 1).ok_or_else(truncated)? as usize;
+                width = be16(segment, 3).ok_or_else(truncated)? as usize;
+                let count = usize::from(*segment.get(5).ok_or_else(truncated)?);
+                if count != 1 && count != 3 {
+                    return Err(format!("不支持 {} 个颜色分量的 JPEG", count));
+                }
+                if width == 0 || height == 0 {
+                    return Err("JPEG 的宽或高为 0".to_string());
+                }
+                for i in 0..count {
+                    let spec = segment.get(6 + i * 3..9 + i * 3).ok_or_else(truncated)?;
+                    let (horizontal, vertical) = (usize::from(spec[1] >> 4), usize::from(spec[1] & 15));
+                    if !(1..=4).contains(&horizontal) || !(1..=4).contains(&vertical) {
+                        return Err("无效的 JPEG 采样因子".to_string());
+                    }
+                    components.push(Component {
+                        id: spec[0],
+                        horizontal,
+                        vertical,
+                        quantization: usize::from(spec[2] & 3),
+                        ..Component::default()
+                    });
+                }
+                max_horizontal = components.iter().map(|c| c.horizontal).max().unwrap_or(1);
+                max_vertical = components.iter().map(|c| c.vertical).max().unwrap_or(1);
+                let mcus_wide = width.div_ceil(8 * max_horizontal);
+                let mcus_high = height.div_ceil(8 * max_vertical);
+                for component in &mut components {
+                    component.blocks_wide = mcus_wide * component.horizontal;
+                    component.blocks_high = mcus_high * component.vertical;
+                    component.samples = vec![0; component.blocks_wide * component.blocks_high * 64];
+                }
+            }
+            0xdd => restart_interval = be16(segment, 0).ok_or_else(truncated)? as usize,
+            0xe1 if segment.starts_with(b"Exif\0\0") => orientation = exif_orientation(&segment[6..]).unwrap_or(1),
+            0xee if segment.starts_with(b"Adobe") => adobe_transform = segment.get(11).copied(),
+            0xda => {
+                if components.is_empty() {
+                    return Err("JPEG 缺少帧头".to_string());
+                }
+                let count = usize::from(*segment.first().ok_or_else(truncated)?);
+                let mut selected = Vec::with_capacity(count);
+                for i in 0..count {
+                    let spec = segment.get(1 + i * 2..3 + i * 2).ok_or_else(truncated)?;
+                    let index = components
+                        .iter()
+                        .position(|component| component.id == spec[0])
+                        .ok_or("JPEG 扫描引用了不存在的颜色分量")?;
+                    components[index].dc_table = usize::from(spec[1] >> 4 & 3);
+                    components[index].ac_table = usize::from(spec[1] & 3);
+                    selected.push(index);
+                }
+                let mut reader = BitReader {
+                    data,
+                    pos,
+                    buffer: 0,
+                    count: 0,
+                    marker: false,
+                };
+                let frame = Frame {
+                    width,
+                    height,
+                    max_horizontal,
+                    max_vertical,
+                    restart_interval,
+                    quantization: &quantization,
+                    dc_tables: &dc_tables,
+                    ac_tables: &ac_tables,
+                };
+                frame.decode_scan(&mut reader, &mut components, &selected)?;
+                scanned = true;
+                // 跳到扫描数据之后的下一个标记
+                pos = reader.pos;
+                while pos + 1 < data.len() {
+                    let next = data[pos + 1];
+                    if data[pos] == 0xff && next != 0 && !(0xd0..=0xd7).contains(&next) {
+                        break;
+                    }
+                    pos += 1;
+                }
+            }
+            _ if is_sof(marker) => return Err("不支持渐进式或无损编码的 JPEG".to_string()),
+            _ => {}
+        }
+    }
+    if !scanned {
+        return Err("JPEG 缺少图像数据".to_string());
+    }
+
+    let mut bitmap = Bitmap::new(width as u32, height as u32);
+    let sample = |component: &Component, x: usize, y: usize| {
+        let sx = x * component.horizontal / max_horizontal;
+        let sy = y * component.vertical / max_vertical;
+        f32::from(component.samples[sy * component.blocks_wide * 8 + sx])
+    };
+    let rgb = components.len() == 3 && adobe_transform == Some(0);
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = if components.len() == 1 {
+                let gray = sample(&components[0], x, y) as u8;
+                [gray, gray, gray, 255]
+            } else {
+                let (a, b, c) = (
+                    sample(&components[0], x, y),
+                    sample(&components[1], x, y),
+                    sample(&components[2], x, y),
+                );
+                let [r, g, b] = if rgb {
+                    [a, b, c]
+                } else {
+                    [
+                        a + 1.402 * (c - 128.0),
+                        a - 0.344136 * (b - 128.0) - 0.714136 * (c - 128.0),
+                        a + 1.772 * (b - 128.0),
+                    ]
+                };
+                let clamp = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+                [clamp(r), clamp(g), clamp(b), 255]
+            };
+            bitmap.set(x, y, pixel);
+        }
+    }
+    Ok(orient(bitmap, orientation))
+}
+
+/// 解码一次扫描用到的帧参数
+struct Frame<'a> {
+    width: usize,
+    height: usize,
+    max_horizontal: usize,
+    max_vertical: usize,
+    restart_interval: usize,
+    quantization: &'a [[u16; 64]; 4],
+    dc_tables: &'a [HuffmanTable; 4],
+    ac_tables: &'a [HuffmanTable; 4],
+}
+
+impl Frame<'_> {
+    fn decode_scan(
+        &self,
+        reader: &mut BitReader,
+        components: &mut [Component],
+        selected: &[usize],
+    ) -> Result<(), String> {
+        for &index in selected {
+            components[index].prediction = 0;
+        }
+        let mut mcu = 0;
+        let mut restart = |reader: &mut BitReader, components: &mut [Component]| {
+            if self.restart_interval > 0 && mcu > 0 && mcu % self.restart_interval == 0 {
+                reader.restart();
+                for &index in selected {
+                    components[index].prediction = 0;
+                }
+            }
+            mcu += 1;
+        };
+        if let [index] = *selected {
+            // 单个分量的扫描不交织，按分量的实际尺寸逐块解码
+            let component = &components[index];
+            let wide = (self.width * component.horizontal).div_ceil(self.max_horizontal).div_ceil(8);
+            let high = (self.height * component.vertical).div_ceil(self.max_vertical).div_ceil(8);
+            for y in 0..high {
+                for x in 0..wide {
+                    restart(reader, components);
+                    self.decode_block(reader, &mut components[index], x, y)?;
+                }
+            }
+        } else {
+            let mcus_wide = self.width.div_ceil(8 * self.max_horizontal);
+            let mcus_high = self.height.div_ceil(8 * self.max_vertical);
+            for my in 0..mcus_high {
+                for mx in 0..mcus_wide {
+                    restart(reader, components);
+                    for &index in selected {
+                        let component = &mut components[index];
+                        for v in 0..component.vertical {
+                            for h in 0..component.horizontal {
+                                let (x, y) = (mx * component.horizontal + h, my * component.vertical + v);
+                                self.decode_block(reader, component, x, y)?;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn decode_block(
+        &self,
+        reader: &mut BitReader,
+        component: &mut Component,
+        x: usize,
+        y: usize,
+    ) -> Result<(), String> {
+        let quantization = &self.quantization[component.quantization];
+        let mut coefficients = [0f32; 64];
+        let size = u32::from(reader.decode(&self.dc_tables[component.dc_table])?);
+        if size > 11 {
+            return Err("无效的 JPEG 直流系数".to_string());
+        }
+        component.prediction += reader.receive(size);
+        coefficients[0] = component.prediction as f32 * f32::from(quantization[0]);
+        let mut k = 1;
+        while k < 64 {
+            let symbol = reader.decode(&self.ac_tables[component.ac_table])?;
+            let (run, size) = (usize::from(symbol >> 4), u32::from(symbol & 15));
+            if size == 0 {
+                if run != 15 {
+                    break;
+                }
+                k += 16;
+                continue;
+            }
+            k += run;
+            if k > 63 {
+                return Err("无效的 JPEG 交流系数".to_string());
+            }
+            coefficients[ZIGZAG[k]] = reader.receive(size) as f32 * f32::from(quantization[k]);
+            k += 1;
+        }
+        let pixels = idct(&coefficients);
+        let stride = component.blocks_wide * 8;
+        for row in 0..8 {
+            for column in 0..8 {
+                let at = (y * 8 + row) * stride + x * 8 + column;
+                component.samples[at] = pixels[row * 8 + column].round().clamp(0.0, 255.0) as u8;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// EXIF中的方向（标签0x0112）
+fn exif_orientation(tiff: &[u8]) -> Option<u16> {
+    let little = match tiff.get(..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let read16 = |at: usize| if little { le16(tiff, at) } else { be16(tiff, at) };
+    let read32 = |at: usize| {
+        let bytes: [u8; 4] = tiff.get(at..at + 4)?.try_into().ok()?;
+        Some(if little { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    };
+    let directory = read32(4)? as usize;
+    let entries = read16(directory)? as usize;
+    (0..entries).find_map(|i| {
+        let entry = directory + 2 + i * 12;
+        (read16(entry)? == 0x0112).then(|| read16(entry + 8)).flatten().map(|value| value as u16)
+    })
+}
+
+/// 按EXIF方向旋转或翻转成正常的显示方向
+fn orient(bitmap: Bitmap, orientation: u16) -> Bitmap {
+    if !(2..=8).contains(&orientation) {
+        return bitmap;
+    }
+    let (width, height) = (bitmap.width as usize, bitmap.height as usize);
+    let swapped = orientation >= 5;
+    let (out_width, out_height) = if swapped { (height, width) } else { (width, height) };
+    let mut output = Bitmap::new(out_width as u32, out_height as u32);
+    for y in 0..out_height {
+        for x in 0..out_width {
+            let (sx, sy) = match orientation {
+                2 => (width - 1 - x, y),
+                3 => (width - 1 - x, height - 1 - y),
+                4 => (x, height - 1 - y),
+                5 => (y, x),
+                6 => (y, height - 1 - x),
+                7 => (width - 1 - y, height - 1 - x),
+                _ => (width - 1 - y, x),
+            };
+            let at = (sy * width + sx) * 4;
+            let pixel = bitmap.pixels[at..at + 4].try_into().unwrap_or([0; 4]);
+            output.set(x, y, pixel);
+        }
+    }
+    output
+}
+
+/// JPEG标准附录K中的亮度量化表（自然顺序）
+const LUMINANCE_QUANTIZATION: [u16; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56, 14, 17, 22, 29, 51,
+    87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113, 92, 49, 64, 78, 87, 103, 121, 120, 101,
+    72, 92, 95, 98, 112, 100, 103, 99,
+];
+
+const CHROMINANCE_QUANTIZATION: [u16; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99, 99, 47, 66, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99,
+];
+
+/// 附录K中的标准Huffman表：各码长的码数和符号
+const DC_LUMINANCE: ([u8; 16], &[u8]) =
+    ([0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0], &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]);
+
+const DC_CHROMINANCE: ([u8; 16], &[u8]) =
+    ([0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0], &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]);
+
+const AC_LUMINANCE: ([u8; 16], &[u8]) = (
+    [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d],
+    &[
+        0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+        0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0,
+        0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+        0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+        0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+        0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+        0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+        0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5,
+        0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+        0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+        0xf9, 0xfa,
+    ],
+);
+
+const AC_CHROMINANCE: ([u8; 16], &[u8]) = (
+    [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77],
+    &[
+        0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+        0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0,
+        0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+        0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+        0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+        0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+        0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5,
+        0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3,
+        0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+        0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+        0xf9, 0xfa,
+    ],
+);
+
+/// 编码用的Huffman表：每个符号的码值和码长
+fn huffman_codes((counts, values): &([u8; 16], &[u8])) -> [(u16, u8); 256] {
+    let mut codes = [(0u16, 0u8); 256];
+    let (mut code, mut index) = (0u16, 0usize);
+    for (length, &count) in counts.iter().enumerate() {
+        for _ in 0..count {
+            codes[usize::from(values[index])] = (code, length as u8 + 1);
+            code += 1;
+            index += 1;
+        }
+        code <<= 1;
+    }
+    codes
+}
+
+/// 熵编码数据的位写入器，`0xFF`后补`0x00`
+#[derive(Default)]
+struct BitWriter {
+    output: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, length: u32) {
+        self.buffer = (self.buffer << length) | (value & ((1 << length) - 1));
+        self.count += length;
+        while self.count >= 8 {
+            let byte = (self.buffer >> (self.count - 8)) as u8;
+            self.output.push(byte);
+            if byte == 0xff {
+                self.output.push(0);
+            }
+            self.count -= 8;
+        }
+    }
+
+    fn code(&mut self, codes: &[(u16, u8); 256], symbol: u8) {
+        let (code, length) = codes[usize::from(symbol)];
+        self.write(u32::from(code), u32::from(length));
+    }
+
+    /// 按JPEG的规则写入一个系数：先写位数的符号，再写数值
+    fn value(&mut self, codes: &[(u16, u8); 256], run: u8, value: i32) {
+        let size = 32 - value.unsigned_abs().leading_zeros();
+        self.code(codes, (run << 4) | size as u8);
+        let bits = if value < 0 { value - 1 } else { value };
+        self.write(bits as u32, size);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.write(0x7f, 8 - self.count);
+        }
+        self.output
+    }
+}
+
+fn jpeg_segment(output: &mut Vec<u8>, marker: u8, body: &[u8]) {
+    output.extend_from_slice(&[0xff, marker]);
+    output.extend_from_slice(&(body.len() as u16 + 2).to_be_bytes());
+    output.extend_from_slice(body);
+}
+
+/// 编码为基线JPEG，色度按4:2:0采样
+fn encode_jpeg(bitmap: &Bitmap, quality: u8) -> Vec<u8> {
+    let quality = u32::from(quality.clamp(1, 100));
+    let scale = if quality < 50 { 5000 / quality } else { 200 - quality * 2 };
+    let table = |base: &[u16; 64]| -> [u16; 64] {
+        let mut table = [0u16; 64];
+        for (k, value) in table.iter_mut().enumerate() {
+            *value = ((u32::from(base[ZIGZAG[k]]) * scale + 50) / 100).clamp(1, 255) as u16;
+        }
+        table
+    };
+    let tables = [table(&LUMINANCE_QUANTIZATION), table(&CHROMINANCE_QUANTIZATION)];
+
+    let mut output = vec![0xff, 0xd8];
+    jpeg_segment(&mut output, 0xe0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+    for (id, table) in tables.iter().enumerate() {
+        let mut body = vec![id as u8];
+        body.extend(table.iter().map(|&value| value as u8));
+        jpeg_segment(&mut output, 0xdb, &body);
+    }
+    let (width, height) = (bitmap.width as usize, bitmap.height as usize);
+    let mut frame = vec![8];
+    frame.extend_from_slice(&(height as u16).to_be_bytes());
+    frame.extend_from_slice(&(width as u16).to_be_bytes());
+    frame.extend_from_slice(&[3, 1, 0x22, 0, 2, 0x11, 1, 3, 0x11, 1]);
+    jpeg_segment(&mut output, 0xc0, &frame);
+    let huffman = [(0x00, DC_LUMINANCE), (0x10, AC_LUMINANCE), (0x01, DC_CHROMINANCE), (0x11, AC_CHROMINANCE)];
+    for (class, (counts, values)) in huffman {
+        let mut body = vec![class];
+        body.extend_from_slice(&counts);
+        body.extend_from_slice(values);
+        jpeg_segment(&mut output, 0xc4, &body);
+    }
+    jpeg_segment(&mut output, 0xda, &[3, 1, 0x00, 2, 0x11, 3, 0x11, 0, 63, 0]);
+
+    let dc = [huffman_codes(&DC_LUMINANCE), huffman_codes(&DC_CHROMINANCE)];
+    let ac = [huffman_codes(&AC_LUMINANCE), huffman_codes(&AC_CHROMINANCE)];
+    let mut writer = BitWriter::default();
+    let mut predictions = [0i32; 3];
+    // 边缘之外的像素取最近的边缘像素
+    let ycbcr = |x: usize, y: usize| {
+        let at = (y.min(height - 1) * width + x.min(width - 1)) * 4;
+        let (r, g, b) = (
+            f32::from(bitmap.pixels[at]),
+            f32::from(bitmap.pixels[at + 1]),
+            f32::from(bitmap.pixels[at + 2]),
+        );
+        [
+            0.299 * r + 0.587 * g + 0.114 * b,
+            -0.168736 * r - 0.331264 * g + 0.5 * b + 128.0,
+            0.5 * r - 0.418688 * g - 0.081312 * b + 128.0,
+        ]
+    };
+    let mut encode_block = |writer: &mut BitWriter, block: &[f32; 64], component: usize| {
+        let class = component.min(1);
+        let coefficients = fdct(block);
+        let quantized: Vec<i32> = (0..64)
+            .map(|k| (coefficients[ZIGZAG[k]] / f32::from(tables[class][k])).round() as i32)
+            .collect();
+        let difference = quantized[0] - predictions[component];
+        predictions[component] = quantized[0];
+        writer.value(&dc[class], 0, difference);
+        let mut run = 0u8;
+        for &value in &quantized[1..] {
+            if value == 0 {
+                run += 1;
+                continue;
+            }
+            while run >= 16 {
+                writer.code(&ac[class], 0xf0);
+                run -= 16;
+            }
+            writer.value(&ac[class], run, value);
+            run = 0;
+        }
+        if run > 0 {
+            writer.code(&ac[class], 0x00);
+        }
+    };
+
+    for my in 0..height.div_ceil(16) {
+        for mx in 0..width.div_ceil(16) {
+            let mut samples = [[[0f32; 3]; 16]; 16];
+            for (y, row) in samples.iter_mut().enumerate() {
+                for (x, sample) in row.iter_mut().enumerate() {
+                    *sample = ycbcr(mx * 16 + x, my * 16 + y);
+                }
+            }
+            for (by, bx) in [(0, 0), (0, 8), (8, 0), (8, 8)] {
+                let mut block = [0f32; 64];
+                for (i, value) in block.iter_mut().enumerate() {
+                    *value = samples[by + i / 8][bx + i % 8][0] - 128.0;
+                }
+                encode_block(&mut writer, &block, 0);
+            }
+            for component in [1, 2] {
+                let mut block = [0f32; 64];
+                for (i, value) in block.iter_mut().enumerate() {
+                    let (y, x) = (i / 8 * 2, i % 8 * 2);
+                    let sum = samples[y][x][component]
+                        + samples[y][x + 1][component]
+                        + samples[y + 1][x][component]
+                        + samples[y + 1][x + 1][component];
+                    *value = sum / 4.0 - 128.0;
+                }
+                encode_block(&mut writer, &block, component);
+            }
+        }
+    }
+    output.extend_from_slice(&writer.finish());
+    output.extend_from_slice(&[0xff, 0xd9]);
+    output
+}
@@ -0,0 +1,539 @@
+//! 监视本地文件夹，自动导入向量索引
+//!
+//! 每隔`indexer.interval_seconds`扫描`indexer.folders`中的各个文件夹（跳过隐藏文件、符号链接、`exclude`匹配的文件、
+//! 过大的文件和不支持的类型），按大小、修改时间和SHA-256判断变化：新增和修改的文件重新导入，文档id为文件的绝对路径；
+//! 删除的文件从索引中删除它的分块；内容不变而路径变了的文件视为改名，在新路径下导入后删除旧路径的分块。
+//! 最后一次修改不到`settle_seconds`的文件留到下一次扫描，导入失败的文件在再次修改或手动重新扫描之前不再重试。
+//! 文件夹不存在时（如移动硬盘未挂载）不删除已导入的内容。
+//!
+//! 已导入文件的清单保存在`state_path`，重启后只导入期间变化的文件。`/admin/indexer`查看各文件夹的状态，
+//! `/admin/indexer/rescan`立即扫描一次。
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use glob::Pattern;
+use openkimi_rag::{Document, DocumentKind};
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map};
+use tokio::sync::Notify;
+
+use crate::config::{Config, IndexerConfig};
+use crate::rag;
+use crate::workspace::{validate_workspace_name, Workspace, Workspaces};
+use crate::AppState;
+
+/// 每个文件夹在状态中保留的最近的导入错误数
+const MAX_ERRORS: usize = 20;
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+fn modified(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_nanos() as u64)
+}
+
+fn sha256(data: &[u8]) -> String {
+    digest(&SHA256, data).as_ref().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// 清单中的一个已导入文件
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct FileEntry {
+    size: u64,
+    /// 修改时间，Unix纳秒
+    modified: u64,
+    sha256: String,
+}
+
+/// 已导入文件的清单，键为[`Folder::key`]，其中的键为相对于文件夹的路径
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    folders: HashMap<String, BTreeMap<String, FileEntry>>,
+}
+
+impl Manifest {
+    fn load(path: &Path) -> Result<Manifest, String> {
+        match fs::read(path) {
+            Ok(data) => {
+                serde_json::from_slice(&data).map_err(|e| format!("解析索引清单 {} 失败: {}", path.display(), e))
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Manifest::default()),
+            Err(err) => Err(format!("读取索引清单 {} 失败: {}", path.display(), err)),
+        }
+    }
+
+    fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(&temporary, path)
+    }
+}
+
+/// 导入失败的文件
+#[derive(Debug, Clone, Serialize)]
+pub struct FileError {
+    pub path: String,
+    pub error: String,
+    pub at: u64,
+}
+
+/// `/admin/indexer`中一个文件夹的状态
+#[derive(Debug, Clone, Serialize)]
+pub struct FolderStatus {
+    pub path: String,
+    pub index: String,
+    pub workspace: String,
+    /// `idle`、`scanning`、`ingesting`，或文件夹不存在、无法读取时为`missing`
+    pub state: &'static str,
+    /// 已导入的文件数
+    pub files: usize,
+    /// 等待导入或删除的文件数，包括修改后还不到`settle_seconds`的
+    pub pending: usize,
+    /// 导入失败、等待再次修改的文件数
+    pub failed: usize,
+    /// 启动以来导入、删除和改名的文件数
+    pub ingested: u64,
+    pub removed: u64,
+    pub renamed: u64,
+    pub last_scan_at: Option<u64>,
+    /// 最近一次导入或删除的时间
+    pub last_change_at: Option<u64>,
+    /// 文件夹本身的错误，如不存在
+    pub last_error: Option<String>,
+    /// 最近的导入错误，新的在前
+    pub errors: Vec<FileError>,
+}
+
+/// 一次扫描发现的变化
+#[derive(Debug, Default)]
+struct Changes {
+    /// 新增或内容变化的文件
+    changed: Vec<String>,
+    /// 内容没变、只是修改时间变了的文件
+    touched: Vec<(String, FileEntry)>,
+    removed: Vec<String>,
+    /// 改名前后的路径
+    renamed: Vec<(String, String)>,
+    /// 修改后还不到`settle_seconds`的文件数
+    unsettled: usize,
+}
+
+impl Changes {
+    fn pending(&self) -> usize {
+        self.changed.len() + self.removed.len() + self.renamed.len() + self.unsettled
+    }
+}
+
+/// 一个监视的文件夹
+#[derive(Debug)]
+struct Folder {
+    path: PathBuf,
+    index: String,
+    workspace: Workspace,
+    exclude: Vec<Pattern>,
+    status: Mutex<FolderStatus>,
+    /// 导入失败的文件和失败时的大小、修改时间，变化之前不再重试
+    failed: Mutex<HashMap<String, (u64, u64)>>,
+}
+
+impl Folder {
+    /// 清单中的键，同一个文件夹可以导入不同的索引
+    fn key(&self) -> String {
+        format!("{}/{}:{}", self.workspace.name(), self.index, self.path.display())
+    }
+
+    /// 文档id
+    fn document_id(&self, relative: &str) -> String {
+        self.path.join(relative).to_string_lossy().into_owned()
+    }
+
+    fn excluded(&self, relative: &str) -> bool {
+        self.exclude.iter().any(|pattern| pattern.matches(relative))
+    }
+
+    /// 递归列出支持导入的文件，键为以`/`分隔的相对路径
+    fn walk(&self, dir: &Path, prefix: &str, max_bytes: u64, files: &mut BTreeMap<String, fs::Metadata>) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let relative = format!("{}{}", prefix, name);
+            if name.starts_with('.') || self.excluded(&relative) {
+                continue;
+            }
+            // 不跟随符号链接
+            let Ok(metadata) = entry.path().symlink_metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                self.walk(&entry.path(), &format!("{}/", relative), max_bytes, files);
+            } else if metadata.is_file() && metadata.len() <= max_bytes && DocumentKind::from_name(&name).is_some() {
+                files.insert(relative, metadata);
+            }
+        }
+    }
+
+    /// 与清单比较，找出需要导入和删除的文件
+    fn scan(&self, known: &BTreeMap<String, FileEntry>, config: &IndexerConfig) -> Changes {
+        let mut files = BTreeMap::new();
+        self.walk(&self.path, "", config.max_file_bytes, &mut files);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+        let settle = Duration::from_secs(config.settle_seconds).as_nanos() as u64;
+        let mut failed = self.failed.lock().unwrap();
+        // 已经不存在的文件不再记录
+        failed.retain(|relative, _| files.contains_key(relative));
+        let mut changes = Changes::default();
+        // 新增文件的内容摘要，用于识别改名
+        let mut added = HashMap::new();
+        for (relative, metadata) in &files {
+            let (size, modified) = (metadata.len(), modified(metadata));
+            let entry = known.get(relative);
+            if entry.is_some_and(|entry| entry.size == size && entry.modified == modified)
+                || failed.get(relative) == Some(&(size, modified))
+            {
+                continue;
+            }
+            if now.saturating_sub(modified) < settle {
+                changes.unsettled += 1;
+                continue;
+            }
+            let Ok(data) = fs::read(self.path.join(relative)) else {
+                continue;
+            };
+            let hash = sha256(&data);
+            match entry {
+                Some(entry) if entry.sha256 == hash => changes.touched.push((
+                    relative.clone(),
+                    FileEntry {
+                        size,
+                        modified,
+                        sha256: hash,
+                    },
+                )),
+                Some(_) => changes.changed.push(relative.clone()),
+                None => {
+                    added.entry(hash).or_insert_with(|| relative.clone());
+                    changes.changed.push(relative.clone());
+                }
+            }
+        }
+        for (relative, entry) in known {
+            if files.contains_key(relative) {
+                continue;
+            }
+            match added.remove(&entry.sha256) {
+                Some(to) => {
+                    changes.changed.retain(|path| path != &to);
+                    changes.renamed.push((relative.clone(), to));
+                }
+                None => changes.removed.push(relative.clone()),
+            }
+        }
+        changes
+    }
+
+    fn update(&self, f: impl FnOnce(&mut FolderStatus)) {
+        f(&mut self.status.lock().unwrap());
+    }
+
+    fn record_error(&self, relative: &str, error: String) {
+        eprintln!("⚠️ 导入 {} 失败: {}", self.path.join(relative).display(), error);
+        self.update(|status| {
+            status.errors.insert(
+                0,
+                FileError {
+                    path: relative.to_string(),
+                    error,
+                    at: unix_now(),
+                },
+            );
+            status.errors.truncate(MAX_ERRORS);
+        });
+    }
+
+    /// 读取并导入一个文件，返回清单中的记录
+    async fn ingest(&self, state: &AppState, relative: &str) -> Result<FileEntry, String> {
+        let path = self.path.join(relative);
+        let metadata = fs::metadata(&path).map_err(|e| e.to_string())?;
+        let data = fs::read(&path).map_err(|e| e.to_string())?;
+        let entry = FileEntry {
+            size: metadata.len(),
+            modified: modified(&metadata),
+            sha256: sha256(&data),
+        };
+        let kind = DocumentKind::from_name(relative).ok_or("不支持的文件类型")?;
+        let mut metadata = Map::new();
+        metadata.insert("folder".to_string(), json!(self.path.to_string_lossy()));
+        metadata.insert("path".to_string(), json!(relative));
+        let document = Document {
+            id: self.document_id(relative),
+            name: self.document_id(relative),
+            kind,
+            data,
+            metadata,
+            chunking: None,
+        };
+        rag::ingest_documents(state, &self.workspace, &self.index, &[document])
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(entry)
+    }
+
+    fn delete(&self, state: &AppState, relative: &str) -> Result<(), String> {
+        let id = self.document_id(relative);
+        state
+            .indexes
+            .with_index(&self.workspace.index_name(&self.index), |store| {
+                store.delete_document(&id)?;
+                store.flush()?;
+                Ok(())
+            })
+            .map_err(|e| e.to_string())
+    }
+
+    /// 扫描并处理变化，返回清单是否有改动
+    async fn sync(&self, state: &AppState, known: &mut BTreeMap<String, FileEntry>, config: &IndexerConfig) -> bool {
+        if !self.path.is_dir() {
+            self.update(|status| {
+                status.state = "missing";
+                status.last_error = Some(format!("文件夹不存在或无法读取: {}", self.path.display()));
+                status.last_scan_at = Some(unix_now());
+            });
+            return false;
+        }
+        self.update(|status| status.state = "scanning");
+        let changes = tokio::task::block_in_place(|| self.scan(known, config));
+        let mut pending = changes.pending();
+        let touched = !changes.touched.is_empty();
+        self.update(|status| {
+            status.state = if pending > changes.unsettled {
+                "ingesting"
+            } else {
+                "idle"
+            };
+            status.pending = pending;
+            status.last_error = None;
+            status.last_scan_at = Some(unix_now());
+        });
+        for (relative, entry) in changes.touched {
+            known.insert(relative, entry);
+        }
+
+        let mut counts = (0, 0, 0);
+        for relative in &changes.changed {
+            match self.ingest(state, relative).await {
+                Ok(entry) => {
+                    self.failed.lock().unwrap().remove(relative);
+                    known.insert(relative.clone(), entry);
+                    counts.0 += 1;
+                }
+                Err(err) => {
+                    if let Ok(metadata) = fs::metadata(self.path.join(relative)) {
+                        self.failed.lock().unwrap().insert(relative.clone(), (metadata.len(), modified(&metadata)));
+                    }
+                    self.record_error(relative, err);
+                }
+            }
+            pending -= 1;
+            self.update(|status| status.pending = pending);
+        }
+        for (from, to) in &changes.renamed {
+            // 先导入新路径，失败时保留旧路径的分块
+            match self.ingest(state, to).await.and_then(|entry| Ok((entry, self.delete(state, from)?))) {
+                Ok((entry, ())) => {
+                    known.remove(from);
+                    known.insert(to.clone(), entry);
+                    counts.2 += 1;
+                }
+                Err(err) => self.record_error(to, err),
+            }
+            pending -= 1;
+            self.update(|status| status.pending = pending);
+        }
+        for relative in &changes.removed {
+            match self.delete(state, relative) {
+                Ok(()) => {
+                    known.remove(relative);
+                    self.failed.lock().unwrap().remove(relative);
+                    counts.1 += 1;
+                }
+                Err(err) => self.record_error(relative, err),
+            }
+            pending -= 1;
+            self.update(|status| status.pending = pending);
+        }
+
+        let failed = self.failed.lock().unwrap().len();
+        self.update(|status| {
+            status.state = "idle";
+            status.files = known.len();
+            status.failed = failed;
+            status.ingested += counts.0;
+            status.removed += counts.1;
+            status.renamed += counts.2;
+            if counts != (0, 0, 0) {
+                status.last_change_at = Some(unix_now());
+            }
+        });
+        touched || counts != (0, 0, 0)
+    }
+}
+
+/// 文件夹监视器
+#[derive(Debug)]
+pub struct Indexer {
+    config: IndexerConfig,
+    folders: Vec<Folder>,
+    manifest: tokio::sync::Mutex<Manifest>,
+    wake: Notify,
+}
+
+impl Indexer {
+    /// 检查各文件夹的索引名、工作区和排除规则，读取清单
+    pub fn new(config: &Config, workspaces: Option<&Workspaces>) -> Result<Indexer, String> {
+        let indexer = &config.indexer;
+        let mut folders = Vec::new();
+        let mut keys = HashSet::new();
+        for watched in &indexer.folders {
+            let index = watched.index.clone().unwrap_or_else(|| config.rag.default_index.clone());
+            rag::validate_index_name(&index).map_err(|e| format!("indexer.folders: {}", e))?;
+            let workspace = match &watched.workspace {
+                Some(name) => {
+                    validate_workspace_name(name)?;
+                    let workspace = Workspace(name.clone());
+                    if !workspace.is_default() && !workspaces.is_some_and(|workspaces| workspaces.contains(name)) {
+                        return Err(format!("indexer.folders: 工作区 {} 不存在或未启用 workspaces", name));
+                    }
+                    workspace
+                }
+                None => Workspace::default(),
+            };
+            let exclude = watched
+                .exclude
+                .iter()
+                .map(|pattern| {
+                    Pattern::new(pattern).map_err(|e| format!("indexer.folders: 无效的排除规则 {}: {}", pattern, e))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let path = std::path::absolute(&watched.path)
+                .map_err(|e| format!("indexer.folders: 无效的路径 {}: {}", watched.path.display(), e))?;
+            let status = FolderStatus {
+                path: path.to_string_lossy().into_owned(),
+                index: index.clone(),
+                workspace: workspace.name().to_string(),
+                state: "idle",
+                files: 0,
+                pending: 0,
+                failed: 0,
+                ingested: 0,
+                removed: 0,
+                renamed: 0,
+                last_scan_at: None,
+                last_change_at: None,
+                last_error: None,
+                errors: Vec::new(),
+            };
+            let folder = Folder {
+                path,
+                index,
+                workspace,
+                exclude,
+                status: Mutex::new(status),
+                failed: Mutex::new(HashMap::new()),
+            };
+            if !keys.insert(folder.key()) {
+                return Err(format!("indexer.folders: 重复的文件夹 {}", folder.path.display()));
+            }
+            folders.push(folder);
+        }
+        let manifest = Manifest::load(&indexer.state_path)?;
+        for folder in &folders {
+            let files = manifest.folders.get(&folder.key()).map_or(0, BTreeMap::len);
+            folder.update(|status| status.files = files);
+        }
+        Ok(Indexer {
+            config: indexer.clone(),
+            folders,
+            manifest: tokio::sync::Mutex::new(manifest),
+            wake: Notify::new(),
+        })
+    }
+
+    /// 在后台开始监视，需要在Tokio运行时中调用
+    pub fn start(&self, state: &Arc<AppState>) {
+        for folder in &self.folders {
+            println!("👀 监视文件夹 {} → 索引 {}", folder.path.display(), folder.index);
+        }
+        tokio::spawn(watch_loop(Arc::downgrade(state)));
+    }
+
+    /// 扫描所有文件夹一次，清单有改动时写回
+    async fn pass(&self, state: &AppState) {
+        let mut manifest = self.manifest.lock().await;
+        let mut dirty = false;
+        for folder in &self.folders {
+            let known = manifest.folders.entry(folder.key()).or_default();
+            dirty |= folder.sync(state, known, &self.config).await;
+        }
+        if dirty {
+            if let Err(err) = manifest.save(&self.config.state_path) {
+                eprintln!("⚠️ 写入索引清单 {} 失败: {}", self.config.state_path.display(), err);
+            }
+        }
+    }
+
+    /// 各文件夹的状态
+    pub fn list(&self) -> Vec<FolderStatus> {
+        self.folders.iter().map(|folder| folder.status.lock().unwrap().clone()).collect()
+    }
+
+    /// 立即扫描，之前导入失败的文件也重试
+    pub fn rescan(&self) {
+        for folder in &self.folders {
+            folder.failed.lock().unwrap().clear();
+        }
+        self.wake.notify_one();
+    }
+}
+
+/// 按间隔扫描，服务状态释放或开始退出后结束
+async fn watch_loop(state: Weak<AppState>) {
+    loop {
+        let Some(strong) = state.upgrade() else {
+            return;
+        };
+        let shutdown = strong.shutdown.clone();
+        if shutdown.is_draining() {
+            return;
+        }
+        let Some(indexer) = strong.indexer.clone() else {
+            return;
+        };
+        {
+            // 退出时等本次扫描结束
+            let _hold = shutdown.hold();
+            indexer.pass(&strong).await;
+        }
+        drop(strong);
+        let interval = Duration::from_secs(indexer.config.interval_seconds.max(1));
+        tokio::select! {
+            () = tokio::time::sleep(interval) => {}
+            () = indexer.wake.notified() => {}
+            () = shutdown.wait() => return,
+        }
+    }
+}
@@ -0,0 +1,611 @@
+//! 代码解释器
+//!
+//! 在WebAssembly沙箱中运行模型生成的Python或JavaScript代码：用`wasmtime`运行编译为WASI的解释器（如CPython的
+//! `python.wasm`、QuickJS的`qjs.wasm`），以燃料限制CPU、以线性内存上限限制内存，超时后结束进程。
+//! 沙箱中没有网络，只能看到解释器自带的文件和按会话隔离的`/workspace`目录（也是当前目录），
+//! 同一会话的多次运行共享其中的文件。标准输出和标准错误按上限截断，运行中新建或修改的文件随结果返回，
+//! 其中的图片即代码画出的图表。启用`tools`时注册为内置的`run_code`工具，模型可以在对话中运行代码。
+
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Component, Path as FsPath, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
+
+use axum::extract::{Path, State};
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::Semaphore;
+
+use crate::config::{InterpreterConfig, SandboxLanguage};
+use crate::error::{ApiError, ApiResult};
+use crate::tools::ToolScope;
+use crate::types::{DeletedResponse, ListResponse};
+use crate::workspace::Workspace;
+use crate::AppState;
+
+/// 内置工具的函数名
+pub const TOOL_NAME: &str = "run_code";
+
+/// 会话目录在沙箱中的路径
+const GUEST_DIR: &str = "/workspace";
+
+/// 代码文件所在目录在沙箱中的路径
+const CODE_DIR: &str = "/code";
+
+/// 未指定会话的请求，其文件在最后一次运行后保留多久
+const REQUEST_TTL: Duration = Duration::from_secs(600);
+
+/// 清理过期会话的间隔
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 统计文件时最多深入几层目录
+const MAX_DEPTH: usize = 16;
+
+/// 超过这个大小的图片只返回路径，不内联到响应中
+const MAX_INLINE_IMAGE_BYTES: u64 = 4 * 1024 * 1024;
+
+/// 会话id只能由字母、数字、下划线和连字符组成，最长64个字符
+pub fn check_session(id: &str) -> ApiResult<()> {
+    let valid = id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if id.is_empty() || id.len() > 64 || !valid {
+        return Err(ApiError::invalid_request(format!(
+            "会话 id {:?} 无效，只能包含字母、数字、_ 和 -，长度为 1 到 64",
+            id
+        )));
+    }
+    Ok(())
+}
+
+/// 按扩展名判断沙箱中文件的类型
+fn mime_type(path: &FsPath) -> &'static str {
+    let extension = path.extension().map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("svg") => "image/svg+xml",
+        Some("txt" | "log" | "md" | "py" | "js") => "text/plain",
+        Some("csv") => "text/csv",
+        Some("html" | "htm") => "text/html",
+        Some("json") => "application/json",
+        Some("pdf") => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+/// 目录中所有普通文件的大小和修改时间，键为相对路径；不跟随符号链接，沙箱中的代码可以创建指向任意位置的链接
+fn snapshot(root: &FsPath) -> BTreeMap<PathBuf, (u64, Option<SystemTime>)> {
+    let mut files = BTreeMap::new();
+    let mut pending = vec![(PathBuf::new(), 0)];
+    while let Some((relative, depth)) = pending.pop() {
+        let Ok(entries) = fs::read_dir(root.join(&relative)) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = relative.join(entry.file_name());
+            let Ok(metadata) = entry.path().symlink_metadata() else {
+                continue;
+            };
+            if metadata.is_dir() && depth < MAX_DEPTH {
+                pending.push((path, depth + 1));
+            } else if metadata.is_file() {
+                files.insert(path, (metadata.len(), metadata.modified().ok()));
+            }
+        }
+    }
+    files
+}
+
+/// 把请求中的路径（`/workspace/plot.png`或`plot.png`）解析为会话目录中的文件，拒绝`..`和符号链接
+fn resolve(root: &FsPath, path: &str) -> ApiResult<PathBuf> {
+    let relative = path.strip_prefix(GUEST_DIR).unwrap_or(path).trim_start_matches('/');
+    let mut resolved = root.to_path_buf();
+    for component in FsPath::new(relative).components() {
+        let Component::Normal(name) = component else {
+            return Err(ApiError::invalid_request(format!("无效的文件路径 {}", path)));
+        };
+        resolved.push(name);
+        if resolved.symlink_metadata().is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+            return Err(ApiError::invalid_request(format!("{} 是符号链接", path)));
+        }
+    }
+    if resolved == root || !resolved.is_file() {
+        return Err(ApiError::NotFound(format!("文件 {} 不存在", path)));
+    }
+    Ok(resolved)
+}
+
+/// 16个十六进制字符的随机串
+pub(crate) fn random_hex() -> Result<String, String> {
+    let mut bytes = [0u8; 8];
+    getrandom::getrandom(&mut bytes).map_err(|e| format!("生成随机数失败: {}", e))?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// 存放代码文件的临时目录，运行结束后删除
+struct CodeDir(PathBuf);
+
+impl CodeDir {
+    fn new(code: &str, extension: &str) -> Result<(CodeDir, String), String> {
+        let path = env::temp_dir().join(format!("openkimi-code-{}", random_hex()?));
+        fs::create_dir(&path).map_err(|e| format!("无法创建临时目录 {}: {}", path.display(), e))?;
+        let dir = CodeDir(path);
+        let name = format!("main.{}", extension);
+        fs::write(dir.0.join(&name), code).map_err(|e| format!("写入代码文件失败: {}", e))?;
+        Ok((dir, format!("{}/{}", CODE_DIR, name)))
+    }
+}
+
+impl Drop for CodeDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// 在后台线程读完管道，只保留前`limit`字节，返回是否有被丢弃的输出
+fn capture(pipe: Option<impl Read + Send + 'static>, limit: usize) -> JoinHandle<(Vec<u8>, bool)> {
+    std::thread::spawn(move || {
+        let mut kept = Vec::new();
+        let mut truncated = false;
+        if let Some(mut pipe) = pipe {
+            let mut buffer = [0u8; 8192];
+            while let Ok(read) = pipe.read(&mut buffer) {
+                if read == 0 {
+                    break;
+                }
+                let take = read.min(limit - kept.len());
+                kept.extend_from_slice(&buffer[..take]);
+                truncated |= take < read;
+            }
+        }
+        (kept, truncated)
+    })
+}
+
+/// 一次运行的进程结果
+pub(crate) struct Execution {
+    pub status: Option<ExitStatus>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub truncated: bool,
+    pub timed_out: bool,
+    pub elapsed: Duration,
+}
+
+/// 运行wasmtime并把`input`写入其标准输入，超时后结束进程；沙箱中的代码以非零状态退出不算失败
+pub(crate) fn execute(
+    args: &[String],
+    input: Vec<u8>,
+    limit: usize,
+    timeout_seconds: u64,
+) -> Result<Execution, String> {
+    let started = Instant::now();
+    let mut child = Command::new(&args[0])
+        .args(&args[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("无法运行 {}: {}", args[0], e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // 关闭标准输入表示输入结束；程序不读输入而提前退出时写入失败，不影响结果
+        std::thread::spawn(move || {
+            let _ = stdin.write_all(&input);
+        });
+    }
+    let stdout = capture(child.stdout.take(), limit);
+    let stderr = capture(child.stderr.take(), limit);
+    let deadline = started + Duration::from_secs(timeout_seconds);
+    let mut timed_out = false;
+    let status = loop {
+        match child.try_wait().map_err(|e| format!("等待 {} 失败: {}", args[0], e))? {
+            Some(status) => break Some(status),
+            None if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                timed_out = true;
+                break None;
+            }
+            None => std::thread::sleep(Duration::from_millis(20)),
+        }
+    };
+    let (stdout, stdout_truncated) = stdout.join().unwrap_or_default();
+    let (stderr, stderr_truncated) = stderr.join().unwrap_or_default();
+    Ok(Execution {
+        status,
+        stdout,
+        stderr,
+        truncated: stdout_truncated || stderr_truncated,
+        timed_out,
+        elapsed: started.elapsed(),
+    })
+}
+
+/// 会话目录中的文件
+#[derive(Debug, Clone, Serialize)]
+pub struct SandboxFile {
+    /// 沙箱中的路径
+    pub path: String,
+    pub bytes: u64,
+    pub mime_type: &'static str,
+    /// 图片内容的base64，只在`POST /v1/interpreter/run`的响应中返回
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+}
+
+/// 一次运行的结果
+#[derive(Debug, Clone, Serialize)]
+pub struct RunResult {
+    pub language: String,
+    /// 未指定会话时为空，文件只在本次请求中可用
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// 超时被结束时为空
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    /// 输出超出`interpreter.max_output_bytes`，多出的部分被丢弃
+    pub truncated: bool,
+    pub timed_out: bool,
+    pub duration_ms: u64,
+    /// 本次运行新建或修改的文件
+    pub files: Vec<SandboxFile>,
+}
+
+/// 可用的解释器和各会话的文件
+#[derive(Debug)]
+pub struct Interpreter {
+    config: InterpreterConfig,
+    /// 模块文件存在的语言
+    languages: BTreeMap<String, SandboxLanguage>,
+    permits: Semaphore,
+    /// 同一会话的运行依次进行，避免同时修改文件
+    sessions: Mutex<HashMap<PathBuf, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl Interpreter {
+    /// 检查解释器模块并启动清理线程，模块不存在的语言会被跳过
+    pub fn new(config: &InterpreterConfig) -> Result<Interpreter, String> {
+        if config.wasmtime.is_empty() {
+            return Err("interpreter.wasmtime 不能为空".to_string());
+        }
+        let mut languages = BTreeMap::new();
+        for (name, language) in &config.languages {
+            if language.extension.is_empty() || language.extension.contains(['/', '.']) {
+                return Err(format!("interpreter.languages.{}.extension 无效", name));
+            }
+            if !language.module.is_file() {
+                eprintln!("⚠️ 解释器 {} 的模块 {} 不存在，已跳过", name, language.module.display());
+                continue;
+            }
+            languages.insert(name.clone(), language.clone());
+        }
+        if languages.is_empty() {
+            return Err("interpreter.languages 中没有可用的解释器模块".to_string());
+        }
+        fs::create_dir_all(&config.dir).map_err(|e| format!("创建目录 {} 失败: {}", config.dir.display(), e))?;
+
+        let (dir, ttl) = (config.dir.clone(), config.session_ttl_seconds);
+        std::thread::spawn(move || loop {
+            std::thread::sleep(CLEANUP_INTERVAL);
+            if ttl > 0 {
+                remove_expired(&dir.join("sessions"), Duration::from_secs(ttl));
+            }
+            remove_expired(&dir.join("requests"), REQUEST_TTL);
+        });
+        Ok(Interpreter {
+            config: config.clone(),
+            languages,
+            permits: Semaphore::new(config.max_concurrent.max(1)),
+            sessions: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn languages(&self) -> impl Iterator<Item = &str> {
+        self.languages.keys().map(String::as_str)
+    }
+
+    /// 命名会话的目录
+    fn session_dir(&self, workspace: &Workspace, session: &str) -> PathBuf {
+        self.config.dir.join("sessions").join(workspace.name()).join(session)
+    }
+
+    /// 作用域对应的目录，未指定会话时按请求隔离
+    fn scope_dir(&self, scope: &ToolScope) -> PathBuf {
+        match &scope.session {
+            Some(session) => self.session_dir(&scope.workspace, session),
+            None => self.config.dir.join("requests").join(scope.workspace.name()).join(&scope.request),
+        }
+    }
+
+    fn session_lock(&self, dir: &FsPath) -> Arc<tokio::sync::Mutex<()>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        // 没有其他运行持有的锁可以丢弃
+        sessions.retain(|_, lock| Arc::strong_count(lock) > 1);
+        Arc::clone(sessions.entry(dir.to_path_buf()).or_default())
+    }
+
+    /// wasmtime的参数
+    fn command(&self, language: &SandboxLanguage, session: &FsPath, code: &FsPath, file: &str) -> Vec<String> {
+        let config = &self.config;
+        let mut args = vec![config.wasmtime.clone(), "run".to_string()];
+        args.extend(["-W".to_string(), format!("max-memory-size={}", config.max_memory_bytes)]);
+        if config.fuel > 0 {
+            args.extend(["-W".to_string(), format!("fuel={}", config.fuel)]);
+        }
+        let session = session.to_string_lossy();
+        // 同时挂载为当前目录，相对路径也落在会话目录中
+        for guest in [GUEST_DIR, "."] {
+            args.extend(["--dir".to_string(), format!("{}::{}", session, guest)]);
+        }
+        args.extend(["--dir".to_string(), format!("{}::{}", code.to_string_lossy(), CODE_DIR)]);
+        for (guest, host) in &language.dirs {
+            args.extend(["--dir".to_string(), format!("{}::{}", host.to_string_lossy(), guest)]);
+        }
+        args.extend(["--env".to_string(), format!("HOME={}", GUEST_DIR)]);
+        for (name, value) in &language.env {
+            args.extend(["--env".to_string(), format!("{}={}", name, value)]);
+        }
+        args.push(language.module.to_string_lossy().into_owned());
+        args.extend(language.args.iter().map(|arg| arg.replace("{file}", file)));
+        args
+    }
+
+    /// 在作用域的会话目录中运行代码，`inline_images`为`true`时返回图片内容
+    pub async fn run(
+        &self,
+        scope: &ToolScope,
+        language: &str,
+        code: &str,
+        inline_images: bool,
+    ) -> ApiResult<RunResult> {
+        let Some(sandbox) = self.languages.get(language) else {
+            let available: Vec<&str> = self.languages().collect();
+            return Err(ApiError::invalid_request(format!(
+                "不支持的语言 {}，可用的语言为 {}",
+                language,
+                available.join("、")
+            )));
+        };
+        if code.trim().is_empty() {
+            return Err(ApiError::invalid_request("code 不能为空"));
+        }
+        if code.len() > self.config.max_code_bytes {
+            return Err(ApiError::PayloadTooLarge(format!(
+                "代码为 {} 字节，超过 {} 字节的上限",
+                code.len(),
+                self.config.max_code_bytes
+            )));
+        }
+
+        let dir = self.scope_dir(scope);
+        let lock = self.session_lock(&dir);
+        let _session = lock.lock().await;
+        let _permit = self
+            .permits
+            .acquire()
+            .await
+            .map_err(|e| ApiError::Internal(format!("等待运行失败: {}", e)))?;
+
+        fs::create_dir_all(&dir).map_err(|e| ApiError::Internal(format!("创建目录 {} 失败: {}", dir.display(), e)))?;
+        let before = snapshot(&dir);
+        let used: u64 = before.values().map(|(bytes, _)| bytes).sum();
+        if used > self.config.max_session_bytes {
+            return Err(ApiError::invalid_request(format!(
+                "会话中的文件共 {} 字节，超过 {} 字节的上限，请先删除不需要的文件",
+                used, self.config.max_session_bytes
+            )));
+        }
+
+        let (code_dir, file) = CodeDir::new(code, &sandbox.extension).map_err(ApiError::Internal)?;
+        let args = self.command(sandbox, &dir, &code_dir.0, &file);
+        let (limit, timeout_seconds) = (self.config.max_output_bytes, self.config.timeout_seconds);
+        let execution = tokio::task::spawn_blocking(move || execute(&args, Vec::new(), limit, timeout_seconds))
+            .await
+            .map_err(|e| ApiError::Internal(format!("运行代码失败: {}", e)))?
+            .map_err(ApiError::Internal)?;
+        drop(code_dir);
+        // 更新目录的修改时间，清理线程据此判断会话是否过期
+        if let Ok(handle) = File::open(&dir) {
+            let _ = handle.set_modified(SystemTime::now());
+        }
+
+        let mut files = Vec::new();
+        for (path, state) in snapshot(&dir) {
+            if before.get(&path) == Some(&state) {
+                continue;
+            }
+            let mime_type = mime_type(&path);
+            let data = (inline_images && mime_type.starts_with("image/") && state.0 <= MAX_INLINE_IMAGE_BYTES)
+                .then(|| fs::read(dir.join(&path)).ok())
+                .flatten()
+                .map(|data| STANDARD.encode(data));
+            files.push(SandboxFile {
+                path: format!("{}/{}", GUEST_DIR, path.to_string_lossy()),
+                bytes: state.0,
+                mime_type,
+                data,
+            });
+        }
+
+        let mut stderr = String::from_utf8_lossy(&execution.stderr).into_owned();
+        if execution.timed_out {
+            stderr.push_str(&format!("\n运行超过 {} 秒，已被结束", timeout_seconds));
+        }
+        Ok(RunResult {
+            language: language.to_string(),
+            session_id: scope.session.clone(),
+            exit_code: execution.status.and_then(|status| status.code()),
+            stdout: String::from_utf8_lossy(&execution.stdout).into_owned(),
+            stderr,
+            truncated: execution.truncated,
+            timed_out: execution.timed_out,
+            duration_ms: execution.elapsed.as_millis() as u64,
+            files,
+        })
+    }
+
+    /// 会话中的所有文件
+    pub fn list(&self, workspace: &Workspace, session: &str) -> ApiResult<Vec<SandboxFile>> {
+        check_session(session)?;
+        let dir = self.session_dir(workspace, session);
+        if !dir.is_dir() {
+            return Err(ApiError::NotFound(format!("会话 {} 没有文件", session)));
+        }
+        Ok(snapshot(&dir)
+            .into_iter()
+            .map(|(path, (bytes, _))| SandboxFile {
+                path: format!("{}/{}", GUEST_DIR, path.to_string_lossy()),
+                bytes,
+                mime_type: mime_type(&path),
+                data: None,
+            })
+            .collect())
+    }
+
+    /// 读取会话中的一个文件
+    pub fn read(&self, workspace: &Workspace, session: &str, path: &str) -> ApiResult<(&'static str, Vec<u8>)> {
+        check_session(session)?;
+        let file = resolve(&self.session_dir(workspace, session), path)?;
+        let data = fs::read(&file).map_err(|e| ApiError::Internal(format!("读取文件 {} 失败: {}", path, e)))?;
+        Ok((mime_type(&file), data))
+    }
+
+    /// 删除会话的所有文件
+    pub fn delete(&self, workspace: &Workspace, session: &str) -> ApiResult<()> {
+        check_session(session)?;
+        let dir = self.session_dir(workspace, session);
+        if !dir.is_dir() {
+            return Err(ApiError::NotFound(format!("会话 {} 没有文件", session)));
+        }
+        fs::remove_dir_all(&dir).map_err(|e| ApiError::Internal(format!("删除会话 {} 的文件失败: {}", session, e)))
+    }
+
+    /// `run_code`工具的定义
+    pub fn tool_definition(&self) -> Value {
+        let languages: Vec<&str> = self.languages().collect();
+        json!({
+            "type": "function",
+            "function": {
+                "name": TOOL_NAME,
+                "description": format!(
+                    "在没有网络的沙箱中运行代码，返回标准输出、标准错误和新建或修改的文件。\
+                     当前目录为 {dir}，其中的文件在同一会话中保留；要返回图表，把图片保存到 {dir} 中。",
+                    dir = GUEST_DIR
+                ),
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "language": {"type": "string", "enum": languages},
+                        "code": {"type": "string", "description": "要运行的完整代码"}
+                    },
+                    "required": ["language", "code"]
+                }
+            }
+        })
+    }
+
+    /// 执行`run_code`工具，结果为JSON，图片只给出路径
+    pub async fn call_tool(&self, scope: &ToolScope, arguments: &Value) -> Result<String, String> {
+        let request: RunRequest = serde_json::from_value(arguments.clone()).map_err(|e| format!("参数无效: {}", e))?;
+        let result = self.run(scope, &request.language, &request.code, false).await.map_err(|e| e.to_string())?;
+        serde_json::to_string(&result).map_err(|e| e.to_string())
+    }
+}
+
+/// 删除`root`下最后一次运行早于`ttl`的会话目录，`root`下按工作区分目录
+fn remove_expired(root: &FsPath, ttl: Duration) {
+    let Ok(workspaces) = fs::read_dir(root) else {
+        return;
+    };
+    for workspace in workspaces.flatten() {
+        let Ok(sessions) = fs::read_dir(workspace.path()) else {
+            continue;
+        };
+        for session in sessions.flatten() {
+            let idle = session
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok());
+            if idle.is_some_and(|idle| idle > ttl) {
+                let _ = fs::remove_dir_all(session.path());
+            }
+        }
+    }
+}
+
+fn interpreter(state: &AppState) -> ApiResult<&Interpreter> {
+    state
+        .interpreter
+        .as_deref()
+        .ok_or_else(|| ApiError::invalid_request("代码解释器未启用（interpreter.enabled 为 false）"))
+}
+
+/// `POST /v1/interpreter/run`请求，也是`run_code`工具的参数
+#[derive(Debug, Clone, Deserialize)]
+pub struct RunRequest {
+    pub language: String,
+    pub code: String,
+    /// 缺省时文件只在本次运行中可用
+    #[serde(default)]
+    pub session_id: Option<String>,
+}
+
+/// `POST /v1/interpreter/run`
+pub async fn run_code(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Json(request): Json<RunRequest>,
+) -> ApiResult<Json<RunResult>> {
+    let interpreter = interpreter(&state)?;
+    let scope = ToolScope::new(workspace, request.session_id)?;
+    Ok(Json(interpreter.run(&scope, &request.language, &request.code, true).await?))
+}
+
+/// `GET /v1/interpreter/sessions/{id}/files`
+pub async fn list_files(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+) -> ApiResult<Json<ListResponse<SandboxFile>>> {
+    Ok(Json(ListResponse::new(interpreter(&state)?.list(&workspace, &id)?)))
+}
+
+/// `GET /v1/interpreter/sessions/{id}/files/{*path}`
+pub async fn file_content(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path((id, path)): Path<(String, String)>,
+) -> ApiResult<Response> {
+    let (mime_type, data) = interpreter(&state)?.read(&workspace, &id, &path)?;
+    // SVG和HTML可能带脚本，除位图外都作为附件下载
+    let raster = matches!(mime_type, "image/png" | "image/jpeg" | "image/gif" | "image/webp");
+    let disposition = if raster { "inline" } else { "attachment" };
+    Ok(([(CONTENT_TYPE, mime_type), (CONTENT_DISPOSITION, disposition)], data).into_response())
+}
+
+/// `DELETE /v1/interpreter/sessions/{id}`
+pub async fn delete_session(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+) -> ApiResult<Json<DeletedResponse>> {
+    interpreter(&state)?.delete(&workspace, &id)?;
+    Ok(Json(DeletedResponse {
+        id,
+        object: "interpreter.session".to_string(),
+        deleted: true,
+    }))
+}
@@ -0,0 +1,498 @@
+//! 定时任务
+//!
+//! 按cron表达式在后台运行维护任务：为会话生成摘要、整理本地向量索引、清理过期的回复缓存、汇总月度用量和删除衰减的长期记忆。
+//! 表达式按UTC计算；到点时上一次运行（包括其中的重试）还没结束就跳过这一次。
+//! 失败的任务按`retries`重试，第一次重试前等待`retry_delay_seconds`，之后每次翻倍。
+//! 任务状态只保存在内存中，`/admin/jobs`查看各任务的状态，`/admin/jobs/{name}/run`立即运行一次。
+//! 依赖的功能未启用的任务不会注册，例如未启用`sessions`时没有`summarize_sessions`。
+
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use openkimi_sessions::time::civil_from_days;
+use openkimi_sessions::{SessionStore, SummaryTask};
+use serde::Serialize;
+
+use crate::config::{Config, JobConfig, JobsConfig, StoreKind};
+use crate::context::{Summarizer, UpstreamSummarizer};
+use crate::error::{ApiError, ApiResult};
+use crate::AppState;
+
+/// 生成摘要时最多发送给模型的对话记录字节数，超出时保留最近的部分
+const MAX_TRANSCRIPT_BYTES: usize = 64 * 1024;
+
+/// 重试间隔最多翻倍的次数
+const MAX_BACKOFF_SHIFT: u32 = 10;
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// 解析后的cron表达式，每个字段用位图表示允许的取值
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u32,
+    days: u32,
+    months: u16,
+    weekdays: u8,
+    /// 日和周字段是否为`*`；两者都有限制时满足其一即可，与常见的cron实现一致
+    any_day: bool,
+    any_weekday: bool,
+}
+
+/// 解析一个cron字段，返回允许取值的位图和字段是否为`*`
+///
+/// 支持`*`、`5`、`1-5`、`*/15`、`10-50/20`以及用逗号分隔的组合。
+fn parse_field(field: &str, min: u32, max: u32) -> Result<(u64, bool), String> {
+    let invalid = || format!("无效的cron字段: {}", field);
+    let number = |text: &str| -> Result<u32, String> {
+        text.parse::<u32>().ok().filter(|value| (min..=max).contains(value)).ok_or_else(invalid)
+    };
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|step| *step > 0).ok_or_else(invalid)?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (number(start)?, number(end)?),
+                // `5/10`表示从5开始每隔10
+                None if step > 1 => (number(range)?, max),
+                None => (number(range)?, number(range)?),
+            },
+        };
+        if start > end {
+            return Err(invalid());
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok((bits, field == "*"))
+}
+
+impl Schedule {
+    /// 解析五段cron表达式（分 时 日 月 周），也接受`@hourly`、`@daily`、`@weekly`和`@monthly`
+    pub fn parse(expression: &str) -> Result<Schedule, String> {
+        let expression = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!("cron表达式需要五个字段（分 时 日 月 周）: {}", expression));
+        };
+        let (minutes, _) = parse_field(minute, 0, 59)?;
+        let (hours, _) = parse_field(hour, 0, 23)?;
+        let (days, any_day) = parse_field(day, 1, 31)?;
+        let (months, _) = parse_field(month, 1, 12)?;
+        // 周日可以写成0或7
+        let (weekdays, any_weekday) = parse_field(weekday, 0, 7)?;
+        let weekdays = (weekdays | weekdays >> 7) & 0x7f;
+        let schedule = Schedule {
+            minutes,
+            hours: hours as u32,
+            days: days as u32,
+            months: months as u16,
+            weekdays: weekdays as u8,
+            any_day,
+            any_weekday,
+        };
+        if schedule.next_after(unix_now()).is_none() {
+            return Err(format!("cron表达式永远不会触发: {}", expression));
+        }
+        Ok(schedule)
+    }
+
+    fn day_matches(&self, days: i64) -> bool {
+        let (_, month, day) = civil_from_days(days);
+        if self.months & (1 << month) == 0 {
+            return false;
+        }
+        // 1970-01-01是周四
+        let weekday = (days + 4).rem_euclid(7);
+        let by_day = self.days & (1 << day) != 0;
+        let by_weekday = self.weekdays & (1 << weekday) != 0;
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => by_weekday,
+            (false, true) => by_day,
+            (false, false) => by_day || by_weekday,
+        }
+    }
+
+    /// `after`之后（不含）第一次触发的Unix时间，五年内都不会触发时返回`None`
+    pub fn next_after(&self, after: u64) -> Option<u64> {
+        let mut minute = (after / 60 + 1) as i64;
+        let limit = minute + 5 * 366 * 1440;
+        while minute < limit {
+            let days = minute.div_euclid(1440);
+            if !self.day_matches(days) {
+                minute = (days + 1) * 1440;
+                continue;
+            }
+            let hour = minute.rem_euclid(1440) / 60;
+            if self.hours & (1 << hour) == 0 {
+                minute = (minute / 60 + 1) * 60;
+                continue;
+            }
+            if self.minutes & (1 << minute.rem_euclid(60)) == 0 {
+                minute += 1;
+                continue;
+            }
+            return Some(minute as u64 * 60);
+        }
+        None
+    }
+}
+
+/// 内置的维护任务
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobKind {
+    SummarizeSessions,
+    CompactIndexes,
+    EvictCache,
+    RollupUsage,
+    ForgetMemories,
+}
+
+impl JobKind {
+    pub const ALL: [JobKind; 5] = [
+        JobKind::SummarizeSessions,
+        JobKind::CompactIndexes,
+        JobKind::EvictCache,
+        JobKind::RollupUsage,
+        JobKind::ForgetMemories,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            JobKind::SummarizeSessions => "summarize_sessions",
+            JobKind::CompactIndexes => "compact_indexes",
+            JobKind::EvictCache => "evict_cache",
+            JobKind::RollupUsage => "rollup_usage",
+            JobKind::ForgetMemories => "forget_memories",
+        }
+    }
+
+    /// 配置中没有写`schedule`时使用的表达式
+    fn default_schedule(self) -> &'static str {
+        match self {
+            JobKind::SummarizeSessions => "*/30 * * * *",
+            JobKind::CompactIndexes => "0 3 * * *",
+            JobKind::EvictCache => "*/10 * * * *",
+            JobKind::RollupUsage => "5 0 * * *",
+            JobKind::ForgetMemories => "15 4 * * *",
+        }
+    }
+
+    fn config(self, config: &JobsConfig) -> &JobConfig {
+        match self {
+            JobKind::SummarizeSessions => &config.summarize_sessions,
+            JobKind::CompactIndexes => &config.compact_indexes,
+            JobKind::EvictCache => &config.evict_cache,
+            JobKind::RollupUsage => &config.rollup_usage,
+            JobKind::ForgetMemories => &config.forget_memories,
+        }
+    }
+
+    /// 任务依赖的功能是否已启用
+    fn available(self, config: &Config) -> bool {
+        match self {
+            JobKind::SummarizeSessions => config.sessions.enabled,
+            JobKind::CompactIndexes => config.rag.store == StoreKind::Hnsw,
+            JobKind::EvictCache => config.cache.enabled,
+            JobKind::RollupUsage => config.metering.enabled,
+            JobKind::ForgetMemories => config.memory.enabled,
+        }
+    }
+
+    /// 运行一次，成功时返回结果说明
+    async fn run(self, state: &AppState) -> Result<String, String> {
+        let config = &state.config().jobs;
+        match self {
+            JobKind::SummarizeSessions => summarize_sessions(state, config).await,
+            JobKind::CompactIndexes => {
+                let names = state.indexes.compact(config.compact_threshold).map_err(|e| e.to_string())?;
+                if names.is_empty() {
+                    Ok("没有需要整理的索引".to_string())
+                } else {
+                    Ok(format!("整理了 {} 个索引: {}", names.len(), names.join(", ")))
+                }
+            }
+            JobKind::EvictCache => {
+                let cache = state.cache.as_ref().ok_or("未启用回复缓存")?;
+                Ok(format!("清理了 {} 条过期的缓存", cache.evict_expired().await?))
+            }
+            JobKind::RollupUsage => {
+                let meter = Arc::clone(state.metering.as_ref().ok_or("未启用用量计量")?);
+                let rows = tokio::task::spawn_blocking(move || meter.rollup())
+                    .await
+                    .map_err(|e| format!("汇总月度用量失败: {}", e))??;
+                Ok(format!("写入了 {} 行月度用量", rows))
+            }
+            JobKind::ForgetMemories => {
+                let memory = state.memory.as_ref().ok_or("未启用长期记忆")?;
+                Ok(format!("删除了 {} 条衰减的记忆", memory.forget()?))
+            }
+        }
+    }
+}
+
+/// 运行结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobOutcome {
+    Succeeded,
+    Failed,
+}
+
+/// 任务的状态，`/admin/jobs`返回的内容
+#[derive(Debug, Clone, Serialize)]
+pub struct JobStatus {
+    pub name: &'static str,
+    pub schedule: String,
+    pub retries: u32,
+    pub running: bool,
+    /// 下一次按计划运行的时间
+    pub next_run_at: Option<u64>,
+    pub last_started_at: Option<u64>,
+    pub last_finished_at: Option<u64>,
+    pub last_outcome: Option<JobOutcome>,
+    /// 最近一次成功运行的结果说明
+    pub last_message: Option<String>,
+    /// 最近一次失败的原因
+    pub last_error: Option<String>,
+    /// 最近一次运行已经尝试的次数，包括重试
+    pub attempts: u32,
+    pub runs: u64,
+    pub failures: u64,
+    /// 连续失败的运行次数，成功后清零
+    pub consecutive_failures: u32,
+}
+
+#[derive(Debug)]
+struct Job {
+    kind: JobKind,
+    schedule: Schedule,
+    config: JobConfig,
+    status: Mutex<JobStatus>,
+}
+
+impl Job {
+    /// 标记为运行中，已经在运行时返回`false`
+    fn begin(&self) -> bool {
+        let mut status = self.status.lock().unwrap();
+        if status.running {
+            return false;
+        }
+        status.running = true;
+        status.last_started_at = Some(unix_now());
+        status.attempts = 0;
+        true
+    }
+
+    fn finish(&self, result: Result<String, String>) {
+        let mut status = self.status.lock().unwrap();
+        status.running = false;
+        status.last_finished_at = Some(unix_now());
+        status.runs += 1;
+        match result {
+            Ok(message) => {
+                status.last_outcome = Some(JobOutcome::Succeeded);
+                status.last_message = Some(message);
+                status.consecutive_failures = 0;
+            }
+            Err(err) => {
+                eprintln!("⚠️ 定时任务 {} 失败: {}", self.kind.name(), err);
+                status.last_outcome = Some(JobOutcome::Failed);
+                status.last_error = Some(err);
+                status.failures += 1;
+                status.consecutive_failures += 1;
+            }
+        }
+    }
+
+    /// 运行一次，失败时按配置重试；调用前需要[`Job::begin`]成功
+    async fn run(&self, state: &AppState) {
+        // 退出时等本次运行结束
+        let _hold = state.shutdown.hold();
+        let mut attempt = 0;
+        let result = loop {
+            attempt += 1;
+            self.status.lock().unwrap().attempts = attempt;
+            match self.kind.run(state).await {
+                Ok(message) => break Ok(message),
+                Err(err) if attempt > self.config.retries => break Err(err),
+                Err(err) => {
+                    let shift = (attempt - 1).min(MAX_BACKOFF_SHIFT);
+                    let delay = self.config.retry_delay_seconds.saturating_mul(1 << shift);
+                    eprintln!("⚠️ 定时任务 {} 第 {} 次运行失败，{} 秒后重试: {}", self.kind.name(), attempt, delay, err);
+                    tokio::time::sleep(Duration::from_secs(delay)).await;
+                }
+            }
+        };
+        self.finish(result);
+    }
+}
+
+/// 按计划运行一个任务，服务状态释放后退出
+async fn schedule_loop(job: Arc<Job>, state: Weak<AppState>) {
+    loop {
+        let Some(next) = job.schedule.next_after(unix_now()) else {
+            return;
+        };
+        job.status.lock().unwrap().next_run_at = Some(next);
+        // 系统时间可能被调整，醒来后没到点就继续等
+        while unix_now() < next {
+            tokio::time::sleep(Duration::from_secs(next - unix_now())).await;
+        }
+        let Some(state) = state.upgrade() else {
+            return;
+        };
+        if state.shutdown.is_draining() {
+            return;
+        }
+        if job.begin() {
+            job.run(&state).await;
+        }
+    }
+}
+
+/// 定时任务调度器
+#[derive(Debug)]
+pub struct Scheduler {
+    jobs: Vec<Arc<Job>>,
+}
+
+impl Scheduler {
+    /// 解析各任务的表达式，只注册已启用且依赖的功能也已启用的任务
+    pub fn new(config: &Config) -> Result<Scheduler, String> {
+        let mut jobs = Vec::new();
+        for kind in JobKind::ALL {
+            let job_config = kind.config(&config.jobs);
+            if !job_config.enabled || !kind.available(config) {
+                continue;
+            }
+            let expression = job_config.schedule.as_deref().unwrap_or(kind.default_schedule());
+            let schedule = Schedule::parse(expression).map_err(|e| format!("定时任务 {}: {}", kind.name(), e))?;
+            let status = JobStatus {
+                name: kind.name(),
+                schedule: expression.to_string(),
+                retries: job_config.retries,
+                running: false,
+                next_run_at: None,
+                last_started_at: None,
+                last_finished_at: None,
+                last_outcome: None,
+                last_message: None,
+                last_error: None,
+                attempts: 0,
+                runs: 0,
+                failures: 0,
+                consecutive_failures: 0,
+            };
+            jobs.push(Arc::new(Job {
+                kind,
+                schedule,
+                config: job_config.clone(),
+                status: Mutex::new(status),
+            }));
+        }
+        Ok(Scheduler { jobs })
+    }
+
+    /// 在后台按计划运行各任务，需要在Tokio运行时中调用
+    pub fn start(&self, state: &Arc<AppState>) {
+        for job in &self.jobs {
+            println!("⏰ 定时任务 {}: {}", job.kind.name(), job.status.lock().unwrap().schedule);
+            tokio::spawn(schedule_loop(Arc::clone(job), Arc::downgrade(state)));
+        }
+    }
+
+    /// 各任务的状态
+    pub fn list(&self) -> Vec<JobStatus> {
+        self.jobs.iter().map(|job| job.status.lock().unwrap().clone()).collect()
+    }
+
+    /// 立即在后台运行一次任务，返回开始运行后的状态
+    pub fn trigger(&self, state: &Arc<AppState>, name: &str) -> ApiResult<JobStatus> {
+        let job = self
+            .jobs
+            .iter()
+            .find(|job| job.kind.name() == name)
+            .ok_or_else(|| ApiError::NotFound(format!("定时任务 {} 不存在或未启用", name)))?;
+        if !job.begin() {
+            return Err(ApiError::invalid_request(format!("定时任务 {} 正在运行", name)));
+        }
+        let status = job.status.lock().unwrap().clone();
+        let (job, state) = (Arc::clone(job), Arc::clone(state));
+        tokio::spawn(async move { job.run(&state).await });
+        Ok(status)
+    }
+}
+
+/// 为空闲的会话生成或更新摘要，部分会话失败时整体算作失败，已生成的摘要保留
+async fn summarize_sessions(state: &AppState, config: &JobsConfig) -> Result<String, String> {
+    let store = state.sessions.as_ref().ok_or("未启用会话存储")?;
+    let idle_before = unix_now().saturating_sub(config.summary_idle_seconds) as i64;
+    let tasks = store
+        .pending_summaries(config.summary_min_messages, idle_before, config.summary_batch)
+        .await
+        .map_err(|e| e.to_string())?;
+    let settings = state.config();
+    let model = settings.context.summary_model.as_deref().unwrap_or(&settings.llm.model_name);
+    let summarizer = UpstreamSummarizer::new(state.models(), model);
+    let (mut done, mut errors) = (0, Vec::new());
+    for task in tasks {
+        let id = task.session.id.clone();
+        match summarize_session(store, &summarizer, task, config.summary_max_tokens as usize).await {
+            Ok(()) => done += 1,
+            Err(err) => errors.push(format!("{}: {}", id, err)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(format!("生成了 {} 个会话的摘要", done))
+    } else {
+        Err(format!("{} 个会话生成摘要失败（成功 {} 个）: {}", errors.len(), done, errors.join("; ")))
+    }
+}
+
+/// 在已有摘要的基础上加入新增的消息重新摘要
+async fn summarize_session(
+    store: &SessionStore,
+    summarizer: &UpstreamSummarizer,
+    task: SummaryTask,
+    max_tokens: usize,
+) -> Result<(), String> {
+    let store = store.in_workspace(&task.workspace);
+    let messages = store.messages(&task.session.id).await.map_err(|e| e.to_string())?;
+    let mut text = messages
+        .iter()
+        .skip(task.summarized as usize)
+        .filter(|message| !message.content.trim().is_empty())
+        .map(|message| format!("{}: {}", message.role, message.content))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    if text.len() > MAX_TRANSCRIPT_BYTES {
+        let mut start = text.len() - MAX_TRANSCRIPT_BYTES;
+        while !text.is_char_boundary(start) {
+            start += 1;
+        }
+        text.drain(..start);
+    }
+    if let Some(summary) = &task.session.summary {
+        text = format!("之前的摘要: {}\n\n{}", summary, text);
+    }
+    let summary = summarizer.summarize(&text, max_tokens).await.map_err(|e| e.to_string())?;
+    store
+        .set_summary(&task.session.id, summary.trim().to_string(), messages.len() as u64)
+        .await
+        .map_err(|e| e.to_string())
+}
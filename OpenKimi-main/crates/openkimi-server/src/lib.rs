@@ -0,0 +1,621 @@
+//! OpenKimi的OpenAI兼容API服务
+//!
+//! 提供`/v1/chat/completions`（含SSE和WebSocket流式输出）、`/v1/models`和`/v1/embeddings`，
+//! 以及对应的gRPC接口，请求按模型名路由到配置中的上游后端或本服务启动的llama.cpp本地模型；`/v1/rag/ingest`把文档导入本地向量索引，
+//! `/v1/sessions`保存和检索对话记录，`/v1/prompts`管理带版本的提示词模板，`/v1/files`分块上传文件并导入索引，
+//! `/v1/audio`转写和合成语音，`/v1/search`搜索网页，`/v1/interpreter`在WebAssembly沙箱中运行代码，
+//! `/v1/mcp`通过MCP向编辑器等宿主提供服务端工具、提示词模板和文件，`/v1/agents`在服务端规划并执行多步任务，
+//! `/v1/batch`在后台以限定的并发处理成批的对话和嵌入请求；
+//! `/admin`管理密钥库中的上游密钥、用户和API令牌，`/healthz`和`/readyz`检查依赖是否可用。
+//! 可选地在入口处认证调用方、限流并记录审计日志，认证失败、密钥和令牌的变更等安全事件记入带哈希链的日志，
+//! 会话、索引和用量可以按工作区隔离，
+//! 并按工作区、用户和API令牌计量用量以便结算，用户和团队可以限定每日token数、每月费用和并发请求数；重复的请求可以直接返回缓存的回复，
+//! 带幂等键重试的请求返回第一次的响应，
+//! 模型发出的工具调用（包括内置的网页搜索、代码解释器、本机文件搜索、插件和外部MCP服务器提供的工具）可以由服务端执行，
+//! 要求结构化输出时按JSON Schema校验模型的回复，
+//! 消息中的图片按模型的限制检查、缩放和转换格式后再转发；从对话中提取的长期记忆在之后的请求中按相关度注入提示词；
+//! 会话摘要、索引整理等维护任务按计划在后台运行，监视的本地文件夹中的文件变化后自动导入索引；交给模型的文档和工具结果检查提示注入，回复按内容安全策略检查；
+//! 日志、保存的会话和发给上游的提示词中的个人信息可以脱敏；请求数、耗时、token数等指标以Prometheus格式导出，
+//! 请求经过的检索、上游调用等步骤以OpenTelemetry链路导出；会话、限流额度、缓存的回复和流式对话的事件可以保存在Redis中，
+//! 负载均衡之后的多个实例共享，流式对话断线后可以从任一实例续读；会话、用量和审计日志也可以保存在PostgreSQL中。
+//! 已有的OpenAI客户端只需把`base_url`指向本服务即可使用。
+
+pub mod admin;
+pub mod agents;
+pub mod audio;
+pub mod audit;
+pub mod auth;
+pub mod batch;
+pub mod cache;
+pub mod config;
+pub mod context;
+pub mod error;
+pub mod file_search;
+pub mod files;
+pub mod grpc;
+pub mod health;
+pub mod idempotency;
+pub mod image;
+pub mod indexer;
+pub mod interpreter;
+pub mod jobs;
+pub mod license;
+pub mod llama;
+pub mod mcp;
+pub mod mcp_client;
+pub mod memory;
+pub mod metering;
+pub mod metrics;
+pub mod migrations;
+pub mod model_store;
+pub mod pii;
+pub mod plugins;
+pub mod pool;
+pub mod probe;
+pub mod prompts;
+pub mod quotas;
+pub mod rag;
+pub mod ratelimit;
+pub mod redis;
+pub mod reload;
+pub mod router;
+pub mod routes;
+pub mod safety;
+pub mod schema;
+pub mod search;
+pub mod sessions;
+pub mod shutdown;
+pub mod speech;
+pub mod sse;
+pub mod streams;
+pub mod structured;
+pub mod telemetry;
+pub mod tools;
+pub mod trail;
+pub mod types;
+pub mod upstream;
+pub mod users;
+pub mod vault;
+pub mod vision;
+pub mod workspace;
+pub mod ws;
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use agents::Agents;
+use batch::Batches;
+use audit::AuditLog;
+use auth::Authenticator;
+use cache::ResponseCache;
+use config::Config;
+use context::{Budget, ContextManager};
+use error::{ApiError, ApiResult};
+use file_search::FileSearch;
+use files::FileStore;
+use idempotency::Idempotency;
+use indexer::Indexer;
+use interpreter::Interpreter;
+use jobs::Scheduler;
+use license::License;
+use llama::LocalModels;
+use mcp::McpSessions;
+use mcp_client::McpClients;
+use memory::MemoryStore;
+use metering::Meter;
+use metrics::Metrics;
+use pii::PiiDetector;
+use config::SessionStoreKind;
+use openkimi_postgres::Pool;
+use openkimi_sessions::SessionStore;
+use openkimi_tokenizer::Tokenizer;
+use plugins::PluginRegistry;
+use probe::Probe;
+use prompts::PromptStore;
+use quotas::Quotas;
+use safety::Safety;
+use types::{ChatCompletionRequest, ChatCompletionResponse, Model, ModelList, ModelPricing, Usage};
+use rag::Indexes;
+use search::WebSearch;
+use shutdown::Shutdown;
+use streams::Streams;
+use telemetry::Tracer;
+use tools::ToolRunner;
+use trail::AuditTrail;
+use ratelimit::RateLimiter;
+use redis::Redis;
+use reload::Live;
+use router::ModelRouter;
+use users::UserDirectory;
+use vault::KeyVault;
+use workspace::Workspaces;
+
+/// 各请求共享的服务状态
+#[derive(Debug)]
+pub struct AppState {
+    /// 当前生效的配置，重新加载时只替换可以热更新的部分，见[`reload`]
+    pub(crate) config: Live<Config>,
+    pub(crate) models: Live<ModelRouter>,
+    pub(crate) context: Live<ContextManager>,
+    /// 没有后端配置`gguf`时为`None`
+    pub local_models: Option<LocalModels>,
+    pub indexes: Indexes,
+    /// 没有启用的部分保存在Redis中时为`None`，见[`Config::uses_redis`]
+    pub redis: Option<Redis>,
+    /// 没有启用的部分保存在PostgreSQL中时为`None`，见[`Config::uses_postgres`]
+    pub postgres: Option<Pool>,
+    /// `sessions.enabled`为`false`时为`None`
+    pub sessions: Option<SessionStore>,
+    /// `vault.enabled`为`false`时为`None`
+    pub vault: Option<Arc<KeyVault>>,
+    /// `rate_limit.enabled`为`false`时为`None`
+    pub(crate) rate_limiter: Live<Option<RateLimiter>>,
+    /// `audit.enabled`为`false`时为`None`
+    pub audit: Option<Arc<AuditLog>>,
+    /// `audit_trail.enabled`为`false`时为`None`
+    pub trail: Option<Arc<AuditTrail>>,
+    /// 没有配置许可证时为`None`
+    pub license: Option<Arc<License>>,
+    /// `users.enabled`为`false`时为`None`
+    pub users: Option<Arc<UserDirectory>>,
+    /// `auth.enabled`为`false`时为`None`
+    pub(crate) auth: Live<Option<Authenticator>>,
+    /// `workspaces.enabled`为`false`时为`None`，所有请求都属于默认工作区
+    pub workspaces: Option<Workspaces>,
+    /// `metering.enabled`为`false`时为`None`
+    pub metering: Option<Arc<Meter>>,
+    /// `quotas.enabled`为`false`时为`None`
+    pub quotas: Option<Arc<Quotas>>,
+    /// `cache.enabled`为`false`时为`None`
+    pub cache: Option<ResponseCache>,
+    /// `idempotency.enabled`为`false`时为`None`
+    pub idempotency: Option<Idempotency>,
+    /// `tools.enabled`为`false`时为`None`
+    pub tools: Option<Arc<ToolRunner>>,
+    /// `prompts.enabled`为`false`时为`None`
+    pub prompts: Option<PromptStore>,
+    /// `files.enabled`为`false`时为`None`
+    pub files: Option<FileStore>,
+    /// `search.enabled`为`false`时为`None`
+    pub search: Option<Arc<WebSearch>>,
+    /// `interpreter.enabled`为`false`时为`None`
+    pub interpreter: Option<Arc<Interpreter>>,
+    /// `plugins.enabled`为`false`时为`None`
+    pub plugins: Option<Arc<PluginRegistry>>,
+    /// `mcp.enabled`为`false`时为`None`，stdio传输不需要它
+    pub mcp: Option<Arc<McpSessions>>,
+    /// `mcp.servers`为空时为`None`
+    pub mcp_clients: Option<Arc<McpClients>>,
+    /// `agents.enabled`为`false`时为`None`
+    pub agents: Option<Agents>,
+    /// `batch.enabled`为`false`时为`None`
+    pub batches: Option<Batches>,
+    /// `streams.enabled`为`false`时为`None`
+    pub streams: Option<Streams>,
+    /// `memory.enabled`为`false`时为`None`
+    pub memory: Option<MemoryStore>,
+    /// `jobs.enabled`为`false`时为`None`，由[`Scheduler::start`]开始运行
+    pub jobs: Option<Scheduler>,
+    /// `indexer.enabled`为`false`时为`None`，由[`Indexer::start`]开始运行
+    pub indexer: Option<Arc<Indexer>>,
+    /// `safety.enabled`为`false`时为`None`
+    pub safety: Option<Arc<Safety>>,
+    /// `pii.enabled`为`false`时为`None`
+    pub pii: Option<Arc<PiiDetector>>,
+    /// `metrics.enabled`为`false`时为`None`
+    pub metrics: Option<Arc<Metrics>>,
+    /// `tracing.enabled`为`false`时为`None`
+    pub tracer: Option<Arc<Tracer>>,
+    /// `/healthz`和`/readyz`的检查
+    pub probe: Probe,
+    /// 收到退出信号后进入排空状态
+    pub shutdown: Shutdown,
+}
+
+impl AppState {
+    pub fn new(config: Config) -> Result<AppState, String> {
+        let vault = if config.vault.enabled {
+            Some(Arc::new(KeyVault::open(&config.vault)?))
+        } else {
+            None
+        };
+        let models = Arc::new(ModelRouter::new(&config, vault.clone())?);
+        let context = ContextManager::from_config(&config.context, &models, &config.llm.model_name);
+        let local_models = LocalModels::start(&config)?;
+        let indexes = Indexes::new(&config.rag);
+        let redis = if config.uses_redis() {
+            Some(Redis::open(&config.redis)?)
+        } else {
+            None
+        };
+        let postgres = if config.uses_postgres() {
+            let timeout = Duration::from_secs(config.postgres.timeout_seconds.max(1));
+            // 同步客户端不能直接在异步线程中使用
+            let pool = tokio::task::block_in_place(|| {
+                Pool::open(&config.postgres.url, config.postgres.pool_size, timeout)
+            })
+            .map_err(|e| format!("连接PostgreSQL失败: {}", e))?;
+            Some(pool)
+        } else {
+            None
+        };
+        let sessions = match (config.sessions.enabled, config.sessions.store, &redis, &postgres) {
+            (false, ..) => None,
+            (true, SessionStoreKind::Redis, Some(redis), _) => {
+                Some(SessionStore::redis(redis.client().clone(), redis.prefix()))
+            }
+            (true, SessionStoreKind::Postgres, _, Some(pool)) => {
+                let store = tokio::task::block_in_place(|| SessionStore::postgres(pool.clone()))
+                    .map_err(|e| format!("打开会话数据库失败: {}", e))?;
+                Some(store)
+            }
+            (true, ..) => {
+                let path = &config.sessions.path;
+                let store = SessionStore::open(path)
+                    .map_err(|e| format!("打开会话数据库 {} 失败: {}", path.display(), e))?;
+                Some(store)
+            }
+        };
+        let rate_limiter = config
+            .rate_limit
+            .enabled
+            .then(|| RateLimiter::new(&config.rate_limit, redis.as_ref()));
+        let pii = if config.pii.enabled {
+            Some(Arc::new(PiiDetector::new(&config.pii)?))
+        } else {
+            None
+        };
+        let log_pii = pii.clone().filter(|_| config.pii.logs);
+        let audit = if config.audit.enabled {
+            Some(Arc::new(AuditLog::new(&config.audit, log_pii.clone(), postgres.as_ref())?))
+        } else {
+            None
+        };
+        let trail = if config.audit_trail.enabled {
+            Some(Arc::new(AuditTrail::open(&config.audit_trail)?))
+        } else {
+            None
+        };
+        let license = if config.license.is_configured() {
+            let license = License::load(&config.license)?;
+            let info = license.validation()?.info;
+            println!("🔑 许可证 {}：{}，{} 个席位", info.license_id, info.organization, info.seats);
+            Some(Arc::new(license))
+        } else {
+            None
+        };
+        let users = if config.users.enabled {
+            Some(Arc::new(UserDirectory::open(&config.users)?.with_license(license.clone())))
+        } else {
+            None
+        };
+        let auth = if config.auth.enabled {
+            Some(Authenticator::new(&config.auth, &config.workspaces, users.clone())?)
+        } else {
+            None
+        };
+        let workspaces = if config.workspaces.enabled {
+            Some(Workspaces::new(&config.workspaces)?)
+        } else {
+            None
+        };
+        let metering = if config.metering.enabled {
+            Some(Arc::new(Meter::open(&config.metering, postgres.as_ref())?))
+        } else {
+            None
+        };
+        let quotas = if config.quotas.enabled {
+            Some(Arc::new(Quotas::new(&config.quotas, &config.metering)?))
+        } else {
+            None
+        };
+        let cache = if config.cache.enabled {
+            Some(ResponseCache::new(&config.cache, config.llm.embedding_model.as_deref(), redis.as_ref())?)
+        } else {
+            None
+        };
+        let search = if config.search.enabled {
+            Some(Arc::new(WebSearch::new(&config.search)?))
+        } else {
+            None
+        };
+        let interpreter = if config.interpreter.enabled {
+            Some(Arc::new(Interpreter::new(&config.interpreter)?))
+        } else {
+            None
+        };
+        let plugins = if config.plugins.enabled {
+            let mut reserved: HashSet<String> = config.tools.functions.keys().cloned().collect();
+            reserved.extend([search::TOOL_NAME, interpreter::TOOL_NAME, file_search::TOOL_NAME].map(String::from));
+            Some(PluginRegistry::open(&config.plugins, reserved)?)
+        } else {
+            None
+        };
+        let mcp_clients = if config.mcp.servers.is_empty() {
+            None
+        } else {
+            Some(McpClients::start(&config.mcp.servers)?)
+        };
+        let safety = if config.safety.enabled {
+            Some(Arc::new(Safety::new(&config.safety, log_pii)?))
+        } else {
+            None
+        };
+        let tools = if config.tools.enabled {
+            let search = search.clone().filter(|_| config.search.tool);
+            let interpreter = interpreter.clone().filter(|_| config.interpreter.tool);
+            let files = if config.file_search.enabled {
+                Some(Arc::new(FileSearch::new(&config.file_search)?))
+            } else {
+                None
+            };
+            let runner = ToolRunner::new(
+                &config.tools,
+                search,
+                interpreter,
+                files,
+                plugins.clone(),
+                mcp_clients.clone(),
+                safety.clone(),
+            )?;
+            Some(Arc::new(runner))
+        } else {
+            None
+        };
+        let prompts = if config.prompts.enabled {
+            Some(PromptStore::open(&config.prompts)?)
+        } else {
+            None
+        };
+        let files = if config.files.enabled {
+            Some(FileStore::open(&config.files)?)
+        } else {
+            None
+        };
+        let mcp = config.mcp.enabled.then(|| Arc::new(McpSessions::new(&config.mcp)));
+        let agents = if config.agents.enabled {
+            Some(Agents::open(&config.agents)?)
+        } else {
+            None
+        };
+        let batches = if config.batch.enabled {
+            Some(Batches::open(&config.batch)?)
+        } else {
+            None
+        };
+        let idempotency = config.idempotency.enabled.then(|| Idempotency::new(&config.idempotency));
+        let streams = config.streams.enabled.then(|| Streams::new(&config.streams, redis.as_ref()));
+        let memory = if config.memory.enabled {
+            Some(MemoryStore::open(&config.memory, &config.llm)?)
+        } else {
+            None
+        };
+        let jobs = if config.jobs.enabled {
+            Some(Scheduler::new(&config)?)
+        } else {
+            None
+        };
+        let indexer = if config.indexer.enabled {
+            Some(Arc::new(Indexer::new(&config, workspaces.as_ref())?))
+        } else {
+            None
+        };
+        let metrics = if config.metrics.enabled {
+            Some(Arc::new(Metrics::new(&config.metrics)?))
+        } else {
+            None
+        };
+        let tracer = if config.tracing.enabled {
+            Some(Arc::new(Tracer::new(&config.tracing)?))
+        } else {
+            None
+        };
+        let probe = Probe::new(&config.health)?;
+        Ok(AppState {
+            config: Live::new(Arc::new(config)),
+            models: Live::new(models),
+            context: Live::new(Arc::new(context)),
+            local_models,
+            indexes,
+            redis,
+            postgres,
+            sessions,
+            vault,
+            rate_limiter: Live::new(Arc::new(rate_limiter)),
+            audit,
+            trail,
+            license,
+            users,
+            auth: Live::new(Arc::new(auth)),
+            workspaces,
+            metering,
+            quotas,
+            cache,
+            tools,
+            prompts,
+            files,
+            search,
+            interpreter,
+            plugins,
+            mcp,
+            mcp_clients,
+            agents,
+            batches,
+            idempotency,
+            streams,
+            memory,
+            jobs,
+            indexer,
+            safety,
+            pii,
+            metrics,
+            tracer,
+            probe,
+            shutdown: Shutdown::new(),
+        })
+    }
+
+    /// 当前生效的配置；重新加载后新的请求读到新配置，已经取得旧配置的请求不受影响
+    pub fn config(&self) -> Arc<Config> {
+        self.config.load()
+    }
+
+    pub fn models(&self) -> Arc<ModelRouter> {
+        self.models.load()
+    }
+
+    pub fn context(&self) -> Arc<ContextManager> {
+        self.context.load()
+    }
+
+    /// `rate_limit.enabled`为`false`时为`None`
+    pub fn rate_limiter(&self) -> Arc<Option<RateLimiter>> {
+        self.rate_limiter.load()
+    }
+
+    /// `auth.enabled`为`false`时为`None`
+    pub fn auth(&self) -> Arc<Option<Authenticator>> {
+        self.auth.load()
+    }
+
+    /// 本服务提供的模型：默认对话模型、可选的嵌入模型及`models`中的逻辑模型，
+    /// 带上下文窗口、接受的输入和`metering.prices`中的单价
+    pub fn model_list(&self) -> ModelList {
+        let config = self.config();
+        let mut names = vec![config.llm.model_name.clone()];
+        names.extend(config.llm.embedding_model.clone());
+        let models = self.models();
+        for name in models.model_names() {
+            if !names.iter().any(|existing| existing == name) {
+                names.push(name.to_string());
+            }
+        }
+        let data = names
+            .into_iter()
+            .map(|name| {
+                let embedding = config.llm.embedding_model.as_ref() == Some(&name);
+                let mut modalities = vec!["text".to_string()];
+                let vision = models.route(&name).vision.unwrap_or(&config.vision.limits);
+                if !embedding && config.vision.enabled && vision.supported {
+                    modalities.push("image".to_string());
+                }
+                Model {
+                    kind: Some(if embedding { "embedding" } else { "chat" }.to_string()),
+                    context_length: self.context_length(&name),
+                    modalities,
+                    pricing: config.metering.prices.get(&name).map(|price| ModelPricing {
+                        prompt: price.prompt,
+                        completion: price.completion,
+                        currency: config.metering.currency.clone(),
+                    }),
+                    ..Model::new(name)
+                }
+            })
+            .collect();
+        ModelList {
+            object: "list".to_string(),
+            data,
+        }
+    }
+
+    /// 模型的上下文窗口：依次使用`models`中的`context_length`、默认模型的`llm.context_length`，
+    /// 最后按实际模型查内置表
+    pub fn context_length(&self, model: &str) -> Option<u32> {
+        let models = self.models();
+        let route = models.route(model);
+        if let Some(length) = route.context_length {
+            return Some(length);
+        }
+        if model == self.config().llm.model_name {
+            if let Some(length) = self.config().llm.context_length {
+                return Some(length);
+            }
+        }
+        openkimi_tokenizer::context_window(route.model)
+    }
+
+    /// 校验对话请求并补全默认模型，返回提示词的token数
+    ///
+    /// 提示词加上`max_tokens`超出上下文窗口时按`context.strategy`压缩较早的消息，
+    /// 未启用压缩或压缩后仍放不下时直接拒绝，不再白白请求上游。
+    /// 分词器只是近似时（如Moonshot模型）放宽10%，避免误拒。
+    pub async fn prepare_chat(&self, request: &mut ChatCompletionRequest) -> ApiResult<u32> {
+        if request.messages.is_empty() {
+            return Err(ApiError::invalid_request("messages 不能为空"));
+        }
+        if request.model.is_empty() {
+            request.model = self.config().llm.model_name.clone();
+        }
+        tools::validate(request, self.tools.as_deref())?;
+        structured::output_format(request)?;
+
+        let tokenizer = Tokenizer::for_model(self.models().route(&request.model).model);
+        let mut prompt_tokens = context::count_messages(&tokenizer, &request.messages);
+
+        if let Some(window) = self.context_length(&request.model) {
+            let allowance = if tokenizer.is_exact() {
+                window as usize
+            } else {
+                window as usize * 10 / 9
+            };
+            let reply_tokens = request.max_tokens.unwrap_or(0) as usize;
+            if prompt_tokens + reply_tokens > allowance {
+                let context = self.context();
+                let Some(strategy) = context.strategy() else {
+                    return Err(ApiError::ContextLengthExceeded(format!(
+                        "模型 {} 的上下文窗口为 {} tokens，但请求需要 {} tokens（提示词 {}，max_tokens {}）",
+                        request.model,
+                        window,
+                        prompt_tokens + reply_tokens,
+                        prompt_tokens,
+                        reply_tokens
+                    )));
+                };
+
+                let reserve = request.max_tokens.unwrap_or(context.reserve_tokens()) as usize;
+                let budget = Budget {
+                    tokenizer: &tokenizer,
+                    max_tokens: allowance.saturating_sub(reserve),
+                };
+                let compressed = strategy.compress(&request.messages, &budget).await?;
+                let compressed_tokens = context::count_messages(&tokenizer, &compressed.messages);
+                println!(
+                    "🗜️ 上下文压缩({}): {} → {} tokens，{} 条消息被压缩，摘要 {} 层",
+                    strategy.name(),
+                    prompt_tokens,
+                    compressed_tokens,
+                    compressed.removed,
+                    compressed.levels
+                );
+                request.messages = compressed.messages;
+                prompt_tokens = compressed_tokens;
+            }
+        }
+
+        for message in &mut request.messages {
+            message.extra.remove(context::PINNED_FIELD);
+        }
+        Ok(prompt_tokens as u32)
+    }
+
+    /// 流式请求开始时还不知道回复的长度，按提示词加`max_tokens`（未指定时`context.reserve_tokens`）计算
+    pub fn stream_tokens(&self, request: &ChatCompletionRequest, prompt_tokens: u32) -> u32 {
+        prompt_tokens + request.max_tokens.unwrap_or(self.context().reserve_tokens())
+    }
+
+    /// 上游未返回用量时按本地分词器估算
+    pub fn usage(&self, model: &str, prompt_tokens: u32, response: &ChatCompletionResponse) -> Usage {
+        let tokenizer = Tokenizer::for_model(self.models().route(model).model);
+        let completion_tokens = response
+            .choices
+            .iter()
+            .map(|choice| tokenizer.count(&choice.message.text()) as u32)
+            .sum();
+        Usage {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! 企业版许可证
+//!
+//! 配置了`license.key`或`license.key_file`时，启动时用内嵌公钥（或`license.public_key`）校验许可证，
+//! 签名无效、尚未生效或超过宽限期都拒绝启动，宽限期内只打印警告。
+//! 许可证的席位数限制用户目录中启用的用户数，创建用户或重新启用用户时超出席位会被拒绝；
+//! 运行期间过期并超过宽限期后不再允许新增席位，已有用户不受影响。
+
+use std::fs;
+
+use openkimi_licensing::{LicenseStatus, LicenseVerifier, Validation};
+
+use crate::config::LicenseConfig;
+
+/// 已校验的许可证
+pub struct License {
+    verifier: LicenseVerifier,
+    key: String,
+}
+
+impl std::fmt::Debug for License {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("License").finish_non_exhaustive()
+    }
+}
+
+impl License {
+    /// 读取并校验许可证，不可用时返回错误，宽限期内打印警告
+    pub fn load(config: &LicenseConfig) -> Result<License, String> {
+        let key = match (&config.key, &config.key_file) {
+            (Some(key), None) => key.trim().to_string(),
+            (None, Some(path)) => fs::read_to_string(path)
+                .map_err(|e| format!("读取许可证 {} 失败: {}", path.display(), e))?
+                .trim()
+                .to_string(),
+            (Some(_), Some(_)) => return Err("license.key 和 license.key_file 只能设置一个".to_string()),
+            (None, None) => return Err("没有配置许可证（license.key 或 license.key_file）".to_string()),
+        };
+        let verifier = match &config.public_key {
+            Some(public_key) => LicenseVerifier::new(public_key),
+            None => LicenseVerifier::embedded(),
+        }
+        .map_err(|e| format!("无法校验许可证: {}", e))?
+        .with_grace_period_days(config.grace_period_days);
+
+        let license = License { verifier, key };
+        let validation = license.validation()?;
+        let info = &validation.info;
+        match validation.status {
+            LicenseStatus::Valid { .. } => {}
+            LicenseStatus::Grace { days_left } => eprintln!(
+                "⚠️ 许可证 {} 已过期，宽限期剩余 {} 天，请尽快续期",
+                info.license_id, days_left
+            ),
+            LicenseStatus::NotYetValid => {
+                return Err(format!("许可证 {} 尚未生效，请检查系统时钟", info.license_id))
+            }
+            LicenseStatus::Expired => return Err(format!("许可证 {} 已过期且超过宽限期", info.license_id)),
+        }
+        Ok(license)
+    }
+
+    /// 按当前时间重新计算许可证状态
+    pub fn validation(&self) -> Result<Validation, String> {
+        self.verifier.validate(&self.key).map_err(|e| e.to_string())
+    }
+
+    /// 检查启用`active_seats`个用户是否在授权席位内
+    pub fn check_seats(&self, active_seats: u32) -> Result<(), String> {
+        let validation = self.validation()?;
+        if !validation.status.is_usable() {
+            return Err(format!("许可证 {} 已过期，不能再增加用户", validation.info.license_id));
+        }
+        if !validation.allows_seats(active_seats) {
+            return Err(format!(
+                "许可证 {} 只授权 {} 个席位，不能启用第 {} 个用户",
+                validation.info.license_id, validation.info.seats, active_seats
+            ));
+        }
+        Ok(())
+    }
+}
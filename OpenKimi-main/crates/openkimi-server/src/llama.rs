@@ -0,0 +1,454 @@
+//! 本地GGUF模型
+//!
+//! `llm`或`backends`中配置了`gguf`的后端由本服务启动llama.cpp的`llama-server`加载模型，再经它的OpenAI兼容接口
+//! 和其他后端一样由模型路由访问，整个链路不需要网络，桌面客户端连接本服务即可离线使用。
+//! 子进程在后台启动，模型加载完成之前的请求返回502，可以用降级链暂时改用其他模型；
+//! 子进程意外退出后每隔5秒重启，本服务退出时结束子进程。
+//!
+//! `/v1/system/devices`列出`llama-server --list-devices`报告的CUDA、Metal、Vulkan等设备和各本地模型的状态，
+//! 客户端可以为本地模型另选设备和卸载到GPU的层数，选择后子进程立即以新参数重启，重启本服务后恢复配置中的值。
+
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::extract::State;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::config::{BackendKind, Config, GgufConfig, DEFAULT_BACKEND};
+use crate::error::{ApiError, ApiResult};
+use crate::model_store::ModelStore;
+use crate::AppState;
+
+/// 子进程退出或启动失败后重试的间隔
+const RESTART_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 检查子进程是否退出、模型是否加载完成的间隔
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 只用CPU时的设备名，llama.cpp中对应`--device none`
+const CPU_DEVICE: &str = "cpu";
+
+/// 一个可以运行模型的设备
+#[derive(Debug, Clone, Serialize)]
+pub struct Device {
+    /// 配置`gguf.device`时使用的名称，如`CUDA0`
+    pub id: String,
+    /// `cuda`、`metal`、`vulkan`、`cpu`等
+    pub kind: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_mib: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub free_mib: Option<u64>,
+}
+
+impl Device {
+    fn cpu() -> Device {
+        Device {
+            id: CPU_DEVICE.to_string(),
+            kind: CPU_DEVICE.to_string(),
+            name: "CPU".to_string(),
+            memory_mib: None,
+            free_mib: None,
+        }
+    }
+}
+
+/// 解析`llama-server --list-devices`的输出，每行形如`CUDA0: NVIDIA GeForce RTX 4090 (24563 MiB, 23750 MiB free)`
+fn parse_devices(output: &str) -> Vec<Device> {
+    let lines = output.lines().skip_while(|line| !line.starts_with("Available devices"));
+    lines
+        .skip(1)
+        .filter_map(|line| {
+            let (id, rest) = line.trim().split_once(": ")?;
+            if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return None;
+            }
+            let (name, memory) = match rest.strip_suffix(" MiB free)").and_then(|rest| rest.rsplit_once(" (")) {
+                Some((name, memory)) => (name, memory.split_once(" MiB, ")),
+                None => (rest, None),
+            };
+            let memory = memory.and_then(|(total, free)| Some((total.parse().ok()?, free.parse().ok()?)));
+            Some(Device {
+                id: id.to_string(),
+                kind: id.trim_end_matches(|c: char| c.is_ascii_digit()).to_lowercase(),
+                name: name.to_string(),
+                memory_mib: memory.map(|(total, _)| total),
+                free_mib: memory.map(|(_, free)| free),
+            })
+        })
+        .collect()
+}
+
+/// 运行`llama-server --list-devices`列出设备，最后总是有CPU
+pub fn list_devices(server: &Path) -> Result<Vec<Device>, String> {
+    let output = Command::new(server)
+        .arg("--list-devices")
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("无法运行 {}: {}", server.display(), e))?;
+    // 较早的版本把设备列表打印在标准错误中
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    let mut devices = parse_devices(&text);
+    devices.push(Device::cpu());
+    Ok(devices)
+}
+
+/// 子进程的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LocalStatus {
+    /// 正在加载模型
+    Loading,
+    Ready,
+    /// 退出或启动失败，等待重启
+    Restarting,
+    Stopped,
+}
+
+/// 一个本地模型的当前设置和状态
+#[derive(Debug, Clone, Serialize)]
+pub struct LocalModelInfo {
+    pub backend: String,
+    pub model: String,
+    pub device: Option<String>,
+    pub gpu_layers: u32,
+    pub status: LocalStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// 一个后端的`llama-server`子进程
+#[derive(Debug)]
+struct LocalServer {
+    backend: String,
+    /// 客户端另选设备后与配置文件不同
+    config: Mutex<GgufConfig>,
+    child: Mutex<Option<Child>>,
+    status: Mutex<(LocalStatus, Option<String>)>,
+    /// 换设备后跳过重启前的等待
+    restart: Notify,
+}
+
+impl LocalServer {
+    fn set_status(&self, status: LocalStatus, error: Option<String>) {
+        *self.status.lock().unwrap() = (status, error);
+    }
+
+    fn info(&self) -> LocalModelInfo {
+        let config = self.config.lock().unwrap();
+        let (status, error) = self.status.lock().unwrap().clone();
+        LocalModelInfo {
+            backend: self.backend.clone(),
+            model: config.model.display().to_string(),
+            device: config.device.clone(),
+            gpu_layers: config.gpu_layers,
+            status,
+            error,
+        }
+    }
+
+    fn command(&self) -> Result<Command, String> {
+        let config = self.config.lock().unwrap().clone();
+        let mut command = Command::new(&config.server);
+        command
+            .arg("--model")
+            .arg(&config.model)
+            .args(["--host", "127.0.0.1"])
+            .args(["--port", &config.port.to_string()])
+            .args(["--ctx-size", &config.context_length.to_string()])
+            .args(["--n-gpu-layers", &config.gpu_layers.to_string()]);
+        match config.device.as_deref() {
+            Some(CPU_DEVICE) => {
+                command.args(["--device", "none"]);
+            }
+            Some(device) => {
+                command.args(["--device", device]);
+            }
+            None => {}
+        }
+        if let Some(threads) = config.threads {
+            command.args(["--threads", &threads.to_string()]);
+        }
+        command.args(&config.args).stdin(Stdio::null());
+        match &config.log {
+            Some(path) => {
+                let log = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| format!("打开日志文件 {} 失败: {}", path.display(), e))?;
+                let stderr = log.try_clone().map_err(|e| format!("打开日志文件 {} 失败: {}", path.display(), e))?;
+                command.stdout(log).stderr(stderr);
+            }
+            None => {
+                command.stdout(Stdio::null()).stderr(Stdio::null());
+            }
+        }
+        Ok(command)
+    }
+
+    fn spawn(&self) -> Result<(), String> {
+        let child = self
+            .command()?
+            .spawn()
+            .map_err(|e| format!("无法运行 {}: {}", self.config.lock().unwrap().server.display(), e))?;
+        *self.child.lock().unwrap() = Some(child);
+        self.set_status(LocalStatus::Loading, None);
+        Ok(())
+    }
+
+    fn kill(&self) {
+        if let Some(mut child) = self.child.lock().unwrap().take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+
+    /// 等待子进程退出，其间检查模型是否加载完成；返回退出状态，被结束时返回`None`
+    async fn watch(&self, http: &reqwest::Client, stopped: &AtomicBool) -> Option<ExitStatus> {
+        let (port, startup_timeout, model) = {
+            let config = self.config.lock().unwrap();
+            (config.port, config.startup_timeout_seconds, config.model.display().to_string())
+        };
+        let health = format!("http://127.0.0.1:{}/health", port);
+        let timeout = Duration::from_secs(startup_timeout);
+        let started = Instant::now();
+        let (mut ready, mut warned) = (false, false);
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            if stopped.load(Ordering::SeqCst) {
+                return None;
+            }
+            let exited = match self.child.lock().unwrap().as_mut().map(Child::try_wait) {
+                Some(Ok(None)) => None,
+                Some(Ok(Some(status))) => Some(Some(status)),
+                Some(Err(_)) | None => Some(None),
+            };
+            if let Some(status) = exited {
+                self.child.lock().unwrap().take();
+                return status;
+            }
+            if ready {
+                continue;
+            }
+            // 加载期间`/health`返回503
+            ready = http.get(&health).send().await.is_ok_and(|response| response.status().is_success());
+            if ready {
+                self.set_status(LocalStatus::Ready, None);
+                println!("🧠 本地模型 {} 已加载: {}", self.backend, model);
+            } else if !warned && started.elapsed() > timeout {
+                warned = true;
+                eprintln!(
+                    "⚠️ 本地模型 {} 在 {} 秒内没有加载完成，请检查模型文件和 gguf.log",
+                    self.backend, startup_timeout
+                );
+            }
+        }
+    }
+
+    /// 启动子进程并在它退出后重启，直到`stopped`
+    async fn supervise(self: Arc<Self>, stopped: Arc<AtomicBool>) {
+        let http = reqwest::Client::new();
+        while !stopped.load(Ordering::SeqCst) {
+            match self.spawn() {
+                Ok(()) if stopped.load(Ordering::SeqCst) => self.kill(),
+                Ok(()) => {
+                    if let Some(status) = self.watch(&http, &stopped).await {
+                        eprintln!(
+                            "⚠️ 本地模型 {} 的 llama-server 已退出（{}），{} 秒后重启",
+                            self.backend,
+                            status,
+                            RESTART_INTERVAL.as_secs()
+                        );
+                        self.set_status(LocalStatus::Restarting, Some(format!("llama-server 已退出（{}）", status)));
+                    }
+                }
+                Err(err) => {
+                    eprintln!("⚠️ 本地模型 {}: {}，{} 秒后重试", self.backend, err, RESTART_INTERVAL.as_secs());
+                    self.set_status(LocalStatus::Restarting, Some(err));
+                }
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(RESTART_INTERVAL) => {}
+                _ = self.restart.notified() => {}
+            }
+        }
+        self.set_status(LocalStatus::Stopped, None);
+    }
+
+    /// 换设备并立即以新参数重启
+    fn select(&self, device: Option<String>, gpu_layers: Option<u32>) {
+        {
+            let mut config = self.config.lock().unwrap();
+            config.device = device;
+            if let Some(gpu_layers) = gpu_layers {
+                config.gpu_layers = gpu_layers;
+            }
+        }
+        self.kill();
+        self.set_status(LocalStatus::Restarting, None);
+        self.restart.notify_one();
+    }
+}
+
+/// 由本服务管理的`llama-server`子进程
+#[derive(Debug)]
+pub struct LocalModels {
+    servers: Vec<Arc<LocalServer>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl LocalModels {
+    /// 检查配置并在后台启动各后端的`llama-server`，没有后端配置`gguf`时返回`None`
+    pub fn start(config: &Config) -> Result<Option<LocalModels>, String> {
+        let all = std::iter::once((DEFAULT_BACKEND, &config.llm.backend))
+            .chain(config.backends.iter().map(|(name, backend)| (name.as_str(), backend)));
+        let store = ModelStore::new(&config.model_store);
+        let mut servers = Vec::new();
+        let mut ports = HashSet::new();
+        for (name, backend) in all {
+            let Some(gguf) = &backend.gguf else { continue };
+            if backend.kind != BackendKind::Local {
+                return Err(format!("后端 {} 配置了 gguf，kind 必须为 local", name));
+            }
+            if backend.api_url.as_deref().is_some_and(|url| !url.is_empty()) {
+                return Err(format!("后端 {} 配置了 gguf，地址由 gguf.port 决定，不能再指定 api_url", name));
+            }
+            let Some(model) = store.locate(&gguf.model) else {
+                return Err(format!(
+                    "后端 {} 的模型文件 {} 不存在，也不在模型目录 {} 中",
+                    name,
+                    gguf.model.display(),
+                    store.dir().display()
+                ));
+            };
+            if !ports.insert(gguf.port) {
+                return Err(format!("后端 {} 的 gguf.port {} 与其他本地模型重复", name, gguf.port));
+            }
+            servers.push(Arc::new(LocalServer {
+                backend: name.to_string(),
+                config: Mutex::new(GgufConfig { model, ..gguf.clone() }),
+                child: Mutex::new(None),
+                status: Mutex::new((LocalStatus::Loading, None)),
+                restart: Notify::new(),
+            }));
+        }
+        if servers.is_empty() {
+            return Ok(None);
+        }
+        let runtime = tokio::runtime::Handle::try_current().map_err(|_| "启动本地模型需要 tokio 运行时".to_string())?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        for server in &servers {
+            runtime.spawn(Arc::clone(server).supervise(Arc::clone(&stopped)));
+        }
+        Ok(Some(LocalModels { servers, stopped }))
+    }
+
+    pub fn info(&self) -> Vec<LocalModelInfo> {
+        self.servers.iter().map(|server| server.info()).collect()
+    }
+
+    /// 列出设备时运行的`llama-server`，取第一个本地模型的
+    pub fn server(&self) -> std::path::PathBuf {
+        self.servers[0].config.lock().unwrap().server.clone()
+    }
+
+    /// 结束所有子进程，之后不再重启
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        for server in &self.servers {
+            server.kill();
+        }
+    }
+}
+
+impl Drop for LocalModels {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// `GET /v1/system/devices`的响应
+#[derive(Debug, Serialize)]
+pub struct DevicesResponse {
+    pub object: String,
+    /// 可以运行模型的设备，最后一项为CPU
+    pub data: Vec<Device>,
+    /// 配置了`gguf`的后端
+    pub models: Vec<LocalModelInfo>,
+    /// 无法运行`llama-server`时的原因，此时只列出CPU
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// `POST /v1/system/devices`请求
+#[derive(Debug, Clone, Deserialize)]
+pub struct SelectDevice {
+    /// 缺省为`default`即`llm`部分
+    #[serde(default)]
+    pub backend: Option<String>,
+    /// `data`中设备的`id`，`null`为由llama.cpp选择
+    pub device: Option<String>,
+    #[serde(default)]
+    pub gpu_layers: Option<u32>,
+}
+
+async fn devices(state: &AppState) -> (Vec<Device>, Option<String>) {
+    let server = match &state.local_models {
+        Some(local_models) => local_models.server(),
+        None => GgufConfig::default().server,
+    };
+    match tokio::task::spawn_blocking(move || list_devices(&server)).await {
+        Ok(Ok(devices)) => (devices, None),
+        Ok(Err(err)) => (vec![Device::cpu()], Some(err)),
+        Err(err) => (vec![Device::cpu()], Some(err.to_string())),
+    }
+}
+
+/// `GET /v1/system/devices`，没有配置本地模型时也可以查看设备
+pub async fn list_system_devices(State(state): State<Arc<AppState>>) -> Json<DevicesResponse> {
+    let (data, error) = devices(&state).await;
+    let models = state.local_models.as_ref().map(LocalModels::info).unwrap_or_default();
+    Json(DevicesResponse {
+        object: "list".to_string(),
+        data,
+        models,
+        error,
+    })
+}
+
+/// `POST /v1/system/devices`，为本地模型另选设备，返回重启中的状态
+pub async fn select_system_device(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<SelectDevice>,
+) -> ApiResult<Json<LocalModelInfo>> {
+    let local_models = state
+        .local_models
+        .as_ref()
+        .ok_or_else(|| ApiError::invalid_request("没有后端配置 gguf"))?;
+    let backend = request.backend.as_deref().unwrap_or(DEFAULT_BACKEND);
+    let server = local_models
+        .servers
+        .iter()
+        .find(|server| server.backend == backend)
+        .ok_or_else(|| ApiError::NotFound(format!("后端 {} 没有配置 gguf", backend)))?;
+    if let Some(device) = &request.device {
+        let (available, error) = devices(&state).await;
+        if error.is_none() && !available.iter().any(|available| &available.id == device) {
+            let ids: Vec<&str> = available.iter().map(|device| device.id.as_str()).collect();
+            return Err(ApiError::invalid_request(format!("设备 {} 不存在，可用的设备: {}", device, ids.join(", "))));
+        }
+    }
+    server.select(request.device, request.gpu_layers);
+    eprintln!("🔁 本地模型 {} 改用设备 {}", backend, server.info().device.as_deref().unwrap_or("自动"));
+    Ok(Json(server.info()))
+}
@@ -0,0 +1,865 @@
+//! openkimi-server命令行入口
+//!
+//! ```text
+//! openkimi-server [--host 127.0.0.1] [--port 8000] [--grpc-port 50051] [--config config.json]
+//! openkimi-server index <文件或目录>... [--index default] [--chunking recursive] [--workspace default] [--config config.json]
+//! openkimi-server mcp [--workspace default] [--config config.json]
+//! openkimi-server migrate [status|up|down] [--component sessions] [--to <版本>] [--config config.json]
+//! openkimi-server users <list|add|show|update|remove|issue|revoke|usage> [<用户名>] [<令牌ID>] [选项] [--config config.json]
+//! openkimi-server models <list|download|convert|quantize|remove> [<参数>]... [选项] [--config config.json]
+//! ```
+
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use openkimi_postgres::Pool;
+use openkimi_rag::{collect_files, ChunkConfig, ChunkStrategy, Document};
+use serde_json::json;
+use openkimi_server::config::{Config, DatabaseKind, DEFAULT_BACKEND};
+use openkimi_server::license::License;
+use openkimi_server::workspace::{validate_workspace_name, Workspace, DEFAULT_WORKSPACE};
+use openkimi_server::metering::{self, Dimension, Meter, UsageQuery};
+use openkimi_server::migrations::{self, Component};
+use openkimi_server::model_store::{ModelFile, ModelStore};
+use openkimi_server::trail::AuditTrail;
+use openkimi_server::users::{self as directory, NewToken, UserDirectory};
+use openkimi_server::{audit, grpc, mcp, rag, reload, routes, shutdown, AppState};
+
+/// 命令行选项，默认值与Python版`run_server.py`一致
+struct Options {
+    host: String,
+    port: u16,
+    /// 指定时在同一地址的该端口上同时提供gRPC接口
+    grpc_port: Option<u16>,
+    config: Option<PathBuf>,
+}
+
+enum Command {
+    Serve(Options),
+    Index(IndexOptions),
+    Mcp(McpOptions),
+    Migrate(MigrateOptions),
+    Users(UsersOptions),
+    Models(ModelsOptions),
+}
+
+/// `index`子命令的选项
+struct IndexOptions {
+    paths: Vec<PathBuf>,
+    index: Option<String>,
+    /// 替换配置中的分块策略，其余分块参数不变
+    chunking: Option<ChunkStrategy>,
+    workspace: Option<String>,
+    config: Option<PathBuf>,
+}
+
+/// `mcp`子命令的选项
+struct McpOptions {
+    workspace: Option<String>,
+    config: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MigrateAction {
+    /// 列出各部分的结构版本
+    Status,
+    Up,
+    Down,
+}
+
+/// `migrate`子命令的选项
+struct MigrateOptions {
+    action: MigrateAction,
+    /// 只迁移这一部分，缺省为配置中用到的全部
+    component: Option<String>,
+    /// 目标版本，升级时缺省为最新版本
+    to: Option<u32>,
+    config: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UsersAction {
+    List,
+    Add,
+    Show,
+    Update,
+    Remove,
+    /// 签发令牌
+    Issue,
+    /// 吊销令牌
+    Revoke,
+    /// 查看计量的用量
+    Usage,
+}
+
+/// `users`子命令的选项
+struct UsersOptions {
+    action: UsersAction,
+    /// 用户名和令牌ID
+    args: Vec<String>,
+    workspace: Option<String>,
+    /// 逗号分隔的权限
+    scopes: Option<Vec<String>>,
+    disabled: Option<bool>,
+    label: Option<String>,
+    expires_days: Option<u32>,
+    from: Option<String>,
+    to: Option<String>,
+    config: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ModelsAction {
+    List,
+    Download,
+    /// 把Hugging Face格式的模型转换为GGUF
+    Convert,
+    Quantize,
+    Remove,
+}
+
+/// `models`子命令的选项
+struct ModelsOptions {
+    action: ModelsAction,
+    /// 下载地址、模型目录、模型名或量化方式
+    args: Vec<String>,
+    /// 保存的文件名
+    name: Option<String>,
+    sha256: Option<String>,
+    /// 转换后的精度
+    outtype: String,
+    config: Option<PathBuf>,
+}
+
+fn print_usage() {
+    println!("用法: openkimi-server [--host <地址>] [--port <端口>] [--grpc-port <端口>] [--config <配置文件>]");
+    println!(
+        "      openkimi-server index <文件或目录>... [--index <索引名>] [--chunking <分块策略>] [--workspace <工作区>] \
+         [--config <配置文件>]"
+    );
+    println!("      openkimi-server mcp [--workspace <工作区>] [--config <配置文件>]");
+    println!("      openkimi-server migrate [status|up|down] [--component <部分>] [--to <版本>] [--config <配置文件>]");
+    println!("      openkimi-server users list|add|show|update|remove <用户名> [--workspace <工作区>] [--scopes <权限>,...]");
+    println!("                            [--disable|--enable] [--config <配置文件>]");
+    println!("      openkimi-server users issue <用户名> [--label <说明>] [--expires-days <天数>] [--config <配置文件>]");
+    println!("      openkimi-server users revoke <用户名> <令牌ID> [--config <配置文件>]");
+    println!("      openkimi-server users usage <用户名> [--from <YYYY-MM-DD>] [--to <YYYY-MM-DD>] [--config <配置文件>]");
+    println!("      openkimi-server models list [--config <配置文件>]");
+    println!("      openkimi-server models download <地址|hf:<用户>/<仓库>/<文件>> [--name <文件名>] [--sha256 <校验和>]");
+    println!("      openkimi-server models convert <Hugging Face模型目录> [--outtype f16|bf16|q8_0] [--name <文件名>]");
+    println!("      openkimi-server models quantize <模型> <量化方式> [--name <文件名>]");
+    println!("      openkimi-server models remove <模型> [--config <配置文件>]");
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        host: "127.0.0.1".to_string(),
+        port: 8000,
+        grpc_port: None,
+        config: None,
+    };
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--host" => options.host = iter.next().ok_or("--host 需要一个地址参数")?.clone(),
+            "--port" => {
+                let value = iter.next().ok_or("--port 需要一个端口参数")?;
+                options.port = value.parse().map_err(|_| format!("无效的端口: {}", value))?;
+            }
+            "--grpc-port" => {
+                let value = iter.next().ok_or("--grpc-port 需要一个端口参数")?;
+                options.grpc_port = Some(value.parse().map_err(|_| format!("无效的端口: {}", value))?);
+            }
+            "--config" | "-c" => {
+                let value = iter.next().ok_or("--config 需要一个文件参数")?;
+                options.config = Some(PathBuf::from(value));
+            }
+            other => return Err(format!("未知参数: {}", other)),
+        }
+    }
+    Ok(options)
+}
+
+fn parse_index_args(args: &[String]) -> Result<IndexOptions, String> {
+    let mut options = IndexOptions {
+        paths: Vec::new(),
+        index: None,
+        chunking: None,
+        workspace: None,
+        config: None,
+    };
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--index" => options.index = Some(iter.next().ok_or("--index 需要一个索引名")?.clone()),
+            "--chunking" => {
+                let value = iter.next().ok_or("--chunking 需要一个分块策略")?;
+                let strategy = serde_json::from_value(serde_json::Value::from(value.as_str())).map_err(|_| {
+                    format!("无效的分块策略: {}（可选 tokens、paragraph、sentence、recursive、code）", value)
+                })?;
+                options.chunking = Some(strategy);
+            }
+            "--workspace" => options.workspace = Some(iter.next().ok_or("--workspace 需要一个工作区名")?.clone()),
+            "--config" | "-c" => {
+                let value = iter.next().ok_or("--config 需要一个文件参数")?;
+                options.config = Some(PathBuf::from(value));
+            }
+            other if other.starts_with('-') => return Err(format!("未知参数: {}", other)),
+            path => options.paths.push(PathBuf::from(path)),
+        }
+    }
+    if options.paths.is_empty() {
+        return Err("index 需要至少一个文件或目录".to_string());
+    }
+    Ok(options)
+}
+
+fn parse_mcp_args(args: &[String]) -> Result<McpOptions, String> {
+    let mut options = McpOptions {
+        workspace: None,
+        config: None,
+    };
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--workspace" => options.workspace = Some(iter.next().ok_or("--workspace 需要一个工作区名")?.clone()),
+            "--config" | "-c" => {
+                let value = iter.next().ok_or("--config 需要一个文件参数")?;
+                options.config = Some(PathBuf::from(value));
+            }
+            other => return Err(format!("未知参数: {}", other)),
+        }
+    }
+    Ok(options)
+}
+
+fn parse_migrate_args(args: &[String]) -> Result<MigrateOptions, String> {
+    let mut options = MigrateOptions {
+        action: MigrateAction::Status,
+        component: None,
+        to: None,
+        config: None,
+    };
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "status" => options.action = MigrateAction::Status,
+            "up" => options.action = MigrateAction::Up,
+            "down" => options.action = MigrateAction::Down,
+            "--component" => options.component = Some(iter.next().ok_or("--component 需要一个部分名")?.clone()),
+            "--to" => {
+                let value = iter.next().ok_or("--to 需要一个版本号")?;
+                let version = value.trim_start_matches('v');
+                options.to = Some(version.parse().map_err(|_| format!("无效的版本号: {}", value))?);
+            }
+            "--config" | "-c" => {
+                let value = iter.next().ok_or("--config 需要一个文件参数")?;
+                options.config = Some(PathBuf::from(value));
+            }
+            other => return Err(format!("未知参数: {}", other)),
+        }
+    }
+    if options.action == MigrateAction::Down && options.to.is_none() {
+        return Err("migrate down 需要 --component 和 --to".to_string());
+    }
+    // 各部分的版本号互不相关
+    if options.to.is_some() && options.component.is_none() {
+        return Err("指定 --to 时需要用 --component 指定迁移哪一部分".to_string());
+    }
+    Ok(options)
+}
+
+fn parse_users_args(args: &[String]) -> Result<UsersOptions, String> {
+    let action = match args.first().map(String::as_str) {
+        Some("list") => UsersAction::List,
+        Some("add") => UsersAction::Add,
+        Some("show") => UsersAction::Show,
+        Some("update") => UsersAction::Update,
+        Some("remove") => UsersAction::Remove,
+        Some("issue") => UsersAction::Issue,
+        Some("revoke") => UsersAction::Revoke,
+        Some("usage") => UsersAction::Usage,
+        Some(other) => {
+            return Err(format!(
+                "未知的 users 操作: {}（可选 list、add、show、update、remove、issue、revoke、usage）",
+                other
+            ))
+        }
+        None => return Err("users 需要指定操作".to_string()),
+    };
+    let mut options = UsersOptions {
+        action,
+        args: Vec::new(),
+        workspace: None,
+        scopes: None,
+        disabled: None,
+        label: None,
+        expires_days: None,
+        from: None,
+        to: None,
+        config: None,
+    };
+
+    let mut iter = args[1..].iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--workspace" => options.workspace = Some(iter.next().ok_or("--workspace 需要一个工作区名")?.clone()),
+            "--scopes" => {
+                let value = iter.next().ok_or("--scopes 需要逗号分隔的权限")?;
+                let scopes = value.split(',').map(str::trim).filter(|scope| !scope.is_empty());
+                options.scopes = Some(scopes.map(str::to_string).collect());
+            }
+            "--disable" => options.disabled = Some(true),
+            "--enable" => options.disabled = Some(false),
+            "--label" => options.label = Some(iter.next().ok_or("--label 需要一个说明")?.clone()),
+            "--expires-days" => {
+                let value = iter.next().ok_or("--expires-days 需要一个天数")?;
+                options.expires_days = Some(value.parse().map_err(|_| format!("无效的天数: {}", value))?);
+            }
+            "--from" => options.from = Some(iter.next().ok_or("--from 需要一个日期")?.clone()),
+            "--to" => options.to = Some(iter.next().ok_or("--to 需要一个日期")?.clone()),
+            "--config" | "-c" => {
+                let value = iter.next().ok_or("--config 需要一个文件参数")?;
+                options.config = Some(PathBuf::from(value));
+            }
+            other if other.starts_with('-') => return Err(format!("未知参数: {}", other)),
+            value => options.args.push(value.to_string()),
+        }
+    }
+    let expected = match options.action {
+        UsersAction::List => 0,
+        UsersAction::Revoke => 2,
+        _ => 1,
+    };
+    if options.args.len() != expected {
+        let usage = match options.action {
+            UsersAction::List => "users list 不需要其他参数",
+            UsersAction::Revoke => "users revoke 需要用户名和令牌ID",
+            _ => "需要一个用户名",
+        };
+        return Err(usage.to_string());
+    }
+    Ok(options)
+}
+
+fn parse_models_args(args: &[String]) -> Result<ModelsOptions, String> {
+    let action = match args.first().map(String::as_str) {
+        Some("list") => ModelsAction::List,
+        Some("download") => ModelsAction::Download,
+        Some("convert") => ModelsAction::Convert,
+        Some("quantize") => ModelsAction::Quantize,
+        Some("remove") => ModelsAction::Remove,
+        Some(other) => {
+            return Err(format!("未知的 models 操作: {}（可选 list、download、convert、quantize、remove）", other))
+        }
+        None => return Err("models 需要指定操作".to_string()),
+    };
+    let mut options = ModelsOptions {
+        action,
+        args: Vec::new(),
+        name: None,
+        sha256: None,
+        outtype: "f16".to_string(),
+        config: None,
+    };
+
+    let mut iter = args[1..].iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--name" => options.name = Some(iter.next().ok_or("--name 需要一个文件名")?.clone()),
+            "--sha256" => options.sha256 = Some(iter.next().ok_or("--sha256 需要一个校验和")?.clone()),
+            "--outtype" => options.outtype = iter.next().ok_or("--outtype 需要一个精度")?.clone(),
+            "--config" | "-c" => {
+                let value = iter.next().ok_or("--config 需要一个文件参数")?;
+                options.config = Some(PathBuf::from(value));
+            }
+            other if other.starts_with('-') => return Err(format!("未知参数: {}", other)),
+            value => options.args.push(value.to_string()),
+        }
+    }
+    let (expected, usage) = match options.action {
+        ModelsAction::List => (0, "models list 不需要其他参数"),
+        ModelsAction::Download => (1, "models download 需要一个下载地址"),
+        ModelsAction::Convert => (1, "models convert 需要一个 Hugging Face 模型目录"),
+        ModelsAction::Quantize => (2, "models quantize 需要模型名和量化方式，如 Q4_K_M"),
+        ModelsAction::Remove => (1, "models remove 需要一个模型名"),
+    };
+    if options.args.len() != expected {
+        return Err(usage.to_string());
+    }
+    Ok(options)
+}
+
+/// 命令行指定的工作区，必须是配置中已有的工作区
+fn workspace(state: &AppState, name: Option<String>) -> Result<Workspace, String> {
+    match name {
+        Some(name) => {
+            validate_workspace_name(&name)?;
+            if name != DEFAULT_WORKSPACE && !state.config().workspaces.list.contains_key(&name) {
+                return Err(format!("工作区 {} 不存在", name));
+            }
+            Ok(Workspace(name))
+        }
+        None => Ok(Workspace::default()),
+    }
+}
+
+/// 把本地文件导入向量索引，单个文件失败时继续处理其余文件
+async fn index(options: IndexOptions) -> Result<(), String> {
+    let config = Config::load(options.config.as_deref())?;
+    let state = AppState::new(config)?;
+    let index = options.index.unwrap_or_else(|| state.config().rag.default_index.clone());
+    let workspace = workspace(&state, options.workspace)?;
+    let files = collect_files(&options.paths).map_err(|e| e.to_string())?;
+    if files.is_empty() {
+        return Err("没有找到支持的文件（pdf、docx、html、md、txt及常见语言的源代码）".to_string());
+    }
+    let chunking = options.chunking.map(|strategy| ChunkConfig {
+        strategy,
+        ..state.config().rag.chunking(&index).clone()
+    });
+
+    let (mut imported, mut chunks, mut failed) = (0, 0, 0);
+    for file in &files {
+        let result = match Document::from_path(file) {
+            Ok(mut document) => {
+                document.chunking = chunking.clone();
+                rag::ingest_documents(&state, &workspace, &index, &[document])
+                    .await
+                    .map_err(|e| e.to_string())
+            }
+            Err(err) => Err(err.to_string()),
+        };
+        match result {
+            Ok(results) => {
+                let count: usize = results.iter().map(|result| result.chunks).sum();
+                println!("📄 {}: {} 块", file.display(), count);
+                imported += 1;
+                chunks += count;
+            }
+            Err(err) => {
+                eprintln!("⚠️ {}: {}", file.display(), err);
+                failed += 1;
+            }
+        }
+    }
+
+    println!(
+        "✅ 已导入 {} 个文件，共 {} 块到索引 {} ({})",
+        imported,
+        chunks,
+        index,
+        state.indexes.location(&workspace.index_name(&index))
+    );
+    if failed > 0 {
+        return Err(format!("{} 个文件导入失败", failed));
+    }
+    Ok(())
+}
+
+/// 以stdio传输提供MCP服务，供MCP宿主作为子进程启动
+async fn serve_mcp(options: McpOptions) -> Result<(), String> {
+    // 在创建服务状态之前接管标准输出，加载过程中的日志也不会混入协议消息
+    let output = mcp::protocol_output();
+    let config = Config::load(options.config.as_deref())?;
+    let state = AppState::new(config)?;
+    let workspace = workspace(&state, options.workspace)?;
+    eprintln!("🚀 OpenKimi MCP 服务已在标准输入输出上启动（工作区 {}）", workspace.name());
+    mcp::serve_stdio(Arc::new(state), workspace, output).await
+}
+
+/// 查看、升级或回退数据库结构，不启动服务
+fn migrate(options: MigrateOptions) -> Result<(), String> {
+    let config = Config::load(options.config.as_deref())?;
+    let mut components = migrations::components(&config);
+    if let Some(name) = &options.component {
+        let names: Vec<&str> = components.iter().map(|component| component.name).collect();
+        components.retain(|component| component.name == name);
+        if components.is_empty() {
+            return Err(format!("配置中没有使用数据库保存的 {}（可选: {}）", name, names.join(", ")));
+        }
+    }
+    if components.is_empty() {
+        println!("配置中没有使用SQLite或PostgreSQL保存的数据");
+        return Ok(());
+    }
+    let pool = if components.iter().any(Component::uses_postgres) {
+        let timeout = Duration::from_secs(config.postgres.timeout_seconds.max(1));
+        let pool = Pool::open(&config.postgres.url, config.postgres.pool_size, timeout)
+            .map_err(|e| format!("连接PostgreSQL失败: {}", e))?;
+        Some(pool)
+    } else {
+        None
+    };
+
+    for component in &components {
+        let status = component.status(pool.as_ref())?;
+        let location = component.location();
+        if options.action == MigrateAction::Status {
+            if status.is_newer() {
+                println!(
+                    "⚠️ {} ({}): v{}，比当前程序支持的 v{} 更新",
+                    status.component, location, status.version, status.latest
+                );
+            } else if status.pending() > 0 {
+                println!("⬆️ {} ({}): v{}，待升级到 v{}", status.component, location, status.version, status.latest);
+                let descriptions = component.descriptions();
+                for (version, description) in descriptions.iter().enumerate().skip(status.version as usize) {
+                    println!("    v{} {}", version + 1, description);
+                }
+            } else {
+                println!("✅ {} ({}): v{}，已是最新", status.component, location, status.version);
+            }
+            continue;
+        }
+
+        if status.is_newer() {
+            return Err(format!(
+                "{} 的结构 v{} 比当前程序支持的 v{} 更新，请使用新版本的程序迁移",
+                status.component, status.version, status.latest
+            ));
+        }
+        let target = options.to.unwrap_or(status.latest);
+        match options.action {
+            MigrateAction::Up if target < status.version => {
+                return Err(format!("{} 当前为 v{}，回退请使用 migrate down", status.component, status.version))
+            }
+            MigrateAction::Down if target > status.version => {
+                return Err(format!("{} 当前为 v{}，升级请使用 migrate up", status.component, status.version))
+            }
+            _ => {}
+        }
+        if target == status.version {
+            println!("✅ {} ({}): 已是 v{}", status.component, location, target);
+            continue;
+        }
+        component.migrate_to(pool.as_ref(), target)?;
+        println!("✅ {} ({}): v{} → v{}", status.component, location, status.version, target);
+    }
+    Ok(())
+}
+
+fn print_json(value: &impl serde::Serialize) -> Result<(), String> {
+    println!("{}", serde_json::to_string_pretty(value).map_err(|e| e.to_string())?);
+    Ok(())
+}
+
+/// 维护用户目录，直接读写`users.path`，服务运行中也立即生效
+fn manage_users(options: UsersOptions) -> Result<(), String> {
+    let config = Config::load(options.config.as_deref())?;
+    if !config.users.enabled {
+        return Err("未启用用户目录（users.enabled 为 false）".to_string());
+    }
+    let license = if config.license.is_configured() {
+        Some(Arc::new(License::load(&config.license)?))
+    } else {
+        None
+    };
+    let users = UserDirectory::open(&config.users)?.with_license(license);
+    let trail = if config.audit_trail.enabled {
+        Some(AuditTrail::open(&config.audit_trail)?)
+    } else {
+        None
+    };
+    // 与管理接口的事件相同，调用方记为命令行和当前的系统用户
+    let actor = match env::var("USER") {
+        Ok(user) if !user.is_empty() => format!("cli:{}", user),
+        _ => "cli".to_string(),
+    };
+    let record = |event: &str, fields: serde_json::Value| {
+        if let Some(trail) = &trail {
+            trail.append(event, Some(&actor), fields);
+        }
+    };
+    let name = options.args.first().map(String::as_str).unwrap_or_default();
+    let workspace = options
+        .workspace
+        .as_deref()
+        .map(|workspace| directory::resolve_workspace(&config.workspaces, workspace))
+        .transpose()?;
+    match options.action {
+        UsersAction::List => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+            for user in users.list().map_err(|e| e.to_string())? {
+                let active = user.tokens.iter().filter(|token| token.is_active(now)).count();
+                println!(
+                    "{}{}  工作区 {}  权限 {}  有效令牌 {}",
+                    user.name,
+                    if user.disabled { "（已停用）" } else { "" },
+                    user.workspace,
+                    user.scopes.join(","),
+                    active
+                );
+            }
+            Ok(())
+        }
+        UsersAction::Add => {
+            let user = users.create(name, workspace.flatten(), options.scopes).map_err(|e| e.to_string())?;
+            let request = json!({ "workspace": user.workspace, "scopes": user.scopes });
+            record("user.created", json!({ "resource": name, "request": request }));
+            print_json(&user)
+        }
+        UsersAction::Show => print_json(&users.get(name).map_err(|e| e.to_string())?),
+        UsersAction::Update => {
+            let request = json!({
+                "workspace": options.workspace,
+                "scopes": options.scopes,
+                "disabled": options.disabled,
+            });
+            let user = users.update(name, workspace, options.scopes, options.disabled).map_err(|e| e.to_string())?;
+            record("user.updated", json!({ "resource": name, "request": request }));
+            print_json(&user)
+        }
+        UsersAction::Remove => {
+            users.remove(name).map_err(|e| e.to_string())?;
+            record("user.deleted", json!({ "resource": name }));
+            println!("✅ 已删除用户 {} 及其全部令牌", name);
+            Ok(())
+        }
+        UsersAction::Issue => {
+            let request = NewToken {
+                label: options.label,
+                expires_in_days: options.expires_days,
+            };
+            let logged = json!({ "label": request.label, "expires_in_days": request.expires_in_days });
+            let issued = users.issue(name, request).map_err(|e| e.to_string())?;
+            record("token.issued", json!({ "user": name, "resource": issued.info.id, "request": logged }));
+            println!("✅ 已为 {} 签发令牌 {}，请妥善保存，之后无法再次查看:", name, issued.info.id);
+            println!("{}", issued.token);
+            Ok(())
+        }
+        UsersAction::Revoke => {
+            let token = users.revoke(name, &options.args[1]).map_err(|e| e.to_string())?;
+            record("token.revoked", json!({ "user": name, "resource": token.id }));
+            println!("✅ 已吊销 {} 的令牌 {}", name, token.id);
+            Ok(())
+        }
+        UsersAction::Usage => {
+            users.get(name).map_err(|e| e.to_string())?;
+            if !config.metering.enabled {
+                return Err("未启用用量计量（metering.enabled 为 false）".to_string());
+            }
+            let today = audit::today();
+            let from = options.from.unwrap_or_else(|| format!("{}-01", &today[..7]));
+            let to = options.to.unwrap_or(today);
+            if let Some(date) = [&from, &to].into_iter().find(|date| !metering::is_date(date)) {
+                return Err(format!("无效的日期: {}（格式为 YYYY-MM-DD）", date));
+            }
+            let pool = if config.metering.store == DatabaseKind::Postgres {
+                let timeout = Duration::from_secs(config.postgres.timeout_seconds.max(1));
+                let pool = Pool::open(&config.postgres.url, config.postgres.pool_size, timeout)
+                    .map_err(|e| format!("连接PostgreSQL失败: {}", e))?;
+                Some(pool)
+            } else {
+                None
+            };
+            let meter = Meter::open(&config.metering, pool.as_ref())?;
+            record("usage.exported", json!({ "user": name, "from": from, "to": to }));
+            let report = meter.report(UsageQuery {
+                from,
+                to,
+                group_by: vec![Dimension::Day, Dimension::ApiKey, Dimension::Model],
+                filters: vec![(Dimension::User, name.to_string())],
+            })?;
+            print!("{}", report.to_csv());
+            Ok(())
+        }
+    }
+}
+
+fn print_model(model: &ModelFile) {
+    let details: Vec<&str> = [&model.architecture, &model.parameters, &model.quantization]
+        .into_iter()
+        .filter_map(Option::as_deref)
+        .collect();
+    let context = model.context_length.map(|length| format!("  上下文 {}", length)).unwrap_or_default();
+    let mib = model.size_bytes as f64 / (1024.0 * 1024.0);
+    let size = if mib >= 1024.0 { format!("{:.1} GiB", mib / 1024.0) } else { format!("{:.1} MiB", mib) };
+    println!(
+        "{}  {}  {}{}",
+        model.id,
+        size,
+        if details.is_empty() { "未知格式".to_string() } else { details.join(" ") },
+        context
+    );
+}
+
+/// 管理`model_store.dir`中的本地模型，新模型在下次启动时可以用作`gguf.model`
+async fn manage_models(options: ModelsOptions) -> Result<(), String> {
+    let config = Config::load(options.config.as_deref())?;
+    let store = ModelStore::new(&config.model_store);
+    let name = options.name.as_deref();
+    match options.action {
+        ModelsAction::List => {
+            let models = store.list()?;
+            if models.is_empty() {
+                println!("模型目录 {} 中没有模型", store.dir().display());
+            }
+            models.iter().for_each(print_model);
+            Ok(())
+        }
+        ModelsAction::Download => {
+            let model = store.download(&options.args[0], name, options.sha256.as_deref()).await?;
+            println!("✅ 已下载 {}", model.path.display());
+            print_model(&model);
+            Ok(())
+        }
+        ModelsAction::Convert => {
+            let source = PathBuf::from(&options.args[0]);
+            let model = tokio::task::block_in_place(|| store.convert(&source, name, &options.outtype))?;
+            println!("✅ 已转换为 {}", model.path.display());
+            print_model(&model);
+            Ok(())
+        }
+        ModelsAction::Quantize => {
+            let model = tokio::task::block_in_place(|| store.quantize(&options.args[0], &options.args[1], name))?;
+            println!("✅ 已量化为 {}", model.path.display());
+            print_model(&model);
+            Ok(())
+        }
+        ModelsAction::Remove => {
+            let path = store.remove(&options.args[0])?;
+            println!("✅ 已删除 {}", path.display());
+            Ok(())
+        }
+    }
+}
+
+/// 是否只监听本机地址
+fn is_loopback(host: &str) -> bool {
+    host == "localhost" || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+async fn serve(options: Options) -> Result<(), String> {
+    let config = Config::load(options.config.as_deref())?;
+    let state = AppState::new(config)?;
+    println!(
+        "🔗 上游模型: {} ({})",
+        state.config().llm.model_name,
+        state.models().default_backend().upstream.base_url()
+    );
+    for backend in state.models().backends().filter(|backend| backend.name != DEFAULT_BACKEND) {
+        println!("🔗 后端 {}: {} ({})", backend.name, backend.upstream.base_url(), backend.kind.name());
+    }
+
+    match state.auth().as_ref() {
+        Some(_) => println!("🔒 已启用认证"),
+        None if !is_loopback(&options.host) => {
+            eprintln!("⚠️ 监听 {} 但未启用认证（auth.enabled），任何能访问该地址的人都可以调用上游模型", options.host)
+        }
+        None => {}
+    }
+
+    let listener = tokio::net::TcpListener::bind((options.host.as_str(), options.port))
+        .await
+        .map_err(|e| format!("监听 {}:{} 失败: {}", options.host, options.port, e))?;
+    println!("🚀 OpenKimi API 服务已启动: http://{}:{}", options.host, options.port);
+
+    let state = Arc::new(state);
+    if let Some(jobs) = &state.jobs {
+        jobs.start(&state);
+    }
+    if let Some(indexer) = &state.indexer {
+        indexer.start(&state);
+    }
+    shutdown::listen(&state);
+    reload::listen(&state, options.config.clone());
+    let http = async {
+        // 限流按IP区分客户端时需要连接的对端地址
+        let app = routes::router(Arc::clone(&state)).into_make_service_with_connect_info::<SocketAddr>();
+        let shutdown = state.shutdown.clone();
+        axum::serve(listener, app)
+            .with_graceful_shutdown(async move { shutdown.wait().await })
+            .await
+            .map_err(|e| format!("服务异常退出: {}", e))
+    };
+
+    let servers = async {
+        match options.grpc_port {
+            Some(port) => {
+                let addr: SocketAddr = format!("{}:{}", options.host, port)
+                    .parse()
+                    .map_err(|_| format!("无效的 gRPC 监听地址: {}:{}", options.host, port))?;
+                println!("🚀 gRPC 服务已启动: {}", addr);
+                tokio::try_join!(http, grpc::serve(Arc::clone(&state), addr)).map(|_| ())
+            }
+            None => http.await,
+        }?;
+        // HTTP和gRPC服务在连接都关闭后才返回，WebSocket连接和定时任务另外等待
+        state.shutdown.idle().await;
+        Ok(())
+    };
+    let drain_seconds = state.config().shutdown.drain_seconds;
+    let deadline = async {
+        state.shutdown.wait().await;
+        tokio::time::sleep(Duration::from_secs(drain_seconds)).await;
+    };
+    let result = tokio::select! {
+        result = servers => result,
+        () = deadline => {
+            eprintln!("⚠️ {} 秒内仍有请求没有完成，强制退出", drain_seconds);
+            Ok(())
+        }
+    };
+    shutdown::finish(&state).await;
+    result
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        print_usage();
+        return ExitCode::SUCCESS;
+    }
+
+    let command = match args.first().map(String::as_str) {
+        Some("index") => parse_index_args(&args[1..]).map(Command::Index),
+        Some("mcp") => parse_mcp_args(&args[1..]).map(Command::Mcp),
+        Some("migrate") => parse_migrate_args(&args[1..]).map(Command::Migrate),
+        Some("users") => parse_users_args(&args[1..]).map(Command::Users),
+        Some("models") => parse_models_args(&args[1..]).map(Command::Models),
+        _ => parse_args(&args).map(Command::Serve),
+    };
+    let command = match command {
+        Ok(command) => command,
+        Err(err) => {
+            eprintln!("❌ {}", err);
+            print_usage();
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            eprintln!("❌ 创建运行时失败: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let result = runtime.block_on(async {
+        match command {
+            Command::Serve(options) => serve(options).await,
+            Command::Index(options) => index(options).await,
+            Command::Mcp(options) => serve_mcp(options).await,
+            // PostgreSQL的同步客户端不能在异步线程中使用
+            Command::Migrate(options) => tokio::task::block_in_place(|| migrate(options)),
+            Command::Users(options) => tokio::task::block_in_place(|| manage_users(options)),
+            Command::Models(options) => manage_models(options).await,
+        }
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("❌ {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
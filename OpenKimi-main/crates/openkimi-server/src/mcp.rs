@@ -0,0 +1,568 @@
+//! MCP（Model Context Protocol）服务
+//!
+//! 把服务端工具、提示词模板和上传的文件通过MCP提供给编辑器、其他智能体等MCP宿主：
+//! 工具即`tools`中的服务端工具（包括内置工具和插件提供的工具），提示词即当前工作区的模板，
+//! 资源即当前工作区上传的文件，URI为`openkimi://files/<文件id>`。
+//!
+//! 消息为JSON-RPC 2.0，支持两种传输：
+//!
+//! - stdio：`openkimi-server mcp`从标准输入逐行读取消息，向标准输出逐行写响应，日志写到标准错误；
+//! - SSE：`GET /v1/mcp/sse`建立会话，第一个`endpoint`事件给出发送消息的地址`/v1/mcp/messages?session_id=...`，
+//!   客户端把消息POST到这个地址，响应作为`message`事件在SSE流中返回。与其他`/v1`接口一样认证并按工作区隔离。
+//!
+//! 同一连接中的代码解释器调用共享文件，连接断开10分钟后清理。
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::sync::{Arc, Mutex};
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures_util::future::join_all;
+use futures_util::stream::{self, Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+
+use crate::config::McpConfig;
+use crate::error::{ApiError, ApiResult};
+use crate::files::FileStore;
+use crate::interpreter;
+use crate::prompts::{PromptStore, VariableKind};
+use crate::sse::KEEP_ALIVE_INTERVAL;
+use crate::tools::{ToolRunner, ToolScope};
+use crate::workspace::Workspace;
+use crate::AppState;
+
+/// 支持的协议版本，按从新到旧排列；客户端请求的版本不在其中时使用最新的
+const PROTOCOL_VERSIONS: [&str; 3] = ["2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// 文件资源的URI前缀
+const FILE_URI: &str = "openkimi://files/";
+
+/// SSE会话中客户端POST消息的地址
+const MESSAGES_PATH: &str = "/v1/mcp/messages";
+
+/// 每个SSE会话中等待发送的响应数
+const CHANNEL_CAPACITY: usize = 32;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+/// MCP约定的资源不存在
+const RESOURCE_NOT_FOUND: i64 = -32002;
+
+/// JSON-RPC错误
+#[derive(Debug)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> RpcError {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<ApiError> for RpcError {
+    fn from(err: ApiError) -> RpcError {
+        let code = match err {
+            ApiError::InvalidRequest(_) | ApiError::NotFound(_) | ApiError::PayloadTooLarge(_) => INVALID_PARAMS,
+            _ => INTERNAL_ERROR,
+        };
+        RpcError::new(code, err.to_string())
+    }
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": err.code, "message": err.message } }),
+    }
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, format!("参数无效: {}", e)))
+}
+
+/// OpenAI格式的工具定义转为MCP的工具
+fn mcp_tool(definition: &Value) -> Value {
+    let function = &definition["function"];
+    let mut schema = function.get("parameters").cloned().filter(Value::is_object).unwrap_or_else(|| json!({}));
+    if let Some(schema) = schema.as_object_mut() {
+        schema.entry("type").or_insert_with(|| Value::from("object"));
+    }
+    let mut tool = json!({ "name": function["name"], "inputSchema": schema });
+    if let Some(description) = function.get("description") {
+        tool["description"] = description.clone();
+    }
+    tool
+}
+
+/// MCP的提示词参数都是字符串，按变量声明的类型解析
+fn argument_value(kind: Option<VariableKind>, value: Value) -> Value {
+    let Value::String(text) = value else {
+        return value;
+    };
+    match kind {
+        None | Some(VariableKind::String) => Value::String(text),
+        Some(_) => serde_json::from_str(&text).unwrap_or(Value::String(text)),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CallToolParams {
+    name: String,
+    #[serde(default)]
+    arguments: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GetPromptParams {
+    name: String,
+    #[serde(default)]
+    arguments: Map<String, Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReadResourceParams {
+    uri: String,
+}
+
+/// 一个MCP连接：stdio进程或一个SSE会话
+pub struct Connection {
+    state: Arc<AppState>,
+    /// 工作区决定可见的提示词和文件，同一连接中的代码解释器调用共享文件
+    scope: ToolScope,
+}
+
+impl Connection {
+    pub fn new(state: Arc<AppState>, workspace: Workspace) -> ApiResult<Connection> {
+        Ok(Connection {
+            state,
+            scope: ToolScope::new(workspace, None)?,
+        })
+    }
+
+    /// 处理一条消息或一批消息，返回要发给客户端的响应；只有通知时返回`None`
+    pub async fn handle(&self, text: &str) -> Option<Value> {
+        let message: Value = match serde_json::from_str(text) {
+            Ok(message) => message,
+            Err(e) => {
+                let err = RpcError::new(PARSE_ERROR, format!("消息不是有效的 JSON: {}", e));
+                return Some(response(Value::Null, Err(err)));
+            }
+        };
+        match message {
+            Value::Array(batch) if batch.is_empty() => {
+                Some(response(Value::Null, Err(RpcError::new(INVALID_REQUEST, "批量消息不能为空"))))
+            }
+            Value::Array(batch) => {
+                let replies: Vec<Value> = join_all(batch.into_iter().map(|message| self.dispatch(message)))
+                    .await
+                    .into_iter()
+                    .flatten()
+                    .collect();
+                (!replies.is_empty()).then_some(Value::Array(replies))
+            }
+            message => self.dispatch(message).await,
+        }
+    }
+
+    async fn dispatch(&self, mut message: Value) -> Option<Value> {
+        let id = message.get("id").cloned();
+        let method = message.get("method").and_then(Value::as_str).map(str::to_string);
+        let Some(method) = method.filter(|_| message["jsonrpc"] == "2.0") else {
+            // 客户端对服务端请求的响应；本服务不向客户端发请求，直接忽略
+            if message.get("result").is_some() || message.get("error").is_some() {
+                return None;
+            }
+            let err = RpcError::new(INVALID_REQUEST, "不是有效的 JSON-RPC 2.0 消息");
+            return Some(response(id.unwrap_or(Value::Null), Err(err)));
+        };
+        // 通知（如notifications/initialized、notifications/cancelled）不需要响应
+        let id = id?;
+        let params = message.get_mut("params").map(Value::take).unwrap_or_else(|| json!({}));
+        Some(response(id, self.call(&method, params).await))
+    }
+
+    async fn call(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "initialize" => Ok(self.initialize(&params)),
+            "ping" => Ok(json!({})),
+            "tools/list" => {
+                let tools: Vec<Value> = self.tools()?.definitions().iter().map(mcp_tool).collect();
+                Ok(json!({ "tools": tools }))
+            }
+            "tools/call" => self.call_tool(self::params(params)?).await,
+            "prompts/list" => self.list_prompts(),
+            "prompts/get" => self.get_prompt(self::params(params)?),
+            "resources/list" => self.list_resources(),
+            "resources/templates/list" => {
+                self.files()?;
+                let template = json!({
+                    "uriTemplate": format!("{}{{id}}", FILE_URI),
+                    "name": "file",
+                    "description": "上传的文件，id 为文件 id",
+                });
+                Ok(json!({ "resourceTemplates": [template] }))
+            }
+            "resources/read" => self.read_resource(self::params(params)?),
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("不支持的方法 {}", method))),
+        }
+    }
+
+    fn initialize(&self, params: &Value) -> Value {
+        let requested = params["protocolVersion"].as_str().unwrap_or_default();
+        let version = PROTOCOL_VERSIONS
+            .iter()
+            .find(|version| **version == requested)
+            .unwrap_or(&PROTOCOL_VERSIONS[0]);
+        let mut capabilities = Map::new();
+        if self.tools().is_ok() {
+            capabilities.insert("tools".to_string(), json!({}));
+        }
+        if self.prompts().is_ok() {
+            capabilities.insert("prompts".to_string(), json!({}));
+        }
+        if self.files().is_ok() {
+            capabilities.insert("resources".to_string(), json!({}));
+        }
+        json!({
+            "protocolVersion": version,
+            "capabilities": capabilities,
+            "serverInfo": { "name": "openkimi", "version": env!("CARGO_PKG_VERSION") },
+        })
+    }
+
+    fn tools(&self) -> Result<&ToolRunner, RpcError> {
+        self.state
+            .tools
+            .as_deref()
+            .filter(|_| self.state.config().mcp.tools)
+            .ok_or_else(|| RpcError::new(METHOD_NOT_FOUND, "未提供工具（需要启用 tools 和 mcp.tools）"))
+    }
+
+    fn prompts(&self) -> Result<&PromptStore, RpcError> {
+        self.state
+            .prompts
+            .as_ref()
+            .filter(|_| self.state.config().mcp.prompts)
+            .ok_or_else(|| RpcError::new(METHOD_NOT_FOUND, "未提供提示词（需要启用 prompts 和 mcp.prompts）"))
+    }
+
+    fn files(&self) -> Result<&FileStore, RpcError> {
+        self.state
+            .files
+            .as_ref()
+            .filter(|_| self.state.config().mcp.resources)
+            .ok_or_else(|| RpcError::new(METHOD_NOT_FOUND, "未提供资源（需要启用 files 和 mcp.resources）"))
+    }
+
+    /// 工具执行失败时以`isError`返回，交给模型处理
+    async fn call_tool(&self, params: CallToolParams) -> Result<Value, RpcError> {
+        let runner = self.tools()?;
+        if !runner.contains(&params.name) {
+            return Err(RpcError::new(INVALID_PARAMS, format!("未知的工具 {}", params.name)));
+        }
+        let arguments = params.arguments.unwrap_or_else(|| json!({}));
+        let (text, is_error) = match runner.execute(&params.name, &arguments, &self.scope).await {
+            Ok(output) => (output, false),
+            Err(message) => (message, true),
+        };
+        Ok(json!({ "content": [{ "type": "text", "text": text }], "isError": is_error }))
+    }
+
+    fn list_prompts(&self) -> Result<Value, RpcError> {
+        let prompts: Vec<Value> = self
+            .prompts()?
+            .list(&self.scope.workspace)
+            .iter()
+            .map(|template| {
+                let arguments: Vec<Value> = template
+                    .variables
+                    .iter()
+                    .map(|variable| {
+                        let required = variable.required && variable.default.is_none();
+                        let mut argument = json!({ "name": variable.name, "required": required });
+                        if let Some(description) = &variable.description {
+                            argument["description"] = Value::from(description.as_str());
+                        }
+                        argument
+                    })
+                    .collect();
+                let mut prompt = json!({ "name": template.name, "arguments": arguments });
+                if let Some(description) = &template.description {
+                    prompt["description"] = Value::from(description.as_str());
+                }
+                prompt
+            })
+            .collect();
+        Ok(json!({ "prompts": prompts }))
+    }
+
+    /// 渲染最新版本，结果作为一条用户消息
+    fn get_prompt(&self, params: GetPromptParams) -> Result<Value, RpcError> {
+        let store = self.prompts()?;
+        let workspace = &self.scope.workspace;
+        let template = store.get(workspace, &params.name, None)?;
+        let values: Map<String, Value> = params
+            .arguments
+            .into_iter()
+            .map(|(name, value)| {
+                let kind = template.variables.iter().find(|variable| variable.name == name).map(|v| v.kind);
+                (name, argument_value(kind, value))
+            })
+            .collect();
+        let (_, text) = store.render(workspace, &params.name, Some(template.version), &values)?;
+        let mut result = json!({
+            "messages": [{ "role": "user", "content": { "type": "text", "text": text } }],
+        });
+        if let Some(description) = template.description {
+            result["description"] = Value::from(description);
+        }
+        Ok(result)
+    }
+
+    fn list_resources(&self) -> Result<Value, RpcError> {
+        let resources: Vec<Value> = self
+            .files()?
+            .list(&self.scope.workspace, None)
+            .into_iter()
+            .map(|file| {
+                json!({
+                    "uri": format!("{}{}", FILE_URI, file.id),
+                    "name": file.filename,
+                    "mimeType": file.mime_type,
+                    "size": file.bytes,
+                })
+            })
+            .collect();
+        Ok(json!({ "resources": resources }))
+    }
+
+    /// UTF-8文本作为`text`返回，其余内容base64编码后作为`blob`返回
+    fn read_resource(&self, params: ReadResourceParams) -> Result<Value, RpcError> {
+        let store = self.files()?;
+        let workspace = &self.scope.workspace;
+        let not_found = || RpcError::new(RESOURCE_NOT_FOUND, format!("资源 {} 不存在", params.uri));
+        let id = params.uri.strip_prefix(FILE_URI).ok_or_else(not_found)?;
+        let file = store.file(workspace, id).map_err(|_| not_found())?;
+        let limit = self.state.config().mcp.max_resource_bytes;
+        if file.bytes > limit {
+            return Err(RpcError::new(
+                INVALID_PARAMS,
+                format!("文件 {} 超过 {} 字节，请通过 /v1/files/{}/content 下载", file.filename, limit, file.id),
+            ));
+        }
+        let (file, data) = store.read(workspace, id)?;
+        let content = match String::from_utf8(data) {
+            Ok(text) => json!({ "uri": params.uri, "mimeType": file.mime_type, "text": text }),
+            Err(err) => {
+                json!({ "uri": params.uri, "mimeType": file.mime_type, "blob": STANDARD.encode(err.as_bytes()) })
+            }
+        };
+        Ok(json!({ "contents": [content] }))
+    }
+}
+
+/// 取得进程原来的标准输出供stdio传输使用，此后写到标准输出的日志改为写到标准错误，不会混入协议消息
+pub fn protocol_output() -> Box<dyn Write + Send> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::FromRawFd;
+
+        // SAFETY: 复制得到的描述符只由返回的File持有
+        let fd = unsafe { libc::dup(libc::STDOUT_FILENO) };
+        if fd >= 0 {
+            if unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } >= 0 {
+                return Box::new(unsafe { std::fs::File::from_raw_fd(fd) });
+            }
+            unsafe { libc::close(fd) };
+        }
+    }
+    Box::new(std::io::stdout())
+}
+
+/// stdio传输：并发处理标准输入中的每行消息，标准输入关闭且进行中的请求完成后返回
+pub async fn serve_stdio(
+    state: Arc<AppState>,
+    workspace: Workspace,
+    output: Box<dyn Write + Send>,
+) -> Result<(), String> {
+    let connection = Arc::new(Connection::new(state, workspace).map_err(|e| e.to_string())?);
+    let (lines, mut incoming) = mpsc::channel::<String>(CHANNEL_CAPACITY);
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if lines.blocking_send(line).is_err() {
+                break;
+            }
+        }
+    });
+    let (replies, outgoing) = std::sync::mpsc::channel::<Value>();
+    let writer = std::thread::spawn(move || {
+        let mut output = output;
+        for reply in outgoing {
+            if writeln!(output, "{}", reply).and_then(|_| output.flush()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut tasks = JoinSet::new();
+    while let Some(line) = incoming.recv().await {
+        while tasks.try_join_next().is_some() {}
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (connection, replies) = (Arc::clone(&connection), replies.clone());
+        tasks.spawn(async move {
+            if let Some(reply) = connection.handle(&line).await {
+                let _ = replies.send(reply);
+            }
+        });
+    }
+    while tasks.join_next().await.is_some() {}
+    drop(replies);
+    tokio::task::spawn_blocking(move || writer.join())
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|_| "写入标准输出的线程异常退出".to_string())
+}
+
+/// SSE传输的一个会话
+struct Session {
+    workspace: Workspace,
+    connection: Arc<Connection>,
+    /// 响应经这里发往会话的SSE流
+    sender: mpsc::Sender<Value>,
+}
+
+/// SSE传输的会话表
+pub struct McpSessions {
+    config: McpConfig,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl fmt::Debug for McpSessions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sessions = self.sessions.lock().unwrap().len();
+        f.debug_struct("McpSessions").field("sessions", &sessions).finish()
+    }
+}
+
+impl McpSessions {
+    pub fn new(config: &McpConfig) -> McpSessions {
+        McpSessions {
+            config: config.clone(),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn insert(&self, id: String, session: Session) -> ApiResult<()> {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.len() >= self.config.max_sessions {
+            return Err(ApiError::RateLimited(format!(
+                "MCP 会话数已达上限 {}（mcp.max_sessions）",
+                self.config.max_sessions
+            )));
+        }
+        sessions.insert(id, session);
+        Ok(())
+    }
+
+    /// 其他工作区的会话视为不存在
+    fn get(&self, id: &str, workspace: &Workspace) -> ApiResult<(Arc<Connection>, mpsc::Sender<Value>)> {
+        let sessions = self.sessions.lock().unwrap();
+        match sessions.get(id) {
+            Some(session) if &session.workspace == workspace => {
+                Ok((Arc::clone(&session.connection), session.sender.clone()))
+            }
+            _ => Err(ApiError::NotFound(format!("MCP 会话 {} 不存在", id))),
+        }
+    }
+}
+
+/// SSE流结束（客户端断开）时移除会话
+struct SessionGuard {
+    sessions: Arc<McpSessions>,
+    id: String,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.sessions.sessions.lock().unwrap().remove(&self.id);
+    }
+}
+
+fn sessions(state: &AppState) -> ApiResult<Arc<McpSessions>> {
+    state
+        .mcp
+        .clone()
+        .ok_or_else(|| ApiError::invalid_request("MCP 服务未启用（mcp.enabled 为 false）"))
+}
+
+/// 建立SSE会话，先发送`endpoint`事件，之后每个响应一个`message`事件
+pub async fn sse(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+) -> ApiResult<Sse<impl Stream<Item = Result<Event, Infallible>>>> {
+    let sessions = sessions(&state)?;
+    let connection = Arc::new(Connection::new(Arc::clone(&state), workspace.clone())?);
+    let id = interpreter::random_hex().map_err(ApiError::Internal)?;
+    let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+    sessions.insert(
+        id.clone(),
+        Session {
+            workspace,
+            connection,
+            sender,
+        },
+    )?;
+    let endpoint = Event::default()
+        .event("endpoint")
+        .data(format!("{}?session_id={}", MESSAGES_PATH, id));
+    let guard = SessionGuard { sessions, id };
+    let messages = stream::unfold((receiver, guard), |(mut receiver, guard)| async move {
+        let message = receiver.recv().await?;
+        let event = Event::default().event("message").data(message.to_string());
+        Some((Ok(event), (receiver, guard)))
+    });
+    Ok(Sse::new(stream::once(async { Ok(endpoint) }).chain(messages))
+        .keep_alive(KeepAlive::new().interval(KEEP_ALIVE_INTERVAL)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MessageParams {
+    pub session_id: String,
+}
+
+/// 接收SSE会话中客户端发来的消息，响应在SSE流中返回
+pub async fn message(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Query(params): Query<MessageParams>,
+    body: String,
+) -> ApiResult<StatusCode> {
+    let (connection, sender) = sessions(&state)?.get(&params.session_id, &workspace)?;
+    tokio::spawn(async move {
+        if let Some(reply) = connection.handle(&body).await {
+            let _ = sender.send(reply).await;
+        }
+    });
+    Ok(StatusCode::ACCEPTED)
+}
@@ -0,0 +1,609 @@
+//! 外部MCP服务器的客户端
+//!
+//! `mcp.servers`中声明的服务器在启动时由后台任务连接：配置了`command`的作为子进程以stdio传输连接，
+//! 配置了`url`的连接其SSE端点。初始化后列出服务器的工具和资源，工具以`<服务器名>__<工具名>`的名称
+//! 作为服务端工具提供给模型，资源通过`<服务器名>__read_resource`工具读取。连接失败或断开后每隔30秒重试，
+//! 重连后重新列出工具，断开期间这个服务器的工具不提供给模型。
+//!
+//! `approval`为`prompt`的服务器的工具调用不由本服务直接执行，而是像客户端工具一样返回给客户端，
+//! 用户确认后客户端在下一个请求的`tool_approvals`中同意或拒绝，见[`crate::tools`]。
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::sync::{oneshot, Notify};
+use tokio::task::AbortHandle;
+
+use crate::config::{McpServerConfig, ToolApproval};
+use crate::sse::SseParser;
+use crate::tools;
+
+/// 服务器名和工具名之间的分隔符
+pub const SEPARATOR: &str = "__";
+
+/// 读取资源的工具名，前面加上服务器名和分隔符
+pub const READ_RESOURCE: &str = "read_resource";
+
+/// 连接失败或断开后重试的间隔
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 作为客户端请求的协议版本，SSE传输在这个版本中定义
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+/// 列出工具和资源时最多翻的页数
+const MAX_PAGES: usize = 100;
+
+/// 资源不超过这个数目时在读取工具的参数中列出全部URI
+const MAX_RESOURCE_ENUM: usize = 100;
+
+/// 发送消息的方式
+enum Outbound {
+    /// 写入子进程的标准输入，每行一条消息
+    Stdio(Mutex<ChildStdin>),
+    /// POST到SSE端点给出的地址
+    Http {
+        http: reqwest::Client,
+        endpoint: reqwest::Url,
+        headers: BTreeMap<String, String>,
+    },
+}
+
+/// 与一个服务器的一次连接
+struct Connection {
+    outbound: Outbound,
+    /// 等待响应的请求，按id索引
+    pending: Mutex<HashMap<u64, oneshot::Sender<Result<Value, String>>>>,
+    next_id: AtomicU64,
+    closed: AtomicBool,
+    /// 连接断开时通知后台任务重连
+    disconnected: Notify,
+    timeout: Duration,
+    /// stdio服务器的子进程，连接释放时结束
+    child: Mutex<Option<Child>>,
+    /// 读取SSE流的任务，连接释放时中止
+    reader: Mutex<Option<AbortHandle>>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.child.lock().unwrap().take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        if let Some(reader) = self.reader.lock().unwrap().take() {
+            reader.abort();
+        }
+    }
+}
+
+impl Connection {
+    fn new(outbound: Outbound, timeout_seconds: u64) -> Connection {
+        Connection {
+            outbound,
+            pending: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+            closed: AtomicBool::new(false),
+            disconnected: Notify::new(),
+            timeout: Duration::from_secs(timeout_seconds),
+            child: Mutex::new(None),
+            reader: Mutex::new(None),
+        }
+    }
+
+    fn write_line(stdin: &Mutex<ChildStdin>, message: &Value) -> Result<(), String> {
+        let mut stdin = stdin.lock().unwrap();
+        writeln!(stdin, "{}", message)
+            .and_then(|_| stdin.flush())
+            .map_err(|e| format!("写入子进程失败: {}", e))
+    }
+
+    async fn send(&self, message: &Value) -> Result<(), String> {
+        match &self.outbound {
+            Outbound::Stdio(stdin) => Self::write_line(stdin, message),
+            Outbound::Http {
+                http,
+                endpoint,
+                headers,
+            } => {
+                let mut request = http.post(endpoint.clone()).timeout(self.timeout).json(message);
+                for (name, value) in headers {
+                    request = request.header(name, value);
+                }
+                let response = request.send().await.map_err(|e| format!("发送消息失败: {}", e))?;
+                if !response.status().is_success() {
+                    return Err(format!("发送消息失败: {}", response.status()));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    async fn notify(&self, method: &str, params: Value) -> Result<(), String> {
+        self.send(&json!({ "jsonrpc": "2.0", "method": method, "params": params })).await
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<Value, String> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err("连接已断开".to_string());
+        }
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (sender, receiver) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, sender);
+        let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        if let Err(err) = self.send(&message).await {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(err);
+        }
+        match tokio::time::timeout(self.timeout, receiver).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err("连接已断开".to_string()),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&id);
+                let _ = self.notify("notifications/cancelled", json!({ "requestId": id, "reason": "超时" })).await;
+                Err(format!("{} 在 {} 秒内没有响应", method, self.timeout.as_secs()))
+            }
+        }
+    }
+
+    /// 处理服务器发来的一条消息，返回需要回复服务器的消息
+    fn receive(&self, message: Value) -> Option<Value> {
+        if let Some(method) = message.get("method").and_then(Value::as_str) {
+            // 服务器发来的请求只支持ping，通知（如列表变化）忽略
+            let id = message.get("id")?;
+            return Some(if method == "ping" {
+                json!({ "jsonrpc": "2.0", "id": id, "result": {} })
+            } else {
+                let error = json!({ "code": -32601, "message": format!("不支持的方法 {}", method) });
+                json!({ "jsonrpc": "2.0", "id": id, "error": error })
+            });
+        }
+        let id = message.get("id").and_then(Value::as_u64)?;
+        let sender = self.pending.lock().unwrap().remove(&id)?;
+        let result = match message.get("error") {
+            Some(error) => Err(error["message"].as_str().map_or_else(|| error.to_string(), str::to_string)),
+            None => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+        };
+        let _ = sender.send(result);
+        None
+    }
+
+    /// 标记为断开，正在等待的请求都以错误结束
+    fn close(&self) {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.pending.lock().unwrap().clear();
+        self.disconnected.notify_one();
+    }
+}
+
+/// 启动子进程，由一个线程逐行读取它的标准输出
+fn connect_stdio(config: &McpServerConfig) -> Result<Arc<Connection>, String> {
+    let mut child = Command::new(&config.command[0])
+        .args(&config.command[1..])
+        .envs(&config.env)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|e| format!("无法运行 {}: {}", config.command[0], e))?;
+    let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+        let _ = child.kill();
+        return Err("无法连接子进程的标准输入输出".to_string());
+    };
+    let connection = Arc::new(Connection::new(Outbound::Stdio(Mutex::new(stdin)), config.timeout_seconds));
+    *connection.child.lock().unwrap() = Some(child);
+
+    let weak: Weak<Connection> = Arc::downgrade(&connection);
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let Ok(line) = line else { break };
+            let Some(connection) = weak.upgrade() else { return };
+            let Ok(message) = serde_json::from_str::<Value>(&line) else {
+                continue;
+            };
+            if let Some(reply) = connection.receive(message) {
+                if let Outbound::Stdio(stdin) = &connection.outbound {
+                    let _ = Connection::write_line(stdin, &reply);
+                }
+            }
+        }
+        if let Some(connection) = weak.upgrade() {
+            connection.close();
+        }
+    });
+    Ok(connection)
+}
+
+/// 连接SSE端点，等到`endpoint`事件后由一个任务继续读取
+async fn connect_sse(config: &McpServerConfig, url: &str) -> Result<Arc<Connection>, String> {
+    let url = reqwest::Url::parse(url).map_err(|e| format!("无效的地址 {}: {}", url, e))?;
+    let http = reqwest::Client::builder()
+        .build()
+        .map_err(|e| format!("无法创建 HTTP 客户端: {}", e))?;
+    let mut request = http.get(url.clone()).header("accept", "text/event-stream");
+    for (name, value) in &config.headers {
+        request = request.header(name, value);
+    }
+    let response = request.send().await.map_err(|e| format!("连接失败: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!("连接失败: {}", response.status()));
+    }
+
+    let mut body = response.bytes_stream();
+    let mut parser = SseParser::default();
+    let timeout = Duration::from_secs(config.timeout_seconds);
+    let endpoint = tokio::time::timeout(timeout, async {
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.map_err(|e| format!("读取事件流失败: {}", e))?;
+            let events = parser.push_events(&chunk);
+            if let Some(event) = events.into_iter().find(|event| event.event.as_deref() == Some("endpoint")) {
+                return Ok(event.data);
+            }
+        }
+        Err("事件流在给出 endpoint 之前结束".to_string())
+    })
+    .await
+    .map_err(|_| format!("{} 秒内没有收到 endpoint 事件", config.timeout_seconds))??;
+    let endpoint = url
+        .join(endpoint.trim())
+        .map_err(|e| format!("无效的 endpoint {}: {}", endpoint, e))?;
+
+    let outbound = Outbound::Http {
+        http,
+        endpoint,
+        headers: config.headers.clone(),
+    };
+    let connection = Arc::new(Connection::new(outbound, config.timeout_seconds));
+    let weak = Arc::downgrade(&connection);
+    let reader = tokio::spawn(async move {
+        while let Some(Ok(chunk)) = body.next().await {
+            let Some(connection) = weak.upgrade() else { return };
+            for event in parser.push_events(&chunk) {
+                if event.event.as_deref().is_some_and(|name| name != "message") {
+                    continue;
+                }
+                let Ok(message) = serde_json::from_str::<Value>(&event.data) else {
+                    continue;
+                };
+                if let Some(reply) = connection.receive(message) {
+                    let _ = connection.send(&reply).await;
+                }
+            }
+        }
+        if let Some(connection) = weak.upgrade() {
+            connection.close();
+        }
+    });
+    *connection.reader.lock().unwrap() = Some(reader.abort_handle());
+    Ok(connection)
+}
+
+/// 提供给模型的一个工具
+#[derive(Debug, Clone)]
+struct RemoteTool {
+    /// `<服务器名>__<工具名>`
+    name: String,
+    /// 服务器上的工具名，读取资源的工具为`None`
+    remote: Option<String>,
+    definition: Value,
+}
+
+/// 服务器的状态
+#[derive(Debug, Clone, Serialize)]
+pub struct McpServerInfo {
+    pub name: String,
+    /// `stdio`或`sse`
+    pub transport: &'static str,
+    pub connected: bool,
+    pub tools: Vec<String>,
+    pub resources: usize,
+    /// 最近一次连接失败或断开的原因
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// 一个外部服务器
+struct Server {
+    name: String,
+    config: McpServerConfig,
+    connection: RwLock<Option<Arc<Connection>>>,
+    /// 连接后列出的工具，断开时清空
+    tools: RwLock<Vec<RemoteTool>>,
+    resources: RwLock<usize>,
+    error: Mutex<Option<String>>,
+}
+
+impl Server {
+    async fn connect(&self) -> Result<Arc<Connection>, String> {
+        match &self.config.url {
+            Some(url) => connect_sse(&self.config, url).await,
+            None => connect_stdio(&self.config),
+        }
+    }
+
+    /// 初始化连接，列出工具和资源
+    async fn discover(&self, connection: &Connection) -> Result<(Vec<RemoteTool>, usize), String> {
+        let params = json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": {},
+            "clientInfo": { "name": "openkimi", "version": env!("CARGO_PKG_VERSION") },
+        });
+        let initialized = connection.request("initialize", params).await?;
+        connection.notify("notifications/initialized", json!({})).await?;
+        let capabilities = &initialized["capabilities"];
+
+        let mut tools = Vec::new();
+        if capabilities.get("tools").is_some() {
+            for tool in list(connection, "tools/list", "tools").await? {
+                let Some(remote) = tool["name"].as_str() else { continue };
+                if self.config.tools.as_ref().is_some_and(|allowed| !allowed.iter().any(|name| name == remote)) {
+                    continue;
+                }
+                let name = format!("{}{}{}", self.name, SEPARATOR, remote);
+                let mut function = json!({
+                    "name": name,
+                    "parameters": tool.get("inputSchema").cloned().unwrap_or_else(|| json!({ "type": "object" })),
+                });
+                if let Some(description) = tool["description"].as_str() {
+                    function["description"] = Value::from(description);
+                }
+                let definition = json!({ "type": "function", "function": function });
+                if let Err(err) = tools::check_tool(&definition) {
+                    eprintln!("⚠️ 跳过 MCP 服务器 {} 的工具 {}: {}", self.name, remote, err);
+                    continue;
+                }
+                tools.push(RemoteTool {
+                    name,
+                    remote: Some(remote.to_string()),
+                    definition,
+                });
+            }
+        }
+
+        let mut resources = Vec::new();
+        if self.config.resources && capabilities.get("resources").is_some() {
+            resources = list(connection, "resources/list", "resources").await?;
+        }
+        if !resources.is_empty() {
+            tools.push(self.read_resource_tool(&resources));
+        }
+        Ok((tools, resources.len()))
+    }
+
+    /// 读取资源的工具，描述中列出资源
+    fn read_resource_tool(&self, resources: &[Value]) -> RemoteTool {
+        let mut description = format!("读取 MCP 服务器 {} 的资源，可用的资源：", self.name);
+        for resource in resources.iter().take(MAX_RESOURCE_ENUM) {
+            let uri = resource["uri"].as_str().unwrap_or_default();
+            let name = resource["name"].as_str().unwrap_or(uri);
+            description.push_str(&format!("\n- {} ({})", name, uri));
+            if let Some(about) = resource["description"].as_str() {
+                description.push_str(&format!(": {}", about));
+            }
+        }
+        let mut uri = json!({ "type": "string", "description": "资源的 URI" });
+        if resources.len() <= MAX_RESOURCE_ENUM {
+            let uris: Vec<&Value> = resources.iter().map(|resource| &resource["uri"]).collect();
+            uri["enum"] = json!(uris);
+        }
+        let name = format!("{}{}{}", self.name, SEPARATOR, READ_RESOURCE);
+        let definition = json!({
+            "type": "function",
+            "function": {
+                "name": name,
+                "description": description,
+                "parameters": { "type": "object", "properties": { "uri": uri }, "required": ["uri"] },
+            },
+        });
+        RemoteTool {
+            name,
+            remote: None,
+            definition,
+        }
+    }
+
+    /// 保持连接，断开或失败后重试
+    async fn run(self: Arc<Self>) {
+        loop {
+            let connection = match self.connect().await {
+                Ok(connection) => connection,
+                Err(err) => {
+                    eprintln!("⚠️ 连接 MCP 服务器 {} 失败: {}", self.name, err);
+                    *self.error.lock().unwrap() = Some(err);
+                    tokio::time::sleep(RETRY_INTERVAL).await;
+                    continue;
+                }
+            };
+            match self.discover(&connection).await {
+                Ok((tools, resources)) => {
+                    let names: Vec<&str> = tools.iter().map(|tool| tool.name.as_str()).collect();
+                    println!("🔌 已连接 MCP 服务器 {}，工具: {}", self.name, names.join("、"));
+                    *self.tools.write().unwrap() = tools;
+                    *self.resources.write().unwrap() = resources;
+                    *self.error.lock().unwrap() = None;
+                    *self.connection.write().unwrap() = Some(Arc::clone(&connection));
+                    connection.disconnected.notified().await;
+                    eprintln!("⚠️ MCP 服务器 {} 已断开", self.name);
+                    *self.error.lock().unwrap() = Some("连接已断开".to_string());
+                    self.tools.write().unwrap().clear();
+                    *self.resources.write().unwrap() = 0;
+                    *self.connection.write().unwrap() = None;
+                }
+                Err(err) => {
+                    eprintln!("⚠️ 初始化 MCP 服务器 {} 失败: {}", self.name, err);
+                    *self.error.lock().unwrap() = Some(err);
+                }
+            }
+            drop(connection);
+            tokio::time::sleep(RETRY_INTERVAL).await;
+        }
+    }
+
+    fn tool(&self, name: &str) -> Option<RemoteTool> {
+        self.tools.read().unwrap().iter().find(|tool| tool.name == name).cloned()
+    }
+
+    async fn call(&self, tool: &RemoteTool, arguments: &Value) -> Result<String, String> {
+        let connection = self.connection.read().unwrap().clone();
+        let connection = connection.ok_or_else(|| format!("MCP 服务器 {} 未连接", self.name))?;
+        match &tool.remote {
+            Some(remote) => {
+                let params = json!({ "name": remote, "arguments": arguments });
+                let result = connection.request("tools/call", params).await?;
+                let text = content_text(&result["content"]);
+                if result["isError"] == true {
+                    return Err(text);
+                }
+                Ok(text)
+            }
+            None => {
+                let uri = arguments["uri"].as_str().ok_or("缺少 uri")?;
+                let result = connection.request("resources/read", json!({ "uri": uri })).await?;
+                Ok(content_text(&result["contents"]))
+            }
+        }
+    }
+}
+
+/// 分页列出工具或资源
+async fn list(connection: &Connection, method: &str, field: &str) -> Result<Vec<Value>, String> {
+    let mut items = Vec::new();
+    let mut cursor: Option<String> = None;
+    for _ in 0..MAX_PAGES {
+        let params = match &cursor {
+            Some(cursor) => json!({ "cursor": cursor }),
+            None => json!({}),
+        };
+        let result = connection.request(method, params).await?;
+        items.extend(result[field].as_array().into_iter().flatten().cloned());
+        cursor = result["nextCursor"].as_str().map(str::to_string);
+        if cursor.is_none() {
+            break;
+        }
+    }
+    Ok(items)
+}
+
+/// 工具结果或资源内容转为交给模型的文本，图片等二进制内容只给出类型和大小
+fn content_text(content: &Value) -> String {
+    let parts: Vec<String> = content
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|item| {
+            if let Some(text) = item["text"].as_str() {
+                return text.to_string();
+            }
+            if item["type"] == "resource" {
+                return content_text(&json!([item["resource"]]));
+            }
+            let mime_type = item["mimeType"].as_str().unwrap_or("application/octet-stream");
+            let bytes = item["data"].as_str().or(item["blob"].as_str()).map_or(0, |data| data.len() / 4 * 3);
+            format!("[{} 内容，约 {} 字节]", mime_type, bytes)
+        })
+        .collect();
+    parts.join("\n")
+}
+
+/// 所有外部MCP服务器
+pub struct McpClients {
+    servers: Vec<Arc<Server>>,
+}
+
+impl std::fmt::Debug for McpClients {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<&str> = self.servers.iter().map(|server| server.name.as_str()).collect();
+        f.debug_struct("McpClients").field("servers", &names).finish()
+    }
+}
+
+impl McpClients {
+    /// 检查配置并在后台连接各服务器，需要在tokio运行时中调用
+    pub fn start(servers: &BTreeMap<String, McpServerConfig>) -> Result<Arc<McpClients>, String> {
+        let runtime = tokio::runtime::Handle::try_current().map_err(|_| "连接 MCP 服务器需要 tokio 运行时".to_string())?;
+        let mut list = Vec::new();
+        for (name, config) in servers {
+            tools::check_name(name).map_err(|e| format!("mcp.servers.{}: {}", name, e))?;
+            if name.contains(SEPARATOR) {
+                return Err(format!("mcp.servers.{}: 服务器名不能包含 {}", name, SEPARATOR));
+            }
+            match (&config.url, config.command.first()) {
+                (Some(_), None) | (None, Some(_)) => {}
+                _ => return Err(format!("mcp.servers.{}: command 和 url 必须且只能指定一个", name)),
+            }
+            if config.command.first().is_some_and(String::is_empty) {
+                return Err(format!("mcp.servers.{}: command 的第一项应为命令", name));
+            }
+            list.push(Arc::new(Server {
+                name: name.clone(),
+                config: config.clone(),
+                connection: RwLock::new(None),
+                tools: RwLock::new(Vec::new()),
+                resources: RwLock::new(0),
+                error: Mutex::new(None),
+            }));
+        }
+        for server in &list {
+            runtime.spawn(Arc::clone(server).run());
+        }
+        Ok(Arc::new(McpClients { servers: list }))
+    }
+
+    fn find(&self, name: &str) -> Option<(&Server, RemoteTool)> {
+        self.servers.iter().find_map(|server| server.tool(name).map(|tool| (server.as_ref(), tool)))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.find(name).is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.servers.iter().all(|server| server.tools.read().unwrap().is_empty())
+    }
+
+    /// 已连接的服务器的工具定义
+    pub fn definitions(&self) -> Vec<Value> {
+        self.servers
+            .iter()
+            .flat_map(|server| {
+                let tools = server.tools.read().unwrap();
+                tools.iter().map(|tool| tool.definition.clone()).collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// 调用前是否需要用户确认
+    pub fn requires_approval(&self, name: &str) -> bool {
+        self.find(name).is_some_and(|(server, tool)| {
+            let remote = tool.remote.as_deref().unwrap_or(READ_RESOURCE);
+            server.config.approval == ToolApproval::Prompt && !server.config.auto_approve.iter().any(|n| n == remote)
+        })
+    }
+
+    pub async fn call(&self, name: &str, arguments: &Value) -> Result<String, String> {
+        let (server, tool) = self.find(name).ok_or_else(|| format!("未知的工具 {}", name))?;
+        server.call(&tool, arguments).await
+    }
+
+    pub fn list(&self) -> Vec<McpServerInfo> {
+        self.servers
+            .iter()
+            .map(|server| McpServerInfo {
+                name: server.name.clone(),
+                transport: if server.config.url.is_some() { "sse" } else { "stdio" },
+                connected: server.connection.read().unwrap().is_some(),
+                tools: server.tools.read().unwrap().iter().map(|tool| tool.name.clone()).collect(),
+                resources: *server.resources.read().unwrap(),
+                error: server.error.lock().unwrap().clone(),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,806 @@
+//! 长期记忆
+//!
+//! 从用户的消息中提取长期有效的事实和偏好，连同嵌入向量保存在`memory.path`指定的SQLite数据库中。
+//! 对话请求到达时按最后一条用户消息检索相关的记忆，在`memory.max_tokens`内作为一条系统消息放在开头的系统消息之后；
+//! 回复成功后在后台从这条用户消息中提取新的记忆，与已有记忆足够相似时替换已有的那条而不是新增。
+//! 记忆按工作区和用户隔离，请求没有用户身份（见[`Consumer`]）或带有`"memory": false`时不读写记忆。
+//!
+//! 未固定的记忆从最后一次使用起按`memory.half_life_days`衰减，检索时相似度乘以衰减后的强度排序，
+//! 定时任务`forget_memories`删除强度低于`memory.forget_below`的记忆；固定的记忆强度始终为1，不会被删除。
+//! 用户通过`/v1/memories`查看、检索、添加、修改和删除自己的记忆。
+
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::extract::{Path, Query, State};
+use axum::Json;
+use openkimi_schema::sqlite::{migrate, Migration};
+use openkimi_schema::SchemaError;
+use openkimi_tokenizer::Tokenizer;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::cache::{dot, normalize};
+use crate::config::{LlmConfig, MemoryConfig};
+use crate::error::{ApiError, ApiResult};
+use crate::metering::Consumer;
+use crate::router::ModelRouter;
+use crate::types::{
+    ChatCompletionRequest, ChatMessage, DeletedResponse, EmbeddingInput, EmbeddingRequest, ListResponse,
+    MessageContent,
+};
+use crate::AppState;
+
+/// 各版本的迁移，下标加一即版本号
+pub(crate) const MIGRATIONS: &[Migration] = &[
+    // v1是初始结构。加入版本号之前建的库已有同样的表，建表语句保留IF NOT EXISTS直接沿用；回退会删除全部记忆，不提供
+    Migration::sql(
+        "长期记忆",
+        "
+        CREATE TABLE IF NOT EXISTS memories (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            workspace TEXT NOT NULL,
+            user TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            content TEXT NOT NULL,
+            model TEXT NOT NULL,
+            embedding BLOB NOT NULL,
+            pinned INTEGER NOT NULL DEFAULT 0,
+            use_count INTEGER NOT NULL DEFAULT 0,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL,
+            last_used_at INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS memories_owner ON memories (workspace, user, last_used_at);
+        ",
+        None,
+    ),
+];
+
+const COLUMNS: &str = "id, kind, content, pinned, use_count, created_at, updated_at, last_used_at";
+
+/// 注入提示词时放在记忆前面的说明
+const RECALL_PREFIX: &str = "以下是之前的对话中记下的关于用户的信息，仅在与当前问题相关时参考，不必主动提起：";
+
+const EXTRACT_INSTRUCTION: &str = "你是记忆提取助手。从用户的这条消息中找出值得长期记住的信息：关于用户本人的事实\
+    （如身份、职业、所在地、家庭、正在进行的项目）和偏好（如喜好、习惯、对回答方式的要求）。\
+    忽略一次性的问题、临时的上下文和寒暄，不要推测消息中没有的信息。每条写成一句以“用户”开头的完整陈述。\
+    只输出JSON数组，形如[{\"kind\": \"fact\", \"content\": \"用户是后端工程师\"}]，kind为fact或preference；\
+    没有值得记住的信息时输出[]。";
+
+/// 单条记忆的最大字符数
+const MAX_CONTENT_CHARS: usize = 500;
+
+/// 短于这么多个字符的消息不提取记忆
+const MIN_MESSAGE_CHARS: usize = 8;
+
+/// 单次列出或检索的最大条数
+const MAX_LIMIT: u32 = 200;
+
+const SECONDS_PER_DAY: f64 = 86400.0;
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+fn db_error(err: rusqlite::Error) -> ApiError {
+    ApiError::Internal(format!("读写记忆数据库失败: {}", err))
+}
+
+fn to_blob(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+fn from_blob(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4).map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).collect()
+}
+
+fn text_message(role: &str, text: String) -> ChatMessage {
+    ChatMessage {
+        role: role.to_string(),
+        content: Some(MessageContent::Text(text)),
+        name: None,
+        extra: Map::new(),
+    }
+}
+
+/// 记忆的类别
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MemoryKind {
+    /// 关于用户的事实，如职业、所在地
+    #[default]
+    Fact,
+    /// 用户的喜好和对回答方式的要求
+    Preference,
+}
+
+impl MemoryKind {
+    fn name(self) -> &'static str {
+        match self {
+            MemoryKind::Fact => "fact",
+            MemoryKind::Preference => "preference",
+        }
+    }
+
+    fn parse(name: &str) -> MemoryKind {
+        match name {
+            "preference" => MemoryKind::Preference,
+            _ => MemoryKind::Fact,
+        }
+    }
+}
+
+/// 一条记忆，时间均为Unix时间戳（秒）
+#[derive(Debug, Clone, Serialize)]
+pub struct Memory {
+    pub id: i64,
+    pub kind: MemoryKind,
+    pub content: String,
+    pub pinned: bool,
+    /// 按最后一次使用以来的时间衰减后的强度，固定的记忆为1
+    pub strength: f32,
+    /// 被注入提示词的次数
+    pub use_count: u64,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub last_used_at: u64,
+}
+
+/// 检索命中的记忆
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryHit {
+    #[serde(flatten)]
+    pub memory: Memory,
+    /// 与查询的余弦相似度
+    pub similarity: f32,
+    /// 相似度乘以强度，按它从高到低排序
+    pub score: f32,
+}
+
+/// 记忆的归属
+#[derive(Debug, Clone)]
+pub struct Owner {
+    pub workspace: String,
+    pub user: String,
+}
+
+/// 模型提取出的一条记忆
+#[derive(Debug, Deserialize)]
+struct Extracted {
+    #[serde(default)]
+    kind: MemoryKind,
+    content: String,
+}
+
+/// 模型的回复可能带有代码块标记或说明文字，只解析其中的JSON数组
+fn parse_extracted(reply: &str) -> ApiResult<Vec<Extracted>> {
+    let json = match (reply.find('['), reply.rfind(']')) {
+        (Some(start), Some(end)) if start < end => &reply[start..=end],
+        _ => return Err(ApiError::Upstream(format!("提取记忆的回复不是JSON数组: {}", reply.trim()))),
+    };
+    let items: Vec<Extracted> = serde_json::from_str(json)
+        .map_err(|e| ApiError::Upstream(format!("无法解析提取出的记忆: {}", e)))?;
+    Ok(items
+        .into_iter()
+        .filter_map(|item| {
+            let content: String = item.content.trim().chars().take(MAX_CONTENT_CHARS).collect();
+            (!content.is_empty()).then_some(Extracted { content, ..item })
+        })
+        .collect())
+}
+
+/// 记忆库
+#[derive(Debug)]
+pub struct MemoryStore {
+    config: MemoryConfig,
+    /// 提取记忆的模型
+    model: String,
+    embedding_model: String,
+    conn: Mutex<Connection>,
+}
+
+impl MemoryStore {
+    pub fn open(config: &MemoryConfig, llm: &LlmConfig) -> Result<MemoryStore, String> {
+        let embedding_model = config
+            .embedding_model
+            .as_deref()
+            .or(llm.embedding_model.as_deref())
+            .ok_or("memory.enabled 为 true 时需要配置 memory.embedding_model 或 llm.embedding_model")?;
+        for (name, value) in [("min_similarity", config.min_similarity), ("merge_threshold", config.merge_threshold)] {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(format!("memory.{} 必须在 0 到 1 之间", name));
+            }
+        }
+        if config.forget_below <= 0.0 || config.forget_below >= 1.0 {
+            return Err("memory.forget_below 必须大于 0 且小于 1".to_string());
+        }
+        if config.half_life_days <= 0.0 {
+            return Err("memory.half_life_days 必须大于 0".to_string());
+        }
+        if config.max_per_user == 0 {
+            return Err("memory.max_per_user 必须大于 0".to_string());
+        }
+
+        let path = &config.path;
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).map_err(|e| format!("创建目录 {} 失败: {}", parent.display(), e))?;
+        }
+        let open = || -> Result<Connection, SchemaError> {
+            let mut conn = Connection::open(path)?;
+            conn.pragma_update(None, "journal_mode", "WAL")?;
+            migrate(&mut conn, "memory", MIGRATIONS)?;
+            Ok(conn)
+        };
+        let conn = open().map_err(|e| format!("打开记忆数据库 {} 失败: {}", path.display(), e))?;
+        Ok(MemoryStore {
+            config: config.clone(),
+            model: config.model.clone().unwrap_or_else(|| llm.model_name.clone()),
+            embedding_model: embedding_model.to_string(),
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// 从`COLUMNS`读出的一行，强度按当前时间计算
+    fn read(&self, row: &Row, now: u64) -> rusqlite::Result<Memory> {
+        let pinned: bool = row.get(3)?;
+        let last_used_at: u64 = row.get(7)?;
+        let strength = if pinned {
+            1.0
+        } else {
+            let days = now.saturating_sub(last_used_at) as f64 / SECONDS_PER_DAY;
+            0.5f64.powf(days / self.config.half_life_days) as f32
+        };
+        Ok(Memory {
+            id: row.get(0)?,
+            kind: MemoryKind::parse(&row.get::<_, String>(1)?),
+            content: row.get(2)?,
+            pinned,
+            strength,
+            use_count: row.get(4)?,
+            created_at: row.get(5)?,
+            updated_at: row.get(6)?,
+            last_used_at,
+        })
+    }
+
+    fn fetch(&self, conn: &Connection, owner: &Owner, id: i64) -> ApiResult<Memory> {
+        let sql = format!("SELECT {} FROM memories WHERE workspace = ?1 AND user = ?2 AND id = ?3", COLUMNS);
+        let now = unix_now();
+        conn.query_row(&sql, params![owner.workspace, owner.user, id], |row| self.read(row, now))
+            .optional()
+            .map_err(db_error)?
+            .ok_or_else(|| ApiError::NotFound(format!("记忆 {} 不存在", id)))
+    }
+
+    pub fn get(&self, owner: &Owner, id: i64) -> ApiResult<Memory> {
+        self.fetch(&self.conn.lock().unwrap(), owner, id)
+    }
+
+    /// 固定的记忆在前，其余按最后一次使用的时间倒序
+    pub fn list(&self, owner: &Owner, limit: u32, offset: u32) -> ApiResult<Vec<Memory>> {
+        let conn = self.conn.lock().unwrap();
+        let sql = format!(
+            "SELECT {} FROM memories WHERE workspace = ?1 AND user = ?2
+             ORDER BY pinned DESC, last_used_at DESC, id DESC LIMIT ?3 OFFSET ?4",
+            COLUMNS
+        );
+        let now = unix_now();
+        let mut statement = conn.prepare(&sql).map_err(db_error)?;
+        let rows = statement
+            .query_map(params![owner.workspace, owner.user, limit, offset], |row| self.read(row, now))
+            .map_err(db_error)?;
+        rows.collect::<rusqlite::Result<_>>().map_err(db_error)
+    }
+
+    /// 与`vector`的相似度不低于`min_similarity`的记忆，按相似度乘以强度排序，最多`limit`条
+    pub fn search(
+        &self,
+        owner: &Owner,
+        vector: &[f32],
+        limit: usize,
+        min_similarity: f32,
+    ) -> ApiResult<Vec<MemoryHit>> {
+        let conn = self.conn.lock().unwrap();
+        let sql = format!(
+            "SELECT {}, embedding FROM memories WHERE workspace = ?1 AND user = ?2 AND model = ?3",
+            COLUMNS
+        );
+        let now = unix_now();
+        let mut statement = conn.prepare(&sql).map_err(db_error)?;
+        let rows = statement
+            .query_map(params![owner.workspace, owner.user, self.embedding_model], |row| {
+                let memory = self.read(row, now)?;
+                let similarity = dot(&from_blob(&row.get::<_, Vec<u8>>(8)?), vector);
+                Ok(MemoryHit {
+                    score: similarity * memory.strength,
+                    memory,
+                    similarity,
+                })
+            })
+            .map_err(db_error)?;
+        let mut hits = Vec::new();
+        for hit in rows {
+            let hit = hit.map_err(db_error)?;
+            if hit.similarity >= min_similarity {
+                hits.push(hit);
+            }
+        }
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(limit);
+        Ok(hits)
+    }
+
+    /// 保存一条记忆；与已有记忆的相似度达到`memory.merge_threshold`时替换已有的那条
+    ///
+    /// 新增后超出`memory.max_per_user`时删除最久未使用的未固定记忆。
+    pub fn remember(
+        &self,
+        owner: &Owner,
+        kind: MemoryKind,
+        content: &str,
+        vector: &[f32],
+        pinned: bool,
+    ) -> ApiResult<Memory> {
+        let conn = self.conn.lock().unwrap();
+        let now = unix_now();
+        let nearest = {
+            let mut statement = conn
+                .prepare("SELECT id, embedding FROM memories WHERE workspace = ?1 AND user = ?2 AND model = ?3")
+                .map_err(db_error)?;
+            let rows = statement
+                .query_map(params![owner.workspace, owner.user, self.embedding_model], |row| {
+                    Ok((row.get::<_, i64>(0)?, dot(&from_blob(&row.get::<_, Vec<u8>>(1)?), vector)))
+                })
+                .map_err(db_error)?;
+            let mut nearest: Option<(i64, f32)> = None;
+            for row in rows {
+                let (id, similarity) = row.map_err(db_error)?;
+                if similarity >= self.config.merge_threshold && nearest.is_none_or(|(_, best)| similarity > best) {
+                    nearest = Some((id, similarity));
+                }
+            }
+            nearest
+        };
+
+        let id = match nearest {
+            Some((id, _)) => {
+                conn.execute(
+                    "UPDATE memories SET kind = ?1, content = ?2, embedding = ?3, pinned = MAX(pinned, ?4),
+                     updated_at = ?5, last_used_at = ?5 WHERE id = ?6",
+                    params![kind.name(), content, to_blob(vector), pinned, now, id],
+                )
+                .map_err(db_error)?;
+                id
+            }
+            None => {
+                conn.execute(
+                    "INSERT INTO memories (workspace, user, kind, content, model, embedding, pinned,
+                     created_at, updated_at, last_used_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?8, ?8)",
+                    params![
+                        owner.workspace,
+                        owner.user,
+                        kind.name(),
+                        content,
+                        self.embedding_model,
+                        to_blob(vector),
+                        pinned,
+                        now
+                    ],
+                )
+                .map_err(db_error)?;
+                let id = conn.last_insert_rowid();
+                conn.execute(
+                    "DELETE FROM memories WHERE id IN (
+                         SELECT id FROM memories WHERE workspace = ?1 AND user = ?2 AND pinned = 0
+                         ORDER BY last_used_at, id
+                         LIMIT MAX(0, (SELECT COUNT(*) FROM memories WHERE workspace = ?1 AND user = ?2) - ?3))",
+                    params![owner.workspace, owner.user, self.config.max_per_user as u64],
+                )
+                .map_err(db_error)?;
+                id
+            }
+        };
+        self.fetch(&conn, owner, id)
+    }
+
+    /// 修改记忆，`vector`为新内容的嵌入向量；修改后从头开始衰减
+    pub fn update(&self, owner: &Owner, id: i64, update: &MemoryUpdate, vector: Option<&[f32]>) -> ApiResult<Memory> {
+        let conn = self.conn.lock().unwrap();
+        let changed = conn
+            .execute(
+                "UPDATE memories SET kind = COALESCE(?1, kind), content = COALESCE(?2, content),
+                 embedding = COALESCE(?3, embedding), model = CASE WHEN ?3 IS NULL THEN model ELSE ?4 END,
+                 pinned = COALESCE(?5, pinned), updated_at = ?6, last_used_at = ?6
+                 WHERE workspace = ?7 AND user = ?8 AND id = ?9",
+                params![
+                    update.kind.map(MemoryKind::name),
+                    update.content.as_deref().map(str::trim),
+                    vector.map(to_blob),
+                    self.embedding_model,
+                    update.pinned,
+                    unix_now(),
+                    owner.workspace,
+                    owner.user,
+                    id
+                ],
+            )
+            .map_err(db_error)?;
+        if changed == 0 {
+            return Err(ApiError::NotFound(format!("记忆 {} 不存在", id)));
+        }
+        self.fetch(&conn, owner, id)
+    }
+
+    /// 记下这些记忆被注入了提示词
+    pub fn touch(&self, owner: &Owner, ids: &[i64]) -> ApiResult<()> {
+        let conn = self.conn.lock().unwrap();
+        let now = unix_now();
+        for id in ids {
+            conn.execute(
+                "UPDATE memories SET use_count = use_count + 1, last_used_at = ?1
+                 WHERE workspace = ?2 AND user = ?3 AND id = ?4",
+                params![now, owner.workspace, owner.user, id],
+            )
+            .map_err(db_error)?;
+        }
+        Ok(())
+    }
+
+    /// 把WAL合并回数据库文件，退出前调用
+    pub fn checkpoint(&self) -> ApiResult<()> {
+        self.conn.lock().unwrap().execute_batch("PRAGMA wal_checkpoint(TRUNCATE)").map_err(db_error)
+    }
+
+    pub fn delete(&self, owner: &Owner, id: i64) -> ApiResult<()> {
+        let deleted = self
+            .conn
+            .lock()
+            .unwrap()
+            .execute(
+                "DELETE FROM memories WHERE workspace = ?1 AND user = ?2 AND id = ?3",
+                params![owner.workspace, owner.user, id],
+            )
+            .map_err(db_error)?;
+        if deleted == 0 {
+            return Err(ApiError::NotFound(format!("记忆 {} 不存在", id)));
+        }
+        Ok(())
+    }
+
+    /// 删除用户的所有记忆，包括固定的，返回删除的条数
+    pub fn clear(&self, owner: &Owner) -> ApiResult<usize> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "DELETE FROM memories WHERE workspace = ?1 AND user = ?2",
+                params![owner.workspace, owner.user],
+            )
+            .map_err(db_error)
+    }
+
+    /// 删除所有用户中强度低于`memory.forget_below`的未固定记忆，返回删除的条数
+    pub fn forget(&self) -> Result<usize, String> {
+        let age = self.config.half_life_days * SECONDS_PER_DAY * (1.0 / self.config.forget_below as f64).log2();
+        let cutoff = unix_now().saturating_sub(age as u64);
+        self.conn
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM memories WHERE pinned = 0 AND last_used_at < ?1", params![cutoff])
+            .map_err(|e| format!("删除衰减的记忆失败: {}", e))
+    }
+
+    /// 用`memory.embedding_model`计算归一化的嵌入向量
+    async fn embed(&self, models: &ModelRouter, texts: Vec<String>) -> ApiResult<Vec<Vec<f32>>> {
+        let count = texts.len();
+        let request = EmbeddingRequest {
+            model: self.embedding_model.clone(),
+            input: EmbeddingInput::Texts(texts),
+            extra: Map::new(),
+        };
+        let mut response = models.embeddings(&request).await?;
+        response.data.sort_by_key(|embedding| embedding.index);
+        let vectors: Vec<Vec<f32>> = response
+            .data
+            .iter()
+            .map(|embedding| {
+                let values = embedding.embedding.as_array()?;
+                Some(normalize(values.iter().filter_map(Value::as_f64).map(|v| v as f32).collect()))
+            })
+            .collect::<Option<_>>()
+            .ok_or_else(|| ApiError::Upstream("上游返回的向量不是数组".to_string()))?;
+        if vectors.len() != count {
+            return Err(ApiError::Upstream(format!("上游返回了 {} 个向量，应为 {} 个", vectors.len(), count)));
+        }
+        Ok(vectors)
+    }
+
+    /// 由`memory.model`从一条用户消息中提取记忆并保存，返回新增或更新的记忆
+    pub async fn extract(&self, models: &ModelRouter, owner: &Owner, text: &str) -> ApiResult<Vec<Memory>> {
+        let request = ChatCompletionRequest {
+            model: self.model.clone(),
+            messages: vec![
+                text_message("system", EXTRACT_INSTRUCTION.to_string()),
+                text_message("user", text.to_string()),
+            ],
+            stream: None,
+            max_tokens: Some(self.config.extract_max_tokens),
+            temperature: Some(0.0),
+            extra: Map::new(),
+        };
+        let response = models.chat_completion(&request).await?;
+        let reply = response.choices.first().map(|choice| choice.message.text()).unwrap_or_default();
+        let extracted = parse_extracted(&reply)?;
+        if extracted.is_empty() {
+            return Ok(Vec::new());
+        }
+        let vectors = self.embed(models, extracted.iter().map(|item| item.content.clone()).collect()).await?;
+        extracted
+            .iter()
+            .zip(vectors)
+            .map(|(item, vector)| self.remember(owner, item.kind, &item.content, &vector, false))
+            .collect()
+    }
+}
+
+/// 回复成功后要从中提取记忆的用户消息，见[`recall`]
+#[derive(Debug)]
+pub struct Pending {
+    owner: Owner,
+    text: String,
+}
+
+impl Pending {
+    /// 在后台提取记忆，失败时只打印日志
+    pub fn extract(self, state: &Arc<AppState>) {
+        if self.text.trim().chars().count() < MIN_MESSAGE_CHARS {
+            return;
+        }
+        let state = Arc::clone(state);
+        tokio::spawn(async move {
+            let Some(store) = &state.memory else {
+                return;
+            };
+            match store.extract(&state.models(), &self.owner, &self.text).await {
+                Ok(memories) if !memories.is_empty() => {
+                    println!("🧠 为用户 {} 记下 {} 条记忆", self.owner.user, memories.len());
+                }
+                Ok(_) => {}
+                Err(err) => eprintln!("⚠️ 提取长期记忆失败: {}", err),
+            }
+        });
+    }
+}
+
+/// 按最后一条用户消息检索相关的记忆并注入提示词，返回回复成功后要提取记忆的消息
+///
+/// 未启用记忆、请求没有用户身份或带有`"memory": false`、最后一条消息不是用户消息时返回`None`；
+/// 显式要求`"memory": true`却无法使用时返回错误。计算嵌入失败时不注入，回复后仍然提取。
+pub async fn recall(
+    state: &AppState,
+    consumer: &Consumer,
+    request: &mut ChatCompletionRequest,
+) -> ApiResult<Option<Pending>> {
+    let requested = match request.extra.remove("memory") {
+        None => None,
+        Some(Value::Bool(enabled)) => Some(enabled),
+        Some(_) => return Err(ApiError::invalid_request("memory 应为布尔值")),
+    };
+    if requested == Some(false) {
+        return Ok(None);
+    }
+    let (store, owner) = match (store(state), owner(state, consumer)) {
+        (Ok(store), Ok(owner)) => (store, owner),
+        (Err(err), _) | (_, Err(err)) if requested == Some(true) => return Err(err),
+        _ => return Ok(None),
+    };
+    let Some(text) = request
+        .messages
+        .last()
+        .filter(|message| message.role == "user")
+        .map(ChatMessage::text)
+        .filter(|text| !text.trim().is_empty())
+    else {
+        return Ok(None);
+    };
+
+    let vector = match store.embed(&state.models(), vec![text.clone()]).await {
+        Ok(mut vectors) => vectors.remove(0),
+        Err(err) => {
+            eprintln!("⚠️ 计算记忆检索的嵌入向量失败: {}", err);
+            return Ok(Some(Pending { owner, text }));
+        }
+    };
+    let hits = store.search(&owner, &vector, store.config.top_k, store.config.min_similarity)?;
+    let tokenizer = Tokenizer::for_model(state.models().route(&request.model).model);
+    let mut content = RECALL_PREFIX.to_string();
+    let mut tokens = tokenizer.count(&content);
+    let mut used = Vec::new();
+    for hit in hits {
+        let line = format!("\n- {}", hit.memory.content);
+        let line_tokens = tokenizer.count(&line);
+        if tokens + line_tokens > store.config.max_tokens as usize {
+            continue;
+        }
+        tokens += line_tokens;
+        content.push_str(&line);
+        used.push(hit.memory.id);
+    }
+    if !used.is_empty() {
+        let position = request.messages.iter().take_while(|message| message.role == "system").count();
+        request.messages.insert(position, text_message("system", content));
+        store.touch(&owner, &used)?;
+    }
+    Ok(Some(Pending { owner, text }))
+}
+
+fn store(state: &AppState) -> ApiResult<&MemoryStore> {
+    state
+        .memory
+        .as_ref()
+        .ok_or_else(|| ApiError::invalid_request("长期记忆未启用（memory.enabled 为 false）"))
+}
+
+/// 记忆属于调用方本人，没有用户身份时无法使用
+fn owner(state: &AppState, consumer: &Consumer) -> ApiResult<Owner> {
+    let user = consumer.user.clone().ok_or_else(|| {
+        ApiError::invalid_request(format!(
+            "使用长期记忆需要用户身份：启用认证或设置 {} 请求头",
+            state.config().metering.user_header
+        ))
+    })?;
+    Ok(Owner {
+        workspace: consumer.workspace.name().to_string(),
+        user,
+    })
+}
+
+/// 检查记忆的内容，返回去掉首尾空白后的文本
+fn check_content(content: &str) -> ApiResult<&str> {
+    let content = content.trim();
+    if content.is_empty() {
+        return Err(ApiError::invalid_request("content 不能为空"));
+    }
+    if content.chars().count() > MAX_CONTENT_CHARS {
+        return Err(ApiError::invalid_request(format!("content 不能超过 {} 个字符", MAX_CONTENT_CHARS)));
+    }
+    Ok(content)
+}
+
+/// `GET /v1/memories`的查询参数
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MemoryQuery {
+    pub limit: u32,
+    pub offset: u32,
+}
+
+impl Default for MemoryQuery {
+    fn default() -> Self {
+        MemoryQuery { limit: 50, offset: 0 }
+    }
+}
+
+/// `GET /v1/memories/search`的查询参数
+#[derive(Debug, Clone, Deserialize)]
+pub struct MemorySearchQuery {
+    pub q: String,
+    #[serde(default = "default_search_limit")]
+    pub limit: u32,
+}
+
+fn default_search_limit() -> u32 {
+    20
+}
+
+/// `POST /v1/memories`请求
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewMemory {
+    pub content: String,
+    #[serde(default)]
+    pub kind: MemoryKind,
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+/// `PATCH /v1/memories/{id}`请求，`None`的字段保持不变
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MemoryUpdate {
+    pub content: Option<String>,
+    pub kind: Option<MemoryKind>,
+    pub pinned: Option<bool>,
+}
+
+/// 调用方的记忆，固定的在前，其余按最后一次使用的时间倒序
+pub async fn list_memories(
+    State(state): State<Arc<AppState>>,
+    consumer: Consumer,
+    Query(query): Query<MemoryQuery>,
+) -> ApiResult<Json<ListResponse<Memory>>> {
+    let owner = owner(&state, &consumer)?;
+    let memories = store(&state)?.list(&owner, query.limit.min(MAX_LIMIT), query.offset)?;
+    Ok(Json(ListResponse::new(memories)))
+}
+
+/// 按语义检索调用方的记忆，不计入使用次数
+pub async fn search_memories(
+    State(state): State<Arc<AppState>>,
+    consumer: Consumer,
+    Query(query): Query<MemorySearchQuery>,
+) -> ApiResult<Json<ListResponse<MemoryHit>>> {
+    let owner = owner(&state, &consumer)?;
+    let store = store(&state)?;
+    if query.q.trim().is_empty() {
+        return Err(ApiError::invalid_request("q 不能为空"));
+    }
+    let vector = store.embed(&state.models(), vec![query.q]).await?.remove(0);
+    let hits = store.search(&owner, &vector, query.limit.min(MAX_LIMIT) as usize, 0.0)?;
+    Ok(Json(ListResponse::new(hits)))
+}
+
+/// 手动添加一条记忆，与已有记忆足够相似时替换已有的那条
+pub async fn create_memory(
+    State(state): State<Arc<AppState>>,
+    consumer: Consumer,
+    Json(request): Json<NewMemory>,
+) -> ApiResult<Json<Memory>> {
+    let owner = owner(&state, &consumer)?;
+    let store = store(&state)?;
+    let content = check_content(&request.content)?;
+    let vector = store.embed(&state.models(), vec![content.to_string()]).await?.remove(0);
+    Ok(Json(store.remember(&owner, request.kind, content, &vector, request.pinned)?))
+}
+
+pub async fn get_memory(
+    State(state): State<Arc<AppState>>,
+    consumer: Consumer,
+    Path(id): Path<i64>,
+) -> ApiResult<Json<Memory>> {
+    let owner = owner(&state, &consumer)?;
+    Ok(Json(store(&state)?.get(&owner, id)?))
+}
+
+/// 修改内容、类别或是否固定
+pub async fn update_memory(
+    State(state): State<Arc<AppState>>,
+    consumer: Consumer,
+    Path(id): Path<i64>,
+    Json(request): Json<MemoryUpdate>,
+) -> ApiResult<Json<Memory>> {
+    let owner = owner(&state, &consumer)?;
+    let store = store(&state)?;
+    let vector = match &request.content {
+        Some(content) => {
+            let content = check_content(content)?;
+            store.get(&owner, id)?;
+            Some(store.embed(&state.models(), vec![content.to_string()]).await?.remove(0))
+        }
+        None => None,
+    };
+    Ok(Json(store.update(&owner, id, &request, vector.as_deref())?))
+}
+
+pub async fn delete_memory(
+    State(state): State<Arc<AppState>>,
+    consumer: Consumer,
+    Path(id): Path<i64>,
+) -> ApiResult<Json<DeletedResponse>> {
+    let owner = owner(&state, &consumer)?;
+    store(&state)?.delete(&owner, id)?;
+    Ok(Json(DeletedResponse {
+        id: id.to_string(),
+        object: "memory.deleted".to_string(),
+        deleted: true,
+    }))
+}
+
+/// 删除调用方的所有记忆
+pub async fn clear_memories(State(state): State<Arc<AppState>>, consumer: Consumer) -> ApiResult<Json<Value>> {
+    let owner = owner(&state, &consumer)?;
+    let deleted = store(&state)?.clear(&owner)?;
+    Ok(Json(json!({ "object": "memory.cleared", "deleted": deleted })))
+}
@@ -0,0 +1,775 @@
+//! 用量计量
+//!
+//! 按UTC日期、工作区、用户、API令牌和模型累计请求数、提示词和回复的token数及费用，
+//! 供`/admin/usage`汇总查询和导出账单。费用在记录时按`metering.prices`计算，修改单价不影响已有记录。
+//! 请求完成时只在内存中累加，后台线程每隔几秒合并写入SQLite，不影响请求延迟。`metering.store`为`postgres`时
+//! 写入PostgreSQL的`usage_daily`表，多个服务实例的用量累加在同一行上。
+//! 定时任务`rollup_usage`把按日的用量汇总到`usage_monthly`表，供外部报表按月查询。
+//!
+//! 流式请求在结束或客户端断开时记录：上游在最后的分块中返回用量时以它为准，否则按本地分词器估算。
+//! 启用`metrics`时token数同时计入Prometheus指标，启用`quotas`时计入用户和团队的配额。
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt::Write as _;
+use std::fs;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use futures_util::stream::{BoxStream, Stream, StreamExt};
+use openkimi_postgres::postgres::types::ToSql;
+use openkimi_postgres::{PgError, Pool};
+use openkimi_schema::{postgres, sqlite, SchemaError};
+use openkimi_tokenizer::Tokenizer;
+use rusqlite::{params, params_from_iter, Connection};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::audit;
+use crate::auth::Principal;
+use crate::config::{DatabaseKind, MeteringConfig};
+use crate::metrics::Metrics;
+use crate::migrations;
+use crate::quotas::{self, Quotas};
+use crate::types::Usage;
+use crate::workspace::Workspace;
+use crate::AppState;
+
+/// 内存中的用量写入数据库的间隔
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// `metering.store`为`sqlite`时数据库各版本的迁移，下标加一即版本号
+pub(crate) const SQLITE_MIGRATIONS: &[sqlite::Migration] = &[
+    // v1与加入版本号之前的建表语句相同，已有的库直接沿用
+    sqlite::Migration::sql(
+        "按天和按月汇总的用量",
+        "
+        CREATE TABLE IF NOT EXISTS usage (
+            day TEXT NOT NULL,
+            workspace TEXT NOT NULL,
+            user TEXT NOT NULL,
+            api_key TEXT NOT NULL,
+            model TEXT NOT NULL,
+            requests INTEGER NOT NULL,
+            prompt_tokens INTEGER NOT NULL,
+            completion_tokens INTEGER NOT NULL,
+            cost REAL NOT NULL,
+            PRIMARY KEY (day, workspace, user, api_key, model)
+        );
+        CREATE TABLE IF NOT EXISTS usage_monthly (
+            month TEXT NOT NULL,
+            workspace TEXT NOT NULL,
+            user TEXT NOT NULL,
+            api_key TEXT NOT NULL,
+            model TEXT NOT NULL,
+            requests INTEGER NOT NULL,
+            prompt_tokens INTEGER NOT NULL,
+            completion_tokens INTEGER NOT NULL,
+            cost REAL NOT NULL,
+            PRIMARY KEY (month, workspace, user, api_key, model)
+        );
+        ",
+        None,
+    ),
+];
+
+/// PostgreSQL中各版本的迁移，下标加一即版本号；`user`是保留字，需要加引号
+pub(crate) const PG_MIGRATIONS: &[postgres::Migration] = &[
+    // v1是初始结构，回退会删除全部用量，不提供
+    postgres::Migration::sql(
+        "按天和按月汇总的用量",
+        r#"
+        CREATE TABLE usage_daily (
+            day TEXT NOT NULL,
+            workspace TEXT NOT NULL,
+            "user" TEXT NOT NULL,
+            api_key TEXT NOT NULL,
+            model TEXT NOT NULL,
+            requests BIGINT NOT NULL,
+            prompt_tokens BIGINT NOT NULL,
+            completion_tokens BIGINT NOT NULL,
+            cost DOUBLE PRECISION NOT NULL,
+            PRIMARY KEY (day, workspace, "user", api_key, model)
+        );
+        CREATE TABLE usage_monthly (
+            month TEXT NOT NULL,
+            workspace TEXT NOT NULL,
+            "user" TEXT NOT NULL,
+            api_key TEXT NOT NULL,
+            model TEXT NOT NULL,
+            requests BIGINT NOT NULL,
+            prompt_tokens BIGINT NOT NULL,
+            completion_tokens BIGINT NOT NULL,
+            cost DOUBLE PRECISION NOT NULL,
+            PRIMARY KEY (month, workspace, "user", api_key, model)
+        );
+        "#,
+        None,
+    ),
+];
+
+/// 用量的归属：工作区、用户和API令牌
+///
+/// 启用认证时用户为调用方身份，API令牌为静态令牌的`name`或用户目录中的令牌ID；
+/// 未启用认证时用户取`metering.user_header`请求头，没有API令牌。
+#[derive(Debug, Clone, Default)]
+pub struct Consumer {
+    pub workspace: Workspace,
+    pub user: Option<String>,
+    pub api_key: Option<String>,
+}
+
+impl Consumer {
+    pub fn new(principal: Option<&Principal>, workspace: Workspace, user_header: Option<&str>) -> Consumer {
+        match principal {
+            Some(principal) => Consumer {
+                workspace,
+                user: Some(principal.subject.clone()),
+                api_key: principal.token.clone(),
+            },
+            None => Consumer {
+                workspace,
+                user: user_header.map(str::trim).filter(|user| !user.is_empty()).map(str::to_string),
+                api_key: None,
+            },
+        }
+    }
+}
+
+impl FromRequestParts<Arc<AppState>> for Consumer {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        let workspace = parts.extensions.get::<Workspace>().cloned().unwrap_or_default();
+        let header = parts
+            .headers
+            .get(state.config().metering.user_header.as_str())
+            .and_then(|value| value.to_str().ok());
+        Ok(Consumer::new(parts.extensions.get::<Principal>(), workspace, header))
+    }
+}
+
+/// 汇总的维度，名称即数据库中的列名
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dimension {
+    Day,
+    Workspace,
+    User,
+    ApiKey,
+    Model,
+}
+
+impl Dimension {
+    pub fn parse(name: &str) -> Option<Dimension> {
+        match name {
+            "day" => Some(Dimension::Day),
+            "workspace" => Some(Dimension::Workspace),
+            "user" => Some(Dimension::User),
+            "api_key" => Some(Dimension::ApiKey),
+            "model" => Some(Dimension::Model),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Dimension::Day => "day",
+            Dimension::Workspace => "workspace",
+            Dimension::User => "user",
+            Dimension::ApiKey => "api_key",
+            Dimension::Model => "model",
+        }
+    }
+}
+
+/// 累计的用量
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UsageTotals {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+    pub cost: f64,
+}
+
+impl UsageTotals {
+    fn add(&mut self, other: &UsageTotals) {
+        self.requests += other.requests;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+        self.cost += other.cost;
+    }
+}
+
+/// 汇总查询的条件
+#[derive(Debug, Clone)]
+pub struct UsageQuery {
+    /// 起止日期，`YYYY-MM-DD`，包含两端
+    pub from: String,
+    pub to: String,
+    pub group_by: Vec<Dimension>,
+    /// 只统计这些维度取指定值的用量
+    pub filters: Vec<(Dimension, String)>,
+}
+
+/// 汇总结果的一行，`group`与查询的`group_by`一一对应
+#[derive(Debug, Clone)]
+pub struct UsageRow {
+    pub group: Vec<Option<String>>,
+    pub totals: UsageTotals,
+}
+
+/// 汇总结果
+#[derive(Debug, Clone)]
+pub struct UsageReport {
+    pub query: UsageQuery,
+    pub currency: String,
+    pub rows: Vec<UsageRow>,
+    pub total: UsageTotals,
+}
+
+impl UsageReport {
+    pub fn to_json(&self) -> Value {
+        let data: Vec<Value> = self
+            .rows
+            .iter()
+            .map(|row| {
+                let mut object = Map::new();
+                for (dimension, value) in self.query.group_by.iter().zip(&row.group) {
+                    object.insert(dimension.name().to_string(), json!(value));
+                }
+                if let Value::Object(totals) = json!(row.totals) {
+                    object.extend(totals);
+                }
+                Value::Object(object)
+            })
+            .collect();
+        json!({
+            "object": "usage.report",
+            "from": self.query.from,
+            "to": self.query.to,
+            "group_by": self.query.group_by.iter().map(|dimension| dimension.name()).collect::<Vec<_>>(),
+            "currency": self.currency,
+            "data": data,
+            "total": self.total,
+        })
+    }
+
+    /// 每行一个分组，首行为列名；费用保留6位小数
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::new();
+        for dimension in &self.query.group_by {
+            csv.push_str(dimension.name());
+            csv.push(',');
+        }
+        csv.push_str("requests,prompt_tokens,completion_tokens,total_tokens,cost\r\n");
+        for row in &self.rows {
+            for value in &row.group {
+                csv.push_str(&csv_field(value.as_deref().unwrap_or_default()));
+                csv.push(',');
+            }
+            let totals = &row.totals;
+            let _ = write!(
+                csv,
+                "{},{},{},{},{:.6}\r\n",
+                totals.requests, totals.prompt_tokens, totals.completion_tokens, totals.total_tokens, totals.cost
+            );
+        }
+        csv
+    }
+}
+
+/// 含逗号、引号或换行的字段加引号；以公式字符开头的字段前加`'`，避免在表格软件中被当作公式执行
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+/// 是否为`YYYY-MM-DD`格式的日期
+pub fn is_date(value: &str) -> bool {
+    let shaped = value.len() == 10
+        && value
+            .bytes()
+            .enumerate()
+            .all(|(index, byte)| if index == 4 || index == 7 { byte == b'-' } else { byte.is_ascii_digit() });
+    if !shaped {
+        return false;
+    }
+    let month: u32 = value[5..7].parse().unwrap_or(0);
+    let day: u32 = value[8..10].parse().unwrap_or(0);
+    (1..=12).contains(&month) && (1..=31).contains(&day)
+}
+
+/// 一行用量在数据库中的主键；用户和API令牌为空字符串表示没有
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct UsageKey {
+    day: String,
+    workspace: String,
+    user: String,
+    api_key: String,
+    model: String,
+}
+
+/// 保存用量的数据库
+#[derive(Debug)]
+enum Database {
+    Sqlite(Mutex<Connection>),
+    Postgres(Pool),
+}
+
+#[derive(Debug)]
+struct Inner {
+    db: Database,
+    pending: Mutex<HashMap<UsageKey, UsageTotals>>,
+}
+
+fn flush_sqlite(conn: &mut Connection, pending: &HashMap<UsageKey, UsageTotals>) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    {
+        let mut statement = tx.prepare_cached(
+            "INSERT INTO usage
+                (day, workspace, user, api_key, model, requests, prompt_tokens, completion_tokens, cost)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+             ON CONFLICT (day, workspace, user, api_key, model) DO UPDATE SET
+                requests = requests + excluded.requests,
+                prompt_tokens = prompt_tokens + excluded.prompt_tokens,
+                completion_tokens = completion_tokens + excluded.completion_tokens,
+                cost = cost + excluded.cost",
+        )?;
+        for (key, totals) in pending {
+            statement.execute(params![
+                key.day,
+                key.workspace,
+                key.user,
+                key.api_key,
+                key.model,
+                totals.requests as i64,
+                totals.prompt_tokens as i64,
+                totals.completion_tokens as i64,
+                totals.cost,
+            ])?;
+        }
+    }
+    tx.commit()
+}
+
+/// 按主键顺序写入，多个服务实例同时写入相同的行时不会互相死锁
+fn flush_postgres(pool: &Pool, pending: &HashMap<UsageKey, UsageTotals>) -> Result<(), PgError> {
+    let mut entries: Vec<_> = pending.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    let mut client = pool.get()?;
+    let mut tx = client.transaction()?;
+    let statement = tx.prepare(
+        r#"INSERT INTO usage_daily
+            (day, workspace, "user", api_key, model, requests, prompt_tokens, completion_tokens, cost)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+         ON CONFLICT (day, workspace, "user", api_key, model) DO UPDATE SET
+            requests = usage_daily.requests + excluded.requests,
+            prompt_tokens = usage_daily.prompt_tokens + excluded.prompt_tokens,
+            completion_tokens = usage_daily.completion_tokens + excluded.completion_tokens,
+            cost = usage_daily.cost + excluded.cost"#,
+    )?;
+    for (key, totals) in entries {
+        tx.execute(
+            &statement,
+            &[
+                &key.day,
+                &key.workspace,
+                &key.user,
+                &key.api_key,
+                &key.model,
+                &(totals.requests as i64),
+                &(totals.prompt_tokens as i64),
+                &(totals.completion_tokens as i64),
+                &totals.cost,
+            ],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+impl Inner {
+    /// 把内存中的用量合并写入数据库，失败时放回内存等下次重试
+    fn flush(&self) -> Result<(), String> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let result = match &self.db {
+            Database::Sqlite(conn) => flush_sqlite(&mut conn.lock().unwrap(), &pending).map_err(|e| e.to_string()),
+            Database::Postgres(pool) => flush_postgres(pool, &pending).map_err(|e| e.to_string()),
+        };
+        if let Err(err) = result {
+            let mut current = self.pending.lock().unwrap();
+            for (key, totals) in pending {
+                current.entry(key).or_default().add(&totals);
+            }
+            return Err(format!("写入用量数据库失败: {}", err));
+        }
+        Ok(())
+    }
+}
+
+fn flush_loop(inner: Arc<Inner>) {
+    loop {
+        std::thread::sleep(FLUSH_INTERVAL);
+        if let Err(err) = inner.flush() {
+            eprintln!("⚠️ {}", err);
+        }
+    }
+}
+
+/// 用量计量器
+#[derive(Debug)]
+pub struct Meter {
+    config: MeteringConfig,
+    inner: Arc<Inner>,
+}
+
+impl Meter {
+    /// `metering.store`为`postgres`时使用`postgres`连接池并升级表结构，否则打开`metering.path`；需要在Tokio运行时中调用
+    pub fn open(config: &MeteringConfig, postgres: Option<&Pool>) -> Result<Meter, String> {
+        let db = match (config.store, postgres) {
+            (DatabaseKind::Postgres, Some(pool)) => {
+                migrations::upgrade_postgres(pool, "metering", PG_MIGRATIONS)
+                    .map_err(|e| format!("打开用量数据库失败: {}", e))?;
+                Database::Postgres(pool.clone())
+            }
+            _ => {
+                let path = &config.path;
+                if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                    fs::create_dir_all(parent).map_err(|e| format!("创建目录 {} 失败: {}", parent.display(), e))?;
+                }
+                let open = || -> Result<Connection, SchemaError> {
+                    let mut conn = Connection::open(path)?;
+                    conn.pragma_update(None, "journal_mode", "WAL")?;
+                    sqlite::migrate(&mut conn, "metering", SQLITE_MIGRATIONS)?;
+                    Ok(conn)
+                };
+                let conn = open().map_err(|e| format!("打开用量数据库 {} 失败: {}", path.display(), e))?;
+                Database::Sqlite(Mutex::new(conn))
+            }
+        };
+        let inner = Arc::new(Inner {
+            db,
+            pending: Mutex::new(HashMap::new()),
+        });
+        let flushed = Arc::clone(&inner);
+        std::thread::spawn(move || flush_loop(flushed));
+        Ok(Meter {
+            config: config.clone(),
+            inner,
+        })
+    }
+
+    pub fn currency(&self) -> &str {
+        &self.config.currency
+    }
+
+    /// 计入一次请求的用量，`model`为客户端请求的模型名
+    pub fn record(&self, consumer: &Consumer, model: &str, prompt_tokens: u32, completion_tokens: u32) {
+        let price = self.config.prices.get(model).cloned().unwrap_or_default();
+        let totals = UsageTotals {
+            requests: 1,
+            prompt_tokens: prompt_tokens as u64,
+            completion_tokens: completion_tokens as u64,
+            total_tokens: prompt_tokens as u64 + completion_tokens as u64,
+            cost: price.cost(prompt_tokens, completion_tokens),
+        };
+        let key = UsageKey {
+            day: audit::today(),
+            workspace: consumer.workspace.name().to_string(),
+            user: consumer.user.clone().unwrap_or_default(),
+            api_key: consumer.api_key.clone().unwrap_or_default(),
+            model: model.to_string(),
+        };
+        self.inner.pending.lock().unwrap().entry(key).or_default().add(&totals);
+    }
+
+    /// 写入内存中尚未保存的用量并把SQLite的WAL合并回数据库文件，退出前调用；会阻塞
+    pub fn close(&self) -> Result<(), String> {
+        self.inner.flush()?;
+        match &self.inner.db {
+            Database::Sqlite(conn) => conn
+                .lock()
+                .unwrap()
+                .execute_batch("PRAGMA wal_checkpoint(TRUNCATE)")
+                .map_err(|e| format!("合并用量数据库日志失败: {}", e)),
+            Database::Postgres(_) => Ok(()),
+        }
+    }
+
+    /// 按条件汇总用量，先写入内存中尚未保存的部分；会阻塞，需在阻塞线程中调用
+    pub fn report(&self, query: UsageQuery) -> Result<UsageReport, String> {
+        self.inner.flush()?;
+
+        let postgres = matches!(self.inner.db, Database::Postgres(_));
+        let placeholder = |index: usize| if postgres { format!("${}", index) } else { format!("?{}", index) };
+        // 列名加引号，`user`在PostgreSQL中是保留字
+        let columns: Vec<String> = query.group_by.iter().map(|dimension| format!("\"{}\"", dimension.name())).collect();
+        let mut sql = String::from("SELECT ");
+        for column in &columns {
+            let _ = write!(sql, "{}, ", column);
+        }
+        sql.push_str("CAST(SUM(requests) AS BIGINT), CAST(SUM(prompt_tokens) AS BIGINT), ");
+        sql.push_str("CAST(SUM(completion_tokens) AS BIGINT), SUM(cost)");
+        let _ = write!(
+            sql,
+            " FROM {} WHERE day >= {} AND day <= {}",
+            if postgres { "usage_daily" } else { "usage" },
+            placeholder(1),
+            placeholder(2)
+        );
+        let mut values = vec![query.from.clone(), query.to.clone()];
+        for (dimension, value) in &query.filters {
+            values.push(value.clone());
+            let _ = write!(sql, " AND \"{}\" = {}", dimension.name(), placeholder(values.len()));
+        }
+        if !columns.is_empty() {
+            let _ = write!(sql, " GROUP BY {0} ORDER BY {0}", columns.join(", "));
+        }
+
+        let mut rows = match &self.inner.db {
+            Database::Sqlite(conn) => {
+                let conn = conn.lock().unwrap();
+                let mut statement = conn.prepare(&sql).map_err(|e| format!("查询用量失败: {}", e))?;
+                let rows = statement
+                    .query_map(params_from_iter(values), |row| {
+                        let mut group = Vec::with_capacity(columns.len());
+                        for index in 0..columns.len() {
+                            group.push(row.get(index)?);
+                        }
+                        let count = |index: usize| row.get::<_, Option<i64>>(columns.len() + index);
+                        Ok(usage_row(group, [count(0)?, count(1)?, count(2)?], row.get(columns.len() + 3)?))
+                    })
+                    .map_err(|e| format!("查询用量失败: {}", e))?;
+                rows.collect::<rusqlite::Result<Vec<_>>>().map_err(|e| format!("查询用量失败: {}", e))?
+            }
+            Database::Postgres(pool) => {
+                let params: Vec<&(dyn ToSql + Sync)> = values.iter().map(|value| value as _).collect();
+                let rows = pool
+                    .get()
+                    .and_then(|mut client| Ok(client.query(&sql, &params)?))
+                    .map_err(|e| format!("查询用量失败: {}", e))?;
+                let read = |row: &openkimi_postgres::postgres::Row| {
+                    let mut group = Vec::with_capacity(columns.len());
+                    for index in 0..columns.len() {
+                        group.push(row.try_get(index)?);
+                    }
+                    let count = |index: usize| row.try_get::<_, Option<i64>>(columns.len() + index);
+                    Ok(usage_row(group, [count(0)?, count(1)?, count(2)?], row.try_get(columns.len() + 3)?))
+                };
+                rows.iter()
+                    .map(read)
+                    .collect::<Result<Vec<_>, openkimi_postgres::postgres::Error>>()
+                    .map_err(|e| format!("查询用量失败: {}", e))?
+            }
+        };
+        // 没有分组时SUM总会返回一行，没有数据时去掉它
+        rows.retain(|row| row.totals.requests > 0);
+
+        let mut total = UsageTotals::default();
+        for row in &rows {
+            total.add(&row.totals);
+        }
+        Ok(UsageReport {
+            query,
+            currency: self.config.currency.clone(),
+            rows,
+            total,
+        })
+    }
+
+    /// 把按日的用量汇总到`usage_monthly`表，返回写入的行数；会阻塞，需在阻塞线程中调用
+    ///
+    /// 月度表为空时汇总全部历史，否则只重算本月和上月，上月最后一天的用量可能在月初才写入。
+    pub fn rollup(&self) -> Result<usize, String> {
+        self.inner.flush()?;
+        let conn = match &self.inner.db {
+            Database::Sqlite(conn) => conn,
+            Database::Postgres(pool) => return rollup_postgres(pool).map_err(|e| format!("汇总月度用量失败: {}", e)),
+        };
+        let mut conn = conn.lock().unwrap();
+        let result = (|| {
+            let tx = conn.transaction()?;
+            let empty: bool = tx.query_row("SELECT NOT EXISTS (SELECT 1 FROM usage_monthly)", [], |row| row.get(0))?;
+            let since = if empty { String::new() } else { previous_month(&audit::today()) };
+            tx.execute("DELETE FROM usage_monthly WHERE month >= ?1", [&since])?;
+            let written = tx.execute(
+                "INSERT INTO usage_monthly
+                    (month, workspace, user, api_key, model, requests, prompt_tokens, completion_tokens, cost)
+                 SELECT substr(day, 1, 7), workspace, user, api_key, model,
+                     SUM(requests), SUM(prompt_tokens), SUM(completion_tokens), SUM(cost)
+                 FROM usage WHERE substr(day, 1, 7) >= ?1
+                 GROUP BY substr(day, 1, 7), workspace, user, api_key, model",
+                [&since],
+            )?;
+            tx.commit()?;
+            Ok(written)
+        })();
+        result.map_err(|e: rusqlite::Error| format!("汇总月度用量失败: {}", e))
+    }
+}
+
+/// 在同一个事务中重算月度用量；多个服务实例同时汇总时锁住月度表依次执行
+fn rollup_postgres(pool: &Pool) -> Result<usize, PgError> {
+    let mut client = pool.get()?;
+    let mut tx = client.transaction()?;
+    tx.batch_execute("LOCK TABLE usage_monthly IN EXCLUSIVE MODE")?;
+    let empty: bool = tx.query_one("SELECT NOT EXISTS (SELECT 1 FROM usage_monthly)", &[])?.try_get(0)?;
+    let since = if empty { String::new() } else { previous_month(&audit::today()) };
+    tx.execute("DELETE FROM usage_monthly WHERE month >= $1", &[&since])?;
+    let written = tx.execute(
+        r#"INSERT INTO usage_monthly
+            (month, workspace, "user", api_key, model, requests, prompt_tokens, completion_tokens, cost)
+         SELECT substr(day, 1, 7), workspace, "user", api_key, model,
+             SUM(requests), SUM(prompt_tokens), SUM(completion_tokens), SUM(cost)
+         FROM usage_daily WHERE substr(day, 1, 7) >= $1
+         GROUP BY substr(day, 1, 7), workspace, "user", api_key, model"#,
+        &[&since],
+    )?;
+    tx.commit()?;
+    Ok(written as usize)
+}
+
+/// 汇总结果的一行，分组的空字符串表示没有该维度
+fn usage_row(group: Vec<String>, counts: [Option<i64>; 3], cost: Option<f64>) -> UsageRow {
+    let [requests, prompt_tokens, completion_tokens] = counts.map(|count| count.unwrap_or(0) as u64);
+    UsageRow {
+        group: group.into_iter().map(|value| Some(value).filter(|value| !value.is_empty())).collect(),
+        totals: UsageTotals {
+            requests,
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+            cost: cost.unwrap_or(0.0),
+        },
+    }
+}
+
+/// `YYYY-MM-DD`所在月份的上一个月，格式为`YYYY-MM`
+fn previous_month(day: &str) -> String {
+    let year: i32 = day[..4].parse().unwrap_or(1970);
+    let month: u32 = day[5..7].parse().unwrap_or(1);
+    if month <= 1 {
+        format!("{:04}-12", year - 1)
+    } else {
+        format!("{:04}-{:02}", year, month - 1)
+    }
+}
+
+/// 计入一次请求的用量，未启用计量时什么也不做
+pub fn record(state: &AppState, consumer: &Consumer, model: &str, prompt_tokens: u32, completion_tokens: u32) {
+    if let Some(meter) = &state.metering {
+        meter.record(consumer, model, prompt_tokens, completion_tokens);
+    }
+    quotas::record(state, consumer, model, prompt_tokens, completion_tokens);
+    if let Some(metrics) = &state.metrics {
+        metrics.record_tokens(model, prompt_tokens, completion_tokens);
+    }
+}
+
+/// 包装流式响应的data流，结束或被丢弃时记录用量；计量、配额和指标都未启用时原样返回
+pub fn meter_stream(
+    state: &AppState,
+    consumer: &Consumer,
+    model: &str,
+    prompt_tokens: u32,
+    data: BoxStream<'static, String>,
+) -> BoxStream<'static, String> {
+    if state.metering.is_none() && state.quotas.is_none() && state.metrics.is_none() {
+        return data;
+    }
+    MeteredStream {
+        inner: data,
+        meter: state.metering.clone(),
+        quotas: state.quotas.clone(),
+        metrics: state.metrics.clone(),
+        consumer: consumer.clone(),
+        model: model.to_string(),
+        upstream_model: state.models().route(model).model.to_string(),
+        prompt_tokens,
+        content: String::new(),
+        usage: None,
+    }
+    .boxed()
+}
+
+struct MeteredStream {
+    inner: BoxStream<'static, String>,
+    meter: Option<Arc<Meter>>,
+    quotas: Option<Arc<Quotas>>,
+    metrics: Option<Arc<Metrics>>,
+    consumer: Consumer,
+    model: String,
+    /// 上游的实际模型，用于选择估算回复长度的分词器
+    upstream_model: String,
+    prompt_tokens: u32,
+    content: String,
+    /// 上游返回的用量，通常在最后一个分块中
+    usage: Option<Usage>,
+}
+
+impl MeteredStream {
+    fn observe(&mut self, data: &str) {
+        let Ok(chunk) = serde_json::from_str::<Value>(data) else {
+            return;
+        };
+        if let Some(usage) = chunk.get("usage").filter(|usage| !usage.is_null()) {
+            self.usage = serde_json::from_value(usage.clone()).ok();
+        }
+        for choice in chunk["choices"].as_array().into_iter().flatten() {
+            if let Some(text) = choice["delta"]["content"].as_str() {
+                self.content.push_str(text);
+            }
+        }
+    }
+}
+
+impl Stream for MeteredStream {
+    type Item = String;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let poll = this.inner.poll_next_unpin(cx);
+        if let Poll::Ready(Some(data)) = &poll {
+            this.observe(data);
+        }
+        poll
+    }
+}
+
+impl Drop for MeteredStream {
+    fn drop(&mut self) {
+        let (prompt_tokens, completion_tokens) = match &self.usage {
+            Some(usage) => (usage.prompt_tokens, usage.completion_tokens),
+            None => {
+                let tokenizer = Tokenizer::for_model(&self.upstream_model);
+                (self.prompt_tokens, tokenizer.count(&self.content) as u32)
+            }
+        };
+        if let Some(meter) = &self.meter {
+            meter.record(&self.consumer, &self.model, prompt_tokens, completion_tokens);
+        }
+        if let (Some(quotas), Some(user)) = (&self.quotas, &self.consumer.user) {
+            quotas.record(user, &self.model, prompt_tokens, completion_tokens);
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.record_tokens(&self.model, prompt_tokens, completion_tokens);
+        }
+    }
+}
@@ -0,0 +1,287 @@
+//! Prometheus指标
+//!
+//! 启用`metrics`时在`metrics.path`（默认`/metrics`）以Prometheus文本格式导出：各路由的请求数和耗时直方图、
+//! 处理中的请求数、各模型的token数、上游端点和密钥的请求结果、回复缓存的命中情况以及后台队列的积压。
+//! 请求数、耗时和token数在请求过程中累计，其余指标在抓取时从各组件的状态读取。指标只保存在内存中，重启后归零。
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+
+use crate::auth::{bearer, constant_time_eq};
+use crate::cache::CacheStats;
+use crate::config::MetricsConfig;
+use crate::error::ApiError;
+use crate::AppState;
+
+/// Prometheus文本格式的内容类型
+const CONTENT_TYPE_TEXT: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// 一个路由的耗时直方图，`counts`中各桶不累计
+#[derive(Debug, Clone)]
+struct Histogram {
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(buckets: usize) -> Histogram {
+        Histogram {
+            counts: vec![0; buckets],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, bounds: &[f64], seconds: f64) {
+        if let Some(index) = bounds.iter().position(|&bound| seconds <= bound) {
+            self.counts[index] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug)]
+struct RouteStats {
+    /// 按状态码的请求数
+    statuses: BTreeMap<u16, u64>,
+    duration: Histogram,
+}
+
+#[derive(Debug)]
+pub struct Metrics {
+    config: MetricsConfig,
+    /// 按方法和路由模板统计，路由模板不含路径参数，避免标签过多
+    routes: Mutex<BTreeMap<(String, String), RouteStats>>,
+    in_flight: AtomicI64,
+    /// 按模型的提示词和回复token数
+    tokens: Mutex<BTreeMap<String, (u64, u64)>>,
+}
+
+/// 请求结束或被取消时减少处理中的请求数
+struct InFlight<'a>(&'a AtomicI64);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    pub fn new(config: &MetricsConfig) -> Result<Metrics, String> {
+        if !config.path.starts_with('/') {
+            return Err(format!("metrics.path 必须以 / 开头: {}", config.path));
+        }
+        if config.buckets.is_empty() || config.buckets.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err("metrics.buckets 不能为空，且必须从小到大排列".to_string());
+        }
+        Ok(Metrics {
+            config: config.clone(),
+            routes: Mutex::new(BTreeMap::new()),
+            in_flight: AtomicI64::new(0),
+            tokens: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    fn observe(&self, method: String, route: String, status: u16, seconds: f64) {
+        let mut routes = self.routes.lock().unwrap();
+        let stats = routes.entry((method, route)).or_insert_with(|| RouteStats {
+            statuses: BTreeMap::new(),
+            duration: Histogram::new(self.config.buckets.len()),
+        });
+        *stats.statuses.entry(status).or_default() += 1;
+        stats.duration.observe(&self.config.buckets, seconds);
+    }
+
+    /// 计入一次请求的token数，`model`为客户端请求的模型名
+    pub fn record_tokens(&self, model: &str, prompt_tokens: u32, completion_tokens: u32) {
+        let mut tokens = self.tokens.lock().unwrap();
+        let entry = tokens.entry(model.to_string()).or_default();
+        entry.0 += prompt_tokens as u64;
+        entry.1 += completion_tokens as u64;
+    }
+
+    /// 请求过程中累计的指标
+    fn write_requests(&self, out: &mut Output) {
+        let routes = self.routes.lock().unwrap();
+        out.header("openkimi_http_requests_total", "counter", "HTTP请求数");
+        for ((method, route), stats) in routes.iter() {
+            for (status, count) in &stats.statuses {
+                let status = status.to_string();
+                let labels = [("method", method.as_str()), ("route", route.as_str()), ("status", status.as_str())];
+                out.sample("openkimi_http_requests_total", &labels, *count);
+            }
+        }
+
+        out.header(
+            "openkimi_http_request_duration_seconds",
+            "histogram",
+            "HTTP请求的处理时间，流式响应计到开始返回为止",
+        );
+        for ((method, route), stats) in routes.iter() {
+            let histogram = &stats.duration;
+            let mut cumulative = 0;
+            for (bound, count) in self.config.buckets.iter().zip(&histogram.counts) {
+                cumulative += count;
+                let bound = bound.to_string();
+                let labels = [("method", method.as_str()), ("route", route.as_str()), ("le", bound.as_str())];
+                out.sample("openkimi_http_request_duration_seconds_bucket", &labels, cumulative);
+            }
+            let labels = [("method", method.as_str()), ("route", route.as_str()), ("le", "+Inf")];
+            out.sample("openkimi_http_request_duration_seconds_bucket", &labels, histogram.count);
+            let labels = [("method", method.as_str()), ("route", route.as_str())];
+            out.sample("openkimi_http_request_duration_seconds_sum", &labels, histogram.sum);
+            out.sample("openkimi_http_request_duration_seconds_count", &labels, histogram.count);
+        }
+        drop(routes);
+
+        out.header("openkimi_http_requests_in_flight", "gauge", "正在处理的HTTP请求数");
+        out.sample("openkimi_http_requests_in_flight", &[], self.in_flight.load(Ordering::Relaxed));
+
+        out.header("openkimi_tokens_total", "counter", "对话补全和嵌入的token数，按请求的模型名区分");
+        for (model, (prompt, completion)) in self.tokens.lock().unwrap().iter() {
+            out.sample("openkimi_tokens_total", &[("model", model.as_str()), ("type", "prompt")], *prompt);
+            out.sample("openkimi_tokens_total", &[("model", model.as_str()), ("type", "completion")], *completion);
+        }
+    }
+}
+
+/// 拼接Prometheus文本格式
+#[derive(Default)]
+struct Output(String);
+
+impl Output {
+    fn header(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.0, "# HELP {} {}", name, help);
+        let _ = writeln!(self.0, "# TYPE {} {}", name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+        self.0.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> =
+                labels.iter().map(|(key, value)| format!("{}=\"{}\"", key, escape(value))).collect();
+            let _ = write!(self.0, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.0, " {}", value);
+    }
+}
+
+/// 转义标签值中的反斜杠、双引号和换行
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// 抓取时从各组件读取的指标
+fn write_components(state: &AppState, cache: Option<CacheStats>, out: &mut Output) {
+    let endpoints: Vec<_> = state.models().backends().flat_map(|backend| backend.upstream.pool().endpoints()).collect();
+    out.header("openkimi_upstream_requests_total", "counter", "发往上游端点的请求数，按结果区分");
+    for endpoint in &endpoints {
+        let labels = |outcome| {
+            [("backend", endpoint.backend.as_str()), ("endpoint", endpoint.id.as_str()), ("outcome", outcome)]
+        };
+        out.sample("openkimi_upstream_requests_total", &labels("success"), endpoint.health.successes);
+        out.sample("openkimi_upstream_requests_total", &labels("failure"), endpoint.health.failures);
+    }
+    out.header("openkimi_upstream_up", "gauge", "上游端点当前是否可用");
+    for endpoint in &endpoints {
+        let labels = [("backend", endpoint.backend.as_str()), ("endpoint", endpoint.id.as_str())];
+        out.sample("openkimi_upstream_up", &labels, u8::from(endpoint.health.status == "healthy"));
+    }
+
+    if let Some(vault) = &state.vault {
+        let keys = vault.list();
+        out.header("openkimi_upstream_key_requests_total", "counter", "使用密钥库中各密钥的请求数，按结果区分");
+        for key in &keys {
+            let labels = |outcome| {
+                [("provider", key.provider.as_str()), ("key", key.id.as_str()), ("outcome", outcome)]
+            };
+            out.sample("openkimi_upstream_key_requests_total", &labels("success"), key.health.successes);
+            out.sample("openkimi_upstream_key_requests_total", &labels("failure"), key.health.failures);
+        }
+    }
+
+    if let Some(stats) = cache {
+        out.header("openkimi_cache_lookups_total", "counter", "回复缓存的查找次数，按结果区分");
+        out.sample("openkimi_cache_lookups_total", &[("result", "hit")], stats.hits);
+        out.sample("openkimi_cache_lookups_total", &[("result", "semantic_hit")], stats.semantic_hits);
+        out.sample("openkimi_cache_lookups_total", &[("result", "miss")], stats.misses);
+        out.header("openkimi_cache_entries", "gauge", "回复缓存中的条目数");
+        out.sample("openkimi_cache_entries", &[], stats.entries);
+        out.header("openkimi_cache_bytes", "gauge", "回复缓存占用的字节数");
+        out.sample("openkimi_cache_bytes", &[], stats.bytes);
+    }
+
+    out.header("openkimi_queue_depth", "gauge", "后台队列中等待处理的条目数");
+    if let Some(audit) = &state.audit {
+        out.sample("openkimi_queue_depth", &[("queue", "audit")], audit.queue_depth());
+    }
+    if let Some(safety) = &state.safety {
+        out.sample("openkimi_queue_depth", &[("queue", "safety")], safety.queue_depth());
+    }
+
+    if let Some(jobs) = &state.jobs {
+        out.header("openkimi_jobs_running", "gauge", "正在运行的定时任务数");
+        out.sample("openkimi_jobs_running", &[], jobs.list().iter().filter(|job| job.running).count());
+    }
+}
+
+/// 记录请求数和耗时的中间件，路由为匹配到的模板，如`/v1/sessions/{id}`
+pub async fn track(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let Some(metrics) = state.metrics.as_deref() else {
+        return next.run(request).await;
+    };
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", MatchedPath::as_str)
+        .to_string();
+    let method = request.method().to_string();
+    let started = Instant::now();
+    metrics.in_flight.fetch_add(1, Ordering::Relaxed);
+    let _in_flight = InFlight(&metrics.in_flight);
+    let response = next.run(request).await;
+    metrics.observe(method, route, response.status().as_u16(), started.elapsed().as_secs_f64());
+    response
+}
+
+/// 导出指标的路由，未启用时返回`None`
+pub fn router(state: Arc<AppState>) -> Option<Router> {
+    let path = state.metrics.as_ref()?.config.path.clone();
+    Some(Router::new().route(&path, get(export)).with_state(state))
+}
+
+async fn export(State(state): State<Arc<AppState>>, request: Request) -> Result<Response, ApiError> {
+    let Some(metrics) = state.metrics.as_deref() else {
+        return Err(ApiError::NotFound("未启用指标导出".to_string()));
+    };
+    if let Some(expected) = &metrics.config.token {
+        let header = request.headers().get(AUTHORIZATION).and_then(|value| value.to_str().ok());
+        let provided = bearer(header).unwrap_or_default();
+        if !constant_time_eq(provided.as_bytes(), expected.as_bytes()) {
+            return Err(ApiError::Unauthorized("指标令牌无效".to_string()));
+        }
+    }
+    let mut out = Output::default();
+    metrics.write_requests(&mut out);
+    // 使用Redis时统计缓存需要访问Redis，在写入其他指标之前取得
+    let cache = match &state.cache {
+        Some(cache) => Some(cache.stats().await),
+        None => None,
+    };
+    write_components(&state, cache, &mut out);
+    out.header("openkimi_build_info", "gauge", "服务版本");
+    out.sample("openkimi_build_info", &[("version", env!("CARGO_PKG_VERSION"))], 1);
+    Ok(([(CONTENT_TYPE, CONTENT_TYPE_TEXT)], out.0).into_response())
+}
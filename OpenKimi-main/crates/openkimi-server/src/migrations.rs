@@ -0,0 +1,150 @@
+//! 数据库结构迁移
+//!
+//! 服务启动时各存储把自己的表升级到最新结构，数据库比程序新时拒绝启动，避免旧版本按旧结构写坏数据。
+//! `openkimi-server migrate`列出配置中用到的数据库及其结构版本，可以在不启动服务的情况下升级，
+//! 或者在降级程序之前把结构回退到旧版本。各部分的迁移定义在各自的模块中，见`openkimi_schema`。
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use openkimi_postgres::Pool;
+use openkimi_schema::{postgres, sqlite, SchemaError, Status};
+use openkimi_sessions::schema as sessions;
+use rusqlite::Connection;
+
+use crate::config::{AuditFormat, Config, DatabaseKind, PromptStoreKind, SessionStoreKind};
+use crate::{audit, memory, metering, prompts, users};
+
+/// 迁移列表及其所在的数据库
+enum Database {
+    Sqlite(PathBuf, &'static [sqlite::Migration]),
+    Postgres(&'static [postgres::Migration]),
+}
+
+/// 保存在数据库中的一部分数据
+pub struct Component {
+    pub name: &'static str,
+    database: Database,
+}
+
+impl Component {
+    fn sqlite(name: &'static str, path: &Path, migrations: &'static [sqlite::Migration]) -> Component {
+        Component {
+            name,
+            database: Database::Sqlite(path.to_path_buf(), migrations),
+        }
+    }
+
+    fn postgres(name: &'static str, migrations: &'static [postgres::Migration]) -> Component {
+        Component {
+            name,
+            database: Database::Postgres(migrations),
+        }
+    }
+
+    pub fn uses_postgres(&self) -> bool {
+        matches!(self.database, Database::Postgres(_))
+    }
+
+    /// SQLite数据库文件的路径，PostgreSQL为`postgres`
+    pub fn location(&self) -> String {
+        match &self.database {
+            Database::Sqlite(path, _) => path.display().to_string(),
+            Database::Postgres(_) => "postgres".to_string(),
+        }
+    }
+
+    /// 各版本的说明，第一个为v1
+    pub fn descriptions(&self) -> Vec<&'static str> {
+        match &self.database {
+            Database::Sqlite(_, migrations) => migrations.iter().map(|migration| migration.description).collect(),
+            Database::Postgres(migrations) => migrations.iter().map(|migration| migration.description).collect(),
+        }
+    }
+
+    /// 数据库当前的结构版本；SQLite文件还不存在时为0，不会创建文件
+    pub fn status(&self, pool: Option<&Pool>) -> Result<Status, String> {
+        let status = match &self.database {
+            Database::Sqlite(path, migrations) if !path.exists() => Ok(Status {
+                component: self.name.to_string(),
+                version: 0,
+                latest: migrations.len() as u32,
+            }),
+            Database::Sqlite(path, migrations) => Connection::open(path)
+                .map_err(SchemaError::from)
+                .and_then(|conn| sqlite::status(&conn, self.name, migrations)),
+            Database::Postgres(migrations) => {
+                let mut client = postgres_pool(pool)?.get().map_err(|e| e.to_string())?;
+                postgres::status(&mut client, self.name, migrations)
+            }
+        };
+        status.map_err(|e| format!("读取 {} 的结构版本失败: {}", self.name, e))
+    }
+
+    /// 把结构升级或回退到`target`版本
+    pub fn migrate_to(&self, pool: Option<&Pool>, target: u32) -> Result<(), String> {
+        match &self.database {
+            Database::Sqlite(path, migrations) => {
+                if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                    fs::create_dir_all(parent).map_err(|e| format!("创建目录 {} 失败: {}", parent.display(), e))?;
+                }
+                let migrate = || -> Result<(), SchemaError> {
+                    let mut conn = Connection::open(path)?;
+                    // 回退时删除的行要级联删除引用它的行
+                    conn.pragma_update(None, "foreign_keys", true)?;
+                    sqlite::migrate_to(&mut conn, self.name, migrations, target)
+                };
+                migrate().map_err(|e| e.to_string())
+            }
+            Database::Postgres(migrations) => {
+                let mut client = postgres_pool(pool)?.get().map_err(|e| e.to_string())?;
+                postgres::migrate_to(&mut client, self.name, migrations, target).map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+fn postgres_pool(pool: Option<&Pool>) -> Result<&Pool, String> {
+    pool.ok_or_else(|| "没有连接 PostgreSQL".to_string())
+}
+
+/// 配置中启用的、保存在SQLite或PostgreSQL中的部分；保存在Redis或文件中的数据没有结构版本
+pub fn components(config: &Config) -> Vec<Component> {
+    let mut components = Vec::new();
+    if config.sessions.enabled {
+        match config.sessions.store {
+            SessionStoreKind::Sqlite => {
+                components.push(Component::sqlite(sessions::COMPONENT, &config.sessions.path, sessions::SQLITE))
+            }
+            SessionStoreKind::Postgres => components.push(Component::postgres(sessions::COMPONENT, sessions::POSTGRES)),
+            SessionStoreKind::Redis => {}
+        }
+    }
+    if config.memory.enabled {
+        components.push(Component::sqlite("memory", &config.memory.path, memory::MIGRATIONS));
+    }
+    if config.prompts.enabled && config.prompts.store == PromptStoreKind::Sqlite {
+        components.push(Component::sqlite("prompts", &config.prompts.database, prompts::MIGRATIONS));
+    }
+    if config.metering.enabled {
+        components.push(match config.metering.store {
+            DatabaseKind::Sqlite => Component::sqlite("metering", &config.metering.path, metering::SQLITE_MIGRATIONS),
+            DatabaseKind::Postgres => Component::postgres("metering", metering::PG_MIGRATIONS),
+        });
+    }
+    if config.users.enabled {
+        components.push(Component::sqlite("users", &config.users.path, users::MIGRATIONS));
+    }
+    if config.audit.enabled && config.audit.format == AuditFormat::Postgres {
+        components.push(Component::postgres("audit", audit::PG_MIGRATIONS));
+    }
+    components
+}
+
+/// 在阻塞线程中把`component`的表升级到最新结构，需要在Tokio运行时中调用
+pub(crate) fn upgrade_postgres(pool: &Pool, component: &str, migrations: &[postgres::Migration]) -> Result<(), String> {
+    tokio::task::block_in_place(|| {
+        let mut client = pool.get().map_err(|e| e.to_string())?;
+        postgres::migrate(&mut client, component, migrations).map_err(|e| e.to_string())
+    })
+}
@@ -0,0 +1,520 @@
+//! 本地模型目录
+//!
+//! `openkimi-server models`把GGUF模型下载、转换或量化到`model_store.dir`中，`gguf.model`可以直接写其中的文件名，
+//! 客户端用`GET /v1/system/models`查看已有的模型。下载的文件先写入`<文件名>.part`，中断后再次下载时从断点继续，
+//! 完成后检查GGUF文件头和SHA-256再改为正式的文件名；转换和量化调用llama.cpp自带的工具。
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use axum::extract::State;
+use axum::Json;
+use futures_util::StreamExt;
+use reqwest::header::{AUTHORIZATION, RANGE};
+use reqwest::StatusCode;
+use ring::digest::{Context, SHA256};
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+
+use crate::config::ModelStoreConfig;
+use crate::error::{ApiError, ApiResult};
+use crate::AppState;
+
+const EXTENSION: &str = "gguf";
+
+/// 未完成的下载和转换使用的后缀
+const PARTIAL: &str = "part";
+
+/// 下载进度的刷新间隔
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// GGUF文件头中`general.file_type`对应的量化方式，下标即取值，空串为已废弃的取值
+const FILE_TYPES: &[&str] = &[
+    "F32", "F16", "Q4_0", "Q4_1", "", "", "", "Q8_0", "Q5_0", "Q5_1", "Q2_K", "Q3_K_S", "Q3_K_M", "Q3_K_L", "Q4_K_S",
+    "Q4_K_M", "Q5_K_S", "Q5_K_M", "Q6_K", "IQ2_XXS", "IQ2_XS", "Q2_K_S", "IQ3_XS", "IQ3_XXS", "IQ1_S", "IQ4_NL",
+    "IQ3_S", "IQ3_M", "IQ2_S", "IQ2_M", "IQ4_XS", "IQ1_M", "BF16",
+];
+
+/// 目录中的一个模型文件
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelFile {
+    /// 去掉`.gguf`的文件名，可以直接作为`gguf.model`
+    pub id: String,
+    pub object: &'static str,
+    pub path: PathBuf,
+    pub size_bytes: u64,
+    /// 以下几项取自GGUF文件头，读取失败时为空
+    pub architecture: Option<String>,
+    /// 如`7B`
+    pub parameters: Option<String>,
+    /// 如`Q4_K_M`
+    pub quantization: Option<String>,
+    /// 训练时的上下文长度
+    pub context_length: Option<u64>,
+    pub modified_at: u64,
+}
+
+/// GGUF文件头中的元数据
+#[derive(Debug, Default)]
+struct Metadata {
+    architecture: Option<String>,
+    parameters: Option<String>,
+    file_type: Option<u64>,
+    context_length: Option<u64>,
+}
+
+/// 文件头中的值，只保留用到的类型
+enum Value {
+    Uint(u64),
+    Str(String),
+    Other,
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut buffer = [0; 4];
+    reader.read_exact(&mut buffer)?;
+    Ok(u32::from_le_bytes(buffer))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut buffer = [0; 8];
+    reader.read_exact(&mut buffer)?;
+    Ok(u64::from_le_bytes(buffer))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_string(reader: &mut BufReader<File>) -> io::Result<String> {
+    let len = read_u64(reader)?;
+    // 词表中的单个字符串不会很长，过长说明文件损坏
+    if len > 1 << 20 {
+        return Err(invalid("字符串过长"));
+    }
+    let mut buffer = vec![0; len as usize];
+    reader.read_exact(&mut buffer)?;
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
+
+/// 定长类型的字节数，字符串和数组返回`None`
+fn fixed_size(kind: u32) -> io::Result<Option<i64>> {
+    match kind {
+        0 | 1 | 7 => Ok(Some(1)),
+        2 | 3 => Ok(Some(2)),
+        4..=6 => Ok(Some(4)),
+        10..=12 => Ok(Some(8)),
+        8 | 9 => Ok(None),
+        _ => Err(invalid("未知的值类型")),
+    }
+}
+
+fn read_value(reader: &mut BufReader<File>, kind: u32) -> io::Result<Value> {
+    match kind {
+        0 => Ok(Value::Uint(u64::from(read_fixed::<1>(reader)?[0]))),
+        2 => Ok(Value::Uint(u64::from(u16::from_le_bytes(read_fixed(reader)?)))),
+        4 => Ok(Value::Uint(u64::from(read_u32(reader)?))),
+        10 => Ok(Value::Uint(read_u64(reader)?)),
+        8 => Ok(Value::Str(read_string(reader)?)),
+        9 => {
+            let item = read_u32(reader)?;
+            let len = read_u64(reader)?;
+            match fixed_size(item)? {
+                Some(size) => {
+                    let bytes = i64::try_from(len).ok().and_then(|len| len.checked_mul(size));
+                    reader.seek_relative(bytes.ok_or_else(|| invalid("数组过长"))?)?;
+                }
+                None => {
+                    for _ in 0..len {
+                        read_value(reader, item)?;
+                    }
+                }
+            }
+            Ok(Value::Other)
+        }
+        _ => {
+            let size = fixed_size(kind)?.ok_or_else(|| invalid("未知的值类型"))?;
+            reader.seek_relative(size)?;
+            Ok(Value::Other)
+        }
+    }
+}
+
+fn read_fixed<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buffer = [0; N];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+/// 读取GGUF文件头，找齐需要的几项后不再读取后面的词表等内容
+fn read_metadata(path: &Path) -> io::Result<Metadata> {
+    let mut reader = BufReader::new(File::open(path)?);
+    if &read_fixed::<4>(&mut reader)? != b"GGUF" {
+        return Err(invalid("不是GGUF文件"));
+    }
+    // 第1版的计数是32位的，llama.cpp早已不再支持
+    if read_u32(&mut reader)? < 2 {
+        return Err(invalid("不支持的GGUF版本"));
+    }
+    let _tensors = read_u64(&mut reader)?;
+    let count = read_u64(&mut reader)?;
+    let mut metadata = Metadata::default();
+    for _ in 0..count {
+        let key = read_string(&mut reader)?;
+        let kind = read_u32(&mut reader)?;
+        let value = read_value(&mut reader, kind)?;
+        match (key.as_str(), value) {
+            ("general.architecture", Value::Str(value)) => metadata.architecture = Some(value),
+            ("general.size_label", Value::Str(value)) => metadata.parameters = Some(value),
+            ("general.file_type", Value::Uint(value)) => metadata.file_type = Some(value),
+            (key, Value::Uint(value)) if key.ends_with(".context_length") => metadata.context_length = Some(value),
+            _ => {}
+        }
+        if metadata.architecture.is_some() && metadata.file_type.is_some() && metadata.context_length.is_some() {
+            break;
+        }
+    }
+    Ok(metadata)
+}
+
+/// 量化方式的名称，如`Q4_K_M`，不区分大小写
+fn quantization(file_type: u64) -> Option<String> {
+    let name = FILE_TYPES.get(usize::try_from(file_type).ok()?)?;
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// 文件名只能是单独的一段，不能包含路径
+fn validate_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(format!("无效的模型名: {}（只能包含字母、数字、-、_ 和 .）", name));
+    }
+    Ok(())
+}
+
+/// 补上`.gguf`
+fn file_name(name: &str) -> Result<String, String> {
+    validate_name(name)?;
+    match Path::new(name).extension() {
+        Some(extension) if extension == EXTENSION => Ok(name.to_string()),
+        _ => Ok(format!("{}.{}", name, EXTENSION)),
+    }
+}
+
+/// 去掉文件名末尾的量化方式，如`qwen2.5-7b-instruct-f16` → `qwen2.5-7b-instruct`
+fn base_name(stem: &str) -> &str {
+    match stem.rsplit_once(['-', '.']) {
+        Some((base, suffix)) if FILE_TYPES.iter().any(|name| !name.is_empty() && name.eq_ignore_ascii_case(suffix)) => {
+            base
+        }
+        _ => stem,
+    }
+}
+
+/// `hf:<仓库>/<文件>`展开为Hugging Face的下载地址，如`hf:Qwen/Qwen2.5-0.5B-Instruct-GGUF/qwen2.5-0.5b-instruct-q4_k_m.gguf`
+fn expand_url(url: &str) -> Result<String, String> {
+    let Some(path) = url.strip_prefix("hf:") else {
+        return Ok(url.to_string());
+    };
+    let mut segments = path.splitn(3, '/');
+    match (segments.next(), segments.next(), segments.next()) {
+        (Some(owner), Some(repo), Some(file)) if !owner.is_empty() && !repo.is_empty() && !file.is_empty() => {
+            Ok(format!("https://huggingface.co/{}/{}/resolve/main/{}", owner, repo, file))
+        }
+        _ => Err(format!("无效的 Hugging Face 地址: {}（格式为 hf:<用户>/<仓库>/<文件>）", url)),
+    }
+}
+
+fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut context = Context::new(&SHA256);
+    let mut buffer = vec![0; 1 << 20];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        context.update(&buffer[..read]);
+    }
+    Ok(context.finish().as_ref().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+fn megabytes(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+/// 运行llama.cpp的工具，输出直接显示在终端上
+fn run(command: &mut Command, program: &Path) -> Result<(), String> {
+    let status = command.status().map_err(|e| format!("无法运行 {}: {}", program.display(), e))?;
+    if !status.success() {
+        return Err(format!("{} 失败（{}）", program.display(), status));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct ModelStore {
+    config: ModelStoreConfig,
+}
+
+impl ModelStore {
+    pub fn new(config: &ModelStoreConfig) -> ModelStore {
+        ModelStore { config: config.clone() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.config.dir
+    }
+
+    fn describe(&self, path: &Path) -> io::Result<ModelFile> {
+        let meta = fs::metadata(path)?;
+        let metadata = read_metadata(path).unwrap_or_default();
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        Ok(ModelFile {
+            id: stem,
+            object: "local_model",
+            path: path.to_path_buf(),
+            size_bytes: meta.len(),
+            architecture: metadata.architecture,
+            parameters: metadata.parameters,
+            quantization: metadata.file_type.and_then(quantization),
+            context_length: metadata.context_length,
+            modified_at: meta
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs()),
+        })
+    }
+
+    /// 目录中的GGUF文件，按文件名排序；目录不存在时为空
+    pub fn list(&self) -> Result<Vec<ModelFile>, String> {
+        let entries = match fs::read_dir(&self.config.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(format!("读取模型目录 {} 失败: {}", self.config.dir.display(), err)),
+        };
+        let mut models = Vec::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|extension| extension == EXTENSION) && path.is_file() {
+                models.push(self.describe(&path).map_err(|e| format!("读取 {} 失败: {}", path.display(), e))?);
+            }
+        }
+        models.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(models)
+    }
+
+    /// `gguf.model`对应的文件：已存在的路径原样使用，否则在目录中按文件名查找
+    pub fn locate(&self, model: &Path) -> Option<PathBuf> {
+        if model.is_file() {
+            return Some(model.to_path_buf());
+        }
+        let name = file_name(model.to_str()?).ok()?;
+        Some(self.config.dir.join(name)).filter(|path| path.is_file())
+    }
+
+    fn find(&self, name: &str) -> Result<PathBuf, String> {
+        self.locate(Path::new(name))
+            .ok_or_else(|| format!("模型 {} 不存在，已有的模型见 openkimi-server models list", name))
+    }
+
+    /// 正式文件和未完成文件的路径，正式文件已存在时出错
+    fn target(&self, name: &str) -> Result<(PathBuf, PathBuf), String> {
+        let name = file_name(name)?;
+        let target = self.config.dir.join(&name);
+        if target.exists() {
+            return Err(format!("模型 {} 已存在", target.display()));
+        }
+        fs::create_dir_all(&self.config.dir)
+            .map_err(|e| format!("创建模型目录 {} 失败: {}", self.config.dir.display(), e))?;
+        let partial = self.config.dir.join(format!("{}.{}", name, PARTIAL));
+        Ok((target, partial))
+    }
+
+    /// 检查文件是GGUF格式后改为正式的文件名
+    fn finish(&self, partial: &Path, target: &Path) -> Result<ModelFile, String> {
+        if let Err(err) = read_metadata(partial) {
+            return Err(format!("{} 不是有效的 GGUF 文件: {}", partial.display(), err));
+        }
+        fs::rename(partial, target).map_err(|e| format!("保存 {} 失败: {}", target.display(), e))?;
+        self.describe(target).map_err(|e| format!("读取 {} 失败: {}", target.display(), e))
+    }
+
+    /// 下载GGUF文件，`name`缺省取地址的最后一段；已有未完成的下载时从断点继续
+    pub async fn download(&self, url: &str, name: Option<&str>, sha256: Option<&str>) -> Result<ModelFile, String> {
+        let url = expand_url(url)?;
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => {
+                let path = url.split(['?', '#']).next().unwrap_or_default();
+                path.rsplit('/').next().unwrap_or_default().to_string()
+            }
+        };
+        let (target, partial) = self.target(&name)?;
+        let offset = fs::metadata(&partial).map_or(0, |meta| meta.len());
+
+        let http = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(30))
+            .build()
+            .map_err(|e| e.to_string())?;
+        let mut request = http.get(&url);
+        if offset > 0 {
+            request = request.header(RANGE, format!("bytes={}-", offset));
+        }
+        // 需要同意许可的仓库要用Hugging Face的令牌下载
+        if url.starts_with("https://huggingface.co/") {
+            if let Ok(token) = std::env::var("HF_TOKEN") {
+                request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+            }
+        }
+        let response = request.send().await.map_err(|e| format!("下载 {} 失败: {}", url, e))?;
+        let status = response.status();
+        // 断点已在文件末尾，上次只差改名
+        let complete = offset > 0 && status == StatusCode::RANGE_NOT_SATISFIABLE;
+        if !status.is_success() && !complete {
+            return Err(format!("下载 {} 失败: HTTP {}", url, status));
+        }
+        if !complete {
+            // 服务器不支持断点续传时返回整个文件
+            let resumed = status == StatusCode::PARTIAL_CONTENT;
+            if offset > 0 {
+                if resumed {
+                    eprintln!("⏯️ 从 {:.1} MiB 处继续下载", megabytes(offset));
+                } else {
+                    eprintln!("⚠️ 服务器不支持断点续传，重新下载");
+                }
+            }
+            let mut written = if resumed { offset } else { 0 };
+            let total = response.content_length().map(|len| len + written);
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(resumed)
+                .truncate(!resumed)
+                .open(&partial)
+                .await
+                .map_err(|e| format!("写入 {} 失败: {}", partial.display(), e))?;
+            let mut body = response.bytes_stream();
+            let mut shown = Instant::now();
+            while let Some(chunk) = body.next().await {
+                let chunk = chunk.map_err(|e| format!("下载 {} 中断: {}，再次运行同一命令可以继续下载", url, e))?;
+                file.write_all(&chunk).await.map_err(|e| format!("写入 {} 失败: {}", partial.display(), e))?;
+                written += chunk.len() as u64;
+                if shown.elapsed() >= PROGRESS_INTERVAL {
+                    shown = Instant::now();
+                    match total {
+                        Some(total) if total > 0 => eprint!(
+                            "\r⬇️ {:.1} / {:.1} MiB ({:.0}%)",
+                            megabytes(written),
+                            megabytes(total),
+                            written as f64 * 100.0 / total as f64
+                        ),
+                        _ => eprint!("\r⬇️ {:.1} MiB", megabytes(written)),
+                    }
+                }
+            }
+            file.flush().await.map_err(|e| format!("写入 {} 失败: {}", partial.display(), e))?;
+            eprintln!("\r⬇️ {:.1} MiB", megabytes(written));
+        }
+
+        let path = partial.clone();
+        let actual = tokio::task::spawn_blocking(move || sha256_file(&path))
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| format!("读取 {} 失败: {}", partial.display(), e))?;
+        if let Some(expected) = sha256 {
+            if !actual.eq_ignore_ascii_case(expected.trim()) {
+                // 内容已经损坏，留着只会在下次续传时接着错下去
+                let _ = fs::remove_file(&partial);
+                return Err(format!("SHA-256 不一致，已删除下载的文件: 期望 {}，实际 {}", expected.trim(), actual));
+            }
+            eprintln!("🔐 SHA-256 校验通过");
+        } else {
+            eprintln!("🔐 SHA-256: {}", actual);
+        }
+        self.finish(&partial, &target)
+    }
+
+    /// 用`convert_hf_to_gguf.py`把Hugging Face格式的模型目录转换为GGUF，`outtype`如`f16`、`bf16`、`q8_0`
+    pub fn convert(&self, source: &Path, name: Option<&str>, outtype: &str) -> Result<ModelFile, String> {
+        if !source.join("config.json").is_file() {
+            return Err(format!("{} 不是 Hugging Face 格式的模型目录（缺少 config.json）", source.display()));
+        }
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => {
+                let stem = source.file_name().unwrap_or_default().to_string_lossy();
+                format!("{}-{}", stem.to_lowercase(), outtype.to_lowercase())
+            }
+        };
+        let (target, partial) = self.target(&name)?;
+        let mut command = Command::new(&self.config.python);
+        command
+            .arg(&self.config.convert)
+            .arg(source)
+            .arg("--outfile")
+            .arg(&partial)
+            .args(["--outtype", outtype]);
+        let result = run(&mut command, &self.config.convert).and_then(|()| self.finish(&partial, &target));
+        if result.is_err() {
+            let _ = fs::remove_file(&partial);
+        }
+        result
+    }
+
+    /// 用`llama-quantize`量化目录中的模型，`kind`如`Q4_K_M`，`name`缺省把原文件名末尾的量化方式换成`kind`
+    pub fn quantize(&self, model: &str, kind: &str, name: Option<&str>) -> Result<ModelFile, String> {
+        let source = self.find(model)?;
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => {
+                let stem = source.file_stem().unwrap_or_default().to_string_lossy();
+                format!("{}-{}", base_name(&stem), kind.to_lowercase())
+            }
+        };
+        let (target, partial) = self.target(&name)?;
+        let mut command = Command::new(&self.config.quantize);
+        command.arg(&source).arg(&partial).arg(kind.to_uppercase());
+        let result = run(&mut command, &self.config.quantize).and_then(|()| self.finish(&partial, &target));
+        if result.is_err() {
+            let _ = fs::remove_file(&partial);
+        }
+        result
+    }
+
+    /// 删除目录中的模型和它未完成的下载
+    pub fn remove(&self, name: &str) -> Result<PathBuf, String> {
+        let name = file_name(name)?;
+        let path = self.config.dir.join(&name);
+        let partial = self.config.dir.join(format!("{}.{}", name, PARTIAL));
+        let removed = [&path, &partial].into_iter().filter(|path| fs::remove_file(path).is_ok()).count();
+        if removed == 0 {
+            return Err(format!("模型 {} 不存在", path.display()));
+        }
+        Ok(path)
+    }
+}
+
+/// `GET /v1/system/models`的响应
+#[derive(Debug, Serialize)]
+pub struct ModelFilesResponse {
+    pub object: &'static str,
+    pub data: Vec<ModelFile>,
+}
+
+/// `GET /v1/system/models`，列出本地模型目录，供客户端选择`gguf.model`
+pub async fn list_model_files(State(state): State<Arc<AppState>>) -> ApiResult<Json<ModelFilesResponse>> {
+    let store = ModelStore::new(&state.config().model_store);
+    let data = tokio::task::spawn_blocking(move || store.list())
+        .await
+        .map_err(|e| ApiError::Internal(e.to_string()))?
+        .map_err(ApiError::Internal)?;
+    Ok(Json(ModelFilesResponse { object: "list", data }))
+}
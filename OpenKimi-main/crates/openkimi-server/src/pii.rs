@@ -0,0 +1,493 @@
+//! 个人信息脱敏
+//!
+//! 按正则规则识别邮箱、电话、证件号和自定义类别，配置了`pii.ner`时再调用NER服务识别人名、机构名等实体。
+//! 用在三处：审计日志和安全日志中的个人信息替换为`[LABEL]`（只用正则规则，不影响请求延迟）；
+//! 保存到会话存储的消息替换为`[LABEL]`，不可还原；发给上游的对话消息替换为`[LABEL_n]`这样的占位符，
+//! 同一个值在一次请求中对应同一个占位符，回复中的占位符还原为原值后再返回客户端。
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use futures_util::stream::{self, BoxStream};
+use futures_util::StreamExt;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::audit;
+use crate::config::{NerConfig, PiiConfig};
+use crate::error::{ApiError, ApiResult};
+use crate::types::{ChatCompletionRequest, ChatCompletionResponse, ChatMessage, MessageContent};
+use crate::AppState;
+
+/// 占位符，如`[EMAIL_1]`
+static TOKEN: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[[A-Z][A-Z0-9_]*_\d+\]").unwrap());
+
+/// 流式回复中为拼出完整占位符最多暂缓的字节数
+const MAX_TOKEN_BYTES: usize = 48;
+
+/// 身份证号：地区码、出生日期、顺序码和校验位
+const ID_CARD_PATTERN: &str = r"[1-9]\d{5}(?:19|20)\d{2}(?:0[1-9]|1[0-2])(?:0[1-9]|[12]\d|3[01])\d{3}[\dXx]";
+
+/// 银行卡号：连续或每四位分隔的13到19位数字，另外要通过Luhn校验
+const BANK_CARD_PATTERN: &str = r"[1-9]\d{12,18}|[1-9]\d{3}(?:[- ]\d{4}){3}(?:[- ]?\d{1,3})?";
+
+/// 手机号（可带`+86`）、固定电话和带国际区号的号码
+const PHONE_PATTERNS: &[&str] = &[
+    r"(?:\+?86[- ]?)?1[3-9]\d{9}",
+    r"0\d{2,3}-\d{7,8}",
+    r"\+\d{1,3}[- ]\d{1,4}(?:[- ]?\d{2,4}){2,3}",
+];
+
+#[derive(Debug)]
+struct Rule {
+    label: String,
+    regex: Regex,
+    /// 匹配两侧不能紧挨着数字，避免从更长的数字串中截出一段
+    bounded: bool,
+    /// 去掉分隔符后的数字要通过Luhn校验
+    luhn: bool,
+}
+
+impl Rule {
+    fn new(label: &str, pattern: &str, bounded: bool) -> Result<Rule, String> {
+        let regex = Regex::new(pattern).map_err(|e| format!("无效的个人信息规则 {}: {}", label, e))?;
+        Ok(Rule {
+            label: label.to_string(),
+            regex,
+            bounded,
+            luhn: false,
+        })
+    }
+
+    fn spans(&self, text: &str, spans: &mut Vec<Span>) {
+        for captures in self.regex.captures_iter(text) {
+            let Some(found) = captures.name("pii").or_else(|| captures.get(0)) else {
+                continue;
+            };
+            if self.bounded {
+                let before = text[..found.start()].chars().next_back();
+                let after = text[found.end()..].chars().next();
+                if before.is_some_and(|c| c.is_ascii_digit()) || after.is_some_and(|c| c.is_ascii_digit()) {
+                    continue;
+                }
+            }
+            if self.luhn && !luhn(found.as_str()) {
+                continue;
+            }
+            spans.push(Span {
+                start: found.start(),
+                end: found.end(),
+                label: self.label.clone(),
+            });
+        }
+    }
+}
+
+/// Luhn校验，银行卡号的最后一位是校验位
+fn luhn(number: &str) -> bool {
+    let digits: Vec<u32> = number.chars().filter_map(|c| c.to_digit(10)).collect();
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(index, &digit)| match index % 2 {
+            0 => digit,
+            _ if digit * 2 > 9 => digit * 2 - 9,
+            _ => digit * 2,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// 类别名只保留大写字母、数字和下划线，以便出现在占位符中
+fn normalize_label(label: &str) -> String {
+    let label: String = label
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    if label.starts_with(|c: char| c.is_ascii_uppercase()) {
+        label
+    } else {
+        format!("PII_{}", label)
+    }
+}
+
+/// 文本中的一处个人信息
+#[derive(Debug, Clone)]
+struct Span {
+    start: usize,
+    end: usize,
+    label: String,
+}
+
+/// 按起点排序，重叠时保留先开始的、其次是更长的
+fn merge(mut spans: Vec<Span>) -> Vec<Span> {
+    spans.sort_by(|a, b| a.start.cmp(&b.start).then(b.end.cmp(&a.end)));
+    let mut merged: Vec<Span> = Vec::with_capacity(spans.len());
+    for span in spans {
+        if merged.last().is_none_or(|last| span.start >= last.end) {
+            merged.push(span);
+        }
+    }
+    merged
+}
+
+/// 把各处个人信息替换为`replace`返回的文本
+fn replace(text: &str, spans: &[Span], mut replace: impl FnMut(&Span, &str) -> String) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut offset = 0;
+    for span in spans {
+        out.push_str(&text[offset..span.start]);
+        out.push_str(&replace(span, &text[span.start..span.end]));
+        offset = span.end;
+    }
+    out.push_str(&text[offset..]);
+    out
+}
+
+#[derive(Debug, Deserialize)]
+struct NerResponse {
+    entities: Vec<Vec<NerEntity>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NerEntity {
+    text: String,
+    label: String,
+}
+
+#[derive(Debug)]
+pub struct PiiDetector {
+    config: PiiConfig,
+    rules: Vec<Rule>,
+    http: reqwest::Client,
+}
+
+impl PiiDetector {
+    pub fn new(config: &PiiConfig) -> Result<PiiDetector, String> {
+        let mut rules = Vec::new();
+        if config.emails {
+            rules.push(Rule::new("EMAIL", audit::EMAIL_PATTERN, false)?);
+        }
+        if config.ids {
+            rules.push(Rule::new("ID_CARD", ID_CARD_PATTERN, true)?);
+            rules.push(Rule {
+                luhn: true,
+                ..Rule::new("BANK_CARD", BANK_CARD_PATTERN, true)?
+            });
+        }
+        if config.phones {
+            for pattern in PHONE_PATTERNS {
+                rules.push(Rule::new("PHONE", pattern, true)?);
+            }
+        }
+        for pattern in &config.patterns {
+            if normalize_label(&pattern.label) != pattern.label {
+                return Err(format!("pii.patterns 的 label 只能包含大写字母、数字和下划线: {}", pattern.label));
+            }
+            rules.push(Rule::new(&pattern.label, &pattern.pattern, false)?);
+        }
+        let timeout = config.ner.as_ref().map_or(10, |ner| ner.timeout_seconds);
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(timeout))
+            .build()
+            .map_err(|e| format!("无法创建 HTTP 客户端: {}", e))?;
+        Ok(PiiDetector {
+            config: config.clone(),
+            rules,
+            http,
+        })
+    }
+
+    /// 正则规则找到的个人信息
+    fn scan(&self, text: &str) -> Vec<Span> {
+        let mut spans = Vec::new();
+        for rule in &self.rules {
+            rule.spans(text, &mut spans);
+        }
+        spans
+    }
+
+    /// 调用NER服务，实体在文本中的每次出现都算一处
+    async fn recognize(&self, ner: &NerConfig, texts: &[&str]) -> ApiResult<Vec<Vec<Span>>> {
+        let mut request = self.http.post(&ner.url).json(&json!({ "texts": texts, "labels": ner.labels }));
+        if let Some(api_key) = &ner.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| ApiError::Upstream(format!("调用 NER 服务失败: {}", e)))?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(ApiError::Upstream(format!("NER 服务返回 {}", status)));
+        }
+        let response: NerResponse = response
+            .json()
+            .await
+            .map_err(|e| ApiError::Upstream(format!("无法解析 NER 服务的响应: {}", e)))?;
+        if response.entities.len() != texts.len() {
+            return Err(ApiError::Upstream(format!(
+                "NER 服务返回了 {} 组结果，应为 {} 组",
+                response.entities.len(),
+                texts.len()
+            )));
+        }
+
+        let labels: Vec<String> = ner.labels.iter().map(|label| label.to_uppercase()).collect();
+        Ok(texts
+            .iter()
+            .zip(response.entities)
+            .map(|(text, entities)| {
+                let mut spans = Vec::new();
+                for entity in entities {
+                    let label = entity.label.to_uppercase();
+                    if entity.text.trim().is_empty() || !(labels.is_empty() || labels.contains(&label)) {
+                        continue;
+                    }
+                    for (start, found) in text.match_indices(entity.text.as_str()) {
+                        spans.push(Span {
+                            start,
+                            end: start + found.len(),
+                            label: normalize_label(&label),
+                        });
+                    }
+                }
+                spans
+            })
+            .collect())
+    }
+
+    /// 各段文本中不重叠的个人信息，配置了NER服务时包括它识别出的实体
+    async fn detect(&self, texts: &[&str]) -> ApiResult<Vec<Vec<Span>>> {
+        let mut spans: Vec<Vec<Span>> = texts.iter().map(|text| self.scan(text)).collect();
+        if let Some(ner) = self.config.ner.as_ref().filter(|_| texts.iter().any(|text| !text.trim().is_empty())) {
+            for (spans, recognized) in spans.iter_mut().zip(self.recognize(ner, texts).await?) {
+                spans.extend(recognized);
+            }
+        }
+        Ok(spans.into_iter().map(merge).collect())
+    }
+
+    /// 只按正则规则把个人信息替换为`[LABEL]`，用于日志
+    pub fn redact(&self, text: &str) -> String {
+        let spans = merge(self.scan(text));
+        if spans.is_empty() {
+            return text.to_string();
+        }
+        replace(text, &spans, |span, _| format!("[{}]", span.label))
+    }
+
+    /// 把各段文本中的个人信息替换为`[LABEL]`，不可还原
+    pub async fn redact_texts(&self, mut texts: Vec<&mut String>) -> ApiResult<()> {
+        let detected = self.detect(&texts.iter().map(|text| text.as_str()).collect::<Vec<_>>()).await?;
+        for (text, spans) in texts.iter_mut().zip(detected) {
+            if !spans.is_empty() {
+                **text = replace(text, &spans, |span, _| format!("[{}]", span.label));
+            }
+        }
+        Ok(())
+    }
+
+    /// 把请求消息中的个人信息替换为占位符，返回用于还原回复的对照表
+    pub async fn tokenize(&self, request: &mut ChatCompletionRequest) -> ApiResult<Tokens> {
+        let mut texts: Vec<&mut String> = request.messages.iter_mut().flat_map(message_texts).collect();
+        let detected = self.detect(&texts.iter().map(|text| text.as_str()).collect::<Vec<_>>()).await?;
+        let mut tokens = Tokens::default();
+        for (text, spans) in texts.iter_mut().zip(detected) {
+            if !spans.is_empty() {
+                **text = replace(text, &spans, |span, value| tokens.token(&span.label, value));
+            }
+        }
+        Ok(tokens)
+    }
+}
+
+/// 消息中会发给上游的文本：内容、多模态内容中的文字和历史工具调用的参数
+fn message_texts(message: &mut ChatMessage) -> Vec<&mut String> {
+    let mut texts = Vec::new();
+    match &mut message.content {
+        Some(MessageContent::Text(text)) => texts.push(text),
+        Some(MessageContent::Parts(parts)) => {
+            for part in parts {
+                if let Some(Value::String(text)) = part.get_mut("text") {
+                    texts.push(text);
+                }
+            }
+        }
+        None => {}
+    }
+    if let Some(Value::Array(calls)) = message.extra.get_mut("tool_calls") {
+        for call in calls {
+            if let Some(Value::String(arguments)) = call.pointer_mut("/function/arguments") {
+                texts.push(arguments);
+            }
+        }
+    }
+    texts
+}
+
+/// 一次请求中占位符与原值的对照表
+#[derive(Debug, Default)]
+pub struct Tokens {
+    originals: HashMap<String, String>,
+    /// 按类别和原值查找已分配的占位符
+    assigned: HashMap<(String, String), String>,
+    counts: HashMap<String, usize>,
+}
+
+impl Tokens {
+    fn token(&mut self, label: &str, value: &str) -> String {
+        let key = (label.to_string(), value.to_string());
+        if let Some(token) = self.assigned.get(&key) {
+            return token.clone();
+        }
+        let count = self.counts.entry(label.to_string()).or_default();
+        *count += 1;
+        let token = format!("[{}_{}]", label, count);
+        self.originals.insert(token.clone(), value.to_string());
+        self.assigned.insert(key, token.clone());
+        token
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.originals.is_empty()
+    }
+
+    /// 还原文本中的占位符，不认识的原样保留
+    pub fn restore(&self, text: &str) -> String {
+        if self.is_empty() {
+            return text.to_string();
+        }
+        TOKEN
+            .replace_all(text, |captures: &regex::Captures| {
+                let token = &captures[0];
+                self.originals.get(token).cloned().unwrap_or_else(|| token.to_string())
+            })
+            .into_owned()
+    }
+
+    /// 还原非流式回复的内容和工具调用参数
+    pub fn restore_response(&self, response: &mut ChatCompletionResponse) {
+        if self.is_empty() {
+            return;
+        }
+        for choice in &mut response.choices {
+            for text in message_texts(&mut choice.message) {
+                *text = self.restore(text);
+            }
+        }
+    }
+
+    /// 还原流式回复中的内容；占位符可能被拆到相邻的分块中，末尾疑似不完整的占位符暂缓到下一个分块再发出
+    pub fn restore_stream(self, data: BoxStream<'static, String>) -> BoxStream<'static, String> {
+        if self.is_empty() {
+            return data;
+        }
+
+        struct Restored {
+            events: BoxStream<'static, String>,
+            tokens: Tokens,
+            /// 按选项序号暂缓的内容
+            held: BTreeMap<u64, String>,
+            /// 最近一个分块，用于生成发出暂缓内容的分块
+            chunk: Value,
+            /// 发出暂缓内容之后再转发的`[DONE]`
+            done: Option<String>,
+            finished: bool,
+        }
+
+        impl Restored {
+            /// 拼上暂缓的内容后还原，结束时全部发出
+            fn take(&mut self, index: u64, text: &str, last: bool) -> String {
+                let mut text = self.held.remove(&index).unwrap_or_default() + text;
+                if !last {
+                    if let Some(start) = text.rfind('[').filter(|&start| {
+                        !text[start..].contains(']') && text.len() - start < MAX_TOKEN_BYTES
+                    }) {
+                        self.held.insert(index, text.split_off(start));
+                    }
+                }
+                self.tokens.restore(&text)
+            }
+        }
+
+        let restored = Restored {
+            events: data,
+            tokens: self,
+            held: BTreeMap::new(),
+            chunk: Value::Null,
+            done: None,
+            finished: false,
+        };
+
+        stream::unfold(restored, |mut restored| async move {
+            if let Some(data) = restored.done.take() {
+                restored.finished = true;
+                return Some((data, restored));
+            }
+            if restored.finished {
+                return None;
+            }
+            let data = restored.events.next().await?;
+            if data == "[DONE]" {
+                let held = std::mem::take(&mut restored.held);
+                if held.is_empty() {
+                    restored.finished = true;
+                    return Some((data, restored));
+                }
+                let choices: Vec<Value> = held
+                    .into_iter()
+                    .map(|(index, text)| json!({ "index": index, "delta": { "content": text }, "finish_reason": null }))
+                    .collect();
+                let chunk = json!({
+                    "id": restored.chunk["id"],
+                    "object": "chat.completion.chunk",
+                    "created": restored.chunk["created"],
+                    "model": restored.chunk["model"],
+                    "choices": choices,
+                });
+                restored.done = Some(data);
+                return Some((chunk.to_string(), restored));
+            }
+            let Ok(mut chunk) = serde_json::from_str::<Value>(&data) else {
+                return Some((data, restored));
+            };
+            let mut changed = false;
+            for choice in chunk["choices"].as_array_mut().into_iter().flatten() {
+                let index = choice["index"].as_u64().unwrap_or(0);
+                let last = !choice["finish_reason"].is_null();
+                let text = choice["delta"]["content"].as_str().unwrap_or_default().to_string();
+                if text.is_empty() && !(last && restored.held.contains_key(&index)) {
+                    continue;
+                }
+                choice["delta"]["content"] = Value::from(restored.take(index, &text, last));
+                changed = true;
+            }
+            if chunk.get("id").is_some() {
+                restored.chunk = chunk.clone();
+            }
+            let data = if changed { chunk.to_string() } else { data };
+            Some((data, restored))
+        })
+        .boxed()
+    }
+}
+
+/// 启用`pii.upstream`时把请求中的个人信息替换为占位符，否则返回空的对照表
+pub async fn tokenize(state: &AppState, request: &mut ChatCompletionRequest) -> ApiResult<Tokens> {
+    match &state.pii {
+        Some(pii) if state.config().pii.upstream => pii.tokenize(request).await,
+        _ => Ok(Tokens::default()),
+    }
+}
+
+/// 启用`pii.transcripts`时把要保存的消息中的个人信息替换为`[LABEL]`
+pub async fn redact_transcript(state: &AppState, texts: Vec<&mut String>) -> ApiResult<()> {
+    match &state.pii {
+        Some(pii) if state.config().pii.transcripts => pii.redact_texts(texts).await,
+        _ => Ok(()),
+    }
+}
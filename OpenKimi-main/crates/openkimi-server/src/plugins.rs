@@ -0,0 +1,722 @@
+//! 工具插件
+//!
+//! 第三方可以把工具做成插件放在`plugins.dir`下，每个插件一个子目录，其中的`plugin.json`声明名称、版本、入口文件
+//! 和需要的能力。插件实现同一个工具接口：列出提供的工具（名称、描述和参数的JSON Schema），按名称执行工具并返回结果。
+//! 入口有两种：
+//!
+//! - WebAssembly：编译为WASI的命令行程序，由`wasmtime`运行。`<模块> describe`向标准输出打印工具列表，
+//!   `<模块> execute <工具名>`从标准输入读取参数JSON，向标准输出写结果。沙箱默认没有网络、文件和环境变量，
+//!   只开放声明且被授予的能力。
+//! - 动态库：导出C ABI的`openkimi_plugin_abi_version`、`openkimi_plugin_describe`、`openkimi_plugin_execute`
+//!   和`openkimi_plugin_free`，在本进程中运行，拥有服务的全部权限，需要开启`plugins.allow_native`并授予`native`能力。
+//!
+//! 插件声明的每项能力都必须在`plugins.grants`中授予，否则不加载。后台线程定期检查插件目录，
+//! 新增、修改或删除的插件无需重启即生效，正在执行的调用仍使用旧版本直到结束。
+
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::config::PluginsConfig;
+use crate::interpreter;
+use crate::tools;
+
+/// 插件目录中的清单文件
+pub const MANIFEST: &str = "plugin.json";
+
+/// 动态库插件的ABI版本，`openkimi_plugin_abi_version`必须返回这个值
+pub const ABI_VERSION: u32 = 1;
+
+/// 插件提供的一个工具
+pub trait Tool: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// OpenAI格式的工具定义
+    fn definition(&self) -> &Value;
+
+    /// 执行工具，返回交给模型的结果
+    fn execute<'a>(&'a self, arguments: &'a Value) -> BoxFuture<'a, Result<String, String>>;
+}
+
+/// 插件可以申请的能力
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Capability {
+    /// 访问网络
+    Network,
+    /// 读写插件自己的数据目录，沙箱中为`/data`
+    Storage,
+    /// 读取本机的一个环境变量
+    Env(String),
+    /// 作为动态库在本进程中运行
+    Native,
+}
+
+impl Capability {
+    fn parse(name: &str) -> Result<Capability, String> {
+        match name {
+            "network" => Ok(Capability::Network),
+            "storage" => Ok(Capability::Storage),
+            "native" => Ok(Capability::Native),
+            _ => match name.strip_prefix("env:") {
+                Some(variable) if !variable.is_empty() => Ok(Capability::Env(variable.to_string())),
+                _ => Err(format!("未知的能力 {}（可选 network、storage、env:<变量名>、native）", name)),
+            },
+        }
+    }
+}
+
+/// `plugin.json`
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    name: String,
+    #[serde(default)]
+    version: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+    /// WebAssembly模块，相对插件目录
+    #[serde(default)]
+    module: Option<PathBuf>,
+    /// 动态库，相对插件目录
+    #[serde(default)]
+    library: Option<PathBuf>,
+    #[serde(default)]
+    capabilities: Vec<String>,
+}
+
+/// `describe`的输出
+#[derive(Debug, Deserialize)]
+struct Description {
+    tools: Vec<ToolSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ToolSpec {
+    name: String,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    parameters: Option<Value>,
+}
+
+/// 解析`describe`的输出，返回各工具的名称和定义
+fn parse_description(text: &str) -> Result<Vec<(String, Value)>, String> {
+    let description: Description =
+        serde_json::from_str(text.trim()).map_err(|e| format!("describe 的输出无效: {}", e))?;
+    if description.tools.is_empty() {
+        return Err("插件没有提供任何工具".to_string());
+    }
+    let mut names = HashSet::new();
+    let mut tools = Vec::new();
+    for spec in description.tools {
+        let parameters = spec.parameters.unwrap_or_else(|| json!({ "type": "object", "properties": {} }));
+        let mut function = json!({ "name": spec.name, "parameters": parameters });
+        if let Some(description) = spec.description {
+            function["description"] = Value::from(description);
+        }
+        let definition = json!({ "type": "function", "function": function });
+        tools::check_tool(&definition)?;
+        if !names.insert(spec.name.clone()) {
+            return Err(format!("工具 {} 重复声明", spec.name));
+        }
+        tools.push((spec.name, definition));
+    }
+    Ok(tools)
+}
+
+/// 入口文件只能在插件目录之内
+fn entry_path(dir: &Path, relative: &Path) -> Result<PathBuf, String> {
+    if !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+        return Err(format!("入口文件 {} 必须是插件目录中的相对路径", relative.display()));
+    }
+    let path = dir.join(relative);
+    if !path.is_file() {
+        return Err(format!("入口文件 {} 不存在", relative.display()));
+    }
+    Ok(path)
+}
+
+/// 由wasmtime运行的WebAssembly插件
+struct WasmPlugin {
+    name: String,
+    module: PathBuf,
+    capabilities: Vec<Capability>,
+    data_dir: PathBuf,
+    config: PluginsConfig,
+}
+
+impl WasmPlugin {
+    fn command(&self, args: &[&str]) -> Vec<String> {
+        let config = &self.config;
+        let mut command = vec![config.wasmtime.clone(), "run".to_string()];
+        command.extend(["-W".to_string(), format!("max-memory-size={}", config.max_memory_bytes)]);
+        if config.fuel > 0 {
+            command.extend(["-W".to_string(), format!("fuel={}", config.fuel)]);
+        }
+        for capability in &self.capabilities {
+            match capability {
+                Capability::Network => {
+                    for flag in ["inherit-network=y", "allow-ip-name-lookup=y"] {
+                        command.extend(["-S".to_string(), flag.to_string()]);
+                    }
+                }
+                Capability::Storage => {
+                    command.extend(["--dir".to_string(), format!("{}::/data", self.data_dir.to_string_lossy())]);
+                }
+                Capability::Env(variable) => {
+                    if let Ok(value) = env::var(variable) {
+                        command.extend(["--env".to_string(), format!("{}={}", variable, value)]);
+                    }
+                }
+                Capability::Native => {}
+            }
+        }
+        command.push(self.module.to_string_lossy().into_owned());
+        command.extend(args.iter().map(|arg| arg.to_string()));
+        command
+    }
+
+    /// 运行模块，成功时返回标准输出
+    fn run(&self, args: &[&str], input: Vec<u8>) -> Result<String, String> {
+        if self.capabilities.contains(&Capability::Storage) {
+            fs::create_dir_all(&self.data_dir)
+                .map_err(|e| format!("创建目录 {} 失败: {}", self.data_dir.display(), e))?;
+        }
+        let config = &self.config;
+        let execution =
+            interpreter::execute(&self.command(args), input, config.max_output_bytes, config.timeout_seconds)?;
+        if execution.timed_out {
+            return Err(format!("插件 {} 超过 {} 秒", self.name, config.timeout_seconds));
+        }
+        match execution.status {
+            Some(status) if status.success() => Ok(String::from_utf8_lossy(&execution.stdout).into_owned()),
+            status => {
+                let stderr = String::from_utf8_lossy(&execution.stderr);
+                let stderr = stderr.trim();
+                let tail = &stderr[stderr.char_indices().rev().nth(499).map_or(0, |(i, _)| i)..];
+                let status = status.map_or_else(|| "被结束".to_string(), |status| status.to_string());
+                Err(format!("插件 {} 异常退出（{}）: {}", self.name, status, tail))
+            }
+        }
+    }
+}
+
+struct WasmTool {
+    plugin: Arc<WasmPlugin>,
+    name: String,
+    definition: Value,
+}
+
+impl Tool for WasmTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn definition(&self) -> &Value {
+        &self.definition
+    }
+
+    fn execute<'a>(&'a self, arguments: &'a Value) -> BoxFuture<'a, Result<String, String>> {
+        async move {
+            let (plugin, name) = (Arc::clone(&self.plugin), self.name.clone());
+            let input = arguments.to_string().into_bytes();
+            tokio::task::spawn_blocking(move || plugin.run(&["execute", &name], input))
+                .await
+                .map_err(|e| format!("执行插件失败: {}", e))?
+        }
+        .boxed()
+    }
+}
+
+#[cfg(unix)]
+mod native {
+    use std::ffi::{c_char, c_void, CStr, CString};
+    use std::fs;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+
+    type AbiVersion = unsafe extern "C" fn() -> u32;
+    type Describe = unsafe extern "C" fn() -> *const c_char;
+    type Execute = unsafe extern "C" fn(*const c_char, *const c_char) -> *mut c_char;
+    type Free = unsafe extern "C" fn(*mut c_char);
+
+    /// 已加载的动态库
+    pub struct Library {
+        handle: *mut c_void,
+        /// 加载的是这个副本，替换原文件不会影响已加载的代码，重新加载时也不会拿到缓存的旧库
+        copy: PathBuf,
+        describe: Describe,
+        execute: Execute,
+        free: Free,
+    }
+
+    // SAFETY: 插件ABI要求导出的函数可以在多个线程中同时调用
+    unsafe impl Send for Library {}
+    unsafe impl Sync for Library {}
+
+    fn last_error() -> String {
+        // SAFETY: dlerror返回的字符串在下一次调用dl*之前有效，这里立即复制
+        let message = unsafe { libc::dlerror() };
+        if message.is_null() {
+            return "未知错误".to_string();
+        }
+        unsafe { CStr::from_ptr(message) }.to_string_lossy().into_owned()
+    }
+
+    fn symbol(handle: *mut c_void, name: &CStr) -> Result<*mut c_void, String> {
+        // SAFETY: handle是dlopen返回的有效句柄
+        let symbol = unsafe { libc::dlsym(handle, name.as_ptr()) };
+        if symbol.is_null() {
+            return Err(format!("缺少导出函数 {}", name.to_string_lossy()));
+        }
+        Ok(symbol)
+    }
+
+    impl Library {
+        /// 把`path`复制到`copy`后加载，检查ABI版本
+        pub fn open(path: &Path, copy: PathBuf) -> Result<Library, String> {
+            fs::copy(path, &copy).map_err(|e| format!("复制 {} 失败: {}", path.display(), e))?;
+            let name = CString::new(copy.as_os_str().as_bytes()).map_err(|e| e.to_string())?;
+            // SAFETY: 加载会执行库的初始化代码，只有管理员授予了native能力的插件才会走到这里
+            let handle = unsafe { libc::dlopen(name.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+            if handle.is_null() {
+                let _ = fs::remove_file(&copy);
+                return Err(format!("加载 {} 失败: {}", path.display(), last_error()));
+            }
+            let resolve = || -> Result<Library, String> {
+                // SAFETY: 导出函数的签名由插件ABI约定
+                let version = unsafe {
+                    let symbol = symbol(handle, c"openkimi_plugin_abi_version")?;
+                    let abi_version = std::mem::transmute::<*mut c_void, AbiVersion>(symbol);
+                    abi_version()
+                };
+                if version != super::ABI_VERSION {
+                    return Err(format!("ABI 版本为 {}，本服务需要 {}", version, super::ABI_VERSION));
+                }
+                Ok(Library {
+                    handle,
+                    copy: copy.clone(),
+                    describe: unsafe {
+                        std::mem::transmute::<*mut c_void, Describe>(symbol(handle, c"openkimi_plugin_describe")?)
+                    },
+                    execute: unsafe {
+                        std::mem::transmute::<*mut c_void, Execute>(symbol(handle, c"openkimi_plugin_execute")?)
+                    },
+                    free: unsafe { std::mem::transmute::<*mut c_void, Free>(symbol(handle, c"openkimi_plugin_free")?) },
+                })
+            };
+            resolve().inspect_err(|_| {
+                // SAFETY: 句柄有效且没有被其他地方使用
+                unsafe { libc::dlclose(handle) };
+                let _ = fs::remove_file(&copy);
+            })
+        }
+
+        /// `describe`的输出
+        pub fn describe(&self) -> Result<String, String> {
+            // SAFETY: 插件ABI约定返回的字符串在库加载期间有效
+            let text = unsafe { (self.describe)() };
+            if text.is_null() {
+                return Err("openkimi_plugin_describe 返回了空指针".to_string());
+            }
+            Ok(unsafe { CStr::from_ptr(text) }.to_string_lossy().into_owned())
+        }
+
+        /// 执行工具，返回插件输出的JSON
+        pub fn execute(&self, tool: &str, arguments: &str) -> Result<String, String> {
+            let tool = CString::new(tool).map_err(|e| e.to_string())?;
+            let arguments = CString::new(arguments).map_err(|e| e.to_string())?;
+            // SAFETY: 参数是有效的C字符串，返回值由插件分配，复制后交还给openkimi_plugin_free释放
+            let output = unsafe { (self.execute)(tool.as_ptr(), arguments.as_ptr()) };
+            if output.is_null() {
+                return Err("openkimi_plugin_execute 返回了空指针".to_string());
+            }
+            let text = unsafe { CStr::from_ptr(output) }.to_string_lossy().into_owned();
+            unsafe { (self.free)(output) };
+            Ok(text)
+        }
+    }
+
+    impl Drop for Library {
+        fn drop(&mut self) {
+            // SAFETY: 最后一个引用释放时已没有调用在执行
+            unsafe { libc::dlclose(self.handle) };
+            let _ = fs::remove_file(&self.copy);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod native {
+    use std::path::{Path, PathBuf};
+
+    pub struct Library;
+
+    impl Library {
+        pub fn open(_path: &Path, _copy: PathBuf) -> Result<Library, String> {
+            Err("当前平台不支持动态库插件".to_string())
+        }
+
+        pub fn describe(&self) -> Result<String, String> {
+            unreachable!()
+        }
+
+        pub fn execute(&self, _tool: &str, _arguments: &str) -> Result<String, String> {
+            unreachable!()
+        }
+    }
+}
+
+use native::Library;
+
+struct NativeTool {
+    library: Arc<Library>,
+    name: String,
+    definition: Value,
+    timeout: Duration,
+}
+
+impl Tool for NativeTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn definition(&self) -> &Value {
+        &self.definition
+    }
+
+    fn execute<'a>(&'a self, arguments: &'a Value) -> BoxFuture<'a, Result<String, String>> {
+        async move {
+            let (library, name, arguments) = (Arc::clone(&self.library), self.name.clone(), arguments.to_string());
+            let task = tokio::task::spawn_blocking(move || library.execute(&name, &arguments));
+            // 本进程中的代码无法强行结束，超时后不再等待结果
+            let output = tokio::time::timeout(self.timeout, task)
+                .await
+                .map_err(|_| format!("插件工具 {} 超过 {} 秒", self.name, self.timeout.as_secs()))?
+                .map_err(|e| format!("执行插件失败: {}", e))??;
+            let output: Value =
+                serde_json::from_str(&output).map_err(|e| format!("插件的返回不是有效的 JSON: {}", e))?;
+            if let Some(error) = output.get("error") {
+                return Err(error.as_str().map_or_else(|| error.to_string(), str::to_string));
+            }
+            match output.get("output") {
+                Some(Value::String(text)) => Ok(text.clone()),
+                Some(value) => Ok(value.to_string()),
+                None => Err("插件的返回缺少 output".to_string()),
+            }
+        }
+        .boxed()
+    }
+}
+
+/// 插件目录的指纹：其中各文件的名称、大小和修改时间，任何一项变化都重新加载
+type Fingerprint = Vec<(String, u64, Option<SystemTime>)>;
+
+fn fingerprint(dir: &Path) -> Fingerprint {
+    let mut files: Fingerprint = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok().filter(|metadata| metadata.is_file())?;
+            Some((entry.file_name().to_string_lossy().into_owned(), metadata.len(), metadata.modified().ok()))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// 插件的状态
+#[derive(Debug, Clone, Serialize)]
+pub struct PluginInfo {
+    /// 清单无法解析时为目录名
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// `wasm`或`native`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<&'static str>,
+    pub path: String,
+    pub capabilities: Vec<String>,
+    pub tools: Vec<String>,
+    /// `loaded`或`rejected`
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub loaded_at: u64,
+}
+
+/// 一个插件目录的加载结果
+struct Entry {
+    fingerprint: Fingerprint,
+    info: PluginInfo,
+    tools: Vec<Arc<dyn Tool>>,
+    /// 加载失败的原因，与其他工具重名不在此列，每次重建索引时重新判断
+    failure: Option<String>,
+}
+
+/// 当前生效的工具
+#[derive(Default)]
+struct Published {
+    tools: BTreeMap<String, Arc<dyn Tool>>,
+    definitions: Vec<Value>,
+}
+
+/// 插件的发现、加载和热更新
+pub struct PluginRegistry {
+    config: PluginsConfig,
+    /// 配置中的服务端工具和内置工具，插件不能与它们重名
+    reserved: HashSet<String>,
+    /// 同时只有一次扫描
+    entries: Mutex<BTreeMap<PathBuf, Entry>>,
+    published: RwLock<Arc<Published>>,
+}
+
+impl fmt::Debug for PluginRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tools: Vec<String> = self.published.read().unwrap().tools.keys().cloned().collect();
+        f.debug_struct("PluginRegistry").field("dir", &self.config.dir).field("tools", &tools).finish()
+    }
+}
+
+impl PluginRegistry {
+    /// 加载`plugins.dir`中的插件，`reload_interval_seconds`大于0时启动检查变化的线程
+    pub fn open(config: &PluginsConfig, reserved: HashSet<String>) -> Result<Arc<PluginRegistry>, String> {
+        for dir in [&config.dir, &config.data_dir] {
+            fs::create_dir_all(dir).map_err(|e| format!("创建目录 {} 失败: {}", dir.display(), e))?;
+        }
+        // 上次运行留下的动态库副本
+        let _ = fs::remove_dir_all(config.data_dir.join(".native"));
+        let registry = Arc::new(PluginRegistry {
+            config: config.clone(),
+            reserved,
+            entries: Mutex::new(BTreeMap::new()),
+            published: RwLock::new(Arc::new(Published::default())),
+        });
+        registry.reload();
+
+        if config.reload_interval_seconds > 0 {
+            let interval = Duration::from_secs(config.reload_interval_seconds);
+            let registry = Arc::clone(&registry);
+            std::thread::spawn(move || loop {
+                std::thread::sleep(interval);
+                registry.reload();
+            });
+        }
+        Ok(registry)
+    }
+
+    pub fn tool(&self, name: &str) -> Option<Arc<dyn Tool>> {
+        self.published.read().unwrap().tools.get(name).cloned()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.published.read().unwrap().tools.contains_key(name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.published.read().unwrap().tools.is_empty()
+    }
+
+    /// 当前生效的工具定义
+    pub fn definitions(&self) -> Vec<Value> {
+        self.published.read().unwrap().definitions.clone()
+    }
+
+    pub fn list(&self) -> Vec<PluginInfo> {
+        self.entries.lock().unwrap().values().map(|entry| entry.info.clone()).collect()
+    }
+
+    /// 扫描插件目录，重新加载有变化的插件，返回各插件的状态
+    pub fn reload(&self) -> Vec<PluginInfo> {
+        let mut entries = self.entries.lock().unwrap();
+        let mut found = BTreeMap::new();
+        for item in fs::read_dir(&self.config.dir).into_iter().flatten().flatten() {
+            let dir = item.path();
+            if dir.is_dir() && dir.join(MANIFEST).is_file() {
+                let fingerprint = fingerprint(&dir);
+                found.insert(dir, fingerprint);
+            }
+        }
+
+        let mut changed = false;
+        entries.retain(|dir, entry| {
+            let keep = found.contains_key(dir);
+            if !keep {
+                println!("🔌 插件 {} 已移除", entry.info.name);
+                changed = true;
+            }
+            keep
+        });
+        for (dir, fingerprint) in found {
+            if entries.get(&dir).is_some_and(|entry| entry.fingerprint == fingerprint) {
+                continue;
+            }
+            changed = true;
+            let entry = self.load(&dir, fingerprint);
+            match &entry.failure {
+                None => println!("🔌 已加载插件 {}，工具: {}", entry.info.name, entry.info.tools.join("、")),
+                Some(failure) => eprintln!("⚠️ 插件 {} 未加载: {}", entry.info.name, failure),
+            }
+            entries.insert(dir, entry);
+        }
+        if changed {
+            self.publish(&mut entries);
+        }
+        entries.values().map(|entry| entry.info.clone()).collect()
+    }
+
+    /// 按目录顺序重建工具索引，与已有工具重名的插件整个不生效
+    fn publish(&self, entries: &mut BTreeMap<PathBuf, Entry>) {
+        let mut published = Published::default();
+        for entry in entries.values_mut() {
+            let conflict = entry.tools.iter().map(|tool| tool.name()).find_map(|name| {
+                if self.reserved.contains(name) {
+                    Some(format!("工具 {} 与服务端工具重名", name))
+                } else if published.tools.contains_key(name) {
+                    Some(format!("工具 {} 已由其他插件提供", name))
+                } else {
+                    None
+                }
+            });
+            let error = entry.failure.clone().or(conflict);
+            entry.info.status = if error.is_none() { "loaded" } else { "rejected" };
+            if error.is_some() && entry.failure.is_none() {
+                eprintln!("⚠️ 插件 {} 未生效: {}", entry.info.name, error.as_deref().unwrap_or_default());
+            }
+            entry.info.error = error;
+            if entry.info.status == "loaded" {
+                for tool in &entry.tools {
+                    published.definitions.push(tool.definition().clone());
+                    published.tools.insert(tool.name().to_string(), Arc::clone(tool));
+                }
+            }
+        }
+        *self.published.write().unwrap() = Arc::new(published);
+    }
+
+    fn load(&self, dir: &Path, fingerprint: Fingerprint) -> Entry {
+        let mut info = PluginInfo {
+            name: dir.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default(),
+            version: None,
+            description: None,
+            kind: None,
+            path: dir.to_string_lossy().into_owned(),
+            capabilities: Vec::new(),
+            tools: Vec::new(),
+            status: "rejected",
+            error: None,
+            loaded_at: unix_now(),
+        };
+        let (tools, failure) = match self.load_tools(dir, &mut info) {
+            Ok(tools) => (tools, None),
+            Err(failure) => (Vec::new(), Some(failure)),
+        };
+        info.tools = tools.iter().map(|tool| tool.name().to_string()).collect();
+        info.error = failure.clone();
+        Entry {
+            fingerprint,
+            info,
+            tools,
+            failure,
+        }
+    }
+
+    fn load_tools(&self, dir: &Path, info: &mut PluginInfo) -> Result<Vec<Arc<dyn Tool>>, String> {
+        let text = fs::read_to_string(dir.join(MANIFEST)).map_err(|e| format!("读取 {} 失败: {}", MANIFEST, e))?;
+        let manifest: Manifest = serde_json::from_str(&text).map_err(|e| format!("{} 无效: {}", MANIFEST, e))?;
+        tools::check_name(&manifest.name).map_err(|e| format!("插件名无效: {}", e))?;
+        info.name = manifest.name.clone();
+        info.version = manifest.version.clone();
+        info.description = manifest.description.clone();
+        info.capabilities = manifest.capabilities.clone();
+
+        let mut capabilities = manifest
+            .capabilities
+            .iter()
+            .map(|name| Capability::parse(name))
+            .collect::<Result<Vec<_>, _>>()?;
+        let native = match (&manifest.module, &manifest.library) {
+            (Some(_), None) => false,
+            (None, Some(_)) => true,
+            _ => return Err("module 和 library 必须且只能指定一个".to_string()),
+        };
+        info.kind = Some(if native { "native" } else { "wasm" });
+        if native {
+            if !self.config.allow_native {
+                return Err("动态库插件需要开启 plugins.allow_native".to_string());
+            }
+            if !capabilities.contains(&Capability::Native) {
+                capabilities.push(Capability::Native);
+                info.capabilities.push("native".to_string());
+            }
+        }
+        let granted = self.config.grants.get(&manifest.name);
+        let missing: Vec<&str> = info
+            .capabilities
+            .iter()
+            .map(String::as_str)
+            .filter(|name| !granted.is_some_and(|granted| granted.iter().any(|grant| grant == name)))
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!("未授予能力 {}（见 plugins.grants.{}）", missing.join("、"), manifest.name));
+        }
+
+        if let Some(library) = &manifest.library {
+            let path = entry_path(dir, library)?;
+            let copies = self.config.data_dir.join(".native");
+            fs::create_dir_all(&copies).map_err(|e| format!("创建目录 {} 失败: {}", copies.display(), e))?;
+            let suffix = interpreter::random_hex()?;
+            let extension = path.extension().map(|extension| extension.to_string_lossy().into_owned());
+            let copy = copies.join(format!("{}-{}.{}", manifest.name, suffix, extension.unwrap_or_default()));
+            let library = Arc::new(Library::open(&path, copy)?);
+            let timeout = Duration::from_secs(self.config.timeout_seconds);
+            let tools = parse_description(&library.describe()?)?;
+            return Ok(tools
+                .into_iter()
+                .map(|(name, definition)| {
+                    Arc::new(NativeTool {
+                        library: Arc::clone(&library),
+                        name,
+                        definition,
+                        timeout,
+                    }) as Arc<dyn Tool>
+                })
+                .collect());
+        }
+
+        let module = entry_path(dir, manifest.module.as_deref().unwrap_or(Path::new("")))?;
+        let plugin = Arc::new(WasmPlugin {
+            name: manifest.name.clone(),
+            module,
+            capabilities,
+            data_dir: self.config.data_dir.join(&manifest.name),
+            config: self.config.clone(),
+        });
+        let tools = parse_description(&plugin.run(&["describe"], Vec::new())?)?;
+        Ok(tools
+            .into_iter()
+            .map(|(name, definition)| {
+                Arc::new(WasmTool {
+                    plugin: Arc::clone(&plugin),
+                    name,
+                    definition,
+                }) as Arc<dyn Tool>
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,222 @@
+//! 上游密钥与端点池
+//!
+//! 每个后端一个池。参与分配的有密钥库中`provider`组的密钥（都使用后端的`api_url`）和`endpoints`中的端点；
+//! 密钥库中没有可用密钥时，`api_url`加`api_key`作为一个端点参与。
+//! 每次请求按`load_balancing`排出尝试顺序，遇到429、5xx、401/403或连接失败时换下一个重试，
+//! 最多尝试`max_attempts`个。暂停中和已用满每分钟上限的密钥排在最后或跳过。
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use serde::Serialize;
+
+use crate::config::{BackendConfig, LoadBalancing, DEFAULT_BACKEND};
+use crate::error::{ApiError, ApiResult};
+use crate::health::{Health, HealthInfo, KeyOutcome, Snapshot};
+use crate::vault::KeyVault;
+
+/// 后端`api_url`对应端点的id
+const PRIMARY: &str = "primary";
+
+#[derive(Debug)]
+struct Endpoint {
+    id: String,
+    base_url: String,
+    api_key: Option<String>,
+    rpm: Option<u32>,
+    health: Mutex<Health>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Source {
+    Vault,
+    /// `endpoints`中的下标
+    Endpoint(usize),
+}
+
+/// 一次尝试使用的地址和密钥
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub id: String,
+    pub base_url: String,
+    pub api_key: Option<String>,
+    source: Source,
+}
+
+/// 管理接口返回的端点信息
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointInfo {
+    pub backend: String,
+    pub id: String,
+    pub api_url: String,
+    pub rpm: Option<u32>,
+    pub health: HealthInfo,
+}
+
+#[derive(Debug)]
+pub struct UpstreamPool {
+    /// 所属后端
+    backend: String,
+    provider: String,
+    strategy: LoadBalancing,
+    max_attempts: usize,
+    /// 第一个是`api_url`，其后是`endpoints`
+    endpoints: Vec<Endpoint>,
+    vault: Option<Arc<KeyVault>>,
+    cursor: AtomicUsize,
+}
+
+fn trim_url(url: &str) -> String {
+    url.trim_end_matches('/').to_string()
+}
+
+impl UpstreamPool {
+    pub fn new(name: &str, config: &BackendConfig, vault: Option<Arc<KeyVault>>) -> Result<UpstreamPool, String> {
+        let mut endpoints = vec![Endpoint {
+            id: PRIMARY.to_string(),
+            base_url: config.api_url(),
+            api_key: config.api_key.clone(),
+            rpm: config.rpm,
+            health: Mutex::new(Health::default()),
+        }];
+        for (index, endpoint) in config.endpoints.iter().enumerate() {
+            if endpoint.api_url.is_empty() {
+                let section = if name == DEFAULT_BACKEND { "llm".to_string() } else { format!("backends.{}", name) };
+                return Err(format!("{}.endpoints[{}] 缺少 api_url", section, index));
+            }
+            endpoints.push(Endpoint {
+                id: endpoint.name.clone().unwrap_or_else(|| format!("endpoint-{}", index + 1)),
+                base_url: trim_url(&endpoint.api_url),
+                api_key: endpoint.api_key.clone().or_else(|| config.api_key.clone()),
+                rpm: endpoint.rpm,
+                health: Mutex::new(Health::default()),
+            });
+        }
+        Ok(UpstreamPool {
+            backend: name.to_string(),
+            provider: config.provider().to_string(),
+            strategy: config.load_balancing,
+            max_attempts: config.max_attempts.max(1) as usize,
+            endpoints,
+            vault,
+            cursor: AtomicUsize::new(0),
+        })
+    }
+
+    /// 后端的`api_url`
+    pub fn base_url(&self) -> &str {
+        &self.endpoints[0].base_url
+    }
+
+    fn endpoint_target(&self, index: usize, now: Instant) -> (Target, Snapshot) {
+        let endpoint = &self.endpoints[index];
+        let snapshot = endpoint.health.lock().unwrap().snapshot(endpoint.rpm, now);
+        let target = Target {
+            id: endpoint.id.clone(),
+            base_url: endpoint.base_url.clone(),
+            api_key: endpoint.api_key.clone(),
+            source: Source::Endpoint(index),
+        };
+        (target, snapshot)
+    }
+
+    /// 本次请求依次尝试的目标
+    ///
+    /// 可用的目标按策略排序；都不可用时只尝试暂停中最早恢复的那个，全部用满每分钟上限时返回429。
+    pub fn plan(&self) -> ApiResult<Vec<Target>> {
+        let now = Instant::now();
+        let mut candidates: Vec<(Target, Snapshot)> = self
+            .vault
+            .as_ref()
+            .map(|vault| vault.keys(&self.provider, now))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(lease, snapshot)| {
+                let target = Target {
+                    id: lease.id,
+                    base_url: self.base_url().to_string(),
+                    api_key: Some(lease.secret),
+                    source: Source::Vault,
+                };
+                (target, snapshot)
+            })
+            .collect();
+        if candidates.is_empty() {
+            candidates.push(self.endpoint_target(0, now));
+        }
+        candidates.extend((1..self.endpoints.len()).map(|index| self.endpoint_target(index, now)));
+
+        let (mut ready, waiting): (Vec<_>, Vec<_>) =
+            candidates.into_iter().partition(|(_, snapshot)| snapshot.ready_at.is_none());
+        if ready.is_empty() {
+            let earliest = waiting
+                .into_iter()
+                .filter(|(_, snapshot)| !snapshot.throttled)
+                .min_by_key(|(_, snapshot)| snapshot.ready_at);
+            return match earliest {
+                Some((target, _)) => Ok(vec![target]),
+                None => Err(ApiError::RateLimited(
+                    "所有上游密钥都已达到每分钟请求数上限，请稍后重试".to_string(),
+                )),
+            };
+        }
+
+        let start = self.cursor.fetch_add(1, Ordering::Relaxed) % ready.len();
+        ready.rotate_left(start);
+        if self.strategy == LoadBalancing::LeastErrors {
+            // 稳定排序，错误数相同的仍按轮询顺序
+            ready.sort_by_key(|(_, snapshot)| (snapshot.consecutive_failures, snapshot.failures));
+        }
+        ready.truncate(self.max_attempts);
+        Ok(ready.into_iter().map(|(target, _)| target).collect())
+    }
+
+    /// 记录一次使用，计入每分钟请求数
+    pub fn mark_used(&self, target: &Target) {
+        let now = Instant::now();
+        match target.source {
+            Source::Vault => {
+                if let Some(vault) = &self.vault {
+                    vault.mark_used(&target.id, now);
+                }
+            }
+            Source::Endpoint(index) => self.endpoints[index].health.lock().unwrap().mark_used(now),
+        }
+    }
+
+    pub fn report(&self, target: &Target, outcome: KeyOutcome) {
+        match target.source {
+            Source::Vault => {
+                if let Some(vault) = &self.vault {
+                    vault.report(&target.id, outcome);
+                }
+            }
+            Source::Endpoint(index) => {
+                let mut health = self.endpoints[index].health.lock().unwrap();
+                if health.record(outcome) {
+                    eprintln!(
+                        "⚠️ 后端 {} 的上游端点 {} ({}) 请求失败: {}",
+                        self.backend,
+                        target.id,
+                        target.base_url,
+                        health.last_error().unwrap_or_default()
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn endpoints(&self) -> Vec<EndpointInfo> {
+        self.endpoints
+            .iter()
+            .map(|endpoint| EndpointInfo {
+                backend: self.backend.clone(),
+                id: endpoint.id.clone(),
+                api_url: endpoint.base_url.clone(),
+                rpm: endpoint.rpm,
+                health: endpoint.health.lock().unwrap().info(true, endpoint.rpm),
+            })
+            .collect()
+    }
+}
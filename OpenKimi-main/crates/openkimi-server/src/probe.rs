@@ -0,0 +1,243 @@
+//! 健康检查和就绪检查
+//!
+//! `/healthz`和`/readyz`检查会话数据库能否读取、PostgreSQL和Redis能否访问、向量存储是否可用以及各上游端点能否连通，
+//! 结果缓存`health.cache_seconds`，频繁的探测不会放大到依赖上。`/healthz`总是返回200，供存活探针和桌面端的服务状态显示；
+//! `/readyz`在必需的检查失败时返回503，供就绪探针在依赖恢复之前把实例摘出负载均衡。
+//! 两个接口都不需要认证，也不受限流影响。
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use futures_util::future::join_all;
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use crate::audit;
+use crate::config::HealthCheckConfig;
+use crate::AppState;
+
+/// 一项检查的结果
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    /// `database`、`postgres`、`redis`、`vector_store`或`upstream`
+    pub name: &'static str,
+    /// 检查的对象，如索引目录或`<后端>/<端点>`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// `ok`或`error`
+    pub status: &'static str,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// 该项失败是否导致未就绪；上游端点只有在所在后端的端点都不可达时为`true`
+    pub required: bool,
+}
+
+/// 一次检查的汇总
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    /// `ok`、`degraded`（有非必需的检查失败）或`unavailable`（有必需的检查失败）
+    pub status: &'static str,
+    pub version: &'static str,
+    pub uptime_seconds: u64,
+    /// 检查完成的时间
+    pub checked_at: String,
+    pub checks: Vec<Check>,
+}
+
+#[derive(Debug)]
+pub struct Probe {
+    config: HealthCheckConfig,
+    http: reqwest::Client,
+    started: Instant,
+    /// 上次的结果；检查期间持有锁，同时到达的探测等待同一次检查
+    cached: Mutex<Option<(Instant, Report)>>,
+}
+
+impl Probe {
+    pub fn new(config: &HealthCheckConfig) -> Result<Probe, String> {
+        let http = reqwest::Client::builder()
+            .user_agent(concat!("openkimi-server/", env!("CARGO_PKG_VERSION")))
+            .timeout(Duration::from_secs(config.timeout_seconds.max(1)))
+            .build()
+            .map_err(|e| format!("创建HTTP客户端失败: {}", e))?;
+        Ok(Probe {
+            config: config.clone(),
+            http,
+            started: Instant::now(),
+            cached: Mutex::new(None),
+        })
+    }
+
+    /// 缓存过期时重新检查
+    pub async fn report(&self, state: &Arc<AppState>) -> Report {
+        let mut cached = self.cached.lock().await;
+        let ttl = Duration::from_secs(self.config.cache_seconds);
+        let mut report = match cached.as_ref() {
+            Some((checked, report)) if checked.elapsed() < ttl => report.clone(),
+            _ => {
+                let report = self.run(state).await;
+                *cached = Some((Instant::now(), report.clone()));
+                report
+            }
+        };
+        report.uptime_seconds = self.started.elapsed().as_secs();
+        if !self.config.details {
+            for check in &mut report.checks {
+                check.target = None;
+                check.error = None;
+            }
+        }
+        report
+    }
+
+    async fn run(&self, state: &Arc<AppState>) -> Report {
+        let database = async {
+            let sessions = state.sessions.clone()?;
+            let ping = async move { sessions.ping().await.map(|_| None).map_err(|e| e.to_string()) };
+            Some(self.check("database", None, true, ping).await)
+        };
+        let redis = async {
+            let redis = state.redis.clone()?;
+            let ping = async move { redis.ping().await.map(|_| None).map_err(|e| e.to_string()) };
+            Some(self.check("redis", None, true, ping).await)
+        };
+        let postgres = async {
+            let pool = state.postgres.clone()?;
+            let ping = async move {
+                tokio::task::spawn_blocking(move || pool.ping().map(|_| None).map_err(|e| e.to_string()))
+                    .await
+                    .map_err(|e| format!("检查任务失败: {}", e))?
+            };
+            Some(self.check("postgres", None, true, ping).await)
+        };
+        let indexes = Arc::clone(state);
+        let vector_store = async move {
+            tokio::task::spawn_blocking(move || indexes.indexes.check().map(Some))
+                .await
+                .map_err(|e| format!("检查任务失败: {}", e))?
+        };
+        let endpoints: Vec<_> = state
+            .models()
+            .backends()
+            .flat_map(|backend| backend.upstream.pool().endpoints())
+            .collect();
+        let upstreams = join_all(endpoints.iter().map(|endpoint| {
+            let target = format!("{}/{}", endpoint.backend, endpoint.id);
+            self.check("upstream", Some(target), false, self.reach(&endpoint.api_url))
+        }));
+        let (database, postgres, redis, vector_store, upstreams) = tokio::join!(
+            database,
+            postgres,
+            redis,
+            self.check("vector_store", None, true, vector_store),
+            upstreams
+        );
+
+        let mut checks: Vec<Check> = database.into_iter().chain(postgres).chain(redis).collect();
+        checks.push(vector_store);
+        // 每个后端只要有一个端点可达就能提供服务
+        let reachable: HashSet<&str> = endpoints
+            .iter()
+            .zip(&upstreams)
+            .filter(|(_, check)| check.error.is_none())
+            .map(|(endpoint, _)| endpoint.backend.as_str())
+            .collect();
+        for (endpoint, mut check) in endpoints.iter().zip(upstreams) {
+            check.required = self.config.upstream && !reachable.contains(endpoint.backend.as_str());
+            checks.push(check);
+        }
+
+        let status = if checks.iter().any(|check| check.required && check.error.is_some()) {
+            "unavailable"
+        } else if checks.iter().any(|check| check.error.is_some()) {
+            "degraded"
+        } else {
+            "ok"
+        };
+        Report {
+            status,
+            version: env!("CARGO_PKG_VERSION"),
+            uptime_seconds: 0,
+            checked_at: audit::rfc3339(audit::unix_ms()),
+            checks,
+        }
+    }
+
+    /// 运行一项检查，超过`health.timeout_seconds`视为失败；成功时可以返回检查的位置
+    async fn check(
+        &self,
+        name: &'static str,
+        target: Option<String>,
+        required: bool,
+        future: impl Future<Output = Result<Option<String>, String>>,
+    ) -> Check {
+        let started = Instant::now();
+        let timeout = Duration::from_secs(self.config.timeout_seconds.max(1));
+        let result = match tokio::time::timeout(timeout, future).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("{} 秒内没有完成", timeout.as_secs())),
+        };
+        let latency_ms = started.elapsed().as_millis() as u64;
+        match result {
+            Ok(location) => Check {
+                name,
+                target: target.or(location),
+                status: "ok",
+                latency_ms,
+                error: None,
+                required,
+            },
+            Err(error) => Check {
+                name,
+                target,
+                status: "error",
+                latency_ms,
+                error: Some(error),
+                required,
+            },
+        }
+    }
+
+    /// 请求端点的`/models`，收到5xx以外的任何响应都算可达；不带密钥，不影响密钥的健康状态
+    async fn reach(&self, api_url: &str) -> Result<Option<String>, String> {
+        let response = self
+            .http
+            .get(format!("{}/models", api_url.trim_end_matches('/')))
+            .send()
+            .await
+            .map_err(|e| format!("连接 {} 失败: {}", api_url, e))?;
+        if response.status().is_server_error() {
+            return Err(format!("{} 返回 {}", api_url, response.status()));
+        }
+        Ok(None)
+    }
+}
+
+/// `/healthz`和`/readyz`
+pub fn router(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(state)
+}
+
+async fn healthz(State(state): State<Arc<AppState>>) -> Json<Report> {
+    Json(state.probe.report(&state).await)
+}
+
+async fn readyz(State(state): State<Arc<AppState>>) -> Response {
+    let report = state.probe.report(&state).await;
+    let status = match report.status {
+        "unavailable" => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::OK,
+    };
+    (status, Json(report)).into_response()
+}
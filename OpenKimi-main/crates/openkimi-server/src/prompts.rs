@@ -0,0 +1,611 @@
+//! 提示词模板
+//!
+//! 系统提示词以命名模板的形式保存在服务端，每次保存产生一个新版本，旧版本保留。对话请求中的
+//! `"prompt": {"name": ..., "version": ..., "variables": {...}}`由服务端渲染后作为系统消息放在最前面，
+//! 客户端不必再拼接提示词字符串。模板语法见[`openkimi_template`]，变量按模板声明的类型校验。
+//! 模板按工作区隔离，保存在`prompts.dir`下的JSON文件或`prompts.database`指定的SQLite数据库中，
+//! 启动时全部载入内存。
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::extract::{Path, Query, State};
+use axum::Json;
+use openkimi_schema::sqlite::{migrate, Migration};
+use openkimi_schema::SchemaError;
+use openkimi_template::Template;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::config::{PromptStoreKind, PromptsConfig};
+use crate::error::{ApiError, ApiResult};
+use crate::types::{ChatCompletionRequest, ChatMessage, DeletedResponse, ListResponse, MessageContent};
+use crate::workspace::Workspace;
+use crate::AppState;
+
+/// `prompts.store`为`sqlite`时数据库各版本的迁移，下标加一即版本号
+pub(crate) const MIGRATIONS: &[Migration] = &[
+    // v1即加入版本号之前的prompts表，不能回退
+    Migration::sql(
+        "提示词模板",
+        "
+        CREATE TABLE IF NOT EXISTS prompts (
+            workspace TEXT NOT NULL,
+            name TEXT NOT NULL,
+            version INTEGER NOT NULL,
+            description TEXT,
+            template TEXT NOT NULL,
+            variables TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            PRIMARY KEY (workspace, name, version)
+        );
+        ",
+        None,
+    ),
+];
+
+/// 变量类型
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VariableKind {
+    #[default]
+    String,
+    Number,
+    Integer,
+    Boolean,
+    Array,
+    Object,
+    /// 不检查类型
+    Any,
+}
+
+impl VariableKind {
+    fn accepts(self, value: &Value) -> bool {
+        match self {
+            VariableKind::String => value.is_string(),
+            VariableKind::Number => value.is_number(),
+            VariableKind::Integer => value.is_i64() || value.is_u64(),
+            VariableKind::Boolean => value.is_boolean(),
+            VariableKind::Array => value.is_array(),
+            VariableKind::Object => value.is_object(),
+            VariableKind::Any => true,
+        }
+    }
+}
+
+fn required_by_default() -> bool {
+    true
+}
+
+/// 模板声明的变量
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Variable {
+    pub name: String,
+    #[serde(rename = "type", default)]
+    pub kind: VariableKind,
+    /// 为`true`且没有`default`时渲染必须提供
+    #[serde(default = "required_by_default")]
+    pub required: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// 模板的一个版本
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptTemplate {
+    pub name: String,
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub template: String,
+    #[serde(default)]
+    pub variables: Vec<Variable>,
+    /// Unix时间戳（秒）
+    pub created_at: u64,
+}
+
+impl PromptTemplate {
+    /// 按声明校验提供的变量并补上默认值
+    pub fn bind(&self, values: &Map<String, Value>) -> ApiResult<Map<String, Value>> {
+        if let Some(name) = values.keys().find(|name| !self.variables.iter().any(|v| &v.name == *name)) {
+            return Err(ApiError::invalid_request(format!("模板 {} 没有声明变量 {}", self.name, name)));
+        }
+        let mut bound = Map::new();
+        for variable in &self.variables {
+            let value = match values.get(&variable.name).or(variable.default.as_ref()) {
+                Some(value) => value,
+                None if variable.required => {
+                    return Err(ApiError::invalid_request(format!(
+                        "模板 {} 缺少必需的变量 {}",
+                        self.name, variable.name
+                    )))
+                }
+                None => continue,
+            };
+            if !variable.kind.accepts(value) {
+                return Err(ApiError::invalid_request(format!(
+                    "模板 {} 的变量 {} 应为 {}",
+                    self.name,
+                    variable.name,
+                    serde_json::to_value(variable.kind).unwrap_or_default().as_str().unwrap_or_default()
+                )));
+            }
+            bound.insert(variable.name.clone(), value.clone());
+        }
+        Ok(bound)
+    }
+}
+
+/// `POST /v1/prompts`请求，保存为该名称的下一个版本
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewPrompt {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub template: String,
+    #[serde(default)]
+    pub variables: Vec<Variable>,
+}
+
+fn valid_name(name: &str) -> bool {
+    let valid = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+    !name.is_empty() && name.len() <= 64 && valid && !name.starts_with('.')
+}
+
+fn valid_variable(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+        && name != "loop"
+}
+
+impl NewPrompt {
+    /// 校验名称、变量声明和模板语法，返回编译后的模板
+    fn check(&self, max_bytes: usize) -> ApiResult<Template> {
+        if !valid_name(&self.name) {
+            return Err(ApiError::invalid_request(
+                "模板名只能包含字母、数字、_、- 和 .，长度为 1 到 64，且不能以 . 开头",
+            ));
+        }
+        if self.template.len() > max_bytes {
+            return Err(ApiError::invalid_request(format!("模板超过 {} 字节", max_bytes)));
+        }
+        for (i, variable) in self.variables.iter().enumerate() {
+            if !valid_variable(&variable.name) {
+                return Err(ApiError::invalid_request(format!("variables[{}]: 无效的变量名 {:?}", i, variable.name)));
+            }
+            if self.variables[..i].iter().any(|other| other.name == variable.name) {
+                return Err(ApiError::invalid_request(format!("variables[{}]: 变量 {} 重复声明", i, variable.name)));
+            }
+            if variable.default.as_ref().is_some_and(|value| !variable.kind.accepts(value)) {
+                return Err(ApiError::invalid_request(format!(
+                    "variables[{}]: 变量 {} 的默认值与类型不符",
+                    i, variable.name
+                )));
+            }
+        }
+        let template = Template::compile(&self.template)
+            .map_err(|e| ApiError::invalid_request(format!("模板语法错误: {}", e)))?;
+        let undeclared: Vec<_> = template
+            .variables()
+            .into_iter()
+            .filter(|name| !self.variables.iter().any(|v| &v.name == name))
+            .collect();
+        if !undeclared.is_empty() {
+            return Err(ApiError::invalid_request(format!("模板使用了未声明的变量: {}", undeclared.join("、"))));
+        }
+        Ok(template)
+    }
+}
+
+/// 模板的持久化方式
+trait Backend: Send + Sync + std::fmt::Debug {
+    /// 所有工作区的所有版本
+    fn load(&self) -> Result<Vec<(String, PromptTemplate)>, String>;
+    fn save(&self, workspace: &str, template: &PromptTemplate) -> Result<(), String>;
+    /// 删除一个模板的所有版本
+    fn delete(&self, workspace: &str, name: &str) -> Result<(), String>;
+}
+
+/// 每个版本一个JSON文件：`<目录>/<工作区>/<模板名>/<版本>.json`
+#[derive(Debug)]
+struct FileBackend {
+    dir: PathBuf,
+}
+
+fn read_dirs(dir: &FsPath) -> Result<Vec<fs::DirEntry>, String> {
+    match fs::read_dir(dir) {
+        Ok(entries) => entries.collect::<Result<_, _>>().map_err(|e| format!("读取目录 {} 失败: {}", dir.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(format!("读取目录 {} 失败: {}", dir.display(), e)),
+    }
+}
+
+impl Backend for FileBackend {
+    fn load(&self) -> Result<Vec<(String, PromptTemplate)>, String> {
+        let mut templates = Vec::new();
+        for workspace in read_dirs(&self.dir)? {
+            let workspace_name = workspace.file_name().to_string_lossy().into_owned();
+            for name in read_dirs(&workspace.path())? {
+                for file in read_dirs(&name.path())? {
+                    let path = file.path();
+                    if path.extension().is_none_or(|extension| extension != "json") {
+                        continue;
+                    }
+                    let text = fs::read_to_string(&path).map_err(|e| format!("读取 {} 失败: {}", path.display(), e))?;
+                    let template = serde_json::from_str(&text)
+                        .map_err(|e| format!("解析提示词模板 {} 失败: {}", path.display(), e))?;
+                    templates.push((workspace_name.clone(), template));
+                }
+            }
+        }
+        Ok(templates)
+    }
+
+    fn save(&self, workspace: &str, template: &PromptTemplate) -> Result<(), String> {
+        let dir = self.dir.join(workspace).join(&template.name);
+        fs::create_dir_all(&dir).map_err(|e| format!("创建目录 {} 失败: {}", dir.display(), e))?;
+        let path = dir.join(format!("{}.json", template.version));
+        let text = serde_json::to_string_pretty(template).unwrap_or_default();
+        fs::write(&path, text).map_err(|e| format!("写入 {} 失败: {}", path.display(), e))
+    }
+
+    fn delete(&self, workspace: &str, name: &str) -> Result<(), String> {
+        let dir = self.dir.join(workspace).join(name);
+        match fs::remove_dir_all(&dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(format!("删除 {} 失败: {}", dir.display(), e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SqliteBackend {
+    conn: Mutex<Connection>,
+}
+
+impl Backend for SqliteBackend {
+    fn load(&self) -> Result<Vec<(String, PromptTemplate)>, String> {
+        let conn = self.conn.lock().unwrap();
+        let read = || -> rusqlite::Result<Vec<(String, PromptTemplate, String)>> {
+            let mut statement = conn.prepare(
+                "SELECT workspace, name, version, description, template, variables, created_at FROM prompts",
+            )?;
+            let rows = statement.query_map([], |row| {
+                let template = PromptTemplate {
+                    name: row.get(1)?,
+                    version: row.get(2)?,
+                    description: row.get(3)?,
+                    template: row.get(4)?,
+                    variables: Vec::new(),
+                    created_at: row.get(6)?,
+                };
+                Ok((row.get(0)?, template, row.get(5)?))
+            })?;
+            rows.collect()
+        };
+        let rows = read().map_err(|e| format!("读取提示词模板失败: {}", e))?;
+        rows.into_iter()
+            .map(|(workspace, mut template, variables)| {
+                template.variables = serde_json::from_str(&variables)
+                    .map_err(|e| format!("解析模板 {} 的变量失败: {}", template.name, e))?;
+                Ok((workspace, template))
+            })
+            .collect()
+    }
+
+    fn save(&self, workspace: &str, template: &PromptTemplate) -> Result<(), String> {
+        let variables = serde_json::to_string(&template.variables).unwrap_or_default();
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT INTO prompts (workspace, name, version, description, template, variables, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    workspace,
+                    template.name,
+                    template.version,
+                    template.description,
+                    template.template,
+                    variables,
+                    template.created_at
+                ],
+            )
+            .map(|_| ())
+            .map_err(|e| format!("保存提示词模板失败: {}", e))
+    }
+
+    fn delete(&self, workspace: &str, name: &str) -> Result<(), String> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM prompts WHERE workspace = ?1 AND name = ?2", params![workspace, name])
+            .map(|_| ())
+            .map_err(|e| format!("删除提示词模板失败: {}", e))
+    }
+}
+
+/// 一个版本及其编译结果
+#[derive(Debug)]
+struct Version {
+    template: PromptTemplate,
+    compiled: Template,
+}
+
+/// 按工作区和名称索引、版本号升序排列
+type Versions = HashMap<(String, String), Vec<Arc<Version>>>;
+
+/// 提示词模板库
+#[derive(Debug)]
+pub struct PromptStore {
+    config: PromptsConfig,
+    backend: Box<dyn Backend>,
+    versions: Mutex<Versions>,
+}
+
+impl PromptStore {
+    pub fn open(config: &PromptsConfig) -> Result<PromptStore, String> {
+        let backend: Box<dyn Backend> = match config.store {
+            PromptStoreKind::File => Box::new(FileBackend {
+                dir: config.dir.clone(),
+            }),
+            PromptStoreKind::Sqlite => {
+                let path = &config.database;
+                if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                    fs::create_dir_all(parent).map_err(|e| format!("创建目录 {} 失败: {}", parent.display(), e))?;
+                }
+                let open = || -> Result<Connection, SchemaError> {
+                    let mut conn = Connection::open(path)?;
+                    migrate(&mut conn, "prompts", MIGRATIONS)?;
+                    Ok(conn)
+                };
+                let conn = open().map_err(|e| format!("打开提示词数据库 {} 失败: {}", path.display(), e))?;
+                Box::new(SqliteBackend {
+                    conn: Mutex::new(conn),
+                })
+            }
+        };
+
+        let mut versions = Versions::new();
+        for (workspace, template) in backend.load()? {
+            let compiled = Template::compile(&template.template)
+                .map_err(|e| format!("提示词模板 {} 版本 {} 无效: {}", template.name, template.version, e))?;
+            versions
+                .entry((workspace, template.name.clone()))
+                .or_default()
+                .push(Arc::new(Version { template, compiled }));
+        }
+        for list in versions.values_mut() {
+            list.sort_by_key(|version| version.template.version);
+        }
+        Ok(PromptStore {
+            config: config.clone(),
+            backend,
+            versions: Mutex::new(versions),
+        })
+    }
+
+    fn key(workspace: &Workspace, name: &str) -> (String, String) {
+        (workspace.name().to_string(), name.to_string())
+    }
+
+    /// 各模板的最新版本，按名称排序
+    pub fn list(&self, workspace: &Workspace) -> Vec<PromptTemplate> {
+        let versions = self.versions.lock().unwrap();
+        let mut latest: Vec<_> = versions
+            .iter()
+            .filter(|((owner, _), _)| owner == workspace.name())
+            .filter_map(|(_, list)| list.last().map(|version| version.template.clone()))
+            .collect();
+        latest.sort_by(|a, b| a.name.cmp(&b.name));
+        latest
+    }
+
+    /// 一个模板的所有版本，按版本号升序
+    pub fn versions(&self, workspace: &Workspace, name: &str) -> ApiResult<Vec<PromptTemplate>> {
+        let versions = self.versions.lock().unwrap();
+        let list = versions
+            .get(&Self::key(workspace, name))
+            .ok_or_else(|| ApiError::NotFound(format!("提示词模板 {} 不存在", name)))?;
+        Ok(list.iter().map(|version| version.template.clone()).collect())
+    }
+
+    /// 指定版本，`None`为最新版本
+    fn version(&self, workspace: &Workspace, name: &str, version: Option<u32>) -> ApiResult<Arc<Version>> {
+        let versions = self.versions.lock().unwrap();
+        let list = versions
+            .get(&Self::key(workspace, name))
+            .ok_or_else(|| ApiError::NotFound(format!("提示词模板 {} 不存在", name)))?;
+        let found = match version {
+            Some(number) => list.iter().find(|version| version.template.version == number),
+            None => list.last(),
+        };
+        found
+            .cloned()
+            .ok_or_else(|| ApiError::NotFound(format!("提示词模板 {} 没有版本 {}", name, version.unwrap_or(0))))
+    }
+
+    pub fn get(&self, workspace: &Workspace, name: &str, version: Option<u32>) -> ApiResult<PromptTemplate> {
+        Ok(self.version(workspace, name, version)?.template.clone())
+    }
+
+    /// 保存为新版本，版本号从1开始递增
+    pub fn create(&self, workspace: &Workspace, request: NewPrompt) -> ApiResult<PromptTemplate> {
+        let compiled = request.check(self.config.max_template_bytes)?;
+        let mut versions = self.versions.lock().unwrap();
+        let list = versions.entry(Self::key(workspace, &request.name)).or_default();
+        let template = PromptTemplate {
+            version: list.last().map_or(1, |version| version.template.version + 1),
+            name: request.name,
+            description: request.description,
+            template: request.template,
+            variables: request.variables,
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+        };
+        if let Err(e) = self.backend.save(workspace.name(), &template) {
+            if list.is_empty() {
+                versions.remove(&Self::key(workspace, &template.name));
+            }
+            return Err(ApiError::Internal(e));
+        }
+        list.push(Arc::new(Version {
+            template: template.clone(),
+            compiled,
+        }));
+        Ok(template)
+    }
+
+    pub fn delete(&self, workspace: &Workspace, name: &str) -> ApiResult<()> {
+        let mut versions = self.versions.lock().unwrap();
+        if !versions.contains_key(&Self::key(workspace, name)) {
+            return Err(ApiError::NotFound(format!("提示词模板 {} 不存在", name)));
+        }
+        self.backend.delete(workspace.name(), name).map_err(ApiError::Internal)?;
+        versions.remove(&Self::key(workspace, name));
+        Ok(())
+    }
+
+    /// 校验变量并渲染，返回使用的版本号和渲染结果
+    pub fn render(
+        &self,
+        workspace: &Workspace,
+        name: &str,
+        version: Option<u32>,
+        values: &Map<String, Value>,
+    ) -> ApiResult<(u32, String)> {
+        let version = self.version(workspace, name, version)?;
+        let bound = version.template.bind(values)?;
+        Ok((version.template.version, version.compiled.render(&bound)))
+    }
+}
+
+fn store(state: &AppState) -> ApiResult<&PromptStore> {
+    state
+        .prompts
+        .as_ref()
+        .ok_or_else(|| ApiError::invalid_request("提示词模板未启用（prompts.enabled 为 false）"))
+}
+
+/// 对话请求中引用的模板
+#[derive(Debug, Deserialize)]
+struct PromptReference {
+    name: String,
+    #[serde(default)]
+    version: Option<u32>,
+    #[serde(default)]
+    variables: Map<String, Value>,
+}
+
+/// 渲染请求中的`prompt`，作为系统消息插入到最前面
+pub fn apply(state: &AppState, workspace: &Workspace, request: &mut ChatCompletionRequest) -> ApiResult<()> {
+    let Some(reference) = request.extra.remove("prompt") else {
+        return Ok(());
+    };
+    let reference: PromptReference = serde_json::from_value(reference)
+        .map_err(|e| ApiError::invalid_request(format!("无效的 prompt: {}", e)))?;
+    let (_, content) = store(state)?.render(workspace, &reference.name, reference.version, &reference.variables)?;
+    request.messages.insert(
+        0,
+        ChatMessage {
+            role: "system".to_string(),
+            content: Some(MessageContent::Text(content)),
+            name: None,
+            extra: Map::new(),
+        },
+    );
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VersionQuery {
+    #[serde(default)]
+    pub version: Option<u32>,
+}
+
+/// `POST /v1/prompts/{name}/render`请求
+#[derive(Debug, Deserialize)]
+pub struct RenderRequest {
+    #[serde(default)]
+    pub version: Option<u32>,
+    #[serde(default)]
+    pub variables: Map<String, Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RenderResponse {
+    pub object: String,
+    pub name: String,
+    pub version: u32,
+    pub content: String,
+}
+
+/// 各模板的最新版本
+pub async fn list_prompts(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+) -> ApiResult<Json<ListResponse<PromptTemplate>>> {
+    Ok(Json(ListResponse::new(store(&state)?.list(&workspace))))
+}
+
+pub async fn create_prompt(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Json(request): Json<NewPrompt>,
+) -> ApiResult<Json<PromptTemplate>> {
+    Ok(Json(store(&state)?.create(&workspace, request)?))
+}
+
+/// 最新版本，或`?version=`指定的版本
+pub async fn get_prompt(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(name): Path<String>,
+    Query(query): Query<VersionQuery>,
+) -> ApiResult<Json<PromptTemplate>> {
+    Ok(Json(store(&state)?.get(&workspace, &name, query.version)?))
+}
+
+pub async fn list_versions(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(name): Path<String>,
+) -> ApiResult<Json<ListResponse<PromptTemplate>>> {
+    Ok(Json(ListResponse::new(store(&state)?.versions(&workspace, &name)?)))
+}
+
+/// 删除模板的所有版本
+pub async fn delete_prompt(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(name): Path<String>,
+) -> ApiResult<Json<DeletedResponse>> {
+    store(&state)?.delete(&workspace, &name)?;
+    Ok(Json(DeletedResponse {
+        id: name,
+        object: "prompt.deleted".to_string(),
+        deleted: true,
+    }))
+}
+
+/// 预览渲染结果，不发起对话
+pub async fn render_prompt(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(name): Path<String>,
+    Json(request): Json<RenderRequest>,
+) -> ApiResult<Json<RenderResponse>> {
+    let (version, content) = store(&state)?.render(&workspace, &name, request.version, &request.variables)?;
+    Ok(Json(RenderResponse {
+        object: "prompt.render".to_string(),
+        name,
+        version,
+        content,
+    }))
+}
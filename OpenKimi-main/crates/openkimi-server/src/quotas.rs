@@ -0,0 +1,560 @@
+//! 按用户和团队的配额
+//!
+//! 用户与用量计量中的用户相同：启用认证时为调用方身份，未启用时取`metering.user_header`请求头；
+//! 团队由`quotas.teams`列出成员。生成类请求到达时检查用户本人和所在各团队的每日token数、
+//! 每月费用和同时进行的请求数，任何一项用完都返回429，错误中说明是哪一项配额、已用多少以及何时恢复。
+//! token和费用在拿到用量后计入，与工作区配额一样用完后才拒绝；没有用户的请求不受这里的配额限制。
+//! 管理员可以在`/admin/quotas`查看剩余配额、临时调整某个用户或团队的配额或清零用量，
+//! 调整和累计用量由后台线程定期写入`quotas.path`，重启后继续有效。
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::header::RETRY_AFTER;
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use futures_util::StreamExt;
+use openkimi_sessions::time::days_from_civil;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::audit;
+use crate::auth::Principal;
+use crate::config::{MeteringConfig, PriceConfig, QuotasConfig, UserQuotaConfig};
+use crate::error::{ApiError, ApiResult};
+use crate::metering::Consumer;
+use crate::workspace::Workspace;
+use crate::AppState;
+
+/// 用量写回文件的间隔
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 配额的归属
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Holder {
+    User,
+    Team,
+}
+
+impl Holder {
+    /// 管理接口路径中的`users`或`teams`
+    pub fn parse(segment: &str) -> Option<Holder> {
+        match segment {
+            "users" => Some(Holder::User),
+            "teams" => Some(Holder::Team),
+            _ => None,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Holder::User => "用户",
+            Holder::Team => "团队",
+        }
+    }
+}
+
+/// 用户或团队的累计用量，按UTC的自然日和自然月归零
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuotaUsage {
+    /// 当前计数所在的日期，`YYYY-MM-DD`
+    pub day: String,
+    pub tokens_today: u64,
+    /// 当前计数所在的月份，`YYYY-MM`
+    pub month: String,
+    pub cost_this_month: f64,
+}
+
+impl QuotaUsage {
+    fn roll(&mut self, day: &str) {
+        if self.day != day {
+            self.day = day.to_string();
+            self.tokens_today = 0;
+        }
+        if self.month != day[..7] {
+            self.month = day[..7].to_string();
+            self.cost_this_month = 0.0;
+        }
+    }
+}
+
+/// 保存在文件中的一个用户或团队
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Account {
+    usage: QuotaUsage,
+    /// 通过管理接口设置的配额，优先于配置文件
+    #[serde(skip_serializing_if = "Option::is_none")]
+    adjusted: Option<UserQuotaConfig>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Accounts {
+    users: BTreeMap<String, Account>,
+    teams: BTreeMap<String, Account>,
+}
+
+impl Accounts {
+    fn get(&self, holder: Holder, name: &str) -> Option<&Account> {
+        match holder {
+            Holder::User => self.users.get(name),
+            Holder::Team => self.teams.get(name),
+        }
+    }
+
+    fn entry(&mut self, holder: Holder, name: &str) -> &mut Account {
+        let accounts = match holder {
+            Holder::User => &mut self.users,
+            Holder::Team => &mut self.teams,
+        };
+        accounts.entry(name.to_string()).or_default()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Ledger {
+    accounts: Accounts,
+    /// 各用户和团队正在进行的请求数，只在本进程中计数
+    active: HashMap<(Holder, String), u32>,
+    dirty: bool,
+}
+
+/// 剩余的配额，`None`为不限
+#[derive(Debug, Clone, Serialize)]
+pub struct Remaining {
+    pub tokens_today: Option<u64>,
+    pub cost_this_month: Option<f64>,
+    pub concurrent: Option<u32>,
+}
+
+/// 接口返回的配额信息
+#[derive(Debug, Clone, Serialize)]
+pub struct QuotaInfo {
+    pub holder: Holder,
+    pub name: String,
+    /// 团队的成员
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<String>,
+    /// 用户所在的团队
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub teams: Vec<String>,
+    /// 当前生效的配额
+    pub quota: UserQuotaConfig,
+    /// 是否通过管理接口调整过，调整过的配额不再跟随配置文件
+    pub adjusted: bool,
+    pub usage: QuotaUsage,
+    /// 正在进行的请求数
+    pub active: u32,
+    pub remaining: Remaining,
+    pub currency: String,
+}
+
+/// 某一项配额已用完
+#[derive(Debug)]
+pub struct QuotaExceeded {
+    message: String,
+    /// 错误体中的`quota`字段
+    detail: Value,
+    /// 同时进行的请求数超限时为`false`，稍后重试即可
+    exhausted: bool,
+    retry_after: Duration,
+}
+
+impl From<QuotaExceeded> for ApiError {
+    fn from(err: QuotaExceeded) -> ApiError {
+        ApiError::RateLimited(err.message)
+    }
+}
+
+impl IntoResponse for QuotaExceeded {
+    fn into_response(self) -> Response {
+        let (status, mut body) = ApiError::RateLimited(self.message).into_parts();
+        // 与OpenAI一致，用完额度的错误码为`insufficient_quota`，客户端不应立即重试
+        if self.exhausted {
+            body["error"]["code"] = json!("insufficient_quota");
+        }
+        body["error"]["quota"] = self.detail;
+        let mut response = (status, Json(body)).into_response();
+        let seconds = self.retry_after.as_secs_f64().ceil().max(1.0) as u64;
+        response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(seconds));
+        response
+    }
+}
+
+/// 请求占用的并发名额，释放时归还
+#[derive(Debug)]
+pub struct Slot {
+    ledger: Arc<Mutex<Ledger>>,
+    holders: Vec<(Holder, String)>,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut ledger = self.ledger.lock().unwrap();
+        for key in &self.holders {
+            if let Some(active) = ledger.active.get_mut(key) {
+                *active = active.saturating_sub(1);
+                if *active == 0 {
+                    ledger.active.remove(key);
+                }
+            }
+        }
+    }
+}
+
+/// 距离下一个UTC日（`monthly`时为下个月1日）0点的时间
+fn until_reset(monthly: bool) -> Duration {
+    let now = audit::unix_ms() / 1000;
+    let days = (now / 86_400) as i64;
+    let next = if monthly {
+        let today = audit::date(days);
+        let year: i64 = today[..4].parse().unwrap_or(1970);
+        let month: u32 = today[5..7].parse().unwrap_or(1);
+        let (year, month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+        days_from_civil(year, month, 1)
+    } else {
+        days + 1
+    };
+    Duration::from_secs((next as u64 * 86_400).saturating_sub(now))
+}
+
+fn load(path: &Path) -> Result<Accounts, String> {
+    match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content).map_err(|e| format!("解析配额文件 {} 失败: {}", path.display(), e)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Accounts::default()),
+        Err(err) => Err(format!("读取配额文件 {} 失败: {}", path.display(), err)),
+    }
+}
+
+/// 先写临时文件再改名，写到一半退出也不会损坏已有的内容
+fn save(path: &Path, accounts: &Accounts) -> std::io::Result<()> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let temporary = path.with_extension("json.tmp");
+    fs::write(&temporary, serde_json::to_vec_pretty(accounts)?)?;
+    fs::rename(&temporary, path)
+}
+
+/// 有变化时写入配额文件，失败时留待下次重试
+fn flush_ledger(path: &Path, ledger: &Mutex<Ledger>) -> Result<(), String> {
+    let snapshot = {
+        let mut ledger = ledger.lock().unwrap();
+        if !ledger.dirty {
+            return Ok(());
+        }
+        ledger.dirty = false;
+        ledger.accounts.clone()
+    };
+    save(path, &snapshot).map_err(|err| {
+        ledger.lock().unwrap().dirty = true;
+        format!("写入配额文件 {} 失败: {}", path.display(), err)
+    })
+}
+
+fn flush_loop(path: PathBuf, ledger: Arc<Mutex<Ledger>>) {
+    loop {
+        std::thread::sleep(FLUSH_INTERVAL);
+        if let Err(err) = flush_ledger(&path, &ledger) {
+            eprintln!("⚠️ {}", err);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Quotas {
+    /// 重新加载配置时原地替换，累计用量和调整过的配额保留
+    config: RwLock<QuotasConfig>,
+    path: PathBuf,
+    prices: HashMap<String, PriceConfig>,
+    currency: String,
+    ledger: Arc<Mutex<Ledger>>,
+}
+
+impl Quotas {
+    /// 费用按`metering`中的单价计算，未启用计量时同样有效
+    pub fn new(config: &QuotasConfig, metering: &MeteringConfig) -> Result<Quotas, String> {
+        let ledger = Arc::new(Mutex::new(Ledger {
+            accounts: load(&config.path)?,
+            ..Ledger::default()
+        }));
+        let path = config.path.clone();
+        let flushed = Arc::clone(&ledger);
+        std::thread::spawn(move || flush_loop(path, flushed));
+        Ok(Quotas {
+            config: RwLock::new(config.clone()),
+            path: config.path.clone(),
+            prices: metering.prices.clone(),
+            currency: metering.currency.clone(),
+            ledger,
+        })
+    }
+
+    /// 换用新的配额配置，没有调整过的用户和团队立即按新配额检查
+    pub fn reconfigure(&self, config: &QuotasConfig) {
+        *self.config.write().unwrap() = config.clone();
+    }
+
+    /// 立即写入配额文件，退出前调用；会阻塞
+    pub fn flush(&self) -> Result<(), String> {
+        flush_ledger(&self.path, &self.ledger)
+    }
+
+    /// 用户本人和所在的各团队
+    fn holders(config: &QuotasConfig, user: &str) -> Vec<(Holder, String)> {
+        let teams = config
+            .teams
+            .iter()
+            .filter(|(_, team)| team.members.iter().any(|member| member == user))
+            .map(|(name, _)| (Holder::Team, name.clone()));
+        std::iter::once((Holder::User, user.to_string())).chain(teams).collect()
+    }
+
+    /// 当前生效的配额；调整过的优先，其次是配置文件，没有单独配置的用户使用`default`
+    fn limits(config: &QuotasConfig, accounts: &Accounts, holder: Holder, name: &str) -> UserQuotaConfig {
+        if let Some(adjusted) = accounts.get(holder, name).and_then(|account| account.adjusted.clone()) {
+            return adjusted;
+        }
+        match holder {
+            Holder::User => config.users.get(name).unwrap_or(&config.default).clone(),
+            Holder::Team => config.teams.get(name).map(|team| team.quota.clone()).unwrap_or_default(),
+        }
+    }
+
+    /// 检查用户及其团队的配额并占用一个并发名额
+    pub fn acquire(&self, user: &str) -> Result<Slot, QuotaExceeded> {
+        let config = self.config.read().unwrap();
+        let holders = Self::holders(&config, user);
+        let day = audit::today();
+        let mut ledger = self.ledger.lock().unwrap();
+        for (holder, name) in &holders {
+            let limits = Self::limits(&config, &ledger.accounts, *holder, name);
+            let active = ledger.active.get(&(*holder, name.clone())).copied().unwrap_or(0);
+            let usage = &mut ledger.accounts.entry(*holder, name).usage;
+            usage.roll(&day);
+            let exceeded = |quota: &str, limit: Value, used: Value, message: String, retry_after: Duration| {
+                let reset = (quota != "max_concurrent")
+                    .then(|| audit::rfc3339((audit::unix_ms() / 1000 + retry_after.as_secs()) * 1000));
+                QuotaExceeded {
+                    message,
+                    detail: json!({
+                        "holder": holder,
+                        "name": name,
+                        "quota": quota,
+                        "limit": limit,
+                        "used": used,
+                        "reset_at": reset,
+                    }),
+                    exhausted: quota != "max_concurrent",
+                    retry_after,
+                }
+            };
+            if let Some(limit) = limits.tokens_per_day.filter(|limit| usage.tokens_today >= *limit) {
+                let wait = until_reset(false);
+                return Err(exceeded(
+                    "tokens_per_day",
+                    json!(limit),
+                    json!(usage.tokens_today),
+                    format!(
+                        "{} {} 今天的token配额已用完（已用 {} / 配额 {}），将在 UTC 0 点（{} 秒后）恢复",
+                        holder.label(),
+                        name,
+                        usage.tokens_today,
+                        limit,
+                        wait.as_secs()
+                    ),
+                    wait,
+                ));
+            }
+            if let Some(limit) = limits.cost_per_month.filter(|limit| usage.cost_this_month >= *limit) {
+                let wait = until_reset(true);
+                return Err(exceeded(
+                    "cost_per_month",
+                    json!(limit),
+                    json!(usage.cost_this_month),
+                    format!(
+                        "{} {} 本月的费用配额已用完（已用 {:.2} / 配额 {:.2} {}），将在下月1日 UTC 0 点恢复",
+                        holder.label(),
+                        name,
+                        usage.cost_this_month,
+                        limit,
+                        self.currency
+                    ),
+                    wait,
+                ));
+            }
+            if let Some(limit) = limits.max_concurrent.filter(|limit| active >= *limit) {
+                return Err(exceeded(
+                    "max_concurrent",
+                    json!(limit),
+                    json!(active),
+                    format!(
+                        "{} {} 同时进行的请求已达上限（{}），请等其他请求完成后重试",
+                        holder.label(),
+                        name,
+                        limit
+                    ),
+                    Duration::from_secs(1),
+                ));
+            }
+        }
+        for key in &holders {
+            *ledger.active.entry(key.clone()).or_default() += 1;
+        }
+        ledger.dirty = true;
+        Ok(Slot {
+            ledger: Arc::clone(&self.ledger),
+            holders,
+        })
+    }
+
+    /// 计入一次请求的token和费用，`model`为客户端请求的模型名
+    pub fn record(&self, user: &str, model: &str, prompt_tokens: u32, completion_tokens: u32) {
+        let cost = self.prices.get(model).map_or(0.0, |price| price.cost(prompt_tokens, completion_tokens));
+        let holders = Self::holders(&self.config.read().unwrap(), user);
+        let day = audit::today();
+        let mut ledger = self.ledger.lock().unwrap();
+        for (holder, name) in &holders {
+            let usage = &mut ledger.accounts.entry(*holder, name).usage;
+            usage.roll(&day);
+            usage.tokens_today += prompt_tokens as u64 + completion_tokens as u64;
+            usage.cost_this_month += cost;
+        }
+        ledger.dirty = true;
+    }
+
+    fn info_locked(&self, config: &QuotasConfig, ledger: &Ledger, holder: Holder, name: &str) -> QuotaInfo {
+        let quota = Self::limits(config, &ledger.accounts, holder, name);
+        let account = ledger.accounts.get(holder, name).cloned().unwrap_or_default();
+        let mut usage = account.usage;
+        usage.roll(&audit::today());
+        let active = ledger.active.get(&(holder, name.to_string())).copied().unwrap_or(0);
+        let (members, teams) = match holder {
+            Holder::User => {
+                let teams = Self::holders(config, name).into_iter().skip(1).map(|(_, team)| team).collect();
+                (Vec::new(), teams)
+            }
+            Holder::Team => (config.teams.get(name).map(|team| team.members.clone()).unwrap_or_default(), Vec::new()),
+        };
+        QuotaInfo {
+            holder,
+            name: name.to_string(),
+            members,
+            teams,
+            remaining: Remaining {
+                tokens_today: quota.tokens_per_day.map(|limit| limit.saturating_sub(usage.tokens_today)),
+                cost_this_month: quota.cost_per_month.map(|limit| (limit - usage.cost_this_month).max(0.0)),
+                concurrent: quota.max_concurrent.map(|limit| limit.saturating_sub(active)),
+            },
+            quota,
+            adjusted: account.adjusted.is_some(),
+            usage,
+            active,
+            currency: self.currency.clone(),
+        }
+    }
+
+    /// 团队必须在配置中，用户可以是任何名字
+    fn check_exists(config: &QuotasConfig, holder: Holder, name: &str) -> ApiResult<()> {
+        if holder == Holder::Team && !config.teams.contains_key(name) {
+            return Err(ApiError::NotFound(format!("团队 {} 不存在", name)));
+        }
+        Ok(())
+    }
+
+    pub fn info(&self, holder: Holder, name: &str) -> ApiResult<QuotaInfo> {
+        let config = self.config.read().unwrap();
+        Self::check_exists(&config, holder, name)?;
+        Ok(self.info_locked(&config, &self.ledger.lock().unwrap(), holder, name))
+    }
+
+    /// 配置中的用户、有过用量或调整过的用户，以及全部团队；用户在前，按名称排序
+    pub fn list(&self) -> Vec<QuotaInfo> {
+        let config = self.config.read().unwrap();
+        let ledger = self.ledger.lock().unwrap();
+        let users: BTreeSet<&String> = config.users.keys().chain(ledger.accounts.users.keys()).collect();
+        let users = users.into_iter().map(|name| (Holder::User, name));
+        let teams = config.teams.keys().map(|name| (Holder::Team, name));
+        users
+            .chain(teams)
+            .map(|(holder, name)| self.info_locked(&config, &ledger, holder, name))
+            .collect()
+    }
+
+    /// 设置某个用户或团队的配额，`None`时恢复为配置文件中的配额
+    pub fn adjust(&self, holder: Holder, name: &str, quota: Option<UserQuotaConfig>) -> ApiResult<QuotaInfo> {
+        let config = self.config.read().unwrap();
+        Self::check_exists(&config, holder, name)?;
+        if let Some(cost) = quota.as_ref().and_then(|quota| quota.cost_per_month).filter(|cost| *cost < 0.0) {
+            return Err(ApiError::invalid_request(format!("cost_per_month 不能为负数: {}", cost)));
+        }
+        let mut ledger = self.ledger.lock().unwrap();
+        ledger.accounts.entry(holder, name).adjusted = quota;
+        ledger.dirty = true;
+        Ok(self.info_locked(&config, &ledger, holder, name))
+    }
+
+    /// 清零今天的token数和本月的费用
+    pub fn reset(&self, holder: Holder, name: &str) -> ApiResult<QuotaInfo> {
+        let config = self.config.read().unwrap();
+        Self::check_exists(&config, holder, name)?;
+        let mut ledger = self.ledger.lock().unwrap();
+        let usage = &mut ledger.accounts.entry(holder, name).usage;
+        usage.roll(&audit::today());
+        usage.tokens_today = 0;
+        usage.cost_this_month = 0.0;
+        ledger.dirty = true;
+        Ok(self.info_locked(&config, &ledger, holder, name))
+    }
+}
+
+/// 检查调用方的配额，未启用配额或没有用户时返回`None`
+pub fn acquire(state: &AppState, consumer: &Consumer) -> Result<Option<Slot>, QuotaExceeded> {
+    match (&state.quotas, &consumer.user) {
+        (Some(quotas), Some(user)) => quotas.acquire(user).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// 计入调用方的用量，未启用配额或没有用户时什么也不做
+pub fn record(state: &AppState, consumer: &Consumer, model: &str, prompt_tokens: u32, completion_tokens: u32) {
+    if let (Some(quotas), Some(user)) = (&state.quotas, &consumer.user) {
+        quotas.record(user, model, prompt_tokens, completion_tokens);
+    }
+}
+
+/// 生成类接口的配额中间件，在认证和工作区之后；并发名额在响应体发送完之后才归还，流式响应一直占用
+pub async fn enforce(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    if state.quotas.is_none() {
+        return next.run(request).await;
+    }
+    let header = request
+        .headers()
+        .get(state.config().metering.user_header.as_str())
+        .and_then(|value| value.to_str().ok());
+    let consumer = Consumer::new(request.extensions().get::<Principal>(), Workspace::default(), header);
+    let slot = match acquire(&state, &consumer) {
+        Ok(slot) => slot,
+        Err(err) => return err.into_response(),
+    };
+    let response = next.run(request).await;
+    let Some(slot) = slot else {
+        return response;
+    };
+    response.map(|body| {
+        Body::from_stream(body.into_data_stream().map(move |chunk| {
+            let _ = &slot;
+            chunk
+        }))
+    })
+}
@@ -0,0 +1,719 @@
+//! 文档导入与索引管理
+//!
+//! 嵌入模型按`rag.indexes.<索引名>.embedder`、`rag.embedder`的顺序选择，都未配置时由上游的
+//! `/embeddings`接口计算，模型为`llm.embedding_model`；分块使用与该模型匹配的分词器。
+//! 索引在第一次使用时从`rag.index_dir`加载，此后常驻内存，每次导入后写回磁盘。存储方式由`rag.store`选择，
+//! 使用HNSW时如果只有同名的`.jsonl`索引，会先把其中的记录转换过来；选择Qdrant、pgvector或Milvus时索引存放在
+//! 外部数据库中，客户端是阻塞的，所有索引操作都在`block_in_place`中执行。
+//! 非默认工作区的索引在存储中以`<工作区>__<索引名>`命名，彼此不可见。
+//!
+//! 检索时按`retrieval.mode`用向量、关键词或两者融合取出候选，配置了重排模型（`rag.indexes.<索引名>.reranker`或
+//! `rag.reranker`）时再重排，按阈值筛选后取前`top_k`个，拼接到提示词中或由`/v1/rag/query`返回。
+//! `/v1/rag/evaluate`用带标注的查询比较重排前后的检索质量。
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::{fmt, fs};
+use std::sync::{Arc, Mutex};
+
+use futures_util::future::BoxFuture;
+use openkimi_rag::{
+    build_embedder, build_reranker, Chunker, Document, Embedder, Filter, FlatStore, HnswStore, Ingestor, RagError,
+    Reranker, VectorStore,
+};
+use openkimi_tokenizer::Tokenizer;
+use openkimi_vectorstore::{MilvusStore, PgvectorStore, QdrantStore};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::config::{RagConfig, RetrievalConfig, RetrievalMode, StoreKind};
+use crate::error::{ApiError, ApiResult};
+use crate::router::ModelRouter;
+use crate::safety::{self, Inbound, Source};
+use crate::types::{
+    ChatCompletionRequest, ChatMessage, EmbeddingInput, EmbeddingRequest, EvaluateResponse, EvaluatedQuery,
+    EvaluationQuery, IngestedDocument, MessageContent, RetrievalMetrics, RetrievedChunk,
+};
+use crate::workspace::Workspace;
+use crate::AppState;
+
+impl From<RagError> for ApiError {
+    fn from(err: RagError) -> ApiError {
+        match err {
+            RagError::Unsupported(_) | RagError::Extract(_) => ApiError::InvalidRequest(err.to_string()),
+            RagError::Embedding(_) | RagError::Rerank(_) => ApiError::Upstream(err.to_string()),
+            RagError::Io(_) | RagError::Store(_) => ApiError::Internal(err.to_string()),
+        }
+    }
+}
+
+/// 由上游嵌入接口计算向量
+pub struct UpstreamEmbedder {
+    models: Arc<ModelRouter>,
+    model: String,
+    batch_size: usize,
+}
+
+impl Embedder for UpstreamEmbedder {
+    fn embed<'a>(&'a self, texts: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>, RagError>> {
+        Box::pin(async move {
+            let request = EmbeddingRequest {
+                model: self.model.clone(),
+                input: EmbeddingInput::Texts(texts.to_vec()),
+                extra: Map::new(),
+            };
+            let mut response = self
+                .models
+                .embeddings(&request)
+                .await
+                .map_err(|e| RagError::Embedding(e.to_string()))?;
+            response.data.sort_by_key(|embedding| embedding.index);
+            response
+                .data
+                .iter()
+                .map(|embedding| {
+                    embedding
+                        .embedding
+                        .as_array()
+                        .map(|values| values.iter().filter_map(Value::as_f64).map(|v| v as f32).collect())
+                        .ok_or_else(|| RagError::Embedding("上游返回的向量不是数组".to_string()))
+                })
+                .collect()
+        })
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+}
+
+/// 已打开的索引
+pub struct Indexes {
+    config: RagConfig,
+    open: Mutex<HashMap<String, Box<dyn VectorStore>>>,
+    /// 按索引名缓存的嵌入模型，本地模型只加载一次
+    embedders: Mutex<HashMap<String, Arc<dyn Embedder>>>,
+    /// 按索引名缓存的重排模型
+    rerankers: Mutex<HashMap<String, Arc<dyn Reranker>>>,
+}
+
+impl fmt::Debug for Indexes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Indexes")
+            .field("dir", &self.config.index_dir)
+            .field("store", &self.config.store)
+            .field("open", &self.open.lock().unwrap().keys().collect::<Vec<_>>())
+            .finish_non_exhaustive()
+    }
+}
+
+/// 只包含字母、数字、`-`和`_`，不会拼出索引目录以外的路径
+fn is_safe_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// 客户端使用的索引名；`__`用于分隔工作区前缀，不能出现在索引名中
+pub fn validate_index_name(name: &str) -> ApiResult<()> {
+    if is_safe_name(name) && name.len() <= 64 && !name.contains("__") {
+        Ok(())
+    } else {
+        Err(ApiError::invalid_request(format!("无效的索引名: {}", name)))
+    }
+}
+
+impl Indexes {
+    pub fn new(config: &RagConfig) -> Indexes {
+        Indexes {
+            config: config.clone(),
+            open: Mutex::new(HashMap::new()),
+            embedders: Mutex::new(HashMap::new()),
+            rerankers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 本地索引文件的路径
+    fn path(&self, name: &str, kind: StoreKind) -> PathBuf {
+        let extension = kind.extension().expect("外部数据库没有索引文件");
+        self.config.index_dir.join(format!("{}.{}", name, extension))
+    }
+
+    /// 索引的存放位置，用于提示
+    pub fn location(&self, name: &str) -> String {
+        let config = &self.config;
+        match config.store {
+            StoreKind::Flat | StoreKind::Hnsw => self.path(name, config.store).display().to_string(),
+            StoreKind::Qdrant => {
+                format!("Qdrant {} 的集合 {}{}", config.qdrant.url, config.qdrant.collection_prefix, name)
+            }
+            StoreKind::Pgvector => format!("PostgreSQL 表 {}{}", config.pgvector.table_prefix, name),
+            StoreKind::Milvus => {
+                let collection = format!("{}{}", config.milvus.collection_prefix, name).replace('-', "_");
+                format!("Milvus {} 的集合 {}", config.milvus.url, collection)
+            }
+        }
+    }
+
+    fn open_store(&self, name: &str) -> Result<Box<dyn VectorStore>, RagError> {
+        let config = &self.config;
+        match config.store {
+            StoreKind::Flat => Ok(Box::new(FlatStore::open(&self.path(name, StoreKind::Flat))?)),
+            StoreKind::Hnsw => {
+                let path = self.path(name, StoreKind::Hnsw);
+                let mut store = HnswStore::open(&path, config.hnsw.clone())?;
+                let legacy = self.path(name, StoreKind::Flat);
+                if !path.exists() && legacy.exists() {
+                    let flat = FlatStore::open(&legacy)?;
+                    store.upsert(flat.records().to_vec())?;
+                    store.flush()?;
+                    eprintln!("🔄 已把 {} 转换为 {}", legacy.display(), path.display());
+                }
+                Ok(Box::new(store))
+            }
+            StoreKind::Qdrant => Ok(Box::new(QdrantStore::open(&config.qdrant, name)?)),
+            StoreKind::Pgvector => Ok(Box::new(PgvectorStore::open(&config.pgvector, name)?)),
+            StoreKind::Milvus => Ok(Box::new(MilvusStore::open(&config.milvus, name)?)),
+        }
+    }
+
+    /// 检查向量存储是否可用，返回检查的位置；会阻塞，需在阻塞线程中调用
+    ///
+    /// 本地存储检查索引目录能否创建；外部数据库不使用已打开的连接，重新连接后统计默认索引的记录数。
+    pub fn check(&self) -> Result<String, String> {
+        let config = &self.config;
+        match config.store {
+            StoreKind::Flat | StoreKind::Hnsw => {
+                fs::create_dir_all(&config.index_dir)
+                    .map_err(|e| format!("创建索引目录 {} 失败: {}", config.index_dir.display(), e))?;
+                Ok(config.index_dir.display().to_string())
+            }
+            StoreKind::Qdrant | StoreKind::Pgvector | StoreKind::Milvus => {
+                let store = self.open_store(&config.default_index).map_err(|e| e.to_string())?;
+                store.len().map_err(|e| e.to_string())?;
+                Ok(self.location(&config.default_index))
+            }
+        }
+    }
+
+    /// 在索引上执行`f`，索引尚未打开时先从磁盘加载
+    pub fn with_index<T>(
+        &self,
+        name: &str,
+        f: impl FnOnce(&mut dyn VectorStore) -> Result<T, RagError>,
+    ) -> ApiResult<T> {
+        if !is_safe_name(name) {
+            return Err(ApiError::invalid_request(format!("无效的索引名: {}", name)));
+        }
+        tokio::task::block_in_place(|| {
+            let mut open = self.open.lock().unwrap();
+            if !open.contains_key(name) {
+                let store = self.open_store(name)?;
+                open.insert(name.to_string(), store);
+            }
+            Ok(f(open.get_mut(name).unwrap().as_mut())?)
+        })
+    }
+
+    /// 写出并关闭已打开的索引，外部数据库的连接随之断开；退出前调用，会阻塞
+    pub fn close(&self) -> Result<(), String> {
+        let mut errors = Vec::new();
+        for (name, mut store) in self.open.lock().unwrap().drain() {
+            if let Err(err) = store.flush() {
+                errors.push(format!("{}: {}", name, err));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("写出索引失败: {}", errors.join("; ")))
+        }
+    }
+
+    /// 整理所有已删除记录占比超过`threshold`的本地HNSW索引，返回整理过的索引名
+    ///
+    /// 其余存储不需要整理，直接返回空列表。
+    pub fn compact(&self, threshold: f32) -> ApiResult<Vec<String>> {
+        if self.config.store != StoreKind::Hnsw {
+            return Ok(Vec::new());
+        }
+        let extension = StoreKind::Hnsw.extension();
+        let entries = match std::fs::read_dir(&self.config.index_dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(ApiError::Internal(format!("读取索引目录失败: {}", err))),
+        };
+        let mut names: Vec<String> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().and_then(|ext| ext.to_str()) == extension)
+            .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+            .filter(|name| is_safe_name(name))
+            .collect();
+        names.sort();
+        let mut compacted = Vec::new();
+        for name in names {
+            if self.with_index(&name, |store| Ok(store.compact(threshold)?))? {
+                compacted.push(name);
+            }
+        }
+        Ok(compacted)
+    }
+
+    /// 索引`name`配置的嵌入模型，第一次使用时创建；未配置时返回`None`
+    pub fn embedder(&self, name: &str, config: &RagConfig) -> ApiResult<Option<Arc<dyn Embedder>>> {
+        let Some(embedder_config) = config.embedder(name) else {
+            return Ok(None);
+        };
+        let mut embedders = self.embedders.lock().unwrap();
+        if let Some(embedder) = embedders.get(name) {
+            return Ok(Some(Arc::clone(embedder)));
+        }
+        let embedder: Arc<dyn Embedder> = Arc::from(build_embedder(embedder_config)?);
+        embedders.insert(name.to_string(), Arc::clone(&embedder));
+        Ok(Some(embedder))
+    }
+
+    /// 索引`name`配置的重排模型，第一次使用时创建；未配置时返回`None`
+    pub fn reranker(&self, name: &str, config: &RagConfig) -> ApiResult<Option<Arc<dyn Reranker>>> {
+        let Some(reranker_config) = config.reranker(name) else {
+            return Ok(None);
+        };
+        let mut rerankers = self.rerankers.lock().unwrap();
+        if let Some(reranker) = rerankers.get(name) {
+            return Ok(Some(Arc::clone(reranker)));
+        }
+        let reranker: Arc<dyn Reranker> = Arc::from(build_reranker(reranker_config)?);
+        rerankers.insert(name.to_string(), Arc::clone(&reranker));
+        Ok(Some(reranker))
+    }
+}
+
+/// 未配置嵌入模型时使用的上游嵌入接口
+fn upstream_embedder(state: &AppState) -> ApiResult<UpstreamEmbedder> {
+    let config = state.config();
+    let model = config.llm.embedding_model.clone().ok_or_else(|| {
+        ApiError::invalid_request("配置中没有 rag.embedder 或 llm.embedding_model，无法计算嵌入")
+    })?;
+    Ok(UpstreamEmbedder {
+        models: state.models(),
+        model,
+        batch_size: config.rag.batch_size,
+    })
+}
+
+/// 导入文档到工作区`workspace`的索引`index`，每个文档导入完成后立即写盘
+///
+/// 嵌入模型按索引名`index`选择，存储中的索引名带有工作区前缀（见[`Workspace::index_name`]）。
+pub async fn ingest_documents(
+    state: &AppState,
+    workspace: &Workspace,
+    index: &str,
+    documents: &[Document],
+) -> ApiResult<Vec<IngestedDocument>> {
+    validate_index_name(index)?;
+    let stored = workspace.index_name(index);
+    let config = state.config();
+    let configured = state.indexes.embedder(index, &config.rag)?;
+    let upstream;
+    let embedder: &dyn Embedder = match &configured {
+        Some(embedder) => embedder.as_ref(),
+        None => {
+            upstream = upstream_embedder(state)?;
+            &upstream
+        }
+    };
+
+    let chunker = Chunker::new(config.rag.chunking(index).clone(), Tokenizer::for_model(embedder.model()));
+    let ingestor = Ingestor::new(&chunker, embedder).with_kinds(&config.rag.chunking_by_kind);
+
+    let mut results = Vec::with_capacity(documents.len());
+    for document in documents {
+        let mut records = ingestor.ingest(document).await?;
+        if let Some(safety) = &state.safety {
+            for record in &mut records {
+                match safety.screen_inbound(workspace, Source::Document, &document.id, &record.text) {
+                    Inbound::Pass => {}
+                    Inbound::Annotated(_) => {
+                        record.metadata.insert("safety".to_string(), Value::from(safety::INJECTION_CATEGORY));
+                    }
+                    Inbound::Blocked => {
+                        return Err(ApiError::invalid_request(format!(
+                            "文档 {} 疑似包含提示注入，已拒绝导入",
+                            document.id
+                        )));
+                    }
+                }
+            }
+        }
+        let chunks = records.len();
+        let replaced = state.indexes.with_index(&stored, |store| {
+            let replaced = store.replace_document(&document.id, records)?;
+            store.flush()?;
+            Ok(replaced)
+        })?;
+        results.push(IngestedDocument {
+            id: document.id.clone(),
+            chunks,
+            replaced,
+        });
+    }
+    Ok(results)
+}
+
+/// 检索选项，未指定的项使用该索引的`retrieval`配置
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetrievalOptions {
+    /// 索引名，缺省为`rag.default_index`
+    pub index: Option<String>,
+    pub top_k: Option<usize>,
+    /// 按元数据筛选，格式见[`Filter::from_json`]
+    pub filter: Option<Value>,
+    pub mode: Option<RetrievalMode>,
+    /// 是否重排，为`true`时索引必须配置了重排模型
+    pub rerank: Option<bool>,
+}
+
+/// 检索结果
+#[derive(Debug, Clone)]
+pub struct Retrieved {
+    pub index: String,
+    pub mode: RetrievalMode,
+    /// 使用的重排模型，未重排时为`None`
+    pub reranker: Option<String>,
+    pub chunks: Vec<RetrievedChunk>,
+}
+
+fn parse_filter(filter: Option<&Value>) -> ApiResult<Option<Filter>> {
+    filter
+        .map(|filter| Filter::from_json(filter).map_err(|e| ApiError::invalid_request(format!("无效的 filter: {}", e))))
+        .transpose()
+}
+
+/// 用索引`index`的嵌入模型计算查询的向量
+async fn embed_query(state: &AppState, index: &str, query: &str) -> ApiResult<Vec<f32>> {
+    let configured = state.indexes.embedder(index, &state.config().rag)?;
+    let upstream;
+    let embedder: &dyn Embedder = match &configured {
+        Some(embedder) => embedder.as_ref(),
+        None => {
+            upstream = upstream_embedder(state)?;
+            &upstream
+        }
+    };
+    embedder
+        .embed(&[query.to_string()])
+        .await?
+        .pop()
+        .ok_or_else(|| ApiError::Upstream("嵌入接口没有返回向量".to_string()))
+}
+
+/// 一次检索的方式和条件
+struct Search<'a> {
+    mode: RetrievalMode,
+    filter: Option<&'a Filter>,
+    config: &'a RetrievalConfig,
+}
+
+/// 按`search.mode`检索出至多`count`个候选，丢弃得分低于`min_score`的
+async fn search(
+    state: &AppState,
+    workspace: &Workspace,
+    index: &str,
+    query: &str,
+    search: &Search<'_>,
+    count: usize,
+) -> ApiResult<Vec<RetrievedChunk>> {
+    let vector = match search.mode {
+        RetrievalMode::Keyword => Vec::new(),
+        RetrievalMode::Vector | RetrievalMode::Hybrid => embed_query(state, index, query).await?,
+    };
+    let filter = search.filter;
+    let hits = state.indexes.with_index(&workspace.index_name(index), |store| {
+        Ok(match search.mode {
+            RetrievalMode::Vector => store.search(&vector, count, filter)?,
+            RetrievalMode::Hybrid => store.search_hybrid(query, &vector, count, filter)?,
+            RetrievalMode::Keyword => store.search_keyword(query, count, filter)?,
+        })
+    })?;
+    Ok(hits
+        .into_iter()
+        .filter(|hit| search.config.min_score.is_none_or(|min| hit.score >= min))
+        .map(|hit| RetrievedChunk {
+            id: hit.record.id,
+            document: hit.record.document,
+            text: hit.record.text,
+            metadata: hit.record.metadata,
+            score: hit.score,
+            rerank_score: None,
+        })
+        .collect())
+}
+
+/// 检查索引是否支持`mode`，关键词检索不像混合检索那样可以退回纯向量检索
+fn check_mode(state: &AppState, workspace: &Workspace, index: &str, mode: RetrievalMode) -> ApiResult<()> {
+    if mode != RetrievalMode::Keyword {
+        return Ok(());
+    }
+    if state.indexes.with_index(&workspace.index_name(index), |store| Ok(store.supports_keyword()))? {
+        Ok(())
+    } else {
+        Err(ApiError::invalid_request(format!("索引 {} 的存储不支持关键词检索", index)))
+    }
+}
+
+/// 给候选重新打分并按得分从高到低排序，丢弃得分低于`min_rerank_score`的
+async fn rerank(
+    reranker: &dyn Reranker,
+    query: &str,
+    chunks: Vec<RetrievedChunk>,
+    config: &RetrievalConfig,
+) -> ApiResult<Vec<RetrievedChunk>> {
+    let texts: Vec<String> = chunks.iter().map(|chunk| chunk.text.clone()).collect();
+    let scores = reranker.rerank(query, &texts).await?;
+    if scores.len() != chunks.len() {
+        return Err(ApiError::Upstream(format!(
+            "重排模型 {} 为 {} 个候选返回了 {} 个得分",
+            reranker.model(),
+            chunks.len(),
+            scores.len()
+        )));
+    }
+    let mut scored: Vec<(f32, RetrievedChunk)> = scores
+        .into_iter()
+        .zip(chunks)
+        .filter(|(score, _)| config.min_rerank_score.is_none_or(|min| *score >= min))
+        .collect();
+    scored.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    Ok(scored
+        .into_iter()
+        .map(|(score, chunk)| RetrievedChunk {
+            rerank_score: Some(score),
+            ..chunk
+        })
+        .collect())
+}
+
+/// 在工作区`workspace`的索引中检索与`query`最相关的分块
+pub async fn retrieve(
+    state: &AppState,
+    workspace: &Workspace,
+    query: &str,
+    options: &RetrievalOptions,
+) -> ApiResult<Retrieved> {
+    let settings = state.config();
+    let index = options.index.as_deref().unwrap_or(&settings.rag.default_index);
+    validate_index_name(index)?;
+    if query.trim().is_empty() {
+        return Err(ApiError::invalid_request("query 不能为空"));
+    }
+    let config = settings.rag.retrieval(index);
+    let top_k = options.top_k.unwrap_or(config.top_k);
+    if top_k == 0 {
+        return Err(ApiError::invalid_request("top_k 应大于0"));
+    }
+    let filter = parse_filter(options.filter.as_ref())?;
+    let reranker = match options.rerank.unwrap_or(config.rerank) {
+        true => state.indexes.reranker(index, &settings.rag)?,
+        false => None,
+    };
+    if options.rerank == Some(true) && reranker.is_none() {
+        return Err(ApiError::invalid_request(format!("索引 {} 没有配置重排模型", index)));
+    }
+
+    let mode = options.mode.unwrap_or(config.mode);
+    check_mode(state, workspace, index, mode)?;
+
+    let count = if reranker.is_some() { config.candidates.max(top_k) } else { top_k };
+    let search_options = Search {
+        mode,
+        filter: filter.as_ref(),
+        config,
+    };
+    let mut chunks = search(state, workspace, index, query, &search_options, count).await?;
+    if let Some(reranker) = &reranker {
+        chunks = rerank(reranker.as_ref(), query, chunks, config).await?;
+    }
+    chunks.truncate(top_k);
+    Ok(Retrieved {
+        index: index.to_string(),
+        mode,
+        reranker: reranker.map(|reranker| reranker.model().to_string()),
+        chunks,
+    })
+}
+
+/// 前`k`个结果对相关项`relevant`（文档id或分块id）的指标，同一相关项的多个分块只计一次
+fn measure(chunks: &[RetrievedChunk], relevant: &HashSet<&str>, k: usize) -> RetrievalMetrics {
+    let mut found = HashSet::new();
+    let mut first = None;
+    let mut dcg = 0.0;
+    for (rank, chunk) in chunks.iter().take(k).enumerate() {
+        let matched: Vec<&str> = [chunk.id.as_str(), chunk.document.as_str()]
+            .into_iter()
+            .filter(|id| relevant.contains(id))
+            .collect();
+        if matched.is_empty() {
+            continue;
+        }
+        first.get_or_insert(rank);
+        if matched.iter().any(|id| found.insert(*id)) {
+            dcg += 1.0 / (rank as f64 + 2.0).log2();
+        }
+    }
+    let ideal: f64 = (0..relevant.len().min(k)).map(|rank| 1.0 / (rank as f64 + 2.0).log2()).sum();
+    RetrievalMetrics {
+        hit_rate: if first.is_some() { 1.0 } else { 0.0 },
+        mrr: first.map_or(0.0, |rank| 1.0 / (rank as f64 + 1.0)),
+        recall: found.len() as f64 / relevant.len() as f64,
+        ndcg: if ideal > 0.0 { dcg / ideal } else { 0.0 },
+    }
+}
+
+impl RetrievalMetrics {
+    fn add(&mut self, other: &RetrievalMetrics) {
+        self.hit_rate += other.hit_rate;
+        self.mrr += other.mrr;
+        self.recall += other.recall;
+        self.ndcg += other.ndcg;
+    }
+
+    fn average(mut self, count: usize) -> RetrievalMetrics {
+        let count = count.max(1) as f64;
+        self.hit_rate /= count;
+        self.mrr /= count;
+        self.recall /= count;
+        self.ndcg /= count;
+        self
+    }
+}
+
+/// 用带标注的查询评估索引`index`的检索质量，配置了重排模型时同时给出重排前后的指标
+///
+/// 两组指标使用同一批候选：重排前的结果取候选的前`top_k`个，重排的结果取重排后的前`top_k`个，
+/// 各自应用对应的得分阈值。
+pub async fn evaluate(
+    state: &AppState,
+    workspace: &Workspace,
+    index: &str,
+    queries: &[EvaluationQuery],
+    top_k: Option<usize>,
+    mode: Option<RetrievalMode>,
+) -> ApiResult<EvaluateResponse> {
+    validate_index_name(index)?;
+    if queries.is_empty() {
+        return Err(ApiError::invalid_request("queries 不能为空"));
+    }
+    let settings = state.config();
+    let config = settings.rag.retrieval(index);
+    let top_k = top_k.unwrap_or(config.top_k);
+    if top_k == 0 {
+        return Err(ApiError::invalid_request("top_k 应大于0"));
+    }
+    let mode = mode.unwrap_or(config.mode);
+    check_mode(state, workspace, index, mode)?;
+    let reranker = state.indexes.reranker(index, &settings.rag)?;
+    let count = if reranker.is_some() { config.candidates.max(top_k) } else { top_k };
+
+    let mut retrieved_metrics = RetrievalMetrics::default();
+    let mut reranked_metrics = RetrievalMetrics::default();
+    let mut evaluated = Vec::with_capacity(queries.len());
+    for query in queries {
+        let relevant: HashSet<&str> = query.relevant.iter().map(String::as_str).collect();
+        if relevant.is_empty() {
+            return Err(ApiError::invalid_request(format!("查询“{}”的 relevant 不能为空", query.query)));
+        }
+        let filter = parse_filter(query.filter.as_ref())?;
+        let search_options = Search {
+            mode,
+            filter: filter.as_ref(),
+            config,
+        };
+        let candidates = search(state, workspace, index, &query.query, &search_options, count).await?;
+        retrieved_metrics.add(&measure(&candidates, &relevant, top_k));
+        let ids = |chunks: &[RetrievedChunk]| chunks.iter().take(top_k).map(|chunk| chunk.id.clone()).collect();
+        let retrieved = ids(&candidates);
+        let reranked = match &reranker {
+            Some(reranker) => {
+                let chunks = rerank(reranker.as_ref(), &query.query, candidates, config).await?;
+                reranked_metrics.add(&measure(&chunks, &relevant, top_k));
+                Some(ids(&chunks))
+            }
+            None => None,
+        };
+        evaluated.push(EvaluatedQuery {
+            query: query.query.clone(),
+            retrieved,
+            reranked,
+        });
+    }
+
+    Ok(EvaluateResponse {
+        object: "rag.evaluation".to_string(),
+        index: index.to_string(),
+        top_k,
+        mode,
+        reranker: reranker.as_ref().map(|reranker| reranker.model().to_string()),
+        retrieved: retrieved_metrics.average(queries.len()),
+        reranked: reranker.is_some().then(|| reranked_metrics.average(queries.len())),
+        queries: evaluated,
+    })
+}
+
+/// 按请求中的`rag`字段检索知识库，把结果作为参考资料插入到开头的系统消息之后
+///
+/// `rag`为`true`时检索`rag.default_index`，为对象时字段同[`RetrievalOptions`]；查询文本为最后一条用户消息。
+pub async fn augment(state: &AppState, workspace: &Workspace, request: &mut ChatCompletionRequest) -> ApiResult<()> {
+    let options = match request.extra.remove("rag") {
+        None | Some(Value::Bool(false)) => return Ok(()),
+        Some(Value::Bool(true)) => RetrievalOptions::default(),
+        Some(value @ Value::Object(_)) => {
+            serde_json::from_value(value).map_err(|e| ApiError::invalid_request(format!("无效的 rag: {}", e)))?
+        }
+        Some(_) => return Err(ApiError::invalid_request("rag 应为布尔值或对象")),
+    };
+    let Some(query) = request
+        .messages
+        .last()
+        .filter(|message| message.role == "user")
+        .map(ChatMessage::text)
+        .filter(|text| !text.trim().is_empty())
+    else {
+        return Ok(());
+    };
+
+    let mut retrieved = retrieve(state, workspace, &query, &options).await?;
+    if let Some(safety) = &state.safety {
+        retrieved.chunks.retain_mut(|chunk| {
+            match safety.screen_inbound(workspace, Source::Retrieval, &chunk.document, &chunk.text) {
+                Inbound::Pass => true,
+                Inbound::Annotated(text) => {
+                    chunk.text = text;
+                    true
+                }
+                Inbound::Blocked => false,
+            }
+        });
+    }
+    if retrieved.chunks.is_empty() {
+        return Ok(());
+    }
+    let mut content = String::from("以下是从知识库中检索到的参考资料，回答时如果用到，请用方括号中的编号注明出处：");
+    for (number, chunk) in retrieved.chunks.iter().enumerate() {
+        content.push_str(&format!("\n\n[{}] {}\n{}", number + 1, chunk.document, chunk.text));
+    }
+    let position = request.messages.iter().take_while(|message| message.role == "system").count();
+    request.messages.insert(
+        position,
+        ChatMessage {
+            role: "system".to_string(),
+            content: Some(MessageContent::Text(content)),
+            name: None,
+            extra: Map::new(),
+        },
+    );
+    Ok(())
+}
@@ -0,0 +1,434 @@
+//! 服务入口处的限流
+//!
+//! 按API密钥、用户或客户端IP分别维护两个令牌桶：请求桶每分钟补满`requests_per_minute`，
+//! token桶每分钟补满`tokens_per_minute`。每个请求到达时扣一次请求，对话的token在拿到用量后扣除，
+//! 余额为负时后续请求被拒绝直到补回。超出时返回429和`Retry-After`，
+//! 每个响应都带有与OpenAI相同的`x-ratelimit-*`头说明当前额度。
+//! `rate_limit.store`为`redis`时令牌桶保存在Redis中，负载均衡之后的各服务实例共用同一份额度；
+//! Redis不可用时放行请求并打印警告。
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::header::{AUTHORIZATION, RETRY_AFTER};
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+
+use crate::audit;
+use crate::auth::Principal;
+use crate::config::{RateLimitConfig, RateLimitKeyKind, StateStoreKind};
+use crate::error::ApiError;
+use crate::redis::Redis;
+use crate::AppState;
+
+/// 令牌桶从空到满的时间
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// 超过该时间没有请求的桶已经补满，可以丢弃；保存在Redis中的桶在这之后过期
+const IDLE: Duration = Duration::from_secs(120);
+
+/// 请求所属的限流对象，由中间件放入请求扩展，处理函数据此扣除token
+#[derive(Debug, Clone)]
+pub struct RateLimitKey(pub String);
+
+/// 客户端IP；`trust_proxy`时优先取`X-Forwarded-For`中的第一个地址
+pub fn client_ip(request: &Request, trust_proxy: bool) -> String {
+    if trust_proxy {
+        let forwarded = request
+            .headers()
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .map(str::trim)
+            .filter(|ip| !ip.is_empty());
+        if let Some(ip) = forwarded {
+            return ip.to_string();
+        }
+    }
+    request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    capacity: f64,
+    level: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(capacity: u32, now: Instant) -> Bucket {
+        Bucket {
+            capacity: capacity as f64,
+            level: capacity as f64,
+            updated: now,
+        }
+    }
+
+    fn resize(&mut self, capacity: u32) {
+        self.capacity = capacity as f64;
+        self.level = self.level.min(self.capacity);
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.level = (self.level + elapsed * self.capacity / WINDOW.as_secs_f64()).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// 余额补到`amount`还需要的时间
+    fn wait(&self, amount: f64) -> Duration {
+        if self.level >= amount || self.capacity <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((amount - self.level) * WINDOW.as_secs_f64() / self.capacity)
+    }
+
+    fn state(&self) -> BucketState {
+        BucketState {
+            limit: self.capacity as u64,
+            remaining: self.level.max(0.0) as u64,
+            reset: self.wait(self.capacity),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Buckets {
+    requests: Bucket,
+    tokens: Option<Bucket>,
+}
+
+impl Buckets {
+    fn new(config: &RateLimitConfig, now: Instant) -> Buckets {
+        Buckets {
+            requests: Bucket::new(config.requests_per_minute, now),
+            tokens: config.tokens_per_minute.map(|capacity| Bucket::new(capacity, now)),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        self.requests.refill(now);
+        if let Some(tokens) = &mut self.tokens {
+            tokens.refill(now);
+        }
+    }
+
+    /// 重新加载配置后按新的额度调整，余额不超过新的容量
+    fn resize(&mut self, config: &RateLimitConfig, now: Instant) {
+        self.requests.resize(config.requests_per_minute);
+        match (&mut self.tokens, config.tokens_per_minute) {
+            (Some(tokens), Some(capacity)) => tokens.resize(capacity),
+            (None, Some(capacity)) => self.tokens = Some(Bucket::new(capacity, now)),
+            (_, None) => self.tokens = None,
+        }
+    }
+}
+
+/// 保存在Redis中的余额
+#[derive(Debug, Serialize, Deserialize)]
+struct Saved {
+    requests: f64,
+    #[serde(default)]
+    tokens: Option<f64>,
+    /// 保存时的Unix时间（毫秒），读取时据此补回经过的时间
+    updated: u64,
+}
+
+impl Buckets {
+    /// 按保存的余额恢复，余额不超过当前配置的容量
+    fn restore(config: &RateLimitConfig, saved: &Saved, now: Instant) -> Buckets {
+        let elapsed = Duration::from_millis(audit::unix_ms().saturating_sub(saved.updated));
+        let mut entry = Buckets::new(config, now.checked_sub(elapsed).unwrap_or(now));
+        entry.requests.level = saved.requests.min(entry.requests.capacity);
+        if let (Some(bucket), Some(level)) = (&mut entry.tokens, saved.tokens) {
+            bucket.level = level.min(bucket.capacity);
+        }
+        entry.refill(now);
+        entry
+    }
+
+    fn save(&self) -> Saved {
+        Saved {
+            requests: self.requests.level,
+            tokens: self.tokens.as_ref().map(|tokens| tokens.level),
+            updated: audit::unix_ms(),
+        }
+    }
+
+    fn quota(&self) -> Quota {
+        Quota {
+            requests: self.requests.state(),
+            tokens: self.tokens.as_ref().map(Bucket::state),
+        }
+    }
+
+    /// 扣除一次请求；超出限额时不扣除，返回需要等待的时间
+    fn take(&mut self, now: Instant) -> Result<Quota, (Duration, Quota)> {
+        self.refill(now);
+        let wait = self
+            .requests
+            .wait(1.0)
+            .max(self.tokens.as_ref().map_or(Duration::ZERO, |tokens| tokens.wait(1.0)));
+        if !wait.is_zero() {
+            return Err((wait, self.quota()));
+        }
+        self.requests.level -= 1.0;
+        Ok(self.quota())
+    }
+
+    /// 按用量扣除token，余额最多欠一分钟的额度
+    fn charge(&mut self, tokens: u32, now: Instant) {
+        if let Some(bucket) = &mut self.tokens {
+            bucket.refill(now);
+            bucket.level = (bucket.level - tokens as f64).max(-bucket.capacity);
+        }
+    }
+}
+
+/// 某个桶当前的额度
+#[derive(Debug, Clone, Copy)]
+pub struct BucketState {
+    pub limit: u64,
+    pub remaining: u64,
+    /// 补满所需时间
+    pub reset: Duration,
+}
+
+/// 一次检查后的额度，用于生成响应头
+#[derive(Debug, Clone, Copy)]
+pub struct Quota {
+    pub requests: BucketState,
+    pub tokens: Option<BucketState>,
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// 重新加载配置时原地替换，已有的桶保留余额
+    config: RwLock<RateLimitConfig>,
+    /// 保存在本进程中的桶，使用Redis时为空
+    buckets: Mutex<HashMap<String, Buckets>>,
+    last_prune: Mutex<Instant>,
+    /// `rate_limit.store`为`redis`时为`Some`
+    redis: Option<Redis>,
+}
+
+impl RateLimiter {
+    /// `rate_limit.store`为`redis`时使用`redis`
+    pub fn new(config: &RateLimitConfig, redis: Option<&Redis>) -> RateLimiter {
+        RateLimiter {
+            config: RwLock::new(config.clone()),
+            buckets: Mutex::new(HashMap::new()),
+            last_prune: Mutex::new(Instant::now()),
+            redis: redis.filter(|_| config.store == StateStoreKind::Redis).cloned(),
+        }
+    }
+
+    /// 换用新的配置，已有的桶按新的额度调整；Redis中的桶在下次读取时调整
+    pub fn reconfigure(&self, config: &RateLimitConfig) {
+        let now = Instant::now();
+        *self.config.write().unwrap() = config.clone();
+        for entry in self.buckets.lock().unwrap().values_mut() {
+            entry.refill(now);
+            entry.resize(config, now);
+        }
+    }
+
+    /// 请求所属的限流对象；取不到API密钥或用户时按IP
+    ///
+    /// 启用认证时按用户限流使用认证得到的身份，不再信任客户端自报的请求头。
+    pub fn key(&self, request: &Request) -> RateLimitKey {
+        let config = self.config.read().unwrap();
+        let headers = request.headers();
+        let key = match config.key_by {
+            RateLimitKeyKind::ApiKey => headers
+                .get(AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+                .map(|key| format!("key:{}", key)),
+            RateLimitKeyKind::User => match request.extensions().get::<Principal>() {
+                Some(principal) => Some(format!("user:{}", principal.subject)),
+                None => headers
+                    .get(config.user_header.as_str())
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::trim)
+                    .filter(|user| !user.is_empty())
+                    .map(|user| format!("user:{}", user)),
+            },
+            RateLimitKeyKind::Ip => None,
+        };
+        RateLimitKey(key.unwrap_or_else(|| format!("ip:{}", client_ip(request, config.trust_proxy))))
+    }
+
+    fn prune(&self, buckets: &mut HashMap<String, Buckets>, now: Instant) {
+        let mut last_prune = self.last_prune.lock().unwrap();
+        if now.saturating_duration_since(*last_prune) < IDLE {
+            return;
+        }
+        buckets.retain(|_, entry| now.saturating_duration_since(entry.requests.updated) < IDLE);
+        *last_prune = now;
+    }
+
+    /// Redis中保存某个对象的桶的键；API密钥等只保存摘要
+    fn redis_key(redis: &Redis, key: &RateLimitKey) -> String {
+        let hash: String = digest(&SHA256, key.0.as_bytes()).as_ref()[..16]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        redis.key(&format!("ratelimit:{}", hash))
+    }
+
+    /// 扣除一次请求；超出限额时不扣除，返回需要等待的时间
+    pub async fn acquire(&self, key: &RateLimitKey) -> Result<Quota, (Duration, Quota)> {
+        let config = self.config.read().unwrap().clone();
+        let Some(redis) = &self.redis else {
+            let now = Instant::now();
+            let mut buckets = self.buckets.lock().unwrap();
+            self.prune(&mut buckets, now);
+            let entry = buckets.entry(key.0.clone()).or_insert_with(|| Buckets::new(&config, now));
+            return entry.take(now);
+        };
+
+        let redis_key = Self::redis_key(redis, key);
+        let shared = config.clone();
+        let result = redis
+            .run(move |redis| {
+                redis.update(&redis_key, IDLE, |saved| {
+                    let now = Instant::now();
+                    let saved = saved.and_then(|bytes| serde_json::from_slice(&bytes).ok());
+                    let mut entry = match &saved {
+                        Some(saved) => Buckets::restore(&shared, saved, now),
+                        None => Buckets::new(&shared, now),
+                    };
+                    let result = entry.take(now);
+                    (serde_json::to_vec(&entry.save()).ok(), result)
+                })
+            })
+            .await;
+        result.unwrap_or_else(|err| {
+            eprintln!("⚠️ 读取Redis中的限流状态失败，放行请求: {}", err);
+            Ok(Buckets::new(&config, Instant::now()).quota())
+        })
+    }
+
+    /// 按用量扣除token，余额最多欠一分钟的额度；使用Redis时在后台写入
+    pub fn charge(&self, key: &RateLimitKey, tokens: u32) {
+        let Some(redis) = &self.redis else {
+            let mut buckets = self.buckets.lock().unwrap();
+            if let Some(entry) = buckets.get_mut(&key.0) {
+                entry.charge(tokens, Instant::now());
+            }
+            return;
+        };
+        if self.config.read().unwrap().tokens_per_minute.is_none() {
+            return;
+        }
+
+        let config = self.config.read().unwrap().clone();
+        let redis_key = Self::redis_key(redis, key);
+        let redis = redis.clone();
+        tokio::spawn(async move {
+            let result = redis
+                .run(move |redis| {
+                    redis.update(&redis_key, IDLE, |saved| {
+                        let now = Instant::now();
+                        let saved: Option<Saved> = saved.and_then(|bytes| serde_json::from_slice(&bytes).ok());
+                        let Some(saved) = saved else {
+                            return (None, ());
+                        };
+                        let mut entry = Buckets::restore(&config, &saved, now);
+                        entry.charge(tokens, now);
+                        (serde_json::to_vec(&entry.save()).ok(), ())
+                    })
+                })
+                .await;
+            if let Err(err) = result {
+                eprintln!("⚠️ 写入Redis中的限流状态失败: {}", err);
+            }
+        });
+    }
+}
+
+/// 扣除对话用掉的token，未启用限流时什么也不做
+pub fn charge(state: &AppState, key: Option<&RateLimitKey>, tokens: u32) {
+    if let (Some(limiter), Some(key)) = (state.rate_limiter().as_ref(), key) {
+        limiter.charge(key, tokens);
+    }
+}
+
+/// `Retry-After`的秒数，至少1秒
+fn retry_seconds(wait: Duration) -> u64 {
+    wait.as_secs_f64().ceil().max(1.0) as u64
+}
+
+/// 超出额度时返回给客户端的错误
+pub fn rate_limited(wait: Duration) -> ApiError {
+    ApiError::RateLimited(format!("请求过于频繁，请在 {} 秒后重试", retry_seconds(wait)))
+}
+
+/// `1s`、`6m0s`形式的时间，与OpenAI的`x-ratelimit-reset-*`一致
+fn format_reset(duration: Duration) -> String {
+    let seconds = duration.as_secs_f64().ceil() as u64;
+    if seconds >= 60 {
+        format!("{}m{}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+fn insert_state(headers: &mut HeaderMap, kind: &str, state: &BucketState) {
+    let values = [
+        ("limit", state.limit.to_string()),
+        ("remaining", state.remaining.to_string()),
+        ("reset", format_reset(state.reset)),
+    ];
+    for (name, value) in values {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::try_from(format!("x-ratelimit-{}-{}", name, kind)),
+            HeaderValue::try_from(value),
+        ) {
+            headers.insert(name, value);
+        }
+    }
+}
+
+fn insert_quota(headers: &mut HeaderMap, quota: &Quota) {
+    insert_state(headers, "requests", &quota.requests);
+    if let Some(tokens) = &quota.tokens {
+        insert_state(headers, "tokens", tokens);
+    }
+}
+
+/// 限流中间件，未启用限流时直接放行
+pub async fn limit(State(state): State<Arc<AppState>>, mut request: Request, next: Next) -> Response {
+    let limiter = state.rate_limiter();
+    let Some(limiter) = limiter.as_ref() else {
+        return next.run(request).await;
+    };
+    let key = limiter.key(&request);
+    match limiter.acquire(&key).await {
+        Ok(quota) => {
+            request.extensions_mut().insert(key);
+            let mut response = next.run(request).await;
+            insert_quota(response.headers_mut(), &quota);
+            response
+        }
+        Err((wait, quota)) => {
+            let mut response = rate_limited(wait).into_response();
+            insert_quota(response.headers_mut(), &quota);
+            response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(retry_seconds(wait)));
+            response
+        }
+    }
+}
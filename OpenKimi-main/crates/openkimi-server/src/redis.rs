@@ -0,0 +1,101 @@
+//! 多个服务实例共享状态用的Redis
+//!
+//! `sessions`、`rate_limit`、`cache`或`streams`的`store`为`redis`时在启动时连接，各部分共用一个连接池，
+//! 键都以`redis.key_prefix`开头。客户端是阻塞的，命令在Tokio的阻塞线程中执行。
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use openkimi_redis::{Client, RedisError};
+
+use crate::config::RedisConfig;
+
+/// 读取之后被其他实例修改时最多重试的次数
+const MAX_ATTEMPTS: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct Redis {
+    client: Client,
+    prefix: Arc<str>,
+}
+
+impl Redis {
+    /// 连接`redis.url`并确认可以访问
+    pub fn open(config: &RedisConfig) -> Result<Redis, String> {
+        let timeout = Duration::from_secs(config.timeout_seconds.max(1));
+        let client = Client::open(&config.url, config.pool_size, timeout)
+            .map_err(|e| format!("连接Redis {} 失败: {}", config.url, e))?;
+        Ok(Redis {
+            client,
+            prefix: Arc::from(config.key_prefix.as_str()),
+        })
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// 加上前缀的键
+    pub fn key(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name)
+    }
+
+    /// 在阻塞线程中执行
+    pub async fn run<T, F>(&self, f: F) -> Result<T, RedisError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Redis) -> Result<T, RedisError> + Send + 'static,
+    {
+        let redis = self.clone();
+        tokio::task::spawn_blocking(move || f(&redis))
+            .await
+            .map_err(|e| RedisError::Io(std::io::Error::other(e.to_string())))?
+    }
+
+    /// 确认Redis可以访问
+    pub async fn ping(&self) -> Result<(), RedisError> {
+        self.run(|redis| redis.client.query(&[&"PING"]).map(|_| ())).await
+    }
+
+    /// 读取、修改并写回一个键，`ttl`后过期；读取之后其他实例修改了这个键时重新读取再试
+    ///
+    /// `change`得到当前的值（不存在时为`None`），返回要写入的值和结果，要写入的值为`None`时不修改。
+    pub fn update<T>(
+        &self,
+        key: &str,
+        ttl: Duration,
+        mut change: impl FnMut(Option<Vec<u8>>) -> (Option<Vec<u8>>, T),
+    ) -> Result<T, RedisError> {
+        let ttl = (ttl.as_millis() as u64).max(1);
+        for _ in 0..MAX_ATTEMPTS {
+            let mut conn = self.client.get()?;
+            let attempt = (|| {
+                conn.query(&[&"WATCH", &key])?;
+                let current = conn.query(&[&"GET", &key])?.into_bytes();
+                let (value, result) = change(current);
+                let Some(value) = value else {
+                    conn.query(&[&"UNWATCH"])?;
+                    return Ok(Some(result));
+                };
+                conn.query(&[&"MULTI"])?;
+                conn.query(&[&"SET", &key, &value, &"PX", &ttl])?;
+                let committed = !conn.query(&[&"EXEC"])?.is_nil();
+                Ok(committed.then_some(result))
+            })();
+            match attempt {
+                Ok(Some(result)) => return Ok(result),
+                Ok(None) => continue,
+                Err(err) => {
+                    // 连接上可能还留着WATCH或未提交的事务，不再放回池中
+                    conn.discard();
+                    return Err(err);
+                }
+            }
+        }
+        Err(RedisError::Server(format!("键 {} 正被频繁修改", key)))
+    }
+}
@@ -0,0 +1,246 @@
+//! 配置热更新
+//!
+//! 收到SIGHUP，或启用`reload.watch`后配置文件的修改时间变化时，重新读取配置文件并替换可以热更新的部分：
+//! 上游后端和模型路由（`llm`、`backends`、`models`）、上下文压缩、限流、认证令牌和权限规则、用户和团队的配额、结构化输出，
+//! 以及`pii.upstream`、`pii.transcripts`和`mcp.tools`等按请求读取的开关。
+//! 新配置先完整解析并构建出新的组件，任何一步失败都保留原配置；进行中的请求和流式响应继续使用开始时的配置。
+//! 其余部分（如数据库路径、各功能的`enabled`）需要重启才能生效，修改时打印警告，运行中仍使用原来的值。
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use serde_json::{json, Value};
+
+use crate::auth::Authenticator;
+use crate::config::{Config, LlmConfig, RateLimitConfig};
+use crate::context::ContextManager;
+use crate::ratelimit::RateLimiter;
+use crate::router::ModelRouter;
+use crate::AppState;
+
+/// 热更新的配置路径，`*`匹配任意一段；修改这些路径之外的配置需要重启
+const RELOADABLE: &[&str] = &[
+    "llm",
+    "backends",
+    "models",
+    "fallback",
+    "context",
+    "rate_limit",
+    "auth",
+    "workspaces.list.*.tokens",
+    "quotas.default",
+    "quotas.users",
+    "quotas.teams",
+    "structured_output",
+    "pii.upstream",
+    "pii.transcripts",
+    "mcp.tools",
+    "mcp.prompts",
+    "mcp.resources",
+];
+
+/// 在`RELOADABLE`之内但仍需要重启的路径，回复缓存和长期记忆在启动时确定了嵌入模型，Redis和本地模型在启动时连接或加载
+const RESTART_REQUIRED: &[&str] = &["llm.embedding_model", "llm.gguf", "backends.*.gguf", "rate_limit.store"];
+
+/// 运行中可以整体替换的值；读取时得到当前值的`Arc`，替换不影响已经取得旧值的请求，如进行中的流式响应
+#[derive(Debug)]
+pub struct Live<T>(RwLock<Arc<T>>);
+
+impl<T> Live<T> {
+    pub fn new(value: Arc<T>) -> Live<T> {
+        Live(RwLock::new(value))
+    }
+
+    pub fn load(&self) -> Arc<T> {
+        Arc::clone(&self.0.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    pub fn store(&self, value: Arc<T>) {
+        *self.0.write().unwrap_or_else(|e| e.into_inner()) = value;
+    }
+}
+
+/// `pattern`是否匹配`path`或它的上级
+fn matches(pattern: &str, path: &str) -> bool {
+    let mut segments = path.split('.');
+    pattern
+        .split('.')
+        .all(|expected| segments.next().is_some_and(|segment| expected == "*" || expected == segment))
+}
+
+fn needs_restart(path: &str) -> bool {
+    RESTART_REQUIRED.iter().any(|pattern| matches(pattern, path))
+        || !RELOADABLE.iter().any(|pattern| matches(pattern, path))
+}
+
+/// 两份配置文件中不同的字段路径，对象逐层比较，数组和其他值整体比较
+fn changed(prefix: &str, old: &Value, new: &Value, paths: &mut Vec<String>) {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+            for key in keys {
+                let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                changed(&path, old.get(key).unwrap_or(&Value::Null), new.get(key).unwrap_or(&Value::Null), paths);
+            }
+        }
+        _ if old != new => paths.push(prefix.to_string()),
+        _ => {}
+    }
+}
+
+/// 配置文件的原始内容，用于比较修改了哪些字段；没有配置文件或无法解析时为`null`
+fn read_raw(path: Option<&Path>) -> Value {
+    path.and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or(Value::Null)
+}
+
+/// 在当前配置上替换可以热更新的部分，其余保持不变
+fn merge(current: &Config, loaded: Config) -> Config {
+    let mut next = current.clone();
+    next.llm = LlmConfig {
+        embedding_model: current.llm.embedding_model.clone(),
+        ..loaded.llm
+    };
+    next.backends = loaded.backends;
+    next.models = loaded.models;
+    next.fallback = loaded.fallback;
+    next.context = loaded.context;
+    next.rate_limit = RateLimitConfig {
+        store: current.rate_limit.store,
+        ..loaded.rate_limit
+    };
+    next.auth = loaded.auth;
+    for (name, workspace) in &mut next.workspaces.list {
+        if let Some(reloaded) = loaded.workspaces.list.get(name) {
+            workspace.tokens = reloaded.tokens.clone();
+        }
+    }
+    next.quotas.default = loaded.quotas.default;
+    next.quotas.users = loaded.quotas.users;
+    next.quotas.teams = loaded.quotas.teams;
+    next.structured_output = loaded.structured_output;
+    next.pii.upstream = loaded.pii.upstream;
+    next.pii.transcripts = loaded.pii.transcripts;
+    next.mcp.tools = loaded.mcp.tools;
+    next.mcp.prompts = loaded.mcp.prompts;
+    next.mcp.resources = loaded.mcp.resources;
+    next
+}
+
+/// 重新加载配置文件，返回修改了的字段，其中一部分可能需要重启才能生效，见[`needs_restart`]
+///
+/// `raw`为上次加载的配置文件内容，成功后更新为这次的内容。
+pub fn reload(state: &AppState, path: Option<&Path>, raw: &mut Value) -> Result<Vec<String>, String> {
+    let config = merge(&state.config(), Config::load(path)?);
+    let models = Arc::new(ModelRouter::new(&config, state.vault.clone())?);
+    let context = ContextManager::from_config(&config.context, &models, &config.llm.model_name);
+    let auth = if config.auth.enabled {
+        Some(Authenticator::new(&config.auth, &config.workspaces, state.users.clone())?)
+    } else {
+        None
+    };
+
+    // 都构建成功后再替换；限流保持启用时原地更新，各客户端的余额不清零
+    let limiter = state.rate_limiter();
+    match (limiter.as_ref(), config.rate_limit.enabled) {
+        (Some(limiter), true) => limiter.reconfigure(&config.rate_limit),
+        (_, enabled) => {
+            let limiter = enabled.then(|| RateLimiter::new(&config.rate_limit, state.redis.as_ref()));
+            state.rate_limiter.store(Arc::new(limiter));
+        }
+    }
+    if let Some(quotas) = &state.quotas {
+        quotas.reconfigure(&config.quotas);
+    }
+    state.models.store(models);
+    state.context.store(Arc::new(context));
+    state.auth.store(Arc::new(auth));
+    state.config.store(Arc::new(config));
+
+    let next = read_raw(path);
+    let mut paths = Vec::new();
+    changed("", raw, &next, &mut paths);
+    *raw = next;
+    Ok(paths)
+}
+
+fn reload_and_report(state: &AppState, path: Option<&Path>, raw: &mut Value, trigger: &str) {
+    match reload(state, path, raw) {
+        Ok(changed) => {
+            println!("🔄 已重新加载配置");
+            let restart: Vec<&String> = changed.iter().filter(|path| needs_restart(path)).collect();
+            if !restart.is_empty() {
+                let list: Vec<&str> = restart.iter().map(|path| path.as_str()).collect();
+                eprintln!("⚠️ 以下配置的修改需要重启才能生效: {}", list.join(", "));
+            }
+            if let Some(trail) = &state.trail {
+                let fields = json!({ "trigger": trigger, "changed": changed, "restart_required": restart });
+                trail.append("config.reloaded", None, fields);
+            }
+        }
+        Err(err) => {
+            eprintln!("⚠️ 重新加载配置失败，继续使用原配置: {}", err);
+            if let Some(trail) = &state.trail {
+                trail.append("config.reload_failed", None, json!({ "trigger": trigger, "error": err }));
+            }
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// 收到SIGHUP或配置文件修改后重新加载；需要在Tokio运行时中调用
+pub fn listen(state: &Arc<AppState>, path: Option<PathBuf>) {
+    let config = state.config().reload.clone();
+    let state = Arc::downgrade(state);
+    tokio::spawn(async move {
+        let mut raw = read_raw(path.as_deref());
+        let watched = path.as_deref().filter(|_| config.watch);
+        let mut last_modified = watched.and_then(modified);
+        let interval = Duration::from_secs(config.interval_seconds.max(1));
+        #[cfg(unix)]
+        let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+            Ok(signal) => Some(signal),
+            Err(err) => {
+                eprintln!("⚠️ 无法监听 SIGHUP: {}", err);
+                None
+            }
+        };
+        loop {
+            #[cfg(unix)]
+            let signaled = async {
+                match &mut hangup {
+                    Some(signal) => signal.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            #[cfg(not(unix))]
+            let signaled = std::future::pending::<Option<()>>();
+            // 触发重新加载的原因，记入安全事件日志
+            let trigger = tokio::select! {
+                _ = signaled => "sighup",
+                () = tokio::time::sleep(interval), if watched.is_some() => {
+                    let current = watched.and_then(modified);
+                    let changed = current != last_modified;
+                    last_modified = current;
+                    if changed { "watch" } else { continue }
+                }
+            };
+            let Some(state) = state.upgrade() else {
+                return;
+            };
+            if state.shutdown.is_draining() {
+                return;
+            }
+            reload_and_report(&state, path.as_deref(), &mut raw, trigger);
+            // 由SIGHUP触发时同样记下修改时间，避免再次加载同一份文件
+            last_modified = watched.and_then(modified);
+        }
+    });
+}
@@ -0,0 +1,346 @@
+//! 模型路由
+//!
+//! 把客户端使用的逻辑模型名（如`kimi-default`、`kimi-fast`）映射到`models`中配置的后端和实际模型id，
+//! 并按后端类型调整请求参数，客户端无需关心各服务商的模型名和参数差异。
+//! 不在`models`中的模型名原样发给默认后端（`llm`部分）。
+//!
+//! 对话补全失败时按`fallbacks`依次改用其他模型，请求体的`fallbacks`字段可以逐请求覆盖，
+//! 实际应答的模型和后端由[`track`]取得，HTTP接口据此设置`x-openkimi-*`响应头。
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::http::{HeaderMap, HeaderValue};
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::config::{BackendKind, Config, FallbackTrigger, ModelRoute, VisionLimits, DEFAULT_BACKEND};
+use crate::error::{ApiError, ApiResult};
+use crate::types::{ChatCompletionRequest, ChatCompletionResponse, EmbeddingRequest, EmbeddingResponse};
+use crate::upstream::Upstream;
+use crate::vault::KeyVault;
+
+/// Ollama的OpenAI兼容接口不支持、会被忽略的参数
+const OLLAMA_UNSUPPORTED: [&str; 3] = ["logit_bias", "n", "user"];
+
+/// Moonshot的`temperature`取值范围是`[0, 1]`
+const MOONSHOT_MAX_TEMPERATURE: f64 = 1.0;
+
+/// 实际应答的后端名
+pub const BACKEND_HEADER: &str = "x-openkimi-backend";
+/// 实际应答的逻辑模型名
+pub const MODEL_HEADER: &str = "x-openkimi-model";
+/// 失败后被跳过的模型名，逗号分隔，没有降级时不设置
+pub const FALLBACK_HEADER: &str = "x-openkimi-fallback";
+
+tokio::task_local! {
+    static ANSWERED: Arc<Mutex<Option<Answered>>>;
+}
+
+/// 实际应答对话请求的模型
+#[derive(Debug, Clone)]
+pub struct Answered {
+    pub model: String,
+    pub backend: String,
+    /// 依次失败的模型名
+    pub failed: Vec<String>,
+}
+
+impl Answered {
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        let values = [
+            (BACKEND_HEADER, self.backend.clone()),
+            (MODEL_HEADER, self.model.clone()),
+            (FALLBACK_HEADER, self.failed.join(",")),
+        ];
+        for (name, value) in values {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                if !value.is_empty() {
+                    headers.insert(name, value);
+                }
+            }
+        }
+    }
+}
+
+/// 执行`future`并取得其中最后一次对话补全实际使用的模型
+///
+/// 流式请求只记录到开始输出为止，服务端工具后续各轮的上游请求不计入。
+pub async fn track<F: Future>(future: F) -> (F::Output, Option<Answered>) {
+    let slot = Arc::new(Mutex::new(None));
+    let output = ANSWERED.scope(Arc::clone(&slot), future).await;
+    let answered = slot.lock().unwrap().take();
+    (output, answered)
+}
+
+/// 一个上游后端
+#[derive(Debug)]
+pub struct Backend {
+    pub name: String,
+    pub kind: BackendKind,
+    pub upstream: Upstream,
+}
+
+/// 模型名解析的结果
+#[derive(Debug, Clone, Copy)]
+pub struct Route<'a> {
+    pub backend: &'a Backend,
+    /// 发给后端的模型id
+    pub model: &'a str,
+    /// `models`中配置的上下文窗口
+    pub context_length: Option<u32>,
+    /// `models`中配置的图片限制
+    pub vision: Option<&'a VisionLimits>,
+}
+
+#[derive(Debug)]
+pub struct ModelRouter {
+    /// 包括名为`default`的`llm`部分
+    backends: BTreeMap<String, Backend>,
+    routes: BTreeMap<String, ModelRoute>,
+    /// 改用下一个模型的条件
+    fallback_on: Vec<FallbackTrigger>,
+}
+
+impl ModelRouter {
+    pub fn new(config: &Config, vault: Option<Arc<KeyVault>>) -> Result<ModelRouter, String> {
+        if config.backends.contains_key(DEFAULT_BACKEND) {
+            return Err(format!("backends 中不能使用保留名称 {}，默认后端请配置在 llm 部分", DEFAULT_BACKEND));
+        }
+        let mut backends = BTreeMap::new();
+        let all = std::iter::once((DEFAULT_BACKEND, &config.llm.backend))
+            .chain(config.backends.iter().map(|(name, backend)| (name.as_str(), backend)));
+        for (name, backend) in all {
+            let upstream = Upstream::new(name, backend, vault.clone())?;
+            backends.insert(
+                name.to_string(),
+                Backend {
+                    name: name.to_string(),
+                    kind: backend.kind,
+                    upstream,
+                },
+            );
+        }
+        for (name, route) in &config.models {
+            if !backends.contains_key(&route.backend) {
+                return Err(format!("模型 {} 使用的后端 {} 不存在", name, route.backend));
+            }
+            if route.model.is_empty() {
+                return Err(format!("模型 {} 缺少 model", name));
+            }
+            for fallback in &route.fallbacks {
+                if fallback == name {
+                    return Err(format!("模型 {} 的 fallbacks 不能包含自身", name));
+                }
+                if !config.models.contains_key(fallback) {
+                    return Err(format!("模型 {} 的 fallbacks 中的 {} 不在 models 中", name, fallback));
+                }
+            }
+        }
+        Ok(ModelRouter {
+            backends,
+            routes: config.models.clone(),
+            fallback_on: config.fallback.on.clone(),
+        })
+    }
+
+    /// `llm`部分对应的后端
+    pub fn default_backend(&self) -> &Backend {
+        &self.backends[DEFAULT_BACKEND]
+    }
+
+    /// 全部后端，按名称排序
+    pub fn backends(&self) -> impl Iterator<Item = &Backend> {
+        self.backends.values()
+    }
+
+    /// `models`中配置的逻辑模型名
+    pub fn model_names(&self) -> impl Iterator<Item = &str> {
+        self.routes.keys().map(String::as_str)
+    }
+
+    /// 解析模型名，未配置的模型名原样发给默认后端
+    pub fn route<'a>(&'a self, model: &'a str) -> Route<'a> {
+        match self.routes.get(model) {
+            Some(route) => Route {
+                backend: &self.backends[&route.backend],
+                model: &route.model,
+                context_length: route.context_length,
+                vision: route.vision.as_ref(),
+            },
+            None => Route {
+                backend: self.default_backend(),
+                model,
+                context_length: None,
+                vision: None,
+            },
+        }
+    }
+
+    /// 依次尝试的模型名：请求的模型，然后是请求体中的`fallbacks`，缺省为该模型配置的`fallbacks`
+    fn chain<'a>(&'a self, request: &'a ChatCompletionRequest) -> ApiResult<Vec<&'a str>> {
+        let mut chain = vec![request.model.as_str()];
+        match request.extra.get("fallbacks") {
+            None | Some(Value::Null) => {
+                let configured = self.routes.get(&request.model).map(|route| route.fallbacks.as_slice());
+                chain.extend(configured.unwrap_or_default().iter().map(String::as_str));
+            }
+            Some(Value::Array(models)) => {
+                for model in models {
+                    match model.as_str() {
+                        Some(model) if !model.is_empty() => chain.push(model),
+                        _ => return Err(ApiError::invalid_request("fallbacks 必须是模型名数组")),
+                    }
+                }
+            }
+            Some(_) => return Err(ApiError::invalid_request("fallbacks 必须是模型名数组")),
+        }
+        Ok(chain)
+    }
+
+    /// 沿模型链发送对话请求，直到成功、遇到不触发降级的错误或用完所有模型
+    async fn with_fallbacks<T>(
+        &self,
+        request: &ChatCompletionRequest,
+        send: impl for<'r> Fn(&'r Upstream, &'r Map<String, Value>) -> BoxFuture<'r, ApiResult<T>>,
+        filtered: impl Fn(&T) -> bool,
+    ) -> ApiResult<T> {
+        let chain = self.chain(request)?;
+        let mut failed = Vec::new();
+        for (position, &model) in chain.iter().enumerate() {
+            let route = self.route(model);
+            let body = translate_chat(route, request)?;
+            let sent = send(&route.backend.upstream, &body);
+            let timeout = self.routes.get(model).and_then(|route| route.timeout_seconds);
+            let (result, trigger) = match timeout {
+                Some(seconds) => match tokio::time::timeout(Duration::from_secs(seconds), sent).await {
+                    Ok(result) => classify(result, &filtered),
+                    Err(_) => {
+                        let message = format!("模型 {} 在 {} 秒内没有响应", model, seconds);
+                        (Err(ApiError::Upstream(message)), Some(FallbackTrigger::Timeout))
+                    }
+                },
+                None => classify(sent.await, &filtered),
+            };
+            let next = chain.get(position + 1);
+            if let (Some(trigger), Some(next)) = (trigger, next) {
+                if self.fallback_on.contains(&trigger) {
+                    eprintln!("↪️ 模型 {} {}，改用 {}", model, describe(trigger), next);
+                    failed.push(model.to_string());
+                    continue;
+                }
+            }
+            if result.is_ok() {
+                let answered = Answered {
+                    model: model.to_string(),
+                    backend: route.backend.name.clone(),
+                    failed,
+                };
+                let _ = ANSWERED.try_with(|slot| *slot.lock().unwrap() = Some(answered));
+            }
+            return result;
+        }
+        Err(ApiError::Internal("模型链为空".to_string()))
+    }
+
+    pub async fn chat_completion(&self, request: &ChatCompletionRequest) -> ApiResult<ChatCompletionResponse> {
+        let filtered = |response: &ChatCompletionResponse| {
+            !response.choices.is_empty()
+                && response
+                    .choices
+                    .iter()
+                    .all(|choice| choice.finish_reason.as_deref() == Some("content_filter"))
+        };
+        self.with_fallbacks(request, |upstream, body| upstream.chat_completion(body).boxed(), filtered)
+            .await
+    }
+
+    /// 流式请求只在开始输出之前降级，输出中途被审核截断时不再改用其他模型
+    pub async fn chat_completion_stream(&self, request: &ChatCompletionRequest) -> ApiResult<reqwest::Response> {
+        self.with_fallbacks(request, |upstream, body| upstream.chat_completion_stream(body).boxed(), |_| false)
+            .await
+    }
+
+    /// 嵌入不降级，不同模型的向量不能混用
+    pub async fn embeddings(&self, request: &EmbeddingRequest) -> ApiResult<EmbeddingResponse> {
+        let route = self.route(&request.model);
+        let body = with_model(request, route.model)?;
+        route.backend.upstream.embeddings(&body).await
+    }
+}
+
+/// 判断一次请求的结果是否触发降级
+fn classify<T>(result: ApiResult<T>, filtered: impl Fn(&T) -> bool) -> (ApiResult<T>, Option<FallbackTrigger>) {
+    let trigger = match &result {
+        Ok(value) => filtered(value).then_some(FallbackTrigger::ContentFilter),
+        Err(ApiError::Upstream(_)) => Some(FallbackTrigger::Error),
+        Err(ApiError::UpstreamStatus(_, body)) if content_filtered(body) => Some(FallbackTrigger::ContentFilter),
+        Err(ApiError::UpstreamStatus(status, _)) => {
+            let retryable = matches!(status.as_u16(), 401 | 403 | 404 | 408 | 429) || status.is_server_error();
+            retryable.then_some(FallbackTrigger::Error)
+        }
+        Err(_) => None,
+    };
+    (result, trigger)
+}
+
+/// 上游的错误体是否表示内容审核拒绝，Moonshot用`type`，OpenAI和Azure用`code`
+fn content_filtered(body: &Value) -> bool {
+    ["type", "code"].iter().any(|key| {
+        body["error"][key]
+            .as_str()
+            .is_some_and(|value| value.contains("content_filter") || value.contains("content_policy"))
+    })
+}
+
+fn describe(trigger: FallbackTrigger) -> &'static str {
+    match trigger {
+        FallbackTrigger::Error => "请求失败",
+        FallbackTrigger::Timeout => "超时",
+        FallbackTrigger::ContentFilter => "被内容审核拒绝",
+    }
+}
+
+/// 序列化请求并换成后端的模型id
+fn with_model<T: Serialize>(request: &T, model: &str) -> ApiResult<Map<String, Value>> {
+    match serde_json::to_value(request) {
+        Ok(Value::Object(mut body)) => {
+            body.insert("model".to_string(), Value::String(model.to_string()));
+            Ok(body)
+        }
+        Ok(_) => Err(ApiError::Internal("请求序列化后不是JSON对象".to_string())),
+        Err(err) => Err(ApiError::Internal(format!("无法序列化请求: {}", err))),
+    }
+}
+
+/// 按后端类型调整对话请求的参数
+fn translate_chat(route: Route<'_>, request: &ChatCompletionRequest) -> ApiResult<Map<String, Value>> {
+    let mut body = with_model(request, route.model)?;
+    body.remove("fallbacks");
+    if route.backend.kind != BackendKind::Openai {
+        // 只有OpenAI把`max_tokens`换成了`max_completion_tokens`，其他后端仍只认前者
+        if let Some(max_tokens) = body.remove("max_completion_tokens") {
+            body.entry("max_tokens").or_insert(max_tokens);
+        }
+    }
+    match route.backend.kind {
+        BackendKind::Moonshot => {
+            if let Some(temperature) = body.get_mut("temperature") {
+                if temperature.as_f64().is_some_and(|value| value > MOONSHOT_MAX_TEMPERATURE) {
+                    *temperature = Value::from(MOONSHOT_MAX_TEMPERATURE);
+                }
+            }
+        }
+        BackendKind::Ollama => {
+            for key in OLLAMA_UNSUPPORTED {
+                body.remove(key);
+            }
+        }
+        BackendKind::Openai | BackendKind::Local => {}
+    }
+    Ok(body)
+}
@@ -0,0 +1,360 @@
+//! HTTP路由
+
+use std::sync::Arc;
+
+use axum::extract::{DefaultBodyLimit, State};
+use axum::http::{HeaderMap, HeaderValue};
+use axum::middleware;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post, MethodRouter};
+use axum::{Extension, Json, Router};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use openkimi_rag::{Document, DocumentKind};
+use serde_json::{json, Value};
+
+use crate::cache::Lookup;
+use crate::error::{ApiError, ApiResult};
+use crate::types::{
+    ChatCompletionRequest, ChatCompletionResponse, EmbeddingRequest, EmbeddingResponse, EvaluateRequest,
+    EvaluateResponse, IngestRequest, IngestResponse, ModelList, QueryRequest, QueryResponse,
+};
+use crate::metering::{self, Consumer};
+use crate::ratelimit::{self, RateLimitKey};
+use crate::rag::RetrievalOptions;
+use crate::tools::ToolScope;
+use crate::workspace::{self, Workspace};
+use crate::{
+    admin, agents, audio, audit, auth, batch, cache, files, idempotency, interpreter, llama, mcp, memory, metrics,
+    model_store, pii, probe, prompts, quotas, rag, router, safety, search, sessions, speech, sse, streams, structured,
+    telemetry, trail, vision, ws, AppState,
+};
+
+/// 消耗上游额度的接口检查用户和团队的配额，读取会话等接口不受影响
+fn metered(state: &Arc<AppState>, route: MethodRouter<Arc<AppState>>) -> MethodRouter<Arc<AppState>> {
+    route.layer(middleware::from_fn_with_state(Arc::clone(state), quotas::enforce))
+}
+
+pub fn router(state: Arc<AppState>) -> Router {
+    let admin = admin::router(Arc::clone(&state));
+    let exporter = metrics::router(Arc::clone(&state));
+    let probes = probe::router(Arc::clone(&state));
+    let tracked = state.metrics.is_some().then(|| Arc::clone(&state));
+    let traced = state.tracer.is_some().then(|| Arc::clone(&state));
+    let mut chat = post(chat_completions);
+    if state.config().vision.enabled {
+        // 带图片的请求体远大于axum默认的2MB上限
+        chat = chat
+            .layer(DefaultBodyLimit::max(state.config().vision.max_request_bytes))
+            .layer(middleware::from_fn_with_state(Arc::clone(&state), vision::limit_body));
+    }
+    let chat = metered(&state, chat);
+    let mut transcriptions = post(audio::transcriptions);
+    if state.config().audio.enabled {
+        transcriptions = transcriptions
+            .layer(DefaultBodyLimit::max(audio::max_request_bytes(&state.config().audio)))
+            .layer(middleware::from_fn_with_state(Arc::clone(&state), audio::limit_body));
+    }
+    let transcriptions = metered(&state, transcriptions);
+    let mut batch = post(batch::create_batch);
+    if state.config().batch.enabled {
+        batch = batch.layer(DefaultBodyLimit::max(state.config().batch.max_request_bytes));
+    }
+    let batch = get(batch::list_batches).merge(metered(&state, batch));
+    let router = Router::new()
+        .route("/v1/chat/completions", chat)
+        .route("/v1/chat/streams/{id}", get(streams::resume_stream))
+        .route("/v1/chat/ws", metered(&state, get(ws::chat_socket)))
+        .route("/v1/models", get(list_models))
+        .route("/v1/embeddings", metered(&state, post(embeddings)))
+        .route("/v1/rag/ingest", metered(&state, post(ingest)))
+        .route("/v1/rag/query", metered(&state, post(query)))
+        .route("/v1/rag/evaluate", metered(&state, post(evaluate)))
+        .route("/v1/audio/transcriptions", transcriptions)
+        .route("/v1/audio/speech", metered(&state, post(speech::speech)))
+        .route("/v1/search", post(search::web_search))
+        .route("/v1/interpreter/run", post(interpreter::run_code))
+        .route("/v1/interpreter/sessions/{id}", delete(interpreter::delete_session))
+        .route("/v1/interpreter/sessions/{id}/files", get(interpreter::list_files))
+        .route("/v1/interpreter/sessions/{id}/files/{*path}", get(interpreter::file_content))
+        .route("/v1/sessions", get(sessions::list_sessions).post(sessions::create_session))
+        .route("/v1/sessions/search", get(sessions::search_sessions))
+        .route("/v1/sessions/import", post(sessions::import_session))
+        .route(
+            "/v1/sessions/import/external",
+            post(sessions::import_external).layer(DefaultBodyLimit::max(state.config().sessions.max_import_bytes)),
+        )
+        .route(
+            "/v1/sessions/{id}",
+            get(sessions::get_session)
+                .patch(sessions::update_session)
+                .delete(sessions::delete_session),
+        )
+        .route(
+            "/v1/sessions/{id}/messages",
+            get(sessions::list_messages).post(sessions::append_messages),
+        )
+        .route("/v1/sessions/{id}/branches", get(sessions::list_branches))
+        .route("/v1/sessions/{id}/checkout", post(sessions::checkout))
+        .route("/v1/sessions/{id}/merge", post(sessions::merge))
+        .route("/v1/sessions/{id}/prune", post(sessions::prune))
+        .route("/v1/sessions/{id}/export", get(sessions::export_session))
+        .route("/v1/prompts", get(prompts::list_prompts).post(prompts::create_prompt))
+        .route("/v1/prompts/{name}", get(prompts::get_prompt).delete(prompts::delete_prompt))
+        .route("/v1/prompts/{name}/versions", get(prompts::list_versions))
+        .route("/v1/prompts/{name}/render", post(prompts::render_prompt))
+        .route("/v1/files", get(files::list_files).post(files::upload_file))
+        .route("/v1/files/uploads", post(files::create_upload))
+        .route(
+            "/v1/files/uploads/{id}",
+            get(files::get_upload).patch(files::append_upload).delete(files::cancel_upload),
+        )
+        .route("/v1/files/uploads/{id}/complete", post(files::complete_upload))
+        .route("/v1/files/{id}", get(files::get_file).delete(files::delete_file))
+        .route("/v1/files/{id}/content", get(files::file_content))
+        .route("/v1/files/{id}/ingest", metered(&state, post(files::ingest_file)))
+        .route(
+            "/v1/memories",
+            get(memory::list_memories).post(memory::create_memory).delete(memory::clear_memories),
+        )
+        .route("/v1/memories/search", get(memory::search_memories))
+        .route(
+            "/v1/memories/{id}",
+            get(memory::get_memory).patch(memory::update_memory).delete(memory::delete_memory),
+        )
+        .route("/v1/workspace", get(current_workspace))
+        .route("/v1/mcp/sse", get(mcp::sse))
+        .route("/v1/mcp/messages", post(mcp::message))
+        .route(
+            "/v1/agents/runs",
+            get(agents::list_runs).merge(metered(&state, post(agents::create_run))),
+        )
+        .route("/v1/agents/runs/{id}", get(agents::get_run).delete(agents::delete_run))
+        .route("/v1/agents/runs/{id}/events", get(agents::run_events))
+        .route("/v1/agents/runs/{id}/cancel", post(agents::cancel_run))
+        .route("/v1/batch", batch)
+        .route("/v1/batch/{id}", get(batch::get_batch).delete(batch::delete_batch))
+        .route("/v1/batch/{id}/results", get(batch::batch_results))
+        .route("/v1/batch/{id}/cancel", post(batch::cancel_batch))
+        .route("/v1/system/devices", get(llama::list_system_devices).post(llama::select_system_device))
+        .route("/v1/system/models", get(model_store::list_model_files))
+        // 在工作区之内，同一个幂等键只在同一工作区内去重
+        .route_layer(middleware::from_fn_with_state(Arc::clone(&state), idempotency::deduplicate))
+        // 在限流之内，被限流拒绝的请求不占用工作区配额
+        .route_layer(middleware::from_fn_with_state(Arc::clone(&state), workspace::scope))
+        .route_layer(middleware::from_fn_with_state(Arc::clone(&state), ratelimit::limit))
+        // 先认证再限流，按用户限流时使用认证得到的身份
+        .route_layer(middleware::from_fn_with_state(Arc::clone(&state), auth::require))
+        // 在认证之外，认证失败也记为安全事件
+        .route_layer(middleware::from_fn_with_state(Arc::clone(&state), trail::watch))
+        // 在限流之外，被拒绝的请求也会记录
+        .route_layer(middleware::from_fn_with_state(Arc::clone(&state), audit::record))
+        .with_state(state);
+    let router = match admin {
+        Some(admin) => router.merge(admin),
+        None => router,
+    };
+    let router = match exporter {
+        Some(exporter) => router.merge(exporter),
+        None => router,
+    };
+    let router = router.merge(probes);
+    // 在最外层，认证、限流等中间件拒绝的请求也计入
+    let router = match tracked {
+        Some(state) => router.layer(middleware::from_fn_with_state(state, metrics::track)),
+        None => router,
+    };
+    match traced {
+        Some(state) => router.layer(middleware::from_fn_with_state(state, telemetry::trace)),
+        None => router,
+    }
+}
+
+/// 对话补全；`stream: true`时以SSE逐块转发上游输出，启用`streams`时可以断线续读
+async fn chat_completions(
+    State(state): State<Arc<AppState>>,
+    limit_key: Option<Extension<RateLimitKey>>,
+    consumer: Consumer,
+    headers: HeaderMap,
+    Json(mut request): Json<ChatCompletionRequest>,
+) -> ApiResult<Response> {
+    let limit_key = limit_key.map(|Extension(key)| key);
+    if request.model.is_empty() {
+        request.model = state.config().llm.model_name.clone();
+    }
+    prompts::apply(&state, &consumer.workspace, &mut request)?;
+    let memory = telemetry::span("memory.recall", memory::recall(&state, &consumer, &mut request)).await?;
+    telemetry::span("rag.augment", rag::augment(&state, &consumer.workspace, &mut request)).await?;
+    safety::screen_request(&state, &consumer.workspace, &mut request);
+    let scope = ToolScope::take(&consumer.workspace, &mut request)?;
+    telemetry::span("vision.prepare", vision::prepare(&state, &consumer.workspace, &mut request)).await?;
+    // 在压缩上下文之前查找，命中时不会为生成摘要请求上游
+    let mut cached = cache::lookup(&state, &consumer.workspace, &request, &headers).await;
+    if let Some((response, kind)) = cached.as_mut().and_then(Lookup::take_hit) {
+        return Ok(cache::hit_response(response, kind));
+    }
+    let placeholders = telemetry::span("pii.tokenize", pii::tokenize(&state, &mut request)).await?;
+    let prompt_tokens = telemetry::span("context.prepare", state.prepare_chat(&mut request)).await?;
+
+    if request.stream == Some(true) {
+        let generate = structured::chat_completion_stream(&state, &request, &scope);
+        let (data, answered) = router::track(telemetry::span("chat.generate", generate)).await;
+        let data = data?;
+        if let Some(memory) = memory {
+            memory.extract(&state);
+        }
+        let tokens = state.stream_tokens(&request, prompt_tokens);
+        ratelimit::charge(&state, limit_key.as_ref(), tokens);
+        workspace::charge(&state, &consumer.workspace, tokens);
+        let data = placeholders.restore_stream(data);
+        let data = safety::screen_stream(&state, &consumer.workspace, &request.model, data);
+        let data = metering::meter_stream(&state, &consumer, &request.model, prompt_tokens, data);
+        let data = telemetry::stream("chat.stream", data);
+        let mut response = match &state.streams {
+            Some(streams) => streams.start(&state, &consumer.workspace, data).await?,
+            None => sse::sse_response(data).into_response(),
+        };
+        if let Some(answered) = answered {
+            answered.apply(response.headers_mut());
+        }
+        return Ok(response);
+    }
+
+    let generate = structured::chat_completion(&state, &request, &scope);
+    let (response, answered) = router::track(telemetry::span("chat.generate", generate)).await;
+    let mut response: ChatCompletionResponse = response?;
+    placeholders.restore_response(&mut response);
+    safety::screen_response(&state, &consumer.workspace, &mut response);
+    if response.usage.is_none() {
+        // 部分上游不返回用量，按本地分词器补上
+        response.usage = Some(state.usage(&request.model, prompt_tokens, &response));
+    }
+    if let Some(memory) = memory {
+        memory.extract(&state);
+    }
+    let usage = response.usage.clone().unwrap_or_default();
+    ratelimit::charge(&state, limit_key.as_ref(), usage.total_tokens);
+    workspace::charge(&state, &consumer.workspace, usage.total_tokens);
+    metering::record(&state, &consumer, &request.model, usage.prompt_tokens, usage.completion_tokens);
+    let miss = cached.is_some();
+    if let Some(lookup) = cached {
+        cache::store(&state, lookup, &response);
+    }
+    let mut response = Json(response).into_response();
+    if miss {
+        response.headers_mut().insert(cache::CACHE_HEADER, HeaderValue::from_static("miss"));
+    }
+    if let Some(answered) = answered {
+        answered.apply(response.headers_mut());
+    }
+    Ok(response)
+}
+
+/// 列出本服务提供的模型
+async fn list_models(State(state): State<Arc<AppState>>) -> Json<ModelList> {
+    Json(state.model_list())
+}
+
+async fn embeddings(
+    State(state): State<Arc<AppState>>,
+    consumer: Consumer,
+    Json(mut request): Json<EmbeddingRequest>,
+) -> ApiResult<Json<EmbeddingResponse>> {
+    if request.model.is_empty() {
+        request.model = state
+            .config()
+            .llm
+            .embedding_model
+            .clone()
+            .ok_or_else(|| ApiError::invalid_request("未指定 model，且配置中没有 llm.embedding_model"))?;
+    }
+
+    let response = state.models().embeddings(&request).await?;
+    let usage = response.usage.clone().unwrap_or_default();
+    workspace::charge(&state, &consumer.workspace, usage.total_tokens);
+    metering::record(&state, &consumer, &request.model, usage.prompt_tokens, 0);
+    Ok(Json(response))
+}
+
+/// 请求所属的工作区及其配额和用量；未启用工作区时只返回名称
+async fn current_workspace(State(state): State<Arc<AppState>>, workspace: Workspace) -> Json<Value> {
+    match &state.workspaces {
+        Some(workspaces) => Json(json!(workspaces.info(workspace.name()))),
+        None => Json(json!({ "name": workspace.name() })),
+    }
+}
+
+/// 导入文档到本地向量索引
+async fn ingest(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Json(request): Json<IngestRequest>,
+) -> ApiResult<Json<IngestResponse>> {
+    if request.documents.is_empty() {
+        return Err(ApiError::invalid_request("documents 不能为空"));
+    }
+    let index = request.index.unwrap_or_else(|| state.config().rag.default_index.clone());
+
+    let mut documents = Vec::with_capacity(request.documents.len());
+    for document in request.documents {
+        let kind = DocumentKind::from_name(&document.name)
+            .ok_or_else(|| ApiError::invalid_request(format!("不支持的文件类型: {}", document.name)))?;
+        let data = match (document.text, document.data) {
+            (Some(text), None) => text.into_bytes(),
+            (None, Some(data)) => STANDARD
+                .decode(data.trim())
+                .map_err(|_| ApiError::invalid_request(format!("{} 的 data 不是有效的 Base64", document.name)))?,
+            _ => return Err(ApiError::invalid_request(format!("{} 需要且只能提供 text 或 data 之一", document.name))),
+        };
+        documents.push(Document {
+            id: document.id.unwrap_or_else(|| document.name.clone()),
+            name: document.name,
+            kind,
+            data,
+            metadata: document.metadata,
+            chunking: document.chunking.or_else(|| request.chunking.clone()),
+        });
+    }
+
+    let results = rag::ingest_documents(&state, &workspace, &index, &documents).await?;
+    Ok(Json(IngestResponse {
+        object: "rag.ingest".to_string(),
+        index,
+        chunks: results.iter().map(|result| result.chunks).sum(),
+        documents: results,
+    }))
+}
+
+/// 检索向量索引，配置了重排模型时返回重排后的结果
+async fn query(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Json(request): Json<QueryRequest>,
+) -> ApiResult<Json<QueryResponse>> {
+    let options = RetrievalOptions {
+        index: request.index,
+        top_k: request.top_k,
+        filter: request.filter,
+        mode: request.mode,
+        rerank: request.rerank,
+    };
+    let retrieved = rag::retrieve(&state, &workspace, &request.query, &options).await?;
+    Ok(Json(QueryResponse {
+        object: "rag.query".to_string(),
+        index: retrieved.index,
+        mode: retrieved.mode,
+        reranker: retrieved.reranker,
+        data: retrieved.chunks,
+    }))
+}
+
+/// 用带标注的查询比较重排前后的检索质量
+async fn evaluate(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Json(request): Json<EvaluateRequest>,
+) -> ApiResult<Json<EvaluateResponse>> {
+    let index = request.index.unwrap_or_else(|| state.config().rag.default_index.clone());
+    let response = rag::evaluate(&state, &workspace, &index, &request.queries, request.top_k, request.mode).await?;
+    Ok(Json(response))
+}
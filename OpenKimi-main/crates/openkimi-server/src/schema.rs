@@ -0,0 +1,340 @@
+//! JSON Schema校验
+//!
+//! 支持结构化输出常用的子集：`type`、`enum`、`const`、`properties`、`required`、`additionalProperties`、
+//! `items`、`minItems`/`maxItems`/`uniqueItems`、`minLength`/`maxLength`/`pattern`、数值范围和`multipleOf`、
+//! `anyOf`/`oneOf`/`allOf`/`not`，以及指向同一文档内的`$ref`（如`#/$defs/item`）。
+//! `format`、`title`、`description`等注解关键字被忽略。
+
+use std::collections::HashMap;
+
+use regex::Regex;
+use serde_json::{Map, Value};
+
+const TYPES: [&str; 7] = ["null", "boolean", "object", "array", "number", "integer", "string"];
+
+/// `$ref`展开的最大深度，避免循环引用无限递归
+const MAX_DEPTH: usize = 64;
+
+/// 最多报告的错误数
+const MAX_ERRORS: usize = 20;
+
+/// 编译后的JSON Schema
+#[derive(Debug, Clone)]
+pub struct Schema {
+    root: Value,
+    patterns: HashMap<String, Regex>,
+}
+
+fn is_schema(value: &Value) -> bool {
+    value.is_object() || value.is_boolean()
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn matches_type(value: &Value, expected: &str) -> bool {
+    match (expected, value) {
+        ("number", Value::Number(_)) => true,
+        // 1.0这样的小数也算整数
+        ("integer", Value::Number(n)) => n.as_f64().is_some_and(|n| n.fract() == 0.0),
+        _ => type_name(value) == expected,
+    }
+}
+
+impl Schema {
+    /// 检查Schema本身是否有效，并预先编译其中的正则表达式
+    pub fn compile(root: &Value) -> Result<Schema, String> {
+        let mut schema = Schema {
+            root: root.clone(),
+            patterns: HashMap::new(),
+        };
+        let mut patterns = HashMap::new();
+        schema.check(root, "#", &mut patterns)?;
+        schema.patterns = patterns;
+        Ok(schema)
+    }
+
+    fn check(&self, schema: &Value, path: &str, patterns: &mut HashMap<String, Regex>) -> Result<(), String> {
+        let object = match schema {
+            Value::Bool(_) => return Ok(()),
+            Value::Object(object) => object,
+            _ => return Err(format!("{} 必须是对象或布尔值", path)),
+        };
+        let child = |key: &str| format!("{}/{}", path, key);
+
+        let known = |kind: &Value| kind.as_str().is_some_and(|kind| TYPES.contains(&kind));
+        match object.get("type") {
+            None => {}
+            Some(kind @ Value::String(_)) if known(kind) => {}
+            Some(Value::Array(kinds)) if kinds.iter().all(known) => {}
+            Some(kind) => return Err(format!("{}: 不支持的 type {}", child("type"), kind)),
+        }
+        if let Some(reference) = object.get("$ref") {
+            let target = reference.as_str().and_then(|reference| self.resolve(reference));
+            if target.is_none() {
+                return Err(format!("{}: 无法解析的引用 {}", child("$ref"), reference));
+            }
+        }
+        if object.get("enum").is_some_and(|values| !values.is_array()) {
+            return Err(format!("{} 必须是数组", child("enum")));
+        }
+        if object.get("required").is_some_and(|required| {
+            !required.as_array().is_some_and(|fields| fields.iter().all(Value::is_string))
+        }) {
+            return Err(format!("{} 必须是字符串数组", child("required")));
+        }
+        for key in [
+            "minimum",
+            "maximum",
+            "exclusiveMinimum",
+            "exclusiveMaximum",
+            "multipleOf",
+            "minLength",
+            "maxLength",
+            "minItems",
+            "maxItems",
+        ] {
+            if object.get(key).is_some_and(|value| !value.is_number()) {
+                return Err(format!("{} 必须是数值", child(key)));
+            }
+        }
+        if let Some(pattern) = object.get("pattern") {
+            let pattern = pattern.as_str().ok_or_else(|| format!("{} 必须是字符串", child("pattern")))?;
+            let regex = Regex::new(pattern).map_err(|e| format!("{}: 无效的正则表达式: {}", child("pattern"), e))?;
+            patterns.insert(pattern.to_string(), regex);
+        }
+
+        for key in ["items", "additionalProperties", "not"] {
+            if let Some(sub) = object.get(key) {
+                self.check(sub, &child(key), patterns)?;
+            }
+        }
+        for key in ["properties", "$defs", "definitions"] {
+            match object.get(key) {
+                None => {}
+                Some(Value::Object(subs)) => {
+                    for (name, sub) in subs {
+                        self.check(sub, &format!("{}/{}", child(key), name), patterns)?;
+                    }
+                }
+                Some(_) => return Err(format!("{} 必须是对象", child(key))),
+            }
+        }
+        for key in ["anyOf", "oneOf", "allOf"] {
+            match object.get(key) {
+                None => {}
+                Some(Value::Array(subs)) if !subs.is_empty() => {
+                    for (i, sub) in subs.iter().enumerate() {
+                        self.check(sub, &format!("{}/{}", child(key), i), patterns)?;
+                    }
+                }
+                Some(_) => return Err(format!("{} 必须是非空数组", child(key))),
+            }
+        }
+        Ok(())
+    }
+
+    /// 解析`#`或`#/...`形式的文档内引用
+    fn resolve(&self, reference: &str) -> Option<&Value> {
+        let pointer = reference.strip_prefix('#')?;
+        self.root.pointer(pointer).filter(|target| is_schema(target))
+    }
+
+    /// 校验`value`，返回所有错误，每条以出错位置开头，如`$.items[0].name`
+    pub fn validate(&self, value: &Value) -> Vec<String> {
+        let mut errors = Vec::new();
+        self.validate_at(&self.root, value, "$", 0, &mut errors);
+        errors.truncate(MAX_ERRORS);
+        errors
+    }
+
+    fn validate_at(&self, schema: &Value, value: &Value, path: &str, depth: usize, errors: &mut Vec<String>) {
+        let schema = match schema {
+            Value::Bool(true) => return,
+            Value::Bool(false) => {
+                errors.push(format!("{}: 不允许出现", path));
+                return;
+            }
+            Value::Object(schema) => schema,
+            _ => return,
+        };
+        if errors.len() >= MAX_ERRORS {
+            return;
+        }
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            if depth >= MAX_DEPTH {
+                errors.push(format!("{}: $ref 嵌套过深", path));
+                return;
+            }
+            if let Some(target) = self.resolve(reference) {
+                self.validate_at(target, value, path, depth + 1, errors);
+            }
+        }
+
+        match schema.get("type") {
+            Some(Value::String(kind)) if !matches_type(value, kind) => {
+                errors.push(format!("{}: 应为 {}，实际为 {}", path, kind, type_name(value)));
+                return;
+            }
+            Some(Value::Array(kinds)) if !kinds.iter().filter_map(Value::as_str).any(|k| matches_type(value, k)) => {
+                let kinds: Vec<_> = kinds.iter().filter_map(Value::as_str).collect();
+                errors.push(format!("{}: 应为 {}，实际为 {}", path, kinds.join(" 或 "), type_name(value)));
+                return;
+            }
+            _ => {}
+        }
+        if let Some(expected) = schema.get("const") {
+            if value != expected {
+                errors.push(format!("{}: 应为 {}", path, expected));
+            }
+        }
+        if let Some(Value::Array(values)) = schema.get("enum") {
+            if !values.contains(value) {
+                let values: Vec<_> = values.iter().map(Value::to_string).collect();
+                errors.push(format!("{}: 应为 {} 之一", path, values.join("、")));
+            }
+        }
+
+        match value {
+            Value::Object(object) => self.validate_object(schema, object, path, depth, errors),
+            Value::Array(items) => self.validate_array(schema, items, path, depth, errors),
+            Value::String(text) => self.validate_string(schema, text, path, errors),
+            Value::Number(number) => {
+                if let Some(number) = number.as_f64() {
+                    validate_number(schema, number, path, errors);
+                }
+            }
+            _ => {}
+        }
+
+        if let Some(Value::Array(subs)) = schema.get("allOf") {
+            for sub in subs {
+                self.validate_at(sub, value, path, depth + 1, errors);
+            }
+        }
+        if let Some(Value::Array(subs)) = schema.get("anyOf") {
+            if !subs.iter().any(|sub| self.is_valid(sub, value, path, depth)) {
+                errors.push(format!("{}: 不符合 anyOf 中的任何一项", path));
+            }
+        }
+        if let Some(Value::Array(subs)) = schema.get("oneOf") {
+            let matched = subs.iter().filter(|sub| self.is_valid(sub, value, path, depth)).count();
+            if matched != 1 {
+                errors.push(format!("{}: 应恰好符合 oneOf 中的一项，实际符合 {} 项", path, matched));
+            }
+        }
+        if let Some(sub) = schema.get("not") {
+            if self.is_valid(sub, value, path, depth) {
+                errors.push(format!("{}: 不应符合 not 中的 Schema", path));
+            }
+        }
+    }
+
+    fn is_valid(&self, schema: &Value, value: &Value, path: &str, depth: usize) -> bool {
+        let mut errors = Vec::new();
+        self.validate_at(schema, value, path, depth + 1, &mut errors);
+        errors.is_empty()
+    }
+
+    fn validate_object(
+        &self,
+        schema: &Map<String, Value>,
+        object: &Map<String, Value>,
+        path: &str,
+        depth: usize,
+        errors: &mut Vec<String>,
+    ) {
+        let properties = schema.get("properties").and_then(Value::as_object);
+        for field in schema.get("required").and_then(Value::as_array).into_iter().flatten() {
+            if let Some(field) = field.as_str().filter(|field| !object.contains_key(*field)) {
+                errors.push(format!("{}: 缺少必需的字段 {}", path, field));
+            }
+        }
+        for (key, item) in object {
+            let item_path = format!("{}.{}", path, key);
+            match properties.and_then(|properties| properties.get(key)) {
+                Some(sub) => self.validate_at(sub, item, &item_path, depth + 1, errors),
+                None => match schema.get("additionalProperties") {
+                    Some(Value::Bool(false)) => errors.push(format!("{}: 不允许的字段", item_path)),
+                    Some(sub) => self.validate_at(sub, item, &item_path, depth + 1, errors),
+                    None => {}
+                },
+            }
+        }
+    }
+
+    fn validate_array(
+        &self,
+        schema: &Map<String, Value>,
+        items: &[Value],
+        path: &str,
+        depth: usize,
+        errors: &mut Vec<String>,
+    ) {
+        let count = |key: &str| schema.get(key).and_then(Value::as_f64);
+        if count("minItems").is_some_and(|min| (items.len() as f64) < min) {
+            errors.push(format!("{}: 至少需要 {} 项，实际为 {} 项", path, schema["minItems"], items.len()));
+        }
+        if count("maxItems").is_some_and(|max| items.len() as f64 > max) {
+            errors.push(format!("{}: 最多 {} 项，实际为 {} 项", path, schema["maxItems"], items.len()));
+        }
+        if schema.get("uniqueItems") == Some(&Value::Bool(true)) {
+            let duplicate = items.iter().enumerate().any(|(i, item)| items[..i].contains(item));
+            if duplicate {
+                errors.push(format!("{}: 各项不能重复", path));
+            }
+        }
+        if let Some(sub) = schema.get("items") {
+            for (i, item) in items.iter().enumerate() {
+                self.validate_at(sub, item, &format!("{}[{}]", path, i), depth + 1, errors);
+            }
+        }
+    }
+
+    fn validate_string(&self, schema: &Map<String, Value>, text: &str, path: &str, errors: &mut Vec<String>) {
+        let length = text.chars().count();
+        if schema.get("minLength").and_then(Value::as_f64).is_some_and(|min| (length as f64) < min) {
+            errors.push(format!("{}: 长度至少为 {}，实际为 {}", path, schema["minLength"], length));
+        }
+        if schema.get("maxLength").and_then(Value::as_f64).is_some_and(|max| length as f64 > max) {
+            errors.push(format!("{}: 长度最多为 {}，实际为 {}", path, schema["maxLength"], length));
+        }
+        if let Some(pattern) = schema.get("pattern").and_then(Value::as_str) {
+            if self.patterns.get(pattern).is_some_and(|regex| !regex.is_match(text)) {
+                errors.push(format!("{}: 不匹配 {}", path, pattern));
+            }
+        }
+    }
+}
+
+fn validate_number(schema: &Map<String, Value>, number: f64, path: &str, errors: &mut Vec<String>) {
+    let bound = |key: &str| schema.get(key).and_then(Value::as_f64);
+    if bound("minimum").is_some_and(|min| number < min) {
+        errors.push(format!("{}: 应不小于 {}", path, schema["minimum"]));
+    }
+    if bound("maximum").is_some_and(|max| number > max) {
+        errors.push(format!("{}: 应不大于 {}", path, schema["maximum"]));
+    }
+    if bound("exclusiveMinimum").is_some_and(|min| number <= min) {
+        errors.push(format!("{}: 应大于 {}", path, schema["exclusiveMinimum"]));
+    }
+    if bound("exclusiveMaximum").is_some_and(|max| number >= max) {
+        errors.push(format!("{}: 应小于 {}", path, schema["exclusiveMaximum"]));
+    }
+    if let Some(step) = bound("multipleOf").filter(|step| *step > 0.0) {
+        let quotient = number / step;
+        if (quotient - quotient.round()).abs() > 1e-9 {
+            errors.push(format!("{}: 应是 {} 的倍数", path, schema["multipleOf"]));
+        }
+    }
+}
@@ -0,0 +1,754 @@
+//! 网页搜索
+//!
+//! 通过SearxNG、Bing或Brave搜索网页，按域名白名单和黑名单过滤结果，抓取排名靠前的网页并提取正文，
+//! 每条结果带编号、标题、地址、站点和发布时间等引用信息。`POST /v1/search`直接调用；
+//! 启用`tools`时注册为内置的`web_search`工具，模型可以在对话中自行搜索并按编号引用来源。
+
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::extract::State;
+use axum::http::header::{ACCEPT, CONTENT_TYPE};
+use axum::Json;
+use futures_util::future::{join_all, BoxFuture};
+use futures_util::{FutureExt, StreamExt};
+use openkimi_rag::{extract, normalize, DocumentKind};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::config::{SearchConfig, SearchProvider};
+use crate::error::{ApiError, ApiResult};
+use crate::AppState;
+
+/// 内置工具的函数名
+pub const TOOL_NAME: &str = "web_search";
+
+/// 单次搜索最多返回的结果数
+const MAX_RESULTS: usize = 20;
+
+/// 一次搜索
+#[derive(Debug, Clone)]
+pub struct SearchQuery {
+    pub query: String,
+    pub count: usize,
+    pub language: Option<String>,
+}
+
+/// 搜索服务返回的一条结果
+#[derive(Debug, Clone)]
+pub struct Hit {
+    pub title: String,
+    pub url: String,
+    pub snippet: String,
+    pub published: Option<String>,
+}
+
+/// 搜索服务
+pub trait SearchBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn search<'a>(&'a self, query: &'a SearchQuery) -> BoxFuture<'a, Result<Vec<Hit>, String>>;
+}
+
+/// 发送GET请求并解析JSON响应
+async fn get_json(request: reqwest::RequestBuilder) -> Result<Value, String> {
+    let response = request.send().await.map_err(|e| format!("请求失败: {}", e))?;
+    let status = response.status();
+    let body = response.text().await.map_err(|e| format!("读取响应失败: {}", e))?;
+    if !status.is_success() {
+        return Err(format!("返回 {}: {}", status, body.chars().take(300).collect::<String>()));
+    }
+    serde_json::from_str(&body).map_err(|e| format!("无法解析响应: {}", e))
+}
+
+/// 去掉摘要中用于高亮的标签
+fn plain(snippet: &str) -> String {
+    extract(DocumentKind::Html, snippet.as_bytes())
+        .map(|text| normalize(&text))
+        .unwrap_or_default()
+        .trim()
+        .to_string()
+}
+
+fn text(value: &Value) -> String {
+    value.as_str().unwrap_or_default().to_string()
+}
+
+/// 自建的SearxNG实例，需要在`settings.yml`中开启`json`格式
+struct Searxng {
+    http: reqwest::Client,
+    url: String,
+}
+
+impl SearchBackend for Searxng {
+    fn name(&self) -> &'static str {
+        "searxng"
+    }
+
+    fn search<'a>(&'a self, query: &'a SearchQuery) -> BoxFuture<'a, Result<Vec<Hit>, String>> {
+        async move {
+            let mut params = vec![("q", query.query.as_str()), ("format", "json"), ("pageno", "1")];
+            if let Some(language) = &query.language {
+                params.push(("language", language));
+            }
+            let url = format!("{}/search", self.url.trim_end_matches('/'));
+            let body = get_json(self.http.get(url).query(&params)).await?;
+            Ok(body["results"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|result| Hit {
+                    title: text(&result["title"]),
+                    url: text(&result["url"]),
+                    snippet: plain(result["content"].as_str().unwrap_or_default()),
+                    published: result["publishedDate"].as_str().map(str::to_string),
+                })
+                .collect())
+        }
+        .boxed()
+    }
+}
+
+/// Bing Web Search API
+struct Bing {
+    http: reqwest::Client,
+    url: String,
+    api_key: String,
+}
+
+impl SearchBackend for Bing {
+    fn name(&self) -> &'static str {
+        "bing"
+    }
+
+    fn search<'a>(&'a self, query: &'a SearchQuery) -> BoxFuture<'a, Result<Vec<Hit>, String>> {
+        async move {
+            let count = query.count.to_string();
+            let mut params = vec![
+                ("q", query.query.as_str()),
+                ("count", count.as_str()),
+                ("responseFilter", "Webpages"),
+                ("textDecorations", "false"),
+            ];
+            if let Some(language) = &query.language {
+                params.push(("mkt", language));
+            }
+            let request = self
+                .http
+                .get(&self.url)
+                .query(&params)
+                .header("Ocp-Apim-Subscription-Key", &self.api_key);
+            let body = get_json(request).await?;
+            Ok(body["webPages"]["value"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|result| Hit {
+                    title: text(&result["name"]),
+                    url: text(&result["url"]),
+                    snippet: text(&result["snippet"]),
+                    published: result["datePublished"].as_str().map(str::to_string),
+                })
+                .collect())
+        }
+        .boxed()
+    }
+}
+
+/// Brave Search API
+struct Brave {
+    http: reqwest::Client,
+    url: String,
+    api_key: String,
+}
+
+impl SearchBackend for Brave {
+    fn name(&self) -> &'static str {
+        "brave"
+    }
+
+    fn search<'a>(&'a self, query: &'a SearchQuery) -> BoxFuture<'a, Result<Vec<Hit>, String>> {
+        async move {
+            let count = query.count.to_string();
+            let mut params = vec![("q", query.query.as_str()), ("count", count.as_str())];
+            if let Some(language) = &query.language {
+                params.push(("search_lang", language));
+            }
+            let request = self
+                .http
+                .get(&self.url)
+                .query(&params)
+                .header(ACCEPT, "application/json")
+                .header("X-Subscription-Token", &self.api_key);
+            let body = get_json(request).await?;
+            Ok(body["web"]["results"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|result| Hit {
+                    title: plain(result["title"].as_str().unwrap_or_default()),
+                    url: text(&result["url"]),
+                    snippet: plain(result["description"].as_str().unwrap_or_default()),
+                    published: result["page_age"].as_str().map(str::to_string),
+                })
+                .collect())
+        }
+        .boxed()
+    }
+}
+
+/// 地址的主机名，去掉`www.`
+fn host(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let host = url.host_str()?.to_ascii_lowercase();
+    Some(host.strip_prefix("www.").map(str::to_string).unwrap_or(host))
+}
+
+/// `host`是否为`domain`或其子域名
+fn within(host: &str, domain: &str) -> bool {
+    host == domain || host.strip_suffix(domain).is_some_and(|rest| rest.ends_with('.'))
+}
+
+fn normalize_domain(domain: &str) -> String {
+    let domain = domain.trim().trim_end_matches('/').to_ascii_lowercase();
+    let domain = domain.split("://").last().unwrap_or_default();
+    domain.strip_prefix("www.").unwrap_or(domain).to_string()
+}
+
+/// 是否为公网地址，抓取网页时不访问本机和内网，避免被搜索结果引到内部服务
+fn public(url: &reqwest::Url) -> bool {
+    if !matches!(url.scheme(), "http" | "https") {
+        return false;
+    }
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']').to_ascii_lowercase();
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            !(ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified() || ip.is_broadcast())
+        }
+        Ok(IpAddr::V6(ip)) => {
+            let first = ip.segments()[0];
+            !(ip.is_loopback() || ip.is_unspecified() || first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80)
+        }
+        Err(_) => !(host == "localhost" || [".localhost", ".local", ".internal"].iter().any(|s| host.ends_with(s))),
+    }
+}
+
+/// 网页中提取出的内容
+#[derive(Debug, Default)]
+struct Page {
+    title: Option<String>,
+    site_name: Option<String>,
+    author: Option<String>,
+    published: Option<String>,
+    description: Option<String>,
+    text: String,
+}
+
+static META: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?is)<meta\s[^>]*>").unwrap());
+static ATTRIBUTE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"([\w:-]+)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#).unwrap());
+static TITLE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap());
+static TIME: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?is)<time\s[^>]*datetime\s*=\s*["']([^"']+)["']"#).unwrap());
+
+/// 不是正文的元素，连同内容去掉
+const BOILERPLATE_TAGS: &[&str] = &[
+    "nav", "header", "footer", "aside", "form", "script", "style", "noscript", "svg", "iframe", "button", "select",
+    "template",
+];
+
+/// 在已转为小写的HTML中找`<tag`开头的标签，后面须是空白、`>`或`/`，避免`<a`匹配`<article`
+fn find_tag(lower: &str, open: &str, from: usize) -> Option<usize> {
+    let mut position = from;
+    while let Some(found) = lower.get(position..)?.find(open) {
+        let start = position + found;
+        let next = lower.as_bytes().get(start + open.len()).copied();
+        if matches!(next, Some(b' ' | b'\t' | b'\r' | b'\n' | b'>' | b'/')) {
+            return Some(start);
+        }
+        position = start + open.len();
+    }
+    None
+}
+
+/// 元素的位置：开始标签的起点、内容的起止和结束标签之后
+struct Element {
+    start: usize,
+    inner: (usize, usize),
+    end: usize,
+}
+
+/// 找出所有最外层的`tag`元素，嵌套的同名元素计入外层
+fn elements(lower: &str, tag: &str) -> Vec<Element> {
+    let (open, close) = (format!("<{}", tag), format!("</{}", tag));
+    let mut result = Vec::new();
+    let mut position = 0;
+    while let Some(start) = find_tag(lower, &open, position) {
+        let Some(inner_start) = lower[start..].find('>').map(|i| start + i + 1) else {
+            break;
+        };
+        let mut depth = 1;
+        let mut cursor = inner_start;
+        let (inner_end, end) = loop {
+            let next_open = find_tag(lower, &open, cursor);
+            let next_close = lower[cursor..].find(&close).map(|i| cursor + i);
+            match (next_open, next_close) {
+                (Some(o), Some(c)) if o < c => {
+                    depth += 1;
+                    cursor = o + open.len();
+                }
+                (_, Some(c)) => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break (c, lower[c..].find('>').map_or(lower.len(), |i| c + i + 1));
+                    }
+                    cursor = c + close.len();
+                }
+                (_, None) => break (lower.len(), lower.len()),
+            }
+        };
+        result.push(Element {
+            start,
+            inner: (inner_start, inner_end),
+            end,
+        });
+        position = end;
+    }
+    result
+}
+
+/// 猜测正文所在的区域：最长的`<article>`，其次`<main>`和`<body>`
+fn main_region(html: &str) -> &str {
+    let lower = html.to_ascii_lowercase();
+    let article = elements(&lower, "article").into_iter().max_by_key(|element| element.inner.1 - element.inner.0);
+    let element = article
+        .or_else(|| elements(&lower, "main").into_iter().next())
+        .or_else(|| elements(&lower, "body").into_iter().next());
+    match element {
+        Some(element) => &html[element.inner.0..element.inner.1],
+        None => html,
+    }
+}
+
+/// 去掉导航、页眉页脚、侧栏和表单等元素
+fn strip_boilerplate(html: &str) -> String {
+    let mut html = html.to_string();
+    for tag in BOILERPLATE_TAGS {
+        let lower = html.to_ascii_lowercase();
+        let found = elements(&lower, tag);
+        if found.is_empty() {
+            continue;
+        }
+        let mut kept = String::with_capacity(html.len());
+        let mut position = 0;
+        for element in found {
+            kept.push_str(&html[position..element.start]);
+            position = element.end;
+        }
+        kept.push_str(&html[position..]);
+        html = kept;
+    }
+    html
+}
+
+/// 去掉菜单、按钮等零散的短行，全部被去掉时保留原文
+fn drop_fragments(text: &str) -> String {
+    let kept: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| {
+            line.chars().count() >= 25 || line.ends_with(['。', '！', '？', '.', '!', '?', '：', ':', '；', ';'])
+        })
+        .collect();
+    if kept.is_empty() {
+        text.trim().to_string()
+    } else {
+        kept.join("\n")
+    }
+}
+
+/// 提取网页的正文和引用信息
+fn readable(html: &str) -> Page {
+    let mut page = Page::default();
+    for tag in META.find_iter(html) {
+        let mut key = None;
+        let mut content = None;
+        for attribute in ATTRIBUTE.captures_iter(tag.as_str()) {
+            let value = attribute.get(2).or(attribute.get(3)).or(attribute.get(4)).map_or("", |m| m.as_str());
+            match attribute[1].to_ascii_lowercase().as_str() {
+                "property" | "name" | "itemprop" => key = Some(value.to_ascii_lowercase()),
+                "content" => content = Some(plain(value)),
+                _ => {}
+            }
+        }
+        let (Some(key), Some(content)) = (key, content.filter(|content| !content.is_empty())) else {
+            continue;
+        };
+        let field = match key.as_str() {
+            "og:title" => &mut page.title,
+            "og:site_name" | "application-name" => &mut page.site_name,
+            "author" | "article:author" => &mut page.author,
+            "article:published_time" | "datepublished" | "pubdate" | "date" => &mut page.published,
+            "og:description" | "description" => &mut page.description,
+            _ => continue,
+        };
+        if field.is_none() {
+            *field = Some(content);
+        }
+    }
+    if page.title.is_none() {
+        page.title = TITLE.captures(html).map(|title| plain(&title[1])).filter(|title| !title.is_empty());
+    }
+    if page.published.is_none() {
+        page.published = TIME.captures(html).map(|time| time[1].trim().to_string());
+    }
+    let region = strip_boilerplate(main_region(html));
+    let text = extract(DocumentKind::Html, region.as_bytes()).unwrap_or_default();
+    page.text = drop_fragments(&normalize(&text));
+    page
+}
+
+fn truncate_chars(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((index, _)) => format!("{}…", &text[..index]),
+        None => text.to_string(),
+    }
+}
+
+/// `POST /v1/search`请求，也是`web_search`工具的参数
+#[derive(Debug, Clone, Deserialize)]
+pub struct SearchRequest {
+    pub query: String,
+    /// 缺省为`search.max_results`
+    #[serde(default)]
+    pub max_results: Option<usize>,
+    /// 是否抓取网页正文，缺省为抓取
+    #[serde(default)]
+    pub fetch: Option<bool>,
+    /// 只搜索这些域名，须在`search.allowed_domains`之内
+    #[serde(default)]
+    pub domains: Vec<String>,
+    /// 缺省为`search.language`
+    #[serde(default)]
+    pub language: Option<String>,
+}
+
+/// 带引用信息的搜索结果
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchResult {
+    /// 引用编号，从1开始
+    pub index: usize,
+    pub title: String,
+    pub url: String,
+    pub domain: String,
+    pub snippet: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub site_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub published: Option<String>,
+    /// 网页正文，未抓取或抓取失败时为空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fetch_error: Option<String>,
+}
+
+/// `POST /v1/search`响应
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchResponse {
+    pub query: String,
+    pub provider: &'static str,
+    /// 搜索时间（Unix秒）
+    pub retrieved_at: u64,
+    pub results: Vec<SearchResult>,
+}
+
+/// 网页搜索和正文抓取
+pub struct WebSearch {
+    config: SearchConfig,
+    backend: Box<dyn SearchBackend>,
+    /// 抓取网页用，只跟随到公网地址的重定向
+    pages: reqwest::Client,
+}
+
+impl fmt::Debug for WebSearch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebSearch").field("provider", &self.backend.name()).finish()
+    }
+}
+
+impl WebSearch {
+    pub fn new(config: &SearchConfig) -> Result<WebSearch, String> {
+        let timeout = Duration::from_secs(config.timeout_seconds);
+        let user_agent = concat!("Mozilla/5.0 (compatible; openkimi-server/", env!("CARGO_PKG_VERSION"), ")");
+        let http = reqwest::Client::builder()
+            .timeout(timeout)
+            .user_agent(user_agent)
+            .build()
+            .map_err(|e| format!("无法创建 HTTP 客户端: {}", e))?;
+        let pages = reqwest::Client::builder()
+            .timeout(timeout)
+            .user_agent(user_agent)
+            .redirect(reqwest::redirect::Policy::custom(|attempt| {
+                if attempt.previous().len() < 5 && public(attempt.url()) {
+                    attempt.follow()
+                } else {
+                    attempt.stop()
+                }
+            }))
+            .build()
+            .map_err(|e| format!("无法创建 HTTP 客户端: {}", e))?;
+
+        let provider = config.provider;
+        let url = config.url.clone().or_else(|| provider.default_url().map(str::to_string));
+        let Some(url) = url else {
+            return Err(format!("search.provider 为 {} 时需要配置 search.url", provider.name()));
+        };
+        let api_key = || {
+            config
+                .api_key
+                .clone()
+                .ok_or_else(|| format!("search.provider 为 {} 时需要配置 search.api_key", provider.name()))
+        };
+        let backend: Box<dyn SearchBackend> = match provider {
+            SearchProvider::Searxng => Box::new(Searxng { http, url }),
+            SearchProvider::Bing => Box::new(Bing {
+                http,
+                url,
+                api_key: api_key()?,
+            }),
+            SearchProvider::Brave => Box::new(Brave {
+                http,
+                url,
+                api_key: api_key()?,
+            }),
+        };
+        Ok(WebSearch {
+            config: config.clone(),
+            backend,
+            pages,
+        })
+    }
+
+    /// 域名是否允许出现在结果中，`domains`为请求中进一步限定的域名
+    fn allowed(&self, host: &str, domains: &[String]) -> bool {
+        let config = &self.config;
+        !config.blocked_domains.iter().any(|domain| within(host, &normalize_domain(domain)))
+            && (config.allowed_domains.is_empty()
+                || config.allowed_domains.iter().any(|domain| within(host, &normalize_domain(domain))))
+            && (domains.is_empty() || domains.iter().any(|domain| within(host, domain)))
+    }
+
+    /// 抓取网页并提取正文
+    async fn fetch(&self, url: &str, domains: &[String]) -> Result<Page, String> {
+        let parsed = reqwest::Url::parse(url).map_err(|e| format!("地址无效: {}", e))?;
+        if !public(&parsed) {
+            return Err("不抓取本机或内网地址".to_string());
+        }
+        let response = self
+            .pages
+            .get(parsed)
+            .header(ACCEPT, "text/html,application/xhtml+xml,text/plain;q=0.9,application/pdf;q=0.8")
+            .send()
+            .await
+            .map_err(|e| format!("抓取失败: {}", e))?;
+        let status = response.status();
+        if status.is_redirection() {
+            return Err("重定向到了不允许的地址".to_string());
+        }
+        if !status.is_success() {
+            return Err(format!("网页返回 {}", status));
+        }
+        if !host(response.url().as_str()).is_some_and(|host| self.allowed(&host, domains)) {
+            return Err(format!("重定向到了不允许的域名: {}", response.url()));
+        }
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("text/html")
+            .to_ascii_lowercase();
+
+        let mut body = Vec::new();
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| format!("读取网页失败: {}", e))?;
+            let room = self.config.max_page_bytes - body.len();
+            body.extend_from_slice(&chunk[..chunk.len().min(room)]);
+            if body.len() >= self.config.max_page_bytes {
+                break;
+            }
+        }
+
+        if content_type.contains("html") {
+            Ok(readable(&String::from_utf8_lossy(&body)))
+        } else if content_type.starts_with("text/plain") {
+            Ok(Page {
+                text: normalize(&String::from_utf8_lossy(&body)),
+                ..Page::default()
+            })
+        } else if content_type.starts_with("application/pdf") {
+            let text = extract(DocumentKind::Pdf, &body).map_err(|e| e.to_string())?;
+            Ok(Page {
+                text: normalize(&text),
+                ..Page::default()
+            })
+        } else {
+            Err(format!("不支持的网页类型 {}", content_type))
+        }
+    }
+
+    pub async fn search(&self, request: &SearchRequest) -> ApiResult<SearchResponse> {
+        let query = request.query.trim();
+        if query.is_empty() {
+            return Err(ApiError::invalid_request("query 不能为空"));
+        }
+        let domains: Vec<String> = request.domains.iter().map(|domain| normalize_domain(domain)).collect();
+        if let Some(domain) = domains.iter().find(|domain| !self.allowed(domain, &[])) {
+            return Err(ApiError::invalid_request(format!("不允许搜索域名 {}", domain)));
+        }
+        let max_results = request.max_results.unwrap_or(self.config.max_results).clamp(1, MAX_RESULTS);
+
+        // 限定域名时交给搜索服务过滤，多要一些结果以抵消本地过滤掉的
+        let mut text = query.to_string();
+        if !domains.is_empty() {
+            let sites: Vec<String> = domains.iter().map(|domain| format!("site:{}", domain)).collect();
+            text = format!("{} {}", text, sites.join(" OR "));
+        }
+        let search = SearchQuery {
+            query: text,
+            count: (max_results * 2).min(MAX_RESULTS),
+            language: request.language.clone().or_else(|| self.config.language.clone()),
+        };
+        let hits = self
+            .backend
+            .search(&search)
+            .await
+            .map_err(|e| ApiError::Upstream(format!("{} 搜索失败: {}", self.backend.name(), e)))?;
+
+        let mut results: Vec<SearchResult> = Vec::new();
+        for hit in hits {
+            let Some(domain) = host(&hit.url) else {
+                continue;
+            };
+            if !self.allowed(&domain, &domains) || results.iter().any(|result| result.url == hit.url) {
+                continue;
+            }
+            results.push(SearchResult {
+                index: results.len() + 1,
+                title: hit.title,
+                url: hit.url,
+                domain,
+                snippet: hit.snippet,
+                site_name: None,
+                author: None,
+                published: hit.published,
+                content: None,
+                fetch_error: None,
+            });
+            if results.len() == max_results {
+                break;
+            }
+        }
+
+        if request.fetch.unwrap_or(true) {
+            let count = self.config.fetch_results.min(results.len());
+            let pages = join_all(results[..count].iter().map(|result| self.fetch(&result.url, &domains))).await;
+            for (result, page) in results.iter_mut().zip(pages) {
+                match page {
+                    Ok(page) => {
+                        if result.title.is_empty() {
+                            result.title = page.title.unwrap_or_default();
+                        }
+                        if result.snippet.is_empty() {
+                            result.snippet = page.description.unwrap_or_default();
+                        }
+                        result.site_name = page.site_name;
+                        result.author = page.author;
+                        result.published = result.published.take().or(page.published);
+                        if !page.text.is_empty() {
+                            result.content = Some(truncate_chars(&page.text, self.config.max_content_chars));
+                        }
+                    }
+                    Err(e) => result.fetch_error = Some(e),
+                }
+            }
+        }
+
+        let retrieved_at = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+        Ok(SearchResponse {
+            query: query.to_string(),
+            provider: self.backend.name(),
+            retrieved_at,
+            results,
+        })
+    }
+
+    /// `web_search`工具的定义
+    pub fn tool_definition() -> Value {
+        json!({
+            "type": "function",
+            "function": {
+                "name": TOOL_NAME,
+                "description": "搜索互联网，返回带编号的搜索结果和网页正文。回答时用 [编号] 标注引用的来源。",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "query": {"type": "string", "description": "搜索词"},
+                        "max_results": {"type": "integer", "description": "返回几条结果"},
+                        "domains": {
+                            "type": "array",
+                            "items": {"type": "string"},
+                            "description": "只搜索这些域名，如 [\"rust-lang.org\"]"
+                        }
+                    },
+                    "required": ["query"]
+                }
+            }
+        })
+    }
+
+    /// 执行`web_search`工具，结果整理为便于模型引用的文本
+    pub async fn call_tool(&self, arguments: &Value) -> Result<String, String> {
+        let request: SearchRequest =
+            serde_json::from_value(arguments.clone()).map_err(|e| format!("参数无效: {}", e))?;
+        let response = self.search(&request).await.map_err(|e| e.to_string())?;
+        if response.results.is_empty() {
+            return Ok(format!("没有找到与“{}”相关的结果。", response.query));
+        }
+        let mut output = String::new();
+        for result in &response.results {
+            output.push_str(&format!("[{}] {}\n地址: {}\n", result.index, result.title, result.url));
+            if let Some(site_name) = &result.site_name {
+                output.push_str(&format!("站点: {}\n", site_name));
+            }
+            if let Some(published) = &result.published {
+                output.push_str(&format!("发布时间: {}\n", published));
+            }
+            let body = result.content.as_deref().unwrap_or(&result.snippet);
+            output.push_str(body);
+            output.push_str("\n\n");
+        }
+        Ok(output)
+    }
+}
+
+/// `POST /v1/search`
+pub async fn web_search(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<SearchRequest>,
+) -> ApiResult<Json<SearchResponse>> {
+    let search = state
+        .search
+        .as_ref()
+        .ok_or_else(|| ApiError::invalid_request("网页搜索未启用（search.enabled 为 false）"))?;
+    Ok(Json(search.search(&request).await?))
+}
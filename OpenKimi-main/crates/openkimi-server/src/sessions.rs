@@ -0,0 +1,276 @@
+//! 会话存储接口
+//!
+//! 会话、消息、附件信息和用量保存在`sessions.path`指定的SQLite数据库中，客户端用这些接口续聊、检索和导出。
+//! 导入和导出使用同一种会话文档格式，也接受旧版客户端保存的消息数组；导出时也可以转成Markdown或HTML。
+//! ChatGPT和Claude导出的对话另有导入接口，保留分支和时间。
+//! 每个请求只能访问所属工作区中的会话，其他工作区的会话视为不存在。
+//! 消息组成树，编辑或重新生成时开出分支；读取会话时只返回当前分支，客户端可以列出分支、切换、合并和删除。
+//! 启用`pii.transcripts`时，追加和导入的消息中的个人信息在保存前替换为`[LABEL]`。
+
+use std::sync::Arc;
+
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use openkimi_sessions::export::{self, Format};
+use openkimi_sessions::external;
+use openkimi_sessions::{
+    Branch, ExternalImport, Message, NewSession, SearchHit, Session, SessionError, SessionQuery, SessionStore,
+    SessionUpdate,
+};
+use serde_json::{json, Value};
+
+use crate::error::{ApiError, ApiResult};
+use crate::pii;
+use crate::types::{
+    AppendMessagesRequest, CheckoutRequest, DeletedResponse, ExportQuery, ListResponse, MergeRequest, MessagesQuery,
+    PruneRequest, SessionDetail, SessionSearchQuery,
+};
+use crate::workspace::Workspace;
+use crate::AppState;
+
+/// 单次列出或检索的最大条数
+pub(crate) const MAX_LIMIT: u32 = 200;
+
+impl From<SessionError> for ApiError {
+    fn from(err: SessionError) -> ApiError {
+        match err {
+            SessionError::NotFound(_) => ApiError::NotFound(err.to_string()),
+            SessionError::Invalid(_) => ApiError::InvalidRequest(err.to_string()),
+            SessionError::Sqlite(_) | SessionError::Schema(_) => ApiError::Internal(err.to_string()),
+            SessionError::Postgres(_) | SessionError::Redis(_) => ApiError::Unavailable(err.to_string()),
+        }
+    }
+}
+
+/// 只能访问请求所属工作区中会话的存储
+pub(crate) fn store(state: &AppState, workspace: &Workspace) -> ApiResult<SessionStore> {
+    state
+        .sessions
+        .as_ref()
+        .map(|store| store.in_workspace(workspace.name()))
+        .ok_or_else(|| ApiError::invalid_request("会话存储未启用（sessions.enabled 为 false）"))
+}
+
+/// 按最后修改时间倒序列出会话
+pub async fn list_sessions(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Query(mut query): Query<SessionQuery>,
+) -> ApiResult<Json<ListResponse<Session>>> {
+    query.limit = query.limit.min(MAX_LIMIT);
+    Ok(Json(ListResponse::new(store(&state, &workspace)?.list_sessions(query).await?)))
+}
+
+pub async fn create_session(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Json(request): Json<NewSession>,
+) -> ApiResult<Json<Session>> {
+    Ok(Json(store(&state, &workspace)?.create_session(request).await?))
+}
+
+/// 会话及其当前分支上的消息，用于续聊
+pub async fn get_session(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+) -> ApiResult<Json<SessionDetail>> {
+    let store = store(&state, &workspace)?;
+    let session = store.session(&id).await?;
+    let messages = store.messages(&id).await?;
+    Ok(Json(SessionDetail { session, messages }))
+}
+
+pub async fn update_session(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+    Json(request): Json<SessionUpdate>,
+) -> ApiResult<Json<Session>> {
+    Ok(Json(store(&state, &workspace)?.update_session(&id, request).await?))
+}
+
+pub async fn delete_session(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+) -> ApiResult<Json<DeletedResponse>> {
+    store(&state, &workspace)?.delete_session(&id).await?;
+    Ok(Json(DeletedResponse {
+        id,
+        object: "session.deleted".to_string(),
+        deleted: true,
+    }))
+}
+
+/// 当前分支上的消息，`tree=true`时为所有分支的消息
+pub async fn list_messages(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+    Query(query): Query<MessagesQuery>,
+) -> ApiResult<Json<ListResponse<Message>>> {
+    let store = store(&state, &workspace)?;
+    let messages = if query.tree {
+        store.message_tree(&id).await?
+    } else {
+        store.messages(&id).await?
+    };
+    Ok(Json(ListResponse::new(messages)))
+}
+
+/// 按顺序追加消息，全部成功或全部不写入；指定`parent_id`时从该消息之后开出分支
+pub async fn append_messages(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+    Json(mut request): Json<AppendMessagesRequest>,
+) -> ApiResult<Json<ListResponse<Message>>> {
+    if request.messages.is_empty() {
+        return Err(ApiError::invalid_request("messages 不能为空"));
+    }
+    let store = store(&state, &workspace)?;
+    pii::redact_transcript(&state, request.messages.iter_mut().map(|message| &mut message.content).collect()).await?;
+    let messages = match request.parent_id {
+        Some(parent) => store.branch_messages(&id, parent, request.messages).await?,
+        None => store.append_messages(&id, request.messages).await?,
+    };
+    Ok(Json(ListResponse::new(messages)))
+}
+
+/// 会话中的所有分支
+pub async fn list_branches(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+) -> ApiResult<Json<ListResponse<Branch>>> {
+    Ok(Json(ListResponse::new(store(&state, &workspace)?.branches(&id).await?)))
+}
+
+/// 切换到经过指定消息的分支
+pub async fn checkout(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+    Json(request): Json<CheckoutRequest>,
+) -> ApiResult<Json<Session>> {
+    Ok(Json(store(&state, &workspace)?.checkout(&id, request.message_id).await?))
+}
+
+/// 把另一个分支上的消息复制到目标分支末尾，返回复制出的消息
+pub async fn merge(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+    Json(request): Json<MergeRequest>,
+) -> ApiResult<Json<ListResponse<Message>>> {
+    let messages = store(&state, &workspace)?.merge(&id, request.source_id, request.target_id).await?;
+    Ok(Json(ListResponse::new(messages)))
+}
+
+/// 删除一条消息及其后续消息，或删除当前分支以外的所有消息
+pub async fn prune(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+    Json(request): Json<PruneRequest>,
+) -> ApiResult<Json<Value>> {
+    let store = store(&state, &workspace)?;
+    let deleted = store.prune(&id, request.message_id).await?;
+    let session = store.session(&id).await?;
+    Ok(Json(json!({
+        "object": "session.pruned",
+        "id": id,
+        "deleted": deleted,
+        "head_id": session.head_id,
+        "message_count": session.message_count,
+    })))
+}
+
+/// 在工作区所有会话的消息中检索关键词
+pub async fn search_sessions(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Query(query): Query<SessionSearchQuery>,
+) -> ApiResult<Json<ListResponse<SearchHit>>> {
+    let hits = store(&state, &workspace)?.search(&query.q, query.limit.min(MAX_LIMIT)).await?;
+    Ok(Json(ListResponse::new(hits)))
+}
+
+/// 导出为会话文档，`format`为`md`或`html`时导出为Markdown或HTML
+pub async fn export_session(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+    Query(query): Query<ExportQuery>,
+) -> ApiResult<Response> {
+    let format = match query.format.as_deref() {
+        None => Format::Json,
+        Some(name) => Format::from_name(name)
+            .ok_or_else(|| ApiError::invalid_request(format!("不支持的导出格式: {}，可选: json、md、html", name)))?,
+    };
+    let document = store(&state, &workspace)?.export(&id).await?;
+    if format == Format::Json {
+        return Ok(Json(document).into_response());
+    }
+    Ok(([(CONTENT_TYPE, format.content_type())], export::render(&document, format)).into_response())
+}
+
+/// 导入会话文档或旧版消息数组，返回新建的会话
+pub async fn import_session(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Json(mut document): Json<Value>,
+) -> ApiResult<Json<Session>> {
+    let store = store(&state, &workspace)?;
+    pii::redact_transcript(&state, document_texts(&mut document)).await?;
+    Ok(Json(store.import(document, None).await?))
+}
+
+/// 导入ChatGPT或Claude导出的对话，请求体为导出的zip或其中的`conversations.json`
+pub async fn import_external(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    body: Bytes,
+) -> ApiResult<Json<ExternalImport>> {
+    let store = store(&state, &workspace)?;
+    let mut conversations = tokio::task::spawn_blocking(move || external::parse(&body))
+        .await
+        .map_err(|e| ApiError::Internal(format!("解析导出文件失败: {}", e)))??;
+    let texts = conversations
+        .iter_mut()
+        .flat_map(|conversation| conversation.messages.iter_mut().map(|node| &mut node.message.content))
+        .collect();
+    pii::redact_transcript(&state, texts).await?;
+    Ok(Json(store.import_external(conversations).await?))
+}
+
+/// 会话文档中各条消息的文本内容
+fn document_texts(document: &mut Value) -> Vec<&mut String> {
+    let messages = match document {
+        Value::Array(messages) => messages,
+        Value::Object(object) => match object.get_mut("messages") {
+            Some(Value::Array(messages)) => messages,
+            _ => return Vec::new(),
+        },
+        _ => return Vec::new(),
+    };
+    let mut texts = Vec::new();
+    for message in messages {
+        match message.get_mut("content") {
+            Some(Value::String(text)) => texts.push(text),
+            Some(Value::Array(parts)) => {
+                for part in parts {
+                    if let Some(Value::String(text)) = part.get_mut("text") {
+                        texts.push(text);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    texts
+}
@@ -0,0 +1,183 @@
+//! 优雅退出
+//!
+//! 收到SIGTERM或Ctrl+C后停止接受新连接，已经开始的请求（包括流式响应）继续完成；WebSocket连接上不再接受新的生成，
+//! 进行中的生成结束后关闭连接；定时任务不再开始新的运行。全部完成或超过`shutdown.drain_seconds`后，
+//! 写出内存中的用量和排队中的审计日志、安全日志和追踪数据，合并各SQLite数据库的WAL并关闭已打开的索引。
+//! 排空期间再次收到退出信号时立即退出。
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::watch;
+
+use crate::AppState;
+
+#[derive(Debug)]
+struct Inner {
+    /// 是否已收到退出信号
+    draining: watch::Sender<bool>,
+    /// 不受HTTP服务跟踪、退出前需要等待的工作数，如WebSocket连接和定时任务
+    active: watch::Sender<usize>,
+}
+
+/// 退出状态，克隆后共享
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+/// 进行中的工作，释放时计数减一
+#[derive(Debug)]
+pub struct Hold {
+    inner: Arc<Inner>,
+}
+
+impl Drop for Hold {
+    fn drop(&mut self) {
+        self.inner.active.send_modify(|active| *active -= 1);
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown {
+            inner: Arc::new(Inner {
+                draining: watch::Sender::new(false),
+                active: watch::Sender::new(0),
+            }),
+        }
+    }
+
+    /// 开始退出，已经开始过时返回`false`
+    pub fn begin(&self) -> bool {
+        !self.inner.draining.send_replace(true)
+    }
+
+    pub fn is_draining(&self) -> bool {
+        *self.inner.draining.borrow()
+    }
+
+    /// 等到开始退出
+    pub async fn wait(&self) {
+        let _ = self.inner.draining.subscribe().wait_for(|draining| *draining).await;
+    }
+
+    /// 登记一项需要在退出前完成的工作
+    pub fn hold(&self) -> Hold {
+        self.inner.active.send_modify(|active| *active += 1);
+        Hold {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+
+    /// 等到登记的工作全部完成
+    pub async fn idle(&self) {
+        let _ = self.inner.active.subscribe().wait_for(|active| *active == 0).await;
+    }
+}
+
+/// 等待SIGTERM或Ctrl+C；无法监听时一直等待
+pub async fn signal() {
+    let interrupt = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            eprintln!("⚠️ 无法监听 Ctrl+C: {}", err);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                eprintln!("⚠️ 无法监听 SIGTERM: {}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        () = interrupt => {}
+        () = terminate => {}
+    }
+}
+
+/// 收到退出信号后开始排空，再次收到时立即退出；需要在Tokio运行时中调用
+pub fn listen(state: &AppState) {
+    let shutdown = state.shutdown.clone();
+    let drain_seconds = state.config().shutdown.drain_seconds;
+    tokio::spawn(async move {
+        signal().await;
+        shutdown.begin();
+        println!("🛑 收到退出信号，停止接受新连接，最多等待 {} 秒让进行中的请求完成", drain_seconds);
+        signal().await;
+        eprintln!("⚠️ 再次收到退出信号，立即退出");
+        std::process::exit(1);
+    });
+}
+
+/// 排空结束后写出各组件缓存和排队的数据，关闭数据库
+pub async fn finish(state: &AppState) {
+    let timeout = Duration::from_secs(state.config().shutdown.flush_seconds);
+    let deadline = Instant::now() + timeout;
+    tokio::task::block_in_place(|| {
+        if let Some(metering) = &state.metering {
+            if let Err(err) = metering.close() {
+                eprintln!("⚠️ {}", err);
+            }
+        }
+        if let Some(workspaces) = &state.workspaces {
+            if let Err(err) = workspaces.flush() {
+                eprintln!("⚠️ {}", err);
+            }
+        }
+        if let Some(quotas) = &state.quotas {
+            if let Err(err) = quotas.flush() {
+                eprintln!("⚠️ {}", err);
+            }
+        }
+        if let Some(memory) = &state.memory {
+            if let Err(err) = memory.checkpoint() {
+                eprintln!("⚠️ 关闭记忆数据库失败: {}", err);
+            }
+        }
+        if let Err(err) = state.indexes.close() {
+            eprintln!("⚠️ {}", err);
+        }
+        if let Some(local_models) = &state.local_models {
+            local_models.stop();
+        }
+    });
+    if let Some(sessions) = &state.sessions {
+        if let Err(err) = sessions.checkpoint().await {
+            eprintln!("⚠️ 关闭会话数据库失败: {}", err);
+        }
+    }
+
+    // 请求都已结束，之后不会再有新的记录；各队列共用`shutdown.flush_seconds`
+    let remaining = || deadline.saturating_duration_since(Instant::now());
+    if let Some(audit) = &state.audit {
+        if !audit.flush(remaining()).await {
+            eprintln!("⚠️ {} 秒内没有写完审计日志，剩余 {} 条", timeout.as_secs(), audit.queue_depth());
+        }
+    }
+    if let Some(safety) = &state.safety {
+        if !safety.flush(remaining()).await {
+            eprintln!("⚠️ {} 秒内没有写完安全日志，剩余 {} 条", timeout.as_secs(), safety.queue_depth());
+        }
+    }
+    if let Some(tracer) = &state.tracer {
+        if !tracer.flush(remaining()).await {
+            eprintln!("⚠️ {} 秒内没有发送完追踪数据", timeout.as_secs());
+        }
+    }
+    println!("👋 OpenKimi API 服务已退出");
+}
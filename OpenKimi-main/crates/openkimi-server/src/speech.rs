@@ -0,0 +1,403 @@
+//! 语音合成
+//!
+//! `/v1/audio/speech`把文本转为语音，请求格式与OpenAI相同，音频边生成边以分块响应发送。
+//! 上游方式转发给模型路由到的后端；本地方式运行TTS命令（如piper、espeak-ng），文本从标准输入写入，
+//! 命令输出的WAV或PCM按请求的格式补上或去掉文件头，其他格式交给`encoders`中配置的编码命令（如ffmpeg）。
+
+use std::io::{Read, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::HeaderValue;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use bytes::Bytes;
+use futures_util::{stream, StreamExt};
+use tokio::sync::mpsc;
+
+use crate::audio;
+use crate::config::{SpeechBackend, SpeechConfig, SpeechOutput};
+use crate::error::{ApiError, ApiResult};
+use crate::metering::{self, Consumer};
+use crate::types::SpeechRequest;
+use crate::AppState;
+
+/// 支持的输出格式
+const FORMATS: [&str; 7] = ["mp3", "opus", "aac", "flac", "wav", "pcm", "ogg"];
+
+/// 每次从命令读取的字节数
+const CHUNK_SIZE: usize = 16 * 1024;
+
+fn mime_type(format: &str) -> &'static str {
+    match format {
+        "mp3" => "audio/mpeg",
+        "opus" | "ogg" => "audio/ogg",
+        "aac" => "audio/aac",
+        "flac" => "audio/flac",
+        "pcm" => "audio/pcm",
+        _ => "audio/wav",
+    }
+}
+
+/// 16位单声道流式WAV的文件头，长度未知时填最大值
+fn wav_header(sample_rate: u32) -> Vec<u8> {
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&u32::MAX.to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    header.extend_from_slice(&2u16.to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&u32::MAX.to_le_bytes());
+    header
+}
+
+/// 把命令的输出转为请求的WAV或PCM
+enum Framing {
+    /// 原样输出
+    Pass,
+    /// 在PCM前加上WAV文件头
+    Header(Option<Vec<u8>>),
+    /// 去掉WAV文件头，缓存到找到`data`块为止
+    Strip(Vec<u8>),
+}
+
+impl Framing {
+    fn push(&mut self, chunk: &[u8]) -> Vec<u8> {
+        match self {
+            Framing::Pass => chunk.to_vec(),
+            Framing::Header(header) => {
+                let mut output = header.take().unwrap_or_default();
+                output.extend_from_slice(chunk);
+                output
+            }
+            Framing::Strip(buffer) => {
+                buffer.extend_from_slice(chunk);
+                let mut position = 12;
+                while position + 8 <= buffer.len() {
+                    if &buffer[position..position + 4] == b"data" {
+                        let rest = buffer[position + 8..].to_vec();
+                        *self = Framing::Pass;
+                        return rest;
+                    }
+                    let size = u32::from_le_bytes([
+                        buffer[position + 4],
+                        buffer[position + 5],
+                        buffer[position + 6],
+                        buffer[position + 7],
+                    ]) as usize;
+                    position += 8 + size + (size & 1);
+                }
+                Vec::new()
+            }
+        }
+    }
+}
+
+/// TTS命令和编码命令，超时或客户端断开时一起结束
+struct Processes {
+    children: Mutex<Vec<(String, Child)>>,
+    /// 已结束，看门狗不再等待
+    done: AtomicBool,
+    timed_out: AtomicBool,
+    timeout_seconds: u64,
+}
+
+impl Processes {
+    fn spawn(&self, args: &[String]) -> Result<Child, String> {
+        let mut command = Command::new(&args[0]);
+        command.args(&args[1..]).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped());
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            command.process_group(0);
+        }
+        command.spawn().map_err(|e| format!("无法运行命令 {}: {}", args[0], e))
+    }
+
+    fn kill(&self) {
+        for (_, child) in self.children.lock().unwrap().iter_mut() {
+            // 命令可能是脚本，连同它启动的进程一起结束，否则这些进程持有的管道不会关闭
+            #[cfg(unix)]
+            let _ = Command::new("kill").args(["-KILL", "--", &format!("-{}", child.id())]).status();
+            let _ = child.kill();
+        }
+    }
+
+    /// 等待第`index`个命令退出，异常退出时返回其标准错误的末尾
+    fn wait(&self, index: usize, stderr: JoinHandle<String>) -> Result<(), String> {
+        let (name, status) = loop {
+            let mut children = self.children.lock().unwrap();
+            let (name, child) = &mut children[index];
+            if let Some(status) = child.try_wait().map_err(|e| format!("等待命令 {} 失败: {}", name, e))? {
+                break (name.clone(), status);
+            }
+            drop(children);
+            std::thread::sleep(Duration::from_millis(20));
+        };
+        let stderr = stderr.join().unwrap_or_default();
+        if self.timed_out.load(Ordering::Relaxed) {
+            return Err(format!("命令 {} 超过 {} 秒", name, self.timeout_seconds));
+        }
+        if !status.success() {
+            let tail: String = stderr.trim().chars().rev().take(500).collect::<Vec<_>>().into_iter().rev().collect();
+            return Err(format!("命令 {} 异常退出（{}）: {}", name, status, tail));
+        }
+        Ok(())
+    }
+
+    /// 超时后结束所有命令
+    fn watch(self: Arc<Self>) {
+        let deadline = Instant::now() + Duration::from_secs(self.timeout_seconds);
+        std::thread::spawn(move || {
+            while !self.done.load(Ordering::Relaxed) {
+                if Instant::now() >= deadline {
+                    self.timed_out.store(true, Ordering::Relaxed);
+                    self.kill();
+                    return;
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        });
+    }
+}
+
+type Chunk = Result<Bytes, String>;
+
+/// 本地合成的参数
+struct Job {
+    command: Vec<String>,
+    encoder: Option<Vec<String>>,
+    framing: Framing,
+    input: String,
+    timeout_seconds: u64,
+}
+
+/// 运行TTS命令（和编码命令），音频逐块发到`tx`；客户端断开时结束命令
+fn synthesize(job: Job, tx: mpsc::Sender<Chunk>) {
+    let processes = Arc::new(Processes {
+        children: Mutex::new(Vec::new()),
+        done: AtomicBool::new(false),
+        timed_out: AtomicBool::new(false),
+        timeout_seconds: job.timeout_seconds,
+    });
+    Arc::clone(&processes).watch();
+    if let Err(e) = run(job, &processes, &tx) {
+        processes.kill();
+        let _ = tx.blocking_send(Err(e));
+    }
+    processes.done.store(true, Ordering::Relaxed);
+}
+
+fn run(mut job: Job, processes: &Arc<Processes>, tx: &mpsc::Sender<Chunk>) -> Result<(), String> {
+    let mut tts = processes.spawn(&job.command)?;
+    let stdin = tts.stdin.take();
+    let input = std::mem::take(&mut job.input);
+    let writer = std::thread::spawn(move || {
+        if let Some(mut stdin) = stdin {
+            let _ = stdin.write_all(input.as_bytes());
+        }
+    });
+    let tts_stderr = audio::drain(tts.stderr.take());
+    let mut stdout = tts.stdout.take().ok_or("无法读取TTS命令的输出")?;
+    processes.children.lock().unwrap().push((job.command[0].clone(), tts));
+
+    // 编码命令的输出在另一个线程里转发
+    let mut encoder = None;
+    if let Some(args) = &job.encoder {
+        let mut child = processes.spawn(args)?;
+        let stdin = child.stdin.take();
+        let stderr = audio::drain(child.stderr.take());
+        let mut output = child.stdout.take().ok_or("无法读取编码命令的输出")?;
+        processes.children.lock().unwrap().push((args[0].clone(), child));
+        let (tx, processes) = (tx.clone(), Arc::clone(processes));
+        let reader = std::thread::spawn(move || {
+            let mut buffer = vec![0u8; CHUNK_SIZE];
+            while let Ok(read) = output.read(&mut buffer) {
+                if read == 0 {
+                    break;
+                }
+                if tx.blocking_send(Ok(Bytes::copy_from_slice(&buffer[..read]))).is_err() {
+                    processes.kill();
+                    break;
+                }
+            }
+        });
+        encoder = Some((stdin, stderr, reader));
+    }
+
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    loop {
+        let read = stdout.read(&mut buffer).map_err(|e| format!("读取TTS命令的输出失败: {}", e))?;
+        if read == 0 {
+            break;
+        }
+        let data = job.framing.push(&buffer[..read]);
+        if data.is_empty() {
+            continue;
+        }
+        match &mut encoder {
+            // 编码命令提前退出时由下面的wait报告原因
+            Some((Some(stdin), _, _)) => {
+                if stdin.write_all(&data).is_err() {
+                    break;
+                }
+            }
+            Some((None, _, _)) => break,
+            None => {
+                if tx.blocking_send(Ok(Bytes::from(data))).is_err() {
+                    processes.kill();
+                    return Ok(());
+                }
+            }
+        }
+    }
+    let _ = writer.join();
+    processes.wait(0, tts_stderr)?;
+    if let Some((stdin, stderr, reader)) = encoder {
+        drop(stdin);
+        let _ = reader.join();
+        processes.wait(1, stderr)?;
+    }
+    Ok(())
+}
+
+/// 转发给上游，音频边收边转发
+async fn upstream(state: &AppState, request: &SpeechRequest) -> ApiResult<Response> {
+    let models = state.models();
+    let route = models.route(&request.model);
+    let body = SpeechRequest {
+        model: route.model.to_string(),
+        ..request.clone()
+    };
+    let response = route.backend.upstream.speech(&body).await?;
+    let format = request.response_format.as_deref().unwrap_or("mp3");
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .cloned()
+        .unwrap_or_else(|| HeaderValue::from_static(mime_type(format)));
+    Ok(([(CONTENT_TYPE, content_type)], Body::from_stream(response.bytes_stream())).into_response())
+}
+
+/// 运行本地TTS命令
+async fn command(config: &SpeechConfig, request: &SpeechRequest) -> ApiResult<Response> {
+    if config.command.is_empty() {
+        return Err(ApiError::Internal("speech.backend 为 command 但没有配置 speech.command".to_string()));
+    }
+    let voice = if config.voices.is_empty() {
+        request.voice.clone()
+    } else {
+        config.voices.get(&request.voice).cloned().ok_or_else(|| {
+            let voices: Vec<&str> = config.voices.keys().map(String::as_str).collect();
+            ApiError::invalid_request(format!("不支持的音色 {}，可用的音色: {}", request.voice, voices.join("、")))
+        })?
+    };
+    let speed = request.speed.unwrap_or(1.0);
+    let command = config
+        .command
+        .iter()
+        .map(|arg| {
+            arg.replace("{voice}", &voice)
+                .replace("{speed}", &speed.to_string())
+                .replace("{length_scale}", &format!("{:.3}", 1.0 / speed))
+                .replace("{model}", &request.model)
+        })
+        .collect();
+
+    let format = request.response_format.as_deref().unwrap_or("wav");
+    let header = || Framing::Header(Some(wav_header(config.sample_rate)));
+    let (framing, encoder) = match (format, config.output) {
+        ("wav", SpeechOutput::Wav) | ("pcm", SpeechOutput::Pcm) => (Framing::Pass, None),
+        ("wav", SpeechOutput::Pcm) => (header(), None),
+        ("pcm", SpeechOutput::Wav) => (Framing::Strip(Vec::new()), None),
+        (format, output) => {
+            let encoder = config.encoders.get(format).ok_or_else(|| {
+                ApiError::invalid_request(format!(
+                    "本地语音合成不支持 {} 格式，需要在 speech.encoders 中配置编码命令",
+                    format
+                ))
+            })?;
+            let framing = if output == SpeechOutput::Pcm { header() } else { Framing::Pass };
+            (framing, Some(encoder.clone()))
+        }
+    };
+    let job = Job {
+        command,
+        encoder,
+        framing,
+        input: request.input.clone(),
+        timeout_seconds: config.timeout_seconds,
+    };
+
+    let (tx, mut rx) = mpsc::channel::<Chunk>(32);
+    std::thread::spawn(move || synthesize(job, tx));
+    // 等到第一块音频再返回响应头，命令一开始就失败时客户端仍能收到普通的JSON错误
+    let first = match rx.recv().await {
+        Some(Ok(first)) => first,
+        Some(Err(e)) => return Err(ApiError::Internal(format!("语音合成失败: {}", e))),
+        None => return Err(ApiError::Internal("语音合成命令没有输出".to_string())),
+    };
+    let rest = stream::unfold(rx, |mut rx| async move {
+        let chunk = rx.recv().await?;
+        Some((chunk.map_err(std::io::Error::other), rx))
+    });
+    let body = stream::once(async move { Ok(first) }).chain(rest);
+    Ok(([(CONTENT_TYPE, mime_type(format))], Body::from_stream(body)).into_response())
+}
+
+/// `POST /v1/audio/speech`
+pub async fn speech(
+    State(state): State<Arc<AppState>>,
+    consumer: Consumer,
+    Json(mut request): Json<SpeechRequest>,
+) -> ApiResult<Response> {
+    let config = &state.config().speech;
+    if !config.enabled {
+        return Err(ApiError::invalid_request("语音合成未启用（speech.enabled 为 false）"));
+    }
+    if request.model.is_empty() {
+        request.model = config.model.clone();
+    }
+    if request.voice.is_empty() {
+        request.voice = config.voice.clone();
+    }
+    if request.input.trim().is_empty() {
+        return Err(ApiError::invalid_request("input 不能为空"));
+    }
+    let chars = request.input.chars().count();
+    if chars > config.max_input_chars {
+        return Err(ApiError::invalid_request(format!(
+            "input 为 {} 个字符，超过 {} 个字符的上限",
+            chars, config.max_input_chars
+        )));
+    }
+    if let Some(speed) = request.speed.filter(|speed| !(0.25..=4.0).contains(speed)) {
+        return Err(ApiError::invalid_request(format!("speed 应在 0.25 到 4.0 之间，收到 {}", speed)));
+    }
+    if let Some(format) = request.response_format.as_deref().filter(|format| !FORMATS.contains(format)) {
+        return Err(ApiError::invalid_request(format!(
+            "不支持的 response_format {}，应为 {}",
+            format,
+            FORMATS.join("、")
+        )));
+    }
+
+    let response = match config.backend {
+        SpeechBackend::Upstream => upstream(&state, &request).await?,
+        SpeechBackend::Command => command(config, &request).await?,
+    };
+    metering::record(&state, &consumer, &request.model, 0, 0);
+    Ok(response)
+}
@@ -0,0 +1,132 @@
+//! 上游SSE响应的解析与转发
+//!
+//! 逐块解析上游的`text/event-stream`，把每个事件的`data`原样交给客户端，
+//! 保证与OpenAI的分块内容逐token一致。转发流按需拉取上游数据：客户端读得慢时
+//! 不会继续读取上游，客户端断开时流被丢弃，上游连接随之关闭，请求也就取消了。
+
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::time::Duration;
+
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures_util::stream::{self, BoxStream, Stream, StreamExt};
+use serde_json::json;
+
+/// 无数据时发送注释行的间隔，避免代理或负载均衡断开空闲连接
+pub(crate) const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// 一个完整的SSE事件
+#[derive(Debug, Clone)]
+pub struct SseEvent {
+    /// `event`字段，未指定时为`None`（即`message`）
+    pub event: Option<String>,
+    pub data: String,
+}
+
+/// 增量SSE解析器
+#[derive(Debug, Default)]
+pub struct SseParser {
+    /// 按字节缓冲，避免多字节字符被分块截断
+    buffer: Vec<u8>,
+    event: Option<String>,
+    data: Vec<String>,
+}
+
+impl SseParser {
+    /// 处理一行，遇到空行时返回完整的事件
+    fn feed_line(&mut self, line: &str) -> Option<SseEvent> {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.is_empty() {
+            let event = self.event.take();
+            if self.data.is_empty() {
+                return None;
+            }
+            let data = std::mem::take(&mut self.data).join("\n");
+            return Some(SseEvent { event, data });
+        }
+        if let Some(value) = line.strip_prefix("data:") {
+            self.data.push(value.strip_prefix(' ').unwrap_or(value).to_string());
+        } else if let Some(value) = line.strip_prefix("event:") {
+            self.event = Some(value.strip_prefix(' ').unwrap_or(value).to_string());
+        }
+        // id、retry及注释行对OpenAI格式和MCP都没有意义，忽略
+        None
+    }
+
+    /// 追加一块数据，返回其中已完整的事件
+    pub fn push_events(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(newline) = self.buffer.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=newline).collect();
+            if let Some(event) = self.feed_line(&String::from_utf8_lossy(&line[..newline])) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    /// 追加一块数据，返回其中已完整的事件的data
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.push_events(chunk).into_iter().map(|event| event.data).collect()
+    }
+
+    /// 上游结束时处理缓冲区中剩余的内容
+    pub fn finish(&mut self) -> Vec<String> {
+        let rest = String::from_utf8_lossy(&std::mem::take(&mut self.buffer)).into_owned();
+        let mut events: Vec<String> = self.feed_line(&rest).into_iter().map(|event| event.data).collect();
+        events.extend(self.feed_line("").map(|event| event.data));
+        events
+    }
+}
+
+/// 把上游响应体转为事件data流；上游中途出错时以一个错误事件结束
+pub fn data_stream(response: reqwest::Response) -> BoxStream<'static, String> {
+    struct State {
+        body: BoxStream<'static, reqwest::Result<bytes::Bytes>>,
+        parser: SseParser,
+        pending: VecDeque<String>,
+        done: bool,
+    }
+
+    let state = State {
+        body: response.bytes_stream().boxed(),
+        parser: SseParser::default(),
+        pending: VecDeque::new(),
+        done: false,
+    };
+
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(data) = state.pending.pop_front() {
+                return Some((data, state));
+            }
+            if state.done {
+                return None;
+            }
+            match state.body.next().await {
+                Some(Ok(chunk)) => state.pending.extend(state.parser.push(&chunk)),
+                Some(Err(err)) => {
+                    state.done = true;
+                    let error = json!({
+                        "error": { "message": format!("上游流式响应中断: {}", err), "type": "upstream_error", "code": null }
+                    });
+                    state.pending.push_back(error.to_string());
+                }
+                None => {
+                    state.done = true;
+                    state.pending.extend(state.parser.finish());
+                }
+            }
+        }
+    })
+    .boxed()
+}
+
+/// 把data流包装为带保活的SSE响应
+pub fn sse_response(
+    data: impl Stream<Item = String> + Send + 'static,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    Sse::new(data.map(|data| Ok(Event::default().data(data))))
+        .keep_alive(KeepAlive::new().interval(KEEP_ALIVE_INTERVAL))
+}
@@ -0,0 +1,342 @@
+//! 可续读的流式对话
+//!
+//! 启用`streams`后，流式对话补全由后台任务从上游一直读到结束，事件依次追加到日志中，客户端的响应只是读取日志：
+//! 响应头`x-openkimi-stream-id`给出流的id，每个SSE事件的`id`是它在流中的序号（从1开始）。
+//! 客户端断线后请求`GET /v1/chat/streams/{id}`，带上`Last-Event-ID`头（或`?after=`参数），
+//! 从下一个事件继续读取，已经生成的部分不会重新请求上游；客户端断开也不会取消生成。
+//! `streams.store`为`redis`时日志同时写入Redis，可以连到负载均衡之后的任一实例续读，
+//! 其他实例每隔一段时间读取一次新的事件。生成结束后日志保留`streams.ttl_seconds`。
+
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, HeaderValue};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use futures_util::stream::{self, BoxStream, Stream, StreamExt};
+use futures_util::FutureExt;
+use openkimi_redis::{Arg, RedisError};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::watch;
+
+use crate::config::{StateStoreKind, StreamsConfig};
+use crate::error::{ApiError, ApiResult};
+use crate::redis::Redis;
+use crate::sse::KEEP_ALIVE_INTERVAL;
+use crate::workspace::Workspace;
+use crate::{interpreter, AppState};
+
+/// 响应头，取值为流的id
+pub const STREAM_HEADER: &str = "x-openkimi-stream-id";
+
+/// 从Redis续读时检查新事件的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 本实例生成的流，读取时不经过Redis
+#[derive(Debug)]
+struct Log {
+    workspace: String,
+    events: Mutex<Vec<String>>,
+    /// 已追加的事件数和是否已结束，追加后通知读取方
+    progress: watch::Sender<(usize, bool)>,
+}
+
+impl Log {
+    fn append(&self, batch: &[String]) {
+        let mut events = self.events.lock().unwrap();
+        events.extend_from_slice(batch);
+        let len = events.len();
+        self.progress.send_modify(|progress| progress.0 = len);
+    }
+
+    fn finish(&self) {
+        self.progress.send_modify(|progress| progress.1 = true);
+    }
+
+    /// 从第`after`个事件之后读到结束
+    fn tail(self: Arc<Log>, after: usize) -> BoxStream<'static, (usize, String)> {
+        let receiver = self.progress.subscribe();
+        stream::unfold((self, receiver, after, VecDeque::new()), |(log, mut receiver, mut next, mut pending)| {
+            async move {
+                loop {
+                    if let Some(event) = pending.pop_front() {
+                        return Some((event, (log, receiver, next, pending)));
+                    }
+                    let (len, done) = *receiver.borrow_and_update();
+                    if next < len {
+                        let events = log.events.lock().unwrap();
+                        pending.extend((next + 1..).zip(events[next..len].iter().cloned()));
+                        next = len;
+                    } else if done || receiver.changed().await.is_err() {
+                        return None;
+                    }
+                }
+            }
+        })
+        .boxed()
+    }
+}
+
+/// 保存在Redis中的流的状态，键为`<前缀>stream:<id>:info`，事件保存在列表`<前缀>stream:<id>`中
+#[derive(Debug, Serialize, Deserialize)]
+struct Info {
+    workspace: String,
+    done: bool,
+}
+
+/// 保存在Redis中的日志，各方法都是阻塞的
+#[derive(Debug, Clone)]
+struct Shared {
+    redis: Redis,
+    id: String,
+    /// 毫秒
+    ttl: u64,
+}
+
+impl Shared {
+    /// 在阻塞线程中执行
+    async fn run<T, F>(&self, f: F) -> Result<T, RedisError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Shared) -> Result<T, RedisError> + Send + 'static,
+    {
+        let shared = self.clone();
+        self.redis.run(move |_| f(&shared)).await
+    }
+
+    fn events_key(&self) -> String {
+        self.redis.key(&format!("stream:{}", self.id))
+    }
+
+    fn info_key(&self) -> String {
+        self.redis.key(&format!("stream:{}:info", self.id))
+    }
+
+    fn info(&self) -> Result<Option<Info>, RedisError> {
+        let reply = self.redis.client().query(&[&"GET", &self.info_key()])?;
+        Ok(reply.into_bytes().and_then(|bytes| serde_json::from_slice(&bytes).ok()))
+    }
+
+    fn set_info(&self, info: &Info) -> Result<(), RedisError> {
+        let value = serde_json::to_string(info).map_err(|e| RedisError::Protocol(e.to_string()))?;
+        self.redis.client().query(&[&"SET", &self.info_key(), &value, &"PX", &self.ttl])?;
+        Ok(())
+    }
+
+    fn append(&self, batch: &[String]) -> Result<(), RedisError> {
+        let key = self.events_key();
+        let mut args: Vec<&dyn Arg> = vec![&"RPUSH", &key];
+        args.extend(batch.iter().map(|data| data as &dyn Arg));
+        let client = self.redis.client();
+        client.query(&args)?;
+        // 生成期间每次追加都延长，生成很慢时也不会在结束前过期
+        client.query(&[&"PEXPIRE", &key, &self.ttl])?;
+        client.query(&[&"PEXPIRE", &self.info_key(), &self.ttl])?;
+        Ok(())
+    }
+
+    /// 是否已结束，以及第`after`个事件之后的事件；先读状态再读事件，已结束时读到的就是全部剩余事件
+    fn poll(&self, after: usize) -> Result<Option<(bool, Vec<String>)>, RedisError> {
+        let Some(info) = self.info()? else {
+            return Ok(None);
+        };
+        let reply = self.redis.client().query(&[&"LRANGE", &self.events_key(), &after, &-1i64])?;
+        let events = reply.into_array()?.into_iter().filter_map(|event| event.into_string()).collect();
+        Ok(Some((info.done, events)))
+    }
+
+    /// 从第`after`个事件之后读到结束；Redis出错时以一个错误事件结束
+    fn tail(self, after: usize) -> BoxStream<'static, (usize, String)> {
+        stream::unfold((self, after, VecDeque::new(), false), |(shared, mut next, mut pending, mut done)| {
+            async move {
+                loop {
+                    if let Some(event) = pending.pop_front() {
+                        return Some((event, (shared, next, pending, done)));
+                    }
+                    if done {
+                        return None;
+                    }
+                    match shared.run(move |shared| shared.poll(next)).await {
+                        Ok(Some((finished, events))) => {
+                            if events.is_empty() && !finished {
+                                tokio::time::sleep(POLL_INTERVAL).await;
+                                continue;
+                            }
+                            pending.extend((next + 1..).zip(events));
+                            next += pending.len();
+                            done = finished;
+                        }
+                        Ok(None) => return None,
+                        Err(err) => {
+                            let error = json!({
+                                "error": {
+                                    "message": format!("读取Redis中的流式响应失败: {}", err),
+                                    "type": "server_error",
+                                    "code": null
+                                }
+                            });
+                            pending.push_back((next + 1, error.to_string()));
+                            done = true;
+                        }
+                    }
+                }
+            }
+        })
+        .boxed()
+    }
+}
+
+/// 可续读的流
+#[derive(Debug, Clone)]
+pub struct Streams {
+    ttl: Duration,
+    /// `streams.store`为`redis`时为`Some`
+    redis: Option<Redis>,
+    local: Arc<Mutex<HashMap<String, Arc<Log>>>>,
+}
+
+impl Streams {
+    /// `streams.store`为`redis`时使用`redis`
+    pub fn new(config: &StreamsConfig, redis: Option<&Redis>) -> Streams {
+        Streams {
+            ttl: Duration::from_secs(config.ttl_seconds.max(1)),
+            redis: redis.filter(|_| config.store == StateStoreKind::Redis).cloned(),
+            local: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn shared(&self, id: &str) -> Option<Shared> {
+        Some(Shared {
+            redis: self.redis.clone()?,
+            id: id.to_string(),
+            ttl: self.ttl.as_millis() as u64,
+        })
+    }
+
+    /// 在后台把`data`读到结束并保存，返回读取它的SSE响应
+    pub async fn start(
+        &self,
+        state: &AppState,
+        workspace: &Workspace,
+        data: impl Stream<Item = String> + Send + 'static,
+    ) -> ApiResult<Response> {
+        let id = format!("stream-{}", interpreter::random_hex().map_err(ApiError::Internal)?);
+        let shared = self.shared(&id);
+        if let Some(shared) = &shared {
+            // 先写入状态，客户端拿到id后立即可以从其他实例续读
+            let info = Info {
+                workspace: workspace.name().to_string(),
+                done: false,
+            };
+            shared
+                .run(move |shared| shared.set_info(&info))
+                .await
+                .map_err(|e| ApiError::Unavailable(format!("保存流式响应失败: {}", e)))?;
+        }
+        let log = Arc::new(Log {
+            workspace: workspace.name().to_string(),
+            events: Mutex::new(Vec::new()),
+            progress: watch::channel((0, false)).0,
+        });
+        self.local.lock().unwrap().insert(id.clone(), Arc::clone(&log));
+
+        let streams = self.clone();
+        let writer = Arc::clone(&log);
+        let hold = state.shutdown.hold();
+        let stream_id = id.clone();
+        tokio::spawn(async move {
+            let _hold = hold;
+            let mut data = Box::pin(data);
+            let mut shared = shared;
+            while let Some(first) = data.next().await {
+                // 已经到达的事件一起写入，减少访问Redis的次数
+                let mut batch = vec![first];
+                while let Some(Some(more)) = data.next().now_or_never() {
+                    batch.push(more);
+                }
+                writer.append(&batch);
+                if let Some(target) = &shared {
+                    if let Err(err) = target.run(move |target| target.append(&batch)).await {
+                        eprintln!("⚠️ 写入Redis中的流式响应 {} 失败，只能从本实例续读: {}", stream_id, err);
+                        shared = None;
+                    }
+                }
+            }
+            writer.finish();
+            if let Some(shared) = shared {
+                let info = Info {
+                    workspace: writer.workspace.clone(),
+                    done: true,
+                };
+                if let Err(err) = shared.run(move |shared| shared.set_info(&info)).await {
+                    eprintln!("⚠️ 写入Redis中的流式响应 {} 失败: {}", stream_id, err);
+                }
+            }
+            tokio::time::sleep(streams.ttl).await;
+            streams.local.lock().unwrap().remove(&stream_id);
+        });
+        Ok(respond(&id, log.tail(0)))
+    }
+
+    /// 从第`after`个事件之后续读；流不存在、已过期或属于其他工作区时报错
+    pub async fn resume(&self, workspace: &Workspace, id: &str, after: usize) -> ApiResult<Response> {
+        let not_found = || ApiError::NotFound(format!("流式响应 {} 不存在或已过期", id));
+        let local = self.local.lock().unwrap().get(id).cloned();
+        if let Some(log) = local {
+            if log.workspace != workspace.name() {
+                return Err(not_found());
+            }
+            return Ok(respond(id, log.tail(after)));
+        }
+
+        let shared = self.shared(id).ok_or_else(not_found)?;
+        let info = shared.run(Shared::info).await.map_err(|e| ApiError::Unavailable(format!("读取流式响应失败: {}", e)))?;
+        if info.is_none_or(|info| info.workspace != workspace.name()) {
+            return Err(not_found());
+        }
+        Ok(respond(id, shared.tail(after)))
+    }
+}
+
+/// 带事件序号的SSE响应
+fn respond(id: &str, events: impl Stream<Item = (usize, String)> + Send + 'static) -> Response {
+    let events = events.map(|(seq, data)| Ok::<_, Infallible>(Event::default().id(seq.to_string()).data(data)));
+    let mut response = Sse::new(events)
+        .keep_alive(KeepAlive::new().interval(KEEP_ALIVE_INTERVAL))
+        .into_response();
+    if let Ok(value) = HeaderValue::from_str(id) {
+        response.headers_mut().insert(STREAM_HEADER, value);
+    }
+    response
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResumeParams {
+    /// 已经收到的最后一个事件的序号，`Last-Event-ID`头优先
+    after: Option<usize>,
+}
+
+/// `GET /v1/chat/streams/{id}`，续读流式对话
+pub async fn resume_stream(
+    State(state): State<Arc<AppState>>,
+    workspace: Workspace,
+    Path(id): Path<String>,
+    Query(params): Query<ResumeParams>,
+    headers: HeaderMap,
+) -> ApiResult<Response> {
+    let streams = state
+        .streams
+        .as_ref()
+        .ok_or_else(|| ApiError::invalid_request("未启用流式响应续读（streams.enabled 为 false）"))?;
+    let last_event = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().parse().map_err(|_| ApiError::invalid_request("Last-Event-ID 必须是事件序号")))
+        .transpose()?;
+    let after = last_event.or(params.after).unwrap_or(0);
+    streams.resume(&workspace, &id, after).await
+}
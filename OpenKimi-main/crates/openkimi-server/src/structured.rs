@@ -0,0 +1,251 @@
+//! 结构化输出
+//!
+//! 请求的`response_format`为`json_schema`时校验模型输出是否符合其中的JSON Schema，为`json_object`时只要求输出是JSON对象。
+//! 不符合时先尝试修复（去掉Markdown代码块、截取其中的JSON），仍不符合就把校验错误告诉模型重新生成，
+//! 最多重试`structured_output.max_retries`次，之后返回`schema_validation_failed`错误。
+//! 流式输出已经发给客户端，无法重试，只在结束时校验，不符合时以一个错误事件代替`[DONE]`。
+
+use std::collections::BTreeMap;
+
+use futures_util::stream::{self, BoxStream};
+use futures_util::StreamExt;
+use serde_json::{Map, Value};
+
+use crate::error::{ApiError, ApiResult};
+use crate::schema::Schema;
+use crate::tools::{self, ToolScope};
+use crate::types::{ChatCompletionRequest, ChatCompletionResponse, ChatMessage, MessageContent};
+use crate::AppState;
+
+/// 要求的输出格式
+#[derive(Debug, Clone)]
+pub enum OutputFormat {
+    /// `json_object`
+    Object,
+    /// `json_schema`
+    Schema { name: String, schema: Schema },
+}
+
+fn check_name(name: &str) -> bool {
+    let valid = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    !name.is_empty() && name.len() <= 64 && valid
+}
+
+/// 解析并校验请求中的`response_format`，`text`或没有时返回`None`
+pub fn output_format(request: &ChatCompletionRequest) -> ApiResult<Option<OutputFormat>> {
+    let format = match request.extra.get("response_format") {
+        None | Some(Value::Null) => return Ok(None),
+        Some(Value::Object(format)) => format,
+        Some(_) => return Err(ApiError::invalid_request("response_format 必须是对象")),
+    };
+    match format.get("type").and_then(Value::as_str) {
+        Some("text") => Ok(None),
+        Some("json_object") => Ok(Some(OutputFormat::Object)),
+        Some("json_schema") => {
+            let Some(definition) = format.get("json_schema").and_then(Value::as_object) else {
+                return Err(ApiError::invalid_request("response_format 缺少 json_schema"));
+            };
+            let name = definition.get("name").and_then(Value::as_str).unwrap_or_default();
+            if !check_name(name) {
+                return Err(ApiError::invalid_request(
+                    "response_format.json_schema.name 只能包含字母、数字、_ 和 -，长度为 1 到 64",
+                ));
+            }
+            if definition.get("strict").is_some_and(|strict| !strict.is_boolean()) {
+                return Err(ApiError::invalid_request("response_format.json_schema.strict 必须是布尔值"));
+            }
+            let schema = definition.get("schema").filter(|schema| schema.is_object());
+            let Some(schema) = schema else {
+                return Err(ApiError::invalid_request("response_format.json_schema.schema 必须是 JSON Schema 对象"));
+            };
+            let schema = Schema::compile(schema)
+                .map_err(|e| ApiError::invalid_request(format!("response_format.json_schema.schema 无效: {}", e)))?;
+            Ok(Some(OutputFormat::Schema {
+                name: name.to_string(),
+                schema,
+            }))
+        }
+        _ => Err(ApiError::invalid_request(
+            "response_format.type 必须是 \"text\"、\"json_object\" 或 \"json_schema\"",
+        )),
+    }
+}
+
+/// 从输出中找出JSON：整段、Markdown代码块中的内容，或第一个`{`/`[`到最后一个`}`/`]`之间的部分
+fn extract(content: &str, repair: bool) -> Option<Value> {
+    let content = content.trim();
+    if let Ok(value) = serde_json::from_str(content) {
+        return Some(value);
+    }
+    if !repair {
+        return None;
+    }
+    if let Some((_, rest)) = content.split_once("```") {
+        // 跳过代码块开头的语言标记，如```json
+        let body = rest.split_once('\n').map_or(rest, |(_, body)| body);
+        let body = body.split("```").next().unwrap_or(body);
+        if let Ok(value) = serde_json::from_str(body.trim()) {
+            return Some(value);
+        }
+    }
+    let start = content.find(['{', '['])?;
+    let end = content.rfind(['}', ']'])?;
+    (start < end).then(|| serde_json::from_str(&content[start..=end]).ok()).flatten()
+}
+
+impl OutputFormat {
+    /// 校验一段输出，符合时返回其中的JSON，以及是否经过了修复
+    fn check(&self, content: &str, repair: bool) -> Result<(Value, bool), Vec<String>> {
+        let Some(value) = extract(content, repair) else {
+            return Err(vec!["输出不是有效的 JSON".to_string()]);
+        };
+        let errors = match self {
+            OutputFormat::Object if !value.is_object() => vec!["输出应是 JSON 对象".to_string()],
+            OutputFormat::Object => Vec::new(),
+            OutputFormat::Schema { schema, .. } => schema.validate(&value),
+        };
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let repaired = serde_json::from_str::<Value>(content.trim()).is_err();
+        Ok((value, repaired))
+    }
+
+    fn violation(&self, errors: Vec<String>) -> ApiError {
+        let message = match self {
+            OutputFormat::Object => "模型输出不是 JSON 对象".to_string(),
+            OutputFormat::Schema { name, .. } => format!("模型输出不符合 JSON Schema {}", name),
+        };
+        ApiError::SchemaValidation(message, errors)
+    }
+}
+
+fn text_message(role: &str, text: String) -> ChatMessage {
+    ChatMessage {
+        role: role.to_string(),
+        content: Some(MessageContent::Text(text)),
+        name: None,
+        extra: Map::new(),
+    }
+}
+
+/// 只有工具调用、没有内容的回复不需要校验
+fn has_tool_calls(message: &ChatMessage) -> bool {
+    message.extra.get("tool_calls").and_then(Value::as_array).is_some_and(|calls| !calls.is_empty())
+}
+
+/// 非流式对话补全，带`response_format`时校验输出，不符合时修复或重新生成，返回的用量是各次之和
+pub async fn chat_completion(
+    state: &AppState,
+    request: &ChatCompletionRequest,
+    scope: &ToolScope,
+) -> ApiResult<ChatCompletionResponse> {
+    let Some(format) = output_format(request)? else {
+        return tools::chat_completion(state, request, scope).await;
+    };
+    let config = &state.config().structured_output;
+
+    let mut retry: Option<ChatCompletionRequest> = None;
+    let mut usage = None;
+    let mut attempt = 0;
+    loop {
+        let mut response = tools::chat_completion(state, retry.as_ref().unwrap_or(request), scope).await?;
+        usage = tools::add_usage(usage, response.usage.as_ref());
+
+        let mut failure = None;
+        for choice in &mut response.choices {
+            if has_tool_calls(&choice.message) {
+                continue;
+            }
+            let content = choice.message.text();
+            match format.check(&content, config.repair) {
+                Ok((value, true)) => choice.message.content = Some(MessageContent::Text(value.to_string())),
+                Ok(_) => {}
+                Err(errors) => {
+                    failure = Some((content, errors));
+                    break;
+                }
+            }
+        }
+        let Some((content, errors)) = failure else {
+            response.usage = usage;
+            return Ok(response);
+        };
+        if attempt >= config.max_retries {
+            return Err(format.violation(errors));
+        }
+
+        attempt += 1;
+        eprintln!("⚠️ 模型输出不符合 response_format，第 {} 次重新生成: {}", attempt, errors.join("; "));
+        let retry = retry.get_or_insert_with(|| request.clone());
+        retry.messages.push(text_message("assistant", content));
+        retry.messages.push(text_message(
+            "user",
+            format!(
+                "上面的回复不符合要求的 JSON 格式：\n- {}\n请改正后重新输出，只输出 JSON，不要包含其他内容。",
+                errors.join("\n- ")
+            ),
+        ));
+    }
+}
+
+/// 流式对话补全的事件data流，带`response_format`时在结束时校验各个选项拼接后的内容
+pub async fn chat_completion_stream(
+    state: &AppState,
+    request: &ChatCompletionRequest,
+    scope: &ToolScope,
+) -> ApiResult<BoxStream<'static, String>> {
+    let format = output_format(request)?;
+    let events = tools::chat_completion_stream(state, request, scope).await?;
+    let Some(format) = format else {
+        return Ok(events);
+    };
+
+    struct Output {
+        events: BoxStream<'static, String>,
+        format: OutputFormat,
+        repair: bool,
+        /// 按选项序号拼接的内容，有工具调用的选项为`None`
+        contents: BTreeMap<u64, Option<String>>,
+        done: bool,
+    }
+
+    let output = Output {
+        events,
+        format,
+        repair: state.config().structured_output.repair,
+        contents: BTreeMap::new(),
+        done: false,
+    };
+
+    let events = stream::unfold(output, |mut output| async move {
+        if output.done {
+            return None;
+        }
+        let data = output.events.next().await?;
+        if data != "[DONE]" {
+            let chunk = serde_json::from_str::<Value>(&data).unwrap_or(Value::Null);
+            for choice in chunk["choices"].as_array().into_iter().flatten() {
+                let index = choice["index"].as_u64().unwrap_or(0);
+                let content = output.contents.entry(index).or_insert_with(|| Some(String::new()));
+                if choice["delta"]["tool_calls"].is_array() {
+                    *content = None;
+                }
+                if let (Some(content), Some(text)) = (content.as_mut(), choice["delta"]["content"].as_str()) {
+                    content.push_str(text);
+                }
+            }
+            return Some((data, output));
+        }
+
+        output.done = true;
+        for content in output.contents.values().flatten() {
+            if let Err(errors) = output.format.check(content, output.repair) {
+                let (_, body) = output.format.violation(errors).into_parts();
+                return Some((body.to_string(), output));
+            }
+        }
+        Some((data, output))
+    });
+    Ok(events.boxed())
+}
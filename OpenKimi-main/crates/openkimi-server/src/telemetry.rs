@@ -0,0 +1,424 @@
+//! 分布式追踪
+//!
+//! 启用`tracing`时每个HTTP请求是一个服务端span，检索、上下文压缩、对上游的每次尝试、工具调用和内容安全检查
+//! 等步骤是它的子span，以OTLP/HTTP（JSON编码）批量发送到`tracing.otlp_endpoint`。
+//! 请求头中的W3C `traceparent`作为父span并沿用其中的采样决定，发往上游模型的请求同样带上`traceparent`。
+//!
+//! 当前span保存在tokio的task-local中：[`Span::run`]在该span下运行一个future，其中创建的span都是它的子span；
+//! 没有当前span（未启用追踪、后台任务）时创建的span什么也不做。
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::task::{Context as TaskContext, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::extract::{MatchedPath, Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use futures_util::stream::{BoxStream, Stream, StreamExt};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+use crate::audit::{attribute, Pending};
+use crate::config::TracingConfig;
+use crate::error::ApiResult;
+use crate::AppState;
+
+/// 等待发送的span的上限，超出时丢弃
+const QUEUE_SIZE: usize = 4096;
+
+/// 一次最多发送的span数
+const EXPORT_BATCH: usize = 512;
+
+/// 攒批的最长等待时间
+const EXPORT_INTERVAL: Duration = Duration::from_secs(2);
+
+tokio::task_local! {
+    static CURRENT: Context;
+}
+
+/// OTLP的SpanKind
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Internal = 1,
+    Server = 2,
+    Client = 3,
+}
+
+/// 当前span在链路中的位置，未采样时仍然向下游传播
+#[derive(Debug, Clone)]
+struct Context {
+    tracer: Arc<Tracer>,
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    sampled: bool,
+}
+
+impl Context {
+    fn traceparent(&self) -> String {
+        format!("00-{}-{}-{:02x}", hex(&self.trace_id), hex(&self.span_id), u8::from(self.sampled))
+    }
+}
+
+/// 结束的span，等待发送
+#[derive(Debug)]
+struct SpanData {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent_span_id: Option<[u8; 8]>,
+    name: String,
+    kind: Kind,
+    start_ns: u128,
+    end_ns: u128,
+    attributes: Vec<Value>,
+    error: Option<String>,
+}
+
+impl SpanData {
+    fn to_otlp(&self) -> Value {
+        let mut span = json!({
+            "traceId": hex(&self.trace_id),
+            "spanId": hex(&self.span_id),
+            "name": self.name,
+            "kind": self.kind as u8,
+            "startTimeUnixNano": self.start_ns.to_string(),
+            "endTimeUnixNano": self.end_ns.to_string(),
+            "attributes": self.attributes,
+            "status": match &self.error {
+                Some(message) => json!({ "code": 2, "message": message }),
+                None => json!({ "code": 0 }),
+            },
+        });
+        if let Some(parent) = &self.parent_span_id {
+            span["parentSpanId"] = json!(hex(parent));
+        }
+        span
+    }
+}
+
+#[derive(Debug)]
+pub struct Tracer {
+    config: TracingConfig,
+    tx: mpsc::Sender<SpanData>,
+    pending: Pending,
+}
+
+impl Tracer {
+    /// 启动后台发送任务，需要在tokio运行时中调用
+    pub fn new(config: &TracingConfig) -> Result<Tracer, String> {
+        if !(0.0..=1.0).contains(&config.sample_ratio) {
+            return Err(format!("tracing.sample_ratio 应在 0 到 1 之间: {}", config.sample_ratio));
+        }
+        let http = reqwest::Client::builder()
+            .user_agent(concat!("openkimi-server/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .map_err(|e| format!("创建HTTP客户端失败: {}", e))?;
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        let pending = Pending::default();
+        tokio::spawn(export(http, config.clone(), rx, pending.clone()));
+        Ok(Tracer {
+            config: config.clone(),
+            tx,
+            pending,
+        })
+    }
+
+    /// 按采样比例决定，同一链路在各个服务中的决定一致
+    fn sample(&self, trace_id: &[u8; 16]) -> bool {
+        let mut low = [0u8; 8];
+        low.copy_from_slice(&trace_id[8..]);
+        self.config.sample_ratio >= 1.0 || (u64::from_be_bytes(low) as f64) < self.config.sample_ratio * u64::MAX as f64
+    }
+
+    fn finish(&self, data: SpanData) {
+        if !self.pending.send(&self.tx, data) {
+            eprintln!("⚠️ 追踪队列已满，丢弃一个span");
+        }
+    }
+
+    /// 等待队列中的span发送完，超过`timeout`时返回`false`
+    pub async fn flush(&self, timeout: Duration) -> bool {
+        self.pending.flush(timeout).await
+    }
+}
+
+#[derive(Debug)]
+struct Recording {
+    parent_span_id: Option<[u8; 8]>,
+    name: String,
+    kind: Kind,
+    start_ns: u128,
+    attributes: Mutex<Vec<Value>>,
+    error: Mutex<Option<String>>,
+}
+
+/// 一个span，被丢弃时结束并发送；不在链路中或未采样时什么也不记录
+#[derive(Debug)]
+pub struct Span {
+    context: Option<Context>,
+    recording: Option<Recording>,
+}
+
+impl Span {
+    /// 当前span的子span
+    pub fn new(name: impl Into<String>) -> Span {
+        Span::child(name.into(), Kind::Internal)
+    }
+
+    /// 对外部服务的调用，其`traceparent`应随请求发出
+    pub fn client(name: impl Into<String>) -> Span {
+        Span::child(name.into(), Kind::Client)
+    }
+
+    fn child(name: String, kind: Kind) -> Span {
+        let Ok(parent) = CURRENT.try_with(Context::clone) else {
+            return Span {
+                context: None,
+                recording: None,
+            };
+        };
+        let parent_span_id = Some(parent.span_id);
+        let context = Context {
+            span_id: random_id(),
+            ..parent
+        };
+        Span::start(context, parent_span_id, name, kind)
+    }
+
+    fn start(context: Context, parent_span_id: Option<[u8; 8]>, name: String, kind: Kind) -> Span {
+        let recording = context.sampled.then(|| Recording {
+            parent_span_id,
+            name,
+            kind,
+            start_ns: unix_ns(),
+            attributes: Mutex::new(Vec::new()),
+            error: Mutex::new(None),
+        });
+        Span {
+            context: Some(context),
+            recording,
+        }
+    }
+
+    /// 设置属性，字符串、数字和布尔值按OTLP的对应类型发送
+    pub fn set(&self, key: &str, value: impl Into<Value>) {
+        if let Some(recording) = &self.recording {
+            recording.attributes.lock().unwrap().push(attribute(key, value.into()));
+        }
+    }
+
+    /// 把span的状态标记为出错
+    pub fn error(&self, message: impl ToString) {
+        if let Some(recording) = &self.recording {
+            *recording.error.lock().unwrap() = Some(message.to_string());
+        }
+    }
+
+    /// 发往下游的`traceparent`请求头，不在链路中时为`None`
+    pub fn traceparent(&self) -> Option<String> {
+        self.context.as_ref().map(Context::traceparent)
+    }
+
+    /// 以该span为当前span运行`future`
+    pub async fn run<F: Future>(&self, future: F) -> F::Output {
+        match &self.context {
+            Some(context) => CURRENT.scope(context.clone(), future).await,
+            None => future.await,
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let (Some(context), Some(recording)) = (&self.context, self.recording.take()) else {
+            return;
+        };
+        context.tracer.finish(SpanData {
+            trace_id: context.trace_id,
+            span_id: context.span_id,
+            parent_span_id: recording.parent_span_id,
+            name: recording.name,
+            kind: recording.kind,
+            start_ns: recording.start_ns,
+            end_ns: unix_ns(),
+            attributes: recording.attributes.into_inner().unwrap(),
+            error: recording.error.into_inner().unwrap(),
+        });
+    }
+}
+
+/// 在名为`name`的子span中运行`future`，返回错误时标记span出错
+pub async fn span<T>(name: &str, future: impl Future<Output = ApiResult<T>>) -> ApiResult<T> {
+    let span = Span::new(name);
+    let result = span.run(future).await;
+    if let Err(err) = &result {
+        span.error(err);
+    }
+    result
+}
+
+/// 当前链路的trace id，不在链路中时为`None`
+pub fn trace_id() -> Option<String> {
+    CURRENT.try_with(|context| hex(&context.trace_id)).ok()
+}
+
+/// 包装流式响应的data流，在名为`name`的子span中读取，流结束或被丢弃时span结束
+///
+/// 响应头返回后服务端span已经结束，流式输出过程中的工具调用和上游请求记在这个span下。
+pub fn stream(name: &str, data: BoxStream<'static, String>) -> BoxStream<'static, String> {
+    let span = Span::new(name);
+    if span.context.is_none() {
+        return data;
+    }
+    Traced { inner: data, span }.boxed()
+}
+
+struct Traced {
+    inner: BoxStream<'static, String>,
+    span: Span,
+}
+
+impl Stream for Traced {
+    type Item = String;
+
+    fn poll_next(mut self: std::pin::Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<String>> {
+        let this = &mut *self;
+        match &this.span.context {
+            Some(context) => CURRENT.sync_scope(context.clone(), || this.inner.poll_next_unpin(cx)),
+            None => this.inner.poll_next_unpin(cx),
+        }
+    }
+}
+
+/// 解析`traceparent`请求头，返回trace id、父span id和是否采样
+fn parse_traceparent(value: &str) -> Option<([u8; 16], [u8; 8], bool)> {
+    let mut parts = value.trim().split('-');
+    let version = parts.next()?;
+    let (trace_id, span_id, flags) = (parts.next()?, parts.next()?, parts.next()?);
+    if version.len() != 2 || version == "ff" || (version == "00" && parts.next().is_some()) {
+        return None;
+    }
+    let trace_id: [u8; 16] = unhex(trace_id)?.try_into().ok()?;
+    let span_id: [u8; 8] = unhex(span_id)?.try_into().ok()?;
+    let flags = unhex(flags)?;
+    if trace_id == [0; 16] || span_id == [0; 8] || flags.len() != 1 {
+        return None;
+    }
+    Some((trace_id, span_id, flags[0] & 1 == 1))
+}
+
+/// 为每个请求创建服务端span的中间件，span名为方法加路由模板，如`POST /v1/chat/completions`
+pub async fn trace(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let Some(tracer) = state.tracer.clone() else {
+        return next.run(request).await;
+    };
+    let parent = request
+        .headers()
+        .get("traceparent")
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_traceparent);
+    let (trace_id, parent_span_id, sampled) = match parent {
+        Some((trace_id, span_id, sampled)) => (trace_id, Some(span_id), sampled),
+        None => {
+            let trace_id = random_trace_id();
+            (trace_id, None, tracer.sample(&trace_id))
+        }
+    };
+    let route = request.extensions().get::<MatchedPath>().map(|path| path.as_str().to_string());
+    let method = request.method().to_string();
+    let name = format!("{} {}", method, route.as_deref().unwrap_or(request.uri().path()));
+    let context = Context {
+        tracer,
+        trace_id,
+        span_id: random_id(),
+        sampled,
+    };
+    let span = Span::start(context, parent_span_id, name, Kind::Server);
+    span.set("http.request.method", method);
+    span.set("url.path", request.uri().path());
+    if let Some(route) = route {
+        span.set("http.route", route);
+    }
+    let response = span.run(next.run(request)).await;
+    let status = response.status();
+    span.set("http.response.status_code", status.as_u16());
+    if status.is_server_error() {
+        span.error(status);
+    }
+    response
+}
+
+/// OTLP追踪请求体
+fn otlp_payload(config: &TracingConfig, spans: &[SpanData]) -> Value {
+    let spans: Vec<Value> = spans.iter().map(SpanData::to_otlp).collect();
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    attribute("service.name", json!(config.service_name)),
+                    attribute("service.version", json!(env!("CARGO_PKG_VERSION"))),
+                ],
+            },
+            "scopeSpans": [{ "scope": { "name": "openkimi.telemetry" }, "spans": spans }],
+        }]
+    })
+}
+
+/// 攒够一批或等待`EXPORT_INTERVAL`后发送，失败时丢弃该批并打印警告
+async fn export(http: reqwest::Client, config: TracingConfig, mut rx: mpsc::Receiver<SpanData>, pending: Pending) {
+    while let Some(first) = rx.recv().await {
+        let mut batch = vec![first];
+        let deadline = tokio::time::Instant::now() + EXPORT_INTERVAL;
+        while batch.len() < EXPORT_BATCH {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some(span)) => batch.push(span),
+                _ => break,
+            }
+        }
+
+        let mut request = http.post(&config.otlp_endpoint).json(&otlp_payload(&config, &batch));
+        for (name, value) in &config.otlp_headers {
+            request = request.header(name, value);
+        }
+        match request.send().await {
+            Ok(response) if response.status().is_success() => {}
+            Ok(response) => eprintln!("⚠️ 发送追踪数据失败: {} 返回 {}", config.otlp_endpoint, response.status()),
+            Err(err) => eprintln!("⚠️ 发送追踪数据失败: {}", err),
+        }
+        pending.done(batch.len());
+    }
+}
+
+/// 全零的id无效，随机数生成失败时最后一位置1
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    let _ = getrandom::getrandom(&mut bytes);
+    bytes[N - 1] |= u8::from(bytes == [0; N]);
+    bytes
+}
+
+fn random_id() -> [u8; 8] {
+    random_bytes()
+}
+
+fn random_trace_id() -> [u8; 16] {
+    random_bytes()
+}
+
+fn unix_ns() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.bytes().all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f')) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok()).collect()
+}
@@ -0,0 +1,705 @@
+//! 工具调用
+//!
+//! 校验请求中OpenAI格式的`tools`和`tool_choice`，上游返回的工具调用（流式输出中为`tool_calls`增量）原样转发给客户端。
+//! 启用`tools`并配置了`tools.functions`时，这些服务端工具会附加到每个对话请求上：模型调用的全部是服务端工具时，
+//! 由本服务执行并把结果作为`tool`消息追加到对话中再次请求上游，直到模型给出回复或达到`tools.max_iterations`；
+//! 其中有客户端自己声明的工具时，工具调用照常返回给客户端执行。启用`search`时还会附加内置的`web_search`工具，
+//! 启用`interpreter`时附加在沙箱中运行代码的`run_code`工具，请求中的`session_id`决定它使用哪个会话的文件；
+//! 启用`plugins`时附加插件提供的工具，插件更新后下一个请求即使用新的工具列表；配置了`mcp.servers`时附加外部MCP服务器的工具。
+//!
+//! 需要用户确认的MCP工具（服务器的`approval`为`prompt`）被调用时，本轮工具调用照常返回给客户端。客户端把这条
+//! assistant消息放回对话末尾，并在下一个请求的`tool_approvals`中按调用id给出`true`（同意）或`false`（拒绝），
+//! 同意的调用由本服务执行，拒绝的调用以错误结果交给模型，然后继续对话。
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::future::join_all;
+use futures_util::stream::{self, BoxStream};
+use futures_util::StreamExt;
+use serde_json::{json, Map, Value};
+
+use crate::config::{ServerToolConfig, ToolsConfig};
+use crate::error::{ApiError, ApiResult};
+use crate::file_search::{self, FileSearch};
+use crate::interpreter::{self, Interpreter};
+use crate::mcp_client::McpClients;
+use crate::plugins::PluginRegistry;
+use crate::router::ModelRouter;
+use crate::safety::{Inbound, Safety, Source};
+use crate::search::{self, WebSearch};
+use crate::sse;
+use crate::telemetry::Span;
+use crate::types::{ChatCompletionRequest, ChatCompletionResponse, ChatMessage, MessageContent, Usage};
+use crate::workspace::Workspace;
+use crate::AppState;
+
+const MAX_NAME_LEN: usize = 64;
+
+/// 请求中指定代码解释器会话的字段，不转发给上游
+pub const SESSION_FIELD: &str = "session_id";
+
+/// 请求中对需要确认的工具调用的答复，不转发给上游
+pub const APPROVALS_FIELD: &str = "tool_approvals";
+
+/// 服务端工具执行时所在的工作区和会话，代码解释器按它隔离文件
+#[derive(Debug, Clone)]
+pub struct ToolScope {
+    pub workspace: Workspace,
+    /// 同一会话的请求共享代码解释器中的文件
+    pub session: Option<String>,
+    /// 为本次请求生成的id，未指定会话时按它隔离
+    pub request: String,
+    /// 用户对需要确认的工具调用的答复，按调用id索引
+    pub approvals: HashMap<String, bool>,
+}
+
+impl ToolScope {
+    pub fn new(workspace: Workspace, session: Option<String>) -> ApiResult<ToolScope> {
+        if let Some(session) = &session {
+            interpreter::check_session(session)?;
+        }
+        Ok(ToolScope {
+            workspace,
+            session,
+            request: interpreter::random_hex().map_err(ApiError::Internal)?,
+            approvals: HashMap::new(),
+        })
+    }
+
+    /// 取出请求中的`session_id`和`tool_approvals`
+    pub fn take(workspace: &Workspace, request: &mut ChatCompletionRequest) -> ApiResult<ToolScope> {
+        let session = match request.extra.remove(SESSION_FIELD) {
+            None | Some(Value::Null) => None,
+            Some(Value::String(session)) => Some(session),
+            Some(_) => return Err(ApiError::invalid_request("session_id 必须是字符串")),
+        };
+        let approvals = match request.extra.remove(APPROVALS_FIELD) {
+            None | Some(Value::Null) => HashMap::new(),
+            Some(Value::Object(approvals)) => approvals
+                .into_iter()
+                .map(|(id, approved)| approved.as_bool().map(|approved| (id, approved)))
+                .collect::<Option<_>>()
+                .ok_or_else(|| ApiError::invalid_request("tool_approvals 必须是调用 id 到布尔值的对象"))?,
+            Some(_) => return Err(ApiError::invalid_request("tool_approvals 必须是调用 id 到布尔值的对象")),
+        };
+        let mut scope = ToolScope::new(workspace.clone(), session)?;
+        scope.approvals = approvals;
+        Ok(scope)
+    }
+}
+
+/// 函数名只能由字母、数字、下划线和连字符组成，最长64个字符
+pub(crate) fn check_name(name: &str) -> Result<(), String> {
+    let valid = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if name.is_empty() || name.len() > MAX_NAME_LEN || !valid {
+        return Err(format!("函数名 {:?} 无效，只能包含字母、数字、_ 和 -，长度为 1 到 {}", name, MAX_NAME_LEN));
+    }
+    Ok(())
+}
+
+/// 参数必须是`type`为`object`的JSON Schema，`required`中的字段都要在`properties`中声明
+fn check_parameters(parameters: &Value) -> Result<(), String> {
+    let Some(schema) = parameters.as_object() else {
+        return Err("parameters 必须是 JSON Schema 对象".to_string());
+    };
+    if let Some(kind) = schema.get("type") {
+        if kind != "object" {
+            return Err("parameters 的 type 必须是 \"object\"".to_string());
+        }
+    }
+    let properties = match schema.get("properties") {
+        Some(Value::Object(properties)) => Some(properties),
+        Some(_) => return Err("parameters.properties 必须是对象".to_string()),
+        None => None,
+    };
+    if let Some((name, _)) = properties.into_iter().flatten().find(|(_, property)| !property.is_object()) {
+        return Err(format!("parameters.properties.{} 必须是 JSON Schema 对象", name));
+    }
+    match schema.get("required") {
+        None => Ok(()),
+        Some(Value::Array(required)) => {
+            for field in required {
+                let Some(field) = field.as_str() else {
+                    return Err("parameters.required 只能包含字符串".to_string());
+                };
+                if !properties.is_some_and(|properties| properties.contains_key(field)) {
+                    return Err(format!("parameters.required 中的 {} 没有在 properties 中声明", field));
+                }
+            }
+            Ok(())
+        }
+        Some(_) => Err("parameters.required 必须是数组".to_string()),
+    }
+}
+
+/// 校验一个工具定义，返回其函数名
+pub(crate) fn check_tool(tool: &Value) -> Result<&str, String> {
+    if tool.get("type").and_then(Value::as_str) != Some("function") {
+        return Err("type 必须是 \"function\"".to_string());
+    }
+    let Some(function) = tool.get("function").and_then(Value::as_object) else {
+        return Err("缺少 function".to_string());
+    };
+    let Some(name) = function.get("name").and_then(Value::as_str) else {
+        return Err("缺少 function.name".to_string());
+    };
+    check_name(name)?;
+    if function.get("description").is_some_and(|description| !description.is_string()) {
+        return Err(format!("函数 {} 的 description 必须是字符串", name));
+    }
+    if let Some(parameters) = function.get("parameters") {
+        check_parameters(parameters).map_err(|e| format!("函数 {}: {}", name, e))?;
+    }
+    Ok(name)
+}
+
+/// 校验请求中的`tools`、`tool_choice`以及对话里的工具调用消息
+pub fn validate(request: &ChatCompletionRequest, runner: Option<&ToolRunner>) -> ApiResult<()> {
+    let mut names = HashSet::new();
+    match request.extra.get("tools") {
+        None | Some(Value::Null) => {}
+        Some(Value::Array(tools)) => {
+            for (i, tool) in tools.iter().enumerate() {
+                let name = check_tool(tool).map_err(|e| ApiError::invalid_request(format!("tools[{}]: {}", i, e)))?;
+                if runner.is_some_and(|runner| runner.contains(name)) {
+                    return Err(ApiError::invalid_request(format!("tools[{}]: 函数 {} 与服务端工具重名", i, name)));
+                }
+                if !names.insert(name) {
+                    return Err(ApiError::invalid_request(format!("tools[{}]: 函数 {} 重复声明", i, name)));
+                }
+            }
+        }
+        Some(_) => return Err(ApiError::invalid_request("tools 必须是数组")),
+    }
+
+    match request.extra.get("tool_choice") {
+        None | Some(Value::Null) => {}
+        Some(Value::String(choice)) if choice == "none" => {}
+        Some(Value::String(choice)) if choice == "auto" || choice == "required" => {
+            if names.is_empty() && runner.is_none_or(|runner| runner.is_empty()) {
+                return Err(ApiError::invalid_request(format!("tool_choice 为 {:?} 时必须提供 tools", choice)));
+            }
+        }
+        Some(choice @ Value::Object(_)) => {
+            let name = (choice["type"] == "function").then(|| choice["function"]["name"].as_str()).flatten();
+            let Some(name) = name else {
+                let expected = r#"{"type":"function","function":{"name":...}}"#;
+                return Err(ApiError::invalid_request(format!("tool_choice 对象必须是 {}", expected)));
+            };
+            if !names.contains(name) && !runner.is_some_and(|runner| runner.contains(name)) {
+                return Err(ApiError::invalid_request(format!("tool_choice 指定的函数 {} 不在 tools 中", name)));
+            }
+        }
+        Some(_) => {
+            return Err(ApiError::invalid_request("tool_choice 必须是 \"none\"、\"auto\"、\"required\" 或函数对象"))
+        }
+    }
+
+    for (i, message) in request.messages.iter().enumerate() {
+        if message.role == "tool" && !message.extra.get("tool_call_id").is_some_and(Value::is_string) {
+            return Err(ApiError::invalid_request(format!("messages[{}]: tool 消息缺少 tool_call_id", i)));
+        }
+        match message.extra.get("tool_calls") {
+            None | Some(Value::Null) => {}
+            Some(Value::Array(calls)) if message.role == "assistant" => {
+                let valid = |call: &Value| call["id"].is_string() && call["function"]["name"].is_string();
+                if !calls.iter().all(valid) {
+                    return Err(ApiError::invalid_request(format!(
+                        "messages[{}]: tool_calls 中的每一项都需要 id 和 function.name",
+                        i
+                    )));
+                }
+            }
+            Some(_) => {
+                return Err(ApiError::invalid_request(format!(
+                    "messages[{}]: tool_calls 必须是 assistant 消息中的数组",
+                    i
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 模型发出的一次工具调用
+#[derive(Debug, Clone, Default)]
+struct ToolCall {
+    id: String,
+    name: String,
+    arguments: String,
+}
+
+impl ToolCall {
+    fn to_value(&self) -> Value {
+        json!({
+            "id": self.id,
+            "type": "function",
+            "function": { "name": self.name, "arguments": self.arguments },
+        })
+    }
+}
+
+/// 从assistant消息中取出工具调用
+fn tool_calls(message: &ChatMessage) -> Vec<ToolCall> {
+    let Some(calls) = message.extra.get("tool_calls").and_then(Value::as_array) else {
+        return Vec::new();
+    };
+    calls
+        .iter()
+        .map(|call| ToolCall {
+            id: call["id"].as_str().unwrap_or_default().to_string(),
+            name: call["function"]["name"].as_str().unwrap_or_default().to_string(),
+            arguments: call["function"]["arguments"].as_str().unwrap_or_default().to_string(),
+        })
+        .collect()
+}
+
+/// 截断到不超过`max_bytes`字节的字符边界
+fn truncate(mut text: String, max_bytes: usize) -> String {
+    if text.len() > max_bytes {
+        let mut end = max_bytes;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+    }
+    text
+}
+
+/// 服务端工具的注册表和执行器
+#[derive(Debug)]
+pub struct ToolRunner {
+    config: ToolsConfig,
+    http: reqwest::Client,
+    /// 附加到请求上的工具定义
+    definitions: Vec<Value>,
+    /// 内置的`web_search`工具
+    search: Option<Arc<WebSearch>>,
+    /// 内置的`run_code`工具
+    interpreter: Option<Arc<Interpreter>>,
+    /// 内置的`find_files`工具
+    files: Option<Arc<FileSearch>>,
+    plugins: Option<Arc<PluginRegistry>>,
+    /// 外部MCP服务器提供的工具
+    mcp: Option<Arc<McpClients>>,
+    /// 检查工具结果中的提示注入
+    safety: Option<Arc<Safety>>,
+}
+
+impl ToolRunner {
+    pub fn new(
+        config: &ToolsConfig,
+        search: Option<Arc<WebSearch>>,
+        interpreter: Option<Arc<Interpreter>>,
+        files: Option<Arc<FileSearch>>,
+        plugins: Option<Arc<PluginRegistry>>,
+        mcp: Option<Arc<McpClients>>,
+        safety: Option<Arc<Safety>>,
+    ) -> Result<ToolRunner, String> {
+        let mut definitions = Vec::new();
+        if search.is_some() {
+            if config.functions.contains_key(search::TOOL_NAME) {
+                return Err(format!("tools.functions.{} 与内置的网页搜索工具重名", search::TOOL_NAME));
+            }
+            definitions.push(WebSearch::tool_definition());
+        }
+        if let Some(interpreter) = &interpreter {
+            if config.functions.contains_key(interpreter::TOOL_NAME) {
+                return Err(format!("tools.functions.{} 与内置的代码解释器工具重名", interpreter::TOOL_NAME));
+            }
+            definitions.push(interpreter.tool_definition());
+        }
+        if let Some(files) = &files {
+            if config.functions.contains_key(file_search::TOOL_NAME) {
+                return Err(format!("tools.functions.{} 与内置的文件搜索工具重名", file_search::TOOL_NAME));
+            }
+            definitions.push(files.tool_definition());
+        }
+        for (name, tool) in &config.functions {
+            let mut function = json!({ "name": name, "parameters": tool.parameters });
+            if let Some(description) = &tool.description {
+                function["description"] = Value::from(description.as_str());
+            }
+            let definition = json!({ "type": "function", "function": function });
+            check_tool(&definition).map_err(|e| format!("tools.functions.{}: {}", name, e))?;
+            if tool.url.is_empty() {
+                return Err(format!("tools.functions.{} 缺少 url", name));
+            }
+            definitions.push(definition);
+        }
+        let http = reqwest::Client::builder()
+            .build()
+            .map_err(|e| format!("无法创建 HTTP 客户端: {}", e))?;
+        Ok(ToolRunner {
+            config: config.clone(),
+            http,
+            definitions,
+            search,
+            interpreter,
+            files,
+            plugins,
+            mcp,
+            safety,
+        })
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.config.functions.contains_key(name)
+            || (self.search.is_some() && name == search::TOOL_NAME)
+            || (self.interpreter.is_some() && name == interpreter::TOOL_NAME)
+            || (self.files.is_some() && name == file_search::TOOL_NAME)
+            || self.plugins.as_ref().is_some_and(|plugins| plugins.contains(name))
+            || self.mcp.as_ref().is_some_and(|mcp| mcp.contains(name))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.config.functions.is_empty()
+            && self.search.is_none()
+            && self.interpreter.is_none()
+            && self.files.is_none()
+            && self.plugins.as_ref().is_none_or(|plugins| plugins.is_empty())
+            && self.mcp.as_ref().is_none_or(|mcp| mcp.is_empty())
+    }
+
+    /// 是否要为这个请求附加服务端工具，`tool_choice`为`none`时不附加
+    fn applies(&self, request: &ChatCompletionRequest) -> bool {
+        !self.is_empty() && request.extra.get("tool_choice").is_none_or(|choice| choice != "none")
+    }
+
+    /// 所有服务端工具的定义，包括内置工具、插件和外部MCP服务器提供的工具
+    pub fn definitions(&self) -> Vec<Value> {
+        let mut definitions = self.definitions.clone();
+        definitions.extend(self.plugins.iter().flat_map(|plugins| plugins.definitions()));
+        definitions.extend(self.mcp.iter().flat_map(|mcp| mcp.definitions()));
+        definitions
+    }
+
+    /// 调用这个工具前是否需要用户确认
+    pub fn requires_approval(&self, name: &str) -> bool {
+        self.mcp.as_ref().is_some_and(|mcp| mcp.requires_approval(name))
+    }
+
+    fn attach(&self, request: &mut ChatCompletionRequest) {
+        let tools = request.extra.entry("tools").or_insert_with(|| Value::Array(Vec::new()));
+        if !tools.is_array() {
+            *tools = Value::Array(Vec::new());
+        }
+        if let Some(tools) = tools.as_array_mut() {
+            tools.extend(self.definitions());
+        }
+    }
+
+    /// 本轮工具调用是否由服务端执行，其中有需要用户确认的调用时交给客户端
+    fn handles(&self, calls: &[ToolCall], iteration: u32) -> bool {
+        !calls.is_empty()
+            && iteration < self.config.max_iterations
+            && calls.iter().all(|call| self.contains(&call.name) && !self.requires_approval(&call.name))
+    }
+
+    /// 把参数POST到配置的地址，响应体即为结果
+    async fn post(&self, tool: &ServerToolConfig, arguments: &Value) -> Result<String, String> {
+        let mut request = self
+            .http
+            .post(&tool.url)
+            .timeout(Duration::from_secs(tool.timeout_seconds))
+            .json(arguments);
+        for (name, value) in &tool.headers {
+            request = request.header(name, value);
+        }
+        match request.send().await {
+            Ok(response) => {
+                let status = response.status();
+                match response.text().await {
+                    Ok(body) if status.is_success() => Ok(body),
+                    Ok(body) => Err(format!("工具返回 {}: {}", status, body)),
+                    Err(e) => Err(format!("读取工具响应失败: {}", e)),
+                }
+            }
+            Err(e) => Err(format!("调用工具失败: {}", e)),
+        }
+    }
+
+    /// 按名称执行一个服务端工具，结果截断到`tools.max_result_bytes`
+    pub async fn execute(&self, name: &str, arguments: &Value, scope: &ToolScope) -> Result<String, String> {
+        let result = if let Some(tool) = self.config.functions.get(name) {
+            self.post(tool, arguments).await
+        } else if let Some(search) = self.search.as_ref().filter(|_| name == search::TOOL_NAME) {
+            search.call_tool(arguments).await
+        } else if let Some(interpreter) = self.interpreter.as_ref().filter(|_| name == interpreter::TOOL_NAME) {
+            interpreter.call_tool(scope, arguments).await
+        } else if let Some(files) = self.files.as_ref().filter(|_| name == file_search::TOOL_NAME) {
+            files.call_tool(arguments).await
+        } else if let Some(tool) = self.plugins.as_ref().and_then(|plugins| plugins.tool(name)) {
+            tool.execute(arguments).await
+        } else if let Some(mcp) = self.mcp.as_ref().filter(|mcp| mcp.contains(name)) {
+            mcp.call(name, arguments).await
+        } else {
+            Err(format!("未知的工具 {}", name))
+        };
+        result.map(|output| truncate(output, self.config.max_result_bytes))
+    }
+
+    /// 执行一个工具，失败时把错误作为结果交给模型
+    async fn call(&self, call: &ToolCall, scope: &ToolScope) -> String {
+        let arguments = if call.arguments.trim().is_empty() {
+            json!({})
+        } else {
+            match serde_json::from_str::<Value>(&call.arguments) {
+                Ok(arguments) => arguments,
+                Err(e) => return json!({ "error": format!("参数不是有效的 JSON: {}", e) }).to_string(),
+            }
+        };
+
+        let span = Span::new("tool.call");
+        span.set("gen_ai.tool.name", call.name.as_str());
+        let result = match span.run(self.execute(&call.name, &arguments, scope)).await {
+            Ok(result) => result,
+            Err(message) => {
+                span.error(&message);
+                eprintln!("⚠️ 工具 {} 执行失败: {}", call.name, message);
+                return truncate(json!({ "error": message }).to_string(), self.config.max_result_bytes);
+            }
+        };
+        let Some(safety) = &self.safety else {
+            return result;
+        };
+        match safety.screen_inbound(&scope.workspace, Source::Tool, &call.name, &result) {
+            Inbound::Pass => result,
+            Inbound::Annotated(text) => text,
+            Inbound::Blocked => json!({ "error": "工具输出疑似包含提示注入，已被拦截" }).to_string(),
+        }
+    }
+
+    /// 并发执行本轮的所有工具调用，返回依次对应的`tool`消息
+    async fn run(&self, calls: &[ToolCall], scope: &ToolScope) -> Vec<ChatMessage> {
+        let results = join_all(calls.iter().map(|call| self.call(call, scope))).await;
+        calls.iter().zip(results).map(|(call, result)| tool_message(&call.id, result)).collect()
+    }
+
+    /// 对话以返回给客户端确认的工具调用结尾时，按`tool_approvals`执行同意的调用，拒绝的调用以错误作为结果
+    ///
+    /// 客户端已经给出结果的调用和客户端自己的工具不受影响。
+    async fn resolve_approvals(&self, request: &mut ChatCompletionRequest, scope: &ToolScope) {
+        if scope.approvals.is_empty() {
+            return;
+        }
+        let answered = request.messages.iter().rev().take_while(|message| message.role == "tool").count();
+        let Some(assistant) = request.messages.iter().rev().nth(answered) else {
+            return;
+        };
+        if assistant.role != "assistant" {
+            return;
+        }
+        let answered: HashSet<&str> = request.messages[request.messages.len() - answered..]
+            .iter()
+            .filter_map(|message| message.extra.get("tool_call_id").and_then(Value::as_str))
+            .collect();
+        let calls: Vec<ToolCall> = tool_calls(assistant)
+            .into_iter()
+            .filter(|call| !answered.contains(call.id.as_str()) && self.contains(&call.name))
+            .collect();
+
+        let results = join_all(calls.iter().map(|call| async move {
+            if self.requires_approval(&call.name) && scope.approvals.get(&call.id) != Some(&true) {
+                json!({ "error": "用户拒绝了这次工具调用" }).to_string()
+            } else {
+                self.call(call, scope).await
+            }
+        }))
+        .await;
+        let messages: Vec<ChatMessage> =
+            calls.iter().zip(results).map(|(call, result)| tool_message(&call.id, result)).collect();
+        request.messages.extend(messages);
+    }
+}
+
+/// 一次工具调用的结果消息
+fn tool_message(id: &str, content: String) -> ChatMessage {
+    let mut extra = Map::new();
+    extra.insert("tool_call_id".to_string(), Value::from(id));
+    ChatMessage {
+        role: "tool".to_string(),
+        content: Some(MessageContent::Text(content)),
+        name: None,
+        extra,
+    }
+}
+
+/// 累加各轮的用量，任一轮没有用量时以有的为准
+pub fn add_usage(total: Option<Usage>, usage: Option<&Usage>) -> Option<Usage> {
+    match (total, usage) {
+        (Some(total), Some(usage)) => Some(Usage {
+            prompt_tokens: total.prompt_tokens + usage.prompt_tokens,
+            completion_tokens: total.completion_tokens + usage.completion_tokens,
+            total_tokens: total.total_tokens + usage.total_tokens,
+        }),
+        (total, usage) => total.or_else(|| usage.cloned()),
+    }
+}
+
+/// 非流式对话补全，需要时执行服务端工具，返回的用量是各轮之和
+pub async fn chat_completion(
+    state: &AppState,
+    request: &ChatCompletionRequest,
+    scope: &ToolScope,
+) -> ApiResult<ChatCompletionResponse> {
+    let Some(runner) = state.tools.as_deref().filter(|runner| runner.applies(request)) else {
+        return state.models().chat_completion(request).await;
+    };
+    let mut request = request.clone();
+    runner.attach(&mut request);
+    runner.resolve_approvals(&mut request, scope).await;
+
+    let mut usage = None;
+    let mut iteration = 0;
+    loop {
+        let mut response = state.models().chat_completion(&request).await?;
+        usage = add_usage(usage, response.usage.as_ref());
+        let message = response.choices.first().map(|choice| choice.message.clone());
+        let calls = message.as_ref().map(tool_calls).unwrap_or_default();
+        if !runner.handles(&calls, iteration) {
+            response.usage = usage;
+            return Ok(response);
+        }
+        request.messages.extend(message);
+        request.messages.extend(runner.run(&calls, scope).await);
+        iteration += 1;
+    }
+}
+
+/// 流式输出中按`index`拼接的工具调用
+#[derive(Default)]
+struct StreamedCalls {
+    calls: Vec<ToolCall>,
+}
+
+impl StreamedCalls {
+    fn feed(&mut self, deltas: &Value) {
+        for (position, delta) in deltas.as_array().into_iter().flatten().enumerate() {
+            let index = delta["index"].as_u64().map_or(position, |index| index as usize);
+            if index >= self.calls.len() {
+                self.calls.resize_with(index + 1, ToolCall::default);
+            }
+            let call = &mut self.calls[index];
+            if let Some(id) = delta["id"].as_str() {
+                call.id = id.to_string();
+            }
+            if let Some(name) = delta["function"]["name"].as_str() {
+                call.name.push_str(name);
+            }
+            if let Some(arguments) = delta["function"]["arguments"].as_str() {
+                call.arguments.push_str(arguments);
+            }
+        }
+    }
+}
+
+/// 流式对话补全的事件data流
+///
+/// 服务端执行工具时，中间各轮的内容和`tool_calls`增量照常转发，但结束分块和`[DONE]`被省略，
+/// 客户端看到的是一个连续的流，最后以一个`[DONE]`结束。第一轮上游请求失败时直接返回错误。
+pub async fn chat_completion_stream(
+    state: &AppState,
+    request: &ChatCompletionRequest,
+    scope: &ToolScope,
+) -> ApiResult<BoxStream<'static, String>> {
+    let Some(runner) = state.tools.as_ref().filter(|runner| runner.applies(request)) else {
+        return Ok(sse::data_stream(state.models().chat_completion_stream(request).await?));
+    };
+    let mut request = request.clone();
+    runner.attach(&mut request);
+    runner.resolve_approvals(&mut request, scope).await;
+    let upstream = state.models().chat_completion_stream(&request).await?;
+
+    struct Round {
+        models: Arc<ModelRouter>,
+        runner: Arc<ToolRunner>,
+        scope: ToolScope,
+        request: ChatCompletionRequest,
+        events: BoxStream<'static, String>,
+        pending: VecDeque<String>,
+        calls: StreamedCalls,
+        content: String,
+        /// 有工具调用时暂存的结束分块，本轮由客户端处理时才转发
+        finish: Option<String>,
+        iteration: u32,
+        done: bool,
+    }
+
+    let round = Round {
+        models: state.models(),
+        runner: Arc::clone(runner),
+        scope: scope.clone(),
+        request,
+        events: sse::data_stream(upstream),
+        pending: VecDeque::new(),
+        calls: StreamedCalls::default(),
+        content: String::new(),
+        finish: None,
+        iteration: 0,
+        done: false,
+    };
+
+    let events = stream::unfold(round, |mut round| async move {
+        loop {
+            if let Some(data) = round.pending.pop_front() {
+                return Some((data, round));
+            }
+            if round.done {
+                return None;
+            }
+            match round.events.next().await {
+                Some(data) if data != "[DONE]" => {
+                    let chunk = serde_json::from_str::<Value>(&data).unwrap_or(Value::Null);
+                    if chunk.get("error").is_some() {
+                        round.done = true;
+                    }
+                    let choice = &chunk["choices"][0];
+                    if let Some(content) = choice["delta"]["content"].as_str() {
+                        round.content.push_str(content);
+                    }
+                    round.calls.feed(&choice["delta"]["tool_calls"]);
+                    if choice["finish_reason"].is_string() && !round.calls.calls.is_empty() {
+                        round.finish = Some(data);
+                    } else {
+                        round.pending.push_back(data);
+                    }
+                }
+                _ => {
+                    let calls = std::mem::take(&mut round.calls).calls;
+                    let finish = round.finish.take();
+                    if !round.runner.handles(&calls, round.iteration) {
+                        round.pending.extend(finish);
+                        round.pending.push_back("[DONE]".to_string());
+                        round.done = true;
+                        continue;
+                    }
+
+                    let mut extra = Map::new();
+                    extra.insert("tool_calls".to_string(), calls.iter().map(ToolCall::to_value).collect());
+                    let content = std::mem::take(&mut round.content);
+                    round.request.messages.push(ChatMessage {
+                        role: "assistant".to_string(),
+                        content: (!content.is_empty()).then_some(MessageContent::Text(content)),
+                        name: None,
+                        extra,
+                    });
+                    let results = round.runner.run(&calls, &round.scope).await;
+                    round.request.messages.extend(results);
+                    round.iteration += 1;
+                    match round.models.chat_completion_stream(&round.request).await {
+                        Ok(upstream) => round.events = sse::data_stream(upstream),
+                        Err(err) => {
+                            let (_, body) = err.into_parts();
+                            round.pending.push_back(body.to_string());
+                            round.done = true;
+                        }
+                    }
+                }
+            }
+        }
+    });
+    Ok(events.boxed())
+}
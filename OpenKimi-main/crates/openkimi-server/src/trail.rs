@@ -0,0 +1,551 @@
+//! 安全事件日志
+//!
+//! 与记录每个请求的[`crate::audit`]不同，这里只记录与安全相关的事件：认证失败、上游密钥和用户令牌的创建与吊销、
+//! 配额的调整、配置的重新加载、数据的导出和删除。事件逐条追加到`audit_trail.path`指定的JSON Lines文件，
+//! 每条记录带有前一条记录的摘要和自身的SHA-256摘要，形成哈希链，修改或删除其中任何一条都会在校验时发现。
+//! 配置了`audit_trail.syslog`时同时以RFC 5424格式通过UDP发送到syslog。
+//! `openkimi-server users`命令修改用户时同样追加到这个文件，追加前重新读取文件末尾的记录，哈希链保持连续。
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::UdpSocket;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use axum::body::Body;
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::{Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use ring::digest::{digest, SHA256};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::audit::{rfc3339, unix_ms};
+use crate::auth::Principal;
+use crate::config::AuditTrailConfig;
+use crate::error::ApiError;
+use crate::ratelimit;
+use crate::workspace::Workspace;
+use crate::AppState;
+
+/// 第一条记录的`prev`
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// 查找最后一条记录时从文件末尾读取的字节数，不够时读取整个文件
+const TAIL_BYTES: u64 = 64 * 1024;
+
+/// 读取请求体和响应体的上限，只有需要记录内容的接口才读取
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// syslog的facility，`authpriv`
+const SYSLOG_FACILITY: u8 = 10;
+
+/// 记录在日志中的请求体不包含这些字段
+const SECRET_FIELDS: &[&str] = &["secret", "token", "api_key"];
+
+/// 需要记录的接口
+struct Rule {
+    method: &'static str,
+    /// 路由模板，与`MatchedPath`相同
+    route: &'static str,
+    event: &'static str,
+    /// 记录去掉`SECRET_FIELDS`后的请求体
+    request: bool,
+    /// 从响应中取新建资源的`id`或`name`
+    created: bool,
+}
+
+const fn rule(method: &'static str, route: &'static str, event: &'static str) -> Rule {
+    Rule {
+        method,
+        route,
+        event,
+        request: false,
+        created: false,
+    }
+}
+
+const fn with_request(rule: Rule) -> Rule {
+    Rule { request: true, ..rule }
+}
+
+const fn with_created(rule: Rule) -> Rule {
+    Rule { created: true, ..rule }
+}
+
+/// 成功后记录的接口；其余`/admin`下的修改记为`admin.changed`，`/v1`下的删除记为`data.deleted`
+const RULES: &[Rule] = &[
+    with_created(with_request(rule("POST", "/admin/keys", "key.created"))),
+    rule("DELETE", "/admin/keys/{id}", "key.deleted"),
+    rule("POST", "/admin/keys/{id}/enable", "key.enabled"),
+    rule("POST", "/admin/keys/{id}/disable", "key.disabled"),
+    rule("POST", "/admin/keys/{id}/rotate", "key.rotated"),
+    with_created(with_request(rule("POST", "/admin/users", "user.created"))),
+    with_request(rule("PATCH", "/admin/users/{name}", "user.updated")),
+    rule("DELETE", "/admin/users/{name}", "user.deleted"),
+    with_created(with_request(rule("POST", "/admin/users/{name}/tokens", "token.issued"))),
+    rule("DELETE", "/admin/users/{name}/tokens/{id}", "token.revoked"),
+    with_request(rule("PUT", "/admin/quotas/{holder}/{name}", "quota.adjusted")),
+    rule("DELETE", "/admin/quotas/{holder}/{name}", "quota.restored"),
+    rule("POST", "/admin/quotas/{holder}/{name}/reset", "quota.reset"),
+    rule("GET", "/admin/usage", "usage.exported"),
+    rule("GET", "/admin/users/{name}/usage", "usage.exported"),
+    rule("GET", "/admin/audit/events", "audit.exported"),
+    rule("DELETE", "/admin/cache", "cache.invalidated"),
+    rule("GET", "/v1/sessions/{id}/export", "data.exported"),
+];
+
+fn sha256_hex(content: &str) -> String {
+    digest(&SHA256, content.as_bytes()).as_ref().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// 记录的摘要：前一条记录的摘要加上不含`hash`字段的记录
+fn entry_hash(prev: &str, entry: &Map<String, Value>) -> String {
+    sha256_hex(&format!("{}\n{}", prev, Value::Object(entry.clone())))
+}
+
+/// 最后一条记录的位置，用于发现其他进程追加的记录
+#[derive(Debug)]
+struct Tail {
+    len: u64,
+    seq: u64,
+    hash: String,
+}
+
+/// 文件中最后一条记录的序号和摘要，空文件为`(0, GENESIS)`
+fn read_tail(file: &mut File) -> std::io::Result<(u64, String)> {
+    let len = file.metadata()?.len();
+    let start = len.saturating_sub(TAIL_BYTES);
+    file.seek(SeekFrom::Start(start))?;
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+    // 从中间开始读时第一行可能不完整
+    let complete = match start {
+        0 => content.as_str(),
+        _ => content.split_once('\n').map_or("", |(_, rest)| rest),
+    };
+    let last = match complete.lines().rev().find(|line| !line.trim().is_empty()) {
+        Some(line) => line.to_string(),
+        None if start > 0 => {
+            // 最后一条记录比读取的长度还长
+            file.seek(SeekFrom::Start(0))?;
+            content.clear();
+            file.read_to_string(&mut content)?;
+            content.lines().rev().find(|line| !line.trim().is_empty()).unwrap_or_default().to_string()
+        }
+        None => return Ok((0, GENESIS.to_string())),
+    };
+    let entry: Value = serde_json::from_str(&last)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("最后一条记录无法解析: {}", e)))?;
+    let seq = entry.get("seq").and_then(Value::as_u64).unwrap_or_default();
+    let hash = entry.get("hash").and_then(Value::as_str).unwrap_or(GENESIS).to_string();
+    Ok((seq, hash))
+}
+
+/// 哈希链的校验结果
+#[derive(Debug, Clone, Serialize)]
+pub struct Verification {
+    pub valid: bool,
+    /// 校验过的记录数
+    pub entries: u64,
+    /// 最后一条有效记录的摘要，可以另外保存，用来发现末尾的记录被整体删除
+    pub last_hash: String,
+    /// 第一条不一致的记录所在的行号
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub broken_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// 查询条件，都为`None`时返回全部记录
+#[derive(Debug, Clone, Default)]
+pub struct TrailQuery {
+    /// 起止日期，`YYYY-MM-DD`，包含两端
+    pub from: Option<String>,
+    pub to: Option<String>,
+    /// 事件名或以`.`结尾的前缀，如`key.`
+    pub event: Option<String>,
+    pub actor: Option<String>,
+    /// 只返回最后的若干条
+    pub limit: Option<usize>,
+}
+
+impl TrailQuery {
+    fn matches(&self, entry: &Value) -> bool {
+        let field = |name: &str| entry.get(name).and_then(Value::as_str).unwrap_or_default();
+        let day = field("time").get(..10).unwrap_or_default();
+        self.from.as_deref().is_none_or(|from| day >= from)
+            && self.to.as_deref().is_none_or(|to| day <= to)
+            && self.event.as_deref().is_none_or(|event| match event.ends_with('.') {
+                true => field("event").starts_with(event),
+                false => field("event") == event,
+            })
+            && self.actor.as_deref().is_none_or(|actor| field("actor") == actor)
+    }
+}
+
+#[derive(Debug)]
+pub struct AuditTrail {
+    path: PathBuf,
+    syslog: Option<String>,
+    socket: Option<UdpSocket>,
+    tail: Mutex<Option<Tail>>,
+}
+
+impl AuditTrail {
+    pub fn open(config: &AuditTrailConfig) -> Result<AuditTrail, String> {
+        let path = config.path.clone();
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).map_err(|e| format!("创建目录 {} 失败: {}", parent.display(), e))?;
+        }
+        let socket = match &config.syslog {
+            Some(address) => {
+                let local = if address.starts_with('[') { "[::]:0" } else { "0.0.0.0:0" };
+                let socket = UdpSocket::bind(local).map_err(|e| format!("无法创建 syslog 套接字: {}", e))?;
+                Some(socket)
+            }
+            None => None,
+        };
+        let trail = AuditTrail {
+            path,
+            syslog: config.syslog.clone(),
+            socket,
+            tail: Mutex::new(None),
+        };
+        // 启动时确认文件可写，最后一条记录无法解析时拒绝启动，避免在损坏的链上继续追加
+        let mut file = trail.open_file().map_err(|e| format!("打开安全事件日志 {} 失败: {}", trail.path.display(), e))?;
+        read_tail(&mut file).map_err(|e| format!("读取安全事件日志 {} 失败: {}", trail.path.display(), e))?;
+        Ok(trail)
+    }
+
+    fn open_file(&self) -> std::io::Result<File> {
+        let mut options = OpenOptions::new();
+        options.read(true).append(true).create(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options.open(&self.path)
+    }
+
+    /// 追加一条事件；`fields`为JSON对象，其中的字段与`seq`、`time`等一起写入记录。写入失败时只打印警告
+    pub fn append(&self, event: &str, actor: Option<&str>, fields: Value) {
+        if let Err(err) = self.try_append(event, actor, fields) {
+            eprintln!("⚠️ 写入安全事件日志失败（{}）: {}", event, err);
+        }
+    }
+
+    fn try_append(&self, event: &str, actor: Option<&str>, fields: Value) -> std::io::Result<()> {
+        let mut tail = self.tail.lock().unwrap_or_else(|e| e.into_inner());
+        let mut file = self.open_file()?;
+        let len = file.metadata()?.len();
+        // 文件长度与上次写入后不同时，其他进程追加过记录
+        let (seq, prev) = match tail.as_ref() {
+            Some(tail) if tail.len == len => (tail.seq, tail.hash.clone()),
+            _ => read_tail(&mut file)?,
+        };
+
+        let time_ms = unix_ms();
+        let mut entry = Map::new();
+        entry.insert("seq".to_string(), json!(seq + 1));
+        entry.insert("time".to_string(), json!(rfc3339(time_ms)));
+        entry.insert("event".to_string(), json!(event));
+        entry.insert("actor".to_string(), json!(actor));
+        if let Value::Object(fields) = fields {
+            entry.extend(fields);
+        }
+        entry.insert("prev".to_string(), json!(prev));
+        let hash = entry_hash(&prev, &entry);
+        entry.insert("hash".to_string(), json!(hash));
+        let line = Value::Object(entry).to_string();
+
+        // 整行一次写入，追加模式下不会与其他进程的写入交错
+        file.write_all(format!("{}\n", line).as_bytes())?;
+        *tail = Some(Tail {
+            len: len + line.len() as u64 + 1,
+            seq: seq + 1,
+            hash,
+        });
+        drop(tail);
+        self.send_syslog(event, time_ms, &line);
+        Ok(())
+    }
+
+    /// RFC 5424格式，拒绝和失败类的事件为warning，其余为notice
+    fn send_syslog(&self, event: &str, time_ms: u64, line: &str) {
+        let (Some(socket), Some(address)) = (&self.socket, &self.syslog) else {
+            return;
+        };
+        let severity = if event.ends_with(".failed") || event.ends_with(".denied") { 4 } else { 5 };
+        let host = std::env::var("HOSTNAME").ok().filter(|host| !host.is_empty()).unwrap_or_else(|| "-".to_string());
+        let message = format!(
+            "<{}>1 {} {} openkimi-server {} {} - {}",
+            SYSLOG_FACILITY * 8 + severity,
+            rfc3339(time_ms),
+            host,
+            std::process::id(),
+            event,
+            line
+        );
+        if let Err(err) = socket.send_to(message.as_bytes(), address.as_str()) {
+            eprintln!("⚠️ 发送 syslog 到 {} 失败: {}", address, err);
+        }
+    }
+
+    fn lines(&self) -> Result<Vec<String>, String> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(format!("读取安全事件日志失败: {}", err)),
+        };
+        BufReader::new(file)
+            .lines()
+            .filter(|line| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("读取安全事件日志失败: {}", e))
+    }
+
+    /// 符合条件的记录，保持写入顺序
+    pub fn query(&self, query: &TrailQuery) -> Result<Vec<String>, String> {
+        let mut matched: Vec<String> = self
+            .lines()?
+            .into_iter()
+            .filter(|line| serde_json::from_str::<Value>(line).is_ok_and(|entry| query.matches(&entry)))
+            .collect();
+        if let Some(limit) = query.limit {
+            matched.drain(..matched.len().saturating_sub(limit));
+        }
+        Ok(matched)
+    }
+
+    /// 从第一条记录起逐条校验序号、`prev`和摘要
+    pub fn verify(&self) -> Result<Verification, String> {
+        let mut result = Verification {
+            valid: true,
+            entries: 0,
+            last_hash: GENESIS.to_string(),
+            broken_at: None,
+            error: None,
+        };
+        for (index, line) in self.lines()?.iter().enumerate() {
+            let line_number = index as u64 + 1;
+            let problem = match serde_json::from_str::<Value>(line) {
+                Ok(Value::Object(mut entry)) => {
+                    let hash = entry.remove("hash");
+                    let seq = entry.get("seq").and_then(Value::as_u64);
+                    let prev = entry.get("prev").and_then(Value::as_str);
+                    if seq != Some(result.entries + 1) {
+                        Some(format!("序号应为 {}", result.entries + 1))
+                    } else if prev != Some(result.last_hash.as_str()) {
+                        Some("prev 与前一条记录的摘要不一致".to_string())
+                    } else {
+                        let expected = entry_hash(&result.last_hash, &entry);
+                        match hash {
+                            Some(Value::String(hash)) if hash == expected => {
+                                result.entries += 1;
+                                result.last_hash = hash;
+                                None
+                            }
+                            _ => Some("摘要与内容不一致".to_string()),
+                        }
+                    }
+                }
+                _ => Some("无法解析".to_string()),
+            };
+            if let Some(problem) = problem {
+                result.valid = false;
+                result.broken_at = Some(line_number);
+                result.error = Some(format!("第 {} 行: {}", line_number, problem));
+                break;
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// 去掉密钥等字段后的请求体
+fn sanitized(bytes: &[u8]) -> Value {
+    let mut body: Value = serde_json::from_slice(bytes).unwrap_or(Value::Null);
+    if let Value::Object(body) = &mut body {
+        for field in SECRET_FIELDS {
+            body.remove(*field);
+        }
+    }
+    body
+}
+
+/// 按请求和响应记录安全事件，需要作为`route_layer`挂在认证中间件之外，才能取得匹配的路由和认证失败的响应
+pub async fn watch(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let Some(trail) = state.trail.clone() else {
+        return next.run(request).await;
+    };
+    let route = request.extensions().get::<MatchedPath>().map(|path| path.as_str().to_string()).unwrap_or_default();
+    let admin = route.starts_with("/admin");
+    let method = request.method().clone();
+    let rule = RULES.iter().find(|rule| rule.method == method.as_str() && rule.route == route);
+    let mut fields = json!({
+        "client": ratelimit::client_ip(&request, state.config().rate_limit.trust_proxy),
+        "method": method.as_str(),
+        "path": request.uri().path(),
+        "query": request.uri().query(),
+    });
+
+    let request = if rule.is_some_and(|rule| rule.request) {
+        let (parts, body) = request.into_parts();
+        let bytes = match axum::body::to_bytes(body, MAX_BODY_BYTES).await {
+            Ok(bytes) => bytes,
+            Err(_) => return ApiError::invalid_request("请求体过大或读取失败").into_response(),
+        };
+        fields["request"] = sanitized(&bytes);
+        Request::from_parts(parts, Body::from(bytes))
+    } else {
+        request
+    };
+
+    let response = next.run(request).await;
+    let status = response.status();
+    fields["status"] = json!(status.as_u16());
+    let principal = response.extensions().get::<Principal>().map(|principal| principal.subject.clone());
+    if let Some(workspace) = response.extensions().get::<Workspace>() {
+        fields["workspace"] = json!(workspace.name());
+    }
+    let actor = if admin { Some("admin".to_string()) } else { principal };
+
+    let event = match (status, rule) {
+        (StatusCode::UNAUTHORIZED, _) if admin => "admin.denied",
+        (StatusCode::UNAUTHORIZED, _) => "auth.failed",
+        (StatusCode::FORBIDDEN, _) => "auth.denied",
+        (status, _) if !status.is_success() => return response,
+        (_, Some(rule)) => rule.event,
+        _ if admin && method != Method::GET => "admin.changed",
+        _ if !admin && method == Method::DELETE => "data.deleted",
+        _ => return response,
+    };
+    let denied = status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN;
+    let actor = if admin && denied { None } else { actor };
+    if !denied && !rule.is_some_and(|rule| rule.created) {
+        trail.append(event, actor.as_deref(), fields);
+        return response;
+    }
+
+    // 拒绝的原因和新建资源的标识都在响应体中
+    let (parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, MAX_BODY_BYTES).await {
+        Ok(bytes) => bytes,
+        Err(_) => {
+            trail.append(event, actor.as_deref(), fields);
+            return ApiError::Internal("读取响应失败".to_string()).into_response();
+        }
+    };
+    let body: Value = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+    if denied {
+        fields["reason"] = body.pointer("/error/message").cloned().unwrap_or(Value::Null);
+    } else {
+        fields["resource"] = body.get("id").or_else(|| body.get("name")).cloned().unwrap_or(Value::Null);
+    }
+    trail.append(event, actor.as_deref(), fields);
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 写入三条记录，返回日志和文件中的各行
+    fn trail(dir: &std::path::Path) -> (AuditTrail, Vec<String>) {
+        let config = AuditTrailConfig {
+            enabled: true,
+            path: dir.join("audit_trail.jsonl"),
+            syslog: None,
+        };
+        let trail = AuditTrail::open(&config).unwrap();
+        trail.append("key.created", Some("admin"), json!({"resource": "openai"}));
+        trail.append("quota.changed", Some("admin"), json!({"resource": "alice"}));
+        trail.append("key.revoked", Some("bob"), json!({"resource": "openai"}));
+        let lines = trail.lines().unwrap();
+        (trail, lines)
+    }
+
+    fn rewrite(trail: &AuditTrail, lines: &[String]) -> Verification {
+        fs::write(&trail.path, lines.join("\n") + "\n").unwrap();
+        trail.verify().unwrap()
+    }
+
+    #[test]
+    fn intact_chain_verifies() {
+        let dir = tempfile::tempdir().unwrap();
+        let (trail, lines) = trail(dir.path());
+        assert_eq!(lines.len(), 3);
+        let result = trail.verify().unwrap();
+        assert!(result.valid, "{:?}", result.error);
+        assert_eq!(result.entries, 3);
+        assert_eq!(result.broken_at, None);
+    }
+
+    #[test]
+    fn modified_entry_breaks_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let (trail, mut lines) = trail(dir.path());
+        lines[1] = lines[1].replace("alice", "mallory");
+        let result = rewrite(&trail, &lines);
+        assert!(!result.valid);
+        assert_eq!(result.broken_at, Some(2));
+        assert_eq!(result.entries, 1);
+
+        // 连同摘要一起重新计算也会在下一条记录的prev处发现
+        let mut entry: Map<String, Value> = serde_json::from_str(&lines[1]).unwrap();
+        entry.remove("hash");
+        let prev = entry["prev"].as_str().unwrap().to_string();
+        let hash = entry_hash(&prev, &entry);
+        entry.insert("hash".to_string(), json!(hash));
+        lines[1] = serde_json::to_string(&entry).unwrap();
+        let result = rewrite(&trail, &lines);
+        assert!(!result.valid);
+        assert_eq!(result.broken_at, Some(3));
+    }
+
+    #[test]
+    fn removed_entry_breaks_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let (trail, mut lines) = trail(dir.path());
+        lines.remove(1);
+        let result = rewrite(&trail, &lines);
+        assert!(!result.valid);
+        assert_eq!(result.broken_at, Some(2));
+
+        lines.remove(0);
+        let result = rewrite(&trail, &lines);
+        assert!(!result.valid);
+        assert_eq!(result.broken_at, Some(1));
+    }
+
+    #[test]
+    fn reordered_entries_break_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let (trail, mut lines) = trail(dir.path());
+        lines.swap(1, 2);
+        let result = rewrite(&trail, &lines);
+        assert!(!result.valid);
+        assert_eq!(result.broken_at, Some(2));
+        assert_eq!(result.entries, 1);
+    }
+
+    #[test]
+    fn append_continues_chain_after_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let (trail, _) = trail(dir.path());
+        let config = AuditTrailConfig {
+            enabled: true,
+            path: trail.path.clone(),
+            syslog: None,
+        };
+        drop(trail);
+        let trail = AuditTrail::open(&config).unwrap();
+        trail.append("config.reloaded", None, json!({}));
+        let result = trail.verify().unwrap();
+        assert!(result.valid, "{:?}", result.error);
+        assert_eq!(result.entries, 4);
+    }
+}
@@ -0,0 +1,476 @@
+//! 与OpenAI API一致的请求与响应结构
+//!
+//! 只显式声明服务端需要读取或改写的字段，其余字段通过`extra`原样转发给上游，
+//! 这样上游新增的参数无需修改这里即可使用。
+
+use openkimi_rag::ChunkConfig;
+use openkimi_sessions::{Message as SessionMessage, NewMessage, Session};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+
+use crate::config::RetrievalMode;
+
+/// 消息内容：纯文本，或由多个内容片段组成（如图文混合）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<Value>),
+}
+
+/// 对话消息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    #[serde(default)]
+    pub content: Option<MessageContent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl ChatMessage {
+    /// 消息的文本内容，多段内容只保留其中的文本片段
+    pub fn text(&self) -> String {
+        match &self.content {
+            Some(MessageContent::Text(text)) => text.clone(),
+            Some(MessageContent::Parts(parts)) => parts
+                .iter()
+                .filter_map(|part| part.get("text").and_then(Value::as_str))
+                .collect::<Vec<_>>()
+                .join(""),
+            None => String::new(),
+        }
+    }
+}
+
+/// `POST /v1/chat/completions`请求
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatCompletionRequest {
+    #[serde(default)]
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Token用量
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Usage {
+    #[serde(default)]
+    pub prompt_tokens: u32,
+    #[serde(default)]
+    pub completion_tokens: u32,
+    #[serde(default)]
+    pub total_tokens: u32,
+}
+
+/// 单个候选回复
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatChoice {
+    pub index: u32,
+    pub message: ChatMessage,
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// `POST /v1/chat/completions`响应
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatCompletionResponse {
+    pub id: String,
+    #[serde(default = "chat_completion_object")]
+    pub object: String,
+    #[serde(default)]
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<ChatChoice>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+fn chat_completion_object() -> String {
+    "chat.completion".to_string()
+}
+
+/// 模型信息；`kind`之后的字段是OpenKimi的扩展，只认识OpenAI格式的客户端会忽略
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Model {
+    pub id: String,
+    pub object: String,
+    pub created: u64,
+    pub owned_by: String,
+    /// `chat`或`embedding`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    /// 上下文窗口（token）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<u32>,
+    /// 接受的输入，如`text`、`image`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub modalities: Vec<String>,
+    /// `metering.prices`中配置的单价
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pricing: Option<ModelPricing>,
+}
+
+impl Model {
+    pub fn new(id: impl Into<String>) -> Model {
+        Model {
+            id: id.into(),
+            object: "model".to_string(),
+            created: 0,
+            owned_by: "openkimi".to_string(),
+            kind: None,
+            context_length: None,
+            modalities: Vec::new(),
+            pricing: None,
+        }
+    }
+}
+
+/// 模型的单价，按每百万token计
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelPricing {
+    pub prompt: f64,
+    pub completion: f64,
+    pub currency: String,
+}
+
+/// `GET /v1/models`响应
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelList {
+    pub object: String,
+    pub data: Vec<Model>,
+}
+
+/// 嵌入输入：单个字符串、字符串数组或token数组
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum EmbeddingInput {
+    Text(String),
+    Texts(Vec<String>),
+    Tokens(Vec<u32>),
+    TokenBatches(Vec<Vec<u32>>),
+}
+
+/// `POST /v1/embeddings`请求
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbeddingRequest {
+    #[serde(default)]
+    pub model: String,
+    pub input: EmbeddingInput,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// 单个嵌入向量；`encoding_format=base64`时为字符串
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Embedding {
+    pub object: String,
+    pub index: u32,
+    pub embedding: Value,
+}
+
+/// 嵌入请求的token用量
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EmbeddingUsage {
+    #[serde(default)]
+    pub prompt_tokens: u32,
+    #[serde(default)]
+    pub total_tokens: u32,
+}
+
+/// `POST /v1/embeddings`响应
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbeddingResponse {
+    pub object: String,
+    pub data: Vec<Embedding>,
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<EmbeddingUsage>,
+}
+
+/// `POST /v1/audio/speech`请求
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpeechRequest {
+    #[serde(default)]
+    pub model: String,
+    pub input: String,
+    #[serde(default)]
+    pub voice: String,
+    /// `mp3`、`opus`、`aac`、`flac`、`wav`、`pcm`或`ogg`，缺省时上游方式由上游决定，本地命令输出WAV
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed: Option<f32>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// `POST /v1/rag/ingest`中的单个文档，`text`和`data`二选一
+#[derive(Debug, Clone, Deserialize)]
+pub struct IngestDocument {
+    /// 文档id，缺省为`name`；同一id重新导入时替换旧内容
+    #[serde(default)]
+    pub id: Option<String>,
+    /// 文件名，按扩展名判断类型
+    pub name: String,
+    /// 文本内容，适合HTML、Markdown和纯文本
+    #[serde(default)]
+    pub text: Option<String>,
+    /// Base64编码的文件内容，PDF和DOCX必须使用
+    #[serde(default)]
+    pub data: Option<String>,
+    #[serde(default)]
+    pub metadata: Map<String, Value>,
+    /// 该文档的分块配置，优先于请求的`chunking`
+    #[serde(default)]
+    pub chunking: Option<ChunkConfig>,
+}
+
+/// `POST /v1/rag/ingest`请求
+#[derive(Debug, Clone, Deserialize)]
+pub struct IngestRequest {
+    /// 索引名，缺省为`rag.default_index`
+    #[serde(default)]
+    pub index: Option<String>,
+    pub documents: Vec<IngestDocument>,
+    /// 本次导入的分块配置，缺省时按文件类型或索引的配置
+    #[serde(default)]
+    pub chunking: Option<ChunkConfig>,
+}
+
+/// 单个文档的导入结果
+#[derive(Debug, Clone, Serialize)]
+pub struct IngestedDocument {
+    pub id: String,
+    pub chunks: usize,
+    /// 被替换的旧分块数
+    pub replaced: usize,
+}
+
+/// `POST /v1/rag/ingest`响应
+#[derive(Debug, Clone, Serialize)]
+pub struct IngestResponse {
+    pub object: String,
+    pub index: String,
+    pub documents: Vec<IngestedDocument>,
+    pub chunks: usize,
+}
+
+/// `POST /v1/rag/query`请求
+#[derive(Debug, Clone, Deserialize)]
+pub struct QueryRequest {
+    /// 索引名，缺省为`rag.default_index`
+    #[serde(default)]
+    pub index: Option<String>,
+    pub query: String,
+    /// 返回的分块数，缺省为该索引的`retrieval.top_k`
+    #[serde(default)]
+    pub top_k: Option<usize>,
+    /// 按元数据筛选，格式见向量存储的过滤条件
+    #[serde(default)]
+    pub filter: Option<Value>,
+    /// 检索方式，缺省为该索引的`retrieval.mode`
+    #[serde(default)]
+    pub mode: Option<RetrievalMode>,
+    /// 是否重排，缺省为该索引的`retrieval.rerank`
+    #[serde(default)]
+    pub rerank: Option<bool>,
+}
+
+/// 检索到的分块
+#[derive(Debug, Clone, Serialize)]
+pub struct RetrievedChunk {
+    pub id: String,
+    pub document: String,
+    pub text: String,
+    pub metadata: Map<String, Value>,
+    /// 检索得分：向量检索为余弦相似度，关键词检索为全文检索得分，混合检索为RRF融合得分
+    pub score: f32,
+    /// 重排得分，未重排时为`null`
+    pub rerank_score: Option<f32>,
+}
+
+/// `POST /v1/rag/query`响应
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryResponse {
+    pub object: String,
+    pub index: String,
+    pub mode: RetrievalMode,
+    /// 使用的重排模型，未重排时为`null`
+    pub reranker: Option<String>,
+    pub data: Vec<RetrievedChunk>,
+}
+
+/// 评估集中的一条查询
+#[derive(Debug, Clone, Deserialize)]
+pub struct EvaluationQuery {
+    pub query: String,
+    /// 相关的文档id或分块id
+    pub relevant: Vec<String>,
+    #[serde(default)]
+    pub filter: Option<Value>,
+}
+
+/// `POST /v1/rag/evaluate`请求
+#[derive(Debug, Clone, Deserialize)]
+pub struct EvaluateRequest {
+    /// 索引名，缺省为`rag.default_index`
+    #[serde(default)]
+    pub index: Option<String>,
+    pub queries: Vec<EvaluationQuery>,
+    /// 评估前`k`个结果，缺省为该索引的`retrieval.top_k`
+    #[serde(default)]
+    pub top_k: Option<usize>,
+    /// 检索方式，缺省为该索引的`retrieval.mode`
+    #[serde(default)]
+    pub mode: Option<RetrievalMode>,
+}
+
+/// 检索质量指标，各项为所有查询的平均值
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RetrievalMetrics {
+    /// 前`k`个结果中至少有一个相关的查询占比
+    pub hit_rate: f64,
+    /// 第一个相关结果排名的倒数
+    pub mrr: f64,
+    /// 前`k`个结果覆盖的相关项占比
+    pub recall: f64,
+    /// 二值相关度的nDCG@k
+    pub ndcg: f64,
+}
+
+/// 单条查询的评估结果
+#[derive(Debug, Clone, Serialize)]
+pub struct EvaluatedQuery {
+    pub query: String,
+    /// 重排前前`k`个结果的分块id
+    pub retrieved: Vec<String>,
+    /// 重排后前`k`个结果的分块id，未配置重排模型时为`null`
+    pub reranked: Option<Vec<String>>,
+}
+
+/// `POST /v1/rag/evaluate`响应
+#[derive(Debug, Clone, Serialize)]
+pub struct EvaluateResponse {
+    pub object: String,
+    pub index: String,
+    pub top_k: usize,
+    pub mode: RetrievalMode,
+    pub reranker: Option<String>,
+    /// 重排前的指标
+    pub retrieved: RetrievalMetrics,
+    /// 未配置重排模型时为`null`
+    pub reranked: Option<RetrievalMetrics>,
+    pub queries: Vec<EvaluatedQuery>,
+}
+
+/// 列表响应`{"object": "list", "data": [...]}`
+#[derive(Debug, Clone, Serialize)]
+pub struct ListResponse<T> {
+    pub object: String,
+    pub data: Vec<T>,
+}
+
+impl<T> ListResponse<T> {
+    pub fn new(data: Vec<T>) -> ListResponse<T> {
+        ListResponse {
+            object: "list".to_string(),
+            data,
+        }
+    }
+}
+
+/// `GET /v1/sessions/{id}`响应：会话及其当前分支上的消息
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionDetail {
+    #[serde(flatten)]
+    pub session: Session,
+    pub messages: Vec<SessionMessage>,
+}
+
+/// `POST /v1/sessions/{id}/messages`请求
+#[derive(Debug, Clone, Deserialize)]
+pub struct AppendMessagesRequest {
+    pub messages: Vec<NewMessage>,
+    /// 接在哪条消息之后：缺省为当前分支末尾，`null`为会话开头；该消息已有后续消息时开出新分支
+    #[serde(default, deserialize_with = "nullable")]
+    pub parent_id: Option<Option<i64>>,
+}
+
+/// 区分缺省的字段和显式的`null`：前者为`None`，后者为`Some(None)`
+fn nullable<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Option<i64>>, D::Error> {
+    Option::<i64>::deserialize(deserializer).map(Some)
+}
+
+/// `GET /v1/sessions/{id}/messages`的查询参数
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MessagesQuery {
+    /// 为`true`时返回所有分支的消息，否则只返回当前分支
+    pub tree: bool,
+}
+
+/// `POST /v1/sessions/{id}/checkout`请求
+#[derive(Debug, Clone, Deserialize)]
+pub struct CheckoutRequest {
+    pub message_id: i64,
+}
+
+/// `POST /v1/sessions/{id}/merge`请求
+#[derive(Debug, Clone, Deserialize)]
+pub struct MergeRequest {
+    /// 要合并的分支上的任一条消息，通常是分支的最后一条
+    pub source_id: i64,
+    /// 合并到哪条消息之后，缺省为当前分支末尾
+    #[serde(default)]
+    pub target_id: Option<i64>,
+}
+
+/// `POST /v1/sessions/{id}/prune`请求
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PruneRequest {
+    /// 删除这条消息及其后续消息，缺省时删除当前分支以外的所有消息
+    pub message_id: Option<i64>,
+}
+
+/// `GET /v1/sessions/{id}/export`的查询参数
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ExportQuery {
+    /// `json`（缺省）、`md`或`html`
+    pub format: Option<String>,
+}
+
+/// `GET /v1/sessions/search`的查询参数
+#[derive(Debug, Clone, Deserialize)]
+pub struct SessionSearchQuery {
+    pub q: String,
+    #[serde(default = "default_search_limit")]
+    pub limit: u32,
+}
+
+fn default_search_limit() -> u32 {
+    20
+}
+
+/// `DELETE /v1/sessions/{id}`响应
+#[derive(Debug, Clone, Serialize)]
+pub struct DeletedResponse {
+    pub id: String,
+    pub object: String,
+    pub deleted: bool,
+}
@@ -0,0 +1,176 @@
+//! 上游模型API客户端
+//!
+//! 代理设置沿用`HTTPS_PROXY`/`ALL_PROXY`等环境变量，支持`socks5://`。
+//! 每次请求由[`UpstreamPool`]选择密钥和端点，遇到429、5xx或连接失败时换下一个重试，
+//! 并把每次尝试的结果报告给密钥池以跟踪健康状态。
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::http::header::{CONTENT_TYPE, RETRY_AFTER};
+use axum::http::StatusCode;
+use bytes::Bytes;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::config::BackendConfig;
+use crate::error::{ApiError, ApiResult};
+use crate::health::KeyOutcome;
+use crate::pool::{Target, UpstreamPool};
+use crate::telemetry::Span;
+use crate::types::{ChatCompletionResponse, EmbeddingResponse};
+use crate::vault::KeyVault;
+
+/// 一个后端的上游客户端，请求体已由[`ModelRouter`](crate::router::ModelRouter)按后端类型调整好
+#[derive(Debug, Clone)]
+pub struct Upstream {
+    http: reqwest::Client,
+    pool: Arc<UpstreamPool>,
+}
+
+impl Upstream {
+    pub fn new(name: &str, config: &BackendConfig, vault: Option<Arc<KeyVault>>) -> Result<Upstream, String> {
+        let http = reqwest::Client::builder()
+            .user_agent(concat!("openkimi-server/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .map_err(|e| format!("创建HTTP客户端失败: {}", e))?;
+        let pool = UpstreamPool::new(name, config, vault)?;
+        Ok(Upstream { http, pool: Arc::new(pool) })
+    }
+
+    pub fn base_url(&self) -> &str {
+        self.pool.base_url()
+    }
+
+    pub fn pool(&self) -> &UpstreamPool {
+        &self.pool
+    }
+
+    /// 向一个目标发送请求，失败时第二项表示是否可以换下一个目标重试
+    async fn attempt(
+        &self,
+        target: &Target,
+        path: &str,
+        content_type: &str,
+        body: Bytes,
+    ) -> Result<reqwest::Response, (ApiError, bool)> {
+        let span = Span::client(format!("POST {}", path));
+        span.set("openkimi.upstream.endpoint", target.id.as_str());
+        let url = format!("{}{}", target.base_url, path);
+        span.set("url.full", url.as_str());
+        let mut request = self
+            .http
+            .post(url)
+            .header(CONTENT_TYPE, content_type);
+        if let Some(key) = &target.api_key {
+            request = request.bearer_auth(key);
+        }
+        if let Some(traceparent) = span.traceparent() {
+            request = request.header("traceparent", traceparent);
+        }
+        self.pool.mark_used(target);
+        let response = match request.body(body).send().await {
+            Ok(response) => response,
+            Err(err) => {
+                span.error(&err);
+                self.pool.report(target, KeyOutcome::Failed(err.to_string()));
+                return Err((err.into(), true));
+            }
+        };
+        let status = response.status();
+        span.set("http.response.status_code", status.as_u16());
+        if status.is_success() {
+            self.pool.report(target, KeyOutcome::Success);
+            return Ok(response);
+        }
+
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse().ok())
+            .map(Duration::from_secs);
+        let bytes = response.bytes().await.map_err(|err| (err.into(), true))?;
+        let body = serde_json::from_slice::<Value>(&bytes)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()));
+        let status = StatusCode::from_u16(status.as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+        let retry = match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                self.pool.report(target, KeyOutcome::Rejected(format!("上游返回 {}", status)));
+                true
+            }
+            StatusCode::TOO_MANY_REQUESTS => {
+                self.pool.report(target, KeyOutcome::RateLimited(retry_after));
+                true
+            }
+            status if status.is_server_error() => {
+                self.pool.report(target, KeyOutcome::Failed(format!("上游返回 {}", status)));
+                true
+            }
+            // 其余4xx是请求本身的问题，换密钥也没有用
+            _ => false,
+        };
+        span.error(status);
+        Err((ApiError::UpstreamStatus(status, body), retry))
+    }
+
+    /// 发送请求，上游返回非2xx时读取错误体并转为[`ApiError::UpstreamStatus`]
+    ///
+    /// 所有目标都失败时返回最后一次的错误。
+    async fn send(&self, path: &str, content_type: &str, body: Bytes) -> ApiResult<reqwest::Response> {
+        let targets = self.pool.plan()?;
+        let mut last_error = None;
+        for (index, target) in targets.iter().enumerate() {
+            match self.attempt(target, path, content_type, body.clone()).await {
+                Ok(response) => return Ok(response),
+                Err((err, true)) => {
+                    if index + 1 < targets.len() {
+                        eprintln!("↪️ 上游 {} 请求失败，改用 {} 重试: {}", target.id, targets[index + 1].id, err);
+                    }
+                    last_error = Some(err);
+                }
+                Err((err, false)) => return Err(err),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| ApiError::Upstream("没有可用的上游密钥".to_string())))
+    }
+
+    /// 发送JSON请求，请求体只序列化一次，重试时复用
+    async fn send_json<B: Serialize>(&self, path: &str, body: &B) -> ApiResult<reqwest::Response> {
+        let body = serde_json::to_vec(body).map_err(|e| ApiError::Internal(format!("无法序列化请求: {}", e)))?;
+        self.send(path, "application/json", Bytes::from(body)).await
+    }
+
+    /// 发送JSON请求并解析JSON响应
+    async fn post_json<B: Serialize, T: DeserializeOwned>(&self, path: &str, body: &B) -> ApiResult<T> {
+        let bytes = self.send_json(path, body).await?.bytes().await?;
+        serde_json::from_slice(&bytes).map_err(|e| ApiError::Upstream(format!("无法解析上游响应: {}", e)))
+    }
+
+    pub async fn chat_completion<B: Serialize>(&self, request: &B) -> ApiResult<ChatCompletionResponse> {
+        self.post_json("/chat/completions", request).await
+    }
+
+    /// 发起流式对话补全，返回尚未读取的SSE响应
+    ///
+    /// 状态码在开始转发前检查，上游拒绝请求时客户端仍能收到普通的JSON错误。
+    pub async fn chat_completion_stream<B: Serialize>(&self, request: &B) -> ApiResult<reqwest::Response> {
+        self.send_json("/chat/completions", request).await
+    }
+
+    pub async fn embeddings<B: Serialize>(&self, request: &B) -> ApiResult<EmbeddingResponse> {
+        self.post_json("/embeddings", request).await
+    }
+
+    /// 语音合成，返回尚未读取的响应，音频边收边转发
+    pub async fn speech<B: Serialize>(&self, request: &B) -> ApiResult<reqwest::Response> {
+        self.send_json("/audio/speech", request).await
+    }
+
+    /// 语音转写，请求体为`multipart/form-data`
+    pub async fn transcription(&self, content_type: &str, body: Bytes) -> ApiResult<Value> {
+        let bytes = self.send("/audio/transcriptions", content_type, body).await?.bytes().await?;
+        serde_json::from_slice(&bytes).map_err(|e| ApiError::Upstream(format!("无法解析上游响应: {}", e)))
+    }
+}
@@ -0,0 +1,450 @@
+//! 用户目录
+//!
+//! 启用`users`后，用户和他们的API令牌保存在`users.path`指定的SQLite数据库中，由管理接口`/admin/users`
+//! 或`openkimi-server users`命令维护，不需要修改配置文件或直接编辑数据库。每个用户有自己的权限和所属工作区，
+//! 可以持有多个令牌；令牌只在签发时返回一次，数据库中只保存它的SHA-256摘要。
+//! 启用认证时这些令牌与`auth.tokens`中的静态令牌一样使用，调用方身份为用户名，每次认证都查询数据库，
+//! 停用用户或吊销令牌立即生效，命令行的修改也不需要重启服务。
+//! 配置了许可证时，启用的用户数不能超过许可证的席位数。
+
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use openkimi_schema::sqlite::{migrate, Migration};
+use openkimi_schema::SchemaError;
+use ring::digest::{digest, SHA256};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::{AuthMethod, Principal};
+use crate::config::{UsersConfig, WorkspacesConfig};
+use crate::error::{ApiError, ApiResult};
+use crate::license::License;
+use crate::workspace::{validate_workspace_name, DEFAULT_WORKSPACE};
+
+/// 用户数据库各版本的迁移，下标加一即版本号
+pub(crate) const MIGRATIONS: &[Migration] = &[Migration::sql(
+    "用户和API令牌",
+    "
+    CREATE TABLE users (
+        name TEXT PRIMARY KEY,
+        workspace TEXT,
+        scopes TEXT NOT NULL,
+        disabled INTEGER NOT NULL DEFAULT 0,
+        created_at INTEGER NOT NULL
+    );
+    CREATE TABLE api_tokens (
+        id TEXT PRIMARY KEY,
+        user TEXT NOT NULL,
+        label TEXT,
+        hash TEXT NOT NULL UNIQUE,
+        prefix TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        expires_at INTEGER,
+        revoked_at INTEGER,
+        last_used_at INTEGER
+    );
+    CREATE INDEX api_tokens_user ON api_tokens (user);
+    ",
+    None,
+)];
+
+/// 签发的令牌都以此开头，认证时据此判断是否需要查询用户目录
+const TOKEN_PREFIX: &str = "ok-";
+
+/// 列表中显示的令牌开头长度
+const SHOWN_CHARS: usize = 10;
+
+/// 最近使用时间的精度，避免每个请求都写一次数据库
+const LAST_USED_PRECISION: u64 = 60;
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+fn random_bytes<const N: usize>() -> ApiResult<[u8; N]> {
+    let mut bytes = [0u8; N];
+    getrandom::getrandom(&mut bytes).map_err(|e| ApiError::Internal(format!("生成随机数失败: {}", e)))?;
+    Ok(bytes)
+}
+
+fn token_hash(token: &str) -> String {
+    digest(&SHA256, token.as_bytes()).as_ref().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn db_error(err: rusqlite::Error) -> ApiError {
+    ApiError::Internal(format!("读写用户数据库失败: {}", err))
+}
+
+/// 用户名只允许字母、数字和`.`、`_`、`@`、`-`，与计量和配额中的用户名一致
+pub fn validate_user_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '@' | '-'));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("无效的用户名: {}（只能使用字母、数字和 . _ @ -，最长64个字符）", name))
+    }
+}
+
+/// 分配给用户的工作区必须是`default`或`workspaces.list`中的工作区，返回保存的值，默认工作区为`None`
+pub fn resolve_workspace(config: &WorkspacesConfig, name: &str) -> Result<Option<String>, String> {
+    if name == DEFAULT_WORKSPACE {
+        return Ok(None);
+    }
+    validate_workspace_name(name)?;
+    if !config.enabled {
+        return Err(format!("未启用工作区（workspaces.enabled 为 false），不能分配到工作区 {}", name));
+    }
+    if !config.list.contains_key(name) {
+        return Err(format!("工作区 {} 不存在", name));
+    }
+    Ok(Some(name.to_string()))
+}
+
+/// 令牌的信息，不含令牌本身
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenInfo {
+    pub id: String,
+    pub label: Option<String>,
+    /// 令牌的开头几个字符，用于辨认
+    pub prefix: String,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    pub revoked_at: Option<u64>,
+    pub last_used_at: Option<u64>,
+}
+
+impl TokenInfo {
+    fn from_row(row: &Row) -> rusqlite::Result<TokenInfo> {
+        Ok(TokenInfo {
+            id: row.get("id")?,
+            label: row.get("label")?,
+            prefix: row.get("prefix")?,
+            created_at: row.get("created_at")?,
+            expires_at: row.get("expires_at")?,
+            revoked_at: row.get("revoked_at")?,
+            last_used_at: row.get("last_used_at")?,
+        })
+    }
+
+    /// 没有吊销也没有过期
+    pub fn is_active(&self, now: u64) -> bool {
+        self.revoked_at.is_none() && self.expires_at.is_none_or(|expires_at| now < expires_at)
+    }
+}
+
+/// 新签发的令牌，`token`只在这一次返回
+#[derive(Debug, Clone, Serialize)]
+pub struct IssuedToken {
+    #[serde(flatten)]
+    pub info: TokenInfo,
+    pub token: String,
+}
+
+/// 用户及其令牌
+#[derive(Debug, Clone, Serialize)]
+pub struct UserInfo {
+    pub name: String,
+    pub workspace: String,
+    pub scopes: Vec<String>,
+    pub disabled: bool,
+    pub created_at: u64,
+    pub tokens: Vec<TokenInfo>,
+}
+
+/// 创建用户的参数
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewUser {
+    pub name: String,
+    /// 缺省为默认工作区
+    #[serde(default)]
+    pub workspace: Option<String>,
+    /// 缺省为全部权限`*`
+    #[serde(default)]
+    pub scopes: Option<Vec<String>>,
+}
+
+/// 修改用户的参数，未给出的字段不变
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct UserUpdate {
+    /// `default`为默认工作区
+    pub workspace: Option<String>,
+    pub scopes: Option<Vec<String>>,
+    pub disabled: Option<bool>,
+}
+
+/// 签发令牌的参数
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct NewToken {
+    /// 令牌的用途说明
+    pub label: Option<String>,
+    /// 有效天数，缺省为永久有效
+    pub expires_in_days: Option<u32>,
+}
+
+fn validate_scopes(scopes: &[String]) -> ApiResult<String> {
+    if scopes.iter().any(|scope| scope.trim().is_empty()) {
+        return Err(ApiError::invalid_request("scopes 中不能有空字符串"));
+    }
+    serde_json::to_string(scopes).map_err(|e| ApiError::Internal(e.to_string()))
+}
+
+#[derive(Debug)]
+pub struct UserDirectory {
+    conn: Mutex<Connection>,
+    /// 限制启用用户数的许可证，未配置许可证时为`None`
+    license: Option<Arc<License>>,
+}
+
+impl UserDirectory {
+    pub fn open(config: &UsersConfig) -> Result<UserDirectory, String> {
+        let path = &config.path;
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).map_err(|e| format!("创建目录 {} 失败: {}", parent.display(), e))?;
+        }
+        let open = || -> Result<Connection, SchemaError> {
+            let mut conn = Connection::open(path)?;
+            // 服务和命令行可能同时打开同一个数据库
+            conn.pragma_update(None, "journal_mode", "WAL")?;
+            conn.busy_timeout(std::time::Duration::from_secs(5))?;
+            migrate(&mut conn, "users", MIGRATIONS)?;
+            Ok(conn)
+        };
+        let conn = open().map_err(|e| format!("打开用户数据库 {} 失败: {}", path.display(), e))?;
+        Ok(UserDirectory {
+            conn: Mutex::new(conn),
+            license: None,
+        })
+    }
+
+    /// 按许可证的席位数限制启用的用户数
+    pub fn with_license(mut self, license: Option<Arc<License>>) -> Self {
+        self.license = license;
+        self
+    }
+
+    fn count_active(conn: &Connection) -> ApiResult<u32> {
+        conn.query_row("SELECT COUNT(*) FROM users WHERE disabled = 0", [], |row| row.get(0))
+            .map_err(db_error)
+    }
+
+    /// 再启用一个用户是否超出许可证的席位
+    fn check_seats(&self, conn: &Connection) -> ApiResult<()> {
+        let Some(license) = &self.license else {
+            return Ok(());
+        };
+        license.check_seats(Self::count_active(conn)? + 1).map_err(ApiError::Forbidden)
+    }
+
+    /// 启用的用户数，即占用的席位数
+    pub fn active_count(&self) -> ApiResult<u32> {
+        Self::count_active(&self.conn.lock().unwrap())
+    }
+
+    fn load(conn: &Connection, name: &str) -> ApiResult<UserInfo> {
+        let user = conn
+            .query_row(
+                "SELECT name, workspace, scopes, disabled, created_at FROM users WHERE name = ?1",
+                [name],
+                |row| {
+                    let workspace: Option<String> = row.get(1)?;
+                    let scopes: String = row.get(2)?;
+                    Ok(UserInfo {
+                        name: row.get(0)?,
+                        workspace: workspace.unwrap_or_else(|| DEFAULT_WORKSPACE.to_string()),
+                        scopes: serde_json::from_str(&scopes).unwrap_or_default(),
+                        disabled: row.get(3)?,
+                        created_at: row.get(4)?,
+                        tokens: Vec::new(),
+                    })
+                },
+            )
+            .optional()
+            .map_err(db_error)?;
+        let mut user = user.ok_or_else(|| ApiError::NotFound(format!("用户 {} 不存在", name)))?;
+        let mut statement = conn
+            .prepare("SELECT * FROM api_tokens WHERE user = ?1 ORDER BY created_at, id")
+            .map_err(db_error)?;
+        user.tokens = statement
+            .query_map([name], TokenInfo::from_row)
+            .and_then(|rows| rows.collect())
+            .map_err(db_error)?;
+        Ok(user)
+    }
+
+    /// 全部用户，按名称排序
+    pub fn list(&self) -> ApiResult<Vec<UserInfo>> {
+        let conn = self.conn.lock().unwrap();
+        let names: Vec<String> = conn
+            .prepare("SELECT name FROM users ORDER BY name")
+            .and_then(|mut statement| statement.query_map([], |row| row.get(0))?.collect())
+            .map_err(db_error)?;
+        names.iter().map(|name| Self::load(&conn, name)).collect()
+    }
+
+    pub fn get(&self, name: &str) -> ApiResult<UserInfo> {
+        Self::load(&self.conn.lock().unwrap(), name)
+    }
+
+    /// 创建用户，`workspace`为[`resolve_workspace`]的结果
+    pub fn create(&self, name: &str, workspace: Option<String>, scopes: Option<Vec<String>>) -> ApiResult<UserInfo> {
+        validate_user_name(name).map_err(ApiError::InvalidRequest)?;
+        let scopes = validate_scopes(&scopes.unwrap_or_else(|| vec!["*".to_string()]))?;
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(db_error)?;
+        if Self::load(&tx, name).is_ok() {
+            return Err(ApiError::invalid_request(format!("用户 {} 已存在", name)));
+        }
+        self.check_seats(&tx)?;
+        let inserted = tx
+            .execute(
+                "INSERT INTO users (name, workspace, scopes, disabled, created_at) VALUES (?1, ?2, ?3, 0, ?4)
+                 ON CONFLICT (name) DO NOTHING",
+                params![name, workspace, scopes, unix_time()],
+            )
+            .map_err(db_error)?;
+        if inserted == 0 {
+            return Err(ApiError::invalid_request(format!("用户 {} 已存在", name)));
+        }
+        let user = Self::load(&tx, name)?;
+        tx.commit().map_err(db_error)?;
+        Ok(user)
+    }
+
+    /// 修改用户；`workspace`为`Some`时是[`resolve_workspace`]的结果，`Some(None)`为默认工作区
+    pub fn update(
+        &self,
+        name: &str,
+        workspace: Option<Option<String>>,
+        scopes: Option<Vec<String>>,
+        disabled: Option<bool>,
+    ) -> ApiResult<UserInfo> {
+        let scopes = scopes.as_deref().map(validate_scopes).transpose()?;
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(db_error)?;
+        let current = Self::load(&tx, name)?;
+        if current.disabled && disabled == Some(false) {
+            self.check_seats(&tx)?;
+        }
+        if let Some(workspace) = workspace {
+            tx.execute("UPDATE users SET workspace = ?2 WHERE name = ?1", params![name, workspace])
+                .map_err(db_error)?;
+        }
+        if let Some(scopes) = scopes {
+            tx.execute("UPDATE users SET scopes = ?2 WHERE name = ?1", params![name, scopes])
+                .map_err(db_error)?;
+        }
+        if let Some(disabled) = disabled {
+            tx.execute("UPDATE users SET disabled = ?2 WHERE name = ?1", params![name, disabled])
+                .map_err(db_error)?;
+        }
+        let user = Self::load(&tx, name)?;
+        tx.commit().map_err(db_error)?;
+        Ok(user)
+    }
+
+    /// 删除用户及其全部令牌
+    pub fn remove(&self, name: &str) -> ApiResult<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(db_error)?;
+        tx.execute("DELETE FROM api_tokens WHERE user = ?1", [name]).map_err(db_error)?;
+        let deleted = tx.execute("DELETE FROM users WHERE name = ?1", [name]).map_err(db_error)?;
+        if deleted == 0 {
+            return Err(ApiError::NotFound(format!("用户 {} 不存在", name)));
+        }
+        tx.commit().map_err(db_error)
+    }
+
+    /// 为用户签发一个新令牌
+    pub fn issue(&self, name: &str, request: NewToken) -> ApiResult<IssuedToken> {
+        let token = format!("{}{}", TOKEN_PREFIX, URL_SAFE_NO_PAD.encode(random_bytes::<32>()?));
+        let id: String = random_bytes::<8>()?.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let now = unix_time();
+        let info = TokenInfo {
+            id: format!("tok_{}", id),
+            label: request.label.filter(|label| !label.trim().is_empty()),
+            prefix: token.chars().take(SHOWN_CHARS).collect(),
+            created_at: now,
+            expires_at: request.expires_in_days.map(|days| now + days as u64 * 86_400),
+            revoked_at: None,
+            last_used_at: None,
+        };
+        let conn = self.conn.lock().unwrap();
+        Self::load(&conn, name)?;
+        conn.execute(
+            "INSERT INTO api_tokens (id, user, label, hash, prefix, created_at, expires_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![info.id, name, info.label, token_hash(&token), info.prefix, info.created_at, info.expires_at],
+        )
+        .map_err(db_error)?;
+        Ok(IssuedToken { info, token })
+    }
+
+    /// 吊销令牌，之后使用它的请求立即被拒绝；已经吊销的令牌保留原来的吊销时间
+    pub fn revoke(&self, name: &str, id: &str) -> ApiResult<TokenInfo> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE api_tokens SET revoked_at = COALESCE(revoked_at, ?3) WHERE user = ?1 AND id = ?2",
+            params![name, id, unix_time()],
+        )
+        .map_err(db_error)?;
+        conn.query_row("SELECT * FROM api_tokens WHERE user = ?1 AND id = ?2", [name, id], TokenInfo::from_row)
+            .optional()
+            .map_err(db_error)?
+            .ok_or_else(|| ApiError::NotFound(format!("用户 {} 没有令牌 {}", name, id)))
+    }
+
+    /// 识别用户目录签发的令牌；不是签发的令牌格式时返回`None`，交给其他认证方式
+    pub fn authenticate(&self, token: &str) -> ApiResult<Option<Principal>> {
+        if !token.starts_with(TOKEN_PREFIX) {
+            return Ok(None);
+        }
+        let conn = self.conn.lock().unwrap();
+        let found = conn
+            .query_row(
+                "SELECT t.*, u.name AS user_name, u.workspace, u.scopes, u.disabled
+                 FROM api_tokens t JOIN users u ON u.name = t.user WHERE t.hash = ?1",
+                [token_hash(token)],
+                |row| {
+                    let scopes: String = row.get("scopes")?;
+                    let principal = Principal {
+                        subject: row.get("user_name")?,
+                        method: AuthMethod::Token,
+                        scopes: serde_json::from_str(&scopes).unwrap_or_default(),
+                        workspace: row.get("workspace")?,
+                        token: Some(row.get("id")?),
+                    };
+                    Ok((TokenInfo::from_row(row)?, principal, row.get::<_, bool>("disabled")?))
+                },
+            )
+            .optional()
+            .map_err(db_error)?;
+        let Some((info, principal, disabled)) = found else {
+            return Ok(None);
+        };
+        let now = unix_time();
+        if info.revoked_at.is_some() {
+            return Err(ApiError::Unauthorized("认证令牌已吊销".to_string()));
+        }
+        if !info.is_active(now) {
+            return Err(ApiError::Unauthorized("认证令牌已过期".to_string()));
+        }
+        if disabled {
+            return Err(ApiError::Unauthorized(format!("用户 {} 已停用", principal.subject)));
+        }
+        if info.last_used_at.is_none_or(|used| now >= used + LAST_USED_PRECISION) {
+            let updated = conn.execute("UPDATE api_tokens SET last_used_at = ?2 WHERE id = ?1", params![info.id, now]);
+            if let Err(err) = updated {
+                eprintln!("⚠️ 记录令牌 {} 的使用时间失败: {}", info.id, err);
+            }
+        }
+        Ok(Some(principal))
+    }
+}
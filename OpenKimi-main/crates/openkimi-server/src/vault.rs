@@ -0,0 +1,378 @@
+//! 上游API密钥库
+//!
+//! 密钥按服务商分组保存在`vault.path`指定的JSON文件中，每个密钥单独用AES-256-GCM加密，
+//! 主密钥取自`OPENKIMI_VAULT_KEY`（Base64编码的32字节），未设置时读取`vault.key_file`，文件不存在时自动生成。
+//!
+//! 同一服务商的密钥由[`UpstreamPool`](crate::pool::UpstreamPool)按策略选择，每次请求后报告结果，
+//! 健康状态的规则见[`health`](crate::health)。返回401/403的密钥标记为失效，直到更换或重新启用。
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::config::VaultConfig;
+use crate::error::{ApiError, ApiResult};
+use crate::health::{Health, HealthInfo, KeyOutcome, Snapshot};
+
+const VAULT_VERSION: u32 = 1;
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn random_bytes<const N: usize>() -> ApiResult<[u8; N]> {
+    let mut bytes = [0u8; N];
+    getrandom::getrandom(&mut bytes).map_err(|e| ApiError::Internal(format!("生成随机数失败: {}", e)))?;
+    Ok(bytes)
+}
+
+/// 只显示前后几个字符，用于列表和日志
+fn mask(secret: &str) -> String {
+    let chars: Vec<char> = secret.chars().collect();
+    if chars.len() <= 8 {
+        return "****".to_string();
+    }
+    let head: String = chars[..3].iter().collect();
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}...{}", head, tail)
+}
+
+/// 文件中保存的密钥，`secret`是Base64编码的密文
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredKey {
+    id: String,
+    provider: String,
+    #[serde(default)]
+    label: Option<String>,
+    nonce: String,
+    secret: String,
+    enabled: bool,
+    created_at: u64,
+    #[serde(default)]
+    rotated_at: Option<u64>,
+    /// 每分钟请求数上限
+    #[serde(default)]
+    rpm: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct VaultFile {
+    version: u32,
+    keys: Vec<StoredKey>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    stored: StoredKey,
+    secret: String,
+    health: Health,
+}
+
+impl Entry {
+    fn info(&mut self) -> KeyInfo {
+        KeyInfo {
+            id: self.stored.id.clone(),
+            provider: self.stored.provider.clone(),
+            label: self.stored.label.clone(),
+            masked: mask(&self.secret),
+            enabled: self.stored.enabled,
+            rpm: self.stored.rpm,
+            created_at: self.stored.created_at,
+            rotated_at: self.stored.rotated_at,
+            health: self.health.info(self.stored.enabled, self.stored.rpm),
+        }
+    }
+}
+
+/// 管理接口返回的密钥信息，不包含密钥本身
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyInfo {
+    pub id: String,
+    pub provider: String,
+    pub label: Option<String>,
+    pub masked: String,
+    pub enabled: bool,
+    pub rpm: Option<u32>,
+    pub created_at: u64,
+    pub rotated_at: Option<u64>,
+    pub health: HealthInfo,
+}
+
+/// 取出的密钥，请求结束后用`id`报告结果
+#[derive(Debug, Clone)]
+pub struct KeyLease {
+    pub id: String,
+    pub secret: String,
+}
+
+
+/// 加密保存的上游密钥
+pub struct KeyVault {
+    path: PathBuf,
+    cipher: Aes256Gcm,
+    entries: Mutex<Vec<Entry>>,
+}
+
+impl std::fmt::Debug for KeyVault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyVault")
+            .field("path", &self.path)
+            .field("keys", &self.entries.lock().unwrap().len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// 读取主密钥，没有时生成新的密钥文件
+fn load_master_key(key_file: &Path) -> Result<[u8; 32], String> {
+    let encoded = match env::var("OPENKIMI_VAULT_KEY") {
+        Ok(value) if !value.trim().is_empty() => value,
+        _ if key_file.exists() => fs::read_to_string(key_file)
+            .map_err(|e| format!("读取主密钥文件 {} 失败: {}", key_file.display(), e))?,
+        _ => {
+            let key = random_bytes::<32>().map_err(|e| e.to_string())?;
+            write_private(key_file, STANDARD.encode(key).as_bytes())
+                .map_err(|e| format!("写入主密钥文件 {} 失败: {}", key_file.display(), e))?;
+            eprintln!("🔑 已生成密钥库主密钥: {}", key_file.display());
+            return Ok(key);
+        }
+    };
+    let bytes = STANDARD
+        .decode(encoded.trim())
+        .map_err(|_| "密钥库主密钥不是有效的Base64".to_string())?;
+    bytes
+        .try_into()
+        .map_err(|_| "密钥库主密钥必须是32字节".to_string())
+}
+
+/// 写入只有当前用户可读的文件，先写临时文件再重命名
+fn write_private(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, content)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(0o600))?;
+    }
+    fs::rename(&tmp, path)
+}
+
+impl KeyVault {
+    /// 打开密钥库并解密全部密钥，文件不存在时为空
+    pub fn open(config: &VaultConfig) -> Result<KeyVault, String> {
+        let master = load_master_key(&config.key_file)?;
+        let vault = KeyVault {
+            path: config.path.clone(),
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&master)),
+            entries: Mutex::new(Vec::new()),
+        };
+        let file: VaultFile = match fs::read(&vault.path) {
+            Ok(content) => serde_json::from_slice(&content)
+                .map_err(|e| format!("解析密钥库 {} 失败: {}", vault.path.display(), e))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vault),
+            Err(err) => return Err(format!("读取密钥库 {} 失败: {}", vault.path.display(), err)),
+        };
+        if file.version > VAULT_VERSION {
+            return Err(format!("密钥库格式 v{} 比当前程序支持的更新", file.version));
+        }
+        let entries = file
+            .keys
+            .into_iter()
+            .map(|stored| {
+                let secret = vault.decrypt(&stored)?;
+                Ok(Entry {
+                    stored,
+                    secret,
+                    health: Health::default(),
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        *vault.entries.lock().unwrap() = entries;
+        Ok(vault)
+    }
+
+    /// 密文与密钥id、服务商绑定，防止在文件中调换
+    fn encrypt(&self, id: &str, provider: &str, secret: &str) -> ApiResult<(String, String)> {
+        let nonce = random_bytes::<12>()?;
+        let aad = format!("{}:{}", provider, id);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: secret.as_bytes(),
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| ApiError::Internal("加密密钥失败".to_string()))?;
+        Ok((STANDARD.encode(nonce), STANDARD.encode(ciphertext)))
+    }
+
+    fn decrypt(&self, stored: &StoredKey) -> Result<String, String> {
+        let invalid = || format!("无法解密密钥 {}，主密钥不正确或密钥库已损坏", stored.id);
+        let nonce = STANDARD.decode(&stored.nonce).map_err(|_| invalid())?;
+        let ciphertext = STANDARD.decode(&stored.secret).map_err(|_| invalid())?;
+        if nonce.len() != 12 {
+            return Err(invalid());
+        }
+        let aad = format!("{}:{}", stored.provider, stored.id);
+        let plaintext = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &ciphertext,
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| invalid())?;
+        String::from_utf8(plaintext).map_err(|_| invalid())
+    }
+
+    fn save(&self, entries: &[Entry]) -> ApiResult<()> {
+        let file = VaultFile {
+            version: VAULT_VERSION,
+            keys: entries.iter().map(|entry| entry.stored.clone()).collect(),
+        };
+        let content = serde_json::to_vec_pretty(&file).map_err(|e| ApiError::Internal(e.to_string()))?;
+        write_private(&self.path, &content)
+            .map_err(|e| ApiError::Internal(format!("写入密钥库 {} 失败: {}", self.path.display(), e)))
+    }
+
+    /// 服务商已启用且未失效的密钥及其状态
+    pub fn keys(&self, provider: &str, now: Instant) -> Vec<(KeyLease, Snapshot)> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .filter(|entry| entry.stored.provider == provider && entry.stored.enabled && !entry.health.invalid())
+            .map(|entry| {
+                let lease = KeyLease {
+                    id: entry.stored.id.clone(),
+                    secret: entry.secret.clone(),
+                };
+                (lease, entry.health.snapshot(entry.stored.rpm, now))
+            })
+            .collect()
+    }
+
+    /// 记录一次使用，计入每分钟请求数
+    pub fn mark_used(&self, id: &str, now: Instant) {
+        if let Some(entry) = self.entries.lock().unwrap().iter_mut().find(|entry| entry.stored.id == id) {
+            entry.health.mark_used(now);
+        }
+    }
+
+    /// 报告一次请求的结果，更新密钥的健康状态
+    pub fn report(&self, id: &str, outcome: KeyOutcome) {
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.iter_mut().find(|entry| entry.stored.id == id) else {
+            return;
+        };
+        if entry.health.record(outcome) {
+            eprintln!(
+                "⚠️ 上游密钥 {} ({}) 请求失败: {}",
+                entry.stored.id,
+                mask(&entry.secret),
+                entry.health.last_error().unwrap_or_default()
+            );
+        }
+    }
+
+    pub fn list(&self) -> Vec<KeyInfo> {
+        self.entries.lock().unwrap().iter_mut().map(Entry::info).collect()
+    }
+
+    pub fn add(&self, provider: String, secret: String, label: Option<String>, rpm: Option<u32>) -> ApiResult<KeyInfo> {
+        if provider.is_empty() || secret.trim().is_empty() {
+            return Err(ApiError::invalid_request("provider 和 secret 不能为空"));
+        }
+        let id = format!("key_{}", random_bytes::<6>()?.iter().map(|b| format!("{:02x}", b)).collect::<String>());
+        let secret = secret.trim().to_string();
+        let (nonce, ciphertext) = self.encrypt(&id, &provider, &secret)?;
+        let mut entries = self.entries.lock().unwrap();
+        entries.push(Entry {
+            stored: StoredKey {
+                id,
+                provider,
+                label,
+                nonce,
+                secret: ciphertext,
+                enabled: true,
+                created_at: unix_time(),
+                rotated_at: None,
+                rpm,
+            },
+            secret,
+            health: Health::default(),
+        });
+        self.save(&entries)?;
+        Ok(entries.last_mut().unwrap().info())
+    }
+
+    fn update<F>(&self, id: &str, f: F) -> ApiResult<KeyInfo>
+    where
+        F: FnOnce(&mut Entry) -> ApiResult<()>,
+    {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries
+            .iter_mut()
+            .find(|entry| entry.stored.id == id)
+            .ok_or_else(|| ApiError::NotFound(format!("密钥 {} 不存在", id)))?;
+        f(entry)?;
+        let info = entry.info();
+        self.save(&entries)?;
+        Ok(info)
+    }
+
+    /// 启用或停用密钥，重新启用时清除失效和暂停状态
+    pub fn set_enabled(&self, id: &str, enabled: bool) -> ApiResult<KeyInfo> {
+        self.update(id, |entry| {
+            entry.stored.enabled = enabled;
+            if enabled {
+                entry.health = Health::default();
+            }
+            Ok(())
+        })
+    }
+
+    /// 替换密钥本身，保留id和服务商
+    pub fn rotate(&self, id: &str, secret: String) -> ApiResult<KeyInfo> {
+        if secret.trim().is_empty() {
+            return Err(ApiError::invalid_request("secret 不能为空"));
+        }
+        self.update(id, |entry| {
+            let secret = secret.trim().to_string();
+            let (nonce, ciphertext) = self.encrypt(&entry.stored.id, &entry.stored.provider, &secret)?;
+            entry.stored.nonce = nonce;
+            entry.stored.secret = ciphertext;
+            entry.stored.rotated_at = Some(unix_time());
+            entry.secret = secret;
+            entry.health = Health::default();
+            Ok(())
+        })
+    }
+
+    pub fn remove(&self, id: &str) -> ApiResult<()> {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|entry| entry.stored.id != id);
+        if entries.len() == before {
+            return Err(ApiError::NotFound(format!("密钥 {} 不存在", id)));
+        }
+        self.save(&entries)
+    }
+}
@@ -0,0 +1,306 @@
+//! 图片输入
+//!
+//! 对话消息的内容片段可以是`image_url`（`data:`URL中的base64图片或http(s)地址），也可以是引用已上传文件的`image_file`。
+//! 启用`vision`后按模型的限制检查每张图片的数量、格式、尺寸和大小：过大的图片等比缩小，上游不接受的格式转为PNG或JPEG，
+//! `image_file`换成`data:`URL再转发；无法处理的图片直接返回说明原因的错误，不把注定失败的请求发给上游。
+//! 明显过大的请求体和图片在读取或解码之前就拒绝。
+
+use std::sync::Arc;
+
+use axum::extract::{Request, State};
+use axum::http::header::CONTENT_LENGTH;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::{json, Value};
+
+use crate::config::{ImageFormat, VisionConfig, VisionLimits};
+use crate::error::{ApiError, ApiResult};
+use crate::image;
+use crate::types::{ChatCompletionRequest, MessageContent};
+use crate::workspace::Workspace;
+use crate::AppState;
+
+/// 超出`max_bytes`时最多缩小几次
+const MAX_SHRINK_ATTEMPTS: usize = 5;
+
+/// 按`Content-Length`拒绝超出`vision.max_request_bytes`的请求，不读取请求体
+pub async fn limit_body(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let limit = state.config().vision.max_request_bytes;
+    let length = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if let Some(length) = length.filter(|&length| length > limit) {
+        let message = format!("请求体为 {} 字节，超过 {} 字节的上限，请缩小或压缩图片", length, limit);
+        return ApiError::PayloadTooLarge(message).into_response();
+    }
+    next.run(request).await
+}
+
+/// 解码`data:image/png;base64,...`，返回声明的类型和图片；解码前按长度估算大小，明显过大时直接拒绝
+fn decode_data_url(url: &str, config: &VisionConfig, location: &str) -> ApiResult<(String, Vec<u8>)> {
+    let Some((header, payload)) = url["data:".len()..].split_once(',') else {
+        return Err(ApiError::invalid_request(format!("{}: 无效的 data: URL", location)));
+    };
+    if !header.ends_with(";base64") {
+        return Err(ApiError::invalid_request(format!("{}: data: URL 中的图片必须是 base64 编码", location)));
+    }
+    let estimated = payload.len() / 4 * 3;
+    if estimated > config.max_image_bytes {
+        return Err(ApiError::PayloadTooLarge(format!(
+            "{}: 图片约 {} 字节，超过 {} 字节的上限",
+            location, estimated, config.max_image_bytes
+        )));
+    }
+    let data = STANDARD
+        .decode(payload.trim())
+        .map_err(|e| ApiError::invalid_request(format!("{}: 图片的 base64 无效: {}", location, e)))?;
+    Ok((header.trim_end_matches(";base64").to_ascii_lowercase(), data))
+}
+
+/// 读取`image_file`引用的已上传文件
+fn read_file(state: &AppState, workspace: &Workspace, id: &str, location: &str) -> ApiResult<Vec<u8>> {
+    let Some(files) = &state.files else {
+        return Err(ApiError::invalid_request(format!(
+            "{}: 引用已上传的文件需要启用 files",
+            location
+        )));
+    };
+    let file = files.file(workspace, id)?;
+    if !file.mime_type.starts_with("image/") {
+        return Err(ApiError::invalid_request(format!(
+            "{}: 文件 {} 的类型为 {}，不是图片",
+            location, id, file.mime_type
+        )));
+    }
+    if file.bytes > state.config().vision.max_image_bytes as u64 {
+        return Err(ApiError::PayloadTooLarge(format!(
+            "{}: 文件 {} 为 {} 字节，超过 {} 字节的上限",
+            location, id, file.bytes, state.config().vision.max_image_bytes
+        )));
+    }
+    Ok(files.read(workspace, id)?.1)
+}
+
+/// 处理后的图片
+struct Processed {
+    format: ImageFormat,
+    data: Vec<u8>,
+    /// 是否缩小或重新编码过
+    changed: bool,
+}
+
+/// 按模型的限制检查一张图片，必要时缩小或转换格式
+fn process(data: Vec<u8>, config: &VisionConfig, limits: &VisionLimits, location: &str) -> ApiResult<Processed> {
+    let info = image::probe(&data).map_err(|e| ApiError::invalid_request(format!("{}: {}", location, e)))?;
+    let (width, height) = (info.width, info.height);
+    if u64::from(width) * u64::from(height) > config.max_pixels {
+        return Err(ApiError::PayloadTooLarge(format!(
+            "{}: 图片为 {}x{}，超过 {} 像素的上限",
+            location, width, height, config.max_pixels
+        )));
+    }
+
+    let longest = width.max(height);
+    let mut problems = Vec::new();
+    if longest > limits.max_dimension {
+        problems.push(format!("长边 {} 像素超过 {}", longest, limits.max_dimension));
+    }
+    if !limits.formats.contains(&info.format) {
+        problems.push(format!("上游不接受 {} 格式", info.format.name()));
+    }
+    if data.len() > limits.max_bytes {
+        problems.push(format!("{} 字节超过 {}", data.len(), limits.max_bytes));
+    }
+    if problems.is_empty() {
+        return Ok(Processed {
+            format: info.format,
+            data,
+            changed: false,
+        });
+    }
+    let problems = problems.join("，");
+    if !info.decodable {
+        return Err(ApiError::invalid_request(format!(
+            "{}: {}，服务端只能转换 PNG 和基线 JPEG 图片，请在客户端处理后重新提交",
+            location, problems
+        )));
+    }
+
+    let bitmap = image::decode(&data).map_err(|e| ApiError::invalid_request(format!("{}: {}", location, e)))?;
+    // 有透明度或原本是PNG（多为截图）时优先PNG，照片优先JPEG
+    let preferred = if info.format == ImageFormat::Png || bitmap.has_alpha() {
+        [ImageFormat::Png, ImageFormat::Jpeg]
+    } else {
+        [ImageFormat::Jpeg, ImageFormat::Png]
+    };
+    let candidates: Vec<ImageFormat> =
+        preferred.into_iter().filter(|format| limits.formats.contains(format)).collect();
+    if candidates.is_empty() {
+        return Err(ApiError::invalid_request(format!(
+            "{}: {}，而上游不接受服务端能生成的 PNG 和 JPEG",
+            location, problems
+        )));
+    }
+
+    // EXIF方向可能交换了宽高，以解码后的尺寸为准
+    let longest = bitmap.width.max(bitmap.height);
+    let mut side = longest.min(limits.max_dimension);
+    let mut smallest = side;
+    for _ in 0..MAX_SHRINK_ATTEMPTS {
+        smallest = side;
+        let scale = f64::from(side) / f64::from(longest);
+        let resized = (scale < 1.0).then(|| {
+            let width = (f64::from(bitmap.width) * scale).round().max(1.0) as u32;
+            let height = (f64::from(bitmap.height) * scale).round().max(1.0) as u32;
+            bitmap.resize(width, height)
+        });
+        let output = resized.as_ref().unwrap_or(&bitmap);
+        for &format in &candidates {
+            let encoded = image::encode(output, format, config.jpeg_quality)
+                .map_err(|e| ApiError::Internal(format!("{}: {}", location, e)))?;
+            if encoded.len() <= limits.max_bytes {
+                println!(
+                    "🖼️ {}: {}（{}x{} {}）→ {}x{} {}，{} 字节",
+                    location,
+                    problems,
+                    width,
+                    height,
+                    info.format.name(),
+                    output.width,
+                    output.height,
+                    format.name(),
+                    encoded.len()
+                );
+                return Ok(Processed {
+                    format,
+                    data: encoded,
+                    changed: true,
+                });
+            }
+        }
+        side = side * 3 / 4;
+    }
+    Err(ApiError::PayloadTooLarge(format!(
+        "{}: 缩小到长边 {} 像素后仍超过 {} 字节",
+        location, smallest, limits.max_bytes
+    )))
+}
+
+/// 一张待处理的图片
+struct Pending {
+    message: usize,
+    part: usize,
+    location: String,
+    /// `data:`URL中声明的类型，`image_file`为`None`
+    declared: Option<String>,
+    data: Vec<u8>,
+}
+
+/// 检查并改写请求中的图片，未启用`vision`时不做任何处理
+pub async fn prepare(state: &AppState, workspace: &Workspace, request: &mut ChatCompletionRequest) -> ApiResult<()> {
+    let settings = state.config();
+    let models = state.models();
+    let config = &settings.vision;
+    if !config.enabled {
+        return Ok(());
+    }
+    let model = if request.model.is_empty() {
+        settings.llm.model_name.as_str()
+    } else {
+        request.model.as_str()
+    };
+    let limits = models.route(model).vision.unwrap_or(&config.limits);
+
+    let mut count = 0;
+    let mut pending = Vec::new();
+    for (i, message) in request.messages.iter().enumerate() {
+        let Some(MessageContent::Parts(parts)) = &message.content else {
+            continue;
+        };
+        for (j, part) in parts.iter().enumerate() {
+            let location = format!("messages[{}].content[{}]", i, j);
+            let data = match part.get("type").and_then(Value::as_str) {
+                Some("image_url") => {
+                    let url = part["image_url"]["url"].as_str().or_else(|| part["image_url"].as_str());
+                    let Some(url) = url else {
+                        return Err(ApiError::invalid_request(format!("{}: 缺少 image_url.url", location)));
+                    };
+                    if url.starts_with("data:") {
+                        let (declared, data) = decode_data_url(url, config, &location)?;
+                        Some((Some(declared), data))
+                    } else if url.starts_with("http://") || url.starts_with("https://") {
+                        if !config.allow_remote_urls {
+                            return Err(ApiError::invalid_request(format!(
+                                "{}: 不允许图片地址，请以 base64 的 data: URL 或 image_file 提交图片",
+                                location
+                            )));
+                        }
+                        None
+                    } else {
+                        return Err(ApiError::invalid_request(format!(
+                            "{}: image_url.url 应为 data: URL 或 http(s) 地址",
+                            location
+                        )));
+                    }
+                }
+                Some("image_file") => {
+                    let Some(id) = part["image_file"]["file_id"].as_str() else {
+                        return Err(ApiError::invalid_request(format!("{}: 缺少 image_file.file_id", location)));
+                    };
+                    Some((None, read_file(state, workspace, id, &location)?))
+                }
+                _ => continue,
+            };
+            count += 1;
+            if let Some((declared, data)) = data {
+                pending.push(Pending {
+                    message: i,
+                    part: j,
+                    location,
+                    declared,
+                    data,
+                });
+            }
+        }
+    }
+    if count == 0 {
+        return Ok(());
+    }
+    if !limits.supported {
+        return Err(ApiError::invalid_request(format!("模型 {} 不支持图片输入", model)));
+    }
+    if count > limits.max_images {
+        return Err(ApiError::invalid_request(format!(
+            "模型 {} 每个请求最多 {} 张图片，请求中有 {} 张",
+            model, limits.max_images, count
+        )));
+    }
+
+    for image in pending {
+        let (config, limits, location) = (config.clone(), limits.clone(), image.location);
+        // 解码和缩放比较耗时，不占用异步运行时的线程
+        let processed = tokio::task::spawn_blocking(move || process(image.data, &config, &limits, &location))
+            .await
+            .map_err(|e| ApiError::Internal(format!("处理图片失败: {}", e)))??;
+        let Some(MessageContent::Parts(parts)) = &mut request.messages[image.message].content else {
+            continue;
+        };
+        let part = &mut parts[image.part];
+        // 未改动且声明的类型正确时保持原样
+        if !processed.changed && image.declared.as_deref() == Some(processed.format.mime_type()) {
+            continue;
+        }
+        let url = format!("data:{};base64,{}", processed.format.mime_type(), STANDARD.encode(&processed.data));
+        let detail = part["image_url"]["detail"].as_str().or_else(|| part["image_file"]["detail"].as_str());
+        let mut image_url = json!({ "url": url });
+        if let Some(detail) = detail {
+            image_url["detail"] = Value::from(detail);
+        }
+        *part = json!({ "type": "image_url", "image_url": image_url });
+    }
+    Ok(())
+}
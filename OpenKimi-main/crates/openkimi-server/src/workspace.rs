@@ -0,0 +1,327 @@
+//! 多租户工作区
+//!
+//! 每个请求属于一个工作区：启用认证时由令牌决定（工作区自己的令牌，或JWT中`jwt.workspace_claim`声明的工作区），
+//! 未启用认证时取`workspaces.header`请求头，都没有时为`default`。会话、向量索引和用量按工作区隔离，
+//! 一个工作区看不到其他工作区的数据。工作区可以配置每天和每月的配额，超出时返回429。
+//! 用量由后台线程定期写入`workspaces.usage_path`，重启后继续累计。
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::extract::{FromRequestParts, Request, State};
+use axum::http::request::Parts;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+
+use crate::audit;
+use crate::auth::Principal;
+use crate::config::{QuotaConfig, WorkspacesConfig};
+use crate::error::{ApiError, ApiResult};
+use crate::AppState;
+
+pub use openkimi_sessions::DEFAULT_WORKSPACE;
+
+/// 用量写回文件的间隔
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// 请求所属的工作区，由中间件放入请求扩展；未启用工作区时为默认工作区
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Workspace(pub String);
+
+impl Default for Workspace {
+    fn default() -> Self {
+        Workspace(DEFAULT_WORKSPACE.to_string())
+    }
+}
+
+impl Workspace {
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.0 == DEFAULT_WORKSPACE
+    }
+
+    /// 索引在存储中的名称；默认工作区保持原名，与启用工作区之前导入的索引兼容
+    pub fn index_name(&self, index: &str) -> String {
+        if self.is_default() {
+            index.to_string()
+        } else {
+            format!("{}__{}", self.0, index)
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Workspace {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts.extensions.get::<Workspace>().cloned().unwrap_or_default())
+    }
+}
+
+/// 工作区名只允许小写字母、数字和`-`，保证拼进索引名后不会与其他工作区的索引混淆
+pub fn validate_workspace_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name.len() <= 32
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("无效的工作区名: {}（只能使用小写字母、数字和 -，最长32个字符）", name))
+    }
+}
+
+/// 工作区的累计用量，按UTC的自然日和自然月归零
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UsageCounters {
+    /// 当前计数所在的日期，`YYYY-MM-DD`
+    pub day: String,
+    pub requests_today: u64,
+    pub tokens_today: u64,
+    /// 当前计数所在的月份，`YYYY-MM`
+    pub month: String,
+    pub requests_this_month: u64,
+    pub tokens_this_month: u64,
+    pub total_requests: u64,
+    pub total_tokens: u64,
+}
+
+impl UsageCounters {
+    /// 换日或换月时把对应的计数归零
+    fn roll(&mut self, day: &str) {
+        if self.day != day {
+            self.day = day.to_string();
+            self.requests_today = 0;
+            self.tokens_today = 0;
+        }
+        if self.month != day[..7] {
+            self.month = day[..7].to_string();
+            self.requests_this_month = 0;
+            self.tokens_this_month = 0;
+        }
+    }
+}
+
+/// 接口返回的工作区信息
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkspaceInfo {
+    pub name: String,
+    pub quota: QuotaConfig,
+    pub usage: UsageCounters,
+}
+
+#[derive(Debug, Default)]
+struct Usage {
+    counters: BTreeMap<String, UsageCounters>,
+    dirty: bool,
+}
+
+fn load_usage(path: &Path) -> Result<BTreeMap<String, UsageCounters>, String> {
+    match fs::read_to_string(path) {
+        Ok(content) => {
+            serde_json::from_str(&content).map_err(|e| format!("解析工作区用量文件 {} 失败: {}", path.display(), e))
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(err) => Err(format!("读取工作区用量文件 {} 失败: {}", path.display(), err)),
+    }
+}
+
+/// 先写临时文件再改名，写到一半退出也不会损坏已有的用量
+fn save_usage(path: &Path, counters: &BTreeMap<String, UsageCounters>) -> std::io::Result<()> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let temporary = path.with_extension("json.tmp");
+    fs::write(&temporary, serde_json::to_vec_pretty(counters)?)?;
+    fs::rename(&temporary, path)
+}
+
+/// 有变化时写入用量文件，失败时留待下次重试
+fn flush_usage(path: &Path, usage: &Mutex<Usage>) -> Result<(), String> {
+    let snapshot = {
+        let mut usage = usage.lock().unwrap();
+        if !usage.dirty {
+            return Ok(());
+        }
+        usage.dirty = false;
+        usage.counters.clone()
+    };
+    save_usage(path, &snapshot).map_err(|err| {
+        usage.lock().unwrap().dirty = true;
+        format!("写入工作区用量文件 {} 失败: {}", path.display(), err)
+    })
+}
+
+fn flush_loop(path: PathBuf, usage: Arc<Mutex<Usage>>) {
+    loop {
+        std::thread::sleep(FLUSH_INTERVAL);
+        if let Err(err) = flush_usage(&path, &usage) {
+            eprintln!("⚠️ {}", err);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Workspaces {
+    config: WorkspacesConfig,
+    usage: Arc<Mutex<Usage>>,
+}
+
+impl Workspaces {
+    pub fn new(config: &WorkspacesConfig) -> Result<Workspaces, String> {
+        for name in config.list.keys() {
+            validate_workspace_name(name)?;
+        }
+        let usage = Arc::new(Mutex::new(Usage {
+            counters: load_usage(&config.usage_path)?,
+            dirty: false,
+        }));
+        let path = config.usage_path.clone();
+        let flushed = Arc::clone(&usage);
+        std::thread::spawn(move || flush_loop(path, flushed));
+        Ok(Workspaces {
+            config: config.clone(),
+            usage,
+        })
+    }
+
+    /// 立即写入用量文件，退出前调用；会阻塞
+    pub fn flush(&self) -> Result<(), String> {
+        flush_usage(&self.config.usage_path, &self.usage)
+    }
+
+    /// `default`或`list`中配置的工作区
+    pub fn contains(&self, name: &str) -> bool {
+        name == DEFAULT_WORKSPACE || self.config.list.contains_key(name)
+    }
+
+    /// 请求所属的工作区；认证得到的工作区优先，未认证时才看请求头
+    pub fn resolve(&self, principal: Option<&Principal>, header: Option<&str>) -> ApiResult<Workspace> {
+        let name = match principal {
+            Some(principal) => principal.workspace.as_deref(),
+            None => header.map(str::trim).filter(|name| !name.is_empty()),
+        }
+        .unwrap_or(DEFAULT_WORKSPACE);
+        if !self.contains(name) {
+            return Err(ApiError::Forbidden(format!("工作区 {} 不存在", name)));
+        }
+        Ok(Workspace(name.to_string()))
+    }
+
+    fn quota(&self, workspace: &Workspace) -> QuotaConfig {
+        self.config
+            .list
+            .get(workspace.name())
+            .map(|config| config.quota.clone())
+            .unwrap_or_default()
+    }
+
+    /// 检查配额并计入一次请求；token配额在用完后才拒绝，单个请求可能使用量略超配额
+    pub fn acquire(&self, workspace: &Workspace) -> ApiResult<()> {
+        let quota = self.quota(workspace);
+        let day = audit::today();
+        let mut usage = self.usage.lock().unwrap();
+        let counters = usage.counters.entry(workspace.0.clone()).or_default();
+        counters.roll(&day);
+        let exceeded = [
+            (quota.requests_per_day, counters.requests_today, "每日请求数"),
+            (quota.tokens_per_day, counters.tokens_today, "每日token数"),
+            (quota.tokens_per_month, counters.tokens_this_month, "每月token数"),
+        ]
+        .into_iter()
+        .find(|(limit, used, _)| limit.is_some_and(|limit| *used >= limit));
+        if let Some((limit, _, name)) = exceeded {
+            return Err(ApiError::RateLimited(format!(
+                "工作区 {} 已用完{}配额（{}）",
+                workspace.name(),
+                name,
+                limit.unwrap_or_default()
+            )));
+        }
+        counters.requests_today += 1;
+        counters.requests_this_month += 1;
+        counters.total_requests += 1;
+        usage.dirty = true;
+        Ok(())
+    }
+
+    /// 计入请求使用的token
+    pub fn record_tokens(&self, workspace: &Workspace, tokens: u32) {
+        let day = audit::today();
+        let mut usage = self.usage.lock().unwrap();
+        let counters = usage.counters.entry(workspace.0.clone()).or_default();
+        counters.roll(&day);
+        counters.tokens_today += tokens as u64;
+        counters.tokens_this_month += tokens as u64;
+        counters.total_tokens += tokens as u64;
+        usage.dirty = true;
+    }
+
+    pub fn info(&self, name: &str) -> WorkspaceInfo {
+        let day = audit::today();
+        let mut counters = self.usage.lock().unwrap().counters.get(name).cloned().unwrap_or_default();
+        counters.roll(&day);
+        WorkspaceInfo {
+            name: name.to_string(),
+            quota: self.quota(&Workspace(name.to_string())),
+            usage: counters,
+        }
+    }
+
+    /// 全部工作区，按名称排序，`default`在前
+    pub fn list(&self) -> Vec<WorkspaceInfo> {
+        std::iter::once(DEFAULT_WORKSPACE)
+            .chain(self.config.list.keys().map(String::as_str).filter(|name| *name != DEFAULT_WORKSPACE))
+            .map(|name| self.info(name))
+            .collect()
+    }
+}
+
+/// 检查工作区配额，未启用工作区时什么也不做
+pub fn acquire(state: &AppState, workspace: &Workspace) -> ApiResult<()> {
+    match &state.workspaces {
+        Some(workspaces) => workspaces.acquire(workspace),
+        None => Ok(()),
+    }
+}
+
+/// 计入工作区的token用量，未启用工作区时什么也不做
+pub fn charge(state: &AppState, workspace: &Workspace, tokens: u32) {
+    if let Some(workspaces) = &state.workspaces {
+        workspaces.record_tokens(workspace, tokens);
+    }
+}
+
+/// 确定请求所属的工作区并检查配额，未启用工作区时直接放行
+pub async fn scope(State(state): State<Arc<AppState>>, mut request: Request, next: Next) -> Response {
+    let Some(workspaces) = &state.workspaces else {
+        return next.run(request).await;
+    };
+    let header = request
+        .headers()
+        .get(workspaces.config.header.as_str())
+        .and_then(|value| value.to_str().ok());
+    let workspace = match workspaces.resolve(request.extensions().get::<Principal>(), header) {
+        Ok(workspace) => workspace,
+        Err(err) => return err.into_response(),
+    };
+    // 取模型列表等不消耗上游额度的请求也计入请求数，与限流一致
+    let mut response = match workspaces.acquire(&workspace) {
+        Ok(()) => {
+            request.extensions_mut().insert(workspace.clone());
+            next.run(request).await
+        }
+        Err(err) => err.into_response(),
+    };
+    // 供外层的审计中间件记录租户
+    response.extensions_mut().insert(workspace);
+    response
+}
@@ -0,0 +1,251 @@
+//! WebSocket对话通道`GET /v1/chat/ws`
+//!
+//! 与SSE相比，同一连接上可以并发多个生成、随时中止其中之一，并收发输入状态。
+//! 每一帧都是一个JSON文本消息，按`type`区分：
+//!
+//! 客户端发送：
+//! - `{"type":"chat","id":"r1","request":{...}}`：开始生成，`request`与`/v1/chat/completions`相同，`stream`总是视为true
+//! - `{"type":"abort","id":"r1"}`：中止生成
+//! - `{"type":"typing","state":true}`：用户正在输入，服务端回显确认，供多端同步显示
+//! - `{"type":"ping"}`：保活
+//!
+//! 服务端发送：
+//! - `{"type":"chunk","id":"r1","data":{...}}`：与SSE中的`chat.completion.chunk`相同
+//! - `{"type":"done","id":"r1","reason":"stop"|"aborted"}`：生成结束
+//! - `{"type":"error","id":"r1","error":{...}}`：OpenAI格式的错误，`id`可能为null
+//! - `{"type":"typing","state":true}`、`{"type":"pong"}`
+//!
+//! 完整说明见`docs/api/websocket.md`，Electron客户端使用同一协议。
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::response::Response;
+use axum::Extension;
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use tokio::task::AbortHandle;
+
+use crate::error::ApiError;
+use crate::metering::{self, Consumer};
+use crate::memory;
+use crate::pii;
+use crate::prompts;
+use crate::quotas::{self, Slot};
+use crate::rag;
+use crate::ratelimit::{self, RateLimitKey};
+use crate::safety;
+use crate::workspace;
+use crate::structured;
+use crate::tools::ToolScope;
+use crate::types::ChatCompletionRequest;
+use crate::vision;
+use crate::AppState;
+
+/// 客户端消息
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    Chat { id: String, request: ChatCompletionRequest },
+    Abort { id: String },
+    Typing { state: bool },
+    Ping,
+}
+
+/// 进行中的生成，按请求id索引
+type Generations = Arc<Mutex<HashMap<String, AbortHandle>>>;
+
+/// 建立连接算一次请求，之后每个`chat`消息再各算一次
+pub async fn chat_socket(
+    State(state): State<Arc<AppState>>,
+    limit_key: Option<Extension<RateLimitKey>>,
+    consumer: Consumer,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let caller = Caller {
+        limit_key: limit_key.map(|Extension(key)| key),
+        consumer,
+    };
+    upgrade.on_upgrade(move |socket| handle_socket(state, caller, socket))
+}
+
+/// 连接所属的限流对象、工作区和计量对象，握手时确定
+#[derive(Debug, Clone)]
+struct Caller {
+    limit_key: Option<RateLimitKey>,
+    consumer: Consumer,
+}
+
+/// 检查限流额度、工作区配额和用户配额，超出时返回错误消息；返回的并发名额在生成结束时归还
+async fn check_limit(state: &AppState, caller: &Caller) -> Result<Option<Slot>, ApiError> {
+    if let (Some(limiter), Some(key)) = (state.rate_limiter().as_ref(), &caller.limit_key) {
+        limiter.acquire(key).await.map_err(|(wait, _)| ratelimit::rate_limited(wait))?;
+    }
+    workspace::acquire(state, &caller.consumer.workspace)?;
+    Ok(quotas::acquire(state, &caller.consumer)?)
+}
+
+fn error_frame(id: Option<&str>, err: ApiError) -> Value {
+    let (_, body) = err.into_parts();
+    json!({ "type": "error", "id": id, "error": body["error"] })
+}
+
+async fn handle_socket(state: Arc<AppState>, caller: Caller, socket: WebSocket) {
+    let (mut sink, mut incoming) = socket.split();
+    let (tx, mut rx) = mpsc::channel::<Value>(64);
+    let generations: Generations = Arc::default();
+    // 升级后的连接不受HTTP服务跟踪，退出时另外等待连接关闭
+    let _hold = state.shutdown.hold();
+
+    // 所有发送都经由同一个通道，避免多个生成任务并发写入
+    let writer = tokio::spawn(async move {
+        while let Some(frame) = rx.recv().await {
+            if sink.send(Message::Text(frame.to_string().into())).await.is_err() {
+                return;
+            }
+        }
+        let _ = sink.close().await;
+    });
+
+    // 服务退出时等进行中的生成结束后关闭连接
+    let drained = async {
+        state.shutdown.wait().await;
+        while !generations.lock().unwrap().is_empty() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    tokio::pin!(drained);
+
+    loop {
+        let message = tokio::select! {
+            message = incoming.next() => message,
+            () = &mut drained => break,
+        };
+        let Some(Ok(message)) = message else {
+            break;
+        };
+        let text = match message {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        let reply = match serde_json::from_str::<ClientMessage>(&text) {
+            Ok(ClientMessage::Chat { id, .. }) if state.shutdown.is_draining() => {
+                Some(error_frame(Some(&id), ApiError::Unavailable("服务正在退出，请稍后重新连接".to_string())))
+            }
+            Ok(ClientMessage::Chat { id, request }) => match check_limit(&state, &caller).await {
+                Ok(slot) => {
+                    start_generation(&state, &generations, &tx, caller.clone(), id, request, slot);
+                    None
+                }
+                Err(err) => Some(error_frame(Some(&id), err)),
+            },
+            Ok(ClientMessage::Abort { id }) => {
+                let handle = generations.lock().unwrap().remove(&id);
+                handle.map(|handle| {
+                    handle.abort();
+                    json!({ "type": "done", "id": id, "reason": "aborted" })
+                })
+            }
+            Ok(ClientMessage::Typing { state }) => Some(json!({ "type": "typing", "state": state })),
+            Ok(ClientMessage::Ping) => Some(json!({ "type": "pong" })),
+            Err(err) => Some(error_frame(None, ApiError::invalid_request(format!("无效的消息: {}", err)))),
+        };
+        if let Some(reply) = reply {
+            if tx.send(reply).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    // 连接关闭后中止所有仍在进行的生成，上游请求随之取消
+    for (_, handle) in generations.lock().unwrap().drain() {
+        handle.abort();
+    }
+    drop(tx);
+    let _ = writer.await;
+}
+
+fn start_generation(
+    state: &Arc<AppState>,
+    generations: &Generations,
+    tx: &mpsc::Sender<Value>,
+    caller: Caller,
+    id: String,
+    request: ChatCompletionRequest,
+    slot: Option<Slot>,
+) {
+    let state = Arc::clone(state);
+    let tx = tx.clone();
+    let task_generations = Arc::clone(generations);
+    let task_id = id.clone();
+    // 持有锁直到登记完成，避免任务过快结束时先删除、后登记
+    let mut running = generations.lock().unwrap();
+    if running.contains_key(&id) {
+        let frame = error_frame(Some(&id), ApiError::invalid_request("该 id 的生成仍在进行"));
+        let _ = tx.try_send(frame);
+        return;
+    }
+
+    let task = tokio::spawn(async move {
+        let _slot = slot;
+        let frame = match generate(&state, &tx, &caller, &task_id, request).await {
+            Ok(()) => json!({ "type": "done", "id": task_id, "reason": "stop" }),
+            Err(err) => error_frame(Some(&task_id), err),
+        };
+        task_generations.lock().unwrap().remove(&task_id);
+        let _ = tx.send(frame).await;
+    });
+    running.insert(id, task.abort_handle());
+}
+
+/// 把上游的流式输出逐块转为`chunk`消息
+async fn generate(
+    state: &Arc<AppState>,
+    tx: &mpsc::Sender<Value>,
+    caller: &Caller,
+    id: &str,
+    mut request: ChatCompletionRequest,
+) -> Result<(), ApiError> {
+    prompts::apply(state, &caller.consumer.workspace, &mut request)?;
+    let memory = memory::recall(state, &caller.consumer, &mut request).await?;
+    rag::augment(state, &caller.consumer.workspace, &mut request).await?;
+    safety::screen_request(state, &caller.consumer.workspace, &mut request);
+    let scope = ToolScope::take(&caller.consumer.workspace, &mut request)?;
+    vision::prepare(state, &caller.consumer.workspace, &mut request).await?;
+    // 上下文压缩可能要先请求上游生成摘要，放在任务里进行，期间同样可以中止
+    let placeholders = pii::tokenize(state, &mut request).await?;
+    let prompt_tokens = state.prepare_chat(&mut request).await?;
+    request.stream = Some(true);
+
+    let data = structured::chat_completion_stream(state, &request, &scope).await?;
+    if let Some(memory) = memory {
+        memory.extract(state);
+    }
+    let tokens = state.stream_tokens(&request, prompt_tokens);
+    ratelimit::charge(state, caller.limit_key.as_ref(), tokens);
+    workspace::charge(state, &caller.consumer.workspace, tokens);
+    let data = placeholders.restore_stream(data);
+    let data = safety::screen_stream(state, &caller.consumer.workspace, &request.model, data);
+    let mut events = metering::meter_stream(state, &caller.consumer, &request.model, prompt_tokens, data);
+    while let Some(data) = events.next().await {
+        if data == "[DONE]" {
+            break;
+        }
+        let data: Value = serde_json::from_str(&data)
+            .map_err(|e| ApiError::Upstream(format!("无法解析上游分块: {}", e)))?;
+        if let Some(error) = data.get("error") {
+            return Err(ApiError::Upstream(error["message"].as_str().unwrap_or("上游流式响应中断").to_string()));
+        }
+        // 发送端关闭说明连接已断开，结束生成
+        if tx.send(json!({ "type": "chunk", "id": id, "data": data })).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
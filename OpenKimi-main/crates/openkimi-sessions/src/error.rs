@@ -0,0 +1,83 @@
+use std::fmt;
+
+use openkimi_schema::SchemaError;
+
+/// 会话存储错误
+#[derive(Debug)]
+pub enum SessionError {
+    /// 读写数据库失败
+    Sqlite(rusqlite::Error),
+    /// 会话或消息不存在
+    NotFound(String),
+    /// 参数或导入的会话文档格式不对
+    Invalid(String),
+    /// 数据库由更新版本的程序创建，或者不能迁移到指定的版本
+    Schema(SchemaError),
+    /// 读写PostgreSQL失败
+    #[cfg(feature = "postgres")]
+    Postgres(openkimi_postgres::PgError),
+    /// 读写Redis失败
+    #[cfg(feature = "redis")]
+    Redis(openkimi_redis::RedisError),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::Sqlite(err) => write!(f, "读写会话数据库失败: {}", err),
+            SessionError::NotFound(what) => write!(f, "{} 不存在", what),
+            SessionError::Invalid(reason) => f.write_str(reason),
+            SessionError::Schema(err) => err.fmt(f),
+            #[cfg(feature = "postgres")]
+            SessionError::Postgres(err) => write!(f, "读写会话数据库失败: {}", err),
+            #[cfg(feature = "redis")]
+            SessionError::Redis(err) => write!(f, "读写会话存储失败: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+impl From<rusqlite::Error> for SessionError {
+    fn from(err: rusqlite::Error) -> SessionError {
+        SessionError::Sqlite(err)
+    }
+}
+
+impl From<serde_json::Error> for SessionError {
+    fn from(err: serde_json::Error) -> SessionError {
+        SessionError::Invalid(format!("无效的JSON: {}", err))
+    }
+}
+
+impl From<SchemaError> for SessionError {
+    fn from(err: SchemaError) -> SessionError {
+        match err {
+            SchemaError::Sqlite(err) => SessionError::Sqlite(err),
+            #[cfg(feature = "postgres")]
+            SchemaError::Postgres(err) => SessionError::Postgres(err.into()),
+            err => SessionError::Schema(err),
+        }
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl From<openkimi_postgres::PgError> for SessionError {
+    fn from(err: openkimi_postgres::PgError) -> SessionError {
+        SessionError::Postgres(err)
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl From<openkimi_postgres::postgres::Error> for SessionError {
+    fn from(err: openkimi_postgres::postgres::Error) -> SessionError {
+        SessionError::Postgres(err.into())
+    }
+}
+
+#[cfg(feature = "redis")]
+impl From<openkimi_redis::RedisError> for SessionError {
+    fn from(err: openkimi_redis::RedisError) -> SessionError {
+        SessionError::Redis(err)
+    }
+}
@@ -0,0 +1,383 @@
+//! 会话文档的导出格式
+//!
+//! [`SessionStore::export`](crate::SessionStore::export)生成的会话文档可以转成Markdown或HTML，服务端的导出接口和
+//! 终端客户端共用这里的代码。消息内容本身就是Markdown，原样保留其中的代码块；附件列出名称、类型、大小和位置，
+//! 文件本身不导出；助手调用的工具以JSON代码块列出。HTML是不引用外部资源的单个页面，消息中的HTML按文本显示，
+//! 链接只保留http、https和mailto。也接受客户端的会话文档：`content`可以是内容片段数组，消息可以没有时间和附件。
+
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+use serde_json::Value;
+
+use crate::time;
+
+/// 导出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// 会话文档本身，可以再导入
+    Json,
+    Markdown,
+    Html,
+}
+
+impl Format {
+    /// `json`、`md`（`markdown`）或`html`
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name.to_ascii_lowercase().as_str() {
+            "json" => Some(Format::Json),
+            "md" | "markdown" => Some(Format::Markdown),
+            "html" | "htm" => Some(Format::Html),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Markdown => "md",
+            Format::Html => "html",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Markdown => "text/markdown; charset=utf-8",
+            Format::Html => "text/html; charset=utf-8",
+        }
+    }
+}
+
+/// 按格式输出会话文档，以换行结尾
+pub fn render(document: &Value, format: Format) -> String {
+    match format {
+        Format::Json => format!("{}\n", serde_json::to_string_pretty(document).unwrap_or_default()),
+        Format::Markdown => markdown(document),
+        Format::Html => html(document),
+    }
+}
+
+/// Unix秒格式化为`YYYY-MM-DD HH:MM UTC`
+pub(crate) fn format_utc(seconds: i64) -> String {
+    format!("{} UTC", time::format_time(seconds))
+}
+
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1_048_575 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+pub(crate) fn title(document: &Value) -> &str {
+    [&document["title"], &document["id"]]
+        .into_iter()
+        .filter_map(Value::as_str)
+        .map(str::trim)
+        .find(|title| !title.is_empty())
+        .unwrap_or("对话")
+}
+
+/// 标题下列出的会话信息
+pub(crate) fn details(document: &Value) -> Vec<(&'static str, String)> {
+    let mut details = Vec::new();
+    if let Some(id) = document["id"].as_str() {
+        details.push(("会话", id.to_string()));
+    }
+    let model = document["model"].as_str().filter(|model| !model.is_empty()).unwrap_or("服务端默认");
+    details.push(("模型", model.to_string()));
+    if let Some(created) = document["created_at"].as_i64() {
+        details.push(("创建时间", format_utc(created)));
+    }
+    if let Some(updated) = document["updated_at"].as_i64() {
+        details.push(("更新时间", format_utc(updated)));
+    }
+    let usage = &document["usage"];
+    if let (Some(prompt), Some(completion)) = (usage["prompt_tokens"].as_u64(), usage["completion_tokens"].as_u64()) {
+        if prompt + completion > 0 {
+            details.push(("用量", format!("输入 {} / 输出 {} token", prompt, completion)));
+        }
+    }
+    details
+}
+
+fn role_name(role: &str) -> &str {
+    match role {
+        "user" => "用户",
+        "assistant" => "助手",
+        "system" => "系统",
+        "tool" => "工具",
+        other => other,
+    }
+}
+
+/// 消息的文本，内容片段中的图片显示为`[图片]`
+fn text(message: &Value) -> String {
+    match &message["content"] {
+        Value::String(text) => text.trim_end().to_string(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| match part["type"].as_str() {
+                Some("text") => part["text"].as_str().map(|text| text.trim_end().to_string()),
+                Some("image_url" | "image_file") => Some("[图片]".to_string()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        _ => String::new(),
+    }
+}
+
+/// 附件的说明，如`report.pdf（application/pdf，1.2 MB）`，以及位置
+fn attachments(message: &Value) -> Vec<(String, Option<&str>)> {
+    let Some(attachments) = message["attachments"].as_array() else {
+        return Vec::new();
+    };
+    attachments
+        .iter()
+        .map(|attachment| {
+            let name = attachment["name"].as_str().unwrap_or("附件");
+            let details: Vec<String> = [
+                attachment["mime_type"].as_str().map(str::to_string),
+                attachment["size"].as_u64().map(format_size),
+            ]
+            .into_iter()
+            .flatten()
+            .collect();
+            let description = if details.is_empty() {
+                name.to_string()
+            } else {
+                format!("{}（{}）", name, details.join("，"))
+            };
+            (description, attachment["uri"].as_str())
+        })
+        .collect()
+}
+
+/// 助手调用的工具，`(名称, 参数)`，参数整理为缩进的JSON
+fn tool_calls(message: &Value) -> Vec<(&str, String)> {
+    let Some(calls) = message["tool_calls"].as_array() else {
+        return Vec::new();
+    };
+    calls
+        .iter()
+        .map(|call| {
+            let function = &call["function"];
+            let arguments = function["arguments"].as_str().unwrap_or("");
+            let arguments = serde_json::from_str::<Value>(arguments)
+                .ok()
+                .and_then(|value| serde_json::to_string_pretty(&value).ok())
+                .unwrap_or_else(|| arguments.to_string());
+            (function["name"].as_str().unwrap_or("工具"), arguments)
+        })
+        .collect()
+}
+
+fn messages(document: &Value) -> &[Value] {
+    document["messages"].as_array().map_or(&[], Vec::as_slice)
+}
+
+/// 比内容中最长的连续反引号多一个，至少三个
+fn fence(text: &str) -> String {
+    let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    "`".repeat((longest + 1).max(3))
+}
+
+/// 导出为Markdown
+pub fn markdown(document: &Value) -> String {
+    let mut text = format!("# {}\n\n", title(document));
+    for (name, value) in details(document) {
+        text.push_str(&format!("- {}：{}\n", name, value));
+    }
+    text.push('\n');
+    text.push_str(&transcript(document));
+    text
+}
+
+/// 系统提示词和各条消息的Markdown，每条消息一个二级标题
+pub(crate) fn transcript(document: &Value) -> String {
+    let mut text = String::new();
+    if let Some(system) = document["metadata"]["system"].as_str() {
+        text.push_str(&format!("## 系统提示词\n\n{}\n\n", system.trim_end()));
+    }
+    for message in messages(document) {
+        let role = role_name(message["role"].as_str().unwrap_or(""));
+        match message["created_at"].as_i64() {
+            Some(created) => text.push_str(&format!("## {} · {}\n\n", role, format_utc(created))),
+            None => text.push_str(&format!("## {}\n\n", role)),
+        }
+        let content = self::text(message);
+        if !content.is_empty() {
+            text.push_str(&format!("{}\n\n", content));
+        }
+        for (name, arguments) in tool_calls(message) {
+            let fence = fence(&arguments);
+            text.push_str(&format!("调用工具 `{}`：\n\n{}json\n{}\n{}\n\n", name, fence, arguments, fence));
+        }
+        let attachments = attachments(message);
+        for (description, uri) in &attachments {
+            match uri {
+                Some(uri) => text.push_str(&format!("- 📎 {}：`{}`\n", description, uri)),
+                None => text.push_str(&format!("- 📎 {}\n", description)),
+            }
+        }
+        if !attachments.is_empty() {
+            text.push('\n');
+        }
+    }
+    text
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// 没有协议的相对地址，或http、https、mailto链接
+fn safe_url(url: &str) -> bool {
+    let lower = url.trim().to_ascii_lowercase();
+    ["http://", "https://", "mailto:"].iter().any(|scheme| lower.starts_with(scheme))
+        || !lower.split(['/', '?', '#']).next().unwrap_or("").contains(':')
+}
+
+/// 把消息中的Markdown转成HTML，标题从`<h3>`开始，原始HTML按文本显示
+fn markdown_to_html(text: &str, html: &mut String) {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    // 每个开始标签对应的结束标签
+    let mut closing: Vec<String> = Vec::new();
+    let mut in_head = false;
+    for event in Parser::new_ext(text, options) {
+        match event {
+            Event::Start(tag) => {
+                let (open, close) = match tag {
+                    Tag::Paragraph | Tag::HtmlBlock => ("<p>".to_string(), "</p>\n".to_string()),
+                    Tag::Heading { level, .. } => {
+                        let level = (level as usize + 2).min(6);
+                        (format!("<h{}>", level), format!("</h{}>\n", level))
+                    }
+                    Tag::BlockQuote(_) => ("<blockquote>\n".to_string(), "</blockquote>\n".to_string()),
+                    Tag::CodeBlock(CodeBlockKind::Fenced(info)) if !info.trim().is_empty() => {
+                        let language = info.split_whitespace().next().unwrap_or("");
+                        (format!("<pre><code class=\"language-{}\">", escape(language)), "</code></pre>\n".to_string())
+                    }
+                    Tag::CodeBlock(_) => ("<pre><code>".to_string(), "</code></pre>\n".to_string()),
+                    Tag::List(Some(1)) => ("<ol>\n".to_string(), "</ol>\n".to_string()),
+                    Tag::List(Some(start)) => (format!("<ol start=\"{}\">\n", start), "</ol>\n".to_string()),
+                    Tag::List(None) => ("<ul>\n".to_string(), "</ul>\n".to_string()),
+                    Tag::Item => ("<li>".to_string(), "</li>\n".to_string()),
+                    Tag::Table(_) => ("<table>\n".to_string(), "</table>\n".to_string()),
+                    Tag::TableHead => {
+                        in_head = true;
+                        ("<thead><tr>".to_string(), "</tr></thead>\n".to_string())
+                    }
+                    Tag::TableRow => ("<tr>".to_string(), "</tr>\n".to_string()),
+                    Tag::TableCell if in_head => ("<th>".to_string(), "</th>".to_string()),
+                    Tag::TableCell => ("<td>".to_string(), "</td>".to_string()),
+                    Tag::Emphasis => ("<em>".to_string(), "</em>".to_string()),
+                    Tag::Strong => ("<strong>".to_string(), "</strong>".to_string()),
+                    Tag::Strikethrough => ("<del>".to_string(), "</del>".to_string()),
+                    // 图片不加载，显示为指向它的链接
+                    Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. } if safe_url(&dest_url) => {
+                        (format!("<a href=\"{}\">", escape(&dest_url)), "</a>".to_string())
+                    }
+                    _ => (String::new(), String::new()),
+                };
+                html.push_str(&open);
+                closing.push(close);
+            }
+            Event::End(end) => {
+                if end == TagEnd::TableHead {
+                    in_head = false;
+                }
+                html.push_str(&closing.pop().unwrap_or_default());
+            }
+            Event::Text(text) | Event::Html(text) | Event::InlineHtml(text) => html.push_str(&escape(&text)),
+            Event::Code(code) | Event::InlineMath(code) | Event::DisplayMath(code) => {
+                html.push_str(&format!("<code>{}</code>", escape(&code)))
+            }
+            Event::FootnoteReference(name) => html.push_str(&format!("[^{}]", escape(&name))),
+            Event::SoftBreak => html.push('\n'),
+            Event::HardBreak => html.push_str("<br>\n"),
+            Event::Rule => html.push_str("<hr>\n"),
+            Event::TaskListMarker(checked) => {
+                html.push_str(if checked { "<input type=\"checkbox\" disabled checked> " } else { "<input type=\"checkbox\" disabled> " })
+            }
+        }
+    }
+}
+
+const STYLE: &str = "body{max-width:52rem;margin:2rem auto;padding:0 1rem;font:16px/1.6 system-ui,-apple-system,\
+\"Segoe UI\",\"PingFang SC\",\"Microsoft YaHei\",sans-serif;color:#1f2328}\
+header ul{list-style:none;padding:0;color:#59636e;font-size:.9rem}\
+section{border-top:1px solid #d1d9e0;padding:.5rem 0}\
+h2{font-size:1rem;margin:.5rem 0}h2 time{font-weight:normal;color:#59636e;font-size:.85rem;margin-left:.5rem}\
+.user h2{color:#0969da}.assistant h2{color:#1a7f37}.system h2,.tool h2{color:#9a6700}\
+pre{background:#f6f8fa;padding:.75rem;overflow:auto;border-radius:6px}code{font-family:ui-monospace,Menlo,Consolas,monospace;font-size:.9em}\
+table{border-collapse:collapse}th,td{border:1px solid #d1d9e0;padding:.25rem .5rem}\
+blockquote{margin:0;padding-left:1rem;border-left:3px solid #d1d9e0;color:#59636e}\
+.attachments{padding-left:0;list-style:none;color:#59636e;font-size:.9rem}";
+
+/// 导出为单个HTML页面
+pub fn html(document: &Value) -> String {
+    let title = escape(title(document));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html lang=\"zh-CN\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n<title>{}</title>\n\
+         <style>{}</style>\n</head>\n<body>\n<header>\n<h1>{}</h1>\n<ul>\n",
+        title, STYLE, title
+    );
+    for (name, value) in details(document) {
+        html.push_str(&format!("<li>{}：{}</li>\n", name, escape(&value)));
+    }
+    html.push_str("</ul>\n</header>\n");
+    if let Some(system) = document["metadata"]["system"].as_str() {
+        html.push_str("<section class=\"system\">\n<h2>系统提示词</h2>\n");
+        markdown_to_html(system, &mut html);
+        html.push_str("</section>\n");
+    }
+    for message in messages(document) {
+        let role = message["role"].as_str().unwrap_or("");
+        let class: String = role.chars().filter(char::is_ascii_alphanumeric).collect();
+        html.push_str(&format!("<section class=\"{}\">\n<h2>{}", class, escape(role_name(role))));
+        if let Some(created) = message["created_at"].as_i64() {
+            html.push_str(&format!("<time>{}</time>", format_utc(created)));
+        }
+        html.push_str("</h2>\n");
+        markdown_to_html(&text(message), &mut html);
+        for (name, arguments) in tool_calls(message) {
+            html.push_str(&format!(
+                "<p>调用工具 <code>{}</code>：</p>\n<pre><code class=\"language-json\">{}</code></pre>\n",
+                escape(name),
+                escape(&arguments)
+            ));
+        }
+        let attachments = attachments(message);
+        if !attachments.is_empty() {
+            html.push_str("<ul class=\"attachments\">\n");
+            for (description, uri) in attachments {
+                match uri {
+                    Some(uri) => html.push_str(&format!(
+                        "<li>📎 {}：<code>{}</code></li>\n",
+                        escape(&description),
+                        escape(uri)
+                    )),
+                    None => html.push_str(&format!("<li>📎 {}</li>\n", escape(&description))),
+                }
+            }
+            html.push_str("</ul>\n");
+        }
+        html.push_str("</section>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
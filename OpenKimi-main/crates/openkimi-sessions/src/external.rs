@@ -0,0 +1,412 @@
+//! 从其他产品导出的对话
+//!
+//! 支持ChatGPT的数据导出（`conversations.json`或整个zip）和Claude的数据导出（zip或其中的`conversations.json`），
+//! 按内容自动识别。每个对话转成一棵消息树：ChatGPT按`mapping`中的父子关系，Claude的消息有`parent_message_uuid`时
+//! 按它组成分支，没有时按顺序连成一个分支。用户和助手的文本、代码和时间保留下来；工具的调用结果、隐藏的消息和
+//! 推理过程不导入，它们的后续消息接到最近的保留下来的消息之后。附件保留名称、类型和大小，Claude附件中提取的
+//! 文字按终端客户端的格式接在消息之后。导入见[`SessionStore::import_external`](crate::SessionStore::import_external)。
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Cursor, Read};
+
+use serde_json::{json, Map, Value};
+
+use crate::time::days_from_civil;
+use crate::{NewAttachment, NewMessage, SessionError};
+
+/// 导出对话的产品
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    ChatGpt,
+    Claude,
+}
+
+impl Source {
+    pub fn name(self) -> &'static str {
+        match self {
+            Source::ChatGpt => "chatgpt",
+            Source::Claude => "claude",
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Source::ChatGpt => "ChatGPT",
+            Source::Claude => "Claude",
+        })
+    }
+}
+
+/// 消息树中的一条消息
+#[derive(Debug, Clone)]
+pub struct Node {
+    /// 父消息在[`Conversation::messages`]中的位置，第一条消息为`None`
+    pub parent: Option<usize>,
+    pub message: NewMessage,
+}
+
+/// 一个导出的对话
+#[derive(Debug, Clone)]
+pub struct Conversation {
+    pub source: Source,
+    /// 原产品中的id，用于识别导入过的对话
+    pub id: String,
+    pub title: String,
+    /// 原产品中使用的模型，只作记录
+    pub model: Option<String>,
+    pub created_at: Option<i64>,
+    pub updated_at: Option<i64>,
+    /// 父消息总在子消息之前
+    pub messages: Vec<Node>,
+    /// 当前分支的最后一条消息
+    pub current: Option<usize>,
+}
+
+impl Conversation {
+    /// 写入会话`metadata.imported_from`的内容
+    pub fn origin(&self) -> Value {
+        let mut origin = json!({ "source": self.source.name(), "id": self.id });
+        if let Some(model) = &self.model {
+            origin["model"] = json!(model);
+        }
+        origin
+    }
+
+    /// 从第一条消息到当前分支最后一条消息的位置
+    pub fn current_path(&self) -> Vec<usize> {
+        let mut path: Vec<usize> = std::iter::successors(self.current, |&index| self.messages[index].parent).collect();
+        path.reverse();
+        path
+    }
+}
+
+/// 按内容识别并解析导出的文件，zip中读取`conversations.json`
+pub fn parse(data: &[u8]) -> Result<Vec<Conversation>, SessionError> {
+    let json = if data.starts_with(b"PK\x03\x04") {
+        read_archive(data)?
+    } else {
+        data.to_vec()
+    };
+    let document: Value =
+        serde_json::from_slice(&json).map_err(|e| SessionError::Invalid(format!("不是有效的JSON: {}", e)))?;
+    let Value::Array(items) = document else {
+        return Err(SessionError::Invalid("导出文件应为对话数组".to_string()));
+    };
+    let source = match items.first() {
+        None => return Ok(Vec::new()),
+        Some(item) if item.get("mapping").is_some() => Source::ChatGpt,
+        Some(item) if item.get("chat_messages").is_some() => Source::Claude,
+        Some(_) => {
+            return Err(SessionError::Invalid(
+                "无法识别的导出格式，支持ChatGPT和Claude的数据导出".to_string(),
+            ))
+        }
+    };
+    Ok(items
+        .iter()
+        .map(|item| match source {
+            Source::ChatGpt => chatgpt(item),
+            Source::Claude => claude(item),
+        })
+        .collect())
+}
+
+fn read_archive(data: &[u8]) -> Result<Vec<u8>, SessionError> {
+    let invalid = |e: zip::result::ZipError| SessionError::Invalid(format!("无法读取zip: {}", e));
+    let mut archive = zip::ZipArchive::new(Cursor::new(data)).map_err(invalid)?;
+    let name = archive
+        .file_names()
+        .filter(|name| name.rsplit('/').next() == Some("conversations.json"))
+        .min_by_key(|name| name.len())
+        .map(str::to_string)
+        .ok_or_else(|| SessionError::Invalid("zip中没有conversations.json".to_string()))?;
+    let mut json = Vec::new();
+    let mut file = archive.by_name(&name).map_err(invalid)?;
+    file.read_to_end(&mut json).map_err(|e| SessionError::Invalid(format!("无法读取{}: {}", name, e)))?;
+    Ok(json)
+}
+
+/// 原产品中的消息，`parent`为原产品中的父消息id
+struct Raw<'a> {
+    id: &'a str,
+    parent: Option<&'a str>,
+    /// 不导入的消息为`None`，它的子消息接到上一条导入的消息之后
+    message: Option<NewMessage>,
+}
+
+/// 按原产品中的父子关系组成消息树，`current`为原产品中当前分支最后一条消息的id
+fn build_tree(raws: Vec<Raw<'_>>, current: Option<&str>, created_at: Option<i64>) -> (Vec<Node>, Option<usize>) {
+    let known: HashMap<&str, usize> = raws.iter().enumerate().map(|(index, raw)| (raw.id, index)).collect();
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); raws.len()];
+    let mut roots = Vec::new();
+    for (index, raw) in raws.iter().enumerate() {
+        match raw.parent.and_then(|parent| known.get(parent)) {
+            Some(&parent) if parent != index => children[parent].push(index),
+            _ => roots.push(index),
+        }
+    }
+    let mut raws: Vec<Option<NewMessage>> = raws.into_iter().map(|raw| raw.message).collect();
+    // 每条原消息对应的最近一条导入的消息（自身或祖先）
+    let mut mapped: Vec<Option<usize>> = vec![None; raws.len()];
+    let mut visited = vec![false; raws.len()];
+    let mut nodes = Vec::new();
+    // 按先序遍历，父消息总在子消息之前
+    let mut stack: Vec<(usize, Option<usize>)> = roots.into_iter().rev().map(|root| (root, None)).collect();
+    while let Some((index, parent)) = stack.pop() {
+        if std::mem::replace(&mut visited[index], true) {
+            continue;
+        }
+        mapped[index] = match raws[index].take() {
+            Some(mut message) => {
+                message.created_at = message.created_at.or(created_at);
+                nodes.push(Node { parent, message });
+                Some(nodes.len() - 1)
+            }
+            None => parent,
+        };
+        stack.extend(children[index].iter().rev().map(|&child| (child, mapped[index])));
+    }
+    let current = current.and_then(|id| known.get(id)).and_then(|&index| mapped[index]);
+    // 没有指定时取最后一条消息所在的分支
+    let current = current.or_else(|| nodes.len().checked_sub(1));
+    (nodes, current)
+}
+
+fn text_message(
+    role: &str,
+    content: String,
+    attachments: Vec<NewAttachment>,
+    created_at: Option<i64>,
+) -> Option<NewMessage> {
+    if content.trim().is_empty() && attachments.is_empty() {
+        return None;
+    }
+    Some(NewMessage {
+        role: role.to_string(),
+        content,
+        extra: Map::new(),
+        attachments,
+        usage: None,
+        model: None,
+        created_at,
+    })
+}
+
+fn string(value: &Value) -> Option<String> {
+    value.as_str().filter(|text| !text.is_empty()).map(str::to_string)
+}
+
+/// ChatGPT的Unix秒，带小数
+fn seconds(value: &Value) -> Option<i64> {
+    value.as_f64().map(|seconds| seconds as i64)
+}
+
+fn chatgpt(item: &Value) -> Conversation {
+    let empty = Map::new();
+    let mapping = item["mapping"].as_object().unwrap_or(&empty);
+    let raws = mapping
+        .iter()
+        .map(|(id, node)| Raw {
+            id: node["id"].as_str().unwrap_or(id),
+            parent: node["parent"].as_str(),
+            message: chatgpt_message(&node["message"]),
+        })
+        .collect();
+    let created_at = seconds(&item["create_time"]);
+    let (messages, current) = build_tree(raws, item["current_node"].as_str(), created_at);
+    Conversation {
+        source: Source::ChatGpt,
+        id: string(&item["conversation_id"]).or_else(|| string(&item["id"])).unwrap_or_default(),
+        title: string(&item["title"]).unwrap_or_default(),
+        model: string(&item["default_model_slug"]),
+        created_at,
+        updated_at: seconds(&item["update_time"]),
+        messages,
+        current,
+    }
+}
+
+fn chatgpt_message(message: &Value) -> Option<NewMessage> {
+    let role = message["author"]["role"].as_str()?;
+    let metadata = &message["metadata"];
+    // 工具的输出、自定义指令等界面上不显示的消息不导入
+    if !matches!(role, "user" | "assistant" | "system") || metadata["is_visually_hidden_from_conversation"] == true {
+        return None;
+    }
+    let content = &message["content"];
+    let mut attachments: Vec<NewAttachment> = metadata["attachments"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|attachment| {
+            Some(NewAttachment {
+                name: string(&attachment["name"])?,
+                mime_type: string(&attachment["mime_type"]),
+                size: attachment["size"].as_u64(),
+                uri: None,
+                sha256: None,
+            })
+        })
+        .collect();
+    let text = match content["content_type"].as_str()? {
+        "text" | "multimodal_text" => {
+            let mut texts = Vec::new();
+            for part in content["parts"].as_array().into_iter().flatten() {
+                match part {
+                    Value::String(text) => texts.push(text.clone()),
+                    part => match part["content_type"].as_str() {
+                        Some("image_asset_pointer") => {
+                            let pointer = part["asset_pointer"].as_str().unwrap_or("");
+                            // 同一张图片也在metadata.attachments中时不重复记录
+                            let name = pointer.rsplit('/').next().unwrap_or(pointer);
+                            if !attachments.iter().any(|attachment| pointer.contains(attachment.name.as_str())) {
+                                attachments.push(NewAttachment {
+                                    name: if name.is_empty() {
+                                        "image".to_string()
+                                    } else {
+                                        name.to_string()
+                                    },
+                                    mime_type: None,
+                                    size: part["size_bytes"].as_u64(),
+                                    uri: string(&part["asset_pointer"]),
+                                    sha256: None,
+                                });
+                            }
+                        }
+                        Some("audio_transcription") => texts.extend(string(&part["text"])),
+                        _ => {}
+                    },
+                }
+            }
+            texts.join("\n")
+        }
+        // 代码解释器执行的代码
+        "code" => {
+            let code = content["text"].as_str().unwrap_or("");
+            let language = content["language"].as_str().filter(|language| *language != "unknown").unwrap_or("");
+            let fence = "`".repeat(code.split(|c| c != '`').map(str::len).max().unwrap_or(0).max(2) + 1);
+            format!("{}{}\n{}\n{}", fence, language, code.trim_end(), fence)
+        }
+        _ => return None,
+    };
+    let mut message = text_message(role, text, attachments, seconds(&message["create_time"]))?;
+    message.model = string(&metadata["model_slug"]);
+    Some(message)
+}
+
+/// Claude中没有父消息的消息所用的父消息id
+const CLAUDE_ROOT: &str = "00000000-0000-4000-8000-000000000000";
+
+fn claude(item: &Value) -> Conversation {
+    let messages = item["chat_messages"].as_array().map_or(&[][..], Vec::as_slice);
+    let branched = messages.iter().any(|message| message.get("parent_message_uuid").is_some());
+    let raws = messages
+        .iter()
+        .enumerate()
+        .map(|(index, message)| Raw {
+            id: message["uuid"].as_str().unwrap_or(""),
+            // 没有父消息id时按顺序连成一个分支
+            parent: if branched {
+                message["parent_message_uuid"].as_str().filter(|parent| *parent != CLAUDE_ROOT)
+            } else {
+                index.checked_sub(1).and_then(|previous| messages[previous]["uuid"].as_str())
+            },
+            message: claude_message(message),
+        })
+        .collect();
+    let created_at = parse_rfc3339(item["created_at"].as_str().unwrap_or(""));
+    let (messages, current) = build_tree(raws, item["current_leaf_message_uuid"].as_str(), created_at);
+    Conversation {
+        source: Source::Claude,
+        id: string(&item["uuid"]).unwrap_or_default(),
+        title: string(&item["name"]).unwrap_or_default(),
+        model: string(&item["model"]),
+        created_at,
+        updated_at: parse_rfc3339(item["updated_at"].as_str().unwrap_or("")),
+        messages,
+        current,
+    }
+}
+
+fn claude_message(message: &Value) -> Option<NewMessage> {
+    let role = match message["sender"].as_str()? {
+        "human" => "user",
+        "assistant" => "assistant",
+        _ => return None,
+    };
+    // 新版导出的正文在content中，思考过程和工具调用不导入
+    let texts: Vec<&str> = message["content"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|part| part["type"] == "text")
+        .filter_map(|part| part["text"].as_str())
+        .collect();
+    let mut text = if texts.is_empty() {
+        message["text"].as_str().unwrap_or("").to_string()
+    } else {
+        texts.join("\n\n")
+    };
+    let mut attachments = Vec::new();
+    for attachment in message["attachments"].as_array().into_iter().flatten() {
+        let name = string(&attachment["file_name"]).unwrap_or_else(|| "附件".to_string());
+        if let Some(content) = string(&attachment["extracted_content"]) {
+            text.push_str(&format!("\n\n附件 {}：\n\n{}", name, content));
+        }
+        attachments.push(NewAttachment {
+            name,
+            mime_type: string(&attachment["file_type"]).filter(|file_type| file_type.contains('/')),
+            size: attachment["file_size"].as_u64(),
+            uri: None,
+            sha256: None,
+        });
+    }
+    for file in message["files"].as_array().into_iter().flatten() {
+        if let Some(name) = string(&file["file_name"]) {
+            attachments.push(NewAttachment {
+                name,
+                mime_type: None,
+                size: None,
+                uri: None,
+                sha256: None,
+            });
+        }
+    }
+    text_message(
+        role,
+        text,
+        attachments,
+        parse_rfc3339(message["created_at"].as_str().unwrap_or("")),
+    )
+}
+
+/// `2024-05-01T12:34:56.789Z`或`+08:00`结尾的时间转为Unix秒
+fn parse_rfc3339(text: &str) -> Option<i64> {
+    let number = |range: std::ops::Range<usize>| text.get(range)?.parse::<u32>().ok();
+    if text.len() < 19 || !matches!(text.as_bytes()[10], b'T' | b't' | b' ') {
+        return None;
+    }
+    let (year, month, day) = (i64::from(number(0..4)?), number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let zone = text[19..].trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+    let offset = match zone {
+        "" | "Z" | "z" => 0,
+        _ => {
+            let sign = match zone.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let (hours, minutes) = zone[1..].split_once(':')?;
+            sign * (i64::from(hours.parse::<u32>().ok()?) * 3600 + i64::from(minutes.parse::<u32>().ok()?) * 60)
+        }
+    };
+    let days = days_from_civil(year, month, day);
+    Some(days * 86_400 + i64::from(hour * 3600 + minute * 60 + second) - offset)
+}
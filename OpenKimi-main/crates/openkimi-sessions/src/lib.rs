@@ -0,0 +1,33 @@
+//! 会话存储
+//!
+//! 对话、消息、附件信息和token用量保存在一个SQLite数据库中，供服务端和客户端续聊、检索和导出，
+//! 取代客户端按会话保存的JSON文件。数据库结构随版本自动升级，各版本的迁移见[`schema`]。
+//! 启用`postgres`或`redis`特性后也可以保存在PostgreSQL或Redis中，多个服务实例共享同一份会话。
+//!
+//! [`SessionStore`]的接口都是异步的，内部在阻塞线程中访问存储，需要在Tokio运行时中调用。
+//! 消息全文检索使用FTS5的trigram分词，中文无需分词也能按子串检索。
+//! 消息按父消息组成树：编辑提问或重新生成回复时从原消息的父消息开出新分支，会话记录当前分支的最后一条消息，
+//! 读取消息时只返回当前分支。
+//! 导出的会话文档可以用[`export`]转成Markdown或HTML，用[`notes`]写成Obsidian或Notion的笔记；
+//! ChatGPT和Claude导出的对话用[`external`]解析后导入。
+
+mod error;
+pub mod export;
+pub mod external;
+pub mod notes;
+mod model;
+#[cfg(feature = "postgres")]
+mod postgres;
+#[cfg(feature = "redis")]
+mod redis;
+pub mod schema;
+mod sqlite;
+mod store;
+pub mod time;
+
+pub use error::SessionError;
+pub use model::{
+    Attachment, Branch, ExternalImport, Message, NewAttachment, NewMessage, NewSession, SearchHit, Session, SessionQuery,
+    SessionUpdate, SummaryTask, TokenUsage,
+};
+pub use store::{SessionStore, DEFAULT_WORKSPACE, UNTITLED};
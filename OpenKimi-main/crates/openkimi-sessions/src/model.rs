@@ -0,0 +1,185 @@
+//! 会话存储中的数据结构，时间均为Unix时间戳（秒）
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Token用量
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+/// 会话
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub title: String,
+    pub model: Option<String>,
+    pub metadata: Map<String, Value>,
+    pub created_at: i64,
+    /// 最后一次修改或追加消息的时间
+    pub updated_at: i64,
+    /// 当前分支的消息数
+    pub message_count: u64,
+    /// 当前分支最后一条消息的id，还没有消息时为`None`
+    #[serde(default)]
+    pub head_id: Option<i64>,
+    /// 会话中累计的用量，包括所有分支
+    pub usage: TokenUsage,
+    /// 后台任务生成的会话摘要，还没有生成时为`None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+}
+
+/// 需要重新生成摘要的会话，见[`crate::SessionStore::pending_summaries`]
+#[derive(Debug, Clone)]
+pub struct SummaryTask {
+    pub workspace: String,
+    pub session: Session,
+    /// 已有摘要覆盖的前几条消息，之后的消息是新增的
+    pub summarized: u64,
+}
+
+/// 创建会话的参数
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct NewSession {
+    /// 缺省时随机生成
+    pub id: Option<String>,
+    /// 缺省时在追加第一条用户消息后取其前30个字符
+    pub title: Option<String>,
+    pub model: Option<String>,
+    pub metadata: Map<String, Value>,
+    /// 缺省为当前时间，导入旧会话时保留原来的时间
+    pub created_at: Option<i64>,
+}
+
+/// 修改会话的参数，`None`的字段保持不变
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SessionUpdate {
+    pub title: Option<String>,
+    pub model: Option<String>,
+    pub metadata: Option<Map<String, Value>>,
+}
+
+/// 附件信息，文件本身不存放在数据库中
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attachment {
+    pub id: i64,
+    pub name: String,
+    pub mime_type: Option<String>,
+    pub size: Option<u64>,
+    /// 文件位置，如本地路径或URL
+    pub uri: Option<String>,
+    pub sha256: Option<String>,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct NewAttachment {
+    pub name: String,
+    pub mime_type: Option<String>,
+    pub size: Option<u64>,
+    pub uri: Option<String>,
+    pub sha256: Option<String>,
+}
+
+/// 会话中的一条消息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub id: i64,
+    pub session_id: String,
+    /// 上一条消息，会话的第一条消息为`None`；同一父消息下的多条消息是不同的分支
+    #[serde(default)]
+    pub parent_id: Option<i64>,
+    pub role: String,
+    pub content: String,
+    /// `name`、`tool_calls`等其余字段
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub extra: Map<String, Value>,
+    pub created_at: i64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+    /// 生成这条消息的用量，一般只有助手消息才有
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
+}
+
+/// 追加消息的参数
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct NewMessage {
+    pub role: String,
+    #[serde(default)]
+    pub content: String,
+    #[serde(default)]
+    pub extra: Map<String, Value>,
+    #[serde(default)]
+    pub attachments: Vec<NewAttachment>,
+    #[serde(default)]
+    pub usage: Option<TokenUsage>,
+    /// 用量所属的模型，缺省为会话的模型
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<i64>,
+}
+
+/// 会话中的一个分支，即从第一条消息到某条没有后续消息的消息的路径
+#[derive(Debug, Clone, Serialize)]
+pub struct Branch {
+    /// 分支最后一条消息的id
+    pub head_id: i64,
+    pub message_count: u64,
+    /// 是否是当前分支
+    pub active: bool,
+    /// 与当前分支分开前的最后一条共同消息，从第一条消息就分开时为`None`；当前分支没有这个字段
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fork_id: Option<i64>,
+    /// 分开后的第一条消息的开头，通常是编辑后的提问或重新生成的回复
+    pub preview: String,
+    /// 分支最后一条消息的时间
+    pub updated_at: i64,
+}
+
+/// 分页列出会话，按最后修改时间倒序
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct SessionQuery {
+    pub limit: u32,
+    pub offset: u32,
+}
+
+impl Default for SessionQuery {
+    fn default() -> Self {
+        SessionQuery { limit: 50, offset: 0 }
+    }
+}
+
+/// 全文检索命中的消息
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    pub session_id: String,
+    pub title: String,
+    pub message_id: i64,
+    pub role: String,
+    /// 命中位置附近的文本，关键词用`[`和`]`标出
+    pub snippet: String,
+    pub created_at: i64,
+}
+
+/// [`SessionStore::import_external`](crate::SessionStore::import_external)的结果
+#[derive(Debug, Clone, Serialize)]
+pub struct ExternalImport {
+    pub imported: Vec<Session>,
+    /// 导入过或没有消息而跳过的对话数
+    pub skipped: usize,
+}
@@ -0,0 +1,449 @@
+//! 导出为笔记：Obsidian库和Notion导入包
+//!
+//! 会话文档（[`SessionStore::export`](crate::SessionStore::export)的结果）和RAG问答转成互相链接的Markdown笔记。
+//! Obsidian布局下笔记放在库中的`OpenKimi/`目录，开头是YAML属性（frontmatter），用`[[...]]`互相链接：每个对话
+//! 一篇笔记，链接到列出全部对话的目录笔记；问答链接到引用的来源，每个来源文件一篇笔记，收集各次检索到的片段。
+//! Notion布局用于打包成zip后在Notion中导入：目录页和同名文件夹中的子页面，用相对路径的Markdown链接，属性列在
+//! 正文开头。笔记的正文由模板生成，`{{名称}}`替换为对应的内容，见[`CONVERSATION_TEMPLATE`]和[`ANSWER_TEMPLATE`]。
+
+use std::io::{Cursor, Write};
+
+use serde_json::Value;
+use zip::write::SimpleFileOptions;
+
+use crate::export::{details, format_utc, title, transcript};
+use crate::time::civil_from_days;
+use crate::SessionError;
+
+/// 对话笔记的默认模板
+///
+/// 可用的名称：`frontmatter`（Obsidian的YAML属性，Notion布局下为空）、`title`、`id`、`model`、`created`、
+/// `updated`、`source`（导入自哪个产品，OpenKimi中的对话为`openkimi`）、`details`（会话信息列表）、
+/// `transcript`（系统提示词和各条消息）、`catalog`（指向对话目录的链接）。
+pub const CONVERSATION_TEMPLATE: &str = "\
+{{frontmatter}}
+# {{title}}
+
+{{details}}
+
+{{transcript}}
+
+---
+
+返回{{catalog}}
+";
+
+/// 问答笔记的默认模板
+///
+/// 可用的名称：`frontmatter`、`title`（问题的第一行）、`question`、`answer`、`index`（检索的索引）、`model`、
+/// `created`、`sources`（来源笔记的链接列表）。
+pub const ANSWER_TEMPLATE: &str = "\
+{{frontmatter}}
+# {{title}}
+
+> 在索引 {{index}} 中检索，{{model}} 回答，{{created}}
+
+## 问题
+
+{{question}}
+
+## 回答
+
+{{answer}}
+
+## 来源
+
+{{sources}}
+";
+
+/// 笔记所在的目录
+const ROOT: &str = "OpenKimi";
+
+/// 文件名中标题的最大字数
+const NAME_CHARS: usize = 40;
+
+/// 笔记的布局
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// Obsidian库：YAML属性和`[[...]]`链接
+    Obsidian,
+    /// Notion导入包：目录页和子页面，相对路径的Markdown链接
+    Notion,
+}
+
+impl Layout {
+    /// `obsidian`或`notion`
+    pub fn from_name(name: &str) -> Option<Layout> {
+        match name.to_ascii_lowercase().as_str() {
+            "obsidian" => Some(Layout::Obsidian),
+            "notion" => Some(Layout::Notion),
+            _ => None,
+        }
+    }
+
+    /// 对话笔记所在的目录，相对于库或导入包的根目录
+    pub fn conversations_dir(self) -> &'static str {
+        match self {
+            Layout::Obsidian => "OpenKimi/对话",
+            Layout::Notion => "OpenKimi 对话",
+        }
+    }
+
+    /// 对话目录笔记的路径
+    pub fn catalog_path(self) -> String {
+        format!("{}.md", self.conversations_dir())
+    }
+}
+
+/// 一篇笔记，`path`为相对于库或导入包根目录、以`/`分隔的路径
+#[derive(Debug, Clone)]
+pub struct Note {
+    pub path: String,
+    pub content: String,
+}
+
+/// 一次RAG问答
+#[derive(Debug, Clone)]
+pub struct Answer {
+    pub question: String,
+    pub answer: String,
+    /// 检索的索引
+    pub index: String,
+    pub model: Option<String>,
+    pub created_at: i64,
+    pub sources: Vec<AnswerSource>,
+}
+
+/// 问答引用的一段资料
+#[derive(Debug, Clone)]
+pub struct AnswerSource {
+    /// 来源文件，如`docs/guide.md`
+    pub document: String,
+    /// 文件中的位置，如`docs/guide.md:10-42`
+    pub location: String,
+    pub text: String,
+}
+
+/// 用模板生成笔记，`{{名称}}`替换为对应的值，不认识的名称原样保留
+pub fn render_template(template: &str, values: &[(&str, String)]) -> String {
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        text.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let value = after.find("}}").and_then(|end| {
+            let name = after[..end].trim();
+            let (_, value) = values.iter().find(|(key, _)| *key == name)?;
+            Some((value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                text.push_str(value);
+                rest = &after[end + 2..];
+            }
+            None => {
+                text.push_str("{{");
+                rest = after;
+            }
+        }
+    }
+    text.push_str(rest);
+    // 属性为空时去掉开头的空行
+    format!("{}\n", text.trim_start_matches('\n').trim_end())
+}
+
+/// Unix秒格式化为`2024-06-01T10:00:00Z`
+fn iso_time(seconds: i64) -> String {
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    let rest = seconds.rem_euclid(86_400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
+
+fn date(seconds: i64) -> String {
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// YAML的双引号字符串，与JSON字符串的写法兼容
+fn yaml_string(text: &str) -> String {
+    serde_json::to_string(text).unwrap_or_default()
+}
+
+/// YAML属性块，值为`None`的属性省略
+fn frontmatter(properties: &[(&str, Option<String>)], tags: &[&str]) -> String {
+    let mut text = "---\n".to_string();
+    for (key, value) in properties {
+        if let Some(value) = value {
+            // 列表的值以换行开头
+            let separator = if value.starts_with('\n') { ":" } else { ": " };
+            text.push_str(&format!("{}{}{}\n", key, separator, value));
+        }
+    }
+    text.push_str("tags:\n");
+    for tag in tags {
+        text.push_str(&format!("  - {}\n", tag));
+    }
+    text.push_str("---\n");
+    text
+}
+
+/// 可以用作文件名的标题：去掉Obsidian链接和各个系统的文件名中不能用的字符，只取第一行的前几个字
+fn file_name(title: &str) -> String {
+    let line = title.lines().map(str::trim).find(|line| !line.is_empty()).unwrap_or("");
+    let name: String = line
+        .chars()
+        .map(|c| {
+            if "/\\:*?\"<>|#^[]".contains(c) || c.is_control() {
+                ' '
+            } else {
+                c
+            }
+        })
+        .take(NAME_CHARS)
+        .collect();
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    let name = name.trim_matches('.').to_string();
+    if name.is_empty() {
+        "未命名".to_string()
+    } else {
+        name
+    }
+}
+
+/// Markdown链接中的路径，非ASCII字符、空格和括号按UTF-8编码
+fn encode_path(path: &str) -> String {
+    let mut encoded = String::new();
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~/".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// 笔记之间的链接：Obsidian为`[[路径|文字]]`，Notion为相对于`from`所在目录的Markdown链接
+fn link(layout: Layout, from: &str, to: &str, text: &str) -> String {
+    let target = to.strip_suffix(".md").unwrap_or(to);
+    match layout {
+        Layout::Obsidian => format!("[[{}|{}]]", target, text.replace(['[', ']', '|'], " ")),
+        Layout::Notion => {
+            let depth = from.matches('/').count();
+            let relative = format!("{}{}", "../".repeat(depth), to);
+            format!("[{}]({})", text.replace(['[', ']'], " "), encode_path(&relative))
+        }
+    }
+}
+
+/// 对话笔记的路径，文件名为`日期 标题 id前8位`，同一个对话再次导出时覆盖
+pub fn conversation_path(document: &Value, layout: Layout) -> String {
+    let created = document["created_at"].as_i64().or(document["updated_at"].as_i64()).unwrap_or(0);
+    let mut name = vec![date(created), file_name(title(document))];
+    if let Some(id) = document["id"].as_str().filter(|id| !id.is_empty()) {
+        name.push(id.chars().take(8).collect());
+    }
+    format!("{}/{}.md", layout.conversations_dir(), name.join(" "))
+}
+
+/// 把会话文档写成对话笔记
+pub fn conversation_note(document: &Value, layout: Layout, template: &str) -> Note {
+    let path = conversation_path(document, layout);
+    let string = |key: &str| document[key].as_str().filter(|value| !value.is_empty()).map(str::to_string);
+    let time = |key: &str| document[key].as_i64();
+    let source = document["metadata"]["imported_from"]["source"].as_str().unwrap_or("openkimi").to_string();
+    let frontmatter = match layout {
+        Layout::Obsidian => self::frontmatter(
+            &[
+                ("title", Some(yaml_string(title(document)))),
+                ("id", string("id").map(|id| yaml_string(&id))),
+                ("model", string("model").map(|model| yaml_string(&model))),
+                ("created", time("created_at").map(iso_time)),
+                ("updated", time("updated_at").map(iso_time)),
+                ("source", Some(yaml_string(&source))),
+            ],
+            &["openkimi", "对话"],
+        ),
+        Layout::Notion => String::new(),
+    };
+    let details: Vec<String> =
+        details(document).into_iter().map(|(name, value)| format!("- {}：{}", name, value)).collect();
+    let catalog = link(layout, &path, &layout.catalog_path(), "全部对话");
+    let content = render_template(
+        template,
+        &[
+            ("frontmatter", frontmatter),
+            ("title", title(document).to_string()),
+            ("id", string("id").unwrap_or_default()),
+            ("model", string("model").unwrap_or_else(|| "服务端默认".to_string())),
+            ("created", time("created_at").map(format_utc).unwrap_or_default()),
+            ("updated", time("updated_at").map(format_utc).unwrap_or_default()),
+            ("source", source),
+            ("details", details.join("\n")),
+            ("transcript", transcript(document).trim_end().to_string()),
+            ("catalog", catalog),
+        ],
+    );
+    Note { path, content }
+}
+
+/// 列出对话笔记的目录笔记，`paths`为各篇对话笔记的路径，按文件名倒序（最新的在前）列出
+pub fn catalog_note(paths: &[String], layout: Layout) -> Note {
+    let path = layout.catalog_path();
+    let mut paths: Vec<&String> = paths.iter().collect();
+    paths.sort_by(|a, b| b.cmp(a));
+    let mut content = match layout {
+        Layout::Obsidian => frontmatter(&[("title", Some(yaml_string("OpenKimi 对话")))], &["openkimi"]),
+        Layout::Notion => String::new(),
+    };
+    content.push_str(&format!("# OpenKimi 对话\n\n共 {} 个对话。\n\n", paths.len()));
+    for note in paths {
+        let name = note.rsplit('/').next().unwrap_or(note);
+        let name = name.strip_suffix(".md").unwrap_or(name);
+        content.push_str(&format!("- {}\n", link(layout, &path, note, name)));
+    }
+    Note { path, content }
+}
+
+/// 问答笔记的路径，文件名为`日期 问题`；同名时由调用方加上序号
+pub fn answer_path(answer: &Answer) -> String {
+    format!(
+        "{}/问答/{} {}.md",
+        ROOT,
+        date(answer.created_at),
+        file_name(&answer.question)
+    )
+}
+
+/// 来源笔记的路径，文件路径中的`/`换成`_`
+pub fn source_path(document: &str) -> String {
+    format!("{}/来源/{}.md", ROOT, file_name(&document.replace(['/', '\\'], "_")))
+}
+
+/// 把问答写成Obsidian笔记，`path`为[`answer_path`]或加上序号后的路径
+pub fn answer_note(answer: &Answer, path: &str, template: &str) -> Note {
+    let mut documents: Vec<&str> = answer.sources.iter().map(|source| source.document.as_str()).collect();
+    documents.dedup();
+    let links: Vec<String> = documents
+        .iter()
+        .map(|document| {
+            let target = source_path(document);
+            let target = target.strip_suffix(".md").unwrap_or(&target);
+            format!("[[{}|{}]]", target, document.replace(['[', ']', '|'], " "))
+        })
+        .collect();
+    let mut sources = String::new();
+    for (number, source) in answer.sources.iter().enumerate() {
+        let position = documents.iter().position(|document| *document == source.document).unwrap_or(0);
+        sources.push_str(&format!("{}. {}：{}\n", number + 1, links[position], source.location));
+    }
+    let model = answer.model.clone().unwrap_or_else(|| "服务端默认模型".to_string());
+    let title = answer.question.lines().map(str::trim).find(|line| !line.is_empty()).unwrap_or("问答");
+    let frontmatter = frontmatter(
+        &[
+            ("title", Some(yaml_string(title))),
+            ("index", Some(yaml_string(&answer.index))),
+            ("model", answer.model.as_deref().map(yaml_string)),
+            ("created", Some(iso_time(answer.created_at))),
+            (
+                "sources",
+                (!links.is_empty()).then(|| {
+                    let items: Vec<String> = links.iter().map(|link| format!("\n  - {}", yaml_string(link))).collect();
+                    items.concat()
+                }),
+            ),
+        ],
+        &["openkimi", "问答"],
+    );
+    let content = render_template(
+        template,
+        &[
+            ("frontmatter", frontmatter),
+            ("title", title.to_string()),
+            ("question", answer.question.trim_end().to_string()),
+            ("answer", answer.answer.trim_end().to_string()),
+            ("index", answer.index.clone()),
+            ("model", model),
+            ("created", format_utc(answer.created_at)),
+            (
+                "sources",
+                if sources.is_empty() {
+                    "没有检索到资料。".to_string()
+                } else {
+                    sources
+                },
+            ),
+        ],
+    );
+    Note {
+        path: path.to_string(),
+        content,
+    }
+}
+
+/// 问答引用的来源笔记，`existing`读取已有的笔记；每段资料以位置为标题，已有的段落不重复添加
+pub fn source_notes(answer: &Answer, existing: impl Fn(&str) -> Option<String>) -> Vec<Note> {
+    let mut notes: Vec<Note> = Vec::new();
+    for source in &answer.sources {
+        let path = source_path(&source.document);
+        let index = match notes.iter().position(|note| note.path == path) {
+            Some(index) => index,
+            None => {
+                let content = existing(&path).unwrap_or_else(|| {
+                    let mut content = frontmatter(
+                        &[
+                            ("title", Some(yaml_string(&source.document))),
+                            ("document", Some(yaml_string(&source.document))),
+                            ("index", Some(yaml_string(&answer.index))),
+                        ],
+                        &["openkimi", "来源"],
+                    );
+                    content.push_str(&format!("# {}\n", source.document));
+                    content
+                });
+                notes.push(Note { path, content });
+                notes.len() - 1
+            }
+        };
+        let heading = format!("## {}", source.location);
+        let content = &mut notes[index].content;
+        if content.lines().any(|line| line == heading) {
+            continue;
+        }
+        let quoted: Vec<String> = source
+            .text
+            .trim_end()
+            .lines()
+            .map(|line| {
+                if line.is_empty() {
+                    ">".to_string()
+                } else {
+                    format!("> {}", line)
+                }
+            })
+            .collect();
+        content.push_str(&format!("\n{}\n\n{}\n", heading, quoted.join("\n")));
+    }
+    notes
+}
+
+/// 打包成zip，用于在Notion中导入
+pub fn zip(notes: &[Note]) -> Result<Vec<u8>, SessionError> {
+    let invalid = |e: zip::result::ZipError| SessionError::Invalid(format!("生成zip失败: {}", e));
+    let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    for note in notes {
+        writer.start_file(note.path.as_str(), SimpleFileOptions::default()).map_err(invalid)?;
+        writer
+            .write_all(note.content.as_bytes())
+            .map_err(|e| SessionError::Invalid(format!("生成zip失败: {}", e)))?;
+    }
+    Ok(writer.finish().map_err(invalid)?.into_inner())
+}
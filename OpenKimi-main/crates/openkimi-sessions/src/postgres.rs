@@ -0,0 +1,576 @@
+//! PostgreSQL后端
+//!
+//! 表结构与SQLite后端相同，消息、附件和用量的表名加`session_`前缀，避免与同一数据库中其他部分的表重名。
+//! 修改会话的事务先锁住会话所在的行，多个服务实例同时修改同一个会话时依次执行。
+//! 检索不依赖额外的扩展，逐条匹配消息内容。
+
+use std::collections::{HashMap, HashSet};
+
+use openkimi_postgres::postgres::{GenericClient, IsolationLevel, Row};
+use openkimi_postgres::Pool;
+use openkimi_schema::postgres::migrate;
+use serde_json::{Map, Value};
+
+use crate::error::SessionError;
+use crate::model::{
+    Attachment, Message, NewMessage, NewSession, SearchHit, Session, SessionQuery, SessionUpdate, SummaryTask,
+    TokenUsage,
+};
+use crate::schema;
+use crate::store::{excerpt, now, path_ids, random_id, title_from, Anchor, Backend, UNTITLED};
+
+const SESSION_COLUMNS: &str = "
+    s.id, s.title, s.model, s.metadata, s.created_at, s.updated_at,
+    COALESCE((SELECT depth FROM session_messages m WHERE m.id = s.head_id), 0),
+    (SELECT COALESCE(SUM(prompt_tokens), 0)::BIGINT FROM session_usage u WHERE u.session_id = s.id),
+    (SELECT COALESCE(SUM(completion_tokens), 0)::BIGINT FROM session_usage u WHERE u.session_id = s.id),
+    s.summary, s.head_id";
+
+fn parse_object(text: &str) -> Map<String, Value> {
+    serde_json::from_str(text).unwrap_or_default()
+}
+
+fn untitled(title: String) -> String {
+    if title.is_empty() {
+        UNTITLED.to_string()
+    } else {
+        title
+    }
+}
+
+fn session_from_row(row: &Row) -> Result<Session, SessionError> {
+    Ok(Session {
+        id: row.try_get(0)?,
+        title: untitled(row.try_get(1)?),
+        model: row.try_get(2)?,
+        metadata: parse_object(row.try_get(3)?),
+        created_at: row.try_get(4)?,
+        updated_at: row.try_get(5)?,
+        message_count: row.try_get::<_, i64>(6)? as u64,
+        usage: TokenUsage {
+            prompt_tokens: row.try_get::<_, i64>(7)? as u64,
+            completion_tokens: row.try_get::<_, i64>(8)? as u64,
+        },
+        summary: row.try_get(9)?,
+        head_id: row.try_get(10)?,
+    })
+}
+
+fn load_session(client: &mut impl GenericClient, workspace: &str, id: &str) -> Result<Session, SessionError> {
+    let row = client
+        .query_opt(
+            &format!("SELECT {} FROM sessions s WHERE s.id = $1 AND s.workspace = $2", SESSION_COLUMNS),
+            &[&id, &workspace],
+        )?
+        .ok_or_else(|| SessionError::NotFound(format!("会话 {}", id)))?;
+    session_from_row(&row)
+}
+
+/// 在事务中锁住会话所在的行，直到事务结束
+fn lock_session(tx: &mut impl GenericClient, workspace: &str, id: &str) -> Result<(), SessionError> {
+    tx.query_opt("SELECT 1 FROM sessions WHERE id = $1 AND workspace = $2 FOR UPDATE", &[&id, &workspace])?
+        .ok_or_else(|| SessionError::NotFound(format!("会话 {}", id)))?;
+    Ok(())
+}
+
+/// 会话中所有分支的消息，按写入顺序排列
+fn load_messages(client: &mut impl GenericClient, session_id: &str) -> Result<Vec<Message>, SessionError> {
+    let mut attachments: HashMap<i64, Vec<Attachment>> = HashMap::new();
+    let rows = client.query(
+        "SELECT a.message_id, a.id, a.name, a.mime_type, a.size, a.uri, a.sha256, a.created_at
+         FROM session_attachments a JOIN session_messages m ON m.id = a.message_id
+         WHERE m.session_id = $1 ORDER BY a.id",
+        &[&session_id],
+    )?;
+    for row in rows {
+        attachments.entry(row.try_get(0)?).or_default().push(Attachment {
+            id: row.try_get(1)?,
+            name: row.try_get(2)?,
+            mime_type: row.try_get(3)?,
+            size: row.try_get::<_, Option<i64>>(4)?.map(|size| size as u64),
+            uri: row.try_get(5)?,
+            sha256: row.try_get(6)?,
+            created_at: row.try_get(7)?,
+        });
+    }
+
+    let mut usage: HashMap<i64, TokenUsage> = HashMap::new();
+    let rows = client.query(
+        "SELECT message_id, SUM(prompt_tokens)::BIGINT, SUM(completion_tokens)::BIGINT FROM session_usage
+         WHERE session_id = $1 AND message_id IS NOT NULL GROUP BY message_id",
+        &[&session_id],
+    )?;
+    for row in rows {
+        usage.insert(
+            row.try_get(0)?,
+            TokenUsage {
+                prompt_tokens: row.try_get::<_, i64>(1)? as u64,
+                completion_tokens: row.try_get::<_, i64>(2)? as u64,
+            },
+        );
+    }
+
+    let rows = client.query(
+        "SELECT id, session_id, parent_id, role, content, extra, created_at FROM session_messages
+         WHERE session_id = $1 ORDER BY id",
+        &[&session_id],
+    )?;
+    rows.iter()
+        .map(|row| {
+            let id: i64 = row.try_get(0)?;
+            Ok(Message {
+                id,
+                session_id: row.try_get(1)?,
+                parent_id: row.try_get(2)?,
+                role: row.try_get(3)?,
+                content: row.try_get(4)?,
+                extra: parse_object(row.try_get(5)?),
+                created_at: row.try_get(6)?,
+                attachments: attachments.remove(&id).unwrap_or_default(),
+                usage: usage.remove(&id),
+            })
+        })
+        .collect()
+}
+
+/// 会话中消息`id`在分支上的位置（第一条为1），消息不存在时报错
+fn message_depth(client: &mut impl GenericClient, session_id: &str, id: i64) -> Result<i64, SessionError> {
+    let row = client
+        .query_opt("SELECT depth FROM session_messages WHERE id = $1 AND session_id = $2", &[&id, &session_id])?
+        .ok_or_else(|| SessionError::NotFound(format!("消息 {}", id)))?;
+    Ok(row.try_get(0)?)
+}
+
+/// `root`及其后续消息中最新的分支末端，`root`为`None`时在整个会话中找
+fn newest_leaf(
+    client: &mut impl GenericClient,
+    session_id: &str,
+    root: Option<i64>,
+) -> Result<Option<i64>, SessionError> {
+    let row = match root {
+        Some(root) => client.query_one(
+            "WITH RECURSIVE subtree(id) AS (
+                 SELECT $1::BIGINT UNION ALL
+                 SELECT m.id FROM session_messages m JOIN subtree ON m.parent_id = subtree.id
+             )
+             SELECT MAX(s.id) FROM subtree s
+             WHERE NOT EXISTS (SELECT 1 FROM session_messages c WHERE c.parent_id = s.id)",
+            &[&root],
+        )?,
+        None => client.query_one(
+            "SELECT MAX(m.id) FROM session_messages m
+             WHERE m.session_id = $1 AND NOT EXISTS (SELECT 1 FROM session_messages c WHERE c.parent_id = m.id)",
+            &[&session_id],
+        )?,
+    };
+    Ok(row.try_get(0)?)
+}
+
+/// 切换会话的当前分支
+///
+/// 原来的分支不是新分支的前缀时，已有摘要概括的消息不再都在当前分支上，清除摘要等后台任务重新生成。
+fn set_head(client: &mut impl GenericClient, session_id: &str, head: Option<i64>) -> Result<(), SessionError> {
+    let extends: bool = match head {
+        Some(head) => client
+            .query_one(
+                "WITH RECURSIVE ancestors(id) AS (
+                     SELECT $2::BIGINT UNION ALL
+                     SELECT m.parent_id FROM session_messages m JOIN ancestors ON m.id = ancestors.id
+                     WHERE m.parent_id IS NOT NULL
+                 )
+                 SELECT head_id IS NULL OR head_id IN (SELECT id FROM ancestors) FROM sessions WHERE id = $1",
+                &[&session_id, &head],
+            )?
+            .try_get(0)?,
+        None => false,
+    };
+    if extends {
+        client.execute("UPDATE sessions SET head_id = $2 WHERE id = $1", &[&session_id, &head])?;
+    } else {
+        client.execute(
+            "UPDATE sessions SET head_id = $2, summary = NULL, summarized = 0 WHERE id = $1",
+            &[&session_id, &head],
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_session(tx: &mut impl GenericClient, workspace: &str, new: NewSession) -> Result<String, SessionError> {
+    let id = match new.id {
+        Some(id) if id.trim().is_empty() => return Err(SessionError::Invalid("会话id不能为空".to_string())),
+        Some(id) => id,
+        None => random_id()?,
+    };
+    let created_at = new.created_at.unwrap_or_else(now);
+    let inserted = tx.execute(
+        "INSERT INTO sessions (id, title, model, metadata, created_at, updated_at, workspace)
+         VALUES ($1, $2, $3, $4, $5, $5, $6) ON CONFLICT (id) DO NOTHING",
+        &[
+            &id,
+            &new.title.unwrap_or_default(),
+            &new.model,
+            &Value::Object(new.metadata).to_string(),
+            &created_at,
+            &workspace,
+        ],
+    )?;
+    if inserted == 0 {
+        return Err(SessionError::Invalid(format!("会话 {} 已存在", id)));
+    }
+    Ok(id)
+}
+
+/// 在事务中从`anchor`之后依次追加消息，新消息所在的分支成为当前分支，返回新消息的id
+fn insert_messages(
+    tx: &mut impl GenericClient,
+    workspace: &str,
+    session_id: &str,
+    anchor: Anchor,
+    messages: Vec<NewMessage>,
+) -> Result<Vec<i64>, SessionError> {
+    let row = tx
+        .query_opt(
+            "SELECT model, head_id FROM sessions WHERE id = $1 AND workspace = $2 FOR UPDATE",
+            &[&session_id, &workspace],
+        )?
+        .ok_or_else(|| SessionError::NotFound(format!("会话 {}", session_id)))?;
+    let (session_model, head): (Option<String>, Option<i64>) = (row.try_get(0)?, row.try_get(1)?);
+    let mut parent = match anchor {
+        Anchor::Head => head,
+        Anchor::After(parent) => parent,
+    };
+    let mut depth = match parent {
+        Some(parent) => message_depth(tx, session_id, parent)?,
+        None => 0,
+    };
+
+    let mut ids = Vec::with_capacity(messages.len());
+    let mut updated_at = None;
+    for message in messages {
+        if message.role.is_empty() {
+            return Err(SessionError::Invalid("消息缺少 role".to_string()));
+        }
+        let created_at = message.created_at.unwrap_or_else(now);
+        updated_at = updated_at.max(Some(created_at));
+        depth += 1;
+        let id: i64 = tx
+            .query_one(
+                "INSERT INTO session_messages (session_id, parent_id, depth, role, content, extra, created_at)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
+                &[
+                    &session_id,
+                    &parent,
+                    &depth,
+                    &message.role,
+                    &message.content,
+                    &Value::Object(message.extra).to_string(),
+                    &created_at,
+                ],
+            )?
+            .try_get(0)?;
+        parent = Some(id);
+
+        for attachment in message.attachments {
+            if attachment.name.is_empty() {
+                return Err(SessionError::Invalid("附件缺少 name".to_string()));
+            }
+            tx.execute(
+                "INSERT INTO session_attachments (message_id, name, mime_type, size, uri, sha256, created_at)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                &[
+                    &id,
+                    &attachment.name,
+                    &attachment.mime_type,
+                    &attachment.size.map(|size| size as i64),
+                    &attachment.uri,
+                    &attachment.sha256,
+                    &created_at,
+                ],
+            )?;
+        }
+
+        if let Some(usage) = message.usage {
+            let model = message.model.or_else(|| session_model.clone()).unwrap_or_default();
+            tx.execute(
+                "INSERT INTO session_usage (session_id, message_id, model, prompt_tokens, completion_tokens, created_at)
+                 VALUES ($1, $2, $3, $4, $5, $6)",
+                &[
+                    &session_id,
+                    &id,
+                    &model,
+                    &(usage.prompt_tokens as i64),
+                    &(usage.completion_tokens as i64),
+                    &created_at,
+                ],
+            )?;
+        }
+
+        if message.role == "user" {
+            // 没有标题的会话用第一条用户消息命名
+            tx.execute(
+                "UPDATE sessions SET title = $2 WHERE id = $1 AND title = ''",
+                &[&session_id, &title_from(&message.content)],
+            )?;
+        }
+        ids.push(id);
+    }
+    if let Some(&last) = ids.last() {
+        set_head(tx, session_id, Some(last))?;
+    }
+    if let Some(updated_at) = updated_at {
+        tx.execute(
+            "UPDATE sessions SET updated_at = GREATEST(updated_at, $2) WHERE id = $1",
+            &[&session_id, &updated_at],
+        )?;
+    }
+    Ok(ids)
+}
+
+/// 保存在PostgreSQL数据库中，每次操作从连接池取一个连接
+#[derive(Debug)]
+pub(crate) struct PostgresBackend {
+    pool: Pool,
+}
+
+impl PostgresBackend {
+    /// 把表升级到最新结构
+    pub fn open(pool: Pool) -> Result<PostgresBackend, SessionError> {
+        migrate(&mut *pool.get()?, schema::COMPONENT, schema::POSTGRES)?;
+        Ok(PostgresBackend { pool })
+    }
+}
+
+impl Backend for PostgresBackend {
+    fn create_session(&self, workspace: &str, new: NewSession) -> Result<Session, SessionError> {
+        let mut client = self.pool.get()?;
+        let mut tx = client.transaction()?;
+        let id = insert_session(&mut tx, workspace, new)?;
+        tx.commit()?;
+        load_session(&mut *client, workspace, &id)
+    }
+
+    fn session(&self, workspace: &str, id: &str) -> Result<Session, SessionError> {
+        load_session(&mut *self.pool.get()?, workspace, id)
+    }
+
+    fn list_sessions(&self, workspace: &str, query: SessionQuery) -> Result<Vec<Session>, SessionError> {
+        let rows = self.pool.get()?.query(
+            &format!(
+                "SELECT {} FROM sessions s WHERE s.workspace = $3
+                 ORDER BY s.updated_at DESC, s.created_at DESC, s.id DESC LIMIT $1 OFFSET $2",
+                SESSION_COLUMNS
+            ),
+            &[&(query.limit as i64), &(query.offset as i64), &workspace],
+        )?;
+        rows.iter().map(session_from_row).collect()
+    }
+
+    fn update_session(&self, workspace: &str, id: &str, update: SessionUpdate) -> Result<Session, SessionError> {
+        let mut client = self.pool.get()?;
+        let metadata = update.metadata.map(|metadata| Value::Object(metadata).to_string());
+        let changed = client.execute(
+            "UPDATE sessions SET title = COALESCE($2, title), model = COALESCE($3, model),
+                 metadata = COALESCE($4, metadata), updated_at = $5
+             WHERE id = $1 AND workspace = $6",
+            &[&id, &update.title, &update.model, &metadata, &now(), &workspace],
+        )?;
+        if changed == 0 {
+            return Err(SessionError::NotFound(format!("会话 {}", id)));
+        }
+        load_session(&mut *client, workspace, id)
+    }
+
+    fn delete_session(&self, workspace: &str, id: &str) -> Result<(), SessionError> {
+        let deleted = self
+            .pool
+            .get()?
+            .execute("DELETE FROM sessions WHERE id = $1 AND workspace = $2", &[&id, &workspace])?;
+        if deleted == 0 {
+            return Err(SessionError::NotFound(format!("会话 {}", id)));
+        }
+        Ok(())
+    }
+
+    fn load(&self, workspace: &str, id: &str) -> Result<(Session, Vec<Message>), SessionError> {
+        let mut client = self.pool.get()?;
+        // 在同一个快照中读取会话和消息
+        let mut tx = client
+            .build_transaction()
+            .read_only(true)
+            .isolation_level(IsolationLevel::RepeatableRead)
+            .start()?;
+        let session = load_session(&mut tx, workspace, id)?;
+        let messages = load_messages(&mut tx, id)?;
+        tx.commit()?;
+        Ok((session, messages))
+    }
+
+    fn insert(
+        &self,
+        workspace: &str,
+        id: &str,
+        anchor: Anchor,
+        messages: Vec<NewMessage>,
+    ) -> Result<Vec<Message>, SessionError> {
+        let mut client = self.pool.get()?;
+        let mut tx = client.transaction()?;
+        let ids = insert_messages(&mut tx, workspace, id, anchor, messages)?;
+        tx.commit()?;
+        let mut messages = load_messages(&mut *client, id)?;
+        messages.retain(|message| ids.contains(&message.id));
+        Ok(messages)
+    }
+
+    fn checkout(&self, workspace: &str, id: &str, message_id: i64) -> Result<Session, SessionError> {
+        let mut client = self.pool.get()?;
+        let mut tx = client.transaction()?;
+        lock_session(&mut tx, workspace, id)?;
+        let session = load_session(&mut tx, workspace, id)?;
+        message_depth(&mut tx, id, message_id)?;
+        if path_ids(&load_messages(&mut tx, id)?, session.head_id).contains(&message_id) {
+            return Ok(session);
+        }
+        let head = newest_leaf(&mut tx, id, Some(message_id))?;
+        set_head(&mut tx, id, head)?;
+        let session = load_session(&mut tx, workspace, id)?;
+        tx.commit()?;
+        Ok(session)
+    }
+
+    fn prune(&self, workspace: &str, id: &str, message_id: Option<i64>) -> Result<usize, SessionError> {
+        let mut client = self.pool.get()?;
+        let mut tx = client.transaction()?;
+        lock_session(&mut tx, workspace, id)?;
+        let session = load_session(&mut tx, workspace, id)?;
+        let deleted = match message_id {
+            Some(message_id) => {
+                message_depth(&mut tx, id, message_id)?;
+                let parent: Option<i64> = tx
+                    .query_one("SELECT parent_id FROM session_messages WHERE id = $1", &[&message_id])?
+                    .try_get(0)?;
+                let deleted = tx.execute(
+                    "WITH RECURSIVE subtree(id) AS (
+                         SELECT $1::BIGINT UNION ALL
+                         SELECT m.id FROM session_messages m JOIN subtree ON m.parent_id = subtree.id
+                     )
+                     DELETE FROM session_messages WHERE id IN (SELECT id FROM subtree)",
+                    &[&message_id],
+                )?;
+                let head_exists = match session.head_id {
+                    Some(head) => message_depth(&mut tx, id, head).is_ok(),
+                    None => false,
+                };
+                if !head_exists {
+                    let head = match parent {
+                        Some(parent) => newest_leaf(&mut tx, id, Some(parent))?,
+                        None => newest_leaf(&mut tx, id, None)?,
+                    };
+                    set_head(&mut tx, id, head)?;
+                }
+                deleted
+            }
+            None => {
+                let messages = load_messages(&mut tx, id)?;
+                let keep: HashSet<i64> = path_ids(&messages, session.head_id).into_iter().collect();
+                let keep: Vec<i64> = keep.into_iter().collect();
+                tx.execute(
+                    "DELETE FROM session_messages WHERE session_id = $1 AND NOT (id = ANY($2))",
+                    &[&id, &keep],
+                )?
+            }
+        };
+        if deleted > 0 {
+            tx.execute("UPDATE sessions SET updated_at = $2 WHERE id = $1", &[&id, &now()])?;
+        }
+        tx.commit()?;
+        Ok(deleted as usize)
+    }
+
+    fn pending_summaries(
+        &self,
+        min_messages: u64,
+        idle_before: i64,
+        limit: u32,
+    ) -> Result<Vec<SummaryTask>, SessionError> {
+        let rows = self.pool.get()?.query(
+            &format!(
+                "SELECT {}, s.workspace, s.summarized FROM sessions s
+                 WHERE COALESCE((SELECT depth FROM session_messages m WHERE m.id = s.head_id), 0) >= s.summarized + $1
+                     AND s.updated_at <= $2
+                 ORDER BY s.updated_at LIMIT $3",
+                SESSION_COLUMNS
+            ),
+            &[&(min_messages.max(1) as i64), &idle_before, &(limit as i64)],
+        )?;
+        rows.iter()
+            .map(|row| {
+                Ok(SummaryTask {
+                    session: session_from_row(row)?,
+                    workspace: row.try_get(11)?,
+                    summarized: row.try_get::<_, i64>(12)? as u64,
+                })
+            })
+            .collect()
+    }
+
+    fn set_summary(&self, workspace: &str, id: &str, summary: String, summarized: u64) -> Result<(), SessionError> {
+        let changed = self.pool.get()?.execute(
+            "UPDATE sessions SET summary = $2, summarized = $3 WHERE id = $1 AND workspace = $4",
+            &[&id, &summary, &(summarized as i64), &workspace],
+        )?;
+        if changed == 0 {
+            return Err(SessionError::NotFound(format!("会话 {}", id)));
+        }
+        Ok(())
+    }
+
+    /// 不区分大小写逐条匹配，按时间倒序
+    fn search(&self, workspace: &str, query: &str, limit: u32) -> Result<Vec<SearchHit>, SessionError> {
+        let rows = self.pool.get()?.query(
+            "SELECT m.id, m.session_id, s.title, m.role, m.content, m.created_at
+             FROM session_messages m JOIN sessions s ON s.id = m.session_id
+             WHERE strpos(lower(m.content), lower($1)) > 0 AND s.workspace = $3 ORDER BY m.id DESC LIMIT $2",
+            &[&query, &(limit as i64), &workspace],
+        )?;
+        rows.iter()
+            .map(|row| {
+                Ok(SearchHit {
+                    message_id: row.try_get(0)?,
+                    session_id: row.try_get(1)?,
+                    title: untitled(row.try_get(2)?),
+                    role: row.try_get(3)?,
+                    snippet: excerpt(row.try_get(4)?, query),
+                    created_at: row.try_get(5)?,
+                })
+            })
+            .collect()
+    }
+
+    fn import(
+        &self,
+        workspace: &str,
+        new: NewSession,
+        messages: Vec<NewMessage>,
+        updated_at: Option<i64>,
+    ) -> Result<Session, SessionError> {
+        let mut client = self.pool.get()?;
+        let mut tx = client.transaction()?;
+        let id = insert_session(&mut tx, workspace, new)?;
+        insert_messages(&mut tx, workspace, &id, Anchor::Head, messages)?;
+        if let Some(updated_at) = updated_at {
+            tx.execute("UPDATE sessions SET updated_at = $2 WHERE id = $1", &[&id, &updated_at])?;
+        }
+        tx.commit()?;
+        load_session(&mut *client, workspace, &id)
+    }
+
+    fn ping(&self) -> Result<(), SessionError> {
+        self.pool.get()?.batch_execute("SELECT 1 FROM sessions LIMIT 1")?;
+        Ok(())
+    }
+
+    /// 数据库自行持久化，没有需要写出的数据
+    fn checkpoint(&self) -> Result<(), SessionError> {
+        Ok(())
+    }
+}
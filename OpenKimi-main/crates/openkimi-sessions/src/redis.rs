@@ -0,0 +1,563 @@
+//! Redis后端
+//!
+//! 每个会话连同所有分支的消息保存为一个JSON值（`<前缀>session:<id>`），另用有序集合按修改时间索引
+//! 各工作区的会话（`<前缀>sessions:<工作区>`）和全部会话（`<前缀>sessions`）。消息和附件的id由计数器分配，
+//! 在所有实例间唯一。修改会话时使用`WATCH`/`MULTI`，其他实例同时修改了同一个会话时重新读取后重试。
+//! 检索逐条匹配消息内容，不使用全文索引。
+
+use std::cmp::Reverse;
+use std::collections::HashSet;
+
+use openkimi_redis::{Client, Pooled, Value as Reply};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::error::SessionError;
+use crate::model::{
+    Attachment, Message, NewMessage, NewSession, SearchHit, Session, SessionQuery, SessionUpdate, SummaryTask,
+    TokenUsage,
+};
+use crate::store::{excerpt, now, path_ids, random_id, title_from, Anchor, Backend, UNTITLED};
+
+/// 同一个会话被并发修改时最多重试的次数
+const MAX_ATTEMPTS: usize = 16;
+
+/// 一次从Redis读取的会话数
+const BATCH: usize = 100;
+
+/// 保存在Redis中的会话
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Record {
+    workspace: String,
+    id: String,
+    /// 为空时显示[`UNTITLED`]，追加第一条用户消息后自动命名
+    title: String,
+    model: Option<String>,
+    #[serde(default)]
+    metadata: Map<String, Value>,
+    created_at: i64,
+    updated_at: i64,
+    head_id: Option<i64>,
+    summary: Option<String>,
+    #[serde(default)]
+    summarized: u64,
+    /// 已删除消息的用量，仍计入会话的累计用量
+    #[serde(default)]
+    removed_usage: TokenUsage,
+    /// 所有分支的消息，按id排列
+    #[serde(default)]
+    messages: Vec<Message>,
+}
+
+fn add_usage(total: &mut TokenUsage, usage: &TokenUsage) {
+    total.prompt_tokens += usage.prompt_tokens;
+    total.completion_tokens += usage.completion_tokens;
+}
+
+impl Record {
+    fn new(workspace: &str, new: NewSession) -> Result<Record, SessionError> {
+        let id = match new.id {
+            Some(id) if id.trim().is_empty() => return Err(SessionError::Invalid("会话id不能为空".to_string())),
+            Some(id) => id,
+            None => random_id()?,
+        };
+        let created_at = new.created_at.unwrap_or_else(now);
+        Ok(Record {
+            workspace: workspace.to_string(),
+            id,
+            title: new.title.unwrap_or_default(),
+            model: new.model,
+            metadata: new.metadata,
+            created_at,
+            updated_at: created_at,
+            head_id: None,
+            summary: None,
+            summarized: 0,
+            removed_usage: TokenUsage::default(),
+            messages: Vec::new(),
+        })
+    }
+
+    fn session(&self) -> Session {
+        let mut usage = self.removed_usage;
+        for message in &self.messages {
+            if let Some(tokens) = &message.usage {
+                add_usage(&mut usage, tokens);
+            }
+        }
+        Session {
+            id: self.id.clone(),
+            title: if self.title.is_empty() { UNTITLED.to_string() } else { self.title.clone() },
+            model: self.model.clone(),
+            metadata: self.metadata.clone(),
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            message_count: self.depth(self.head_id),
+            usage,
+            summary: self.summary.clone(),
+            head_id: self.head_id,
+        }
+    }
+
+    /// 消息在分支上的位置（第一条为1），`None`为0
+    fn depth(&self, id: Option<i64>) -> u64 {
+        path_ids(&self.messages, id).len() as u64
+    }
+
+    fn message(&self, id: i64) -> Result<&Message, SessionError> {
+        self.messages
+            .iter()
+            .find(|message| message.id == id)
+            .ok_or_else(|| SessionError::NotFound(format!("消息 {}", id)))
+    }
+
+    /// `root`及其后续消息；子消息总是在父消息之后写入，按顺序扫描一遍即可
+    fn subtree(&self, root: i64) -> HashSet<i64> {
+        let mut ids = HashSet::from([root]);
+        for message in &self.messages {
+            if message.parent_id.is_some_and(|parent| ids.contains(&parent)) {
+                ids.insert(message.id);
+            }
+        }
+        ids
+    }
+
+    /// `root`及其后续消息中最新的分支末端，`root`为`None`时在整个会话中找
+    fn newest_leaf(&self, root: Option<i64>) -> Option<i64> {
+        let parents: HashSet<i64> = self.messages.iter().filter_map(|message| message.parent_id).collect();
+        let scope = root.map(|root| self.subtree(root));
+        self.messages
+            .iter()
+            .map(|message| message.id)
+            .filter(|id| !parents.contains(id))
+            .filter(|id| scope.as_ref().is_none_or(|scope| scope.contains(id)))
+            .max()
+    }
+
+    /// 切换当前分支；原来的分支不是新分支的前缀时清除摘要
+    fn set_head(&mut self, head: Option<i64>) {
+        let extends = match (head, self.head_id) {
+            (Some(_), None) => true,
+            (Some(head), Some(current)) => path_ids(&self.messages, Some(head)).contains(&current),
+            (None, _) => false,
+        };
+        if !extends {
+            self.summary = None;
+            self.summarized = 0;
+        }
+        self.head_id = head;
+    }
+
+    /// 删除`ids`中的消息，用量计入`removed_usage`
+    fn remove(&mut self, ids: &HashSet<i64>) -> usize {
+        let before = self.messages.len();
+        let mut removed = self.removed_usage;
+        self.messages.retain(|message| {
+            if !ids.contains(&message.id) {
+                return true;
+            }
+            if let Some(usage) = &message.usage {
+                add_usage(&mut removed, usage);
+            }
+            false
+        });
+        self.removed_usage = removed;
+        before - self.messages.len()
+    }
+}
+
+/// 保存在Redis中，多个服务实例共享
+#[derive(Debug)]
+pub(crate) struct RedisBackend {
+    client: Client,
+    prefix: String,
+}
+
+impl RedisBackend {
+    pub fn new(client: Client, prefix: &str) -> RedisBackend {
+        RedisBackend {
+            client,
+            prefix: prefix.to_string(),
+        }
+    }
+
+    fn key(&self, id: &str) -> String {
+        format!("{}session:{}", self.prefix, id)
+    }
+
+    fn index(&self, workspace: &str) -> String {
+        format!("{}sessions:{}", self.prefix, workspace)
+    }
+
+    fn all(&self) -> String {
+        format!("{}sessions", self.prefix)
+    }
+
+    fn parse(id: &str, reply: Reply) -> Result<Option<Record>, SessionError> {
+        let Some(bytes) = reply.into_bytes() else {
+            return Ok(None);
+        };
+        serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|e| SessionError::Invalid(format!("会话 {} 的记录损坏: {}", id, e)))
+    }
+
+    /// 工作区中的会话，不存在或属于其他工作区时报错
+    fn get(&self, conn: &mut Pooled, workspace: &str, id: &str) -> Result<Record, SessionError> {
+        Self::parse(id, conn.query(&[&"GET", &self.key(id)])?)?
+            .filter(|record| record.workspace == workspace)
+            .ok_or_else(|| SessionError::NotFound(format!("会话 {}", id)))
+    }
+
+    fn record(&self, workspace: &str, id: &str) -> Result<Record, SessionError> {
+        self.get(&mut self.client.get()?, workspace, id)
+    }
+
+    /// 按id批量读取，已删除的会话跳过
+    fn records(&self, ids: &[String]) -> Result<Vec<Record>, SessionError> {
+        let mut records = Vec::with_capacity(ids.len());
+        for chunk in ids.chunks(BATCH) {
+            let keys: Vec<String> = chunk.iter().map(|id| self.key(id)).collect();
+            let mut args: Vec<&dyn openkimi_redis::Arg> = vec![&"MGET"];
+            args.extend(keys.iter().map(|key| key as &dyn openkimi_redis::Arg));
+            let replies = self.client.query(&args)?.into_array()?;
+            for (id, reply) in chunk.iter().zip(replies) {
+                records.extend(Self::parse(id, reply)?);
+            }
+        }
+        Ok(records)
+    }
+
+    fn ids(reply: Reply) -> Result<Vec<String>, SessionError> {
+        Ok(reply.into_array()?.into_iter().filter_map(Reply::into_string).collect())
+    }
+
+    /// 分配`count`个连续的id，返回第一个
+    fn allocate(&self, counter: &str, count: usize) -> Result<i64, SessionError> {
+        let key = format!("{}{}", self.prefix, counter);
+        let last = self.client.query(&[&"INCRBY", &key, &count])?.as_i64().unwrap_or(0);
+        Ok(last - count as i64 + 1)
+    }
+
+    /// 在事务中写入会话及其索引
+    fn queue_write(&self, conn: &mut Pooled, record: &Record) -> Result<(), SessionError> {
+        let json = serde_json::to_string(record)?;
+        conn.query(&[&"SET", &self.key(&record.id), &json])?;
+        conn.query(&[&"ZADD", &self.index(&record.workspace), &record.updated_at, &record.id])?;
+        conn.query(&[&"ZADD", &self.all(), &record.updated_at, &record.id])?;
+        Ok(())
+    }
+
+    /// 读取、修改并写回会话；读取之后会话被其他实例修改时重试
+    fn update<T>(
+        &self,
+        workspace: &str,
+        id: &str,
+        mut change: impl FnMut(&mut Record) -> Result<T, SessionError>,
+    ) -> Result<(T, Record), SessionError> {
+        let key = self.key(id);
+        for _ in 0..MAX_ATTEMPTS {
+            let mut conn = self.client.get()?;
+            let attempt = (|| {
+                conn.query(&[&"WATCH", &key])?;
+                let mut record = self.get(&mut conn, workspace, id)?;
+                let result = change(&mut record)?;
+                conn.query(&[&"MULTI"])?;
+                self.queue_write(&mut conn, &record)?;
+                let committed = !conn.query(&[&"EXEC"])?.is_nil();
+                Ok(committed.then_some((result, record)))
+            })();
+            match attempt {
+                Ok(Some(done)) => return Ok(done),
+                Ok(None) => continue,
+                Err(err) => {
+                    // 连接上可能还留着WATCH或未提交的事务，不再放回池中
+                    conn.discard();
+                    return Err(err);
+                }
+            }
+        }
+        Err(SessionError::Invalid(format!("会话 {} 正被频繁修改，请稍后重试", id)))
+    }
+
+    /// 新建会话，id已存在时报错
+    fn create(&self, record: &Record) -> Result<(), SessionError> {
+        let json = serde_json::to_string(record)?;
+        if self.client.query(&[&"SET", &self.key(&record.id), &json, &"NX"])?.is_nil() {
+            return Err(SessionError::Invalid(format!("会话 {} 已存在", record.id)));
+        }
+        self.client.query(&[&"ZADD", &self.index(&record.workspace), &record.updated_at, &record.id])?;
+        self.client.query(&[&"ZADD", &self.all(), &record.updated_at, &record.id])?;
+        Ok(())
+    }
+
+    /// 从`anchor`之后依次追加消息，新消息所在的分支成为当前分支，返回新消息
+    fn append(
+        &self,
+        record: &mut Record,
+        anchor: Anchor,
+        messages: Vec<NewMessage>,
+    ) -> Result<Vec<Message>, SessionError> {
+        let mut parent = match anchor {
+            Anchor::Head => record.head_id,
+            Anchor::After(parent) => parent,
+        };
+        if let Some(parent) = parent {
+            record.message(parent)?;
+        }
+        for message in &messages {
+            if message.role.is_empty() {
+                return Err(SessionError::Invalid("消息缺少 role".to_string()));
+            }
+            if message.attachments.iter().any(|attachment| attachment.name.is_empty()) {
+                return Err(SessionError::Invalid("附件缺少 name".to_string()));
+            }
+        }
+        if messages.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let attachments = messages.iter().map(|message| message.attachments.len()).sum();
+        let first_id = self.allocate("message_id", messages.len())?;
+        let mut attachment_id = if attachments > 0 { self.allocate("attachment_id", attachments)? } else { 0 };
+        let mut inserted = Vec::with_capacity(messages.len());
+        for (message_id, message) in (first_id..).zip(messages) {
+            let created_at = message.created_at.unwrap_or_else(now);
+            record.updated_at = record.updated_at.max(created_at);
+            if message.role == "user" && record.title.is_empty() {
+                // 没有标题的会话用第一条用户消息命名
+                record.title = title_from(&message.content);
+            }
+            let attachments = message
+                .attachments
+                .into_iter()
+                .map(|attachment| {
+                    attachment_id += 1;
+                    Attachment {
+                        id: attachment_id - 1,
+                        name: attachment.name,
+                        mime_type: attachment.mime_type,
+                        size: attachment.size,
+                        uri: attachment.uri,
+                        sha256: attachment.sha256,
+                        created_at,
+                    }
+                })
+                .collect();
+            let message = Message {
+                id: message_id,
+                session_id: record.id.clone(),
+                parent_id: parent,
+                role: message.role,
+                content: message.content,
+                extra: message.extra,
+                created_at,
+                attachments,
+                usage: message.usage,
+            };
+            parent = Some(message_id);
+            record.messages.push(message.clone());
+            inserted.push(message);
+        }
+        record.set_head(parent);
+        Ok(inserted)
+    }
+}
+
+impl Backend for RedisBackend {
+    fn create_session(&self, workspace: &str, new: NewSession) -> Result<Session, SessionError> {
+        let record = Record::new(workspace, new)?;
+        self.create(&record)?;
+        Ok(record.session())
+    }
+
+    fn session(&self, workspace: &str, id: &str) -> Result<Session, SessionError> {
+        Ok(self.record(workspace, id)?.session())
+    }
+
+    fn list_sessions(&self, workspace: &str, query: SessionQuery) -> Result<Vec<Session>, SessionError> {
+        if query.limit == 0 {
+            return Ok(Vec::new());
+        }
+        let stop = query.offset as u64 + query.limit as u64 - 1;
+        let ids = Self::ids(self.client.query(&[&"ZREVRANGE", &self.index(workspace), &query.offset, &stop])?)?;
+        Ok(self.records(&ids)?.iter().map(Record::session).collect())
+    }
+
+    fn update_session(&self, workspace: &str, id: &str, update: SessionUpdate) -> Result<Session, SessionError> {
+        let (_, record) = self.update(workspace, id, |record| {
+            if let Some(title) = update.title.clone() {
+                record.title = title;
+            }
+            if let Some(model) = update.model.clone() {
+                record.model = Some(model);
+            }
+            if let Some(metadata) = update.metadata.clone() {
+                record.metadata = metadata;
+            }
+            record.updated_at = now();
+            Ok(())
+        })?;
+        Ok(record.session())
+    }
+
+    fn delete_session(&self, workspace: &str, id: &str) -> Result<(), SessionError> {
+        self.record(workspace, id)?;
+        self.client.query(&[&"DEL", &self.key(id)])?;
+        self.client.query(&[&"ZREM", &self.index(workspace), &id])?;
+        self.client.query(&[&"ZREM", &self.all(), &id])?;
+        Ok(())
+    }
+
+    fn load(&self, workspace: &str, id: &str) -> Result<(Session, Vec<Message>), SessionError> {
+        let record = self.record(workspace, id)?;
+        Ok((record.session(), record.messages))
+    }
+
+    fn insert(
+        &self,
+        workspace: &str,
+        id: &str,
+        anchor: Anchor,
+        messages: Vec<NewMessage>,
+    ) -> Result<Vec<Message>, SessionError> {
+        let (inserted, _) = self.update(workspace, id, |record| self.append(record, anchor, messages.clone()))?;
+        Ok(inserted)
+    }
+
+    fn checkout(&self, workspace: &str, id: &str, message_id: i64) -> Result<Session, SessionError> {
+        let record = self.record(workspace, id)?;
+        record.message(message_id)?;
+        if path_ids(&record.messages, record.head_id).contains(&message_id) {
+            return Ok(record.session());
+        }
+        let (_, record) = self.update(workspace, id, |record| {
+            record.message(message_id)?;
+            let head = record.newest_leaf(Some(message_id));
+            record.set_head(head);
+            Ok(())
+        })?;
+        Ok(record.session())
+    }
+
+    fn prune(&self, workspace: &str, id: &str, message_id: Option<i64>) -> Result<usize, SessionError> {
+        let (deleted, _) = self.update(workspace, id, |record| {
+            let deleted = match message_id {
+                Some(message_id) => {
+                    let parent = record.message(message_id)?.parent_id;
+                    let deleted = record.remove(&record.subtree(message_id));
+                    let head_exists = record.head_id.is_some_and(|head| record.message(head).is_ok());
+                    if !head_exists {
+                        let head = record.newest_leaf(parent);
+                        record.set_head(head);
+                    }
+                    deleted
+                }
+                None => {
+                    let keep: HashSet<i64> = path_ids(&record.messages, record.head_id).into_iter().collect();
+                    let removed = record
+                        .messages
+                        .iter()
+                        .map(|message| message.id)
+                        .filter(|id| !keep.contains(id))
+                        .collect();
+                    record.remove(&removed)
+                }
+            };
+            if deleted > 0 {
+                record.updated_at = now();
+            }
+            Ok(deleted)
+        })?;
+        Ok(deleted)
+    }
+
+    fn pending_summaries(
+        &self,
+        min_messages: u64,
+        idle_before: i64,
+        limit: u32,
+    ) -> Result<Vec<SummaryTask>, SessionError> {
+        let ids = Self::ids(self.client.query(&[&"ZRANGEBYSCORE", &self.all(), &"-inf", &idle_before])?)?;
+        let mut tasks = Vec::new();
+        for chunk in ids.chunks(BATCH) {
+            for record in self.records(chunk)? {
+                if tasks.len() >= limit as usize {
+                    return Ok(tasks);
+                }
+                if record.depth(record.head_id) >= record.summarized + min_messages.max(1) {
+                    tasks.push(SummaryTask {
+                        session: record.session(),
+                        workspace: record.workspace,
+                        summarized: record.summarized,
+                    });
+                }
+            }
+        }
+        Ok(tasks)
+    }
+
+    fn set_summary(&self, workspace: &str, id: &str, summary: String, summarized: u64) -> Result<(), SessionError> {
+        self.update(workspace, id, |record| {
+            record.summary = Some(summary.clone());
+            record.summarized = summarized;
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// 逐条匹配所有分支的消息，按时间倒序
+    fn search(&self, workspace: &str, query: &str, limit: u32) -> Result<Vec<SearchHit>, SessionError> {
+        let ids = Self::ids(self.client.query(&[&"ZREVRANGE", &self.index(workspace), &0i64, &-1i64])?)?;
+        let needle = query.to_lowercase();
+        let mut hits = Vec::new();
+        for chunk in ids.chunks(BATCH) {
+            for record in self.records(chunk)? {
+                let title = if record.title.is_empty() { UNTITLED.to_string() } else { record.title.clone() };
+                hits.extend(
+                    record
+                        .messages
+                        .iter()
+                        .filter(|message| message.content.to_lowercase().contains(&needle))
+                        .map(|message| SearchHit {
+                            message_id: message.id,
+                            session_id: record.id.clone(),
+                            title: title.clone(),
+                            role: message.role.clone(),
+                            snippet: excerpt(&message.content, query),
+                            created_at: message.created_at,
+                        }),
+                );
+            }
+        }
+        hits.sort_by_key(|hit| Reverse(hit.message_id));
+        hits.truncate(limit as usize);
+        Ok(hits)
+    }
+
+    fn import(
+        &self,
+        workspace: &str,
+        new: NewSession,
+        messages: Vec<NewMessage>,
+        updated_at: Option<i64>,
+    ) -> Result<Session, SessionError> {
+        let mut record = Record::new(workspace, new)?;
+        self.append(&mut record, Anchor::Head, messages)?;
+        if let Some(updated_at) = updated_at {
+            record.updated_at = updated_at;
+        }
+        self.create(&record)?;
+        Ok(record.session())
+    }
+
+    fn ping(&self) -> Result<(), SessionError> {
+        self.client.query(&[&"PING"])?;
+        Ok(())
+    }
+
+    /// 写入都已提交，没有需要写出的数据
+    fn checkpoint(&self) -> Result<(), SessionError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+//! 数据库结构迁移
+//!
+//! 各版本的升级和回退步骤，新增版本时只需追加到列表末尾。SQLite的结构版本记录在`PRAGMA user_version`中，
+//! PostgreSQL的记录在`openkimi_schema`表中，见`openkimi_schema`。
+
+#[cfg(feature = "postgres")]
+use openkimi_schema::postgres;
+use openkimi_schema::sqlite::{self, Migration};
+use rusqlite::Connection;
+
+use crate::error::SessionError;
+
+/// 会话在结构版本记录中的名称
+pub const COMPONENT: &str = "sessions";
+
+/// SQLite各版本的迁移，下标加一即版本号
+pub const SQLITE: &[Migration] = &[
+    // v1是初始结构，回退会删除全部会话，不提供
+    Migration::sql(
+        "会话、消息、附件和用量",
+        "
+        CREATE TABLE sessions (
+            id TEXT PRIMARY KEY,
+            title TEXT NOT NULL,
+            model TEXT,
+            metadata TEXT NOT NULL DEFAULT '{}',
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL
+        );
+        CREATE INDEX sessions_updated_at ON sessions(updated_at DESC);
+
+        CREATE TABLE messages (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            session_id TEXT NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+            role TEXT NOT NULL,
+            content TEXT NOT NULL,
+            extra TEXT NOT NULL DEFAULT '{}',
+            created_at INTEGER NOT NULL
+        );
+        CREATE INDEX messages_session ON messages(session_id, id);
+
+        CREATE TABLE attachments (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            message_id INTEGER NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+            name TEXT NOT NULL,
+            mime_type TEXT,
+            size INTEGER,
+            uri TEXT,
+            sha256 TEXT,
+            created_at INTEGER NOT NULL
+        );
+        CREATE INDEX attachments_message ON attachments(message_id);
+
+        CREATE TABLE usage (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            session_id TEXT NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+            message_id INTEGER REFERENCES messages(id) ON DELETE SET NULL,
+            model TEXT NOT NULL,
+            prompt_tokens INTEGER NOT NULL,
+            completion_tokens INTEGER NOT NULL,
+            created_at INTEGER NOT NULL
+        );
+        CREATE INDEX usage_session ON usage(session_id);
+
+        -- trigram分词不依赖空格，中文也能按子串检索
+        CREATE VIRTUAL TABLE messages_fts USING fts5(
+            content, content = 'messages', content_rowid = 'id', tokenize = 'trigram'
+        );
+        CREATE TRIGGER messages_fts_insert AFTER INSERT ON messages BEGIN
+            INSERT INTO messages_fts(rowid, content) VALUES (new.id, new.content);
+        END;
+        CREATE TRIGGER messages_fts_delete AFTER DELETE ON messages BEGIN
+            INSERT INTO messages_fts(messages_fts, rowid, content) VALUES ('delete', old.id, old.content);
+        END;
+        CREATE TRIGGER messages_fts_update AFTER UPDATE OF content ON messages BEGIN
+            INSERT INTO messages_fts(messages_fts, rowid, content) VALUES ('delete', old.id, old.content);
+            INSERT INTO messages_fts(rowid, content) VALUES (new.id, new.content);
+        END;
+        ",
+        None,
+    ),
+    // 回退会让各工作区的会话混在一起，不提供
+    Migration::sql(
+        "按工作区隔离会话，已有会话归入默认工作区",
+        "
+        ALTER TABLE sessions ADD COLUMN workspace TEXT NOT NULL DEFAULT 'default';
+        CREATE INDEX sessions_workspace_updated_at ON sessions(workspace, updated_at DESC);
+        ",
+        None,
+    ),
+    Migration::sql(
+        "后台任务生成的会话摘要及其覆盖的消息条数",
+        "
+        ALTER TABLE sessions ADD COLUMN summary TEXT;
+        ALTER TABLE sessions ADD COLUMN summarized INTEGER NOT NULL DEFAULT 0;
+        ",
+        Some(
+            "
+            ALTER TABLE sessions DROP COLUMN summarized;
+            ALTER TABLE sessions DROP COLUMN summary;
+            ",
+        ),
+    ),
+    // parent_id不用外键级联删除，很长的分支会超出SQLite的触发器递归深度。
+    // 回退时只保留各会话当前分支上的消息，旧版本按id顺序读出的仍是同一段对话
+    Migration::sql(
+        "消息按父消息组成树，已有会话的消息连成一条分支",
+        "
+        ALTER TABLE messages ADD COLUMN parent_id INTEGER;
+        ALTER TABLE messages ADD COLUMN depth INTEGER NOT NULL DEFAULT 1;
+        ALTER TABLE sessions ADD COLUMN head_id INTEGER;
+        UPDATE messages SET
+            parent_id = (
+                SELECT MAX(p.id) FROM messages p WHERE p.session_id = messages.session_id AND p.id < messages.id
+            ),
+            depth = (SELECT COUNT(*) FROM messages p WHERE p.session_id = messages.session_id AND p.id <= messages.id);
+        UPDATE sessions SET head_id = (SELECT MAX(m.id) FROM messages m WHERE m.session_id = sessions.id);
+        CREATE INDEX messages_parent ON messages(parent_id);
+        ",
+        Some(
+            "
+            WITH RECURSIVE path(id) AS (
+                SELECT head_id FROM sessions WHERE head_id IS NOT NULL
+                UNION ALL
+                SELECT m.parent_id FROM messages m JOIN path ON m.id = path.id WHERE m.parent_id IS NOT NULL
+            )
+            DELETE FROM messages WHERE id NOT IN (SELECT id FROM path);
+            DROP INDEX messages_parent;
+            ALTER TABLE sessions DROP COLUMN head_id;
+            ALTER TABLE messages DROP COLUMN depth;
+            ALTER TABLE messages DROP COLUMN parent_id;
+            ",
+        ),
+    ),
+];
+
+/// PostgreSQL各版本的迁移，下标加一即版本号
+#[cfg(feature = "postgres")]
+pub const POSTGRES: &[postgres::Migration] = &[
+    // v1是初始结构，回退会删除全部会话，不提供
+    postgres::Migration::sql(
+        "会话、消息、附件和用量",
+        "
+        CREATE TABLE sessions (
+            id TEXT PRIMARY KEY,
+            workspace TEXT NOT NULL,
+            title TEXT NOT NULL,
+            model TEXT,
+            metadata TEXT NOT NULL DEFAULT '{}',
+            created_at BIGINT NOT NULL,
+            updated_at BIGINT NOT NULL,
+            summary TEXT,
+            summarized BIGINT NOT NULL DEFAULT 0,
+            head_id BIGINT
+        );
+        CREATE INDEX sessions_workspace_updated_at ON sessions(workspace, updated_at DESC);
+        CREATE INDEX sessions_updated_at ON sessions(updated_at);
+
+        CREATE TABLE session_messages (
+            id BIGSERIAL PRIMARY KEY,
+            session_id TEXT NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+            parent_id BIGINT,
+            depth BIGINT NOT NULL,
+            role TEXT NOT NULL,
+            content TEXT NOT NULL,
+            extra TEXT NOT NULL DEFAULT '{}',
+            created_at BIGINT NOT NULL
+        );
+        CREATE INDEX session_messages_session ON session_messages(session_id, id);
+        CREATE INDEX session_messages_parent ON session_messages(parent_id);
+
+        CREATE TABLE session_attachments (
+            id BIGSERIAL PRIMARY KEY,
+            message_id BIGINT NOT NULL REFERENCES session_messages(id) ON DELETE CASCADE,
+            name TEXT NOT NULL,
+            mime_type TEXT,
+            size BIGINT,
+            uri TEXT,
+            sha256 TEXT,
+            created_at BIGINT NOT NULL
+        );
+        CREATE INDEX session_attachments_message ON session_attachments(message_id);
+
+        CREATE TABLE session_usage (
+            id BIGSERIAL PRIMARY KEY,
+            session_id TEXT NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+            message_id BIGINT REFERENCES session_messages(id) ON DELETE SET NULL,
+            model TEXT NOT NULL,
+            prompt_tokens BIGINT NOT NULL,
+            completion_tokens BIGINT NOT NULL,
+            created_at BIGINT NOT NULL
+        );
+        CREATE INDEX session_usage_session ON session_usage(session_id);
+        ",
+        None,
+    ),
+];
+
+/// 把数据库升级到最新结构
+pub(crate) fn migrate(conn: &mut Connection) -> Result<(), SessionError> {
+    sqlite::migrate(conn, COMPONENT, SQLITE)?;
+    Ok(())
+}
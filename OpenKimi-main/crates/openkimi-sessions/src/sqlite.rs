@@ -0,0 +1,603 @@
+//! SQLite后端
+//!
+//! 连接放在互斥锁中，一次追加的多条消息及其附件、用量在同一个事务中写入。
+//! 消息记录父消息和在分支上的位置，会话记录当前分支的最后一条消息；按父消息回溯即得到当前分支。
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use rusqlite::{params, Connection, OptionalExtension, Row, Transaction};
+use serde_json::{Map, Value};
+
+use crate::error::SessionError;
+use crate::model::{
+    Attachment, Message, NewMessage, NewSession, SearchHit, Session, SessionQuery, SessionUpdate, SummaryTask,
+    TokenUsage,
+};
+use crate::schema;
+use crate::store::{excerpt, now, path_ids, random_id, title_from, Anchor, Backend, UNTITLED};
+
+const SESSION_COLUMNS: &str = "
+    s.id, s.title, s.model, s.metadata, s.created_at, s.updated_at,
+    COALESCE((SELECT depth FROM messages m WHERE m.id = s.head_id), 0),
+    (SELECT COALESCE(SUM(prompt_tokens), 0) FROM usage u WHERE u.session_id = s.id),
+    (SELECT COALESCE(SUM(completion_tokens), 0) FROM usage u WHERE u.session_id = s.id),
+    s.summary, s.head_id";
+
+fn parse_object(text: String) -> Map<String, Value> {
+    serde_json::from_str(&text).unwrap_or_default()
+}
+
+fn session_from_row(row: &Row) -> rusqlite::Result<Session> {
+    let title: String = row.get(1)?;
+    Ok(Session {
+        id: row.get(0)?,
+        title: if title.is_empty() { UNTITLED.to_string() } else { title },
+        model: row.get(2)?,
+        metadata: parse_object(row.get(3)?),
+        created_at: row.get(4)?,
+        updated_at: row.get(5)?,
+        message_count: row.get(6)?,
+        usage: TokenUsage {
+            prompt_tokens: row.get(7)?,
+            completion_tokens: row.get(8)?,
+        },
+        summary: row.get(9)?,
+        head_id: row.get(10)?,
+    })
+}
+
+fn load_session(conn: &Connection, workspace: &str, id: &str) -> Result<Session, SessionError> {
+    conn.query_row(
+        &format!("SELECT {} FROM sessions s WHERE s.id = ?1 AND s.workspace = ?2", SESSION_COLUMNS),
+        [id, workspace],
+        session_from_row,
+    )
+    .optional()?
+    .ok_or_else(|| SessionError::NotFound(format!("会话 {}", id)))
+}
+
+/// 会话中所有分支的消息，按写入顺序排列
+fn load_messages(conn: &Connection, session_id: &str) -> Result<Vec<Message>, SessionError> {
+    let mut attachments: HashMap<i64, Vec<Attachment>> = HashMap::new();
+    let mut statement = conn.prepare(
+        "SELECT a.message_id, a.id, a.name, a.mime_type, a.size, a.uri, a.sha256, a.created_at
+         FROM attachments a JOIN messages m ON m.id = a.message_id
+         WHERE m.session_id = ?1 ORDER BY a.id",
+    )?;
+    let rows = statement.query_map([session_id], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            Attachment {
+                id: row.get(1)?,
+                name: row.get(2)?,
+                mime_type: row.get(3)?,
+                size: row.get(4)?,
+                uri: row.get(5)?,
+                sha256: row.get(6)?,
+                created_at: row.get(7)?,
+            },
+        ))
+    })?;
+    for row in rows {
+        let (message_id, attachment) = row?;
+        attachments.entry(message_id).or_default().push(attachment);
+    }
+
+    let mut usage: HashMap<i64, TokenUsage> = HashMap::new();
+    let mut statement = conn.prepare(
+        "SELECT message_id, SUM(prompt_tokens), SUM(completion_tokens) FROM usage
+         WHERE session_id = ?1 AND message_id IS NOT NULL GROUP BY message_id",
+    )?;
+    let rows = statement.query_map([session_id], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            TokenUsage {
+                prompt_tokens: row.get(1)?,
+                completion_tokens: row.get(2)?,
+            },
+        ))
+    })?;
+    for row in rows {
+        let (message_id, tokens) = row?;
+        usage.insert(message_id, tokens);
+    }
+
+    let mut statement = conn.prepare(
+        "SELECT id, session_id, parent_id, role, content, extra, created_at FROM messages
+         WHERE session_id = ?1 ORDER BY id",
+    )?;
+    let messages = statement
+        .query_map([session_id], |row| {
+            let id: i64 = row.get(0)?;
+            Ok(Message {
+                id,
+                session_id: row.get(1)?,
+                parent_id: row.get(2)?,
+                role: row.get(3)?,
+                content: row.get(4)?,
+                extra: parse_object(row.get(5)?),
+                created_at: row.get(6)?,
+                attachments: attachments.remove(&id).unwrap_or_default(),
+                usage: usage.remove(&id),
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(messages)
+}
+
+/// 会话中消息`id`在分支上的位置（第一条为1），消息不存在时报错
+fn message_depth(conn: &Connection, session_id: &str, id: i64) -> Result<u64, SessionError> {
+    conn.query_row(
+        "SELECT depth FROM messages WHERE id = ?1 AND session_id = ?2",
+        params![id, session_id],
+        |row| row.get(0),
+    )
+    .optional()?
+    .ok_or_else(|| SessionError::NotFound(format!("消息 {}", id)))
+}
+
+/// `root`及其后续消息中最新的分支末端，`root`为`None`时在整个会话中找
+fn newest_leaf(conn: &Connection, session_id: &str, root: Option<i64>) -> Result<Option<i64>, SessionError> {
+    let leaf = match root {
+        Some(root) => conn.query_row(
+            "WITH RECURSIVE subtree(id) AS (
+                 SELECT ?1 UNION ALL SELECT m.id FROM messages m JOIN subtree ON m.parent_id = subtree.id
+             )
+             SELECT MAX(s.id) FROM subtree s WHERE NOT EXISTS (SELECT 1 FROM messages c WHERE c.parent_id = s.id)",
+            [root],
+            |row| row.get(0),
+        )?,
+        None => conn.query_row(
+            "SELECT MAX(m.id) FROM messages m
+             WHERE m.session_id = ?1 AND NOT EXISTS (SELECT 1 FROM messages c WHERE c.parent_id = m.id)",
+            [session_id],
+            |row| row.get(0),
+        )?,
+    };
+    Ok(leaf)
+}
+
+/// 切换会话的当前分支
+///
+/// 原来的分支不是新分支的前缀时，已有摘要概括的消息不再都在当前分支上，清除摘要等后台任务重新生成。
+fn set_head(conn: &Connection, session_id: &str, head: Option<i64>) -> Result<(), SessionError> {
+    let extends: bool = match head {
+        Some(head) => conn.query_row(
+            "WITH RECURSIVE ancestors(id) AS (
+                 SELECT ?2 UNION ALL
+                 SELECT m.parent_id FROM messages m JOIN ancestors ON m.id = ancestors.id WHERE m.parent_id IS NOT NULL
+             )
+             SELECT head_id IS NULL OR head_id IN (SELECT id FROM ancestors) FROM sessions WHERE id = ?1",
+            params![session_id, head],
+            |row| row.get(0),
+        )?,
+        None => false,
+    };
+    if extends {
+        conn.execute("UPDATE sessions SET head_id = ?2 WHERE id = ?1", params![session_id, head])?;
+    } else {
+        conn.execute(
+            "UPDATE sessions SET head_id = ?2, summary = NULL, summarized = 0 WHERE id = ?1",
+            params![session_id, head],
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_session(tx: &Transaction, workspace: &str, new: NewSession) -> Result<String, SessionError> {
+    let id = match new.id {
+        Some(id) if id.trim().is_empty() => return Err(SessionError::Invalid("会话id不能为空".to_string())),
+        Some(id) => id,
+        None => random_id()?,
+    };
+    let exists = tx
+        .query_row("SELECT 1 FROM sessions WHERE id = ?1", [&id], |_| Ok(()))
+        .optional()?
+        .is_some();
+    if exists {
+        return Err(SessionError::Invalid(format!("会话 {} 已存在", id)));
+    }
+    let created_at = new.created_at.unwrap_or_else(now);
+    tx.execute(
+        "INSERT INTO sessions (id, title, model, metadata, created_at, updated_at, workspace)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?5, ?6)",
+        params![
+            id,
+            new.title.unwrap_or_default(),
+            new.model,
+            Value::Object(new.metadata).to_string(),
+            created_at,
+            workspace
+        ],
+    )?;
+    Ok(id)
+}
+
+/// 在事务中从`anchor`之后依次追加消息，新消息所在的分支成为当前分支，返回新消息的id
+fn insert_messages(
+    tx: &Transaction,
+    workspace: &str,
+    session_id: &str,
+    anchor: Anchor,
+    messages: Vec<NewMessage>,
+) -> Result<Vec<i64>, SessionError> {
+    let (session_model, head): (Option<String>, Option<i64>) = tx
+        .query_row(
+            "SELECT model, head_id FROM sessions WHERE id = ?1 AND workspace = ?2",
+            [session_id, workspace],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?
+        .ok_or_else(|| SessionError::NotFound(format!("会话 {}", session_id)))?;
+    let mut parent = match anchor {
+        Anchor::Head => head,
+        Anchor::After(parent) => parent,
+    };
+    let mut depth = match parent {
+        Some(parent) => message_depth(tx, session_id, parent)?,
+        None => 0,
+    };
+
+    let mut ids = Vec::with_capacity(messages.len());
+    let mut updated_at = None;
+    for message in messages {
+        if message.role.is_empty() {
+            return Err(SessionError::Invalid("消息缺少 role".to_string()));
+        }
+        let created_at = message.created_at.unwrap_or_else(now);
+        updated_at = updated_at.max(Some(created_at));
+        depth += 1;
+        tx.execute(
+            "INSERT INTO messages (session_id, parent_id, depth, role, content, extra, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                session_id,
+                parent,
+                depth,
+                message.role,
+                message.content,
+                Value::Object(message.extra).to_string(),
+                created_at
+            ],
+        )?;
+        let id = tx.last_insert_rowid();
+        parent = Some(id);
+
+        for attachment in message.attachments {
+            if attachment.name.is_empty() {
+                return Err(SessionError::Invalid("附件缺少 name".to_string()));
+            }
+            tx.execute(
+                "INSERT INTO attachments (message_id, name, mime_type, size, uri, sha256, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    id,
+                    attachment.name,
+                    attachment.mime_type,
+                    attachment.size,
+                    attachment.uri,
+                    attachment.sha256,
+                    created_at
+                ],
+            )?;
+        }
+
+        if let Some(usage) = message.usage {
+            let model = message.model.or_else(|| session_model.clone()).unwrap_or_default();
+            tx.execute(
+                "INSERT INTO usage (session_id, message_id, model, prompt_tokens, completion_tokens, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![session_id, id, model, usage.prompt_tokens, usage.completion_tokens, created_at],
+            )?;
+        }
+
+        if message.role == "user" {
+            // 没有标题的会话用第一条用户消息命名
+            tx.execute(
+                "UPDATE sessions SET title = ?2 WHERE id = ?1 AND title = ''",
+                params![session_id, title_from(&message.content)],
+            )?;
+        }
+        ids.push(id);
+    }
+    if let Some(&last) = ids.last() {
+        set_head(tx, session_id, Some(last))?;
+    }
+    if let Some(updated_at) = updated_at {
+        tx.execute(
+            "UPDATE sessions SET updated_at = MAX(updated_at, ?2) WHERE id = ?1",
+            params![session_id, updated_at],
+        )?;
+    }
+    Ok(ids)
+}
+
+/// 保存在一个SQLite数据库文件中
+#[derive(Debug)]
+pub(crate) struct SqliteBackend {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteBackend {
+    /// 打开或创建数据库文件，并升级到最新结构
+    pub fn open(path: &Path) -> Result<SqliteBackend, SessionError> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .map_err(|e| SessionError::Invalid(format!("创建目录 {} 失败: {}", parent.display(), e)))?;
+        }
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        SqliteBackend::init(conn)
+    }
+
+    pub fn open_in_memory() -> Result<SqliteBackend, SessionError> {
+        SqliteBackend::init(Connection::open_in_memory()?)
+    }
+
+    fn init(mut conn: Connection) -> Result<SqliteBackend, SessionError> {
+        conn.pragma_update(None, "foreign_keys", true)?;
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
+        schema::migrate(&mut conn)?;
+        Ok(SqliteBackend { conn: Mutex::new(conn) })
+    }
+
+    fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Backend for SqliteBackend {
+    fn create_session(&self, workspace: &str, new: NewSession) -> Result<Session, SessionError> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        let id = insert_session(&tx, workspace, new)?;
+        tx.commit()?;
+        load_session(&conn, workspace, &id)
+    }
+
+    fn session(&self, workspace: &str, id: &str) -> Result<Session, SessionError> {
+        load_session(&self.conn(), workspace, id)
+    }
+
+    fn list_sessions(&self, workspace: &str, query: SessionQuery) -> Result<Vec<Session>, SessionError> {
+        let conn = self.conn();
+        let mut statement = conn.prepare(&format!(
+            "SELECT {} FROM sessions s WHERE s.workspace = ?3
+             ORDER BY s.updated_at DESC, s.rowid DESC LIMIT ?1 OFFSET ?2",
+            SESSION_COLUMNS
+        ))?;
+        let sessions = statement
+            .query_map(params![query.limit, query.offset, workspace], session_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(sessions)
+    }
+
+    fn update_session(&self, workspace: &str, id: &str, update: SessionUpdate) -> Result<Session, SessionError> {
+        let conn = self.conn();
+        let metadata = update.metadata.map(|metadata| Value::Object(metadata).to_string());
+        let changed = conn.execute(
+            "UPDATE sessions SET title = COALESCE(?2, title), model = COALESCE(?3, model),
+                 metadata = COALESCE(?4, metadata), updated_at = ?5
+             WHERE id = ?1 AND workspace = ?6",
+            params![id, update.title, update.model, metadata, now(), workspace],
+        )?;
+        if changed == 0 {
+            return Err(SessionError::NotFound(format!("会话 {}", id)));
+        }
+        load_session(&conn, workspace, id)
+    }
+
+    fn delete_session(&self, workspace: &str, id: &str) -> Result<(), SessionError> {
+        if self.conn().execute("DELETE FROM sessions WHERE id = ?1 AND workspace = ?2", [id, workspace])? == 0 {
+            return Err(SessionError::NotFound(format!("会话 {}", id)));
+        }
+        Ok(())
+    }
+
+    fn load(&self, workspace: &str, id: &str) -> Result<(Session, Vec<Message>), SessionError> {
+        let conn = self.conn();
+        let session = load_session(&conn, workspace, id)?;
+        let messages = load_messages(&conn, id)?;
+        Ok((session, messages))
+    }
+
+    fn insert(
+        &self,
+        workspace: &str,
+        id: &str,
+        anchor: Anchor,
+        messages: Vec<NewMessage>,
+    ) -> Result<Vec<Message>, SessionError> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        let ids = insert_messages(&tx, workspace, id, anchor, messages)?;
+        tx.commit()?;
+        let mut messages = load_messages(&conn, id)?;
+        messages.retain(|message| ids.contains(&message.id));
+        Ok(messages)
+    }
+
+    fn checkout(&self, workspace: &str, id: &str, message_id: i64) -> Result<Session, SessionError> {
+        let mut conn = self.conn();
+        let session = load_session(&conn, workspace, id)?;
+        message_depth(&conn, id, message_id)?;
+        if path_ids(&load_messages(&conn, id)?, session.head_id).contains(&message_id) {
+            return Ok(session);
+        }
+        let tx = conn.transaction()?;
+        let head = newest_leaf(&tx, id, Some(message_id))?;
+        set_head(&tx, id, head)?;
+        tx.commit()?;
+        load_session(&conn, workspace, id)
+    }
+
+    fn prune(&self, workspace: &str, id: &str, message_id: Option<i64>) -> Result<usize, SessionError> {
+        let mut conn = self.conn();
+        let session = load_session(&conn, workspace, id)?;
+        let tx = conn.transaction()?;
+        let deleted = match message_id {
+            Some(message_id) => {
+                message_depth(&tx, id, message_id)?;
+                let parent: Option<i64> =
+                    tx.query_row("SELECT parent_id FROM messages WHERE id = ?1", [message_id], |row| row.get(0))?;
+                let deleted = tx.execute(
+                    "WITH RECURSIVE subtree(id) AS (
+                         SELECT ?1 UNION ALL SELECT m.id FROM messages m JOIN subtree ON m.parent_id = subtree.id
+                     )
+                     DELETE FROM messages WHERE id IN (SELECT id FROM subtree)",
+                    [message_id],
+                )?;
+                let head_exists = match session.head_id {
+                    Some(head) => message_depth(&tx, id, head).is_ok(),
+                    None => false,
+                };
+                if !head_exists {
+                    let head = match parent {
+                        Some(parent) => newest_leaf(&tx, id, Some(parent))?,
+                        None => newest_leaf(&tx, id, None)?,
+                    };
+                    set_head(&tx, id, head)?;
+                }
+                deleted
+            }
+            None => {
+                let messages = load_messages(&tx, id)?;
+                let keep: HashSet<i64> = path_ids(&messages, session.head_id).into_iter().collect();
+                let mut statement = tx.prepare("DELETE FROM messages WHERE id = ?1")?;
+                let mut deleted = 0;
+                for message in messages.iter().filter(|message| !keep.contains(&message.id)) {
+                    deleted += statement.execute([message.id])?;
+                }
+                deleted
+            }
+        };
+        if deleted > 0 {
+            tx.execute("UPDATE sessions SET updated_at = ?2 WHERE id = ?1", params![id, now()])?;
+        }
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    fn pending_summaries(
+        &self,
+        min_messages: u64,
+        idle_before: i64,
+        limit: u32,
+    ) -> Result<Vec<SummaryTask>, SessionError> {
+        let conn = self.conn();
+        let mut statement = conn.prepare(&format!(
+            "SELECT {}, s.workspace, s.summarized FROM sessions s
+             WHERE COALESCE((SELECT depth FROM messages m WHERE m.id = s.head_id), 0) >= s.summarized + ?1
+                 AND s.updated_at <= ?2
+             ORDER BY s.updated_at LIMIT ?3",
+            SESSION_COLUMNS
+        ))?;
+        let tasks = statement
+            .query_map(params![min_messages.max(1), idle_before, limit], |row| {
+                Ok(SummaryTask {
+                    session: session_from_row(row)?,
+                    workspace: row.get(11)?,
+                    summarized: row.get(12)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(tasks)
+    }
+
+    fn set_summary(&self, workspace: &str, id: &str, summary: String, summarized: u64) -> Result<(), SessionError> {
+        let changed = self.conn().execute(
+            "UPDATE sessions SET summary = ?2, summarized = ?3 WHERE id = ?1 AND workspace = ?4",
+            params![id, summary, summarized, workspace],
+        )?;
+        if changed == 0 {
+            return Err(SessionError::NotFound(format!("会话 {}", id)));
+        }
+        Ok(())
+    }
+
+    /// 三个字符以上的关键词走全文索引、按相关度排序；更短的关键词逐条匹配，按时间倒序
+    fn search(&self, workspace: &str, query: &str, limit: u32) -> Result<Vec<SearchHit>, SessionError> {
+        let conn = self.conn();
+        let hit = |row: &Row| {
+            Ok(SearchHit {
+                message_id: row.get(0)?,
+                session_id: row.get(1)?,
+                title: row.get::<_, String>(2).map(|title| {
+                    if title.is_empty() {
+                        UNTITLED.to_string()
+                    } else {
+                        title
+                    }
+                })?,
+                role: row.get(3)?,
+                snippet: row.get(4)?,
+                created_at: row.get(5)?,
+            })
+        };
+        let hits = if query.chars().count() >= 3 {
+            let mut statement = conn.prepare(
+                "SELECT m.id, m.session_id, s.title, m.role,
+                        snippet(messages_fts, 0, '[', ']', '…', 16), m.created_at
+                 FROM messages_fts
+                 JOIN messages m ON m.id = messages_fts.rowid
+                 JOIN sessions s ON s.id = m.session_id
+                 WHERE messages_fts MATCH ?1 AND s.workspace = ?3 ORDER BY rank LIMIT ?2",
+            )?;
+            let phrase = format!("\"{}\"", query.replace('"', "\"\""));
+            let hits = statement
+                .query_map(params![phrase, limit, workspace], hit)?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            hits
+        } else {
+            let pattern = format!("%{}%", query.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+            let mut statement = conn.prepare(
+                "SELECT m.id, m.session_id, s.title, m.role, m.content, m.created_at
+                 FROM messages m JOIN sessions s ON s.id = m.session_id
+                 WHERE m.content LIKE ?1 ESCAPE '\\' AND s.workspace = ?3 ORDER BY m.id DESC LIMIT ?2",
+            )?;
+            let hits = statement
+                .query_map(params![pattern, limit, workspace], hit)?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            hits.into_iter()
+                .map(|hit| SearchHit {
+                    snippet: excerpt(&hit.snippet, query),
+                    ..hit
+                })
+                .collect()
+        };
+        Ok(hits)
+    }
+
+    fn import(
+        &self,
+        workspace: &str,
+        new: NewSession,
+        messages: Vec<NewMessage>,
+        updated_at: Option<i64>,
+    ) -> Result<Session, SessionError> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        let id = insert_session(&tx, workspace, new)?;
+        insert_messages(&tx, workspace, &id, Anchor::Head, messages)?;
+        if let Some(updated_at) = updated_at {
+            tx.execute("UPDATE sessions SET updated_at = ?2 WHERE id = ?1", params![id, updated_at])?;
+        }
+        tx.commit()?;
+        load_session(&conn, workspace, &id)
+    }
+
+    /// 执行一次简单的查询，确认数据库可以读取
+    fn ping(&self) -> Result<(), SessionError> {
+        self.conn().prepare_cached("SELECT 1 FROM sessions LIMIT 1")?.exists([])?;
+        Ok(())
+    }
+
+    /// 把WAL合并回数据库文件
+    fn checkpoint(&self) -> Result<(), SessionError> {
+        self.conn().execute_batch("PRAGMA wal_checkpoint(TRUNCATE)")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,610 @@
+//! 会话存储的接口
+//!
+//! [`SessionStore`]把操作交给存储后端：默认的SQLite后端保存在本地数据库文件中，启用`postgres`或`redis`特性后
+//! 也可以保存在PostgreSQL或Redis中，供多个服务实例共享。后端的方法都是阻塞的，在阻塞线程中调用。
+//! 每个会话属于一个工作区，[`SessionStore::in_workspace`]得到的存储只能看到该工作区的会话。
+//! 与后端无关的部分，如分支列表、合并分支、导入导出的文档格式和导入其他产品的对话，在这里按会话的全部消息计算。
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Map, Value};
+
+use crate::error::SessionError;
+use crate::external::Conversation;
+use crate::model::{
+    Branch, ExternalImport, Message, NewAttachment, NewMessage, NewSession, SearchHit, Session, SessionQuery, SessionUpdate,
+    SummaryTask,
+};
+use crate::sqlite::SqliteBackend;
+
+/// 未设置标题、也还没有用户消息的会话显示的标题
+pub const UNTITLED: &str = "未命名会话";
+
+/// [`SessionStore::open`]得到的存储所在的工作区，也是升级前已有会话所在的工作区
+pub const DEFAULT_WORKSPACE: &str = "default";
+
+/// 自动标题取第一条用户消息的前几个字符
+const TITLE_CHARS: usize = 30;
+
+/// 导出文档的格式版本，与客户端旧版会话文件的v1格式兼容
+const EXPORT_VERSION: u32 = 1;
+
+/// 导入时作为消息字段识别的键，其余键保存到`extra`
+const MESSAGE_FIELDS: &[&str] = &["id", "role", "content", "created_at", "attachments", "usage", "model"];
+
+pub(crate) fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+pub(crate) fn random_id() -> Result<String, SessionError> {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).map_err(|e| SessionError::Invalid(format!("生成会话id失败: {}", e)))?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+pub(crate) fn title_from(content: &str) -> String {
+    content.split_whitespace().collect::<Vec<_>>().join(" ").chars().take(TITLE_CHARS).collect()
+}
+
+/// 从`head`沿父消息回溯到第一条消息的路径，按从第一条消息开始的顺序排列
+pub(crate) fn path_ids(messages: &[Message], head: Option<i64>) -> Vec<i64> {
+    let parents: HashMap<i64, Option<i64>> = messages.iter().map(|message| (message.id, message.parent_id)).collect();
+    let mut ids = Vec::new();
+    let mut current = head;
+    while let Some(id) = current {
+        ids.push(id);
+        current = parents.get(&id).copied().flatten();
+    }
+    ids.reverse();
+    ids
+}
+
+/// 会话当前分支上的消息；子消息总是在父消息之后写入，按id排列即按路径排列
+fn current_path(session: &Session, mut messages: Vec<Message>) -> Vec<Message> {
+    let path: HashSet<i64> = path_ids(&messages, session.head_id).into_iter().collect();
+    messages.retain(|message| path.contains(&message.id));
+    messages
+}
+
+/// 新消息接在哪条消息之后
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Anchor {
+    /// 当前分支的最后一条消息
+    Head,
+    /// 指定的消息，`None`为会话开头
+    After(Option<i64>),
+}
+
+/// 存储后端，`workspace`限定可以访问的会话
+pub(crate) trait Backend: Send + Sync + fmt::Debug {
+    fn create_session(&self, workspace: &str, new: NewSession) -> Result<Session, SessionError>;
+    fn session(&self, workspace: &str, id: &str) -> Result<Session, SessionError>;
+    fn list_sessions(&self, workspace: &str, query: SessionQuery) -> Result<Vec<Session>, SessionError>;
+    fn update_session(&self, workspace: &str, id: &str, update: SessionUpdate) -> Result<Session, SessionError>;
+    fn delete_session(&self, workspace: &str, id: &str) -> Result<(), SessionError>;
+    /// 会话及其所有分支的消息，消息按写入顺序排列
+    fn load(&self, workspace: &str, id: &str) -> Result<(Session, Vec<Message>), SessionError>;
+    /// 从`anchor`之后依次追加消息，新消息所在的分支成为当前分支，返回写入后的消息
+    fn insert(&self, workspace: &str, id: &str, anchor: Anchor, messages: Vec<NewMessage>)
+        -> Result<Vec<Message>, SessionError>;
+    fn checkout(&self, workspace: &str, id: &str, message_id: i64) -> Result<Session, SessionError>;
+    fn prune(&self, workspace: &str, id: &str, message_id: Option<i64>) -> Result<usize, SessionError>;
+    /// 所有工作区中需要重新生成摘要的会话
+    fn pending_summaries(&self, min_messages: u64, idle_before: i64, limit: u32)
+        -> Result<Vec<SummaryTask>, SessionError>;
+    fn set_summary(&self, workspace: &str, id: &str, summary: String, summarized: u64) -> Result<(), SessionError>;
+    fn search(&self, workspace: &str, query: &str, limit: u32) -> Result<Vec<SearchHit>, SessionError>;
+    /// 新建会话并写入导入的消息，`updated_at`为文档中记录的修改时间
+    fn import(
+        &self,
+        workspace: &str,
+        new: NewSession,
+        messages: Vec<NewMessage>,
+        updated_at: Option<i64>,
+    ) -> Result<Session, SessionError>;
+    fn ping(&self) -> Result<(), SessionError>;
+    /// 退出前写出缓存的数据
+    fn checkpoint(&self) -> Result<(), SessionError>;
+}
+
+/// 命中位置前后各取一段文本，用于不走全文索引的短关键词
+pub(crate) fn excerpt(content: &str, query: &str) -> String {
+    const CONTEXT_CHARS: usize = 20;
+    let lower = content.to_lowercase();
+    let Some(start) = lower.find(&query.to_lowercase()).filter(|_| lower.len() == content.len()) else {
+        return content.chars().take(CONTEXT_CHARS * 2).collect();
+    };
+    let end = start + query.len();
+    let before: Vec<char> = content[..start].chars().collect();
+    let after: String = content[end..].chars().take(CONTEXT_CHARS).collect();
+    let prefix: String = before[before.len().saturating_sub(CONTEXT_CHARS)..].iter().collect();
+    let ellipsis = if before.len() > CONTEXT_CHARS { "…" } else { "" };
+    format!("{}{}[{}]{}", ellipsis, prefix, &content[start..end], after)
+}
+
+/// 导入文档中的消息内容，可以是字符串或OpenAI格式的内容片段
+fn content_text(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part.get("text").and_then(Value::as_str))
+            .collect::<Vec<_>>()
+            .join(""),
+        _ => String::new(),
+    }
+}
+
+fn import_message(item: &Value) -> Result<Option<NewMessage>, SessionError> {
+    let Some(object) = item.as_object() else {
+        return Err(SessionError::Invalid("消息必须是对象".to_string()));
+    };
+    let Some(role) = object.get("role").and_then(Value::as_str) else {
+        // 旧版导出文件中可能混有不是消息的条目
+        return Ok(None);
+    };
+    let attachments = match object.get("attachments") {
+        Some(value) => serde_json::from_value(value.clone())?,
+        None => Vec::new(),
+    };
+    let usage = match object.get("usage") {
+        Some(value) if !value.is_null() => Some(serde_json::from_value(value.clone())?),
+        _ => None,
+    };
+    let extra = object
+        .iter()
+        .filter(|(key, _)| !MESSAGE_FIELDS.contains(&key.as_str()))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    Ok(Some(NewMessage {
+        role: role.to_string(),
+        content: object.get("content").map(content_text).unwrap_or_default(),
+        extra,
+        attachments,
+        usage,
+        model: object.get("model").and_then(Value::as_str).map(str::to_string),
+        created_at: object.get("created_at").and_then(Value::as_i64),
+    }))
+}
+
+
+/// 会话存储，克隆后共享同一个后端
+#[derive(Debug, Clone)]
+pub struct SessionStore {
+    backend: Arc<dyn Backend>,
+    workspace: Arc<str>,
+}
+
+impl SessionStore {
+    fn with_backend(backend: impl Backend + 'static) -> SessionStore {
+        SessionStore {
+            backend: Arc::new(backend),
+            workspace: Arc::from(DEFAULT_WORKSPACE),
+        }
+    }
+
+    /// 打开或创建SQLite数据库文件，并升级到最新结构
+    pub fn open(path: impl AsRef<Path>) -> Result<SessionStore, SessionError> {
+        Ok(SessionStore::with_backend(SqliteBackend::open(path.as_ref())?))
+    }
+
+    /// 内存数据库，关闭后数据丢失
+    pub fn open_in_memory() -> Result<SessionStore, SessionError> {
+        Ok(SessionStore::with_backend(SqliteBackend::open_in_memory()?))
+    }
+
+    /// 保存在PostgreSQL中，并把表升级到最新结构；连接同一个数据库的多个实例看到相同的会话
+    #[cfg(feature = "postgres")]
+    pub fn postgres(pool: openkimi_postgres::Pool) -> Result<SessionStore, SessionError> {
+        Ok(SessionStore::with_backend(crate::postgres::PostgresBackend::open(pool)?))
+    }
+
+    /// 保存在Redis中，键名以`prefix`开头；连接同一个Redis的多个实例看到相同的会话
+    #[cfg(feature = "redis")]
+    pub fn redis(client: openkimi_redis::Client, prefix: &str) -> SessionStore {
+        SessionStore::with_backend(crate::redis::RedisBackend::new(client, prefix))
+    }
+
+    /// 共享同一个后端、只访问工作区`workspace`中会话的存储
+    pub fn in_workspace(&self, workspace: &str) -> SessionStore {
+        SessionStore {
+            backend: Arc::clone(&self.backend),
+            workspace: Arc::from(workspace),
+        }
+    }
+
+    pub fn workspace(&self) -> &str {
+        &self.workspace
+    }
+
+    /// 在阻塞线程中使用后端
+    async fn run<T, F>(&self, f: F) -> Result<T, SessionError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn Backend, &str) -> Result<T, SessionError> + Send + 'static,
+    {
+        let (backend, workspace) = (Arc::clone(&self.backend), Arc::clone(&self.workspace));
+        tokio::task::spawn_blocking(move || f(&*backend, &workspace))
+            .await
+            .map_err(|e| SessionError::Invalid(format!("会话存储任务失败: {}", e)))?
+    }
+
+    pub async fn create_session(&self, new: NewSession) -> Result<Session, SessionError> {
+        self.run(move |backend, workspace| backend.create_session(workspace, new)).await
+    }
+
+    pub async fn session(&self, id: &str) -> Result<Session, SessionError> {
+        let id = id.to_string();
+        self.run(move |backend, workspace| backend.session(workspace, &id)).await
+    }
+
+    pub async fn list_sessions(&self, query: SessionQuery) -> Result<Vec<Session>, SessionError> {
+        self.run(move |backend, workspace| backend.list_sessions(workspace, query)).await
+    }
+
+    pub async fn update_session(&self, id: &str, update: SessionUpdate) -> Result<Session, SessionError> {
+        let id = id.to_string();
+        self.run(move |backend, workspace| backend.update_session(workspace, &id, update)).await
+    }
+
+    /// 执行一次简单的查询，确认存储可以读取
+    pub async fn ping(&self) -> Result<(), SessionError> {
+        self.run(|backend, _| backend.ping()).await
+    }
+
+    /// 退出前调用：SQLite把WAL合并回数据库文件
+    pub async fn checkpoint(&self) -> Result<(), SessionError> {
+        self.run(|backend, _| backend.checkpoint()).await
+    }
+
+    /// 删除会话及其全部消息、附件信息和用量
+    pub async fn delete_session(&self, id: &str) -> Result<(), SessionError> {
+        let id = id.to_string();
+        self.run(move |backend, workspace| backend.delete_session(workspace, &id)).await
+    }
+
+    /// 在当前分支末尾按顺序追加消息，返回写入后的消息
+    pub async fn append_messages(&self, id: &str, messages: Vec<NewMessage>) -> Result<Vec<Message>, SessionError> {
+        self.insert(id, Anchor::Head, messages).await
+    }
+
+    /// 从消息`parent`之后（`None`为会话开头）按顺序追加消息，返回写入后的消息
+    ///
+    /// `parent`已有后续消息时开出新的分支，例如编辑提问时传入原提问的父消息，重新生成回复时传入提问本身。
+    /// 新消息所在的分支成为当前分支。
+    pub async fn branch_messages(
+        &self,
+        id: &str,
+        parent: Option<i64>,
+        messages: Vec<NewMessage>,
+    ) -> Result<Vec<Message>, SessionError> {
+        self.insert(id, Anchor::After(parent), messages).await
+    }
+
+    async fn insert(&self, id: &str, anchor: Anchor, messages: Vec<NewMessage>) -> Result<Vec<Message>, SessionError> {
+        let id = id.to_string();
+        self.run(move |backend, workspace| backend.insert(workspace, &id, anchor, messages)).await
+    }
+
+    /// 会话当前分支上的消息，按顺序排列
+    pub async fn messages(&self, id: &str) -> Result<Vec<Message>, SessionError> {
+        let id = id.to_string();
+        self.run(move |backend, workspace| {
+            let (session, messages) = backend.load(workspace, &id)?;
+            Ok(current_path(&session, messages))
+        })
+        .await
+    }
+
+    /// 会话中所有分支的消息，按写入顺序排列，用`parent_id`组成树
+    pub async fn message_tree(&self, id: &str) -> Result<Vec<Message>, SessionError> {
+        let id = id.to_string();
+        self.run(move |backend, workspace| Ok(backend.load(workspace, &id)?.1)).await
+    }
+
+    /// 会话中的所有分支，按创建顺序排列
+    pub async fn branches(&self, id: &str) -> Result<Vec<Branch>, SessionError> {
+        let id = id.to_string();
+        self.run(move |backend, workspace| {
+            let (session, messages) = backend.load(workspace, &id)?;
+            let active: HashSet<i64> = path_ids(&messages, session.head_id).into_iter().collect();
+            let parents: HashSet<i64> = messages.iter().filter_map(|message| message.parent_id).collect();
+            let branches = messages
+                .iter()
+                .filter(|message| !parents.contains(&message.id))
+                .map(|leaf| {
+                    let path = path_ids(&messages, Some(leaf.id));
+                    let is_active = session.head_id == Some(leaf.id);
+                    Branch {
+                        head_id: leaf.id,
+                        message_count: path.len() as u64,
+                        active: is_active,
+                        fork_id: path
+                            .iter()
+                            .take_while(|id| active.contains(id))
+                            .last()
+                            .copied()
+                            .filter(|_| !is_active),
+                        preview: title_from(&leaf.content),
+                        updated_at: leaf.created_at,
+                    }
+                })
+                .collect();
+            Ok(branches)
+        })
+        .await
+    }
+
+    /// 切换到经过消息`message_id`的分支，返回切换后的会话
+    ///
+    /// 当前分支已经经过这条消息时不切换，否则有多个这样的分支时选最新的一个。
+    pub async fn checkout(&self, id: &str, message_id: i64) -> Result<Session, SessionError> {
+        let id = id.to_string();
+        self.run(move |backend, workspace| backend.checkout(workspace, &id, message_id)).await
+    }
+
+    /// 把分支`source`在与`target`分开之后的消息复制到`target`之后，返回复制出的消息
+    ///
+    /// `target`缺省为当前分支的最后一条消息；复制的消息不带用量，避免重复计算。合并后的分支成为当前分支。
+    pub async fn merge(&self, id: &str, source: i64, target: Option<i64>) -> Result<Vec<Message>, SessionError> {
+        let id = id.to_string();
+        self.run(move |backend, workspace| {
+            let (session, messages) = backend.load(workspace, &id)?;
+            let target = target
+                .or(session.head_id)
+                .ok_or_else(|| SessionError::Invalid("会话还没有消息".to_string()))?;
+            for message_id in [source, target] {
+                if !messages.iter().any(|message| message.id == message_id) {
+                    return Err(SessionError::NotFound(format!("消息 {}", message_id)));
+                }
+            }
+            let shared: HashSet<i64> = path_ids(&messages, Some(target)).into_iter().collect();
+            let copied: HashSet<i64> =
+                path_ids(&messages, Some(source)).into_iter().filter(|id| !shared.contains(id)).collect();
+            if copied.is_empty() {
+                return Err(SessionError::Invalid(format!("消息 {} 所在分支的消息都已在目标分支上", source)));
+            }
+            let new = messages
+                .into_iter()
+                .filter(|message| copied.contains(&message.id))
+                .map(|message| NewMessage {
+                    role: message.role,
+                    content: message.content,
+                    extra: message.extra,
+                    attachments: message
+                        .attachments
+                        .into_iter()
+                        .map(|attachment| NewAttachment {
+                            name: attachment.name,
+                            mime_type: attachment.mime_type,
+                            size: attachment.size,
+                            uri: attachment.uri,
+                            sha256: attachment.sha256,
+                        })
+                        .collect(),
+                    ..NewMessage::default()
+                })
+                .collect();
+            backend.insert(workspace, &id, Anchor::After(Some(target)), new)
+        })
+        .await
+    }
+
+    /// 删除消息`message_id`及其后续的所有消息，不指定时删除当前分支以外的所有消息，返回删除的消息数
+    ///
+    /// 当前分支被删除时切换到被删消息的父消息之后最新的分支。
+    pub async fn prune(&self, id: &str, message_id: Option<i64>) -> Result<usize, SessionError> {
+        let id = id.to_string();
+        self.run(move |backend, workspace| backend.prune(workspace, &id, message_id)).await
+    }
+
+    /// 所有工作区中需要重新生成摘要的会话，按最后修改时间排列
+    ///
+    /// 自上次摘要以来新增了至少`min_messages`条消息、且`idle_before`之后没有再修改的会话才需要摘要，
+    /// 避免对还在进行的对话反复摘要。
+    pub async fn pending_summaries(
+        &self,
+        min_messages: u64,
+        idle_before: i64,
+        limit: u32,
+    ) -> Result<Vec<SummaryTask>, SessionError> {
+        self.run(move |backend, _| backend.pending_summaries(min_messages, idle_before, limit)).await
+    }
+
+    /// 保存会话摘要及其覆盖的消息条数，不改变会话的修改时间
+    pub async fn set_summary(&self, id: &str, summary: String, summarized: u64) -> Result<(), SessionError> {
+        let id = id.to_string();
+        self.run(move |backend, workspace| backend.set_summary(workspace, &id, summary, summarized)).await
+    }
+
+    /// 在工作区所有会话的消息中检索关键词
+    ///
+    /// SQLite后端对三个字符以上的关键词走全文索引、按相关度排序，更短的关键词逐条匹配、按时间倒序；
+    /// PostgreSQL和Redis后端都逐条匹配。
+    pub async fn search(&self, query: &str, limit: u32) -> Result<Vec<SearchHit>, SessionError> {
+        let query = query.trim().to_string();
+        if query.is_empty() {
+            return Err(SessionError::Invalid("检索关键词不能为空".to_string()));
+        }
+        self.run(move |backend, workspace| backend.search(workspace, &query, limit)).await
+    }
+
+    /// 把当前分支导出为会话文档`{version, id, title, created_at, messages, ...}`，可再用[`SessionStore::import`]导入
+    pub async fn export(&self, id: &str) -> Result<Value, SessionError> {
+        let id = id.to_string();
+        self.run(move |backend, workspace| {
+            let (session, messages) = backend.load(workspace, &id)?;
+            let messages: Vec<Value> = current_path(&session, messages)
+                .into_iter()
+                .map(|message| {
+                    let mut item = message.extra;
+                    item.insert("role".to_string(), json!(message.role));
+                    item.insert("content".to_string(), json!(message.content));
+                    item.insert("created_at".to_string(), json!(message.created_at));
+                    if !message.attachments.is_empty() {
+                        item.insert("attachments".to_string(), json!(message.attachments));
+                    }
+                    if let Some(usage) = message.usage {
+                        item.insert("usage".to_string(), json!(usage));
+                    }
+                    Value::Object(item)
+                })
+                .collect();
+            Ok(json!({
+                "version": EXPORT_VERSION,
+                "id": session.id,
+                "title": session.title,
+                "model": session.model,
+                "metadata": session.metadata,
+                "created_at": session.created_at,
+                "updated_at": session.updated_at,
+                "usage": session.usage,
+                "messages": messages,
+            }))
+        })
+        .await
+    }
+
+    /// 导入会话文档，也接受旧版客户端的消息数组`[{role, content}]`
+    ///
+    /// 文档中的id已存在时报错；`id`缺省时使用`fallback_id`，两者都没有时随机生成。
+    pub async fn import(&self, document: Value, fallback_id: Option<String>) -> Result<Session, SessionError> {
+        let (object, items) = match document {
+            Value::Array(items) => (Map::new(), items),
+            Value::Object(mut object) => match object.remove("messages") {
+                Some(Value::Array(items)) => (object, items),
+                _ => return Err(SessionError::Invalid("会话文档缺少 messages 数组".to_string())),
+            },
+            _ => return Err(SessionError::Invalid("会话文档必须是对象或消息数组".to_string())),
+        };
+        let messages = items
+            .iter()
+            .map(import_message)
+            .filter_map(Result::transpose)
+            .collect::<Result<Vec<_>, _>>()?;
+        let string = |key: &str| object.get(key).and_then(Value::as_str).map(str::to_string);
+        let new = NewSession {
+            id: string("id").or(fallback_id),
+            title: string("title").filter(|title| title != UNTITLED),
+            model: string("model"),
+            metadata: object.get("metadata").and_then(Value::as_object).cloned().unwrap_or_default(),
+            created_at: object.get("created_at").and_then(Value::as_i64),
+        };
+        // 旧消息没有时间时视为与会话同时创建
+        let messages = messages
+            .into_iter()
+            .map(|message| NewMessage {
+                created_at: message.created_at.or(new.created_at),
+                ..message
+            })
+            .collect();
+        let updated_at = object.get("updated_at").and_then(Value::as_i64);
+        self.run(move |backend, workspace| backend.import(workspace, new, messages, updated_at)).await
+    }
+
+    /// 导入ChatGPT或Claude导出的对话（见[`crate::external`]），保留各分支和消息的时间
+    ///
+    /// 原产品中的对话id记录在会话的`metadata.imported_from`中，导入过的对话和没有消息的对话跳过，
+    /// 重复导入同一份导出文件只会补上新增的对话。
+    pub async fn import_external(&self, conversations: Vec<Conversation>) -> Result<ExternalImport, SessionError> {
+        let sessions = self.list_sessions(SessionQuery { limit: u32::MAX, offset: 0 }).await?;
+        let mut seen: HashSet<(String, String)> = sessions
+            .iter()
+            .filter_map(|session| {
+                let origin = session.metadata.get("imported_from")?;
+                Some((origin["source"].as_str()?.to_string(), origin["id"].as_str()?.to_string()))
+            })
+            .collect();
+        let mut imported = Vec::new();
+        let mut skipped = 0;
+        for conversation in conversations {
+            let key = (conversation.source.name().to_string(), conversation.id.clone());
+            if conversation.messages.is_empty() || (!key.1.is_empty() && !seen.insert(key)) {
+                skipped += 1;
+                continue;
+            }
+            imported.push(self.import_conversation(conversation).await?);
+        }
+        Ok(ExternalImport { imported, skipped })
+    }
+
+    /// 先导入当前分支，再从各个分叉处补上其余分支，最后切换回当前分支
+    async fn import_conversation(&self, conversation: Conversation) -> Result<Session, SessionError> {
+        let path = conversation.current_path();
+        let mut metadata = Map::new();
+        metadata.insert("imported_from".to_string(), conversation.origin());
+        let new = NewSession {
+            id: None,
+            title: Some(conversation.title).filter(|title| !title.is_empty()),
+            model: None,
+            metadata,
+            created_at: conversation.created_at,
+        };
+        // 修改时间不早于最后一条消息，包括不在当前分支上的
+        let latest = conversation.messages.iter().filter_map(|node| node.message.created_at).max();
+        let updated_at = conversation.updated_at.max(latest);
+        let mut parents = Vec::with_capacity(conversation.messages.len());
+        let mut pending = Vec::with_capacity(conversation.messages.len());
+        for node in conversation.messages {
+            parents.push(node.parent);
+            pending.push(Some(node.message));
+        }
+        let messages = path.iter().filter_map(|&index| pending[index].take()).collect();
+        let session = self.run(move |backend, workspace| backend.import(workspace, new, messages, updated_at)).await?;
+        if pending.iter().all(Option::is_none) {
+            return Ok(session);
+        }
+        let result = self.import_branches(&session.id, &path, &parents, pending).await;
+        if result.is_err() {
+            let _ = self.delete_session(&session.id).await;
+        }
+        result
+    }
+
+    async fn import_branches(
+        &self,
+        id: &str,
+        path: &[usize],
+        parents: &[Option<usize>],
+        mut pending: Vec<Option<NewMessage>>,
+    ) -> Result<Session, SessionError> {
+        let mut children: Vec<Vec<usize>> = vec![Vec::new(); parents.len()];
+        for (index, parent) in parents.iter().enumerate() {
+            if let Some(parent) = parent {
+                children[*parent].push(index);
+            }
+        }
+        let mut stored: Vec<Option<i64>> = vec![None; parents.len()];
+        let tree = self.message_tree(id).await?;
+        for (&index, message) in path.iter().zip(&tree) {
+            stored[index] = Some(message.id);
+        }
+        // 父消息总在子消息之前；每次沿第一条未导入的子消息一直走到底，作为一个分支写入
+        for start in 0..parents.len() {
+            if pending[start].is_none() {
+                continue;
+            }
+            let mut chain = vec![start];
+            while let Some(&next) = children[chain[chain.len() - 1]].iter().find(|&&child| pending[child].is_some()) {
+                chain.push(next);
+            }
+            let parent = parents[start].and_then(|parent| stored[parent]);
+            let messages = chain.iter().filter_map(|&index| pending[index].take()).collect();
+            let inserted = self.branch_messages(id, parent, messages).await?;
+            for (&index, message) in chain.iter().zip(&inserted) {
+                stored[index] = Some(message.id);
+            }
+        }
+        match path.last().and_then(|&index| stored[index]) {
+            Some(head) => self.checkout(id, head).await,
+            None => self.session(id).await,
+        }
+    }
+}
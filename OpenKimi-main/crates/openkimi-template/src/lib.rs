@@ -0,0 +1,642 @@
+//! 提示词模板引擎，服务端的提示词模板和kimi的预设共用
+//!
+//! 语法是Jinja的子集：
+//! - `{{ user.name }}`输出变量，可以接过滤器，如`{{ tone | default("友好") | upper }}`
+//! - `{% if 条件 %}...{% elif 条件 %}...{% else %}...{% endif %}`，条件支持`==`、`!=`、`not`、`and`、`or`
+//! - `{% for item in items %}...{% endfor %}`，循环内可用`loop.index`、`loop.index0`、`loop.first`、`loop.last`
+//! - `{# 注释 #}`；标签内侧加`-`（如`{%- if x -%}`）去掉该侧相邻的空白
+//!
+//! 过滤器有`default`、`upper`、`lower`、`trim`、`capitalize`、`length`、`join`和`tojson`。
+//! 未提供的变量按空值处理，输出为空字符串，在条件中为假。
+
+use std::collections::BTreeSet;
+
+use serde_json::{Map, Value};
+
+const FILTERS: [&str; 8] = ["default", "upper", "lower", "trim", "capitalize", "length", "join", "tojson"];
+
+/// 编译后的模板
+#[derive(Debug, Clone)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Output(Expr),
+    If {
+        branches: Vec<(Cond, Vec<Node>)>,
+        otherwise: Vec<Node>,
+    },
+    For {
+        var: String,
+        items: Expr,
+        body: Vec<Node>,
+    },
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Path(Vec<String>),
+    Literal(Value),
+}
+
+#[derive(Debug, Clone)]
+struct Expr {
+    operand: Operand,
+    filters: Vec<(String, Vec<Value>)>,
+}
+
+#[derive(Debug, Clone)]
+enum Cond {
+    Truthy(Expr),
+    Equal(Expr, Expr, bool),
+    Not(Box<Cond>),
+    And(Vec<Cond>),
+    Or(Vec<Cond>),
+}
+
+/// 模板源码切分出的片段
+enum Piece {
+    Text(String),
+    Output(String, usize),
+    Tag(String, usize),
+}
+
+fn line_of(source: &str, offset: usize) -> usize {
+    source[..offset].matches('\n').count() + 1
+}
+
+/// 切分模板源码，同时处理`-`空白控制
+fn split(source: &str) -> Result<Vec<Piece>, String> {
+    let mut pieces = Vec::new();
+    let mut rest = 0;
+    let mut trim_next = false;
+    while let Some(found) = source[rest..].find(['{']).map(|i| rest + i) {
+        let close = match source[found..].chars().nth(1) {
+            Some('{') => "}}",
+            Some('%') => "%}",
+            Some('#') => "#}",
+            _ => {
+                let text = &source[rest..found + 1];
+                pieces.push(Piece::Text(if trim_next { text.trim_start() } else { text }.to_string()));
+                trim_next = false;
+                rest = found + 1;
+                continue;
+            }
+        };
+        let line = line_of(source, found);
+        let mut text = &source[rest..found];
+        if trim_next {
+            text = text.trim_start();
+        }
+        let mut inner_start = found + 2;
+        if source[inner_start..].starts_with('-') {
+            text = text.trim_end();
+            inner_start += 1;
+        }
+        pieces.push(Piece::Text(text.to_string()));
+
+        let Some(end) = source[inner_start..].find(close).map(|i| inner_start + i) else {
+            return Err(format!("第 {} 行: 缺少结束的 {}", line, close));
+        };
+        let mut inner = &source[inner_start..end];
+        trim_next = inner.ends_with('-');
+        if trim_next {
+            inner = &inner[..inner.len() - 1];
+        }
+        match close {
+            "}}" => pieces.push(Piece::Output(inner.trim().to_string(), line)),
+            "%}" => pieces.push(Piece::Tag(inner.trim().to_string(), line)),
+            _ => {}
+        }
+        rest = end + 2;
+    }
+    let text = &source[rest..];
+    pieces.push(Piece::Text(if trim_next { text.trim_start() } else { text }.to_string()));
+    Ok(pieces)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Literal(Value),
+    Symbol(&'static str),
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '"' || c == '\'' {
+            let mut literal = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err("字符串缺少结束引号".to_string()),
+                    Some(&quote) if quote == c => break,
+                    Some('\\') => {
+                        i += 1;
+                        match chars.get(i) {
+                            Some('n') => literal.push('\n'),
+                            Some('t') => literal.push('\t'),
+                            Some(&escaped) => literal.push(escaped),
+                            None => return Err("字符串缺少结束引号".to_string()),
+                        }
+                    }
+                    Some(&other) => literal.push(other),
+                }
+                i += 1;
+            }
+            tokens.push(Token::Literal(Value::String(literal)));
+            i += 1;
+        } else if c.is_ascii_digit() || (c == '-' && chars.get(i + 1).is_some_and(char::is_ascii_digit)) {
+            let start = i;
+            i += 1;
+            while chars.get(i).is_some_and(|c| c.is_ascii_digit() || *c == '.') {
+                i += 1;
+            }
+            let number: String = chars[start..i].iter().collect();
+            let number = serde_json::from_str::<Value>(&number).map_err(|_| format!("无效的数字 {}", number))?;
+            tokens.push(Token::Literal(number));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while chars.get(i).is_some_and(|c| c.is_alphanumeric() || *c == '_' || *c == '.') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            let pair: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+            let symbol = match (pair.as_str(), c) {
+                ("==", _) => "==",
+                ("!=", _) => "!=",
+                (_, '|') => "|",
+                (_, '(') => "(",
+                (_, ')') => ")",
+                (_, ',') => ",",
+                _ => return Err(format!("无法识别的字符 {:?}", c)),
+            };
+            i += symbol.len();
+            tokens.push(Token::Symbol(symbol));
+        }
+    }
+    Ok(tokens)
+}
+
+/// 标签或输出中的表达式
+struct ExprParser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl ExprParser {
+    fn new(text: &str) -> Result<ExprParser, String> {
+        Ok(ExprParser {
+            tokens: tokenize(text)?,
+            pos: 0,
+        })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Ident(word)) if word == keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn eat_symbol(&mut self, symbol: &'static str) -> bool {
+        let found = self.peek() == Some(&Token::Symbol(symbol));
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn finish(&self) -> Result<(), String> {
+        match self.peek() {
+            None => Ok(()),
+            Some(token) => Err(format!("多余的 {}", describe(token))),
+        }
+    }
+
+    fn literal(&mut self) -> Result<Value, String> {
+        match self.next() {
+            Some(Token::Literal(value)) => Ok(value),
+            Some(Token::Ident(word)) if word == "true" => Ok(Value::Bool(true)),
+            Some(Token::Ident(word)) if word == "false" => Ok(Value::Bool(false)),
+            Some(Token::Ident(word)) if word == "none" || word == "null" => Ok(Value::Null),
+            Some(token) => Err(format!("过滤器参数只能是字面量，而不是 {}", describe(&token))),
+            None => Err("表达式不完整".to_string()),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let operand = match self.next() {
+            Some(Token::Literal(value)) => Operand::Literal(value),
+            Some(Token::Ident(word)) => match word.as_str() {
+                "true" => Operand::Literal(Value::Bool(true)),
+                "false" => Operand::Literal(Value::Bool(false)),
+                "none" | "null" => Operand::Literal(Value::Null),
+                "not" | "and" | "or" | "in" => return Err(format!("{} 的位置应是变量或字面量", word)),
+                _ => {
+                    let path: Vec<String> = word.split('.').map(str::to_string).collect();
+                    if path.iter().any(String::is_empty) {
+                        return Err(format!("无效的变量名 {}", word));
+                    }
+                    Operand::Path(path)
+                }
+            },
+            Some(token) => return Err(format!("{} 的位置应是变量或字面量", describe(&token))),
+            None => return Err("表达式为空".to_string()),
+        };
+
+        let mut filters = Vec::new();
+        while self.eat_symbol("|") {
+            let Some(Token::Ident(name)) = self.next() else {
+                return Err("| 后面应是过滤器名".to_string());
+            };
+            if !FILTERS.contains(&name.as_str()) {
+                return Err(format!("未知的过滤器 {}", name));
+            }
+            let mut args = Vec::new();
+            if self.eat_symbol("(") && !self.eat_symbol(")") {
+                loop {
+                    args.push(self.literal()?);
+                    if self.eat_symbol(")") {
+                        break;
+                    }
+                    if !self.eat_symbol(",") {
+                        return Err(format!("过滤器 {} 的参数列表缺少 )", name));
+                    }
+                }
+            }
+            filters.push((name, args));
+        }
+        Ok(Expr { operand, filters })
+    }
+
+    fn cond(&mut self) -> Result<Cond, String> {
+        let mut any = vec![self.all()?];
+        while self.eat_keyword("or") {
+            any.push(self.all()?);
+        }
+        Ok(if any.len() == 1 { any.remove(0) } else { Cond::Or(any) })
+    }
+
+    fn all(&mut self) -> Result<Cond, String> {
+        let mut all = vec![self.negation()?];
+        while self.eat_keyword("and") {
+            all.push(self.negation()?);
+        }
+        Ok(if all.len() == 1 { all.remove(0) } else { Cond::And(all) })
+    }
+
+    fn negation(&mut self) -> Result<Cond, String> {
+        if self.eat_keyword("not") {
+            return Ok(Cond::Not(Box::new(self.negation()?)));
+        }
+        if self.eat_symbol("(") {
+            let cond = self.cond()?;
+            if !self.eat_symbol(")") {
+                return Err("条件缺少 )".to_string());
+            }
+            return Ok(cond);
+        }
+        let left = self.expr()?;
+        for (symbol, equal) in [("==", true), ("!=", false)] {
+            if self.eat_symbol(symbol) {
+                return Ok(Cond::Equal(left, self.expr()?, equal));
+            }
+        }
+        Ok(Cond::Truthy(left))
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Ident(word) => word.clone(),
+        Token::Literal(value) => value.to_string(),
+        Token::Symbol(symbol) => symbol.to_string(),
+    }
+}
+
+/// 由片段构建语法树
+struct Parser {
+    pieces: std::vec::IntoIter<Piece>,
+}
+
+/// 结束一个块的标签：关键字、其后的内容和所在行
+type Closing = Option<(String, String, usize)>;
+
+impl Parser {
+    fn nodes(&mut self, closers: &[&str]) -> Result<(Vec<Node>, Closing), String> {
+        let mut nodes = Vec::new();
+        while let Some(piece) = self.pieces.next() {
+            match piece {
+                Piece::Text(text) if text.is_empty() => {}
+                Piece::Text(text) => nodes.push(Node::Text(text)),
+                Piece::Output(text, line) => {
+                    let mut parser = ExprParser::new(&text).map_err(|e| format!("第 {} 行: {}", line, e))?;
+                    let expr = parser.expr().and_then(|expr| parser.finish().map(|_| expr));
+                    nodes.push(Node::Output(expr.map_err(|e| format!("第 {} 行: {}", line, e))?));
+                }
+                Piece::Tag(text, line) => {
+                    let (keyword, rest) = text.split_once(char::is_whitespace).unwrap_or((&text, ""));
+                    let (keyword, rest) = (keyword.to_string(), rest.trim().to_string());
+                    if closers.contains(&keyword.as_str()) {
+                        return Ok((nodes, Some((keyword, rest, line))));
+                    }
+                    let node = match keyword.as_str() {
+                        "if" => self.if_block(&rest, line)?,
+                        "for" => self.for_block(&rest, line)?,
+                        _ => return Err(format!("第 {} 行: 意外的标签 {}", line, keyword)),
+                    };
+                    nodes.push(node);
+                }
+            }
+        }
+        Ok((nodes, None))
+    }
+
+    fn if_block(&mut self, rest: &str, line: usize) -> Result<Node, String> {
+        let mut branches = Vec::new();
+        let mut cond = parse_cond(rest).map_err(|e| format!("第 {} 行: {}", line, e))?;
+        loop {
+            let (body, closing) = self.nodes(&["elif", "else", "endif"])?;
+            branches.push((cond, body));
+            match closing {
+                Some((keyword, rest, line)) if keyword == "elif" => {
+                    cond = parse_cond(&rest).map_err(|e| format!("第 {} 行: {}", line, e))?;
+                }
+                Some((keyword, _, _)) if keyword == "else" => {
+                    let (otherwise, closing) = self.nodes(&["endif"])?;
+                    if closing.is_none() {
+                        return Err(format!("第 {} 行的 if 缺少 endif", line));
+                    }
+                    return Ok(Node::If { branches, otherwise });
+                }
+                Some(_) => {
+                    return Ok(Node::If {
+                        branches,
+                        otherwise: Vec::new(),
+                    })
+                }
+                None => return Err(format!("第 {} 行的 if 缺少 endif", line)),
+            }
+        }
+    }
+
+    fn for_block(&mut self, rest: &str, line: usize) -> Result<Node, String> {
+        let header = || {
+            let mut parser = ExprParser::new(rest)?;
+            let var = match parser.next() {
+                Some(Token::Ident(var)) if !var.contains('.') && var != "loop" => var,
+                _ => return Err("for 的格式应为 for 变量 in 列表".to_string()),
+            };
+            if !parser.eat_keyword("in") {
+                return Err("for 的格式应为 for 变量 in 列表".to_string());
+            }
+            let items = parser.expr()?;
+            parser.finish()?;
+            Ok((var, items))
+        };
+        let (var, items) = header().map_err(|e| format!("第 {} 行: {}", line, e))?;
+        let (body, closing) = self.nodes(&["endfor"])?;
+        if closing.is_none() {
+            return Err(format!("第 {} 行的 for 缺少 endfor", line));
+        }
+        Ok(Node::For { var, items, body })
+    }
+}
+
+fn parse_cond(text: &str) -> Result<Cond, String> {
+    let mut parser = ExprParser::new(text)?;
+    let cond = parser.cond()?;
+    parser.finish()?;
+    Ok(cond)
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(value) => *value,
+        Value::Number(number) => number.as_f64().is_some_and(|n| n != 0.0),
+        Value::String(text) => !text.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(object) => !object.is_empty(),
+    }
+}
+
+fn equal(left: &Value, right: &Value) -> bool {
+    match (left.as_f64(), right.as_f64()) {
+        (Some(left), Some(right)) => left == right,
+        _ => left == right,
+    }
+}
+
+/// 输出时的文本形式，数组和对象输出为JSON
+fn display(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn apply_filter(value: Value, name: &str, args: &[Value]) -> Value {
+    let arg = |i: usize| args.get(i).cloned().unwrap_or(Value::Null);
+    match name {
+        "default" if !truthy(&value) => arg(0),
+        "upper" => Value::String(display(&value).to_uppercase()),
+        "lower" => Value::String(display(&value).to_lowercase()),
+        "trim" => Value::String(display(&value).trim().to_string()),
+        "capitalize" => {
+            let text = display(&value);
+            let mut chars = text.chars();
+            let capitalized = match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars.flat_map(char::to_lowercase)).collect(),
+                None => String::new(),
+            };
+            Value::String(capitalized)
+        }
+        "length" => Value::from(match &value {
+            Value::Array(items) => items.len(),
+            Value::Object(object) => object.len(),
+            Value::Null => 0,
+            other => display(other).chars().count(),
+        }),
+        "join" => match &value {
+            Value::Array(items) => {
+                let separator = args.first().map(display).unwrap_or_default();
+                Value::String(items.iter().map(display).collect::<Vec<_>>().join(&separator))
+            }
+            _ => value,
+        },
+        "tojson" => Value::String(value.to_string()),
+        _ => value,
+    }
+}
+
+/// 渲染时的变量作用域，内层的循环变量遮盖外层
+struct Scope<'a> {
+    layers: Vec<Map<String, Value>>,
+    root: &'a Map<String, Value>,
+}
+
+impl Scope<'_> {
+    fn lookup(&self, path: &[String]) -> Value {
+        let first = &path[0];
+        let found = self.layers.iter().rev().find_map(|layer| layer.get(first)).or_else(|| self.root.get(first));
+        let mut value = found.cloned().unwrap_or(Value::Null);
+        for key in &path[1..] {
+            value = match &value {
+                Value::Object(object) => object.get(key).cloned().unwrap_or_default(),
+                Value::Array(items) => {
+                    key.parse::<usize>().ok().and_then(|i| items.get(i).cloned()).unwrap_or_default()
+                }
+                _ => Value::Null,
+            };
+        }
+        value
+    }
+
+    fn eval(&self, expr: &Expr) -> Value {
+        let value = match &expr.operand {
+            Operand::Path(path) => self.lookup(path),
+            Operand::Literal(value) => value.clone(),
+        };
+        expr.filters.iter().fold(value, |value, (name, args)| apply_filter(value, name, args))
+    }
+
+    fn test(&self, cond: &Cond) -> bool {
+        match cond {
+            Cond::Truthy(expr) => truthy(&self.eval(expr)),
+            Cond::Equal(left, right, expected) => equal(&self.eval(left), &self.eval(right)) == *expected,
+            Cond::Not(cond) => !self.test(cond),
+            Cond::And(conds) => conds.iter().all(|cond| self.test(cond)),
+            Cond::Or(conds) => conds.iter().any(|cond| self.test(cond)),
+        }
+    }
+
+    fn render(&mut self, nodes: &[Node], output: &mut String) {
+        for node in nodes {
+            match node {
+                Node::Text(text) => output.push_str(text),
+                Node::Output(expr) => output.push_str(&display(&self.eval(expr))),
+                Node::If { branches, otherwise } => {
+                    let body = branches.iter().find(|(cond, _)| self.test(cond)).map(|(_, body)| body);
+                    self.render(body.unwrap_or(otherwise), output);
+                }
+                Node::For { var, items, body } => {
+                    let items = match self.eval(items) {
+                        Value::Array(items) => items,
+                        Value::Object(object) => object.into_iter().map(|(_, value)| value).collect(),
+                        _ => Vec::new(),
+                    };
+                    let count = items.len();
+                    for (i, item) in items.into_iter().enumerate() {
+                        let mut layer = Map::new();
+                        layer.insert(var.clone(), item);
+                        let info = serde_json::json!({
+                            "index": i + 1,
+                            "index0": i,
+                            "first": i == 0,
+                            "last": i + 1 == count,
+                        });
+                        layer.insert("loop".to_string(), info);
+                        self.layers.push(layer);
+                        self.render(body, output);
+                        self.layers.pop();
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn collect_expr(expr: &Expr, bound: &[String], names: &mut BTreeSet<String>) {
+    if let Operand::Path(path) = &expr.operand {
+        if !bound.contains(&path[0]) {
+            names.insert(path[0].clone());
+        }
+    }
+}
+
+fn collect_cond(cond: &Cond, bound: &[String], names: &mut BTreeSet<String>) {
+    match cond {
+        Cond::Truthy(expr) => collect_expr(expr, bound, names),
+        Cond::Equal(left, right, _) => {
+            collect_expr(left, bound, names);
+            collect_expr(right, bound, names);
+        }
+        Cond::Not(cond) => collect_cond(cond, bound, names),
+        Cond::And(all) | Cond::Or(all) => all.iter().for_each(|cond| collect_cond(cond, bound, names)),
+    }
+}
+
+/// 收集模板用到的顶层变量名，不含循环变量
+fn collect(nodes: &[Node], bound: &mut Vec<String>, names: &mut BTreeSet<String>) {
+    for node in nodes {
+        match node {
+            Node::Text(_) => {}
+            Node::Output(expr) => collect_expr(expr, bound, names),
+            Node::If { branches, otherwise } => {
+                for (cond, body) in branches {
+                    collect_cond(cond, bound, names);
+                    collect(body, bound, names);
+                }
+                collect(otherwise, bound, names);
+            }
+            Node::For { var, items, body } => {
+                collect_expr(items, bound, names);
+                bound.extend([var.clone(), "loop".to_string()]);
+                collect(body, bound, names);
+                bound.truncate(bound.len() - 2);
+            }
+        }
+    }
+}
+
+impl Template {
+    /// 解析模板，语法错误时返回带行号的说明
+    pub fn compile(source: &str) -> Result<Template, String> {
+        let mut parser = Parser {
+            pieces: split(source)?.into_iter(),
+        };
+        let (nodes, _) = parser.nodes(&[])?;
+        Ok(Template { nodes })
+    }
+
+    /// 模板用到的顶层变量名
+    pub fn variables(&self) -> BTreeSet<String> {
+        let mut names = BTreeSet::new();
+        collect(&self.nodes, &mut Vec::new(), &mut names);
+        names
+    }
+
+    pub fn render(&self, variables: &Map<String, Value>) -> String {
+        let mut output = String::new();
+        let mut scope = Scope {
+            layers: Vec::new(),
+            root: variables,
+        };
+        scope.render(&self.nodes, &mut output);
+        output
+    }
+}
@@ -0,0 +1,39 @@
+//! 常见模型的上下文窗口
+
+/// 按模型名前缀匹配的上下文窗口（token），更具体的前缀写在前面
+const CONTEXT_WINDOWS: &[(&str, u32)] = &[
+    ("moonshot-v1-8k", 8_192),
+    ("moonshot-v1-32k", 32_768),
+    ("moonshot-v1-128k", 131_072),
+    ("moonshot-v1-auto", 131_072),
+    ("kimi-k2-0711", 131_072),
+    ("kimi-k2", 262_144),
+    ("kimi-latest", 131_072),
+    ("gpt-5", 400_000),
+    ("gpt-4.1", 1_047_576),
+    ("gpt-4o", 128_000),
+    ("chatgpt-4o", 128_000),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4-1106", 128_000),
+    ("gpt-4-0125", 128_000),
+    ("gpt-4-32k", 32_768),
+    ("gpt-4", 8_192),
+    ("gpt-3.5-turbo-instruct", 4_096),
+    ("gpt-3.5-turbo", 16_385),
+    ("o1-mini", 128_000),
+    ("o1", 200_000),
+    ("o3", 200_000),
+    ("o4-mini", 200_000),
+    ("text-embedding-3", 8_191),
+    ("text-embedding-ada-002", 8_191),
+];
+
+/// 查询模型的上下文窗口，未知模型返回`None`
+pub fn context_window(model: &str) -> Option<u32> {
+    let model = model.to_ascii_lowercase();
+    let model = model.rsplit('/').next().unwrap_or(&model);
+    CONTEXT_WINDOWS
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map(|(_, window)| *window)
+}
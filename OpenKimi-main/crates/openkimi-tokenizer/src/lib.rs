@@ -0,0 +1,305 @@
+//! 按模型选择分词器的token计数、编解码与截断
+//!
+//! OpenAI系列模型使用内置的tiktoken词表，结果与官方一致；没有公开内置词表的模型（如Moonshot/Kimi）
+//! 默认用`cl100k_base`近似，[`Tokenizer::is_exact`]为false，也可以通过[`Tokenizer::from_tiktoken_file`]
+//! 加载模型自带的`.tiktoken`词表得到精确结果。
+//!
+//! 服务端、命令行和上下文管理都应通过[`Tokenizer::for_model`]取得分词器，
+//! 避免各处用字符数估算token导致超出上下文窗口。
+
+mod context;
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use rustc_hash::FxHashMap;
+use tiktoken_rs::CoreBPE;
+
+pub use context::context_window;
+
+/// `cl100k_base`及多数开源模型使用的预分词正则
+pub const CL100K_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+
+/// OpenAI对话格式中每条消息的固定开销
+const TOKENS_PER_MESSAGE: usize = 3;
+/// 消息带`name`字段时的额外开销
+const TOKENS_PER_NAME: usize = 1;
+/// 回复开头`<|start|>assistant<|message|>`的开销
+const REPLY_PRIMING_TOKENS: usize = 3;
+
+/// 分词器错误
+#[derive(Debug)]
+pub enum TokenizerError {
+    /// 读取词表文件失败
+    Io(std::io::Error),
+    /// 词表文件或正则格式错误
+    InvalidVocabulary(String),
+    /// token id不在词表中或解码结果不是合法UTF-8
+    Decode(String),
+}
+
+impl fmt::Display for TokenizerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenizerError::Io(err) => write!(f, "读取词表失败: {}", err),
+            TokenizerError::InvalidVocabulary(reason) => write!(f, "词表格式错误: {}", reason),
+            TokenizerError::Decode(reason) => write!(f, "解码失败: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for TokenizerError {}
+
+/// 内置词表
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// GPT-4o、GPT-4.1、GPT-5、o系列
+    O200kBase,
+    /// GPT-4、GPT-3.5、text-embedding-3
+    Cl100kBase,
+    /// Codex、text-davinci-002/003
+    P50kBase,
+    /// GPT-3（davinci等）
+    R50kBase,
+}
+
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::O200kBase => "o200k_base",
+            Encoding::Cl100kBase => "cl100k_base",
+            Encoding::P50kBase => "p50k_base",
+            Encoding::R50kBase => "r50k_base",
+        }
+    }
+
+    fn bpe(&self) -> &'static CoreBPE {
+        match self {
+            Encoding::O200kBase => tiktoken_rs::o200k_base_singleton(),
+            Encoding::Cl100kBase => tiktoken_rs::cl100k_base_singleton(),
+            Encoding::P50kBase => tiktoken_rs::p50k_base_singleton(),
+            Encoding::R50kBase => tiktoken_rs::r50k_base_singleton(),
+        }
+    }
+
+    /// 按模型名选择词表，返回的布尔值表示该词表是否就是模型本身的词表
+    pub fn for_model(model: &str) -> (Encoding, bool) {
+        let model = model.to_ascii_lowercase();
+        // 去掉`openai/gpt-4o`、`ft:gpt-4o:org`等前后缀
+        let model = model.rsplit('/').next().unwrap_or(&model);
+        let model = model.strip_prefix("ft:").unwrap_or(model);
+
+        let starts_with = |prefixes: &[&str]| prefixes.iter().any(|prefix| model.starts_with(prefix));
+        if starts_with(&["gpt-4o", "chatgpt-4o", "gpt-4.1", "gpt-4.5", "gpt-5", "o1", "o3", "o4"]) {
+            (Encoding::O200kBase, true)
+        } else if starts_with(&["gpt-4", "gpt-3.5", "gpt-35", "text-embedding-3", "text-embedding-ada-002"]) {
+            (Encoding::Cl100kBase, true)
+        } else if starts_with(&["text-davinci-002", "text-davinci-003", "code-"]) {
+            (Encoding::P50kBase, true)
+        } else if starts_with(&["davinci", "curie", "babbage", "ada"]) {
+            (Encoding::R50kBase, true)
+        } else {
+            // Moonshot/Kimi、Qwen、DeepSeek等使用与cl100k规模相近的BPE词表，误差通常在10%以内
+            (Encoding::Cl100kBase, false)
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Bpe {
+    Builtin(&'static CoreBPE),
+    Custom(Arc<CoreBPE>),
+}
+
+impl Bpe {
+    fn get(&self) -> &CoreBPE {
+        match self {
+            Bpe::Builtin(bpe) => bpe,
+            Bpe::Custom(bpe) => bpe,
+        }
+    }
+}
+
+/// 分词器
+#[derive(Clone)]
+pub struct Tokenizer {
+    bpe: Bpe,
+    name: String,
+    exact: bool,
+}
+
+impl fmt::Debug for Tokenizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tokenizer")
+            .field("name", &self.name)
+            .field("exact", &self.exact)
+            .finish()
+    }
+}
+
+impl Tokenizer {
+    /// 使用内置词表
+    pub fn new(encoding: Encoding) -> Tokenizer {
+        Tokenizer {
+            bpe: Bpe::Builtin(encoding.bpe()),
+            name: encoding.name().to_string(),
+            exact: true,
+        }
+    }
+
+    /// 按模型名选择内置词表
+    pub fn for_model(model: &str) -> Tokenizer {
+        let (encoding, exact) = Encoding::for_model(model);
+        Tokenizer {
+            exact,
+            ..Tokenizer::new(encoding)
+        }
+    }
+
+    /// 加载tiktoken格式的词表文件（每行`<Base64 token> <rank>`），如Kimi模型自带的`tiktoken.model`
+    ///
+    /// `pattern`为预分词正则，缺省时使用[`CL100K_PATTERN`]。
+    pub fn from_tiktoken_file(path: &Path, pattern: Option<&str>) -> Result<Tokenizer, TokenizerError> {
+        let content = fs::read_to_string(path).map_err(TokenizerError::Io)?;
+        let mut encoder = FxHashMap::default();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || TokenizerError::InvalidVocabulary(format!("第 {} 行: {}", number + 1, line));
+            let (token, rank) = line.split_once(' ').ok_or_else(invalid)?;
+            let token = STANDARD.decode(token).map_err(|_| invalid())?;
+            let rank = rank.trim().parse().map_err(|_| invalid())?;
+            encoder.insert(token, rank);
+        }
+
+        let bpe = CoreBPE::new(encoder, FxHashMap::default(), pattern.unwrap_or(CL100K_PATTERN))
+            .map_err(|e| TokenizerError::InvalidVocabulary(e.to_string()))?;
+        Ok(Tokenizer {
+            bpe: Bpe::Custom(Arc::new(bpe)),
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            exact: true,
+        })
+    }
+
+    /// 词表名称
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 词表是否与模型一致；为false时计数是近似值，调用方应预留余量
+    pub fn is_exact(&self) -> bool {
+        self.exact
+    }
+
+    /// 编码文本；`<|endoftext|>`等特殊标记按普通文本处理，避免用户输入被当作控制标记
+    pub fn encode(&self, text: &str) -> Vec<u32> {
+        self.bpe.get().encode_ordinary(text)
+    }
+
+    pub fn decode(&self, tokens: &[u32]) -> Result<String, TokenizerError> {
+        self.bpe
+            .get()
+            .decode(tokens.to_vec())
+            .map_err(|e| TokenizerError::Decode(e.to_string()))
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        self.encode(text).len()
+    }
+
+    /// 单条消息在OpenAI对话格式中的token数（不含回复开头的开销）
+    pub fn count_message(&self, role: &str, content: &str, name: Option<&str>) -> usize {
+        let mut total = TOKENS_PER_MESSAGE + self.count(role) + self.count(content);
+        if let Some(name) = name {
+            total += TOKENS_PER_NAME + self.count(name);
+        }
+        total
+    }
+
+    /// 按OpenAI对话格式计算消息列表的token数，`messages`为`(role, content, name)`
+    pub fn count_messages<'a, I>(&self, messages: I) -> usize
+    where
+        I: IntoIterator<Item = (&'a str, &'a str, Option<&'a str>)>,
+    {
+        REPLY_PRIMING_TOKENS
+            + messages
+                .into_iter()
+                .map(|(role, content, name)| self.count_message(role, content, name))
+                .sum::<usize>()
+    }
+
+    /// 对话格式中与消息无关的固定开销，空消息列表的token数
+    pub fn reply_priming_tokens(&self) -> usize {
+        REPLY_PRIMING_TOKENS
+    }
+
+    /// 解码前`len`个token；截断点落在多字节字符中间时向前退到完整字符
+    fn decode_prefix(&self, tokens: &[u32], mut len: usize) -> String {
+        while len > 0 {
+            if let Ok(text) = self.decode(&tokens[..len]) {
+                return text;
+            }
+            len -= 1;
+        }
+        String::new()
+    }
+
+    /// 解码从`start`开始的token；起点落在多字节字符中间时向后退到完整字符
+    fn decode_suffix(&self, tokens: &[u32], mut start: usize) -> String {
+        while start < tokens.len() {
+            if let Ok(text) = self.decode(&tokens[start..]) {
+                return text;
+            }
+            start += 1;
+        }
+        String::new()
+    }
+
+    /// 截断到不超过`max_tokens`个token，保留开头
+    pub fn truncate(&self, text: &str, max_tokens: usize) -> String {
+        let tokens = self.encode(text);
+        if tokens.len() <= max_tokens {
+            return text.to_string();
+        }
+        self.decode_prefix(&tokens, max_tokens)
+    }
+
+    /// 截断到不超过`max_tokens`个token，保留结尾（如日志、对话的最新部分）
+    pub fn truncate_start(&self, text: &str, max_tokens: usize) -> String {
+        let tokens = self.encode(text);
+        if tokens.len() <= max_tokens {
+            return text.to_string();
+        }
+        self.decode_suffix(&tokens, tokens.len() - max_tokens)
+    }
+
+    /// 按token数把文本切成若干段，每段不超过`max_tokens`，切分点不会落在多字节字符中间
+    pub fn chunks(&self, text: &str, max_tokens: usize) -> Vec<String> {
+        let tokens = self.encode(text);
+        let max_tokens = max_tokens.max(1);
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        while start < tokens.len() {
+            // 末尾不完整时向前退；单个字符就超过`max_tokens`时只能向后扩展到完整字符
+            let mut end = (start + max_tokens).min(tokens.len());
+            while end > start + 1 && self.decode(&tokens[start..end]).is_err() {
+                end -= 1;
+            }
+            while end < tokens.len() && self.decode(&tokens[start..end]).is_err() {
+                end += 1;
+            }
+            chunks.push(self.decode(&tokens[start..end]).unwrap_or_default());
+            start = end;
+        }
+        chunks
+    }
+}
@@ -0,0 +1,170 @@
+//! 本地索引的BM25关键词检索
+//!
+//! 英文、数字等按字母数字切词并转为小写，由`_`、`.`、`-`、`:`、`/`连接的标识符（如`ERR_CONN_RESET`、
+//! `v1.2.3`、`E-1001`）同时保留整体和各部分；中日韩文字没有空格分词，按相邻两字切分，单字成词。
+//! 倒排索引只在内存中，打开存储时由记录的文本重建，不写入索引文件。
+
+use std::collections::{HashMap, HashSet};
+
+/// 词频饱和参数
+const K1: f32 = 1.2;
+/// 文档长度归一化参数
+const B: f32 = 0.75;
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}'
+        | '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}'
+        | '\u{ac00}'..='\u{d7af}'
+        | '\u{f900}'..='\u{faff}')
+}
+
+fn is_connector(c: char) -> bool {
+    matches!(c, '_' | '.' | '-' | ':' | '/')
+}
+
+/// 切词的中间状态
+#[derive(Default)]
+struct Tokens {
+    tokens: Vec<String>,
+    /// 连续的中日韩文字
+    cjk: Vec<char>,
+    /// 当前标识符已结束的部分，以及拼接起来的整体
+    parts: Vec<String>,
+    compound: String,
+    word: String,
+    /// 上一部分之后的连接符，后面还有部分时才计入整体
+    connector: String,
+}
+
+impl Tokens {
+    fn end_cjk(&mut self) {
+        match self.cjk.len() {
+            0 => {}
+            1 => self.tokens.push(self.cjk[0].to_string()),
+            _ => self.tokens.extend(self.cjk.windows(2).map(|pair| pair.iter().collect())),
+        }
+        self.cjk.clear();
+    }
+
+    fn end_word(&mut self) {
+        if !self.word.is_empty() {
+            self.compound.push_str(&self.connector);
+            self.compound.push_str(&self.word);
+            self.connector.clear();
+            self.parts.push(std::mem::take(&mut self.word));
+        }
+    }
+
+    fn end_identifier(&mut self) {
+        self.end_word();
+        if self.parts.len() > 1 {
+            self.tokens.push(std::mem::take(&mut self.compound));
+        }
+        self.tokens.append(&mut self.parts);
+        self.compound.clear();
+        self.connector.clear();
+    }
+}
+
+/// 切分出检索用的词
+pub(crate) fn tokenize(text: &str) -> Vec<String> {
+    let mut state = Tokens::default();
+    for c in text.chars() {
+        if is_cjk(c) {
+            state.end_identifier();
+            state.cjk.push(c);
+        } else if c.is_alphanumeric() {
+            state.end_cjk();
+            state.word.extend(c.to_lowercase());
+        } else if is_connector(c) && !(state.word.is_empty() && state.parts.is_empty()) && state.connector.len() < 2 {
+            state.end_word();
+            state.connector.push(c);
+        } else {
+            state.end_cjk();
+            state.end_identifier();
+        }
+    }
+    state.end_cjk();
+    state.end_identifier();
+    state.tokens
+}
+
+/// 按记录id组织的倒排索引
+#[derive(Debug, Default)]
+pub(crate) struct KeywordIndex {
+    /// 词 → 记录id → 词频
+    postings: HashMap<String, HashMap<String, u32>>,
+    /// 记录id → (词数, 出现过的词)
+    documents: HashMap<String, (u32, Vec<String>)>,
+    total_length: u64,
+}
+
+impl KeywordIndex {
+    /// 加入或替换一条记录
+    pub(crate) fn insert(&mut self, id: &str, text: &str) {
+        self.remove(id);
+        let tokens = tokenize(text);
+        let mut frequencies: HashMap<String, u32> = HashMap::new();
+        for token in &tokens {
+            *frequencies.entry(token.clone()).or_default() += 1;
+        }
+        let terms: Vec<String> = frequencies.keys().cloned().collect();
+        for (term, frequency) in frequencies {
+            self.postings.entry(term).or_default().insert(id.to_string(), frequency);
+        }
+        self.total_length += tokens.len() as u64;
+        self.documents.insert(id.to_string(), (tokens.len() as u32, terms));
+    }
+
+    pub(crate) fn remove(&mut self, id: &str) {
+        let Some((length, terms)) = self.documents.remove(id) else {
+            return;
+        };
+        self.total_length -= length as u64;
+        for term in terms {
+            if let Some(posting) = self.postings.get_mut(&term) {
+                posting.remove(id);
+                if posting.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        *self = KeywordIndex::default();
+    }
+
+    /// 按BM25得分返回至多`limit`条`accept`接受的记录，不含任何查询词的记录不返回
+    pub(crate) fn search(&self, query: &str, limit: usize, accept: impl Fn(&str) -> bool) -> Vec<(String, f32)> {
+        let count = self.documents.len() as f32;
+        if count == 0.0 || limit == 0 {
+            return Vec::new();
+        }
+        let average = (self.total_length as f32 / count).max(1.0);
+        let terms: HashSet<String> = tokenize(query).into_iter().collect();
+        let mut scores: HashMap<&str, f32> = HashMap::new();
+        for term in &terms {
+            let Some(posting) = self.postings.get(term) else {
+                continue;
+            };
+            let frequency = posting.len() as f32;
+            let idf = (1.0 + (count - frequency + 0.5) / (frequency + 0.5)).ln();
+            for (id, &tf) in posting {
+                let length = self.documents[id].0 as f32;
+                let tf = tf as f32;
+                *scores.entry(id).or_default() += idf * tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * length / average));
+            }
+        }
+        let mut hits: Vec<(String, f32)> = scores
+            .into_iter()
+            .filter(|(id, _)| accept(id))
+            .map(|(id, score)| (id.to_string(), score))
+            .collect();
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        hits.truncate(limit);
+        hits
+    }
+}
@@ -0,0 +1,41 @@
+use std::fmt;
+use std::io;
+
+/// 向量存储错误
+#[derive(Debug)]
+pub enum StoreError {
+    Io(io::Error),
+    /// 索引文件损坏或格式不对
+    Corrupt(String),
+    /// 向量维度与索引中已有的不一致
+    Dimension { expected: usize, actual: usize },
+    /// 过滤条件格式错误，或存储后端不支持该条件
+    Filter(String),
+    /// 远程向量数据库返回错误
+    Backend(String),
+    /// 存储不支持该操作
+    Unsupported(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Io(err) => write!(f, "读写索引失败: {}", err),
+            StoreError::Corrupt(reason) => write!(f, "索引文件损坏: {}", reason),
+            StoreError::Dimension { expected, actual } => {
+                write!(f, "向量维度 {} 与索引中的 {} 不一致，请使用同一个嵌入模型", actual, expected)
+            }
+            StoreError::Filter(reason) => write!(f, "无效的过滤条件: {}", reason),
+            StoreError::Backend(reason) => write!(f, "向量数据库错误: {}", reason),
+            StoreError::Unsupported(operation) => write!(f, "该存储不支持{}", operation),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<io::Error> for StoreError {
+    fn from(err: io::Error) -> StoreError {
+        StoreError::Io(err)
+    }
+}
@@ -0,0 +1,155 @@
+//! 元数据过滤
+//!
+//! 过滤条件写成JSON对象，语法与MongoDB的查询子集相同：
+//!
+//! ```json
+//! {"kind": "pdf", "chunk": {"$lt": 10}, "$or": [{"lang": "zh"}, {"lang": {"$exists": false}}]}
+//! ```
+//!
+//! 同一对象中的多个字段同时满足；元数据值为数组时，只要有一个元素相等即算相等。
+
+use std::cmp::Ordering;
+
+use serde_json::{Map, Value};
+
+use crate::error::StoreError;
+
+/// 比较运算
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compare {
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+}
+
+/// 元数据过滤条件
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Eq(String, Value),
+    In(String, Vec<Value>),
+    Compare(String, Compare, Value),
+    Exists(String),
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+}
+
+impl Filter {
+    /// 解析JSON形式的过滤条件
+    pub fn from_json(value: &Value) -> Result<Filter, StoreError> {
+        let object = value
+            .as_object()
+            .ok_or_else(|| StoreError::Filter(format!("应为对象: {}", value)))?;
+        let mut filters = Vec::with_capacity(object.len());
+        for (key, condition) in object {
+            filters.push(match key.as_str() {
+                "$and" => Filter::And(parse_list(key, condition)?),
+                "$or" => Filter::Or(parse_list(key, condition)?),
+                "$not" => Filter::Not(Box::new(Filter::from_json(condition)?)),
+                _ if key.starts_with('$') => return Err(StoreError::Filter(format!("未知的运算符 {}", key))),
+                _ => parse_condition(key, condition)?,
+            });
+        }
+        Ok(if filters.len() == 1 {
+            filters.pop().unwrap()
+        } else {
+            Filter::And(filters)
+        })
+    }
+
+    pub fn matches(&self, metadata: &Map<String, Value>) -> bool {
+        match self {
+            Filter::Eq(key, expected) => metadata.get(key).is_some_and(|value| contains(value, expected)),
+            Filter::In(key, options) => metadata
+                .get(key)
+                .is_some_and(|value| options.iter().any(|option| contains(value, option))),
+            Filter::Compare(key, op, bound) => metadata.get(key).and_then(|value| compare(value, bound)).is_some_and(
+                |ordering| match op {
+                    Compare::Gt => ordering == Ordering::Greater,
+                    Compare::Gte => ordering != Ordering::Less,
+                    Compare::Lt => ordering == Ordering::Less,
+                    Compare::Lte => ordering != Ordering::Greater,
+                },
+            ),
+            Filter::Exists(key) => metadata.contains_key(key),
+            Filter::And(filters) => filters.iter().all(|filter| filter.matches(metadata)),
+            Filter::Or(filters) => filters.iter().any(|filter| filter.matches(metadata)),
+            Filter::Not(filter) => !filter.matches(metadata),
+        }
+    }
+}
+
+fn parse_list(key: &str, value: &Value) -> Result<Vec<Filter>, StoreError> {
+    value
+        .as_array()
+        .ok_or_else(|| StoreError::Filter(format!("{} 应为数组", key)))?
+        .iter()
+        .map(Filter::from_json)
+        .collect()
+}
+
+/// 解析单个字段的条件：标量表示相等，对象中为运算符
+fn parse_condition(key: &str, condition: &Value) -> Result<Filter, StoreError> {
+    let Some(operators) = condition.as_object().filter(|object| object.keys().all(|op| op.starts_with('$'))) else {
+        return Ok(Filter::Eq(key.to_string(), condition.clone()));
+    };
+    let mut filters = Vec::with_capacity(operators.len());
+    for (op, operand) in operators {
+        let field = key.to_string();
+        filters.push(match op.as_str() {
+            "$eq" => Filter::Eq(field, operand.clone()),
+            "$ne" => Filter::Not(Box::new(Filter::Eq(field, operand.clone()))),
+            "$in" | "$nin" => {
+                let options = operand
+                    .as_array()
+                    .ok_or_else(|| StoreError::Filter(format!("{}.{} 应为数组", key, op)))?
+                    .clone();
+                if op == "$in" {
+                    Filter::In(field, options)
+                } else {
+                    Filter::Not(Box::new(Filter::In(field, options)))
+                }
+            }
+            "$gt" => Filter::Compare(field, Compare::Gt, operand.clone()),
+            "$gte" => Filter::Compare(field, Compare::Gte, operand.clone()),
+            "$lt" => Filter::Compare(field, Compare::Lt, operand.clone()),
+            "$lte" => Filter::Compare(field, Compare::Lte, operand.clone()),
+            "$exists" => match operand.as_bool() {
+                Some(true) => Filter::Exists(field),
+                Some(false) => Filter::Not(Box::new(Filter::Exists(field))),
+                None => return Err(StoreError::Filter(format!("{}.$exists 应为布尔值", key))),
+            },
+            _ => return Err(StoreError::Filter(format!("未知的运算符 {}", op))),
+        });
+    }
+    Ok(if filters.len() == 1 {
+        filters.pop().unwrap()
+    } else {
+        Filter::And(filters)
+    })
+}
+
+/// 数字按数值比较，`1`与`1.0`相等
+fn equal(a: &Value, b: &Value) -> bool {
+    match (a.as_f64(), b.as_f64()) {
+        (Some(x), Some(y)) => x == y,
+        _ => a == b,
+    }
+}
+
+fn contains(value: &Value, expected: &Value) -> bool {
+    match value {
+        Value::Array(items) if !expected.is_array() => items.iter().any(|item| equal(item, expected)),
+        _ => equal(value, expected),
+    }
+}
+
+/// 只比较数字与数字、字符串与字符串，其他组合视为不满足
+fn compare(value: &Value, bound: &Value) -> Option<Ordering> {
+    match (value, bound) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
@@ -0,0 +1,196 @@
+//! 暴力检索的向量存储
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::bm25::KeywordIndex;
+use crate::error::StoreError;
+use crate::filter::Filter;
+use crate::store::{check_dimension, cosine, fuse, Record, SearchHit, VectorStore};
+
+/// 暴力检索的向量存储，每个索引一个JSON Lines文件
+///
+/// 数据全部放在内存中，每次检索都与全部记录比较，适合只有几千个分块的索引。
+#[derive(Debug)]
+pub struct FlatStore {
+    path: PathBuf,
+    records: Vec<Record>,
+    keywords: KeywordIndex,
+    dirty: bool,
+}
+
+impl FlatStore {
+    /// 打开索引文件，不存在时创建空索引
+    pub fn open(path: &Path) -> Result<FlatStore, StoreError> {
+        let mut records = Vec::new();
+        if path.exists() {
+            let file = fs::File::open(path)?;
+            for (number, line) in BufReader::new(file).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let record: Record = serde_json::from_str(&line)
+                    .map_err(|e| StoreError::Corrupt(format!("{} 第 {} 行: {}", path.display(), number + 1, e)))?;
+                records.push(record);
+            }
+        }
+        let mut keywords = KeywordIndex::default();
+        for record in &records {
+            keywords.insert(&record.id, &record.text);
+        }
+        Ok(FlatStore {
+            path: path.to_path_buf(),
+            records,
+            keywords,
+            dirty: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    fn extend(&mut self, records: Vec<Record>) {
+        for record in &records {
+            self.keywords.insert(&record.id, &record.text);
+        }
+        self.records.extend(records);
+        self.dirty = true;
+    }
+}
+
+impl VectorStore for FlatStore {
+    fn upsert(&mut self, records: Vec<Record>) -> Result<(), StoreError> {
+        check_dimension(self.dimension(), &records)?;
+        let ids: Vec<String> = records.iter().map(|record| record.id.clone()).collect();
+        self.delete(&ids)?;
+        self.extend(records);
+        Ok(())
+    }
+
+    fn delete(&mut self, ids: &[String]) -> Result<usize, StoreError> {
+        let ids: HashSet<&str> = ids.iter().map(String::as_str).collect();
+        for id in &ids {
+            self.keywords.remove(id);
+        }
+        let before = self.records.len();
+        self.records.retain(|record| !ids.contains(record.id.as_str()));
+        let removed = before - self.records.len();
+        self.dirty |= removed > 0;
+        Ok(removed)
+    }
+
+    fn replace_document(&mut self, document: &str, records: Vec<Record>) -> Result<usize, StoreError> {
+        // 替换的正是唯一的文档时允许换用不同维度的嵌入模型
+        let only_this = self.records.iter().all(|existing| existing.document == document);
+        check_dimension(if only_this { None } else { self.dimension() }, &records)?;
+        let removed = self.delete_document(document)?;
+        self.extend(records);
+        Ok(removed)
+    }
+
+    fn delete_document(&mut self, document: &str) -> Result<usize, StoreError> {
+        for record in self.records.iter().filter(|record| record.document == document) {
+            self.keywords.remove(&record.id);
+        }
+        let before = self.records.len();
+        self.records.retain(|record| record.document != document);
+        let removed = before - self.records.len();
+        self.dirty |= removed > 0;
+        Ok(removed)
+    }
+
+    fn search(&self, vector: &[f32], top_k: usize, filter: Option<&Filter>) -> Result<Vec<SearchHit>, StoreError> {
+        let mut hits: Vec<(f32, &Record)> = self
+            .records
+            .iter()
+            .filter(|record| record.vector.len() == vector.len())
+            .filter(|record| filter.is_none_or(|filter| filter.matches(&record.metadata)))
+            .map(|record| (cosine(vector, &record.vector), record))
+            .collect();
+        hits.sort_by(|a, b| b.0.total_cmp(&a.0));
+        hits.truncate(top_k);
+        Ok(hits
+            .into_iter()
+            .map(|(score, record)| SearchHit {
+                score,
+                record: record.clone(),
+            })
+            .collect())
+    }
+
+    fn search_hybrid(
+        &self,
+        text: &str,
+        vector: &[f32],
+        top_k: usize,
+        filter: Option<&Filter>,
+    ) -> Result<Vec<SearchHit>, StoreError> {
+        let semantic = self.search(vector, top_k * 4, filter)?;
+        let keyword = self.search_keyword(text, top_k * 4, filter)?;
+        Ok(fuse(vec![semantic, keyword], top_k))
+    }
+
+    fn supports_hybrid(&self) -> bool {
+        true
+    }
+
+    fn search_keyword(&self, text: &str, top_k: usize, filter: Option<&Filter>) -> Result<Vec<SearchHit>, StoreError> {
+        let records: HashMap<&str, &Record> = self.records.iter().map(|record| (record.id.as_str(), record)).collect();
+        Ok(self
+            .keywords
+            .search(text, top_k, |id| {
+                filter.is_none_or(|filter| records.get(id).is_some_and(|record| filter.matches(&record.metadata)))
+            })
+            .into_iter()
+            .filter_map(|(id, score)| {
+                records.get(id.as_str()).map(|record| SearchHit {
+                    score,
+                    record: (*record).clone(),
+                })
+            })
+            .collect())
+    }
+
+    fn supports_keyword(&self) -> bool {
+        true
+    }
+
+    fn len(&self) -> Result<usize, StoreError> {
+        Ok(self.records.len())
+    }
+
+    /// 以第一条记录为准
+    fn dimension(&self) -> Option<usize> {
+        self.records.first().map(|record| record.vector.len())
+    }
+
+    /// 先写临时文件再改名，中途失败不会损坏已有索引
+    fn flush(&mut self) -> Result<(), StoreError> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp = self.path.with_extension("jsonl.tmp");
+        {
+            let mut writer = BufWriter::new(fs::File::create(&temp)?);
+            for record in &self.records {
+                serde_json::to_writer(&mut writer, record).map_err(|e| StoreError::Io(e.into()))?;
+                writer.write_all(b"\n")?;
+            }
+            writer.flush()?;
+        }
+        fs::rename(&temp, &self.path)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
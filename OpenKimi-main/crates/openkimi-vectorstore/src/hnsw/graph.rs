@@ -0,0 +1,294 @@
+//! HNSW图（Malkov & Yashunin, 2016）
+//!
+//! 节点编号即槽位号，与[`HnswStore`](super::HnswStore)中的记录一一对应。删除只做标记，
+//! 被删除的节点仍参与遍历以保持连通，但不出现在结果中，压缩时才真正移除。
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+
+use crate::store::norm;
+
+/// 层数上限，百万级节点也用不到这么多层
+const MAX_LEVEL: usize = 16;
+const LEVEL_SEED: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// 节点及其到查询向量的余弦距离
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Candidate {
+    pub distance: f32,
+    pub slot: u32,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance.total_cmp(&other.distance).then(self.slot.cmp(&other.slot))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Node {
+    pub vector: Vec<f32>,
+    norm: f32,
+    /// 每层的邻居，长度为节点所在的最高层加一
+    pub links: Vec<Vec<u32>>,
+    pub deleted: bool,
+}
+
+impl Node {
+    pub fn new(vector: Vec<f32>, links: Vec<Vec<u32>>, deleted: bool) -> Node {
+        Node {
+            norm: norm(&vector),
+            vector,
+            links,
+            deleted,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Graph {
+    /// 第1层及以上每个节点的邻居数，第0层为两倍
+    pub m: usize,
+    pub ef_construction: usize,
+    pub nodes: Vec<Node>,
+    pub entry: Option<u32>,
+    pub deleted: usize,
+}
+
+/// splitmix64，用槽位号生成层数，同样的插入顺序总是得到同样的图
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+impl Graph {
+    pub fn new(m: usize, ef_construction: usize) -> Graph {
+        let m = m.max(2);
+        Graph {
+            m,
+            ef_construction: ef_construction.max(m),
+            nodes: Vec::new(),
+            entry: None,
+            deleted: 0,
+        }
+    }
+
+    pub fn from_nodes(m: usize, ef_construction: usize, nodes: Vec<Node>, entry: Option<u32>) -> Graph {
+        let deleted = nodes.iter().filter(|node| node.deleted).count();
+        Graph {
+            m: m.max(2),
+            ef_construction,
+            nodes,
+            entry,
+            deleted,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn max_links(&self, layer: usize) -> usize {
+        if layer == 0 {
+            self.m * 2
+        } else {
+            self.m
+        }
+    }
+
+    fn random_level(&self, slot: u32) -> usize {
+        let bits = splitmix64(slot as u64 ^ LEVEL_SEED) >> 11;
+        // 取值范围(0, 1]，避免对0取对数
+        let uniform = (bits + 1) as f64 / (1u64 << 53) as f64;
+        let level = -uniform.ln() / (self.m as f64).ln();
+        (level as usize).min(MAX_LEVEL)
+    }
+
+    fn top_level(&self) -> usize {
+        self.entry.map_or(0, |entry| self.nodes[entry as usize].links.len() - 1)
+    }
+
+    fn distance(&self, query: &[f32], query_norm: f32, slot: u32) -> f32 {
+        let node = &self.nodes[slot as usize];
+        if query_norm == 0.0 || node.norm == 0.0 {
+            return 1.0;
+        }
+        let dot: f32 = query.iter().zip(&node.vector).map(|(a, b)| a * b).sum();
+        1.0 - dot / (query_norm * node.norm)
+    }
+
+    /// 在`layer`层从`entries`出发贪心搜索，返回按距离升序的至多`ef`个节点
+    fn search_layer(
+        &self,
+        query: &[f32],
+        query_norm: f32,
+        entries: &[Candidate],
+        ef: usize,
+        layer: usize,
+    ) -> Vec<Candidate> {
+        let mut visited: HashSet<u32> = entries.iter().map(|candidate| candidate.slot).collect();
+        let mut candidates: BinaryHeap<Reverse<Candidate>> = entries.iter().copied().map(Reverse).collect();
+        let mut results: BinaryHeap<Candidate> = entries.iter().copied().collect();
+
+        while let Some(Reverse(current)) = candidates.pop() {
+            let furthest = results.peek().map_or(f32::INFINITY, |furthest| furthest.distance);
+            if results.len() >= ef && current.distance > furthest {
+                break;
+            }
+            for &neighbor in &self.nodes[current.slot as usize].links[layer] {
+                if !visited.insert(neighbor) {
+                    continue;
+                }
+                let distance = self.distance(query, query_norm, neighbor);
+                let furthest = results.peek().map_or(f32::INFINITY, |furthest| furthest.distance);
+                if results.len() < ef || distance < furthest {
+                    let candidate = Candidate { distance, slot: neighbor };
+                    candidates.push(Reverse(candidate));
+                    results.push(candidate);
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+        results.into_sorted_vec()
+    }
+
+    /// 从上往下逐层贪心，找到`layer`层的入口
+    fn descend(&self, query: &[f32], query_norm: f32, entry: u32, layer: usize) -> Vec<Candidate> {
+        let mut entries = vec![Candidate {
+            distance: self.distance(query, query_norm, entry),
+            slot: entry,
+        }];
+        for upper in (layer + 1..=self.top_level()).rev() {
+            entries = self.search_layer(query, query_norm, &entries, 1, upper);
+        }
+        entries
+    }
+
+    /// 启发式选邻居：候选比已选中的任一邻居离得更近时才选，保证邻居分布在不同方向；
+    /// 不足`m`个时再用被跳过的候选补齐
+    fn select(&self, candidates: &[Candidate], m: usize) -> Vec<u32> {
+        let mut selected: Vec<Candidate> = Vec::with_capacity(m);
+        let mut skipped = Vec::new();
+        for &candidate in candidates {
+            if selected.len() >= m {
+                break;
+            }
+            let node = &self.nodes[candidate.slot as usize];
+            let diverse = selected
+                .iter()
+                .all(|chosen| self.distance(&node.vector, node.norm, chosen.slot) > candidate.distance);
+            if diverse {
+                selected.push(candidate);
+            } else {
+                skipped.push(candidate);
+            }
+        }
+        let missing = m.saturating_sub(selected.len());
+        selected.extend(skipped.into_iter().take(missing));
+        selected.into_iter().map(|candidate| candidate.slot).collect()
+    }
+
+    /// 给`from`加一条指向`to`的边，超出上限时重新挑选邻居
+    fn connect(&mut self, from: u32, to: u32, layer: usize) {
+        let max = self.max_links(layer);
+        let links = &mut self.nodes[from as usize].links[layer];
+        links.push(to);
+        if links.len() <= max {
+            return;
+        }
+        let node = &self.nodes[from as usize];
+        let mut candidates: Vec<Candidate> = node.links[layer]
+            .iter()
+            .map(|&slot| Candidate {
+                distance: self.distance(&node.vector, node.norm, slot),
+                slot,
+            })
+            .collect();
+        candidates.sort();
+        let kept = self.select(&candidates, max);
+        self.nodes[from as usize].links[layer] = kept;
+    }
+
+    /// 插入向量，返回槽位号
+    pub fn insert(&mut self, vector: Vec<f32>) -> u32 {
+        let slot = self.nodes.len() as u32;
+        let level = self.random_level(slot);
+        self.nodes.push(Node::new(vector, vec![Vec::new(); level + 1], false));
+        let Some(entry) = self.entry else {
+            self.entry = Some(slot);
+            return slot;
+        };
+
+        let node = &self.nodes[slot as usize];
+        let (query, query_norm) = (node.vector.clone(), node.norm);
+        let top = self.top_level();
+        let mut entries = self.descend(&query, query_norm, entry, level.min(top));
+        for layer in (0..=level.min(top)).rev() {
+            let found = self.search_layer(&query, query_norm, &entries, self.ef_construction, layer);
+            let neighbors = self.select(&found, self.m);
+            for &neighbor in &neighbors {
+                self.connect(neighbor, slot, layer);
+            }
+            self.nodes[slot as usize].links[layer] = neighbors;
+            entries = found;
+        }
+        if level > top {
+            self.entry = Some(slot);
+        }
+        slot
+    }
+
+    pub fn mark_deleted(&mut self, slot: u32) {
+        let node = &mut self.nodes[slot as usize];
+        if !node.deleted {
+            node.deleted = true;
+            self.deleted += 1;
+        }
+    }
+
+    /// 返回至多`k`个满足`accept`的未删除节点，按距离升序
+    ///
+    /// 满足条件的节点不足`k`个时逐步扩大`ef`，直到遍历完整张图。
+    pub fn search(&self, query: &[f32], k: usize, ef: usize, accept: impl Fn(u32) -> bool) -> Vec<Candidate> {
+        let Some(entry) = self.entry else {
+            return Vec::new();
+        };
+        if k == 0 {
+            return Vec::new();
+        }
+        let query_norm = norm(query);
+        let entries = self.descend(query, query_norm, entry, 0);
+        let mut ef = ef.max(k);
+        loop {
+            let found = self.search_layer(query, query_norm, &entries, ef, 0);
+            let exhausted = found.len() < ef || ef >= self.nodes.len();
+            let hits: Vec<Candidate> = found
+                .into_iter()
+                .filter(|candidate| !self.nodes[candidate.slot as usize].deleted && accept(candidate.slot))
+                .take(k)
+                .collect();
+            if hits.len() >= k || exhausted {
+                return hits;
+            }
+            ef = (ef * 4).min(self.nodes.len());
+        }
+    }
+}
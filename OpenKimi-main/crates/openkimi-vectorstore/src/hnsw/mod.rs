@@ -0,0 +1,465 @@
+//! 磁盘上的HNSW索引
+//!
+//! 每个索引一个`.hnsw`文件，依次保存图的参数、每个节点的向量和各层邻居，以及未删除节点的记录（JSON），
+//! 打开时直接读入，不需要重建图。文件格式（整数均为小端序）：
+//!
+//! ```text
+//! "OKHNSW" 版本(u16)
+//! m(u32) ef_construction(u32) 维度(u32) 节点数(u32) 入口节点(u32，空图为u32::MAX)
+//! 每个节点: 已删除(u8) 层数(u8) 向量(f32 × 维度) 每层[邻居数(u32) 邻居(u32 × 邻居数)]
+//!           未删除时再跟 记录长度(u32) 记录JSON
+//! ```
+//!
+//! 关键词检索使用的BM25索引不写入文件，打开时由记录的文本重建。
+
+mod graph;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::bm25::KeywordIndex;
+use crate::error::StoreError;
+use crate::filter::Filter;
+use crate::store::{check_dimension, fuse, Record, SearchHit, VectorStore};
+use graph::{Graph, Node};
+
+const MAGIC: &[u8; 6] = b"OKHNSW";
+const VERSION: u16 = 1;
+const NO_ENTRY: u32 = u32::MAX;
+
+/// HNSW参数
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HnswConfig {
+    /// 每个节点的邻居数，越大召回越高、索引越大
+    pub m: usize,
+    /// 建图时的候选数
+    pub ef_construction: usize,
+    /// 检索时的候选数，至少为`top_k`
+    pub ef_search: usize,
+    /// 已删除节点占比超过该值时，写盘前压缩索引
+    pub compact_threshold: f32,
+}
+
+impl Default for HnswConfig {
+    fn default() -> Self {
+        HnswConfig {
+            m: 16,
+            ef_construction: 200,
+            ef_search: 64,
+            compact_threshold: 0.3,
+        }
+    }
+}
+
+/// 记录中除向量外的部分，向量已在节点中保存
+#[derive(Serialize)]
+struct StoredRecordRef<'a> {
+    id: &'a str,
+    document: &'a str,
+    text: &'a str,
+    metadata: &'a Map<String, Value>,
+}
+
+#[derive(Deserialize)]
+struct StoredRecord {
+    id: String,
+    document: String,
+    text: String,
+    #[serde(default)]
+    metadata: Map<String, Value>,
+}
+
+/// 基于HNSW图的向量存储
+///
+/// 检索复杂度约为O(log n)，适合几万到几十万个分块的本地索引。删除和覆盖只标记旧节点，
+/// 已删除节点过多时在[`flush`](VectorStore::flush)中自动压缩，也可以调用[`HnswStore::compact`]。
+#[derive(Debug)]
+pub struct HnswStore {
+    path: PathBuf,
+    config: HnswConfig,
+    graph: Graph,
+    /// 按槽位号保存的记录，已删除的为`None`
+    slots: Vec<Option<Record>>,
+    ids: HashMap<String, u32>,
+    keywords: KeywordIndex,
+    dirty: bool,
+}
+
+fn corrupt(path: &Path, err: io::Error) -> StoreError {
+    if err.kind() == io::ErrorKind::UnexpectedEof {
+        StoreError::Corrupt(format!("{} 不完整", path.display()))
+    } else {
+        StoreError::Io(err)
+    }
+}
+
+fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+impl HnswStore {
+    /// 打开索引文件，不存在时创建空索引
+    pub fn open(path: &Path, config: HnswConfig) -> Result<HnswStore, StoreError> {
+        let mut store = HnswStore {
+            path: path.to_path_buf(),
+            graph: Graph::new(config.m, config.ef_construction),
+            config,
+            slots: Vec::new(),
+            ids: HashMap::new(),
+            keywords: KeywordIndex::default(),
+            dirty: false,
+        };
+        if path.exists() {
+            let mut reader = BufReader::new(fs::File::open(path)?);
+            store.read(&mut reader).map_err(|e| corrupt(path, e))??;
+        }
+        Ok(store)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 外层错误是读文件失败，内层是内容不合法
+    fn read(&mut self, reader: &mut impl Read) -> io::Result<Result<(), StoreError>> {
+        let path = self.path.display().to_string();
+        let invalid = |reason: String| Ok(Err(StoreError::Corrupt(format!("{}: {}", path, reason))));
+
+        let mut magic = [0; 6];
+        reader.read_exact(&mut magic)?;
+        let mut version = [0; 2];
+        reader.read_exact(&mut version)?;
+        if &magic != MAGIC {
+            return invalid("不是HNSW索引文件".to_string());
+        }
+        if u16::from_le_bytes(version) != VERSION {
+            return invalid(format!("不支持的版本 {}", u16::from_le_bytes(version)));
+        }
+        let m = read_u32(reader)? as usize;
+        let ef_construction = read_u32(reader)? as usize;
+        let dimension = read_u32(reader)? as usize;
+        let count = read_u32(reader)?;
+        let entry = read_u32(reader)?;
+        if (entry == NO_ENTRY) != (count == 0) || (entry != NO_ENTRY && entry >= count) {
+            return invalid(format!("入口节点 {} 与节点数 {} 不符", entry, count));
+        }
+
+        let mut nodes = Vec::with_capacity(count as usize);
+        let mut slots = Vec::with_capacity(count as usize);
+        let mut ids = HashMap::new();
+        for slot in 0..count {
+            let deleted = read_u8(reader)? != 0;
+            let levels = read_u8(reader)? as usize;
+            let mut vector = vec![0f32; dimension];
+            for value in &mut vector {
+                let mut buf = [0; 4];
+                reader.read_exact(&mut buf)?;
+                *value = f32::from_le_bytes(buf);
+            }
+            let mut links = Vec::with_capacity(levels);
+            for _ in 0..levels {
+                let len = read_u32(reader)? as usize;
+                let mut layer = Vec::with_capacity(len.min(1024));
+                for _ in 0..len {
+                    let neighbor = read_u32(reader)?;
+                    if neighbor >= count {
+                        return invalid(format!("节点 {} 的邻居 {} 超出节点数", slot, neighbor));
+                    }
+                    layer.push(neighbor);
+                }
+                links.push(layer);
+            }
+            if levels == 0 {
+                return invalid(format!("节点 {} 没有层", slot));
+            }
+
+            if deleted {
+                slots.push(None);
+            } else {
+                let len = read_u32(reader)? as usize;
+                let mut json = vec![0; len];
+                reader.read_exact(&mut json)?;
+                let stored: StoredRecord = match serde_json::from_slice(&json) {
+                    Ok(stored) => stored,
+                    Err(err) => return invalid(format!("节点 {} 的记录: {}", slot, err)),
+                };
+                ids.insert(stored.id.clone(), slot);
+                slots.push(Some(Record {
+                    id: stored.id,
+                    document: stored.document,
+                    text: stored.text,
+                    metadata: stored.metadata,
+                    vector: vector.clone(),
+                }));
+            }
+            nodes.push(Node::new(vector, links, deleted));
+        }
+
+        // 邻居必须也在该层，否则遍历时会越界
+        for (slot, node) in nodes.iter().enumerate() {
+            for (layer, neighbors) in node.links.iter().enumerate() {
+                let outside = neighbors.iter().find(|&&neighbor| nodes[neighbor as usize].links.len() <= layer);
+                if let Some(neighbor) = outside {
+                    return invalid(format!("节点 {} 在第 {} 层的邻居 {} 不在该层", slot, layer, neighbor));
+                }
+            }
+        }
+
+        let entry = (entry != NO_ENTRY).then_some(entry);
+        self.graph = Graph::from_nodes(m, ef_construction, nodes, entry);
+        for record in slots.iter().flatten() {
+            self.keywords.insert(&record.id, &record.text);
+        }
+        self.slots = slots;
+        self.ids = ids;
+        Ok(Ok(()))
+    }
+
+    fn write(&self, writer: &mut impl Write) -> Result<(), StoreError> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        let dimension = self.graph.nodes.first().map_or(0, |node| node.vector.len());
+        for value in [self.graph.m, self.graph.ef_construction, dimension, self.graph.len()] {
+            writer.write_all(&(value as u32).to_le_bytes())?;
+        }
+        writer.write_all(&self.graph.entry.unwrap_or(NO_ENTRY).to_le_bytes())?;
+
+        for (node, record) in self.graph.nodes.iter().zip(&self.slots) {
+            writer.write_all(&[node.deleted as u8, node.links.len() as u8])?;
+            for value in &node.vector {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+            for layer in &node.links {
+                writer.write_all(&(layer.len() as u32).to_le_bytes())?;
+                for neighbor in layer {
+                    writer.write_all(&neighbor.to_le_bytes())?;
+                }
+            }
+            if let Some(record) = record {
+                let json = serde_json::to_vec(&StoredRecordRef {
+                    id: &record.id,
+                    document: &record.document,
+                    text: &record.text,
+                    metadata: &record.metadata,
+                })
+                .map_err(|e| StoreError::Io(e.into()))?;
+                writer.write_all(&(json.len() as u32).to_le_bytes())?;
+                writer.write_all(&json)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, record: Record) {
+        if let Some(old) = self.ids.remove(&record.id) {
+            self.remove_slot(old);
+        }
+        let slot = self.graph.insert(record.vector.clone());
+        self.ids.insert(record.id.clone(), slot);
+        self.keywords.insert(&record.id, &record.text);
+        self.slots.push(Some(record));
+        self.dirty = true;
+    }
+
+    /// 只剩已删除的节点且新向量维度不同时清空图，旧节点无法再与新向量比较距离
+    fn reset_for(&mut self, records: &[Record]) {
+        let dimension = self.graph.nodes.first().map(|node| node.vector.len());
+        if let (Some(dimension), Some(record)) = (dimension, records.first()) {
+            if record.vector.len() != dimension && self.ids.is_empty() {
+                self.graph = Graph::new(self.config.m, self.config.ef_construction);
+                self.slots.clear();
+            }
+        }
+    }
+
+    fn remove_slot(&mut self, slot: u32) {
+        self.graph.mark_deleted(slot);
+        if let Some(record) = self.slots[slot as usize].take() {
+            self.keywords.remove(&record.id);
+        }
+        self.dirty = true;
+    }
+
+    /// 已删除节点的占比
+    pub fn deleted_ratio(&self) -> f32 {
+        if self.graph.len() == 0 {
+            0.0
+        } else {
+            self.graph.deleted as f32 / self.graph.len() as f32
+        }
+    }
+
+    /// 丢弃已删除的节点，用剩余记录重建图
+    pub fn compact(&mut self) {
+        let records: Vec<Record> = self.slots.drain(..).flatten().collect();
+        self.graph = Graph::new(self.config.m, self.config.ef_construction);
+        self.ids.clear();
+        self.keywords.clear();
+        for record in records {
+            self.insert(record);
+        }
+        self.dirty = true;
+    }
+}
+
+impl VectorStore for HnswStore {
+    fn upsert(&mut self, records: Vec<Record>) -> Result<(), StoreError> {
+        check_dimension(self.dimension(), &records)?;
+        self.reset_for(&records);
+        for record in records {
+            self.insert(record);
+        }
+        Ok(())
+    }
+
+    fn delete(&mut self, ids: &[String]) -> Result<usize, StoreError> {
+        let mut removed = 0;
+        for id in ids {
+            if let Some(slot) = self.ids.remove(id) {
+                self.remove_slot(slot);
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn replace_document(&mut self, document: &str, records: Vec<Record>) -> Result<usize, StoreError> {
+        // 替换的正是唯一的文档时允许换用不同维度的嵌入模型
+        let only_this = self.slots.iter().flatten().all(|existing| existing.document == document);
+        check_dimension(if only_this { None } else { self.dimension() }, &records)?;
+        let removed = self.delete_document(document)?;
+        self.reset_for(&records);
+        for record in records {
+            self.insert(record);
+        }
+        Ok(removed)
+    }
+
+    fn delete_document(&mut self, document: &str) -> Result<usize, StoreError> {
+        let slots: Vec<u32> = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter(|(_, record)| record.as_ref().is_some_and(|record| record.document == document))
+            .map(|(slot, _)| slot as u32)
+            .collect();
+        for &slot in &slots {
+            if let Some(record) = &self.slots[slot as usize] {
+                self.ids.remove(&record.id);
+            }
+            self.remove_slot(slot);
+        }
+        Ok(slots.len())
+    }
+
+    fn search(&self, vector: &[f32], top_k: usize, filter: Option<&Filter>) -> Result<Vec<SearchHit>, StoreError> {
+        if self.dimension() != Some(vector.len()) {
+            return Ok(Vec::new());
+        }
+        Ok(self
+            .graph
+            .search(vector, top_k, self.config.ef_search, |slot| {
+                self.slots[slot as usize]
+                    .as_ref()
+                    .is_some_and(|record| filter.is_none_or(|filter| filter.matches(&record.metadata)))
+            })
+            .into_iter()
+            .filter_map(|candidate| {
+                self.slots[candidate.slot as usize].as_ref().map(|record| SearchHit {
+                    score: 1.0 - candidate.distance,
+                    record: record.clone(),
+                })
+            })
+            .collect())
+    }
+
+    fn search_hybrid(
+        &self,
+        text: &str,
+        vector: &[f32],
+        top_k: usize,
+        filter: Option<&Filter>,
+    ) -> Result<Vec<SearchHit>, StoreError> {
+        let semantic = self.search(vector, top_k * 4, filter)?;
+        let keyword = self.search_keyword(text, top_k * 4, filter)?;
+        Ok(fuse(vec![semantic, keyword], top_k))
+    }
+
+    fn supports_hybrid(&self) -> bool {
+        true
+    }
+
+    fn search_keyword(&self, text: &str, top_k: usize, filter: Option<&Filter>) -> Result<Vec<SearchHit>, StoreError> {
+        let record = |id: &str| self.ids.get(id).and_then(|&slot| self.slots[slot as usize].as_ref());
+        Ok(self
+            .keywords
+            .search(text, top_k, |id| {
+                record(id).is_some_and(|record| filter.is_none_or(|filter| filter.matches(&record.metadata)))
+            })
+            .into_iter()
+            .filter_map(|(id, score)| {
+                record(&id).map(|record| SearchHit {
+                    score,
+                    record: record.clone(),
+                })
+            })
+            .collect())
+    }
+
+    fn supports_keyword(&self) -> bool {
+        true
+    }
+
+    fn len(&self) -> Result<usize, StoreError> {
+        Ok(self.ids.len())
+    }
+
+    fn dimension(&self) -> Option<usize> {
+        self.slots.iter().flatten().next().map(|record| record.vector.len())
+    }
+
+    /// 先写临时文件再改名，中途失败不会损坏已有索引
+    fn flush(&mut self) -> Result<(), StoreError> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if self.deleted_ratio() > self.config.compact_threshold {
+            self.compact();
+        }
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp = self.path.with_extension("hnsw.tmp");
+        {
+            let mut writer = BufWriter::new(fs::File::create(&temp)?);
+            self.write(&mut writer)?;
+            writer.flush()?;
+        }
+        fs::rename(&temp, &self.path)?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    fn compact(&mut self, threshold: f32) -> Result<bool, StoreError> {
+        if self.deleted_ratio() <= threshold {
+            return Ok(false);
+        }
+        HnswStore::compact(self);
+        self.flush()?;
+        Ok(true)
+    }
+}
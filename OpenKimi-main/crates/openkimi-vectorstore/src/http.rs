@@ -0,0 +1,57 @@
+//! Qdrant和Milvus共用的JSON接口客户端
+
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::error::StoreError;
+
+#[derive(Debug, Clone)]
+pub(crate) struct JsonClient {
+    agent: ureq::Agent,
+    base: String,
+    headers: Vec<(&'static str, String)>,
+}
+
+impl JsonClient {
+    pub fn new(base: &str, headers: Vec<(&'static str, String)>) -> JsonClient {
+        JsonClient {
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(60))
+                .user_agent(concat!("openkimi-vectorstore/", env!("CARGO_PKG_VERSION")))
+                .build(),
+            base: base.trim_end_matches('/').to_string(),
+            headers,
+        }
+    }
+
+    /// 发送请求，返回`None`表示404
+    pub fn call_optional(&self, method: &str, path: &str, body: Option<&Value>) -> Result<Option<Value>, StoreError> {
+        let mut request = self.agent.request(method, &format!("{}{}", self.base, path));
+        for (name, value) in &self.headers {
+            request = request.set(name, value);
+        }
+        let result = match body {
+            Some(body) => request.send_json(body),
+            None => request.call(),
+        };
+        let response = match result {
+            Ok(response) => response,
+            Err(ureq::Error::Status(404, _)) => return Ok(None),
+            Err(ureq::Error::Status(code, response)) => {
+                let body = response.into_string().unwrap_or_default();
+                return Err(StoreError::Backend(format!("{} {} 返回 {}: {}", method, path, code, body.trim())));
+            }
+            Err(err) => return Err(StoreError::Backend(format!("{} {} 失败: {}", method, path, err))),
+        };
+        response
+            .into_json()
+            .map(Some)
+            .map_err(|e| StoreError::Backend(format!("{} {} 的响应无法解析: {}", method, path, e)))
+    }
+
+    pub fn call(&self, method: &str, path: &str, body: Option<&Value>) -> Result<Value, StoreError> {
+        self.call_optional(method, path, body)?
+            .ok_or_else(|| StoreError::Backend(format!("{} {} 返回 404", method, path)))
+    }
+}
@@ -0,0 +1,37 @@
+//! 向量存储
+//!
+//! 内嵌的实现有两种：[`FlatStore`]把记录存为JSON Lines、检索时逐条比较，[`HnswStore`]在磁盘上保存HNSW图，
+//! 检索只访问少量节点，小规模部署不需要另外运行向量数据库。数据量更大或需要多实例共享时，
+//! 可以启用`qdrant`、`pgvector`、`milvus`特性使用外部向量数据库。
+//!
+//! 所有实现都通过[`VectorStore`]访问，支持按id写入和删除、按文档替换、按元数据过滤（见[`Filter`]）。
+//! 内嵌的实现在内存中另建BM25关键词索引，外部数据库使用各自的全文检索，都支持关键词与向量的混合检索；
+//! 除Qdrant外也都支持只按关键词检索。
+//! 外部后端使用阻塞IO，在异步代码中应放到阻塞线程中调用。
+
+mod bm25;
+mod error;
+mod filter;
+mod flat;
+mod hnsw;
+#[cfg(any(feature = "qdrant", feature = "milvus"))]
+mod http;
+#[cfg(feature = "milvus")]
+mod milvus;
+#[cfg(feature = "pgvector")]
+mod pgvector;
+#[cfg(feature = "qdrant")]
+mod qdrant;
+mod store;
+
+pub use error::StoreError;
+pub use filter::{Compare, Filter};
+pub use flat::FlatStore;
+pub use hnsw::{HnswConfig, HnswStore};
+#[cfg(feature = "milvus")]
+pub use milvus::{MilvusConfig, MilvusStore};
+#[cfg(feature = "pgvector")]
+pub use pgvector::{PgvectorConfig, PgvectorStore};
+#[cfg(feature = "qdrant")]
+pub use qdrant::{QdrantConfig, QdrantStore};
+pub use store::{Record, SearchHit, VectorStore};
@@ -0,0 +1,400 @@
+//! Milvus后端，使用RESTful API v2
+//!
+//! 每个索引对应一个集合，`vector`字段建HNSW索引（余弦相似度），元数据保存为JSON字段。
+//! 开启`full_text`后新建的集合会带上BM25全文检索（需要Milvus 2.5及以上），已有集合是否支持
+//! 混合检索以打开时的集合结构为准。
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::error::StoreError;
+use crate::filter::{Compare, Filter};
+use crate::http::JsonClient;
+use crate::store::{check_dimension, Record, SearchHit, VectorStore};
+
+/// `id`和`document`字段的最大字节数
+const MAX_ID_LENGTH: usize = 4096;
+/// `text`字段的最大字节数，也是Milvus对VarChar的上限
+const MAX_TEXT_LENGTH: usize = 65535;
+const OUTPUT_FIELDS: [&str; 5] = ["id", "document", "text", "metadata", "vector"];
+
+/// Milvus连接配置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MilvusConfig {
+    pub url: String,
+    /// `用户名:密码`或Zilliz Cloud的API密钥
+    pub token: Option<String>,
+    pub database: Option<String>,
+    /// 集合名为前缀加索引名，Milvus的集合名不能包含`-`，会替换为`_`
+    pub collection_prefix: String,
+    /// 每次写入的实体数
+    pub batch_size: usize,
+    /// 新建集合时是否开启BM25全文检索
+    pub full_text: bool,
+    /// 全文检索的分析器，如`standard`、`english`、`chinese`
+    pub analyzer: String,
+}
+
+impl Default for MilvusConfig {
+    fn default() -> Self {
+        MilvusConfig {
+            url: "http://localhost:19530".to_string(),
+            token: None,
+            database: None,
+            collection_prefix: "openkimi_".to_string(),
+            batch_size: 256,
+            full_text: false,
+            analyzer: "chinese".to_string(),
+        }
+    }
+}
+
+/// 存放在Milvus集合中的索引
+#[derive(Debug)]
+pub struct MilvusStore {
+    client: JsonClient,
+    collection: String,
+    database: Option<String>,
+    batch_size: usize,
+    full_text: bool,
+    analyzer: String,
+    /// 集合不存在时为`None`，第一次写入时按向量维度创建
+    dimension: Option<usize>,
+}
+
+fn literal(value: &Value) -> Result<String, StoreError> {
+    match value {
+        Value::String(_) | Value::Number(_) | Value::Bool(_) => Ok(value.to_string()),
+        _ => Err(StoreError::Filter(format!("Milvus不支持按 {} 过滤", value))),
+    }
+}
+
+/// 把过滤条件转换为Milvus的布尔表达式，元数据位于JSON字段`metadata`中
+fn to_expression(filter: &Filter) -> Result<String, StoreError> {
+    let field = |key: &str| format!("metadata[{}]", Value::from(key));
+    let join = |filters: &[Filter], joiner: &str, empty: &str| -> Result<String, StoreError> {
+        if filters.is_empty() {
+            return Ok(empty.to_string());
+        }
+        let parts = filters.iter().map(to_expression).collect::<Result<Vec<_>, _>>()?;
+        Ok(format!("({})", parts.join(joiner)))
+    };
+    Ok(match filter {
+        Filter::Eq(key, value) => {
+            let (field, value) = (field(key), literal(value)?);
+            format!("({field} == {value} or json_contains({field}, {value}))")
+        }
+        Filter::In(key, values) => {
+            let values = values.iter().map(literal).collect::<Result<Vec<_>, _>>()?.join(", ");
+            let field = field(key);
+            format!("({field} in [{values}] or json_contains_any({field}, [{values}]))")
+        }
+        Filter::Compare(key, op, value) => {
+            let op = match op {
+                Compare::Gt => ">",
+                Compare::Gte => ">=",
+                Compare::Lt => "<",
+                Compare::Lte => "<=",
+            };
+            format!("{} {} {}", field(key), op, literal(value)?)
+        }
+        Filter::Exists(key) => format!("exists {}", field(key)),
+        // Milvus表达式没有true/false字面量，用主键条件代替
+        Filter::And(filters) => join(filters, " and ", "id != \"\"")?,
+        Filter::Or(filters) => join(filters, " or ", "id == \"\"")?,
+        Filter::Not(filter) => format!("not ({})", to_expression(filter)?),
+    })
+}
+
+fn parse_entity(entity: &Value) -> Result<SearchHit, StoreError> {
+    let field = |name: &str| {
+        entity[name]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| StoreError::Backend(format!("Milvus返回的实体缺少 {}", name)))
+    };
+    Ok(SearchHit {
+        score: entity["distance"].as_f64().unwrap_or_default() as f32,
+        record: Record {
+            id: field("id")?,
+            document: field("document")?,
+            text: field("text")?,
+            metadata: entity["metadata"].as_object().cloned().unwrap_or_default(),
+            vector: entity["vector"]
+                .as_array()
+                .map(|values| values.iter().filter_map(Value::as_f64).map(|v| v as f32).collect())
+                .unwrap_or_default(),
+        },
+    })
+}
+
+impl MilvusStore {
+    /// 连接Milvus并读取索引`index`对应集合的结构，集合不存在时不创建
+    pub fn open(config: &MilvusConfig, index: &str) -> Result<MilvusStore, StoreError> {
+        let headers = config
+            .token
+            .iter()
+            .filter(|token| !token.is_empty())
+            .map(|token| ("Authorization", format!("Bearer {}", token)))
+            .collect();
+        let mut store = MilvusStore {
+            client: JsonClient::new(&config.url, headers),
+            collection: format!("{}{}", config.collection_prefix, index).replace('-', "_"),
+            database: config.database.clone(),
+            batch_size: config.batch_size.max(1),
+            full_text: config.full_text,
+            analyzer: config.analyzer.clone(),
+            dimension: None,
+        };
+
+        let exists = store.call("/v2/vectordb/collections/has", json!({}))?["has"].as_bool().unwrap_or(false);
+        if exists {
+            let description = store.call("/v2/vectordb/collections/describe", json!({}))?;
+            let fields = description["fields"].as_array().cloned().unwrap_or_default();
+            store.full_text = fields.iter().any(|field| field["name"] == "sparse");
+            store.dimension = fields
+                .iter()
+                .find(|field| field["name"] == "vector")
+                .and_then(|field| field["params"].as_array())
+                .and_then(|params| params.iter().find(|param| param["key"] == "dim"))
+                .and_then(|param| match &param["value"] {
+                    Value::String(value) => value.parse().ok(),
+                    value => value.as_u64().map(|value| value as usize),
+                });
+            store.call("/v2/vectordb/collections/load", json!({}))?;
+        }
+        Ok(store)
+    }
+
+    pub fn collection(&self) -> &str {
+        &self.collection
+    }
+
+    /// 调用接口并检查返回的`code`，请求体自动带上集合名和数据库名
+    fn call(&self, path: &str, mut body: Value) -> Result<Value, StoreError> {
+        body["collectionName"] = Value::from(self.collection.as_str());
+        if let Some(database) = &self.database {
+            body["dbName"] = Value::from(database.as_str());
+        }
+        let mut response = self.client.call("POST", path, Some(&body))?;
+        match response["code"].as_i64() {
+            Some(0) | None => Ok(response["data"].take()),
+            Some(code) => Err(StoreError::Backend(format!(
+                "{} 返回 {}: {}",
+                path,
+                code,
+                response["message"].as_str().unwrap_or_default()
+            ))),
+        }
+    }
+
+    fn create(&mut self, dimension: usize) -> Result<(), StoreError> {
+        let mut text = json!({ "max_length": MAX_TEXT_LENGTH });
+        let mut fields = vec![
+            json!({ "fieldName": "id", "dataType": "VarChar", "isPrimary": true,
+                    "elementTypeParams": { "max_length": MAX_ID_LENGTH } }),
+            json!({ "fieldName": "document", "dataType": "VarChar",
+                    "elementTypeParams": { "max_length": MAX_ID_LENGTH } }),
+            json!({ "fieldName": "metadata", "dataType": "JSON" }),
+            json!({ "fieldName": "vector", "dataType": "FloatVector",
+                    "elementTypeParams": { "dim": dimension.to_string() } }),
+        ];
+        let mut index_params = vec![json!({
+            "fieldName": "vector",
+            "indexName": "vector",
+            "metricType": "COSINE",
+            "params": { "index_type": "HNSW", "M": 16, "efConstruction": 200 },
+        })];
+        let mut functions = Vec::new();
+        if self.full_text {
+            text["enable_analyzer"] = Value::Bool(true);
+            text["analyzer_params"] = json!({ "type": self.analyzer });
+            fields.push(json!({ "fieldName": "sparse", "dataType": "SparseFloatVector" }));
+            functions.push(json!({
+                "name": "text_bm25",
+                "type": "BM25",
+                "inputFieldNames": ["text"],
+                "outputFieldNames": ["sparse"],
+                "params": {},
+            }));
+            index_params.push(json!({
+                "fieldName": "sparse",
+                "indexName": "sparse",
+                "metricType": "BM25",
+                "params": { "index_type": "SPARSE_INVERTED_INDEX" },
+            }));
+        }
+        fields.push(json!({ "fieldName": "text", "dataType": "VarChar", "elementTypeParams": text }));
+
+        let body = json!({
+            "schema": { "autoId": false, "enableDynamicField": false, "fields": fields, "functions": functions },
+            "indexParams": index_params,
+        });
+        self.call("/v2/vectordb/collections/create", body)?;
+        self.dimension = Some(dimension);
+        Ok(())
+    }
+
+    fn count(&self, filter: &str) -> Result<usize, StoreError> {
+        let data = self.call(
+            "/v2/vectordb/entities/query",
+            json!({ "filter": filter, "outputFields": ["count(*)"] }),
+        )?;
+        Ok(data[0]["count(*)"].as_u64().unwrap_or_default() as usize)
+    }
+
+    /// 删除满足表达式的实体，返回删除数
+    fn delete_where(&self, filter: &str) -> Result<usize, StoreError> {
+        if self.dimension.is_none() {
+            return Ok(0);
+        }
+        let count = self.count(filter)?;
+        if count > 0 {
+            self.call("/v2/vectordb/entities/delete", json!({ "filter": filter }))?;
+        }
+        Ok(count)
+    }
+
+    fn hits(data: Value) -> Result<Vec<SearchHit>, StoreError> {
+        data.as_array()
+            .map(|entities| entities.iter().map(parse_entity).collect())
+            .unwrap_or_else(|| Ok(Vec::new()))
+    }
+}
+
+impl VectorStore for MilvusStore {
+    fn upsert(&mut self, records: Vec<Record>) -> Result<(), StoreError> {
+        check_dimension(self.dimension, &records)?;
+        let Some(first) = records.first() else {
+            return Ok(());
+        };
+        if self.dimension.is_none() {
+            self.create(first.vector.len())?;
+        }
+        for batch in records.chunks(self.batch_size) {
+            let data: Vec<Value> = batch
+                .iter()
+                .map(|record| {
+                    json!({
+                        "id": record.id,
+                        "document": record.document,
+                        "text": record.text,
+                        "metadata": record.metadata,
+                        "vector": record.vector,
+                    })
+                })
+                .collect();
+            self.call("/v2/vectordb/entities/upsert", json!({ "data": data }))?;
+        }
+        Ok(())
+    }
+
+    fn delete(&mut self, ids: &[String]) -> Result<usize, StoreError> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        self.delete_where(&format!("id in {}", Value::from(ids.to_vec())))
+    }
+
+    fn replace_document(&mut self, document: &str, records: Vec<Record>) -> Result<usize, StoreError> {
+        check_dimension(self.dimension, &records)?;
+        let removed = self.delete_document(document)?;
+        self.upsert(records)?;
+        Ok(removed)
+    }
+
+    fn delete_document(&mut self, document: &str) -> Result<usize, StoreError> {
+        self.delete_where(&format!("document == {}", Value::from(document)))
+    }
+
+    fn search(&self, vector: &[f32], top_k: usize, filter: Option<&Filter>) -> Result<Vec<SearchHit>, StoreError> {
+        if self.dimension.is_none() {
+            return Ok(Vec::new());
+        }
+        let mut body = json!({
+            "data": [vector],
+            "annsField": "vector",
+            "limit": top_k,
+            "outputFields": OUTPUT_FIELDS,
+            "searchParams": { "metricType": "COSINE", "params": { "ef": top_k.max(64) } },
+        });
+        if let Some(filter) = filter {
+            body["filter"] = Value::from(to_expression(filter)?);
+        }
+        MilvusStore::hits(self.call("/v2/vectordb/entities/search", body)?)
+    }
+
+    /// 向量检索与BM25全文检索各取`top_k`的4倍候选，按倒数排名融合（k=60）
+    fn search_hybrid(
+        &self,
+        text: &str,
+        vector: &[f32],
+        top_k: usize,
+        filter: Option<&Filter>,
+    ) -> Result<Vec<SearchHit>, StoreError> {
+        if !self.full_text {
+            return self.search(vector, top_k, filter);
+        }
+        if self.dimension.is_none() {
+            return Ok(Vec::new());
+        }
+        let mut semantic = json!({ "data": [vector], "annsField": "vector", "limit": top_k * 4 });
+        let mut keyword = json!({ "data": [text], "annsField": "sparse", "limit": top_k * 4 });
+        if let Some(filter) = filter {
+            let expression = Value::from(to_expression(filter)?);
+            semantic["filter"] = expression.clone();
+            keyword["filter"] = expression;
+        }
+        let body = json!({
+            "search": [semantic, keyword],
+            "rerank": { "strategy": "rrf", "params": { "k": 60 } },
+            "limit": top_k,
+            "outputFields": OUTPUT_FIELDS,
+        });
+        MilvusStore::hits(self.call("/v2/vectordb/entities/hybrid_search", body)?)
+    }
+
+    fn supports_hybrid(&self) -> bool {
+        self.full_text
+    }
+
+    /// 在BM25稀疏向量上检索，只有开启`full_text`后创建的集合支持
+    fn search_keyword(&self, text: &str, top_k: usize, filter: Option<&Filter>) -> Result<Vec<SearchHit>, StoreError> {
+        if !self.full_text {
+            return Err(StoreError::Unsupported("关键词检索，需要开启 full_text 后重新创建集合".to_string()));
+        }
+        if self.dimension.is_none() {
+            return Ok(Vec::new());
+        }
+        let mut body = json!({
+            "data": [text],
+            "annsField": "sparse",
+            "limit": top_k,
+            "outputFields": OUTPUT_FIELDS,
+        });
+        if let Some(filter) = filter {
+            body["filter"] = Value::from(to_expression(filter)?);
+        }
+        MilvusStore::hits(self.call("/v2/vectordb/entities/search", body)?)
+    }
+
+    fn supports_keyword(&self) -> bool {
+        self.full_text
+    }
+
+    fn len(&self) -> Result<usize, StoreError> {
+        if self.dimension.is_none() {
+            return Ok(0);
+        }
+        self.count("")
+    }
+
+    fn dimension(&self) -> Option<usize> {
+        self.dimension
+    }
+
+    fn flush(&mut self) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
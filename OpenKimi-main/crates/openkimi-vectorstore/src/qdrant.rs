@@ -0,0 +1,314 @@
+//! Qdrant后端
+//!
+//! 每个索引对应一个集合，使用余弦距离；记录的id、所属文档、文本和元数据放在payload中，
+//! 点id由记录id的SHA-256生成（Qdrant只接受整数或UUID）。`document`字段建关键词索引，
+//! `text`字段建全文索引用于混合检索。
+
+use std::env;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::error::StoreError;
+use crate::filter::{Compare, Filter};
+use crate::http::JsonClient;
+use crate::store::{check_dimension, Record, SearchHit, VectorStore};
+
+/// Qdrant连接配置
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct QdrantConfig {
+    pub url: String,
+    /// 缺省时读取`QDRANT_API_KEY`
+    pub api_key: Option<String>,
+    /// 集合名为前缀加索引名
+    pub collection_prefix: String,
+    /// 每次写入的点数
+    pub batch_size: usize,
+    /// `text`字段全文索引的分词器，中文需要`multilingual`
+    pub tokenizer: String,
+}
+
+impl Default for QdrantConfig {
+    fn default() -> Self {
+        QdrantConfig {
+            url: "http://localhost:6333".to_string(),
+            api_key: None,
+            collection_prefix: "openkimi_".to_string(),
+            batch_size: 256,
+            tokenizer: "multilingual".to_string(),
+        }
+    }
+}
+
+/// 存放在Qdrant集合中的索引
+#[derive(Debug)]
+pub struct QdrantStore {
+    client: JsonClient,
+    collection: String,
+    batch_size: usize,
+    tokenizer: String,
+    /// 集合不存在时为`None`，第一次写入时按向量维度创建
+    dimension: Option<usize>,
+}
+
+/// 由记录id生成UUID形式的点id
+fn point_id(id: &str) -> String {
+    let digest = Sha256::digest(id.as_bytes());
+    let hex: String = digest[..16].iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+fn match_value(key: String, value: &Value) -> Result<Value, StoreError> {
+    match value {
+        Value::String(_) | Value::Bool(_) => Ok(json!({ "key": key, "match": { "value": value } })),
+        Value::Number(number) if number.is_i64() || number.is_u64() => {
+            Ok(json!({ "key": key, "match": { "value": value } }))
+        }
+        Value::Number(_) => Ok(json!({ "key": key, "range": { "gte": value, "lte": value } })),
+        _ => Err(StoreError::Filter(format!("Qdrant不支持按 {} 过滤", value))),
+    }
+}
+
+/// 把过滤条件转换为Qdrant的filter，元数据位于payload的`metadata`下
+fn to_qdrant(filter: &Filter) -> Result<Value, StoreError> {
+    let key = |name: &str| format!("metadata.{}", name);
+    let all = |filters: &[Filter]| filters.iter().map(to_qdrant).collect::<Result<Vec<_>, _>>();
+    Ok(match filter {
+        Filter::Eq(name, value) => json!({ "must": [match_value(key(name), value)?] }),
+        Filter::In(name, values) => {
+            let keywords = values.iter().all(Value::is_string);
+            let integers = values.iter().all(|value| value.is_i64() || value.is_u64());
+            if keywords || integers {
+                json!({ "must": [{ "key": key(name), "match": { "any": values } }] })
+            } else {
+                let conditions = values
+                    .iter()
+                    .map(|value| match_value(key(name), value))
+                    .collect::<Result<Vec<_>, _>>()?;
+                json!({ "should": conditions })
+            }
+        }
+        Filter::Compare(name, op, value) => {
+            if !value.is_number() {
+                return Err(StoreError::Filter(format!("Qdrant只支持数值比较: {}", value)));
+            }
+            let op = match op {
+                Compare::Gt => "gt",
+                Compare::Gte => "gte",
+                Compare::Lt => "lt",
+                Compare::Lte => "lte",
+            };
+            json!({ "must": [{ "key": key(name), "range": { op: value } }] })
+        }
+        Filter::Exists(name) => json!({ "must_not": [{ "is_empty": { "key": key(name) } }] }),
+        Filter::And(filters) => json!({ "must": all(filters)? }),
+        Filter::Or(filters) => json!({ "should": all(filters)? }),
+        Filter::Not(filter) => json!({ "must_not": [to_qdrant(filter)?] }),
+    })
+}
+
+fn parse_point(point: &Value) -> Result<SearchHit, StoreError> {
+    let payload = &point["payload"];
+    let field = |name: &str| {
+        payload[name]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| StoreError::Backend(format!("Qdrant返回的点缺少 {}", name)))
+    };
+    Ok(SearchHit {
+        score: point["score"].as_f64().unwrap_or_default() as f32,
+        record: Record {
+            id: field("id")?,
+            document: field("document")?,
+            text: field("text")?,
+            metadata: payload["metadata"].as_object().cloned().unwrap_or_default(),
+            vector: point["vector"]
+                .as_array()
+                .map(|values| values.iter().filter_map(Value::as_f64).map(|v| v as f32).collect())
+                .unwrap_or_default(),
+        },
+    })
+}
+
+impl QdrantStore {
+    /// 连接Qdrant并读取索引`index`对应集合的维度，集合不存在时不创建
+    pub fn open(config: &QdrantConfig, index: &str) -> Result<QdrantStore, StoreError> {
+        let api_key = config.api_key.clone().filter(|key| !key.is_empty()).or_else(|| env::var("QDRANT_API_KEY").ok());
+        let headers = api_key.into_iter().map(|key| ("api-key", key)).collect();
+        let mut store = QdrantStore {
+            client: JsonClient::new(&config.url, headers),
+            collection: format!("{}{}", config.collection_prefix, index),
+            batch_size: config.batch_size.max(1),
+            tokenizer: config.tokenizer.clone(),
+            dimension: None,
+        };
+        store.dimension = store
+            .client
+            .call_optional("GET", &store.path(""), None)?
+            .and_then(|info| info["result"]["config"]["params"]["vectors"]["size"].as_u64())
+            .map(|size| size as usize);
+        Ok(store)
+    }
+
+    pub fn collection(&self) -> &str {
+        &self.collection
+    }
+
+    fn path(&self, suffix: &str) -> String {
+        format!("/collections/{}{}", self.collection, suffix)
+    }
+
+    fn create(&mut self, dimension: usize) -> Result<(), StoreError> {
+        let vectors = json!({ "vectors": { "size": dimension, "distance": "Cosine" } });
+        self.client.call("PUT", &self.path(""), Some(&vectors))?;
+        let indexes = [
+            json!({ "field_name": "document", "field_schema": "keyword" }),
+            json!({ "field_name": "text", "field_schema": { "type": "text", "tokenizer": self.tokenizer } }),
+        ];
+        for index in &indexes {
+            self.client.call("PUT", &self.path("/index?wait=true"), Some(index))?;
+        }
+        self.dimension = Some(dimension);
+        Ok(())
+    }
+
+    fn count(&self, filter: Option<Value>) -> Result<usize, StoreError> {
+        let mut body = json!({ "exact": true });
+        if let Some(filter) = filter {
+            body["filter"] = filter;
+        }
+        let response = self.client.call("POST", &self.path("/points/count"), Some(&body))?;
+        Ok(response["result"]["count"].as_u64().unwrap_or_default() as usize)
+    }
+
+    /// 删除满足`filter`的点，返回删除数
+    fn delete_where(&self, filter: Value) -> Result<usize, StoreError> {
+        if self.dimension.is_none() {
+            return Ok(0);
+        }
+        let count = self.count(Some(filter.clone()))?;
+        if count > 0 {
+            let body = json!({ "filter": filter });
+            self.client.call("POST", &self.path("/points/delete?wait=true"), Some(&body))?;
+        }
+        Ok(count)
+    }
+
+    fn query(&self, body: &Value) -> Result<Vec<SearchHit>, StoreError> {
+        if self.dimension.is_none() {
+            return Ok(Vec::new());
+        }
+        let response = self.client.call("POST", &self.path("/points/query"), Some(body))?;
+        response["result"]["points"]
+            .as_array()
+            .map(|points| points.iter().map(parse_point).collect())
+            .unwrap_or_else(|| Ok(Vec::new()))
+    }
+}
+
+fn document_filter(document: &str) -> Value {
+    json!({ "must": [{ "key": "document", "match": { "value": document } }] })
+}
+
+impl VectorStore for QdrantStore {
+    fn upsert(&mut self, records: Vec<Record>) -> Result<(), StoreError> {
+        check_dimension(self.dimension, &records)?;
+        let Some(first) = records.first() else {
+            return Ok(());
+        };
+        if self.dimension.is_none() {
+            self.create(first.vector.len())?;
+        }
+        for batch in records.chunks(self.batch_size) {
+            let points: Vec<Value> = batch
+                .iter()
+                .map(|record| {
+                    json!({
+                        "id": point_id(&record.id),
+                        "vector": record.vector,
+                        "payload": {
+                            "id": record.id,
+                            "document": record.document,
+                            "text": record.text,
+                            "metadata": record.metadata,
+                        },
+                    })
+                })
+                .collect();
+            let body = json!({ "points": points });
+            self.client.call("PUT", &self.path("/points?wait=true"), Some(&body))?;
+        }
+        Ok(())
+    }
+
+    fn delete(&mut self, ids: &[String]) -> Result<usize, StoreError> {
+        let points: Vec<String> = ids.iter().map(|id| point_id(id)).collect();
+        self.delete_where(json!({ "must": [{ "has_id": points }] }))
+    }
+
+    fn replace_document(&mut self, document: &str, records: Vec<Record>) -> Result<usize, StoreError> {
+        check_dimension(self.dimension, &records)?;
+        let removed = self.delete_document(document)?;
+        self.upsert(records)?;
+        Ok(removed)
+    }
+
+    fn delete_document(&mut self, document: &str) -> Result<usize, StoreError> {
+        self.delete_where(document_filter(document))
+    }
+
+    fn search(&self, vector: &[f32], top_k: usize, filter: Option<&Filter>) -> Result<Vec<SearchHit>, StoreError> {
+        let mut body = json!({ "query": vector, "limit": top_k, "with_payload": true, "with_vector": true });
+        if let Some(filter) = filter {
+            body["filter"] = to_qdrant(filter)?;
+        }
+        self.query(&body)
+    }
+
+    /// 一路按向量检索，一路只在包含关键词的点中按向量检索，两路结果用RRF融合
+    fn search_hybrid(
+        &self,
+        text: &str,
+        vector: &[f32],
+        top_k: usize,
+        filter: Option<&Filter>,
+    ) -> Result<Vec<SearchHit>, StoreError> {
+        let mut semantic = json!({ "query": vector, "limit": top_k * 4 });
+        let mut keyword_conditions = vec![json!({ "key": "text", "match": { "text": text } })];
+        if let Some(filter) = filter {
+            let filter = to_qdrant(filter)?;
+            semantic["filter"] = filter.clone();
+            keyword_conditions.push(filter);
+        }
+        let keyword = json!({ "query": vector, "limit": top_k * 4, "filter": { "must": keyword_conditions } });
+        let body = json!({
+            "prefetch": [semantic, keyword],
+            "query": { "fusion": "rrf" },
+            "limit": top_k,
+            "with_payload": true,
+            "with_vector": true,
+        });
+        self.query(&body)
+    }
+
+    fn supports_hybrid(&self) -> bool {
+        true
+    }
+
+    fn len(&self) -> Result<usize, StoreError> {
+        if self.dimension.is_none() {
+            return Ok(0);
+        }
+        self.count(None)
+    }
+
+    fn dimension(&self) -> Option<usize> {
+        self.dimension
+    }
+
+    fn flush(&mut self) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+//! 存储接口
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::error::StoreError;
+use crate::filter::Filter;
+
+/// 一个分块及其向量
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    /// `<文档id>#<分块序号>`
+    pub id: String,
+    /// 所属文档，重新导入同一文档时按它替换旧的分块
+    pub document: String,
+    pub text: String,
+    #[serde(default)]
+    pub metadata: Map<String, Value>,
+    pub vector: Vec<f32>,
+}
+
+/// 检索结果
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    /// 向量检索为余弦相似度，关键词检索为BM25等全文检索得分，混合检索为融合后的得分，越大越相关
+    pub score: f32,
+    pub record: Record,
+}
+
+/// 向量存储
+pub trait VectorStore: Send {
+    /// 按id写入记录，id已存在时覆盖
+    fn upsert(&mut self, records: Vec<Record>) -> Result<(), StoreError>;
+
+    /// 按id删除记录，返回删除数
+    fn delete(&mut self, ids: &[String]) -> Result<usize, StoreError>;
+
+    /// 用`records`替换文档`document`已有的全部分块，返回删除的旧分块数
+    fn replace_document(&mut self, document: &str, records: Vec<Record>) -> Result<usize, StoreError>;
+
+    /// 删除文档的全部分块，返回删除数
+    fn delete_document(&mut self, document: &str) -> Result<usize, StoreError>;
+
+    /// 返回最相似的`top_k`条记录，`filter`按元数据筛选
+    fn search(&self, vector: &[f32], top_k: usize, filter: Option<&Filter>) -> Result<Vec<SearchHit>, StoreError>;
+
+    /// 混合检索：同时按关键词`text`和向量匹配，结果用倒数排名融合（RRF）合并
+    ///
+    /// 不支持全文检索的存储只按向量检索，见[`VectorStore::supports_hybrid`]。
+    fn search_hybrid(
+        &self,
+        text: &str,
+        vector: &[f32],
+        top_k: usize,
+        filter: Option<&Filter>,
+    ) -> Result<Vec<SearchHit>, StoreError> {
+        let _ = text;
+        self.search(vector, top_k, filter)
+    }
+
+    fn supports_hybrid(&self) -> bool {
+        false
+    }
+
+    /// 只按关键词`text`检索，返回全文检索得分最高的`top_k`条记录
+    ///
+    /// 不支持全文检索的存储返回[`StoreError::Unsupported`]，见[`VectorStore::supports_keyword`]。
+    fn search_keyword(&self, text: &str, top_k: usize, filter: Option<&Filter>) -> Result<Vec<SearchHit>, StoreError> {
+        let _ = (text, top_k, filter);
+        Err(StoreError::Unsupported("关键词检索".to_string()))
+    }
+
+    fn supports_keyword(&self) -> bool {
+        false
+    }
+
+    /// 记录数，远程存储需要查询一次
+    fn len(&self) -> Result<usize, StoreError>;
+
+    fn is_empty(&self) -> Result<bool, StoreError> {
+        Ok(self.len()? == 0)
+    }
+
+    /// 向量维度，空索引为`None`
+    fn dimension(&self) -> Option<usize>;
+
+    /// 写入磁盘，远程存储在每次写入时已提交，无需操作
+    fn flush(&mut self) -> Result<(), StoreError>;
+
+    /// 已删除记录的占比超过`threshold`时整理存储并写回，返回是否做了整理
+    ///
+    /// 只有本地HNSW索引需要整理，其余存储什么也不做。
+    fn compact(&mut self, threshold: f32) -> Result<bool, StoreError> {
+        let _ = threshold;
+        Ok(false)
+    }
+}
+
+/// 检查待写入记录的维度：彼此一致，且与索引已有的维度`existing`一致
+pub(crate) fn check_dimension(existing: Option<usize>, records: &[Record]) -> Result<(), StoreError> {
+    let Some(expected) = existing.or_else(|| records.first().map(|record| record.vector.len())) else {
+        return Ok(());
+    };
+    match records.iter().find(|record| record.vector.len() != expected) {
+        Some(record) => Err(StoreError::Dimension {
+            expected,
+            actual: record.vector.len(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// 倒数排名融合的平滑参数，与Qdrant、Milvus的默认值相同
+pub(crate) const RRF_K: f32 = 60.0;
+
+/// 按倒数排名融合多路检索结果，每路中排第`r`（从1开始）的记录得`1 / (RRF_K + r)`分，取总分最高的`top_k`条
+pub(crate) fn fuse(rankings: Vec<Vec<SearchHit>>, top_k: usize) -> Vec<SearchHit> {
+    let mut fused: HashMap<String, SearchHit> = HashMap::new();
+    for ranking in rankings {
+        for (rank, hit) in ranking.into_iter().enumerate() {
+            let score = 1.0 / (RRF_K + rank as f32 + 1.0);
+            fused
+                .entry(hit.record.id.clone())
+                .and_modify(|existing| existing.score += score)
+                .or_insert(SearchHit { score, ..hit });
+        }
+    }
+    let mut hits: Vec<SearchHit> = fused.into_values().collect();
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.record.id.cmp(&b.record.id)));
+    hits.truncate(top_k);
+    hits
+}
+
+pub(crate) fn norm(vector: &[f32]) -> f32 {
+    vector.iter().map(|v| v * v).sum::<f32>().sqrt()
+}
+
+pub(crate) fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let (mut dot, mut norm_a, mut norm_b) = (0.0f32, 0.0f32, 0.0f32);
+    for (x, y) in a.iter().zip(b) {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a.sqrt() * norm_b.sqrt())
+    }
+}
@@ -0,0 +1,103 @@
+// 调用Rust编写的本地辅助程序 openkimi-helper
+const { app } = require('electron');
+const { spawn, spawnSync } = require('child_process');
+const path = require('path');
+const fs = require('fs');
+
+// 本地程序路径：打包后位于resources/bin，开发时使用工作区的构建产物
+function nativeBinPath(name) {
+  const file = process.platform === 'win32' ? `${name}.exe` : name;
+  if (app.isPackaged) {
+    return path.join(process.resourcesPath, 'bin', file);
+  }
+  return path.join(__dirname, '..', 'target', 'debug', file);
+}
+
+// 同步执行一个辅助程序命令，返回 { ok, result } 或 { ok: false, error }
+function runHelper(args) {
+  const file = nativeBinPath('openkimi-helper');
+  if (!fs.existsSync(file)) {
+    return { ok: false, error: `找不到辅助程序: ${file}` };
+  }
+
+  const child = spawnSync(file, args, { encoding: 'utf8' });
+  if (child.error) {
+    return { ok: false, error: child.error.message };
+  }
+
+  const lastLine = (child.stdout || '').trim().split('\n').pop();
+  try {
+    return JSON.parse(lastLine);
+  } catch (err) {
+    return { ok: false, error: `无法解析辅助程序输出: ${child.stdout}` };
+  }
+}
+
+// 升级后首次启动时迁移旧版本数据，失败时数据已由迁移工具自动回滚
+function runDataMigration(dataDir, appVersion) {
+  const file = nativeBinPath('migrate');
+  if (!fs.existsSync(file)) {
+    return { ok: false, error: `找不到迁移工具: ${file}` };
+  }
+
+  const child = spawnSync(file, ['run', '--data-dir', dataDir, '--app-version', appVersion], { encoding: 'utf8' });
+  if (child.error) {
+    return { ok: false, error: child.error.message };
+  }
+  return { ok: child.status === 0, output: `${child.stdout}${child.stderr}` };
+}
+
+// 常驻的辅助程序进程（--stdio模式），按行收发JSON请求
+let helperProcess = null;
+let nextRequestId = 1;
+const pendingRequests = new Map();
+
+function ensureHelperProcess() {
+  if (helperProcess) {
+    return helperProcess;
+  }
+
+  helperProcess = spawn(nativeBinPath('openkimi-helper'), ['--stdio'], { stdio: ['pipe', 'pipe', 'inherit'] });
+  let buffer = '';
+  helperProcess.stdout.setEncoding('utf8');
+  helperProcess.stdout.on('data', chunk => {
+    buffer += chunk;
+    let newline;
+    while ((newline = buffer.indexOf('\n')) >= 0) {
+      const line = buffer.slice(0, newline);
+      buffer = buffer.slice(newline + 1);
+      try {
+        const reply = JSON.parse(line);
+        const resolve = pendingRequests.get(reply.id);
+        if (resolve) {
+          pendingRequests.delete(reply.id);
+          resolve(reply);
+        }
+      } catch (err) {
+        console.error('无法解析辅助程序响应:', line);
+      }
+    }
+  });
+
+  const failAll = (error) => {
+    for (const resolve of pendingRequests.values()) {
+      resolve({ ok: false, error });
+    }
+    pendingRequests.clear();
+    helperProcess = null;
+  };
+  helperProcess.on('error', err => failAll(err.message));
+  helperProcess.on('exit', () => failAll('辅助程序已退出'));
+  return helperProcess;
+}
+
+// 通过常驻辅助程序异步执行命令
+function requestHelper(args) {
+  return new Promise(resolve => {
+    const id = nextRequestId++;
+    pendingRequests.set(id, resolve);
+    ensureHelperProcess().stdin.write(`${JSON.stringify({ id, args })}\n`);
+  });
+}
+
+module.exports = { runHelper, runDataMigration, requestHelper };
@@ -1,11 +1,75 @@
 const { app, BrowserWindow, Menu, ipcMain, dialog } = require('electron');
 const path = require('path');
 const fs = require('fs');
+const net = require('net');
+const crypto = require('crypto');
+const { runHelper, runDataMigration, requestHelper } = require('./helper');
+const { loadNative, openKeyStore } = require('./native');
+
+// 由系统再次启动客户端时使用的程序路径；AppImage运行时process.execPath位于临时挂载点，退出后即失效
+const launcherPath = process.env.APPIMAGE || process.execPath;
+
+// 便携模式：可执行文件旁存在portable标记时，将用户数据重定向到同级的data目录
+// 必须在任何app.getPath('userData')调用之前执行
+function applyPortableMode() {
+  const status = runHelper(['portable', 'status', '--exe', process.execPath]);
+  if (!status.ok) {
+    console.error('检测便携模式失败:', status.error);
+    return;
+  }
+  if (!status.result.portable) {
+    return;
+  }
+
+  if (status.result.needs_migration) {
+    const migration = runHelper(['portable', 'migrate', '--exe', process.execPath]);
+    if (migration.ok) {
+      console.log(`已迁移 ${migration.result.copied_files} 个文件到便携数据目录`);
+    } else {
+      console.error('迁移用户数据失败:', migration.error);
+    }
+  }
+
+  app.setPath('userData', status.result.data_dir);
+}
+
+applyPortableMode();
+
+// 只允许一个客户端实例（按数据目录加锁，须在便携模式设置之后），
+// 重复启动时参数中的链接经second-instance交给已有实例，并在迁移数据之前直接退出
+if (!app.requestSingleInstanceLock()) {
+  app.exit(0);
+}
+
+// 客户端版本变化后迁移旧数据格式，迁移失败不阻止启动
+const migration = runDataMigration(app.getPath('userData'), app.getVersion());
+if (!migration.ok) {
+  console.error('数据迁移失败:', migration.error || migration.output);
+}
 
 // 保持对窗口对象的全局引用，避免JavaScript对象被垃圾回收时窗口关闭
 let mainWindow;
 let pluginWindow;
 
+// 窗口加载完成前收到的kimi://链接
+let pendingDeepLink = process.argv.find(arg => /^kimi:\/\//i.test(arg)) || null;
+
+app.on('second-instance', (event, argv) => {
+  const link = argv.find(arg => /^kimi:\/\//i.test(arg));
+  if (link) {
+    handleDeepLink(link);
+  } else if (mainWindow) {
+    if (mainWindow.isMinimized()) mainWindow.restore();
+    mainWindow.focus();
+  }
+});
+
+// macOS由系统直接把链接交给应用
+app.on('open-url', (event, url) => {
+  event.preventDefault();
+  handleDeepLink(url);
+});
+
 // 插件存储路径
 const pluginsPath = path.join(app.getPath('userData'), 'plugins');
 
@@ -29,9 +93,23 @@ function createWindow() {
     icon: path.join(__dirname, 'assets/icon.png') // 应用图标
   });
 
+  // 开机自启动时以最小化方式启动
+  if (process.argv.includes('--hidden')) {
+    mainWindow.minimize();
+  }
+
   // 加载OpenKimi网站
   mainWindow.loadURL('https://chieko-seren.github.io/OpenKimi/');
 
+  // 页面加载完成后处理启动参数或转发中暂存的链接
+  mainWindow.webContents.on('did-finish-load', () => {
+    if (pendingDeepLink) {
+      const url = pendingDeepLink;
+      pendingDeepLink = null;
+      handleDeepLink(url);
+    }
+  });
+
   // 创建应用菜单
   createMenu();
 
@@ -92,6 +170,53 @@ function createMenu() {
   Menu.setApplicationMenu(menu);
 }
 
+// 把kimi://链接交给页面处理，窗口未就绪时先暂存
+function handleDeepLink(url) {
+  if (!mainWindow || mainWindow.webContents.isLoading()) {
+    pendingDeepLink = url;
+    return;
+  }
+  if (mainWindow.isMinimized()) mainWindow.restore();
+  mainWindow.focus();
+  mainWindow.webContents.send('deep-link', url);
+}
+
+// 监听本机端口接收openkimi-helper转发的链接，端口和令牌写入instance.json
+function startInstanceServer() {
+  const token = crypto.randomBytes(16).toString('hex');
+  const instanceFile = path.join(app.getPath('userData'), 'instance.json');
+
+  const server = net.createServer(socket => {
+    let buffer = '';
+    socket.setEncoding('utf8');
+    socket.on('data', chunk => {
+      buffer += chunk;
+      const newline = buffer.indexOf('\n');
+      if (newline < 0) return;
+      try {
+        const request = JSON.parse(buffer.slice(0, newline));
+        if (request.token !== token || !/^kimi:\/\//i.test(request.url || '')) {
+          socket.end('denied\n');
+          return;
+        }
+        handleDeepLink(request.url);
+        socket.end('ok\n');
+      } catch (err) {
+        socket.end('invalid\n');
+      }
+    });
+    socket.on('error', () => {});
+  });
+
+  server.listen(0, '127.0.0.1', () => {
+    fs.writeFileSync(instanceFile, JSON.stringify({ port: server.address().port, token, pid: process.pid }));
+  });
+  app.on('will-quit', () => {
+    server.close();
+    fs.rmSync(instanceFile, { force: true });
+  });
+}
+
 // 本地插件管理窗口
 function createPluginManager() {
   // 如果已经打开则聚焦而不是创建新窗口
@@ -121,8 +246,84 @@ function createPluginManager() {
   });
 }
 
+// 读取企业MSI/组策略写入的托管配置（仅Windows），原生模块未构建时通过辅助程序读取
+function readManagedPolicy() {
+  const native = loadNative();
+  if (native) {
+    return native.managedPolicy();
+  }
+  const reply = runHelper(['policy']);
+  return reply.ok ? reply.result : { disableAutoUpdate: false, apiEndpoint: null, maxDownloadKbps: null, updateQuietHours: null };
+}
+
+// 读取当前版本的发布说明，请求的语言不存在时依次回退到同语言、英文
+function readReleaseNotes(locale) {
+  const baseDir = app.isPackaged
+    ? path.join(process.resourcesPath, 'release-notes')
+    : path.join(__dirname, 'release-notes');
+  const version = app.getVersion();
+  const versionDir = path.join(baseDir, version);
+  if (!fs.existsSync(versionDir)) {
+    return null;
+  }
+
+  const available = fs.readdirSync(versionDir)
+    .filter(file => file.endsWith('.md'))
+    .map(file => file.slice(0, -3));
+  const language = locale.split('-')[0].toLowerCase();
+  const resolved = available.find(l => l === locale)
+    || available.find(l => l.split('-')[0].toLowerCase() === language)
+    || (available.includes('en') ? 'en' : available[0]);
+  if (!resolved) {
+    return null;
+  }
+
+  return {
+    version,
+    locale: resolved,
+    content: fs.readFileSync(path.join(versionDir, `${resolved}.md`), 'utf8')
+  };
+}
+
 // 设置IPC通信
 function setupIPC() {
+  // 获取“新功能”发布说明
+  ipcMain.handle('get-release-notes', async (event, locale) => readReleaseNotes(locale || app.getLocale()));
+
+  // 获取托管策略
+  ipcMain.handle('get-managed-policy', async () => readManagedPolicy());
+
+  // 开机自启动
+  ipcMain.handle('get-autostart', async () => requestHelper(['autostart', 'status']));
+  ipcMain.handle('set-autostart', async (event, enabled, hidden) => {
+    if (!enabled) {
+      return requestHelper(['autostart', 'disable']);
+    }
+    const args = ['autostart', 'enable', '--exe', launcherPath];
+    if (hidden) args.push('--hidden');
+    return requestHelper(args);
+  });
+
+  // 企业版许可证
+  ipcMain.handle('get-license-status', async () => {
+    return runHelper(['license', 'status', '--data-dir', app.getPath('userData')]);
+  });
+  ipcMain.handle('install-license', async (event, key) => {
+    return runHelper(['license', 'install', key, '--data-dir', app.getPath('userData')]);
+  });
+
+  // 加密保存的API密钥；渲染进程只能写入和删除，不能读出明文
+  const withKeyStore = (action) => {
+    try {
+      return { ok: true, result: action(openKeyStore()) };
+    } catch (err) {
+      return { ok: false, error: err.message };
+    }
+  };
+  ipcMain.handle('list-secrets', async () => withKeyStore(store => store.names()));
+  ipcMain.handle('set-secret', async (event, name, secret) => withKeyStore(store => store.set(name, secret)));
+  ipcMain.handle('delete-secret', async (event, name) => withKeyStore(store => store.delete(name)));
+
   // 列出所有本地插件
   ipcMain.handle('list-plugins', async () => {
     ensurePluginsDirectory();
@@ -231,7 +432,15 @@ function setupIPC() {
 app.whenReady().then(() => {
   setupIPC();
   ensurePluginsDirectory();
+  startInstanceServer();
   createWindow();
+
+  // 打包版本每次启动时刷新kimi://协议注册，安装位置变化后链接仍指向当前版本
+  if (app.isPackaged) {
+    requestHelper(['deeplink', 'register', '--exe', launcherPath]).then(result => {
+      if (!result.ok) console.error('注册kimi://协议失败:', result.error);
+    });
+  }
   
   // 在macOS上，点击dock图标时没有已打开的窗口则重新创建一个窗口
   app.on('activate', function() {
@@ -0,0 +1,57 @@
+// 加载Rust编写的Node原生模块 openkimi-node
+const { app, safeStorage } = require('electron');
+const path = require('path');
+const fs = require('fs');
+
+let nativeModule;
+
+// 打包后位于resources/bin，开发时使用crates/openkimi-node的构建产物；未构建时返回null
+function loadNative() {
+  if (nativeModule !== undefined) {
+    return nativeModule;
+  }
+
+  const file = app.isPackaged
+    ? path.join(process.resourcesPath, 'bin', 'openkimi-node.node')
+    : path.join(__dirname, '..', 'crates', 'openkimi-node', 'openkimi-node.node');
+  nativeModule = null;
+  if (fs.existsSync(file)) {
+    try {
+      nativeModule = require(file);
+    } catch (err) {
+      console.error('加载原生模块失败:', err.message);
+    }
+  }
+  return nativeModule;
+}
+
+let keyStore = null;
+
+// 打开加密的密钥存储；主密钥用safeStorage加密后保存在secrets.key，不与密文放在一起
+function openKeyStore() {
+  if (keyStore) {
+    return keyStore;
+  }
+  const native = loadNative();
+  if (!native) {
+    throw new Error('原生模块未构建，无法使用密钥存储');
+  }
+  if (!safeStorage.isEncryptionAvailable()) {
+    throw new Error('系统不支持安全存储，无法保存密钥');
+  }
+
+  const dataDir = app.getPath('userData');
+  const keyFile = path.join(dataDir, 'secrets.key');
+  let masterKey;
+  if (fs.existsSync(keyFile)) {
+    masterKey = Buffer.from(safeStorage.decryptString(fs.readFileSync(keyFile)), 'base64');
+  } else {
+    masterKey = native.KeyStore.generateMasterKey();
+    fs.mkdirSync(dataDir, { recursive: true });
+    fs.writeFileSync(keyFile, safeStorage.encryptString(masterKey.toString('base64')), { mode: 0o600 });
+  }
+  keyStore = new native.KeyStore(dataDir, masterKey);
+  return keyStore;
+}
+
+module.exports = { loadNative, openKeyStore };
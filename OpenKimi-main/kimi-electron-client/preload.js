@@ -10,6 +10,32 @@ contextBridge.exposeInMainWorld('electronAPI', {
     });
   },
   
+  // 打开kimi://链接（如分享的对话）
+  onDeepLink: (callback) => {
+    ipcRenderer.on('deep-link', (event, url) => {
+      callback(url);
+    });
+  },
+
+  // 获取企业部署的托管策略
+  getManagedPolicy: () => ipcRenderer.invoke('get-managed-policy'),
+
+  // 开机自启动
+  getAutostart: () => ipcRenderer.invoke('get-autostart'),
+  setAutostart: (enabled, hidden) => ipcRenderer.invoke('set-autostart', enabled, hidden),
+
+  // 企业版许可证
+  getLicenseStatus: () => ipcRenderer.invoke('get-license-status'),
+  installLicense: (key) => ipcRenderer.invoke('install-license', key),
+
+  // 加密保存的API密钥
+  listSecrets: () => ipcRenderer.invoke('list-secrets'),
+  setSecret: (name, secret) => ipcRenderer.invoke('set-secret', name, secret),
+  deleteSecret: (name) => ipcRenderer.invoke('delete-secret', name),
+
+  // 获取当前版本的发布说明
+  getReleaseNotes: (locale) => ipcRenderer.invoke('get-release-notes', locale),
+
   // 获取平台信息
   getPlatformInfo: () => {
     return {
@@ -500,6 +500,53 @@ def health_check():
     else:
          return {"status": "error", "engine_initialized": False, "detail": "KimiEngine failed to initialize."}
 
+# 发布说明目录：优先使用构建工具打包的发布目录，开发时回退到客户端源码中的说明
+RELEASE_NOTES_DIR = os.environ.get("OPENKIMI_RELEASE_NOTES_DIR") or next(
+    (d for d in (
+        os.path.join(project_root, "kimi-electron-client", "releases", "release-notes"),
+        os.path.join(project_root, "kimi-electron-client", "release-notes"),
+    ) if os.path.isdir(d)),
+    os.path.join(project_root, "kimi-electron-client", "release-notes"),
+)
+
+def _release_note_locales(version: str) -> List[str]:
+    version_dir = os.path.join(RELEASE_NOTES_DIR, version)
+    if not os.path.isdir(version_dir):
+        return []
+    return sorted(name[:-3] for name in os.listdir(version_dir) if name.endswith(".md"))
+
+def _resolve_locale(requested: str, available: List[str]) -> Optional[str]:
+    """按 完全匹配 → 同语言 → en → 任意 的顺序选择语言"""
+    if not available:
+        return None
+    if requested in available:
+        return requested
+    language = requested.split("-")[0].lower()
+    for locale in available:
+        if locale.split("-")[0].lower() == language:
+            return locale
+    return "en" if "en" in available else available[0]
+
+@app.get("/v1/release-notes", summary="List Release Notes", tags=["Management"])
+def list_release_notes():
+    """列出所有包含发布说明的版本及其语言"""
+    if not os.path.isdir(RELEASE_NOTES_DIR):
+        return {"versions": {}}
+    versions = sorted(d for d in os.listdir(RELEASE_NOTES_DIR) if os.path.isdir(os.path.join(RELEASE_NOTES_DIR, d)))
+    return {"versions": {version: _release_note_locales(version) for version in versions}}
+
+@app.get("/v1/release-notes/{version}", summary="Get Release Notes", tags=["Management"])
+def get_release_notes(version: str, locale: str = "en"):
+    """获取指定版本的发布说明，请求的语言不存在时自动回退"""
+    if os.path.basename(version) != version:
+        raise HTTPException(status_code=400, detail=f"无效的版本号: {version}")
+    resolved = _resolve_locale(locale, _release_note_locales(version))
+    if resolved is None:
+        raise HTTPException(status_code=404, detail=f"版本 {version} 没有发布说明")
+    with open(os.path.join(RELEASE_NOTES_DIR, version, f"{resolved}.md"), encoding="utf-8") as f:
+        content = f.read()
+    return {"version": version, "locale": resolved, "content": content}
+
 # 处理文件上传
 @app.post("/v1/files/upload", tags=["Files"])
 async def upload_file(file: UploadFile = File(...)):
@@ -0,0 +1,239 @@
+//! 企业版MSI安装包生成
+//!
+//! 从electron-builder输出的`win-unpacked`目录生成WiX源文件，并调用WiX v4+的`wix`命令行构建MSI。
+//! 生成的安装包支持`msiexec /a`管理安装，以及以下可通过组策略或命令行设置的公共属性：
+//!
+//! - `INSTALLDIR`：安装目录
+//! - `DISABLE_AUTOUPDATE`：设为`1`时禁用客户端自动更新
+//! - `API_ENDPOINT`：客户端使用的API服务地址
+//! - `MAX_DOWNLOAD_KBPS`：下载更新的最大速度（千比特每秒）
+//! - `UPDATE_QUIET_HOURS`：不下载更新的本地时段，如`09:00-18:00`
+//!
+//! 除`INSTALLDIR`外的属性会写入`HKLM\SOFTWARE\Policies\OpenKimi`，与组策略使用的注册表位置一致。
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// 产品升级码，所有版本必须保持不变，否则无法原地升级
+const UPGRADE_CODE: &str = "6F1C2A0B-3D4E-4F6A-9B8C-7D2E1F0A5B3C";
+
+/// 策略注册表路径
+const POLICY_KEY: &str = r"SOFTWARE\Policies\OpenKimi";
+
+/// 记录开始菜单快捷方式已安装的注册表路径，作为快捷方式组件的KeyPath
+const PRODUCT_KEY: &str = r"SOFTWARE\OpenKimi";
+
+/// WiX生成过程中的目录/文件编号状态
+struct WxsBuilder {
+    next_id: usize,
+    component_ids: Vec<String>,
+}
+
+impl WxsBuilder {
+    fn new() -> Self {
+        WxsBuilder {
+            next_id: 0,
+            component_ids: Vec::new(),
+        }
+    }
+
+    fn next_id(&mut self, prefix: &str) -> String {
+        self.next_id += 1;
+        format!("{}{}", prefix, self.next_id)
+    }
+
+    /// 递归生成目录下所有文件的`Directory`/`Component`元素
+    fn harvest_dir(&mut self, dir: &Path, indent: usize, out: &mut String) -> io::Result<()> {
+        let mut entries: Vec<_> = fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        let pad = " ".repeat(indent);
+        for entry in entries {
+            let path = entry.path();
+            let name = xml_escape(&entry.file_name().to_string_lossy());
+
+            if entry.file_type()?.is_dir() {
+                let dir_id = self.next_id("dir");
+                out.push_str(&format!("{}<Directory Id=\"{}\" Name=\"{}\">\n", pad, dir_id, name));
+                self.harvest_dir(&path, indent + 2, out)?;
+                out.push_str(&format!("{}</Directory>\n", pad));
+            } else {
+                let component_id = self.next_id("cmp");
+                let file_id = self.next_id("fil");
+                out.push_str(&format!(
+                    "{}<Component Id=\"{}\" Guid=\"*\" Bitness=\"always64\">\n",
+                    pad, component_id
+                ));
+                out.push_str(&format!(
+                    "{}  <File Id=\"{}\" Source=\"{}\" KeyPath=\"yes\" />\n",
+                    pad,
+                    file_id,
+                    xml_escape(&path.to_string_lossy())
+                ));
+                out.push_str(&format!("{}</Component>\n", pad));
+                self.component_ids.push(component_id);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 转义XML属性中的特殊字符
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// 从package.json中读取`version`字段
+///
+/// 只做简单的文本匹配，避免为构建工具引入JSON解析依赖。
+pub fn read_client_version(client_dir: &Path) -> io::Result<String> {
+    let content = fs::read_to_string(client_dir.join("package.json"))?;
+    content
+        .lines()
+        .find_map(|line| {
+            let line = line.trim();
+            let rest = line.strip_prefix("\"version\"")?;
+            let value = rest.trim_start().strip_prefix(':')?.trim();
+            Some(value.trim_end_matches(',').trim_matches('"').to_string())
+        })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "package.json 缺少 version 字段"))
+}
+
+/// 生成策略注册表组件，仅在对应属性有值时安装；`kind`为`string`或`integer`（REG_DWORD）
+fn policy_component(id: &str, condition: &str, value_name: &str, kind: &str, value: &str) -> String {
+    let mut out = String::new();
+    out.push_str(&format!(
+        "      <Component Id=\"{}\" Directory=\"INSTALLDIR\" Guid=\"*\" Bitness=\"always64\" Condition=\"{}\">\n",
+        id, condition
+    ));
+    out.push_str(&format!(
+        "        <RegistryValue Root=\"HKLM\" Key=\"{}\" Name=\"{}\" Type=\"{}\" Value=\"{}\" KeyPath=\"yes\" />\n",
+        POLICY_KEY, value_name, kind, value
+    ));
+    out.push_str("      </Component>\n");
+    out
+}
+
+/// 生成完整的WiX源文件内容
+fn generate_wxs(app_dir: &Path, version: &str) -> io::Result<String> {
+    let mut builder = WxsBuilder::new();
+    let mut files = String::new();
+    builder.harvest_dir(app_dir, 8, &mut files)?;
+
+    let mut component_refs = String::new();
+    for id in &builder.component_ids {
+        component_refs.push_str(&format!("      <ComponentRef Id=\"{}\" />\n", id));
+    }
+
+    let mut policy = String::new();
+    policy.push_str(&policy_component(
+        "PolicyDisableAutoUpdate",
+        "DISABLE_AUTOUPDATE = 1",
+        "DisableAutoUpdate",
+        "integer",
+        "1",
+    ));
+    policy.push_str(&policy_component(
+        "PolicyApiEndpoint",
+        "API_ENDPOINT",
+        "ApiEndpoint",
+        "string",
+        "[API_ENDPOINT]",
+    ));
+    policy.push_str(&policy_component(
+        "PolicyMaxDownloadKbps",
+        "MAX_DOWNLOAD_KBPS",
+        "MaxDownloadKbps",
+        "integer",
+        "[MAX_DOWNLOAD_KBPS]",
+    ));
+    policy.push_str(&policy_component(
+        "PolicyUpdateQuietHours",
+        "UPDATE_QUIET_HOURS",
+        "UpdateQuietHours",
+        "string",
+        "[UPDATE_QUIET_HOURS]",
+    ));
+
+    Ok(format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<Wix xmlns="http://wixtoolset.org/schemas/v4/wxs">
+  <Package Name="OpenKimi" Manufacturer="OpenKimi" Version="{version}" UpgradeCode="{upgrade_code}" Scope="perMachine" InstallerVersion="500">
+    <MajorUpgrade DowngradeErrorMessage="已安装更新版本的 OpenKimi。" />
+    <MediaTemplate EmbedCab="yes" />
+
+    <Property Id="DISABLE_AUTOUPDATE" Value="0" Secure="yes" />
+    <Property Id="API_ENDPOINT" Secure="yes" />
+    <Property Id="MAX_DOWNLOAD_KBPS" Secure="yes" />
+    <Property Id="UPDATE_QUIET_HOURS" Secure="yes" />
+    <Property Id="ARPNOMODIFY" Value="1" />
+
+    <StandardDirectory Id="ProgramFiles64Folder">
+      <Directory Id="INSTALLDIR" Name="OpenKimi">
+{files}      </Directory>
+    </StandardDirectory>
+
+    <StandardDirectory Id="ProgramMenuFolder">
+      <Component Id="StartMenuShortcut" Guid="*" Bitness="always64">
+        <Shortcut Id="OpenKimiShortcut" Name="OpenKimi" Target="[INSTALLDIR]OpenKimi.exe" WorkingDirectory="INSTALLDIR" />
+        <RegistryValue Root="HKLM" Key="{product_key}" Name="StartMenuShortcut" Type="integer" Value="1" KeyPath="yes" />
+      </Component>
+    </StandardDirectory>
+
+    <Feature Id="Main" Title="OpenKimi" Level="1">
+{component_refs}      <ComponentRef Id="StartMenuShortcut" />
+{policy}    </Feature>
+  </Package>
+</Wix>
+"#,
+        version = version,
+        upgrade_code = UPGRADE_CODE,
+        product_key = PRODUCT_KEY,
+        files = files,
+        component_refs = component_refs,
+        policy = policy,
+    ))
+}
+
+/// 构建MSI并返回生成的安装包路径
+///
+/// 没有安装WiX工具时返回`Ok(None)`，由调用方提示用户。
+pub fn build_msi(client_dir: &Path, dist_dir: &Path) -> io::Result<Option<PathBuf>> {
+    let app_dir = dist_dir.join("win-unpacked");
+    if !app_dir.is_dir() {
+        eprintln!("❌ 找不到 {:?}，请先完成 Windows 版本构建", app_dir);
+        return Ok(None);
+    }
+
+    if Command::new("wix").arg("--version").output().is_err() {
+        eprintln!("❌ 未找到 wix 命令，请安装 WiX Toolset v4+: dotnet tool install --global wix");
+        return Ok(None);
+    }
+
+    let version = read_client_version(client_dir)?;
+    let wxs_path = dist_dir.join("OpenKimi.wxs");
+    fs::write(&wxs_path, generate_wxs(&app_dir, &version)?)?;
+    println!("📝 已生成 WiX 源文件: {:?}", wxs_path);
+
+    let msi_path = dist_dir.join(format!("OpenKimi-{}-x64.msi", version));
+    let status = Command::new("wix")
+        .arg("build")
+        .args(["-arch", "x64"])
+        .arg("-o")
+        .arg(&msi_path)
+        .arg(&wxs_path)
+        .status()?;
+
+    if !status.success() {
+        eprintln!("❌ wix build 失败");
+        return Ok(None);
+    }
+
+    Ok(Some(msi_path))
+}
@@ -0,0 +1,85 @@
+//! 多语言发布说明打包
+//!
+//! 发布说明按版本和语言存放在`kimi-electron-client/release-notes/<版本>/<语言>.md`，
+//! 构建时复制到`releases/release-notes/`并生成`index.json`：
+//!
+//! ```json
+//! { "latest": "1.0.0", "versions": { "1.0.0": ["en", "zh-CN"] } }
+//! ```
+//!
+//! 发布目录上传到CDN后，客户端和API服务都按`release-notes/<版本>/<语言>.md`读取。
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// 某个版本包含的语言列表，按名称排序
+pub fn locales_for_version(notes_dir: &Path, version: &str) -> io::Result<Vec<String>> {
+    let version_dir = notes_dir.join(version);
+    if !version_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut locales: Vec<String> = fs::read_dir(version_dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            if path.extension()? != "md" {
+                return None;
+            }
+            Some(path.file_stem()?.to_string_lossy().into_owned())
+        })
+        .collect();
+    locales.sort();
+    Ok(locales)
+}
+
+/// 生成manifest中的`release_notes`字段：语言 -> 相对发布目录的路径
+pub fn manifest_field(notes_dir: &Path, version: &str) -> io::Result<String> {
+    let entries: Vec<String> = locales_for_version(notes_dir, version)?
+        .iter()
+        .map(|locale| format!("\"{}\": \"release-notes/{}/{}.md\"", locale, version, locale))
+        .collect();
+    Ok(format!("{{ {} }}", entries.join(", ")))
+}
+
+/// 将发布说明复制到发布目录并生成索引
+pub fn package_release_notes(client_dir: &Path, output_dir: &Path, current_version: &str) -> io::Result<()> {
+    let notes_dir = client_dir.join("release-notes");
+    if !notes_dir.is_dir() {
+        println!("⚠️ 找不到 {:?}，跳过发布说明打包", notes_dir);
+        return Ok(());
+    }
+
+    let mut versions: Vec<String> = fs::read_dir(&notes_dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    versions.sort();
+
+    let dest_dir = output_dir.join("release-notes");
+    let mut index_entries = Vec::new();
+    for version in &versions {
+        let locales = locales_for_version(&notes_dir, version)?;
+        fs::create_dir_all(dest_dir.join(version))?;
+        for locale in &locales {
+            let file_name = format!("{}.md", locale);
+            fs::copy(notes_dir.join(version).join(&file_name), dest_dir.join(version).join(&file_name))?;
+        }
+        let quoted: Vec<String> = locales.iter().map(|locale| format!("\"{}\"", locale)).collect();
+        index_entries.push(format!("    \"{}\": [{}]", version, quoted.join(", ")));
+    }
+
+    if !versions.iter().any(|version| version == current_version) {
+        println!("⚠️ 当前版本 {} 没有发布说明", current_version);
+    }
+
+    let index = format!(
+        "{{\n  \"latest\": \"{}\",\n  \"versions\": {{\n{}\n  }}\n}}\n",
+        current_version,
+        index_entries.join(",\n")
+    );
+    fs::write(dest_dir.join("index.json"), index)?;
+    println!("📝 已打包 {} 个版本的发布说明", versions.len());
+    Ok(())
+}
@@ -0,0 +1,154 @@
+//! 在线安装程序发布
+//!
+//! 将`win-unpacked`打包为安装包（zip）并生成`latest-windows.json`清单，
+//! 然后构建`openkimi-setup`在线安装程序。设置了签名证书时会用`signtool`签名安装程序。
+//! 上传到CDN时需要将以下三个文件放在同一目录：
+//!
+//! - `openkimi-setup.exe`
+//! - `OpenKimi-<版本>-win-x64.zip`
+//! - `latest-windows.json`
+//!
+//! 清单和安装包还可以用`build-client export-update-bundle`导出为签名的离线更新包，供无法访问CDN的内网使用。
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use openkimi_helper::bundle;
+use openkimi_licensing::LicenseIssuer;
+use sha2::{Digest, Sha256};
+use zip::write::SimpleFileOptions;
+
+use crate::msi::read_client_version;
+use crate::release_notes;
+
+/// 递归将目录写入zip
+fn zip_dir(
+    writer: &mut zip::ZipWriter<File>,
+    root: &Path,
+    dir: &Path,
+    options: SimpleFileOptions,
+) -> io::Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        let name = path
+            .strip_prefix(root)
+            .map_err(io::Error::other)?
+            .to_string_lossy()
+            .replace('\\', "/");
+
+        if entry.file_type()?.is_dir() {
+            writer.add_directory(name, options).map_err(io::Error::other)?;
+            zip_dir(writer, root, &path, options)?;
+        } else {
+            writer.start_file(name, options).map_err(io::Error::other)?;
+            io::copy(&mut File::open(&path)?, writer)?;
+        }
+    }
+    Ok(())
+}
+
+/// 计算文件的SHA-256
+fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// 使用signtool签名，未配置证书时跳过
+fn sign_file(path: &Path) -> io::Result<()> {
+    let Ok(cert) = env::var("OPENKIMI_SIGN_CERT") else {
+        println!("⚠️ 未设置 OPENKIMI_SIGN_CERT，跳过代码签名");
+        return Ok(());
+    };
+
+    let mut command = Command::new("signtool");
+    command
+        .args(["sign", "/fd", "SHA256", "/tr", "http://timestamp.digicert.com", "/td", "SHA256"])
+        .arg("/f")
+        .arg(&cert);
+    if let Ok(password) = env::var("OPENKIMI_SIGN_PASSWORD") {
+        command.arg("/p").arg(password);
+    }
+    let status = command.arg(path).status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("签名失败: {:?}", path)));
+    }
+    println!("🔏 已签名: {:?}", path);
+    Ok(())
+}
+
+/// 构建在线安装程序，返回存放发布文件的目录
+pub fn build_web_installer(client_dir: &Path, dist_dir: &Path, output_dir: &Path) -> io::Result<Option<PathBuf>> {
+    let app_dir = dist_dir.join("win-unpacked");
+    if !app_dir.is_dir() {
+        eprintln!("❌ 找不到 {:?}，请先完成 Windows 版本构建", app_dir);
+        return Ok(None);
+    }
+
+    let version = read_client_version(client_dir)?;
+    let web_dir = output_dir.join("web");
+    fs::create_dir_all(&web_dir)?;
+
+    // 打包安装包
+    let payload_name = format!("OpenKimi-{}-win-x64.zip", version);
+    let payload_path = web_dir.join(&payload_name);
+    println!("📦 正在打包安装包 {}...", payload_name);
+    let mut writer = zip::ZipWriter::new(File::create(&payload_path)?);
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    zip_dir(&mut writer, &app_dir, &app_dir, options)?;
+    writer.finish().map_err(io::Error::other)?;
+
+    // 生成清单
+    let manifest = format!(
+        "{{\n  \"version\": \"{}\",\n  \"file\": \"{}\",\n  \"sha256\": \"{}\",\n  \"size\": {},\n  \"release_notes\": {}\n}}\n",
+        version,
+        payload_name,
+        sha256_file(&payload_path)?,
+        fs::metadata(&payload_path)?.len(),
+        release_notes::manifest_field(&client_dir.join("release-notes"), &version)?
+    );
+    File::create(web_dir.join("latest-windows.json"))?.write_all(manifest.as_bytes())?;
+
+    // 构建安装程序，CDN地址通过OPENKIMI_CDN_BASE在编译期注入
+    if env::var("OPENKIMI_CDN_BASE").is_err() {
+        println!("⚠️ 未设置 OPENKIMI_CDN_BASE，安装程序运行时需要 --base-url 参数");
+    }
+    let workspace_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+    let status = Command::new("cargo")
+        .args(["build", "--release", "-p", "openkimi-installer", "--target", "x86_64-pc-windows-msvc"])
+        .current_dir(&workspace_dir)
+        .status()?;
+    if !status.success() {
+        eprintln!("❌ 在线安装程序编译失败");
+        return Ok(None);
+    }
+
+    let setup_src = workspace_dir.join("target/x86_64-pc-windows-msvc/release/openkimi-setup.exe");
+    let setup_dest = web_dir.join("openkimi-setup.exe");
+    fs::copy(&setup_src, &setup_dest)?;
+    sign_file(&setup_dest)?;
+
+    Ok(Some(web_dir))
+}
+
+/// 把`release_dir`中的清单和安装包导出为离线更新包，私钥从`OPENKIMI_UPDATE_SECRET`读取
+pub fn export_update_bundle(release_dir: &Path, output_dir: &Path) -> io::Result<PathBuf> {
+    let secret = env::var("OPENKIMI_UPDATE_SECRET")
+        .map_err(|_| io::Error::other("未设置 OPENKIMI_UPDATE_SECRET（可用 openkimi-license keygen 生成）"))?;
+    let issuer = LicenseIssuer::from_secret(&secret).map_err(io::Error::other)?;
+    bundle::export(release_dir, "windows", &issuer, output_dir).map_err(io::Error::other)
+}